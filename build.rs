@@ -2,9 +2,10 @@ extern crate bootloader;
 extern crate fs_extra;
 
 use std::fs;
-use std::io::Error;
+use std::io::{Error, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::time::{Duration, UNIX_EPOCH};
 
 use bootloader::BootConfig;
 
@@ -68,6 +69,42 @@ fn main() {
     let os_disk_dir = build_os_disk(&out_dir);
     let os_disk_image = create_ext2_image(&out_dir, &os_disk_dir);
     println!("cargo:rustc-env=OS_DISK={}", os_disk_image.display());
+
+    let cdrom_image = create_iso_image(&out_dir);
+    println!("cargo:rustc-env=CDROM_IMAGE={}", cdrom_image.display());
+}
+
+/// Creates the image of the CD-ROM that the test kernels see on the secondary
+/// IDE channel. It only holds the volume descriptors of an ISO 9660 file
+/// system, which is enough for the kernel tests to recognize the medium.
+fn create_iso_image(out_dir: &Path) -> PathBuf {
+    const SECTOR_SIZE: usize = 2048;
+    const SECTORS: usize = 32;
+
+    let mut image = vec![0_u8; SECTORS * SECTOR_SIZE];
+    // the first 16 sectors are the system area, the volume descriptors follow
+    let primary = &mut image[16 * SECTOR_SIZE..17 * SECTOR_SIZE];
+    primary[0] = 1;
+    primary[1..6].copy_from_slice(b"CD001");
+    primary[6] = 1;
+    primary[8..40].fill(b' ');
+    primary[40..72].fill(b' ');
+    primary[40..45].copy_from_slice(b"DEVOS");
+    // both-endian fields, the little endian value comes first
+    primary[80..84].copy_from_slice(&(SECTORS as u32).to_le_bytes());
+    primary[84..88].copy_from_slice(&(SECTORS as u32).to_be_bytes());
+    primary[128..130].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    primary[130..132].copy_from_slice(&(SECTOR_SIZE as u16).to_be_bytes());
+    primary[881] = 1;
+
+    let terminator = &mut image[17 * SECTOR_SIZE..18 * SECTOR_SIZE];
+    terminator[0] = 255;
+    terminator[1..6].copy_from_slice(b"CD001");
+    terminator[6] = 1;
+
+    let image_file = out_dir.join("cdrom.iso");
+    fs::write(&image_file, image).unwrap();
+    image_file
 }
 
 fn create_ext2_image(out_dir: &Path, os_disk_dir: &Path) -> PathBuf {
@@ -133,12 +170,88 @@ fn build_os_disk(out_dir: &Path) -> PathBuf {
         }
     };
 
+    copy_bindep("alloc_check", "/bin");
+    copy_bindep("console_check", "/bin");
+    copy_bindep("dev_check", "/bin");
+    copy_bindep("env_check", "/bin");
+    copy_bindep("flock_check", "/bin");
+    copy_bindep("fs_check", "/bin");
     copy_bindep("hello_world", "/bin");
+    copy_bindep("lspci", "/bin");
+    copy_bindep("muffin_check", "/bin");
+    copy_bindep("print_check", "/bin");
+    copy_bindep("proc_check", "/bin");
+    copy_bindep("syscall_check", "/bin");
+    copy_bindep("thread_check", "/bin");
     copy_bindep("window_server", "/bin");
 
+    create_symlinks(&os_disk_dir);
+    create_sparse_file(&os_disk_dir);
+    set_fixture_mtime(&os_disk_dir);
+
     os_disk_dir
 }
 
+/// Creates the symbolic links that the kernel tests resolve. They are created
+/// here instead of being checked in, because copying the os_disk dir would
+/// follow them.
+fn create_symlinks(os_disk_dir: &Path) {
+    let links_dir = os_disk_dir.join("var/data/links");
+    fs::create_dir_all(&links_dir).unwrap();
+
+    // long enough to not fit into the inode, so that it is stored in a data block
+    let long_target = format!("..{}/hello.txt", "/links/..".repeat(8));
+    for (name, target) in [
+        ("relative", "../hello.txt"),
+        ("absolute", "/var/data/hello.txt"),
+        ("chain", "relative"),
+        ("loop", "loop"),
+        ("long", long_target.as_str()),
+    ] {
+        std::os::unix::fs::symlink(target, links_dir.join(name)).unwrap();
+    }
+}
+
+/// Creates a large file that is mostly holes, with data in the direct blocks
+/// and in each tier of indirect blocks of its inode. With 1KiB blocks, the
+/// triply indirect blocks start at about 64MiB. The kernel tests expect the
+/// byte at offset `o` of each data region to be `o % 251 + 1`, and zeros
+/// everywhere else.
+fn create_sparse_file(os_disk_dir: &Path) {
+    const SIZE: u64 = 72 * 1024 * 1024;
+    const REGION_LEN: u64 = 4096;
+    const REGIONS: [u64; 4] = [
+        0,                      // direct
+        100 * 1024 + 100,       // singly indirect
+        20 * 1024 * 1024 + 100, // doubly indirect
+        70 * 1024 * 1024 + 100, // triply indirect
+    ];
+
+    let mut file = fs::File::create(os_disk_dir.join("var/data/sparse.bin")).unwrap();
+    file.set_len(SIZE).unwrap();
+    for start in REGIONS {
+        let data = (start..start + REGION_LEN)
+            .map(|offset| (offset % 251 + 1) as u8)
+            .collect::<Vec<_>>();
+        file.seek(SeekFrom::Start(start)).unwrap();
+        file.write_all(&data).unwrap();
+    }
+}
+
+/// Gives `/var/data/hello.txt` a fixed modification time, so that the kernel
+/// tests can check that `stat` reports the value stored in the image. Keep
+/// this in sync with `HELLO_MTIME` in the kernel's syscall tests.
+fn set_fixture_mtime(os_disk_dir: &Path) {
+    const HELLO_MTIME: u64 = 1_600_000_000;
+
+    fs::File::options()
+        .write(true)
+        .open(os_disk_dir.join("var/data/hello.txt"))
+        .unwrap()
+        .set_modified(UNIX_EPOCH + Duration::from_secs(HELLO_MTIME))
+        .unwrap();
+}
+
 fn copy_artifact_into_dir<P>(destination: P, artifact_file: P) -> Result<(), Error>
 where
     P: AsRef<Path>,
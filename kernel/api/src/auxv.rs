@@ -0,0 +1,23 @@
+//! The auxiliary vector, which the kernel puts on the initial stack of a
+//! process to tell it about its environment.
+//!
+//! The initial stack is laid out like the System V ABI describes it, starting
+//! at the stack pointer that the entry point gets:
+//!
+//! ```text
+//! argc
+//! argv[0] .. argv[argc - 1], null
+//! envp[0] .. envp[n - 1], null
+//! key, value pairs of the auxiliary vector, ended by AT_NULL
+//! the strings
+//! ```
+//!
+//! Every entry is a `usize`. Programs must skip keys that they don't know,
+//! since the kernel may add more of them.
+
+/// Ends the auxiliary vector. Its value is meaningless.
+pub const AT_NULL: usize = 0;
+/// The size of a page in bytes.
+pub const AT_PAGESZ: usize = 6;
+/// The address of the entry point of the executable.
+pub const AT_ENTRY: usize = 9;
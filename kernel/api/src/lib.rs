@@ -3,6 +3,15 @@
 #![feature(allocator_api)]
 extern crate alloc;
 
+pub mod auxv;
+pub mod font;
+pub mod pixel;
 pub mod syscall;
+pub mod time;
 
 pub const PATH_MAX: usize = 4096;
+/// The longest name of a directory entry that the C library can represent.
+pub const NAME_MAX: usize = 255;
+/// The most bytes that the arguments and the environment of a new process can
+/// take up together, including the pointers to them.
+pub const ARG_MAX: usize = 128 * 1024;
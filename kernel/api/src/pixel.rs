@@ -0,0 +1,210 @@
+//! The layouts of framebuffer pixels, and converting the `0x00RRGGBB` colors
+//! that are drawn with into them.
+//!
+//! Drawing happens in a single format, and pixels are converted when they
+//! are copied to the framebuffer, a row at a time. The writers are types
+//! rather than a `match` per pixel, so that the conversion of each format is
+//! compiled into its own loop.
+
+use num_enum::TryFromPrimitive;
+
+/// How the color of a pixel is laid out in the memory of a framebuffer.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(u32)]
+pub enum PixelFormat {
+    /// Blue, green, red and an unused byte, in this order in memory. As a
+    /// little endian `u32`, this is `0x00RRGGBB`, the format that is drawn
+    /// in.
+    #[default]
+    Bgrx8888 = 0,
+    /// Red, green, blue and an unused byte, in this order in memory.
+    Rgbx8888 = 1,
+    /// A little endian `u16` with 5 bits of red in the highest bits, followed
+    /// by 6 bits of green and 5 bits of blue.
+    Rgb565 = 2,
+}
+
+impl PixelFormat {
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Bgrx8888 | PixelFormat::Rgbx8888 => 4,
+            PixelFormat::Rgb565 => 2,
+        }
+    }
+}
+
+/// Converts `0x00RRGGBB` colors into the pixels of a [`PixelFormat`].
+pub trait PixelWriter {
+    const FORMAT: PixelFormat;
+    const BYTES_PER_PIXEL: usize = Self::FORMAT.bytes_per_pixel();
+
+    /// Writes the colors into the row of pixels. `dst` must have room for
+    /// all of them.
+    fn write_row(src: &[u32], dst: &mut [u8]);
+}
+
+/// Writes the pixels that `pixel` turns the colors into into `dst`.
+fn write_pixels<const N: usize>(src: &[u32], dst: &mut [u8], pixel: impl Fn(u32) -> [u8; N]) {
+    debug_assert!(dst.len() >= src.len() * N);
+    let (pixels, _) = dst.as_chunks_mut::<N>();
+    for (&color, dst) in src.iter().zip(pixels) {
+        *dst = pixel(color);
+    }
+}
+
+pub struct Bgrx8888Writer;
+
+impl PixelWriter for Bgrx8888Writer {
+    const FORMAT: PixelFormat = PixelFormat::Bgrx8888;
+
+    fn write_row(src: &[u32], dst: &mut [u8]) {
+        write_pixels(src, dst, |color| (color & 0x00FF_FFFF).to_le_bytes());
+    }
+}
+
+pub struct Rgbx8888Writer;
+
+impl PixelWriter for Rgbx8888Writer {
+    const FORMAT: PixelFormat = PixelFormat::Rgbx8888;
+
+    fn write_row(src: &[u32], dst: &mut [u8]) {
+        write_pixels(src, dst, |color| {
+            let [blue, green, red, _] = color.to_le_bytes();
+            [red, green, blue, 0]
+        });
+    }
+}
+
+pub struct Rgb565Writer;
+
+impl PixelWriter for Rgb565Writer {
+    const FORMAT: PixelFormat = PixelFormat::Rgb565;
+
+    fn write_row(src: &[u32], dst: &mut [u8]) {
+        write_pixels(src, dst, |color| rgb_to_rgb565(color).to_le_bytes());
+    }
+}
+
+/// Packs the color into 16 bits, rounding every channel to the closest
+/// value.
+pub const fn rgb_to_rgb565(color: u32) -> u16 {
+    let [blue, green, red, _] = color.to_le_bytes();
+    (scale(red, 31) << 11 | scale(green, 63) << 5 | scale(blue, 31)) as u16
+}
+
+/// Scales the channel from 0 to 255 down to 0 to `max`, rounded.
+const fn scale(channel: u8, max: u32) -> u32 {
+    (channel as u32 * max + 127) / 255
+}
+
+/// Unpacks the 16 bit color. The bits of each channel are repeated into the
+/// low bits, so that the largest value becomes 0xFF.
+pub const fn rgb565_to_rgb(value: u16) -> u32 {
+    let value = value as u32;
+    let (red, green, blue) = (value >> 11, (value >> 5) & 0x3F, value & 0x1F);
+    let red = red << 3 | red >> 2;
+    let green = green << 2 | green >> 4;
+    let blue = blue << 3 | blue >> 2;
+    red << 16 | green << 8 | blue
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Black, white, the primary colors and a gray, with garbage in the top
+    /// byte that must be ignored.
+    const SCENE: [u32; 6] = [
+        0x0000_0000,
+        0x00FF_FFFF,
+        0xFFFF_0000,
+        0x0000_FF00,
+        0x0000_00FF,
+        0x1280_8080,
+    ];
+
+    fn render<W: PixelWriter>() -> Vec<u8> {
+        let mut buffer = vec![0xAA; SCENE.len() * W::BYTES_PER_PIXEL + 1];
+        W::write_row(&SCENE, &mut buffer);
+        // the byte after the row is left alone
+        assert_eq!(Some(&0xAA), buffer.last());
+        buffer.pop();
+        buffer
+    }
+
+    #[test]
+    fn test_bgrx8888() {
+        assert_eq!(
+            vec![
+                0, 0, 0, 0, //
+                0xFF, 0xFF, 0xFF, 0, //
+                0, 0, 0xFF, 0, //
+                0, 0xFF, 0, 0, //
+                0xFF, 0, 0, 0, //
+                0x80, 0x80, 0x80, 0,
+            ],
+            render::<Bgrx8888Writer>()
+        );
+    }
+
+    #[test]
+    fn test_rgbx8888() {
+        assert_eq!(
+            vec![
+                0, 0, 0, 0, //
+                0xFF, 0xFF, 0xFF, 0, //
+                0xFF, 0, 0, 0, //
+                0, 0xFF, 0, 0, //
+                0, 0, 0xFF, 0, //
+                0x80, 0x80, 0x80, 0,
+            ],
+            render::<Rgbx8888Writer>()
+        );
+    }
+
+    #[test]
+    fn test_rgb565() {
+        assert_eq!(
+            vec![
+                0x00, 0x00, //
+                0xFF, 0xFF, //
+                0x00, 0xF8, //
+                0xE0, 0x07, //
+                0x1F, 0x00, //
+                0x10, 0x84,
+            ],
+            render::<Rgb565Writer>()
+        );
+    }
+
+    #[test]
+    fn test_rgb565_round_trip() {
+        for value in 0..=u16::MAX {
+            assert_eq!(value, rgb_to_rgb565(rgb565_to_rgb(value)), "{value:#06x}");
+        }
+        // every color ends up at most half a step away from where it started
+        for color in (0..0x0100_0000).step_by(0x0001_0107) {
+            let back = rgb565_to_rgb(rgb_to_rgb565(color));
+            for shift in [0, 8, 16] {
+                let channel = |c: u32| ((c >> shift) & 0xFF) as i32;
+                let step = if shift == 8 { 255 / 63 } else { 255 / 31 };
+                assert!(
+                    (channel(color) - channel(back)).abs() <= step / 2 + 1,
+                    "{color:#08x} became {back:#08x}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_bytes_per_pixel() {
+        assert_eq!(4, Bgrx8888Writer::BYTES_PER_PIXEL);
+        assert_eq!(4, Rgbx8888Writer::BYTES_PER_PIXEL);
+        assert_eq!(2, Rgb565Writer::BYTES_PER_PIXEL);
+        assert_eq!(Ok(PixelFormat::Rgb565), PixelFormat::try_from(2));
+        assert!(PixelFormat::try_from(3).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use core::mem::offset_of;
+
+/// The type of the file is unknown.
+pub const DT_UNKNOWN: u8 = 0;
+pub const DT_FIFO: u8 = 1;
+pub const DT_CHR: u8 = 2;
+pub const DT_DIR: u8 = 4;
+pub const DT_BLK: u8 = 6;
+pub const DT_REG: u8 = 8;
+pub const DT_LNK: u8 = 10;
+pub const DT_SOCK: u8 = 12;
+
+/// The fixed size part of a directory entry as `getdents` writes it. In the
+/// buffer, it's directly followed by the nul terminated name of the entry,
+/// and padding up to the next multiple of 8 bytes, where the next entry
+/// starts.
+///
+/// Entries are read and written with [`Dirent::write`] and [`Dirent::read`],
+/// which don't need the buffer to be aligned.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct Dirent {
+    pub ino: u64,
+    /// The position in the directory after this entry. Seeking the directory
+    /// there with `lseek` continues reading with the next entry.
+    pub off: u64,
+    /// The length of the whole entry, including the name and the padding.
+    pub reclen: u16,
+    /// One of the `DT_*` constants.
+    pub typ: u8,
+}
+
+impl Dirent {
+    /// Where the name starts, relative to the start of the entry.
+    pub const NAME_OFFSET: usize = offset_of!(Dirent, typ) + 1;
+
+    /// The length of an entry with a name of `name_len` bytes.
+    pub const fn record_len(name_len: usize) -> usize {
+        (Self::NAME_OFFSET + name_len + 1).next_multiple_of(8)
+    }
+
+    /// Writes this entry with the given name to the start of `buf`, and sets
+    /// [`Dirent::reclen`] accordingly. Returns the length of the entry, or
+    /// [`None`] if it doesn't fit.
+    pub fn write(mut self, buf: &mut [u8], name: &[u8]) -> Option<usize> {
+        let len = Self::record_len(name.len());
+        self.reclen = u16::try_from(len).ok()?;
+        let record = buf.get_mut(..len)?;
+        record[offset_of!(Dirent, ino)..][..8].copy_from_slice(&self.ino.to_ne_bytes());
+        record[offset_of!(Dirent, off)..][..8].copy_from_slice(&self.off.to_ne_bytes());
+        record[offset_of!(Dirent, reclen)..][..2].copy_from_slice(&self.reclen.to_ne_bytes());
+        record[offset_of!(Dirent, typ)] = self.typ;
+        let (name_part, padding) = record[Self::NAME_OFFSET..].split_at_mut(name.len());
+        name_part.copy_from_slice(name);
+        padding.fill(0);
+        Some(len)
+    }
+
+    /// Reads the entry at the start of `buf`, and returns it together with
+    /// its name, without the terminator. Returns [`None`] if `buf` doesn't
+    /// start with a complete entry.
+    pub fn read(buf: &[u8]) -> Option<(Self, &[u8])> {
+        let u64_at = |offset: usize| {
+            Some(u64::from_ne_bytes(
+                buf.get(offset..offset + 8)?.try_into().ok()?,
+            ))
+        };
+        let reclen_bytes = buf.get(offset_of!(Dirent, reclen)..offset_of!(Dirent, reclen) + 2)?;
+        let dirent = Self {
+            ino: u64_at(offset_of!(Dirent, ino))?,
+            off: u64_at(offset_of!(Dirent, off))?,
+            reclen: u16::from_ne_bytes(reclen_bytes.try_into().ok()?),
+            typ: *buf.get(offset_of!(Dirent, typ))?,
+        };
+        let name = buf.get(Self::NAME_OFFSET..usize::from(dirent.reclen))?;
+        let len = name.iter().position(|&b| b == 0)?;
+        Some((dirent, &name[..len]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(0, offset_of!(Dirent, ino));
+        assert_eq!(8, offset_of!(Dirent, off));
+        assert_eq!(16, offset_of!(Dirent, reclen));
+        assert_eq!(18, offset_of!(Dirent, typ));
+        assert_eq!(19, Dirent::NAME_OFFSET);
+        // the name and the terminator just fit into the padding of the header
+        assert_eq!(24, Dirent::record_len(4));
+        assert_eq!(32, Dirent::record_len(5));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = [0xAA_u8; 64];
+        let dirent = Dirent {
+            ino: 42,
+            off: 7 << 32,
+            reclen: 0,
+            typ: DT_DIR,
+        };
+        let first = dirent.write(&mut buf, b"hello").unwrap();
+        assert_eq!(32, first);
+        let second = dirent.write(&mut buf[first..], b"a").unwrap();
+        assert_eq!(24, second);
+
+        let (read, name) = Dirent::read(&buf).unwrap();
+        assert_eq!(
+            Dirent {
+                reclen: 32,
+                ..dirent
+            },
+            read
+        );
+        assert_eq!(b"hello", name);
+        let (read, name) = Dirent::read(&buf[first..]).unwrap();
+        assert_eq!(24, read.reclen);
+        assert_eq!(b"a", name);
+        assert_eq!(None, Dirent::read(&buf[first + second..]));
+    }
+
+    #[test]
+    fn test_too_small() {
+        let mut buf = [0_u8; 31];
+        assert_eq!(None, Dirent::default().write(&mut buf, b"hello"));
+        assert_eq!(Some(24), Dirent::default().write(&mut buf, b"hell"));
+        assert_eq!(None, Dirent::read(&buf[..23]));
+    }
+}
@@ -1,91 +1,46 @@
 use core::alloc::AllocError;
-use derive_more::Deref;
-
-#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Deref)]
-pub struct Errno(isize);
-
-impl Errno {
-    pub fn unwrap(self) -> usize {
-        if self.0 < 0 {
-            panic!("errno {}", self);
-        }
-        self.0 as usize
-    }
-}
-
-impl From<()> for Errno {
-    fn from(_: ()) -> Self {
-        Self::new(0)
-    }
-}
-
-impl<T: Into<Errno>, E: Into<Errno>> From<Result<T, E>> for Errno {
-    fn from(value: Result<T, E>) -> Self {
-        match value {
-            Ok(v) => v.into(),
-            Err(e) => e.into(),
-        }
-    }
-}
-
-impl From<isize> for Errno {
-    fn from(value: isize) -> Self {
-        Self(value)
-    }
-}
-
-impl From<usize> for Errno {
-    fn from(value: usize) -> Self {
-        match TryInto::<isize>::try_into(value) {
-            Ok(v) => Self::new(v),
-            Err(_) => Self::EINVAL,
-        }
-    }
-}
-
-impl From<Errno> for isize {
-    fn from(value: Errno) -> Self {
-        value.as_isize()
-    }
-}
-
-impl From<AllocError> for Errno {
-    fn from(_: AllocError) -> Self {
-        Errno::ENOMEM
-    }
-}
+use core::fmt::{Display, Formatter};
 
 macro_rules! errnos {
-    ($($(#[$($attrs:tt)*])* $name:ident = -$rc:expr),*,) => {
-        impl Errno {
+    ($($(#[$attr:meta])* $name:ident = $code:literal => $message:literal,)*) => {
+        /// An error that a syscall can return.
+        ///
+        /// The discriminants are the positive error codes. Syscalls return the
+        /// negated code, see [`Errno::from_return_value`] and `From<Errno> for isize`.
+        #[non_exhaustive]
+        #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+        #[repr(i32)]
+        pub enum Errno {
             $(
-                $(#[$($attrs)*])*
-                pub const $name: Self = Self::new(-$rc);
+                $(#[$attr])*
+                $name = $code,
             )*
         }
 
-        impl ::core::fmt::Display for Errno {
-            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
-                let num: isize = (*self).into();
-                match num {
-                    $(
-                    -$rc => write!(f, stringify!($name)),
-                    )*
-                    x if x < 0 => write!(f, "Unknown({})", num),
-                    _ => write!(f, "{}", num),
+        impl Errno {
+            /// All known error codes, in ascending order.
+            pub const ALL: &'static [Errno] = &[$(Errno::$name,)*];
+
+            /// Returns the error with the given (positive) code, or [`None`]
+            /// if the code is unknown.
+            pub const fn from_raw(code: i32) -> Option<Self> {
+                match code {
+                    $($code => Some(Errno::$name),)*
+                    _ => None,
+                }
+            }
+
+            /// The name of this error, e.g. `ENOENT`.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Errno::$name => stringify!($name),)*
                 }
             }
-        }
 
-        impl ::core::fmt::Debug for Errno {
-            fn fmt(&self, f: &mut ::core::fmt::Formatter<'_>) -> ::core::fmt::Result {
-                let num: isize = (*self).into();
-                match num {
-                    $(
-                    -$rc => write!(f, "Err({})", stringify!($name)),
-                    )*
-                    x if x < 0 => write!(f, "Err({})", num),
-                    _ => write!(f, "Ok({})", num),
+            /// A short description of this error, e.g. `no such file or directory`.
+            pub const fn message(self) -> &'static str {
+                match self {
+                    $(Errno::$name => $message,)*
                 }
             }
         }
@@ -96,210 +51,290 @@ errnos! {
     /// Operation not permitted
     ///
     /// An attempt was made to perform an operation that the caller does not have the required permissions to perform.
-    EPERM = -1,
+    EPERM = 1 => "operation not permitted",
 
     /// No such file or directory
     ///
     /// A component of a specified pathname did not exist, or the pathname was an empty string.
-    ENOENT = -2,
+    ENOENT = 2 => "no such file or directory",
 
     /// No such process
     ///
     /// A specified process does not exist, or the process group ID does not match any existing process or process group.
-    ESRCH = -3,
+    ESRCH = 3 => "no such process",
 
     /// Interrupted system call
     ///
     /// A system call was interrupted by a signal before it could complete.
-    EINTR = -4,
+    EINTR = 4 => "interrupted system call",
 
     /// Input/output error
     ///
     /// An error occurred while performing an input or output operation on a device or file.
-    EIO = -5,
+    EIO = 5 => "input/output error",
 
     /// No such device or address
     ///
     /// The specified device or address does not exist or is not accessible.
-    ENXIO = -6,
+    ENXIO = 6 => "no such device or address",
 
     /// Argument list too long
     ///
     /// The number of arguments or the total length of the arguments for a command or system call exceeded the maximum allowed size.
-    E2BIG = -7,
+    E2BIG = 7 => "argument list too long",
 
     /// Exec format error
     ///
     /// An executable file has a format error or is not suitable for execution on the current system.
-    ENOEXEC = -8,
+    ENOEXEC = 8 => "exec format error",
 
     /// Bad file descriptor
     ///
     /// The specified file descriptor is invalid or not open for the requested operation.
-    EBADF = -9,
+    EBADF = 9 => "bad file descriptor",
 
     /// No child processes
     ///
     /// A wait or similar function was called, but there are no child processes to wait for.
-    ECHILD = -10,
+    ECHILD = 10 => "no child processes",
 
     /// Resource temporarily unavailable (also EAGAIN)
     ///
     /// The requested operation would cause the process to be blocked, and the operation was requested to be non-blocking.
-    EWOULDBLOCK = -11,
+    EWOULDBLOCK = 11 => "resource temporarily unavailable",
 
     /// Not enough space (out of memory)
     ///
     /// The system does not have enough memory to complete the requested operation.
-    ENOMEM = -12,
+    ENOMEM = 12 => "out of memory",
 
     /// Permission denied
     ///
     /// The requested operation is not allowed due to insufficient permissions or access rights.
-    EACCES = -13,
+    EACCES = 13 => "permission denied",
 
     /// Bad address
     ///
     /// The address specified in a system call or operation is invalid or outside the address space of the process.
-    EFAULT = -14,
+    EFAULT = 14 => "bad address",
 
     /// Block device required
     ///
     /// The operation requires a block device, but a non-block device was specified.
-    ENOTBLK = -15,
+    ENOTBLK = 15 => "block device required",
 
     /// Device or resource busy
     ///
     /// The requested resource or device is in use and cannot be accessed or modified at this time.
-    EBUSY = -16,
+    EBUSY = 16 => "device or resource busy",
 
     /// File exists
     ///
     /// The specified pathname already exists, and the operation requires that it does not exist.
-    EEXIST = -17,
+    EEXIST = 17 => "file exists",
 
     /// Cross-device link
     ///
     /// An attempt was made to create a hard link between files on different filesystems or devices.
-    EXDEV = -18,
+    EXDEV = 18 => "cross-device link",
 
     /// No such device
     ///
     /// The specified device does not exist or is not recognized by the system.
-    ENODEV = -19,
+    ENODEV = 19 => "no such device",
 
     /// Not a directory
     ///
     /// A component of the specified pathname exists, but it is not a directory when a directory was expected.
-    ENOTDIR = -20,
+    ENOTDIR = 20 => "not a directory",
 
     /// Is a directory
     ///
     /// The specified pathname refers to a directory, but the operation requires a non-directory object.
-    EISDIR = -21,
+    EISDIR = 21 => "is a directory",
 
     /// Invalid argument
     ///
     /// One or more of the arguments provided to a system call or operation are invalid or out of the acceptable range.
-    EINVAL = -22,
+    EINVAL = 22 => "invalid argument",
 
     /// File table overflow
     ///
     /// The system-wide limit on the total number of open files has been reached.
-    ENFILE = -23,
+    ENFILE = 23 => "file table overflow",
 
     /// Too many open files
     ///
     /// The per-process limit on the number of open file descriptors has been reached.
-    EMFILE = -24,
+    EMFILE = 24 => "too many open files",
 
     /// Not a typewriter (Inappropriate ioctl for device)
     ///
     /// The specified file descriptor does not refer to a device that supports the requested ioctl operation.
-    ENOTTY = -25,
+    ENOTTY = 25 => "inappropriate ioctl for device",
 
     /// Text file busy
     ///
     /// An attempt was made to execute a pure-procedure program that is currently open for writing, or an operation that would modify an executable image is attempted.
-    ETXTBSY = -26,
+    ETXTBSY = 26 => "text file busy",
 
     /// File too large
     ///
     /// The size of a file would exceed the maximum file size allowed by the filesystem or the process.
-    EFBIG = -27,
+    EFBIG = 27 => "file too large",
 
     /// No space left on device
     ///
     /// There is not enough space left on the device or filesystem to complete the requested operation.
-    ENOSPC = -28,
+    ENOSPC = 28 => "no space left on device",
 
     /// Invalid seek
     ///
     /// An attempt was made to seek to an invalid position within a file or device.
-    ESPIPE = -29,
+    ESPIPE = 29 => "invalid seek",
 
     /// Read-only file system
     ///
     /// An attempt was made to modify a file or directory on a read-only file system.
-    EROFS = -30,
+    EROFS = 30 => "read-only file system",
 
     /// Too many links
     ///
     /// An attempt was made to create a new hard link, but the maximum number of hard links for a file has been reached.
-    EMLINK = -31,
+    EMLINK = 31 => "too many links",
 
     /// Broken pipe
     ///
     /// A write operation was attempted on a pipe or socket that is not connected or has been closed by the peer.
-    EPIPE = -32,
+    EPIPE = 32 => "broken pipe",
 
     /// Math argument out of domain of function
     ///
     /// A mathematical function was called with an argument outside its domain.
-    EDOM = -33,
+    EDOM = 33 => "math argument out of domain of function",
 
     /// Result too large
     ///
     /// The result of a mathematical operation is too large to be represented within the range of representable values.
-    ERANGE = -34,
+    ERANGE = 34 => "result too large",
 
     /// Resource deadlock avoided
     ///
     /// An attempt was made to lock a resource that would have caused a deadlock.
-    EDEADLK = -35,
+    EDEADLK = 35 => "resource deadlock avoided",
 
     /// File name too long
     ///
     /// A specified pathname or filename is longer than the maximum allowed length.
-    ENAMETOOLONG = -36,
+    ENAMETOOLONG = 36 => "file name too long",
 
     /// No locks available
     ///
     /// The system has reached the maximum number of file locks available.
-    ENOLCK = -37,
+    ENOLCK = 37 => "no locks available",
 
     /// Function not implemented
     ///
     /// The requested function or system call is not implemented or not known by the system.
-    ENOSYS = -38,
+    ENOSYS = 38 => "function not implemented",
 
     /// Directory not empty
     ///
     /// An attempt was made to remove a directory that is not empty.
-    ENOTEMPTY = -39,
+    ENOTEMPTY = 39 => "directory not empty",
 
     /// Too many levels of symbolic links
     ///
     /// The maximum number of symbolic link expansions has been exceeded during the resolution of a pathname.
-    ELOOP = -40,
+    ELOOP = 40 => "too many levels of symbolic links",
+
+    /// Value too large for defined data type
+    ///
+    /// A value, such as a file offset, is too large to be stored or used in the requested operation.
+    EOVERFLOW = 75 => "value too large for defined data type",
 }
 
 impl Errno {
-    pub const fn new(value: isize) -> Self {
-        Self(value)
+    /// The positive error code.
+    pub const fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Interprets the raw return value of a syscall. Non-negative values are
+    /// successful results, negative values are negated error codes. Negative
+    /// values that aren't a known error code are reported as [`Errno::EINVAL`].
+    pub fn from_return_value(value: isize) -> Result<usize, Errno> {
+        if value >= 0 {
+            return Ok(value as usize);
+        }
+        Err(value
+            .checked_neg()
+            .and_then(|code| i32::try_from(code).ok())
+            .and_then(Errno::from_raw)
+            .unwrap_or(Errno::EINVAL))
+    }
+}
+
+impl Display for Errno {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} ({}): {}", self.name(), self.code(), self.message())
+    }
+}
+
+impl core::error::Error for Errno {}
+
+/// Syscalls return errors as negated error codes.
+impl From<Errno> for isize {
+    fn from(value: Errno) -> Self {
+        -(value.code() as isize)
+    }
+}
+
+impl From<AllocError> for Errno {
+    fn from(_: AllocError) -> Self {
+        Errno::ENOMEM
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        for &errno in Errno::ALL {
+            assert_eq!(Some(errno), Errno::from_raw(errno.code()));
+            assert_eq!(
+                Err(errno),
+                Errno::from_return_value(isize::from(errno)),
+                "{}",
+                errno
+            );
+        }
+    }
+
+    #[test]
+    fn test_from_raw_unknown() {
+        assert_eq!(None, Errno::from_raw(0));
+        assert_eq!(None, Errno::from_raw(-2));
+        assert_eq!(None, Errno::from_raw(i32::MAX));
+    }
+
+    #[test]
+    fn test_return_value() {
+        assert_eq!(Ok(0), Errno::from_return_value(0));
+        assert_eq!(Ok(42), Errno::from_return_value(42));
+        assert_eq!(Err(Errno::ENOENT), Errno::from_return_value(-2));
+        assert_eq!(Err(Errno::EINVAL), Errno::from_return_value(-4095));
+        assert_eq!(Err(Errno::EINVAL), Errno::from_return_value(isize::MIN));
     }
 
-    pub fn as_isize(self) -> isize {
-        *self
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            "ENOENT (2): no such file or directory",
+            alloc::format!("{}", Errno::ENOENT)
+        );
+        assert_eq!(-2_isize, Errno::ENOENT.into());
     }
 }
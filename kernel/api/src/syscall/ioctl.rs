@@ -0,0 +1,32 @@
+use crate::pixel::PixelFormat;
+
+/// Get the geometry of a framebuffer device. The argument must point to
+/// a [`FbVarScreenInfo`], which is filled in by the kernel.
+pub const FBIOGET_VSCREENINFO: u32 = 0x4600;
+
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct FbVarScreenInfo {
+    /// The visible width in pixels.
+    pub width: u32,
+    /// The visible height in pixels.
+    pub height: u32,
+    pub bytes_per_pixel: u32,
+    /// The number of bytes between the start of two consecutive lines.
+    /// This may be larger than `width * bytes_per_pixel`.
+    pub pitch: u32,
+    pub format: PixelFormat,
+}
+
+/// Get the line discipline mode of a console. The argument must point to a
+/// `u32`, which is set to [`CONSOLE_MODE_LINE`] or [`CONSOLE_MODE_RAW`].
+pub const CONSOLE_GET_MODE: u32 = 0x5400;
+/// Set the line discipline mode of a console. Unlike most requests, the
+/// argument is the mode itself, not a pointer to it.
+pub const CONSOLE_SET_MODE: u32 = 0x5401;
+
+/// Input is echoed and can be edited with backspace. Reads return whole lines,
+/// and only once the line is completed with a newline.
+pub const CONSOLE_MODE_LINE: u32 = 0;
+/// Input is passed to readers as it arrives, without echo or editing.
+pub const CONSOLE_MODE_RAW: u32 = 1;
@@ -1,30 +1,97 @@
 use alloc::string::String;
 use core::fmt::{Display, Formatter};
 use core::ops::BitAnd;
+use core::time::Duration;
 
 use bitflags::bitflags;
 use derive_more::From;
 use num_enum::TryFromPrimitive;
 
+pub use dirent::*;
 pub use errno::*;
+pub use ioctl::*;
+pub use poll::*;
+pub use signal::*;
 
+mod dirent;
 mod errno;
+mod ioctl;
+mod poll;
+mod signal;
 
 pub const SYSCALL_INTERRUPT_INDEX: u8 = 0x80;
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, TryFromPrimitive)]
-#[repr(usize)]
-pub enum Syscall {
-    Read = 0,
-    Write,
-    Open,
-    Close,
-    Mmap,
-    Access,
-    Exit,
-    Socket,
-    Bind,
-    Stat,
+/// Defines [`Syscall`] with one variant for each syscall, numbered from 0 in
+/// order, and [`SYS_MAX`] as the number of variants. New syscalls are added at
+/// the end, so that the numbers of the others don't change.
+macro_rules! syscalls {
+    ($($variant:ident => $name:literal,)*) => {
+        #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, TryFromPrimitive)]
+        #[repr(usize)]
+        pub enum Syscall {
+            $($variant,)*
+        }
+
+        /// The number of syscalls. Every valid syscall number is smaller than
+        /// this.
+        pub const SYS_MAX: usize = [$(Syscall::$variant),*].len();
+
+        impl Syscall {
+            /// The name of this syscall, without the `sys_` prefix.
+            pub const fn name(self) -> &'static str {
+                match self {
+                    $(Syscall::$variant => $name,)*
+                }
+            }
+        }
+    };
+}
+
+syscalls! {
+    Read => "read",
+    Write => "write",
+    Open => "open",
+    Close => "close",
+    Mmap => "mmap",
+    Access => "access",
+    Exit => "exit",
+    Socket => "socket",
+    Bind => "bind",
+    Stat => "stat",
+    Poll => "poll",
+    Pipe => "pipe",
+    TraceMe => "traceme",
+    Ioctl => "ioctl",
+    Mkdir => "mkdir",
+    Unlink => "unlink",
+    Rmdir => "rmdir",
+    Rename => "rename",
+    Ftruncate => "ftruncate",
+    Openat => "openat",
+    Chdir => "chdir",
+    Getcwd => "getcwd",
+    Readlink => "readlink",
+    Fstat => "fstat",
+    Flock => "flock",
+    Link => "link",
+    Lseek => "lseek",
+    Munmap => "munmap",
+    ThreadCreate => "thread_create",
+    ThreadExit => "thread_exit",
+    ThreadJoin => "thread_join",
+    SetTls => "set_tls",
+    Futex => "futex",
+    Spawn => "spawn",
+    Waitpid => "waitpid",
+    ClockGettime => "clock_gettime",
+    Getdents => "getdents",
+    Sigaction => "sigaction",
+    Sigprocmask => "sigprocmask",
+    Kill => "kill",
+    Sigreturn => "sigreturn",
+    Sigsuspend => "sigsuspend",
+    Getpid => "getpid",
+    Nanosleep => "nanosleep",
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, TryFromPrimitive)]
@@ -46,6 +113,108 @@ pub struct FfiSockAddr {
     pub data: *const u8,
 }
 
+bitflags! {
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+    pub struct OpenFlags: u32 {
+        const O_RDONLY = 0x0000;
+        const O_WRONLY = 0x0001;
+        const O_RDWR = 0x0002;
+        /// Create the file if it doesn't exist.
+        const O_CREAT = 0x0040;
+        /// Together with [`OpenFlags::O_CREAT`], fail if the file already exists.
+        const O_EXCL = 0x0080;
+        /// Truncate regular files to length zero.
+        const O_TRUNC = 0x0200;
+        /// Write at the end of the file, no matter where the file offset is.
+        /// Moving to the end and writing happens atomically.
+        const O_APPEND = 0x0400;
+        /// Fail with [`Errno::EWOULDBLOCK`] instead of waiting for data when
+        /// reading.
+        const O_NONBLOCK = 0x0800;
+        /// Fail with [`Errno::ENOTDIR`] if the path doesn't refer to a
+        /// directory.
+        const O_DIRECTORY = 0x0001_0000;
+        /// Fail with [`Errno::ELOOP`] if the last component of the path is a
+        /// symbolic link.
+        const O_NOFOLLOW = 0x0002_0000;
+        /// Only resolve the path beneath the directory that it is relative to.
+        /// Absolute paths and `..` components that would leave that directory
+        /// fail with [`Errno::EXDEV`].
+        const O_RESOLVE_BENEATH = 0x0080_0000;
+    }
+}
+
+bitflags! {
+    /// The operation of an advisory lock, as passed to `flock`. Exactly one of
+    /// [`FlockOperation::LOCK_SH`], [`FlockOperation::LOCK_EX`] and
+    /// [`FlockOperation::LOCK_UN`] must be set.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct FlockOperation: u32 {
+        /// Acquire a shared lock.
+        const LOCK_SH = 0x1;
+        /// Acquire an exclusive lock.
+        const LOCK_EX = 0x2;
+        /// Fail with [`Errno::EWOULDBLOCK`] instead of waiting for the lock.
+        const LOCK_NB = 0x4;
+        /// Release the lock.
+        const LOCK_UN = 0x8;
+    }
+}
+
+/// What the offset that is passed to `lseek` is relative to.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(usize)]
+pub enum Whence {
+    /// The start of the file.
+    SeekSet = 0,
+    /// The current offset.
+    SeekCur = 1,
+    /// The end of the file.
+    SeekEnd = 2,
+}
+
+/// The operation of a `futex` call, which works on a `u32` in memory.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(usize)]
+pub enum FutexOp {
+    /// Waits until the futex is woken, unless it doesn't have the expected
+    /// value, in which case this fails with [`Errno::EWOULDBLOCK`].
+    Wait = 0,
+    /// Wakes up to the given number of waiters, and returns how many were
+    /// woken.
+    Wake = 1,
+}
+
+/// The clock that `clock_gettime` reads.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(usize)]
+pub enum ClockId {
+    /// The wall clock time since the Unix epoch.
+    Realtime = 0,
+    /// The time since boot, which never goes backwards.
+    Monotonic = 1,
+}
+
+/// Passed as the directory file descriptor to `openat` and friends to resolve
+/// relative paths against the current working directory.
+pub const AT_FDCWD: i32 = -100;
+
+impl OpenFlags {
+    /// Mask for the access mode, which is one of [`OpenFlags::O_RDONLY`],
+    /// [`OpenFlags::O_WRONLY`] or [`OpenFlags::O_RDWR`].
+    pub const O_ACCMODE: Self = Self::from_bits_retain(0x0003);
+
+    pub fn is_readable(self) -> bool {
+        let mode = self & Self::O_ACCMODE;
+        mode == Self::O_RDONLY || mode == Self::O_RDWR
+    }
+
+    pub fn is_writable(self) -> bool {
+        let mode = self & Self::O_ACCMODE;
+        mode == Self::O_WRONLY || mode == Self::O_RDWR
+    }
+}
+
 bitflags! {
     #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
     pub struct FileMode: u32 {
@@ -166,21 +335,36 @@ is_mode!(is_directory, FileMode::S_IFDIR);
 is_mode!(is_char_device, FileMode::S_IFCHR);
 is_mode!(is_fifo, FileMode::S_IFIFO);
 
+impl FileMode {
+    /// The permission bits of the mode, without the file type.
+    pub fn permissions(self) -> Self {
+        self.difference(Self::S_IFMT)
+    }
+}
+
+/// The metadata of a file, as returned by `stat` and `fstat`. Userspace uses
+/// this type directly, so the layout must not change without updating the
+/// userspace side as well.
 #[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(C)]
 pub struct Stat {
+    /// The id of the file system that contains the file.
     pub dev: u64,
     pub ino: u64,
+    /// The file type and the permission bits.
     pub mode: FileMode,
     pub nlink: u32,
     pub uid: u32,
     pub gid: u32,
+    /// The device that this file represents, if it is a device file.
     pub rdev: u64,
     pub size: u64,
-    pub atime: Time,
-    pub mtime: Time,
-    pub ctime: Time,
+    pub atime: Timespec,
+    pub mtime: Timespec,
+    pub ctime: Timespec,
+    /// The preferred block size for I/O on this file.
     pub blksize: u64,
+    /// The number of 512-byte blocks that are allocated for this file.
     pub blocks: u64,
 }
 
@@ -194,6 +378,12 @@ impl From<u32> for Time {
     }
 }
 
+impl From<Time> for u64 {
+    fn from(value: Time) -> Self {
+        value.0
+    }
+}
+
 #[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
 #[repr(C)]
 pub struct Timespec {
@@ -210,3 +400,60 @@ impl<T: Into<Time>> From<T> for Timespec {
         }
     }
 }
+
+impl From<Duration> for Timespec {
+    fn from(value: Duration) -> Self {
+        Self {
+            tv_sec: value.as_secs().into(),
+            tv_nsec: value.subsec_nanos() as u64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::{offset_of, size_of};
+
+    use super::*;
+
+    #[test]
+    fn test_stat_layout() {
+        assert_eq!(0, offset_of!(Stat, dev));
+        assert_eq!(8, offset_of!(Stat, ino));
+        assert_eq!(16, offset_of!(Stat, mode));
+        assert_eq!(20, offset_of!(Stat, nlink));
+        assert_eq!(24, offset_of!(Stat, uid));
+        assert_eq!(28, offset_of!(Stat, gid));
+        assert_eq!(32, offset_of!(Stat, rdev));
+        assert_eq!(40, offset_of!(Stat, size));
+        assert_eq!(48, offset_of!(Stat, atime));
+        assert_eq!(64, offset_of!(Stat, mtime));
+        assert_eq!(80, offset_of!(Stat, ctime));
+        assert_eq!(96, offset_of!(Stat, blksize));
+        assert_eq!(104, offset_of!(Stat, blocks));
+        assert_eq!(112, size_of::<Stat>());
+    }
+
+    #[test]
+    fn test_file_type() {
+        let dir = FileMode::S_IFDIR | FileMode::S_IRWXU;
+        assert!(is_directory(dir));
+        assert!(!is_regular_file(dir));
+
+        let file = FileMode::S_IFREG | FileMode::S_IRUSR;
+        assert!(is_regular_file(file));
+        assert!(!is_directory(file));
+
+        // S_IFSOCK shares bits with S_IFREG and S_IFDIR
+        assert!(!is_regular_file(FileMode::S_IFSOCK));
+        assert!(!is_directory(FileMode::S_IFBLK));
+        assert!(is_char_device(FileMode::S_IFCHR | FileMode::S_IRUSR));
+    }
+
+    #[test]
+    fn test_timespec_from_duration() {
+        let ts = Timespec::from(Duration::new(3, 250));
+        assert_eq!(Time::from(3_u64), ts.tv_sec);
+        assert_eq!(250, ts.tv_nsec);
+    }
+}
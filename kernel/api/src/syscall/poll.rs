@@ -0,0 +1,50 @@
+use bitflags::bitflags;
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+    #[repr(transparent)]
+    pub struct PollEvents: u16 {
+        /// There is data to read.
+        const POLLIN = 0x001;
+        /// There is urgent data to read.
+        const POLLPRI = 0x002;
+        /// Writing now will not block.
+        const POLLOUT = 0x004;
+        /// Error condition. Only valid in `revents`.
+        const POLLERR = 0x008;
+        /// The peer closed its end. Only valid in `revents`.
+        const POLLHUP = 0x010;
+        /// The file descriptor is not open. Only valid in `revents`.
+        const POLLNVAL = 0x020;
+    }
+}
+
+impl PollEvents {
+    /// Events that are always reported in `revents`, whether they were requested or not.
+    pub const ALWAYS: Self = Self::POLLERR.union(Self::POLLHUP).union(Self::POLLNVAL);
+}
+
+/// The maximum number of entries that can be passed to a single poll call.
+pub const POLL_NFDS_MAX: usize = 1024;
+
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct PollFd {
+    /// The file descriptor to poll. Negative values are ignored
+    /// and result in empty `revents`.
+    pub fd: i32,
+    /// The events that the caller is interested in.
+    pub events: PollEvents,
+    /// The events that occurred, written by the kernel.
+    pub revents: PollEvents,
+}
+
+impl PollFd {
+    pub const fn new(fd: i32, events: PollEvents) -> Self {
+        Self {
+            fd,
+            events,
+            revents: PollEvents::empty(),
+        }
+    }
+}
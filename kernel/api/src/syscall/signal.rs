@@ -0,0 +1,246 @@
+use core::mem::size_of;
+
+use bitflags::bitflags;
+use num_enum::TryFromPrimitive;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+/// One more than the highest signal number. Signal numbers start at 1.
+pub const NSIG: usize = 32;
+
+/// The handler of a signal that performs the default action.
+pub const SIG_DFL: usize = 0;
+/// The handler of a signal that is ignored.
+pub const SIG_IGN: usize = 1;
+
+/// A set of signals, where signal `n` is bit `n - 1`.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(transparent)]
+pub struct SigSet(u64);
+
+impl SigSet {
+    pub const EMPTY: Self = Self(0);
+    pub const FULL: Self = Self((1 << (NSIG - 1)) - 1);
+    /// The signals that can't be caught, blocked or ignored.
+    pub const UNBLOCKABLE: Self = Self::EMPTY.with(SIGKILL).with(SIGSTOP);
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & Self::FULL.0)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Whether `sig` is a valid signal number, which is what all the other
+    /// methods expect.
+    pub const fn is_valid(sig: usize) -> bool {
+        sig > 0 && sig < NSIG
+    }
+
+    pub const fn with(self, sig: usize) -> Self {
+        Self(self.0 | 1 << (sig - 1))
+    }
+
+    pub const fn without(self, sig: usize) -> Self {
+        Self(self.0 & !(1 << (sig - 1)))
+    }
+
+    pub const fn contains(self, sig: usize) -> bool {
+        self.0 & 1 << (sig - 1) != 0
+    }
+
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    pub const fn difference(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// The lowest signal in the set.
+    pub const fn first(self) -> Option<usize> {
+        if self.is_empty() {
+            None
+        } else {
+            Some(self.0.trailing_zeros() as usize + 1)
+        }
+    }
+}
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+    #[repr(transparent)]
+    pub struct SigActionFlags: u64 {
+        /// Don't generate `SIGCHLD` when children stop.
+        const SA_NOCLDSTOP = 0x0000_0001;
+        /// Call the handler with the [`SigInfo`] and the context of the
+        /// signal.
+        const SA_SIGINFO = 0x0000_0004;
+        /// [`SigAction::restorer`] is set.
+        const SA_RESTORER = 0x0400_0000;
+        const SA_ONSTACK = 0x0800_0000;
+        /// Restart syscalls that were interrupted by the signal instead of
+        /// failing them with [`crate::syscall::Errno::EINTR`].
+        const SA_RESTART = 0x1000_0000;
+        /// Don't block the signal while its handler runs.
+        const SA_NODEFER = 0x4000_0000;
+        /// Reset the action to [`SIG_DFL`] when the handler is called.
+        const SA_RESETHAND = 0x8000_0000;
+    }
+}
+
+/// What happens when a signal is delivered, as passed to `sigaction`.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigAction {
+    /// [`SIG_DFL`], [`SIG_IGN`] or the address of the handler.
+    pub handler: usize,
+    pub flags: SigActionFlags,
+    /// Where the handler returns to, which must make the `sigreturn` syscall
+    /// with the stack pointer that it finds.
+    pub restorer: usize,
+    /// The signals that are blocked in addition while the handler runs.
+    pub mask: SigSet,
+}
+
+/// The signal was sent by `kill`.
+pub const SI_USER: i32 = 0;
+/// The signal was sent by the kernel.
+pub const SI_KERNEL: i32 = 0x80;
+/// The faulting address isn't mapped.
+pub const SEGV_MAPERR: i32 = 1;
+/// The faulting address is mapped, but the access isn't allowed.
+pub const SEGV_ACCERR: i32 = 2;
+
+/// Information about a signal, which handlers that were installed with
+/// [`SigActionFlags::SA_SIGINFO`] get.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SigInfo {
+    pub signo: i32,
+    pub errno: i32,
+    /// One of the `SI_*` or signal specific constants like [`SEGV_MAPERR`].
+    pub code: i32,
+    /// The process that sent the signal, for [`SI_USER`].
+    pub pid: i32,
+    pub uid: u32,
+    /// The faulting address, for `SIGSEGV`.
+    pub addr: usize,
+    pub status: i32,
+    pub value: usize,
+}
+
+/// How `sigprocmask` changes the signal mask.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, TryFromPrimitive)]
+#[repr(usize)]
+pub enum SigprocmaskHow {
+    /// Adds the given signals to the mask.
+    Block = 0,
+    /// Removes the given signals from the mask.
+    Unblock = 1,
+    /// Replaces the mask.
+    SetMask = 2,
+}
+
+/// The state of a thread at the time that a signal interrupted it, which is
+/// restored by `sigreturn`. Registers that a function call preserves are
+/// missing, because the handler preserves them.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C)]
+pub struct SignalContext {
+    pub rax: usize,
+    pub rcx: usize,
+    pub rdx: usize,
+    pub rsi: usize,
+    pub rdi: usize,
+    pub r8: usize,
+    pub r9: usize,
+    pub r10: usize,
+    pub r11: usize,
+    pub rip: usize,
+    pub rsp: usize,
+    pub rflags: usize,
+    /// The signal mask before the handler was called.
+    pub mask: SigSet,
+}
+
+/// What the kernel puts on the stack for a signal handler. The handler
+/// returns to the restorer with the stack pointer pointing at this frame,
+/// which is what the restorer passes to `sigreturn`.
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(C, align(16))]
+pub struct SignalFrame {
+    pub info: SigInfo,
+    pub context: SignalContext,
+}
+
+const _: () = assert!(size_of::<SignalFrame>().is_multiple_of(16));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sig_set() {
+        let set = SigSet::EMPTY.with(SIGHUP).with(SIGSYS);
+        assert_eq!(0x4000_0001, set.bits());
+        assert!(set.contains(SIGHUP));
+        assert!(set.contains(SIGSYS));
+        assert!(!set.contains(SIGINT));
+        assert_eq!(Some(SIGHUP), set.first());
+        assert_eq!(Some(SIGSYS), set.without(SIGHUP).first());
+        assert_eq!(None, SigSet::EMPTY.first());
+
+        assert!(SigSet::FULL.contains(SIGSYS));
+        assert_eq!(SigSet::FULL, SigSet::from_bits(u64::MAX));
+        assert!(SigSet::FULL
+            .difference(SigSet::UNBLOCKABLE)
+            .contains(SIGSEGV));
+        assert!(!SigSet::FULL
+            .difference(SigSet::UNBLOCKABLE)
+            .contains(SIGKILL));
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(!SigSet::is_valid(0));
+        assert!(SigSet::is_valid(1));
+        assert!(SigSet::is_valid(NSIG - 1));
+        assert!(!SigSet::is_valid(NSIG));
+    }
+}
@@ -0,0 +1,118 @@
+//! Conversions between days since the Unix epoch and dates in the proleptic
+//! Gregorian calendar, which the kernel and the C library share.
+//!
+//! These are the `days_from_civil` and `civil_from_days` algorithms by Howard
+//! Hinnant. They work in eras of 400 years, which all have the same number of
+//! days, so they only divide values that can't overflow for any year within
+//! a few million millennia of the epoch.
+
+pub const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The number of days in an era of 400 years.
+const DAYS_PER_ERA: i64 = 146_097;
+
+/// The number of days from 0000-03-01, where the first era starts, to
+/// 1970-01-01.
+const EPOCH_OFFSET: i64 = 719_468;
+
+/// The number of days from 1970-01-01 to the given date, which is negative
+/// for dates before it. `month` is in `1..=12`, and `day` in `1..=31`.
+pub const fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // years start in March, so that the leap day is the last day of a year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let month_from_march = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_from_march + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * DAYS_PER_ERA + day_of_era - EPOCH_OFFSET
+}
+
+/// The date that is `days` days after 1970-01-01, as year, month in `1..=12`
+/// and day in `1..=31`.
+pub const fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + EPOCH_OFFSET;
+    let era = days.div_euclid(DAYS_PER_ERA);
+    let day_of_era = days.rem_euclid(DAYS_PER_ERA);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month = if month_from_march < 10 {
+        month_from_march + 3
+    } else {
+        month_from_march - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+/// The day of the week of the day that is `days` days after 1970-01-01, with
+/// Sunday being 0.
+pub const fn weekday_from_days(days: i64) -> u32 {
+    // 1970-01-01 was a Thursday
+    (days + 4).rem_euclid(7) as u32
+}
+
+pub const fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_epoch() {
+        assert_eq!(0, days_from_civil(1970, 1, 1));
+        assert_eq!((1970, 1, 1), civil_from_days(0));
+        assert_eq!(4, weekday_from_days(0));
+    }
+
+    #[test]
+    fn test_known_dates() {
+        assert_eq!(-1, days_from_civil(1969, 12, 31));
+        assert_eq!(10_957, days_from_civil(2000, 1, 1));
+        assert_eq!(19_782, days_from_civil(2024, 2, 29));
+        assert_eq!((2024, 2, 29), civil_from_days(19_782));
+        assert_eq!((2024, 3, 1), civil_from_days(19_783));
+        // 2024-02-29 was a Thursday, 2000-01-01 a Saturday
+        assert_eq!(4, weekday_from_days(19_782));
+        assert_eq!(6, weekday_from_days(10_957));
+        assert_eq!(
+            (1900, 3, 1),
+            civil_from_days(days_from_civil(1900, 2, 28) + 1)
+        );
+        assert_eq!(-719_528, days_from_civil(0, 1, 1));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // a few eras around the epoch, including the years before year 0
+        for days in (-800_000..800_000).step_by(13) {
+            let (year, month, day) = civil_from_days(days);
+            assert!((1..=12).contains(&month));
+            assert!((1..=31).contains(&day));
+            assert_eq!(days, days_from_civil(year, month, day));
+        }
+    }
+
+    #[test]
+    fn test_far_away() {
+        for year in [-1_000_000_000, 1_000_000_000] {
+            let days = days_from_civil(year, 7, 14);
+            assert_eq!((year, 7, 14), civil_from_days(days));
+        }
+    }
+
+    #[test]
+    fn test_is_leap_year() {
+        assert!(is_leap_year(2024));
+        assert!(is_leap_year(2000));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2023));
+        assert!(is_leap_year(-4));
+    }
+}
@@ -129,6 +129,7 @@ impl<T> FVec<T> {
             pub fn len(&self) -> usize;
             pub fn pop(&mut self) -> Option<T>;
             pub fn push_within_capacity(&mut self, t: T) -> Result<(), T>;
+            pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F);
             pub fn try_reserve(&mut self, additional: usize) -> Result<(), TryReserveError>;
             pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError>;
         }
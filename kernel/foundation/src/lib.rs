@@ -15,4 +15,5 @@ pub mod future;
 pub mod io;
 pub mod mem;
 pub mod net;
+pub mod random;
 pub mod time;
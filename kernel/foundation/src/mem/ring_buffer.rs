@@ -41,6 +41,24 @@ impl<T> RingBuffer<T> {
         })
     }
 
+    /// Returns the number of elements that can currently be read.
+    pub fn len(&self) -> usize {
+        if self.read_pos <= self.write_pos {
+            self.write_pos - self.read_pos
+        } else {
+            self.data.len() - self.read_pos + self.write_pos
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read_pos == self.write_pos
+    }
+
+    /// Returns whether a write would block because there is no space left.
+    pub fn is_full(&self) -> bool {
+        (self.write_pos + 1) % self.data.len() == self.read_pos
+    }
+
     pub fn current(&self) -> (&[T], Option<&[T]>) {
         if self.read_pos <= self.write_pos {
             (&self.data[self.read_pos..self.write_pos], None)
@@ -148,4 +166,21 @@ mod tests {
         let second: &[u8] = b"llo";
         assert_eq!((first, Some(second)), buf.current());
     }
+
+    #[test]
+    fn test_len() {
+        let mut buf = RingBuffer::try_with_size(6).unwrap();
+        assert!(buf.is_empty());
+        assert_eq!(0, buf.len());
+
+        buf.write_exact(b"abcd").unwrap();
+        assert_eq!(4, buf.len());
+        buf.read_exact(&mut [0; 3]).unwrap();
+        assert_eq!(1, buf.len());
+
+        buf.write_exact(b"efgh").unwrap();
+        assert_eq!(5, buf.len());
+        assert!(buf.is_full());
+        assert!(!buf.is_empty());
+    }
 }
@@ -0,0 +1,185 @@
+use crate::random::RandomSource;
+
+const BLOCK_LEN: usize = 64;
+const KEY_LEN: usize = 32;
+/// "expand 32-byte k"
+const CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+
+/// A pseudo random number generator that outputs the ChaCha20 key stream.
+///
+/// After every call to [`RandomSource::fill_bytes`], the key is replaced with
+/// fresh output of the key stream, so that a leaked state can't be used to
+/// reconstruct earlier output.
+pub struct ChaCha20Rng {
+    key: [u32; 8],
+    counter: u64,
+    buffer: [u8; BLOCK_LEN],
+    /// The number of bytes in `buffer` that have already been used.
+    position: usize,
+}
+
+impl ChaCha20Rng {
+    pub fn from_seed(seed: [u8; KEY_LEN]) -> Self {
+        Self {
+            key: key_from_bytes(&seed),
+            counter: 0,
+            buffer: [0; BLOCK_LEN],
+            position: BLOCK_LEN,
+        }
+    }
+
+    fn refill(&mut self) {
+        self.buffer = block(&self.key, self.counter, 0);
+        self.counter = self.counter.wrapping_add(1);
+        self.position = 0;
+    }
+
+    fn fill_from_stream(&mut self, buf: &mut [u8]) {
+        let mut done = 0;
+        while done < buf.len() {
+            if self.position == BLOCK_LEN {
+                self.refill();
+            }
+            let n = (BLOCK_LEN - self.position).min(buf.len() - done);
+            buf[done..done + n].copy_from_slice(&self.buffer[self.position..self.position + n]);
+            // don't keep output around that has already been handed out
+            self.buffer[self.position..self.position + n].fill(0);
+            self.position += n;
+            done += n;
+        }
+    }
+
+    fn rekey(&mut self, entropy: &[u8]) {
+        let mut key = [0_u8; KEY_LEN];
+        self.fill_from_stream(&mut key);
+        for (i, b) in entropy.iter().enumerate() {
+            key[i % KEY_LEN] ^= b;
+        }
+        self.key = key_from_bytes(&key);
+        self.counter = 0;
+        self.position = BLOCK_LEN;
+    }
+}
+
+impl RandomSource for ChaCha20Rng {
+    fn fill_bytes(&mut self, buf: &mut [u8]) {
+        self.fill_from_stream(buf);
+        self.rekey(&[]);
+    }
+
+    fn add_entropy(&mut self, entropy: &[u8]) {
+        self.rekey(entropy);
+    }
+}
+
+fn key_from_bytes(bytes: &[u8; KEY_LEN]) -> [u32; 8] {
+    let mut key = [0_u32; 8];
+    for (word, chunk) in key.iter_mut().zip(bytes.as_chunks::<4>().0) {
+        *word = u32::from_le_bytes(*chunk);
+    }
+    key
+}
+
+/// Computes a single ChaCha20 block. The 64 bit counter and the 64 bit nonce
+/// occupy the last four words of the state, like in the original ChaCha.
+fn block(key: &[u32; 8], counter: u64, nonce: u64) -> [u8; BLOCK_LEN] {
+    let mut state = [0_u32; 16];
+    state[..4].copy_from_slice(&CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter as u32;
+    state[13] = (counter >> 32) as u32;
+    state[14] = nonce as u32;
+    state[15] = (nonce >> 32) as u32;
+
+    let mut working = state;
+    for _ in 0..10 {
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut out = [0_u8; BLOCK_LEN];
+    for (chunk, (w, s)) in out
+        .as_chunks_mut::<4>()
+        .0
+        .iter_mut()
+        .zip(working.iter().zip(state))
+    {
+        *chunk = w.wrapping_add(s).to_le_bytes();
+    }
+    out
+}
+
+fn quarter_round(s: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(16);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(12);
+    s[a] = s[a].wrapping_add(s[b]);
+    s[d] = (s[d] ^ s[a]).rotate_left(8);
+    s[c] = s[c].wrapping_add(s[d]);
+    s[b] = (s[b] ^ s[c]).rotate_left(7);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_rfc8439() {
+        // test vector from RFC 8439, section 2.3.2, with the 96 bit nonce and
+        // 32 bit counter mapped onto the 64 bit counter and nonce
+        let mut seed = [0_u8; KEY_LEN];
+        seed.iter_mut().enumerate().for_each(|(i, b)| *b = i as u8);
+        let key = key_from_bytes(&seed);
+
+        let out = block(&key, 1 | (0x0900_0000 << 32), 0x4a00_0000);
+        assert_eq!(
+            [
+                0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20,
+                0x71, 0xc4, 0xc7, 0xd1, 0xf4, 0xc7, 0x33, 0xc0, 0x68, 0x03, 0x04, 0x22, 0xaa, 0x9a,
+                0xc3, 0xd4, 0x6c, 0x4e, 0xd2, 0x82, 0x64, 0x46, 0x07, 0x9f, 0xaa, 0x09, 0x14, 0xc2,
+                0xd7, 0x05, 0xd9, 0x8b, 0x02, 0xa2, 0xb5, 0x12, 0x9c, 0xd1, 0xde, 0x16, 0x4e, 0xb9,
+                0xcb, 0xd0, 0x83, 0xe8, 0xa2, 0x50, 0x3c, 0x4e,
+            ],
+            out
+        );
+    }
+
+    #[test]
+    fn test_rng_key_stream() {
+        // the first output of a fresh generator is the plain key stream
+        let mut rng = ChaCha20Rng::from_seed([0; KEY_LEN]);
+        let mut buf = [0_u8; 8];
+        rng.fill_bytes(&mut buf);
+        assert_eq!([0x76, 0xb8, 0xe0, 0xad, 0xa0, 0xf1, 0x3d, 0x90], buf);
+    }
+
+    #[test]
+    fn test_rng_output_differs() {
+        let mut rng = ChaCha20Rng::from_seed([0; KEY_LEN]);
+        let mut first = [0_u8; 100];
+        let mut second = [0_u8; 100];
+        rng.fill_bytes(&mut first);
+        rng.fill_bytes(&mut second);
+        assert_ne!(first, second);
+        assert!(second.iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn test_add_entropy() {
+        let mut a = ChaCha20Rng::from_seed([7; KEY_LEN]);
+        let mut b = ChaCha20Rng::from_seed([7; KEY_LEN]);
+        b.add_entropy(b"some entropy");
+
+        let (mut out_a, mut out_b) = ([0_u8; 32], [0_u8; 32]);
+        a.fill_bytes(&mut out_a);
+        b.fill_bytes(&mut out_b);
+        assert_ne!(out_a, out_b);
+    }
+}
@@ -0,0 +1,15 @@
+pub use chacha::*;
+
+mod chacha;
+
+/// A source of random bytes.
+pub trait RandomSource {
+    /// Fills `buf` with random bytes.
+    fn fill_bytes(&mut self, buf: &mut [u8]);
+
+    /// Mixes `entropy` into the state of this source. Sources that can't make
+    /// use of additional entropy ignore it.
+    fn add_entropy(&mut self, entropy: &[u8]) {
+        let _ = entropy;
+    }
+}
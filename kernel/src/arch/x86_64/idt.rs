@@ -1,5 +1,7 @@
+use crate::arch::signal;
 use crate::arch::syscall::syscall_handler_impl;
 use crate::driver::apic::LAPIC;
+use crate::driver::ide;
 use crate::driver::rtl8139::rtl8139_interrupt_handler;
 use crate::process;
 use crate::process::vmm;
@@ -7,7 +9,7 @@ use alloc::boxed::Box;
 use conquer_once::spin::OnceCell;
 use core::mem::transmute;
 use core::pin::Pin;
-use kernel_api::syscall::SYSCALL_INTERRUPT_INDEX;
+use kernel_api::syscall::{SigInfo, SEGV_ACCERR, SEGV_MAPERR, SIGSEGV, SYSCALL_INTERRUPT_INDEX};
 use log::{info, warn};
 use num_enum::IntoPrimitive;
 use seq_macro::seq;
@@ -283,6 +285,12 @@ extern "x86-interrupt" fn invalid_opcode_handler(stack_frame: InterruptStackFram
 }
 
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // disk commands that never complete would otherwise never wake their waiters
+    ide::check_timeouts();
+    // a kernel test that hangs would otherwise hang the whole test kernel
+    #[cfg(feature = "kernel_test")]
+    crate::test_runner::check_timeout();
+
     unsafe {
         end_of_interrupt();
     }
@@ -292,7 +300,7 @@ extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFr
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
     use x86_64::registers::control::Cr2;
@@ -301,36 +309,52 @@ extern "x86-interrupt" fn page_fault_handler(
 
     let accessed_address = Cr2::read();
 
-    let do_panic = || -> ! {
-        panic!(
-            "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
-            accessed_address, error_code, stack_frame
-        );
-    };
-
     interrupts::enable();
 
     // IMPORTANT: From here, we need to be 100% thread safe!
 
-    let vm_objects = vmm().vm_objects().read();
-    let vm_object = vm_objects
-        .iter()
-        .find(|(_, vm_object)| vm_object.contains_addr(accessed_address))
-        .map(|(_, vm_object)| vm_object);
-
-    if vm_object.is_none() {
-        do_panic();
-    }
+    let code = {
+        let vm_objects = vmm().vm_objects().read();
+        let vm_object = vm_objects
+            .iter()
+            .find(|(_, vm_object)| vm_object.contains_addr(accessed_address))
+            .map(|(_, vm_object)| vm_object);
+
+        match vm_object {
+            None => SEGV_MAPERR,
+            // mappings without access, like guard pages, are never backed by memory
+            Some(vm_object) if !vm_object.flags().contains(PageTableFlags::PRESENT) => SEGV_ACCERR,
+            Some(vm_object)
+                if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+                    && !vm_object.flags().contains(PageTableFlags::WRITABLE) =>
+            {
+                SEGV_ACCERR
+            }
+            Some(vm_object) => {
+                let offset = (accessed_address.as_u64() - vm_object.addr().as_u64()) as usize;
+                vm_object.prepare_for_access(offset).unwrap();
+                return;
+            }
+        }
+    };
 
-    let vm_object = vm_object.unwrap();
-    if error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
-        && !vm_object.flags().contains(PageTableFlags::WRITABLE)
-    {
-        do_panic();
+    // faults of the kernel itself, also on behalf of a process, are bugs
+    if !process::current().is_in_image(stack_frame.instruction_pointer.as_u64() as usize) {
+        panic!(
+            "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+            accessed_address, error_code, stack_frame
+        );
     }
 
-    let offset = (accessed_address.as_u64() - vm_object.addr().as_u64()) as usize;
-    vm_object.prepare_for_access(offset).unwrap();
+    signal::deliver_fault(
+        &mut stack_frame,
+        SigInfo {
+            signo: SIGSEGV as i32,
+            code,
+            addr: accessed_address.as_u64() as usize,
+            ..Default::default()
+        },
+    );
 }
 
 extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
@@ -2,5 +2,6 @@ pub mod gdt;
 pub mod idt;
 pub mod panic;
 pub mod serial;
+pub mod signal;
 pub mod switch;
 pub mod syscall;
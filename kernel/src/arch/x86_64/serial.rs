@@ -1,17 +1,136 @@
+use alloc::sync::Arc;
+
 use conquer_once::spin::Lazy;
+use foundation::io::{Read, Write};
+use foundation::mem::RingBuffer;
+use log::{debug, warn};
 use spin::Mutex;
 use uart_16550::SerialPort;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::arch::idt;
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::apic;
+use crate::process::wait_queue::WaitQueue;
+
+const SERIAL1_BASE: u16 = 0x3F8;
+/// The ISA IRQ of the first serial port.
+const SERIAL1_IRQ: u8 = 4;
+
+/// How many received bytes are buffered until they are read.
+const INPUT_BUFFER_SIZE: usize = 4096;
 
 static SERIAL1: Lazy<Mutex<SerialPort>> = Lazy::new(|| {
-    let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+    let mut serial_port = unsafe { SerialPort::new(SERIAL1_BASE) };
     serial_port.init();
+    // the port only raises interrupts once they're routed, see
+    // `init_input_interrupt`
+    unsafe { Port::<u8>::new(SERIAL1_BASE + 1).write(0) };
     Mutex::new(serial_port)
 });
 
+/// The bytes that were received through the serial interface, but not read yet.
+/// It's filled by the interrupt handler, so it must only be locked with
+/// interrupts disabled.
+static INPUT: Lazy<Mutex<RingBuffer<u8>>> = Lazy::new(|| {
+    Mutex::new(
+        RingBuffer::try_with_size(INPUT_BUFFER_SIZE)
+            .expect("should be able to allocate the serial input buffer"),
+    )
+});
+
+/// The threads that wait for input, which the interrupt handler wakes.
+static INPUT_WAITERS: Lazy<Arc<WaitQueue>> = Lazy::new(|| Arc::new(WaitQueue::new()));
+
+/// Moves the bytes that the serial port has received into the input buffer.
+/// Bytes that don't fit into the buffer stay in the port until there is space.
+fn receive_pending(input: &mut RingBuffer<u8>) {
+    let mut port = SERIAL1.lock();
+    while !input.is_full() {
+        let Ok(byte) = port.try_receive() else {
+            break;
+        };
+        let _ = input.write(&[byte]);
+    }
+}
+
+/// Makes the serial port raise an interrupt when it receives bytes, so that
+/// readers of the input can wait for it instead of checking it over and over.
+/// Without the interrupt, nothing wakes them, so this must be called before
+/// anyone waits for input.
+pub fn init_input_interrupt() {
+    // the interrupt handler must not allocate
+    Lazy::force(&INPUT);
+    Lazy::force(&INPUT_WAITERS);
+
+    let Some(vector) = idt::next_free_interrupt_vector() else {
+        warn!("no free interrupt vector for the serial port");
+        return;
+    };
+    idt::register_interrupt_handler(vector, input_interrupt_handler);
+    if let Err(e) = apic::route_isa_irq(SERIAL1_IRQ, vector) {
+        warn!("failed to route IRQ {SERIAL1_IRQ} of the serial port: {e}");
+        return;
+    }
+    debug!("IRQ {SERIAL1_IRQ} of the serial port is interrupt {vector}");
+
+    interrupts::without_interrupts(|| {
+        let _port = SERIAL1.lock();
+        // raise an interrupt when a byte is received
+        unsafe { Port::<u8>::new(SERIAL1_BASE + 1).write(1) };
+    });
+}
+
+extern "x86-interrupt" fn input_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    // bytes that don't fit into the buffer stay in the port until the woken
+    // readers make room
+    receive_pending(&mut INPUT.lock());
+    INPUT_WAITERS.wake_all();
+    unsafe { end_of_interrupt() };
+}
+
+/// The queue that is woken when bytes are received that can be read with
+/// [`read_input`].
+pub fn input_waiters() -> Arc<WaitQueue> {
+    INPUT_WAITERS.clone()
+}
+
+/// Reads bytes that were received through the serial interface into `buf`, and
+/// returns how many bytes were read. This doesn't wait for input, so it returns
+/// zero if nothing was received.
+pub fn read_input(buf: &mut [u8]) -> usize {
+    interrupts::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        receive_pending(&mut input);
+        input.read(buf).unwrap_or(0)
+    })
+}
+
+/// Whether there are received bytes that can be read with [`read_input`].
+pub fn has_input() -> bool {
+    interrupts::without_interrupts(|| {
+        let mut input = INPUT.lock();
+        receive_pending(&mut input);
+        !input.is_empty()
+    })
+}
+
+/// Writes the given bytes to the serial interface as they are. Nothing else
+/// that is printed is interleaved with them.
+pub fn write_bytes(bytes: &[u8]) {
+    interrupts::without_interrupts(|| {
+        let mut port = SERIAL1.lock();
+        for &byte in bytes {
+            port.send_raw(byte);
+        }
+    });
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
     use core::fmt::Write;
-    use x86_64::instructions::interrupts;
 
     // disable interrupts while holding a lock on the WRITER
     // so that no deadlock can occur when we want to print
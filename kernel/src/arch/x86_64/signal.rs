@@ -0,0 +1,176 @@
+//! Entering and leaving signal handlers.
+//!
+//! Interrupt handlers run on the stack of the thread that they interrupted,
+//! right below its stack pointer, so they can't build the frame of a signal
+//! handler there. Instead, they only point the thread at
+//! [`signal_trampoline`] and move its stack pointer below the red zone. The
+//! trampoline then builds the [`SignalFrame`] once the interrupt handler has
+//! returned, and calls the handler, which returns to the restorer of the
+//! signal action. The restorer makes the `sigreturn` syscall with the frame,
+//! and the thread continues where it was interrupted when that syscall
+//! returns.
+
+use core::mem::{offset_of, size_of};
+
+use kernel_api::syscall::{SigInfo, SignalContext, SignalFrame};
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use crate::arch::syscall::SyscallRegisters;
+use crate::process;
+use crate::process::signal::{Action, Interrupted};
+use crate::process::Process;
+
+/// The bytes below the stack pointer that the interrupted code may use
+/// without moving the stack pointer.
+const RED_ZONE: u64 = 128;
+
+/// The flags in `rflags` that the interrupted code may change, which are
+/// the status flags, the trap flag and the direction flag.
+const USER_FLAGS: u64 = 0xDD5;
+
+/// Delivers the pending signals of the current process to the current
+/// thread, which is about to return from a syscall. If the syscall was
+/// `sigreturn`, the thread continues at the context that was passed to it
+/// instead.
+pub(in crate::arch) fn return_from_syscall(
+    stack_frame: &mut InterruptStackFrame,
+    regs: &mut SyscallRegisters,
+) {
+    let process = process::current();
+    let tid = *process::current_thread().id();
+    let action = {
+        let mut signals = process.signals();
+        if let Some(context) = signals.take_return(tid) {
+            restore(stack_frame, regs, &context);
+        }
+        signals.next(tid, interrupted(stack_frame))
+    };
+    enter(process, stack_frame, action);
+}
+
+/// Delivers the signal in `info` to the current thread, which caused it by
+/// faulting. If the signal can't be handled, the process is terminated.
+pub(in crate::arch) fn deliver_fault(stack_frame: &mut InterruptStackFrame, info: SigInfo) {
+    let process = process::current();
+    let tid = *process::current_thread().id();
+    let action = process.signals().fault(info, tid, interrupted(stack_frame));
+    enter(process, stack_frame, action);
+}
+
+fn interrupted(stack_frame: &InterruptStackFrame) -> Interrupted {
+    Interrupted {
+        rip: stack_frame.instruction_pointer.as_u64() as usize,
+        rsp: stack_frame.stack_pointer.as_u64() as usize,
+        rflags: stack_frame.cpu_flags as usize,
+    }
+}
+
+fn enter(process: &Process, stack_frame: &mut InterruptStackFrame, action: Action) {
+    match action {
+        Action::Resume => {}
+        Action::Terminate(sig) => process.terminate_by_signal(sig),
+        Action::Handle => unsafe {
+            stack_frame.as_mut().update(|frame| {
+                let rsp = ((frame.stack_pointer.as_u64() - RED_ZONE) & !15)
+                    - size_of::<SignalFrame>() as u64;
+                frame.stack_pointer = VirtAddr::new(rsp);
+                frame.instruction_pointer = VirtAddr::new(signal_trampoline as *const () as u64);
+            });
+        },
+    }
+}
+
+fn restore(
+    stack_frame: &mut InterruptStackFrame,
+    regs: &mut SyscallRegisters,
+    context: &SignalContext,
+) {
+    regs.rax = context.rax;
+    regs.rcx = context.rcx;
+    regs.rdx = context.rdx;
+    regs.rsi = context.rsi;
+    regs.rdi = context.rdi;
+    regs.r8 = context.r8;
+    regs.r9 = context.r9;
+    regs.r10 = context.r10;
+    regs.r11 = context.r11;
+    unsafe {
+        stack_frame.as_mut().update(|frame| {
+            // sys_sigreturn only accepts canonical addresses
+            frame.instruction_pointer = VirtAddr::new(context.rip as u64);
+            frame.stack_pointer = VirtAddr::new(context.rsp as u64);
+            frame.cpu_flags =
+                (frame.cpu_flags & !USER_FLAGS) | (context.rflags as u64 & USER_FLAGS);
+        });
+    }
+}
+
+/// What the trampoline needs from [`setup_signal_frame`].
+#[repr(C)]
+struct HandlerEntry {
+    handler: usize,
+    restorer: usize,
+}
+
+/// Fills in the parts of the frame that the trampoline doesn't know.
+extern "sysv64" fn setup_signal_frame(frame: &mut SignalFrame) -> HandlerEntry {
+    let tid = *process::current_thread().id();
+    let delivery = process::current()
+        .signals()
+        .take_delivery(tid)
+        .expect("signal trampoline entered without a signal to deliver");
+    frame.info = delivery.info;
+    frame.context.rip = delivery.interrupted.rip;
+    frame.context.rsp = delivery.interrupted.rsp;
+    frame.context.rflags = delivery.interrupted.rflags;
+    frame.context.mask = delivery.mask;
+    HandlerEntry {
+        handler: delivery.handler,
+        restorer: delivery.restorer,
+    }
+}
+
+/// Where a thread continues after an interrupt handler decided to deliver a
+/// signal to it. The stack pointer points to the space for the
+/// [`SignalFrame`], and all registers still have the values of the
+/// interrupted code.
+///
+/// The handler is called as `handler(signo, &frame.info, &frame.context)`,
+/// and returns to the restorer with the stack pointer at the frame.
+#[naked]
+unsafe extern "sysv64" fn signal_trampoline() -> ! {
+    core::arch::naked_asm!(
+        "mov [rsp + {rax}], rax",
+        "mov [rsp + {rcx}], rcx",
+        "mov [rsp + {rdx}], rdx",
+        "mov [rsp + {rsi}], rsi",
+        "mov [rsp + {rdi}], rdi",
+        "mov [rsp + {r8}], r8",
+        "mov [rsp + {r9}], r9",
+        "mov [rsp + {r10}], r10",
+        "mov [rsp + {r11}], r11",
+        "cld",
+        "mov rdi, rsp",
+        "call {setup}",
+        // the handler returns to the restorer
+        "push rdx",
+        "mov edi, [rsp + 8 + {signo}]",
+        "lea rsi, [rsp + 8 + {info}]",
+        "lea rdx, [rsp + 8 + {context}]",
+        "jmp rax",
+        rax = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, rax),
+        rcx = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, rcx),
+        rdx = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, rdx),
+        rsi = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, rsi),
+        rdi = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, rdi),
+        r8 = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, r8),
+        r9 = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, r9),
+        r10 = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, r10),
+        r11 = const offset_of!(SignalFrame, context) + offset_of!(SignalContext, r11),
+        signo = const offset_of!(SignalFrame, info) + offset_of!(SigInfo, signo),
+        info = const offset_of!(SignalFrame, info),
+        context = const offset_of!(SignalFrame, context),
+        setup = sym setup_signal_frame,
+    );
+}
@@ -1,5 +1,6 @@
 use x86_64::structures::idt::InterruptStackFrame;
 
+use crate::arch::signal;
 use crate::syscall::dispatch_syscall;
 
 #[repr(align(8), C)]
@@ -17,7 +18,7 @@ pub(in crate::arch) struct SyscallRegisters {
 }
 
 pub(in crate::arch) extern "sysv64" fn syscall_handler_impl(
-    _stack_frame: &mut InterruptStackFrame,
+    stack_frame: &mut InterruptStackFrame,
     regs: &mut SyscallRegisters,
 ) {
     // The registers order follow the System V ABI convention
@@ -32,4 +33,6 @@ pub(in crate::arch) extern "sysv64" fn syscall_handler_impl(
     let res = dispatch_syscall(n, arg1, arg2, arg3, arg4, arg5, arg6);
 
     regs.rax = res as usize; // save result
+
+    signal::return_from_syscall(stack_frame, regs);
 }
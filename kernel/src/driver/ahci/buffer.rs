@@ -0,0 +1,96 @@
+use alloc::vec::Vec;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+
+use crate::mem::virt::OwnedInterval;
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+use crate::{map_page, unmap_page};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// Memory that the HBA transfers data from or to. Every frame is described by
+/// its own PRD entry, so the frames don't have to be contiguous.
+///
+/// The frames are mapped contiguously into the current address space for as
+/// long as the buffer lives, so the buffer must not be used from a different
+/// address space.
+pub struct DataBuffer {
+    frames: Vec<PhysFrame>,
+    interval: OwnedInterval<'static>,
+    len: usize,
+}
+
+impl DataBuffer {
+    /// Allocates a buffer of `len` bytes. Returns `None` if there is not
+    /// enough memory, or if the memory is above 4GiB and `below_4gib` is set,
+    /// because the HBA only takes 32 bit addresses.
+    pub fn allocate(len: usize, below_4gib: bool) -> Option<Self> {
+        let count = len.div_ceil(PAGE_SIZE);
+        let mut frames = Vec::with_capacity(count);
+        while frames.len() < count {
+            match PhysicalMemoryManager::allocate_frame() {
+                Some(frame) if !below_4gib || frame.start_address().as_u64() < 1 << 32 => {
+                    frames.push(frame)
+                }
+                other => {
+                    other
+                        .into_iter()
+                        .chain(frames)
+                        .for_each(PhysicalMemoryManager::deallocate_frame);
+                    return None;
+                }
+            }
+        }
+        let Ok(interval) = vmm().reserve(count * PAGE_SIZE) else {
+            frames
+                .into_iter()
+                .for_each(PhysicalMemoryManager::deallocate_frame);
+            return None;
+        };
+
+        let first = Page::<Size4KiB>::containing_address(interval.start());
+        for (i, &frame) in frames.iter().enumerate() {
+            map_page!(
+                first + i as u64,
+                frame,
+                Size4KiB,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+            );
+        }
+        Some(Self {
+            frames,
+            interval,
+            len,
+        })
+    }
+
+    /// The physical memory of the buffer, as address and length.
+    pub fn regions(&self) -> impl Iterator<Item = (u64, usize)> + '_ {
+        self.frames.iter().enumerate().map(|(i, frame)| {
+            let len = (self.len - i * PAGE_SIZE).min(PAGE_SIZE);
+            (frame.start_address().as_u64(), len)
+        })
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts(self.interval.start().as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { from_raw_parts_mut(self.interval.start().as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for DataBuffer {
+    fn drop(&mut self) {
+        let first = Page::<Size4KiB>::containing_address(self.interval.start());
+        for i in 0..self.frames.len() {
+            unmap_page!(first + i as u64, Size4KiB);
+        }
+        core::mem::take(&mut self.frames)
+            .into_iter()
+            .for_each(PhysicalMemoryManager::deallocate_frame);
+    }
+}
@@ -0,0 +1,198 @@
+use core::hint::spin_loop;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicBool, AtomicU32};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use foundation::time::Instant;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::VirtAddr;
+
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::ahci::AhciError;
+use crate::time::HpetInstantProvider;
+
+/// The generic host control registers.
+pub const CAP: usize = 0x00;
+pub const GHC: usize = 0x04;
+pub const IS: usize = 0x08;
+pub const PI: usize = 0x0C;
+
+/// The HBA can access memory above 4GiB.
+pub const CAP_64BIT: u32 = 1 << 31;
+
+pub const GHC_RESET: u32 = 1 << 0;
+pub const GHC_INTERRUPT_ENABLE: u32 = 1 << 1;
+pub const GHC_AHCI_ENABLE: u32 = 1 << 31;
+
+/// The registers of a port, relative to the registers of the port.
+pub const PX_CLB: usize = 0x00;
+pub const PX_CLBU: usize = 0x04;
+pub const PX_FB: usize = 0x08;
+pub const PX_FBU: usize = 0x0C;
+pub const PX_IS: usize = 0x10;
+pub const PX_IE: usize = 0x14;
+pub const PX_CMD: usize = 0x18;
+pub const PX_TFD: usize = 0x20;
+pub const PX_SIG: usize = 0x24;
+pub const PX_SSTS: usize = 0x28;
+pub const PX_SERR: usize = 0x30;
+pub const PX_CI: usize = 0x38;
+
+pub const PX_CMD_START: u32 = 1 << 0;
+pub const PX_CMD_FIS_RECEIVE_ENABLE: u32 = 1 << 4;
+pub const PX_CMD_FIS_RECEIVE_RUNNING: u32 = 1 << 14;
+pub const PX_CMD_LIST_RUNNING: u32 = 1 << 15;
+
+/// The interrupts of a port that complete commands.
+pub const PX_IS_D2H_REGISTER_FIS: u32 = 1 << 0;
+pub const PX_IS_PIO_SETUP_FIS: u32 = 1 << 1;
+pub const PX_IS_DMA_SETUP_FIS: u32 = 1 << 2;
+pub const PX_IS_SET_DEVICE_BITS: u32 = 1 << 3;
+pub const PX_IS_TASK_FILE_ERROR: u32 = 1 << 30;
+/// The interrupts of a port that fail the command in flight: task file,
+/// host bus fatal, host bus data and interface fatal errors. The hot plug
+/// interrupts (port connect change and PhyRdy change) are not among them,
+/// they are acknowledged and otherwise ignored.
+pub const PX_IS_ERRORS: u32 = PX_IS_TASK_FILE_ERROR | 1 << 29 | 1 << 28 | 1 << 27;
+
+pub const PX_TFD_ERROR: u32 = 1 << 0;
+pub const PX_TFD_DATA_READY: u32 = 1 << 3;
+pub const PX_TFD_BUSY: u32 = 1 << 7;
+
+/// The signature of a SATA hard disk, as opposed to a packet device or a port
+/// multiplier.
+pub const SIGNATURE_ATA: u32 = 0x0000_0101;
+
+/// The device detection of PxSSTS reports a device with established
+/// communication.
+pub const SSTS_DEVICE_PRESENT: u32 = 3;
+
+/// The distance of the registers of two ports, and where those of port 0 are.
+const PORT_REGISTERS: usize = 0x100;
+const PORT_REGISTERS_LEN: usize = 0x80;
+
+/// The most host bus adapters whose interrupts are handled.
+const MAX_HBAS: usize = 4;
+
+/// How long the HBA may take to reset, as required by the specification.
+const RESET_TIMEOUT: Duration = Duration::from_secs(1);
+
+static HBAS: [OnceCell<&'static Hba>; MAX_HBAS] = [const { OnceCell::uninit() }; MAX_HBAS];
+
+/// The memory mapped registers of a host bus adapter, which ABAR points to.
+pub struct Hba {
+    base: VirtAddr,
+    /// The interrupt status of every port, as acknowledged by the interrupt
+    /// handler, until the command that waits for it takes it.
+    port_status: [AtomicU32; 32],
+    interrupts: AtomicBool,
+}
+
+impl Hba {
+    /// # Safety
+    ///
+    /// The registers of the HBA must be mapped at `base` for as long as the
+    /// HBA lives.
+    pub unsafe fn new(base: VirtAddr) -> Self {
+        Self {
+            base,
+            port_status: [const { AtomicU32::new(0) }; 32],
+            interrupts: AtomicBool::new(false),
+        }
+    }
+
+    pub fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register as u64).as_ptr()) }
+    }
+
+    pub fn write(&self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register as u64).as_mut_ptr(), value) }
+    }
+
+    pub fn read_port(&self, port: usize, register: usize) -> u32 {
+        self.read(PORT_REGISTERS + port * PORT_REGISTERS_LEN + register)
+    }
+
+    pub fn write_port(&self, port: usize, register: usize, value: u32) {
+        self.write(PORT_REGISTERS + port * PORT_REGISTERS_LEN + register, value)
+    }
+
+    pub fn supports_64bit(&self) -> bool {
+        self.read(CAP) & CAP_64BIT != 0
+    }
+
+    /// Resets the HBA, which also resets its ports, and puts it into AHCI
+    /// mode with interrupts disabled.
+    pub fn reset(&self) -> Result<(), AhciError> {
+        self.write(GHC, GHC_AHCI_ENABLE);
+        self.write(GHC, GHC_AHCI_ENABLE | GHC_RESET);
+        let start = Instant::now();
+        while self.read(GHC) & GHC_RESET != 0 {
+            if start.elapsed() > RESET_TIMEOUT {
+                return Err(AhciError::ResetTimeout);
+            }
+            spin_loop();
+        }
+        // the reset may have cleared AHCI mode
+        self.write(GHC, GHC_AHCI_ENABLE);
+        self.write(IS, !0);
+        Ok(())
+    }
+
+    pub fn implemented_ports(&self) -> impl Iterator<Item = usize> {
+        let implemented = self.read(PI);
+        (0..32).filter(move |port| implemented & (1 << port) != 0)
+    }
+
+    /// Whether the interrupt of the HBA is routed, so that waiting for a
+    /// command can halt instead of spinning.
+    pub fn interrupts_enabled(&self) -> bool {
+        self.interrupts.load(SeqCst)
+    }
+
+    /// Makes the interrupt handler serve this HBA, and enables its interrupt.
+    /// Returns `false` if there are too many HBAs.
+    pub fn enable_interrupts(&'static self) -> bool {
+        if !HBAS.iter().any(|slot| slot.try_init_once(|| self).is_ok()) {
+            return false;
+        }
+        self.interrupts.store(true, SeqCst);
+        self.write(GHC, self.read(GHC) | GHC_INTERRUPT_ENABLE);
+        true
+    }
+
+    /// Takes the interrupt status of the port, both what the interrupt
+    /// handler acknowledged and what is pending, and acknowledges it.
+    pub fn take_port_status(&self, port: usize) -> u32 {
+        let pending = self.read_port(port, PX_IS);
+        self.write_port(port, PX_IS, pending);
+        self.port_status[port].swap(0, SeqCst) | pending
+    }
+
+    fn handle(&self) {
+        let pending = self.read(IS);
+        if pending == 0 {
+            // the interrupt line is shared
+            return;
+        }
+        for port in (0..32).filter(|port| pending & (1 << port) != 0) {
+            let status = self.read_port(port, PX_IS);
+            self.write_port(port, PX_IS, status);
+            self.port_status[port].fetch_or(status, SeqCst);
+        }
+        // the ports are acknowledged first, otherwise IS would be set again
+        self.write(IS, pending);
+    }
+}
+
+/// Acknowledges the interrupts of all HBAs. The commands that wait for them
+/// find out themselves whether they completed, the interrupt only ends their
+/// halt.
+pub extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    for hba in HBAS.iter().filter_map(OnceCell::get) {
+        hba.handle();
+    }
+    unsafe { end_of_interrupt() };
+}
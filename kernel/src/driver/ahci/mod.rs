@@ -0,0 +1,255 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Debug, Formatter};
+
+use filesystem::BlockDevice;
+use linkme::distributed_slice;
+use log::{debug, info, warn};
+use spin::Mutex;
+use thiserror::Error;
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::PhysAddr;
+
+use crate::arch::idt;
+use crate::driver::ahci::hba::Hba;
+use crate::driver::ahci::port::{Port, MAX_SECTORS};
+use crate::driver::apic;
+use crate::driver::disk;
+use crate::driver::disk::Disk;
+use crate::driver::ide::{DriveInfo, IdeError};
+use crate::driver::pci::{Bar, PciDevice, PciDriverDescriptor, PciMatch, PCI_DRIVERS};
+use crate::io::vfs::cache::{Flush, MultiBlock};
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::process::vmm;
+
+mod buffer;
+mod hba;
+mod port;
+
+const SECTOR_SIZE: usize = 512;
+
+/// The BAR that holds the registers of the HBA.
+const ABAR: usize = 5;
+
+#[distributed_slice(PCI_DRIVERS)]
+static AHCI_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
+    name: "AHCI",
+    matches: &[PciMatch::Class {
+        class: 0x01,
+        subclass: Some(0x06),
+        prog_if: Some(0x01),
+    }],
+    init,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum AhciError {
+    #[error("device has no memory mapped ABAR")]
+    NoAbar,
+    #[error("host bus adapter did not reset")]
+    ResetTimeout,
+    #[error("command engine of the port did not stop or start")]
+    PortStuck,
+    #[error("failed to allocate memory")]
+    NoMemory,
+    #[error("command timed out")]
+    Timeout,
+    #[error("device reported an error: {0:?}")]
+    Device(IdeError),
+    #[error("host bus or interface error")]
+    Bus,
+    #[error("disk does not support 48 bit LBA")]
+    NoLba48,
+    #[error("device is not connected")]
+    DeviceDisconnected,
+}
+
+fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
+    let device = device.upgrade().ok_or(AhciError::DeviceDisconnected)?;
+    let mut device = device.lock();
+
+    // memory space, so that the ABAR decodes
+    device.command.update(|command| command | 1 << 1);
+    device.enable_bus_mastering();
+
+    let hba = map_abar(&mut device)?;
+    hba.reset()?;
+    enable_interrupts(hba, device.interrupt_line.read());
+
+    for index in hba.implemented_ports() {
+        match Port::new(hba, index) {
+            Ok(Some(port)) => {
+                let disk = AhciBlockDevice {
+                    port: Arc::new(port),
+                };
+                let info = disk.info();
+                info!(
+                    "found {} (serial {}, firmware {}) with {} sectors on AHCI port {index}",
+                    info.model, info.serial, info.firmware, info.sector_count
+                );
+                disk::devices().register(Disk::Ahci(disk))?;
+            }
+            Ok(None) => debug!("no SATA disk on AHCI port {index}"),
+            Err(e) => warn!("failed to set up AHCI port {index}: {e}"),
+        }
+    }
+    Ok(())
+}
+
+/// Maps the registers of the HBA. They stay mapped for as long as the kernel
+/// runs, since the interrupt handler may access them at any time.
+fn map_abar(device: &mut PciDevice) -> Result<&'static Hba, AhciError> {
+    let name = format!("ahci {device} abar");
+    let Some(Bar::Memory { base, size, .. }) = device.bar(ABAR) else {
+        return Err(AhciError::NoAbar);
+    };
+    let size = size.max(Size4KiB::SIZE) as usize;
+    let addr = PhysAddr::try_new(base).map_err(|_| AhciError::NoAbar)?;
+
+    let frames = (0..size.div_ceil(Size4KiB::SIZE as usize))
+        .map(|i| PhysFrame::containing_address(addr + i as u64 * Size4KiB::SIZE))
+        .collect::<Vec<_>>();
+    let base = vmm()
+        .allocate_memory_backed_vmobject(
+            name,
+            MapAt::Anywhere,
+            frames.len() * Size4KiB::SIZE as usize,
+            AllocationStrategy::MapNow(&frames),
+            PageTableFlags::PRESENT
+                | PageTableFlags::NO_CACHE
+                | PageTableFlags::NO_EXECUTE
+                | PageTableFlags::WRITABLE,
+        )
+        .map_err(|_| AhciError::NoMemory)?;
+    Ok(Box::leak(Box::new(unsafe { Hba::new(base) })))
+}
+
+/// Routes the interrupt of the HBA. Without it, commands spin until they
+/// complete.
+fn enable_interrupts(hba: &'static Hba, irq: u8) {
+    if irq > 15 {
+        warn!("AHCI controller has no interrupt line, polling instead");
+        return;
+    }
+    let Some(vector) = idt::next_free_interrupt_vector() else {
+        warn!("no free interrupt vector for the AHCI controller, polling instead");
+        return;
+    };
+    idt::register_interrupt_handler(vector, hba::interrupt_handler);
+    if let Err(e) = apic::route_isa_irq(irq, vector) {
+        warn!("failed to route IRQ {irq} of the AHCI controller, polling instead: {e}");
+        return;
+    }
+    if !hba.enable_interrupts() {
+        warn!("too many AHCI controllers, polling instead");
+    }
+}
+
+/// A SATA disk on a port of an AHCI host bus adapter.
+#[derive(Clone)]
+pub struct AhciBlockDevice {
+    port: Arc<Port>,
+}
+
+impl Debug for AhciBlockDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AhciBlockDevice")
+            .field("port", &self.port.index())
+            .field("model", &self.info().model)
+            .finish()
+    }
+}
+
+impl AhciBlockDevice {
+    pub fn info(&self) -> &DriveInfo {
+        self.port.info()
+    }
+
+    pub fn read_sectors(&self, first_sector: usize, buf: &mut [u8]) -> Result<usize, AhciError> {
+        assert_eq!(0, buf.len() % SECTOR_SIZE);
+        for (i, chunk) in buf.chunks_mut(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let buffer = self.port.buffer(chunk.len())?;
+            self.port
+                .read((first_sector + i * MAX_SECTORS) as u64, &buffer)?;
+            chunk.copy_from_slice(buffer.as_slice());
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the sectors through to the medium, like the IDE driver does.
+    pub fn write_sectors(&self, first_sector: usize, buf: &[u8]) -> Result<usize, AhciError> {
+        assert_eq!(0, buf.len() % SECTOR_SIZE);
+        for (i, chunk) in buf.chunks(MAX_SECTORS * SECTOR_SIZE).enumerate() {
+            let mut buffer = self.port.buffer(chunk.len())?;
+            buffer.as_mut_slice().copy_from_slice(chunk);
+            self.port
+                .write((first_sector + i * MAX_SECTORS) as u64, &buffer)?;
+        }
+        self.port.flush()?;
+        Ok(buf.len())
+    }
+
+    pub fn flush_write_cache(&self) -> Result<(), AhciError> {
+        self.port.flush()
+    }
+}
+
+impl BlockDevice for AhciBlockDevice {
+    type Error = AhciError;
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sector_count(&self) -> usize {
+        self.info().sector_count as usize
+    }
+
+    fn read_sector(&self, sector: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_sectors(sector, buf)
+    }
+
+    fn write_sector(&mut self, sector: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_sectors(sector, buf)
+    }
+}
+
+impl MultiBlock for AhciBlockDevice {
+    /// Reads up to [`MAX_SECTORS`] blocks with a single command.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        for (i, chunk) in bufs.chunks_mut(MAX_SECTORS).enumerate() {
+            let buffer = self.port.buffer(chunk.len() * SECTOR_SIZE)?;
+            self.port.read((start + i * MAX_SECTORS) as u64, &buffer)?;
+            for (buf, data) in chunk.iter_mut().zip(buffer.as_slice().chunks(SECTOR_SIZE)) {
+                buf.copy_from_slice(data);
+            }
+        }
+        Ok(bufs.len() * SECTOR_SIZE)
+    }
+
+    /// Writes up to [`MAX_SECTORS`] blocks with a single command.
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        for (i, chunk) in bufs.chunks(MAX_SECTORS).enumerate() {
+            let mut buffer = self.port.buffer(chunk.len() * SECTOR_SIZE)?;
+            for (data, buf) in buffer
+                .as_mut_slice()
+                .chunks_mut(SECTOR_SIZE)
+                .zip(chunk.iter())
+            {
+                data.copy_from_slice(buf);
+            }
+            self.port.write((start + i * MAX_SECTORS) as u64, &buffer)?;
+        }
+        self.port.flush()?;
+        Ok(bufs.len() * SECTOR_SIZE)
+    }
+}
+
+impl Flush for AhciBlockDevice {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.port.flush()
+    }
+}
@@ -0,0 +1,405 @@
+use alloc::format;
+use core::hint::spin_loop;
+use core::ptr::{copy_nonoverlapping, write_bytes};
+use core::sync::atomic::{compiler_fence, Ordering};
+use core::time::Duration;
+
+use foundation::time::Instant;
+use log::warn;
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::driver::ahci::buffer::DataBuffer;
+use crate::driver::ahci::hba::{
+    Hba, PX_CI, PX_CLB, PX_CLBU, PX_CMD, PX_CMD_FIS_RECEIVE_ENABLE, PX_CMD_FIS_RECEIVE_RUNNING,
+    PX_CMD_LIST_RUNNING, PX_CMD_START, PX_FB, PX_FBU, PX_IE, PX_IS_D2H_REGISTER_FIS,
+    PX_IS_DMA_SETUP_FIS, PX_IS_ERRORS, PX_IS_PIO_SETUP_FIS, PX_IS_SET_DEVICE_BITS,
+    PX_IS_TASK_FILE_ERROR, PX_SERR, PX_SIG, PX_SSTS, PX_TFD, PX_TFD_BUSY, PX_TFD_DATA_READY,
+    PX_TFD_ERROR, SIGNATURE_ATA, SSTS_DEVICE_PRESENT,
+};
+use crate::driver::ahci::AhciError;
+use crate::driver::ide::{DriveInfo, IdeError};
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+use crate::time::HpetInstantProvider;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// Where the command list, the received FISes and the command table of the
+/// only slot are in the page of a port. They have to be aligned to 1KiB, 256
+/// bytes and 128 bytes.
+const COMMAND_LIST: usize = 0x000;
+const RECEIVED_FIS: usize = 0x400;
+const COMMAND_TABLE: usize = 0x800;
+/// The PRD table follows the command FIS and the ATAPI command in the command
+/// table.
+const PRD_TABLE: usize = COMMAND_TABLE + 0x80;
+const PRD_ENTRY_LEN: usize = 16;
+const MAX_PRD_ENTRIES: usize = (PAGE_SIZE - PRD_TABLE) / PRD_ENTRY_LEN;
+
+/// The most sectors that one command transfers. Every page of the data buffer
+/// takes a PRD entry, so this must fit into [`MAX_PRD_ENTRIES`] pages.
+pub const MAX_SECTORS: usize = 256;
+const _: () = assert!(MAX_SECTORS * 512 <= MAX_PRD_ENTRIES * PAGE_SIZE);
+
+/// The command slot that every command is issued in. A port has one command
+/// in flight at a time, so there is no need for more.
+const SLOT: usize = 0;
+
+const FIS_TYPE_REGISTER_H2D: u8 = 0x27;
+/// The register FIS carries a command, not a device control update.
+const FIS_COMMAND: u8 = 1 << 7;
+const DEVICE_LBA: u8 = 1 << 6;
+
+const ATA_READ_DMA_EXT: u8 = 0x25;
+const ATA_WRITE_DMA_EXT: u8 = 0x35;
+const ATA_FLUSH_CACHE_EXT: u8 = 0xEA;
+const ATA_IDENTIFY: u8 = 0xEC;
+
+/// How long a command may take. Flushing the cache of a large disk is the
+/// slowest of them.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the command engine may take to stop or start, as required by the
+/// specification.
+const ENGINE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Whether the data of a command goes from the device to memory or the other
+/// way.
+enum Transfer<'a> {
+    None,
+    Read(&'a DataBuffer),
+    Write(&'a DataBuffer),
+}
+
+impl Transfer<'_> {
+    fn buffer(&self) -> Option<&DataBuffer> {
+        match self {
+            Transfer::None => None,
+            Transfer::Read(buffer) | Transfer::Write(buffer) => Some(buffer),
+        }
+    }
+}
+
+/// A port of an HBA with a SATA disk attached. The command list, the
+/// received FISes and the command table live in a single page.
+pub struct Port {
+    hba: &'static Hba,
+    index: usize,
+    memory: VirtAddr,
+    frame: PhysFrame,
+    info: DriveInfo,
+    /// Held while a command is in flight.
+    command: Mutex<()>,
+}
+
+impl Port {
+    /// Starts the port and identifies the disk on it. Returns `Ok(None)` if
+    /// there is no disk, or something that is not a disk, like a packet
+    /// device or a port multiplier.
+    pub fn new(hba: &'static Hba, index: usize) -> Result<Option<Self>, AhciError> {
+        if hba.read_port(index, PX_SSTS) & 0xF != SSTS_DEVICE_PRESENT
+            || hba.read_port(index, PX_SIG) != SIGNATURE_ATA
+        {
+            return Ok(None);
+        }
+
+        let frame = PhysicalMemoryManager::allocate_frame().ok_or(AhciError::NoMemory)?;
+        if !hba.supports_64bit() && frame.start_address().as_u64() >= 1 << 32 {
+            PhysicalMemoryManager::deallocate_frame(frame);
+            return Err(AhciError::NoMemory);
+        }
+        let memory = vmm()
+            .allocate_memory_backed_vmobject(
+                format!("ahci port {index}"),
+                MapAt::Anywhere,
+                PAGE_SIZE,
+                AllocationStrategy::MapNow(&[frame]),
+                PageTableFlags::PRESENT
+                    | PageTableFlags::NO_CACHE
+                    | PageTableFlags::NO_EXECUTE
+                    | PageTableFlags::WRITABLE,
+            )
+            .map_err(|_| AhciError::NoMemory)?;
+        unsafe { write_bytes(memory.as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+
+        let mut port = Self {
+            hba,
+            index,
+            memory,
+            frame,
+            info: DriveInfo::from_identify(&[0; 256]),
+            command: Mutex::new(()),
+        };
+        port.stop()?;
+        let phys = frame.start_address().as_u64();
+        let command_list = phys + COMMAND_LIST as u64;
+        let received_fis = phys + RECEIVED_FIS as u64;
+        hba.write_port(index, PX_CLB, command_list as u32);
+        hba.write_port(index, PX_CLBU, (command_list >> 32) as u32);
+        hba.write_port(index, PX_FB, received_fis as u32);
+        hba.write_port(index, PX_FBU, (received_fis >> 32) as u32);
+        // whatever happened before the reset is of no interest
+        hba.write_port(index, PX_SERR, !0);
+        hba.take_port_status(index);
+        hba.write_port(
+            index,
+            PX_IE,
+            PX_IS_D2H_REGISTER_FIS
+                | PX_IS_PIO_SETUP_FIS
+                | PX_IS_DMA_SETUP_FIS
+                | PX_IS_SET_DEVICE_BITS
+                | PX_IS_ERRORS,
+        );
+        port.start()?;
+
+        let buffer = port.buffer(512)?;
+        port.execute(ATA_IDENTIFY, 0, 0, Transfer::Read(&buffer))?;
+        let mut words = [0_u16; 256];
+        for (word, bytes) in words.iter_mut().zip(buffer.as_slice().chunks_exact(2)) {
+            *word = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+        port.info = DriveInfo::from_identify(&words);
+        // every SATA disk should, and the DMA EXT commands need it
+        if !port.info.lba48 {
+            return Err(AhciError::NoLba48);
+        }
+        Ok(Some(port))
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn info(&self) -> &DriveInfo {
+        &self.info
+    }
+
+    /// Allocates a buffer that the HBA can transfer `len` bytes from or to.
+    pub fn buffer(&self, len: usize) -> Result<DataBuffer, AhciError> {
+        DataBuffer::allocate(len, !self.hba.supports_64bit()).ok_or(AhciError::NoMemory)
+    }
+
+    /// Reads as many sectors as fit into the buffer, which are at most
+    /// [`MAX_SECTORS`].
+    pub fn read(&self, lba: u64, buffer: &DataBuffer) -> Result<(), AhciError> {
+        let count = sector_count(buffer);
+        self.execute(ATA_READ_DMA_EXT, lba, count, Transfer::Read(buffer))
+    }
+
+    /// Writes as many sectors as fit into the buffer, which are at most
+    /// [`MAX_SECTORS`].
+    pub fn write(&self, lba: u64, buffer: &DataBuffer) -> Result<(), AhciError> {
+        let count = sector_count(buffer);
+        self.execute(ATA_WRITE_DMA_EXT, lba, count, Transfer::Write(buffer))
+    }
+
+    pub fn flush(&self) -> Result<(), AhciError> {
+        self.execute(ATA_FLUSH_CACHE_EXT, 0, 0, Transfer::None)
+    }
+
+    /// Issues the command and waits for it to complete.
+    fn execute(
+        &self,
+        command: u8,
+        lba: u64,
+        count: u16,
+        transfer: Transfer<'_>,
+    ) -> Result<(), AhciError> {
+        let _command = self.command.lock();
+
+        let memory = self.memory.as_mut_ptr::<u8>();
+        let fis = command_fis(command, lba, count);
+        let mut entries = 0;
+        unsafe {
+            write_bytes(memory.add(COMMAND_TABLE), 0, PAGE_SIZE - COMMAND_TABLE);
+            copy_nonoverlapping(fis.as_ptr(), memory.add(COMMAND_TABLE), fis.len());
+            for (addr, len) in transfer.buffer().into_iter().flat_map(DataBuffer::regions) {
+                assert!(entries < MAX_PRD_ENTRIES, "too many PRD entries");
+                let entry = memory
+                    .add(PRD_TABLE + entries * PRD_ENTRY_LEN)
+                    .cast::<u32>();
+                entry.write_volatile(addr as u32);
+                entry.add(1).write_volatile((addr >> 32) as u32);
+                entry.add(3).write_volatile(len as u32 - 1);
+                entries += 1;
+            }
+
+            let header = memory.add(COMMAND_LIST + SLOT * 32).cast::<u32>();
+            let write = matches!(transfer, Transfer::Write(_)) as u32;
+            let fis_dwords = (fis.len() / 4) as u32;
+            header.write_volatile(fis_dwords | write << 6 | (entries as u32) << 16);
+            // the number of bytes that were transferred, updated by the HBA
+            header.add(1).write_volatile(0);
+            let table = self.frame.start_address().as_u64() + COMMAND_TABLE as u64;
+            header.add(2).write_volatile(table as u32);
+            header.add(3).write_volatile((table >> 32) as u32);
+        }
+        // the command must be in memory before the HBA is told about it
+        compiler_fence(Ordering::SeqCst);
+
+        self.hba.take_port_status(self.index);
+        self.hba.write_port(self.index, PX_CI, 1 << SLOT);
+        self.wait()
+    }
+
+    /// Waits for the command in the slot to complete, and restarts the port
+    /// if it failed.
+    fn wait(&self) -> Result<(), AhciError> {
+        let start = Instant::now();
+        loop {
+            let status = self.hba.take_port_status(self.index);
+            if status & PX_IS_ERRORS != 0 {
+                let error = if status & PX_IS_TASK_FILE_ERROR != 0 {
+                    self.device_error()
+                } else {
+                    AhciError::Bus
+                };
+                self.recover();
+                return Err(error);
+            }
+            if self.hba.read_port(self.index, PX_CI) & (1 << SLOT) == 0 {
+                break;
+            }
+            if start.elapsed() > COMMAND_TIMEOUT {
+                self.recover();
+                return Err(AhciError::Timeout);
+            }
+            if self.hba.interrupts_enabled() && interrupts::are_enabled() {
+                hlt();
+            } else {
+                spin_loop();
+            }
+        }
+
+        if self.hba.read_port(self.index, PX_TFD) & PX_TFD_ERROR != 0 {
+            let error = self.device_error();
+            self.recover();
+            return Err(error);
+        }
+        Ok(())
+    }
+
+    /// The error that the device reported, which is in the error register
+    /// that PxTFD mirrors.
+    fn device_error(&self) -> AhciError {
+        let tfd = self.hba.read_port(self.index, PX_TFD);
+        AhciError::Device(IdeError::from_bits_truncate((tfd >> 8) as u8))
+    }
+
+    /// Restarts the command engine, which stops by itself when a command
+    /// fails, so that the next command can be issued.
+    fn recover(&self) {
+        if let Err(e) = self.stop() {
+            warn!("AHCI port {} did not stop after an error: {e}", self.index);
+        }
+        self.hba.write_port(self.index, PX_SERR, !0);
+        self.hba.take_port_status(self.index);
+        if let Err(e) = self.start() {
+            warn!(
+                "AHCI port {} did not restart after an error: {e}",
+                self.index
+            );
+        }
+    }
+
+    fn stop(&self) -> Result<(), AhciError> {
+        let cmd = self.hba.read_port(self.index, PX_CMD);
+        self.hba.write_port(
+            self.index,
+            PX_CMD,
+            cmd & !(PX_CMD_START | PX_CMD_FIS_RECEIVE_ENABLE),
+        );
+        self.wait_for_port(
+            |port| {
+                port.hba.read_port(port.index, PX_CMD)
+                    & (PX_CMD_LIST_RUNNING | PX_CMD_FIS_RECEIVE_RUNNING)
+                    == 0
+            },
+            ENGINE_TIMEOUT,
+        )
+    }
+
+    fn start(&self) -> Result<(), AhciError> {
+        // the engine must not start while the device is still busy
+        self.wait_for_port(
+            |port| port.hba.read_port(port.index, PX_TFD) & (PX_TFD_BUSY | PX_TFD_DATA_READY) == 0,
+            COMMAND_TIMEOUT,
+        )?;
+        let cmd = self.hba.read_port(self.index, PX_CMD) | PX_CMD_FIS_RECEIVE_ENABLE;
+        self.hba.write_port(self.index, PX_CMD, cmd);
+        self.hba.write_port(self.index, PX_CMD, cmd | PX_CMD_START);
+        Ok(())
+    }
+
+    fn wait_for_port(
+        &self,
+        condition: impl Fn(&Self) -> bool,
+        timeout: Duration,
+    ) -> Result<(), AhciError> {
+        let start = Instant::now();
+        while !condition(self) {
+            if start.elapsed() > timeout {
+                return Err(AhciError::PortStuck);
+            }
+            spin_loop();
+        }
+        Ok(())
+    }
+}
+
+fn sector_count(buffer: &DataBuffer) -> u16 {
+    let count = buffer.as_slice().len() / 512;
+    assert!(count <= MAX_SECTORS, "{count} sectors in a single command");
+    count as u16
+}
+
+/// Builds the register FIS that carries a command with a 48 bit LBA.
+fn command_fis(command: u8, lba: u64, count: u16) -> [u8; 20] {
+    let lba = lba.to_le_bytes();
+    let count = count.to_le_bytes();
+    [
+        FIS_TYPE_REGISTER_H2D,
+        FIS_COMMAND,
+        command,
+        0,
+        lba[0],
+        lba[1],
+        lba[2],
+        DEVICE_LBA,
+        lba[3],
+        lba[4],
+        lba[5],
+        0,
+        count[0],
+        count[1],
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+    ]
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ahci::port::command_fis;
+
+    #[kernel_test]
+    fn test_command_fis() {
+        let fis = command_fis(0x25, 0x0605_0403_0201, 0x0180);
+        assert_eq!(0x27, fis[0]);
+        assert_eq!(0x80, fis[1]);
+        assert_eq!(0x25, fis[2]);
+        assert_eq!([0x01, 0x02, 0x03], fis[4..7]);
+        assert_eq!(0x40, fis[7]);
+        assert_eq!([0x04, 0x05, 0x06], fis[8..11]);
+        assert_eq!([0x80, 0x01], fis[12..14]);
+        assert!(fis[14..].iter().all(|&b| b == 0));
+    }
+}
@@ -1,7 +1,8 @@
-use acpi::platform::interrupt::Apic;
+use acpi::platform::interrupt::{Apic, Polarity, TriggerMode};
 use alloc::alloc::Global;
 use alloc::format;
 use alloc::string::ToString;
+use alloc::vec::Vec;
 use conquer_once::spin::OnceCell;
 use spin::Mutex;
 use x2apic::ioapic::{IoApic, IrqFlags, IrqMode, RedirectionTableEntry};
@@ -23,12 +24,37 @@ pub static KERNEL_LAPIC_LEN: Size = Size::KiB(4); // 1 page
 pub static KERNEL_IOAPIC_ADDR: OnceCell<VirtAddr> = OnceCell::uninit();
 pub static KERNEL_IOAPIC_LEN: Size = Size::KiB(4); // 1 page
 
+/// The mapped IO APICs, with the first global system interrupt that each of
+/// them handles, and the number of interrupts that it handles.
+static IO_APICS: Mutex<Vec<(VirtAddr, u32, u32)>> = Mutex::new(Vec::new());
+/// The ISA IRQs that the firmware connected to a different global system
+/// interrupt or with different flags than the ISA defaults.
+static ISA_OVERRIDES: Mutex<Vec<(u8, u32, IrqFlags)>> = Mutex::new(Vec::new());
+/// The address range that message signaled interrupts are written to.
+const MSI_ADDRESS: u64 = 0xFEE0_0000;
+/// The local APIC that routed interrupts are delivered to.
+static DESTINATION: OnceCell<u8> = OnceCell::uninit();
+
 pub fn init(apic: Apic<Global>) -> Result<()> {
     disable_8259();
 
     let lapic_address = apic.local_apic_address;
 
     let lapic_id = init_lapic(lapic_address)?;
+    DESTINATION.init_once(|| u8::try_from(lapic_id).unwrap());
+
+    ISA_OVERRIDES
+        .lock()
+        .extend(apic.interrupt_source_overrides.iter().map(|o| {
+            let mut flags = IrqFlags::empty();
+            if o.polarity == Polarity::ActiveLow {
+                flags |= IrqFlags::LOW_ACTIVE;
+            }
+            if o.trigger_mode == TriggerMode::Level {
+                flags |= IrqFlags::LEVEL_TRIGGERED;
+            }
+            (o.isa_source, o.global_system_interrupt, flags)
+        }));
 
     for (i, io_apic) in apic.io_apics.iter().enumerate() {
         let ioapic_phys_addr = PhysAddr::try_new(io_apic.address as u64)
@@ -64,12 +90,68 @@ pub fn init(apic: Apic<Global>) -> Result<()> {
                 ioapic.set_table_entry(vector, entry);
                 ioapic.enable_irq(vector);
             }
+            let entries = u32::from(ioapic.max_table_entry()) + 1;
+            IO_APICS.lock().push((
+                ioapic_virtual_address,
+                io_apic.global_system_interrupt_base,
+                entries,
+            ));
         }
     }
 
     Ok(())
 }
 
+/// Delivers the given ISA IRQ (like IRQ 14 of the primary IDE channel) as the
+/// given interrupt vector. Overrides of the firmware are taken into account,
+/// otherwise the IRQ is edge triggered and active high, like on the ISA bus.
+pub fn route_isa_irq(irq: u8, vector: u8) -> Result<()> {
+    let (gsi, flags) = ISA_OVERRIDES
+        .lock()
+        .iter()
+        .find(|(source, _, _)| *source == irq)
+        .map_or((u32::from(irq), IrqFlags::empty()), |&(_, gsi, flags)| {
+            (gsi, flags)
+        });
+    let destination = *DESTINATION
+        .get()
+        .ok_or("the local APIC is not initialized")?;
+
+    let io_apics = IO_APICS.lock();
+    let &(addr, base, _) = io_apics
+        .iter()
+        .find(|(_, base, entries)| (*base..*base + *entries).contains(&gsi))
+        .ok_or_else(|| format!("no IO APIC handles global system interrupt {gsi}"))?;
+    let index = u8::try_from(gsi - base).unwrap();
+
+    let mut entry = RedirectionTableEntry::default();
+    entry.set_mode(IrqMode::Fixed);
+    entry.set_flags(flags);
+    entry.set_vector(vector);
+    entry.set_dest(destination);
+    unsafe {
+        // safety: the IO APIC is mapped at this address since `init`
+        let mut ioapic = IoApic::new(addr.as_u64());
+        ioapic.set_table_entry(index, entry);
+        ioapic.enable_irq(index);
+    }
+    Ok(())
+}
+
+/// The address and the data of a message signaled interrupt, like MSI-X,
+/// that delivers the given interrupt vector. Like routed interrupts, the
+/// message goes to the local APIC of the bootstrap processor.
+pub fn msi_message(vector: u8) -> Result<(u64, u32)> {
+    let destination = *DESTINATION
+        .get()
+        .ok_or("the local APIC is not initialized")?;
+    // fixed delivery mode and edge triggered, which are all zeros
+    Ok((
+        MSI_ADDRESS | u64::from(destination) << 12,
+        u32::from(vector),
+    ))
+}
+
 fn init_lapic(lapic_address: u64) -> Result<u32> {
     debug_assert_eq!(unsafe { xapic_base() }, lapic_address);
     let lapic_phys_addr = PhysAddr::try_new(lapic_address)
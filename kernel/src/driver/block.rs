@@ -0,0 +1,343 @@
+//! A registry of block devices that drivers add their devices to, and remove
+//! them from when the hardware goes away.
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::alloc::AllocError;
+use core::ops::Deref;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::SeqCst;
+
+use filesystem::BlockDevice;
+use foundation::falloc::vec::FVec;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use spin::{Mutex, MutexGuard};
+
+use crate::io::vfs::cache::{Flush, MultiBlock};
+
+/// A change of the devices in a [`BlockDevices`] registry.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlockDeviceEvent {
+    /// The device with the given index was added.
+    Registered(usize),
+    /// The device with the given index was removed, and can't be accessed
+    /// anymore.
+    Unregistered(usize),
+}
+
+type Subscriber = Box<dyn Fn(BlockDeviceEvent) + Send + Sync>;
+
+/// The block devices of a driver, by their index.
+///
+/// Indices are never reused. A device that is unregistered keeps its slot,
+/// but it is revoked, so that nobody who still holds a clone of it touches
+/// the hardware anymore.
+pub struct BlockDevices<T> {
+    devices: Mutex<FVec<Revocable<T>>>,
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl<T> Default for BlockDevices<T> {
+    fn default() -> Self {
+        Self {
+            devices: Mutex::default(),
+            subscribers: Mutex::default(),
+        }
+    }
+}
+
+impl<T> BlockDevices<T>
+where
+    T: Clone,
+{
+    pub fn lock(&self) -> MutexGuard<'_, FVec<Revocable<T>>> {
+        self.devices.lock()
+    }
+
+    /// Adds the device and returns its index.
+    pub fn register(&self, device: T) -> Result<usize, AllocError> {
+        let index = {
+            let mut devices = self.devices.lock();
+            devices
+                .try_push(Revocable::new(device))
+                .map_err(|_| AllocError)?;
+            devices.len() - 1
+        };
+        self.notify(BlockDeviceEvent::Registered(index));
+        Ok(index)
+    }
+
+    /// Revokes the device with the given index. Accesses through clones of the
+    /// device fail with [`RevocableError::DeviceGone`] from now on. Returns
+    /// `false` if there is no such device, or if it was already removed.
+    pub fn unregister(&self, index: usize) -> bool {
+        let revoked = match self.devices.lock().get(index) {
+            Some(device) => !device.revoked.swap(true, SeqCst),
+            None => false,
+        };
+        if revoked {
+            self.notify(BlockDeviceEvent::Unregistered(index));
+        }
+        revoked
+    }
+
+    /// Calls `callback` for every device that is registered or unregistered
+    /// from now on. Subscribers are called in the order in which they
+    /// subscribed, without the registry being locked, but they must not
+    /// subscribe themselves.
+    pub fn on_change<F>(&self, callback: F)
+    where
+        F: Fn(BlockDeviceEvent) + Send + Sync + 'static,
+    {
+        self.subscribers.lock().push(Box::new(callback));
+    }
+
+    fn notify(&self, event: BlockDeviceEvent) {
+        for subscriber in self.subscribers.lock().iter() {
+            subscriber(event);
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RevocableError<E> {
+    /// The device was removed from its registry.
+    DeviceGone,
+    Device(E),
+}
+
+/// A block device in a [`BlockDevices`] registry. Clones share whether the
+/// device was revoked. Inherent methods of the device are available through
+/// [`Deref`], but only the block device traits check for revocation.
+#[derive(Debug, Clone)]
+pub struct Revocable<T> {
+    device: T,
+    revoked: Arc<AtomicBool>,
+}
+
+impl<T> Revocable<T> {
+    fn new(device: T) -> Self {
+        Self {
+            device,
+            revoked: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.load(SeqCst)
+    }
+
+    /// Narrows the device down to what `f` returns for it, like a variant of
+    /// an enum of devices. The result is revoked together with this device.
+    pub fn filter_map<U>(&self, f: impl FnOnce(&T) -> Option<&U>) -> Option<Revocable<U>>
+    where
+        U: Clone,
+    {
+        f(&self.device).map(|device| Revocable {
+            device: device.clone(),
+            revoked: self.revoked.clone(),
+        })
+    }
+
+    fn check<E>(&self) -> Result<(), RevocableError<E>> {
+        if self.is_revoked() {
+            Err(RevocableError::DeviceGone)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<T> Deref for Revocable<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.device
+    }
+}
+
+impl<T> BlockDevice for Revocable<T>
+where
+    T: BlockDevice,
+{
+    type Error = RevocableError<T::Error>;
+
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> usize {
+        self.device.sector_count()
+    }
+
+    fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.check()?;
+        self.device
+            .read_sector(sector_index, buf)
+            .map_err(RevocableError::Device)
+    }
+
+    fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.check()?;
+        self.device
+            .write_sector(sector_index, buf)
+            .map_err(RevocableError::Device)
+    }
+}
+
+impl<T> MultiBlock for Revocable<T>
+where
+    T: MultiBlock,
+{
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        self.check()?;
+        self.device
+            .read_blocks(start, bufs)
+            .map_err(RevocableError::Device)
+    }
+
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        self.check()?;
+        self.device
+            .write_blocks(start, bufs)
+            .map_err(RevocableError::Device)
+    }
+
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            self.check()?;
+            self.device
+                .read_blocks_async(start, buf)
+                .await
+                .map_err(RevocableError::Device)
+        }
+        .boxed()
+    }
+}
+
+impl<T> Flush for Revocable<T>
+where
+    T: Flush,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.check()?;
+        self.device.flush().map_err(RevocableError::Device)
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use filesystem::BlockDevice;
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+
+    use crate::driver::block::{BlockDeviceEvent, BlockDevices, RevocableError};
+
+    #[derive(Clone)]
+    struct Memory(Arc<Mutex<[u8; 512]>>);
+
+    impl BlockDevice for Memory {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            512
+        }
+
+        fn sector_count(&self) -> usize {
+            1
+        }
+
+        fn read_sector(&self, _: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            buf.copy_from_slice(&*self.0.lock());
+            Ok(buf.len())
+        }
+
+        fn write_sector(&mut self, _: usize, buf: &[u8]) -> Result<usize, ()> {
+            self.0.lock().copy_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[kernel_test]
+    fn test_unregister_revokes_held_devices() {
+        let devices = BlockDevices::default();
+        let index = devices
+            .register(Memory(Arc::new(Mutex::new([7; 512]))))
+            .unwrap();
+        let mut held = devices.lock()[index].clone();
+        let mut buf = [0; 512];
+        assert_eq!(Ok(512), held.read_sector(0, &mut buf));
+        assert_eq!([7; 512], buf);
+
+        assert!(devices.unregister(index));
+        assert!(held.is_revoked());
+        assert_eq!(
+            Err(RevocableError::DeviceGone),
+            held.read_sector(0, &mut buf)
+        );
+        assert_eq!(
+            Err(RevocableError::DeviceGone),
+            held.write_sector(0, &[1; 512])
+        );
+        // the memory behind the device was never touched
+        assert_eq!([7; 512], *held.0.lock());
+
+        assert!(!devices.unregister(index));
+        assert!(!devices.unregister(index + 1));
+    }
+
+    #[kernel_test]
+    fn test_narrowed_devices_are_revoked_together() {
+        let devices = BlockDevices::default();
+        let index = devices
+            .register((Memory(Arc::new(Mutex::new([7; 512]))), ()))
+            .unwrap();
+        let memory = devices.lock()[index]
+            .filter_map(|(memory, _)| Some(memory))
+            .unwrap();
+        assert!(devices.lock()[index]
+            .filter_map(|_| None::<&Memory>)
+            .is_none());
+
+        devices.unregister(index);
+        assert!(memory.is_revoked());
+        assert_eq!(
+            Err(RevocableError::DeviceGone),
+            memory.read_sector(0, &mut [0; 512])
+        );
+    }
+
+    #[kernel_test]
+    fn test_subscribers_see_events_in_order() {
+        let devices = BlockDevices::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        for subscriber in 0..2 {
+            let seen = seen.clone();
+            devices.on_change(move |event| seen.lock().push((subscriber, event)));
+        }
+
+        let index = devices
+            .register(Memory(Arc::new(Mutex::new([0; 512]))))
+            .unwrap();
+        devices.unregister(index);
+        assert_eq!(
+            vec![
+                (0, BlockDeviceEvent::Registered(index)),
+                (1, BlockDeviceEvent::Registered(index)),
+                (0, BlockDeviceEvent::Unregistered(index)),
+                (1, BlockDeviceEvent::Unregistered(index)),
+            ],
+            *seen.lock()
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! The disks of all storage drivers in a single [`BlockDevices`] registry, so
+//! that the devfs, the partition scanner and the mounts don't have to know
+//! which controller a disk is attached to.
+
+use alloc::boxed::Box;
+use core::alloc::AllocError;
+use core::error::Error;
+
+use conquer_once::spin::OnceCell;
+use filesystem::BlockDevice;
+use foundation::falloc::vec::FVec;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::{debug, info, warn};
+use spin::Mutex;
+use thiserror::Error;
+
+use crate::driver::ahci::{AhciBlockDevice, AhciError};
+use crate::driver::block::{BlockDeviceEvent, BlockDevices, Revocable};
+use crate::driver::ide::{DriveInfo, IdeBlockDevice};
+use crate::driver::nvme::{NvmeBlockDevice, NvmeError};
+use crate::driver::virtio::blk::{VirtioBlkDevice, VirtioBlkError};
+use crate::io::partition;
+use crate::io::partition::Partition;
+use crate::io::vfs::cache::{Flush, MultiBlock};
+use crate::io::vfs::devfs;
+
+static DISKS: OnceCell<BlockDevices<Disk>> = OnceCell::uninit();
+static PARTITIONS: OnceCell<Mutex<FVec<DiskPartition>>> = OnceCell::uninit();
+
+/// A disk on any of the storage controllers.
+#[derive(Debug, Clone)]
+pub enum Disk {
+    Ide(IdeBlockDevice),
+    Ahci(AhciBlockDevice),
+    Nvme(NvmeBlockDevice),
+    VirtioBlk(VirtioBlkDevice),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum DiskError {
+    #[error("IDE transfer failed")]
+    Ide,
+    #[error(transparent)]
+    Ahci(#[from] AhciError),
+    #[error(transparent)]
+    Nvme(#[from] NvmeError),
+    #[error(transparent)]
+    VirtioBlk(#[from] VirtioBlkError),
+}
+
+impl Disk {
+    pub fn as_ide(&self) -> Option<&IdeBlockDevice> {
+        match self {
+            Disk::Ide(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    pub fn as_ahci(&self) -> Option<&AhciBlockDevice> {
+        match self {
+            Disk::Ahci(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    pub fn as_nvme(&self) -> Option<&NvmeBlockDevice> {
+        match self {
+            Disk::Nvme(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    pub fn as_virtio_blk(&self) -> Option<&VirtioBlkDevice> {
+        match self {
+            Disk::VirtioBlk(device) => Some(device),
+            _ => None,
+        }
+    }
+
+    /// What an ATA drive reported about itself.
+    pub fn info(&self) -> Option<&DriveInfo> {
+        match self {
+            Disk::Ide(device) => Some(device.info()),
+            Disk::Ahci(device) => Some(device.info()),
+            Disk::Nvme(_) | Disk::VirtioBlk(_) => None,
+        }
+    }
+}
+
+impl BlockDevice for Disk {
+    type Error = DiskError;
+
+    fn sector_size(&self) -> usize {
+        match self {
+            Disk::Ide(device) => device.sector_size(),
+            Disk::Ahci(device) => device.sector_size(),
+            Disk::Nvme(device) => device.sector_size(),
+            Disk::VirtioBlk(device) => device.sector_size(),
+        }
+    }
+
+    fn sector_count(&self) -> usize {
+        match self {
+            Disk::Ide(device) => device.sector_count(),
+            Disk::Ahci(device) => device.sector_count(),
+            Disk::Nvme(device) => device.sector_count(),
+            Disk::VirtioBlk(device) => device.sector_count(),
+        }
+    }
+
+    fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self {
+            Disk::Ide(device) => device
+                .read_sector(sector_index, buf)
+                .map_err(|_| DiskError::Ide),
+            Disk::Ahci(device) => Ok(device.read_sector(sector_index, buf)?),
+            Disk::Nvme(device) => Ok(device.read_sector(sector_index, buf)?),
+            Disk::VirtioBlk(device) => Ok(device.read_sector(sector_index, buf)?),
+        }
+    }
+
+    fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self {
+            Disk::Ide(device) => device
+                .write_sector(sector_index, buf)
+                .map_err(|_| DiskError::Ide),
+            Disk::Ahci(device) => Ok(device.write_sector(sector_index, buf)?),
+            Disk::Nvme(device) => Ok(device.write_sector(sector_index, buf)?),
+            Disk::VirtioBlk(device) => Ok(device.write_sector(sector_index, buf)?),
+        }
+    }
+}
+
+impl MultiBlock for Disk {
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        match self {
+            Disk::Ide(device) => device.read_blocks(start, bufs).map_err(|_| DiskError::Ide),
+            Disk::Ahci(device) => Ok(device.read_blocks(start, bufs)?),
+            Disk::Nvme(device) => Ok(device.read_blocks(start, bufs)?),
+            Disk::VirtioBlk(device) => Ok(device.read_blocks(start, bufs)?),
+        }
+    }
+
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        match self {
+            Disk::Ide(device) => device.write_blocks(start, bufs).map_err(|_| DiskError::Ide),
+            Disk::Ahci(device) => Ok(device.write_blocks(start, bufs)?),
+            Disk::Nvme(device) => Ok(device.write_blocks(start, bufs)?),
+            Disk::VirtioBlk(device) => Ok(device.write_blocks(start, bufs)?),
+        }
+    }
+
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            match self {
+                Disk::Ide(device) => device
+                    .read_blocks_async(start, buf)
+                    .await
+                    .map_err(|_| DiskError::Ide),
+                Disk::Ahci(device) => Ok(device.read_blocks_async(start, buf).await?),
+                Disk::Nvme(device) => Ok(device.read_blocks_async(start, buf).await?),
+                Disk::VirtioBlk(device) => Ok(device.read_blocks_async(start, buf).await?),
+            }
+        }
+        .boxed()
+    }
+}
+
+impl Flush for Disk {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        match self {
+            Disk::Ide(device) => device.flush().map_err(|_| DiskError::Ide),
+            Disk::Ahci(device) => Ok(device.flush()?),
+            Disk::Nvme(device) => Ok(device.flush()?),
+            Disk::VirtioBlk(device) => Ok(device.flush()?),
+        }
+    }
+}
+
+/// A partition on one of the [`devices`].
+#[derive(Debug, Clone)]
+pub struct DiskPartition {
+    /// The index of the disk in [`devices`].
+    pub device: usize,
+    pub partition: Partition<Revocable<Disk>>,
+}
+
+/// The disks of all controllers, by the order in which they were found. Their
+/// partitions are read as soon as they are registered.
+pub fn devices() -> &'static BlockDevices<Disk> {
+    DISKS.get_or_init(|| {
+        let disks = BlockDevices::default();
+        // the first subscriber, so that the partitions of a disk exist before
+        // anyone else hears of it
+        disks.on_change(scan_partitions);
+        disks
+    })
+}
+
+pub fn partitions() -> &'static Mutex<FVec<DiskPartition>> {
+    PARTITIONS.get_or_init(Mutex::default)
+}
+
+/// Keeps the [`partitions`] in sync with the [`devices`].
+fn scan_partitions(event: BlockDeviceEvent) {
+    match event {
+        BlockDeviceEvent::Registered(index) => {
+            if let Err(e) = register_partitions(index) {
+                warn!("failed to register the partitions of block device {index}: {e}");
+            }
+        }
+        BlockDeviceEvent::Unregistered(index) => {
+            info!("block device {index} was removed");
+            partitions()
+                .lock()
+                .retain(|partition| partition.device != index);
+        }
+    }
+}
+
+/// Reads the partition table of the disk with the given index, and registers
+/// its partitions as block devices.
+fn register_partitions(index: usize) -> Result<(), Box<dyn Error>> {
+    let Some(device) = devices().lock().get(index).cloned() else {
+        return Ok(());
+    };
+    let extents = match partition::scan(&device) {
+        Ok(extents) => extents,
+        Err(e) => {
+            // a CD-ROM drive without a medium can't be read, for example
+            debug!("not reading partitions of block device {index}: {e}");
+            return Ok(());
+        }
+    };
+    for extent in extents {
+        info!("block device {index} has partition {extent:?}");
+        let partition = DiskPartition {
+            device: index,
+            partition: Partition::new(device.clone(), extent),
+        };
+        if partitions().lock().try_push(partition.clone()).is_err() {
+            return Err(Box::new(AllocError));
+        }
+        if let Some(devfs) = devfs::devfs() {
+            if let Err(e) = devfs.write().register_partition(&partition) {
+                warn!(
+                    "failed to publish partition {} of block device {index} in devfs: {e:?}",
+                    extent.number
+                );
+            }
+        }
+    }
+    Ok(())
+}
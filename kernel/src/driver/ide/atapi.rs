@@ -0,0 +1,266 @@
+use core::fmt::{Display, Formatter};
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::driver::ide::channel::IdeChannel;
+use crate::driver::ide::command::Command;
+use crate::driver::ide::Status;
+
+/// The size of a sector of an optical medium.
+pub const ATAPI_SECTOR_SIZE: usize = 2048;
+
+/// The signature that a packet device leaves in the LBA mid and high registers
+/// after it rejected an IDENTIFY command.
+pub const ATAPI_SIGNATURE: (u8, u8) = (0x14, 0xEB);
+
+/// How often a command is retried if the device reports a unit attention,
+/// which it does once for the first command after a reset or a medium change.
+const UNIT_ATTENTION_RETRIES: usize = 3;
+
+/// The most bytes that the device transfers per DRQ block. It must be even and
+/// must not be 0xFFFF.
+const BYTE_COUNT_LIMIT: u16 = 0xF800;
+
+const REQUEST_SENSE: u8 = 0x03;
+const READ_CAPACITY: u8 = 0x25;
+const READ_12: u8 = 0xA8;
+
+/// The additional sense code for a missing medium.
+const ASC_MEDIUM_NOT_PRESENT: u8 = 0x3A;
+
+/// The sense key of a SCSI command that failed, as reported by REQUEST SENSE.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SenseKey {
+    NoSense,
+    RecoveredError,
+    NotReady,
+    MediumError,
+    HardwareError,
+    IllegalRequest,
+    UnitAttention,
+    DataProtect,
+    AbortedCommand,
+    Other(u8),
+}
+
+impl From<u8> for SenseKey {
+    fn from(value: u8) -> Self {
+        match value & 0x0F {
+            0x0 => Self::NoSense,
+            0x1 => Self::RecoveredError,
+            0x2 => Self::NotReady,
+            0x3 => Self::MediumError,
+            0x4 => Self::HardwareError,
+            0x5 => Self::IllegalRequest,
+            0x6 => Self::UnitAttention,
+            0x7 => Self::DataProtect,
+            0xB => Self::AbortedCommand,
+            v => Self::Other(v),
+        }
+    }
+}
+
+/// Why a packet command failed.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Sense {
+    pub key: SenseKey,
+    /// The additional sense code.
+    pub asc: u8,
+    /// The additional sense code qualifier.
+    pub ascq: u8,
+}
+
+impl Sense {
+    /// Decodes fixed format sense data.
+    pub fn from_sense_data(data: &[u8; 18]) -> Self {
+        Self {
+            key: SenseKey::from(data[2]),
+            asc: data[12],
+            ascq: data[13],
+        }
+    }
+
+    pub fn is_medium_not_present(&self) -> bool {
+        self.key == SenseKey::NotReady && self.asc == ASC_MEDIUM_NOT_PRESENT
+    }
+}
+
+impl Display for Sense {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        if self.is_medium_not_present() {
+            write!(f, "medium not present")
+        } else {
+            write!(
+                f,
+                "{:?} (asc={:#04X} ascq={:#04X})",
+                self.key, self.asc, self.ascq
+            )
+        }
+    }
+}
+
+/// Reads the capacity of the medium, and returns the number of sectors and
+/// the size of a sector.
+pub fn read_capacity(channel: &mut IdeChannel, drive_num: u8) -> Result<(u64, usize), Sense> {
+    let mut packet = [0_u8; 12];
+    packet[0] = READ_CAPACITY;
+    let mut data = [0_u8; 8];
+    send_packet(channel, drive_num, &packet, &mut data)?;
+    let last_lba = u32::from_be_bytes(data[0..4].try_into().unwrap());
+    let block_len = u32::from_be_bytes(data[4..8].try_into().unwrap());
+    Ok((last_lba as u64 + 1, block_len as usize))
+}
+
+/// Reads the sectors starting at `first_sector` into `buf`, whose length must
+/// be a multiple of [`ATAPI_SECTOR_SIZE`].
+pub fn read_sectors(
+    channel: &mut IdeChannel,
+    drive_num: u8,
+    first_sector: usize,
+    buf: &mut [u8],
+) -> Result<usize, Sense> {
+    assert_eq!(0, buf.len() % ATAPI_SECTOR_SIZE);
+    let count = buf.len() / ATAPI_SECTOR_SIZE;
+    let mut packet = [0_u8; 12];
+    packet[0] = READ_12;
+    packet[2..6].copy_from_slice(&(first_sector as u32).to_be_bytes());
+    packet[6..10].copy_from_slice(&(count as u32).to_be_bytes());
+    send_packet(channel, drive_num, &packet, buf)
+}
+
+/// Sends the packet and reads the data that the device returns into `buf`.
+/// If the device reports an error, the reason is fetched with REQUEST SENSE.
+fn send_packet(
+    channel: &mut IdeChannel,
+    drive_num: u8,
+    packet: &[u8; 12],
+    buf: &mut [u8],
+) -> Result<usize, Sense> {
+    let mut retries = 0;
+    loop {
+        match transfer(channel, drive_num, packet, buf) {
+            Ok(n) => return Ok(n),
+            Err(()) => {
+                let sense = request_sense(channel, drive_num)?;
+                if sense.key == SenseKey::UnitAttention && retries < UNIT_ATTENTION_RETRIES {
+                    retries += 1;
+                    continue;
+                }
+                return Err(sense);
+            }
+        }
+    }
+}
+
+fn request_sense(channel: &mut IdeChannel, drive_num: u8) -> Result<Sense, Sense> {
+    let mut packet = [0_u8; 12];
+    packet[0] = REQUEST_SENSE;
+    packet[4] = 18;
+    let mut data = [0_u8; 18];
+    // if even this fails, there is nothing that we could tell the caller
+    transfer(channel, drive_num, &packet, &mut data).map_err(|_| Sense {
+        key: SenseKey::HardwareError,
+        asc: 0,
+        ascq: 0,
+    })?;
+    Ok(Sense::from_sense_data(&data))
+}
+
+/// Executes a packet command with PIO, and returns the number of bytes that
+/// the device transferred. Data beyond the end of `buf` is discarded. Fails if
+/// the device reports a check condition.
+fn transfer(
+    channel: &mut IdeChannel,
+    drive_num: u8,
+    packet: &[u8; 12],
+    buf: &mut [u8],
+) -> Result<usize, ()> {
+    let limit = buf
+        .len()
+        .clamp(2, BYTE_COUNT_LIMIT as usize)
+        .next_multiple_of(2) as u16;
+    unsafe {
+        channel.disable_irq();
+        channel.ports.drive_select.write(drive_num);
+        channel.wait_for_not_busy();
+        channel.ports.features.write(0); // PIO
+        channel.ports.lba_mid.write(limit as u8);
+        channel.ports.lba_hi.write((limit >> 8) as u8);
+    }
+    channel.write_command(Command::Packet);
+    channel.wait_for_not_busy();
+    channel.poll_on_status(|status| status.intersects(Status::DATA_READY | Status::ERROR));
+    if channel.status().contains(Status::ERROR) {
+        return Err(());
+    }
+    for word in packet.iter().copied().array_chunks::<2>() {
+        unsafe { channel.ports.data.write(u16::from_le_bytes(word)) };
+    }
+
+    let mut transferred = 0;
+    loop {
+        channel.wait_for_not_busy();
+        let status = channel.status();
+        if status.contains(Status::ERROR) {
+            return Err(());
+        }
+        if !status.contains(Status::DATA_READY) {
+            return Ok(transferred.min(buf.len()));
+        }
+
+        let len = unsafe {
+            channel.ports.lba_mid.read() as usize | (channel.ports.lba_hi.read() as usize) << 8
+        };
+        without_interrupts(|| {
+            for _ in 0..len.div_ceil(2) {
+                let word = unsafe { channel.ports.data.read() }.to_le_bytes();
+                for byte in word {
+                    if let Some(b) = buf.get_mut(transferred) {
+                        *b = byte;
+                    }
+                    transferred += 1;
+                }
+            }
+        });
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use filesystem::BlockDevice;
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide;
+    use crate::driver::ide::atapi::{Sense, SenseKey, ATAPI_SECTOR_SIZE};
+
+    #[kernel_test]
+    fn test_sense_medium_not_present() {
+        let mut data = [0_u8; 18];
+        data[0] = 0x70;
+        data[2] = 0x02;
+        data[12] = 0x3A;
+        let sense = Sense::from_sense_data(&data);
+        assert_eq!(SenseKey::NotReady, sense.key);
+        assert!(sense.is_medium_not_present());
+
+        data[2] = 0x06;
+        data[12] = 0x28;
+        assert!(!Sense::from_sense_data(&data).is_medium_not_present());
+    }
+
+    #[kernel_test]
+    fn test_read_primary_volume_descriptor() {
+        let device = ide::devices()
+            .into_iter()
+            .find(|device| device.is_atapi())
+            .expect("the test runner attaches a CD-ROM");
+        assert_eq!(ATAPI_SECTOR_SIZE, device.sector_size());
+
+        let mut buf = vec![0_u8; ATAPI_SECTOR_SIZE];
+        assert_eq!(Ok(ATAPI_SECTOR_SIZE), device.read_sectors(16, &mut buf));
+        assert_eq!(1, buf[0]); // primary volume descriptor
+        assert_eq!(b"CD001", &buf[1..6]);
+    }
+}
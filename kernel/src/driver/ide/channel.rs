@@ -1,10 +1,17 @@
 use alloc::format;
 use core::fmt::{Debug, Formatter};
+use core::time::Duration;
 
+use foundation::time::Instant;
 use x86_64::instructions::port::{Port, PortReadOnly, PortWriteOnly};
 
 use crate::driver::ide::command::Command;
+use crate::driver::ide::dma::{
+    BUS_MASTER_ERROR, BUS_MASTER_INTERRUPT, BUS_MASTER_READ, BUS_MASTER_START,
+};
+use crate::driver::ide::interrupt::ChannelInterrupt;
 use crate::driver::ide::{IdeError, Status};
+use crate::time::HpetInstantProvider;
 
 #[allow(dead_code)] // a lot of fields are unused, but they exist according to spec, so we keep them
 pub struct IdeChannel {
@@ -15,10 +22,15 @@ pub struct IdeChannel {
     iobase: u16,
     pub ports: ChannelsLBA28DataPorts,
     bmide: u16,
-    master_ports: ChannelsLBA28DataPorts,
+    bus_master: Option<BusMasterPorts>,
+    interrupt: Option<&'static ChannelInterrupt>,
+    /// The drive that the drive select register selects, if we know it.
+    selected: Option<u8>,
 }
 
 impl IdeChannel {
+    /// Creates a channel with the given ports. A `bus_master_ide` base of zero
+    /// means that the controller can't do DMA on this channel.
     pub fn new(ctrlbase: u16, iobase: u16, bus_master_ide: u16) -> Self {
         IdeChannel {
             ctrlbase,
@@ -28,7 +40,9 @@ impl IdeChannel {
             iobase,
             ports: ChannelsLBA28DataPorts::new(iobase),
             bmide: bus_master_ide,
-            master_ports: ChannelsLBA28DataPorts::new(bus_master_ide),
+            bus_master: (bus_master_ide != 0).then(|| BusMasterPorts::new(bus_master_ide)),
+            interrupt: None,
+            selected: None,
         }
     }
 
@@ -48,6 +62,123 @@ impl IdeChannel {
         self.device_control.write(2);
     }
 
+    /// Clears the nIEN bit in the device control port, so that the drive raises
+    /// an interrupt when a command completes.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it writes to a port,
+    /// which could have side effects that violate memory safety.
+    pub unsafe fn enable_irq(&mut self) {
+        self.device_control.write(0);
+    }
+
+    /// The interrupt that the channel raises when a DMA transfer completes, if
+    /// the channel can do DMA transfers.
+    pub fn dma_interrupt(&self) -> Option<&'static ChannelInterrupt> {
+        self.bus_master.as_ref().and(self.interrupt)
+    }
+
+    /// Sets the interrupt that the channel raises. Without one, transfers
+    /// can't be completed on the interrupt, so they only use PIO.
+    pub fn set_interrupt(&mut self, interrupt: &'static ChannelInterrupt) {
+        self.interrupt = Some(interrupt);
+    }
+
+    pub fn bus_master_base(&self) -> u16 {
+        self.bmide
+    }
+
+    /// Points the bus master at the given PRD table, sets the direction of the
+    /// transfer and clears the interrupt and error bits. The transfer starts
+    /// with [`IdeChannel::start_dma`] after the command was sent to the drive.
+    ///
+    /// # Safety
+    ///
+    /// The PRD table and the memory that it describes must stay valid until
+    /// the transfer is stopped.
+    pub unsafe fn prepare_dma(&mut self, prdt: u32, read: bool) {
+        let bus_master = self
+            .bus_master
+            .as_mut()
+            .expect("channel should be a bus master");
+        bus_master.prdt.write(prdt);
+        bus_master
+            .command
+            .write(if read { BUS_MASTER_READ } else { 0 });
+        bus_master
+            .status
+            .write(BUS_MASTER_INTERRUPT | BUS_MASTER_ERROR);
+    }
+
+    /// # Safety
+    ///
+    /// The transfer must have been prepared with [`IdeChannel::prepare_dma`].
+    pub unsafe fn start_dma(&mut self, read: bool) {
+        let bus_master = self
+            .bus_master
+            .as_mut()
+            .expect("channel should be a bus master");
+        let direction = if read { BUS_MASTER_READ } else { 0 };
+        bus_master.command.write(direction | BUS_MASTER_START);
+    }
+
+    /// Stops the transfer, clears the interrupt and error bits and returns the
+    /// bus master status from before they were cleared.
+    ///
+    /// # Safety
+    ///
+    /// This function is unsafe because it writes to a port,
+    /// which could have side effects that violate memory safety.
+    pub unsafe fn stop_dma(&mut self) -> u8 {
+        let bus_master = self
+            .bus_master
+            .as_mut()
+            .expect("channel should be a bus master");
+        bus_master.command.write(0);
+        let status = bus_master.status.read();
+        bus_master
+            .status
+            .write(BUS_MASTER_INTERRUPT | BUS_MASTER_ERROR);
+        status
+    }
+
+    /// Resets the drives of the channel and stops the bus master, so that the
+    /// channel can be used again after a command got lost.
+    pub fn soft_reset(&mut self) {
+        unsafe {
+            if self.bus_master.is_some() {
+                self.stop_dma();
+            }
+            // SRST must be set for at least 5us, and every read of the
+            // alternate status takes about 100ns
+            self.device_control.write(0x04 | 0x02);
+            for _ in 0..64 {
+                let _ = self.alternate_status.read();
+            }
+            self.device_control.write(0x02);
+        }
+        // a reset selects the master
+        self.selected = None;
+        self.wait_for_not_busy();
+    }
+
+    /// Selects the drive, like `0xA0` or `0xB0`, for the next command. After
+    /// switching to the other drive, the status register needs 400ns to
+    /// reflect it, which is waited for by reading the alternate status.
+    pub fn select(&mut self, drive: u8) {
+        if self.selected == Some(drive) {
+            return;
+        }
+        unsafe {
+            self.ports.drive_select.write(drive);
+            for _ in 0..4 {
+                let _ = self.alternate_status.read();
+            }
+        }
+        self.selected = Some(drive);
+    }
+
     pub fn status(&mut self) -> Status {
         unsafe { Status::from_bits_truncate(self.ports.status.read()) }
     }
@@ -82,6 +213,24 @@ impl IdeChannel {
         self.poll(IdeChannel::status, f)
     }
 
+    /// Like [`IdeChannel::poll_on_status`], but gives up after `timeout`, for
+    /// when there may be no drive that ever answers. Returns whether the status
+    /// satisfied `f` in time.
+    pub fn poll_on_status_for<F>(&mut self, f: F, timeout: Duration) -> bool
+    where
+        F: Fn(Status) -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            if f(self.status()) {
+                return true;
+            }
+            if start.elapsed() > timeout {
+                return false;
+            }
+        }
+    }
+
     pub fn poll<P, F, T>(&mut self, p: P, f: F)
     where
         P: Fn(&mut Self) -> T,
@@ -135,3 +284,20 @@ impl ChannelsLBA28DataPorts {
         }
     }
 }
+
+/// The bus master IDE registers of a channel, which control DMA transfers.
+struct BusMasterPorts {
+    command: Port<u8>,
+    status: Port<u8>,
+    prdt: Port<u32>,
+}
+
+impl BusMasterPorts {
+    fn new(base: u16) -> Self {
+        Self {
+            command: Port::new(base),
+            status: Port::new(base + 2),
+            prdt: Port::new(base + 4),
+        }
+    }
+}
@@ -7,14 +7,22 @@ pub enum Command {
     ReadSectorsNoRetry = 0x21,
     ReadLong = 0x22,
     ReadLongNoRetry = 0x23,
+    ReadDmaExt = 0x25,
     WriteSectors = 0x30,
     WriteSectorsNoRetry = 0x31,
     WriteLong = 0x32,
     WriteLongNoRetry = 0x33,
+    WriteDmaExt = 0x35,
     FormatTrack = 0x50,
+    Packet = 0xA0,
+    IdentifyPacket = 0xA1,
+    Smart = 0xB0,
     ReadMultiple = 0xC4,
     WriteMultiple = 0xC5,
+    ReadDma = 0xC8,
+    WriteDma = 0xCA,
     FlushCache = 0xE7,
+    FlushCacheExt = 0xEA,
     Identify = 0xEC,
 }
 
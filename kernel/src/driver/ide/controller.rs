@@ -1,5 +1,9 @@
+use crate::arch::idt;
+use crate::driver::apic;
 use crate::driver::ide::channel::IdeChannel;
 use crate::driver::ide::drive::IdeDrive;
+use crate::driver::ide::interrupt;
+use crate::driver::ide::queue::ChannelQueue;
 use crate::driver::ide::{is_bit_set, register_ide_block_device, IdeBlockDevice};
 use crate::driver::pci::PciDevice;
 use alloc::boxed::Box;
@@ -8,14 +12,15 @@ use alloc::vec;
 use alloc::vec::Vec;
 use core::error::Error;
 use core::fmt::{Debug, Formatter};
-use spin::{Mutex, RwLock};
+use log::{debug, warn};
+use spin::Mutex;
 use thiserror::Error;
 
 pub struct IdeController {
     _device: Weak<Mutex<PciDevice>>,
 
-    primary: Arc<RwLock<IdeChannel>>,
-    secondary: Arc<RwLock<IdeChannel>>,
+    primary: Arc<ChannelQueue<IdeChannel>>,
+    secondary: Arc<ChannelQueue<IdeChannel>>,
     interrupt_pin: u8,
     interrupt_line: Option<u8>,
 
@@ -23,10 +28,6 @@ pub struct IdeController {
 }
 
 impl IdeController {
-    pub fn probe(device: &PciDevice) -> bool {
-        device.class == 0x01 && device.subclass == 0x01
-    }
-
     pub fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
         let ide_controller = IdeController::try_from(device)?;
         for drive in ide_controller.drives {
@@ -55,44 +56,43 @@ impl TryFrom<Weak<Mutex<PciDevice>>> for IdeController {
 
 impl From<Arc<Mutex<PciDevice>>> for IdeController {
     fn from(value: Arc<Mutex<PciDevice>>) -> Self {
-        let device = value.lock();
-        assert!(IdeController::probe(&device));
-
-        let prog_if = device.prog;
-        let (primary_ctrlbase, primary_iobase) = if is_bit_set(prog_if as u64, 0) {
-            (
-                device.base_addresses[1].read() as u16,
-                device.base_addresses[0].read() as u16,
-            )
-        } else {
-            (0x3F6, 0x1F0)
-        };
-
-        let (secondary_ctrlbase, secondary_iobase) = if is_bit_set(prog_if as u64, 2) {
-            (
-                device.base_addresses[3].read() as u16,
-                device.base_addresses[2].read() as u16,
-            )
-        } else {
-            (0x376, 0x170)
-        };
+        let mut device = value.lock();
+        assert!(device.class == 0x01 && device.subclass == 0x01);
 
-        let bus_master_ide = device.base_addresses[4].read();
-        let primary_master_base = bus_master_ide as u16;
-        let secondary_master_base = (bus_master_ide >> 16) as u16;
+        let interrupt_line = device.interrupt_line.read();
+        let configs = channel_configs(
+            device.prog,
+            core::array::from_fn(|i| device.base_addresses[i].read()),
+            interrupt_line,
+        );
+        if configs[0].bus_master_base != 0 {
+            device.enable_bus_mastering();
+        }
 
-        let mut primary_channels =
-            IdeChannel::new(primary_ctrlbase, primary_iobase, primary_master_base);
-        let mut secondary_channels =
-            IdeChannel::new(secondary_ctrlbase, secondary_iobase, secondary_master_base);
+        let mut channels = configs
+            .map(|config| IdeChannel::new(config.ctrlbase, config.iobase, config.bus_master_base));
         unsafe {
             // disable interrupts
-            primary_channels.disable_irq();
-            secondary_channels.disable_irq();
+            channels
+                .iter_mut()
+                .for_each(|channel| channel.disable_irq());
+        }
+
+        // channels in native mode share the interrupt of the PCI function, so
+        // an IRQ is only routed once
+        let mut routed = None;
+        for (index, (channel, config)) in channels.iter_mut().zip(&configs).enumerate() {
+            let Some(irq) = config.irq else {
+                debug!("IDE channel {index} has no IRQ, using PIO");
+                continue;
+            };
+            if setup_dma_interrupt(index, channel, irq, routed == Some(irq)) {
+                routed = Some(irq);
+            }
         }
 
-        let primary_channel = Arc::new(RwLock::new(primary_channels));
-        let secondary_channel = Arc::new(RwLock::new(secondary_channels));
+        let [primary_channel, secondary_channel] =
+            channels.map(|channel| Arc::new(ChannelQueue::new(channel)));
         let mut drives = vec![];
         for (chan, drive) in [
             (primary_channel.clone(), 0xA0),
@@ -101,7 +101,9 @@ impl From<Arc<Mutex<PciDevice>>> for IdeController {
             (secondary_channel.clone(), 0xB0),
         ] {
             if let Ok(drive) = IdeDrive::new(chan, drive) {
-                drives.push(drive);
+                if drive.exists() {
+                    drives.push(drive);
+                }
             }
         }
 
@@ -111,16 +113,98 @@ impl From<Arc<Mutex<PciDevice>>> for IdeController {
             primary: primary_channel,
             secondary: secondary_channel,
             interrupt_pin: device.interrupt_pin.read(),
-            interrupt_line: if (0..=15).contains(&device.interrupt_line.read()) {
-                Some(device.interrupt_line.read())
-            } else {
-                None
-            },
+            interrupt_line: (interrupt_line <= 15).then_some(interrupt_line),
             drives,
         }
     }
 }
 
+/// Where the registers of a channel are, and which IRQ it raises.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct ChannelConfig {
+    iobase: u16,
+    ctrlbase: u16,
+    /// Zero if the controller can't do DMA.
+    bus_master_base: u16,
+    irq: Option<u8>,
+}
+
+/// The command block, control block and IRQ of the primary and secondary
+/// channel in compatibility mode.
+const LEGACY_CHANNELS: [(u16, u16, u8); 2] = [(0x1F0, 0x3F6, 14), (0x170, 0x376, 15)];
+
+/// Decodes the configuration of both channels from the programming interface,
+/// the BARs and the interrupt line of the controller.
+///
+/// Bit 0 of the programming interface is set if the primary channel is in
+/// native mode, bit 2 if the secondary one is. Channels in compatibility mode
+/// are at the legacy ports and raise the legacy IRQs. Channels in native mode
+/// are at BAR0/BAR1 and BAR2/BAR3, and raise the interrupt of the PCI function.
+fn channel_configs(prog_if: u8, bars: [u32; 6], interrupt_line: u8) -> [ChannelConfig; 2] {
+    // BAR4 holds the bus master registers of both channels, eight ports each
+    let bus_master_base = if is_bit_set(bars[4] as u64, 0) {
+        (bars[4] & !0b11) as u16
+    } else {
+        0
+    };
+    core::array::from_fn(|channel| {
+        let bus_master_base = match bus_master_base {
+            0 => 0,
+            base => base + 8 * channel as u16,
+        };
+        if is_bit_set(prog_if as u64, 2 * channel as u8) {
+            ChannelConfig {
+                iobase: (bars[2 * channel] & !0b11) as u16,
+                // the control block BAR has four ports, the alternate status
+                // and device control register is the third one
+                ctrlbase: (bars[2 * channel + 1] & !0b11) as u16 + 2,
+                bus_master_base,
+                // 0xFF means that the interrupt isn't connected
+                irq: (interrupt_line <= 15).then_some(interrupt_line),
+            }
+        } else {
+            let (iobase, ctrlbase, irq) = LEGACY_CHANNELS[channel];
+            ChannelConfig {
+                iobase,
+                ctrlbase,
+                bus_master_base,
+                irq: Some(irq),
+            }
+        }
+    })
+}
+
+/// Routes the IRQ of the channel to the interrupt handler, so that the channel
+/// can complete DMA transfers. Channels without it only use PIO. If `routed`,
+/// the IRQ is already routed for the other channel, which shares it.
+///
+/// Returns whether the IRQ is routed.
+fn setup_dma_interrupt(index: usize, channel: &mut IdeChannel, irq: u8, routed: bool) -> bool {
+    if channel.bus_master_base() == 0 {
+        return false;
+    }
+    let Some(interrupt) =
+        interrupt::channel_interrupt(index, channel.bus_master_base(), channel.iobase())
+    else {
+        return false;
+    };
+    if !routed {
+        let Some(vector) = idt::next_free_interrupt_vector() else {
+            warn!("no free interrupt vector for IDE channel {index}, using PIO");
+            return false;
+        };
+        idt::register_interrupt_handler(vector, interrupt::interrupt_handler);
+        if let Err(e) = apic::route_isa_irq(irq, vector) {
+            warn!("failed to route IRQ {irq} of IDE channel {index}, using PIO: {e}");
+            return false;
+        }
+        debug!("IRQ {irq} is interrupt {vector}");
+    }
+    debug!("IDE channel {index} raises IRQ {irq}");
+    channel.set_interrupt(interrupt);
+    true
+}
+
 impl Debug for IdeController {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("IDEController")
@@ -131,3 +215,65 @@ impl Debug for IdeController {
             .finish()
     }
 }
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide::controller::{channel_configs, ChannelConfig};
+
+    #[kernel_test]
+    fn test_channel_configs_legacy() {
+        // PIIX3: both channels in compatibility mode, bus master at BAR4
+        let bars = [0, 0, 0, 0, 0xC041, 0];
+        let [primary, secondary] = channel_configs(0x80, bars, 0xFF);
+        assert_eq!(
+            ChannelConfig {
+                iobase: 0x1F0,
+                ctrlbase: 0x3F6,
+                bus_master_base: 0xC040,
+                irq: Some(14),
+            },
+            primary
+        );
+        assert_eq!(
+            ChannelConfig {
+                iobase: 0x170,
+                ctrlbase: 0x376,
+                bus_master_base: 0xC048,
+                irq: Some(15),
+            },
+            secondary
+        );
+    }
+
+    #[kernel_test]
+    fn test_channel_configs_native() {
+        let bars = [0xC001, 0xC011, 0xC021, 0xC031, 0xC041, 0];
+        let [primary, secondary] = channel_configs(0x8F, bars, 11);
+        assert_eq!(
+            ChannelConfig {
+                iobase: 0xC000,
+                ctrlbase: 0xC012,
+                bus_master_base: 0xC040,
+                irq: Some(11),
+            },
+            primary
+        );
+        assert_eq!(
+            ChannelConfig {
+                iobase: 0xC020,
+                ctrlbase: 0xC032,
+                bus_master_base: 0xC048,
+                irq: Some(11),
+            },
+            secondary
+        );
+
+        // without a connected interrupt and without a bus master
+        let bars = [0xC001, 0xC011, 0xC021, 0xC031, 0, 0];
+        let [primary, _] = channel_configs(0x05, bars, 0xFF);
+        assert_eq!(None, primary.irq);
+        assert_eq!(0, primary.bus_master_base);
+    }
+}
@@ -1,11 +1,26 @@
+use alloc::vec::Vec;
 use core::fmt::Debug;
 
 use filesystem::BlockDevice;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::warn;
+use x86_64::instructions::interrupts;
 use x86_64::instructions::interrupts::without_interrupts;
 
+use crate::driver::ide::channel::IdeChannel;
 use crate::driver::ide::command::Command;
-use crate::driver::ide::drive::IdeDrive;
-use crate::driver::ide::Status;
+use crate::driver::ide::dma::{DmaBuffer, BUS_MASTER_ERROR, MAX_DMA_SECTORS};
+use crate::driver::ide::drive::{DriveKind, IdeDrive};
+use crate::driver::ide::identify::DriveInfo;
+use crate::driver::ide::interrupt::{ChannelInterrupt, Completion, TimedOut};
+use crate::driver::ide::queue::ChannelGuard;
+use crate::driver::ide::smart::{SmartAttribute, SmartError, SmartStatus};
+use crate::driver::ide::{atapi, smart, Status};
+use crate::io::vfs::cache::{Flush, MultiBlock};
+
+/// The number of sectors that are addressable with LBA28.
+const LBA28_SECTORS: usize = 1 << 28;
 
 #[derive(Debug, Clone)]
 pub struct IdeBlockDevice {
@@ -17,7 +32,335 @@ enum AccessMode<'a> {
     Write(&'a [u8]),
 }
 
+/// How data is moved between the drive and memory.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransferMode {
+    /// The CPU moves every word through the data port of the channel.
+    Pio,
+    /// The bus master of the controller moves the data, and the transfer
+    /// completes on the interrupt of the channel.
+    Dma,
+}
+
 impl IdeBlockDevice {
+    /// Whether this is a packet device like a CD-ROM drive, whose medium is
+    /// read-only and has sectors of 2048 bytes.
+    pub fn is_atapi(&self) -> bool {
+        self.ide_drive.kind() == DriveKind::Atapi
+    }
+
+    /// The model, serial number and capabilities that the drive reported.
+    pub fn info(&self) -> &DriveInfo {
+        self.ide_drive.info()
+    }
+
+    /// The overall health that the drive reports with SMART.
+    pub fn smart_status(&self) -> Result<SmartStatus, SmartError> {
+        let mut channel = self.smart_channel()?;
+        smart::return_status(&mut channel, self.ide_drive.drive_num())
+    }
+
+    /// The SMART attributes of the drive, with their thresholds.
+    pub fn smart_attributes(&self) -> Result<Vec<SmartAttribute>, SmartError> {
+        let mut channel = self.smart_channel()?;
+        smart::read_attributes(&mut channel, self.ide_drive.drive_num())
+    }
+
+    /// Locks the channel and makes sure that SMART is enabled, which it may
+    /// not be after a reset.
+    fn smart_channel(&self) -> Result<ChannelGuard<IdeChannel>, SmartError> {
+        if self.is_atapi() || !self.info().smart {
+            return Err(SmartError::Unsupported);
+        }
+        let mut channel = self.ide_drive.channel();
+        smart::enable(&mut channel, self.ide_drive.drive_num())?;
+        Ok(channel)
+    }
+
+    /// The mode that transfers use right now. DMA needs a drive that
+    /// advertises UDMA, a controller that is a bus master with a routed
+    /// interrupt, and enabled interrupts to wait for the transfer, so
+    /// everything else falls back to PIO. Packet devices always use PIO.
+    pub fn transfer_mode(&self) -> TransferMode {
+        if !self.is_atapi()
+            && !self.ide_drive.supported_udma_modes().is_empty()
+            && interrupts::are_enabled()
+            && self.ide_drive.dma_interrupt().is_some()
+        {
+            TransferMode::Dma
+        } else {
+            TransferMode::Pio
+        }
+    }
+
+    /// Reads the sectors starting at `first_sector` into `buf`, whose length
+    /// must be a multiple of the sector size.
+    pub fn read_sectors(&self, first_sector: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        self.read_sectors_with(self.transfer_mode(), first_sector, buf)
+    }
+
+    /// Like [`IdeBlockDevice::read_sectors`], but with the given transfer mode.
+    /// DMA falls back to PIO if it's not possible right now, or if there is no
+    /// memory for the transfer.
+    pub fn read_sectors_with(
+        &self,
+        mode: TransferMode,
+        first_sector: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, ()> {
+        let sector_size = self.sector_size();
+        assert_eq!(0, buf.len() % sector_size);
+        let chunk_len = MAX_DMA_SECTORS * sector_size;
+        for (i, chunk) in buf.chunks_mut(chunk_len).enumerate() {
+            let sector = first_sector + i * MAX_DMA_SECTORS;
+            if self.is_atapi() {
+                self.read_packet_sectors(sector, chunk)?;
+                continue;
+            }
+            match self.dma_buffer(mode, chunk.len()) {
+                Some(mut buffer) => {
+                    self.access_disk_dma(sector, &mut buffer, true)?;
+                    chunk.copy_from_slice(buffer.as_slice());
+                }
+                None => {
+                    for (j, sector_buf) in chunk.chunks_mut(sector_size).enumerate() {
+                        self.access_disk(sector + j, AccessMode::Read(sector_buf))?;
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes `buf`, whose length must be a multiple of the sector size, to the
+    /// sectors starting at `first_sector`.
+    pub fn write_sectors(&self, first_sector: usize, buf: &[u8]) -> Result<usize, ()> {
+        if self.is_atapi() {
+            // we only read optical media
+            return Err(());
+        }
+        let mode = self.transfer_mode();
+        let sector_size = self.sector_size();
+        assert_eq!(0, buf.len() % sector_size);
+        let chunk_len = MAX_DMA_SECTORS * sector_size;
+        for (i, chunk) in buf.chunks(chunk_len).enumerate() {
+            let sector = first_sector + i * MAX_DMA_SECTORS;
+            match self.dma_buffer(mode, chunk.len()) {
+                Some(mut buffer) => {
+                    buffer.as_mut_slice().copy_from_slice(chunk);
+                    self.access_disk_dma(sector, &mut buffer, false)?;
+                }
+                None => {
+                    for (j, sector_buf) in chunk.chunks(sector_size).enumerate() {
+                        self.access_disk(sector + j, AccessMode::Write(sector_buf))?;
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Like [`IdeBlockDevice::read_sectors`], but awaits the channel and the
+    /// completion of the transfer instead of halting, so that a task on an
+    /// executor doesn't hold up its thread. Requests of different tasks and
+    /// threads are serialized by the lock of the channel.
+    ///
+    /// Without DMA, the sectors are read with PIO, which blocks. The future must
+    /// be polled from a single address space, since the DMA buffer is only
+    /// mapped in the one that it was allocated in.
+    pub async fn read_block_async(&self, first_sector: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let sector_size = self.sector_size();
+        assert_eq!(0, buf.len() % sector_size);
+        let chunk_len = MAX_DMA_SECTORS * sector_size;
+        for (i, chunk) in buf.chunks_mut(chunk_len).enumerate() {
+            let sector = first_sector + i * MAX_DMA_SECTORS;
+            match self.dma_buffer(self.transfer_mode(), chunk.len()) {
+                Some(mut buffer) => {
+                    self.access_disk_dma_async(sector, &mut buffer, true)
+                        .await?;
+                    chunk.copy_from_slice(buffer.as_slice());
+                }
+                None => {
+                    self.read_sectors_with(TransferMode::Pio, sector, chunk)?;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    /// Reads sectors of a packet device with READ(12). Errors are logged with
+    /// the sense key that the device reports, so that a missing medium can be
+    /// told apart from a failing drive.
+    fn read_packet_sectors(&self, first_sector: usize, buf: &mut [u8]) -> Result<usize, ()> {
+        let mut channel = self.ide_drive.channel();
+        atapi::read_sectors(&mut channel, self.ide_drive.drive_num(), first_sector, buf).map_err(
+            |sense| {
+                warn!(
+                    "failed to read sector {first_sector} of {}: {sense}",
+                    self.ide_drive
+                )
+            },
+        )
+    }
+
+    /// Allocates the buffer for a DMA transfer of `len` bytes, or returns
+    /// `None` if the transfer has to use PIO.
+    fn dma_buffer(&self, mode: TransferMode, len: usize) -> Option<DmaBuffer> {
+        match mode {
+            TransferMode::Dma if self.transfer_mode() == TransferMode::Dma => {
+                DmaBuffer::allocate(len)
+            }
+            _ => None,
+        }
+    }
+
+    /// Transfers the sectors of the buffer with DMA, and halts until the
+    /// interrupt of the channel completes the transfer.
+    fn access_disk_dma(&self, sector: usize, buffer: &mut DmaBuffer, read: bool) -> Result<(), ()> {
+        let mut channel = self.ide_drive.channel();
+        let interrupt = self.issue_dma(&mut channel, sector, buffer, read)?;
+        // the interrupt handler doesn't need the channel, so we can keep it locked
+        let completion = interrupt.wait();
+        self.finish_dma(&mut channel, completion, read)
+    }
+
+    /// Like [`IdeBlockDevice::access_disk_dma`], but awaits the channel and
+    /// the interrupt.
+    async fn access_disk_dma_async(
+        &self,
+        sector: usize,
+        buffer: &mut DmaBuffer,
+        read: bool,
+    ) -> Result<(), ()> {
+        let mut channel = self.ide_drive.channel_async().await;
+        let interrupt = self.issue_dma(&mut channel, sector, buffer, read)?;
+        let completion = interrupt.wait_async().await;
+        self.finish_dma(&mut channel, completion, read)
+    }
+
+    /// Starts a DMA transfer of the sectors of the buffer on the locked
+    /// channel, and returns the interrupt that completes it. The transfer must
+    /// be finished with [`IdeBlockDevice::finish_dma`] before the channel is
+    /// unlocked.
+    fn issue_dma(
+        &self,
+        channel: &mut IdeChannel,
+        sector: usize,
+        buffer: &DmaBuffer,
+        read: bool,
+    ) -> Result<&'static ChannelInterrupt, ()> {
+        let count = buffer.as_slice().len() / self.sector_size();
+        debug_assert!(count <= MAX_DMA_SECTORS);
+        let lba48 = sector + count > LBA28_SECTORS;
+        if lba48 && !self.ide_drive.is_lba48_supported() {
+            return Err(());
+        }
+        let interrupt = self.ide_drive.dma_interrupt().ok_or(())?;
+
+        let drive_num = self.ide_drive.drive_num();
+        unsafe {
+            channel.prepare_dma(buffer.prdt_addr(), read);
+            if lba48 {
+                channel.ports.drive_select.write(0x40 + drive_num);
+                // the high bytes go first, the registers are two bytes deep
+                channel.ports.sector_count.write((count >> 8) as u8);
+                channel.ports.lba_lo.write((sector >> 24) as u8);
+                channel.ports.lba_mid.write((sector >> 32) as u8);
+                channel.ports.lba_hi.write((sector >> 40) as u8);
+            } else {
+                channel
+                    .ports
+                    .drive_select
+                    .write((0x40 + drive_num) | ((sector >> 24) & 0x0F) as u8);
+            }
+            channel.ports.features.write(0);
+            // a count of 256 wraps to zero, which means 256 for LBA28
+            channel.ports.sector_count.write(count as u8);
+            channel.ports.lba_lo.write(sector as u8);
+            channel.ports.lba_mid.write((sector >> 8) as u8);
+            channel.ports.lba_hi.write((sector >> 16) as u8);
+
+            interrupt.arm();
+            channel.enable_irq();
+            channel.write_command(match (read, lba48) {
+                (true, false) => Command::ReadDma,
+                (true, true) => Command::ReadDmaExt,
+                (false, false) => Command::WriteDma,
+                (false, true) => Command::WriteDmaExt,
+            });
+            channel.start_dma(read);
+        }
+        Ok(interrupt)
+    }
+
+    /// Stops the DMA transfer on the channel, and checks the status that the
+    /// interrupt captured. A transfer that timed out is aborted by resetting
+    /// the channel, so that the next command finds it usable.
+    fn finish_dma(
+        &self,
+        channel: &mut IdeChannel,
+        completion: Result<Completion, TimedOut>,
+        read: bool,
+    ) -> Result<(), ()> {
+        unsafe {
+            channel.stop_dma();
+            channel.disable_irq();
+        }
+        let completion = match completion {
+            Ok(completion) => completion,
+            Err(TimedOut) => {
+                warn!("IDE command on {channel:?} timed out, resetting the channel");
+                channel.soft_reset();
+                return Err(());
+            }
+        };
+        if completion.bus_master_status & BUS_MASTER_ERROR != 0
+            || completion
+                .status
+                .intersects(Status::ERROR | Status::DRIVE_FAULT_ERROR)
+        {
+            return Err(());
+        }
+
+        if !read {
+            self.flush_write_cache(channel)?;
+        }
+        Ok(())
+    }
+
+    /// Makes the drive write its volatile write cache to the medium, and
+    /// waits until it's done.
+    fn flush_write_cache(&self, channel: &mut IdeChannel) -> Result<(), ()> {
+        if self.is_atapi() {
+            // we never write to packet devices
+            return Ok(());
+        }
+        unsafe {
+            channel
+                .ports
+                .drive_select
+                .write(0x40 + self.ide_drive.drive_num());
+        }
+        channel.write_command(if self.ide_drive.is_lba48_supported() {
+            Command::FlushCacheExt
+        } else {
+            Command::FlushCache
+        });
+        channel.wait_for_not_busy();
+        if channel
+            .status()
+            .intersects(Status::ERROR | Status::DRIVE_FAULT_ERROR)
+        {
+            warn!(
+                "flushing the write cache of {} failed: {}",
+                self.ide_drive,
+                channel.error()
+            );
+            return Err(());
+        }
+        Ok(())
+    }
+
     fn access_disk(
         &self,
         sector: usize,
@@ -34,7 +377,7 @@ impl IdeBlockDevice {
             buf.iter()
                 .copied()
                 .array_chunks::<2>()
-                .map(u16::from_be_bytes)
+                .map(u16::from_le_bytes)
                 .enumerate()
                 .for_each(|(i, v)| buffer[i] = v);
         }
@@ -54,7 +397,10 @@ impl IdeBlockDevice {
             channel.ports.lba_lo.write(lba as u8);
             channel.ports.lba_mid.write((lba >> 8) as u8);
             channel.ports.lba_hi.write((lba >> 16) as u8);
-            channel.write_command(Command::ReadSectors);
+            channel.write_command(match access_mode {
+                AccessMode::Read(_) => Command::ReadSectors,
+                AccessMode::Write(_) => Command::WriteSectors,
+            });
             channel.disable_irq();
             channel.wait_for_not_busy();
             without_interrupts(|| {
@@ -81,11 +427,7 @@ impl IdeBlockDevice {
                 Ok(buf.len())
             }
             AccessMode::Write(buf) => {
-                // flush the cache
-                channel.write_command(Command::FlushCache);
-                channel.poll_on_status(|status| {
-                    status.contains(Status::READY) && !status.contains(Status::BUSY)
-                });
+                self.flush_write_cache(&mut channel)?;
                 Ok(buf.len())
             }
         }
@@ -102,7 +444,7 @@ impl BlockDevice for IdeBlockDevice {
     type Error = ();
 
     fn sector_size(&self) -> usize {
-        512
+        self.ide_drive.sector_size()
     }
 
     fn sector_count(&self) -> usize {
@@ -110,10 +452,238 @@ impl BlockDevice for IdeBlockDevice {
     }
 
     fn read_sector(&self, sector: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.access_disk(sector, AccessMode::Read(buf))
+        self.read_sectors(sector, buf)
     }
 
     fn write_sector(&mut self, sector: usize, buf: &[u8]) -> Result<usize, Self::Error> {
-        self.access_disk(sector, AccessMode::Write(buf))
+        self.write_sectors(sector, buf)
+    }
+}
+
+impl MultiBlock for IdeBlockDevice {
+    /// Reads up to [`MAX_DMA_SECTORS`] blocks with a single command, if the
+    /// device can do DMA right now.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let sector_size = self.sector_size();
+        let mode = self.transfer_mode();
+        for (i, chunk) in bufs.chunks_mut(MAX_DMA_SECTORS).enumerate() {
+            let sector = start + i * MAX_DMA_SECTORS;
+            match self.dma_buffer(mode, chunk.len() * sector_size) {
+                Some(mut buffer) => {
+                    self.access_disk_dma(sector, &mut buffer, true)?;
+                    for (buf, data) in chunk.iter_mut().zip(buffer.as_slice().chunks(sector_size)) {
+                        buf.copy_from_slice(data);
+                    }
+                }
+                None => {
+                    for (j, buf) in chunk.iter_mut().enumerate() {
+                        self.read_sectors_with(TransferMode::Pio, sector + j, buf)?;
+                    }
+                }
+            }
+        }
+        Ok(bufs.len() * sector_size)
+    }
+
+    /// Writes up to [`MAX_DMA_SECTORS`] blocks with a single command, if the
+    /// device can do DMA right now.
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        let sector_size = self.sector_size();
+        let mode = self.transfer_mode();
+        for (i, chunk) in bufs.chunks(MAX_DMA_SECTORS).enumerate() {
+            let sector = start + i * MAX_DMA_SECTORS;
+            match self.dma_buffer(mode, chunk.len() * sector_size) {
+                Some(mut buffer) => {
+                    for (data, buf) in buffer.as_mut_slice().chunks_mut(sector_size).zip(chunk) {
+                        data.copy_from_slice(buf);
+                    }
+                    self.access_disk_dma(sector, &mut buffer, false)?;
+                }
+                None => {
+                    for (j, buf) in chunk.iter().enumerate() {
+                        self.write_sectors(sector + j, buf)?;
+                    }
+                }
+            }
+        }
+        Ok(bufs.len() * sector_size)
+    }
+
+    /// Awaits the channel and the interrupt, see
+    /// [`IdeBlockDevice::read_block_async`].
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            if self.is_atapi() {
+                // packet commands are only issued synchronously
+                return self.read_sectors(start, buf);
+            }
+            self.read_block_async(start, buf).await
+        }
+        .boxed()
+    }
+}
+
+impl Flush for IdeBlockDevice {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        let mut channel = self.ide_drive.channel();
+        self.flush_write_cache(&mut channel)
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::ffi::c_void;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use filesystem::BlockDevice;
+    use foundation::future::executor::{Executor, Tick, TickResult};
+    use foundation::time::Instant;
+    use kernel_test_framework::kernel_test;
+    use x86_64::instructions::hlt;
+
+    use crate::driver::ide;
+    use crate::driver::ide::TransferMode;
+    use crate::io::vfs::cache::MultiBlock;
+    use crate::process;
+    use crate::process::Priority;
+    use crate::time::HpetInstantProvider;
+
+    const READS: usize = 64;
+    const THREADS: usize = 4;
+    const READ_SECTORS: usize = 256;
+    const READ_LEN: usize = READ_SECTORS * 512;
+
+    static READS_DONE: AtomicUsize = AtomicUsize::new(0);
+
+    #[kernel_test]
+    fn test_dma_reads_like_pio() {
+        let device = ide::devices()[1].clone();
+        assert_eq!(TransferMode::Dma, device.transfer_mode());
+
+        // more than one command, and a chunk that is not a full command
+        let sectors = (4 * 1024 * 1024 / 512 + 3).min(device.sector_count());
+        let mut dma = vec![0_u8; sectors * 512];
+        let mut pio = vec![0xAA_u8; sectors * 512];
+        device
+            .read_sectors_with(TransferMode::Dma, 0, &mut dma)
+            .unwrap();
+        device
+            .read_sectors_with(TransferMode::Pio, 0, &mut pio)
+            .unwrap();
+        assert!(dma == pio, "DMA and PIO read different data");
+        assert!(dma.iter().any(|&b| b != 0));
+    }
+
+    /// Reads every [`THREADS`]th of the [`READS`] regions, starting at the
+    /// region with the index that is passed as argument, and compares them
+    /// with the expected content, which is passed after the index.
+    extern "C" fn read_regions(arg: *mut c_void) {
+        let (first, expected) = unsafe { *(arg as *const (usize, &[u8])) };
+        let device = ide::devices()[1].clone();
+        let mut buf = vec![0_u8; READ_LEN];
+        for region in (first..READS).step_by(THREADS) {
+            assert_eq!(
+                Ok(READ_LEN),
+                device.read_sectors(region * READ_SECTORS, &mut buf)
+            );
+            assert!(
+                buf == expected[region * READ_LEN..(region + 1) * READ_LEN],
+                "region {region} differs"
+            );
+            READS_DONE.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[kernel_test]
+    fn test_concurrent_reads() {
+        let device = ide::devices()[1].clone();
+        assert!(device.sector_count() >= READS * READ_SECTORS);
+
+        let start = Instant::now();
+        let mut expected = vec![0_u8; READS * READ_LEN];
+        for (region, buf) in expected.chunks_mut(READ_LEN).enumerate() {
+            device
+                .read_sectors_with(TransferMode::Pio, region * READ_SECTORS, buf)
+                .unwrap();
+        }
+        let sequential = start.elapsed();
+
+        READS_DONE.store(0, Ordering::SeqCst);
+        let args = (0..THREADS)
+            .map(|first| (first, expected.as_slice()))
+            .collect::<Vec<_>>();
+        let start = Instant::now();
+        for arg in &args {
+            process::spawn_thread_in_current_process(
+                "ide_reader",
+                Priority::Normal,
+                read_regions,
+                arg as *const (usize, &[u8]) as *mut c_void,
+            );
+        }
+        while READS_DONE.load(Ordering::SeqCst) < READS {
+            hlt();
+        }
+        let concurrent = start.elapsed();
+        assert!(
+            concurrent * 2 < sequential,
+            "{READS} concurrent reads took {concurrent:?}, {READS} sequential PIO reads took {sequential:?}"
+        );
+    }
+
+    #[kernel_test]
+    fn test_read_blocks_like_read_sectors() {
+        let device = ide::devices()[1].clone();
+        // more than one command
+        let sectors = 300;
+        let mut expected = vec![0_u8; sectors * 512];
+        device.read_sectors(7, &mut expected).unwrap();
+
+        let mut blocks = vec![[0_u8; 512]; sectors];
+        let mut bufs = blocks
+            .iter_mut()
+            .map(|block| block.as_mut_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(Ok(sectors * 512), device.read_blocks(7, &mut bufs));
+        assert!(
+            blocks.as_flattened() == expected,
+            "read_blocks read different data"
+        );
+    }
+
+    #[kernel_test]
+    fn test_read_block_async() {
+        let device = ide::devices()[1].clone();
+        let mut expected = vec![0_u8; 4 * READ_LEN];
+        device
+            .read_sectors_with(TransferMode::Pio, 0, &mut expected)
+            .unwrap();
+
+        let done = AtomicUsize::new(0);
+        let executor = Executor::default();
+        for region in 0..4 {
+            let device = &device;
+            let expected = &expected[region * READ_LEN..(region + 1) * READ_LEN];
+            let done = &done;
+            executor.spawn(async move {
+                let mut buf = vec![0_u8; READ_LEN];
+                let read = device.read_block_async(region * READ_SECTORS, &mut buf);
+                assert_eq!(Ok(READ_LEN), read.await);
+                assert!(buf == expected, "region {region} differs");
+                done.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        // the tasks wait for the channel and for their interrupts
+        while done.load(Ordering::SeqCst) < 4 {
+            if executor.tick() == TickResult::Idled {
+                hlt();
+            }
+        }
     }
 }
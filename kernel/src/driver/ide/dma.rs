@@ -0,0 +1,233 @@
+use alloc::vec::Vec;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+
+use crate::mem::virt::OwnedInterval;
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+use crate::{map_page, unmap_page};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// The most bytes that a single PRD entry can describe. The memory of an entry
+/// must also not cross a boundary of this size.
+const PRD_MAX_BYTES: usize = 64 * 1024;
+/// Marks the last entry of a PRD table.
+const PRD_END_OF_TABLE: u16 = 1 << 15;
+
+/// The most sectors that are transferred with a single command, which is the
+/// most that an LBA28 command can transfer.
+pub const MAX_DMA_SECTORS: usize = 256;
+
+pub const BUS_MASTER_START: u8 = 1 << 0;
+/// Set if the bus master writes to memory, which is the case for reads from the
+/// drive.
+pub const BUS_MASTER_READ: u8 = 1 << 3;
+pub const BUS_MASTER_ERROR: u8 = 1 << 1;
+pub const BUS_MASTER_INTERRUPT: u8 = 1 << 2;
+
+/// An entry of a physical region descriptor table, which tells the bus master
+/// where in physical memory to transfer data.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PrdEntry {
+    pub addr: u32,
+    /// A byte count of zero means 64KiB.
+    pub byte_count: u16,
+    pub flags: u16,
+}
+
+/// Describes the given physical memory regions (address and length) with PRD
+/// entries. Adjacent regions are merged, and entries are split so that none of
+/// them crosses a 64KiB boundary. Returns `None` if a region is not below
+/// 4GiB, since the bus master only takes 32 bit addresses.
+pub fn prd_entries(regions: impl IntoIterator<Item = (u64, usize)>) -> Option<Vec<PrdEntry>> {
+    let mut merged: Vec<(u64, usize)> = Vec::new();
+    for (addr, len) in regions {
+        match merged.last_mut() {
+            Some((start, merged_len)) if *start + *merged_len as u64 == addr => {
+                *merged_len += len;
+            }
+            _ => merged.push((addr, len)),
+        }
+    }
+
+    let mut entries = Vec::new();
+    for (start, len) in merged {
+        let end = start + len as u64;
+        if end > 1 << 32 {
+            return None;
+        }
+        let mut position = start;
+        while position < end {
+            let boundary = (position / PRD_MAX_BYTES as u64 + 1) * PRD_MAX_BYTES as u64;
+            let chunk = (end.min(boundary) - position) as usize;
+            entries.push(PrdEntry {
+                addr: position as u32,
+                byte_count: chunk as u16, // 64KiB wraps to zero, which means 64KiB
+                flags: 0,
+            });
+            position += chunk as u64;
+        }
+    }
+    if let Some(last) = entries.last_mut() {
+        last.flags |= PRD_END_OF_TABLE;
+    }
+    Some(entries)
+}
+
+/// Memory that the bus master transfers data from or to, along with the PRD
+/// table that describes it.
+///
+/// The frames are mapped contiguously into the current address space for as
+/// long as the buffer lives, so the buffer must not be used from a different
+/// address space.
+pub struct DmaBuffer {
+    prdt: PhysFrame,
+    frames: Vec<PhysFrame>,
+    interval: OwnedInterval<'static>,
+    len: usize,
+}
+
+impl DmaBuffer {
+    /// Allocates a buffer of `len` bytes. Returns `None` if there is not
+    /// enough memory, or if the memory can't be reached by the bus master.
+    pub fn allocate(len: usize) -> Option<Self> {
+        let prdt = PhysicalMemoryManager::allocate_frame()?;
+        let count = len.div_ceil(PAGE_SIZE);
+        let mut frames = Vec::with_capacity(count);
+        while frames.len() < count {
+            match PhysicalMemoryManager::allocate_frame() {
+                Some(frame) => frames.push(frame),
+                None => {
+                    deallocate(prdt, frames);
+                    return None;
+                }
+            }
+        }
+
+        // the table must describe exactly as many bytes as are transferred
+        let entries = prd_entries(frames.iter().enumerate().map(|(i, frame)| {
+            let len = (len - i * PAGE_SIZE).min(PAGE_SIZE);
+            (frame.start_address().as_u64(), len)
+        }))
+        .filter(|entries| entries.len() <= PAGE_SIZE / size_of::<PrdEntry>());
+        let interval = vmm().reserve((frames.len() + 1) * PAGE_SIZE);
+        let (Some(entries), Ok(interval)) = (entries, interval) else {
+            deallocate(prdt, frames);
+            return None;
+        };
+        if prdt.start_address().as_u64() >= 1 << 32 {
+            deallocate(prdt, frames);
+            return None;
+        }
+
+        let first = Page::<Size4KiB>::containing_address(interval.start());
+        for (i, &frame) in [prdt].iter().chain(&frames).enumerate() {
+            map_page!(
+                first + i as u64,
+                frame,
+                Size4KiB,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+            );
+        }
+        unsafe {
+            // safety: the first page is the PRD table, and we checked that the
+            // entries fit into it
+            from_raw_parts_mut(interval.start().as_mut_ptr::<PrdEntry>(), entries.len())
+                .copy_from_slice(&entries);
+        }
+        Some(Self {
+            prdt,
+            frames,
+            interval,
+            len,
+        })
+    }
+
+    /// The physical address of the PRD table, for the bus master.
+    pub fn prdt_addr(&self) -> u32 {
+        self.prdt.start_address().as_u64() as u32
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe {
+            // safety: the frames are mapped after the PRD table
+            from_raw_parts(
+                (self.interval.start() + PAGE_SIZE as u64).as_ptr(),
+                self.len,
+            )
+        }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe {
+            // safety: the frames are mapped after the PRD table
+            from_raw_parts_mut(
+                (self.interval.start() + PAGE_SIZE as u64).as_mut_ptr(),
+                self.len,
+            )
+        }
+    }
+}
+
+impl Drop for DmaBuffer {
+    fn drop(&mut self) {
+        let first = Page::<Size4KiB>::containing_address(self.interval.start());
+        for i in 0..=self.frames.len() {
+            unmap_page!(first + i as u64, Size4KiB);
+        }
+        deallocate(self.prdt, core::mem::take(&mut self.frames));
+    }
+}
+
+fn deallocate(prdt: PhysFrame, frames: Vec<PhysFrame>) {
+    PhysicalMemoryManager::deallocate_frame(prdt);
+    frames
+        .into_iter()
+        .for_each(PhysicalMemoryManager::deallocate_frame);
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide::dma::{prd_entries, PrdEntry, PRD_END_OF_TABLE};
+
+    #[kernel_test]
+    fn test_prd_entries() {
+        // adjacent regions are merged, but split at the 64KiB boundary
+        let entries = prd_entries([(0xF000, 0x1000), (0x10000, 0x1000), (0x30000, 0x200)]);
+        assert_eq!(
+            Some(vec![
+                PrdEntry {
+                    addr: 0xF000,
+                    byte_count: 0x1000,
+                    flags: 0,
+                },
+                PrdEntry {
+                    addr: 0x10000,
+                    byte_count: 0x1000,
+                    flags: 0,
+                },
+                PrdEntry {
+                    addr: 0x30000,
+                    byte_count: 0x200,
+                    flags: PRD_END_OF_TABLE,
+                },
+            ]),
+            entries
+        );
+
+        // a full 64KiB entry has a byte count of zero
+        let entries = prd_entries([(0x20000, 0x10000), (0x30000, 0x8000)]).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(0, entries[0].byte_count);
+        assert_eq!(0x8000, entries[1].byte_count);
+
+        assert_eq!(None, prd_entries([(0xFFFF_F000, 0x2000)]));
+    }
+}
@@ -1,30 +1,49 @@
 use alloc::format;
 use alloc::sync::Arc;
 use core::fmt::{Debug, Display, Formatter};
+use core::time::Duration;
 
-use spin::{RwLock, RwLockWriteGuard};
+use log::{debug, warn};
 use x86_64::instructions::interrupts;
+use x86_64::instructions::interrupts::without_interrupts;
 
+use crate::driver::ide::atapi::{ATAPI_SECTOR_SIZE, ATAPI_SIGNATURE};
 use crate::driver::ide::channel::IdeChannel;
 use crate::driver::ide::command::Command;
-use crate::driver::ide::{is_bit_set, Status, UDMAMode};
+use crate::driver::ide::identify::DriveInfo;
+use crate::driver::ide::interrupt::ChannelInterrupt;
+use crate::driver::ide::queue::{ChannelGuard, ChannelQueue};
+use crate::driver::ide::{atapi, Status, UDMAMode};
+
+/// How long a drive may take to answer IDENTIFY. Drives answer within
+/// milliseconds, so this only bounds how long probing a channel takes when a
+/// drive is missing or doesn't work.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// The command set that a drive understands.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DriveKind {
+    /// A hard disk, which is accessed with ATA commands.
+    Ata,
+    /// A packet device like a CD-ROM drive, which is accessed with SCSI
+    /// commands that are sent with the PACKET command.
+    Atapi,
+}
 
 #[derive(Clone)]
 pub struct IdeDrive {
-    channel: Arc<RwLock<IdeChannel>>,
+    channel: Arc<ChannelQueue<IdeChannel>>,
 
     ctrlbase: u16,
     iobase: u16,
     drive: u8,
+    dma_interrupt: Option<&'static ChannelInterrupt>,
 
     exists: bool,
+    kind: DriveKind,
 
-    // The following block consists of the identify_sector and then values
-    // that were read from it.
-    identify_sector: [u16; 256],
-    supported_udma_modes: UDMAMode,
-    active_udma_mode: UDMAMode,
-    sector_count: u64,
+    info: DriveInfo,
+    sector_size: usize,
 }
 
 impl Display for IdeDrive {
@@ -40,30 +59,40 @@ impl Display for IdeDrive {
 impl Debug for IdeDrive {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("IDEDrive")
-            .field("channel", &self.channel.read())
+            .field("ctrlbase", &format!("{:#X}", self.ctrlbase))
+            .field("iobase", &format!("{:#X}", self.iobase))
             .field("drive", &format!("{:#X}", self.drive))
             .field("exists", &self.exists)
-            .field("sector count", &self.sector_count)
-            .field("udma support", &self.supported_udma_modes)
-            .field("active udma", &self.active_udma_mode)
+            .field("kind", &self.kind)
+            .field("model", &self.info.model)
+            .field("sector count", &self.info.sector_count)
+            .field("sector size", &self.sector_size)
+            .field("udma support", &self.info.supported_udma_modes)
+            .field("active udma", &self.info.active_udma_mode)
             .finish()
     }
 }
 
 impl IdeDrive {
-    pub fn new(channel: Arc<RwLock<IdeChannel>>, drive: u8) -> Result<Self, IdentifyError> {
-        let ctrlbase = channel.read().ctrlbase();
-        let iobase = channel.read().iobase();
+    pub fn new(channel: Arc<ChannelQueue<IdeChannel>>, drive: u8) -> Result<Self, IdentifyError> {
+        let (ctrlbase, iobase, dma_interrupt) = {
+            let channel = channel.lock(drive);
+            (
+                channel.ctrlbase(),
+                channel.iobase(),
+                channel.dma_interrupt(),
+            )
+        };
         let mut drive = IdeDrive {
             channel,
             ctrlbase,
             iobase,
             drive,
+            dma_interrupt,
             exists: false,
-            identify_sector: [0; 256],
-            supported_udma_modes: UDMAMode::empty(),
-            active_udma_mode: UDMAMode::empty(),
-            sector_count: 0,
+            kind: DriveKind::Ata,
+            info: DriveInfo::from_identify(&[0; 256]),
+            sector_size: 512,
         };
         drive.exists = drive.identify()?;
         Ok(drive)
@@ -76,59 +105,89 @@ impl IdeDrive {
     }
 
     pub fn ctrlbase(&self) -> u16 {
-        self.channel.read().ctrlbase()
+        self.ctrlbase
     }
 
     pub fn iobase(&self) -> u16 {
-        self.channel.read().iobase()
+        self.iobase
     }
 
     pub fn drive_num(&self) -> u8 {
         self.drive
     }
 
+    pub fn kind(&self) -> DriveKind {
+        self.kind
+    }
+
     pub fn sector_count(&self) -> u64 {
-        self.sector_count
+        self.info.sector_count
+    }
+
+    /// What the drive reported about itself when it was identified.
+    pub fn info(&self) -> &DriveInfo {
+        &self.info
+    }
+
+    pub fn sector_size(&self) -> usize {
+        self.sector_size
+    }
+
+    /// The interrupt that completes DMA transfers on the channel of this
+    /// drive, if the channel can do DMA transfers.
+    pub fn dma_interrupt(&self) -> Option<&'static ChannelInterrupt> {
+        self.dma_interrupt
     }
 }
 
 pub struct IdentifyError;
 
 impl IdeDrive {
-    pub fn channel(&self) -> RwLockWriteGuard<IdeChannel> {
-        self.channel.write()
+    /// Waits for the turn of this drive on its channel, and selects it.
+    /// Commands on a channel are serialized by the [`ChannelQueue`], so the
+    /// guard is held until a command completes.
+    pub fn channel(&self) -> ChannelGuard<IdeChannel> {
+        let mut channel = self.channel.lock(self.drive);
+        channel.select(self.drive);
+        channel
+    }
+
+    /// Like [`IdeDrive::channel`], but for callers that run on an executor.
+    pub async fn channel_async(&self) -> ChannelGuard<IdeChannel> {
+        let mut channel = self.channel.lock_async(self.drive).await;
+        channel.select(self.drive);
+        channel
     }
 
     fn identify(&mut self) -> Result<bool, IdentifyError> {
-        let mut channel = self.channel.write();
+        let channel = self.channel.clone();
+        let mut channel = channel.lock(self.drive);
+        channel.select(self.drive);
         unsafe {
-            channel.ports.drive_select.write(self.drive);
-
             channel.ports.lba_lo.write(0);
             channel.ports.lba_mid.write(0);
             channel.ports.lba_hi.write(0);
 
             channel.write_command(Command::Identify);
             let status = channel.status();
-            if status.bits() == 0 {
+            // nothing drives the bus of a channel without drives, so it reads
+            // as all ones
+            if status.bits() == 0 || status.bits() == 0xFF {
                 return Ok(false);
             }
 
-            while channel.status().contains(Status::BUSY) {
-                // do nothing
-            }
-            if channel.ports.lba_mid.read() != 0 || channel.ports.lba_hi.read() != 0 {
+            if !channel.poll_on_status_for(|s| !s.contains(Status::BUSY), PROBE_TIMEOUT) {
+                debug!("drive {:#X} stays busy after IDENTIFY", self.drive);
                 return Ok(false);
             }
-            loop {
-                let status = channel.status();
-                if status.contains(Status::ERROR) {
-                    return Err(IdentifyError);
-                }
-                if status.contains(Status::DATA_READY) {
-                    break;
-                }
+            let signature = (channel.ports.lba_mid.read(), channel.ports.lba_hi.read());
+            if signature == ATAPI_SIGNATURE {
+                return self.identify_packet_device(&mut channel);
+            }
+            if signature != (0, 0) {
+                return Ok(false);
             }
+            wait_for_identify_data(&mut channel)?;
 
             channel.wait_for_not_busy();
 
@@ -136,37 +195,69 @@ impl IdeDrive {
             channel.wait_for_ready();
             channel.ports.command.write(Command::ReadSectors.into());
 
-            for i in 0..self.identify_sector.len() {
-                self.identify_sector[i] = channel.ports.data.read();
+            let mut identify_sector = [0_u16; 256];
+            for word in &mut identify_sector {
+                *word = channel.ports.data.read();
             }
             interrupts::enable();
 
-            let udma_indicator = self.identify_sector[88];
-            self.active_udma_mode = UDMAMode::from_bits_truncate((udma_indicator >> 8) as u8);
-            self.supported_udma_modes = UDMAMode::from_bits_truncate(udma_indicator as u8);
-
-            if self.is_lba48_supported() {
-                self.sector_count = self.identify_sector[100] as u64
-                    | ((self.identify_sector[101] as u64) << 16)
-                    | ((self.identify_sector[102] as u64) << 32)
-                    | ((self.identify_sector[103] as u64) << 48)
-            } else {
-                self.sector_count =
-                    self.identify_sector[60] as u64 | ((self.identify_sector[61] as u64) << 16)
+            self.info = DriveInfo::from_identify(&identify_sector);
+        }
+        Ok(true)
+    }
+
+    /// Identifies a packet device, which rejected the IDENTIFY command, and
+    /// reads the capacity of its medium. A drive without a medium has no
+    /// sectors.
+    fn identify_packet_device(&mut self, channel: &mut IdeChannel) -> Result<bool, IdentifyError> {
+        channel.write_command(Command::IdentifyPacket);
+        wait_for_identify_data(channel)?;
+        let mut identify_sector = [0_u16; 256];
+        without_interrupts(|| {
+            for word in &mut identify_sector {
+                *word = unsafe { channel.ports.data.read() };
+            }
+        });
+
+        self.kind = DriveKind::Atapi;
+        self.info = DriveInfo::from_identify(&identify_sector);
+        // the capacity is a property of the medium, not of the drive
+        self.info.sector_count = 0;
+        self.sector_size = ATAPI_SECTOR_SIZE;
+        match atapi::read_capacity(channel, self.drive) {
+            Ok((sector_count, sector_size)) => {
+                if sector_size != ATAPI_SECTOR_SIZE {
+                    warn!("{self} has sectors of {sector_size} bytes, which are read as {ATAPI_SECTOR_SIZE} bytes");
+                }
+                self.info.sector_count = sector_count;
             }
+            Err(sense) => debug!("can't read the capacity of {self}: {sense}"),
         }
         Ok(true)
     }
 
     pub fn is_lba48_supported(&self) -> bool {
-        is_bit_set(self.identify_sector[83] as u64, 10)
+        self.info.lba48
     }
 
     pub fn supported_udma_modes(&self) -> UDMAMode {
-        self.supported_udma_modes
+        self.info.supported_udma_modes
     }
 
     pub fn active_udma_mode(&self) -> UDMAMode {
-        self.active_udma_mode
+        self.info.active_udma_mode
+    }
+}
+
+/// Waits until the drive has the data of IDENTIFY (PACKET) DEVICE ready, or
+/// reports an error.
+fn wait_for_identify_data(channel: &mut IdeChannel) -> Result<(), IdentifyError> {
+    // the other bits are only valid once the drive is no longer busy
+    let done =
+        |s: Status| !s.contains(Status::BUSY) && s.intersects(Status::ERROR | Status::DATA_READY);
+    if !channel.poll_on_status_for(done, PROBE_TIMEOUT) || channel.status().contains(Status::ERROR)
+    {
+        return Err(IdentifyError);
     }
+    Ok(())
 }
@@ -0,0 +1,195 @@
+use alloc::string::String;
+use core::fmt::{Display, Formatter};
+use core::ops::RangeInclusive;
+
+use crate::driver::ide::UDMAMode;
+
+const SERIAL_WORDS: RangeInclusive<usize> = 10..=19;
+const FIRMWARE_WORDS: RangeInclusive<usize> = 23..=26;
+const MODEL_WORDS: RangeInclusive<usize> = 27..=46;
+const LBA28_SECTORS_WORD: usize = 60;
+const FEATURE_SETS_WORD: usize = 82;
+const SMART_BIT: u16 = 1 << 0;
+const COMMAND_SETS_WORD: usize = 83;
+const LBA48_BIT: u16 = 1 << 10;
+const UDMA_WORD: usize = 88;
+const LBA48_SECTORS_WORD: usize = 100;
+
+/// What a drive tells about itself in the data of IDENTIFY DEVICE, or of
+/// IDENTIFY PACKET DEVICE for packet devices.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DriveInfo {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// Whether the drive understands the 48 bit LBA commands.
+    pub lba48: bool,
+    /// Whether the drive supports the SMART feature set.
+    pub smart: bool,
+    /// The number of addressable sectors. For packet devices, this is the
+    /// capacity of the medium, which isn't part of the IDENTIFY data.
+    pub sector_count: u64,
+    pub supported_udma_modes: UDMAMode,
+    pub active_udma_mode: UDMAMode,
+}
+
+impl DriveInfo {
+    pub fn from_identify(data: &[u16; 256]) -> Self {
+        let lba48 = data[COMMAND_SETS_WORD] & LBA48_BIT != 0;
+        let lba28_sectors = dwords(&data[LBA28_SECTORS_WORD..LBA28_SECTORS_WORD + 2]);
+        let lba48_sectors = dwords(&data[LBA48_SECTORS_WORD..LBA48_SECTORS_WORD + 4]);
+        // some drives that support LBA48 leave its capacity empty
+        let sector_count = if lba48 && lba48_sectors != 0 {
+            lba48_sectors
+        } else {
+            lba28_sectors
+        };
+        let udma = data[UDMA_WORD];
+
+        Self {
+            model: ata_string(&data[MODEL_WORDS]),
+            serial: ata_string(&data[SERIAL_WORDS]),
+            firmware: ata_string(&data[FIRMWARE_WORDS]),
+            lba48,
+            smart: data[FEATURE_SETS_WORD] & SMART_BIT != 0,
+            sector_count,
+            supported_udma_modes: UDMAMode::from_bits_truncate(udma as u8),
+            active_udma_mode: UDMAMode::from_bits_truncate((udma >> 8) as u8),
+        }
+    }
+}
+
+impl Display for DriveInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        writeln!(f, "model: {}", self.model)?;
+        writeln!(f, "serial: {}", self.serial)?;
+        writeln!(f, "firmware: {}", self.firmware)?;
+        writeln!(f, "lba48: {}", if self.lba48 { "yes" } else { "no" })?;
+        writeln!(f, "smart: {}", if self.smart { "yes" } else { "no" })?;
+        writeln!(f, "sectors: {}", self.sector_count)?;
+        write!(f, "udma supported:")?;
+        write_udma_modes(f, self.supported_udma_modes)?;
+        write!(f, "udma active:")?;
+        write_udma_modes(f, self.active_udma_mode)
+    }
+}
+
+/// Writes the numbers of the modes, like ` 1 2 3`, and ends the line.
+fn write_udma_modes(f: &mut Formatter<'_>, modes: UDMAMode) -> core::fmt::Result {
+    for mode in modes.iter() {
+        write!(f, " {}", mode.bits().trailing_zeros() + 1)?;
+    }
+    writeln!(f)
+}
+
+/// Combines little endian words, the first word being the least significant.
+fn dwords(words: &[u16]) -> u64 {
+    words
+        .iter()
+        .rev()
+        .fold(0, |value, &word| (value << 16) | word as u64)
+}
+
+/// Decodes a string of the IDENTIFY data, which has the first character of
+/// every word in its upper byte. The string ends at the first byte that is not
+/// printable ASCII, since some drives pad with zeros or garbage instead of
+/// spaces, and surrounding spaces are trimmed.
+fn ata_string(words: &[u16]) -> String {
+    let text = words
+        .iter()
+        .flat_map(|word| word.to_be_bytes())
+        .take_while(|byte| (0x20..=0x7E).contains(byte))
+        .map(char::from)
+        .collect::<String>();
+    String::from(text.trim())
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::string::ToString;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide::identify::DriveInfo;
+    use crate::driver::ide::UDMAMode;
+
+    /// Stores the string in the given words like a drive does, padded with
+    /// `padding`.
+    fn put_string(data: &mut [u16; 256], first_word: usize, len: usize, s: &str, padding: u8) {
+        let mut bytes = [padding; 40];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        for (i, pair) in bytes[..len * 2].chunks(2).enumerate() {
+            data[first_word + i] = u16::from_be_bytes([pair[0], pair[1]]);
+        }
+    }
+
+    fn identify(padding: u8) -> [u16; 256] {
+        let mut data = [0_u16; 256];
+        put_string(&mut data, 10, 10, "QM00001", padding);
+        put_string(&mut data, 23, 4, "2.5+", padding);
+        put_string(&mut data, 27, 20, "QEMU HARDDISK", padding);
+        data
+    }
+
+    #[kernel_test]
+    fn test_lba28_only() {
+        let mut data = identify(b' ');
+        data[60] = 0x2800;
+        data[61] = 0x0001;
+        // an LBA48 capacity without the feature bit is ignored
+        data[100] = 0xFFFF;
+        data[88] = 0x0007;
+
+        let info = DriveInfo::from_identify(&data);
+        assert_eq!("QEMU HARDDISK", info.model);
+        assert_eq!("QM00001", info.serial);
+        assert_eq!("2.5+", info.firmware);
+        assert!(!info.lba48);
+        assert!(!info.smart);
+        assert_eq!(0x1_2800, info.sector_count);
+        assert_eq!(
+            UDMAMode::UDMA_1 | UDMAMode::UDMA_2 | UDMAMode::UDMA_3,
+            info.supported_udma_modes
+        );
+        assert_eq!(UDMAMode::empty(), info.active_udma_mode);
+    }
+
+    #[kernel_test]
+    fn test_lba48() {
+        let mut data = identify(b' ');
+        // the 28 bit field is capped for disks of 128GiB and more
+        data[60] = 0xFFFF;
+        data[61] = 0x0FFF;
+        data[82] = 1 << 0;
+        data[83] = 1 << 10;
+        data[100] = 0x0000;
+        data[101] = 0x4000;
+        data[102] = 0x0001;
+        data[103] = 0x0000;
+        data[88] = 0x207F;
+
+        let info = DriveInfo::from_identify(&data);
+        assert!(info.lba48);
+        assert!(info.smart);
+        assert_eq!(0x1_4000_0000, info.sector_count);
+        assert_eq!(UDMAMode::all(), info.supported_udma_modes);
+        assert_eq!(UDMAMode::UDMA_6, info.active_udma_mode);
+        assert!(info.to_string().contains("udma active: 6\n"));
+    }
+
+    #[kernel_test]
+    fn test_garbage_padding() {
+        for padding in [0x00, 0xFF] {
+            let mut data = identify(padding);
+            // leading spaces are trimmed as well
+            put_string(&mut data, 10, 10, "  SN-42", padding);
+            data[60] = 100;
+
+            let info = DriveInfo::from_identify(&data);
+            assert_eq!("QEMU HARDDISK", info.model);
+            assert_eq!("SN-42", info.serial);
+            assert_eq!("2.5+", info.firmware);
+            assert_eq!(100, info.sector_count);
+        }
+    }
+}
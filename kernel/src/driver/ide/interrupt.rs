@@ -0,0 +1,179 @@
+use core::future::{poll_fn, Future};
+use core::sync::atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering};
+use core::task::Poll;
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use foundation::time::Instant;
+use futures::task::AtomicWaker;
+use x86_64::instructions::hlt;
+use x86_64::instructions::port::{Port, PortReadOnly};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::ide::dma::BUS_MASTER_INTERRUPT;
+use crate::driver::ide::Status;
+use crate::time::HpetInstantProvider;
+
+/// How long a command may take until it's considered lost, and the channel is
+/// reset.
+pub const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The interrupts of the primary and secondary channel of the controller that
+/// does DMA transfers.
+static INTERRUPTS: [OnceCell<ChannelInterrupt>; 2] = [OnceCell::uninit(), OnceCell::uninit()];
+
+/// The status of the drive and of the bus master when the interrupt of a
+/// command was raised.
+pub struct Completion {
+    pub status: Status,
+    pub bus_master_status: u8,
+}
+
+/// The command didn't complete within [`COMMAND_TIMEOUT`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TimedOut;
+
+/// The interrupt of a channel, which completes the command that is in flight
+/// on the channel. There is at most one, since the channel is locked for the
+/// duration of a command.
+pub struct ChannelInterrupt {
+    bus_master_status: u16,
+    status: u16,
+    raised: AtomicBool,
+    /// The drive status in the low byte, the bus master status in the high
+    /// byte, as read by the interrupt handler.
+    completion: AtomicU16,
+    /// When the command in flight times out, in nanoseconds since boot, or
+    /// zero if no command is in flight.
+    deadline: AtomicU64,
+    waker: AtomicWaker,
+}
+
+impl ChannelInterrupt {
+    /// Must be called before a command is issued, so that only the interrupt
+    /// of that command completes it.
+    pub fn arm(&self) {
+        self.raised.store(false, Ordering::SeqCst);
+        let deadline = Instant::now() + COMMAND_TIMEOUT;
+        self.deadline.store(nanos(deadline), Ordering::SeqCst);
+    }
+
+    /// Waits for the armed command by halting until its interrupt was raised.
+    pub fn wait(&self) -> Result<Completion, TimedOut> {
+        loop {
+            if let Some(result) = self.take() {
+                return result;
+            }
+            hlt();
+        }
+    }
+
+    /// Like [`ChannelInterrupt::wait`], but for callers that run on an
+    /// executor. Besides the interrupt handler, the timer interrupt wakes the
+    /// task once the command timed out.
+    pub fn wait_async(&self) -> impl Future<Output = Result<Completion, TimedOut>> + '_ {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            match self.take() {
+                Some(result) => Poll::Ready(result),
+                None => Poll::Pending,
+            }
+        })
+    }
+
+    fn take(&self) -> Option<Result<Completion, TimedOut>> {
+        if self.raised.swap(false, Ordering::SeqCst) {
+            self.deadline.store(0, Ordering::SeqCst);
+            let completion = self.completion.load(Ordering::SeqCst);
+            return Some(Ok(Completion {
+                status: Status::from_bits_truncate(completion as u8),
+                bus_master_status: (completion >> 8) as u8,
+            }));
+        }
+        if self.timed_out(nanos(Instant::now())) {
+            self.deadline.store(0, Ordering::SeqCst);
+            return Some(Err(TimedOut));
+        }
+        None
+    }
+
+    fn timed_out(&self, now: u64) -> bool {
+        let deadline = self.deadline.load(Ordering::SeqCst);
+        deadline != 0 && now >= deadline
+    }
+
+    fn handle(&self) {
+        let mut bus_master_status = Port::<u8>::new(self.bus_master_status);
+        let mut status = PortReadOnly::<u8>::new(self.status);
+        let completion = unsafe {
+            let bus_master_status = bus_master_status.read();
+            if bus_master_status & BUS_MASTER_INTERRUPT == 0 {
+                return;
+            }
+            // reading the status acknowledges the interrupt on the drive
+            u16::from(status.read()) | (u16::from(bus_master_status) << 8)
+        };
+        self.completion.store(completion, Ordering::SeqCst);
+        self.raised.store(true, Ordering::SeqCst);
+        self.waker.wake();
+    }
+}
+
+fn nanos(instant: Instant) -> u64 {
+    instant.duration_since(Instant::new(0)).as_nanos() as u64
+}
+
+/// Wakes the tasks that wait for commands that timed out. This is called from
+/// the timer interrupt, so it must not wait for any lock.
+pub fn check_timeouts() {
+    let in_flight = || {
+        INTERRUPTS
+            .iter()
+            .filter_map(OnceCell::get)
+            .filter(|interrupt| interrupt.deadline.load(Ordering::Relaxed) != 0)
+    };
+    // the clock is only read if there is something to time out
+    if in_flight().next().is_none() {
+        return;
+    }
+    let now = nanos(Instant::now());
+    in_flight()
+        .filter(|interrupt| interrupt.timed_out(now))
+        .for_each(|interrupt| interrupt.waker.wake());
+}
+
+/// Sets up the interrupt of the given channel (0 for the primary, 1 for the
+/// secondary channel), and returns it. Returns `None` if the channel already
+/// has an interrupt, which is the case if there is more than one controller.
+///
+/// The interrupt is handled by [`interrupt_handler`].
+pub fn channel_interrupt(
+    channel: usize,
+    bus_master_base: u16,
+    iobase: u16,
+) -> Option<&'static ChannelInterrupt> {
+    let mut initialized = false;
+    let interrupt = INTERRUPTS[channel].get_or_init(|| {
+        initialized = true;
+        ChannelInterrupt {
+            bus_master_status: bus_master_base + 2,
+            status: iobase + 7,
+            raised: AtomicBool::new(false),
+            completion: AtomicU16::new(0),
+            deadline: AtomicU64::new(0),
+            waker: AtomicWaker::new(),
+        }
+    });
+    initialized.then_some(interrupt)
+}
+
+/// Handles the IRQs of both channels. Channels in native mode share an IRQ,
+/// so the bus master status tells which channel raised it.
+pub extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    INTERRUPTS
+        .iter()
+        .filter_map(OnceCell::get)
+        .for_each(ChannelInterrupt::handle);
+    unsafe { end_of_interrupt() };
+}
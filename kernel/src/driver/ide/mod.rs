@@ -1,41 +1,92 @@
 use alloc::boxed::Box;
-use core::alloc::AllocError;
+use alloc::vec::Vec;
 use core::error::Error;
 use core::fmt::{Debug, Display, Formatter};
 
+use crate::driver::block::Revocable;
+use crate::driver::disk;
+use crate::driver::disk::Disk;
 use crate::driver::ide::controller::IdeController;
-use crate::driver::pci::{PciDriverDescriptor, PCI_DRIVERS};
+use crate::driver::pci::{PciDriverDescriptor, PciMatch, PCI_DRIVERS};
 use bitflags::bitflags;
-use conquer_once::spin::OnceCell;
 pub use device::*;
-use foundation::falloc::vec::FVec;
+use filesystem::BlockDevice;
+pub use identify::DriveInfo;
+pub use interrupt::check_timeouts;
 use linkme::distributed_slice;
-use spin::Mutex;
+use log::{info, warn};
+pub use smart::{SmartAttribute, SmartError, SmartStatus};
 
+mod atapi;
 mod channel;
 mod command;
 mod controller;
 mod device;
+mod dma;
 mod drive;
+mod identify;
+mod interrupt;
+mod queue;
+mod smart;
 
 #[distributed_slice(PCI_DRIVERS)]
 static IDE_CONTROLLER_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
     name: "IDEController",
-    probe: IdeController::probe,
+    matches: &[PciMatch::Class {
+        class: 0x01,
+        subclass: Some(0x01),
+        prog_if: None,
+    }],
     init: IdeController::init,
 };
 
-static IDE_DEVICES: OnceCell<Mutex<FVec<IdeBlockDevice>>> = OnceCell::uninit();
-
 fn register_ide_block_device(device: IdeBlockDevice) -> Result<(), Box<dyn Error>> {
-    match devices().lock().try_push(device) {
-        Ok(_) => Ok(()),
-        Err(_e) => Err(Box::new(AllocError)),
+    let info = device.info();
+    info!(
+        "found {} (serial {}, firmware {}) with {} sectors of {} bytes",
+        info.model,
+        info.serial,
+        info.firmware,
+        info.sector_count,
+        device.sector_size()
+    );
+    // the partitions are read and the devfs publishes the device when the
+    // registry notifies them
+    let index = disk::devices().register(Disk::Ide(device.clone()))?;
+    log_smart_health(index, &device);
+    Ok(())
+}
+
+/// Logs the health that the device reports with SMART in a single line, so
+/// that a failing drive stands out at boot.
+fn log_smart_health(index: usize, device: &IdeBlockDevice) {
+    let health = device
+        .smart_status()
+        .and_then(|status| Ok((status, device.smart_attributes()?)));
+    match health {
+        Ok((status, attributes)) => {
+            let summary = smart::summary(status, &attributes);
+            if status == SmartStatus::Healthy
+                && !attributes.iter().any(SmartAttribute::is_threshold_exceeded)
+            {
+                info!("block device {index} is {summary}");
+            } else {
+                warn!("block device {index} is about to fail: {summary}");
+            }
+        }
+        Err(SmartError::Unsupported) => {}
+        Err(e) => warn!("can't read the SMART data of block device {index}: {e}"),
     }
 }
 
-pub fn devices() -> &'static Mutex<FVec<IdeBlockDevice>> {
-    IDE_DEVICES.get_or_init(Mutex::default)
+/// The IDE drives among the [`disk::devices`], in the order in which they
+/// were found.
+pub fn devices() -> Vec<Revocable<IdeBlockDevice>> {
+    disk::devices()
+        .lock()
+        .iter()
+        .filter_map(|disk| disk.filter_map(Disk::as_ide))
+        .collect()
 }
 
 bitflags! {
@@ -0,0 +1,247 @@
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::future::poll_fn;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::SeqCst;
+use core::task::{Poll, Waker};
+
+use foundation::future::lock::{FutureMutex, FutureMutexGuard};
+use spin::Mutex;
+
+/// The value of [`ChannelQueue::last`] before the first request.
+const NOBODY: usize = usize::MAX;
+
+/// The requests for an IDE channel. Both drives of a channel share one set of
+/// task file registers, so the channel is handed to one request at a time,
+/// which keeps it until its command completed. If both drives have requests
+/// waiting, they take turns, so that a busy drive can't starve the other one.
+pub struct ChannelQueue<T> {
+    channel: FutureMutex<T>,
+    /// The number of requests that wait for the channel, by drive.
+    waiting: [AtomicUsize; 2],
+    /// The drive of the request that had the channel last.
+    last: AtomicUsize,
+    /// The tasks that wait for the channel. They are all woken whenever the
+    /// channel is released, and find out themselves whose turn it is.
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl<T> ChannelQueue<T> {
+    pub fn new(channel: T) -> Self {
+        Self {
+            channel: FutureMutex::new(channel),
+            waiting: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            last: AtomicUsize::new(NOBODY),
+            wakers: Mutex::default(),
+        }
+    }
+
+    /// Waits for the turn of the request for `drive`, which is the value of
+    /// the drive select register that selects it, like `0xA0` or `0xB0`.
+    pub fn lock(&self, drive: u8) -> ChannelGuard<'_, T> {
+        let mut ticket = self.enqueue(drive);
+        loop {
+            if let Some(guard) = self.try_take(&mut ticket) {
+                return guard;
+            }
+            spin_loop();
+        }
+    }
+
+    /// Like [`ChannelQueue::lock`], but for callers that run on an executor.
+    pub async fn lock_async(&self, drive: u8) -> ChannelGuard<'_, T> {
+        let mut ticket = self.enqueue(drive);
+        poll_fn(|cx| {
+            // registered before trying, so that a release in between isn't lost
+            self.wakers.lock().push(cx.waker().clone());
+            match self.try_take(&mut ticket) {
+                Some(guard) => Poll::Ready(guard),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    /// Locks the channel if nobody has it, regardless of whose turn it is.
+    pub fn try_lock(&self) -> Option<FutureMutexGuard<'_, T>> {
+        self.channel.try_lock()
+    }
+
+    fn enqueue(&self, drive: u8) -> Ticket<'_, T> {
+        let index = usize::from(drive >> 4 & 1);
+        self.waiting[index].fetch_add(1, SeqCst);
+        Ticket {
+            queue: self,
+            index,
+            granted: false,
+        }
+    }
+
+    fn try_take(&self, ticket: &mut Ticket<'_, T>) -> Option<ChannelGuard<'_, T>> {
+        let other = 1 - ticket.index;
+        if self.last.load(SeqCst) == ticket.index && self.waiting[other].load(SeqCst) > 0 {
+            return None;
+        }
+        let guard = self.channel.try_lock()?;
+        ticket.granted = true;
+        self.waiting[ticket.index].fetch_sub(1, SeqCst);
+        self.last.store(ticket.index, SeqCst);
+        Some(ChannelGuard {
+            guard: Some(guard),
+            queue: self,
+        })
+    }
+
+    fn wake_all(&self) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+impl<T: Debug> Debug for ChannelQueue<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ChannelQueue")
+            .field("channel", &self.try_lock())
+            .field("waiting", &self.waiting)
+            .finish()
+    }
+}
+
+/// A request that waits for its turn. A request that stops waiting without
+/// getting the channel, because its future was dropped, leaves the queue.
+struct Ticket<'a, T> {
+    queue: &'a ChannelQueue<T>,
+    index: usize,
+    granted: bool,
+}
+
+impl<T> Drop for Ticket<'_, T> {
+    fn drop(&mut self) {
+        if !self.granted {
+            self.queue.waiting[self.index].fetch_sub(1, SeqCst);
+            // the other drive may not have to wait anymore
+            self.queue.wake_all();
+        }
+    }
+}
+
+/// The channel, for the request whose turn it is.
+pub struct ChannelGuard<'a, T> {
+    guard: Option<FutureMutexGuard<'a, T>>,
+    queue: &'a ChannelQueue<T>,
+}
+
+impl<T> Deref for ChannelGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        self.guard.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for ChannelGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.guard.as_mut().unwrap()
+    }
+}
+
+impl<T> Drop for ChannelGuard<'_, T> {
+    fn drop(&mut self) {
+        // unlocked first, so that the woken tasks can take the channel
+        drop(self.guard.take());
+        self.queue.wake_all();
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::ffi::c_void;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::SeqCst;
+    use core::task::{Context, Waker};
+
+    use kernel_test_framework::kernel_test;
+    use x86_64::instructions::hlt;
+
+    use crate::driver::ide::queue::ChannelQueue;
+    use crate::process;
+    use crate::process::Priority;
+
+    const REQUESTS: usize = 64;
+
+    /// A channel that remembers which drive it was programmed for and which
+    /// drives it served.
+    #[derive(Default)]
+    struct Simulated {
+        selected: Option<u8>,
+        served: [usize; 2],
+        /// How many requests the other drive got done when the first drive
+        /// finished all of its requests.
+        other_when_done: Option<usize>,
+    }
+
+    static DONE: AtomicUsize = AtomicUsize::new(0);
+
+    extern "C" fn hammer(arg: *mut c_void) {
+        let (queue, drive) = unsafe { *(arg as *const (&ChannelQueue<Simulated>, u8)) };
+        let index = usize::from(drive >> 4 & 1);
+        for _ in 0..REQUESTS {
+            let mut channel = queue.lock(drive);
+            channel.selected = Some(drive);
+            // like a real command, wait for an interrupt, which lets the
+            // other thread run and queue up
+            hlt();
+            // nobody else programmed the channel in the meantime
+            assert_eq!(Some(drive), channel.selected);
+            channel.served[index] += 1;
+            if channel.served[index] == REQUESTS && channel.other_when_done.is_none() {
+                channel.other_when_done = Some(channel.served[1 - index]);
+            }
+        }
+        DONE.fetch_add(1, SeqCst);
+    }
+
+    #[kernel_test]
+    fn test_drives_take_turns() {
+        let queue = ChannelQueue::new(Simulated::default());
+        DONE.store(0, SeqCst);
+        let args = [(&queue, 0xA0_u8), (&queue, 0xB0_u8)];
+        for arg in &args {
+            process::spawn_thread_in_current_process(
+                "ide_hammer",
+                Priority::Normal,
+                hammer,
+                arg as *const (&ChannelQueue<Simulated>, u8) as *mut c_void,
+            );
+        }
+        while DONE.load(SeqCst) < 2 {
+            hlt();
+        }
+
+        let channel = queue.try_lock().unwrap();
+        assert_eq!([REQUESTS, REQUESTS], channel.served);
+        // the drive that finished last wasn't left far behind
+        let other = channel.other_when_done.unwrap();
+        assert!(other >= REQUESTS / 2, "{other} of {REQUESTS} requests");
+    }
+
+    #[kernel_test]
+    fn test_cancelled_request_leaves_the_queue() {
+        let queue = ChannelQueue::new(Simulated::default());
+        let master = queue.lock(0xA0);
+        {
+            let mut request = pin!(queue.lock_async(0xB0));
+            let mut cx = Context::from_waker(Waker::noop());
+            assert!(request.as_mut().poll(&mut cx).is_pending());
+        }
+        drop(master);
+        // the master doesn't wait for the slave that gave up
+        drop(queue.lock(0xA0));
+    }
+}
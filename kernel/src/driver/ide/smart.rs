@@ -0,0 +1,349 @@
+//! The SMART feature set, with which a drive reports its own health.
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Display, Formatter, Write};
+
+use thiserror::Error;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::driver::ide::channel::IdeChannel;
+use crate::driver::ide::command::Command;
+use crate::driver::ide::{IdeError, Status};
+
+/// The LBA mid and high registers of every SMART command. A drive that
+/// reports that a threshold was exceeded swaps them for
+/// [`THRESHOLD_EXCEEDED_SIGNATURE`].
+const SMART_SIGNATURE: (u8, u8) = (0x4F, 0xC2);
+const THRESHOLD_EXCEEDED_SIGNATURE: (u8, u8) = (0xF4, 0x2C);
+
+const READ_DATA: u8 = 0xD0;
+const READ_THRESHOLDS: u8 = 0xD1;
+const ENABLE_OPERATIONS: u8 = 0xD8;
+const RETURN_STATUS: u8 = 0xDA;
+
+/// Where the attribute table starts in the data of READ DATA and READ
+/// THRESHOLDS, and how many entries of how many bytes it has.
+const ATTRIBUTES_OFFSET: usize = 2;
+const ATTRIBUTE_COUNT: usize = 30;
+const ATTRIBUTE_LEN: usize = 12;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum SmartError {
+    #[error("the drive doesn't support SMART")]
+    Unsupported,
+    #[error("the drive rejected the SMART command: {0}")]
+    Command(IdeError),
+    #[error("the checksum of the SMART data doesn't match")]
+    Checksum,
+    #[error("the drive returned an unknown SMART status")]
+    UnknownStatus,
+}
+
+/// The overall health that a drive reports.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SmartStatus {
+    Healthy,
+    /// At least one attribute is at or below its threshold, so the drive
+    /// expects to fail.
+    ThresholdExceeded,
+}
+
+impl Display for SmartStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            SmartStatus::Healthy => write!(f, "healthy"),
+            SmartStatus::ThresholdExceeded => write!(f, "threshold exceeded"),
+        }
+    }
+}
+
+/// An entry of the attribute table of a drive. The meaning of the values is
+/// up to the vendor, except for a few [`SmartAttribute::name`]d attributes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SmartAttribute {
+    pub id: u8,
+    pub flags: u16,
+    /// The normalized value, where higher is better.
+    pub current: u8,
+    /// The lowest normalized value so far.
+    pub worst: u8,
+    pub raw: u64,
+    /// The normalized value at or below which the drive is expected to fail,
+    /// or 0 if there is none.
+    pub threshold: u8,
+}
+
+impl SmartAttribute {
+    /// The attributes that tell about sectors going bad, which are the same
+    /// for all vendors.
+    pub const REALLOCATED_SECTORS: u8 = 5;
+    pub const REPORTED_UNCORRECTABLE: u8 = 187;
+    pub const PENDING_SECTORS: u8 = 197;
+    pub const OFFLINE_UNCORRECTABLE: u8 = 198;
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self.id {
+            Self::REALLOCATED_SECTORS => Some("reallocated sectors"),
+            Self::REPORTED_UNCORRECTABLE => Some("reported uncorrectable"),
+            Self::PENDING_SECTORS => Some("pending sectors"),
+            Self::OFFLINE_UNCORRECTABLE => Some("offline uncorrectable"),
+            _ => None,
+        }
+    }
+
+    pub fn is_threshold_exceeded(&self) -> bool {
+        self.threshold != 0 && self.current <= self.threshold
+    }
+}
+
+impl Display for SmartAttribute {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:3} {:#06X} {:3} {:3} {:3} {}",
+            self.id, self.flags, self.current, self.worst, self.threshold, self.raw
+        )?;
+        if let Some(name) = self.name() {
+            write!(f, " ({name})")?;
+        }
+        if self.is_threshold_exceeded() {
+            write!(f, " FAILING")?;
+        }
+        Ok(())
+    }
+}
+
+/// Describes the health in a single line, like `healthy, reallocated
+/// sectors 0, pending sectors 2`, with the raw values of the named attributes
+/// and the attributes that exceeded their threshold.
+pub fn summary(status: SmartStatus, attributes: &[SmartAttribute]) -> String {
+    let mut summary = status.to_string();
+    for attribute in attributes {
+        if let Some(name) = attribute.name() {
+            let _ = write!(summary, ", {name} {}", attribute.raw);
+        }
+        if attribute.is_threshold_exceeded() {
+            let _ = write!(summary, ", attribute {} failing", attribute.id);
+        }
+    }
+    summary
+}
+
+/// Decodes the attribute table of READ DATA, with the thresholds of READ
+/// THRESHOLDS. Unused entries are skipped.
+pub fn parse_attributes(
+    data: &[u8; 512],
+    thresholds: &[u8; 512],
+) -> Result<Vec<SmartAttribute>, SmartError> {
+    check_checksum(data)?;
+    check_checksum(thresholds)?;
+    let threshold_entries = entries(thresholds).collect::<Vec<_>>();
+    Ok(entries(data)
+        .map(|entry| SmartAttribute {
+            id: entry[0],
+            flags: u16::from_le_bytes([entry[1], entry[2]]),
+            current: entry[3],
+            worst: entry[4],
+            raw: entry[5..11]
+                .iter()
+                .rev()
+                .fold(0, |raw, &byte| (raw << 8) | byte as u64),
+            threshold: threshold_entries
+                .iter()
+                .find(|threshold| threshold[0] == entry[0])
+                .map_or(0, |threshold| threshold[1]),
+        })
+        .collect())
+}
+
+fn entries(data: &[u8; 512]) -> impl Iterator<Item = &[u8]> {
+    data[ATTRIBUTES_OFFSET..ATTRIBUTES_OFFSET + ATTRIBUTE_COUNT * ATTRIBUTE_LEN]
+        .chunks(ATTRIBUTE_LEN)
+        .filter(|entry| entry[0] != 0)
+}
+
+/// The last byte is chosen so that all bytes add up to zero.
+fn check_checksum(data: &[u8; 512]) -> Result<(), SmartError> {
+    if data.iter().fold(0_u8, |sum, &byte| sum.wrapping_add(byte)) == 0 {
+        Ok(())
+    } else {
+        Err(SmartError::Checksum)
+    }
+}
+
+/// Enables SMART on the drive. Drives may come up with SMART disabled, and
+/// enabling it again is harmless.
+pub fn enable(channel: &mut IdeChannel, drive_num: u8) -> Result<(), SmartError> {
+    execute(channel, drive_num, ENABLE_OPERATIONS)
+}
+
+pub fn return_status(channel: &mut IdeChannel, drive_num: u8) -> Result<SmartStatus, SmartError> {
+    execute(channel, drive_num, RETURN_STATUS)?;
+    let signature = unsafe { (channel.ports.lba_mid.read(), channel.ports.lba_hi.read()) };
+    match signature {
+        SMART_SIGNATURE => Ok(SmartStatus::Healthy),
+        THRESHOLD_EXCEEDED_SIGNATURE => Ok(SmartStatus::ThresholdExceeded),
+        _ => Err(SmartError::UnknownStatus),
+    }
+}
+
+/// Reads the attribute table and its thresholds.
+pub fn read_attributes(
+    channel: &mut IdeChannel,
+    drive_num: u8,
+) -> Result<Vec<SmartAttribute>, SmartError> {
+    let mut data = [0_u8; 512];
+    read(channel, drive_num, READ_DATA, &mut data)?;
+    let mut thresholds = [0_u8; 512];
+    read(channel, drive_num, READ_THRESHOLDS, &mut thresholds)?;
+    parse_attributes(&data, &thresholds)
+}
+
+/// Executes a SMART command that transfers a sector of data to the host.
+fn read(
+    channel: &mut IdeChannel,
+    drive_num: u8,
+    feature: u8,
+    buf: &mut [u8; 512],
+) -> Result<(), SmartError> {
+    execute(channel, drive_num, feature)?;
+    channel.poll_on_status(|status| status.intersects(Status::DATA_READY | Status::ERROR));
+    if channel.status().contains(Status::ERROR) {
+        return Err(SmartError::Command(channel.error()));
+    }
+    without_interrupts(|| {
+        for word in buf.chunks_mut(2) {
+            word.copy_from_slice(&unsafe { channel.ports.data.read() }.to_le_bytes());
+        }
+    });
+    Ok(())
+}
+
+/// Issues the SMART command with the given feature, and waits until the
+/// drive accepted it.
+fn execute(channel: &mut IdeChannel, drive_num: u8, feature: u8) -> Result<(), SmartError> {
+    unsafe {
+        channel.disable_irq();
+        channel.ports.drive_select.write(drive_num);
+        channel.wait_for_not_busy();
+        channel.ports.features.write(feature);
+        channel.ports.sector_count.write(0);
+        channel.ports.lba_lo.write(0);
+        channel.ports.lba_mid.write(SMART_SIGNATURE.0);
+        channel.ports.lba_hi.write(SMART_SIGNATURE.1);
+    }
+    channel.write_command(Command::Smart);
+    channel.wait_for_not_busy();
+    if channel
+        .status()
+        .intersects(Status::ERROR | Status::DRIVE_FAULT_ERROR)
+    {
+        return Err(SmartError::Command(channel.error()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide;
+    use crate::driver::ide::smart::{parse_attributes, SmartAttribute, SmartError, SmartStatus};
+
+    /// Builds a table with the entries, `(id, current, raw)` for READ DATA and
+    /// `(id, threshold)` for READ THRESHOLDS, and a valid checksum.
+    fn table(entries: &[(u8, u8, u64)]) -> [u8; 512] {
+        let mut data = [0_u8; 512];
+        data[0] = 0x10; // revision
+        for (entry, &(id, current, raw)) in data[2..].chunks_mut(12).zip(entries) {
+            entry[0] = id;
+            entry[1] = 0x33; // pre-failure, online
+            entry[3] = current;
+            entry[4] = current - 1;
+            entry[5..11].copy_from_slice(&raw.to_le_bytes()[..6]);
+        }
+        seal(&mut data);
+        data
+    }
+
+    fn thresholds(entries: &[(u8, u8)]) -> [u8; 512] {
+        let mut data = [0_u8; 512];
+        for (entry, &(id, threshold)) in data[2..].chunks_mut(12).zip(entries) {
+            entry[0] = id;
+            entry[1] = threshold;
+        }
+        seal(&mut data);
+        data
+    }
+
+    fn seal(data: &mut [u8; 512]) {
+        let sum = data[..511]
+            .iter()
+            .fold(0_u8, |sum, &byte| sum.wrapping_add(byte));
+        data[511] = sum.wrapping_neg();
+    }
+
+    #[kernel_test]
+    fn test_parse_attributes() {
+        let data = table(&[(5, 100, 0), (9, 98, 0x0102_0304_0506), (197, 200, 3)]);
+        let thresholds = thresholds(&[(5, 36), (197, 0)]);
+        let attributes = parse_attributes(&data, &thresholds).unwrap();
+
+        assert_eq!(3, attributes.len());
+        assert_eq!(
+            SmartAttribute {
+                id: 9,
+                flags: 0x33,
+                current: 98,
+                worst: 97,
+                raw: 0x0102_0304_0506,
+                threshold: 0,
+            },
+            attributes[1]
+        );
+        assert_eq!(Some("reallocated sectors"), attributes[0].name());
+        assert_eq!(36, attributes[0].threshold);
+        assert_eq!(3, attributes[2].raw);
+        assert!(attributes.iter().all(|a| !a.is_threshold_exceeded()));
+    }
+
+    #[kernel_test]
+    fn test_threshold_exceeded() {
+        let data = table(&[(5, 30, 1500), (187, 100, 0)]);
+        let thresholds = thresholds(&[(5, 36), (187, 0)]);
+        let attributes = parse_attributes(&data, &thresholds).unwrap();
+
+        assert!(attributes[0].is_threshold_exceeded());
+        assert_eq!(1500, attributes[0].raw);
+        assert!(!attributes[1].is_threshold_exceeded());
+    }
+
+    #[kernel_test]
+    fn test_checksum_mismatch() {
+        let mut data = table(&[(5, 100, 0)]);
+        let thresholds = thresholds(&[(5, 36)]);
+        data[7] ^= 1;
+        assert_eq!(
+            Err(SmartError::Checksum),
+            parse_attributes(&data, &thresholds)
+        );
+        let data = table(&[(5, 100, 0)]);
+        let mut broken = thresholds;
+        broken[511] ^= 0xFF;
+        assert_eq!(Err(SmartError::Checksum), parse_attributes(&data, &broken));
+    }
+
+    #[kernel_test]
+    fn test_drives() {
+        for device in ide::devices() {
+            if device.is_atapi() {
+                // packet devices don't have the feature set
+                assert_eq!(Err(SmartError::Unsupported), device.smart_status());
+            } else if device.info().smart {
+                assert_eq!(Ok(SmartStatus::Healthy), device.smart_status());
+                assert!(!device.smart_attributes().unwrap().is_empty());
+            }
+        }
+    }
+}
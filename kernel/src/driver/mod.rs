@@ -1,9 +1,15 @@
 pub mod acpi;
+pub mod ahci;
 pub mod apic;
+pub mod block;
+pub mod disk;
 pub mod hpet;
 pub mod ide;
+pub mod nvme;
 pub mod pci;
+pub mod rtc;
 pub mod rtl8139;
 pub mod usb;
 pub mod vga;
+pub mod virtio;
 pub mod xhci;
@@ -0,0 +1,170 @@
+use alloc::vec::Vec;
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::mem::virt::OwnedInterval;
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+use crate::{map_page, unmap_page};
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// The most pages that a buffer can have, which is as many as a single PRP
+/// list page describes, plus the one in PRP1.
+pub const MAX_PAGES: usize = PAGE_SIZE / size_of::<u64>() + 1;
+
+/// Memory that the controller transfers data from or to, along with the PRP
+/// list that describes it if it's more than two pages.
+///
+/// The pages are mapped contiguously into the current address space for as
+/// long as the buffer lives, so the buffer must not be used from a different
+/// address space.
+pub struct DataBuffer {
+    prp_list: Option<PhysFrame>,
+    frames: Vec<PhysFrame>,
+    interval: OwnedInterval<'static>,
+    len: usize,
+}
+
+impl DataBuffer {
+    /// Allocates a buffer of `len` bytes, which must not be more than
+    /// [`MAX_PAGES`] pages. Returns `None` if there is not enough memory.
+    pub fn allocate(len: usize) -> Option<Self> {
+        let count = len.div_ceil(PAGE_SIZE);
+        assert!(count <= MAX_PAGES, "{len} bytes in a single transfer");
+        let mut frames = Vec::with_capacity(count + 1);
+        // the PRP list comes first, if there is one
+        let pages = if count > 2 { count + 1 } else { count };
+        while frames.len() < pages {
+            match PhysicalMemoryManager::allocate_frame() {
+                Some(frame) => frames.push(frame),
+                None => {
+                    deallocate(frames);
+                    return None;
+                }
+            }
+        }
+        let Ok(interval) = vmm().reserve(pages * PAGE_SIZE) else {
+            deallocate(frames);
+            return None;
+        };
+
+        let first = Page::<Size4KiB>::containing_address(interval.start());
+        for (i, &frame) in frames.iter().enumerate() {
+            map_page!(
+                first + i as u64,
+                frame,
+                Size4KiB,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+            );
+        }
+        let prp_list = (pages > count).then(|| frames.remove(0));
+        let buffer = Self {
+            prp_list,
+            frames,
+            interval,
+            len,
+        };
+        if prp_list.is_some() {
+            let entries = prp_entries(&buffer.frames);
+            unsafe {
+                // safety: the first page is the PRP list, and MAX_PAGES makes
+                // sure that the entries fit into it
+                from_raw_parts_mut(buffer.interval.start().as_mut_ptr::<u64>(), entries.len())
+                    .copy_from_slice(&entries);
+            }
+        }
+        Some(buffer)
+    }
+
+    /// PRP1 and PRP2 of a command that transfers the buffer.
+    pub fn prps(&self) -> (u64, u64) {
+        prps(&self.frames, self.prp_list)
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        unsafe { from_raw_parts(self.data().as_ptr(), self.len) }
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        unsafe { from_raw_parts_mut(self.data().as_mut_ptr(), self.len) }
+    }
+
+    fn data(&self) -> VirtAddr {
+        match self.prp_list {
+            Some(_) => self.interval.start() + PAGE_SIZE as u64,
+            None => self.interval.start(),
+        }
+    }
+}
+
+impl Drop for DataBuffer {
+    fn drop(&mut self) {
+        let pages = self.frames.len() + usize::from(self.prp_list.is_some());
+        let first = Page::<Size4KiB>::containing_address(self.interval.start());
+        for i in 0..pages {
+            unmap_page!(first + i as u64, Size4KiB);
+        }
+        let frames = core::mem::take(&mut self.frames);
+        deallocate(self.prp_list.into_iter().chain(frames).collect());
+    }
+}
+
+fn deallocate(frames: Vec<PhysFrame>) {
+    frames
+        .into_iter()
+        .for_each(PhysicalMemoryManager::deallocate_frame);
+}
+
+/// PRP1 points to the first page. PRP2 points to the second one if there
+/// are two, or to the PRP list with all pages but the first one if there
+/// are more.
+fn prps(frames: &[PhysFrame], prp_list: Option<PhysFrame>) -> (u64, u64) {
+    let addr = |frame: &PhysFrame| frame.start_address().as_u64();
+    let prp1 = frames.first().map_or(0, addr);
+    let prp2 = match (frames.len(), prp_list) {
+        (0 | 1, _) => 0,
+        (2, _) => addr(&frames[1]),
+        (_, list) => addr(&list.expect("more than two pages need a PRP list")),
+    };
+    (prp1, prp2)
+}
+
+fn prp_entries(frames: &[PhysFrame]) -> Vec<u64> {
+    frames[1..]
+        .iter()
+        .map(|frame| frame.start_address().as_u64())
+        .collect()
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use kernel_test_framework::kernel_test;
+    use x86_64::structures::paging::PhysFrame;
+    use x86_64::PhysAddr;
+
+    use crate::driver::nvme::buffer::{prp_entries, prps};
+
+    fn frames(addrs: &[u64]) -> Vec<PhysFrame> {
+        addrs
+            .iter()
+            .map(|&addr| PhysFrame::containing_address(PhysAddr::new(addr)))
+            .collect()
+    }
+
+    #[kernel_test]
+    fn test_prps() {
+        assert_eq!((0x5000, 0), prps(&frames(&[0x5000]), None));
+        assert_eq!((0x5000, 0x9000), prps(&frames(&[0x5000, 0x9000]), None));
+
+        let three = frames(&[0x5000, 0x9000, 0x3000]);
+        let list = frames(&[0x7000])[0];
+        assert_eq!((0x5000, 0x7000), prps(&three, Some(list)));
+        assert_eq!(vec![0x9000, 0x3000], prp_entries(&three));
+    }
+}
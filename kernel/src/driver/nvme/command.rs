@@ -0,0 +1,258 @@
+use thiserror::Error;
+
+const OPCODE_DELETE_IO_SQ: u8 = 0x00;
+const OPCODE_CREATE_IO_SQ: u8 = 0x01;
+const OPCODE_DELETE_IO_CQ: u8 = 0x04;
+const OPCODE_CREATE_IO_CQ: u8 = 0x05;
+const OPCODE_IDENTIFY: u8 = 0x06;
+const OPCODE_ABORT: u8 = 0x08;
+
+const OPCODE_FLUSH: u8 = 0x00;
+const OPCODE_WRITE: u8 = 0x01;
+const OPCODE_READ: u8 = 0x02;
+
+/// What IDENTIFY returns.
+pub const CNS_NAMESPACE: u32 = 0x00;
+pub const CNS_CONTROLLER: u32 = 0x01;
+
+/// The queue is physically contiguous.
+const QUEUE_CONTIGUOUS: u32 = 1 << 0;
+const CQ_INTERRUPTS_ENABLED: u32 = 1 << 1;
+
+/// An entry of a submission queue.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Command {
+    pub opcode: u8,
+    pub flags: u8,
+    /// Set by the queue when the command is submitted, and reported back in
+    /// the completion.
+    pub id: u16,
+    pub nsid: u32,
+    reserved: u64,
+    metadata: u64,
+    pub prp1: u64,
+    pub prp2: u64,
+    pub cdw10: u32,
+    pub cdw11: u32,
+    pub cdw12: u32,
+    pub cdw13: u32,
+    pub cdw14: u32,
+    pub cdw15: u32,
+}
+
+const _: () = assert!(size_of::<Command>() == 64);
+
+impl Command {
+    pub fn identify(cns: u32, nsid: u32, prp1: u64) -> Self {
+        Self {
+            opcode: OPCODE_IDENTIFY,
+            nsid,
+            prp1,
+            cdw10: cns,
+            ..Self::default()
+        }
+    }
+
+    pub fn create_io_cq(id: u16, size: u16, prp1: u64, interrupts: bool) -> Self {
+        let interrupts = if interrupts { CQ_INTERRUPTS_ENABLED } else { 0 };
+        Self {
+            opcode: OPCODE_CREATE_IO_CQ,
+            prp1,
+            cdw10: u32::from(id) | u32::from(size - 1) << 16,
+            // the interrupt vector, which is the first entry of the MSI-X table
+            cdw11: QUEUE_CONTIGUOUS | interrupts,
+            ..Self::default()
+        }
+    }
+
+    pub fn create_io_sq(id: u16, size: u16, prp1: u64, cq: u16) -> Self {
+        Self {
+            opcode: OPCODE_CREATE_IO_SQ,
+            prp1,
+            cdw10: u32::from(id) | u32::from(size - 1) << 16,
+            cdw11: QUEUE_CONTIGUOUS | u32::from(cq) << 16,
+            ..Self::default()
+        }
+    }
+
+    pub fn delete_io_sq(id: u16) -> Self {
+        Self {
+            opcode: OPCODE_DELETE_IO_SQ,
+            cdw10: u32::from(id),
+            ..Self::default()
+        }
+    }
+
+    pub fn delete_io_cq(id: u16) -> Self {
+        Self {
+            opcode: OPCODE_DELETE_IO_CQ,
+            cdw10: u32::from(id),
+            ..Self::default()
+        }
+    }
+
+    /// Asks the controller to abort the command `command` in the submission
+    /// queue `sq`.
+    pub fn abort(sq: u16, command: u16) -> Self {
+        Self {
+            opcode: OPCODE_ABORT,
+            cdw10: u32::from(sq) | u32::from(command) << 16,
+            ..Self::default()
+        }
+    }
+
+    pub fn read(nsid: u32, lba: u64, blocks: u16, (prp1, prp2): (u64, u64)) -> Self {
+        Self::transfer(OPCODE_READ, nsid, lba, blocks, prp1, prp2)
+    }
+
+    pub fn write(nsid: u32, lba: u64, blocks: u16, (prp1, prp2): (u64, u64)) -> Self {
+        Self::transfer(OPCODE_WRITE, nsid, lba, blocks, prp1, prp2)
+    }
+
+    pub fn flush(nsid: u32) -> Self {
+        Self {
+            opcode: OPCODE_FLUSH,
+            nsid,
+            ..Self::default()
+        }
+    }
+
+    fn transfer(opcode: u8, nsid: u32, lba: u64, blocks: u16, prp1: u64, prp2: u64) -> Self {
+        Self {
+            opcode,
+            nsid,
+            prp1,
+            prp2,
+            cdw10: lba as u32,
+            cdw11: (lba >> 32) as u32,
+            // the number of blocks is zero based
+            cdw12: u32::from(blocks - 1),
+            ..Self::default()
+        }
+    }
+}
+
+/// An entry of a completion queue.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Completion {
+    pub result: u32,
+    reserved: u32,
+    pub sq_head: u16,
+    pub sq_id: u16,
+    pub id: u16,
+    /// The phase tag in the lowest bit, followed by the status.
+    pub status: u16,
+}
+
+const _: () = assert!(size_of::<Completion>() == 16);
+
+impl Completion {
+    /// The phase tag, which the controller inverts every time it wraps
+    /// around the queue, so that new entries can be told from old ones.
+    pub fn phase(&self) -> bool {
+        self.status & 1 != 0
+    }
+
+    pub fn status(&self) -> Result<(), StatusCode> {
+        match StatusCode::from_status(self.status) {
+            Some(code) => Err(code),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Why the controller failed a command.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum StatusCode {
+    #[error("invalid opcode")]
+    InvalidOpcode,
+    #[error("invalid field in command")]
+    InvalidField,
+    #[error("data transfer error")]
+    DataTransferError,
+    #[error("command abort requested")]
+    AbortRequested,
+    #[error("invalid namespace or format")]
+    InvalidNamespace,
+    #[error("LBA out of range")]
+    LbaOutOfRange,
+    #[error("capacity exceeded")]
+    CapacityExceeded,
+    #[error("namespace not ready")]
+    NamespaceNotReady,
+    #[error("status code {code:#04x} of type {code_type}")]
+    Other { code_type: u8, code: u8 },
+}
+
+impl StatusCode {
+    /// Decodes the status field of a completion, which includes the phase
+    /// tag. Returns `None` if the command succeeded.
+    pub fn from_status(status: u16) -> Option<Self> {
+        let code = (status >> 1) as u8;
+        let code_type = (status >> 9 & 0b111) as u8;
+        Some(match (code_type, code) {
+            (0, 0x00) => return None,
+            (0, 0x01) => Self::InvalidOpcode,
+            (0, 0x02) => Self::InvalidField,
+            (0, 0x04) => Self::DataTransferError,
+            (0, 0x07) => Self::AbortRequested,
+            (0, 0x0B) => Self::InvalidNamespace,
+            (0, 0x80) => Self::LbaOutOfRange,
+            (0, 0x81) => Self::CapacityExceeded,
+            (0, 0x82) => Self::NamespaceNotReady,
+            _ => Self::Other { code_type, code },
+        })
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::nvme::command::{Command, Completion, StatusCode};
+
+    fn completion(status: u16) -> Completion {
+        Completion {
+            status,
+            ..Completion::default()
+        }
+    }
+
+    #[kernel_test]
+    fn test_status() {
+        // the phase tag alone is a success
+        assert!(completion(1).phase());
+        assert_eq!(Ok(()), completion(1).status());
+        assert_eq!(Ok(()), completion(0).status());
+        assert_eq!(
+            Err(StatusCode::LbaOutOfRange),
+            completion(0x80 << 1 | 1).status()
+        );
+        // the more and do not retry bits don't change the status
+        assert_eq!(
+            Err(StatusCode::InvalidField),
+            completion(1 << 15 | 1 << 14 | 0x02 << 1).status()
+        );
+        // media errors are of type 2
+        assert_eq!(
+            Err(StatusCode::Other {
+                code_type: 2,
+                code: 0x81
+            }),
+            completion(2 << 9 | 0x81 << 1).status()
+        );
+    }
+
+    #[kernel_test]
+    fn test_transfer() {
+        let command = Command::read(1, 0x1_2345_6789, 8, (0x1000, 0x2000));
+        assert_eq!(0x02, command.opcode);
+        assert_eq!(1, command.nsid);
+        assert_eq!(0x2345_6789, command.cdw10);
+        assert_eq!(0x1, command.cdw11);
+        assert_eq!(7, command.cdw12);
+        assert_eq!((0x1000, 0x2000), (command.prp1, command.prp2));
+    }
+}
@@ -0,0 +1,377 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::ptr::{read_volatile, write_volatile};
+use core::time::Duration;
+
+use foundation::time::Instant;
+use log::{debug, warn};
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::VirtAddr;
+
+use crate::driver::nvme::buffer::{DataBuffer, MAX_PAGES};
+use crate::driver::nvme::command::{Command, Completion, CNS_CONTROLLER, CNS_NAMESPACE};
+use crate::driver::nvme::queue::{QueuePair, MAX_QUEUE_SIZE};
+use crate::driver::nvme::NvmeError;
+use crate::time::HpetInstantProvider;
+
+const CAP: usize = 0x00;
+const CC: usize = 0x14;
+const CSTS: usize = 0x1C;
+const AQA: usize = 0x24;
+const ASQ: usize = 0x28;
+const ACQ: usize = 0x30;
+const DOORBELLS: usize = 0x1000;
+
+const CC_ENABLE: u32 = 1 << 0;
+/// The sizes of submission and completion queue entries, as powers of two.
+const CC_IO_QUEUE_ENTRY_SIZES: u32 = 6 << 16 | 4 << 20;
+
+const CSTS_READY: u32 = 1 << 0;
+const CSTS_FATAL: u32 = 1 << 1;
+
+const ADMIN_QUEUE: u16 = 0;
+const IO_QUEUE: u16 = 1;
+
+/// The most bytes that a single command transfers, unless the controller
+/// allows less.
+const MAX_TRANSFER: usize = 128 * 1024;
+const PAGE_SIZE: usize = 4096;
+
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What IDENTIFY CONTROLLER tells about the controller.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ControllerInfo {
+    pub model: String,
+    pub serial: String,
+    pub firmware: String,
+    /// The most bytes that a single command transfers.
+    pub max_transfer: usize,
+    /// Whether written data may sit in a cache until it is flushed.
+    pub volatile_write_cache: bool,
+    pub namespaces: u32,
+}
+
+impl ControllerInfo {
+    fn from_identify(data: &[u8]) -> Self {
+        // in units of the minimum page size, which is 4KiB for us
+        let max_transfer = match data[77] {
+            0 => MAX_TRANSFER,
+            mdts => (PAGE_SIZE << mdts).min(MAX_TRANSFER),
+        };
+        Self {
+            serial: nvme_string(&data[4..24]),
+            model: nvme_string(&data[24..64]),
+            firmware: nvme_string(&data[64..72]),
+            max_transfer,
+            volatile_write_cache: data[525] & 1 != 0,
+            namespaces: u32::from_le_bytes(data[516..520].try_into().unwrap()),
+        }
+    }
+}
+
+/// What IDENTIFY NAMESPACE tells about a namespace.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Namespace {
+    pub id: u32,
+    pub block_count: u64,
+    pub block_size: usize,
+    /// Bytes of metadata per block. Only namespaces without are used.
+    pub metadata_size: usize,
+}
+
+impl Namespace {
+    /// Returns `None` if the namespace is not active.
+    fn from_identify(id: u32, data: &[u8]) -> Option<Self> {
+        let block_count = u64::from_le_bytes(data[0..8].try_into().unwrap());
+        if block_count == 0 {
+            return None;
+        }
+        let format = usize::from(data[26] & 0xF);
+        let lba_format = u32::from_le_bytes(data[128 + format * 4..][..4].try_into().unwrap());
+        Some(Self {
+            id,
+            block_count,
+            block_size: 1 << (lba_format >> 16 & 0xFF),
+            metadata_size: (lba_format & 0xFFFF) as usize,
+        })
+    }
+}
+
+/// An NVMe controller with one IO queue pair, which every namespace shares.
+pub struct Controller {
+    base: VirtAddr,
+    /// Whether completions raise an interrupt, so that waiting for them can
+    /// halt instead of spinning.
+    interrupts: bool,
+    info: ControllerInfo,
+    admin: Mutex<QueuePair>,
+    io: Mutex<QueuePair>,
+}
+
+impl Controller {
+    /// Resets the controller, whose registers are mapped at `base`, sets up
+    /// its queues and identifies it.
+    ///
+    /// # Safety
+    ///
+    /// The registers of the controller must be mapped at `base` for as long
+    /// as the controller lives.
+    pub unsafe fn new(base: VirtAddr, interrupts: bool) -> Result<Self, NvmeError> {
+        let cap = unsafe { read_volatile((base + CAP as u64).as_ptr::<u64>()) };
+        let max_entries = (cap & 0xFFFF) as u16 + 1;
+        let doorbell_stride = 4 << (cap >> 32 & 0xF);
+        // in units of 500ms
+        let ready_timeout = Duration::from_millis((cap >> 24 & 0xFF).max(1) * 500);
+        let queue_size = max_entries.min(MAX_QUEUE_SIZE);
+        let doorbell = |queue: u16, completion: usize| {
+            base + (DOORBELLS + (2 * usize::from(queue) + completion) * doorbell_stride) as u64
+        };
+        let admin = QueuePair::new(
+            ADMIN_QUEUE,
+            queue_size,
+            doorbell(ADMIN_QUEUE, 0),
+            doorbell(ADMIN_QUEUE, 1),
+        )?;
+        let io = QueuePair::new(
+            IO_QUEUE,
+            queue_size,
+            doorbell(IO_QUEUE, 0),
+            doorbell(IO_QUEUE, 1),
+        )?;
+
+        let mut controller = Self {
+            base,
+            interrupts,
+            info: ControllerInfo::from_identify(&[0; 4096]),
+            admin: Mutex::new(admin),
+            io: Mutex::new(io),
+        };
+        controller.enable(ready_timeout)?;
+
+        let buffer = DataBuffer::allocate(PAGE_SIZE).ok_or(NvmeError::NoMemory)?;
+        controller.admin(Command::identify(CNS_CONTROLLER, 0, buffer.prps().0))?;
+        controller.info = ControllerInfo::from_identify(buffer.as_slice());
+
+        controller.create_io_queues()?;
+        Ok(controller)
+    }
+
+    pub fn info(&self) -> &ControllerInfo {
+        &self.info
+    }
+
+    /// The namespaces that are active, in order of their IDs.
+    pub fn namespaces(&self) -> Result<Vec<Namespace>, NvmeError> {
+        let buffer = DataBuffer::allocate(PAGE_SIZE).ok_or(NvmeError::NoMemory)?;
+        let mut namespaces = Vec::new();
+        // inactive namespaces identify as all zeros, which even the first
+        // controllers do, unlike the list of active namespaces
+        for id in 1..=self.info.namespaces.min(1024) {
+            self.admin(Command::identify(CNS_NAMESPACE, id, buffer.prps().0))?;
+            namespaces.extend(Namespace::from_identify(id, buffer.as_slice()));
+        }
+        Ok(namespaces)
+    }
+
+    /// Issues an IO command and waits for it to complete. If it doesn't in
+    /// time, it is aborted and the IO queues are created anew, so that a
+    /// late completion can't be mistaken for the one of a later command.
+    pub fn io(&self, command: Command) -> Result<Completion, NvmeError> {
+        let mut io = self.io.lock();
+        let id = io.submit(command);
+        let result = self.wait(&mut io, id);
+        if result == Err(NvmeError::Timeout) {
+            warn!("NVMe command {id} timed out, resetting the IO queues");
+            if let Err(e) = self.reset_io_queues(&mut io, id) {
+                warn!("failed to reset the NVMe IO queues: {e}");
+            }
+        }
+        result
+    }
+
+    fn admin(&self, command: Command) -> Result<Completion, NvmeError> {
+        let mut admin = self.admin.lock();
+        let id = admin.submit(command);
+        self.wait(&mut admin, id)
+    }
+
+    /// Waits for the completion of the command with the given ID. Stale
+    /// completions of commands that timed out before are skipped.
+    fn wait(&self, queue: &mut QueuePair, id: u16) -> Result<Completion, NvmeError> {
+        let start = Instant::now();
+        loop {
+            while let Some(completion) = queue.poll() {
+                if completion.id == id {
+                    return completion
+                        .status()
+                        .map(|_| completion)
+                        .map_err(NvmeError::Command);
+                }
+                debug!("skipping stale NVMe completion {}", completion.id);
+            }
+            if self.read(CSTS) & CSTS_FATAL != 0 {
+                return Err(NvmeError::ControllerFatal);
+            }
+            if start.elapsed() > COMMAND_TIMEOUT {
+                return Err(NvmeError::Timeout);
+            }
+            if self.interrupts && interrupts::are_enabled() {
+                hlt();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    fn enable(&self, timeout: Duration) -> Result<(), NvmeError> {
+        self.write(CC, 0);
+        self.wait_for_ready(false, timeout)?;
+
+        let admin = self.admin.lock();
+        let size = u32::from(admin.size() - 1);
+        self.write(AQA, size | size << 16);
+        self.write64(ASQ, admin.sq_addr());
+        self.write64(ACQ, admin.cq_addr());
+        drop(admin);
+
+        // NVM command set, 4KiB pages and round robin arbitration are zeros
+        self.write(CC, CC_ENABLE | CC_IO_QUEUE_ENTRY_SIZES);
+        self.wait_for_ready(true, timeout)
+    }
+
+    fn wait_for_ready(&self, ready: bool, timeout: Duration) -> Result<(), NvmeError> {
+        let start = Instant::now();
+        loop {
+            let status = self.read(CSTS);
+            if status & CSTS_FATAL != 0 {
+                return Err(NvmeError::ControllerFatal);
+            }
+            if (status & CSTS_READY != 0) == ready {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(NvmeError::Timeout);
+            }
+            spin_loop();
+        }
+    }
+
+    fn create_io_queues(&self) -> Result<(), NvmeError> {
+        let io = self.io.lock();
+        self.admin(Command::create_io_cq(
+            io.id(),
+            io.size(),
+            io.cq_addr(),
+            self.interrupts,
+        ))?;
+        self.admin(Command::create_io_sq(
+            io.id(),
+            io.size(),
+            io.sq_addr(),
+            io.id(),
+        ))?;
+        Ok(())
+    }
+
+    /// Aborts the command, and deletes and creates the IO queues, which
+    /// discards whatever the controller still has in them.
+    fn reset_io_queues(&self, io: &mut QueuePair, command: u16) -> Result<(), NvmeError> {
+        // the controller may not be able to abort it, which deleting the
+        // queue takes care of
+        if let Err(e) = self.admin(Command::abort(io.id(), command)) {
+            debug!("failed to abort NVMe command {command}: {e}");
+        }
+        self.admin(Command::delete_io_sq(io.id()))?;
+        self.admin(Command::delete_io_cq(io.id()))?;
+        io.reset();
+        self.admin(Command::create_io_cq(
+            io.id(),
+            io.size(),
+            io.cq_addr(),
+            self.interrupts,
+        ))?;
+        self.admin(Command::create_io_sq(
+            io.id(),
+            io.size(),
+            io.sq_addr(),
+            io.id(),
+        ))?;
+        Ok(())
+    }
+
+    fn read(&self, register: usize) -> u32 {
+        unsafe { read_volatile((self.base + register as u64).as_ptr()) }
+    }
+
+    fn write(&self, register: usize, value: u32) {
+        unsafe { write_volatile((self.base + register as u64).as_mut_ptr(), value) }
+    }
+
+    fn write64(&self, register: usize, value: u64) {
+        self.write(register, value as u32);
+        self.write(register + 4, (value >> 32) as u32);
+    }
+}
+
+/// The largest transfer that fits into a buffer, in blocks of the given size.
+pub fn max_blocks(max_transfer: usize, block_size: usize) -> usize {
+    max_transfer.min((MAX_PAGES - 1) * PAGE_SIZE) / block_size
+}
+
+/// Decodes a string of the IDENTIFY data, which is ASCII padded with spaces.
+fn nvme_string(bytes: &[u8]) -> String {
+    let text = bytes
+        .iter()
+        .take_while(|byte| (0x20..=0x7E).contains(*byte))
+        .map(|&byte| char::from(byte))
+        .collect::<String>();
+    String::from(text.trim())
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::nvme::controller::{ControllerInfo, Namespace};
+
+    #[kernel_test]
+    fn test_identify_controller() {
+        let mut data = vec![0_u8; 4096];
+        data[4..24].copy_from_slice(b"devos               ");
+        data[24..40].copy_from_slice(b"QEMU NVMe Ctrl  ");
+        data[40..64].fill(b' ');
+        data[64..72].copy_from_slice(b"9.1.0   ");
+        data[77] = 3;
+        data[516] = 2;
+        data[525] = 1;
+
+        let info = ControllerInfo::from_identify(&data);
+        assert_eq!("devos", info.serial);
+        assert_eq!("QEMU NVMe Ctrl", info.model);
+        assert_eq!("9.1.0", info.firmware);
+        assert_eq!(32 * 1024, info.max_transfer);
+        assert!(info.volatile_write_cache);
+        assert_eq!(2, info.namespaces);
+    }
+
+    #[kernel_test]
+    fn test_identify_namespace() {
+        let mut data = vec![0_u8; 4096];
+        assert_eq!(None, Namespace::from_identify(1, &data));
+
+        data[0..8].copy_from_slice(&0x2_0000_u64.to_le_bytes());
+        // the second format, which has 4KiB blocks
+        data[26] = 1;
+        data[128..132].copy_from_slice(&(9_u32 << 16).to_le_bytes());
+        data[132..136].copy_from_slice(&(12_u32 << 16).to_le_bytes());
+        let namespace = Namespace::from_identify(1, &data).unwrap();
+        assert_eq!(0x2_0000, namespace.block_count);
+        assert_eq!(4096, namespace.block_size);
+        assert_eq!(0, namespace.metadata_size);
+    }
+}
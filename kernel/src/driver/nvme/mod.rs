@@ -0,0 +1,344 @@
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Debug, Formatter};
+use core::slice;
+
+use filesystem::BlockDevice;
+use linkme::distributed_slice;
+use log::{info, warn};
+use spin::Mutex;
+use thiserror::Error;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::arch::idt;
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::apic;
+use crate::driver::disk;
+use crate::driver::disk::Disk;
+use crate::driver::nvme::buffer::DataBuffer;
+use crate::driver::nvme::command::Command;
+pub use crate::driver::nvme::command::StatusCode;
+use crate::driver::nvme::controller::{max_blocks, Controller, Namespace};
+use crate::driver::pci::{
+    Bar, MsiX, MsiXCapability, MsiXTableEntry, PciDevice, PciDriverDescriptor, PciMatch,
+    PCI_DRIVERS,
+};
+use crate::io::vfs::cache::{Flush, MultiBlock};
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::process::vmm;
+
+mod buffer;
+mod command;
+mod controller;
+mod queue;
+
+const COMMAND_MEMORY_SPACE: u16 = 1 << 1;
+/// Disables the interrupt pin, which completions would otherwise raise when
+/// there is no MSI-X.
+const COMMAND_INTERRUPT_DISABLE: u16 = 1 << 10;
+
+#[distributed_slice(PCI_DRIVERS)]
+static NVME_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
+    name: "NVMe",
+    matches: &[PciMatch::Class {
+        class: 0x01,
+        subclass: Some(0x08),
+        prog_if: Some(0x02),
+    }],
+    init,
+};
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum NvmeError {
+    #[error("device has no memory mapped BAR0")]
+    NoBar,
+    #[error("controller reported a fatal status")]
+    ControllerFatal,
+    #[error("command timed out")]
+    Timeout,
+    #[error("failed to allocate memory")]
+    NoMemory,
+    #[error("command failed: {0}")]
+    Command(StatusCode),
+    #[error("device is not connected")]
+    DeviceDisconnected,
+}
+
+fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
+    let device = device.upgrade().ok_or(NvmeError::DeviceDisconnected)?;
+    let mut device = device.lock();
+
+    device
+        .command
+        .update(|command| command | COMMAND_MEMORY_SPACE | COMMAND_INTERRUPT_DISABLE);
+    device.enable_bus_mastering();
+
+    let registers = map_bar(&mut device, 0)?;
+    let interrupts = enable_msix(&mut device, registers);
+    if !interrupts {
+        warn!("NVMe controller has no usable MSI-X, polling instead");
+    }
+    let controller = Arc::new(unsafe { Controller::new(registers, interrupts) }?);
+    let info = controller.info();
+    info!(
+        "found NVMe controller {} (serial {}, firmware {})",
+        info.model, info.serial, info.firmware
+    );
+
+    for namespace in controller.namespaces()? {
+        if namespace.metadata_size != 0 {
+            warn!(
+                "skipping NVMe namespace {} with {} bytes of metadata per block",
+                namespace.id, namespace.metadata_size
+            );
+            continue;
+        }
+        info!(
+            "found NVMe namespace {} with {} blocks of {} bytes",
+            namespace.id, namespace.block_count, namespace.block_size
+        );
+        disk::devices().register(Disk::Nvme(NvmeBlockDevice {
+            controller: controller.clone(),
+            namespace,
+        }))?;
+    }
+    Ok(())
+}
+
+/// Maps the BAR for as long as the kernel runs.
+fn map_bar(device: &mut PciDevice, index: usize) -> Result<VirtAddr, NvmeError> {
+    let name = format!("nvme {device} bar{index}");
+    let Some(Bar::Memory { base, size, .. }) = device.bar(index) else {
+        return Err(NvmeError::NoBar);
+    };
+    let size = size.max(Size4KiB::SIZE) as usize;
+    let addr = PhysAddr::try_new(base).map_err(|_| NvmeError::NoBar)?;
+
+    let frames = (0..size.div_ceil(Size4KiB::SIZE as usize))
+        .map(|i| PhysFrame::containing_address(addr + i as u64 * Size4KiB::SIZE))
+        .collect::<Vec<_>>();
+    let base = vmm()
+        .allocate_memory_backed_vmobject(
+            name,
+            MapAt::Anywhere,
+            frames.len() * Size4KiB::SIZE as usize,
+            AllocationStrategy::MapNow(&frames),
+            PageTableFlags::PRESENT
+                | PageTableFlags::NO_CACHE
+                | PageTableFlags::NO_EXECUTE
+                | PageTableFlags::WRITABLE,
+        )
+        .map_err(|_| NvmeError::NoMemory)?;
+    Ok(base)
+}
+
+/// Makes the first entry of the MSI-X table deliver to an interrupt vector,
+/// which all queues of the controller use. Returns `false` if the controller
+/// can't interrupt, and completions have to be polled.
+fn enable_msix(device: &mut PciDevice, bar0: VirtAddr) -> bool {
+    let Some(capability) = MsiXCapability::find(device) else {
+        return false;
+    };
+    let Some(vector) = idt::next_free_interrupt_vector() else {
+        warn!("no free interrupt vector for the NVMe controller");
+        return false;
+    };
+    let (address, data) = match apic::msi_message(vector) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("can't deliver the interrupts of the NVMe controller: {e}");
+            return false;
+        }
+    };
+    let table = match capability.table_location() {
+        (0, offset) => bar0 + offset as u64,
+        (bar, offset) => match map_bar(device, bar) {
+            Ok(addr) => addr + offset as u64,
+            Err(e) => {
+                warn!("failed to map the MSI-X table of the NVMe controller: {e}");
+                return false;
+            }
+        },
+    };
+    let table = unsafe {
+        // safety: the BAR stays mapped for as long as the controller lives
+        slice::from_raw_parts_mut(
+            table.as_mut_ptr::<MsiXTableEntry>(),
+            capability.table_size(),
+        )
+    };
+    let mut msix = MsiX::new(capability, table);
+    idt::register_interrupt_handler(vector, interrupt_handler);
+    if let Err(e) = msix.set_entry(0, address, data) {
+        warn!("can't set up the interrupt of the NVMe controller: {e}");
+        return false;
+    }
+    msix.enable(device);
+    true
+}
+
+/// Only ends the halt of the command that waits, which finds its completion
+/// in the completion queue itself.
+extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    unsafe { end_of_interrupt() };
+}
+
+/// A namespace of an NVMe controller.
+#[derive(Clone)]
+pub struct NvmeBlockDevice {
+    controller: Arc<Controller>,
+    namespace: Namespace,
+}
+
+impl Debug for NvmeBlockDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NvmeBlockDevice")
+            .field("model", &self.controller.info().model)
+            .field("namespace", &self.namespace)
+            .finish()
+    }
+}
+
+impl NvmeBlockDevice {
+    pub fn model(&self) -> &str {
+        &self.controller.info().model
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.namespace.block_size
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.namespace.block_count
+    }
+
+    /// Reads as many blocks as fit into the buffer, starting at `start`.
+    pub fn read_blocks_into(&self, start: usize, buf: &mut [u8]) -> Result<usize, NvmeError> {
+        let block_size = self.block_size();
+        assert_eq!(0, buf.len() % block_size);
+        for (i, chunk) in buf.chunks_mut(self.max_blocks() * block_size).enumerate() {
+            let buffer =
+                self.read_chunk(start + i * self.max_blocks(), chunk.len() / block_size)?;
+            chunk.copy_from_slice(buffer.as_slice());
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the blocks in the buffer, starting at `start`. If the
+    /// controller has a volatile write cache, it is flushed afterwards, like
+    /// the IDE driver does.
+    pub fn write_blocks_from(&self, start: usize, buf: &[u8]) -> Result<usize, NvmeError> {
+        let block_size = self.block_size();
+        assert_eq!(0, buf.len() % block_size);
+        for (i, chunk) in buf.chunks(self.max_blocks() * block_size).enumerate() {
+            let mut buffer = self.buffer(chunk.len())?;
+            buffer.as_mut_slice().copy_from_slice(chunk);
+            self.write_chunk(start + i * self.max_blocks(), &buffer)?;
+        }
+        self.flush_if_cached()?;
+        Ok(buf.len())
+    }
+
+    pub fn flush_write_cache(&self) -> Result<(), NvmeError> {
+        self.controller
+            .io(Command::flush(self.namespace.id))
+            .map(|_| ())
+    }
+
+    fn flush_if_cached(&self) -> Result<(), NvmeError> {
+        if self.controller.info().volatile_write_cache {
+            self.flush_write_cache()?;
+        }
+        Ok(())
+    }
+
+    fn max_blocks(&self) -> usize {
+        max_blocks(self.controller.info().max_transfer, self.block_size())
+    }
+
+    fn buffer(&self, len: usize) -> Result<DataBuffer, NvmeError> {
+        DataBuffer::allocate(len).ok_or(NvmeError::NoMemory)
+    }
+
+    /// Reads up to [`NvmeBlockDevice::max_blocks`] blocks with a single
+    /// command.
+    fn read_chunk(&self, lba: usize, blocks: usize) -> Result<DataBuffer, NvmeError> {
+        let buffer = self.buffer(blocks * self.block_size())?;
+        let (id, lba, blocks) = (self.namespace.id, lba as u64, blocks as u16);
+        self.controller
+            .io(Command::read(id, lba, blocks, buffer.prps()))?;
+        Ok(buffer)
+    }
+
+    /// Writes the buffer, which holds up to
+    /// [`NvmeBlockDevice::max_blocks`] blocks, with a single command.
+    fn write_chunk(&self, lba: usize, buffer: &DataBuffer) -> Result<(), NvmeError> {
+        let blocks = (buffer.as_slice().len() / self.block_size()) as u16;
+        self.controller.io(Command::write(
+            self.namespace.id,
+            lba as u64,
+            blocks,
+            buffer.prps(),
+        ))?;
+        Ok(())
+    }
+}
+
+impl BlockDevice for NvmeBlockDevice {
+    type Error = NvmeError;
+
+    fn sector_size(&self) -> usize {
+        self.block_size()
+    }
+
+    fn sector_count(&self) -> usize {
+        self.block_count() as usize
+    }
+
+    fn read_sector(&self, sector: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_blocks_into(sector, buf)
+    }
+
+    fn write_sector(&mut self, sector: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_blocks_from(sector, buf)
+    }
+}
+
+impl MultiBlock for NvmeBlockDevice {
+    /// Reads as many blocks with a single command as the controller allows.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let block_size = self.block_size();
+        for (i, chunk) in bufs.chunks_mut(self.max_blocks()).enumerate() {
+            let buffer = self.read_chunk(start + i * self.max_blocks(), chunk.len())?;
+            for (buf, data) in chunk.iter_mut().zip(buffer.as_slice().chunks(block_size)) {
+                buf.copy_from_slice(data);
+            }
+        }
+        Ok(bufs.len() * block_size)
+    }
+
+    /// Writes as many blocks with a single command as the controller allows.
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        let block_size = self.block_size();
+        for (i, chunk) in bufs.chunks(self.max_blocks()).enumerate() {
+            let mut buffer = self.buffer(chunk.len() * block_size)?;
+            for (data, buf) in buffer.as_mut_slice().chunks_mut(block_size).zip(chunk) {
+                data.copy_from_slice(buf);
+            }
+            self.write_chunk(start + i * self.max_blocks(), &buffer)?;
+        }
+        self.flush_if_cached()?;
+        Ok(bufs.len() * block_size)
+    }
+}
+
+impl Flush for NvmeBlockDevice {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_write_cache()
+    }
+}
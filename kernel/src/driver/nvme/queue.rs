@@ -0,0 +1,153 @@
+use alloc::format;
+use core::ptr::{read_volatile, write_bytes, write_volatile};
+
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::driver::nvme::command::{Command, Completion};
+use crate::driver::nvme::NvmeError;
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// The most entries of a queue, so that the submission queue fits into a
+/// single page.
+pub const MAX_QUEUE_SIZE: u16 = (PAGE_SIZE / size_of::<Command>()) as u16;
+
+/// A page that the controller reads a queue from or writes it to.
+struct QueueMemory {
+    addr: VirtAddr,
+    frame: PhysFrame,
+}
+
+impl QueueMemory {
+    fn allocate(name: &str) -> Result<Self, NvmeError> {
+        let frame = PhysicalMemoryManager::allocate_frame().ok_or(NvmeError::NoMemory)?;
+        let addr = vmm()
+            .allocate_memory_backed_vmobject(
+                format!("nvme {name}"),
+                MapAt::Anywhere,
+                PAGE_SIZE,
+                AllocationStrategy::MapNow(&[frame]),
+                PageTableFlags::PRESENT
+                    | PageTableFlags::NO_CACHE
+                    | PageTableFlags::NO_EXECUTE
+                    | PageTableFlags::WRITABLE,
+            )
+            .map_err(|_| NvmeError::NoMemory)?;
+        let memory = Self { addr, frame };
+        memory.clear();
+        Ok(memory)
+    }
+
+    fn clear(&self) {
+        unsafe { write_bytes(self.addr.as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+    }
+}
+
+/// A submission queue and the completion queue that its commands complete
+/// in. The admin queues are the pair with ID 0.
+pub struct QueuePair {
+    id: u16,
+    size: u16,
+    sq: QueueMemory,
+    cq: QueueMemory,
+    sq_doorbell: VirtAddr,
+    cq_doorbell: VirtAddr,
+    sq_tail: u16,
+    cq_head: u16,
+    /// The phase tag that new completions have.
+    phase: bool,
+    next_command: u16,
+}
+
+impl QueuePair {
+    /// Allocates the memory of the queues. The doorbells are the tail
+    /// doorbell of the submission queue and the head doorbell of the
+    /// completion queue.
+    pub fn new(
+        id: u16,
+        size: u16,
+        sq_doorbell: VirtAddr,
+        cq_doorbell: VirtAddr,
+    ) -> Result<Self, NvmeError> {
+        assert!((2..=MAX_QUEUE_SIZE).contains(&size));
+        Ok(Self {
+            id,
+            size,
+            sq: QueueMemory::allocate(&format!("sq{id}"))?,
+            cq: QueueMemory::allocate(&format!("cq{id}"))?,
+            sq_doorbell,
+            cq_doorbell,
+            sq_tail: 0,
+            cq_head: 0,
+            phase: true,
+            next_command: 0,
+        })
+    }
+
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn sq_addr(&self) -> u64 {
+        self.sq.frame.start_address().as_u64()
+    }
+
+    pub fn cq_addr(&self) -> u64 {
+        self.cq.frame.start_address().as_u64()
+    }
+
+    /// Puts the command into the submission queue and tells the controller
+    /// about it. Returns the ID that the command completes with.
+    ///
+    /// There is one command in flight at a time, so the queue can't be full.
+    pub fn submit(&mut self, mut command: Command) -> u16 {
+        command.id = self.next_command;
+        self.next_command = self.next_command.wrapping_add(1);
+        unsafe {
+            let entry = self
+                .sq
+                .addr
+                .as_mut_ptr::<Command>()
+                .add(self.sq_tail.into());
+            write_volatile(entry, command);
+        }
+        self.sq_tail = (self.sq_tail + 1) % self.size;
+        unsafe { write_volatile(self.sq_doorbell.as_mut_ptr::<u32>(), self.sq_tail.into()) };
+        command.id
+    }
+
+    /// Takes the next completion from the completion queue, if the controller
+    /// posted one.
+    pub fn poll(&mut self) -> Option<Completion> {
+        let completion =
+            unsafe { read_volatile(self.cq.addr.as_ptr::<Completion>().add(self.cq_head.into())) };
+        if completion.phase() != self.phase {
+            return None;
+        }
+        self.cq_head += 1;
+        if self.cq_head == self.size {
+            self.cq_head = 0;
+            self.phase = !self.phase;
+        }
+        unsafe { write_volatile(self.cq_doorbell.as_mut_ptr::<u32>(), self.cq_head.into()) };
+        Some(completion)
+    }
+
+    /// Forgets all entries, for when the queues are created anew on the
+    /// controller, which starts them empty.
+    pub fn reset(&mut self) {
+        self.sq.clear();
+        self.cq.clear();
+        self.sq_tail = 0;
+        self.cq_head = 0;
+        self.phase = true;
+    }
+}
@@ -0,0 +1,322 @@
+use crate::driver::pci::command::{command, update_command, Command};
+use crate::driver::pci::raw::{OFFSET_BAR0, OFFSET_HEADER_TYPE};
+use crate::driver::pci::{ReadConfig, WriteConfig};
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_MEMORY_TYPE: u32 = 0b11 << 1;
+const BAR_MEMORY_TYPE_64BIT: u32 = 0b10 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// How many bits the address of a memory BAR has.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BarWidth {
+    Bits32,
+    /// The BAR takes two slots, the second one holds the upper half of the
+    /// address.
+    Bits64,
+}
+
+/// A decoded base address register, along with the size of the region that
+/// it describes.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Bar {
+    Memory {
+        base: u64,
+        size: u64,
+        prefetchable: bool,
+        width: BarWidth,
+    },
+    Io {
+        base: u32,
+        size: u32,
+    },
+}
+
+impl Bar {
+    /// How many of the six BAR slots this BAR takes.
+    pub fn slots(&self) -> usize {
+        match self {
+            Bar::Memory {
+                width: BarWidth::Bits64,
+                ..
+            } => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// How many BARs a function with the given header type has. Bridges have two,
+/// CardBus bridges none.
+fn bar_count(header_type: u8) -> usize {
+    match header_type & 0x7F {
+        0 => 6,
+        1 => 2,
+        _ => 0,
+    }
+}
+
+/// Decodes the BAR with the given index and determines its size, by writing
+/// all ones to it and reading back which bits stick. The BAR is restored
+/// afterwards, and decoding is disabled while it's probed.
+///
+/// Returns `None` if the function has no BAR with this index, or if the BAR is
+/// not implemented. A 64-bit BAR takes the next index as well, which must not
+/// be decoded on its own.
+pub fn bar<C>(config: &mut C, index: usize) -> Option<Bar>
+where
+    C: ReadConfig<u8> + ReadConfig<u16> + WriteConfig<u16> + ReadConfig<u32> + WriteConfig<u32>,
+{
+    let header_type: u8 = config.read_config(OFFSET_HEADER_TYPE);
+    let count = bar_count(header_type);
+    if index >= count {
+        return None;
+    }
+    let offset = OFFSET_BAR0 + 4 * index as u16;
+    let original: u32 = config.read_config(offset);
+    let is_64bit = original & BAR_IO == 0 && original & BAR_MEMORY_TYPE == BAR_MEMORY_TYPE_64BIT;
+    if is_64bit && index + 1 >= count {
+        return None;
+    }
+
+    let decoding = command(config) & (Command::IO_SPACE | Command::MEMORY_SPACE);
+    update_command(config, Command::empty(), decoding);
+    let mask = probe(config, offset);
+    let upper = is_64bit.then(|| {
+        let original: u32 = config.read_config(offset + 4);
+        (original, probe(config, offset + 4))
+    });
+    update_command(config, decoding, Command::empty());
+
+    if original & BAR_IO != 0 {
+        // I/O addresses only have 16 bits
+        let mask = mask & !0b11 & 0xFFFF;
+        return (mask != 0).then_some(Bar::Io {
+            base: original & !0b11,
+            size: (!mask & 0xFFFF) + 1,
+        });
+    }
+    let mask = mask & !0b1111;
+    let (base, size) = match upper {
+        Some((upper, upper_mask)) => (
+            u64::from(upper) << 32 | u64::from(original & !0b1111),
+            (!(u64::from(upper_mask) << 32 | u64::from(mask))).wrapping_add(1),
+        ),
+        None => (
+            u64::from(original & !0b1111),
+            u64::from((!mask).wrapping_add(1)),
+        ),
+    };
+    // nothing sticks in a BAR that is not implemented, which makes the size
+    // wrap around to zero
+    (size != 0).then_some(Bar::Memory {
+        base,
+        size,
+        prefetchable: original & BAR_PREFETCHABLE != 0,
+        width: if is_64bit {
+            BarWidth::Bits64
+        } else {
+            BarWidth::Bits32
+        },
+    })
+}
+
+/// Writes all ones to the register at `offset`, and returns what is read back
+/// after restoring the register.
+fn probe<C>(config: &mut C, offset: u16) -> u32
+where
+    C: ReadConfig<u32> + WriteConfig<u32>,
+{
+    let original = config.read_config(offset);
+    config.write_config(offset, 0xFFFF_FFFF);
+    let mask = config.read_config(offset);
+    config.write_config(offset, original);
+    mask
+}
+
+/// All BARs of the function, along with their index. The second slot of a
+/// 64-bit BAR is skipped.
+pub fn bars<C>(config: &mut C) -> impl Iterator<Item = (usize, Bar)> + '_
+where
+    C: ReadConfig<u8> + ReadConfig<u16> + WriteConfig<u16> + ReadConfig<u32> + WriteConfig<u32>,
+{
+    let mut index = 0;
+    core::iter::from_fn(move || {
+        while index < 6 {
+            let current = index;
+            match bar(config, current) {
+                Some(bar) => {
+                    index += bar.slots();
+                    return Some((current, bar));
+                }
+                None => index += 1,
+            }
+        }
+        None
+    })
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::bar::{bar, bars, Bar, BarWidth};
+    use crate::driver::pci::mock::MockConfig;
+    use crate::driver::pci::{ReadConfig, WriteConfig};
+
+    /// A configuration space whose BARs only keep the bits that are set in
+    /// their mask when they are written, the others are read-only.
+    struct BarConfig {
+        config: MockConfig,
+        masks: [u32; 6],
+    }
+
+    impl BarConfig {
+        fn new(header_type: u8, bars: [(u32, u32); 6]) -> Self {
+            let mut config = MockConfig::new(&[]);
+            config.0[0x0E] = header_type;
+            // decoding is enabled
+            config.0[0x04] = 0b11;
+            for (i, (value, _)) in bars.iter().enumerate() {
+                config.write_config(0x10 + 4 * i as u16, *value);
+            }
+            Self {
+                config,
+                masks: bars.map(|(_, mask)| mask),
+            }
+        }
+
+        fn bar(&self, index: u16) -> u32 {
+            self.config.read_config(0x10 + 4 * index)
+        }
+    }
+
+    impl<T> ReadConfig<T> for BarConfig
+    where
+        MockConfig: ReadConfig<T>,
+    {
+        fn read_config(&self, offset: u16) -> T {
+            self.config.read_config(offset)
+        }
+    }
+
+    impl WriteConfig<u16> for BarConfig {
+        fn write_config(&mut self, offset: u16, value: u16) {
+            self.config.write_config(offset, value)
+        }
+    }
+
+    impl WriteConfig<u32> for BarConfig {
+        fn write_config(&mut self, offset: u16, value: u32) {
+            let value = match offset {
+                0x10..0x28 => {
+                    let index = usize::from(offset - 0x10) / 4;
+                    // BARs are only probed while decoding is disabled
+                    assert_eq!(0, self.config.0[0x04] & 0b11);
+                    let original: u32 = self.config.read_config(offset);
+                    let mask = self.masks[index];
+                    value & mask | original & !mask
+                }
+                _ => value,
+            };
+            self.config.write_config(offset, value)
+        }
+    }
+
+    #[kernel_test]
+    fn test_bar_64bit_prefetchable() {
+        // 16KiB at 0x1_8000_0000, spanning BAR2 and BAR3
+        let mut config = BarConfig::new(
+            0,
+            [
+                (0, 0),
+                (0, 0),
+                (0x8000_000C, 0xFFFF_C000),
+                (0x0000_0001, 0xFFFF_FFFF),
+                (0, 0),
+                (0, 0),
+            ],
+        );
+        assert_eq!(
+            Some(Bar::Memory {
+                base: 0x1_8000_0000,
+                size: 0x4000,
+                prefetchable: true,
+                width: BarWidth::Bits64,
+            }),
+            bar(&mut config, 2)
+        );
+        // the BAR and the command register are restored
+        assert_eq!(0x8000_000C, config.bar(2));
+        assert_eq!(0x0000_0001, config.bar(3));
+        assert_eq!(0b11, config.config.0[0x04]);
+    }
+
+    #[kernel_test]
+    fn test_bar_io_and_unimplemented() {
+        // 32 ports at 0xC040, and a 4KiB memory BAR at 0xFEBF_1000
+        let mut config = BarConfig::new(
+            0,
+            [
+                (0, 0),
+                (0xC041, 0xFFFF_FFE0),
+                (0xFEBF_1000, 0xFFFF_F000),
+                (0, 0),
+                (0, 0),
+                (0, 0),
+            ],
+        );
+        assert_eq!(None, bar(&mut config, 0));
+        assert_eq!(
+            Some(Bar::Io {
+                base: 0xC040,
+                size: 32,
+            }),
+            bar(&mut config, 1)
+        );
+        assert_eq!(0xC041, config.bar(1));
+        assert_eq!(None, bar(&mut config, 6));
+        assert_eq!(
+            vec![
+                (
+                    1,
+                    Bar::Io {
+                        base: 0xC040,
+                        size: 32
+                    }
+                ),
+                (
+                    2,
+                    Bar::Memory {
+                        base: 0xFEBF_1000,
+                        size: 0x1000,
+                        prefetchable: false,
+                        width: BarWidth::Bits32,
+                    }
+                ),
+            ],
+            bars(&mut config).collect::<Vec<_>>()
+        );
+    }
+
+    #[kernel_test]
+    fn test_bar_bridge() {
+        // bridges only have two BARs, a 64-bit BAR can't start in the second
+        let mut config = BarConfig::new(
+            1,
+            [
+                (0xFEB0_0000, 0xFFF0_0000),
+                (0x0000_0004, 0xFFFF_F000),
+                (0xFEA0_0000, 0xFFF0_0000),
+                (0, 0),
+                (0, 0),
+                (0, 0),
+            ],
+        );
+        assert_eq!(1, bars(&mut config).count());
+        assert_eq!(None, bar(&mut config, 1));
+        assert_eq!(None, bar(&mut config, 2));
+    }
+}
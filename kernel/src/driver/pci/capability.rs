@@ -0,0 +1,316 @@
+use core::iter;
+
+use crate::driver::pci::raw::{OFFSET_CAPABILITIES, OFFSET_STATUS};
+use crate::driver::pci::register::PciRegisterOps;
+use crate::driver::pci::{PciAddress, PciDevice, Status};
+
+/// The most capabilities that fit into the configuration space after the
+/// header.
+const MAX_CAPABILITIES: usize = 48;
+
+/// Capabilities start after the standard header.
+const FIRST_CAPABILITY_OFFSET: u16 = 0x40;
+
+const ID_POWER_MANAGEMENT: u8 = 0x01;
+const ID_MSI: u8 = 0x05;
+const ID_VENDOR_SPECIFIC: u8 = 0x09;
+const ID_MSIX: u8 = 0x11;
+
+const MSI_CONTROL_64BIT: u16 = 1 << 7;
+const MSI_CONTROL_PER_VECTOR_MASKING: u16 = 1 << 8;
+const MSI_CONTROL_MULTIPLE_MESSAGE_CAPABLE: u16 = 0b111 << 1;
+
+const MSIX_CONTROL_TABLE_SIZE: u16 = 0x7FF;
+
+/// Reads from the configuration space of a function. This is implemented by
+/// [`PciDevice`], and allows parsing the configuration space without a
+/// device. Offsets above 0xFF are in the extended configuration space, which
+/// is only reachable through ECAM.
+pub trait ReadConfig<T> {
+    fn read_config(&self, offset: u16) -> T;
+}
+
+/// Writes to the configuration space of a function, like [`ReadConfig`].
+pub trait WriteConfig<T> {
+    fn write_config(&mut self, offset: u16, value: T);
+}
+
+impl<T: PciRegisterOps> ReadConfig<T> for PciDevice {
+    fn read_config(&self, offset: u16) -> T {
+        T::read_pci_register(self.address(), offset)
+    }
+}
+
+impl<T: PciRegisterOps> WriteConfig<T> for PciDevice {
+    fn write_config(&mut self, offset: u16, value: T) {
+        T::write_pci_register(self.address(), offset, value)
+    }
+}
+
+impl<T: PciRegisterOps> ReadConfig<T> for PciAddress {
+    fn read_config(&self, offset: u16) -> T {
+        T::read_pci_register(*self, offset)
+    }
+}
+
+/// An entry of the capability list of a function.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Capability {
+    PowerManagement {
+        offset: u16,
+        /// The power management capabilities register.
+        capabilities: u16,
+    },
+    Msi(MsiCapability),
+    MsiX(MsiXCapability),
+    VendorSpecific {
+        offset: u16,
+        /// The length of the capability, including the header.
+        len: u8,
+    },
+    Unknown {
+        offset: u16,
+        id: u8,
+    },
+}
+
+impl Capability {
+    /// The offset of the capability in the configuration space.
+    pub fn offset(&self) -> u16 {
+        match self {
+            Capability::PowerManagement { offset, .. }
+            | Capability::VendorSpecific { offset, .. }
+            | Capability::Unknown { offset, .. } => *offset,
+            Capability::Msi(msi) => msi.offset,
+            Capability::MsiX(msix) => msix.offset,
+        }
+    }
+
+    fn read<C>(config: &C, offset: u16, id: u8) -> Self
+    where
+        C: ReadConfig<u8> + ReadConfig<u16> + ReadConfig<u32>,
+    {
+        match id {
+            ID_POWER_MANAGEMENT => Capability::PowerManagement {
+                offset,
+                capabilities: config.read_config(offset + 2),
+            },
+            ID_MSI => Capability::Msi(MsiCapability {
+                offset,
+                control: config.read_config(offset + 2),
+            }),
+            ID_MSIX => Capability::MsiX(MsiXCapability {
+                offset,
+                control: config.read_config(offset + 2),
+                table: config.read_config(offset + 4),
+                pba: config.read_config(offset + 8),
+            }),
+            ID_VENDOR_SPECIFIC => Capability::VendorSpecific {
+                offset,
+                len: config.read_config(offset + 2),
+            },
+            id => Capability::Unknown { offset, id },
+        }
+    }
+}
+
+/// The MSI capability, as it was when the capability list was read.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MsiCapability {
+    pub offset: u16,
+    /// The message control register.
+    pub control: u16,
+}
+
+impl MsiCapability {
+    /// Finds the MSI capability of the function, if it has one.
+    pub fn find<C>(config: &C) -> Option<Self>
+    where
+        C: ReadConfig<u8> + ReadConfig<u16> + ReadConfig<u32>,
+    {
+        capabilities(config).find_map(|capability| match capability {
+            Capability::Msi(msi) => Some(msi),
+            _ => None,
+        })
+    }
+
+    /// Whether the message address has 64 bits.
+    pub fn is_64bit(&self) -> bool {
+        self.control & MSI_CONTROL_64BIT != 0
+    }
+
+    /// Whether single vectors can be masked.
+    pub fn per_vector_masking(&self) -> bool {
+        self.control & MSI_CONTROL_PER_VECTOR_MASKING != 0
+    }
+
+    /// How many vectors the function can use.
+    pub fn vectors(&self) -> usize {
+        1 << ((self.control & MSI_CONTROL_MULTIPLE_MESSAGE_CAPABLE) >> 1)
+    }
+}
+
+/// The MSI-X capability, as it was when the capability list was read.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MsiXCapability {
+    pub offset: u16,
+    /// The message control register.
+    pub control: u16,
+    table: u32,
+    pba: u32,
+}
+
+impl MsiXCapability {
+    /// Finds the MSI-X capability of the function, if it has one.
+    pub fn find<C>(config: &C) -> Option<Self>
+    where
+        C: ReadConfig<u8> + ReadConfig<u16> + ReadConfig<u32>,
+    {
+        capabilities(config).find_map(|capability| match capability {
+            Capability::MsiX(msix) => Some(msix),
+            _ => None,
+        })
+    }
+
+    /// The number of entries in the table.
+    pub fn table_size(&self) -> usize {
+        usize::from(self.control & MSIX_CONTROL_TABLE_SIZE) + 1
+    }
+
+    /// The index of the BAR that the table is in, and the offset of the table
+    /// in it.
+    pub fn table_location(&self) -> (usize, usize) {
+        bar_location(self.table)
+    }
+
+    /// The index of the BAR that the pending bit array is in, and the offset
+    /// of the array in it.
+    pub fn pba_location(&self) -> (usize, usize) {
+        bar_location(self.pba)
+    }
+}
+
+/// The lowest three bits are the BAR index, the others the offset in the BAR.
+fn bar_location(value: u32) -> (usize, usize) {
+    ((value & 0b111) as usize, (value & !0b111) as usize)
+}
+
+/// The capabilities in the configuration space.
+///
+/// A broken list ends at the first pointer into the header, at the first
+/// unaligned pointer, and at the first capability that was already visited.
+pub fn capabilities<C>(config: &C) -> impl Iterator<Item = Capability> + '_
+where
+    C: ReadConfig<u8> + ReadConfig<u16> + ReadConfig<u32>,
+{
+    let status = Status::from_bits_truncate(config.read_config(OFFSET_STATUS));
+    let mut next: u8 = if status.contains(Status::CAPABILITIES_LIST) {
+        config.read_config(OFFSET_CAPABILITIES)
+    } else {
+        0
+    };
+    // one bit for every dword of the configuration space
+    let mut visited = 0_u64;
+    iter::from_fn(move || {
+        let offset = u16::from(next);
+        if offset < FIRST_CAPABILITY_OFFSET || offset & 0b11 != 0 {
+            return None;
+        }
+        let bit = 1 << (offset / 4);
+        if visited & bit != 0 {
+            return None;
+        }
+        visited |= bit;
+        let id = config.read_config(offset);
+        next = config.read_config(offset + 1);
+        Some(Capability::read(config, offset, id))
+    })
+    .take(MAX_CAPABILITIES)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::capability::{capabilities, Capability, MsiCapability, MsiXCapability};
+    use crate::driver::pci::mock::MockConfig;
+
+    #[kernel_test]
+    fn test_capabilities() {
+        let config = MockConfig::new(&[
+            (0x40, 0x01, 0x50, &[0x03, 0x00]),
+            (0x50, 0x05, 0x70, &[0x84, 0x01]),
+            (
+                0x70,
+                0x11,
+                0x90,
+                &[0x3F, 0x00, 0x00, 0x20, 0, 0, 0x04, 0x30, 0, 0],
+            ),
+            (0x90, 0x09, 0xA0, &[0x08]),
+            (0xA0, 0x10, 0x00, &[]),
+        ]);
+        let capabilities = capabilities(&config).collect::<Vec<_>>();
+        let msi = MsiCapability {
+            offset: 0x50,
+            control: 0x0184,
+        };
+        let msix = MsiXCapability {
+            offset: 0x70,
+            control: 0x003F,
+            table: 0x2000,
+            pba: 0x3004,
+        };
+        assert_eq!(
+            vec![
+                Capability::PowerManagement {
+                    offset: 0x40,
+                    capabilities: 0x0003,
+                },
+                Capability::Msi(msi),
+                Capability::MsiX(msix),
+                Capability::VendorSpecific {
+                    offset: 0x90,
+                    len: 8,
+                },
+                Capability::Unknown {
+                    offset: 0xA0,
+                    id: 0x10,
+                },
+            ],
+            capabilities
+        );
+
+        assert!(msi.is_64bit());
+        assert!(msi.per_vector_masking());
+        assert_eq!(4, msi.vectors());
+        assert_eq!(64, msix.table_size());
+        assert_eq!((0, 0x2000), msix.table_location());
+        assert_eq!((4, 0x3000), msix.pba_location());
+    }
+
+    #[kernel_test]
+    fn test_capabilities_looping() {
+        let config = MockConfig::new(&[(0x40, 0x01, 0x50, &[]), (0x50, 0x05, 0x40, &[])]);
+        assert_eq!(2, capabilities(&config).count());
+
+        // pointers into the header and unaligned pointers end the list
+        let config = MockConfig::new(&[(0x40, 0x01, 0x34, &[])]);
+        assert_eq!(1, capabilities(&config).count());
+        let config = MockConfig::new(&[(0x40, 0x01, 0x51, &[])]);
+        assert_eq!(1, capabilities(&config).count());
+    }
+
+    #[kernel_test]
+    fn test_no_capabilities() {
+        let mut config = MockConfig::new(&[]);
+        assert_eq!(0, capabilities(&config).count());
+
+        // the pointer is ignored without the bit in the status register
+        config.0[0x34] = 0x40;
+        config.0[0x40] = 0x05;
+        assert_eq!(0, capabilities(&config).count());
+    }
+}
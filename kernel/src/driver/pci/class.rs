@@ -0,0 +1,53 @@
+/// The name of a device class, as lspci shows it. Classes that we don't know
+/// the subclass of fall back to the name of the class.
+pub fn class_name(class: u8, subclass: u8, prog_if: u8) -> &'static str {
+    match (class, subclass, prog_if) {
+        (0x00, 0x01, _) => "VGA compatible unclassified device",
+        (0x00, _, _) => "Unclassified device",
+        (0x01, 0x00, _) => "SCSI storage controller",
+        (0x01, 0x01, _) => "IDE interface",
+        (0x01, 0x05, _) => "ATA controller",
+        (0x01, 0x06, _) => "SATA controller",
+        (0x01, 0x08, _) => "Non-Volatile memory controller",
+        (0x01, _, _) => "Mass storage controller",
+        (0x02, 0x00, _) => "Ethernet controller",
+        (0x02, _, _) => "Network controller",
+        (0x03, 0x00, _) => "VGA compatible controller",
+        (0x03, _, _) => "Display controller",
+        (0x04, 0x01, _) => "Multimedia audio controller",
+        (0x04, 0x03, _) => "Audio device",
+        (0x04, _, _) => "Multimedia controller",
+        (0x05, _, _) => "Memory controller",
+        (0x06, 0x00, _) => "Host bridge",
+        (0x06, 0x01, _) => "ISA bridge",
+        (0x06, 0x04, _) => "PCI bridge",
+        (0x06, _, _) => "Bridge",
+        (0x07, 0x00, _) => "Serial controller",
+        (0x07, _, _) => "Communication controller",
+        (0x08, _, _) => "System peripheral",
+        (0x09, _, _) => "Input device controller",
+        (0x0C, 0x03, 0x30) => "USB controller (xHCI)",
+        (0x0C, 0x03, _) => "USB controller",
+        (0x0C, 0x05, _) => "SMBus",
+        (0x0C, _, _) => "Serial bus controller",
+        (0x0D, _, _) => "Wireless controller",
+        (0xFF, _, _) => "Unassigned class",
+        _ => "Unknown class",
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::class::class_name;
+
+    #[kernel_test]
+    fn test_class_name() {
+        assert_eq!("IDE interface", class_name(0x01, 0x01, 0x80));
+        assert_eq!("USB controller (xHCI)", class_name(0x0C, 0x03, 0x30));
+        assert_eq!("USB controller", class_name(0x0C, 0x03, 0x20));
+        assert_eq!("Mass storage controller", class_name(0x01, 0x80, 0x00));
+        assert_eq!("Unknown class", class_name(0x42, 0x00, 0x00));
+    }
+}
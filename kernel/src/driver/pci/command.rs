@@ -0,0 +1,72 @@
+use bitflags::bitflags;
+
+use crate::driver::pci::raw::OFFSET_COMMAND;
+use crate::driver::pci::{ReadConfig, WriteConfig};
+
+bitflags! {
+    /// The command register, which controls how the device takes part in
+    /// PCI transactions.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct Command: u16 {
+        const IO_SPACE = 1 << 0;
+        const MEMORY_SPACE = 1 << 1;
+        const BUS_MASTER = 1 << 2;
+        const SPECIAL_CYCLES = 1 << 3;
+        const MEMORY_WRITE_AND_INVALIDATE = 1 << 4;
+        const VGA_PALETTE_SNOOP = 1 << 5;
+        const PARITY_ERROR_RESPONSE = 1 << 6;
+        const SERR = 1 << 8;
+        const FAST_BACK_TO_BACK = 1 << 9;
+        const INTERRUPT_DISABLE = 1 << 10;
+    }
+}
+
+pub fn command<C: ReadConfig<u16>>(config: &C) -> Command {
+    Command::from_bits_retain(config.read_config(OFFSET_COMMAND))
+}
+
+/// Sets and clears bits of the command register, and keeps all others,
+/// including the reserved ones.
+pub fn update_command<C>(config: &mut C, set: Command, clear: Command)
+where
+    C: ReadConfig<u16> + WriteConfig<u16>,
+{
+    let command = (command(config) | set) - clear;
+    config.write_config(OFFSET_COMMAND, command.bits());
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::command::{command, update_command, Command};
+    use crate::driver::pci::mock::MockConfig;
+    use crate::driver::pci::ReadConfig;
+
+    #[kernel_test]
+    fn test_update_command() {
+        let mut config = MockConfig::new(&[]);
+        // parity error response and a reserved bit
+        config.0[0x04] = 0x40;
+        config.0[0x05] = 0x80;
+
+        update_command(&mut config, Command::BUS_MASTER, Command::empty());
+        assert_eq!(0x8044, ReadConfig::<u16>::read_config(&config, 0x04));
+        update_command(
+            &mut config,
+            Command::MEMORY_SPACE | Command::IO_SPACE,
+            Command::empty(),
+        );
+        assert_eq!(0x8047, ReadConfig::<u16>::read_config(&config, 0x04));
+        update_command(&mut config, Command::INTERRUPT_DISABLE, Command::IO_SPACE);
+        assert_eq!(0x8446, ReadConfig::<u16>::read_config(&config, 0x04));
+        assert_eq!(
+            Command::MEMORY_SPACE
+                | Command::BUS_MASTER
+                | Command::PARITY_ERROR_RESPONSE
+                | Command::INTERRUPT_DISABLE
+                | Command::from_bits_retain(0x8000),
+            command(&config)
+        );
+    }
+}
@@ -1,19 +1,73 @@
+use crate::driver::pci::bar::{self, Bar};
+use crate::driver::pci::capability::{self, Capability};
+use crate::driver::pci::command::{self, Command};
 use crate::driver::pci::raw::{
     read_config_half_word, read_config_word, OFFSET_BAR0, OFFSET_BAR1, OFFSET_BAR2, OFFSET_BAR3,
     OFFSET_BAR4, OFFSET_BAR5, OFFSET_BIST, OFFSET_CLASS, OFFSET_COMMAND, OFFSET_DEVICE_ID,
-    OFFSET_HEADER_TYPE, OFFSET_INTERRUPT_LINE, OFFSET_INTERRUPT_PIN, OFFSET_PROG_IF_REVISION_ID,
-    OFFSET_STATUS, OFFSET_SUBCLASS, OFFSET_VENDOR_ID,
+    OFFSET_HEADER_TYPE, OFFSET_INTERRUPT_LINE, OFFSET_INTERRUPT_PIN, OFFSET_PROG_IF,
+    OFFSET_REVISION_ID, OFFSET_STATUS, OFFSET_SUBCLASS, OFFSET_VENDOR_ID,
 };
 use crate::driver::pci::register::{BaseAddressRegister, PciRegister};
-use crate::driver::pci::{Status, BIST};
+use crate::driver::pci::{class_name, ecam, Status, BIST};
 use core::fmt::Formatter;
 use derive_more::Display;
 
+/// The location of a function in the configuration space.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PciAddress {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// The address of a function in segment 0, which is the only segment on
+    /// most machines.
+    pub const fn new(bus: u8, device: u8, function: u8) -> Self {
+        Self::with_segment(0, bus, device, function)
+    }
+
+    pub const fn with_segment(segment: u16, bus: u8, device: u8, function: u8) -> Self {
+        Self {
+            segment,
+            bus,
+            device,
+            function,
+        }
+    }
+
+    /// How many bytes of configuration space the function has that we can
+    /// reach. Without ECAM, that's only the first 256.
+    pub fn config_space_size(&self) -> u16 {
+        if ecam::function(*self).is_some() {
+            0x1000
+        } else {
+            0x100
+        }
+    }
+}
+
+impl Display for PciAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.segment, self.bus, self.device, self.function
+        )
+    }
+}
+
 #[derive(Debug)]
 pub struct PciDevice {
+    pub segment: u16,
     pub bus: u8,
     pub slot: u8,
     pub function: u8,
+    /// The PCI-to-PCI bridge that the device is behind, if any.
+    pub parent: Option<PciAddress>,
+    /// The name of the driver that claimed the device.
+    pub(in crate::driver::pci) driver: Option<&'static str>,
     pub vendor_id: u16,
     pub device_id: u16,
     pub status: PciRegister<u16>,
@@ -33,63 +87,65 @@ impl Display for PciDevice {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         write!(
             f,
-            "id {:04x}:{:04x}, slot {:02x}:{:02x}.{:x}",
-            self.vendor_id, self.device_id, self.bus, self.slot, self.function,
+            "id {:04x}:{:04x}, slot {}",
+            self.vendor_id,
+            self.device_id,
+            self.address(),
         )
     }
 }
 
-pub enum ProbeResult {
-    Exists,
-    MultiFunction,
-    None,
-}
-
 impl PciDevice {
-    pub fn probe(bus: u8, slot: u8, function: u8) -> ProbeResult {
-        let vendor_id = unsafe { read_config_word(bus, slot, function, OFFSET_VENDOR_ID) };
-        if vendor_id == 0xFFFF {
-            return ProbeResult::None;
-        }
-
-        let header_type = unsafe { read_config_half_word(bus, slot, function, OFFSET_HEADER_TYPE) };
-        if header_type & 0x80 > 0 {
-            ProbeResult::MultiFunction
-        } else {
-            ProbeResult::Exists
-        }
-    }
-
-    pub(in crate::driver::pci) unsafe fn new(bus: u8, slot: u8, function: u8) -> Self {
+    pub(in crate::driver::pci) unsafe fn new(
+        address: PciAddress,
+        parent: Option<PciAddress>,
+    ) -> Self {
         unsafe {
             Self {
-                bus,
-                slot,
-                function,
-                vendor_id: read_config_word(bus, slot, function, OFFSET_VENDOR_ID),
-                device_id: read_config_word(bus, slot, function, OFFSET_DEVICE_ID),
-                status: PciRegister::new(bus, slot, function, OFFSET_STATUS),
-                command: PciRegister::new(bus, slot, function, OFFSET_COMMAND),
-                rev: read_config_half_word(bus, slot, function, OFFSET_PROG_IF_REVISION_ID),
-                prog: read_config_half_word(bus, slot, function, OFFSET_PROG_IF_REVISION_ID),
-                subclass: read_config_half_word(bus, slot, function, OFFSET_SUBCLASS),
-                class: read_config_half_word(bus, slot, function, OFFSET_CLASS),
-                header_type: read_config_half_word(bus, slot, function, OFFSET_HEADER_TYPE),
-                bist: PciRegister::new(bus, slot, function, OFFSET_BIST),
+                segment: address.segment,
+                bus: address.bus,
+                slot: address.device,
+                function: address.function,
+                parent,
+                driver: None,
+                vendor_id: read_config_word(address, OFFSET_VENDOR_ID),
+                device_id: read_config_word(address, OFFSET_DEVICE_ID),
+                status: PciRegister::new(address, OFFSET_STATUS),
+                command: PciRegister::new(address, OFFSET_COMMAND),
+                rev: read_config_half_word(address, OFFSET_REVISION_ID),
+                prog: read_config_half_word(address, OFFSET_PROG_IF),
+                subclass: read_config_half_word(address, OFFSET_SUBCLASS),
+                class: read_config_half_word(address, OFFSET_CLASS),
+                header_type: read_config_half_word(address, OFFSET_HEADER_TYPE),
+                bist: PciRegister::new(address, OFFSET_BIST),
                 base_addresses: [
-                    PciRegister::new(bus, slot, function, OFFSET_BAR0).into(),
-                    PciRegister::new(bus, slot, function, OFFSET_BAR1).into(),
-                    PciRegister::new(bus, slot, function, OFFSET_BAR2).into(),
-                    PciRegister::new(bus, slot, function, OFFSET_BAR3).into(),
-                    PciRegister::new(bus, slot, function, OFFSET_BAR4).into(),
-                    PciRegister::new(bus, slot, function, OFFSET_BAR5).into(),
+                    PciRegister::new(address, OFFSET_BAR0).into(),
+                    PciRegister::new(address, OFFSET_BAR1).into(),
+                    PciRegister::new(address, OFFSET_BAR2).into(),
+                    PciRegister::new(address, OFFSET_BAR3).into(),
+                    PciRegister::new(address, OFFSET_BAR4).into(),
+                    PciRegister::new(address, OFFSET_BAR5).into(),
                 ],
-                interrupt_pin: PciRegister::new(bus, slot, function, OFFSET_INTERRUPT_PIN),
-                interrupt_line: PciRegister::new(bus, slot, function, OFFSET_INTERRUPT_LINE),
+                interrupt_pin: PciRegister::new(address, OFFSET_INTERRUPT_PIN),
+                interrupt_line: PciRegister::new(address, OFFSET_INTERRUPT_LINE),
             }
         }
     }
 
+    pub fn address(&self) -> PciAddress {
+        PciAddress::with_segment(self.segment, self.bus, self.slot, self.function)
+    }
+
+    /// The name of the driver that the device is bound to, if any.
+    pub fn driver(&self) -> Option<&'static str> {
+        self.driver
+    }
+
+    /// The name of the class of the device, see [`class_name`].
+    pub fn class_name(&self) -> &'static str {
+        class_name(self.class, self.subclass, self.prog)
+    }
+
     pub fn status(&self) -> Status {
         Status::from_bits_truncate(self.status.read())
     }
@@ -98,9 +154,72 @@ impl PciDevice {
         BIST::from_bits_truncate(self.bist.read())
     }
 
+    pub fn command(&self) -> Command {
+        command::command(self)
+    }
+
+    /// Lets the device access memory by itself, which devices that do DMA
+    /// need.
     pub fn enable_bus_mastering(&mut self) {
-        let mut command = self.command.read();
-        command |= 1 << 2;
-        self.command.write(command);
+        command::update_command(self, Command::BUS_MASTER, Command::empty());
+    }
+
+    /// Lets the device respond to accesses to its memory BARs.
+    pub fn enable_memory_space(&mut self) {
+        command::update_command(self, Command::MEMORY_SPACE, Command::empty());
+    }
+
+    /// Lets the device respond to accesses to its I/O BARs.
+    pub fn enable_io_space(&mut self) {
+        command::update_command(self, Command::IO_SPACE, Command::empty());
+    }
+
+    /// Keeps the device from asserting its interrupt pin, for drivers that use
+    /// message signaled interrupts.
+    pub fn disable_intx(&mut self) {
+        command::update_command(self, Command::INTERRUPT_DISABLE, Command::empty());
+    }
+
+    /// Decodes the BAR with the given index and determines its size, see
+    /// [`bar`](bar::bar).
+    pub fn bar(&mut self, index: usize) -> Option<Bar> {
+        bar::bar(self, index)
+    }
+
+    /// All BARs of the device, along with their index.
+    pub fn bars(&mut self) -> impl Iterator<Item = (usize, Bar)> + '_ {
+        bar::bars(self)
+    }
+
+    /// The capabilities in the configuration space.
+    pub fn capabilities(&self) -> impl Iterator<Item = Capability> + '_ {
+        capability::capabilities(self)
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::string::ToString;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::PciAddress;
+
+    #[kernel_test]
+    fn test_address() {
+        assert_eq!("0000:00:1f.2", PciAddress::new(0, 0x1F, 2).to_string());
+        assert_eq!(
+            "0001:80:03.0",
+            PciAddress::with_segment(1, 0x80, 3, 0).to_string()
+        );
+
+        // the segment sorts first, and tells otherwise equal addresses apart
+        let first = PciAddress::new(0xFF, 31, 7);
+        let second = PciAddress::with_segment(1, 0, 0, 0);
+        assert!(first < second);
+        assert_ne!(
+            PciAddress::new(0, 1, 0),
+            PciAddress::with_segment(1, 0, 1, 0)
+        );
     }
 }
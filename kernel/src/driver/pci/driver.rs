@@ -0,0 +1,315 @@
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::error::Error;
+
+use log::{error, info};
+use spin::Mutex;
+
+use crate::driver::pci::PciDevice;
+
+/// A device that a driver can drive. Drivers for a specific device win over
+/// drivers that match by class, and the more of the class that a driver
+/// matches, the better.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PciMatch {
+    Device {
+        vendor_id: u16,
+        device_id: u16,
+    },
+    /// `None` matches any subclass or programming interface.
+    Class {
+        class: u8,
+        subclass: Option<u8>,
+        prog_if: Option<u8>,
+    },
+}
+
+impl PciMatch {
+    /// How well this matches the device, or `None` if it doesn't. Higher is
+    /// better.
+    pub fn specificity(&self, device: &PciDevice) -> Option<u8> {
+        match *self {
+            PciMatch::Device {
+                vendor_id,
+                device_id,
+            } => (device.vendor_id == vendor_id && device.device_id == device_id).then_some(3),
+            PciMatch::Class {
+                class,
+                subclass,
+                prog_if,
+            } => {
+                if device.class != class
+                    || subclass.is_some_and(|subclass| subclass != device.subclass)
+                    || prog_if.is_some_and(|prog_if| prog_if != device.prog)
+                {
+                    return None;
+                }
+                Some(u8::from(subclass.is_some()) + u8::from(prog_if.is_some()))
+            }
+        }
+    }
+}
+
+pub struct PciDriverDescriptor {
+    pub name: &'static str,
+    pub matches: &'static [PciMatch],
+    /// Sets up the device. If this fails, the device is released, and the
+    /// next best driver gets it.
+    #[allow(clippy::type_complexity)]
+    pub init: fn(Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>>,
+}
+
+impl PciDriverDescriptor {
+    /// How well the best entry of the match table matches the device, or
+    /// `None` if the driver doesn't drive the device.
+    pub fn specificity(&self, device: &PciDevice) -> Option<u8> {
+        self.matches
+            .iter()
+            .filter_map(|m| m.specificity(device))
+            .max()
+    }
+}
+
+/// The registered drivers, which bind to the devices once they are
+/// enumerated. Every device is bound to at most one driver.
+pub(in crate::driver::pci) struct Registry {
+    drivers: Vec<&'static PciDriverDescriptor>,
+    enumerated: bool,
+}
+
+impl Registry {
+    pub const fn new() -> Self {
+        Self {
+            drivers: Vec::new(),
+            enumerated: false,
+        }
+    }
+
+    /// Adds the driver. If the devices were already bound, the driver gets
+    /// the chance to bind to the devices that no other driver claimed.
+    pub fn register(
+        &mut self,
+        driver: &'static PciDriverDescriptor,
+        devices: &[Arc<Mutex<PciDevice>>],
+    ) {
+        self.drivers.push(driver);
+        if self.enumerated {
+            for device in devices {
+                self.bind(device);
+            }
+        }
+    }
+
+    /// Binds the enumerated devices to the registered drivers. Returns the
+    /// devices that no driver claimed.
+    pub fn bind_all<'a>(
+        &mut self,
+        devices: &'a [Arc<Mutex<PciDevice>>],
+    ) -> Vec<&'a Arc<Mutex<PciDevice>>> {
+        self.enumerated = true;
+        devices.iter().filter(|device| !self.bind(device)).collect()
+    }
+
+    /// Binds the device to the best matching driver, unless it's already
+    /// claimed. Returns whether the device has a driver afterwards.
+    fn bind(&self, device: &Arc<Mutex<PciDevice>>) -> bool {
+        let mut candidates = {
+            let device = device.lock();
+            if device.driver.is_some() {
+                return true;
+            }
+            self.drivers
+                .iter()
+                .filter_map(|driver| Some((driver.specificity(&device)?, *driver)))
+                .collect::<Vec<_>>()
+        };
+        // the sort is stable, so the driver that was registered first wins
+        // among equally good matches
+        candidates.sort_by_key(|(specificity, _)| Reverse(*specificity));
+
+        for (_, driver) in candidates {
+            device.lock().driver = Some(driver.name);
+            match (driver.init)(Arc::downgrade(device)) {
+                Ok(()) => {
+                    info!("loaded driver {} for {}", driver.name, device.lock());
+                    return true;
+                }
+                Err(e) => {
+                    let mut device = device.lock();
+                    error!(
+                        "failed to load driver {} for {}: {}",
+                        driver.name, device, e
+                    );
+                    device.driver = None;
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::sync::{Arc, Weak};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::error::Error;
+
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+
+    use crate::driver::pci::driver::Registry;
+    use crate::driver::pci::register::PciRegister;
+    use crate::driver::pci::{PciAddress, PciDevice, PciDriverDescriptor, PciMatch};
+
+    fn register<T>() -> PciRegister<T> {
+        PciRegister::new(PciAddress::new(0xFF, 31, 7), 0)
+    }
+
+    /// A device that is never accessed, its registers point to a function
+    /// that doesn't exist.
+    fn device(vendor_id: u16, device_id: u16, class: u8, subclass: u8) -> Arc<Mutex<PciDevice>> {
+        Arc::new(Mutex::new(PciDevice {
+            segment: 0,
+            bus: 0xFF,
+            slot: 31,
+            function: 7,
+            parent: None,
+            driver: None,
+            vendor_id,
+            device_id,
+            status: register(),
+            command: register(),
+            rev: 0,
+            prog: 0,
+            subclass,
+            class,
+            header_type: 0,
+            bist: register(),
+            base_addresses: core::array::from_fn(|_| register().into()),
+            interrupt_pin: register(),
+            interrupt_line: register(),
+        }))
+    }
+
+    static BOUND: Mutex<Vec<(&str, u16)>> = Mutex::new(Vec::new());
+
+    fn bound() -> Vec<(&'static str, u16)> {
+        core::mem::take(&mut *BOUND.lock())
+    }
+
+    fn record(name: &'static str, device: Weak<Mutex<PciDevice>>) {
+        let device_id = device.upgrade().unwrap().lock().device_id;
+        BOUND.lock().push((name, device_id));
+    }
+
+    static STORAGE: PciDriverDescriptor = PciDriverDescriptor {
+        name: "storage",
+        matches: &[PciMatch::Class {
+            class: 0x01,
+            subclass: None,
+            prog_if: None,
+        }],
+        init: |device| {
+            record("storage", device);
+            Ok(())
+        },
+    };
+
+    static SPECIFIC: PciDriverDescriptor = PciDriverDescriptor {
+        name: "specific",
+        matches: &[
+            PciMatch::Device {
+                vendor_id: 0x1234,
+                device_id: 0x0001,
+            },
+            PciMatch::Device {
+                vendor_id: 0x1234,
+                device_id: 0x0002,
+            },
+        ],
+        init: |device| -> Result<(), Box<dyn Error>> {
+            let device_id = device.upgrade().unwrap().lock().device_id;
+            if device_id == 0x0002 {
+                return Err("unsupported revision".into());
+            }
+            record("specific", device);
+            Ok(())
+        },
+    };
+
+    #[kernel_test]
+    fn test_driver_precedence() {
+        let devices = vec![
+            device(0x1234, 0x0001, 0x01, 0x06),
+            device(0x1234, 0x0002, 0x01, 0x06),
+            device(0x1234, 0x0003, 0x01, 0x08),
+            device(0x1234, 0x0004, 0x02, 0x00),
+        ];
+        let mut registry = Registry::new();
+        registry.register(&STORAGE, &devices);
+        registry.register(&SPECIFIC, &devices);
+        assert!(bound().is_empty());
+
+        let unclaimed = registry.bind_all(&devices);
+        // the device that the specific driver fails on falls back to the
+        // class driver
+        assert_eq!(
+            vec![("specific", 1), ("storage", 2), ("storage", 3)],
+            bound()
+        );
+        assert_eq!(1, unclaimed.len());
+        assert_eq!(0x0004, unclaimed[0].lock().device_id);
+        assert_eq!(Some("specific"), devices[0].lock().driver());
+        assert_eq!(Some("storage"), devices[1].lock().driver());
+        assert_eq!(None, devices[3].lock().driver());
+    }
+
+    static NETWORK: PciDriverDescriptor = PciDriverDescriptor {
+        name: "network",
+        matches: &[PciMatch::Class {
+            class: 0x02,
+            subclass: Some(0x00),
+            prog_if: None,
+        }],
+        init: |device| {
+            record("network", device);
+            Ok(())
+        },
+    };
+
+    static LATE_STORAGE: PciDriverDescriptor = PciDriverDescriptor {
+        name: "late storage",
+        matches: &[PciMatch::Class {
+            class: 0x01,
+            subclass: Some(0x06),
+            prog_if: None,
+        }],
+        init: |device| {
+            record("late storage", device);
+            Ok(())
+        },
+    };
+
+    #[kernel_test]
+    fn test_driver_late_registration() {
+        let devices = vec![
+            device(0x1234, 0x0011, 0x01, 0x06),
+            device(0x1234, 0x0012, 0x02, 0x00),
+        ];
+        let mut registry = Registry::new();
+        registry.register(&STORAGE, &devices);
+        assert_eq!(1, registry.bind_all(&devices).len());
+        assert_eq!(vec![("storage", 0x11)], bound());
+
+        // a better match doesn't take claimed devices away
+        registry.register(&LATE_STORAGE, &devices);
+        registry.register(&NETWORK, &devices);
+        assert_eq!(vec![("network", 0x12)], bound());
+        assert_eq!(Some("storage"), devices[0].lock().driver());
+        assert_eq!(Some("network"), devices[1].lock().driver());
+    }
+}
@@ -0,0 +1,283 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::ptr::{read_volatile, write_volatile};
+
+use acpi::mcfg::Mcfg;
+use conquer_once::spin::OnceCell;
+use log::{debug, info};
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::driver::acpi::acpi_tables;
+use crate::driver::pci::{PciAddress, ReadConfig, WriteConfig};
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::process::vmm;
+use crate::Result;
+
+/// Every function has 4KiB of configuration space with ECAM.
+const FUNCTION_CONFIG_SIZE: u16 = 0x1000;
+const BUS_WINDOW_SIZE: u64 = 1 << 20;
+
+/// The ECAM windows that the firmware describes. Config space accesses go
+/// through them instead of port I/O once they're set.
+static ECAM: OnceCell<EcamWindows> = OnceCell::uninit();
+
+/// The memory-mapped configuration space of the buses of a PCI segment.
+/// Unlike port I/O, which only reaches the first 256 bytes, this reaches the
+/// full 4KiB of configuration space of every function.
+#[derive(Debug)]
+pub struct EcamConfigAccess {
+    base: VirtAddr,
+    start_bus: u8,
+    bus_count: u16,
+}
+
+impl EcamConfigAccess {
+    /// # Safety
+    ///
+    /// The configuration space of `bus_count` buses, starting at `start_bus`,
+    /// must be mapped uncached at `base`, for as long as this lives.
+    pub unsafe fn new(base: VirtAddr, start_bus: u8, bus_count: u16) -> Self {
+        Self {
+            base,
+            start_bus,
+            bus_count,
+        }
+    }
+
+    /// The address of the configuration space of the function, or `None` if
+    /// it's not in the window.
+    pub fn address(&self, bus: u8, device: u8, function: u8) -> Option<VirtAddr> {
+        let bus = bus.checked_sub(self.start_bus)?;
+        if u16::from(bus) >= self.bus_count || device >= 32 || function >= 8 {
+            return None;
+        }
+        Some(
+            self.base
+                + (u64::from(bus) << 20 | u64::from(device) << 15 | u64::from(function) << 12),
+        )
+    }
+
+    /// The configuration space of the function, or `None` if it's not in the
+    /// window.
+    pub fn function(&self, bus: u8, device: u8, function: u8) -> Option<EcamFunction<'_>> {
+        self.address(bus, device, function)
+            .map(|base| EcamFunction {
+                base,
+                _ecam: PhantomData,
+            })
+    }
+}
+
+/// The ECAM windows of all segments. A segment may be split into several
+/// windows, each covering a range of its buses.
+#[derive(Debug, Default)]
+pub struct EcamWindows {
+    windows: Vec<(u16, EcamConfigAccess)>,
+}
+
+impl EcamWindows {
+    pub fn insert(&mut self, segment: u16, window: EcamConfigAccess) {
+        self.windows.push((segment, window));
+    }
+
+    /// The configuration space of the function, or `None` if no window
+    /// covers it.
+    pub fn function(&self, address: PciAddress) -> Option<EcamFunction<'_>> {
+        self.windows
+            .iter()
+            .filter(|(segment, _)| *segment == address.segment)
+            .find_map(|(_, window)| window.function(address.bus, address.device, address.function))
+    }
+
+    /// The segments that have a window, along with the first bus of the
+    /// segment, in ascending order.
+    pub fn segments(&self) -> Vec<(u16, u8)> {
+        let mut segments = Vec::<(u16, u8)>::new();
+        for (segment, window) in &self.windows {
+            match segments.iter_mut().find(|(s, _)| s == segment) {
+                Some((_, start_bus)) => *start_bus = (*start_bus).min(window.start_bus),
+                None => segments.push((*segment, window.start_bus)),
+            }
+        }
+        segments.sort_unstable();
+        segments
+    }
+}
+
+/// The configuration space of a single function in an ECAM window.
+/// Accesses outside of the 4KiB read as all ones, and writes to them are
+/// ignored.
+pub struct EcamFunction<'a> {
+    base: VirtAddr,
+    _ecam: PhantomData<&'a EcamConfigAccess>,
+}
+
+macro_rules! impl_config_ops {
+    ($typ:ty) => {
+        impl ReadConfig<$typ> for EcamFunction<'_> {
+            fn read_config(&self, offset: u16) -> $typ {
+                if offset as usize + size_of::<$typ>() > FUNCTION_CONFIG_SIZE as usize {
+                    return <$typ>::MAX;
+                }
+                debug_assert_eq!(0, offset as usize % size_of::<$typ>());
+                unsafe { read_volatile((self.base + u64::from(offset)).as_ptr::<$typ>()) }
+            }
+        }
+
+        impl WriteConfig<$typ> for EcamFunction<'_> {
+            fn write_config(&mut self, offset: u16, value: $typ) {
+                if offset as usize + size_of::<$typ>() > FUNCTION_CONFIG_SIZE as usize {
+                    return;
+                }
+                debug_assert_eq!(0, offset as usize % size_of::<$typ>());
+                unsafe {
+                    write_volatile((self.base + u64::from(offset)).as_mut_ptr::<$typ>(), value)
+                }
+            }
+        }
+    };
+}
+
+impl_config_ops!(u8);
+impl_config_ops!(u16);
+impl_config_ops!(u32);
+
+/// The configuration space of the function through ECAM, if there is an ECAM
+/// window that covers it.
+pub fn function(address: PciAddress) -> Option<EcamFunction<'static>> {
+    ECAM.get()?.function(address)
+}
+
+/// The segments that have an ECAM window, along with their first bus. Segment
+/// 0 is reachable through port I/O even if it's not in here.
+pub fn segments() -> Vec<(u16, u8)> {
+    ECAM.get().map(EcamWindows::segments).unwrap_or_default()
+}
+
+/// Maps the ECAM windows of all segments from the MCFG table, so that config
+/// space accesses use them from now on. Without an MCFG table, port I/O is
+/// used, which only reaches segment 0.
+pub fn init() -> Result<()> {
+    let tables = acpi_tables().ok_or("ACPI is not initialized")?.lock();
+    let Ok(mcfg) = tables.find_table::<Mcfg>() else {
+        debug!("no MCFG table, using port I/O for the PCI configuration space");
+        return Ok(());
+    };
+    let mut windows = EcamWindows::default();
+    for entry in mcfg.entries() {
+        let window = map_window(
+            entry.pci_segment_group,
+            entry.base_address,
+            entry.bus_number_start,
+            entry.bus_number_end,
+        )?;
+        windows.insert(entry.pci_segment_group, window);
+    }
+    ECAM.init_once(|| windows);
+    Ok(())
+}
+
+fn map_window(segment: u16, base: u64, start_bus: u8, end_bus: u8) -> Result<EcamConfigAccess> {
+    let bus_count = u16::from(end_bus.saturating_sub(start_bus)) + 1;
+
+    // the base address is the one of bus 0, even if the window starts later
+    let start = PhysAddr::try_new(base + u64::from(start_bus) * BUS_WINDOW_SIZE)
+        .map_err(|_| "invalid ECAM base address")?;
+    let size = u64::from(bus_count) * BUS_WINDOW_SIZE;
+    let frames = (0..size / Size4KiB::SIZE)
+        .map(|i| PhysFrame::containing_address(start + i * Size4KiB::SIZE))
+        .collect::<Vec<_>>();
+    let addr = vmm()
+        .allocate_memory_backed_vmobject(
+            format!("ecam segment {segment} buses {start_bus}-{end_bus}"),
+            MapAt::Anywhere,
+            size as usize,
+            AllocationStrategy::MapNow(&frames),
+            PageTableFlags::PRESENT
+                | PageTableFlags::NO_CACHE
+                | PageTableFlags::NO_EXECUTE
+                | PageTableFlags::WRITABLE,
+        )
+        .map_err(|_| "failed to map the ECAM window")?;
+    info!("using ECAM at {start:p} for PCI segment {segment} buses {start_bus}-{end_bus}");
+    Ok(unsafe { EcamConfigAccess::new(addr, start_bus, bus_count) })
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use kernel_test_framework::kernel_test;
+    use x86_64::VirtAddr;
+
+    use crate::driver::pci::ecam::{EcamConfigAccess, EcamWindows};
+    use crate::driver::pci::{PciAddress, ReadConfig, WriteConfig};
+
+    #[kernel_test]
+    fn test_ecam_address() {
+        let base = VirtAddr::new(0x1000_0000);
+        let ecam = unsafe { EcamConfigAccess::new(base, 0, 256) };
+        assert_eq!(Some(base), ecam.address(0, 0, 0));
+        assert_eq!(Some(base + 0x0FFF_F000_u64), ecam.address(255, 31, 7));
+        assert_eq!(None, ecam.address(0, 32, 0));
+        assert_eq!(None, ecam.address(0, 0, 8));
+
+        // a window of the buses 16 to 31
+        let ecam = unsafe { EcamConfigAccess::new(base, 16, 16) };
+        assert_eq!(Some(base), ecam.address(16, 0, 0));
+        assert_eq!(Some(base + 0x00F0_8000_u64), ecam.address(31, 1, 0));
+        assert_eq!(None, ecam.address(15, 0, 0));
+        assert_eq!(None, ecam.address(32, 0, 0));
+    }
+
+    #[kernel_test]
+    fn test_ecam_access() {
+        // the configuration space of the first two functions of device 0
+        let mut memory = vec![0_u32; 2 * 0x1000 / 4];
+        memory[1024] = 0x1234_8086;
+        let base = VirtAddr::from_ptr(memory.as_mut_ptr());
+        let ecam = unsafe { EcamConfigAccess::new(base, 0, 1) };
+
+        let mut function = ecam.function(0, 0, 1).unwrap();
+        assert_eq!(0x8086, ReadConfig::<u16>::read_config(&function, 0x00));
+        assert_eq!(0x12, ReadConfig::<u8>::read_config(&function, 0x03));
+        function.write_config(0x100, 0xDEAD_BEEF_u32);
+        function.write_config(0xFFE, 0x55AA_u16);
+        // outside of the configuration space of the function
+        function.write_config(0x1000, 0xFFFF_u16);
+        assert_eq!(u32::MAX, function.read_config(0x1000));
+
+        assert_eq!(0xDEAD_BEEF, memory[1024 + 0x40]);
+        assert_eq!(0x55AA_0000, memory[2047]);
+        assert_eq!(0, memory[0]);
+    }
+
+    #[kernel_test]
+    fn test_ecam_segments() {
+        // the configuration space of function 0 on bus 0 of two segments
+        let mut first = vec![0_u32; 0x1000 / 4];
+        let mut second = vec![0_u32; 0x1000 / 4];
+        first[0] = 0x0001_8086;
+        second[0] = 0x0002_1AF4;
+
+        let mut windows = EcamWindows::default();
+        windows.insert(1, unsafe {
+            EcamConfigAccess::new(VirtAddr::from_ptr(second.as_mut_ptr()), 0, 1)
+        });
+        windows.insert(0, unsafe {
+            EcamConfigAccess::new(VirtAddr::from_ptr(first.as_mut_ptr()), 0, 1)
+        });
+        assert_eq!(vec![(0, 0), (1, 0)], windows.segments());
+
+        let read = |address: PciAddress| -> Option<u32> {
+            Some(windows.function(address)?.read_config(0x00))
+        };
+        let in_segment = |segment: u16, bus: u8| PciAddress::with_segment(segment, bus, 0, 0);
+        assert_eq!(Some(0x0001_8086), read(in_segment(0, 0)));
+        assert_eq!(Some(0x0002_1AF4), read(in_segment(1, 0)));
+        assert_eq!(None, read(in_segment(1, 1)));
+        assert_eq!(None, read(in_segment(2, 0)));
+    }
+}
@@ -0,0 +1,252 @@
+use alloc::vec::Vec;
+
+use log::warn;
+
+use crate::driver::pci::raw::{
+    OFFSET_HEADER_TYPE, OFFSET_SECONDARY_BUS, OFFSET_SUBORDINATE_BUS, OFFSET_VENDOR_ID,
+};
+use crate::driver::pci::{PciAddress, ReadConfig};
+
+const HEADER_TYPE_MULTIFUNCTION: u8 = 0x80;
+const HEADER_TYPE_PCI_BRIDGE: u8 = 0x01;
+
+/// A function that was found while enumerating, along with the bridge that it
+/// is behind.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct EnumeratedFunction {
+    pub address: PciAddress,
+    /// The PCI-to-PCI bridge whose secondary bus the function is on, or
+    /// `None` for functions on the root bus.
+    pub parent: Option<PciAddress>,
+}
+
+/// Finds all functions of a segment, by scanning its root bus and recursing
+/// into the secondary bus of every PCI-to-PCI bridge. Functions 1 to 7 are
+/// only probed on multifunction devices.
+///
+/// `config` gives access to the configuration space of a function.
+pub fn enumerate<F, C>(segment: u16, root_bus: u8, config: F) -> Vec<EnumeratedFunction>
+where
+    F: Fn(PciAddress) -> C,
+    C: ReadConfig<u8> + ReadConfig<u16>,
+{
+    let mut functions = Vec::new();
+    // one bit for every bus, so that broken bridges can't make us loop
+    let mut visited = [0_u64; 4];
+    scan_bus(
+        &config,
+        segment,
+        root_bus,
+        None,
+        &mut visited,
+        &mut functions,
+    );
+    functions
+}
+
+fn scan_bus<F, C>(
+    config: &F,
+    segment: u16,
+    bus: u8,
+    parent: Option<PciAddress>,
+    visited: &mut [u64; 4],
+    functions: &mut Vec<EnumeratedFunction>,
+) where
+    F: Fn(PciAddress) -> C,
+    C: ReadConfig<u8> + ReadConfig<u16>,
+{
+    let bit = 1 << (bus % 64);
+    if visited[usize::from(bus / 64)] & bit != 0 {
+        warn!("PCI bus {bus} is behind more than one bridge, skipping it");
+        return;
+    }
+    visited[usize::from(bus / 64)] |= bit;
+
+    for device in 0..32 {
+        let first = PciAddress::with_segment(segment, bus, device, 0);
+        let header_type: u8 = {
+            let config = config(first);
+            if ReadConfig::<u16>::read_config(&config, OFFSET_VENDOR_ID) == 0xFFFF {
+                continue;
+            }
+            config.read_config(OFFSET_HEADER_TYPE)
+        };
+        let function_count = if header_type & HEADER_TYPE_MULTIFUNCTION != 0 {
+            8
+        } else {
+            1
+        };
+        for function in 0..function_count {
+            let address = PciAddress::with_segment(segment, bus, device, function);
+            let function_config = config(address);
+            if function != 0
+                && ReadConfig::<u16>::read_config(&function_config, OFFSET_VENDOR_ID) == 0xFFFF
+            {
+                continue;
+            }
+            functions.push(EnumeratedFunction { address, parent });
+
+            let header_type: u8 = function_config.read_config(OFFSET_HEADER_TYPE);
+            if header_type & !HEADER_TYPE_MULTIFUNCTION != HEADER_TYPE_PCI_BRIDGE {
+                continue;
+            }
+            let secondary: u8 = function_config.read_config(OFFSET_SECONDARY_BUS);
+            let subordinate: u8 = function_config.read_config(OFFSET_SUBORDINATE_BUS);
+            // buses behind a bridge are numbered higher than the bus of the
+            // bridge, firmware that didn't number them leaves them at zero
+            if secondary <= bus || subordinate < secondary {
+                warn!(
+                    "PCI bridge {address} has no valid bus numbers (secondary {secondary}, subordinate {subordinate}), skipping the devices behind it"
+                );
+                continue;
+            }
+            scan_bus(
+                config,
+                segment,
+                secondary,
+                Some(address),
+                visited,
+                functions,
+            );
+        }
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::enumerate::{enumerate, EnumeratedFunction};
+    use crate::driver::pci::mock::MockConfig;
+    use crate::driver::pci::{PciAddress, ReadConfig};
+
+    /// All functions that exist, and the functions that were probed.
+    struct MockBus {
+        functions: BTreeMap<PciAddress, MockConfig>,
+        probed: RefCell<Vec<PciAddress>>,
+    }
+
+    impl MockBus {
+        fn new() -> Self {
+            Self {
+                functions: BTreeMap::new(),
+                probed: RefCell::new(Vec::new()),
+            }
+        }
+
+        fn add(&mut self, address: PciAddress, header_type: u8, buses: Option<(u8, u8)>) {
+            let mut config = MockConfig::new(&[]);
+            config.0[0x00..0x02].copy_from_slice(&0x8086_u16.to_le_bytes());
+            config.0[0x0E] = header_type;
+            if let Some((secondary, subordinate)) = buses {
+                config.0[0x19] = secondary;
+                config.0[0x1A] = subordinate;
+            }
+            self.functions.insert(address, config);
+        }
+    }
+
+    /// Nothing answers for functions that don't exist, so they read as all
+    /// ones.
+    static MISSING: MockConfig = MockConfig([0xFF; 4096]);
+
+    struct MockFunction<'a>(Option<&'a MockConfig>);
+
+    impl<T> ReadConfig<T> for MockFunction<'_>
+    where
+        MockConfig: ReadConfig<T>,
+    {
+        fn read_config(&self, offset: u16) -> T {
+            self.0.unwrap_or(&MISSING).read_config(offset)
+        }
+    }
+
+    fn function(address: PciAddress, parent: Option<PciAddress>) -> EnumeratedFunction {
+        EnumeratedFunction { address, parent }
+    }
+
+    #[kernel_test]
+    fn test_enumerate_bridges() {
+        let host = PciAddress::new(0, 0, 0);
+        let bridge = PciAddress::new(0, 1, 0);
+        let duplicate = PciAddress::new(0, 2, 0);
+        let unnumbered = PciAddress::new(0, 5, 0);
+        let nested = PciAddress::new(1, 4, 0);
+        let device = PciAddress::new(2, 3, 0);
+        let device_fn2 = PciAddress::new(2, 3, 2);
+
+        let mut bus = MockBus::new();
+        bus.add(host, 0x00, None);
+        bus.add(bridge, 0x01, Some((1, 2)));
+        // a bridge to buses that are already behind another bridge, and one
+        // whose buses weren't numbered by the firmware
+        bus.add(duplicate, 0x01, Some((1, 2)));
+        bus.add(unnumbered, 0x01, Some((0, 0)));
+        bus.add(nested, 0x01, Some((2, 2)));
+        bus.add(device, 0x80, None);
+        bus.add(device_fn2, 0x80, None);
+
+        let functions = enumerate(0, 0, |address| {
+            bus.probed.borrow_mut().push(address);
+            MockFunction(bus.functions.get(&address))
+        });
+        assert_eq!(
+            vec![
+                function(host, None),
+                function(bridge, None),
+                function(nested, Some(bridge)),
+                function(device, Some(nested)),
+                function(device_fn2, Some(nested)),
+                function(duplicate, None),
+                function(unnumbered, None),
+            ],
+            functions
+        );
+
+        let probed = bus.probed.borrow();
+        // only the multifunction device has its other functions probed
+        assert!(probed
+            .iter()
+            .filter(|address| address.function != 0)
+            .all(|address| address.bus == 2 && address.device == 3));
+        assert!(probed.contains(&PciAddress::new(2, 3, 1)));
+        // the buses behind the duplicate bridge are scanned only once
+        assert_eq!(
+            1,
+            probed
+                .iter()
+                .filter(|&&address| address == PciAddress::new(1, 0, 0))
+                .count()
+        );
+    }
+
+    #[kernel_test]
+    fn test_enumerate_segment() {
+        // a segment whose root bus is 0x80
+        let root = PciAddress::with_segment(1, 0x80, 0, 0);
+        let bridge = PciAddress::with_segment(1, 0x80, 1, 0);
+        let device = PciAddress::with_segment(1, 0x81, 0, 0);
+
+        let mut bus = MockBus::new();
+        bus.add(root, 0x00, None);
+        bus.add(bridge, 0x01, Some((0x81, 0x81)));
+        bus.add(device, 0x00, None);
+        // the same location in segment 0 is a different function
+        bus.add(PciAddress::new(0x80, 2, 0), 0x00, None);
+
+        let functions = enumerate(1, 0x80, |address| MockFunction(bus.functions.get(&address)));
+        assert_eq!(
+            vec![
+                function(root, None),
+                function(bridge, None),
+                function(device, Some(bridge)),
+            ],
+            functions
+        );
+    }
+}
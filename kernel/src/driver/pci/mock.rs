@@ -0,0 +1,50 @@
+use crate::driver::pci::{ReadConfig, WriteConfig};
+
+/// A configuration space in memory, for testing code that accesses the
+/// configuration space of a device.
+pub struct MockConfig(pub [u8; 4096]);
+
+impl MockConfig {
+    /// A configuration space with the given capabilities, as offset, ID,
+    /// next pointer and the bytes after the header.
+    pub fn new(list: &[(u8, u8, u8, &[u8])]) -> Self {
+        let mut config = [0; 4096];
+        if let Some(&(first, ..)) = list.first() {
+            config[0x06] = 1 << 4;
+            config[0x34] = first;
+        }
+        for &(offset, id, next, data) in list {
+            let offset = usize::from(offset);
+            config[offset] = id;
+            config[offset + 1] = next;
+            config[offset + 2..offset + 2 + data.len()].copy_from_slice(data);
+        }
+        Self(config)
+    }
+}
+
+macro_rules! impl_config_ops {
+    ($typ:ty) => {
+        impl ReadConfig<$typ> for MockConfig {
+            fn read_config(&self, offset: u16) -> $typ {
+                let offset = usize::from(offset);
+                <$typ>::from_le_bytes(
+                    self.0[offset..offset + size_of::<$typ>()]
+                        .try_into()
+                        .unwrap(),
+                )
+            }
+        }
+
+        impl WriteConfig<$typ> for MockConfig {
+            fn write_config(&mut self, offset: u16, value: $typ) {
+                let offset = usize::from(offset);
+                self.0[offset..offset + size_of::<$typ>()].copy_from_slice(&value.to_le_bytes());
+            }
+        }
+    };
+}
+
+impl_config_ops!(u8);
+impl_config_ops!(u16);
+impl_config_ops!(u32);
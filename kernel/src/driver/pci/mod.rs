@@ -1,16 +1,34 @@
-use alloc::boxed::Box;
-use alloc::sync::{Arc, Weak};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use bitflags::bitflags;
 use conquer_once::spin::OnceCell;
-use core::error::Error;
+use driver::Registry;
 use linkme::distributed_slice;
-use log::{error, info, trace, warn};
+use log::{trace, warn};
 use spin::Mutex;
 
+pub use bar::*;
+pub use capability::*;
+pub use class::class_name;
+pub use command::*;
 pub use device::*;
+pub use driver::{PciDriverDescriptor, PciMatch};
+pub use ecam::{EcamConfigAccess, EcamFunction, EcamWindows};
+pub use msi::*;
+pub use msix::*;
 
+mod bar;
+mod capability;
+mod class;
+mod command;
 mod device;
+mod driver;
+mod ecam;
+mod enumerate;
+#[cfg(feature = "kernel_test")]
+mod mock;
+mod msi;
+mod msix;
 mod raw;
 mod register;
 
@@ -18,54 +36,45 @@ mod register;
 pub static PCI_DRIVERS: [PciDriverDescriptor];
 
 pub fn init() {
-    PCI_DRIVERS
-        .iter()
-        .map(|driver| driver.name)
-        .for_each(|name| {
-            trace!("have driver: {}", name);
-        });
+    if let Err(e) = ecam::init() {
+        warn!("failed to set up ECAM, using port I/O for the PCI configuration space: {e}");
+    }
 
-    devices().for_each(|dev| {
-        for driver in PCI_DRIVERS.iter() {
-            if (driver.probe)(&dev.lock()) {
-                match (driver.init)(Arc::downgrade(dev)) {
-                    Ok(_) => {
-                        info!("loaded driver {} for {}", driver.name, dev.lock());
-                        return;
-                    }
-                    Err(e) => {
-                        error!(
-                            "failed to load driver {} for {}: {}",
-                            driver.name,
-                            dev.lock(),
-                            e
-                        )
-                    }
-                }
-            }
-        }
-        warn!("no driver found for {}", dev.lock());
+    let devices = enumerate_devices();
+    let mut registry = REGISTRY.lock();
+    PCI_DRIVERS.iter().for_each(|driver| {
+        trace!("have driver: {}", driver.name);
+        registry.register(driver, devices);
     });
+    for device in registry.bind_all(devices) {
+        warn!("no driver found for {}", device.lock());
+    }
+}
+
+/// Registers a driver that isn't in [`PCI_DRIVERS`]. If the devices were
+/// already bound, the driver is bound to the devices that have no driver yet.
+pub fn register_driver(driver: &'static PciDriverDescriptor) {
+    REGISTRY.lock().register(driver, devices());
 }
 
 static DEVICES: OnceCell<Devices> = OnceCell::uninit();
 
-fn devices<'a>() -> impl Iterator<Item = &'a Arc<Mutex<PciDevice>>> {
-    DEVICES
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::new());
+
+/// The devices that were enumerated in [`init`], or nothing before that.
+pub fn devices() -> &'static [Arc<Mutex<PciDevice>>] {
+    DEVICES.get().map_or(&[], |devices| &devices.devices)
+}
+
+fn enumerate_devices() -> &'static [Arc<Mutex<PciDevice>>] {
+    &DEVICES
         .get_or_init(|| {
             let devices = unsafe { raw::iterate_all() }
                 .map(|v| Arc::new(Mutex::new(v)))
                 .collect::<Vec<_>>();
             Devices { devices }
         })
-        .iter()
-}
-
-pub struct PciDriverDescriptor {
-    pub name: &'static str,
-    pub probe: fn(&PciDevice) -> bool,
-    #[allow(clippy::type_complexity)]
-    pub init: fn(Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>>,
+        .devices
 }
 
 pub struct Devices {
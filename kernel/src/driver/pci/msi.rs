@@ -0,0 +1,79 @@
+use crate::driver::pci::{MsiCapability, ReadConfig, WriteConfig};
+
+const CONTROL_ENABLE: u16 = 1 << 0;
+/// How many vectors are enabled, as a power of two.
+const CONTROL_MULTIPLE_MESSAGE_ENABLE: u16 = 0b111 << 4;
+
+/// The MSI capability of a device. Like with MSI-X, the device delivers its
+/// interrupts as messages, but there is a single message, which is in the
+/// configuration space.
+pub struct Msi {
+    capability: MsiCapability,
+}
+
+impl Msi {
+    pub fn new(capability: MsiCapability) -> Self {
+        Self { capability }
+    }
+
+    /// Writes the message and enables MSI with a single vector, which also
+    /// disables the interrupt pin of the device. The message data only has
+    /// 16 bits, so the upper bits of `message_data` are ignored.
+    ///
+    /// Devices that can only address 32 bits must get a message address
+    /// below 4GiB.
+    pub fn enable<C>(&mut self, config: &mut C, message_addr: u64, message_data: u32)
+    where
+        C: ReadConfig<u16> + WriteConfig<u16> + WriteConfig<u32>,
+    {
+        let offset = self.capability.offset;
+        config.write_config(offset + 4, message_addr as u32);
+        let data_offset = if self.capability.is_64bit() {
+            config.write_config(offset + 8, (message_addr >> 32) as u32);
+            offset + 0xC
+        } else {
+            debug_assert_eq!(0, message_addr >> 32, "message address above 4GiB");
+            offset + 8
+        };
+        config.write_config(data_offset, message_data as u16);
+
+        let control: u16 = config.read_config(offset + 2);
+        config.write_config(
+            offset + 2,
+            (control & !CONTROL_MULTIPLE_MESSAGE_ENABLE) | CONTROL_ENABLE,
+        );
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::mock::MockConfig;
+    use crate::driver::pci::{Msi, MsiCapability, ReadConfig};
+
+    #[kernel_test]
+    fn test_msi_64bit() {
+        // 64-bit addresses, four vectors enabled
+        let mut config = MockConfig::new(&[(0x50, 0x05, 0, &[0xA0, 0x00])]);
+        let mut msi = Msi::new(MsiCapability::find(&config).unwrap());
+        msi.enable(&mut config, 0x1_FEE0_1000, 0x0041);
+
+        assert_eq!(0x0081, ReadConfig::<u16>::read_config(&config, 0x52));
+        assert_eq!(0xFEE0_1000, ReadConfig::<u32>::read_config(&config, 0x54));
+        assert_eq!(0x1, ReadConfig::<u32>::read_config(&config, 0x58));
+        assert_eq!(0x0041, ReadConfig::<u16>::read_config(&config, 0x5C));
+    }
+
+    #[kernel_test]
+    fn test_msi_32bit() {
+        let mut config = MockConfig::new(&[(0x50, 0x05, 0, &[0x00, 0x00])]);
+        let mut msi = Msi::new(MsiCapability::find(&config).unwrap());
+        msi.enable(&mut config, 0xFEE0_0000, 0x0030);
+
+        assert_eq!(0x0001, ReadConfig::<u16>::read_config(&config, 0x52));
+        assert_eq!(0xFEE0_0000, ReadConfig::<u32>::read_config(&config, 0x54));
+        assert_eq!(0x0030, ReadConfig::<u16>::read_config(&config, 0x58));
+        assert_eq!(0, ReadConfig::<u16>::read_config(&config, 0x5A));
+    }
+}
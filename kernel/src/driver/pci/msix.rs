@@ -0,0 +1,232 @@
+use core::ptr::{read_volatile, write_volatile};
+
+use thiserror::Error;
+
+use crate::driver::pci::{MsiXCapability, ReadConfig, WriteConfig};
+
+const CONTROL_ENABLE: u16 = 1 << 15;
+const CONTROL_FUNCTION_MASK: u16 = 1 << 14;
+
+/// The lowest bit of the vector control of an entry masks the entry.
+const VECTOR_CONTROL_MASK: u32 = 1 << 0;
+
+/// An entry of an MSI-X table, which is in one of the BARs of the device.
+/// The fields are only accessed with volatile reads and writes.
+#[repr(C)]
+#[derive(Debug)]
+pub struct MsiXTableEntry {
+    address_low: u32,
+    address_high: u32,
+    data: u32,
+    vector_control: u32,
+}
+
+const _: () = assert!(size_of::<MsiXTableEntry>() == 16);
+
+impl MsiXTableEntry {
+    /// The address and the data of the message.
+    pub fn message(&self) -> (u64, u32) {
+        unsafe {
+            let low = read_volatile(&self.address_low);
+            let high = read_volatile(&self.address_high);
+            (
+                u64::from(high) << 32 | u64::from(low),
+                read_volatile(&self.data),
+            )
+        }
+    }
+
+    pub fn set_message(&mut self, address: u64, data: u32) {
+        unsafe {
+            write_volatile(&mut self.address_low, address as u32);
+            write_volatile(&mut self.address_high, (address >> 32) as u32);
+            write_volatile(&mut self.data, data);
+        }
+    }
+
+    pub fn is_masked(&self) -> bool {
+        unsafe { read_volatile(&self.vector_control) & VECTOR_CONTROL_MASK != 0 }
+    }
+
+    pub fn set_masked(&mut self, masked: bool) {
+        unsafe {
+            let control = read_volatile(&self.vector_control);
+            let control = if masked {
+                control | VECTOR_CONTROL_MASK
+            } else {
+                control & !VECTOR_CONTROL_MASK
+            };
+            write_volatile(&mut self.vector_control, control);
+        }
+    }
+}
+
+/// The table has no entry with this index.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+#[error("there is no entry {index} in the MSI-X table of {size} entries")]
+pub struct EntryOutOfBounds {
+    pub index: usize,
+    pub size: usize,
+}
+
+/// The MSI-X capability of a device, along with its table. With MSI-X, the
+/// device delivers its interrupts as messages, which are configured in the
+/// table, instead of through its interrupt pin.
+///
+/// The table must be mapped by the caller, at the location that
+/// [`MsiXCapability::table_location`] tells.
+pub struct MsiX<'a> {
+    capability: MsiXCapability,
+    table: &'a mut [MsiXTableEntry],
+}
+
+impl<'a> MsiX<'a> {
+    /// Creates the handle from the capability and the mapped table. The table
+    /// is cut to the size that the capability tells.
+    pub fn new(capability: MsiXCapability, table: &'a mut [MsiXTableEntry]) -> Self {
+        let size = capability.table_size().min(table.len());
+        Self {
+            capability,
+            table: &mut table[..size],
+        }
+    }
+
+    /// The number of entries in the table.
+    pub fn table_size(&self) -> usize {
+        self.table.len()
+    }
+
+    /// Enables MSI-X, which also disables the interrupt pin of the device,
+    /// and clears the function mask. The entries of the table should be set
+    /// up before.
+    pub fn enable<C>(&mut self, config: &mut C)
+    where
+        C: ReadConfig<u16> + WriteConfig<u16>,
+    {
+        self.update_control(config, |control| {
+            (control | CONTROL_ENABLE) & !CONTROL_FUNCTION_MASK
+        });
+    }
+
+    /// Masks or unmasks all entries at once, regardless of their own mask.
+    pub fn function_mask<C>(&mut self, config: &mut C, masked: bool)
+    where
+        C: ReadConfig<u16> + WriteConfig<u16>,
+    {
+        self.update_control(config, |control| {
+            if masked {
+                control | CONTROL_FUNCTION_MASK
+            } else {
+                control & !CONTROL_FUNCTION_MASK
+            }
+        });
+    }
+
+    /// Sets the message of the entry and unmasks it. The entry is masked
+    /// while it's modified, so that the device never sends a message that is
+    /// only partly written.
+    pub fn set_entry(
+        &mut self,
+        index: usize,
+        message_addr: u64,
+        message_data: u32,
+    ) -> Result<(), EntryOutOfBounds> {
+        let entry = self.entry(index)?;
+        entry.set_masked(true);
+        entry.set_message(message_addr, message_data);
+        entry.set_masked(false);
+        Ok(())
+    }
+
+    pub fn mask_entry(&mut self, index: usize) -> Result<(), EntryOutOfBounds> {
+        self.entry(index)?.set_masked(true);
+        Ok(())
+    }
+
+    pub fn unmask_entry(&mut self, index: usize) -> Result<(), EntryOutOfBounds> {
+        self.entry(index)?.set_masked(false);
+        Ok(())
+    }
+
+    fn entry(&mut self, index: usize) -> Result<&mut MsiXTableEntry, EntryOutOfBounds> {
+        let size = self.table.len();
+        self.table
+            .get_mut(index)
+            .ok_or(EntryOutOfBounds { index, size })
+    }
+
+    fn update_control<C>(&mut self, config: &mut C, f: impl FnOnce(u16) -> u16)
+    where
+        C: ReadConfig<u16> + WriteConfig<u16>,
+    {
+        let offset = self.capability.offset + 2;
+        let control = config.read_config(offset);
+        config.write_config(offset, f(control));
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::mock::MockConfig;
+    use crate::driver::pci::{EntryOutOfBounds, MsiX, MsiXCapability, MsiXTableEntry, ReadConfig};
+
+    fn entry() -> MsiXTableEntry {
+        MsiXTableEntry {
+            address_low: 0,
+            address_high: 0,
+            data: 0,
+            // entries are masked after reset
+            vector_control: 1,
+        }
+    }
+
+    fn control(config: &MockConfig) -> u16 {
+        config.read_config(0x42)
+    }
+
+    #[kernel_test]
+    fn test_msix() {
+        // a table of two entries, with the function mask set
+        let mut config = MockConfig::new(&[(0x40, 0x11, 0, &[0x01, 0x40, 0, 0x20, 0, 0])]);
+        let capability = MsiXCapability::find(&config).unwrap();
+        let mut table = [entry(), entry(), entry()];
+        let mut msix = MsiX::new(capability, &mut table);
+        assert_eq!(2, msix.table_size());
+
+        msix.set_entry(1, 0x1_FEE0_1000, 0x41).unwrap();
+        assert_eq!(
+            Err(EntryOutOfBounds { index: 2, size: 2 }),
+            msix.set_entry(2, 0xFEE0_0000, 0x42)
+        );
+        msix.enable(&mut config);
+        assert_eq!(0x8001, control(&config));
+
+        msix.function_mask(&mut config, true);
+        assert_eq!(0xC001, control(&config));
+        msix.function_mask(&mut config, false);
+        assert_eq!(0x8001, control(&config));
+
+        msix.mask_entry(1).unwrap();
+        assert!(table[1].is_masked());
+        assert_eq!((0x1_FEE0_1000, 0x41), table[1].message());
+        assert!(table[0].is_masked());
+        assert_eq!((0, 0), table[0].message());
+        // the entry after the table isn't touched
+        assert!(table[2].is_masked());
+    }
+
+    #[kernel_test]
+    fn test_msix_unmask_entry() {
+        let config = MockConfig::new(&[(0x40, 0x11, 0, &[0x00, 0, 0, 0x20, 0, 0])]);
+        let mut table = [entry()];
+        let mut msix = MsiX::new(MsiXCapability::find(&config).unwrap(), &mut table);
+        msix.unmask_entry(0).unwrap();
+        assert_eq!(
+            Err(EntryOutOfBounds { index: 1, size: 1 }),
+            msix.mask_entry(1)
+        );
+        assert_eq!(0, table[0].vector_control);
+    }
+}
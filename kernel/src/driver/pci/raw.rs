@@ -1,103 +1,103 @@
-use crate::driver::pci::{PciDevice, ProbeResult};
+use crate::driver::pci::enumerate::enumerate;
+use crate::driver::pci::{ecam, PciAddress, PciDevice, ReadConfig, WriteConfig};
 use x86_64::instructions::port::Port;
 
 const CONFIG_ADDRESS: u16 = 0xCF8;
 const CONFIG_DATA: u16 = 0xCFC;
 
-pub const OFFSET_VENDOR_ID: u8 = 0x00;
-pub const OFFSET_DEVICE_ID: u8 = 0x02;
-pub const OFFSET_STATUS: u8 = 0x06;
-pub const OFFSET_COMMAND: u8 = 0x04;
-pub const OFFSET_PROG_IF_REVISION_ID: u8 = 0x08;
-pub const OFFSET_SUBCLASS: u8 = 0x0A;
-pub const OFFSET_CLASS: u8 = 0x0B;
-pub const OFFSET_HEADER_TYPE: u8 = 0x0E;
-pub const OFFSET_BIST: u8 = 0x0F;
-pub const OFFSET_BAR0: u8 = 0x10;
-pub const OFFSET_BAR1: u8 = 0x14;
-pub const OFFSET_BAR2: u8 = 0x18;
-pub const OFFSET_BAR3: u8 = 0x1C;
-pub const OFFSET_BAR4: u8 = 0x20;
-pub const OFFSET_BAR5: u8 = 0x24;
-pub const OFFSET_INTERRUPT_LINE: u8 = 0x3C;
-pub const OFFSET_INTERRUPT_PIN: u8 = 0x3D;
+pub const OFFSET_VENDOR_ID: u16 = 0x00;
+pub const OFFSET_DEVICE_ID: u16 = 0x02;
+pub const OFFSET_STATUS: u16 = 0x06;
+pub const OFFSET_COMMAND: u16 = 0x04;
+pub const OFFSET_REVISION_ID: u16 = 0x08;
+pub const OFFSET_PROG_IF: u16 = 0x09;
+pub const OFFSET_SUBCLASS: u16 = 0x0A;
+pub const OFFSET_CLASS: u16 = 0x0B;
+pub const OFFSET_HEADER_TYPE: u16 = 0x0E;
+pub const OFFSET_BIST: u16 = 0x0F;
+pub const OFFSET_BAR0: u16 = 0x10;
+pub const OFFSET_BAR1: u16 = 0x14;
+pub const OFFSET_BAR2: u16 = 0x18;
+pub const OFFSET_BAR3: u16 = 0x1C;
+pub const OFFSET_BAR4: u16 = 0x20;
+pub const OFFSET_BAR5: u16 = 0x24;
+pub const OFFSET_SECONDARY_BUS: u16 = 0x19;
+pub const OFFSET_SUBORDINATE_BUS: u16 = 0x1A;
+pub const OFFSET_CAPABILITIES: u16 = 0x34;
+pub const OFFSET_INTERRUPT_LINE: u16 = 0x3C;
+pub const OFFSET_INTERRUPT_PIN: u16 = 0x3D;
 
+/// All functions of all segments, see [`enumerate`].
 pub unsafe fn iterate_all() -> impl Iterator<Item = PciDevice> {
-    (0..=u8::MAX)
-        .flat_map(|bus| (0_u8..32).map(move |slot| (bus, slot)))
-        .flat_map(|(bus, slot)| {
-            match PciDevice::probe(bus, slot, 0) {
-                ProbeResult::Exists => 0_u8..1,
-                ProbeResult::MultiFunction => 0..8,
-                ProbeResult::None => 0..0,
-            }
-            .map(move |function| (bus, slot, function))
-        })
-        .filter_map(
-            |(bus, slot, function)| match PciDevice::probe(bus, slot, function) {
-                ProbeResult::Exists | ProbeResult::MultiFunction => {
-                    Some(PciDevice::new(bus, slot, function))
-                }
-                ProbeResult::None => None,
-            },
-        )
+    // segment 0 is always there, through port I/O if there is no ECAM
+    let mut segments = ecam::segments();
+    if !segments.iter().any(|(segment, _)| *segment == 0) {
+        segments.insert(0, (0, 0));
+    }
+    segments
+        .into_iter()
+        .flat_map(|(segment, root_bus)| enumerate(segment, root_bus, |address| address))
+        .map(|function| unsafe { PciDevice::new(function.address, function.parent) })
 }
 
-pub unsafe fn read_config_double_word(bus: u8, slot: u8, function: u8, offset: u8) -> u32 {
-    read_config_word(bus, slot, function, offset) as u32
-        | ((read_config_word(bus, slot, function, offset + 2) as u32) << 16)
+pub unsafe fn read_config_double_word(address: PciAddress, offset: u16) -> u32 {
+    read_config_word(address, offset) as u32
+        | ((read_config_word(address, offset + 2) as u32) << 16)
 }
 
-pub unsafe fn read_config_word(bus: u8, slot: u8, function: u8, offset: u8) -> u16 {
+pub unsafe fn read_config_word(address: PciAddress, offset: u16) -> u16 {
     #[cfg(debug_assertions)]
     if offset & 1 > 0 {
         panic!("can not read unaligned word, use read_config_half_word instead");
     }
 
+    if let Some(function) = ecam::function(address) {
+        return function.read_config(offset);
+    }
+    // only the first 256 bytes of segment 0 are reachable without ECAM, the
+    // rest reads like a function that doesn't exist
+    if address.segment != 0 || offset > 0xFF {
+        return 0xFFFF;
+    }
+
     let mut config_address = Port::<u32>::new(CONFIG_ADDRESS);
     let mut config_data = Port::<u32>::new(CONFIG_DATA);
-
-    let mut address: u32 = 0;
-    address |= 1 << 31; // enable bit
-    address |= (bus as u32) << 16;
-    address |= (slot as u32) << 11;
-    address |= (function as u32) << 8;
-    address |= (offset as u32) & 0xFC;
-    config_address.write(address);
+    config_address.write(port_address(address, offset));
 
     let i = config_data.read();
     (i >> ((offset & 2) * 8) & 0xFFFF) as u16
 }
 
-pub unsafe fn read_config_half_word(bus: u8, slot: u8, function: u8, offset: u8) -> u8 {
-    let word = read_config_word(bus, slot, function, offset & (!1));
+pub unsafe fn read_config_half_word(address: PciAddress, offset: u16) -> u8 {
+    let word = read_config_word(address, offset & (!1));
     if offset & 1 > 0 {
         return (word >> 8) as u8;
     }
     word as u8
 }
 
-pub unsafe fn write_config_double_word(bus: u8, slot: u8, function: u8, offset: u8, value: u32) {
-    write_config_word(bus, slot, function, offset, value as u16);
-    write_config_word(bus, slot, function, offset + 2, (value >> 16) as u16);
+pub unsafe fn write_config_double_word(address: PciAddress, offset: u16, value: u32) {
+    write_config_word(address, offset, value as u16);
+    write_config_word(address, offset + 2, (value >> 16) as u16);
 }
 
-pub unsafe fn write_config_word(bus: u8, slot: u8, function: u8, offset: u8, value: u16) {
+pub unsafe fn write_config_word(address: PciAddress, offset: u16, value: u16) {
     #[cfg(debug_assertions)]
     if offset & 1 > 0 {
         panic!("can not write unaligned word, use write_config_half_word instead");
     }
 
+    if let Some(mut function) = ecam::function(address) {
+        function.write_config(offset, value);
+        return;
+    }
+    if address.segment != 0 || offset > 0xFF {
+        return;
+    }
+
     let mut config_address = Port::<u32>::new(CONFIG_ADDRESS);
     let mut config_data = Port::<u32>::new(CONFIG_DATA);
-
-    let mut address: u32 = 0;
-    address |= 1 << 31; // enable bit
-    address |= (bus as u32) << 16;
-    address |= (slot as u32) << 11;
-    address |= (function as u32) << 8;
-    address |= (offset as u32) & 0xFC;
-    config_address.write(address);
+    config_address.write(port_address(address, offset));
 
     let mut i = config_data.read();
     i &= !(0xFFFF << ((offset & 2) * 8));
@@ -105,8 +105,8 @@ pub unsafe fn write_config_word(bus: u8, slot: u8, function: u8, offset: u8, val
     config_data.write(i);
 }
 
-pub unsafe fn write_config_half_word(bus: u8, slot: u8, function: u8, offset: u8, value: u8) {
-    let mut word = read_config_word(bus, slot, function, offset & (!1));
+pub unsafe fn write_config_half_word(address: PciAddress, offset: u16, value: u8) {
+    let mut word = read_config_word(address, offset & (!1));
     if offset & 1 > 0 {
         word &= 0x00FF;
         word |= (value as u16) << 8;
@@ -114,5 +114,38 @@ pub unsafe fn write_config_half_word(bus: u8, slot: u8, function: u8, offset: u8
         word &= 0xFF00;
         word |= value as u16;
     }
-    write_config_word(bus, slot, function, offset & (!1), word);
+    write_config_word(address, offset & (!1), word);
+}
+
+/// The value for the address port, which selects the dword at `offset`.
+fn port_address(address: PciAddress, offset: u16) -> u32 {
+    let mut value: u32 = 0;
+    value |= 1 << 31; // enable bit
+    value |= (address.bus as u32) << 16;
+    value |= (address.device as u32) << 11;
+    value |= (address.function as u32) << 8;
+    value |= (offset as u32) & 0xFC;
+    value
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::pci::raw::{port_address, read_config_word, write_config_word};
+    use crate::driver::pci::PciAddress;
+
+    #[kernel_test]
+    fn test_port_io_only_reaches_segment_0() {
+        // there are no ECAM windows for other segments in QEMU, so these
+        // would have to go through port I/O
+        let address = PciAddress::with_segment(1, 0, 0, 0);
+        unsafe {
+            assert_eq!(0xFFFF, read_config_word(address, 0x00));
+            write_config_word(address, 0x04, 0);
+            // the host bridge is there in segment 0
+            assert_ne!(0xFFFF, read_config_word(PciAddress::new(0, 0, 0), 0x00));
+        }
+        assert_eq!(0x8001_0A3C, port_address(PciAddress::new(1, 1, 2), 0x3E));
+    }
 }
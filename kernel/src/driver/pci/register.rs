@@ -2,17 +2,18 @@ use crate::driver::pci::raw::{
     read_config_double_word, read_config_half_word, read_config_word, write_config_double_word,
     write_config_half_word, write_config_word,
 };
+use crate::driver::pci::PciAddress;
 use derive_more::{Deref, DerefMut, From};
 
 macro_rules! impl_reg_ops {
     ($typ:ty, $read_fn:ident, $write_fn:ident) => {
         impl PciRegisterOps for $typ {
-            fn read_pci_register(bus: u8, slot: u8, function: u8, offset: u8) -> Self {
-                unsafe { $read_fn(bus, slot, function, offset) }
+            fn read_pci_register(address: PciAddress, offset: u16) -> Self {
+                unsafe { $read_fn(address, offset) }
             }
 
-            fn write_pci_register(bus: u8, slot: u8, function: u8, offset: u8, value: Self) {
-                unsafe { $write_fn(bus, slot, function, offset, value) }
+            fn write_pci_register(address: PciAddress, offset: u16, value: Self) {
+                unsafe { $write_fn(address, offset, value) }
             }
         }
     };
@@ -77,19 +78,15 @@ impl BaseAddressRegister {
 
 #[derive(Debug)]
 pub struct PciRegister<T> {
-    bus: u8,
-    slot: u8,
-    function: u8,
-    offset: u8,
+    address: PciAddress,
+    offset: u16,
     _output_type: core::marker::PhantomData<T>,
 }
 
 impl<T> PciRegister<T> {
-    pub fn new(bus: u8, slot: u8, function: u8, offset: u8) -> Self {
+    pub fn new(address: PciAddress, offset: u16) -> Self {
         Self {
-            bus,
-            slot,
-            function,
+            address,
             offset,
             _output_type: core::marker::PhantomData,
         }
@@ -97,8 +94,8 @@ impl<T> PciRegister<T> {
 }
 
 pub trait PciRegisterOps {
-    fn read_pci_register(bus: u8, slot: u8, function: u8, offset: u8) -> Self;
-    fn write_pci_register(bus: u8, slot: u8, function: u8, offset: u8, value: Self);
+    fn read_pci_register(address: PciAddress, offset: u16) -> Self;
+    fn write_pci_register(address: PciAddress, offset: u16, value: Self);
 }
 
 impl<T> PciRegister<T>
@@ -106,11 +103,11 @@ where
     T: PciRegisterOps,
 {
     pub fn read(&self) -> T {
-        T::read_pci_register(self.bus, self.slot, self.function, self.offset)
+        T::read_pci_register(self.address, self.offset)
     }
 
     pub fn write(&mut self, value: T) {
-        T::write_pci_register(self.bus, self.slot, self.function, self.offset, value)
+        T::write_pci_register(self.address, self.offset, value)
     }
 
     pub fn update(&mut self, f: impl FnOnce(T) -> T) {
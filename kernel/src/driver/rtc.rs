@@ -0,0 +1,184 @@
+//! The real time clock in the CMOS, which keeps the wall clock time while the
+//! machine is off.
+//!
+//! It only counts whole seconds, so it's read once during boot. After that,
+//! the wall clock time is the time of boot plus the time since then, which
+//! the HPET measures much more precisely.
+
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use foundation::time::Instant;
+use kernel_api::time::{days_from_civil, SECONDS_PER_DAY};
+use log::{info, warn};
+use x86_64::instructions::port::Port;
+
+use crate::time::HpetInstantProvider;
+
+const CMOS_ADDRESS: u16 = 0x70;
+const CMOS_DATA: u16 = 0x71;
+
+const REGISTER_SECONDS: u8 = 0x00;
+const REGISTER_MINUTES: u8 = 0x02;
+const REGISTER_HOURS: u8 = 0x04;
+const REGISTER_DAY: u8 = 0x07;
+const REGISTER_MONTH: u8 = 0x08;
+const REGISTER_YEAR: u8 = 0x09;
+const REGISTER_STATUS_A: u8 = 0x0A;
+const REGISTER_STATUS_B: u8 = 0x0B;
+
+/// Set in status register A while the clock updates its registers.
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status register B if the hours go from 0 to 23.
+const HOURS_24: u8 = 1 << 1;
+/// Set in status register B if the values are binary instead of BCD.
+const BINARY: u8 = 1 << 2;
+/// Set in the hours register for PM in 12 hour mode.
+const PM: u8 = 1 << 7;
+
+/// The wall clock time at boot, and when that was.
+static BOOT_TIME: OnceCell<(Duration, Instant)> = OnceCell::uninit();
+
+pub fn init() {
+    let registers = Registers::read_consistent();
+    let status_b = read_register(REGISTER_STATUS_B);
+    let boot_time = registers.decode(status_b).unwrap_or_else(|| {
+        warn!("the real time clock holds an invalid time: {registers:?}");
+        Duration::ZERO
+    });
+    info!("the wall clock time at boot is {}s", boot_time.as_secs());
+    BOOT_TIME.init_once(|| (boot_time, Instant::now()));
+}
+
+/// The time since the Unix epoch.
+pub fn now() -> Duration {
+    let (boot_time, booted_at) = BOOT_TIME.get().unwrap();
+    *boot_time + booted_at.elapsed()
+}
+
+fn read_register(register: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(CMOS_ADDRESS).write(register);
+        Port::<u8>::new(CMOS_DATA).read()
+    }
+}
+
+/// The raw values of the time registers, in whatever format status register
+/// B says.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Registers {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+}
+
+impl Registers {
+    fn read() -> Self {
+        while read_register(REGISTER_STATUS_A) & UPDATE_IN_PROGRESS != 0 {}
+        Self {
+            seconds: read_register(REGISTER_SECONDS),
+            minutes: read_register(REGISTER_MINUTES),
+            hours: read_register(REGISTER_HOURS),
+            day: read_register(REGISTER_DAY),
+            month: read_register(REGISTER_MONTH),
+            year: read_register(REGISTER_YEAR),
+        }
+    }
+
+    /// Reads until two reads in a row agree, because an update can still
+    /// start while the registers are being read.
+    fn read_consistent() -> Self {
+        let mut registers = Self::read();
+        loop {
+            let again = Self::read();
+            if again == registers {
+                return registers;
+            }
+            registers = again;
+        }
+    }
+
+    /// The time since the Unix epoch. The clock only has two digits for the
+    /// year, which are taken to be in the 21st century. Returns [`None`] if
+    /// the registers don't hold a valid time.
+    fn decode(self, status_b: u8) -> Option<Duration> {
+        let value = |raw: u8| {
+            if status_b & BINARY != 0 {
+                raw
+            } else {
+                (raw >> 4) * 10 + (raw & 0x0F)
+            }
+        };
+        let pm = self.hours & PM != 0;
+        let mut hours = value(self.hours & !PM);
+        if status_b & HOURS_24 == 0 {
+            // 12 AM is midnight, 12 PM is noon
+            hours %= 12;
+            if pm {
+                hours += 12;
+            }
+        }
+        let (seconds, minutes) = (value(self.seconds), value(self.minutes));
+        let (day, month) = (value(self.day), value(self.month));
+        if seconds > 59 || minutes > 59 || hours > 23 {
+            return None;
+        }
+        if !(1..=31).contains(&day) || !(1..=12).contains(&month) {
+            return None;
+        }
+
+        let year = 2000 + i64::from(value(self.year));
+        let days = days_from_civil(year, u32::from(month), u32::from(day));
+        let seconds = days * SECONDS_PER_DAY
+            + i64::from(hours) * 3600
+            + i64::from(minutes) * 60
+            + i64::from(seconds);
+        Some(Duration::from_secs(u64::try_from(seconds).ok()?))
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+
+    #[kernel_test]
+    fn test_decode() {
+        // 2024-02-29 13:05:09
+        let expected = Duration::from_secs(1_709_211_909);
+        let bcd = Registers {
+            seconds: 0x09,
+            minutes: 0x05,
+            hours: 0x13,
+            day: 0x29,
+            month: 0x02,
+            year: 0x24,
+        };
+        assert_eq!(Some(expected), bcd.decode(HOURS_24));
+
+        let binary_12_hours = Registers {
+            seconds: 9,
+            minutes: 5,
+            hours: PM | 1,
+            day: 29,
+            month: 2,
+            year: 24,
+        };
+        assert_eq!(Some(expected), binary_12_hours.decode(BINARY));
+        let midnight = Registers {
+            hours: 12,
+            ..binary_12_hours
+        };
+        assert_eq!(
+            Some(expected - Duration::from_secs(13 * 3600)),
+            midnight.decode(BINARY)
+        );
+
+        let invalid = Registers { month: 0, ..bcd };
+        assert_eq!(None, invalid.decode(HOURS_24));
+    }
+}
@@ -1,5 +1,5 @@
 use crate::arch::idt::{end_of_interrupt, InterruptIndex};
-use crate::driver::pci::{PciDevice, PciDriverDescriptor, PCI_DRIVERS};
+use crate::driver::pci::{PciDevice, PciDriverDescriptor, PciMatch, PCI_DRIVERS};
 use crate::mem::virt::{AllocationStrategy, MapAt};
 use crate::net;
 use crate::process::vmm;
@@ -27,7 +27,10 @@ use x86_64::PhysAddr;
 #[distributed_slice(PCI_DRIVERS)]
 static RTL8239_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
     name: "RTL8139",
-    probe: Rtl8139::probe,
+    matches: &[PciMatch::Device {
+        vendor_id: Rtl8139::VENDOR_ID,
+        device_id: Rtl8139::DEVICE_ID,
+    }],
     init: Rtl8139::init,
 };
 
@@ -0,0 +1,360 @@
+//! A text console on the framebuffer that the firmware set up, which shows
+//! the log where the serial port isn't connected. It owns the screen until
+//! userspace maps the framebuffer, and takes it back when the kernel panics.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use bootloader_api::info::{FrameBuffer, PixelFormat};
+use kernel_api::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use log::{Level, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+const TAB_WIDTH: usize = 8;
+
+const BACKGROUND: u32 = 0x000000;
+const TEXT: u32 = 0xC0C0C0;
+
+static CONSOLE: Mutex<Option<Console<'static>>> = Mutex::new(None);
+
+/// Whether log output goes to the screen.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Starts showing the log on the framebuffer.
+pub fn init(framebuffer: &'static FrameBuffer) {
+    let info = framebuffer.info();
+    let buffer = framebuffer.buffer();
+    let buffer = unsafe {
+        // safety: the bootloader mapped the framebuffer writable, and nothing
+        // else in the kernel accesses it
+        slice::from_raw_parts_mut(buffer.as_ptr().cast_mut(), buffer.len())
+    };
+    let geometry = Geometry {
+        width: info.width,
+        height: info.height,
+        pitch: info.stride * info.bytes_per_pixel,
+        bytes_per_pixel: info.bytes_per_pixel,
+        format: info.pixel_format,
+    };
+    let Some(console) = Console::new(buffer, geometry) else {
+        return;
+    };
+    *CONSOLE.lock() = Some(console);
+    ACTIVE.store(true, Ordering::Release);
+}
+
+/// Stops drawing the log, because userspace draws on the screen now.
+pub fn release() {
+    ACTIVE.store(false, Ordering::Release);
+}
+
+/// Draws the log record, if the console owns the screen. A record that is
+/// logged while another one is drawn, for example by an exception handler,
+/// is dropped rather than deadlocking.
+pub fn log(record: &Record) {
+    // drawing is a lot slower than the serial port, so the screen only shows
+    // what's important
+    if !ACTIVE.load(Ordering::Acquire) || record.level() > Level::Info {
+        return;
+    }
+    interrupts::without_interrupts(|| {
+        if let Some(console) = CONSOLE.try_lock().as_mut().and_then(|c| c.as_mut()) {
+            console.log(record);
+        }
+    });
+}
+
+/// Makes the console show the log again, even if the thread that panicked
+/// was drawing to it.
+///
+/// # Safety
+/// This must only be called from the panic handler. The console may be left
+/// half drawn, so nothing but the panic may use it afterwards.
+pub unsafe fn force_enable() {
+    if CONSOLE.is_locked() {
+        unsafe { CONSOLE.force_unlock() };
+    }
+    ACTIVE.store(true, Ordering::Release);
+}
+
+/// The layout of the framebuffer.
+#[derive(Debug, Copy, Clone)]
+struct Geometry {
+    width: usize,
+    height: usize,
+    pitch: usize,
+    bytes_per_pixel: usize,
+    format: PixelFormat,
+}
+
+impl Geometry {
+    /// The bytes of a pixel with the given `0xRRGGBB` color.
+    fn encode(&self, color: u32) -> [u8; 4] {
+        let [_, r, g, b] = color.to_be_bytes();
+        match self.format {
+            PixelFormat::Bgr => [b, g, r, 0],
+            PixelFormat::U8 => [
+                ((u16::from(r) + u16::from(g) + u16::from(b)) / 3) as u8,
+                0,
+                0,
+                0,
+            ],
+            PixelFormat::Unknown {
+                red_position,
+                green_position,
+                blue_position,
+            } => (u32::from(r) << red_position
+                | u32::from(g) << green_position
+                | u32::from(b) << blue_position)
+                .to_le_bytes(),
+            _ => [r, g, b, 0],
+        }
+    }
+}
+
+/// A grid of characters on the framebuffer. Text is written from the top,
+/// and once the bottom is reached, every new line scrolls the screen up.
+struct Console<'a> {
+    buffer: &'a mut [u8],
+    geometry: Geometry,
+    columns: usize,
+    rows: usize,
+    /// What is shown on the screen, row after row.
+    cells: Vec<char>,
+    column: usize,
+    row: usize,
+    /// The color of the text that is written next.
+    color: u32,
+}
+
+impl<'a> Console<'a> {
+    /// Creates a console that covers the framebuffer and clears it. Returns
+    /// `None` if not even a single character fits on it.
+    fn new(buffer: &'a mut [u8], geometry: Geometry) -> Option<Self> {
+        let columns = geometry.width / GLYPH_WIDTH;
+        let rows = geometry.height / GLYPH_HEIGHT;
+        if columns == 0
+            || rows == 0
+            || !(1..=4).contains(&geometry.bytes_per_pixel)
+            || geometry.pitch < geometry.width * geometry.bytes_per_pixel
+            || buffer.len() < geometry.pitch * geometry.height
+        {
+            return None;
+        }
+        buffer[..geometry.pitch * geometry.height].fill(0);
+        Some(Self {
+            buffer,
+            geometry,
+            columns,
+            rows,
+            cells: vec![' '; columns * rows],
+            column: 0,
+            row: 0,
+            color: TEXT,
+        })
+    }
+
+    /// Writes the record on a line of its own, with its level colored.
+    fn log(&mut self, record: &Record) {
+        if self.column != 0 {
+            self.new_line();
+        }
+        self.color = level_color(record.level());
+        let _ = write!(self, "{:5}", record.level());
+        self.color = TEXT;
+        let _ = writeln!(self, " [{}] {}", record.target(), record.args());
+    }
+
+    /// The text of the given row, without the trailing blanks.
+    #[cfg(feature = "kernel_test")]
+    fn row_text(&self, row: usize) -> alloc::string::String {
+        let cells = &self.cells[row * self.columns..(row + 1) * self.columns];
+        cells
+            .iter()
+            .collect::<alloc::string::String>()
+            .trim_end()
+            .into()
+    }
+
+    fn put(&mut self, c: char) {
+        match c {
+            '\n' => self.new_line(),
+            '\r' => self.column = 0,
+            '\t' => {
+                let next = (self.column / TAB_WIDTH + 1) * TAB_WIDTH;
+                while self.column < next.min(self.columns) {
+                    self.put(' ');
+                }
+            }
+            c => {
+                if self.column == self.columns {
+                    self.new_line();
+                }
+                self.cells[self.row * self.columns + self.column] = c;
+                self.draw(self.column, self.row, c);
+                self.column += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.column = 0;
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll_up();
+        }
+    }
+
+    /// Moves every row up by one, and clears the last one.
+    fn scroll_up(&mut self) {
+        let row_bytes = GLYPH_HEIGHT * self.geometry.pitch;
+        let end = self.rows * row_bytes;
+        self.buffer.copy_within(row_bytes..end, 0);
+        self.buffer[end - row_bytes..end].fill(0);
+        self.cells.copy_within(self.columns.., 0);
+        let last = self.cells.len() - self.columns;
+        self.cells[last..].fill(' ');
+    }
+
+    fn draw(&mut self, column: usize, row: usize, c: char) {
+        let bpp = self.geometry.bytes_per_pixel;
+        let foreground = self.geometry.encode(self.color);
+        let background = self.geometry.encode(BACKGROUND);
+        let x = column * GLYPH_WIDTH * bpp;
+        for (i, bits) in glyph(c).iter().enumerate() {
+            let mut line = [0_u8; GLYPH_WIDTH * 4];
+            for (j, pixel) in line.chunks_exact_mut(bpp).enumerate() {
+                let color = if bits & (0x80 >> j) != 0 {
+                    foreground
+                } else {
+                    background
+                };
+                pixel.copy_from_slice(&color[..bpp]);
+            }
+            let start = (row * GLYPH_HEIGHT + i) * self.geometry.pitch + x;
+            self.buffer[start..start + GLYPH_WIDTH * bpp]
+                .copy_from_slice(&line[..GLYPH_WIDTH * bpp]);
+        }
+    }
+}
+
+impl Write for Console<'_> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        s.chars().for_each(|c| self.put(c));
+        Ok(())
+    }
+}
+
+/// The same colors that the serial log uses.
+fn level_color(level: Level) -> u32 {
+    match level {
+        Level::Error => 0xFF5555,
+        Level::Warn => 0xFFFF55,
+        Level::Info => 0x5555FF,
+        Level::Debug => 0x808080,
+        Level::Trace => 0x606060,
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use bootloader_api::info::PixelFormat;
+    use kernel_api::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+    use kernel_test_framework::kernel_test;
+    use log::{Level, Record};
+
+    use crate::driver::vga::console::{Console, Geometry, CONSOLE, TEXT};
+
+    const GEOMETRY: Geometry = Geometry {
+        width: 10 * GLYPH_WIDTH,
+        height: 3 * GLYPH_HEIGHT,
+        // one pixel of padding on every line
+        pitch: (10 * GLYPH_WIDTH + 1) * 4,
+        bytes_per_pixel: 4,
+        format: PixelFormat::Bgr,
+    };
+
+    #[kernel_test]
+    fn test_console_write() {
+        let mut buffer = vec![0_u8; GEOMETRY.pitch * GEOMETRY.height];
+        let mut console = Console::new(&mut buffer, GEOMETRY).unwrap();
+        console.put('|');
+        console.put('\t');
+        console.put('x');
+        console.put('\r');
+        console.put('-');
+        console.put('\n');
+        console.put('y');
+        assert_eq!("-       x", console.row_text(0));
+        assert_eq!("y", console.row_text(1));
+
+        // the pixels of the glyph are in the text color, in BGR order
+        for (i, bits) in glyph('x').iter().enumerate() {
+            for j in 0..GLYPH_WIDTH {
+                let offset = i * GEOMETRY.pitch + (8 * GLYPH_WIDTH + j) * 4;
+                let expected = if bits & (0x80 >> j) != 0 { 0xC0 } else { 0 };
+                assert_eq!(
+                    [expected, expected, expected, 0],
+                    buffer[offset..offset + 4]
+                );
+            }
+        }
+    }
+
+    #[kernel_test]
+    fn test_console_wrap_and_scroll() {
+        let mut buffer = vec![0_u8; GEOMETRY.pitch * GEOMETRY.height];
+        let mut console = Console::new(&mut buffer, GEOMETRY).unwrap();
+        console.color = 0x123456;
+        for c in "0123456789abc\nline two\nthree".chars() {
+            console.put(c);
+        }
+        assert_eq!("abc", console.row_text(0));
+        assert_eq!("line two", console.row_text(1));
+        assert_eq!("three", console.row_text(2));
+        assert!(buffer[..GEOMETRY.pitch * GEOMETRY.height]
+            .chunks_exact(4)
+            .all(|pixel| pixel == [0, 0, 0, 0] || pixel == [0x56, 0x34, 0x12, 0]));
+    }
+
+    #[kernel_test]
+    fn test_console_rejects_small_framebuffer() {
+        let mut buffer = vec![0_u8; GEOMETRY.pitch * GEOMETRY.height - 1];
+        assert!(Console::new(&mut buffer, GEOMETRY).is_none());
+        let geometry = Geometry {
+            height: GLYPH_HEIGHT - 1,
+            ..GEOMETRY
+        };
+        assert!(Console::new(&mut buffer, geometry).is_none());
+    }
+
+    #[kernel_test]
+    fn test_console_log_marker() {
+        let mut guard = CONSOLE.lock();
+        let Some(console) = guard.as_mut() else {
+            // no framebuffer in this machine
+            return;
+        };
+        let marker = "console marker 7f3a";
+        console.log(
+            &Record::builder()
+                .level(Level::Warn)
+                .target("kernel::test")
+                .args(format_args!("{marker}"))
+                .build(),
+        );
+        let row = console.row;
+        assert_eq!(
+            "WARN  [kernel::test] console marker 7f3a",
+            console.row_text(row - 1)
+        );
+        assert_eq!(TEXT, console.color);
+    }
+}
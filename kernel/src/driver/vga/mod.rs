@@ -1,26 +1,53 @@
-use crate::driver::pci::{PciDevice, PciDriverDescriptor, PCI_DRIVERS};
+use crate::driver::pci::{PciDevice, PciDriverDescriptor, PciMatch, PCI_DRIVERS};
 use alloc::boxed::Box;
 use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
+use bootloader_api::info::{FrameBufferInfo, PixelFormat as BootPixelFormat};
+use bootloader_api::BootInfo;
 use conquer_once::spin::OnceCell;
 use core::alloc::AllocError;
 use core::error::Error;
+use core::slice;
 use foundation::falloc::vec::FVec;
+use kernel_api::pixel::PixelFormat;
+use kernel_api::syscall::FbVarScreenInfo;
 use linkme::distributed_slice;
 use spin::Mutex;
 use thiserror::Error;
 use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
 use x86_64::PhysAddr;
 
+pub mod console;
+
 #[distributed_slice(PCI_DRIVERS)]
 static VGA_DEVICE_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
     name: "VGADevice",
-    probe: VgaDevice::probe,
+    matches: &[PciMatch::Class {
+        class: 0x03,
+        subclass: Some(0x00),
+        prog_if: None,
+    }],
     init: VgaDevice::init,
 };
 
 static VGA_DEVICES: OnceCell<Mutex<FVec<VgaDevice>>> = OnceCell::uninit();
 
+/// The geometry of the framebuffer that the firmware set up for us.
+/// We don't program the VGA device ourselves, so this is the mode it's in.
+static BOOT_FRAMEBUFFER_INFO: OnceCell<FrameBufferInfo> = OnceCell::uninit();
+
+/// Where the bootloader mapped the framebuffer, and how many bytes long the
+/// mapping is.
+static BOOT_FRAMEBUFFER_BUFFER: OnceCell<(usize, usize)> = OnceCell::uninit();
+
+pub fn init(boot_info: &'static BootInfo) {
+    if let Some(fb) = boot_info.framebuffer.as_ref() {
+        BOOT_FRAMEBUFFER_INFO.init_once(|| fb.info());
+        BOOT_FRAMEBUFFER_BUFFER.init_once(|| (fb.buffer().as_ptr() as usize, fb.buffer().len()));
+        console::init(fb);
+    }
+}
+
 fn register_vga_device(device: VgaDevice) -> Result<(), Box<dyn Error>> {
     match devices().lock().try_push(device) {
         Ok(_) => Ok(()),
@@ -40,10 +67,6 @@ pub struct VgaDevice {
 }
 
 impl VgaDevice {
-    fn probe(device: &PciDevice) -> bool {
-        device.class == 0x03 && device.subclass == 0x00
-    }
-
     fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
         let device = device.upgrade().ok_or(AllocError)?;
         register_vga_device(VgaDevice::try_from(device)?)?;
@@ -53,6 +76,47 @@ impl VgaDevice {
     pub fn physical_frames(&self) -> &'_ [PhysFrame] {
         &self.frames
     }
+
+    /// Calls `f` with the memory of the framebuffer, or returns `None` if the
+    /// bootloader didn't set one up. The memory is also mapped by the console
+    /// and by whoever mapped the device, so it may change at any time.
+    pub fn with_buffer<R>(&self, f: impl FnOnce(&mut [u8]) -> R) -> Option<R> {
+        let &(addr, len) = BOOT_FRAMEBUFFER_BUFFER.get()?;
+        // safety: the bootloader mapped the framebuffer writable for us, and
+        // the pixels are plain bytes that anyone may overwrite
+        let buffer = unsafe { slice::from_raw_parts_mut(addr as *mut u8, len) };
+        Some(f(buffer))
+    }
+
+    /// Returns the current geometry of this device, or `None` if it isn't known
+    /// or its pixels are in a format that we can't draw in.
+    pub fn screen_info(&self) -> Option<FbVarScreenInfo> {
+        let info = BOOT_FRAMEBUFFER_INFO.get()?;
+        Some(FbVarScreenInfo {
+            width: info.width as u32,
+            height: info.height as u32,
+            bytes_per_pixel: info.bytes_per_pixel as u32,
+            pitch: (info.stride * info.bytes_per_pixel) as u32,
+            format: pixel_format(info)?,
+        })
+    }
+}
+
+/// The format of the pixels of the framebuffer, as far as we support it.
+fn pixel_format(info: &FrameBufferInfo) -> Option<PixelFormat> {
+    match (info.pixel_format, info.bytes_per_pixel) {
+        (BootPixelFormat::Bgr, 4) => Some(PixelFormat::Bgrx8888),
+        (BootPixelFormat::Rgb, 4) => Some(PixelFormat::Rgbx8888),
+        (
+            BootPixelFormat::Unknown {
+                red_position: 11,
+                green_position: 5,
+                blue_position: 0,
+            },
+            2,
+        ) => Some(PixelFormat::Rgb565),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
@@ -0,0 +1,827 @@
+//! virtio-blk. Requests go through a single queue, and several of them may be
+//! in flight at once. Each request has a slot with the memory that the device
+//! reads from and writes to, and the interrupt of the queue marks the slots
+//! of the requests that the device is done with. Asynchronous reads yield to
+//! their executor while they wait, so that one thread can have many of them
+//! outstanding.
+
+use alloc::boxed::Box;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Debug, Formatter};
+use core::hint::spin_loop;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use core::sync::atomic::{AtomicU8, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use filesystem::BlockDevice;
+use foundation::future::yield_now;
+use foundation::time::Instant;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use linkme::distributed_slice;
+use log::{info, warn};
+use spin::Mutex;
+use thiserror::Error;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::{hlt, interrupts};
+use x86_64::structures::idt::InterruptStackFrame;
+
+use crate::arch::idt;
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::apic;
+use crate::driver::disk;
+use crate::driver::pci::{
+    MsiX, MsiXCapability, MsiXTableEntry, PciDevice, PciDriverDescriptor, PciMatch, PCI_DRIVERS,
+};
+use crate::driver::virtio::queue::{Buffer, Virtqueue, MAX_QUEUE_SIZE};
+use crate::driver::virtio::transport::{map_bar, Transport, ISR_QUEUE, NO_VECTOR};
+use crate::driver::virtio::{DmaMemory, VirtioError, PAGE_SIZE};
+use crate::io::vfs::cache::{Flush, MultiBlock};
+use crate::time::HpetInstantProvider;
+
+const VENDOR_ID: u16 = 0x1AF4;
+/// Modern devices have the device type plus 0x1040 as device ID.
+const DEVICE_ID: u16 = 0x1040 + 2;
+/// The ID of transitional devices, which also speak virtio 1.0.
+const TRANSITIONAL_DEVICE_ID: u16 = 0x1001;
+
+#[distributed_slice(PCI_DRIVERS)]
+static VIRTIO_BLK_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
+    name: "virtio-blk",
+    matches: &[
+        PciMatch::Device {
+            vendor_id: VENDOR_ID,
+            device_id: DEVICE_ID,
+        },
+        PciMatch::Device {
+            vendor_id: VENDOR_ID,
+            device_id: TRANSITIONAL_DEVICE_ID,
+        },
+    ],
+    init,
+};
+
+const REQUEST_QUEUE: u16 = 0;
+
+const FEATURE_SEG_MAX: u64 = 1 << 2;
+const FEATURE_BLK_SIZE: u64 = 1 << 6;
+const FEATURE_FLUSH: u64 = 1 << 9;
+
+const REQUEST_IN: u32 = 0;
+const REQUEST_OUT: u32 = 1;
+const REQUEST_FLUSH: u32 = 4;
+
+const STATUS_OK: u8 = 0;
+const STATUS_IOERR: u8 = 1;
+const STATUS_UNSUPP: u8 = 2;
+
+/// The offsets of the fields of the device configuration.
+const CONFIG_CAPACITY: u64 = 0x00;
+const CONFIG_SEG_MAX: u64 = 0x0C;
+const CONFIG_BLK_SIZE: u64 = 0x14;
+
+/// Requests address the disk in sectors of this size, whatever the block
+/// size of the device is.
+const SECTOR_SIZE: usize = 512;
+
+/// The most bytes that a single request transfers.
+const MAX_TRANSFER: usize = 64 * 1024;
+/// The most requests that are in flight at once.
+const SLOTS: usize = 8;
+/// Where the device finds the header of the request, writes the status to,
+/// and transfers the data from or to, in the memory of a slot.
+const HEADER: usize = 0;
+const STATUS: usize = size_of::<RequestHeader>();
+const DATA: usize = PAGE_SIZE;
+
+const SLOT_FREE: u8 = 0;
+const SLOT_TAKEN: u8 = 1;
+const SLOT_PENDING: u8 = 2;
+const SLOT_DONE: u8 = 3;
+const SLOT_ABORTED: u8 = 4;
+
+/// The most devices whose interrupts are handled.
+const MAX_DISKS: usize = 8;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+static DISKS: [OnceCell<&'static Disk>; MAX_DISKS] = [const { OnceCell::uninit() }; MAX_DISKS];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum VirtioBlkError {
+    #[error("{0}")]
+    Virtio(#[from] VirtioError),
+    #[error("device failed the request")]
+    IoError,
+    #[error("device doesn't support the request")]
+    Unsupported,
+    #[error("device answered with status {0}")]
+    Status(u8),
+    #[error("request was cancelled by a reset of the queue")]
+    Reset,
+    #[error("device has a block size of {0} bytes")]
+    BlockSize(u32),
+}
+
+/// Maps the status byte that the device wrote at the end of a request.
+fn status(byte: u8) -> Result<(), VirtioBlkError> {
+    match byte {
+        STATUS_OK => Ok(()),
+        STATUS_IOERR => Err(VirtioBlkError::IoError),
+        STATUS_UNSUPP => Err(VirtioBlkError::Unsupported),
+        _ => Err(VirtioBlkError::Status(byte)),
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct RequestHeader {
+    kind: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+/// How the device tells about requests that it's done with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Interrupts {
+    /// The queue interrupts with the first entry of the MSI-X table.
+    MsiX,
+    /// The device raises its interrupt line, which may be shared.
+    Legacy,
+    /// Requests are polled.
+    Polled,
+}
+
+impl Interrupts {
+    /// The entry of the MSI-X table that the queue interrupts with.
+    fn queue_vector(self) -> u16 {
+        match self {
+            Interrupts::MsiX => 0,
+            Interrupts::Legacy | Interrupts::Polled => NO_VECTOR,
+        }
+    }
+}
+
+/// The memory of a request, and how far the request got.
+struct Slot {
+    state: AtomicU8,
+    memory: DmaMemory,
+}
+
+/// The queue, and the slot of each request in flight by the descriptor that
+/// it starts with.
+struct Requests {
+    queue: Virtqueue,
+    in_flight: [Option<usize>; MAX_QUEUE_SIZE as usize],
+}
+
+/// A virtio-blk device. It lives for as long as the kernel runs, since the
+/// interrupt handler may access it at any time.
+struct Disk {
+    transport: Transport,
+    features: u64,
+    interrupts: Interrupts,
+    /// The size of a block, in bytes, which is a multiple of the sector size.
+    block_size: usize,
+    block_count: u64,
+    /// The most blocks that a single request transfers.
+    max_blocks: usize,
+    requests: Mutex<Requests>,
+    slots: Vec<Slot>,
+}
+
+fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
+    let device = device.upgrade().ok_or(VirtioError::DeviceDisconnected)?;
+    let mut device = device.lock();
+
+    let transport = Transport::new(&mut device)?;
+    let features = transport.negotiate(FEATURE_SEG_MAX | FEATURE_BLK_SIZE | FEATURE_FLUSH)?;
+    let config = transport
+        .device_config()
+        .ok_or(VirtioError::MissingStructure("device"))?;
+    let read_config = |offset: u64| unsafe { read_volatile((config + offset).as_ptr::<u32>()) };
+    let capacity =
+        u64::from(read_config(CONFIG_CAPACITY)) | u64::from(read_config(CONFIG_CAPACITY + 4)) << 32;
+    let block_size = if features & FEATURE_BLK_SIZE != 0 {
+        read_config(CONFIG_BLK_SIZE)
+    } else {
+        SECTOR_SIZE as u32
+    };
+    if !block_size.is_power_of_two() || !(SECTOR_SIZE..=PAGE_SIZE).contains(&(block_size as usize))
+    {
+        return Err(VirtioBlkError::BlockSize(block_size).into());
+    }
+    let block_size = block_size as usize;
+
+    // drivers are initialized one at a time, so a free slot stays free until
+    // the disk takes it
+    let interrupts = if DISKS.iter().all(OnceCell::is_initialized) {
+        warn!("too many virtio-blk devices, polling instead");
+        Interrupts::Polled
+    } else {
+        enable_interrupts(&mut device, &transport)
+    };
+    let queue = transport.setup_queue(REQUEST_QUEUE, interrupts.queue_vector())?;
+    // the header and the status take a descriptor each, and every page of
+    // data one more
+    let seg_max = if features & FEATURE_SEG_MAX != 0 {
+        read_config(CONFIG_SEG_MAX).max(1) as usize
+    } else {
+        usize::MAX
+    };
+    let segments = seg_max
+        .min(usize::from(queue.size()) - 2)
+        .min(MAX_TRANSFER / PAGE_SIZE);
+    let max_blocks = segments * PAGE_SIZE / block_size;
+
+    let slots = (0..SLOTS)
+        .map(|_| {
+            DmaMemory::allocate("blk request", DATA + MAX_TRANSFER).map(|memory| Slot {
+                state: AtomicU8::new(SLOT_FREE),
+                memory,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    transport.driver_ok();
+
+    let disk: &'static Disk = Box::leak(Box::new(Disk {
+        transport,
+        features,
+        interrupts,
+        block_size,
+        block_count: capacity * SECTOR_SIZE as u64 / block_size as u64,
+        max_blocks,
+        requests: Mutex::new(Requests {
+            queue,
+            in_flight: [None; MAX_QUEUE_SIZE as usize],
+        }),
+        slots,
+    }));
+    if interrupts != Interrupts::Polled {
+        for slot in &DISKS {
+            if slot.try_init_once(|| disk).is_ok() {
+                break;
+            }
+        }
+    }
+    info!(
+        "found virtio-blk with {} blocks of {} bytes",
+        disk.block_count, disk.block_size
+    );
+    disk::devices().register(disk::Disk::VirtioBlk(VirtioBlkDevice { disk }))?;
+    Ok(())
+}
+
+/// Makes the queue interrupt, through MSI-X if the device has it, or its
+/// interrupt line otherwise.
+fn enable_interrupts(device: &mut PciDevice, transport: &Transport) -> Interrupts {
+    let Some(vector) = idt::next_free_interrupt_vector() else {
+        warn!("no free interrupt vector for virtio-blk, polling instead");
+        return Interrupts::Polled;
+    };
+    idt::register_interrupt_handler(vector, interrupt_handler);
+    if enable_msix(device, vector) {
+        return Interrupts::MsiX;
+    }
+
+    let irq = device.interrupt_line.read();
+    if irq > 15 {
+        warn!("virtio-blk has neither MSI-X nor an interrupt line, polling instead");
+        return Interrupts::Polled;
+    }
+    if !transport.has_isr() {
+        warn!("virtio-blk has no ISR status, polling instead");
+        return Interrupts::Polled;
+    }
+    if let Err(e) = apic::route_isa_irq(irq, vector) {
+        warn!("failed to route IRQ {irq} of virtio-blk, polling instead: {e}");
+        return Interrupts::Polled;
+    }
+    Interrupts::Legacy
+}
+
+/// Makes the first entry of the MSI-X table deliver to the interrupt vector.
+/// Returns `false` if the device has no usable MSI-X.
+fn enable_msix(device: &mut PciDevice, vector: u8) -> bool {
+    let Some(capability) = MsiXCapability::find(device) else {
+        return false;
+    };
+    let (address, data) = match apic::msi_message(vector) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("can't deliver the interrupts of virtio-blk: {e}");
+            return false;
+        }
+    };
+    let (bar, offset) = capability.table_location();
+    let table = match map_bar(device, bar as u8) {
+        Ok(addr) => addr + offset as u64,
+        Err(e) => {
+            warn!("failed to map the MSI-X table of virtio-blk: {e}");
+            return false;
+        }
+    };
+    let table = unsafe {
+        // safety: the BAR stays mapped for as long as the kernel runs
+        slice::from_raw_parts_mut(
+            table.as_mut_ptr::<MsiXTableEntry>(),
+            capability.table_size(),
+        )
+    };
+    let mut msix = MsiX::new(capability, table);
+    if let Err(e) = msix.set_entry(0, address, data) {
+        warn!("can't set up the interrupt of virtio-blk: {e}");
+        return false;
+    }
+    msix.enable(device);
+    true
+}
+
+/// Completes the requests that the devices are done with.
+extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    for disk in DISKS.iter().filter_map(OnceCell::get) {
+        disk.handle_interrupt();
+    }
+    unsafe { end_of_interrupt() };
+}
+
+impl Disk {
+    fn handle_interrupt(&self) {
+        // the interrupt line is shared, and reading the status lowers it
+        if self.interrupts == Interrupts::Legacy && self.transport.isr_status() & ISR_QUEUE == 0 {
+            return;
+        }
+        self.complete();
+    }
+
+    /// Marks the slots of the requests that the device is done with.
+    fn complete(&self) {
+        self.with_requests(|requests| {
+            while let Some(used) = requests.queue.poll() {
+                if let Some(slot) = requests.in_flight[usize::from(used.head)].take() {
+                    self.slots[slot].state.store(SLOT_DONE, Ordering::Release);
+                }
+            }
+        });
+    }
+
+    /// Locks the requests with interrupts disabled, since the interrupt
+    /// handler locks them too.
+    fn with_requests<R>(&self, f: impl FnOnce(&mut Requests) -> R) -> R {
+        without_interrupts(|| f(&mut self.requests.lock()))
+    }
+
+    fn wait(&self) {
+        if self.interrupts != Interrupts::Polled && interrupts::are_enabled() {
+            hlt();
+        } else {
+            self.complete();
+            spin_loop();
+        }
+    }
+
+    /// Like [`Disk::wait`], but yields to the executor instead of halting, so
+    /// that other requests can be sent in the meantime.
+    async fn wait_async(&self) {
+        if self.interrupts == Interrupts::Polled || !interrupts::are_enabled() {
+            self.complete();
+        }
+        yield_now().await;
+    }
+
+    /// Takes a slot that no request uses, if there is one.
+    fn try_take_slot(&self) -> Option<TakenSlot<'_>> {
+        self.slots
+            .iter()
+            .position(|slot| {
+                slot.state
+                    .compare_exchange(SLOT_FREE, SLOT_TAKEN, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            })
+            .map(|index| TakenSlot { disk: self, index })
+    }
+
+    /// Submits a request whose data is already in the slot. `data` is the
+    /// number of bytes that are transferred. Returns `false` if the queue has
+    /// no room for the request right now.
+    fn try_submit(
+        &self,
+        slot: usize,
+        kind: u32,
+        sector: u64,
+        data: usize,
+    ) -> Result<bool, VirtioBlkError> {
+        let memory = &self.slots[slot].memory;
+        unsafe {
+            write_volatile(
+                (memory.addr() + HEADER as u64).as_mut_ptr::<RequestHeader>(),
+                RequestHeader {
+                    kind,
+                    reserved: 0,
+                    sector,
+                },
+            );
+            write_volatile((memory.addr() + STATUS as u64).as_mut_ptr::<u8>(), 0xFF);
+        }
+        let buffers = [Buffer {
+            addr: memory.phys(HEADER),
+            len: size_of::<RequestHeader>() as u32,
+            writable: false,
+        }]
+        .into_iter()
+        .chain(memory.segments(DATA, data).map(|(addr, len)| Buffer {
+            addr,
+            len,
+            writable: kind == REQUEST_IN,
+        }))
+        .chain([Buffer {
+            addr: memory.phys(STATUS),
+            len: 1,
+            writable: true,
+        }])
+        .collect::<Vec<_>>();
+
+        // the device may complete the request right away, so it must be
+        // known as in flight before it's submitted
+        let submitted = self.with_requests(|requests| {
+            let head = requests.queue.submit(&buffers)?;
+            requests.in_flight[usize::from(head)] = Some(slot);
+            self.slots[slot]
+                .state
+                .store(SLOT_PENDING, Ordering::Release);
+            Ok::<_, VirtioError>(())
+        });
+        match submitted {
+            Ok(()) => Ok(true),
+            Err(VirtioError::QueueFull) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// What became of the submitted request in the slot, or `None` if the
+    /// device is not done with it yet. If the device needs a reset, or the
+    /// request is still not done [`REQUEST_TIMEOUT`] after `start`, the queue
+    /// is reset and the request fails.
+    fn poll_request(&self, slot: usize, start: Instant) -> Option<Result<(), VirtioBlkError>> {
+        let memory = &self.slots[slot].memory;
+        match self.slots[slot].state.load(Ordering::Acquire) {
+            SLOT_DONE => {
+                let byte = unsafe { read_volatile((memory.addr() + STATUS as u64).as_ptr::<u8>()) };
+                return Some(status(byte));
+            }
+            SLOT_ABORTED => return Some(Err(VirtioBlkError::Reset)),
+            _ => {}
+        }
+        let error = if self.transport.needs_reset() {
+            VirtioError::NeedsReset
+        } else if start.elapsed() > REQUEST_TIMEOUT {
+            VirtioError::Timeout
+        } else {
+            return None;
+        };
+        warn!("virtio-blk request failed: {error}, resetting the queue");
+        if let Err(e) = self.reset() {
+            warn!("failed to reset virtio-blk: {e}");
+        }
+        Some(Err(error.into()))
+    }
+
+    /// Takes a slot, sends a request with it and waits for the device to be
+    /// done with it. `before` fills the data of the slot, `after` takes it out
+    /// once the request is done.
+    fn request(
+        &self,
+        kind: u32,
+        sector: u64,
+        data: usize,
+        before: impl FnOnce(&mut [u8]),
+        after: impl FnOnce(&[u8]),
+    ) -> Result<(), VirtioBlkError> {
+        assert!(data <= MAX_TRANSFER);
+        let mut slot = loop {
+            match self.try_take_slot() {
+                Some(slot) => break slot,
+                None => self.wait(),
+            }
+        };
+        before(slot.data(data));
+        let start = Instant::now();
+        while !self.try_submit(slot.index, kind, sector, data)? {
+            if start.elapsed() > REQUEST_TIMEOUT {
+                return Err(VirtioError::QueueFull.into());
+            }
+            self.wait();
+        }
+        let result = loop {
+            match self.poll_request(slot.index, start) {
+                Some(result) => break result,
+                None => self.wait(),
+            }
+        };
+        if result.is_ok() {
+            after(slot.data(data));
+        }
+        result
+    }
+
+    /// Like [`Disk::request`], but yields to the executor while it waits for
+    /// a slot, for room in the queue or for the device.
+    async fn request_async(
+        &self,
+        kind: u32,
+        sector: u64,
+        data: usize,
+        before: impl FnOnce(&mut [u8]),
+        after: impl FnOnce(&[u8]),
+    ) -> Result<(), VirtioBlkError> {
+        assert!(data <= MAX_TRANSFER);
+        let mut slot = loop {
+            match self.try_take_slot() {
+                Some(slot) => break slot,
+                None => self.wait_async().await,
+            }
+        };
+        before(slot.data(data));
+        let start = Instant::now();
+        while !self.try_submit(slot.index, kind, sector, data)? {
+            if start.elapsed() > REQUEST_TIMEOUT {
+                return Err(VirtioError::QueueFull.into());
+            }
+            self.wait_async().await;
+        }
+        let result = loop {
+            match self.poll_request(slot.index, start) {
+                Some(result) => break result,
+                None => self.wait_async().await,
+            }
+        };
+        if result.is_ok() {
+            after(slot.data(data));
+        }
+        result
+    }
+
+    /// Resets the device and sets up the queue again. Requests that are in
+    /// flight fail with [`VirtioBlkError::Reset`].
+    fn reset(&self) -> Result<(), VirtioBlkError> {
+        self.with_requests(|requests| {
+            self.transport.reset();
+            for slot in requests.in_flight.iter_mut().filter_map(Option::take) {
+                self.slots[slot]
+                    .state
+                    .store(SLOT_ABORTED, Ordering::Release);
+            }
+            requests.queue.reset();
+
+            if self.transport.negotiate(self.features)? != self.features {
+                return Err(VirtioError::FeaturesRejected.into());
+            }
+            self.transport
+                .enable_queue(&requests.queue, self.interrupts.queue_vector())?;
+            self.transport.driver_ok();
+            Ok(())
+        })
+    }
+}
+
+/// A slot that a request took. It is freed when it's dropped, which waits for
+/// the device to be done with a request that is still in flight, since the
+/// device may still write into the memory of the slot. That only happens to
+/// asynchronous requests that are dropped before they are done.
+struct TakenSlot<'a> {
+    disk: &'a Disk,
+    index: usize,
+}
+
+impl TakenSlot<'_> {
+    /// The first `len` bytes of the data of the slot.
+    fn data(&mut self, len: usize) -> &mut [u8] {
+        unsafe {
+            // safety: the slot is ours until we free it
+            slice::from_raw_parts_mut(
+                (self.disk.slots[self.index].memory.addr() + DATA as u64).as_mut_ptr::<u8>(),
+                len,
+            )
+        }
+    }
+}
+
+impl Drop for TakenSlot<'_> {
+    fn drop(&mut self) {
+        let state = &self.disk.slots[self.index].state;
+        if state.load(Ordering::Acquire) == SLOT_PENDING {
+            let start = Instant::now();
+            while self.disk.poll_request(self.index, start).is_none() {
+                self.disk.wait();
+            }
+        }
+        state.store(SLOT_FREE, Ordering::Release);
+    }
+}
+
+/// A virtio-blk device.
+#[derive(Clone)]
+pub struct VirtioBlkDevice {
+    disk: &'static Disk,
+}
+
+impl Debug for VirtioBlkDevice {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("VirtioBlkDevice")
+            .field("block_size", &self.disk.block_size)
+            .field("block_count", &self.disk.block_count)
+            .field("interrupts", &self.disk.interrupts)
+            .finish()
+    }
+}
+
+impl VirtioBlkDevice {
+    pub fn block_size(&self) -> usize {
+        self.disk.block_size
+    }
+
+    pub fn block_count(&self) -> u64 {
+        self.disk.block_count
+    }
+
+    /// Whether requests complete through an interrupt rather than by polling.
+    pub fn interrupts(&self) -> bool {
+        self.disk.interrupts != Interrupts::Polled
+    }
+
+    /// Reads as many blocks as fit into the buffer, starting at `start`.
+    pub fn read_blocks_into(&self, start: usize, buf: &mut [u8]) -> Result<usize, VirtioBlkError> {
+        let block_size = self.block_size();
+        assert_eq!(0, buf.len() % block_size);
+        for (i, chunk) in buf.chunks_mut(self.max_blocks() * block_size).enumerate() {
+            let sector = self.sector(start + i * self.max_blocks());
+            self.disk.request(
+                REQUEST_IN,
+                sector,
+                chunk.len(),
+                |_| {},
+                |data| chunk.copy_from_slice(data),
+            )?;
+        }
+        Ok(buf.len())
+    }
+
+    /// Writes the blocks in the buffer, starting at `start`, and flushes
+    /// them, like the IDE driver does.
+    pub fn write_blocks_from(&self, start: usize, buf: &[u8]) -> Result<usize, VirtioBlkError> {
+        let block_size = self.block_size();
+        assert_eq!(0, buf.len() % block_size);
+        for (i, chunk) in buf.chunks(self.max_blocks() * block_size).enumerate() {
+            let sector = self.sector(start + i * self.max_blocks());
+            self.disk.request(
+                REQUEST_OUT,
+                sector,
+                chunk.len(),
+                |data| data.copy_from_slice(chunk),
+                |_| {},
+            )?;
+        }
+        self.flush_write_cache()?;
+        Ok(buf.len())
+    }
+
+    /// Makes written blocks durable. Devices without a write cache write
+    /// through, so there is nothing to flush.
+    pub fn flush_write_cache(&self) -> Result<(), VirtioBlkError> {
+        if self.disk.features & FEATURE_FLUSH == 0 {
+            return Ok(());
+        }
+        self.disk.request(REQUEST_FLUSH, 0, 0, |_| {}, |_| {})
+    }
+
+    /// Resets the device and sets up its queue again, which discards whatever
+    /// the device still has in it. Requests in flight fail with
+    /// [`VirtioBlkError::Reset`].
+    pub fn reset(&self) -> Result<(), VirtioBlkError> {
+        self.disk.reset()
+    }
+
+    fn max_blocks(&self) -> usize {
+        self.disk.max_blocks
+    }
+
+    fn sector(&self, block: usize) -> u64 {
+        (block * (self.block_size() / SECTOR_SIZE)) as u64
+    }
+}
+
+impl BlockDevice for VirtioBlkDevice {
+    type Error = VirtioBlkError;
+
+    fn sector_size(&self) -> usize {
+        self.block_size()
+    }
+
+    fn sector_count(&self) -> usize {
+        self.block_count() as usize
+    }
+
+    fn read_sector(&self, sector: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_blocks_into(sector, buf)
+    }
+
+    fn write_sector(&mut self, sector: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_blocks_from(sector, buf)
+    }
+}
+
+impl MultiBlock for VirtioBlkDevice {
+    /// Reads as many blocks with a single request as the device allows.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let block_size = self.block_size();
+        for (i, chunk) in bufs.chunks_mut(self.max_blocks()).enumerate() {
+            let sector = self.sector(start + i * self.max_blocks());
+            self.disk.request(
+                REQUEST_IN,
+                sector,
+                chunk.len() * block_size,
+                |_| {},
+                |data| {
+                    for (buf, data) in chunk.iter_mut().zip(data.chunks(block_size)) {
+                        buf.copy_from_slice(data);
+                    }
+                },
+            )?;
+        }
+        Ok(bufs.len() * block_size)
+    }
+
+    /// Reads as many blocks with a single request as the device allows, and
+    /// yields to the executor while it waits.
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            let block_size = self.block_size();
+            assert_eq!(0, buf.len() % block_size);
+            for (i, chunk) in buf.chunks_mut(self.max_blocks() * block_size).enumerate() {
+                let sector = self.sector(start + i * self.max_blocks());
+                self.disk
+                    .request_async(
+                        REQUEST_IN,
+                        sector,
+                        chunk.len(),
+                        |_| {},
+                        |data| chunk.copy_from_slice(data),
+                    )
+                    .await?;
+            }
+            Ok(buf.len())
+        }
+        .boxed()
+    }
+
+    /// Writes as many blocks with a single request as the device allows.
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        let block_size = self.block_size();
+        for (i, chunk) in bufs.chunks(self.max_blocks()).enumerate() {
+            let sector = self.sector(start + i * self.max_blocks());
+            self.disk.request(
+                REQUEST_OUT,
+                sector,
+                chunk.len() * block_size,
+                |data| {
+                    for (data, buf) in data.chunks_mut(block_size).zip(chunk) {
+                        data.copy_from_slice(buf);
+                    }
+                },
+                |_| {},
+            )?;
+        }
+        self.flush_write_cache()?;
+        Ok(bufs.len() * block_size)
+    }
+}
+
+impl Flush for VirtioBlkDevice {
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_write_cache()
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::virtio::blk::{status, VirtioBlkError};
+
+    #[kernel_test]
+    fn test_status() {
+        assert_eq!(Ok(()), status(0));
+        assert_eq!(Err(VirtioBlkError::IoError), status(1));
+        assert_eq!(Err(VirtioBlkError::Unsupported), status(2));
+        assert_eq!(Err(VirtioBlkError::Status(0xFF)), status(0xFF));
+    }
+}
@@ -0,0 +1,660 @@
+//! The 2D part of virtio-gpu. The framebuffer is ordinary memory that the
+//! device copies to the host when it's flushed, so a kernel thread flushes
+//! it periodically, and drawing works like with any other framebuffer.
+
+use alloc::boxed::Box;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::ffi::c_void;
+use core::hint::spin_loop;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::slice;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+
+use conquer_once::spin::OnceCell;
+use foundation::time::Instant;
+use kernel_api::pixel::PixelFormat;
+use kernel_api::syscall::FbVarScreenInfo;
+use linkme::distributed_slice;
+use log::{info, warn};
+use spin::Mutex;
+use x86_64::instructions::hlt;
+use x86_64::structures::idt::InterruptStackFrame;
+use x86_64::structures::paging::PhysFrame;
+
+use crate::arch::idt;
+use crate::arch::idt::end_of_interrupt;
+use crate::driver::apic;
+use crate::driver::pci::{
+    MsiX, MsiXCapability, MsiXTableEntry, PciDevice, PciDriverDescriptor, PciMatch, PCI_DRIVERS,
+};
+use crate::driver::virtio::queue::{Buffer, Virtqueue};
+use crate::driver::virtio::transport::{map_bar, Transport, NO_VECTOR};
+use crate::driver::virtio::{DmaMemory, VirtioError, PAGE_SIZE};
+use crate::process;
+use crate::process::Priority;
+use crate::time::HpetInstantProvider;
+
+const VENDOR_ID: u16 = 0x1AF4;
+/// Modern devices have the device type plus 0x1040 as device ID.
+const DEVICE_ID: u16 = 0x1040 + 16;
+
+#[distributed_slice(PCI_DRIVERS)]
+static VIRTIO_GPU_DRIVER: PciDriverDescriptor = PciDriverDescriptor {
+    name: "virtio-gpu",
+    matches: &[PciMatch::Device {
+        vendor_id: VENDOR_ID,
+        device_id: DEVICE_ID,
+    }],
+    init,
+};
+
+const CONTROL_QUEUE: u16 = 0;
+
+const CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+const CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+const CMD_RESOURCE_UNREF: u32 = 0x0102;
+const CMD_SET_SCANOUT: u32 = 0x0103;
+const CMD_RESOURCE_FLUSH: u32 = 0x0104;
+const CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+const CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+
+const RESP_OK_NODATA: u32 = 0x1100;
+const RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+/// Blue, green and red in this order in memory, which is
+/// [`PixelFormat::Bgrx8888`].
+const FORMAT_B8G8R8X8_UNORM: u32 = 2;
+const BYTES_PER_PIXEL: u32 = 4;
+
+const MAX_SCANOUTS: usize = 16;
+
+/// The offsets of the fields of the device configuration.
+const EVENTS_READ: u64 = 0x00;
+const EVENTS_CLEAR: u64 = 0x04;
+const EVENT_DISPLAY: u32 = 1 << 0;
+
+/// The size of the displays if the device doesn't know better.
+const DEFAULT_WIDTH: u32 = 1024;
+const DEFAULT_HEIGHT: u32 = 768;
+
+/// Enough for a list of backing pages of a 32MiB framebuffer, even if none of
+/// them are contiguous.
+const REQUEST_PAGES: usize = 32;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
+const FLUSH_INTERVAL: Duration = Duration::from_millis(33);
+
+static VIRTIO_GPU_DEVICES: OnceCell<Mutex<Vec<Arc<VirtioGpu>>>> = OnceCell::uninit();
+
+/// Set by the interrupt of a configuration change, until the flusher thread
+/// deals with it.
+static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+
+pub fn devices() -> &'static Mutex<Vec<Arc<VirtioGpu>>> {
+    VIRTIO_GPU_DEVICES.get_or_init(Mutex::default)
+}
+
+/// A rectangle on a scanout, in pixels.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Header {
+    kind: u32,
+    flags: u32,
+    fence_id: u64,
+    context_id: u32,
+    ring_index: u8,
+    padding: [u8; 3],
+}
+
+impl Header {
+    fn new(kind: u32) -> Self {
+        Self {
+            kind,
+            ..Default::default()
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct Display {
+    rect: Rect,
+    enabled: u32,
+    flags: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct DisplayInfo {
+    header: Header,
+    displays: [Display; MAX_SCANOUTS],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ResourceCreate2d {
+    header: Header,
+    resource_id: u32,
+    format: u32,
+    width: u32,
+    height: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ResourceUnref {
+    header: Header,
+    resource_id: u32,
+    padding: u32,
+}
+
+/// Followed by as many [`MemoryEntry`]s as it says.
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ResourceAttachBacking {
+    header: Header,
+    resource_id: u32,
+    entries: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct MemoryEntry {
+    addr: u64,
+    len: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct SetScanout {
+    header: Header,
+    rect: Rect,
+    scanout_id: u32,
+    resource_id: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct TransferToHost2d {
+    header: Header,
+    rect: Rect,
+    offset: u64,
+    resource_id: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct ResourceFlush {
+    header: Header,
+    rect: Rect,
+    resource_id: u32,
+    padding: u32,
+}
+
+/// The control queue, and the memory that requests and responses go
+/// through. There is one request in flight at a time.
+struct Control {
+    queue: Virtqueue,
+    request: DmaMemory,
+    response: DmaMemory,
+}
+
+impl Control {
+    /// Sends the request, along with `extra` bytes that were written into the
+    /// request memory after it, and waits for the response.
+    fn send<T: Copy, R: Copy + Default>(
+        &mut self,
+        transport: &Transport,
+        request: T,
+        extra: usize,
+        expected: u32,
+    ) -> Result<R, VirtioError> {
+        unsafe { write_volatile(self.request.addr().as_mut_ptr::<T>(), request) };
+        let buffers = self
+            .request
+            .segments(0, size_of::<T>() + extra)
+            .map(|(addr, len)| Buffer {
+                addr,
+                len,
+                writable: false,
+            })
+            .chain([Buffer {
+                addr: self.response.phys(0),
+                len: size_of::<R>() as u32,
+                writable: true,
+            }])
+            .collect::<Vec<_>>();
+        self.queue.submit(&buffers)?;
+
+        let start = Instant::now();
+        while self.queue.poll().is_none() {
+            if transport.needs_reset() {
+                return Err(VirtioError::NeedsReset);
+            }
+            if start.elapsed() > REQUEST_TIMEOUT {
+                return Err(VirtioError::Timeout);
+            }
+            spin_loop();
+        }
+
+        let header = unsafe { read_volatile(self.response.addr().as_ptr::<Header>()) };
+        if header.kind != expected {
+            return Err(VirtioError::Request(header.kind));
+        }
+        Ok(unsafe { read_volatile(self.response.addr().as_ptr::<R>()) })
+    }
+
+    /// Sends a request that is answered without data.
+    fn command<T: Copy>(&mut self, transport: &Transport, request: T) -> Result<(), VirtioError> {
+        self.send::<T, Header>(transport, request, 0, RESP_OK_NODATA)
+            .map(|_| ())
+    }
+}
+
+/// What is shown on the display, and the memory that it comes from.
+struct Scanout {
+    id: u32,
+    resource_id: u32,
+    width: u32,
+    height: u32,
+    memory: DmaMemory,
+}
+
+impl Scanout {
+    fn screen_info(&self) -> FbVarScreenInfo {
+        FbVarScreenInfo {
+            width: self.width,
+            height: self.height,
+            bytes_per_pixel: BYTES_PER_PIXEL,
+            pitch: self.width * BYTES_PER_PIXEL,
+            format: PixelFormat::Bgrx8888,
+        }
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+}
+
+pub struct VirtioGpu {
+    transport: Transport,
+    control: Mutex<Control>,
+    scanout: Mutex<Scanout>,
+    /// Whether configuration changes interrupt, so that they don't have to
+    /// be polled.
+    interrupts: bool,
+    on_resize: Mutex<Option<fn(FbVarScreenInfo)>>,
+}
+
+fn init(device: Weak<Mutex<PciDevice>>) -> Result<(), Box<dyn Error>> {
+    let device = device.upgrade().ok_or(VirtioError::DeviceDisconnected)?;
+    let mut device = device.lock();
+
+    let transport = Transport::new(&mut device)?;
+    transport.negotiate(0)?;
+    let control = Control {
+        queue: transport.setup_queue(CONTROL_QUEUE, NO_VECTOR)?,
+        request: DmaMemory::allocate("gpu request", REQUEST_PAGES * PAGE_SIZE)?,
+        response: DmaMemory::allocate("gpu response", PAGE_SIZE)?,
+    };
+    let interrupts = enable_config_interrupt(&mut device, &transport);
+    if !interrupts {
+        warn!("virtio-gpu has no usable MSI-X, polling for display changes instead");
+    }
+    transport.driver_ok();
+
+    let gpu = VirtioGpu::new(transport, control, interrupts)?;
+    let info = gpu.screen_info();
+    info!(
+        "found virtio-gpu with a {}x{} display",
+        info.width, info.height
+    );
+
+    let mut devices = devices().lock();
+    devices.push(Arc::new(gpu));
+    if devices.len() == 1 {
+        process::spawn_thread_in_current_process(
+            "virtio_gpu_flush",
+            Priority::Normal,
+            flusher,
+            core::ptr::null_mut(),
+        );
+    }
+    Ok(())
+}
+
+/// Makes configuration changes, which are how the device announces a new
+/// display size, interrupt with the first entry of the MSI-X table. Returns
+/// `false` if the device can't interrupt, and changes have to be polled.
+fn enable_config_interrupt(device: &mut PciDevice, transport: &Transport) -> bool {
+    let Some(capability) = MsiXCapability::find(device) else {
+        return false;
+    };
+    let Some(vector) = idt::next_free_interrupt_vector() else {
+        warn!("no free interrupt vector for virtio-gpu");
+        return false;
+    };
+    let (address, data) = match apic::msi_message(vector) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("can't deliver the interrupts of virtio-gpu: {e}");
+            return false;
+        }
+    };
+    let (bar, offset) = capability.table_location();
+    let table = match map_bar(device, bar as u8) {
+        Ok(addr) => addr + offset as u64,
+        Err(e) => {
+            warn!("failed to map the MSI-X table of virtio-gpu: {e}");
+            return false;
+        }
+    };
+    let table = unsafe {
+        // safety: the BAR stays mapped for as long as the kernel runs
+        slice::from_raw_parts_mut(
+            table.as_mut_ptr::<MsiXTableEntry>(),
+            capability.table_size(),
+        )
+    };
+    let mut msix = MsiX::new(capability, table);
+    idt::register_interrupt_handler(vector, interrupt_handler);
+    if let Err(e) = msix.set_entry(0, address, data) {
+        warn!("can't set up the interrupt of virtio-gpu: {e}");
+        return false;
+    }
+    msix.enable(device);
+    transport.set_config_vector(0)
+}
+
+extern "x86-interrupt" fn interrupt_handler(_stack_frame: InterruptStackFrame) {
+    CONFIG_CHANGED.store(true, Ordering::Release);
+    unsafe { end_of_interrupt() };
+}
+
+/// Copies the framebuffers of all devices to the host, and deals with
+/// display changes. The framebuffers are mapped into userspace as ordinary
+/// memory, so there is no way to tell when they were written to.
+extern "C" fn flusher(_: *mut c_void) {
+    loop {
+        let start = Instant::now();
+        while start.elapsed() < FLUSH_INTERVAL {
+            hlt();
+        }
+
+        let changed = CONFIG_CHANGED.swap(false, Ordering::Acquire);
+        let devices = devices().lock().clone();
+        for gpu in devices {
+            if changed || !gpu.interrupts {
+                if let Err(e) = gpu.handle_config_change() {
+                    warn!("failed to handle a display change of virtio-gpu: {e}");
+                }
+            }
+            if let Err(e) = gpu.flush_all() {
+                warn!("failed to flush virtio-gpu: {e}");
+            }
+        }
+    }
+}
+
+impl VirtioGpu {
+    /// Sets up a resource as large as the display, and shows it.
+    fn new(
+        transport: Transport,
+        mut control: Control,
+        interrupts: bool,
+    ) -> Result<Self, VirtioError> {
+        let (id, rect) = preferred_display(&mut control, &transport)?;
+        let scanout = create_scanout(&mut control, &transport, id, rect, 1)?;
+        let gpu = Self {
+            transport,
+            control: Mutex::new(control),
+            scanout: Mutex::new(scanout),
+            interrupts,
+            on_resize: Mutex::new(None),
+        };
+        gpu.flush_all()?;
+        Ok(gpu)
+    }
+
+    /// The current geometry of the framebuffer.
+    pub fn screen_info(&self) -> FbVarScreenInfo {
+        self.scanout.lock().screen_info()
+    }
+
+    /// The memory of the framebuffer, which changes when the display does.
+    pub fn physical_frames(&self) -> Vec<PhysFrame> {
+        self.scanout.lock().memory.frames().to_vec()
+    }
+
+    /// Calls `f` with the pixels of the framebuffer, row after row.
+    pub fn with_framebuffer<R>(&self, f: impl FnOnce(&mut [u32]) -> R) -> R {
+        let scanout = self.scanout.lock();
+        let len = (scanout.width * scanout.height) as usize;
+        let pixels =
+            unsafe { slice::from_raw_parts_mut(scanout.memory.addr().as_mut_ptr::<u32>(), len) };
+        f(pixels)
+    }
+
+    /// Calls `callback` with the new geometry whenever the size of the display
+    /// changes.
+    pub fn on_resize(&self, callback: fn(FbVarScreenInfo)) {
+        *self.on_resize.lock() = Some(callback);
+    }
+
+    /// Copies the rectangle of the framebuffer to the host, and updates it on
+    /// the display.
+    pub fn flush(&self, rect: Rect) -> Result<(), VirtioError> {
+        let scanout = self.scanout.lock();
+        let rect = clip(rect, scanout.bounds());
+        if rect.width == 0 || rect.height == 0 {
+            return Ok(());
+        }
+        let mut control = self.control.lock();
+        control.command(
+            &self.transport,
+            TransferToHost2d {
+                header: Header::new(CMD_TRANSFER_TO_HOST_2D),
+                rect,
+                offset: u64::from(
+                    rect.y * scanout.width * BYTES_PER_PIXEL + rect.x * BYTES_PER_PIXEL,
+                ),
+                resource_id: scanout.resource_id,
+                padding: 0,
+            },
+        )?;
+        control.command(
+            &self.transport,
+            ResourceFlush {
+                header: Header::new(CMD_RESOURCE_FLUSH),
+                rect,
+                resource_id: scanout.resource_id,
+                padding: 0,
+            },
+        )
+    }
+
+    fn flush_all(&self) -> Result<(), VirtioError> {
+        let bounds = self.scanout.lock().bounds();
+        self.flush(bounds)
+    }
+
+    /// Checks whether the display changed, and if its size did, replaces the
+    /// framebuffer with one of the new size. The old memory is not freed,
+    /// since it may still be mapped into a process.
+    fn handle_config_change(&self) -> Result<(), VirtioError> {
+        let Some(config) = self.transport.device_config() else {
+            return Ok(());
+        };
+        let events = unsafe { read_volatile((config + EVENTS_READ).as_ptr::<u32>()) };
+        if events & EVENT_DISPLAY == 0 {
+            return Ok(());
+        }
+        unsafe { write_volatile((config + EVENTS_CLEAR).as_mut_ptr::<u32>(), EVENT_DISPLAY) };
+
+        let mut scanout = self.scanout.lock();
+        let mut control = self.control.lock();
+        let (id, rect) = preferred_display(&mut control, &self.transport)?;
+        if id == scanout.id && rect.width == scanout.width && rect.height == scanout.height {
+            return Ok(());
+        }
+        let resource_id = scanout.resource_id + 1;
+        let new = create_scanout(&mut control, &self.transport, id, rect, resource_id)?;
+        let old = core::mem::replace(&mut *scanout, new);
+        control.command(
+            &self.transport,
+            ResourceUnref {
+                header: Header::new(CMD_RESOURCE_UNREF),
+                resource_id: old.resource_id,
+                padding: 0,
+            },
+        )?;
+
+        let info = scanout.screen_info();
+        info!(
+            "virtio-gpu display changed to {}x{}",
+            info.width, info.height
+        );
+        drop(control);
+        drop(scanout);
+        if let Some(callback) = *self.on_resize.lock() {
+            callback(info);
+        }
+        Ok(())
+    }
+}
+
+/// The first enabled display, or the first one with a default size if none
+/// is enabled.
+fn preferred_display(
+    control: &mut Control,
+    transport: &Transport,
+) -> Result<(u32, Rect), VirtioError> {
+    let info: DisplayInfo = control.send(
+        transport,
+        Header::new(CMD_GET_DISPLAY_INFO),
+        0,
+        RESP_OK_DISPLAY_INFO,
+    )?;
+    Ok(info
+        .displays
+        .iter()
+        .enumerate()
+        .find(|(_, display)| {
+            display.enabled != 0 && display.rect.width != 0 && display.rect.height != 0
+        })
+        .map_or(
+            (0, Rect::new(0, 0, DEFAULT_WIDTH, DEFAULT_HEIGHT)),
+            |(id, display)| (id as u32, display.rect),
+        ))
+}
+
+/// Creates a resource of the size of the display, backs it with memory and
+/// shows it on the display.
+fn create_scanout(
+    control: &mut Control,
+    transport: &Transport,
+    id: u32,
+    rect: Rect,
+    resource_id: u32,
+) -> Result<Scanout, VirtioError> {
+    let (width, height) = (rect.width, rect.height);
+    let len = (width * height * BYTES_PER_PIXEL) as usize;
+    let memory = DmaMemory::allocate("gpu framebuffer", len)?;
+
+    control.command(
+        transport,
+        ResourceCreate2d {
+            header: Header::new(CMD_RESOURCE_CREATE_2D),
+            resource_id,
+            format: FORMAT_B8G8R8X8_UNORM,
+            width,
+            height,
+        },
+    )?;
+
+    // pages that happen to be contiguous share an entry
+    let mut entries = Vec::<MemoryEntry>::new();
+    for (addr, len) in memory.segments(0, len) {
+        match entries.last_mut() {
+            Some(last) if last.addr + u64::from(last.len) == addr => last.len += len,
+            _ => entries.push(MemoryEntry {
+                addr,
+                len,
+                padding: 0,
+            }),
+        }
+    }
+    let header = size_of::<ResourceAttachBacking>();
+    if header + entries.len() * size_of::<MemoryEntry>() > REQUEST_PAGES * PAGE_SIZE {
+        return Err(VirtioError::NoMemory);
+    }
+    let list = (control.request.addr() + header as u64).as_mut_ptr::<MemoryEntry>();
+    for (i, entry) in entries.iter().enumerate() {
+        unsafe { write_volatile(list.add(i), *entry) };
+    }
+    control.send::<_, Header>(
+        transport,
+        ResourceAttachBacking {
+            header: Header::new(CMD_RESOURCE_ATTACH_BACKING),
+            resource_id,
+            entries: entries.len() as u32,
+        },
+        entries.len() * size_of::<MemoryEntry>(),
+        RESP_OK_NODATA,
+    )?;
+
+    control.command(
+        transport,
+        SetScanout {
+            header: Header::new(CMD_SET_SCANOUT),
+            rect: Rect::new(0, 0, width, height),
+            scanout_id: id,
+            resource_id,
+        },
+    )?;
+    Ok(Scanout {
+        id,
+        resource_id,
+        width,
+        height,
+        memory,
+    })
+}
+
+/// Cuts the rectangle to the bounds.
+fn clip(rect: Rect, bounds: Rect) -> Rect {
+    let x = rect.x.min(bounds.width);
+    let y = rect.y.min(bounds.height);
+    Rect::new(
+        x,
+        y,
+        rect.width.min(bounds.width - x),
+        rect.height.min(bounds.height - y),
+    )
+}
@@ -0,0 +1,116 @@
+//! Devices on the virtio bus, which talk to us through the PCI transport of
+//! virtio 1.0 and later. Legacy devices are not supported.
+
+use alloc::format;
+use alloc::vec::Vec;
+use core::ptr::write_bytes;
+
+use thiserror::Error;
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+
+pub mod blk;
+pub mod gpu;
+mod queue;
+mod transport;
+
+const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum VirtioError {
+    #[error("device is not connected")]
+    DeviceDisconnected,
+    #[error("device has no {0} configuration structure")]
+    MissingStructure(&'static str),
+    #[error("device has no memory mapped BAR{0}")]
+    NoBar(u8),
+    #[error("device doesn't support virtio 1.0")]
+    Legacy,
+    #[error("device didn't accept the features")]
+    FeaturesRejected,
+    #[error("device has no queue {0}")]
+    NoQueue(u16),
+    #[error("device didn't accept the interrupt vector of queue {0}")]
+    NoVector(u16),
+    #[error("queue has no room for the request")]
+    QueueFull,
+    #[error("failed to allocate memory")]
+    NoMemory,
+    #[error("request timed out")]
+    Timeout,
+    #[error("request failed with response {0:#x}")]
+    Request(u32),
+    #[error("device needs to be reset")]
+    NeedsReset,
+}
+
+/// Memory that the device reads from or writes to, mapped contiguously into
+/// the kernel for as long as the driver runs.
+///
+/// The pages are allocated one at a time, so they are not contiguous in
+/// physical memory, and the device sees one segment per page.
+pub(crate) struct DmaMemory {
+    addr: VirtAddr,
+    frames: Vec<PhysFrame>,
+}
+
+impl DmaMemory {
+    pub fn allocate(name: &str, len: usize) -> Result<Self, VirtioError> {
+        let mut frames = Vec::with_capacity(len.div_ceil(PAGE_SIZE));
+        while frames.len() < len.div_ceil(PAGE_SIZE) {
+            match PhysicalMemoryManager::allocate_frame() {
+                Some(frame) => frames.push(frame),
+                None => {
+                    frames
+                        .into_iter()
+                        .for_each(PhysicalMemoryManager::deallocate_frame);
+                    return Err(VirtioError::NoMemory);
+                }
+            }
+        }
+        let addr = vmm()
+            .allocate_memory_backed_vmobject(
+                format!("virtio {name}"),
+                MapAt::Anywhere,
+                frames.len() * PAGE_SIZE,
+                AllocationStrategy::MapNow(&frames),
+                PageTableFlags::PRESENT | PageTableFlags::NO_EXECUTE | PageTableFlags::WRITABLE,
+            )
+            .map_err(|_| VirtioError::NoMemory)?;
+        unsafe { write_bytes(addr.as_mut_ptr::<u8>(), 0, frames.len() * PAGE_SIZE) };
+        Ok(Self { addr, frames })
+    }
+
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+
+    pub fn frames(&self) -> &[PhysFrame] {
+        &self.frames
+    }
+
+    /// The physical address of the byte at `offset`.
+    pub fn phys(&self, offset: usize) -> u64 {
+        self.frames[offset / PAGE_SIZE].start_address().as_u64() + (offset % PAGE_SIZE) as u64
+    }
+
+    /// The physical segments of `len` bytes starting at `offset`, which the
+    /// device needs one descriptor each for.
+    pub fn segments(&self, offset: usize, len: usize) -> impl Iterator<Item = (u64, u32)> + '_ {
+        let mut offset = offset;
+        let end = offset + len;
+        core::iter::from_fn(move || {
+            if offset >= end {
+                return None;
+            }
+            let len = (PAGE_SIZE - offset % PAGE_SIZE).min(end - offset);
+            let segment = (self.phys(offset), len as u32);
+            offset += len;
+            Some(segment)
+        })
+    }
+}
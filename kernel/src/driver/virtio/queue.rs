@@ -0,0 +1,260 @@
+use alloc::format;
+use core::ptr::{read_volatile, write_bytes, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+
+use x86_64::VirtAddr;
+
+use crate::driver::virtio::{DmaMemory, VirtioError, PAGE_SIZE};
+
+/// The most entries of a queue, so that all three parts of it fit into a
+/// single page.
+pub const MAX_QUEUE_SIZE: u16 = 64;
+
+const DESCRIPTORS: usize = 0;
+const AVAILABLE: usize = 1024;
+const USED: usize = 2048;
+
+const DESCRIPTOR_NEXT: u16 = 1 << 0;
+const DESCRIPTOR_WRITE: u16 = 1 << 1;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A part of a request. The device reads the parts that aren't writable,
+/// and writes its response into the ones that are, which must come last.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Buffer {
+    pub addr: u64,
+    pub len: u32,
+    pub writable: bool,
+}
+
+/// A request that the device is done with.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Used {
+    /// The descriptor that the request starts with, as returned by
+    /// [`Virtqueue::submit`].
+    pub head: u16,
+    /// How many bytes the device wrote.
+    pub len: u32,
+}
+
+/// A split virtqueue. Requests may be in flight at the same time, as long as
+/// there are enough free descriptors for them.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    memory: DmaMemory,
+    notify: VirtAddr,
+    /// The index in the available ring that the next request goes to.
+    available: u16,
+    /// The index in the used ring of the next request that the device is done
+    /// with.
+    used: u16,
+    /// The first descriptor that no request uses. The free descriptors are
+    /// linked through `next`, like the descriptors of a request.
+    free_head: u16,
+    free: u16,
+    /// The descriptor that follows each descriptor, in a request or in the
+    /// free list.
+    next: [u16; MAX_QUEUE_SIZE as usize],
+    /// The number of descriptors of each request, by its first descriptor.
+    chains: [u16; MAX_QUEUE_SIZE as usize],
+}
+
+impl Virtqueue {
+    pub fn new(index: u16, size: u16, notify: VirtAddr) -> Result<Self, VirtioError> {
+        assert!(size <= MAX_QUEUE_SIZE);
+        Ok(Self {
+            index,
+            size,
+            memory: DmaMemory::allocate(&format!("queue{index}"), PAGE_SIZE)?,
+            notify,
+            available: 0,
+            used: 0,
+            free_head: 0,
+            free: size,
+            next: core::array::from_fn(|i| i as u16 + 1),
+            chains: [0; MAX_QUEUE_SIZE as usize],
+        })
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// The physical addresses of the descriptor table, the available ring and
+    /// the used ring.
+    pub fn addresses(&self) -> (u64, u64, u64) {
+        (
+            self.memory.phys(DESCRIPTORS),
+            self.memory.phys(AVAILABLE),
+            self.memory.phys(USED),
+        )
+    }
+
+    /// Puts the request into the queue and tells the device about it. Returns
+    /// the descriptor that the request starts with, which it is used with.
+    pub fn submit(&mut self, buffers: &[Buffer]) -> Result<u16, VirtioError> {
+        assert!(!buffers.is_empty() && buffers.len() <= usize::from(self.size));
+        if buffers.len() > usize::from(self.free) {
+            return Err(VirtioError::QueueFull);
+        }
+        let head = self.free_head;
+        let descriptors = self.memory.addr().as_mut_ptr::<Descriptor>();
+        let mut current = head;
+        for (i, buffer) in buffers.iter().enumerate() {
+            let last = i == buffers.len() - 1;
+            let next = self.next[usize::from(current)];
+            let descriptor = Descriptor {
+                addr: buffer.addr,
+                len: buffer.len,
+                flags: if buffer.writable { DESCRIPTOR_WRITE } else { 0 }
+                    | if last { 0 } else { DESCRIPTOR_NEXT },
+                next: if last { 0 } else { next },
+            };
+            unsafe { write_volatile(descriptors.add(usize::from(current)), descriptor) };
+            if !last {
+                current = next;
+            }
+        }
+        self.free_head = self.next[usize::from(current)];
+        self.free -= buffers.len() as u16;
+        self.chains[usize::from(head)] = buffers.len() as u16;
+
+        let ring = (self.memory.addr() + AVAILABLE as u64).as_mut_ptr::<u16>();
+        unsafe {
+            // the ring comes after the flags and the index
+            write_volatile(ring.add(2 + usize::from(self.available % self.size)), head);
+            // the device must see the entry before the index that covers it
+            fence(Ordering::SeqCst);
+            self.available = self.available.wrapping_add(1);
+            write_volatile(ring.add(1), self.available);
+            fence(Ordering::SeqCst);
+            write_volatile(self.notify.as_mut_ptr::<u16>(), self.index);
+        }
+        Ok(head)
+    }
+
+    /// Takes the next request that the device is done with, if there is one,
+    /// and frees its descriptors.
+    pub fn poll(&mut self) -> Option<Used> {
+        let used = (self.memory.addr() + USED as u64).as_ptr::<u16>();
+        let index = unsafe { read_volatile(used.add(1)) };
+        if index == self.used {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        // the ring of id and length pairs comes after the flags and the index
+        let (id, len) = unsafe {
+            let entry = used
+                .add(2)
+                .cast::<u32>()
+                .add(2 * usize::from(self.used % self.size));
+            (read_volatile(entry), read_volatile(entry.add(1)))
+        };
+        self.used = self.used.wrapping_add(1);
+
+        let head = id as u16;
+        let chain = self.chains[usize::from(head)];
+        let mut last = head;
+        for _ in 1..chain {
+            last = self.next[usize::from(last)];
+        }
+        self.next[usize::from(last)] = self.free_head;
+        self.free_head = head;
+        self.free += chain;
+        Some(Used { head, len })
+    }
+
+    /// Forgets all requests, for when the device was reset, which starts the
+    /// queue empty.
+    pub fn reset(&mut self) {
+        unsafe { write_bytes(self.memory.addr().as_mut_ptr::<u8>(), 0, PAGE_SIZE) };
+        self.available = 0;
+        self.used = 0;
+        self.free_head = 0;
+        self.free = self.size;
+        self.next = core::array::from_fn(|i| i as u16 + 1);
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::ptr::{read_volatile, write_volatile};
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::virtio::queue::{Buffer, Used, Virtqueue, USED};
+    use crate::driver::virtio::{DmaMemory, VirtioError, PAGE_SIZE};
+
+    const BUFFER: Buffer = Buffer {
+        addr: 0x1000,
+        len: 16,
+        writable: false,
+    };
+
+    /// Does what the device does when it's done with a request.
+    fn complete(queue: &Virtqueue, head: u16, len: u32) {
+        let used = (queue.memory.addr() + USED as u64).as_mut_ptr::<u16>();
+        unsafe {
+            let index = read_volatile(used.add(1));
+            let entry = used
+                .add(2)
+                .cast::<u32>()
+                .add(2 * usize::from(index % queue.size));
+            write_volatile(entry, u32::from(head));
+            write_volatile(entry.add(1), len);
+            write_volatile(used.add(1), index.wrapping_add(1));
+        }
+    }
+
+    #[kernel_test]
+    fn test_requests_in_flight() {
+        // notifications go to ordinary memory
+        let notify = DmaMemory::allocate("test notify", PAGE_SIZE).unwrap();
+        let mut queue = Virtqueue::new(0, 8, notify.addr()).unwrap();
+
+        let first = queue.submit(&[BUFFER; 3]).unwrap();
+        let second = queue.submit(&[BUFFER; 4]).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(1, queue.free);
+        assert_eq!(Err(VirtioError::QueueFull), queue.submit(&[BUFFER; 2]));
+        assert_eq!(None, queue.poll());
+
+        // the device may be done with requests in any order
+        complete(&queue, second, 7);
+        assert_eq!(
+            Some(Used {
+                head: second,
+                len: 7
+            }),
+            queue.poll()
+        );
+        assert_eq!(None, queue.poll());
+        assert_eq!(5, queue.free);
+
+        // the freed descriptors are used again
+        let third = queue.submit(&[BUFFER; 4]).unwrap();
+        assert_eq!(second, third);
+        complete(&queue, first, 0);
+        complete(&queue, third, 1);
+        assert_eq!(Some(first), queue.poll().map(|used| used.head));
+        assert_eq!(Some(third), queue.poll().map(|used| used.head));
+        assert_eq!(8, queue.free);
+
+        queue.reset();
+        assert_eq!(None, queue.poll());
+        assert_eq!(Ok(0), queue.submit(&[BUFFER; 1]));
+    }
+}
@@ -0,0 +1,313 @@
+use alloc::format;
+use alloc::vec::Vec;
+use core::hint::spin_loop;
+use core::ptr::{read_volatile, write_volatile};
+
+use x86_64::structures::paging::{PageSize, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::driver::pci::{Bar, Capability, PciDevice, ReadConfig};
+use crate::driver::virtio::queue::{Virtqueue, MAX_QUEUE_SIZE};
+use crate::driver::virtio::VirtioError;
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::process::vmm;
+
+/// The types of the vendor specific capabilities that point to the
+/// configuration structures. The ISR status structure is only needed without
+/// MSI-X.
+const CAP_COMMON: u8 = 1;
+const CAP_NOTIFY: u8 = 2;
+const CAP_ISR: u8 = 3;
+const CAP_DEVICE: u8 = 4;
+
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const MSIX_CONFIG: usize = 0x10;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_MSIX_VECTOR: usize = 0x1A;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+const STATUS_ACKNOWLEDGE: u8 = 1 << 0;
+const STATUS_DRIVER: u8 = 1 << 1;
+const STATUS_DRIVER_OK: u8 = 1 << 2;
+const STATUS_FEATURES_OK: u8 = 1 << 3;
+const STATUS_NEEDS_RESET: u8 = 1 << 6;
+const STATUS_FAILED: u8 = 1 << 7;
+
+const FEATURE_VERSION_1: u64 = 1 << 32;
+
+/// Set in the ISR status when a queue interrupted.
+pub const ISR_QUEUE: u8 = 1 << 0;
+
+/// Tells the device not to interrupt for a queue or a configuration change.
+pub const NO_VECTOR: u16 = 0xFFFF;
+
+/// Where one of the configuration structures is.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Structure {
+    bar: u8,
+    offset: u32,
+}
+
+/// The configuration structures of a device, which the PCI transport puts
+/// into its BARs.
+pub struct Transport {
+    common: VirtAddr,
+    notify: VirtAddr,
+    notify_multiplier: u32,
+    isr: Option<VirtAddr>,
+    device: Option<VirtAddr>,
+}
+
+impl Transport {
+    /// Finds and maps the configuration structures of the device, and resets
+    /// it.
+    pub fn new(device: &mut PciDevice) -> Result<Self, VirtioError> {
+        device.enable_memory_space();
+        device.enable_bus_mastering();
+
+        let mut structures: [Option<Structure>; 5] = [None; 5];
+        let mut notify_multiplier = 0;
+        let vendor_specific = device
+            .capabilities()
+            .filter_map(|capability| match capability {
+                Capability::VendorSpecific { offset, .. } => Some(offset),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        for offset in vendor_specific {
+            let kind: u8 = device.read_config(offset + 3);
+            let bar: u8 = device.read_config(offset + 4);
+            if !(CAP_COMMON..=CAP_DEVICE).contains(&kind) || bar > 5 {
+                continue;
+            }
+            // the first structure of a type is the preferred one
+            let structure = &mut structures[usize::from(kind)];
+            if structure.is_none() {
+                *structure = Some(Structure {
+                    bar,
+                    offset: device.read_config(offset + 8),
+                });
+                if kind == CAP_NOTIFY {
+                    notify_multiplier = device.read_config(offset + 16);
+                }
+            }
+        }
+
+        let mut bars: [Option<VirtAddr>; 6] = [None; 6];
+        let mut map = |kind: u8, name: &'static str| -> Result<VirtAddr, VirtioError> {
+            let structure =
+                structures[usize::from(kind)].ok_or(VirtioError::MissingStructure(name))?;
+            let bar = match bars[usize::from(structure.bar)] {
+                Some(bar) => bar,
+                None => {
+                    let bar = map_bar(device, structure.bar)?;
+                    bars[usize::from(structure.bar)] = Some(bar);
+                    bar
+                }
+            };
+            Ok(bar + u64::from(structure.offset))
+        };
+        let common = map(CAP_COMMON, "common")?;
+        let notify = map(CAP_NOTIFY, "notification")?;
+        let isr = map(CAP_ISR, "ISR status").ok();
+        // not every device has a device specific structure
+        let device_config = map(CAP_DEVICE, "device").ok();
+
+        let transport = Self {
+            common,
+            notify,
+            notify_multiplier,
+            isr,
+            device: device_config,
+        };
+        transport.reset();
+        Ok(transport)
+    }
+
+    /// The device specific configuration structure, if the device has one.
+    pub fn device_config(&self) -> Option<VirtAddr> {
+        self.device
+    }
+
+    pub fn reset(&self) {
+        self.write8(DEVICE_STATUS, 0);
+        // the reset is done once the status reads back as zero
+        while self.read8(DEVICE_STATUS) != 0 {
+            spin_loop();
+        }
+    }
+
+    /// Acknowledges the device and negotiates the features, of which the
+    /// device gets those in `wanted` that it offers. Returns the features that
+    /// both agreed on.
+    pub fn negotiate(&self, wanted: u64) -> Result<u64, VirtioError> {
+        self.write8(DEVICE_STATUS, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+        let mut offered = 0;
+        for select in 0..2 {
+            self.write32(DEVICE_FEATURE_SELECT, select);
+            offered |= u64::from(self.read32(DEVICE_FEATURE)) << (32 * select);
+        }
+        if offered & FEATURE_VERSION_1 == 0 {
+            self.fail();
+            return Err(VirtioError::Legacy);
+        }
+        let features = offered & (wanted | FEATURE_VERSION_1);
+        for select in 0..2 {
+            self.write32(DRIVER_FEATURE_SELECT, select);
+            self.write32(DRIVER_FEATURE, (features >> (32 * select)) as u32);
+        }
+
+        self.write8(
+            DEVICE_STATUS,
+            STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_FEATURES_OK,
+        );
+        if self.read8(DEVICE_STATUS) & STATUS_FEATURES_OK == 0 {
+            self.fail();
+            return Err(VirtioError::FeaturesRejected);
+        }
+        Ok(features)
+    }
+
+    /// Sets up the queue with the given index, as large as the device and we
+    /// allow. The queue interrupts with the given entry of the MSI-X table,
+    /// or not at all with [`NO_VECTOR`], in which case its requests are
+    /// polled.
+    pub fn setup_queue(&self, index: u16, vector: u16) -> Result<Virtqueue, VirtioError> {
+        self.write16(QUEUE_SELECT, index);
+        let size = self.read16(QUEUE_SIZE);
+        if size == 0 {
+            return Err(VirtioError::NoQueue(index));
+        }
+        let size = size.min(MAX_QUEUE_SIZE);
+        let notify_off = u64::from(self.read16(QUEUE_NOTIFY_OFF));
+        let notify = self.notify + notify_off * u64::from(self.notify_multiplier);
+        let queue = Virtqueue::new(index, size, notify)?;
+        self.enable_queue(&queue, vector)?;
+        Ok(queue)
+    }
+
+    /// Tells the device where the queue is, and enables it. This is also how
+    /// a queue is set up again after a reset of the device.
+    pub fn enable_queue(&self, queue: &Virtqueue, vector: u16) -> Result<(), VirtioError> {
+        let (descriptors, available, used) = queue.addresses();
+        self.write16(QUEUE_SELECT, queue.index());
+        self.write16(QUEUE_SIZE, queue.size());
+        self.write64(QUEUE_DESC, descriptors);
+        self.write64(QUEUE_DRIVER, available);
+        self.write64(QUEUE_DEVICE, used);
+        self.write16(QUEUE_MSIX_VECTOR, vector);
+        // the device answers with NO_VECTOR if it can't use the entry
+        if self.read16(QUEUE_MSIX_VECTOR) != vector {
+            return Err(VirtioError::NoVector(queue.index()));
+        }
+        self.write16(QUEUE_ENABLE, 1);
+        Ok(())
+    }
+
+    /// Makes configuration changes interrupt with the given entry of the
+    /// MSI-X table. Returns whether the device accepted it.
+    pub fn set_config_vector(&self, entry: u16) -> bool {
+        self.write16(MSIX_CONFIG, entry);
+        self.read16(MSIX_CONFIG) == entry
+    }
+
+    /// Tells the device that the driver is ready.
+    pub fn driver_ok(&self) {
+        let status = self.read8(DEVICE_STATUS);
+        self.write8(DEVICE_STATUS, status | STATUS_DRIVER_OK);
+    }
+
+    /// Whether the device ran into an error that it can only recover from by
+    /// being reset.
+    pub fn needs_reset(&self) -> bool {
+        self.read8(DEVICE_STATUS) & STATUS_NEEDS_RESET != 0
+    }
+
+    /// Whether the device has the ISR status structure, which its interrupt
+    /// line needs.
+    pub fn has_isr(&self) -> bool {
+        self.isr.is_some()
+    }
+
+    /// Reads and thereby clears the ISR status, which tells why the device
+    /// raised its interrupt line. Devices without the structure report
+    /// nothing.
+    pub fn isr_status(&self) -> u8 {
+        match self.isr {
+            Some(isr) => unsafe { read_volatile(isr.as_ptr()) },
+            None => 0,
+        }
+    }
+
+    fn fail(&self) {
+        let status = self.read8(DEVICE_STATUS);
+        self.write8(DEVICE_STATUS, status | STATUS_FAILED);
+    }
+
+    fn read8(&self, offset: usize) -> u8 {
+        unsafe { read_volatile((self.common + offset as u64).as_ptr()) }
+    }
+
+    fn read16(&self, offset: usize) -> u16 {
+        unsafe { read_volatile((self.common + offset as u64).as_ptr()) }
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.common + offset as u64).as_ptr()) }
+    }
+
+    fn write8(&self, offset: usize, value: u8) {
+        unsafe { write_volatile((self.common + offset as u64).as_mut_ptr(), value) }
+    }
+
+    fn write16(&self, offset: usize, value: u16) {
+        unsafe { write_volatile((self.common + offset as u64).as_mut_ptr(), value) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.common + offset as u64).as_mut_ptr(), value) }
+    }
+
+    /// 64 bit fields are written as two halves, since the device doesn't
+    /// have to support wider accesses.
+    fn write64(&self, offset: usize, value: u64) {
+        self.write32(offset, value as u32);
+        self.write32(offset + 4, (value >> 32) as u32);
+    }
+}
+
+/// Maps the BAR for as long as the kernel runs.
+pub fn map_bar(device: &mut PciDevice, index: u8) -> Result<VirtAddr, VirtioError> {
+    let name = format!("virtio {device} bar{index}");
+    let Some(Bar::Memory { base, size, .. }) = device.bar(usize::from(index)) else {
+        return Err(VirtioError::NoBar(index));
+    };
+    let size = size.max(Size4KiB::SIZE) as usize;
+    let addr = PhysAddr::try_new(base).map_err(|_| VirtioError::NoBar(index))?;
+
+    let frames = (0..size.div_ceil(Size4KiB::SIZE as usize))
+        .map(|i| PhysFrame::containing_address(addr + i as u64 * Size4KiB::SIZE))
+        .collect::<Vec<_>>();
+    vmm()
+        .allocate_memory_backed_vmobject(
+            name,
+            MapAt::Anywhere,
+            frames.len() * Size4KiB::SIZE as usize,
+            AllocationStrategy::MapNow(&frames),
+            PageTableFlags::PRESENT
+                | PageTableFlags::NO_CACHE
+                | PageTableFlags::NO_EXECUTE
+                | PageTableFlags::WRITABLE,
+        )
+        .map_err(|_| VirtioError::NoMemory)
+}
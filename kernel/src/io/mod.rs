@@ -1,3 +1,4 @@
+pub mod partition;
 pub mod path;
 pub mod socket;
 pub mod vfs;
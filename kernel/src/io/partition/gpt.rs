@@ -0,0 +1,104 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use filesystem::BlockDevice;
+
+use crate::io::partition::PartitionTableError;
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+/// The size of the header that we know, newer revisions may have larger ones.
+const MIN_HEADER_LEN: usize = 92;
+const MIN_ENTRY_LEN: usize = 128;
+/// More entries than any sane table has, so that a broken header doesn't make
+/// us read the whole disk.
+const MAX_ENTRIES: usize = 1024;
+
+/// A used entry of a GUID partition table.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct GptEntry {
+    /// The position of the entry in the table, starting at 1.
+    pub number: usize,
+    pub type_guid: [u8; 16],
+    pub first_lba: u64,
+    /// The last sector of the partition, which belongs to it.
+    pub last_lba: u64,
+}
+
+/// Reads the GPT header in the second sector of the device, and the partition
+/// entries that it points to. Both are validated with their CRC32.
+pub fn read<T>(device: &T) -> Result<Vec<GptEntry>, PartitionTableError>
+where
+    T: BlockDevice,
+{
+    let sector_size = device.sector_size();
+    let mut header = vec![0_u8; sector_size];
+    device
+        .read_sector(1, &mut header)
+        .map_err(|_| PartitionTableError::Read)?;
+    if &header[0..8] != SIGNATURE {
+        return Err(PartitionTableError::NoTable);
+    }
+
+    let header_len = u32_at(&header, 12) as usize;
+    if !(MIN_HEADER_LEN..=sector_size).contains(&header_len) {
+        return Err(PartitionTableError::InvalidHeader);
+    }
+    let header_crc = u32_at(&header, 16);
+    header[16..20].fill(0);
+    if crc32(&header[..header_len]) != header_crc {
+        return Err(PartitionTableError::HeaderChecksum);
+    }
+
+    let entries_lba = u64_at(&header, 72) as usize;
+    let entry_count = u32_at(&header, 80) as usize;
+    let entry_len = u32_at(&header, 84) as usize;
+    let entries_crc = u32_at(&header, 88);
+    if entry_len < MIN_ENTRY_LEN || entry_len % 8 != 0 || entry_count > MAX_ENTRIES {
+        return Err(PartitionTableError::InvalidHeader);
+    }
+
+    let table_len = entry_count * entry_len;
+    let mut table = vec![0_u8; table_len.next_multiple_of(sector_size)];
+    for (i, sector) in table.chunks_mut(sector_size).enumerate() {
+        device
+            .read_sector(entries_lba + i, sector)
+            .map_err(|_| PartitionTableError::Read)?;
+    }
+    let table = &table[..table_len];
+    if crc32(table) != entries_crc {
+        return Err(PartitionTableError::EntriesChecksum);
+    }
+
+    Ok(table
+        .chunks_exact(entry_len)
+        .enumerate()
+        .map(|(i, entry)| GptEntry {
+            number: i + 1,
+            type_guid: entry[0..16].try_into().unwrap(),
+            first_lba: u64_at(entry, 32),
+            last_lba: u64_at(entry, 40),
+        })
+        .filter(|entry| entry.type_guid != [0; 16])
+        .collect())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+}
+
+/// The CRC32 that the GPT uses, which is the one of zlib and Ethernet.
+pub fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0_u32, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
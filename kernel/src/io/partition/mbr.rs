@@ -0,0 +1,48 @@
+/// The partition type of the single partition of a protective MBR, which spans
+/// the disk in front of a GPT.
+pub const PROTECTIVE_TYPE: u8 = 0xEE;
+
+/// Partition types of extended partitions, which contain logical partitions
+/// instead of data.
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+
+const ENTRIES_OFFSET: usize = 446;
+const ENTRY_LEN: usize = 16;
+const SIGNATURE_OFFSET: usize = 510;
+const SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// A used entry of the partition table in a master boot record.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MbrEntry {
+    /// The slot of the entry in the table, starting at 1.
+    pub number: usize,
+    pub partition_type: u8,
+    pub first_sector: u32,
+    pub sector_count: u32,
+}
+
+impl MbrEntry {
+    pub fn is_extended(&self) -> bool {
+        EXTENDED_TYPES.contains(&self.partition_type)
+    }
+}
+
+/// Parses the partition table of the master boot record in the first sector
+/// of a disk. Returns `None` if the sector doesn't end with the boot
+/// signature, and only the used entries otherwise.
+pub fn parse(sector: &[u8]) -> Option<impl Iterator<Item = MbrEntry> + '_> {
+    if sector.get(SIGNATURE_OFFSET..SIGNATURE_OFFSET + 2)? != SIGNATURE {
+        return None;
+    }
+    let entries = sector[ENTRIES_OFFSET..SIGNATURE_OFFSET]
+        .chunks_exact(ENTRY_LEN)
+        .enumerate()
+        .map(|(i, entry)| MbrEntry {
+            number: i + 1,
+            partition_type: entry[4],
+            first_sector: u32::from_le_bytes(entry[8..12].try_into().unwrap()),
+            sector_count: u32::from_le_bytes(entry[12..16].try_into().unwrap()),
+        })
+        .filter(|entry| entry.partition_type != 0 && entry.sector_count != 0);
+    Some(entries)
+}
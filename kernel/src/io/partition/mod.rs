@@ -0,0 +1,432 @@
+//! Partition tables, and block devices for the partitions in them.
+//!
+//! A disk with a GUID partition table starts with a protective MBR, so the
+//! MBR is read first, and the GPT only if the MBR says that there is one.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use filesystem::BlockDevice;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use log::warn;
+use thiserror::Error;
+
+use crate::io::partition::gpt::GptEntry;
+use crate::io::partition::mbr::MbrEntry;
+use crate::io::vfs::cache::{Flush, MultiBlock};
+
+mod gpt;
+mod mbr;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Error)]
+pub enum PartitionTableError {
+    #[error("failed to read the partition table")]
+    Read,
+    #[error("there is no GUID partition table")]
+    NoTable,
+    #[error("the GPT header is invalid")]
+    InvalidHeader,
+    #[error("the checksum of the GPT header doesn't match")]
+    HeaderChecksum,
+    #[error("the checksum of the GPT partition entries doesn't match")]
+    EntriesChecksum,
+}
+
+/// Where a partition is on its disk.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct PartitionExtent {
+    /// The number of the partition in its table, starting at 1. Partitions are
+    /// published as `/dev/blk{disk}p{number}`.
+    pub number: usize,
+    pub first_sector: usize,
+    pub sector_count: usize,
+}
+
+impl From<MbrEntry> for PartitionExtent {
+    fn from(entry: MbrEntry) -> Self {
+        Self {
+            number: entry.number,
+            first_sector: entry.first_sector as usize,
+            sector_count: entry.sector_count as usize,
+        }
+    }
+}
+
+impl From<GptEntry> for PartitionExtent {
+    fn from(entry: GptEntry) -> Self {
+        Self {
+            number: entry.number,
+            first_sector: entry.first_lba as usize,
+            sector_count: entry
+                .last_lba
+                .saturating_add(1)
+                .saturating_sub(entry.first_lba) as usize,
+        }
+    }
+}
+
+/// Reads the partition table of the device. A device without an MBR has no
+/// partitions. If the MBR is a protective one, the GPT must be valid, but if
+/// there is no GPT after all, the MBR entries are used.
+///
+/// Partitions that don't fit on the device or overlap with a partition that
+/// starts before them are left out with a warning.
+pub fn scan<T>(device: &T) -> Result<Vec<PartitionExtent>, PartitionTableError>
+where
+    T: BlockDevice,
+{
+    let mut sector = vec![0_u8; device.sector_size()];
+    device
+        .read_sector(0, &mut sector)
+        .map_err(|_| PartitionTableError::Read)?;
+    let Some(entries) = mbr::parse(&sector) else {
+        return Ok(Vec::new());
+    };
+    let entries = entries.collect::<Vec<_>>();
+
+    let extents = if entries
+        .iter()
+        .any(|entry| entry.partition_type == mbr::PROTECTIVE_TYPE)
+    {
+        match gpt::read(device) {
+            Ok(entries) => entries.into_iter().map(PartitionExtent::from).collect(),
+            Err(PartitionTableError::NoTable) => mbr_extents(entries),
+            Err(e) => return Err(e),
+        }
+    } else {
+        mbr_extents(entries)
+    };
+    Ok(validate(extents, device.sector_count()))
+}
+
+fn mbr_extents(entries: Vec<MbrEntry>) -> Vec<PartitionExtent> {
+    // we don't read the logical partitions in extended partitions yet
+    entries
+        .into_iter()
+        .filter(|entry| !entry.is_extended())
+        .map(PartitionExtent::from)
+        .collect()
+}
+
+fn validate(mut extents: Vec<PartitionExtent>, sector_count: usize) -> Vec<PartitionExtent> {
+    extents.sort_by_key(|extent| extent.first_sector);
+    let mut valid: Vec<PartitionExtent> = Vec::with_capacity(extents.len());
+    for extent in extents {
+        let end = extent.first_sector.checked_add(extent.sector_count);
+        if extent.sector_count == 0 || end.is_none_or(|end| end > sector_count) {
+            warn!("ignoring partition {extent:?}, which doesn't fit on the disk");
+            continue;
+        }
+        if let Some(previous) = valid
+            .iter()
+            .find(|previous| previous.first_sector + previous.sector_count > extent.first_sector)
+        {
+            warn!("ignoring partition {extent:?}, which overlaps with {previous:?}");
+            continue;
+        }
+        valid.push(extent);
+    }
+    valid.sort_by_key(|extent| extent.number);
+    valid
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PartitionError<E> {
+    /// The access is not within the partition.
+    OutOfRange,
+    Device(E),
+}
+
+/// A partition of a block device, which is a block device itself. Its sectors
+/// are numbered from the start of the partition, and it refuses accesses that
+/// are not within the partition, so that they never reach a neighbor.
+#[derive(Debug, Clone)]
+pub struct Partition<T> {
+    device: T,
+    extent: PartitionExtent,
+}
+
+impl<T> Partition<T>
+where
+    T: BlockDevice,
+{
+    pub fn new(device: T, extent: PartitionExtent) -> Self {
+        Self { device, extent }
+    }
+
+    pub fn extent(&self) -> PartitionExtent {
+        self.extent
+    }
+
+    /// Translates the access of `len` bytes at the given sector of the
+    /// partition to the sector of the device.
+    fn translate(&self, sector: usize, len: usize) -> Result<usize, PartitionError<T::Error>> {
+        let sectors = len.div_ceil(self.device.sector_size());
+        match sector.checked_add(sectors) {
+            Some(end) if end <= self.extent.sector_count => Ok(self.extent.first_sector + sector),
+            _ => Err(PartitionError::OutOfRange),
+        }
+    }
+}
+
+impl<T> BlockDevice for Partition<T>
+where
+    T: BlockDevice,
+{
+    type Error = PartitionError<T::Error>;
+
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> usize {
+        self.extent.sector_count
+    }
+
+    fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let sector = self.translate(sector_index, buf.len())?;
+        self.device
+            .read_sector(sector, buf)
+            .map_err(PartitionError::Device)
+    }
+
+    fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        let sector = self.translate(sector_index, buf.len())?;
+        self.device
+            .write_sector(sector, buf)
+            .map_err(PartitionError::Device)
+    }
+}
+
+impl<T> MultiBlock for Partition<T>
+where
+    T: MultiBlock,
+{
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let start = self.translate(start, len)?;
+        self.device
+            .read_blocks(start, bufs)
+            .map_err(PartitionError::Device)
+    }
+
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let start = self.translate(start, len)?;
+        self.device
+            .write_blocks(start, bufs)
+            .map_err(PartitionError::Device)
+    }
+
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            let start = self.translate(start, buf.len())?;
+            self.device
+                .read_blocks_async(start, buf)
+                .await
+                .map_err(PartitionError::Device)
+        }
+        .boxed()
+    }
+}
+
+impl<T> Flush for Partition<T>
+where
+    T: Flush,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.device.flush().map_err(PartitionError::Device)
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use filesystem::BlockDevice;
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+
+    use crate::io::partition::gpt::crc32;
+    use crate::io::partition::{
+        scan, Partition, PartitionError, PartitionExtent, PartitionTableError,
+    };
+
+    const SECTORS: usize = 256;
+    /// The first sector after the protective MBR, the GPT header and a table
+    /// of 128 entries.
+    const FIRST_USABLE: u64 = 34;
+    const BASIC_DATA: [u8; 16] = [
+        0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99,
+        0xC7,
+    ];
+
+    #[derive(Clone)]
+    struct Memory(Arc<Mutex<Vec<u8>>>);
+
+    impl BlockDevice for Memory {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            512
+        }
+
+        fn sector_count(&self) -> usize {
+            self.0.lock().len() / 512
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            let offset = sector_index * 512;
+            buf.copy_from_slice(&self.0.lock()[offset..offset + buf.len()]);
+            Ok(buf.len())
+        }
+
+        fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, ()> {
+            let offset = sector_index * 512;
+            self.0.lock()[offset..offset + buf.len()].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    fn mbr(image: &mut [u8], entries: &[(u8, u32, u32)]) {
+        for (i, &(partition_type, first, count)) in entries.iter().enumerate() {
+            let entry = &mut image[446 + i * 16..446 + (i + 1) * 16];
+            entry[4] = partition_type;
+            entry[8..12].copy_from_slice(&first.to_le_bytes());
+            entry[12..16].copy_from_slice(&count.to_le_bytes());
+        }
+        image[510..512].copy_from_slice(&[0x55, 0xAA]);
+    }
+
+    /// Creates a disk with a protective MBR and a GPT with the given first and
+    /// last sectors of partitions.
+    fn gpt_disk(partitions: &[(u64, u64)]) -> Memory {
+        let mut image = vec![0_u8; SECTORS * 512];
+        mbr(&mut image, &[(0xEE, 1, SECTORS as u32 - 1)]);
+
+        let table = &mut image[2 * 512..FIRST_USABLE as usize * 512];
+        for (i, &(first, last)) in partitions.iter().enumerate() {
+            let entry = &mut table[i * 128..(i + 1) * 128];
+            entry[0..16].copy_from_slice(&BASIC_DATA);
+            entry[16] = i as u8 + 1; // unique guid
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+        }
+        let entries_crc = crc32(table);
+
+        let header = &mut image[512..1024];
+        header[0..8].copy_from_slice(b"EFI PART");
+        header[8..12].copy_from_slice(&0x0001_0000_u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92_u32.to_le_bytes());
+        header[24..32].copy_from_slice(&1_u64.to_le_bytes());
+        header[32..40].copy_from_slice(&(SECTORS as u64 - 1).to_le_bytes());
+        header[40..48].copy_from_slice(&FIRST_USABLE.to_le_bytes());
+        header[48..56].copy_from_slice(&(SECTORS as u64 - 1).to_le_bytes());
+        header[72..80].copy_from_slice(&2_u64.to_le_bytes());
+        header[80..84].copy_from_slice(&128_u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128_u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let header_crc = crc32(&header[..92]);
+        header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+
+        Memory(Arc::new(Mutex::new(image)))
+    }
+
+    fn extent(number: usize, first_sector: usize, sector_count: usize) -> PartitionExtent {
+        PartitionExtent {
+            number,
+            first_sector,
+            sector_count,
+        }
+    }
+
+    #[kernel_test]
+    fn test_crc32() {
+        assert_eq!(0xCBF4_3926, crc32(b"123456789"));
+    }
+
+    #[kernel_test]
+    fn test_gpt_partitions() {
+        // an aligned one, an unaligned one, and one that ends with the disk
+        let disk = gpt_disk(&[(64, 127), (37, 50), (200, SECTORS as u64 - 1)]);
+        assert_eq!(
+            Ok(vec![
+                extent(1, 64, 64),
+                extent(2, 37, 14),
+                extent(3, 200, 56)
+            ]),
+            scan(&disk)
+        );
+    }
+
+    #[kernel_test]
+    fn test_gpt_checksums() {
+        let disk = gpt_disk(&[(64, 127)]);
+        // the type guid of the second entry, which is covered by the entry crc
+        disk.0.lock()[2 * 512 + 128] = 1;
+        assert_eq!(Err(PartitionTableError::EntriesChecksum), scan(&disk));
+
+        let disk = gpt_disk(&[(64, 127)]);
+        // the first usable sector, which is covered by the header crc
+        disk.0.lock()[512 + 40] = 35;
+        assert_eq!(Err(PartitionTableError::HeaderChecksum), scan(&disk));
+    }
+
+    #[kernel_test]
+    fn test_mbr_partitions() {
+        let mut image = vec![0_u8; SECTORS * 512];
+        mbr(
+            &mut image,
+            &[
+                (0x83, 8, 100),
+                (0x05, 108, 20),  // extended
+                (0x83, 100, 20),  // overlaps with the first one
+                (0x83, 200, 100), // doesn't fit
+            ],
+        );
+        let disk = Memory(Arc::new(Mutex::new(image)));
+        assert_eq!(Ok(vec![extent(1, 8, 100)]), scan(&disk));
+
+        // no signature, no partitions
+        let disk = Memory(Arc::new(Mutex::new(vec![0_u8; SECTORS * 512])));
+        assert_eq!(Ok(vec![]), scan(&disk));
+    }
+
+    #[kernel_test]
+    fn test_partition_bounds() {
+        let disk = gpt_disk(&[(40, 49), (50, 59)]);
+        let extents = scan(&disk).unwrap();
+        let mut first = Partition::new(disk.clone(), extents[0]);
+        assert_eq!(10, first.sector_count());
+
+        first.write_sector(9, &[1; 512]).unwrap();
+        assert_eq!([1; 512], disk.0.lock()[49 * 512..50 * 512]);
+
+        // neither a sector after the end, nor a transfer that runs over it
+        assert_eq!(
+            Err(PartitionError::OutOfRange),
+            first.write_sector(10, &[2; 512])
+        );
+        assert_eq!(
+            Err(PartitionError::OutOfRange),
+            first.write_sector(9, &[2; 1024])
+        );
+        let mut buf = [0; 512];
+        assert_eq!(
+            Err(PartitionError::OutOfRange),
+            first.read_sector(usize::MAX, &mut buf)
+        );
+        assert!(disk.0.lock()[50 * 512..60 * 512].iter().all(|&b| b == 0));
+
+        let second = Partition::new(disk, extents[1]);
+        second.read_sector(0, &mut buf).unwrap();
+        assert_eq!([0; 512], buf);
+    }
+}
@@ -1,5 +1,6 @@
 use alloc::borrow::ToOwned;
 use alloc::string::String;
+use alloc::vec::Vec;
 use core::fmt::{Display, Formatter};
 use core::ops::Deref;
 
@@ -44,16 +45,77 @@ impl Path {
         Components::new(self)
     }
 
+    /// Returns whether this path starts at the root.
+    pub fn is_absolute(&self) -> bool {
+        self.inner.starts_with(SEPARATOR)
+    }
+
+    /// Returns the last component of this path if it is a normal component,
+    /// so `None` for `/` and for paths that end in `.` or `..`.
+    pub fn file_name(&self) -> Option<&str> {
+        match self.components().next_back()? {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns this path with `other` appended. If `other` is absolute, it
+    /// replaces this path.
+    pub fn join<P: AsRef<Path>>(&self, other: P) -> OwnedPath {
+        let other = other.as_ref();
+        if other.is_absolute() {
+            return other.into();
+        }
+        let mut result = OwnedPath::from(self);
+        result.push(other);
+        result
+    }
+
+    /// Returns the canonical form of this path. Duplicate and trailing separators
+    /// and `.` components are removed, and `..` removes the previous component.
+    /// This is purely textual, symbolic links are not taken into account.
+    ///
+    /// `..` never goes above the root of an absolute path. Relative paths keep
+    /// leading `..` components, and normalize to `.` if nothing else is left.
+    pub fn normalize(&self) -> OwnedPath {
+        let absolute = self.is_absolute();
+        let mut components = Vec::new();
+        for component in self.components() {
+            match component {
+                Component::RootDir | Component::CurrentDir => {}
+                Component::ParentDir => {
+                    if components.last().is_some_and(|&c| c != "..") {
+                        components.pop();
+                    } else if !absolute {
+                        components.push("..");
+                    }
+                }
+                Component::Normal(name) => components.push(name),
+            }
+        }
+
+        let mut result = match (absolute, components.is_empty()) {
+            (true, _) => OwnedPath::from("/"),
+            (false, true) => OwnedPath::from("."),
+            (false, false) => OwnedPath::new(),
+        };
+        components.into_iter().for_each(|c| result.push(c));
+        result
+    }
+
     pub fn parent(&self) -> Option<&Path> {
         let mut components = self.components();
         let last = components.next_back();
         last.and_then(|p| match p {
             Component::CurrentDir | Component::ParentDir | Component::Normal(_) => {
                 let path = components.as_path();
-                if path.is_empty() {
-                    None
-                } else {
+                if !path.is_empty() {
                     Some(path)
+                } else if self.is_absolute() {
+                    // the parent of a top level component is the root
+                    Some(Path::new("/"))
+                } else {
+                    None
                 }
             }
             _ => None,
@@ -92,3 +154,85 @@ impl AsRef<Path> for String {
         Path::new(self)
     }
 }
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::path::Path;
+
+    #[kernel_test]
+    fn test_normalize() {
+        for (input, expected) in [
+            ("/", "/"),
+            ("//", "/"),
+            ("///", "/"),
+            ("/.", "/"),
+            ("/..", "/"),
+            ("/../..", "/"),
+            ("/../..//./a/..", "/"),
+            ("/a", "/a"),
+            ("/a/", "/a"),
+            ("/a//", "/a"),
+            ("//a//b//", "/a/b"),
+            ("/a/./b", "/a/b"),
+            ("/a/b/.", "/a/b"),
+            ("/a/b/..", "/a"),
+            ("/a/b/../..", "/"),
+            ("/a/b/../../..", "/"),
+            ("/a/../b", "/b"),
+            ("/a/../../b", "/b"),
+            ("/./a/./b/./", "/a/b"),
+            ("/dev/../dev//null", "/dev/null"),
+            ("/a/b/c/../../d", "/a/d"),
+            ("/a/.../b", "/a/.../b"),
+            ("/a/..b/c", "/a/..b/c"),
+            ("/a/.b/c", "/a/.b/c"),
+            ("a", "a"),
+            ("a/", "a"),
+            ("./a", "a"),
+            ("a/..", "."),
+            ("", "."),
+            (".", "."),
+            ("./", "."),
+            ("..", ".."),
+            ("../a", "../a"),
+            ("../../a/..", "../.."),
+            ("a/../..", ".."),
+            ("a/b/../../../c", "../c"),
+        ] {
+            assert_eq!(
+                expected,
+                Path::new(input).normalize().as_str(),
+                "normalizing {input:?}"
+            );
+        }
+    }
+
+    #[kernel_test]
+    fn test_file_name() {
+        assert_eq!(Some("b"), Path::new("/a/b").file_name());
+        assert_eq!(Some("b"), Path::new("/a/b/").file_name());
+        assert_eq!(Some("a"), Path::new("a").file_name());
+        assert_eq!(None, Path::new("/").file_name());
+        assert_eq!(None, Path::new("/a/..").file_name());
+        assert_eq!(None, Path::new("/a/.").file_name());
+    }
+
+    #[kernel_test]
+    fn test_join() {
+        assert_eq!("/a/b", Path::new("/a").join("b").as_str());
+        assert_eq!("/a/b/c", Path::new("/a/").join("b/c").as_str());
+        assert_eq!("/b", Path::new("/a").join("/b").as_str());
+        assert_eq!("/a/../b", Path::new("/a").join("../b").as_str());
+        assert_eq!("/b", Path::new("/a").join("../b").normalize().as_str());
+    }
+
+    #[kernel_test]
+    fn test_parent() {
+        assert_eq!(Some(Path::new("/a")), Path::new("/a/b").parent());
+        assert_eq!(Some(Path::new("/")), Path::new("/a").parent());
+        assert_eq!(None, Path::new("/").parent());
+        assert_eq!(None, Path::new("a").parent());
+    }
+}
@@ -3,6 +3,7 @@ use alloc::string::String;
 use alloc::vec::Vec;
 use core::borrow::Borrow;
 use core::fmt::{Display, Formatter};
+use core::ops::Deref;
 
 #[derive(Ord, PartialOrd, Eq, PartialEq, Clone, Debug)]
 pub struct OwnedPath {
@@ -23,6 +24,14 @@ impl Borrow<Path> for OwnedPath {
     }
 }
 
+impl Deref for OwnedPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Self::Target {
+        self.as_path()
+    }
+}
+
 impl Default for OwnedPath {
     fn default() -> Self {
         Self::new()
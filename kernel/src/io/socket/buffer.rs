@@ -1,11 +1,16 @@
+use alloc::sync::Arc;
 use core::alloc::AllocError;
 use foundation::io::{Read, ReadError, Write, WriteError};
 use foundation::mem::RingBuffer;
 use spin::Mutex;
 
+use crate::process::wait_queue::WaitQueue;
+
 /// A ring buffer for socket communication.
 pub struct SocketBuffer {
     inner: Mutex<RingBuffer<u8>>,
+    /// Woken when data is written to or read from the buffer.
+    waiters: Arc<WaitQueue>,
 }
 
 impl SocketBuffer {
@@ -16,18 +21,31 @@ impl SocketBuffer {
     fn try_with_size(size: usize) -> Result<Self, AllocError> {
         Ok(Self {
             inner: Mutex::new(RingBuffer::try_with_size(size)?),
+            waiters: Arc::new(WaitQueue::new()),
         })
     }
+
+    /// The queue of the threads that wait for the buffer to become readable
+    /// or writable.
+    pub fn wait_queue(&self) -> &Arc<WaitQueue> {
+        &self.waiters
+    }
 }
 
 impl Read<u8> for SocketBuffer {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, ReadError> {
-        self.inner.lock().read(buf)
+        let read = self.inner.lock().read(buf)?;
+        // there is room for writers now
+        self.waiters.wake_all();
+        Ok(read)
     }
 }
 
 impl Write<u8> for SocketBuffer {
     fn write(&mut self, buf: &[u8]) -> Result<usize, WriteError> {
-        self.inner.lock().write(buf)
+        let written = self.inner.lock().write(buf)?;
+        // there is data for readers now
+        self.waiters.wake_all();
+        Ok(written)
     }
 }
@@ -1,146 +1,1044 @@
-use alloc::collections::btree_map::Entry::{Occupied, Vacant};
-use alloc::collections::{BTreeMap, VecDeque};
-use alloc::vec;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::alloc::AllocError;
+use core::hint::spin_loop;
+use core::ops::{Deref, DerefMut, Range};
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
 
 use filesystem::BlockDevice;
-use spin::RwLock;
+use foundation::falloc::vec::FVec;
+use foundation::future::yield_now;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use spin::{Mutex, RwLock};
 
-pub struct CachingBlockDevice<T> {
-    inner: RwLock<Inner<T>>,
+/// A block device that can transfer consecutive blocks with a single request.
+/// Every buffer holds exactly one block, and the buffers are transferred from
+/// or to the blocks starting at `start`, in order.
+///
+/// The default implementations transfer one block at a time, so devices that
+/// can't do better don't need to implement anything.
+///
+/// Block devices are shared between threads and tasks, so they must be
+/// [`Send`] and [`Sync`].
+pub trait MultiBlock: BlockDevice + Send + Sync {
+    /// Reads the blocks starting at `start` into the buffers, and returns the
+    /// number of bytes that were read.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let mut read = 0;
+        for (i, buf) in bufs.iter_mut().enumerate() {
+            read += self.read_sector(start + i, buf)?;
+        }
+        Ok(read)
+    }
+
+    /// Writes the buffers to the blocks starting at `start`, and returns the
+    /// number of bytes that were written.
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        let mut written = 0;
+        for (i, buf) in bufs.iter().enumerate() {
+            written += self.write_sector(start + i, buf)?;
+        }
+        Ok(written)
+    }
+
+    /// Like [`MultiBlock::read_blocks`], but returns a future, so that callers
+    /// that run on an executor don't block while the device reads. `buf` holds
+    /// the consecutive blocks starting at `start`, so its length must be a
+    /// multiple of the block size.
+    ///
+    /// The default implementation performs the synchronous
+    /// [`MultiBlock::read_blocks`] when the future is first polled. Devices
+    /// that can complete requests asynchronously override this.
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            let mut bufs = buf.chunks_mut(self.sector_size()).collect::<Vec<_>>();
+            self.read_blocks(start, &mut bufs)
+        }
+        .boxed()
+    }
+}
+
+/// A block device that may hold written data in a volatile cache, and needs
+/// to be flushed for the data to reach the medium. File systems use this as a
+/// write barrier.
+///
+/// This and [`MultiBlock`] are not part of [`BlockDevice`], because that
+/// trait lives in the `filesystem` crate, which also runs outside the kernel.
+pub trait Flush: MultiBlock {
+    /// Writes all modified data back to the medium, and returns once it is
+    /// there.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+/// A buffer that holds exactly one block of a device. The size of a block is
+/// only known at runtime, for example 512 bytes for most disks, 2048 bytes for
+/// optical media, and 4096 bytes for drives with native 4K sectors.
+pub struct BlockBuf {
+    data: FVec<u8>,
+}
+
+impl BlockBuf {
+    /// Allocates a zeroed buffer for a block of the device, or fails if there
+    /// is no memory for it.
+    pub fn new_for<T: BlockDevice>(device: &T) -> Result<Self, AllocError> {
+        FVec::try_with_len(device.sector_size())
+            .map(|data| Self { data })
+            .map_err(|_| AllocError)
+    }
+}
+
+impl Deref for BlockBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl DerefMut for BlockBuf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.data
+    }
+}
+
+/// Why a [`BlockCache`] couldn't transfer a block.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BlockCacheError<E> {
+    /// The buffer doesn't have the size of a block of the device.
+    BufferSize {
+        expected: usize,
+        actual: usize,
+    },
+    Device(E),
 }
 
-struct CachedSector {
-    dirty: bool,
-    data: Vec<u8>,
+/// A write-back cache for the blocks of a block device.
+///
+/// Blocks are read from the device when they are first accessed, and written
+/// back when they are evicted or the cache is flushed. Runs of consecutive
+/// blocks are read and flushed with a single request to the device. If the cache holds more
+/// than its capacity, the least recently used blocks are evicted. If multiple
+/// threads read the same missing block, only one of them reads it from the
+/// device, and the others wait for it.
+///
+/// Clones share the same cache, so that the file system driver and code that
+/// needs raw access to the device see the same data.
+pub struct BlockCache<T> {
+    inner: Arc<Inner<T>>,
+}
+
+impl<T> Clone for BlockCache<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+/// Counters of a [`BlockCache`] since it was created.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct BlockCacheStats {
+    /// Reads and writes that found their block in the cache.
+    pub hits: u64,
+    /// Reads that had to read their block from the device.
+    pub misses: u64,
+    /// Dirty blocks that were written to the device.
+    pub writebacks: u64,
 }
 
 struct Inner<T> {
-    max_sector_count: usize,
-    device: T,
-    cached_sectors: BTreeMap<usize, CachedSector>,
-    accessed_sectors: VecDeque<usize>,
+    /// Locked after `state` if both are needed.
+    device: RwLock<T>,
+    capacity: usize,
+    state: Mutex<State>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    writebacks: AtomicU64,
 }
 
-impl<T> CachingBlockDevice<T> {
-    pub fn new(device: T, max_sector_count: usize) -> Self {
-        CachingBlockDevice {
-            inner: RwLock::new(Inner {
-                max_sector_count,
-                device,
-                cached_sectors: Default::default(),
-                accessed_sectors: Default::default(),
+#[derive(Default)]
+struct State {
+    blocks: BTreeMap<usize, CachedBlock>,
+    /// The cached blocks by the time of their last access, oldest first.
+    /// Blocks that are being loaded are not in here, so they are never
+    /// evicted.
+    lru: BTreeMap<u64, usize>,
+    clock: u64,
+}
+
+enum CachedBlock {
+    /// The block is being read from the device by another thread.
+    Loading,
+    Ready {
+        data: Vec<u8>,
+        dirty: bool,
+        last_access: u64,
+    },
+}
+
+impl<T> BlockCache<T>
+where
+    T: MultiBlock,
+{
+    /// Creates a cache that holds up to `capacity` blocks of the device.
+    pub fn new(device: T, capacity: usize) -> Self {
+        BlockCache {
+            inner: Arc::new(Inner {
+                device: RwLock::new(device),
+                capacity,
+                state: Mutex::new(State::default()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                writebacks: AtomicU64::new(0),
             }),
         }
     }
+
+    pub fn stats(&self) -> BlockCacheStats {
+        BlockCacheStats {
+            hits: self.inner.hits.load(Relaxed),
+            misses: self.inner.misses.load(Relaxed),
+            writebacks: self.inner.writebacks.load(Relaxed),
+        }
+    }
 }
 
-impl<T> BlockDevice for CachingBlockDevice<T>
+impl<T> BlockCache<T>
 where
-    T: BlockDevice,
+    T: MultiBlock,
 {
-    type Error = T::Error;
+    /// Checks that the buffer holds exactly one block, since the cached
+    /// blocks are copied from and to it as a whole.
+    fn check_len(&self, buf: &[u8]) -> Result<(), BlockCacheError<T::Error>> {
+        let expected = self.sector_size();
+        if buf.len() == expected {
+            Ok(())
+        } else {
+            Err(BlockCacheError::BufferSize {
+                expected,
+                actual: buf.len(),
+            })
+        }
+    }
+}
+
+impl<T> BlockDevice for BlockCache<T>
+where
+    T: MultiBlock,
+{
+    type Error = BlockCacheError<T::Error>;
 
     fn sector_size(&self) -> usize {
-        self.inner.read().device.sector_size()
+        self.inner.device.read().sector_size()
     }
 
     fn sector_count(&self) -> usize {
-        self.inner.read().device.sector_count()
+        self.inner.device.read().sector_count()
     }
 
     fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        let mut inner = self.inner.write();
-        inner.read_sector(sector_index, buf)
+        self.check_len(buf)?;
+        self.inner
+            .read(sector_index, buf)
+            .map_err(BlockCacheError::Device)
     }
 
     fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
-        let mut inner = self.inner.write();
-        inner.write_sector(sector_index, buf)
+        self.check_len(buf)?;
+        self.inner
+            .write(sector_index, buf)
+            .map_err(BlockCacheError::Device)
+    }
+}
+
+impl<T> MultiBlock for BlockCache<T>
+where
+    T: MultiBlock,
+{
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        for buf in bufs.iter() {
+            self.check_len(buf)?;
+        }
+        self.inner
+            .read_blocks(start, bufs)
+            .map_err(BlockCacheError::Device)
+    }
+
+    /// Blocks that are not in the cache are read with
+    /// [`MultiBlock::read_blocks_async`] of the device, and blocks that
+    /// someone else is reading are awaited instead of spun on.
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            let block_size = self.sector_size();
+            for block in buf.chunks(block_size) {
+                self.check_len(block)?;
+            }
+            self.inner
+                .read_blocks_async(start, buf, block_size)
+                .await
+                .map_err(BlockCacheError::Device)
+        }
+        .boxed()
+    }
+}
+
+impl<T> Flush for BlockCache<T>
+where
+    T: Flush,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush().map_err(BlockCacheError::Device)
     }
 }
 
 impl<T> Inner<T>
 where
-    T: BlockDevice,
+    T: MultiBlock,
 {
-    fn read_sector(
-        &mut self,
-        sector_index: usize,
+    fn read(&self, block: usize, buf: &mut [u8]) -> Result<usize, T::Error> {
+        loop {
+            let mut state = self.state.lock();
+            match state.blocks.get(&block) {
+                Some(CachedBlock::Ready { data, .. }) => {
+                    buf.copy_from_slice(data);
+                    state.touch(block);
+                    self.hits.fetch_add(1, Relaxed);
+                    return Ok(buf.len());
+                }
+                Some(CachedBlock::Loading) => {
+                    // someone else is reading the block from the device, so we
+                    // wait for them instead of reading it a second time
+                    drop(state);
+                    spin_loop();
+                }
+                None => {
+                    state.blocks.insert(block, CachedBlock::Loading);
+                    drop(state);
+                    self.misses.fetch_add(1, Relaxed);
+                    let len = buf.len();
+                    self.load(block, &mut [buf])?;
+                    return Ok(len);
+                }
+            }
+        }
+    }
+
+    /// Like [`Inner::read`], but for consecutive blocks. Every run of blocks
+    /// that are not in the cache is read from the device with one request.
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, T::Error> {
+        let mut done = 0;
+        while done < bufs.len() {
+            let block = start + done;
+            let mut state = self.state.lock();
+            match state.blocks.get(&block) {
+                Some(CachedBlock::Ready { data, .. }) => {
+                    bufs[done].copy_from_slice(data);
+                    state.touch(block);
+                    self.hits.fetch_add(1, Relaxed);
+                    done += 1;
+                }
+                Some(CachedBlock::Loading) => {
+                    drop(state);
+                    spin_loop();
+                }
+                None => {
+                    let run = (block..start + bufs.len())
+                        .take_while(|block| !state.blocks.contains_key(block))
+                        .count();
+                    for block in block..block + run {
+                        state.blocks.insert(block, CachedBlock::Loading);
+                    }
+                    drop(state);
+                    self.misses.fetch_add(run as u64, Relaxed);
+                    self.load(block, &mut bufs[done..done + run])?;
+                    done += run;
+                }
+            }
+        }
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
+    }
+
+    /// Reads consecutive blocks that were marked as loading from the device,
+    /// without blocking other accesses to the cache in the meantime.
+    fn load(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<(), T::Error> {
+        let result = self.device.read().read_blocks(start, bufs);
+
+        let mut state = self.state.lock();
+        if let Err(e) = result {
+            for block in start..start + bufs.len() {
+                state.blocks.remove(&block);
+            }
+            return Err(e);
+        }
+        for (i, buf) in bufs.iter().enumerate() {
+            state.insert(start + i, buf.to_vec(), false);
+        }
+        self.evict_if_necessary(&mut state)
+    }
+
+    /// Like [`Inner::read_blocks`], but awaits the device and the blocks that
+    /// others are loading. `buf` holds the blocks, `block_size` bytes each.
+    async fn read_blocks_async(
+        &self,
+        start: usize,
         buf: &mut [u8],
-    ) -> Result<usize, <CachingBlockDevice<T> as BlockDevice>::Error> {
-        if let Vacant(e) = self.cached_sectors.entry(sector_index) {
-            // we don't have the sector in cache, so we need to load it from the device
-            let mut sector = CachedSector {
-                dirty: false,
-                data: vec![0; self.device.sector_size()],
+        block_size: usize,
+    ) -> Result<usize, T::Error> {
+        let count = buf.len() / block_size;
+        let mut done = 0;
+        while done < count {
+            let block = start + done;
+            let mut state = self.state.lock();
+            let missing = match state.blocks.get(&block) {
+                Some(CachedBlock::Ready { data, .. }) => {
+                    buf[done * block_size..(done + 1) * block_size].copy_from_slice(data);
+                    state.touch(block);
+                    self.hits.fetch_add(1, Relaxed);
+                    done += 1;
+                    continue;
+                }
+                Some(CachedBlock::Loading) => None,
+                None => {
+                    let run = (block..start + count)
+                        .take_while(|block| !state.blocks.contains_key(block))
+                        .count();
+                    for block in block..block + run {
+                        state.blocks.insert(block, CachedBlock::Loading);
+                    }
+                    Some(run)
+                }
             };
-            // read it  from the device
-            let n_read = self.device.read_sector(sector_index, &mut sector.data)?;
-            debug_assert_eq!(n_read, sector.data.len());
+            drop(state);
+            match missing {
+                // someone else is reading the block, and they need the
+                // executor to get it
+                None => yield_now().await,
+                Some(run) => {
+                    self.misses.fetch_add(run as u64, Relaxed);
+                    let blocks = &mut buf[done * block_size..(done + run) * block_size];
+                    self.load_async(block, blocks, block_size).await?;
+                    done += run;
+                }
+            }
+        }
+        Ok(buf.len())
+    }
 
-            buf.copy_from_slice(&sector.data);
+    /// Like [`Inner::load`], but awaits the device. The device stays read
+    /// locked until the blocks arrive, so writing blocks back to it waits
+    /// for them.
+    async fn load_async(
+        &self,
+        start: usize,
+        buf: &mut [u8],
+        block_size: usize,
+    ) -> Result<(), T::Error> {
+        // if the future is dropped before the blocks arrive, nobody waits
+        // for them anymore
+        let loading = LoadingMarks {
+            state: &self.state,
+            blocks: start..start + buf.len() / block_size,
+        };
+        let result = self.device.read().read_blocks_async(start, buf).await;
 
-            e.insert(sector);
-            self.accessed_sectors.push_front(sector_index);
+        let mut state = self.state.lock();
+        let result = result.and_then(|_| {
+            for (i, data) in buf.chunks(block_size).enumerate() {
+                state.insert(start + i, data.to_vec(), false);
+            }
+            self.evict_if_necessary(&mut state)
+        });
+        drop(state);
+        // removes the marks of the blocks if the read failed
+        drop(loading);
+        result
+    }
 
-            self.evict_if_necessary()?;
-        } else {
-            // we already have the sector in cache, now move it to the front of the accessed list
-            self.accessed_sectors.retain(|&x| x != sector_index);
-            self.accessed_sectors.push_front(sector_index);
-            // then read it into the read buffer
-            let sector = self.cached_sectors.get(&sector_index).unwrap();
-            buf.copy_from_slice(&sector.data);
+    fn write(&self, block: usize, buf: &[u8]) -> Result<usize, T::Error> {
+        loop {
+            let mut state = self.state.lock();
+            match state.blocks.get_mut(&block) {
+                Some(CachedBlock::Ready { data, dirty, .. }) => {
+                    data.copy_from_slice(buf);
+                    *dirty = true;
+                    state.touch(block);
+                    self.hits.fetch_add(1, Relaxed);
+                }
+                Some(CachedBlock::Loading) => {
+                    // the load would overwrite what we write, so we wait for it
+                    drop(state);
+                    spin_loop();
+                    continue;
+                }
+                None => {
+                    // we overwrite the whole block, so there is no need to read it
+                    state.insert(block, buf.to_vec(), true);
+                }
+            }
+            self.evict_if_necessary(&mut state)?;
+            return Ok(buf.len());
         }
+    }
 
-        Ok(buf.len())
+    /// Writes the dirty blocks back, and then flushes the device, so that the
+    /// blocks have reached the medium when this returns.
+    fn flush(&self) -> Result<(), T::Error>
+    where
+        T: Flush,
+    {
+        let mut state = self.state.lock();
+        let mut device = self.device.write();
+        let dirty = state
+            .blocks
+            .iter()
+            .filter(|(_, cached)| matches!(cached, CachedBlock::Ready { dirty: true, .. }))
+            .map(|(&block, _)| block)
+            .collect::<Vec<_>>();
+        for run in dirty.chunk_by(|a, b| a + 1 == *b) {
+            let bufs = run
+                .iter()
+                .map(|block| match &state.blocks[block] {
+                    CachedBlock::Ready { data, .. } => data.as_slice(),
+                    CachedBlock::Loading => unreachable!("only ready blocks are dirty"),
+                })
+                .collect::<Vec<_>>();
+            device.write_blocks(run[0], &bufs)?;
+            for block in run {
+                if let Some(CachedBlock::Ready { dirty, .. }) = state.blocks.get_mut(block) {
+                    *dirty = false;
+                }
+            }
+            self.writebacks.fetch_add(run.len() as u64, Relaxed);
+        }
+        device.flush()
     }
 
-    fn write_sector(
-        &mut self,
-        sector_index: usize,
-        buf: &[u8],
-    ) -> Result<usize, <CachingBlockDevice<T> as BlockDevice>::Error> {
-        match self.cached_sectors.entry(sector_index) {
-            Vacant(e) => {
-                // The sector is not in the cache, so we create a new one. We don't need to
-                // read it from the device, because we are going to overwrite it anyway.
-                let sector = CachedSector {
-                    dirty: true,
-                    data: buf.to_vec(),
-                };
-                e.insert(sector);
+    /// Evicts the least recently used blocks until the cache is within its
+    /// capacity. Dirty blocks are written back while the state is locked, so
+    /// that nobody reads the stale data from the device in the meantime.
+    fn evict_if_necessary(&self, state: &mut State) -> Result<(), T::Error> {
+        while state.lru.len() > self.capacity {
+            let (last_access, block) = state.lru.pop_first().expect("lru is empty");
+            let Some(CachedBlock::Ready { data, dirty, .. }) = state.blocks.remove(&block) else {
+                unreachable!("only ready blocks are in the lru list");
+            };
+            if dirty {
+                if let Err(e) = self.device.write().write_sector(block, &data) {
+                    // keep the data, maybe the next attempt succeeds
+                    state.blocks.insert(
+                        block,
+                        CachedBlock::Ready {
+                            data,
+                            dirty,
+                            last_access,
+                        },
+                    );
+                    state.lru.insert(last_access, block);
+                    return Err(e);
+                }
+                self.writebacks.fetch_add(1, Relaxed);
             }
-            Occupied(mut e) => {
-                // The sector is already in the cache, so we update it
-                let sector = e.get_mut();
-                sector.data.copy_from_slice(buf);
-                sector.dirty = true;
+        }
+        Ok(())
+    }
+}
+
+/// Blocks that a future marked as loading. The marks that are left when this
+/// is dropped are removed, so that others read the blocks themselves instead
+/// of waiting for a future that will never load them.
+struct LoadingMarks<'a> {
+    state: &'a Mutex<State>,
+    blocks: Range<usize>,
+}
+
+impl Drop for LoadingMarks<'_> {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        for block in self.blocks.clone() {
+            if matches!(state.blocks.get(&block), Some(CachedBlock::Loading)) {
+                state.blocks.remove(&block);
             }
         }
+    }
+}
 
-        // Move the sector to the front of the accessed list
-        self.accessed_sectors.retain(|&x| x != sector_index);
-        self.accessed_sectors.push_front(sector_index);
+impl State {
+    fn next_access(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
 
-        self.evict_if_necessary()?;
+    fn insert(&mut self, block: usize, data: Vec<u8>, dirty: bool) {
+        let last_access = self.next_access();
+        self.blocks.insert(
+            block,
+            CachedBlock::Ready {
+                data,
+                dirty,
+                last_access,
+            },
+        );
+        self.lru.insert(last_access, block);
+    }
 
-        Ok(buf.len())
+    /// Marks the block as the most recently used one.
+    fn touch(&mut self, block: usize) {
+        let now = self.next_access();
+        if let Some(CachedBlock::Ready { last_access, .. }) = self.blocks.get_mut(&block) {
+            self.lru.remove(last_access);
+            *last_access = now;
+            self.lru.insert(now, block);
+        }
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::Relaxed;
+
+    use filesystem::BlockDevice;
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+
+    use kernel_api::syscall::FileMode;
+
+    use crate::driver::ide;
+    use crate::io::path::Path;
+    use crate::io::vfs::cache::{BlockBuf, BlockCache, BlockCacheError, Flush, MultiBlock};
+    use crate::io::vfs::ext2::VirtualExt2Fs;
+    use crate::io::vfs::{vfs, FileSystem, FileType, FsId, MountFlags};
+
+    /// A block device that counts how often it is accessed, and how many
+    /// requests transfer multiple blocks. Clones share the same data and
+    /// counters.
+    #[derive(Clone)]
+    struct Counting<T> {
+        device: T,
+        reads: Arc<AtomicUsize>,
+        writes: Arc<AtomicUsize>,
+        requests: Arc<AtomicUsize>,
+        flushes: Arc<AtomicUsize>,
     }
 
-    fn evict_if_necessary(&mut self) -> Result<(), <CachingBlockDevice<T> as BlockDevice>::Error> {
-        while self.accessed_sectors.len() > self.max_sector_count {
-            let to_remove = self
-                .accessed_sectors
-                .pop_back()
-                .expect("accessed_sectors is empty");
-            let sector = self.cached_sectors.remove(&to_remove).unwrap();
-            if sector.dirty {
-                // If the removed sector is dirty, write it back to the device
-                self.device.write_sector(to_remove, &sector.data)?;
+    impl<T> Counting<T> {
+        fn new(device: T) -> Self {
+            Self {
+                device,
+                reads: Arc::new(AtomicUsize::new(0)),
+                writes: Arc::new(AtomicUsize::new(0)),
+                requests: Arc::new(AtomicUsize::new(0)),
+                flushes: Arc::new(AtomicUsize::new(0)),
             }
         }
-        Ok(())
+    }
+
+    impl<T: BlockDevice> BlockDevice for Counting<T> {
+        type Error = T::Error;
+
+        fn sector_size(&self) -> usize {
+            self.device.sector_size()
+        }
+
+        fn sector_count(&self) -> usize {
+            self.device.sector_count()
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.reads.fetch_add(1, Relaxed);
+            self.device.read_sector(sector_index, buf)
+        }
+
+        fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.writes.fetch_add(1, Relaxed);
+            self.device.write_sector(sector_index, buf)
+        }
+    }
+
+    impl<T: MultiBlock> MultiBlock for Counting<T> {
+        fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+            self.reads.fetch_add(bufs.len(), Relaxed);
+            self.requests.fetch_add(1, Relaxed);
+            self.device.read_blocks(start, bufs)
+        }
+
+        fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+            self.writes.fetch_add(bufs.len(), Relaxed);
+            self.requests.fetch_add(1, Relaxed);
+            self.device.write_blocks(start, bufs)
+        }
+    }
+
+    impl<T: Flush> Flush for Counting<T> {
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.flushes.fetch_add(1, Relaxed);
+            self.device.flush()
+        }
+    }
+
+    #[derive(Clone)]
+    struct Memory(Arc<Mutex<Vec<[u8; 512]>>>);
+
+    impl Memory {
+        fn new(sectors: usize) -> Self {
+            Self(Arc::new(Mutex::new(vec![[0; 512]; sectors])))
+        }
+
+        fn sector(&self, index: usize) -> [u8; 512] {
+            self.0.lock()[index]
+        }
+    }
+
+    impl BlockDevice for Memory {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            512
+        }
+
+        fn sector_count(&self) -> usize {
+            self.0.lock().len()
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            buf.copy_from_slice(&self.0.lock()[sector_index]);
+            Ok(buf.len())
+        }
+
+        fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, ()> {
+            self.0.lock()[sector_index].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    // the default implementations, like a driver that can't do better
+    impl MultiBlock for Memory {}
+
+    impl Flush for Memory {
+        fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    /// A disk image in memory with native 4K sectors.
+    #[derive(Clone)]
+    struct Image4K(Arc<Mutex<Vec<u8>>>);
+
+    impl Image4K {
+        const SECTOR_SIZE: usize = 4096;
+
+        /// Copies the OS disk, which has 512 byte sectors.
+        fn of_os_disk() -> Self {
+            let drive = ide::devices()[1].clone();
+            let len = drive.sector_count() * 512;
+            let mut data = vec![0_u8; len.next_multiple_of(Self::SECTOR_SIZE)];
+            drive.read_sectors(0, &mut data[..len]).unwrap();
+            Self(Arc::new(Mutex::new(data)))
+        }
+    }
+
+    impl BlockDevice for Image4K {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            Self::SECTOR_SIZE
+        }
+
+        fn sector_count(&self) -> usize {
+            self.0.lock().len() / Self::SECTOR_SIZE
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            let offset = sector_index * Self::SECTOR_SIZE;
+            buf.copy_from_slice(&self.0.lock()[offset..offset + Self::SECTOR_SIZE]);
+            Ok(buf.len())
+        }
+
+        fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, ()> {
+            let offset = sector_index * Self::SECTOR_SIZE;
+            self.0.lock()[offset..offset + Self::SECTOR_SIZE].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    impl MultiBlock for Image4K {}
+
+    impl Flush for Image4K {
+        fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[kernel_test]
+    fn test_directory_traversal_is_cached() {
+        let drive = ide::devices()[1].clone();
+        let device = Counting::new(drive);
+        let cache = BlockCache::new(device.clone(), 1024);
+        let mut fs = VirtualExt2Fs::try_new(FsId::new(), cache.clone()).unwrap();
+
+        let mut traverse = || {
+            let handle = fs.open(Path::new("/var/data")).unwrap();
+            let (entries, _) = fs.read_dir(handle, 0).unwrap();
+            assert!(entries.iter().any(|entry| entry.name == "hello.txt"));
+            fs.close(handle).unwrap();
+        };
+
+        traverse();
+        let reads = device.reads.load(Relaxed);
+        assert!(reads > 0);
+        let misses = cache.stats().misses;
+
+        traverse();
+        assert_eq!(reads, device.reads.load(Relaxed));
+        assert_eq!(misses, cache.stats().misses);
+        assert!(cache.stats().hits > 0);
+    }
+
+    #[kernel_test]
+    fn test_buffer_size_mismatch() {
+        let mut cache = BlockCache::new(Memory::new(4), 4);
+        let mismatch = Err(BlockCacheError::BufferSize {
+            expected: 512,
+            actual: 100,
+        });
+        assert_eq!(mismatch, cache.read_sector(0, &mut [0; 100]));
+        assert_eq!(mismatch, cache.write_sector(0, &[0; 100]));
+        assert_eq!(
+            mismatch,
+            cache.read_blocks(0, &mut [&mut [0; 512][..], &mut [0; 100][..]])
+        );
+
+        let mut buf = BlockBuf::new_for(&cache).unwrap();
+        assert_eq!(512, buf.len());
+        assert_eq!(Ok(512), cache.read_sector(0, &mut buf));
+    }
+
+    #[kernel_test]
+    fn test_ext2_on_4k_sectors() {
+        // the OS disk has 1KiB file system blocks, so every write of a block
+        // reads and writes a quarter of a sector
+        let image = Image4K::of_os_disk();
+        let mut fs =
+            VirtualExt2Fs::try_new(FsId::new(), BlockCache::new(image.clone(), 64)).unwrap();
+
+        let mut expected = [0_u8; 64];
+        let node = vfs().open("/var/data/hello.txt").unwrap();
+        let len = vfs().read(&node, &mut expected, 0).unwrap();
+        let handle = fs.open(Path::new("/var/data/hello.txt")).unwrap();
+        let mut buf = [0_u8; 64];
+        assert_eq!(Ok(len), fs.read(handle, &mut buf, 0));
+        assert_eq!(expected[..len], buf[..len]);
+        fs.close(handle).unwrap();
+
+        let path = Path::new("/var/data/quarter.txt");
+        let content = (0..3000).map(|i| i as u8).collect::<Vec<_>>();
+        fs.create(
+            path,
+            FileType::RegularFile,
+            FileMode::S_IRUSR | FileMode::S_IWUSR,
+        )
+        .unwrap();
+        let handle = fs.open(path).unwrap();
+        assert_eq!(Ok(content.len()), fs.write(handle, &content, 0));
+        fs.close(handle).unwrap();
+        fs.sync().unwrap();
+        drop(fs);
+
+        // a new cache only sees what reached the device
+        let mut fs = VirtualExt2Fs::try_new(FsId::new(), BlockCache::new(image, 64)).unwrap();
+        let handle = fs.open(path).unwrap();
+        let mut buf = vec![0_u8; content.len()];
+        assert_eq!(Ok(content.len()), fs.read(handle, &mut buf, 0));
+        assert_eq!(content, buf);
+        let handle = fs.open(Path::new("/var/data/hello.txt")).unwrap();
+        let mut buf = [0_u8; 64];
+        assert_eq!(Ok(len), fs.read(handle, &mut buf, 0));
+        assert_eq!(expected[..len], buf[..len]);
+    }
+
+    #[kernel_test]
+    fn test_dirty_blocks_written_on_eviction() {
+        let memory = Memory::new(4);
+        let device = Counting::new(memory.clone());
+        let mut cache = BlockCache::new(device.clone(), 2);
+
+        cache.write_sector(0, &[1; 512]).unwrap();
+        cache.write_sector(1, &[2; 512]).unwrap();
+        assert_eq!(0, device.writes.load(Relaxed));
+
+        // block 1 is now the least recently used one
+        let mut buf = [0; 512];
+        cache.read_sector(0, &mut buf).unwrap();
+        assert_eq!([1; 512], buf);
+        cache.write_sector(2, &[3; 512]).unwrap();
+        assert_eq!(1, device.writes.load(Relaxed));
+        assert_eq!([2; 512], memory.sector(1));
+        assert_eq!([0; 512], memory.sector(0));
+        assert_eq!(1, cache.stats().writebacks);
+
+        // clean blocks are evicted without writing them
+        cache.flush().unwrap();
+        assert_eq!(3, device.writes.load(Relaxed));
+        cache.read_sector(3, &mut buf).unwrap();
+        cache.read_sector(1, &mut buf).unwrap();
+        assert_eq!([2; 512], buf);
+        assert_eq!(3, device.writes.load(Relaxed));
+        assert_eq!([1; 512], memory.sector(0));
+        assert_eq!([3; 512], memory.sector(2));
+    }
+
+    #[kernel_test]
+    fn test_flush_writes_dirty_blocks() {
+        let memory = Memory::new(4);
+        let device = Counting::new(memory.clone());
+        let mut cache = BlockCache::new(device.clone(), 4);
+
+        cache.write_sector(1, &[1; 512]).unwrap();
+        cache.write_sector(2, &[2; 512]).unwrap();
+        let mut buf = [0; 512];
+        cache.read_sector(3, &mut buf).unwrap();
+        assert_eq!([0; 512], memory.sector(1));
+
+        cache.flush().unwrap();
+        assert_eq!(2, device.writes.load(Relaxed));
+        assert_eq!([1; 512], memory.sector(1));
+        assert_eq!([2; 512], memory.sector(2));
+
+        // nothing is dirty anymore
+        cache.flush().unwrap();
+        assert_eq!(2, device.writes.load(Relaxed));
+        assert_eq!(2, cache.stats().writebacks);
+        assert_eq!(1, cache.stats().misses);
+    }
+
+    /// Creates a device with the given number of blocks of pseudorandom data.
+    fn random_memory(sectors: usize) -> Memory {
+        let memory = Memory::new(sectors);
+        let mut state = 0x2545_F491_4F6C_DD1D_u64;
+        for sector in memory.0.lock().iter_mut() {
+            for byte in sector.iter_mut() {
+                // xorshift
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+        }
+        memory
+    }
+
+    #[kernel_test]
+    fn test_default_multi_block_transfers() {
+        let mut memory = random_memory(8);
+        let mut bufs = [[0_u8; 512]; 3];
+        let mut refs = bufs.each_mut().map(|buf| buf.as_mut_slice());
+        assert_eq!(Ok(3 * 512), memory.read_blocks(4, &mut refs));
+        for (i, buf) in bufs.iter().enumerate() {
+            assert_eq!(memory.sector(4 + i), *buf);
+        }
+
+        assert_eq!(Ok(2 * 512), memory.write_blocks(0, &[&[1; 512], &[2; 512]]));
+        assert_eq!([1; 512], memory.sector(0));
+        assert_eq!([2; 512], memory.sector(1));
+    }
+
+    #[kernel_test]
+    fn test_read_blocks_in_runs() {
+        let memory = random_memory(16);
+        let device = Counting::new(memory.clone());
+        let cache = BlockCache::new(device.clone(), 16);
+
+        // blocks 3 and 7 are cached, so the rest are three runs
+        let mut buf = [0_u8; 512];
+        cache.read_sector(3, &mut buf).unwrap();
+        cache.read_sector(7, &mut buf).unwrap();
+        assert_eq!(2, device.requests.load(Relaxed));
+
+        let mut bufs = vec![[0_u8; 512]; 12];
+        let mut refs = bufs
+            .iter_mut()
+            .map(|buf| buf.as_mut_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(Ok(12 * 512), cache.read_blocks(1, &mut refs));
+        assert_eq!(5, device.requests.load(Relaxed));
+        assert_eq!(12, device.reads.load(Relaxed));
+        for (i, buf) in bufs.iter().enumerate() {
+            assert_eq!(memory.sector(1 + i), *buf, "block {} differs", 1 + i);
+        }
+
+        // the blocks are cached now
+        let mut refs = bufs
+            .iter_mut()
+            .map(|buf| buf.as_mut_slice())
+            .collect::<Vec<_>>();
+        assert_eq!(Ok(12 * 512), cache.read_blocks(1, &mut refs));
+        assert_eq!(5, device.requests.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_flush_writes_runs() {
+        let memory = Memory::new(8);
+        let device = Counting::new(memory.clone());
+        let mut cache = BlockCache::new(device.clone(), 8);
+
+        for block in [1, 2, 3, 6] {
+            cache.write_sector(block, &[block as u8; 512]).unwrap();
+        }
+        cache.flush().unwrap();
+        assert_eq!(2, device.requests.load(Relaxed));
+        assert_eq!(4, device.writes.load(Relaxed));
+        for block in [1, 2, 3, 6] {
+            assert_eq!([block as u8; 512], memory.sector(block));
+        }
+    }
+
+    #[kernel_test]
+    fn test_flush_reaches_the_device() {
+        let memory = Memory::new(4);
+        let device = Counting::new(memory.clone());
+        let mut cache = BlockCache::new(device.clone(), 4);
+
+        // the device is flushed after the dirty blocks were written
+        cache.write_sector(1, &[1; 512]).unwrap();
+        cache.flush().unwrap();
+        assert_eq!(1, device.writes.load(Relaxed));
+        assert_eq!(1, device.flushes.load(Relaxed));
+
+        // even if nothing is dirty, someone else could have written to it
+        cache.flush().unwrap();
+        assert_eq!(2, device.flushes.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_unmount_flushes_the_device_once() {
+        let drive = ide::devices()[1].clone();
+        let device = Counting::new(drive);
+        let cache = BlockCache::new(device.clone(), 1024);
+        let fs = VirtualExt2Fs::try_new(FsId::new(), cache).unwrap();
+
+        vfs()
+            .mount("/mnt/flush", fs, MountFlags::READ_ONLY)
+            .unwrap();
+        assert!(vfs().exists("/mnt/flush/var/data/hello.txt").unwrap());
+        assert_eq!(0, device.flushes.load(Relaxed));
+
+        // this sends FLUSH CACHE to the drive
+        vfs().unmount("/mnt/flush").unwrap();
+        assert_eq!(1, device.flushes.load(Relaxed));
+        assert_eq!(0, device.writes.load(Relaxed));
     }
 }
@@ -0,0 +1,86 @@
+use filesystem::BlockDevice;
+use kernel_api::syscall::{FileMode, Stat};
+
+use crate::io::vfs::cache::BlockBuf;
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
+use crate::io::vfs::error::{Result, VfsError};
+
+/// A block device like `/dev/blk0` or `/dev/blk0p1`, which gives byte wise
+/// access to the sectors of a drive or a partition.
+#[derive(Clone)]
+pub struct Block<T> {
+    device: T,
+}
+
+impl<T> From<T> for Block<T> {
+    fn from(device: T) -> Self {
+        Self { device }
+    }
+}
+
+impl<T: BlockDevice> Block<T> {
+    fn len(&self) -> usize {
+        self.device.sector_size() * self.device.sector_count()
+    }
+}
+
+impl<T> DeviceIoctl for Block<T> {}
+
+impl<T> DevFile for Block<T>
+where
+    T: BlockDevice + Send + Sync,
+{
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize> {
+        let sector_size = self.device.sector_size();
+        let end = self.len().min(offset.saturating_add(buf.len()));
+        let mut sector = BlockBuf::new_for(&self.device).map_err(|_| VfsError::NoSpace)?;
+        let mut position = offset;
+        while position < end {
+            self.device
+                .read_sector(position / sector_size, &mut sector)
+                .map_err(|_| VfsError::ReadError)?;
+            let start = position % sector_size;
+            let n = (sector_size - start).min(end - position);
+            let done = position - offset;
+            buf[done..done + n].copy_from_slice(&sector[start..start + n]);
+            position += n;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+
+    fn write(&mut self, buf: &[u8], offset: usize) -> Result<usize> {
+        let sector_size = self.device.sector_size();
+        if offset >= self.len() && !buf.is_empty() {
+            return Err(VfsError::NoSpace);
+        }
+        let end = self.len().min(offset.saturating_add(buf.len()));
+        let mut sector = BlockBuf::new_for(&self.device).map_err(|_| VfsError::NoSpace)?;
+        let mut position = offset;
+        while position < end {
+            let start = position % sector_size;
+            let n = (sector_size - start).min(end - position);
+            // partial sectors have to be read first, so that the rest is preserved
+            if n < sector_size {
+                self.device
+                    .read_sector(position / sector_size, &mut sector)
+                    .map_err(|_| VfsError::ReadError)?;
+            }
+            let done = position - offset;
+            sector[start..start + n].copy_from_slice(&buf[done..done + n]);
+            self.device
+                .write_sector(position / sector_size, &sector)
+                .map_err(|_| VfsError::WriteError)?;
+            position += n;
+        }
+        Ok(end.saturating_sub(offset))
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat.mode = FileMode::S_IFBLK | FileMode::S_IRUSR | FileMode::S_IWUSR;
+        stat.nlink = 1;
+        stat.size = self.len() as u64;
+        stat.blksize = self.device.sector_size() as u64;
+        stat.blocks = self.device.sector_count() as u64;
+        Ok(())
+    }
+}
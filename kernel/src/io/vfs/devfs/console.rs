@@ -0,0 +1,262 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use spin::Mutex;
+
+use kernel_api::syscall::{
+    Errno, FileMode, PollEvents, Stat, CONSOLE_GET_MODE, CONSOLE_MODE_LINE, CONSOLE_MODE_RAW,
+    CONSOLE_SET_MODE,
+};
+
+use crate::arch::serial;
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
+use crate::io::vfs::error::Result;
+use crate::io::vfs::VfsError;
+use crate::process::wait_queue::WaitQueue;
+use crate::syscall::convert::UserspaceMutPtr;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+
+/// The line discipline is shared by all handles of the console, since they all
+/// read from the same serial input.
+static DISCIPLINE: Mutex<LineDiscipline> = Mutex::new(LineDiscipline::new());
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Mode {
+    Line,
+    Raw,
+}
+
+/// Turns the bytes that are typed into the console into what readers get.
+///
+/// In [`Mode::Line`], typed bytes are echoed, a backspace removes the last byte
+/// of the current line, and a line only becomes readable once it's completed
+/// with a newline (or carriage return). In [`Mode::Raw`], bytes are readable as
+/// they are typed.
+struct LineDiscipline {
+    mode: Mode,
+    /// The line that is currently typed, and that is not readable yet.
+    line: Vec<u8>,
+    /// The bytes that readers can read.
+    ready: VecDeque<u8>,
+}
+
+impl LineDiscipline {
+    const fn new() -> Self {
+        Self {
+            mode: Mode::Line,
+            line: Vec::new(),
+            ready: VecDeque::new(),
+        }
+    }
+
+    /// Processes a typed byte, and passes what has to be echoed to `echo`.
+    fn input(&mut self, byte: u8, echo: impl FnOnce(&[u8])) {
+        if self.mode == Mode::Raw {
+            self.ready.push_back(byte);
+            return;
+        }
+
+        match byte {
+            b'\r' | b'\n' => {
+                self.ready.extend(self.line.drain(..));
+                self.ready.push_back(b'\n');
+                echo(b"\n");
+            }
+            BACKSPACE | DELETE => {
+                if self.line.pop().is_some() {
+                    // move back, overwrite the byte and move back again
+                    echo(b"\x08 \x08");
+                }
+            }
+            _ => {
+                self.line.push(byte);
+                echo(&[byte]);
+            }
+        }
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        // what has been typed so far can't be edited anymore in raw mode
+        if mode == Mode::Raw {
+            self.ready.extend(self.line.drain(..));
+        }
+        self.mode = mode;
+    }
+
+    /// Processes the serial input until there is something to read, or the
+    /// input is exhausted. Input that is not needed yet is left in the serial
+    /// input buffer, so that it's processed in the mode that is active when
+    /// it's read.
+    fn fill(&mut self, wanted: usize) {
+        while self.ready.is_empty() || (self.mode == Mode::Raw && self.ready.len() < wanted) {
+            let mut byte = [0_u8];
+            if serial::read_input(&mut byte) == 0 {
+                return;
+            }
+            self.input(byte[0], serial::write_bytes);
+        }
+    }
+
+    /// Reads the readable bytes into `buf`. In line mode, a read never goes
+    /// past the end of a line.
+    fn read(&mut self, buf: &mut [u8]) -> usize {
+        let mut read = 0;
+        while read < buf.len() {
+            let Some(byte) = self.ready.pop_front() else {
+                break;
+            };
+            buf[read] = byte;
+            read += 1;
+            if self.mode == Mode::Line && byte == b'\n' {
+                break;
+            }
+        }
+        read
+    }
+}
+
+/// `/dev/console`, which writes to the serial output and reads from the serial
+/// input.
+///
+/// Every write is written as a whole, so output of different processes doesn't
+/// interleave within a single write. Reads return [`VfsError::WouldBlock`] if
+/// there is nothing to read, the waiting happens outside of the VFS, woken by
+/// the interrupt of the serial port.
+pub struct Console;
+
+impl DeviceIoctl for Console {
+    fn ioctl(
+        &mut self,
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        match request {
+            CONSOLE_GET_MODE => {
+                let mode = match DISCIPLINE.lock().mode {
+                    Mode::Line => CONSOLE_MODE_LINE,
+                    Mode::Raw => CONSOLE_MODE_RAW,
+                };
+                let mut ptr = arg.cast::<u32>()?;
+                ptr.write_value(mode)?;
+                Ok(0)
+            }
+            CONSOLE_SET_MODE => {
+                let mode = match u32::try_from(arg.addr()) {
+                    Ok(CONSOLE_MODE_LINE) => Mode::Line,
+                    Ok(CONSOLE_MODE_RAW) => Mode::Raw,
+                    _ => return Err(Errno::EINVAL),
+                };
+                DISCIPLINE.lock().set_mode(mode);
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
+        }
+    }
+}
+
+impl DevFile for Console {
+    fn read(&self, buf: &mut [u8], _: usize) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut discipline = DISCIPLINE.lock();
+        discipline.fill(buf.len());
+        match discipline.read(buf) {
+            0 => Err(VfsError::WouldBlock),
+            read => Ok(read),
+        }
+    }
+
+    fn write(&mut self, buf: &[u8], _: usize) -> Result<usize> {
+        serial::write_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        // TODO: rdev
+
+        stat.mode = FileMode::S_IFCHR
+            | FileMode::S_IRUSR
+            | FileMode::S_IWUSR
+            | FileMode::S_IRGRP
+            | FileMode::S_IWGRP;
+        stat.nlink = 1;
+        stat.size = 0;
+        stat.blksize = 0;
+        stat.blocks = 0;
+
+        Ok(())
+    }
+
+    fn poll(&self, interest: PollEvents) -> Result<PollEvents> {
+        let mut discipline = DISCIPLINE.lock();
+        discipline.fill(1);
+        let mut events = interest & PollEvents::POLLOUT;
+        if !discipline.ready.is_empty() {
+            events |= interest & PollEvents::POLLIN;
+        }
+        Ok(events)
+    }
+
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        Some(serial::input_waiters())
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec::Vec;
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::vfs::devfs::console::{LineDiscipline, Mode};
+
+    fn type_bytes(discipline: &mut LineDiscipline, bytes: &[u8]) -> Vec<u8> {
+        let mut echoed = Vec::new();
+        for &byte in bytes {
+            discipline.input(byte, |echo| echoed.extend_from_slice(echo));
+        }
+        echoed
+    }
+
+    #[kernel_test]
+    fn test_line_editing() {
+        let mut discipline = LineDiscipline::new();
+        let mut buf = [0_u8; 32];
+
+        let echoed = type_bytes(&mut discipline, b"helo\x7f\x7flo");
+        assert_eq!(b"helo\x08 \x08\x08 \x08lo", echoed.as_slice());
+        // nothing is readable until the line is complete
+        assert_eq!(0, discipline.read(&mut buf));
+
+        // backspace at the start of a line does nothing
+        let echoed = type_bytes(&mut discipline, b"\rx\x08\x08\n");
+        assert_eq!(b"\nx\x08 \x08\n", echoed.as_slice());
+
+        // reads stop at the end of a line
+        assert_eq!(6, discipline.read(&mut buf));
+        assert_eq!(b"hello\n", &buf[..6]);
+        assert_eq!(1, discipline.read(&mut buf));
+        assert_eq!(b"\n", &buf[..1]);
+    }
+
+    #[kernel_test]
+    fn test_raw_mode() {
+        let mut discipline = LineDiscipline::new();
+        let mut buf = [0_u8; 32];
+
+        type_bytes(&mut discipline, b"ab");
+        discipline.set_mode(Mode::Raw);
+        // the unfinished line becomes readable
+        assert_eq!(2, discipline.read(&mut buf));
+
+        let echoed = type_bytes(&mut discipline, b"c\x7f\nd");
+        assert!(echoed.is_empty());
+        assert_eq!(4, discipline.read(&mut buf));
+        assert_eq!(b"c\x7f\nd", &buf[..4]);
+    }
+}
@@ -1,11 +1,19 @@
 use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::slice;
 use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
 
 use crate::driver::vga;
+use crate::driver::vga::console;
 use crate::driver::vga::VgaDevice;
-use crate::io::vfs::devfs::DevFile;
+use crate::driver::virtio::gpu;
+use crate::driver::virtio::gpu::VirtioGpu;
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
 use crate::io::vfs::{Result, VfsError};
-use kernel_api::syscall::{FileMode, Stat};
+use crate::syscall::convert::UserspaceMutPtr;
+use kernel_api::syscall::{Errno, FbVarScreenInfo, FileMode, Stat, FBIOGET_VSCREENINFO};
 
 pub fn find_fbs() -> impl Iterator<Item = Fb> {
     vga::devices()
@@ -14,43 +22,277 @@ pub fn find_fbs() -> impl Iterator<Item = Fb> {
         .unwrap() // TODO: handle error
         .into_iter()
         .map(Fb::Vga)
+        .chain(gpu::devices().lock().clone().into_iter().map(Fb::VirtioGpu))
 }
 
 #[derive(Clone)]
 pub enum Fb {
     Vga(VgaDevice),
+    VirtioGpu(Arc<VirtioGpu>),
 }
 
 impl Fb {
-    fn frames(&self) -> impl Iterator<Item = &PhysFrame> {
+    fn frames(&self) -> Vec<PhysFrame> {
         match self {
-            Fb::Vga(vga) => vga.physical_frames().iter(),
+            Fb::Vga(vga) => vga.physical_frames().to_vec(),
+            Fb::VirtioGpu(gpu) => gpu.physical_frames(),
+        }
+    }
+
+    fn screen_info(&self) -> Option<FbVarScreenInfo> {
+        match self {
+            Fb::Vga(vga) => vga.screen_info(),
+            Fb::VirtioGpu(gpu) => Some(gpu.screen_info()),
+        }
+    }
+
+    /// Calls `f` with the layout and the memory of the framebuffer, or fails
+    /// with [`VfsError::Unsupported`] if we can't tell how it's laid out.
+    fn with_memory<R>(&self, f: impl FnOnce(&Layout, &mut [u8]) -> R) -> Result<R> {
+        let layout = Layout::new(&self.screen_info().ok_or(VfsError::Unsupported)?);
+        let result = match self {
+            Fb::Vga(vga) => vga.with_buffer(|buffer| f(&layout, buffer)),
+            Fb::VirtioGpu(gpu) => Some(gpu.with_framebuffer(|pixels| {
+                // safety: the pixels are plain integers, so they can be
+                // viewed as bytes
+                let bytes = unsafe {
+                    slice::from_raw_parts_mut(pixels.as_mut_ptr().cast::<u8>(), size_of_val(pixels))
+                };
+                f(&layout, bytes)
+            })),
+        };
+        result.ok_or(VfsError::Unsupported)
+    }
+}
+
+/// How the file of a framebuffer maps to its memory. The file is laid out
+/// like the memory, `pitch` bytes per row, so that offsets mean the same as
+/// in a mapping of the device. The padding at the end of a row isn't part
+/// of the screen though: it reads as zeros, and writes to it are dropped.
+#[derive(Debug, Copy, Clone)]
+struct Layout {
+    pitch: usize,
+    /// The number of bytes of a row that are visible.
+    visible: usize,
+    height: usize,
+}
+
+impl Layout {
+    fn new(info: &FbVarScreenInfo) -> Self {
+        Self {
+            pitch: info.pitch as usize,
+            visible: info.width as usize * info.bytes_per_pixel as usize,
+            height: info.height as usize,
+        }
+    }
+
+    /// The size of the file.
+    fn size(&self) -> usize {
+        self.pitch * self.height
+    }
+
+    /// The visible parts of the `len` bytes from `offset` on, each as the
+    /// range in memory and where it starts, relative to `offset`.
+    fn visible_parts(
+        &self,
+        offset: usize,
+        len: usize,
+    ) -> impl Iterator<Item = (Range<usize>, usize)> {
+        let end = offset + len;
+        let rows = if len == 0 {
+            0..0
+        } else {
+            offset / self.pitch..(end - 1) / self.pitch + 1
+        };
+        let layout = *self;
+        rows.filter_map(move |row| {
+            let start = (row * layout.pitch).max(offset);
+            let visible_end = (row * layout.pitch + layout.visible).min(end);
+            (start < visible_end).then(|| (start..visible_end, start - offset))
+        })
+    }
+
+    /// Reads from the file at `offset`, and returns how many bytes were read.
+    fn read(&self, memory: &[u8], buf: &mut [u8], offset: usize) -> Result<usize> {
+        if memory.len() < self.size() {
+            return Err(VfsError::ReadError);
+        }
+        let len = buf.len().min(self.size().saturating_sub(offset));
+        buf[..len].fill(0);
+        for (range, at) in self.visible_parts(offset, len) {
+            buf[at..at + range.len()].copy_from_slice(&memory[range]);
+        }
+        Ok(len)
+    }
+
+    /// Writes to the file at `offset`, and returns how many bytes were
+    /// written, which is less than `buf.len()` at the end of the file.
+    fn write(&self, memory: &mut [u8], buf: &[u8], offset: usize) -> Result<usize> {
+        if memory.len() < self.size() {
+            return Err(VfsError::WriteError);
+        }
+        if offset >= self.size() && !buf.is_empty() {
+            return Err(VfsError::NoSpace);
+        }
+        let len = buf.len().min(self.size() - offset);
+        for (range, at) in self.visible_parts(offset, len) {
+            let len = range.len();
+            memory[range].copy_from_slice(&buf[at..at + len]);
+        }
+        Ok(len)
+    }
+}
+
+impl DeviceIoctl for Fb {
+    fn ioctl(
+        &mut self,
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        match request {
+            FBIOGET_VSCREENINFO => {
+                let info = self.screen_info().ok_or(Errno::ENODEV)?;
+                let mut ptr = arg.cast::<FbVarScreenInfo>()?;
+                ptr.write_value(info)?;
+                Ok(0)
+            }
+            _ => Err(Errno::ENOTTY),
         }
     }
 }
 
 impl DevFile for Fb {
-    fn read(&self, _: &mut [u8], _: usize) -> Result<usize> {
-        Err(VfsError::Unsupported)
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize> {
+        self.with_memory(|layout, memory| layout.read(memory, buf, offset))?
     }
 
-    fn write(&mut self, _: &[u8], _: usize) -> Result<usize> {
-        Err(VfsError::Unsupported)
+    fn write(&mut self, buf: &[u8], offset: usize) -> Result<usize> {
+        if let Fb::Vga(_) = self {
+            console::release();
+        }
+        self.with_memory(|layout, memory| layout.write(memory, buf, offset))?
     }
 
     fn stat(&self, stat: &mut Stat) -> Result<()> {
-        // TODO: ino, dev, nlink, uid, gid, rdev, blksize, blocks
+        // TODO: rdev
 
-        stat.mode |= FileMode::S_IFCHR;
-        stat.nlink = 1; // TODO: can this change?
-        stat.size = self.frames().map(|f| f.size()).sum::<u64>(); // TODO: is this correct? might the memory be shorter?
+        stat.mode = FileMode::S_IFCHR
+            | FileMode::S_IRUSR
+            | FileMode::S_IWUSR
+            | FileMode::S_IRGRP
+            | FileMode::S_IWGRP;
+        stat.nlink = 1;
+        // the memory of the device may be larger than what the screen shows,
+        // the size is the same that userspace computes from the ioctl
+        stat.size = match self.screen_info() {
+            Some(info) => u64::from(info.pitch) * u64::from(info.height),
+            None => self.frames().iter().map(|f| f.size()).sum::<u64>(),
+        };
         stat.blksize = Size4KiB::SIZE; // the size of a PhysFrame
-        stat.blocks = self.frames().count() as u64;
+        stat.blocks = stat.size.div_ceil(Size4KiB::SIZE);
 
         Ok(())
     }
 
     fn physical_memory(&self) -> Result<Option<Box<dyn Iterator<Item = PhysFrame> + '_>>> {
-        Ok(Some(Box::new(self.frames().cloned())))
+        if let Fb::Vga(_) = self {
+            // whoever maps the framebuffer draws on it, the log would only
+            // get in the way
+            console::release();
+        }
+        Ok(Some(Box::new(self.frames().into_iter())))
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use kernel_api::pixel::PixelFormat;
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+
+    /// 800x600 with 32 bytes of padding at the end of every row.
+    const INFO: FbVarScreenInfo = FbVarScreenInfo {
+        width: 800,
+        height: 600,
+        bytes_per_pixel: 4,
+        pitch: 800 * 4 + 32,
+        format: PixelFormat::Bgrx8888,
+    };
+    const PITCH: usize = INFO.pitch as usize;
+    const VISIBLE: usize = 800 * 4;
+    const SIZE: usize = PITCH * 600;
+
+    /// The memory of the framebuffer, with a few more bytes at the end that
+    /// must never be touched.
+    fn memory() -> Vec<u8> {
+        vec![0xAA; SIZE + 16]
+    }
+
+    #[kernel_test]
+    fn test_offsets_follow_the_pitch() {
+        let layout = Layout::new(&INFO);
+        assert_eq!(SIZE, layout.size());
+        let mut memory = memory();
+
+        // the pixel at (10, 2)
+        let offset = 2 * PITCH + 10 * 4;
+        assert_eq!(Ok(4), layout.write(&mut memory, &[1, 2, 3, 4], offset));
+        assert_eq!([1, 2, 3, 4], memory[offset..offset + 4]);
+        let mut buf = [0; 4];
+        assert_eq!(Ok(4), layout.read(&memory, &mut buf, offset));
+        assert_eq!([1, 2, 3, 4], buf);
+        // with the width as the stride, this would have been somewhere else
+        assert_eq!([0xAA; 4], memory[2 * VISIBLE + 40..2 * VISIBLE + 44]);
+    }
+
+    #[kernel_test]
+    fn test_padding_is_skipped() {
+        let layout = Layout::new(&INFO);
+        let mut memory = memory();
+
+        // the last pixel of row 0, its padding, and the first pixel of row 1
+        let offset = VISIBLE - 4;
+        let buf = [0x11; 4 + 32 + 4];
+        assert_eq!(Ok(buf.len()), layout.write(&mut memory, &buf, offset));
+        assert_eq!([0x11; 4], memory[VISIBLE - 4..VISIBLE]);
+        assert!(memory[VISIBLE..PITCH].iter().all(|&b| b == 0xAA));
+        assert_eq!([0x11; 4], memory[PITCH..PITCH + 4]);
+
+        // the padding reads as zeros
+        let mut read = [0xFF; 4 + 32 + 4];
+        assert_eq!(Ok(read.len()), layout.read(&memory, &mut read, offset));
+        assert_eq!([0x11; 4], read[..4]);
+        assert!(read[4..36].iter().all(|&b| b == 0));
+        assert_eq!([0x11; 4], read[36..]);
+    }
+
+    #[kernel_test]
+    fn test_end_of_file() {
+        let layout = Layout::new(&INFO);
+        let mut memory = memory();
+
+        // the last visible bytes are written, and the padding of the last
+        // row is not
+        let offset = SIZE - 32 - 2;
+        assert_eq!(Ok(34), layout.write(&mut memory, &[0x22; 64], offset));
+        assert_eq!([0x22; 2], memory[offset..offset + 2]);
+        assert!(memory[SIZE - 32..].iter().all(|&b| b == 0xAA));
+        assert_eq!(
+            Err(VfsError::NoSpace),
+            layout.write(&mut memory, &[0; 4], SIZE)
+        );
+        assert_eq!(Ok(0), layout.write(&mut memory, &[], SIZE));
+
+        let mut buf = [0; 64];
+        assert_eq!(Ok(34), layout.read(&memory, &mut buf, offset));
+        assert_eq!(Ok(0), layout.read(&memory, &mut buf, SIZE));
+        assert_eq!(Ok(0), layout.read(&memory, &mut buf, SIZE + 100));
+
+        // memory that is smaller than the geometry says is never touched
+        let mut short = vec![0; SIZE - 1];
+        assert_eq!(Err(VfsError::WriteError), layout.write(&mut short, &[1], 0));
     }
 }
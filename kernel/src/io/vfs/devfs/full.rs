@@ -0,0 +1,29 @@
+use kernel_api::syscall::Stat;
+
+use crate::io::vfs::devfs::{stat_memory_device, DevFile, DeviceIoctl};
+use crate::io::vfs::error::{Result, VfsError};
+
+/// `/dev/full`, which reads like `/dev/zero`, but behaves like a full disk
+/// when written to.
+pub struct Full;
+
+impl DeviceIoctl for Full {}
+
+impl DevFile for Full {
+    fn read(&self, buf: &mut [u8], _: usize) -> Result<usize> {
+        buf.fill(0);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8], _: usize) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat_memory_device(stat);
+        Ok(())
+    }
+}
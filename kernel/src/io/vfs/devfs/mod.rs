@@ -2,31 +2,113 @@ use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering::Relaxed;
 
-use x86_64::structures::paging::PhysFrame;
+use conquer_once::spin::OnceCell;
+use foundation::falloc::vec::FVec;
+use foundation::time::Instant;
+use log::warn;
+use spin::RwLock;
+use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
 
-use kernel_api::syscall::Stat;
+use kernel_api::syscall::{Errno, FileMode, PollEvents, Stat, Timespec};
 
+use crate::driver::block::{BlockDeviceEvent, Revocable};
+use crate::driver::disk;
+use crate::driver::disk::{Disk, DiskPartition};
 use crate::io::path::Path;
+use crate::io::vfs::devfs::block::Block;
+use crate::io::vfs::devfs::console::Console;
+use crate::io::vfs::devfs::full::Full;
+use crate::io::vfs::devfs::null::Null;
+use crate::io::vfs::devfs::smart::Smart;
+use crate::io::vfs::devfs::text::Text;
+use crate::io::vfs::devfs::urandom::Urandom;
 use crate::io::vfs::devfs::zero::Zero;
 use crate::io::vfs::error::{Result, VfsError};
-use crate::io::vfs::{DirEntry, FileSystem, FileType, FsId, VfsHandle};
-
+use crate::io::vfs::{DirEntry, FileSystem, FileType, FsId, MmapBacking, VfsHandle};
+use crate::process;
+use crate::process::fd::Fileno;
+use crate::process::wait_queue::WaitQueue;
+use crate::syscall::convert::UserspaceMutPtr;
+use crate::time::HpetInstantProvider;
+
+mod block;
+mod console;
 mod fb;
+mod full;
+mod null;
+mod smart;
 mod stdio;
+mod text;
+mod urandom;
 mod zero;
 
 static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+static DEVFS: OnceCell<ArcLockedDevFs> = OnceCell::uninit();
+
+pub type ArcLockedDevFs = Arc<RwLock<VirtualDevFs<'static>>>;
+
+/// Creates the devfs that is mounted at `/dev`. Devices can be added to and
+/// removed from it through [`devfs`] at any time.
+pub(in crate::io::vfs) fn init() -> ArcLockedDevFs {
+    DEVFS
+        .get_or_init(|| {
+            // devices that exist already are published by the constructor
+            disk::devices().on_change(publish_block_device);
+            Arc::new(RwLock::new(VirtualDevFs::new(FsId::new())))
+        })
+        .clone()
+}
+
+/// Keeps the block devices in the devfs in sync with [`disk::devices`].
+fn publish_block_device(event: BlockDeviceEvent) {
+    let Some(devfs) = devfs() else {
+        return;
+    };
+    match event {
+        BlockDeviceEvent::Registered(index) => {
+            let Some(device) = disk::devices().lock().get(index).cloned() else {
+                return;
+            };
+            if let Err(e) = devfs.write().register_block_device(index, device) {
+                warn!("failed to publish block device {index} in devfs: {e:?}");
+            }
+        }
+        BlockDeviceEvent::Unregistered(index) => devfs.write().unregister_block_device(index),
+    }
+}
+
+/// Returns the devfs that is mounted at `/dev`, or `None` if the VFS is not
+/// initialized yet.
+pub fn devfs() -> Option<&'static ArcLockedDevFs> {
+    DEVFS.get()
+}
 
 /// Helper to create a new handle.
 fn next_handle() -> VfsHandle {
     VfsHandle::new(HANDLE_COUNTER.fetch_add(1, Relaxed))
 }
 
-pub trait DevFile: Send + Sync {
+/// Device specific operations that don't fit into read and write.
+pub trait DeviceIoctl {
+    /// Performs the operation identified by `request`. What `arg` points to
+    /// depends on the request. Devices that don't know the request must
+    /// return [`Errno::ENOTTY`].
+    fn ioctl(
+        &mut self,
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        let _ = (request, arg);
+        Err(Errno::ENOTTY)
+    }
+}
+
+pub trait DevFile: DeviceIoctl + Send + Sync {
     fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize>;
 
     fn write(&mut self, buf: &[u8], offset: usize) -> Result<usize>;
@@ -36,14 +118,89 @@ pub trait DevFile: Send + Sync {
     fn physical_memory(&self) -> Result<Option<Box<dyn Iterator<Item = PhysFrame> + '_>>> {
         Ok(None)
     }
+
+    /// Device files are always ready unless they say otherwise.
+    fn poll(&self, interest: PollEvents) -> Result<PollEvents> {
+        Ok(interest & (PollEvents::POLLIN | PollEvents::POLLOUT))
+    }
+
+    /// Device files that are not always ready wake this queue when they may
+    /// have become ready, see [`FileSystem::wait_queue`].
+    fn wait_queue(&self) -> Option<Arc<WaitQueue>> {
+        None
+    }
+}
+
+/// Fills in the stat of a device like `/dev/null`, that everyone can read and
+/// write, and that has no size.
+fn stat_memory_device(stat: &mut Stat) {
+    stat.mode = FileMode::S_IFCHR
+        | FileMode::S_IRUSR
+        | FileMode::S_IWUSR
+        | FileMode::S_IRGRP
+        | FileMode::S_IWGRP
+        | FileMode::S_IROTH
+        | FileMode::S_IWOTH;
+    stat.nlink = 1;
+    stat.size = 0;
+    stat.blksize = 0;
+    stat.blocks = 0;
+}
+
+/// Lists `/dev/fd`, which has an entry for every file descriptor of the calling
+/// process. The entries are listed as symbolic links, because their type is
+/// the type of the file that the descriptor refers to.
+fn read_fd_dir(cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+    let mut entries = FVec::new();
+    if cookie > 0 {
+        return Ok((entries, cookie));
+    }
+    for fileno in process::current().open_fds().read().keys() {
+        entries
+            .try_push(DirEntry::new(fileno.to_string(), FileType::SymbolicLink))
+            .map_err(|_| VfsError::NoSpace)?;
+    }
+    Ok((entries, 1))
 }
 
 pub type OpenFileFn<'a> = dyn Fn() -> Box<dyn DevFile> + 'a + Send + Sync;
 
+/// The inode number of the root directory.
+const ROOT_INO: u64 = 1;
+/// The inode number of `/dev/fd`. Device files get numbers after it in the
+/// order in which they are registered.
+const FD_DIR_INO: u64 = 2;
+/// The name of the directory that contains an entry for every file descriptor
+/// of the calling process.
+const FD_DIR: &str = "fd";
+
+/// A registered device file.
+struct DevEntry<'a> {
+    ino: u64,
+    open_fn: Box<OpenFileFn<'a>>,
+}
+
+/// What a handle of the devfs refers to.
+enum DevNode {
+    /// The root directory, which contains all device files and [`DevNode::FdDir`].
+    Root,
+    /// `/dev/fd`, which contains an entry for every file descriptor of the
+    /// calling process.
+    FdDir,
+    /// An entry of `/dev/fd`, which stands for the file descriptor with the
+    /// same number. The VFS opens the file of the descriptor instead.
+    Fd(Fileno),
+    File {
+        ino: u64,
+        file: Box<dyn DevFile>,
+    },
+}
+
 pub struct VirtualDevFs<'a> {
     fsid: FsId,
-    handles: BTreeMap<VfsHandle, Box<dyn DevFile>>,
-    open_functions: BTreeMap<String, Box<OpenFileFn<'a>>>,
+    handles: BTreeMap<VfsHandle, DevNode>,
+    open_functions: BTreeMap<String, DevEntry<'a>>,
+    next_ino: u64,
 }
 
 impl<'a> VirtualDevFs<'a> {
@@ -52,45 +209,157 @@ impl<'a> VirtualDevFs<'a> {
             fsid,
             handles: BTreeMap::new(),
             open_functions: BTreeMap::new(),
+            next_ino: FD_DIR_INO + 1,
         };
 
-        res.register_file("/zero", || Box::new(Zero));
-        res.register_file("/null", || Box::new(Zero));
-        res.register_file("/stdin", || Box::new(stdio::STDIN));
-        res.register_file("/stdout", || Box::new(stdio::STDOUT));
-        res.register_file("/stderr", || Box::new(stdio::STDERR));
+        // the file system is empty, so none of these can already exist
+        let _ = res.register_file("/null", || Box::new(Null));
+        let _ = res.register_file("/zero", || Box::new(Zero));
+        let _ = res.register_file("/full", || Box::new(Full));
+        let _ = res.register_file("/urandom", || Box::new(Urandom));
+        let _ = res.register_file("/stdin", || Box::new(stdio::STDIN));
+        let _ = res.register_file("/stdout", || Box::new(stdio::STDOUT));
+        let _ = res.register_file("/stderr", || Box::new(stdio::STDERR));
+        let _ = res.register_file("/console", || Box::new(Console));
 
         for (i, fb) in fb::find_fbs().enumerate() {
-            res.register_file(format!("/fb{i}"), move || Box::new(fb.clone()));
+            let _ = res.register_file(format!("/fb{i}"), move || Box::new(fb.clone()));
+        }
+
+        for (i, device) in disk::devices().lock().iter().enumerate() {
+            let _ = res.register_block_device(i, device.clone());
+        }
+        for partition in disk::partitions().lock().iter() {
+            let _ = res.register_partition(partition);
         }
 
         res
     }
 
+    /// Registers a device file at the given path, which must start with a `/`.
+    /// `open_fn` is called every time the file is opened, and the device it
+    /// returns belongs to the opened handle.
+    ///
+    /// New files are visible to lookups and directory listings immediately.
+    /// Returns [`VfsError::AlreadyExists`] if there already is a file at that
+    /// path.
     pub fn register_file<F: Fn() -> Box<dyn DevFile> + 'a + Send + Sync>(
         &mut self,
         path: impl AsRef<str>,
         open_fn: F,
-    ) {
+    ) -> Result<()> {
+        let path = path.as_ref();
+        if self.open_functions.contains_key(path) {
+            return Err(VfsError::AlreadyExists);
+        }
+        // numbers are not reused, so that a file that is registered again
+        // isn't mistaken for the one that was removed
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.open_functions.insert(
+            path.to_string(),
+            DevEntry {
+                ino,
+                open_fn: Box::new(open_fn),
+            },
+        );
+        Ok(())
+    }
+
+    /// Removes the device file at the given path, so that it can't be opened
+    /// anymore. Handles that are already open keep their device and continue
+    /// to work until they are closed, which is when the device is dropped.
+    ///
+    /// Returns [`VfsError::NoSuchFile`] if there is no file at that path.
+    #[allow(dead_code)]
+    pub fn unregister_file(&mut self, path: impl AsRef<str>) -> Result<()> {
         self.open_functions
-            .insert(path.as_ref().to_string(), Box::new(open_fn));
+            .remove(path.as_ref())
+            .map(|_| ())
+            .ok_or(VfsError::NoSuchFile)
+    }
+
+    /// Publishes the disk with the given index as `/dev/blk{index}`, and what
+    /// an ATA drive reported about itself as `/dev/blk{index}.info`.
+    pub fn register_block_device(&mut self, index: usize, device: Revocable<Disk>) -> Result<()> {
+        if let Some(info) = device.info() {
+            let info = Text::from(info.to_string());
+            self.register_file(format!("/blk{index}.info"), move || Box::new(info.clone()))?;
+        }
+        // only IDE drives are asked for their SMART data so far
+        if let Some(ide) = device.filter_map(Disk::as_ide) {
+            if ide.info().smart && !ide.is_atapi() {
+                let smart = Smart::from(ide);
+                self.register_file(format!("/blk{index}.smart"), move || {
+                    Box::new(smart.clone())
+                })?;
+            }
+        }
+        let block = Block::from(device);
+        self.register_file(format!("/blk{index}"), move || Box::new(block.clone()))
+    }
+
+    /// Removes `/dev/blk{index}`, its info, its SMART data and the files of
+    /// its partitions.
+    /// Handles that are still open fail because the device is revoked.
+    pub fn unregister_block_device(&mut self, index: usize) {
+        let device = format!("/blk{index}");
+        let info = format!("{device}.info");
+        let smart = format!("{device}.smart");
+        let partition_prefix = format!("{device}p");
+        self.open_functions.retain(|path, _| {
+            *path != device
+                && *path != info
+                && *path != smart
+                && !path.starts_with(&partition_prefix)
+        });
+    }
+
+    /// Publishes the partition as `/dev/blk{device}p{number}`.
+    pub fn register_partition(&mut self, partition: &DiskPartition) -> Result<()> {
+        let number = partition.partition.extent().number;
+        let block = Block::from(partition.partition.clone());
+        self.register_file(format!("/blk{}p{number}", partition.device), move || {
+            Box::new(block.clone())
+        })
     }
 }
 
 impl VirtualDevFs<'_> {
     fn get_impl(&self, handle: VfsHandle) -> Result<&dyn DevFile> {
         match self.handles.get(&handle) {
-            Some(v) => Ok(v.as_ref()),
+            Some(DevNode::File { file, .. }) => Ok(file.as_ref()),
+            Some(DevNode::Root | DevNode::FdDir) => Err(VfsError::IsDirectory),
+            Some(DevNode::Fd(_)) => Err(VfsError::Unsupported),
             None => Err(VfsError::NoSuchFile),
         }
     }
 
     fn get_impl_mut(&mut self, handle: VfsHandle) -> Result<&mut dyn DevFile> {
         match self.handles.get_mut(&handle) {
-            Some(v) => Ok(v.as_mut()),
+            Some(DevNode::File { file, .. }) => Ok(file.as_mut()),
+            Some(DevNode::Root | DevNode::FdDir) => Err(VfsError::IsDirectory),
+            Some(DevNode::Fd(_)) => Err(VfsError::Unsupported),
             None => Err(VfsError::NoSuchFile),
         }
     }
+
+    fn lookup_fd(&mut self, name: &str) -> Result<VfsHandle> {
+        // only the canonical spelling of a number, so that every descriptor
+        // has exactly one name
+        let fileno = name
+            .parse::<usize>()
+            .ok()
+            .filter(|fileno| fileno.to_string() == name)
+            .ok_or(VfsError::NoSuchFile)?;
+        Ok(self.insert_handle(DevNode::Fd(Fileno::new(fileno))))
+    }
+
+    fn insert_handle(&mut self, node: DevNode) -> VfsHandle {
+        let handle = next_handle();
+        self.handles.insert(handle, node);
+        handle
+    }
 }
 
 impl FileSystem for VirtualDevFs<'_> {
@@ -98,14 +367,32 @@ impl FileSystem for VirtualDevFs<'_> {
         self.fsid
     }
 
-    fn open(&mut self, path: &Path) -> Result<VfsHandle> {
-        let implementation = self
+    fn name(&self) -> &'static str {
+        "devfs"
+    }
+
+    fn root(&mut self) -> Result<VfsHandle> {
+        Ok(self.insert_handle(DevNode::Root))
+    }
+
+    fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+        match self.handles.get(&parent) {
+            Some(DevNode::Root) if name == FD_DIR => return Ok(self.insert_handle(DevNode::FdDir)),
+            Some(DevNode::Root) => {}
+            Some(DevNode::FdDir) => return self.lookup_fd(name),
+            Some(DevNode::File { .. } | DevNode::Fd(_)) => return Err(VfsError::NotDirectory),
+            None => return Err(VfsError::HandleClosed),
+        }
+
+        let entry = self
             .open_functions
-            .get(path.to_string().as_str())
-            .ok_or(VfsError::NoSuchFile)?();
-        let handle = next_handle();
-        self.handles.insert(handle, implementation);
-        Ok(handle)
+            .get(format!("/{name}").as_str())
+            .ok_or(VfsError::NoSuchFile)?;
+        let node = DevNode::File {
+            ino: entry.ino,
+            file: (entry.open_fn)(),
+        };
+        Ok(self.insert_handle(node))
     }
 
     fn close(&mut self, handle: VfsHandle) -> Result<()> {
@@ -113,25 +400,44 @@ impl FileSystem for VirtualDevFs<'_> {
         Ok(())
     }
 
-    fn read_dir(&mut self, path: &Path) -> Result<Vec<DirEntry>> {
-        if path.as_str() != "" {
-            return Err(VfsError::NoSuchFile);
+    fn file_descriptor(&self, handle: VfsHandle) -> Option<Fileno> {
+        match self.handles.get(&handle) {
+            Some(DevNode::Fd(fileno)) => Some(*fileno),
+            _ => None,
         }
+    }
 
-        let mut entries = Vec::with_capacity(self.open_functions.len());
+    fn read_dir(&mut self, handle: VfsHandle, cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+        match self.handles.get(&handle) {
+            Some(DevNode::Root) => {}
+            Some(DevNode::FdDir) => return read_fd_dir(cookie),
+            Some(DevNode::File { .. } | DevNode::Fd(_)) => return Err(VfsError::NotDirectory),
+            None => return Err(VfsError::HandleClosed),
+        }
 
-        for (name, open_fn) in &self.open_functions {
+        // the fd directory comes first, after that the cookie is one more than
+        // the index of the next device in the list of registered devices
+        let mut entries = FVec::new();
+        if cookie == 0 {
+            entries
+                .try_push(DirEntry::new(FD_DIR.to_string(), FileType::Directory))
+                .map_err(|_| VfsError::NoSpace)?;
+        }
+        let skip = cookie.saturating_sub(1) as usize;
+        for (name, entry) in self.open_functions.iter().skip(skip) {
             let mut stat = Stat::default();
-            if open_fn().stat(&mut stat).is_err() {
+            if (entry.open_fn)().stat(&mut stat).is_err() {
                 continue;
             }
-            entries.push(DirEntry {
-                name: name.chars().skip(1).collect(),
-                typ: stat.mode.into(),
-            });
+            entries
+                .try_push(DirEntry {
+                    name: name.chars().skip(1).collect(),
+                    typ: stat.mode.into(),
+                })
+                .map_err(|_| VfsError::NoSpace)?;
         }
 
-        Ok(entries)
+        Ok((entries, self.open_functions.len() as u64 + 1))
     }
 
     fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
@@ -143,25 +449,288 @@ impl FileSystem for VirtualDevFs<'_> {
     }
 
     fn truncate(&mut self, _handle: VfsHandle, _size: usize) -> Result<()> {
-        todo!()
+        // devices don't have a length that could be changed
+        Err(VfsError::InvalidArgument)
     }
 
     fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
-        self.get_impl(handle)?.stat(stat)
+        match self.handles.get(&handle) {
+            Some(DevNode::Root) => {
+                stat.ino = ROOT_INO;
+                stat.mode = FileMode::S_IFDIR
+                    | FileMode::S_IRWXU
+                    | FileMode::S_IRGRP
+                    | FileMode::S_IXGRP
+                    | FileMode::S_IROTH
+                    | FileMode::S_IXOTH;
+                // the fd directory links back to the root
+                stat.nlink = 3;
+            }
+            Some(DevNode::FdDir) => {
+                stat.ino = FD_DIR_INO;
+                stat.mode = FileMode::S_IFDIR
+                    | FileMode::S_IRUSR
+                    | FileMode::S_IXUSR
+                    | FileMode::S_IRGRP
+                    | FileMode::S_IXGRP
+                    | FileMode::S_IROTH
+                    | FileMode::S_IXOTH;
+                stat.nlink = 2;
+            }
+            // the entries only exist to be resolved by the VFS, which never
+            // returns them
+            Some(DevNode::Fd(_)) => return Err(VfsError::Unsupported),
+            Some(DevNode::File { ino, file }) => {
+                file.stat(stat)?;
+                stat.ino = *ino;
+            }
+            None => return Err(VfsError::NoSuchFile),
+        }
+
+        // devices don't keep timestamps, they always look like they were just
+        // accessed and modified
+        let now = Timespec::from(Instant::now().duration_since(Instant::new(0)));
+        stat.dev = self.fsid.0;
+        stat.atime = now;
+        stat.mtime = now;
+        stat.ctime = now;
+        Ok(())
     }
 
-    fn create(&mut self, _: &Path, _: FileType) -> Result<()> {
-        Err(VfsError::Unsupported)
+    fn create(&mut self, _: &Path, _: FileType, _: FileMode) -> Result<()> {
+        Err(VfsError::PermissionDenied)
     }
 
     fn remove(&mut self, _: &Path) -> Result<()> {
-        Err(VfsError::Unsupported)
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn rename(&mut self, _: &Path, _: &Path) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn mmap(&mut self, handle: VfsHandle, offset: usize, len: usize) -> Result<MmapBacking> {
+        let frames = self
+            .get_impl(handle)?
+            .physical_memory()?
+            .ok_or(VfsError::Unsupported)?;
+        let frame_size = Size4KiB::SIZE as usize;
+        let frames = frames
+            .skip(offset / frame_size)
+            .take(len.div_ceil(frame_size))
+            .collect::<Vec<_>>();
+        // the device memory must cover the whole mapping
+        if frames.len() * frame_size < len {
+            return Err(VfsError::Overflow);
+        }
+        Ok(MmapBacking::Physical(frames))
+    }
+
+    fn poll(&self, handle: VfsHandle, interest: PollEvents) -> Result<PollEvents> {
+        self.get_impl(handle)?.poll(interest)
     }
 
-    fn physical_memory(
-        &self,
+    fn wait_queue(&self, handle: VfsHandle) -> Option<Arc<WaitQueue>> {
+        self.get_impl(handle).ok()?.wait_queue()
+    }
+
+    fn ioctl(
+        &mut self,
         handle: VfsHandle,
-    ) -> Result<Option<Box<dyn Iterator<Item = PhysFrame> + '_>>> {
-        self.get_impl(handle)?.physical_memory()
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        self.get_impl_mut(handle)?.ioctl(request, arg)
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::string::ToString;
+    use alloc::sync::Arc;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::Relaxed;
+
+    use kernel_api::syscall::{Errno, OpenFlags, Stat};
+    use kernel_test_framework::kernel_test;
+    use spin::RwLock;
+
+    use crate::io::vfs::devfs::{stat_memory_device, DevFile, DeviceIoctl, VirtualDevFs};
+    use crate::io::vfs::error::Result;
+    use crate::io::vfs::{vfs, FsId, MountFlags, Vfs, VfsError};
+    use crate::syscall::{sys_close, sys_open, sys_pipe, sys_read, sys_write};
+
+    /// A device that reads as its value and counts how many instances are alive.
+    struct Counted {
+        value: u8,
+        alive: Arc<AtomicUsize>,
+    }
+
+    impl Counted {
+        fn new(value: u8, alive: Arc<AtomicUsize>) -> Self {
+            alive.fetch_add(1, Relaxed);
+            Self { value, alive }
+        }
+    }
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.alive.fetch_sub(1, Relaxed);
+        }
+    }
+
+    impl DeviceIoctl for Counted {}
+
+    impl DevFile for Counted {
+        fn read(&self, buf: &mut [u8], _: usize) -> Result<usize> {
+            buf.fill(self.value);
+            Ok(buf.len())
+        }
+
+        fn write(&mut self, _: &[u8], _: usize) -> Result<usize> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn stat(&self, stat: &mut Stat) -> Result<()> {
+            stat_memory_device(stat);
+            Ok(())
+        }
+    }
+
+    #[kernel_test]
+    fn test_register_and_unregister() {
+        let devfs = Arc::new(RwLock::new(VirtualDevFs::new(FsId::new())));
+        let vfs = Vfs::new();
+        vfs.mount_shared("/", devfs.clone(), MountFlags::empty())
+            .unwrap();
+        let alive = Arc::new(AtomicUsize::new(0));
+
+        let open_alive = alive.clone();
+        devfs
+            .write()
+            .register_file("/counted", move || {
+                Box::new(Counted::new(0x42, open_alive.clone()))
+            })
+            .unwrap();
+        assert_eq!(
+            Err(VfsError::AlreadyExists),
+            devfs
+                .write()
+                .register_file("/counted", || Box::new(super::Null))
+        );
+        assert!(vfs.read_dir("/").unwrap().any(|e| e.name == "counted"));
+
+        let node = vfs.open("/counted").unwrap();
+        assert_eq!(1, alive.load(Relaxed));
+
+        devfs.write().unregister_file("/counted").unwrap();
+        assert_eq!(Err(VfsError::NoSuchFile), vfs.open("/counted").map(|_| ()));
+        assert!(!vfs.read_dir("/").unwrap().any(|e| e.name == "counted"));
+        assert_eq!(
+            Err(VfsError::NoSuchFile),
+            devfs.write().unregister_file("/counted")
+        );
+
+        // the handle that was opened before keeps working until it is closed
+        let mut buf = [0_u8; 4];
+        assert_eq!(Ok(buf.len()), vfs.read(&node, &mut buf, 0));
+        assert_eq!([0x42; 4], buf);
+        assert_eq!(1, alive.load(Relaxed));
+
+        drop(node);
+        assert_eq!(0, alive.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_memory_devices() {
+        let mut buf = [0xAA_u8; 64];
+
+        let null = vfs().open("/dev/null").unwrap();
+        assert_eq!(Ok(0), vfs().read(&null, &mut buf, 0));
+        assert_eq!(Ok(buf.len()), vfs().write(&null, buf, 1 << 20));
+
+        let zero = vfs().open("/dev/zero").unwrap();
+        assert_eq!(Ok(buf.len()), vfs().read(&zero, &mut buf, 1 << 20));
+        assert_eq!([0; 64], buf);
+
+        let full = vfs().open("/dev/full").unwrap();
+        buf.fill(0xAA);
+        assert_eq!(Ok(buf.len()), vfs().read(&full, &mut buf, 0));
+        assert_eq!([0; 64], buf);
+        assert_eq!(Err(VfsError::NoSpace), vfs().write(&full, buf, 0));
+
+        for node in [null, zero, full] {
+            let mut stat = Stat::default();
+            vfs().stat(&node, &mut stat).unwrap();
+            assert!(stat.mode.is_char_device());
+            assert_eq!(0, stat.size);
+        }
+    }
+
+    #[kernel_test]
+    fn test_block_device_info() {
+        let node = vfs().open("/dev/blk1.info").unwrap();
+        let mut buf = [0_u8; 512];
+        let n = vfs().read(&node, &mut buf, 0).unwrap();
+        let text = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(text.starts_with("model: QEMU HARDDISK\n"), "{text}");
+        assert!(text.contains("lba48: yes\n"), "{text}");
+        assert_eq!(Err(VfsError::PermissionDenied), vfs().write(&node, b"x", 0));
+    }
+
+    #[kernel_test]
+    fn test_block_device_smart() {
+        let node = vfs().open("/dev/blk1.smart").unwrap();
+        let mut buf = [0_u8; 4096];
+        let n = vfs().read(&node, &mut buf, 0).unwrap();
+        let text = core::str::from_utf8(&buf[..n]).unwrap();
+        assert!(text.starts_with("status: healthy\n"), "{text}");
+        assert!(text.contains("(reallocated sectors)"), "{text}");
+    }
+
+    #[kernel_test]
+    fn test_urandom() {
+        let urandom = vfs().open("/dev/urandom").unwrap();
+        let mut first = [0_u8; 64];
+        let mut second = [0_u8; 64];
+        assert_eq!(Ok(first.len()), vfs().read(&urandom, &mut first, 0));
+        assert_eq!(Ok(second.len()), vfs().read(&urandom, &mut second, 0));
+        assert_ne!(first, second);
+        assert!(first.iter().any(|&b| b != 0));
+    }
+
+    #[kernel_test]
+    fn test_fd_dir() {
+        let (read, write) = sys_pipe().unwrap();
+
+        let names = vfs()
+            .read_dir("/dev/fd")
+            .unwrap()
+            .map(|e| e.name)
+            .collect::<alloc::vec::Vec<_>>();
+        assert!(names.contains(&read.to_string()));
+        assert!(names.contains(&write.to_string()));
+
+        // the entry opens the pipe that the descriptor refers to
+        let flags = OpenFlags::O_WRONLY.bits() as usize;
+        let alias = sys_open(format!("/dev/fd/{write}"), flags, 0).unwrap();
+        assert_eq!(Ok(5), sys_write(alias, b"hello"));
+        let mut buf = [0_u8; 8];
+        assert_eq!(Ok(5), sys_read(read, &mut buf));
+        assert_eq!(b"hello", &buf[..5]);
+
+        sys_close(alias).unwrap();
+        sys_close(write).unwrap();
+        assert_eq!(
+            Err(Errno::ENOENT),
+            sys_open(format!("/dev/fd/{write}"), flags, 0)
+        );
+        assert_eq!(
+            Err(Errno::ENOENT),
+            sys_open(format!("/dev/fd/0{read}"), 0, 0)
+        );
+        sys_close(read).unwrap();
     }
 }
@@ -0,0 +1,24 @@
+use kernel_api::syscall::Stat;
+
+use crate::io::vfs::devfs::{stat_memory_device, DevFile, DeviceIoctl};
+use crate::io::vfs::error::Result;
+
+/// `/dev/null`, which is always at the end of the file and discards writes.
+pub struct Null;
+
+impl DeviceIoctl for Null {}
+
+impl DevFile for Null {
+    fn read(&self, _: &mut [u8], _: usize) -> Result<usize> {
+        Ok(0)
+    }
+
+    fn write(&mut self, buf: &[u8], _: usize) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat_memory_device(stat);
+        Ok(())
+    }
+}
@@ -0,0 +1,67 @@
+use alloc::format;
+use alloc::string::String;
+use core::fmt::Write;
+
+use kernel_api::syscall::{FileMode, Stat};
+
+use crate::driver::block::Revocable;
+use crate::driver::ide::{IdeBlockDevice, SmartError};
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
+use crate::io::vfs::error::{Result, VfsError};
+
+/// The SMART health of a drive, like `/dev/blk0.smart`. Every read asks the
+/// drive, so that monitoring tools see the current values.
+#[derive(Clone)]
+pub struct Smart {
+    device: Revocable<IdeBlockDevice>,
+}
+
+impl From<Revocable<IdeBlockDevice>> for Smart {
+    fn from(device: Revocable<IdeBlockDevice>) -> Self {
+        Self { device }
+    }
+}
+
+impl Smart {
+    /// A line with the overall status, followed by the attribute table.
+    fn report(&self) -> core::result::Result<String, SmartError> {
+        let status = self.device.smart_status()?;
+        let attributes = self.device.smart_attributes()?;
+        let mut report = format!("status: {status}\n id  flags cur wst thr raw\n");
+        for attribute in attributes {
+            let _ = writeln!(report, "{attribute}");
+        }
+        Ok(report)
+    }
+}
+
+impl DeviceIoctl for Smart {}
+
+impl DevFile for Smart {
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize> {
+        if self.device.is_revoked() {
+            return Err(VfsError::DeviceGone);
+        }
+        let report = self.report().map_err(|_| VfsError::ReadError)?;
+        let Some(rest) = report.as_bytes().get(offset..) else {
+            return Ok(0);
+        };
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, _: &[u8], _: usize) -> Result<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat.mode = FileMode::S_IFREG | FileMode::S_IRUSR | FileMode::S_IRGRP | FileMode::S_IROTH;
+        stat.nlink = 1;
+        // the length isn't known without asking the drive
+        stat.size = 0;
+        stat.blksize = 0;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
@@ -1,6 +1,6 @@
 use kernel_api::syscall::{FileMode, Stat};
 
-use crate::io::vfs::devfs::DevFile;
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
 use crate::io::vfs::error::Result;
 use crate::io::vfs::VfsError;
 use crate::process::fd::Fileno;
@@ -13,6 +13,8 @@ pub const STDERR: StdFile = StdFile(Fileno::new(2));
 #[derive(Debug, Eq, PartialEq)]
 pub struct StdFile(Fileno);
 
+impl DeviceIoctl for StdFile {}
+
 impl DevFile for StdFile {
     fn read(&self, _: &mut [u8], _: usize) -> Result<usize> {
         unimplemented!()
@@ -28,10 +30,10 @@ impl DevFile for StdFile {
     }
 
     fn stat(&self, stat: &mut Stat) -> Result<()> {
-        // TODO: ino, dev, nlink, uid, gid, rdev
+        // TODO: rdev
 
-        stat.mode |= FileMode::S_IFCHR; // TODO: permissions
-        stat.nlink = 1; // TODO: can this change?
+        stat.mode = FileMode::S_IFCHR | FileMode::S_IRUSR | FileMode::S_IWUSR | FileMode::S_IWGRP;
+        stat.nlink = 1;
         stat.size = 0;
         stat.blksize = 0;
         stat.blocks = 0;
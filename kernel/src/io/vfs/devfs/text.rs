@@ -0,0 +1,46 @@
+use alloc::sync::Arc;
+
+use kernel_api::syscall::{FileMode, Stat};
+
+use crate::io::vfs::devfs::{DevFile, DeviceIoctl};
+use crate::io::vfs::error::{Result, VfsError};
+
+/// A read-only file with fixed text, like `/dev/blk0.info`, which describes a
+/// device.
+#[derive(Clone)]
+pub struct Text {
+    text: Arc<str>,
+}
+
+impl<T: Into<Arc<str>>> From<T> for Text {
+    fn from(text: T) -> Self {
+        Self { text: text.into() }
+    }
+}
+
+impl DeviceIoctl for Text {}
+
+impl DevFile for Text {
+    fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize> {
+        let bytes = self.text.as_bytes();
+        let Some(rest) = bytes.get(offset..) else {
+            return Ok(0);
+        };
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        Ok(n)
+    }
+
+    fn write(&mut self, _: &[u8], _: usize) -> Result<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat.mode = FileMode::S_IFREG | FileMode::S_IRUSR | FileMode::S_IRGRP | FileMode::S_IROTH;
+        stat.nlink = 1;
+        stat.size = self.text.len() as u64;
+        stat.blksize = 0;
+        stat.blocks = 0;
+        Ok(())
+    }
+}
@@ -0,0 +1,28 @@
+use kernel_api::syscall::Stat;
+
+use crate::io::vfs::devfs::{stat_memory_device, DevFile, DeviceIoctl};
+use crate::io::vfs::error::Result;
+use crate::random;
+
+/// `/dev/urandom`, which reads from the kernel's random number generator.
+/// Writes are mixed into the generator.
+pub struct Urandom;
+
+impl DeviceIoctl for Urandom {}
+
+impl DevFile for Urandom {
+    fn read(&self, buf: &mut [u8], _: usize) -> Result<usize> {
+        random::fill_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn write(&mut self, buf: &[u8], _: usize) -> Result<usize> {
+        random::add_entropy(buf);
+        Ok(buf.len())
+    }
+
+    fn stat(&self, stat: &mut Stat) -> Result<()> {
+        stat_memory_device(stat);
+        Ok(())
+    }
+}
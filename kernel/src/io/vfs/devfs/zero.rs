@@ -1,10 +1,13 @@
-use kernel_api::syscall::{FileMode, Stat};
+use kernel_api::syscall::Stat;
 
-use crate::io::vfs::devfs::DevFile;
+use crate::io::vfs::devfs::{stat_memory_device, DevFile, DeviceIoctl};
 use crate::io::vfs::error::Result;
 
+/// `/dev/zero`, which reads as an endless stream of zeros and discards writes.
 pub struct Zero;
 
+impl DeviceIoctl for Zero {}
+
 impl DevFile for Zero {
     fn read(&self, buf: &mut [u8], _: usize) -> Result<usize> {
         buf.fill(0);
@@ -16,14 +19,7 @@ impl DevFile for Zero {
     }
 
     fn stat(&self, stat: &mut Stat) -> Result<()> {
-        // TODO: ino, dev, nlink, uid, gid, rdev
-
-        stat.mode |= FileMode::S_IFCHR; // TODO: permissions
-        stat.nlink = 1; // TODO: can this change?
-        stat.size = 0;
-        stat.blksize = 0;
-        stat.blocks = 0;
-
+        stat_memory_device(stat);
         Ok(())
     }
 }
@@ -2,7 +2,7 @@ use kernel_api::syscall::Errno;
 
 pub(crate) type Result<T> = core::result::Result<T, VfsError>;
 
-#[derive(Debug)]
+#[derive(Debug, Eq, PartialEq)]
 pub enum VfsError {
     /// There is no file system associated with the given path or file system id.
     NoSuchFileSystem,
@@ -18,6 +18,56 @@ pub enum VfsError {
     ReadError,
     WriteError,
     NoSpace,
+    /// The operation would block, for example because a pipe has no data available.
+    WouldBlock,
+    /// A node already exists at the given path.
+    AlreadyExists,
+    /// The directory can't be removed because it still has entries.
+    NotEmpty,
+    /// The operation expected something other than a directory.
+    IsDirectory,
+    /// The operation expected a directory, or a path component is not a directory.
+    NotDirectory,
+    /// The file system doesn't allow this operation at all, for example
+    /// creating files in devfs.
+    PermissionDenied,
+    /// The node is in use, for example because a file system is mounted on it.
+    Busy,
+    /// The operation would cross file system boundaries, which it can't.
+    CrossDevice,
+    /// The arguments don't make sense for this operation, for example moving
+    /// a directory into itself.
+    InvalidArgument,
+    /// A file system is already mounted at the given path.
+    AlreadyMounted,
+    /// No file system is mounted at the given path.
+    NotMounted,
+    /// Resolving a path followed too many symbolic links, probably because
+    /// they form a loop.
+    SymlinkLoop,
+    /// An offset or length lies beyond what the node can represent, for
+    /// example mapping a file at an offset past its end.
+    Overflow,
+    /// The file would grow beyond the maximum size that the file system
+    /// supports.
+    FileTooLarge,
+    /// Waiting for a lock would never end, because another holder of the
+    /// lock waits for this one.
+    Deadlock,
+    /// Waiting was cancelled, for example because the process is terminating.
+    Interrupted,
+    /// The operation would change a file system that is mounted read-only.
+    ReadOnly,
+    /// The node already has as many names as the file system supports.
+    TooManyLinks,
+    /// The file was not opened for this kind of access, for example writing
+    /// to a file that was opened read-only.
+    NotOpenForAccess,
+    /// The device that the file system is on was removed.
+    DeviceGone,
+    /// The pipe has no readers anymore, so nothing that is written to it
+    /// would ever be read.
+    BrokenPipe,
 }
 
 impl From<VfsError> for Errno {
@@ -26,9 +76,28 @@ impl From<VfsError> for Errno {
             VfsError::NoSuchFileSystem => Errno::ENXIO,
             VfsError::NoSuchFile => Errno::ENOENT,
             VfsError::Unsupported => Errno::ENOSYS,
-            VfsError::HandleClosed => Errno::EBADF,
+            VfsError::HandleClosed | VfsError::NotOpenForAccess => Errno::EBADF,
             VfsError::ReadError | VfsError::WriteError => Errno::EIO,
             VfsError::NoSpace => Errno::ENOSPC,
+            VfsError::WouldBlock => Errno::EWOULDBLOCK,
+            VfsError::AlreadyExists => Errno::EEXIST,
+            VfsError::NotEmpty => Errno::ENOTEMPTY,
+            VfsError::IsDirectory => Errno::EISDIR,
+            VfsError::NotDirectory => Errno::ENOTDIR,
+            VfsError::PermissionDenied => Errno::EPERM,
+            VfsError::Busy => Errno::EBUSY,
+            VfsError::CrossDevice => Errno::EXDEV,
+            VfsError::InvalidArgument | VfsError::NotMounted => Errno::EINVAL,
+            VfsError::AlreadyMounted => Errno::EBUSY,
+            VfsError::SymlinkLoop => Errno::ELOOP,
+            VfsError::Overflow => Errno::EOVERFLOW,
+            VfsError::FileTooLarge => Errno::EFBIG,
+            VfsError::Deadlock => Errno::EDEADLK,
+            VfsError::Interrupted => Errno::EINTR,
+            VfsError::ReadOnly => Errno::EROFS,
+            VfsError::TooManyLinks => Errno::EMLINK,
+            VfsError::DeviceGone => Errno::ENODEV,
+            VfsError::BrokenPipe => Errno::EPIPE,
         }
     }
 }
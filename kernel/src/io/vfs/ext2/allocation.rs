@@ -0,0 +1,160 @@
+use alloc::vec;
+
+use crate::io::vfs::cache::Flush;
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::ext2::layout::{read_bytes, write_bytes, GroupDescriptor};
+use crate::io::vfs::ext2::VirtualExt2Fs;
+
+impl<T> VirtualExt2Fs<T>
+where
+    T: Flush,
+{
+    pub(super) fn group_of_inode(&self, num: u32) -> u32 {
+        (num - 1) / self.superblock.inodes_per_group()
+    }
+
+    /// Allocates a block, preferably in the given group, and fills it with
+    /// zeros, so that whatever points to it next never sees stale data.
+    pub(super) fn allocate_block(&mut self, goal_group: u32) -> Result<u32> {
+        let group_count = self.superblock.group_count();
+        for group in (0..group_count).map(|i| (goal_group + i) % group_count) {
+            let mut descriptor = GroupDescriptor::read(&self.device, &self.superblock, group)?;
+            if descriptor.free_blocks_count() == 0 {
+                continue;
+            }
+
+            let Some(bit) =
+                self.take_free_bit(descriptor.block_bitmap(), self.blocks_in_group(group), 0)?
+            else {
+                continue;
+            };
+            descriptor.set_free_blocks_count(descriptor.free_blocks_count() - 1);
+            descriptor.write(&mut self.device, &self.superblock, group)?;
+            let free = self.superblock.free_blocks_count();
+            self.superblock
+                .set_free_blocks_count(free.saturating_sub(1));
+            self.superblock.write(&mut self.device)?;
+
+            let block = self.superblock.first_data_block()
+                + group * self.superblock.blocks_per_group()
+                + bit;
+            let zeros = vec![0_u8; self.block_size()];
+            self.write_block(block, &zeros)?;
+            return Ok(block);
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    pub(super) fn free_block(&mut self, block: u32) -> Result<()> {
+        let index = block
+            .checked_sub(self.superblock.first_data_block())
+            .ok_or(VfsError::WriteError)?;
+        let group = index / self.superblock.blocks_per_group();
+        let mut descriptor = GroupDescriptor::read(&self.device, &self.superblock, group)?;
+        self.clear_bit(
+            descriptor.block_bitmap(),
+            index % self.superblock.blocks_per_group(),
+        )?;
+        descriptor.set_free_blocks_count(descriptor.free_blocks_count() + 1);
+        descriptor.write(&mut self.device, &self.superblock, group)?;
+        let free = self.superblock.free_blocks_count();
+        self.superblock.set_free_blocks_count(free + 1);
+        self.superblock.write(&mut self.device)
+    }
+
+    /// Allocates an inode, preferably in the given group. The inode itself is
+    /// not touched, the caller has to initialize it.
+    pub(super) fn allocate_inode(&mut self, goal_group: u32, directory: bool) -> Result<u32> {
+        let inodes_per_group = self.superblock.inodes_per_group();
+        let group_count = self.superblock.group_count();
+        for group in (0..group_count).map(|i| (goal_group + i) % group_count) {
+            let mut descriptor = GroupDescriptor::read(&self.device, &self.superblock, group)?;
+            if descriptor.free_inodes_count() == 0 {
+                continue;
+            }
+
+            // the first inodes are reserved, even if the bitmap says otherwise
+            let first =
+                (self.superblock.first_inode() - 1).saturating_sub(group * inodes_per_group);
+            let Some(bit) =
+                self.take_free_bit(descriptor.inode_bitmap(), inodes_per_group, first)?
+            else {
+                continue;
+            };
+            descriptor.set_free_inodes_count(descriptor.free_inodes_count() - 1);
+            if directory {
+                descriptor.set_used_dirs_count(descriptor.used_dirs_count() + 1);
+            }
+            descriptor.write(&mut self.device, &self.superblock, group)?;
+            let free = self.superblock.free_inodes_count();
+            self.superblock
+                .set_free_inodes_count(free.saturating_sub(1));
+            self.superblock.write(&mut self.device)?;
+
+            return Ok(group * inodes_per_group + bit + 1);
+        }
+        Err(VfsError::NoSpace)
+    }
+
+    pub(super) fn free_inode(&mut self, num: u32, directory: bool) -> Result<()> {
+        let group = self.group_of_inode(num);
+        let mut descriptor = GroupDescriptor::read(&self.device, &self.superblock, group)?;
+        self.clear_bit(
+            descriptor.inode_bitmap(),
+            (num - 1) % self.superblock.inodes_per_group(),
+        )?;
+        descriptor.set_free_inodes_count(descriptor.free_inodes_count() + 1);
+        if directory {
+            descriptor.set_used_dirs_count(descriptor.used_dirs_count().saturating_sub(1));
+        }
+        descriptor.write(&mut self.device, &self.superblock, group)?;
+        let free = self.superblock.free_inodes_count();
+        self.superblock.set_free_inodes_count(free + 1);
+        self.superblock.write(&mut self.device)
+    }
+
+    /// The last group might be smaller than the others.
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let blocks_per_group = self.superblock.blocks_per_group();
+        let remaining = self.superblock.blocks_count()
+            - self.superblock.first_data_block()
+            - group * blocks_per_group;
+        remaining.min(blocks_per_group)
+    }
+
+    /// Finds the first clear bit in `first..count` of the bitmap in the given
+    /// block, sets it and returns its index.
+    fn take_free_bit(&mut self, bitmap_block: u32, count: u32, first: u32) -> Result<Option<u32>> {
+        let mut bitmap = vec![0_u8; self.block_size()];
+        self.read_block(bitmap_block, &mut bitmap)?;
+        let Some(bit) =
+            (first..count).find(|&bit| bitmap[bit as usize / 8] & (1 << (bit % 8)) == 0)
+        else {
+            return Ok(None);
+        };
+        bitmap[bit as usize / 8] |= 1 << (bit % 8);
+        self.write_block(bitmap_block, &bitmap)?;
+        Ok(Some(bit))
+    }
+
+    fn clear_bit(&mut self, bitmap_block: u32, bit: u32) -> Result<()> {
+        let mut bitmap = vec![0_u8; self.block_size()];
+        self.read_block(bitmap_block, &mut bitmap)?;
+        let mask = 1 << (bit % 8);
+        if bitmap[bit as usize / 8] & mask == 0 {
+            // freeing something twice means that the file system is corrupted
+            return Err(VfsError::WriteError);
+        }
+        bitmap[bit as usize / 8] &= !mask;
+        self.write_block(bitmap_block, &bitmap)
+    }
+
+    pub(super) fn read_block(&self, block: u32, buf: &mut [u8]) -> Result<()> {
+        read_bytes(&self.device, block as u64 * self.block_size() as u64, buf)
+    }
+
+    pub(super) fn write_block(&mut self, block: u32, buf: &[u8]) -> Result<()> {
+        let offset = block as u64 * self.block_size() as u64;
+        write_bytes(&mut self.device, offset, buf)
+    }
+}
@@ -0,0 +1,375 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::io::vfs::cache::Flush;
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::ext2::layout::{
+    set_u16, set_u32, u16_at, u32_at, Inode, INCOMPAT_FILETYPE, INODE_FLAG_INDEX,
+};
+use crate::io::vfs::ext2::VirtualExt2Fs;
+use crate::io::vfs::FileType;
+
+/// The size of a directory entry without its name.
+const ENTRY_HEADER_SIZE: usize = 8;
+const MAX_NAME_LEN: usize = 255;
+
+pub struct RawDirEntry {
+    pub inode: u32,
+    pub name: String,
+    /// The type from the directory entry, if the file system stores it there.
+    pub file_type: Option<FileType>,
+}
+
+/// Names must fit into a directory entry and must not contain characters
+/// that have a special meaning in paths.
+pub fn check_name(name: &str) -> Result<()> {
+    if name.is_empty()
+        || name.len() > MAX_NAME_LEN
+        || name == "."
+        || name == ".."
+        || name.contains(['/', '\0'])
+    {
+        return Err(VfsError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// The space that an entry with a name of the given length needs.
+fn entry_size(name_len: usize) -> usize {
+    (ENTRY_HEADER_SIZE + name_len).next_multiple_of(4)
+}
+
+fn file_type_from_byte(byte: u8) -> Option<FileType> {
+    Some(match byte {
+        1 => FileType::RegularFile,
+        2 => FileType::Directory,
+        3 => FileType::CharacterDevice,
+        4 => FileType::BlockDevice,
+        5 => FileType::FIFO,
+        6 => FileType::Socket,
+        7 => FileType::SymbolicLink,
+        _ => return None,
+    })
+}
+
+fn file_type_to_byte(file_type: FileType) -> u8 {
+    match file_type {
+        FileType::RegularFile => 1,
+        FileType::Directory => 2,
+        FileType::CharacterDevice => 3,
+        FileType::BlockDevice => 4,
+        FileType::FIFO => 5,
+        FileType::Socket => 6,
+        FileType::SymbolicLink => 7,
+    }
+}
+
+/// A directory entry within a directory block, as it is on disk.
+struct EntryRef<'a> {
+    block: &'a [u8],
+    offset: usize,
+    has_file_type: bool,
+}
+
+impl EntryRef<'_> {
+    fn inode(&self) -> u32 {
+        u32_at(self.block, self.offset)
+    }
+
+    fn rec_len(&self) -> usize {
+        u16_at(self.block, self.offset + 4) as usize
+    }
+
+    fn name_len(&self) -> usize {
+        if self.has_file_type {
+            self.block[self.offset + 6] as usize
+        } else {
+            u16_at(self.block, self.offset + 6) as usize
+        }
+    }
+
+    fn name(&self) -> &[u8] {
+        let start = self.offset + ENTRY_HEADER_SIZE;
+        &self.block[start..start + self.name_len()]
+    }
+
+    fn file_type(&self) -> Option<FileType> {
+        if self.has_file_type {
+            file_type_from_byte(self.block[self.offset + 7])
+        } else {
+            None
+        }
+    }
+}
+
+/// Iterates over all entries in a directory block, including unused ones.
+/// Stops at the first entry that is malformed.
+fn entries(block: &[u8], has_file_type: bool) -> impl Iterator<Item = EntryRef<'_>> {
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        if offset + ENTRY_HEADER_SIZE > block.len() {
+            return None;
+        }
+        let entry = EntryRef {
+            block,
+            offset,
+            has_file_type,
+        };
+        let rec_len = entry.rec_len();
+        if rec_len < ENTRY_HEADER_SIZE
+            || offset + rec_len > block.len()
+            || ENTRY_HEADER_SIZE + entry.name_len() > rec_len
+        {
+            return None;
+        }
+        offset += rec_len;
+        Some(entry)
+    })
+}
+
+impl<T> VirtualExt2Fs<T>
+where
+    T: Flush,
+{
+    fn has_file_type(&self) -> bool {
+        self.superblock.has_incompat(INCOMPAT_FILETYPE)
+    }
+
+    fn dir_block_count(&self, dir: &Inode) -> usize {
+        (dir.size() as usize).div_ceil(self.block_size())
+    }
+
+    /// Reads the directory block with the given index. Holes are returned as
+    /// `None`.
+    fn read_dir_block(&self, dir: &Inode, index: usize) -> Result<Option<Vec<u8>>> {
+        match self.block_for_index(dir, index)? {
+            0 => Ok(None),
+            block => {
+                let mut data = vec![0_u8; self.block_size()];
+                self.read_block(block, &mut data)?;
+                Ok(Some(data))
+            }
+        }
+    }
+
+    /// Lists all used entries of the directory, including `.` and `..`.
+    pub(super) fn list_dir(&self, dir: &Inode) -> Result<Vec<RawDirEntry>> {
+        let has_file_type = self.has_file_type();
+        let mut result = Vec::new();
+        for index in 0..self.dir_block_count(dir) {
+            let Some(data) = self.read_dir_block(dir, index)? else {
+                continue;
+            };
+            for entry in entries(&data, has_file_type).filter(|entry| entry.inode() != 0) {
+                result.push(RawDirEntry {
+                    inode: entry.inode(),
+                    name: String::from_utf8_lossy(entry.name()).into_owned(),
+                    file_type: entry.file_type(),
+                });
+            }
+        }
+        Ok(result)
+    }
+
+    pub(super) fn find_entry(&self, dir: &Inode, name: &str) -> Result<Option<u32>> {
+        let has_file_type = self.has_file_type();
+        for index in 0..self.dir_block_count(dir) {
+            let Some(data) = self.read_dir_block(dir, index)? else {
+                continue;
+            };
+            let found = entries(&data, has_file_type)
+                .find(|entry| entry.inode() != 0 && entry.name() == name.as_bytes())
+                .map(|entry| entry.inode());
+            if found.is_some() {
+                return Ok(found);
+            }
+        }
+        Ok(None)
+    }
+
+    pub(super) fn is_empty_dir(&self, dir: &Inode) -> Result<bool> {
+        Ok(self
+            .list_dir(dir)?
+            .iter()
+            .all(|entry| entry.name == "." || entry.name == ".."))
+    }
+
+    /// Writes the first block of a new directory with the `.` and `..`
+    /// entries, and then the inode of the directory.
+    pub(super) fn init_dir(&mut self, num: u32, inode: &mut Inode, parent_num: u32) -> Result<()> {
+        let block_size = self.block_size();
+        let block = self.allocate_block_for_index(num, inode, 0)?;
+        let mut data = vec![0_u8; block_size];
+        let dot_len = entry_size(1);
+        self.write_entry(&mut data, 0, dot_len, num, ".", FileType::Directory);
+        self.write_entry(
+            &mut data,
+            dot_len,
+            block_size - dot_len,
+            parent_num,
+            "..",
+            FileType::Directory,
+        );
+        self.write_block(block, &data)?;
+
+        inode.set_size(block_size as u64);
+        inode.write_new(&mut self.device, &self.superblock, num)
+    }
+
+    /// Adds an entry for `child` to the directory. The entry is put into the
+    /// first gap that is large enough, or into a new block at the end of the
+    /// directory.
+    pub(super) fn add_entry(
+        &mut self,
+        dir_num: u32,
+        dir: &mut Inode,
+        name: &str,
+        child: u32,
+        file_type: FileType,
+    ) -> Result<()> {
+        let has_file_type = self.has_file_type();
+        let needed = entry_size(name.len());
+        let block_count = self.dir_block_count(dir);
+        for index in 0..block_count {
+            let Some(mut data) = self.read_dir_block(dir, index)? else {
+                continue;
+            };
+            let gap = entries(&data, has_file_type).find_map(|entry| {
+                let used = if entry.inode() == 0 {
+                    0
+                } else {
+                    entry_size(entry.name_len())
+                };
+                (entry.rec_len() - used >= needed).then_some((entry.offset, entry.rec_len(), used))
+            });
+            let Some((offset, rec_len, used)) = gap else {
+                continue;
+            };
+
+            if used > 0 {
+                // shrink the existing entry, the new one takes the rest of its space
+                set_u16(&mut data, offset + 4, used as u16);
+            }
+            self.write_entry(
+                &mut data,
+                offset + used,
+                rec_len - used,
+                child,
+                name,
+                file_type,
+            );
+            let block = self.block_for_index(dir, index)?;
+            self.write_block(block, &data)?;
+            return self.finish_dir_update(dir_num, dir);
+        }
+
+        // no gap is large enough, so the directory grows by one block
+        let block_size = self.block_size();
+        let block = self.allocate_block_for_index(dir_num, dir, block_count)?;
+        let mut data = vec![0_u8; block_size];
+        self.write_entry(&mut data, 0, block_size, child, name, file_type);
+        self.write_block(block, &data)?;
+        dir.set_size(((block_count + 1) * block_size) as u64);
+        self.finish_dir_update(dir_num, dir)
+    }
+
+    /// Removes the entry with the given name from the directory. Its space is
+    /// merged into the previous entry, or the entry is marked as unused if it
+    /// is the first one in its block.
+    pub(super) fn remove_entry(&mut self, dir_num: u32, dir: &mut Inode, name: &str) -> Result<()> {
+        let has_file_type = self.has_file_type();
+        for index in 0..self.dir_block_count(dir) {
+            let Some(mut data) = self.read_dir_block(dir, index)? else {
+                continue;
+            };
+
+            let mut previous: Option<(usize, usize)> = None;
+            let mut found = None;
+            for entry in entries(&data, has_file_type) {
+                if entry.inode() != 0 && entry.name() == name.as_bytes() {
+                    found = Some((entry.offset, entry.rec_len()));
+                    break;
+                }
+                previous = Some((entry.offset, entry.rec_len()));
+            }
+            let Some((offset, rec_len)) = found else {
+                continue;
+            };
+
+            match previous {
+                Some((previous_offset, previous_rec_len)) => set_u16(
+                    &mut data,
+                    previous_offset + 4,
+                    (previous_rec_len + rec_len) as u16,
+                ),
+                None => set_u32(&mut data, offset, 0),
+            }
+            let block = self.block_for_index(dir, index)?;
+            self.write_block(block, &data)?;
+            return self.finish_dir_update(dir_num, dir);
+        }
+        Err(VfsError::NoSuchFile)
+    }
+
+    /// Points the entry with the given name to `child` instead. The names in
+    /// the directory don't change, so neither does its hash tree index.
+    pub(super) fn replace_entry(
+        &mut self,
+        dir: &Inode,
+        name: &str,
+        child: u32,
+        file_type: FileType,
+    ) -> Result<()> {
+        let has_file_type = self.has_file_type();
+        for index in 0..self.dir_block_count(dir) {
+            let Some(mut data) = self.read_dir_block(dir, index)? else {
+                continue;
+            };
+            let Some(offset) = entries(&data, has_file_type)
+                .find(|entry| entry.inode() != 0 && entry.name() == name.as_bytes())
+                .map(|entry| entry.offset)
+            else {
+                continue;
+            };
+
+            set_u32(&mut data, offset, child);
+            if has_file_type {
+                data[offset + 7] = file_type_to_byte(file_type);
+            }
+            let block = self.block_for_index(dir, index)?;
+            return self.write_block(block, &data);
+        }
+        Err(VfsError::NoSuchFile)
+    }
+
+    fn write_entry(
+        &self,
+        data: &mut [u8],
+        offset: usize,
+        rec_len: usize,
+        inode: u32,
+        name: &str,
+        file_type: FileType,
+    ) {
+        set_u32(data, offset, inode);
+        set_u16(data, offset + 4, rec_len as u16);
+        if self.has_file_type() {
+            data[offset + 6] = name.len() as u8;
+            data[offset + 7] = file_type_to_byte(file_type);
+        } else {
+            set_u16(data, offset + 6, name.len() as u16);
+        }
+        let start = offset + ENTRY_HEADER_SIZE;
+        data[start..start + name.len()].copy_from_slice(name.as_bytes());
+    }
+
+    /// Writes the inode of a modified directory. The hash tree index of the
+    /// directory, if it has one, doesn't know about the modification, so the
+    /// directory falls back to a linear one. The tree nodes look like unused
+    /// directory entries, so the directory stays valid.
+    fn finish_dir_update(&mut self, dir_num: u32, dir: &mut Inode) -> Result<()> {
+        dir.set_flags(dir.flags() & !INODE_FLAG_INDEX);
+        self.write_inode(dir_num, dir)
+    }
+}
@@ -1,170 +1,670 @@
-use alloc::sync::Arc;
+use alloc::vec;
 
-use ext2::{Inode, InodeAddress, Permissions, Type};
 use filesystem::BlockDevice;
-use spin::RwLock;
 
-use kernel_api::syscall::{FileMode, Stat};
+use crate::io::vfs::cache::{BlockBuf, Flush, MultiBlock};
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::ext2::layout::{
+    read_bytes, read_bytes_async, set_u32, u32_at, write_bytes, Inode, DIRECT_BLOCKS,
+    DOUBLY_INDIRECT_BLOCK, SINGLY_INDIRECT_BLOCK, TRIPLY_INDIRECT_BLOCK,
+};
+use crate::io::vfs::ext2::VirtualExt2Fs;
 
-use crate::io::vfs::error::Result;
-use crate::io::vfs::{FsId, VfsError};
+/// The block pointers of the indirect trees, and how many levels of indirect
+/// blocks they have.
+const INDIRECT_TREES: [(usize, usize); 3] = [
+    (SINGLY_INDIRECT_BLOCK, 1),
+    (DOUBLY_INDIRECT_BLOCK, 2),
+    (TRIPLY_INDIRECT_BLOCK, 3),
+];
 
-pub struct Ext2Inode<T> {
-    fsid: FsId,
-    fs: Arc<RwLock<ext2::Ext2Fs<T>>>,
-    inode_num: InodeAddress,
-    inner: Inner,
+/// Where the pointer to a data block of a file is stored: in the block
+/// pointers of the inode, or at the end of a chain of indirect blocks.
+#[derive(Debug, Eq, PartialEq)]
+pub(super) struct BlockPath {
+    /// The index into the block pointers of the inode.
+    slot: usize,
+    /// The indices into the indirect blocks, starting with the one that the
+    /// inode points to.
+    indices: [usize; 3],
+    depth: usize,
 }
 
-impl<T> Ext2Inode<T>
-where
-    T: BlockDevice,
-{
-    pub fn new(
-        fsid: FsId,
-        fs: Arc<RwLock<ext2::Ext2Fs<T>>>,
-        inode_num: InodeAddress,
-        inode: Inode,
-    ) -> Self {
-        let inner = match inode.typ() {
-            Type::RegularFile => Inner::RegularFile((inode_num, inode).try_into().unwrap()),
-            Type::Directory => Inner::Directory((inode_num, inode).try_into().unwrap()),
-            _ => panic!("unsupported inode type"),
-        };
-        Self {
-            fsid,
-            fs,
-            inode_num,
-            inner,
-        }
+impl BlockPath {
+    fn indices(&self) -> &[usize] {
+        &self.indices[..self.depth]
     }
 }
 
-enum Inner {
-    RegularFile(ext2::RegularFile),
-    Directory(ext2::Directory),
+/// Returns where the pointer to the data block with the given index is
+/// stored, or `None` if the index is beyond what the triply indirect block can
+/// address.
+pub(super) fn block_path(index: usize, pointers_per_block: usize) -> Option<BlockPath> {
+    if index < DIRECT_BLOCKS {
+        return Some(BlockPath {
+            slot: index,
+            indices: [0; 3],
+            depth: 0,
+        });
+    }
+
+    let mut index = index - DIRECT_BLOCKS;
+    for (slot, depth) in INDIRECT_TREES {
+        let span = pointers_per_block.pow(depth as u32);
+        if index >= span {
+            index -= span;
+            continue;
+        }
+
+        let mut indices = [0; 3];
+        for (level, i) in indices.iter_mut().take(depth).enumerate() {
+            *i = index / pointers_per_block.pow((depth - 1 - level) as u32) % pointers_per_block;
+        }
+        return Some(BlockPath {
+            slot,
+            indices,
+            depth,
+        });
+    }
+    None
 }
 
-impl AsRef<Inode> for Inner {
-    fn as_ref(&self) -> &Inode {
-        match self {
-            Inner::RegularFile(f) => f,
-            Inner::Directory(d) => d,
+/// Follows the path from the inode through the indirect blocks, which are
+/// read with `read_pointer(block, index)`. Returns 0 if the path runs into a
+/// hole.
+pub(super) fn resolve_block_path<F>(
+    inode: &Inode,
+    path: &BlockPath,
+    mut read_pointer: F,
+) -> Result<u32>
+where
+    F: FnMut(u32, usize) -> Result<u32>,
+{
+    let mut block = inode.block(path.slot);
+    for &index in path.indices() {
+        if block == 0 {
+            return Ok(0);
         }
+        block = read_pointer(block, index)?;
     }
+    Ok(block)
 }
 
-impl<T> Ext2Inode<T>
+impl<T> VirtualExt2Fs<T>
 where
-    T: BlockDevice,
+    T: Flush,
 {
-    pub fn read(&self, buf: &mut [u8], offset: usize) -> Result<usize> {
-        match &self.inner {
-            Inner::RegularFile(f) => self
-                .fs
-                .read()
-                .read_from_file(f, offset, buf)
-                .map_err(|_| VfsError::ReadError),
-            _ => Err(VfsError::Unsupported),
-        }
-    }
-
-    pub fn write(&mut self, buf: &[u8], offset: usize) -> Result<usize> {
-        match &mut self.inner {
-            Inner::RegularFile(f) => self
-                .fs
-                .write()
-                .write_to_file(f, offset, buf)
-                .map_err(|_| VfsError::WriteError),
-            _ => Err(VfsError::Unsupported),
-        }
-    }
-
-    pub fn stat(&self, stat: &mut Stat) -> Result<()> {
-        let inode = self.inner.as_ref();
-
-        stat.dev = self.fsid.0;
-        stat.ino = self.inode_num.get() as u64;
-
-        let typ = self.inner.as_ref().typ();
-        stat.mode |= match typ {
-            t if t == Type::FIFO => FileMode::S_IFIFO,
-            t if t == Type::CharacterDevice => FileMode::S_IFCHR,
-            t if t == Type::Directory => FileMode::S_IFDIR,
-            t if t == Type::BlockDevice => FileMode::S_IFBLK,
-            t if t == Type::RegularFile => FileMode::S_IFREG,
-            t if t == Type::SymLink => FileMode::S_IFLNK,
-            t if t == Type::UnixSocket => FileMode::S_IFSOCK,
-            _ => unreachable!(),
-        };
-        stat.mode |= ext2_permissions_to_file_mode(inode.perm());
-
-        stat.nlink = inode.num_hard_links() as u32;
-        // TODO: uid, gid
-        stat.rdev = 0; // TODO: set correct rdev
-        stat.size = inode.len() as u64;
-        stat.atime = inode.last_access_time().into();
-        stat.mtime = inode.last_modification_time().into();
-        stat.ctime = inode.creation_time().into();
-        stat.blksize = self.fs.read().superblock().block_size() as u64;
-        stat.blocks = inode.num_disk_sectors() as u64;
+    fn pointers_per_block(&self) -> usize {
+        self.block_size() / 4
+    }
+
+    fn sectors_per_block(&self) -> u32 {
+        (self.block_size() / 512) as u32
+    }
+
+    fn read_pointer(&self, block: u32, index: usize) -> Result<u32> {
+        let mut pointer = [0_u8; 4];
+        let offset = block as u64 * self.block_size() as u64 + index as u64 * 4;
+        read_bytes(&self.device, offset, &mut pointer)?;
+        Ok(u32::from_le_bytes(pointer))
+    }
+
+    fn write_pointer(&mut self, block: u32, index: usize, pointer: u32) -> Result<()> {
+        let offset = block as u64 * self.block_size() as u64 + index as u64 * 4;
+        write_bytes(&mut self.device, offset, &pointer.to_le_bytes())
+    }
+
+    /// Returns the block that holds the data block with the given index of the
+    /// file, or 0 if that part of the file is a hole.
+    pub(super) fn block_for_index(&self, inode: &Inode, index: usize) -> Result<u32> {
+        let path = block_path(index, self.pointers_per_block()).ok_or(VfsError::FileTooLarge)?;
+        resolve_block_path(inode, &path, |block, index| self.read_pointer(block, index))
+    }
+
+    /// Like [`Self::block_for_index`], but allocates the data block and the
+    /// indirect blocks on the way if they don't exist yet. The inode is only
+    /// updated in memory, the caller has to write it.
+    pub(super) fn allocate_block_for_index(
+        &mut self,
+        num: u32,
+        inode: &mut Inode,
+        index: usize,
+    ) -> Result<u32> {
+        let path = block_path(index, self.pointers_per_block()).ok_or(VfsError::FileTooLarge)?;
+        let group = self.group_of_inode(num);
 
+        let mut block = inode.block(path.slot);
+        if block == 0 {
+            block = self.allocate_block(group)?;
+            inode.set_block(path.slot, block);
+            inode.set_sectors(inode.sectors() + self.sectors_per_block());
+        }
+        for &i in path.indices() {
+            let mut next = self.read_pointer(block, i)?;
+            if next == 0 {
+                next = self.allocate_block(group)?;
+                self.write_pointer(block, i, next)?;
+                inode.set_sectors(inode.sectors() + self.sectors_per_block());
+            }
+            block = next;
+        }
+        Ok(block)
+    }
+
+    /// Returns where the data at the given position of the file is on the
+    /// device, or `None` if it's in a hole, and how many of the following
+    /// `len` bytes are there, because the blocks of the file that follow are
+    /// adjacent on disk, or because the hole continues.
+    fn data_run(&self, inode: &Inode, position: usize, len: usize) -> Result<(Option<u64>, usize)> {
+        let block_size = self.block_size();
+        let index = position / block_size;
+        let start = position % block_size;
+        let mut n = (block_size - start).min(len);
+        let block = self.block_for_index(inode, index)?;
+        if block == 0 {
+            return Ok((None, n));
+        }
+
+        // the following blocks of the file that are adjacent on disk are read
+        // with the same request
+        let mut next = 1;
+        while n < len
+            && block.checked_add(next as u32) == Some(self.block_for_index(inode, index + next)?)
+        {
+            n += block_size.min(len - n);
+            next += 1;
+        }
+        Ok((Some(block as u64 * block_size as u64 + start as u64), n))
+    }
+
+    /// The number of bytes that a read of `len` bytes at the given offset
+    /// returns, which ends at the end of the file.
+    fn readable(inode: &Inode, offset: usize, len: usize) -> usize {
+        (inode.size() as usize).saturating_sub(offset).min(len)
+    }
+
+    pub(super) fn read_data(&self, inode: &Inode, buf: &mut [u8], offset: usize) -> Result<usize> {
+        let len = Self::readable(inode, offset, buf.len());
+        let mut done = 0;
+        while done < len {
+            let (at, n) = self.data_run(inode, offset + done, len - done)?;
+            let chunk = &mut buf[done..done + n];
+            match at {
+                None => chunk.fill(0),
+                Some(at) => read_bytes(&self.device, at, chunk)?,
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Like [`Self::read_data`], but awaits the device for the data. The
+    /// indirect blocks are read synchronously, they are usually cached.
+    pub(super) async fn read_data_async(
+        &self,
+        inode: &Inode,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<usize> {
+        let len = Self::readable(inode, offset, buf.len());
+        let mut done = 0;
+        while done < len {
+            let (at, n) = self.data_run(inode, offset + done, len - done)?;
+            let chunk = &mut buf[done..done + n];
+            match at {
+                None => chunk.fill(0),
+                Some(at) => read_bytes_async(&self.device, at, chunk).await?,
+            }
+            done += n;
+        }
+        Ok(len)
+    }
+
+    /// Reads the sectors that a write of `len` bytes at the given offset
+    /// covers only partly, which the write reads and modifies, with
+    /// [`MultiBlock::read_blocks_async`]. If the device is a block cache, the
+    /// write then finds them there instead of waiting for the device.
+    pub(super) async fn read_partial_sectors_async(
+        &self,
+        inode: &Inode,
+        offset: usize,
+        len: usize,
+    ) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let sector_size = self.device.sector_size();
+        let end = offset + len;
+        let head = (offset % sector_size != 0 || len < sector_size).then_some(offset);
+        let tail = (end % sector_size != 0 && (end - 1) / sector_size != offset / sector_size)
+            .then_some(end - 1);
+
+        let mut sector = BlockBuf::new_for(&self.device).map_err(|_| VfsError::NoSpace)?;
+        for position in head.into_iter().chain(tail) {
+            // blocks that don't exist yet are zeroed, not read
+            if let (Some(at), _) = self.data_run(inode, position, 1)? {
+                self.device
+                    .read_blocks_async(at as usize / sector_size, &mut sector)
+                    .await
+                    .map_err(|_| VfsError::ReadError)?;
+            }
+        }
         Ok(())
     }
+
+    /// Writes `buf` at the given offset of the file and grows the file if
+    /// necessary. The inode is written after the data, so that the file never
+    /// contains data that wasn't written yet.
+    pub(super) fn write_data(
+        &mut self,
+        num: u32,
+        inode: &mut Inode,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<usize> {
+        let block_size = self.block_size();
+        let mut done = 0;
+        let mut result = Ok(());
+        while done < buf.len() {
+            let position = offset + done;
+            let start = position % block_size;
+            let n = (block_size - start).min(buf.len() - done);
+            result = self
+                .allocate_block_for_index(num, inode, position / block_size)
+                .and_then(|block| {
+                    let offset = block as u64 * block_size as u64 + start as u64;
+                    write_bytes(&mut self.device, offset, &buf[done..done + n])
+                });
+            if result.is_err() {
+                break;
+            }
+            done += n;
+        }
+
+        // the inode has to be written even if the write failed, because blocks
+        // might have been allocated for it
+        let end = (offset + done) as u64;
+        if end > inode.size() {
+            inode.set_size(end);
+        }
+        self.write_inode(num, inode)?;
+
+        match result {
+            // report what has been written, the next write will report the error
+            Err(_) if done > 0 => Ok(done),
+            Err(e) => Err(e),
+            Ok(()) => Ok(done),
+        }
+    }
+
+    /// Sets the size of the file. Blocks beyond the new size are freed, and
+    /// growing the file leaves a hole.
+    pub(super) fn truncate_data(&mut self, num: u32, inode: &mut Inode, size: usize) -> Result<()> {
+        let block_size = self.block_size();
+        if (size as u64) < inode.size() {
+            self.free_blocks_after(inode, size.div_ceil(block_size))?;
+
+            // the rest of the last block must read as zeros if the file grows again
+            let start = size % block_size;
+            if start != 0 {
+                let block = self.block_for_index(inode, size / block_size)?;
+                if block != 0 {
+                    let zeros = vec![0_u8; block_size - start];
+                    let offset = block as u64 * block_size as u64 + start as u64;
+                    write_bytes(&mut self.device, offset, &zeros)?;
+                }
+            }
+        }
+        inode.set_size(size as u64);
+        self.write_inode(num, inode)
+    }
+
+    /// Frees all data blocks from the given index on, and all indirect blocks
+    /// that aren't needed anymore. The inode is only updated in memory.
+    pub(super) fn free_blocks_after(&mut self, inode: &mut Inode, keep: usize) -> Result<()> {
+        let mut freed = 0;
+        for index in keep..DIRECT_BLOCKS {
+            let block = inode.block(index);
+            if block != 0 {
+                self.free_block(block)?;
+                inode.set_block(index, 0);
+                freed += 1;
+            }
+        }
+
+        let pointers = self.pointers_per_block();
+        let mut first = DIRECT_BLOCKS;
+        for (slot, depth) in INDIRECT_TREES {
+            let span = pointers.pow(depth as u32);
+            let block = inode.block(slot);
+            if block != 0 && first + span > keep {
+                let (empty, n) = if first >= keep {
+                    (true, self.free_tree(block, depth)?)
+                } else {
+                    self.truncate_tree(block, depth, keep - first)?
+                };
+                freed += n;
+                if empty {
+                    self.free_block(block)?;
+                    inode.set_block(slot, 0);
+                    freed += 1;
+                }
+            }
+            first += span;
+        }
+
+        let sectors = inode
+            .sectors()
+            .saturating_sub(freed * self.sectors_per_block());
+        inode.set_sectors(sectors);
+        Ok(())
+    }
+
+    /// Frees everything that the indirect block with the given depth points
+    /// to, but not the block itself. Returns the number of freed blocks.
+    fn free_tree(&mut self, block: u32, depth: usize) -> Result<u32> {
+        let mut pointers = vec![0_u8; self.block_size()];
+        self.read_block(block, &mut pointers)?;
+        let mut freed = 0;
+        for index in 0..self.pointers_per_block() {
+            let child = u32_at(&pointers, index * 4);
+            if child == 0 {
+                continue;
+            }
+            if depth > 1 {
+                freed += self.free_tree(child, depth - 1)?;
+            }
+            self.free_block(child)?;
+            freed += 1;
+        }
+        Ok(freed)
+    }
+
+    /// Frees everything after the first `keep` data blocks that the indirect
+    /// block with the given depth points to. Returns whether the indirect
+    /// block is empty afterwards, and the number of freed blocks.
+    fn truncate_tree(&mut self, block: u32, depth: usize, keep: usize) -> Result<(bool, u32)> {
+        let mut pointers = vec![0_u8; self.block_size()];
+        self.read_block(block, &mut pointers)?;
+        let span = self.pointers_per_block().pow(depth as u32 - 1);
+        let mut freed = 0;
+        let mut modified = false;
+        for index in 0..self.pointers_per_block() {
+            let child = u32_at(&pointers, index * 4);
+            let first = index * span;
+            if child == 0 || first + span <= keep {
+                continue;
+            }
+
+            let empty = if first >= keep {
+                if depth > 1 {
+                    freed += self.free_tree(child, depth - 1)?;
+                }
+                true
+            } else {
+                let (empty, n) = self.truncate_tree(child, depth - 1, keep - first)?;
+                freed += n;
+                empty
+            };
+            if empty {
+                self.free_block(child)?;
+                freed += 1;
+                set_u32(&mut pointers, index * 4, 0);
+                modified = true;
+            }
+        }
+        if modified {
+            self.write_block(block, &pointers)?;
+        }
+        let empty = (0..self.pointers_per_block()).all(|index| u32_at(&pointers, index * 4) == 0);
+        Ok((empty, freed))
+    }
 }
 
-fn ext2_permissions_to_file_mode(permissions: Permissions) -> FileMode {
-    let mut mode = FileMode::empty();
-
-    mode |= if permissions.contains(Permissions::UserRead) {
-        FileMode::S_IRUSR
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::UserWrite) {
-        FileMode::S_IWUSR
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::UserExec) {
-        FileMode::S_IXUSR
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::GroupRead) {
-        FileMode::S_IRGRP
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::GroupWrite) {
-        FileMode::S_IWGRP
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::GroupExec) {
-        FileMode::S_IXGRP
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::OtherRead) {
-        FileMode::S_IROTH
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::OtherWrite) {
-        FileMode::S_IWOTH
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::OtherExec) {
-        FileMode::S_IXOTH
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::SetUID) {
-        FileMode::S_ISUID
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::SetGID) {
-        FileMode::S_ISGID
-    } else {
-        FileMode::empty()
-    } | if permissions.contains(Permissions::Sticky) {
-        FileMode::S_ISVTX
-    } else {
-        FileMode::empty()
-    };
-
-    mode
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::SeqCst;
+
+    use filesystem::BlockDevice;
+    use foundation::future::executor::{Executor, Tick};
+    use foundation::future::yield_now;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use kernel_test_framework::kernel_test;
+
+    use crate::driver::ide;
+    use crate::driver::ide::IdeBlockDevice;
+    use crate::io::path::Path;
+    use crate::io::vfs::cache::{BlockCache, Flush, MultiBlock};
+    use crate::io::vfs::error::VfsError;
+    use crate::io::vfs::ext2::file::{block_path, resolve_block_path, BlockPath};
+    use crate::io::vfs::ext2::layout::Inode;
+    use crate::io::vfs::ext2::VirtualExt2Fs;
+    use crate::io::vfs::{vfs, FileSystem, FsId};
+
+    /// The number of block pointers in a 1KiB block.
+    const POINTERS: usize = 256;
+    const SINGLY: usize = 12;
+    const DOUBLY: usize = SINGLY + POINTERS;
+    const TRIPLY: usize = DOUBLY + POINTERS * POINTERS;
+
+    fn path(slot: usize, indices: &[usize]) -> BlockPath {
+        let mut path = BlockPath {
+            slot,
+            indices: [0; 3],
+            depth: indices.len(),
+        };
+        path.indices[..indices.len()].copy_from_slice(indices);
+        path
+    }
+
+    #[kernel_test]
+    fn test_block_path() {
+        for (index, expected) in [
+            (0, path(0, &[])),
+            (11, path(11, &[])),
+            (SINGLY, path(12, &[0])),
+            (DOUBLY - 1, path(12, &[255])),
+            (DOUBLY, path(13, &[0, 0])),
+            (DOUBLY + POINTERS + 3, path(13, &[1, 3])),
+            (TRIPLY - 1, path(13, &[255, 255])),
+            (TRIPLY, path(14, &[0, 0, 0])),
+            (
+                TRIPLY + POINTERS * POINTERS + 2 * POINTERS + 3,
+                path(14, &[1, 2, 3]),
+            ),
+            (TRIPLY + POINTERS.pow(3) - 1, path(14, &[255, 255, 255])),
+        ] {
+            assert_eq!(Some(expected), block_path(index, POINTERS), "index {index}");
+        }
+        assert_eq!(None, block_path(TRIPLY + POINTERS.pow(3), POINTERS));
+    }
+
+    #[kernel_test]
+    fn test_resolve_block_path() {
+        // the inode points to a doubly and a triply indirect block, the
+        // indirect blocks are given as (block, pointers)
+        let mut inode = Inode::zeroed();
+        inode.set_block(3, 50);
+        inode.set_block(13, 100);
+        inode.set_block(14, 300);
+        let indirect = BTreeMap::from([
+            (100, vec![(1, 200)]),
+            (200, vec![(5, 777)]),
+            (300, vec![(0, 400)]),
+            (400, vec![(2, 500)]),
+            (500, vec![(7, 999)]),
+        ]);
+
+        let resolve = |index: usize| {
+            let mut reads = Vec::new();
+            let block =
+                resolve_block_path(&inode, &block_path(index, POINTERS).unwrap(), |block, i| {
+                    reads.push(block);
+                    let pointers = indirect.get(&block).ok_or(VfsError::ReadError)?;
+                    Ok(pointers
+                        .iter()
+                        .find(|(index, _)| *index == i)
+                        .map_or(0, |(_, pointer)| *pointer))
+                })
+                .unwrap();
+            (block, reads)
+        };
+
+        assert_eq!((50, vec![]), resolve(3));
+        assert_eq!((0, vec![]), resolve(4));
+        // the singly indirect block doesn't exist, so nothing is read
+        assert_eq!((0, vec![]), resolve(SINGLY + 1));
+        assert_eq!((777, vec![100, 200]), resolve(DOUBLY + POINTERS + 5));
+        assert_eq!((0, vec![100, 200]), resolve(DOUBLY + POINTERS + 6));
+        assert_eq!((0, vec![100]), resolve(DOUBLY + 2 * POINTERS));
+        assert_eq!(
+            (999, vec![300, 400, 500]),
+            resolve(TRIPLY + 2 * POINTERS + 7)
+        );
+        assert_eq!((0, vec![300]), resolve(TRIPLY + POINTERS * POINTERS));
+    }
+
+    /// The content of the data regions of `/var/data/sparse.bin`, which is
+    /// created by the build script with data in each tier of indirect blocks.
+    fn expected(start: usize, len: usize) -> Vec<u8> {
+        (start..start + len)
+            .map(|offset| (offset % 251 + 1) as u8)
+            .collect()
+    }
+
+    #[kernel_test]
+    fn test_read_sparse_file() {
+        let node = vfs().open("/var/data/sparse.bin").unwrap();
+        let mut buf = vec![0_u8; 4096];
+        for start in [
+            0,
+            100 * 1024 + 100,
+            20 * 1024 * 1024 + 100,
+            70 * 1024 * 1024 + 100,
+        ] {
+            assert_eq!(4096, vfs().read(&node, &mut buf, start).unwrap());
+            assert_eq!(expected(start, 4096), buf, "region at {start}");
+        }
+
+        for hole in [50 * 1024, 10 * 1024 * 1024, 71 * 1024 * 1024] {
+            buf.fill(0xFF);
+            assert_eq!(4096, vfs().read(&node, &mut buf, hole).unwrap());
+            assert!(buf.iter().all(|&b| b == 0), "hole at {hole}");
+        }
+
+        // a read across the end of a region continues into the hole after it
+        let start = 20 * 1024 * 1024 + 100 + 4000;
+        assert_eq!(4096, vfs().read(&node, &mut buf, start).unwrap());
+        assert_eq!(expected(start, 96), buf[..96]);
+        assert!(buf[96..].iter().all(|&b| b == 0));
+
+        // the file ends in a hole
+        assert_eq!(
+            1,
+            vfs().read(&node, &mut buf, 72 * 1024 * 1024 - 1).unwrap()
+        );
+        assert_eq!(0, buf[0]);
+    }
+
+    /// The number of times that [`Slow`] yields before it reads.
+    const DELAY: usize = 10;
+
+    /// An IDE disk whose asynchronous reads yield a few times before they
+    /// read, and which counts how many of them are waiting at most. Clones
+    /// share the counters.
+    #[derive(Clone)]
+    struct Slow {
+        device: IdeBlockDevice,
+        in_flight: Arc<AtomicUsize>,
+        max_in_flight: Arc<AtomicUsize>,
+    }
+
+    impl BlockDevice for Slow {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            self.device.sector_size()
+        }
+
+        fn sector_count(&self) -> usize {
+            self.device.sector_count()
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            self.device.read_sector(sector_index, buf)
+        }
+
+        fn write_sector(&mut self, _: usize, _: &[u8]) -> Result<usize, ()> {
+            Err(())
+        }
+    }
+
+    impl MultiBlock for Slow {
+        fn read_blocks_async<'a>(
+            &'a self,
+            start: usize,
+            buf: &'a mut [u8],
+        ) -> BoxFuture<'a, Result<usize, ()>> {
+            async move {
+                let in_flight = self.in_flight.fetch_add(1, SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, SeqCst);
+                for _ in 0..DELAY {
+                    yield_now().await;
+                }
+                let result = self.device.read_sectors(start, buf);
+                self.in_flight.fetch_sub(1, SeqCst);
+                result
+            }
+            .boxed()
+        }
+    }
+
+    impl Flush for Slow {
+        fn flush(&mut self) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    /// Two reads of the same cached disk overlap: the second one reaches the
+    /// disk while the first one still waits for it.
+    #[kernel_test]
+    fn test_overlapping_async_reads() {
+        let device = Slow {
+            device: IdeBlockDevice::clone(&ide::devices()[1]),
+            in_flight: Arc::default(),
+            max_in_flight: Arc::default(),
+        };
+        let cache = BlockCache::new(device.clone(), 1024);
+        let path = Path::new("/var/data/sparse.bin");
+        let mut first = VirtualExt2Fs::try_new(FsId::new(), cache.clone()).unwrap();
+        let mut second = VirtualExt2Fs::try_new(FsId::new(), cache).unwrap();
+        let first_handle = first.open(path).unwrap();
+        let second_handle = second.open(path).unwrap();
+        let done = AtomicUsize::new(0);
+
+        let executor = Executor::default();
+        for (fs, handle, start) in [
+            (&mut first, first_handle, 100),
+            (&mut second, second_handle, 20 * 1024 * 1024 + 100),
+        ] {
+            let done = &done;
+            executor.spawn(async move {
+                let mut buf = vec![0_u8; 4096];
+                assert_eq!(Ok(4096), fs.read_async(handle, &mut buf, start).await);
+                assert_eq!(expected(start, 4096), buf, "region at {start}");
+                done.fetch_add(1, SeqCst);
+            });
+        }
+        while done.load(SeqCst) < 2 {
+            executor.tick();
+        }
+        assert_eq!(2, device.max_in_flight.load(SeqCst));
+        assert_eq!(0, device.in_flight.load(SeqCst));
+    }
 }
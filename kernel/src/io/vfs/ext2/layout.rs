@@ -0,0 +1,506 @@
+use alloc::vec;
+use alloc::vec::Vec;
+
+use kernel_api::syscall::FileMode;
+
+use crate::io::vfs::cache::{BlockBuf, MultiBlock};
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::FileType;
+
+// The on-disk structures of ext2, see https://www.nongnu.org/ext2-doc/ext2.html.
+// All of them are kept in their raw form and accessed through getters and
+// setters, so that fields we don't know about survive a write.
+
+pub const ROOT_INODE: u32 = 2;
+/// The first inode that is not reserved in revision 0 file systems.
+const GOOD_OLD_FIRST_INODE: u32 = 11;
+/// The size of an inode in revision 0 file systems, and the part of an inode
+/// that we access in all revisions.
+pub const GOOD_OLD_INODE_SIZE: usize = 128;
+
+const SUPERBLOCK_OFFSET: u64 = 1024;
+const SUPERBLOCK_SIZE: usize = 1024;
+const MAGIC: u16 = 0xEF53;
+const GROUP_DESCRIPTOR_SIZE: usize = 32;
+
+/// Directory entries have a file type byte.
+pub const INCOMPAT_FILETYPE: u32 = 0x0002;
+/// All incompatible features that we can write to.
+const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE;
+/// Backup superblocks only in some groups, and files larger than 2 GiB. We
+/// never touch the backups and always write the upper half of the file size.
+const RO_COMPAT_SUPPORTED: u32 = 0x0001 | 0x0002;
+
+/// The directory is indexed with a hash tree.
+pub const INODE_FLAG_INDEX: u32 = 0x1000;
+pub const DIRECT_BLOCKS: usize = 12;
+pub const SINGLY_INDIRECT_BLOCK: usize = 12;
+pub const DOUBLY_INDIRECT_BLOCK: usize = 13;
+pub const TRIPLY_INDIRECT_BLOCK: usize = 14;
+
+pub struct Superblock {
+    raw: [u8; SUPERBLOCK_SIZE],
+}
+
+impl Superblock {
+    pub fn read<T: MultiBlock>(device: &T) -> Result<Self> {
+        let mut raw = [0; SUPERBLOCK_SIZE];
+        read_bytes(device, SUPERBLOCK_OFFSET, &mut raw)?;
+        let superblock = Self { raw };
+        if superblock.magic() != MAGIC {
+            return Err(VfsError::ReadError);
+        }
+        Ok(superblock)
+    }
+
+    pub fn write<T: MultiBlock>(&self, device: &mut T) -> Result<()> {
+        write_bytes(device, SUPERBLOCK_OFFSET, &self.raw)
+    }
+
+    pub fn inodes_count(&self) -> u32 {
+        u32_at(&self.raw, 0)
+    }
+
+    pub fn blocks_count(&self) -> u32 {
+        u32_at(&self.raw, 4)
+    }
+
+    pub fn free_blocks_count(&self) -> u32 {
+        u32_at(&self.raw, 12)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u32) {
+        set_u32(&mut self.raw, 12, count);
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        u32_at(&self.raw, 16)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        set_u32(&mut self.raw, 16, count);
+    }
+
+    pub fn first_data_block(&self) -> u32 {
+        u32_at(&self.raw, 20)
+    }
+
+    pub fn block_size(&self) -> usize {
+        1024 << u32_at(&self.raw, 24)
+    }
+
+    pub fn blocks_per_group(&self) -> u32 {
+        u32_at(&self.raw, 32)
+    }
+
+    pub fn inodes_per_group(&self) -> u32 {
+        u32_at(&self.raw, 40)
+    }
+
+    /// The last time the file system was written to. We don't have a wall
+    /// clock, so this is the best guess of the current time that we have.
+    pub fn write_time(&self) -> u32 {
+        u32_at(&self.raw, 48)
+    }
+
+    fn magic(&self) -> u16 {
+        u16_at(&self.raw, 56)
+    }
+
+    fn rev_level(&self) -> u32 {
+        u32_at(&self.raw, 76)
+    }
+
+    pub fn first_inode(&self) -> u32 {
+        match self.rev_level() {
+            0 => GOOD_OLD_FIRST_INODE,
+            _ => u32_at(&self.raw, 84),
+        }
+    }
+
+    pub fn inode_size(&self) -> usize {
+        match self.rev_level() {
+            0 => GOOD_OLD_INODE_SIZE,
+            _ => u16_at(&self.raw, 88) as usize,
+        }
+    }
+
+    fn feature_incompat(&self) -> u32 {
+        match self.rev_level() {
+            0 => 0,
+            _ => u32_at(&self.raw, 96),
+        }
+    }
+
+    fn feature_ro_compat(&self) -> u32 {
+        match self.rev_level() {
+            0 => 0,
+            _ => u32_at(&self.raw, 100),
+        }
+    }
+
+    pub fn has_incompat(&self, feature: u32) -> bool {
+        self.feature_incompat() & feature != 0
+    }
+
+    /// Whether we know all incompatible and read-only compatible features of
+    /// this file system well enough to modify it.
+    pub fn is_writable(&self) -> bool {
+        self.feature_incompat() & !INCOMPAT_SUPPORTED == 0
+            && self.feature_ro_compat() & !RO_COMPAT_SUPPORTED == 0
+    }
+
+    pub fn group_count(&self) -> u32 {
+        (self.blocks_count() - self.first_data_block()).div_ceil(self.blocks_per_group())
+    }
+}
+
+pub struct GroupDescriptor {
+    raw: [u8; GROUP_DESCRIPTOR_SIZE],
+}
+
+impl GroupDescriptor {
+    /// The descriptor table starts in the block after the superblock.
+    fn offset(superblock: &Superblock, group: u32) -> u64 {
+        (superblock.first_data_block() as u64 + 1) * superblock.block_size() as u64
+            + group as u64 * GROUP_DESCRIPTOR_SIZE as u64
+    }
+
+    pub fn read<T: MultiBlock>(device: &T, superblock: &Superblock, group: u32) -> Result<Self> {
+        let mut raw = [0; GROUP_DESCRIPTOR_SIZE];
+        read_bytes(device, Self::offset(superblock, group), &mut raw)?;
+        Ok(Self { raw })
+    }
+
+    pub fn write<T: MultiBlock>(
+        &self,
+        device: &mut T,
+        superblock: &Superblock,
+        group: u32,
+    ) -> Result<()> {
+        write_bytes(device, Self::offset(superblock, group), &self.raw)
+    }
+
+    pub fn block_bitmap(&self) -> u32 {
+        u32_at(&self.raw, 0)
+    }
+
+    pub fn inode_bitmap(&self) -> u32 {
+        u32_at(&self.raw, 4)
+    }
+
+    pub fn inode_table(&self) -> u32 {
+        u32_at(&self.raw, 8)
+    }
+
+    pub fn free_blocks_count(&self) -> u16 {
+        u16_at(&self.raw, 12)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u16) {
+        set_u16(&mut self.raw, 12, count);
+    }
+
+    pub fn free_inodes_count(&self) -> u16 {
+        u16_at(&self.raw, 14)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u16) {
+        set_u16(&mut self.raw, 14, count);
+    }
+
+    pub fn used_dirs_count(&self) -> u16 {
+        u16_at(&self.raw, 16)
+    }
+
+    pub fn set_used_dirs_count(&mut self, count: u16) {
+        set_u16(&mut self.raw, 16, count);
+    }
+}
+
+#[derive(Clone)]
+pub struct Inode {
+    raw: [u8; GOOD_OLD_INODE_SIZE],
+}
+
+impl Inode {
+    /// An inode with all fields set to zero.
+    pub fn zeroed() -> Self {
+        Self {
+            raw: [0; GOOD_OLD_INODE_SIZE],
+        }
+    }
+
+    fn offset<T: MultiBlock>(device: &T, superblock: &Superblock, num: u32) -> Result<u64> {
+        if num == 0 || num > superblock.inodes_per_group() * superblock.group_count() {
+            return Err(VfsError::ReadError);
+        }
+        let index = num - 1;
+        let group = index / superblock.inodes_per_group();
+        let descriptor = GroupDescriptor::read(device, superblock, group)?;
+        Ok(
+            descriptor.inode_table() as u64 * superblock.block_size() as u64
+                + (index % superblock.inodes_per_group()) as u64 * superblock.inode_size() as u64,
+        )
+    }
+
+    pub fn read<T: MultiBlock>(device: &T, superblock: &Superblock, num: u32) -> Result<Self> {
+        let mut raw = [0; GOOD_OLD_INODE_SIZE];
+        read_bytes(device, Self::offset(device, superblock, num)?, &mut raw)?;
+        Ok(Self { raw })
+    }
+
+    /// Writes the inode back. Only the fields of a revision 0 inode are
+    /// written, the rest of a larger on-disk inode is left as it is.
+    pub fn write<T: MultiBlock>(
+        &self,
+        device: &mut T,
+        superblock: &Superblock,
+        num: u32,
+    ) -> Result<()> {
+        let offset = Self::offset(device, superblock, num)?;
+        write_bytes(device, offset, &self.raw)
+    }
+
+    /// Writes a newly allocated inode. Unlike [`Inode::write`], this clears
+    /// the rest of a larger on-disk inode, which might still hold fields of a
+    /// previously freed inode.
+    pub fn write_new<T: MultiBlock>(
+        &self,
+        device: &mut T,
+        superblock: &Superblock,
+        num: u32,
+    ) -> Result<()> {
+        let offset = Self::offset(device, superblock, num)?;
+        let mut raw = vec![0_u8; superblock.inode_size()];
+        raw[..GOOD_OLD_INODE_SIZE].copy_from_slice(&self.raw);
+        write_bytes(device, offset, &raw)
+    }
+
+    pub fn mode(&self) -> FileMode {
+        FileMode::from_bits_truncate(u16_at(&self.raw, 0) as u32)
+    }
+
+    pub fn set_mode(&mut self, mode: FileMode) {
+        set_u16(&mut self.raw, 0, mode.bits() as u16);
+    }
+
+    pub fn file_type(&self) -> Option<FileType> {
+        let mode = self.mode();
+        Some(if mode.is_regular_file() {
+            FileType::RegularFile
+        } else if mode.is_directory() {
+            FileType::Directory
+        } else if mode.is_symlink() {
+            FileType::SymbolicLink
+        } else if mode.is_char_device() {
+            FileType::CharacterDevice
+        } else if mode.is_block_device() {
+            FileType::BlockDevice
+        } else if mode.is_fifo() {
+            FileType::FIFO
+        } else if mode.is_socket() {
+            FileType::Socket
+        } else {
+            return None;
+        })
+    }
+
+    pub fn uid(&self) -> u16 {
+        u16_at(&self.raw, 2)
+    }
+
+    /// The size of the file. The upper 32 bits are only used by regular files.
+    pub fn size(&self) -> u64 {
+        let high = if self.mode().is_regular_file() {
+            u32_at(&self.raw, 108)
+        } else {
+            0
+        };
+        (high as u64) << 32 | u32_at(&self.raw, 4) as u64
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        set_u32(&mut self.raw, 4, size as u32);
+        if self.mode().is_regular_file() {
+            set_u32(&mut self.raw, 108, (size >> 32) as u32);
+        }
+    }
+
+    pub fn atime(&self) -> u32 {
+        u32_at(&self.raw, 8)
+    }
+
+    pub fn ctime(&self) -> u32 {
+        u32_at(&self.raw, 12)
+    }
+
+    pub fn mtime(&self) -> u32 {
+        u32_at(&self.raw, 16)
+    }
+
+    pub fn set_times(&mut self, time: u32) {
+        set_u32(&mut self.raw, 8, time);
+        set_u32(&mut self.raw, 12, time);
+        set_u32(&mut self.raw, 16, time);
+    }
+
+    pub fn set_dtime(&mut self, time: u32) {
+        set_u32(&mut self.raw, 20, time);
+    }
+
+    pub fn gid(&self) -> u16 {
+        u16_at(&self.raw, 24)
+    }
+
+    pub fn links_count(&self) -> u16 {
+        u16_at(&self.raw, 26)
+    }
+
+    pub fn set_links_count(&mut self, count: u16) {
+        set_u16(&mut self.raw, 26, count);
+    }
+
+    /// The number of 512 byte sectors that are allocated for this inode,
+    /// including indirect blocks and the extended attribute block.
+    pub fn sectors(&self) -> u32 {
+        u32_at(&self.raw, 28)
+    }
+
+    pub fn set_sectors(&mut self, sectors: u32) {
+        set_u32(&mut self.raw, 28, sectors);
+    }
+
+    pub fn flags(&self) -> u32 {
+        u32_at(&self.raw, 32)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        set_u32(&mut self.raw, 32, flags);
+    }
+
+    pub fn block(&self, index: usize) -> u32 {
+        u32_at(&self.raw, 40 + index * 4)
+    }
+
+    pub fn set_block(&mut self, index: usize, block: u32) {
+        set_u32(&mut self.raw, 40 + index * 4, block);
+    }
+
+    /// The raw bytes of the block pointers, which hold the target of fast
+    /// symbolic links.
+    pub fn block_bytes(&self) -> &[u8] {
+        &self.raw[40..100]
+    }
+
+    pub fn file_acl(&self) -> u32 {
+        u32_at(&self.raw, 104)
+    }
+}
+
+/// Reads `buf.len()` bytes starting at the given byte offset of the device.
+/// The whole sectors are read into `buf` with a single request, only partial
+/// sectors at the start and the end are read separately.
+pub fn read_bytes<T: MultiBlock>(device: &T, offset: u64, buf: &mut [u8]) -> Result<()> {
+    let sector_size = device.sector_size();
+    let mut sector = BlockBuf::new_for(device).map_err(|_| VfsError::NoSpace)?;
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset as usize + done;
+        let start = position % sector_size;
+        let whole = (buf.len() - done) / sector_size;
+        if start == 0 && whole > 0 {
+            let mut sectors = buf[done..done + whole * sector_size]
+                .chunks_mut(sector_size)
+                .collect::<Vec<_>>();
+            device
+                .read_blocks(position / sector_size, &mut sectors)
+                .map_err(|_| VfsError::ReadError)?;
+            done += whole * sector_size;
+            continue;
+        }
+
+        device
+            .read_sector(position / sector_size, &mut sector)
+            .map_err(|_| VfsError::ReadError)?;
+        let n = (sector_size - start).min(buf.len() - done);
+        buf[done..done + n].copy_from_slice(&sector[start..start + n]);
+        done += n;
+    }
+    Ok(())
+}
+
+/// Like [`read_bytes`], but awaits the device, see
+/// [`MultiBlock::read_blocks_async`].
+pub async fn read_bytes_async<T: MultiBlock>(
+    device: &T,
+    offset: u64,
+    buf: &mut [u8],
+) -> Result<()> {
+    let sector_size = device.sector_size();
+    let mut sector = BlockBuf::new_for(device).map_err(|_| VfsError::NoSpace)?;
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset as usize + done;
+        let start = position % sector_size;
+        let whole = (buf.len() - done) / sector_size;
+        if start == 0 && whole > 0 {
+            let len = whole * sector_size;
+            device
+                .read_blocks_async(position / sector_size, &mut buf[done..done + len])
+                .await
+                .map_err(|_| VfsError::ReadError)?;
+            done += len;
+            continue;
+        }
+
+        device
+            .read_blocks_async(position / sector_size, &mut sector)
+            .await
+            .map_err(|_| VfsError::ReadError)?;
+        let n = (sector_size - start).min(buf.len() - done);
+        buf[done..done + n].copy_from_slice(&sector[start..start + n]);
+        done += n;
+    }
+    Ok(())
+}
+
+/// Writes `buf` to the given byte offset of the device. Sectors that are
+/// only partially written are read first.
+pub fn write_bytes<T: MultiBlock>(device: &mut T, offset: u64, buf: &[u8]) -> Result<()> {
+    let sector_size = device.sector_size();
+    let mut sector = BlockBuf::new_for(device).map_err(|_| VfsError::NoSpace)?;
+    let mut done = 0;
+    while done < buf.len() {
+        let position = offset as usize + done;
+        let start = position % sector_size;
+        let n = (sector_size - start).min(buf.len() - done);
+        if n < sector_size {
+            device
+                .read_sector(position / sector_size, &mut sector)
+                .map_err(|_| VfsError::ReadError)?;
+        }
+        sector[start..start + n].copy_from_slice(&buf[done..done + n]);
+        device
+            .write_sector(position / sector_size, &sector)
+            .map_err(|_| VfsError::WriteError)?;
+        done += n;
+    }
+    Ok(())
+}
+
+pub fn u16_at(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+pub fn u32_at(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn set_u16(buf: &mut [u8], offset: usize, value: u16) {
+    buf[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+}
+
+pub fn set_u32(buf: &mut [u8], offset: usize, value: u32) {
+    buf[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
@@ -1,178 +1,547 @@
-use alloc::borrow::ToOwned;
-use alloc::collections::BTreeMap;
-use alloc::string::ToString;
-use alloc::sync::Arc;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering::Relaxed;
 
-use ext2::Type;
-use filesystem::BlockDevice;
-use spin::RwLock;
-
-use file::Ext2Inode;
-use kernel_api::syscall::Stat;
+use foundation::falloc::vec::FVec;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use kernel_api::syscall::{FileMode, Stat};
 
 use crate::io::path::{Component, Path};
+use crate::io::vfs::cache::Flush;
 use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::ext2::layout::{Inode, Superblock, ROOT_INODE};
 use crate::io::vfs::{DirEntry, FileSystem, FileType, FsId, VfsHandle};
 
+mod allocation;
+mod dir;
 mod file;
+mod layout;
+mod symlink;
 
 static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+/// The maximum number of names that an inode can have in ext2.
+const MAX_LINKS: u16 = 32000;
+
 fn next_handle() -> VfsHandle {
     VfsHandle::new(HANDLE_COUNTER.fetch_add(1, Relaxed))
 }
 
+/// An ext2 file system on a block device.
+///
+/// Modifications are written in an order that keeps the file system
+/// consistent if they are interrupted: blocks are marked as used and zeroed
+/// before anything points to them, file data is written before the size of
+/// the file grows, and an inode is fully initialized before a directory entry
+/// refers to it. An interrupted operation can leak blocks or inodes, but never
+/// exposes stale data.
 pub struct VirtualExt2Fs<T> {
     fsid: FsId,
-    handles: BTreeMap<VfsHandle, Arc<RwLock<Ext2Inode<T>>>>, // there might be multiple VfsHandles pointing to the same inode
-    inner: Arc<RwLock<ext2::Ext2Fs<T>>>,
+    device: T,
+    superblock: Superblock,
+    /// The inode that each handle refers to. There might be multiple handles
+    /// for the same inode.
+    handles: BTreeMap<VfsHandle, u32>,
+    /// Inodes that were removed while they were still open. They are freed
+    /// when their last handle is closed.
+    orphans: BTreeSet<u32>,
 }
 
 impl<T> VirtualExt2Fs<T>
 where
-    T: BlockDevice,
+    T: Flush,
 {
-    pub fn new(fsid: FsId, inner: ext2::Ext2Fs<T>) -> Self {
-        Self {
+    /// Opens the ext2 file system on the given device.
+    pub fn try_new(fsid: FsId, device: T) -> Result<Self> {
+        let superblock = Superblock::read(&device)?;
+        Ok(Self {
             fsid,
+            device,
+            superblock,
             handles: BTreeMap::new(),
-            inner: Arc::new(RwLock::new(inner)),
+            orphans: BTreeSet::new(),
+        })
+    }
+
+    fn block_size(&self) -> usize {
+        self.superblock.block_size()
+    }
+
+    fn read_inode(&self, num: u32) -> Result<Inode> {
+        Inode::read(&self.device, &self.superblock, num)
+    }
+
+    fn write_inode(&mut self, num: u32, inode: &Inode) -> Result<()> {
+        inode.write(&mut self.device, &self.superblock, num)
+    }
+
+    fn resolve_handle(&self, handle: VfsHandle) -> Result<(u32, Inode)> {
+        let num = *self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+        Ok((num, self.read_inode(num)?))
+    }
+
+    fn insert_handle(&mut self, num: u32, inode: &Inode) -> Result<VfsHandle> {
+        if !matches!(
+            inode.file_type(),
+            Some(FileType::RegularFile | FileType::Directory | FileType::SymbolicLink)
+        ) {
+            return Err(VfsError::Unsupported);
         }
+
+        let handle = next_handle();
+        self.handles.insert(handle, num);
+        Ok(handle)
     }
 
-    fn resolve_handle(&self, handle: VfsHandle) -> Result<&Arc<RwLock<Ext2Inode<T>>>> {
-        self.handles.get(&handle).ok_or(VfsError::HandleClosed)
+    fn check_writable(&self) -> Result<()> {
+        if self.superblock.is_writable() {
+            Ok(())
+        } else {
+            // the file system uses features that we might break
+            Err(VfsError::PermissionDenied)
+        }
     }
 
-    fn find_inode(&self, path: &Path) -> Result<(ext2::InodeAddress, ext2::Inode)> {
-        let root_inode = self
-            .inner
-            .read()
-            .read_root_inode()
-            .map_err(|_| VfsError::NoSuchFile)?;
-        self.find_inode_from(path, root_inode.into_inner())
+    /// Finds the directory that contains the given path, and returns its inode
+    /// number, the inode, and the last component of the path. Returns `None`
+    /// as the name if the path refers to the root directory.
+    fn parent_of<'p>(&self, path: &'p Path) -> Result<(u32, Inode, Option<&'p str>)> {
+        let mut names = path
+            .components()
+            .filter_map(|component| match component {
+                Component::RootDir | Component::CurrentDir => None,
+                Component::ParentDir => Some(Err(VfsError::Unsupported)),
+                Component::Normal(name) => Some(Ok(name)),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let name = names.pop();
+
+        let mut num = ROOT_INODE;
+        let mut inode = self.read_inode(num)?;
+        for component in names {
+            if !inode.mode().is_directory() {
+                return Err(VfsError::NotDirectory);
+            }
+            num = self
+                .find_entry(&inode, component)?
+                .ok_or(VfsError::NoSuchFile)?;
+            inode = self.read_inode(num)?;
+        }
+        if !inode.mode().is_directory() {
+            return Err(VfsError::NotDirectory);
+        }
+        Ok((num, inode, name))
     }
 
-    fn find_inode_from(
-        &self,
-        path: &Path,
-        starting_point: (ext2::InodeAddress, ext2::Inode),
-    ) -> Result<(ext2::InodeAddress, ext2::Inode)> {
-        let path = path.to_owned();
-        let components = path.components();
-        let fs = self.inner.read();
+    /// Whether the directory `dir` is `ancestor` or lies somewhere below it.
+    fn is_within(&self, mut dir: u32, ancestor: u32) -> Result<bool> {
+        // a corrupted file system might have a loop of `..` entries
+        for _ in 0..self.superblock.inodes_count() {
+            if dir == ancestor {
+                return Ok(true);
+            }
+            if dir == ROOT_INODE {
+                return Ok(false);
+            }
+            let inode = self.read_inode(dir)?;
+            dir = self.find_entry(&inode, "..")?.ok_or(VfsError::ReadError)?;
+        }
+        Err(VfsError::ReadError)
+    }
 
-        let (mut current_num, mut current) = starting_point;
-        for component in components {
-            match component {
-                Component::RootDir => {}
-                Component::CurrentDir => {} // do nothing,
-                Component::ParentDir => {
-                    todo!("parent dir");
-                }
-                Component::Normal(v) => {
-                    let x = current.typ();
-                    if x != Type::Directory {
-                        // TODO: symlink support
-                        return Err(VfsError::NoSuchFile);
-                    }
-                    // x is a directory
-                    let found_entry = fs
-                        .list_dir(&current) // list entries in the directory
-                        .map_err(|_| VfsError::NoSuchFile)?
-                        .into_iter()
-                        .find(|entry| entry.name() == Some(v))
-                        .ok_or(VfsError::NoSuchFile)?;
-                    (current_num, current) = fs
-                        .resolve_dir_entry(found_entry)
-                        .map_err(|_| VfsError::NoSuchFile)?;
-                }
+    /// Drops one name of the inode, after its directory entry was removed.
+    /// A directory has no names left then. The inode is freed once it has no
+    /// names left, or when its last handle is closed if it's still open.
+    fn drop_link(&mut self, num: u32, mut inode: Inode) -> Result<()> {
+        if inode.mode().is_directory() {
+            inode.set_links_count(0);
+        } else {
+            inode.set_links_count(inode.links_count().saturating_sub(1));
+        }
+        self.write_inode(num, &inode)?;
+
+        if inode.links_count() == 0 {
+            if self.handles.values().any(|&n| n == num) {
+                self.orphans.insert(num);
+            } else {
+                self.release_inode(num, inode)?;
             }
         }
+        Ok(())
+    }
 
-        Ok((current_num, current))
+    /// Frees the inode and all of its blocks. It must not be referenced by any
+    /// directory entry or handle anymore.
+    fn release_inode(&mut self, num: u32, mut inode: Inode) -> Result<()> {
+        // the block pointers of fast symbolic links hold the target, not blocks
+        if !self.is_fast_symlink(&inode) {
+            self.free_blocks_after(&mut inode, 0)?;
+        }
+        inode.set_size(0);
+        inode.set_links_count(0);
+        inode.set_dtime(self.superblock.write_time().max(1));
+        self.write_inode(num, &inode)?;
+        self.free_inode(num, inode.mode().is_directory())
     }
 }
 
 impl<T> FileSystem for VirtualExt2Fs<T>
 where
-    T: BlockDevice + Send + Sync,
+    T: Flush + Send + Sync,
 {
     fn fsid(&self) -> FsId {
         self.fsid
     }
 
-    fn open(&mut self, path: &Path) -> Result<VfsHandle> {
-        // FIXME: instead of returning a new handle, check whether we already have that inode open behind another handle
-        let (found_num, found) = self.find_inode(path)?;
-        let handle = next_handle();
-        let inode = Ext2Inode::new(self.fsid(), self.inner.clone(), found_num, found);
-        self.handles.insert(handle, Arc::new(RwLock::new(inode)));
-        Ok(handle)
+    fn name(&self) -> &'static str {
+        "ext2"
+    }
+
+    fn uses_page_cache(&self) -> bool {
+        true
+    }
+
+    fn root(&mut self) -> Result<VfsHandle> {
+        let root = self.read_inode(ROOT_INODE)?;
+        self.insert_handle(ROOT_INODE, &root)
+    }
+
+    fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+        let (_, parent) = self.resolve_handle(parent)?;
+        if !parent.mode().is_directory() {
+            return Err(VfsError::NotDirectory);
+        }
+        let num = self
+            .find_entry(&parent, name)?
+            .ok_or(VfsError::NoSuchFile)?;
+        let inode = self.read_inode(num)?;
+        self.insert_handle(num, &inode)
     }
 
     fn close(&mut self, handle: VfsHandle) -> Result<()> {
-        self.handles.remove(&handle).ok_or(VfsError::HandleClosed)?;
+        let num = self.handles.remove(&handle).ok_or(VfsError::HandleClosed)?;
+        if self.orphans.contains(&num) && !self.handles.values().any(|&n| n == num) {
+            self.orphans.remove(&num);
+            let inode = self.read_inode(num)?;
+            self.release_inode(num, inode)?;
+        }
         Ok(())
     }
 
-    fn read_dir(&mut self, path: &Path) -> Result<Vec<DirEntry>> {
-        let node = self.find_inode(path)?.1;
-        Ok(self
-            .inner
-            .read()
-            .list_dir(&node)
-            .map_err(|_| VfsError::NoSuchFile)?
-            .into_iter()
-            .filter(|entry| entry.name().is_some()) // TODO: could be none if the name is not valid utf8, we should maybe handle that differently
-            .filter(|entry| entry.typ().is_some()) // TODO: dir entries not necessarily have a type, do we want to support that?
-            .map(|entry| {
-                let name = entry.name().unwrap().to_string();
-                let ext2_type = entry.typ().unwrap();
-                DirEntry::new(name, ext2_type.into())
-            })
-            .collect())
+    fn read_dir(&mut self, handle: VfsHandle, cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+        let (_, dir) = self.resolve_handle(handle)?;
+        if !dir.mode().is_directory() {
+            return Err(VfsError::NotDirectory);
+        }
+        let dir_entries = self.list_dir(&dir)?;
+
+        // the cookie is the index of the next raw directory entry
+        let next_cookie = dir_entries.len() as u64;
+        let mut entries = FVec::new();
+        for entry in dir_entries.into_iter().skip(cookie as usize) {
+            if entry.name == "." || entry.name == ".." {
+                continue;
+            }
+            let typ = match entry.file_type {
+                Some(typ) => typ,
+                // file systems without file types in directory entries
+                None => match self.read_inode(entry.inode)?.file_type() {
+                    Some(typ) => typ,
+                    None => continue,
+                },
+            };
+            entries
+                .try_push(DirEntry::new(entry.name, typ))
+                .map_err(|_| VfsError::NoSpace)?;
+        }
+        Ok((entries, next_cookie))
     }
 
     fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
-        self.resolve_handle(handle)?.read().read(buf, offset)
+        let (_, inode) = self.resolve_handle(handle)?;
+        if !inode.mode().is_regular_file() {
+            return Err(VfsError::Unsupported);
+        }
+        self.read_data(&inode, buf, offset)
     }
 
     fn write(&mut self, handle: VfsHandle, buf: &[u8], offset: usize) -> Result<usize> {
-        self.resolve_handle(handle)?.write().write(buf, offset)
+        let (num, mut inode) = self.resolve_handle(handle)?;
+        if !inode.mode().is_regular_file() {
+            return Err(VfsError::Unsupported);
+        }
+        self.check_writable()?;
+        self.write_data(num, &mut inode, buf, offset)
+    }
+
+    fn read_async<'a>(
+        &'a mut self,
+        handle: VfsHandle,
+        buf: &'a mut [u8],
+        offset: usize,
+    ) -> BoxFuture<'a, Result<usize>> {
+        async move {
+            let (_, inode) = self.resolve_handle(handle)?;
+            if !inode.mode().is_regular_file() {
+                return Err(VfsError::Unsupported);
+            }
+            self.read_data_async(&inode, buf, offset).await
+        }
+        .boxed()
     }
 
-    fn truncate(&mut self, _handle: VfsHandle, _size: usize) -> Result<()> {
-        todo!()
+    /// Mounted file systems write to a block cache, so the write itself
+    /// doesn't wait for the disk, except for the sectors that it only partly
+    /// overwrites. Those are read asynchronously first.
+    fn write_async<'a>(
+        &'a mut self,
+        handle: VfsHandle,
+        buf: &'a [u8],
+        offset: usize,
+    ) -> BoxFuture<'a, Result<usize>> {
+        async move {
+            let (_, inode) = self.resolve_handle(handle)?;
+            if inode.mode().is_regular_file() && self.superblock.is_writable() {
+                self.read_partial_sectors_async(&inode, offset, buf.len())
+                    .await?;
+            }
+            self.write(handle, buf, offset)
+        }
+        .boxed()
+    }
+
+    fn truncate(&mut self, handle: VfsHandle, size: usize) -> Result<()> {
+        let (num, mut inode) = self.resolve_handle(handle)?;
+        if inode.mode().is_directory() {
+            return Err(VfsError::IsDirectory);
+        }
+        if !inode.mode().is_regular_file() {
+            return Err(VfsError::InvalidArgument);
+        }
+        self.check_writable()?;
+        self.truncate_data(num, &mut inode, size)
     }
 
     fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
-        self.resolve_handle(handle)?.read().stat(stat)
+        let (num, inode) = self.resolve_handle(handle)?;
+
+        stat.dev = self.fsid.0;
+        stat.ino = num as u64;
+        stat.mode = inode.mode();
+        stat.nlink = inode.links_count() as u32;
+        stat.uid = inode.uid() as u32;
+        stat.gid = inode.gid() as u32;
+        stat.rdev = 0; // TODO: set correct rdev
+        stat.size = inode.size();
+        stat.atime = inode.atime().into();
+        stat.mtime = inode.mtime().into();
+        stat.ctime = inode.ctime().into();
+        stat.blksize = self.block_size() as u64;
+        stat.blocks = inode.sectors() as u64;
+        Ok(())
+    }
+
+    fn readlink(&mut self, handle: VfsHandle) -> Result<String> {
+        let (_, inode) = self.resolve_handle(handle)?;
+        if !inode.mode().is_symlink() {
+            return Err(VfsError::InvalidArgument);
+        }
+        self.read_symlink(&inode)
     }
 
-    fn create(&mut self, _path: &Path, _ftype: FileType) -> Result<()> {
-        todo!()
+    fn create(&mut self, path: &Path, ftype: FileType, mode: FileMode) -> Result<()> {
+        self.check_writable()?;
+        let (parent_num, mut parent, name) = self.parent_of(path)?;
+        // the root directory always exists
+        let name = name.ok_or(VfsError::AlreadyExists)?;
+        if self.find_entry(&parent, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+
+        let (typ, directory) = match ftype {
+            FileType::RegularFile => (FileMode::S_IFREG, false),
+            FileType::Directory => (FileMode::S_IFDIR, true),
+            _ => return Err(VfsError::Unsupported),
+        };
+        dir::check_name(name)?;
+
+        let num = self.allocate_inode(self.group_of_inode(parent_num), directory)?;
+        let mut inode = Inode::zeroed();
+        inode.set_mode(typ | mode);
+        inode.set_times(self.superblock.write_time());
+        inode.set_links_count(if directory { 2 } else { 1 });
+        let initialized = if directory {
+            self.init_dir(num, &mut inode, parent_num)
+        } else {
+            inode.write_new(&mut self.device, &self.superblock, num)
+        };
+        let linked =
+            initialized.and_then(|_| self.add_entry(parent_num, &mut parent, name, num, ftype));
+        if let Err(e) = linked {
+            let _ = self.release_inode(num, inode);
+            return Err(e);
+        }
+
+        if directory {
+            parent.set_links_count(parent.links_count() + 1);
+            self.write_inode(parent_num, &parent)?;
+        }
+        Ok(())
     }
 
-    fn remove(&mut self, _path: &Path) -> Result<()> {
-        todo!()
+    fn remove(&mut self, path: &Path) -> Result<()> {
+        self.check_writable()?;
+        let (parent_num, mut parent, name) = self.parent_of(path)?;
+        // this is the mount point
+        let name = name.ok_or(VfsError::Busy)?;
+        let num = self
+            .find_entry(&parent, name)?
+            .ok_or(VfsError::NoSuchFile)?;
+        let inode = self.read_inode(num)?;
+        let directory = inode.mode().is_directory();
+        if directory && !self.is_empty_dir(&inode)? {
+            return Err(VfsError::NotEmpty);
+        }
+
+        self.remove_entry(parent_num, &mut parent, name)?;
+        if directory {
+            // the `..` entry of the removed directory doesn't count anymore
+            parent.set_links_count(parent.links_count().saturating_sub(1));
+            self.write_inode(parent_num, &parent)?;
+        }
+        self.drop_link(num, inode)
     }
-}
 
-impl From<ext2::DirType> for FileType {
-    fn from(value: ext2::DirType) -> Self {
-        match value {
-            x if x == ext2::DirType::Directory => FileType::Directory,
-            x if x == ext2::DirType::RegularFile => FileType::RegularFile,
-            x if x == ext2::DirType::CharacterDevice => FileType::CharacterDevice,
-            x if x == ext2::DirType::BlockDevice => FileType::BlockDevice,
-            x if x == ext2::DirType::FIFO => FileType::FIFO,
-            x if x == ext2::DirType::UnixSocket => FileType::Socket,
-            x if x == ext2::DirType::SymLink => FileType::SymbolicLink,
-            _ => unreachable!(),
+    fn link(&mut self, existing: &Path, to: &Path) -> Result<()> {
+        self.check_writable()?;
+        let (_, existing_parent, existing_name) = self.parent_of(existing)?;
+        // the root directory can't be linked, like any other directory
+        let existing_name = existing_name.ok_or(VfsError::PermissionDenied)?;
+        let num = self
+            .find_entry(&existing_parent, existing_name)?
+            .ok_or(VfsError::NoSuchFile)?;
+        let mut inode = self.read_inode(num)?;
+        let ftype = inode.file_type().ok_or(VfsError::Unsupported)?;
+        if ftype == FileType::Directory {
+            return Err(VfsError::PermissionDenied);
+        }
+        if inode.links_count() >= MAX_LINKS {
+            return Err(VfsError::TooManyLinks);
         }
+
+        let (parent_num, mut parent, name) = self.parent_of(to)?;
+        let name = name.ok_or(VfsError::AlreadyExists)?;
+        if self.find_entry(&parent, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        dir::check_name(name)?;
+
+        // count the link before the entry exists, so that an interrupted link
+        // leaves a link count that is too high, which only leaks the inode
+        inode.set_links_count(inode.links_count() + 1);
+        self.write_inode(num, &inode)?;
+        if let Err(e) = self.add_entry(parent_num, &mut parent, name, num, ftype) {
+            inode.set_links_count(inode.links_count() - 1);
+            let _ = self.write_inode(num, &inode);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        self.check_writable()?;
+        let (from_parent_num, from_parent, from_name) = self.parent_of(from)?;
+        let (to_parent_num, mut to_parent, to_name) = self.parent_of(to)?;
+        // one of them is the mount point
+        let (Some(from_name), Some(to_name)) = (from_name, to_name) else {
+            return Err(VfsError::Busy);
+        };
+        let num = self
+            .find_entry(&from_parent, from_name)?
+            .ok_or(VfsError::NoSuchFile)?;
+        let target = self.find_entry(&to_parent, to_name)?;
+        if target == Some(num) {
+            // both names already refer to the same node
+            return Ok(());
+        }
+        let mut inode = self.read_inode(num)?;
+        let ftype = inode.file_type().ok_or(VfsError::Unsupported)?;
+        let directory = ftype == FileType::Directory;
+        dir::check_name(to_name)?;
+        if directory && self.is_within(to_parent_num, num)? {
+            // can't move a directory into itself
+            return Err(VfsError::InvalidArgument);
+        }
+
+        let replaced = match target {
+            None => None,
+            Some(target_num) => {
+                let target_inode = self.read_inode(target_num)?;
+                match (directory, target_inode.mode().is_directory()) {
+                    (true, false) => return Err(VfsError::NotDirectory),
+                    (false, true) => return Err(VfsError::IsDirectory),
+                    (true, true) if !self.is_empty_dir(&target_inode)? => {
+                        return Err(VfsError::NotEmpty)
+                    }
+                    _ => {}
+                }
+                Some((target_num, target_inode))
+            }
+        };
+
+        // like in `link`, count the new name before its entry exists, so that
+        // an interrupted rename leaves a link count that is too high
+        inode.set_links_count(inode.links_count() + 1);
+        self.write_inode(num, &inode)?;
+        // an existing entry is pointed to the moved node in place, so that
+        // `to` refers to either the old or the new node at all times
+        let linked = match replaced {
+            Some(_) => self.replace_entry(&to_parent, to_name, num, ftype),
+            None => self.add_entry(to_parent_num, &mut to_parent, to_name, num, ftype),
+        };
+        if let Err(e) = linked {
+            inode.set_links_count(inode.links_count() - 1);
+            let _ = self.write_inode(num, &inode);
+            return Err(e);
+        }
+
+        // adding the entry might have changed the parent if it's the same one
+        let mut from_parent = self.read_inode(from_parent_num)?;
+        self.remove_entry(from_parent_num, &mut from_parent, from_name)?;
+        inode.set_links_count(inode.links_count() - 1);
+        self.write_inode(num, &inode)?;
+
+        // the `..` entries of directories count as links of their parents
+        let (mut from_delta, mut to_delta) = (0, 0);
+        if directory && from_parent_num != to_parent_num {
+            self.replace_entry(&inode, "..", to_parent_num, FileType::Directory)?;
+            from_delta -= 1;
+            to_delta += 1;
+        }
+        if replaced
+            .as_ref()
+            .is_some_and(|(_, target_inode)| target_inode.mode().is_directory())
+        {
+            to_delta -= 1;
+        }
+        for (parent_num, delta) in [(from_parent_num, from_delta), (to_parent_num, to_delta)] {
+            if delta != 0 {
+                let mut parent = self.read_inode(parent_num)?;
+                parent.set_links_count(parent.links_count().saturating_add_signed(delta));
+                self.write_inode(parent_num, &parent)?;
+            }
+        }
+
+        match replaced {
+            Some((target_num, target_inode)) => self.drop_link(target_num, target_inode),
+            None => Ok(()),
+        }
+    }
+
+    fn sync(&mut self) -> Result<()> {
+        self.device.flush().map_err(|_| VfsError::WriteError)
     }
 }
@@ -0,0 +1,41 @@
+use alloc::string::String;
+use alloc::vec;
+
+use crate::io::vfs::cache::Flush;
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::ext2::layout::Inode;
+use crate::io::vfs::ext2::VirtualExt2Fs;
+
+/// Targets that are shorter than this are stored in the block pointers of the
+/// inode itself ("fast" symlinks).
+const FAST_SYMLINK_MAX_LEN: usize = 60;
+
+impl<T> VirtualExt2Fs<T>
+where
+    T: Flush,
+{
+    /// Whether the inode is a symbolic link that stores its target in the
+    /// block pointers instead of a data block.
+    pub(super) fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        if !inode.mode().is_symlink() {
+            return false;
+        }
+        let mut sectors = inode.sectors();
+        if inode.file_acl() != 0 {
+            // an extended attribute block also counts towards the sectors of the inode
+            sectors = sectors.saturating_sub((self.block_size() / 512) as u32);
+        }
+        sectors == 0 && (inode.size() as usize) < FAST_SYMLINK_MAX_LEN
+    }
+
+    pub(super) fn read_symlink(&self, inode: &Inode) -> Result<String> {
+        let len = inode.size() as usize;
+        let mut target = vec![0_u8; len];
+        if self.is_fast_symlink(inode) {
+            target.copy_from_slice(&inode.block_bytes()[..len]);
+        } else if self.read_data(inode, &mut target, 0)? != len {
+            return Err(VfsError::ReadError);
+        }
+        String::from_utf8(target).map_err(|_| VfsError::ReadError)
+    }
+}
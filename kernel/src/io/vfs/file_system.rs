@@ -1,16 +1,24 @@
-use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
 use core::ops::BitAnd;
 
 use derive_more::{Constructor, Display};
+use foundation::falloc::vec::FVec;
+use futures::future::BoxFuture;
+use futures::FutureExt;
 use x86_64::structures::paging::PhysFrame;
 
-use kernel_api::syscall::{FileMode, Stat};
+use kernel_api::syscall::{
+    Errno, FileMode, PollEvents, Stat, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK,
+};
 
-use crate::io::path::Path;
-use crate::io::vfs::error::Result;
+use crate::io::path::{Component, Path};
+use crate::io::vfs::error::{Result, VfsError};
 use crate::io::vfs::FsId;
+use crate::process::fd::Fileno;
+use crate::process::wait_queue::WaitQueue;
+use crate::syscall::convert::UserspaceMutPtr;
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct VfsHandle(u64);
@@ -21,6 +29,17 @@ impl VfsHandle {
     }
 }
 
+/// What a memory mapping of a file is backed by, as returned by
+/// [`FileSystem::mmap`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum MmapBacking {
+    /// The file is device memory, like a framebuffer. The frames are mapped
+    /// directly, starting with the frame at the requested offset.
+    Physical(Vec<PhysFrame>),
+    /// The file is read into memory when the mapping is accessed.
+    FileBacked,
+}
+
 #[derive(Constructor, Debug, Clone, Eq, PartialEq)]
 pub struct DirEntry {
     pub name: String,
@@ -31,11 +50,27 @@ pub trait FileSystem: Send + Sync {
     /// Returns the file system id of this file system.
     fn fsid(&self) -> FsId;
 
+    /// Returns the name of the file system type, like `ext2` or `tmpfs`.
+    fn name(&self) -> &'static str;
+
     fn exists(&mut self, path: &Path) -> Result<bool> {
         let _ = self.open(path)?;
         Ok(true)
     }
 
+    /// Opens the root directory of this file system and returns a handle to it.
+    /// The handle must be closed with [`FileSystem::close`] like any other handle.
+    fn root(&mut self) -> Result<VfsHandle>;
+
+    /// Opens the entry with the given name in the directory associated with
+    /// `parent` and returns a new handle to it. `parent` stays open.
+    ///
+    /// `name` is a single path component, and is never `.` or `..`, which are
+    /// handled by the VFS. Implementations return [`VfsError::NotDirectory`] if
+    /// `parent` is not a directory, and [`VfsError::NoSuchFile`] if there is no
+    /// entry with that name.
+    fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle>;
+
     /// Opens the file at the given path and returns a handle to it.
     /// the handle is file system specific, and the file system must coordinate
     /// and associate it with the appropriate file.
@@ -44,12 +79,51 @@ pub trait FileSystem: Send + Sync {
     /// Files that have been closed must not be read from or written to.
     /// Implementations should return [`VfsError::HandleClosed`] if the handle
     /// is invalid.
-    fn open(&mut self, path: &Path) -> Result<VfsHandle>;
+    ///
+    /// The path is relative to the root of this file system. The default
+    /// implementation walks the path with [`FileSystem::lookup`], starting at
+    /// [`FileSystem::root`]. `..` components are not supported, the VFS resolves
+    /// them before it calls into the file system.
+    fn open(&mut self, path: &Path) -> Result<VfsHandle> {
+        let mut current = self.root()?;
+        for component in path.components() {
+            let name = match component {
+                Component::RootDir | Component::CurrentDir => continue,
+                Component::ParentDir => {
+                    let _ = self.close(current);
+                    return Err(VfsError::Unsupported);
+                }
+                Component::Normal(name) => name,
+            };
+            let next = self.lookup(current, name);
+            let _ = self.close(current);
+            current = next?;
+        }
+        Ok(current)
+    }
 
     /// Closes the file associated with the given handle.
     fn close(&mut self, handle: VfsHandle) -> Result<()>;
 
-    fn read_dir(&mut self, path: &Path) -> Result<Vec<DirEntry>>;
+    /// Returns the file descriptor of the calling process that the file
+    /// associated with the given handle stands for, like `/dev/fd/1` stands for
+    /// descriptor 1. Opening such a file opens the file of the descriptor
+    /// instead, so the handle is only used to find the descriptor.
+    ///
+    /// The default implementation returns `None`, since files usually stand
+    /// for themselves.
+    fn file_descriptor(&self, handle: VfsHandle) -> Option<Fileno> {
+        let _ = handle;
+        None
+    }
+
+    /// Reads the entries of the directory associated with the given handle,
+    /// starting at `cookie`. The first call passes a cookie of zero, and every
+    /// following call passes the cookie that the previous call returned.
+    /// An empty list of entries means that there are no more entries.
+    ///
+    /// `.` and `..` are not part of the returned entries.
+    fn read_dir(&mut self, handle: VfsHandle, cookie: u64) -> Result<(FVec<DirEntry>, u64)>;
 
     /// Reads from the given offset from the file associated with the given handle
     /// into the given buffer.
@@ -63,10 +137,47 @@ pub trait FileSystem: Send + Sync {
     /// If an error occurs, the file may be partially written.
     fn write(&mut self, handle: VfsHandle, buf: &[u8], offset: usize) -> Result<usize>;
 
+    /// Like [`FileSystem::read`], but returns a future, so that callers that
+    /// run on an executor don't block while the file system waits for its device.
+    ///
+    /// The default implementation performs the synchronous [`FileSystem::read`]
+    /// when the future is first polled. File systems whose devices can complete
+    /// requests asynchronously override this.
+    fn read_async<'a>(
+        &'a mut self,
+        handle: VfsHandle,
+        buf: &'a mut [u8],
+        offset: usize,
+    ) -> BoxFuture<'a, Result<usize>> {
+        async move { self.read(handle, buf, offset) }.boxed()
+    }
+
+    /// Like [`FileSystem::write`], but returns a future. See
+    /// [`FileSystem::read_async`].
+    fn write_async<'a>(
+        &'a mut self,
+        handle: VfsHandle,
+        buf: &'a [u8],
+        offset: usize,
+    ) -> BoxFuture<'a, Result<usize>> {
+        async move { self.write(handle, buf, offset) }.boxed()
+    }
+
     fn truncate(&mut self, handle: VfsHandle, size: usize) -> Result<()>;
 
     fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()>;
 
+    /// Reads the target of the symbolic link associated with the given handle.
+    /// The target is returned as it is stored, without resolving it.
+    ///
+    /// Returns [`VfsError::InvalidArgument`] if the node is not a symbolic link,
+    /// which is what the default implementation does for file systems that
+    /// don't support symbolic links.
+    fn readlink(&mut self, handle: VfsHandle) -> Result<String> {
+        let _ = handle;
+        Err(VfsError::InvalidArgument)
+    }
+
     fn stat_path(&mut self, p: &Path, stat: &mut Stat) -> Result<()> {
         let handle = self.open(p)?;
         let res = self.stat(handle, stat);
@@ -75,25 +186,109 @@ pub trait FileSystem: Send + Sync {
     }
 
     /// Creates a node at the given path.
-    /// The type of the node is specified by the [`ftype`] parameter.
+    /// The type of the node is specified by the [`ftype`] parameter, and its
+    /// permission bits by `mode`, which never contains file type bits.
     /// The node must be opened with [`FileSystem::open`] to use it.
     ///
     /// In a single threaded environment, if this function returns successfully,
     /// it is guaranteed that [`FileSystem::open`] will succeed with the newly
     /// created node.
-    fn create(&mut self, path: &Path, ftype: FileType) -> Result<()>;
+    fn create(&mut self, path: &Path, ftype: FileType, mode: FileMode) -> Result<()>;
 
     /// Removes the node at the given path.
+    ///
+    /// Handles that are open on the removed node must stay usable until they
+    /// are closed. The content of the node may only be freed once its last
+    /// handle is closed.
     fn remove(&mut self, path: &Path) -> Result<()>;
 
-    /// Some files may not be backed by disk storage or virtual memory, but by physical memory.
-    /// This method allows the file system to expose the physical memory backing the file in case
-    /// it needs to be mmapped (such as frame buffers or other device-specific memory).
-    fn physical_memory(
-        &self,
+    /// Adds `to` as another name for the node at `existing`, which is never a
+    /// directory. Both names refer to the same node afterwards, and the node
+    /// is only removed once it is removed under all of its names.
+    ///
+    /// Returns [`VfsError::AlreadyExists`] if there is a node at `to`. The
+    /// default implementation returns [`VfsError::PermissionDenied`], which is
+    /// correct for file systems that don't support hard links.
+    fn link(&mut self, existing: &Path, to: &Path) -> Result<()> {
+        let _ = (existing, to);
+        Err(VfsError::PermissionDenied)
+    }
+
+    /// Moves the node at `from` to `to`, replacing whatever is at `to`.
+    ///
+    /// This must be atomic: as long as the file system is locked for the
+    /// duration of the call, any concurrent open of `to` must see either the
+    /// old or the new node. Handles that are open on a replaced node must stay
+    /// usable until they are closed.
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Writes everything that the file system holds in memory back to the
+    /// underlying device, so that it survives a reboot.
+    ///
+    /// The default implementation does nothing, which is correct for file
+    /// systems that don't have a device.
+    fn sync(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the VFS caches the content of the regular files of this file
+    /// system in the page cache. Reads of cached files only reach the file
+    /// system for pages that are not cached yet, writes are written through.
+    ///
+    /// File systems that keep their content in memory anyway, or whose content
+    /// changes behind the VFS's back, don't use the page cache, which is the
+    /// default.
+    fn uses_page_cache(&self) -> bool {
+        false
+    }
+
+    /// Returns how `len` bytes of the file starting at `offset` can be mapped
+    /// into memory. The VFS has already checked the range against the size of
+    /// the file.
+    ///
+    /// The default implementation maps regular files through the file system's
+    /// `read` and rejects everything else with [`VfsError::Unsupported`]. File
+    /// systems that expose device memory (such as framebuffers) return the
+    /// physical frames instead.
+    fn mmap(&mut self, handle: VfsHandle, _offset: usize, _len: usize) -> Result<MmapBacking> {
+        let mut stat = Stat::default();
+        self.stat(handle, &mut stat)?;
+        if stat.mode.is_regular_file() {
+            Ok(MmapBacking::FileBacked)
+        } else {
+            Err(VfsError::Unsupported)
+        }
+    }
+
+    /// Returns which of the events in `interest` are currently ready for the file
+    /// associated with the given handle. [`PollEvents::ALWAYS`] may be reported
+    /// regardless of `interest`.
+    ///
+    /// The default implementation reports the file as always readable and writable,
+    /// which is correct for files that never block.
+    fn poll(&self, _handle: VfsHandle, interest: PollEvents) -> Result<PollEvents> {
+        Ok(interest & (PollEvents::POLLIN | PollEvents::POLLOUT))
+    }
+
+    /// Returns the queue that is woken whenever what [`FileSystem::poll`]
+    /// reports for the file associated with the given handle may have changed,
+    /// so that threads can wait for the file to become ready.
+    ///
+    /// Files that never block don't need one, which is the default.
+    fn wait_queue(&self, _handle: VfsHandle) -> Option<Arc<WaitQueue>> {
+        None
+    }
+
+    /// Performs a device specific operation on the file associated with the given
+    /// handle. Files that don't support the request return [`Errno::ENOTTY`], which
+    /// is the default for all requests.
+    fn ioctl(
+        &mut self,
         _handle: VfsHandle,
-    ) -> Result<Option<Box<dyn Iterator<Item = PhysFrame> + '_>>> {
-        Ok(None)
+        _request: u32,
+        _arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        Err(Errno::ENOTTY)
     }
 }
 
@@ -123,3 +318,18 @@ impl From<FileMode> for FileType {
         }
     }
 }
+
+impl FileType {
+    /// The `DT_*` constant for this type, as directory entries carry it.
+    pub fn dirent_type(self) -> u8 {
+        match self {
+            Self::RegularFile => DT_REG,
+            Self::Directory => DT_DIR,
+            Self::CharacterDevice => DT_CHR,
+            Self::BlockDevice => DT_BLK,
+            Self::FIFO => DT_FIFO,
+            Self::Socket => DT_SOCK,
+            Self::SymbolicLink => DT_LNK,
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use spin::Mutex;
+use x86_64::instructions::hlt;
+
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::InodeId;
+use crate::process::attributes::ProcessId;
+
+/// The kind of an advisory file lock.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LockKind {
+    /// Any number of processes can hold a shared lock at the same time.
+    Shared,
+    /// Only a single process can hold an exclusive lock, and no other process
+    /// can hold a shared lock at the same time.
+    Exclusive,
+}
+
+/// A process that waits for a lock.
+struct Waiter {
+    ticket: u64,
+    owner: ProcessId,
+    kind: LockKind,
+}
+
+#[derive(Default)]
+struct FileLock {
+    holders: BTreeMap<ProcessId, LockKind>,
+    /// The processes that wait for the lock, in the order in which they asked
+    /// for it.
+    waiters: VecDeque<Waiter>,
+}
+
+impl FileLock {
+    fn is_unused(&self) -> bool {
+        self.holders.is_empty() && self.waiters.is_empty()
+    }
+
+    /// Whether `owner` could hold the lock as `kind` next to the other holders.
+    fn is_compatible(&self, owner: ProcessId, kind: LockKind) -> bool {
+        self.holders
+            .iter()
+            .filter(|(holder, _)| **holder != owner)
+            .all(|(_, held)| kind == LockKind::Shared && *held == LockKind::Shared)
+    }
+
+    /// Whether the waiter with the given ticket can't ever be granted the lock,
+    /// because it converts a held lock, and so does another holder. Both of them
+    /// would wait for the other one to release its lock.
+    fn is_deadlocked(&self, ticket: u64) -> bool {
+        let mut converting = self
+            .waiters
+            .iter()
+            .filter(|waiter| self.holders.contains_key(&waiter.owner));
+        converting.clone().any(|waiter| waiter.ticket == ticket)
+            && converting.any(|waiter| waiter.ticket != ticket)
+    }
+
+    /// Grants the lock to the waiter with the given ticket if that is possible
+    /// right now, and returns whether it did.
+    ///
+    /// New holders are granted the lock in the order in which they asked for it.
+    /// Holders that convert their lock don't queue behind the other waiters,
+    /// since those wait for the held lock anyway. The held lock is only replaced
+    /// once the conversion is granted, so no other waiter can take the lock in
+    /// between.
+    fn try_grant(&mut self, ticket: u64) -> bool {
+        let Some(index) = self.waiters.iter().position(|w| w.ticket == ticket) else {
+            return false;
+        };
+        let waiter = &self.waiters[index];
+        let is_holder = self.holders.contains_key(&waiter.owner);
+        if !self.is_compatible(waiter.owner, waiter.kind) || (!is_holder && index != 0) {
+            return false;
+        }
+
+        let waiter = self.waiters.remove(index).unwrap();
+        self.holders.insert(waiter.owner, waiter.kind);
+        true
+    }
+
+    fn remove_waiter(&mut self, ticket: u64) {
+        self.waiters.retain(|waiter| waiter.ticket != ticket);
+    }
+}
+
+/// The advisory locks of all files, as used by `flock`.
+///
+/// Locks are held by processes. A process holds at most one lock per file,
+/// which it can convert between shared and exclusive.
+pub struct LockTable {
+    locks: Mutex<BTreeMap<InodeId, FileLock>>,
+    next_ticket: AtomicU64,
+}
+
+impl LockTable {
+    pub(super) const fn new() -> Self {
+        Self {
+            locks: Mutex::new(BTreeMap::new()),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Acquires the lock of the given file for `owner`, or converts the lock
+    /// that `owner` already holds to `kind`.
+    ///
+    /// If the lock can't be granted right away, this waits until it can if
+    /// `block` is set, and returns [`VfsError::WouldBlock`] otherwise. A lock that
+    /// is already held is kept if the conversion fails.
+    ///
+    /// Returns [`VfsError::Deadlock`] if another holder already waits to convert
+    /// its lock, and [`VfsError::Interrupted`] if the waiting was cancelled with
+    /// [`LockTable::release_all`].
+    pub fn lock(
+        &self,
+        inode: InodeId,
+        owner: ProcessId,
+        kind: LockKind,
+        block: bool,
+    ) -> Result<()> {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        {
+            let mut locks = self.locks.lock();
+            let lock = locks.entry(inode).or_default();
+            if lock.holders.get(&owner) == Some(&kind) {
+                return Ok(());
+            }
+
+            lock.waiters.push_back(Waiter {
+                ticket,
+                owner,
+                kind,
+            });
+            if lock.try_grant(ticket) {
+                return Ok(());
+            }
+
+            let error = if lock.is_deadlocked(ticket) {
+                Some(VfsError::Deadlock)
+            } else if !block {
+                Some(VfsError::WouldBlock)
+            } else {
+                None
+            };
+            if let Some(error) = error {
+                lock.remove_waiter(ticket);
+                if lock.is_unused() {
+                    locks.remove(&inode);
+                }
+                return Err(error);
+            }
+        }
+
+        loop {
+            // we can't park threads yet, so we give up our time slice and check again
+            hlt();
+
+            let mut locks = self.locks.lock();
+            let lock = locks.get_mut(&inode).ok_or(VfsError::Interrupted)?;
+            if !lock.waiters.iter().any(|waiter| waiter.ticket == ticket) {
+                return Err(VfsError::Interrupted);
+            }
+            if lock.try_grant(ticket) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Releases the lock that `owner` holds on the given file, if any.
+    pub fn unlock(&self, inode: InodeId, owner: ProcessId) {
+        let mut locks = self.locks.lock();
+        if let Some(lock) = locks.get_mut(&inode) {
+            lock.holders.remove(&owner);
+            if lock.is_unused() {
+                locks.remove(&inode);
+            }
+        }
+    }
+
+    /// Releases all locks that `owner` holds, and cancels all of its waits.
+    pub fn release_all(&self, owner: ProcessId) {
+        let mut locks = self.locks.lock();
+        locks.retain(|_, lock| {
+            lock.holders.remove(&owner);
+            lock.waiters.retain(|waiter| waiter.owner != owner);
+            !lock.is_unused()
+        });
+    }
+
+    /// Returns the kind of lock that `owner` holds on the given file.
+    pub fn held_by(&self, inode: InodeId, owner: ProcessId) -> Option<LockKind> {
+        self.locks
+            .lock()
+            .get(&inode)
+            .and_then(|lock| lock.holders.get(&owner).copied())
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::vfs::lock::{LockKind, LockTable};
+    use crate::io::vfs::{FsId, InodeId, VfsError};
+    use crate::process::attributes::ProcessId;
+
+    #[kernel_test]
+    fn test_shared_and_exclusive() {
+        let table = LockTable::new();
+        let key = InodeId::new(FsId::new(), 1);
+        let (a, b) = (ProcessId::new(), ProcessId::new());
+
+        table.lock(key, a, LockKind::Shared, false).unwrap();
+        table.lock(key, b, LockKind::Shared, false).unwrap();
+        assert_eq!(
+            Err(VfsError::WouldBlock),
+            table.lock(key, a, LockKind::Exclusive, false)
+        );
+        // the failed upgrade keeps the shared lock
+        assert_eq!(Some(LockKind::Shared), table.held_by(key, a));
+
+        table.unlock(key, b);
+        table.lock(key, a, LockKind::Exclusive, false).unwrap();
+        assert_eq!(
+            Err(VfsError::WouldBlock),
+            table.lock(key, b, LockKind::Shared, false)
+        );
+
+        // downgrading never waits
+        table.lock(key, a, LockKind::Shared, false).unwrap();
+        table.lock(key, b, LockKind::Shared, false).unwrap();
+
+        // locks of other files are independent
+        let other = InodeId::new(FsId::new(), 1);
+        table.lock(other, b, LockKind::Exclusive, false).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_release_all() {
+        let table = LockTable::new();
+        let (first, second) = (InodeId::new(FsId::new(), 1), InodeId::new(FsId::new(), 2));
+        let (a, b) = (ProcessId::new(), ProcessId::new());
+
+        table.lock(first, a, LockKind::Exclusive, false).unwrap();
+        table.lock(second, a, LockKind::Shared, false).unwrap();
+        table.release_all(a);
+        assert_eq!(None, table.held_by(first, a));
+        assert_eq!(None, table.held_by(second, a));
+        table.lock(first, b, LockKind::Exclusive, false).unwrap();
+        table.lock(second, b, LockKind::Exclusive, false).unwrap();
+        assert_eq!(2, table.locks.lock().len());
+
+        table.release_all(b);
+        assert!(table.locks.lock().is_empty());
+    }
+
+    #[kernel_test]
+    fn test_concurrent_upgrades_deadlock() {
+        let table = LockTable::new();
+        let key = InodeId::new(FsId::new(), 1);
+        let (a, b) = (ProcessId::new(), ProcessId::new());
+
+        table.lock(key, a, LockKind::Shared, false).unwrap();
+        table.lock(key, b, LockKind::Shared, false).unwrap();
+
+        // pretend that `a` waits for its upgrade
+        {
+            let mut locks = table.locks.lock();
+            let lock = locks.get_mut(&key).unwrap();
+            lock.waiters.push_back(super::Waiter {
+                ticket: u64::MAX,
+                owner: a,
+                kind: LockKind::Exclusive,
+            });
+        }
+        assert_eq!(
+            Err(VfsError::Deadlock),
+            table.lock(key, b, LockKind::Exclusive, true)
+        );
+        assert_eq!(Some(LockKind::Shared), table.held_by(key, b));
+
+        // once `b` gives up its lock, the upgrade of `a` goes through, even
+        // though new waiters queued up in front of it
+        table.unlock(key, b);
+        let mut locks = table.locks.lock();
+        let lock = locks.get_mut(&key).unwrap();
+        lock.waiters.push_front(super::Waiter {
+            ticket: u64::MAX - 1,
+            owner: b,
+            kind: LockKind::Shared,
+        });
+        assert!(lock.try_grant(u64::MAX));
+        assert_eq!(Some(&LockKind::Exclusive), lock.holders.get(&a));
+        assert!(!lock.try_grant(u64::MAX - 1));
+    }
+}
@@ -2,19 +2,36 @@ use alloc::borrow::ToOwned;
 use alloc::collections::BTreeMap;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
-use core::sync::atomic::AtomicU64;
-use core::sync::atomic::Ordering::Relaxed;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering::{Relaxed, SeqCst};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize};
 
+use bitflags::bitflags;
+use conquer_once::spin::OnceCell;
 use spin::RwLock;
+use x86_64::structures::paging::{PageSize, PhysFrame, Size4KiB};
 
-use crate::driver::ide;
-use crate::io::path::{OwnedPath, Path};
-use crate::io::vfs::cache::CachingBlockDevice;
-use crate::io::vfs::devfs::VirtualDevFs;
+use crate::driver::block::BlockDeviceEvent;
+use crate::driver::disk;
+use crate::io::path::{Component, OwnedPath, Path, SEPARATOR};
+use crate::io::vfs::cache::BlockCache;
 use crate::io::vfs::ext2::VirtualExt2Fs;
+use crate::io::vfs::lock::{LockKind, LockTable};
+use crate::io::vfs::page_cache::{CachedFile, PageCache};
+use crate::io::vfs::pipe::PipeFs;
+use crate::io::vfs::procfs::ProcFs;
+use crate::io::vfs::scheduler::IoScheduler;
+use crate::io::vfs::tmpfs::TmpFs;
+use crate::mem::PhysicalMemoryManager;
+use crate::process;
+use crate::process::attributes::ProcessId;
+use crate::process::wait_queue::WaitQueue;
+use crate::syscall::convert::UserspaceMutPtr;
 pub use error::*;
 pub use file_system::*;
-use kernel_api::syscall::Stat;
+use kernel_api::syscall::{Errno, FileMode, PollEvents, Stat};
+use log::warn;
+pub use resolve::*;
 pub use vfs_node::*;
 
 pub mod cache;
@@ -22,99 +39,442 @@ pub mod devfs;
 mod error;
 pub mod ext2;
 mod file_system;
+pub mod lock;
+pub mod page_cache;
+pub mod pipe;
+pub mod procfs;
+mod resolve;
+pub mod scheduler;
+pub mod tmpfs;
 mod vfs_node;
 
 static VFS: Vfs = Vfs::new();
+static PIPEFS: OnceCell<Arc<RwLock<PipeFs>>> = OnceCell::uninit();
 
 pub fn vfs() -> &'static Vfs {
     &VFS
 }
 
 pub fn init() {
-    let root_drive = ide::devices()
+    // the second IDE drive, after the one that the kernel boots from
+    let (root_index, root_drive) = disk::devices()
         .lock()
-        .get(1)
-        .expect("we need at least one additional IDE drive for now")
-        .clone();
-    let root_drive_cache = CachingBlockDevice::new(
-        root_drive, 204_800, // 100 MB
+        .iter()
+        .enumerate()
+        .filter(|(_, device)| device.as_ide().is_some())
+        .nth(1)
+        .map(|(index, device)| (index, device.clone()))
+        .expect("we need at least one additional IDE drive for now");
+    let root_drive_cache = BlockCache::new(
+        IoScheduler::new(root_drive),
+        204_800, // 100 MB
     );
 
-    let ext2fs = VirtualExt2Fs::new(
-        FsId::new(),
-        ::ext2::Ext2Fs::try_new(root_drive_cache).expect("root drive must be ext2 for now"),
-    );
-    vfs().mount("/", ext2fs).expect("failed to mount root fs");
+    let ext2fs = VirtualExt2Fs::try_new(FsId::new(), root_drive_cache)
+        .expect("root drive must be ext2 for now");
+    // the root file system is only written to once it is explicitly remounted
+    // read-write with `Vfs::remount`
+    vfs()
+        .mount_device("/", ext2fs, MountFlags::READ_ONLY, root_index)
+        .expect("failed to mount root fs");
+    disk::devices().on_change(|event| {
+        if let BlockDeviceEvent::Unregistered(index) = event {
+            vfs().device_gone(index);
+        }
+    });
+
+    vfs()
+        .mount_shared("/dev", devfs::init(), MountFlags::empty())
+        .expect("failed to mount devfs");
+
+    vfs()
+        .mount("/proc", ProcFs::new(FsId::new()), MountFlags::empty())
+        .expect("failed to mount procfs");
+
+    for mount_point in ["/tmp", "/var/tmp"] {
+        vfs()
+            .mount(mount_point, TmpFs::new(FsId::new()), MountFlags::empty())
+            .expect("failed to mount tmpfs");
+    }
 
-    let devfs = VirtualDevFs::new(FsId::new());
-    vfs().mount("/dev", devfs).expect("failed to mount devfs");
+    PIPEFS.init_once(|| Arc::new(RwLock::new(PipeFs::new(FsId::new()))));
+
+    PhysicalMemoryManager::set_reclaim_hook(page_cache::reclaim);
 }
 
 static FSID_COUNTER: AtomicU64 = AtomicU64::new(0);
 
+/// The maximum number of symbolic links that are followed while resolving a
+/// single path.
+const MAX_SYMLINK_HOPS: usize = 40;
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct FsId(u64);
 
 impl FsId {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self(FSID_COUNTER.fetch_add(1, Relaxed))
     }
 }
 
+/// Identifies a file across all mounted file systems. Handles are opened per
+/// path walk, so two handles of the same file only share the inode number.
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
+pub struct InodeId {
+    fsid: FsId,
+    ino: u64,
+}
+
+impl InodeId {
+    pub fn new(fsid: FsId, ino: u64) -> Self {
+        Self { fsid, ino }
+    }
+}
+
+bitflags! {
+    /// How a file system is mounted.
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+    pub struct MountFlags: u32 {
+        /// Nothing on the file system can be changed. Operations that would
+        /// change it fail with [`VfsError::ReadOnly`] before they reach the
+        /// file system.
+        const READ_ONLY = 0x1;
+    }
+}
+
+/// The state of a mount that its [`VfsNode`]s share.
+struct MountState {
+    /// The number of [`VfsNode`]s that are currently open on this mount.
+    open_handles: AtomicUsize,
+    /// The number of open [`VfsNode`]s that have been written through.
+    dirty_handles: AtomicUsize,
+    flags: AtomicU32,
+    /// The index of the block device in [`disk::devices`] that the file system
+    /// is on, if it is on one.
+    device: Option<usize>,
+    /// Whether the device was removed, after which the mount can only be
+    /// unmounted.
+    gone: AtomicBool,
+}
+
+impl MountState {
+    fn new(flags: MountFlags, device: Option<usize>) -> Self {
+        Self {
+            open_handles: AtomicUsize::new(0),
+            dirty_handles: AtomicUsize::new(0),
+            flags: AtomicU32::new(flags.bits()),
+            device,
+            gone: AtomicBool::new(false),
+        }
+    }
+
+    fn check_present(&self) -> Result<()> {
+        if self.gone.load(SeqCst) {
+            Err(VfsError::DeviceGone)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn flags(&self) -> MountFlags {
+        MountFlags::from_bits_retain(self.flags.load(SeqCst))
+    }
+
+    fn check_writable(&self) -> Result<()> {
+        self.check_present()?;
+        if self.flags().contains(MountFlags::READ_ONLY) {
+            Err(VfsError::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A file system that is mounted in the VFS.
+#[derive(Clone)]
+struct Mount {
+    fs: Arc<RwLock<dyn FileSystem>>,
+    /// The name of the file system type. It is kept here so that listing the
+    /// mounts doesn't need to lock the file systems, which may be the one that
+    /// lists them, like the procfs.
+    fs_name: &'static str,
+    state: Arc<MountState>,
+}
+
+/// Information about a mounted file system, as returned by [`Vfs::mounts`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MountInfo {
+    pub path: OwnedPath,
+    pub fs_name: &'static str,
+    pub flags: MountFlags,
+    pub open_handles: usize,
+}
+
 pub struct Vfs {
-    mounts: RwLock<BTreeMap<OwnedPath, Arc<RwLock<dyn FileSystem>>>>,
+    mounts: RwLock<BTreeMap<OwnedPath, Mount>>,
+    locks: LockTable,
+    page_cache: PageCache,
 }
 
 impl Vfs {
-    pub fn mount<P, F>(&self, mount_point: P, fs: F) -> Result<()>
+    /// Mounts `fs` at the given path. The mount point doesn't have to exist in
+    /// the parent file system. If it does, it is shadowed until the file system
+    /// is unmounted again.
+    ///
+    /// Returns [`VfsError::AlreadyMounted`] if there already is a file system
+    /// mounted at that path.
+    pub fn mount<P, F>(&self, mount_point: P, fs: F, flags: MountFlags) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FileSystem + 'static,
+    {
+        self.mount_shared(mount_point, Arc::new(RwLock::new(fs)), flags)
+    }
+
+    /// Like [`Vfs::mount`], but for a file system that is also used outside
+    /// of the VFS, like the devfs, which drivers register their devices with.
+    pub fn mount_shared<P>(
+        &self,
+        mount_point: P,
+        fs: Arc<RwLock<dyn FileSystem>>,
+        flags: MountFlags,
+    ) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.insert_mount(mount_point.as_ref(), fs, flags, None)
+    }
+
+    /// Like [`Vfs::mount`], but for a file system on the block device with the
+    /// given index in [`disk::devices`]. If the device is removed, the mount
+    /// fails all operations with [`VfsError::DeviceGone`], see
+    /// [`Vfs::device_gone`].
+    pub fn mount_device<P, F>(
+        &self,
+        mount_point: P,
+        fs: F,
+        flags: MountFlags,
+        device: usize,
+    ) -> Result<()>
     where
         P: AsRef<Path>,
         F: FileSystem + 'static,
     {
-        let fs = Arc::new(RwLock::new(fs));
-        self.mounts.write().insert(mount_point.into(), fs);
+        self.insert_mount(
+            mount_point.as_ref(),
+            Arc::new(RwLock::new(fs)),
+            flags,
+            Some(device),
+        )
+    }
+
+    fn insert_mount(
+        &self,
+        mount_point: &Path,
+        fs: Arc<RwLock<dyn FileSystem>>,
+        flags: MountFlags,
+        device: Option<usize>,
+    ) -> Result<()> {
+        let mut guard = self.mounts.write();
+        let mount_point = mount_point.normalize();
+        if guard.contains_key(&mount_point) {
+            return Err(VfsError::AlreadyMounted);
+        }
+        let fs_name = fs.read().name();
+        guard.insert(
+            mount_point,
+            Mount {
+                fs,
+                fs_name,
+                state: Arc::new(MountState::new(flags, device)),
+            },
+        );
         Ok(())
     }
 
+    /// Puts the mounts of the block device with the given index into an
+    /// errored state, because the device was removed. Everything on them,
+    /// including nodes that are already open, fails with
+    /// [`VfsError::DeviceGone`] from now on, and they can only be unmounted.
+    pub fn device_gone(&self, device: usize) {
+        for (mount_point, mount) in self.mounts.read().iter() {
+            if mount.state.device == Some(device) && !mount.state.gone.swap(true, SeqCst) {
+                warn!("the device of the file system at {mount_point} was removed");
+            }
+        }
+    }
+
+    /// Changes the flags of the file system that is mounted at the given path.
+    /// Nodes that are already open on it are affected as well.
+    ///
+    /// Making a file system read-only writes back what it holds in memory.
+    /// Returns [`VfsError::Busy`] if there are open nodes that have been written
+    /// through, since their owners expect to be able to continue writing.
+    pub fn remount<P>(&self, mount_point: P, flags: MountFlags) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let guard = self.mounts.read();
+        let mount = guard
+            .get(&mount_point.as_ref().normalize())
+            .ok_or(VfsError::NotMounted)?;
+        let previous = MountFlags::from_bits_retain(mount.state.flags.swap(flags.bits(), SeqCst));
+        if !flags.contains(MountFlags::READ_ONLY) || previous.contains(MountFlags::READ_ONLY) {
+            return Ok(());
+        }
+
+        // the flag is set before the check, so that a node either sees the
+        // flag or is counted, see `Inner::begin_write`
+        if mount.state.dirty_handles.load(SeqCst) > 0 {
+            mount.state.flags.store(previous.bits(), SeqCst);
+            return Err(VfsError::Busy);
+        }
+        mount.fs.write().sync()
+    }
+
+    /// Unmounts the file system at the given path, which makes whatever was
+    /// shadowed by the mount visible again.
+    ///
+    /// Returns [`VfsError::Busy`] if there are still open nodes on the file
+    /// system, or if another file system is mounted below it.
     #[allow(dead_code)]
     pub fn unmount<P>(&self, mount_point: P) -> Result<()>
     where
         P: AsRef<Path>,
     {
         let mut guard = self.mounts.write();
-        guard.remove::<OwnedPath>(&mount_point.into());
+        let mount_point = mount_point.as_ref().normalize();
+        let mount = guard.get(&mount_point).ok_or(VfsError::NotMounted)?;
+        if mount.state.open_handles.load(Relaxed) > 0 {
+            return Err(VfsError::Busy);
+        }
+        if guard
+            .keys()
+            .any(|p| is_below(p.as_path(), mount_point.as_path()))
+        {
+            return Err(VfsError::Busy);
+        }
+        // there is nothing left to write back to
+        if mount.state.check_present().is_ok() {
+            mount.fs.write().sync()?;
+        }
+        guard.remove(&mount_point);
         Ok(())
     }
 
+    /// Returns information about all mounted file systems, ordered by their
+    /// mount point.
+    #[allow(dead_code)]
+    pub fn mounts(&self) -> impl Iterator<Item = MountInfo> {
+        self.mounts
+            .read()
+            .iter()
+            .map(|(path, mount)| MountInfo {
+                path: path.clone(),
+                fs_name: mount.fs_name,
+                flags: mount.state.flags(),
+                open_handles: mount.state.open_handles.load(Relaxed),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Writes everything that the mounted file systems hold in memory back to
+    /// their devices. Returns the first error, but tries to sync all file
+    /// systems regardless.
+    pub fn sync(&self) -> Result<()> {
+        let mounts = self
+            .mounts
+            .read()
+            .values()
+            // removed devices can't be written back to anymore
+            .filter(|mount| mount.state.check_present().is_ok())
+            .map(|mount| mount.fs.clone())
+            .collect::<Vec<_>>();
+        mounts
+            .iter()
+            .map(|fs| fs.write().sync())
+            .fold(Ok(()), |result, synced| result.and(synced))
+    }
+
     #[allow(dead_code)]
     pub fn exists<P>(&self, path: P) -> Result<bool>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        let (fs, relative_path) = self.find_fs_and_relativize(path)?;
-        let result = fs.write().exists(relative_path.as_path())?;
-        Ok(result)
+        match self.walk(path.as_ref(), true) {
+            Ok(_) => Ok(true),
+            Err(VfsError::NoSuchFile) => Ok(false),
+            Err(e) => Err(e),
+        }
     }
 
     pub fn open<P>(&self, path: P) -> Result<VfsNode>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        let (fs, relative_path) = self.find_fs_and_relativize(path)?;
-        let handle = fs.write().open(relative_path.as_path())?;
-        Ok(VfsNode::new(path.into(), handle, fs))
+        self.walk(path.as_ref(), true)
+    }
+
+    /// Opens the node at the given path like [`Vfs::open`], but if the last
+    /// component is a symbolic link, the link itself is opened.
+    pub fn open_nofollow<P>(&self, path: P) -> Result<VfsNode>
+    where
+        P: AsRef<Path>,
+    {
+        self.walk(path.as_ref(), false)
+    }
+
+    /// Reads the target of the symbolic link that `node` refers to.
+    pub fn readlink(&self, node: &VfsNode) -> Result<String> {
+        let mut guard = node.fs().write();
+        guard.readlink(node.handle())
     }
 
     pub fn read_dir<P>(&self, path: P) -> Result<impl Iterator<Item = DirEntry>>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        let (fs, relative_path) = self.find_fs_and_relativize(path)?;
-        let vec = fs.write().read_dir(relative_path.as_path())?;
-        Ok(vec.into_iter())
+        let node = self.walk(path.as_ref(), true)?;
+        let mut guard = node.fs().write();
+        let mut entries = Vec::new();
+        let mut cookie = 0;
+        loop {
+            let (batch, next_cookie) = guard.read_dir(node.handle(), cookie)?;
+            if batch.is_empty() {
+                break;
+            }
+            entries
+                .try_reserve(batch.len())
+                .map_err(|_| VfsError::NoSpace)?;
+            entries.extend(batch);
+            cookie = next_cookie;
+        }
+        Ok(entries.into_iter())
+    }
+
+    /// Reads the batch of entries of the directory `node` that starts at
+    /// `cookie`, together with their inode numbers, and returns the cookie
+    /// that the next batch starts at. The batch is empty at the end of the
+    /// directory.
+    pub fn read_dir_batch(
+        &self,
+        node: &VfsNode,
+        cookie: u64,
+    ) -> Result<(Vec<(DirEntry, u64)>, u64)> {
+        let mut guard = node.fs().write();
+        let (batch, next_cookie) = guard.read_dir(node.handle(), cookie)?;
+        let mut entries = Vec::new();
+        entries
+            .try_reserve(batch.len())
+            .map_err(|_| VfsError::NoSpace)?;
+        for entry in batch {
+            // entries that were removed since the batch was read have no
+            // inode anymore, so they get the number that no inode has
+            let ino = inode_number(&mut *guard, node.handle(), &entry.name).unwrap_or(0);
+            entries.push((entry, ino));
+        }
+        Ok((entries, next_cookie))
     }
 
     pub fn read<B>(&self, node: &VfsNode, mut buf: B, offset: usize) -> Result<usize>
@@ -122,8 +482,16 @@ impl Vfs {
         B: AsMut<[u8]>,
     {
         let buf = buf.as_mut();
+        node.check_present()?;
         let mut guard = node.fs().write();
-        guard.read(node.handle(), buf, offset)
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => {
+                let mut read_ahead = node.read_ahead().lock();
+                self.page_cache
+                    .read(&mut *guard, file, &mut read_ahead, buf, offset)
+            }
+            None => guard.read(node.handle(), buf, offset),
+        }
     }
 
     pub fn write<B>(&self, node: &VfsNode, buf: B, offset: usize) -> Result<usize>
@@ -131,14 +499,204 @@ impl Vfs {
         B: AsRef<[u8]>,
     {
         let buf = buf.as_ref();
+        node.begin_write()?;
         let mut guard = node.fs().write();
-        guard.write(node.handle(), buf, offset)
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => self.page_cache.write(&mut *guard, file, buf, offset),
+            None => guard.write(node.handle(), buf, offset),
+        }
+    }
+
+    /// Writes `buf` at the end of the file that `node` refers to, and returns
+    /// how many bytes were written and the offset that they were written at.
+    ///
+    /// The size of the file is determined under the same lock as the write,
+    /// so concurrent appends never overwrite or interleave with each other.
+    pub fn append(&self, node: &VfsNode, buf: &[u8]) -> Result<(usize, usize)> {
+        node.begin_write()?;
+        let mut guard = node.fs().write();
+        let mut stat = Stat::default();
+        guard.stat(node.handle(), &mut stat)?;
+        let offset = stat.size as usize;
+        let written = match cached_file(&mut *guard, node.handle())? {
+            Some(file) => self.page_cache.write(&mut *guard, file, buf, offset),
+            None => guard.write(node.handle(), buf, offset),
+        }?;
+        Ok((written, offset))
+    }
+
+    /// Like [`Vfs::read`], but awaits the file system instead of blocking, see
+    /// [`FileSystem::read_async`]. The file system stays locked until the read
+    /// completes.
+    ///
+    /// Files in the page cache are read through the cache, which only blocks
+    /// for the pages that aren't cached yet.
+    pub async fn read_async(&self, node: &VfsNode, buf: &mut [u8], offset: usize) -> Result<usize> {
+        node.check_present()?;
+        let mut guard = node.fs().write();
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => {
+                let mut read_ahead = node.read_ahead().lock();
+                self.page_cache
+                    .read(&mut *guard, file, &mut read_ahead, buf, offset)
+            }
+            None => guard.read_async(node.handle(), buf, offset).await,
+        }
+    }
+
+    /// Like [`Vfs::write`], but awaits the file system instead of blocking. See
+    /// [`Vfs::read_async`].
+    pub async fn write_async(&self, node: &VfsNode, buf: &[u8], offset: usize) -> Result<usize> {
+        node.begin_write()?;
+        let mut guard = node.fs().write();
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => self.page_cache.write(&mut *guard, file, buf, offset),
+            None => guard.write_async(node.handle(), buf, offset).await,
+        }
     }
 
-    #[allow(dead_code)]
     pub fn truncate(&self, node: &VfsNode, size: usize) -> Result<()> {
+        node.begin_write()?;
+        let mut guard = node.fs().write();
+        let cached = cached_file(&mut *guard, node.handle())?;
+        guard.truncate(node.handle(), size)?;
+        match cached {
+            Some(file) => self.page_cache.truncate(file.inode, size),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns how `len` bytes of the given node, starting at `offset`, can be
+    /// mapped into memory.
+    ///
+    /// Returns [`VfsError::InvalidArgument`] if `len` is zero or `offset` is
+    /// not page aligned, and [`VfsError::Overflow`] if the range doesn't fit
+    /// into the address space or `offset` is past the end of the node.
+    pub fn mmap(&self, node: &VfsNode, offset: usize, len: usize) -> Result<MmapBacking> {
+        if len == 0 || offset % Size4KiB::SIZE as usize != 0 {
+            return Err(VfsError::InvalidArgument);
+        }
+        if offset.checked_add(len).is_none() {
+            return Err(VfsError::Overflow);
+        }
+
+        let mut guard = node.fs().write();
+        let mut stat = Stat::default();
+        guard.stat(node.handle(), &mut stat)?;
+        if offset as u64 > stat.size {
+            return Err(VfsError::Overflow);
+        }
+        guard.mmap(node.handle(), offset, len)
+    }
+
+    /// Pins the page with the given index of the file that `node` refers to in
+    /// the page cache and returns its frame, so that it can be mapped into
+    /// memory. Returns `None` if the file is not cached, in which case its
+    /// content can only be read into memory.
+    ///
+    /// The page must be unpinned with [`Vfs::unpin_page`] once it is unmapped.
+    pub fn pin_page(
+        &self,
+        node: &VfsNode,
+        index: usize,
+        writable: bool,
+    ) -> Result<Option<PhysFrame>> {
+        if writable {
+            node.begin_write()?;
+        }
+        let mut guard = node.fs().write();
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => self
+                .page_cache
+                .pin(&mut *guard, file, index, writable)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Releases a page that was pinned with [`Vfs::pin_page`], which writes it
+    /// back to the file system if it was pinned for writing.
+    pub fn unpin_page(&self, node: &VfsNode, index: usize) -> Result<()> {
         let mut guard = node.fs().write();
-        guard.truncate(node.handle(), size)
+        match cached_file(&mut *guard, node.handle())? {
+            Some(file) => self.page_cache.unpin(&mut *guard, file, index),
+            None => Ok(()),
+        }
+    }
+
+    pub fn page_cache(&self) -> &PageCache {
+        &self.page_cache
+    }
+
+    /// Returns which of the events in `interest` are currently ready for the given node.
+    /// This never blocks.
+    pub fn poll(&self, node: &VfsNode, interest: PollEvents) -> Result<PollEvents> {
+        let guard = node.fs().read();
+        guard.poll(node.handle(), interest)
+    }
+
+    /// Returns the queue that is woken when the node may have become ready, or
+    /// `None` if the node never blocks.
+    pub fn wait_queue(&self, node: &VfsNode) -> Option<Arc<WaitQueue>> {
+        let guard = node.fs().read();
+        guard.wait_queue(node.handle())
+    }
+
+    pub fn ioctl(
+        &self,
+        node: &VfsNode,
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> core::result::Result<usize, Errno> {
+        let mut guard = node.fs().write();
+        guard.ioctl(node.handle(), request, arg)
+    }
+
+    /// Creates a new anonymous pipe and returns the read end and the write end,
+    /// in that order.
+    pub fn create_pipe(&self) -> Result<(VfsNode, VfsNode)> {
+        let pipefs = PIPEFS.get().ok_or(VfsError::NoSuchFileSystem)?;
+        let handles = pipefs.write().create_pipe()?;
+        let fs: Arc<RwLock<dyn FileSystem>> = pipefs.clone();
+        let path = OwnedPath::from(handles.name);
+        Ok((
+            VfsNode::new(path.clone(), handles.read, fs.clone(), None),
+            VfsNode::new(path, handles.write, fs, None),
+        ))
+    }
+
+    /// Acquires the advisory lock of the file that `node` refers to for `owner`,
+    /// or converts the lock that `owner` already holds. See [`LockTable::lock`]
+    /// for when this waits.
+    ///
+    /// The lock is released when `node` and all of its clones are dropped,
+    /// or with [`Vfs::unlock`] or [`Vfs::release_locks`].
+    pub fn lock(
+        &self,
+        node: &VfsNode,
+        owner: ProcessId,
+        kind: LockKind,
+        block: bool,
+    ) -> Result<()> {
+        let inode = self.inode_id(node)?;
+        self.locks.lock(inode, owner, kind, block)?;
+        node.set_lock(inode, owner);
+        Ok(())
+    }
+
+    /// Releases the advisory lock that `owner` holds on the file that `node`
+    /// refers to, no matter through which node it was acquired.
+    pub fn unlock(&self, node: &VfsNode, owner: ProcessId) -> Result<()> {
+        let inode = self.inode_id(node)?;
+        self.locks.unlock(inode, owner);
+        node.take_lock();
+        Ok(())
+    }
+
+    /// Releases all advisory locks that `owner` holds and cancels the waits
+    /// for locks, which is necessary when the process terminates.
+    pub fn release_locks(&self, owner: ProcessId) {
+        self.locks.release_all(owner);
     }
 
     pub fn stat(&self, node: &VfsNode, stat: &mut Stat) -> Result<()> {
@@ -150,32 +708,120 @@ impl Vfs {
     where
         P: AsRef<Path>,
     {
-        let path = p.as_ref();
-        let (fs, path) = self.find_fs_and_relativize(path)?;
-        let mut guard = fs.write();
-        guard.stat_path(path.as_path(), stat)
+        let node = self.walk(p.as_ref(), true)?;
+        self.stat(&node, stat)
     }
 
-    #[allow(dead_code)]
+    /// Creates a node at the given path with the default permissions, which
+    /// are `rw-r--r--` for files and `rwxr-xr-x` for directories.
     pub fn create<P>(&self, path: P, ftype: FileType) -> Result<()>
     where
         P: AsRef<Path>,
     {
-        let path = path.as_ref();
-        let (fs, path) = self.find_fs_and_relativize(path)?;
-        let mut guard = fs.write();
-        guard.create(path.as_path(), ftype)
+        let mode = match ftype {
+            FileType::Directory => FileMode::from_bits_retain(0o755),
+            _ => FileMode::from_bits_retain(0o644),
+        };
+        self.create_with_mode(path, ftype, mode)
     }
 
-    #[allow(dead_code)]
-    pub fn remove<P>(&self, path: P) -> Result<()>
+    /// Creates a node at the given path with the permission bits of `mode`.
+    /// File type bits in `mode` are ignored.
+    pub fn create_with_mode<P>(&self, path: P, ftype: FileType, mode: FileMode) -> Result<()>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        let (fs, path) = self.find_fs_and_relativize(path)?;
-        let mut guard = fs.write();
-        guard.remove(path.as_path())
+        let (mount, path) = self.find_mount_and_relativize(path)?;
+        let mut guard = mount.fs.write();
+        if let Err(e) = mount.state.check_writable() {
+            // an existing node is reported first, so that creating a file only
+            // if it doesn't exist yet works on read-only file systems
+            let mut stat = Stat::default();
+            return match guard.stat_path(path.as_path(), &mut stat) {
+                Ok(()) => Err(VfsError::AlreadyExists),
+                Err(_) => Err(e),
+            };
+        }
+        guard.create(path.as_path(), ftype, mode.permissions())
+    }
+
+    /// Removes the node at the given path, which must not be a directory.
+    pub fn remove_file<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.remove(path, |ftype| match ftype {
+            FileType::Directory => Err(VfsError::IsDirectory),
+            _ => Ok(()),
+        })
+    }
+
+    /// Moves the node at `from` to `to`. Both paths must be on the same file system.
+    pub fn rename<P, Q>(&self, from: P, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (from, to) = (from.as_ref().normalize(), to.as_ref().normalize());
+        {
+            let mounts = self.mounts.read();
+            if mounts.contains_key(&from) || mounts.contains_key(&to) {
+                return Err(VfsError::Busy);
+            }
+        }
+
+        let (from_mount, from) = self.find_mount_and_relativize(from.as_path())?;
+        let (to_mount, to) = self.find_mount_and_relativize(to.as_path())?;
+        if !Arc::ptr_eq(&from_mount.fs, &to_mount.fs) {
+            return Err(VfsError::CrossDevice);
+        }
+        from_mount.state.check_writable()?;
+
+        let mut guard = from_mount.fs.write();
+        let mut replaced = Stat::default();
+        let replaces_file =
+            guard.stat_path(to.as_path(), &mut replaced).is_ok() && replaced.mode.is_regular_file();
+        guard.rename(from.as_path(), to.as_path())?;
+        if replaces_file && guard.uses_page_cache() {
+            self.page_cache
+                .invalidate(InodeId::new(guard.fsid(), replaced.ino));
+        }
+        Ok(())
+    }
+
+    /// Adds `to` as another name for the node at `existing`. Both paths must be
+    /// on the same file system, and hard links to directories are not allowed.
+    pub fn link<P, Q>(&self, existing: P, to: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let (existing_mount, existing) = self.find_mount_and_relativize(existing)?;
+        let (to_mount, to) = self.find_mount_and_relativize(to)?;
+        if !Arc::ptr_eq(&existing_mount.fs, &to_mount.fs) {
+            return Err(VfsError::CrossDevice);
+        }
+        existing_mount.state.check_writable()?;
+
+        let mut guard = existing_mount.fs.write();
+        let mut stat = Stat::default();
+        guard.stat_path(existing.as_path(), &mut stat)?;
+        if stat.mode.is_directory() {
+            return Err(VfsError::PermissionDenied);
+        }
+        guard.link(existing.as_path(), to.as_path())
+    }
+
+    /// Removes the directory at the given path, which must be empty.
+    pub fn remove_dir<P>(&self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        self.remove(path, |ftype| match ftype {
+            FileType::Directory => Ok(()),
+            _ => Err(VfsError::NotDirectory),
+        })
     }
 }
 
@@ -183,23 +829,29 @@ impl Vfs {
     const fn new() -> Self {
         Self {
             mounts: RwLock::new(BTreeMap::new()),
+            locks: LockTable::new(),
+            page_cache: PageCache::new(page_cache::DEFAULT_CAPACITY),
         }
     }
 
-    /// Finds the appropriate file system for the given path and relativizes the path
-    /// relative to the mount point of the found fs.
+    /// Finds the mount of the appropriate file system for the given path and
+    /// relativizes the path relative to its mount point.
     /// The returned path can be passed into the file system's methods.
-    fn find_fs_and_relativize<P>(&self, path: P) -> Result<(Arc<RwLock<dyn FileSystem>>, OwnedPath)>
+    fn find_mount_and_relativize<P>(&self, path: P) -> Result<(Mount, OwnedPath)>
     where
         P: AsRef<Path>,
     {
         let guard = self.mounts.read();
-        let original_path = path.as_ref().to_owned().to_string();
-        let mut path = path.as_ref().to_owned();
+        let original_path = path.as_ref().normalize();
+        let mut path = original_path.clone();
         loop {
-            if let Some(fs) = guard.get::<OwnedPath>(&path) {
-                let new_path = original_path.chars().skip(path.len()).collect::<String>();
-                return Ok((fs.clone(), OwnedPath::from(new_path)));
+            if let Some(mount) = guard.get::<OwnedPath>(&path) {
+                let new_path = original_path
+                    .as_str()
+                    .chars()
+                    .skip(path.len())
+                    .collect::<String>();
+                return Ok((mount.clone(), OwnedPath::from(new_path)));
             }
             if let Some(parent) = path.parent() {
                 parent.clone_into(&mut path);
@@ -209,25 +861,749 @@ impl Vfs {
         }
     }
 
+    /// Walks the given absolute path component by component, starting at the root
+    /// of the file system that is mounted at `/`. Whenever the walk reaches a
+    /// mount point, it continues at the root of the file system that is mounted
+    /// there. `..` goes back to the previous directory, also across mount points,
+    /// and `..` at the root stays at the root.
+    ///
+    /// Symbolic links are resolved relative to the directory that contains them,
+    /// or from the root if their target is absolute. The last component is only
+    /// resolved if `follow_last` is set or the path has a trailing separator.
+    /// After [`MAX_SYMLINK_HOPS`] links, the walk fails with
+    /// [`VfsError::SymlinkLoop`].
+    ///
+    /// A file that stands for a file descriptor (see
+    /// [`FileSystem::file_descriptor`]) resolves to the node of that descriptor
+    /// in the calling process. Such a file must be the last component.
+    ///
+    /// Empty components are ignored. A trailing separator requires the final node
+    /// to be a directory.
+    fn walk(&self, path: &Path, follow_last: bool) -> Result<VfsNode> {
+        let mounts = self.mounts.read();
+        let root_mount = mounts
+            .get::<OwnedPath>(&"/".into())
+            .ok_or(VfsError::NoSuchFileSystem)?
+            .clone();
+        root_mount.state.check_present()?;
+        let root = root_mount.fs.write().root()?;
+
+        let mut stack = WalkStack::default();
+        stack.push(root_mount, root, OwnedPath::from("/"));
+
+        let follow_last = follow_last || path.ends_with(SEPARATOR);
+        let mut remaining = Vec::new();
+        push_remaining(&mut remaining, path);
+        let mut hops = 0;
+        let mut aliased = None;
+        while let Some(name) = remaining.pop() {
+            if name == ".." {
+                if stack.len() > 1 {
+                    stack.pop_and_close();
+                }
+                continue;
+            }
+
+            let (mount, parent, parent_path) = stack.top();
+            let mut path = parent_path.clone();
+            path.push(&name);
+
+            // the mount point doesn't have to exist in the parent file system
+            let (mount, handle) = match mounts.get(&path) {
+                Some(mounted) => {
+                    mounted.state.check_present()?;
+                    (mounted.clone(), mounted.fs.write().root()?)
+                }
+                None => {
+                    let handle = mount.fs.write().lookup(parent, &name)?;
+                    (mount.clone(), handle)
+                }
+            };
+            let fileno = mount.fs.read().file_descriptor(handle);
+            if let Some(fileno) = fileno {
+                let _ = mount.fs.write().close(handle);
+                // we can't continue the walk from a descriptor, so it must be last
+                if !remaining.is_empty() {
+                    return Err(VfsError::NotDirectory);
+                }
+                let node = process::current()
+                    .open_fds()
+                    .read()
+                    .get(&fileno)
+                    .map(|descriptor| descriptor.node().clone())
+                    .ok_or(VfsError::NoSuchFile)?;
+                aliased = Some(node);
+                break;
+            }
+            stack.push(mount, handle, path);
+
+            if remaining.is_empty() && !follow_last {
+                break;
+            }
+            let (mount, handle, _) = stack.top();
+            let target = {
+                let mut fs = mount.fs.write();
+                let mut stat = Stat::default();
+                fs.stat(handle, &mut stat)?;
+                if !stat.mode.is_symlink() {
+                    continue;
+                }
+                hops += 1;
+                if hops > MAX_SYMLINK_HOPS {
+                    return Err(VfsError::SymlinkLoop);
+                }
+                fs.readlink(handle)?
+            };
+            if target.is_empty() {
+                return Err(VfsError::NoSuchFile);
+            }
+
+            // continue at the directory that contains the link
+            stack.pop_and_close();
+            if target.starts_with(SEPARATOR) {
+                while stack.len() > 1 {
+                    stack.pop_and_close();
+                }
+            }
+            push_remaining(&mut remaining, Path::new(&target));
+        }
+
+        let node = match aliased {
+            Some(node) => node,
+            None => {
+                let (mount, handle, walked_path) = stack.pop();
+                VfsNode::new(walked_path, handle, mount.fs, Some(mount.state))
+            }
+        };
+        if path.ends_with(SEPARATOR) {
+            let mut stat = Stat::default();
+            self.stat(&node, &mut stat)?;
+            if !stat.mode.is_directory() {
+                return Err(VfsError::NotDirectory);
+            }
+        }
+        Ok(node)
+    }
+
+    /// Removes the node at the given path if `check` accepts its type.
+    /// The type check and the removal happen under the same lock.
+    fn remove<P, F>(&self, path: P, check: F) -> Result<()>
+    where
+        P: AsRef<Path>,
+        F: FnOnce(FileType) -> Result<()>,
+    {
+        let path = path.as_ref().normalize();
+        if self.mounts.read().contains_key(&path) {
+            return Err(VfsError::Busy);
+        }
+
+        let (mount, path) = self.find_mount_and_relativize(path.as_path())?;
+        mount.state.check_writable()?;
+        let mut guard = mount.fs.write();
+        let mut stat = Stat::default();
+        guard.stat_path(path.as_path(), &mut stat)?;
+        check(stat.mode.into())?;
+        guard.remove(path.as_path())?;
+        if stat.mode.is_regular_file() && guard.uses_page_cache() {
+            self.page_cache
+                .invalidate(InodeId::new(guard.fsid(), stat.ino));
+        }
+        Ok(())
+    }
+
+    /// Returns the id of the inode that `node` refers to.
+    fn inode_id(&self, node: &VfsNode) -> Result<InodeId> {
+        let mut guard = node.fs().write();
+        let mut stat = Stat::default();
+        guard.stat(node.handle(), &mut stat)?;
+        Ok(InodeId::new(guard.fsid(), stat.ino))
+    }
+
     fn internal_close(&self, node: &Inner) -> Result<()> {
         let mut guard = node.fs().write();
+        if guard.uses_page_cache() {
+            // the inode number of a removed file is reused once its last handle is closed
+            let mut stat = Stat::default();
+            if guard.stat(node.handle(), &mut stat).is_ok() && stat.nlink == 0 {
+                self.page_cache
+                    .invalidate(InodeId::new(guard.fsid(), stat.ino));
+            }
+        }
         guard.close(node.handle())
     }
 }
 
+/// The nodes that [`Vfs::walk`] has visited so far, from the root to the
+/// current node. Handles that are still on the stack when it is dropped are
+/// closed.
+#[derive(Default)]
+struct WalkStack {
+    entries: Vec<(Mount, VfsHandle, OwnedPath)>,
+}
+
+impl WalkStack {
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    fn push(&mut self, mount: Mount, handle: VfsHandle, path: OwnedPath) {
+        self.entries.push((mount, handle, path));
+    }
+
+    fn top(&self) -> (&Mount, VfsHandle, &OwnedPath) {
+        let (mount, handle, path) = self.entries.last().expect("walk stack must not be empty");
+        (mount, *handle, path)
+    }
+
+    /// Removes the top entry without closing its handle.
+    fn pop(&mut self) -> (Mount, VfsHandle, OwnedPath) {
+        self.entries.pop().expect("walk stack must not be empty")
+    }
+
+    fn pop_and_close(&mut self) {
+        let (mount, handle, _) = self.pop();
+        let _ = mount.fs.write().close(handle);
+    }
+}
+
+impl Drop for WalkStack {
+    fn drop(&mut self) {
+        while !self.entries.is_empty() {
+            self.pop_and_close();
+        }
+    }
+}
+
+/// Pushes the components of `path` that [`Vfs::walk`] still has to visit onto
+/// `remaining`, in reverse order, so that the first component is popped first.
+/// `..` is kept as a component, because it can only be resolved during the walk.
+fn push_remaining(remaining: &mut Vec<String>, path: &Path) {
+    let start = remaining.len();
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurrentDir => {}
+            Component::ParentDir => remaining.push("..".to_string()),
+            Component::Normal(name) => remaining.push(name.to_string()),
+        }
+    }
+    remaining[start..].reverse();
+}
+
+/// Returns the file that `handle` refers to as the page cache sees it, or `None`
+/// if the content of the file is not cached.
+fn cached_file(fs: &mut dyn FileSystem, handle: VfsHandle) -> Result<Option<CachedFile>> {
+    if !fs.uses_page_cache() {
+        return Ok(None);
+    }
+    let mut stat = Stat::default();
+    fs.stat(handle, &mut stat)?;
+    if !stat.mode.is_regular_file() {
+        return Ok(None);
+    }
+    Ok(Some(CachedFile {
+        inode: InodeId::new(fs.fsid(), stat.ino),
+        handle,
+        size: stat.size as usize,
+    }))
+}
+
+/// The inode number of the entry `name` of the directory `parent`.
+fn inode_number(fs: &mut dyn FileSystem, parent: VfsHandle, name: &str) -> Result<u64> {
+    let handle = fs.lookup(parent, name)?;
+    let mut stat = Stat::default();
+    let result = fs.stat(handle, &mut stat);
+    fs.close(handle)?;
+    result.map(|()| stat.ino)
+}
+
+/// Returns whether `path` is strictly below `base`, comparing whole components.
+fn is_below(path: &Path, base: &Path) -> bool {
+    let mut components = path.components();
+    base.components().all(|c| components.next() == Some(c)) && components.next().is_some()
+}
+
 /// This method is intended to be called by the VfsNode when it is dropped.
 /// It is not intended to be called by you.
 fn close_vfs_node(node: &Inner) {
+    if let Some((inode, owner)) = node.take_lock() {
+        vfs().locks.unlock(inode, owner);
+    }
     let _ = vfs().internal_close(node);
 }
 
 #[cfg(feature = "kernel_test")]
 mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::format;
+    use alloc::string::{String, ToString};
+    use alloc::sync::Arc;
     use alloc::vec;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::Relaxed;
 
+    use foundation::falloc::vec::FVec;
+    use foundation::future::executor::{Executor, Tick, TickResult};
+    use foundation::future::yield_now;
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+    use kernel_api::syscall::{FileMode, Stat};
     use kernel_test_framework::kernel_test;
 
-    use crate::io::vfs::vfs;
+    use crate::io::path::Path;
+    use crate::io::vfs::devfs::VirtualDevFs;
+    use crate::io::vfs::error::Result;
+    use crate::io::vfs::tmpfs::TmpFs;
+    use crate::io::vfs::{
+        vfs, DirEntry, FileSystem, FileType, FsId, MountFlags, Vfs, VfsError, VfsHandle, VfsNode,
+    };
+
+    /// A file system with a fixed set of paths, which counts its open handles.
+    /// Closing a handle that isn't open panics, so every handle must be closed
+    /// exactly once. The content of a file is its path.
+    struct MockFs {
+        fsid: FsId,
+        /// All paths relative to the root, and whether they are directories.
+        paths: BTreeMap<String, bool>,
+        handles: BTreeMap<VfsHandle, String>,
+        next_handle: u64,
+        open: Arc<AtomicUsize>,
+        /// How often an asynchronous read yields before it completes.
+        read_delay: usize,
+        /// The number of calls to `write`.
+        writes: Arc<AtomicUsize>,
+    }
+
+    impl MockFs {
+        fn new(fsid: FsId, paths: &[(&str, bool)], open: Arc<AtomicUsize>) -> Self {
+            let mut all = BTreeMap::new();
+            all.insert(String::new(), true);
+            for (path, is_dir) in paths {
+                all.insert(path.to_string(), *is_dir);
+            }
+            Self {
+                fsid,
+                paths: all,
+                handles: BTreeMap::new(),
+                next_handle: 0,
+                open,
+                read_delay: 0,
+                writes: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+
+        fn open_path(&mut self, path: String) -> VfsHandle {
+            let handle = VfsHandle::new(self.next_handle);
+            self.next_handle += 1;
+            self.handles.insert(handle, path);
+            self.open.fetch_add(1, Relaxed);
+            handle
+        }
+    }
+
+    impl FileSystem for MockFs {
+        fn fsid(&self) -> FsId {
+            self.fsid
+        }
+
+        fn name(&self) -> &'static str {
+            "mockfs"
+        }
+
+        fn root(&mut self) -> Result<VfsHandle> {
+            Ok(self.open_path(String::new()))
+        }
+
+        fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+            let parent = self.handles.get(&parent).ok_or(VfsError::HandleClosed)?;
+            if !self.paths[parent] {
+                return Err(VfsError::NotDirectory);
+            }
+            let path = if parent.is_empty() {
+                name.to_string()
+            } else {
+                parent.clone() + "/" + name
+            };
+            if !self.paths.contains_key(&path) {
+                return Err(VfsError::NoSuchFile);
+            }
+            Ok(self.open_path(path))
+        }
+
+        fn close(&mut self, handle: VfsHandle) -> Result<()> {
+            self.handles
+                .remove(&handle)
+                .expect("handle must not be closed twice");
+            self.open.fetch_sub(1, Relaxed);
+            Ok(())
+        }
+
+        fn read_dir(&mut self, _: VfsHandle, _: u64) -> Result<(FVec<DirEntry>, u64)> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
+            let path = self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+            if self.paths[path] {
+                return Err(VfsError::IsDirectory);
+            }
+            let content = path.as_bytes();
+            let start = offset.min(content.len());
+            let len = buf.len().min(content.len() - start);
+            buf[..len].copy_from_slice(&content[start..start + len]);
+            Ok(len)
+        }
+
+        fn read_async<'a>(
+            &'a mut self,
+            handle: VfsHandle,
+            buf: &'a mut [u8],
+            offset: usize,
+        ) -> BoxFuture<'a, Result<usize>> {
+            async move {
+                for _ in 0..self.read_delay {
+                    yield_now().await;
+                }
+                self.read(handle, buf, offset)
+            }
+            .boxed()
+        }
+
+        fn write(&mut self, _: VfsHandle, buf: &[u8], _: usize) -> Result<usize> {
+            self.writes.fetch_add(1, Relaxed);
+            Ok(buf.len())
+        }
+
+        fn truncate(&mut self, _: VfsHandle, _: usize) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
+            let path = self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+            stat.mode = if self.paths[path] {
+                FileMode::S_IFDIR
+            } else {
+                FileMode::S_IFREG
+            };
+            Ok(())
+        }
+
+        fn create(&mut self, _: &Path, _: FileType, _: FileMode) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn remove(&mut self, _: &Path) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn rename(&mut self, _: &Path, _: &Path) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+    }
+
+    /// Creates a vfs with a mock file system at `/` and another one at `/a/mnt`.
+    fn mock_vfs(open: &Arc<AtomicUsize>) -> (Vfs, FsId, FsId) {
+        let (root_id, nested_id) = (FsId::new(), FsId::new());
+        let root = MockFs::new(
+            root_id,
+            &[("a", true), ("a/b", true), ("a/file", false)],
+            open.clone(),
+        );
+        let nested = MockFs::new(nested_id, &[("x", false)], open.clone());
+
+        let vfs = Vfs::new();
+        vfs.mount("/", root, MountFlags::empty()).unwrap();
+        vfs.mount("/a/mnt", nested, MountFlags::empty()).unwrap();
+        (vfs, root_id, nested_id)
+    }
+
+    fn fsid_of(node: &VfsNode) -> FsId {
+        node.fs().read().fsid()
+    }
+
+    #[kernel_test]
+    fn test_walk_separators() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let (vfs, _, _) = mock_vfs(&open);
+
+        for path in ["/a/b", "//a///b", "/a/b/", "/a/./b/.", "a/b"] {
+            let node = vfs.open(path).unwrap();
+            assert_eq!("/a/b", node.path().as_str());
+            assert_eq!(1, open.load(Relaxed));
+        }
+        assert_eq!("/a/file", vfs.open("/a//file").unwrap().path().as_str());
+
+        // a trailing separator requires a directory
+        assert_eq!(Err(VfsError::NotDirectory), vfs.open("/a/file/").map(drop));
+        assert_eq!(Err(VfsError::NotDirectory), vfs.open("/a/file/x").map(drop));
+        assert_eq!(Err(VfsError::NoSuchFile), vfs.open("/a/missing").map(drop));
+
+        // failed walks must not leak handles
+        assert_eq!(0, open.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_walk_nested_mount() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let (vfs, root_id, nested_id) = mock_vfs(&open);
+
+        let node = vfs.open("/a/mnt/x").unwrap();
+        assert_eq!("/a/mnt/x", node.path().as_str());
+        assert!(fsid_of(&node) == nested_id);
+        drop(node);
+
+        // the mount point itself is the root of the mounted file system
+        let node = vfs.open("/a/mnt/").unwrap();
+        assert!(fsid_of(&node) == nested_id);
+        drop(node);
+
+        // `..` leaves the mounted file system again
+        let node = vfs.open("/a/mnt/../b").unwrap();
+        assert_eq!("/a/b", node.path().as_str());
+        assert!(fsid_of(&node) == root_id);
+        drop(node);
+
+        // `..` at the root stays at the root
+        let node = vfs.open("/../../a/mnt/x").unwrap();
+        assert_eq!("/a/mnt/x", node.path().as_str());
+        drop(node);
+
+        assert_eq!(
+            Err(VfsError::NoSuchFile),
+            vfs.open("/a/mnt/missing").map(drop)
+        );
+        assert_eq!(0, open.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_close_on_last_reference() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let (vfs, _, _) = mock_vfs(&open);
+
+        let node = vfs.open("/a/file").unwrap();
+        let clones = [node.clone(), node.clone()];
+        assert_eq!(1, open.load(Relaxed));
+        drop(node);
+        assert_eq!(1, open.load(Relaxed));
+        let [first, second] = clones;
+        drop(first);
+        assert_eq!(1, open.load(Relaxed));
+        drop(second);
+        assert_eq!(0, open.load(Relaxed));
+
+        // every open is its own open file
+        let (first, second) = (vfs.open("/a/file").unwrap(), vfs.open("/a/file").unwrap());
+        assert_eq!(2, open.load(Relaxed));
+        drop(first);
+        assert_eq!(1, open.load(Relaxed));
+        drop(second);
+        assert_eq!(0, open.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_read_async() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let mut slow_fs = MockFs::new(FsId::new(), &[("file", false)], open.clone());
+        slow_fs.read_delay = 3;
+        let vfs = Vfs::new();
+        vfs.mount("/", slow_fs, MountFlags::empty()).unwrap();
+        vfs.mount("/tmp", TmpFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        vfs.create("/tmp/file", FileType::RegularFile).unwrap();
+        let slow = vfs.open("/file").unwrap();
+        let fast = vfs.open("/tmp/file").unwrap();
+        vfs.write(&fast, b"fast", 0).unwrap();
+        let done = AtomicUsize::new(0);
+
+        async fn read(vfs: &Vfs, node: &VfsNode, expected: &[u8], done: &AtomicUsize) {
+            let mut buf = [0_u8; 4];
+            assert_eq!(Ok(4), vfs.read_async(node, &mut buf, 0).await);
+            assert_eq!(expected, &buf);
+            done.fetch_add(1, Relaxed);
+        }
+
+        let executor = Executor::default();
+        // the default implementation completes when it is first polled
+        executor.spawn(read(&vfs, &fast, b"fast", &done));
+        assert_eq!(TickResult::Worked, executor.tick());
+        assert_eq!(1, done.load(Relaxed));
+
+        // the caller awaits the file system until it is done
+        executor.spawn(read(&vfs, &slow, b"file", &done));
+        for _ in 0..3 {
+            assert_eq!(TickResult::Worked, executor.tick());
+            assert_eq!(1, done.load(Relaxed));
+        }
+        assert_eq!(TickResult::Worked, executor.tick());
+        assert_eq!(2, done.load(Relaxed));
+        assert_eq!(TickResult::Idled, executor.tick());
+    }
+
+    #[kernel_test]
+    fn test_read_only_mount() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let fs = MockFs::new(FsId::new(), &[("file", false)], open.clone());
+        let writes = fs.writes.clone();
+        let vfs = Vfs::new();
+        vfs.mount("/", fs, MountFlags::READ_ONLY).unwrap();
+        assert_eq!(MountFlags::READ_ONLY, vfs.mounts().next().unwrap().flags);
+
+        // nothing reaches the file system
+        let node = vfs.open("/file").unwrap();
+        assert_eq!(Err(VfsError::ReadOnly), vfs.write(&node, b"x", 0));
+        assert_eq!(Err(VfsError::ReadOnly), vfs.truncate(&node, 0));
+        assert_eq!(
+            Err(VfsError::ReadOnly),
+            vfs.create("/new", FileType::RegularFile)
+        );
+        assert_eq!(
+            Err(VfsError::AlreadyExists),
+            vfs.create("/file", FileType::RegularFile)
+        );
+        assert_eq!(Err(VfsError::ReadOnly), vfs.remove_file("/file"));
+        assert_eq!(Err(VfsError::ReadOnly), vfs.rename("/file", "/other"));
+        assert_eq!(0, writes.load(Relaxed));
+        let mut buf = [0_u8; 4];
+        assert_eq!(Ok(4), vfs.read(&node, &mut buf, 0));
+
+        vfs.remount("/", MountFlags::empty()).unwrap();
+        assert_eq!(MountFlags::empty(), vfs.mounts().next().unwrap().flags);
+        assert_eq!(Ok(1), vfs.write(&node, b"x", 0));
+        assert_eq!(1, writes.load(Relaxed));
+
+        // a node that has been written through keeps the file system writable,
+        // nodes that have only been read don't
+        let reader = vfs.open("/file").unwrap();
+        assert_eq!(Err(VfsError::Busy), vfs.remount("/", MountFlags::READ_ONLY));
+        assert_eq!(MountFlags::empty(), vfs.mounts().next().unwrap().flags);
+        drop(node);
+        vfs.remount("/", MountFlags::READ_ONLY).unwrap();
+        drop(reader);
+
+        assert_eq!(
+            Err(VfsError::NotMounted),
+            vfs.remount("/missing", MountFlags::empty())
+        );
+    }
+
+    #[kernel_test]
+    fn test_device_gone() {
+        let open = Arc::new(AtomicUsize::new(0));
+        let vfs = Vfs::new();
+        vfs.mount("/", TmpFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        let fs = MockFs::new(FsId::new(), &[("file", false)], open.clone());
+        let writes = fs.writes.clone();
+        vfs.mount_device("/mnt", fs, MountFlags::empty(), 7)
+            .unwrap();
+        let node = vfs.open("/mnt/file").unwrap();
+
+        // other devices don't affect the mount
+        vfs.device_gone(8);
+        let mut buf = [0_u8; 4];
+        assert_eq!(Ok(4), vfs.read(&node, &mut buf, 0));
+
+        vfs.device_gone(7);
+        assert_eq!(Err(VfsError::DeviceGone), vfs.read(&node, &mut buf, 0));
+        assert_eq!(Err(VfsError::DeviceGone), vfs.write(&node, b"x", 0));
+        assert_eq!(Err(VfsError::DeviceGone), vfs.open("/mnt/file").map(|_| ()));
+        assert_eq!(0, writes.load(Relaxed));
+        assert_eq!(Ok(()), vfs.sync());
+
+        // the mount can still be cleaned up
+        assert_eq!(Err(VfsError::Busy), vfs.unmount("/mnt"));
+        drop(node);
+        vfs.unmount("/mnt").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_mount_shadows_and_unmount_restores() {
+        let vfs = Vfs::new();
+        vfs.mount("/", TmpFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        vfs.create("/dev", FileType::Directory).unwrap();
+        vfs.create("/dev/shadowed", FileType::RegularFile).unwrap();
+
+        vfs.mount("/dev", VirtualDevFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        assert_eq!(Ok(true), vfs.exists("/dev/zero"));
+        assert_eq!(Ok(false), vfs.exists("/dev/shadowed"));
+
+        // mounts can't be stacked on the same path, no matter how it is spelled
+        assert_eq!(
+            Err(VfsError::AlreadyMounted),
+            vfs.mount("/dev", TmpFs::new(FsId::new()), MountFlags::empty())
+        );
+        assert_eq!(
+            Err(VfsError::AlreadyMounted),
+            vfs.mount(
+                "/tmp/../dev//",
+                TmpFs::new(FsId::new()),
+                MountFlags::empty()
+            )
+        );
+
+        vfs.unmount("/dev").unwrap();
+        assert_eq!(Ok(false), vfs.exists("/dev/zero"));
+        assert_eq!(Ok(true), vfs.exists("/dev/shadowed"));
+        assert_eq!(Err(VfsError::NotMounted), vfs.unmount("/dev"));
+    }
+
+    #[kernel_test]
+    fn test_unmount_busy() {
+        let vfs = Vfs::new();
+        vfs.mount("/", TmpFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        vfs.mount("/dev", VirtualDevFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        let devfs_info = |vfs: &Vfs| {
+            vfs.mounts()
+                .find(|m| m.path.as_path() == Path::new("/dev"))
+                .unwrap()
+        };
+
+        let node = vfs.open("/dev/zero").unwrap();
+        assert_eq!("devfs", devfs_info(&vfs).fs_name);
+        assert_eq!(1, devfs_info(&vfs).open_handles);
+        assert_eq!(Err(VfsError::Busy), vfs.unmount("/dev"));
+        // a file system with another one mounted below it is busy as well
+        assert_eq!(Err(VfsError::Busy), vfs.unmount("/"));
+
+        drop(node);
+        assert_eq!(0, devfs_info(&vfs).open_handles);
+        vfs.unmount("/dev").unwrap();
+        vfs.unmount("/").unwrap();
+        assert_eq!(0, vfs.mounts().count());
+    }
+
+    #[kernel_test]
+    fn test_symlinks() {
+        for link in ["relative", "absolute", "chain", "long"] {
+            let file = vfs().open(format!("/var/data/links/{link}")).unwrap();
+            assert_eq!("/var/data/hello.txt", file.path().as_str());
+            let mut buf = [0_u8; 5];
+            assert_eq!(5, vfs().read(&file, &mut buf, 0).unwrap());
+            assert_eq!(b"Hello", &buf);
+        }
+
+        let link = vfs().open_nofollow("/var/data/links/chain").unwrap();
+        assert_eq!(Ok("relative".to_string()), vfs().readlink(&link));
+        let file = vfs().open("/var/data/hello.txt").unwrap();
+        assert_eq!(Err(VfsError::InvalidArgument), vfs().readlink(&file));
+    }
+
+    #[kernel_test]
+    fn test_symlink_loop() {
+        assert_eq!(
+            Err(VfsError::SymlinkLoop),
+            vfs().open("/var/data/links/loop").map(drop)
+        );
+        assert_eq!(
+            Err(VfsError::SymlinkLoop),
+            vfs().open("/var/data/links/loop/").map(drop)
+        );
+        // the link itself can still be opened
+        assert!(vfs().open_nofollow("/var/data/links/loop").is_ok());
+    }
 
     #[kernel_test]
     fn test_read_from_offset_zero() {
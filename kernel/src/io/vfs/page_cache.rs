@@ -0,0 +1,659 @@
+use alloc::collections::BTreeMap;
+use core::ops::Range;
+use core::slice::from_raw_parts_mut;
+
+use spin::Mutex;
+use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size4KiB};
+
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::{vfs, FileSystem, InodeId, VfsHandle};
+use crate::mem::PhysicalMemoryManager;
+use crate::process::vmm;
+use crate::{map_page, unmap_page};
+
+pub const PAGE_SIZE: usize = Size4KiB::SIZE as usize;
+
+/// The number of pages that the cache holds before it evicts pages to make
+/// room for new ones. Pages that are mapped can't be evicted, so this is a
+/// soft limit.
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The number of pages after the last read page that are read into the cache
+/// while a file is read sequentially.
+pub const READ_AHEAD_PAGES: usize = 4;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+struct PageKey {
+    inode: InodeId,
+    index: usize,
+}
+
+struct CachedPage {
+    frame: PhysFrame,
+    /// Whether the page contains data that is not written back to the file
+    /// system yet.
+    dirty: bool,
+    /// The number of mappings of this page. Mapped pages can't be evicted.
+    pins: usize,
+    /// When the page was last accessed, in cache accesses.
+    last_used: u64,
+}
+
+/// A regular file whose content is cached, as seen by [`PageCache::read`] and
+/// friends.
+#[derive(Copy, Clone)]
+pub struct CachedFile {
+    pub inode: InodeId,
+    pub handle: VfsHandle,
+    pub size: usize,
+}
+
+impl CachedFile {
+    fn page_count(&self) -> usize {
+        self.size.div_ceil(PAGE_SIZE)
+    }
+}
+
+/// The read-ahead state of an open file. Reads that continue where the
+/// previous read stopped are sequential, as is the first read at the start of
+/// the file.
+#[derive(Debug, Default)]
+pub struct ReadAhead {
+    /// The index of the page after the last page that was read.
+    next_index: usize,
+}
+
+impl ReadAhead {
+    fn is_sequential(&self, first_index: usize) -> bool {
+        first_index == self.next_index || first_index + 1 == self.next_index
+    }
+}
+
+struct Inner {
+    pages: BTreeMap<PageKey, CachedPage>,
+    clock: u64,
+}
+
+/// Caches the pages of regular files of the file systems that
+/// [use the page cache](FileSystem::uses_page_cache).
+///
+/// Reads are served from the cache and fill it through the file system. Writes
+/// are written through to the file system and update the cached pages. Pages
+/// that are mapped into memory with a shared mapping are the same frames that
+/// reads and writes go through, and they are written back when they are unmapped.
+///
+/// Callers lock the file system before they call into the cache.
+pub struct PageCache {
+    inner: Mutex<Inner>,
+    capacity: usize,
+}
+
+impl PageCache {
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                pages: BTreeMap::new(),
+                clock: 0,
+            }),
+            capacity,
+        }
+    }
+
+    /// Reads from the file through the cache, like [`FileSystem::read`].
+    pub fn read(
+        &self,
+        fs: &mut dyn FileSystem,
+        file: CachedFile,
+        read_ahead: &mut ReadAhead,
+        buf: &mut [u8],
+        offset: usize,
+    ) -> Result<usize> {
+        if offset >= file.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(file.size - offset);
+        let pages = offset / PAGE_SIZE..(offset + len).div_ceil(PAGE_SIZE);
+
+        let mut inner = self.inner.lock();
+        for index in pages.clone() {
+            let frame = self.get_or_fill(&mut inner, fs, file, index)?;
+            let (in_page, in_buf) = overlap(index, offset, len);
+            with_frame(frame, |data| buf[in_buf].copy_from_slice(&data[in_page]))?;
+        }
+
+        if read_ahead.is_sequential(pages.start) {
+            let ahead = pages.end..(pages.end + READ_AHEAD_PAGES).min(file.page_count());
+            for index in ahead {
+                self.get_or_fill(&mut inner, fs, file, index)?;
+            }
+        }
+        read_ahead.next_index = pages.end;
+        Ok(len)
+    }
+
+    /// Writes to the file through to the file system, like [`FileSystem::write`],
+    /// and updates the pages that are already cached.
+    pub fn write(
+        &self,
+        fs: &mut dyn FileSystem,
+        file: CachedFile,
+        buf: &[u8],
+        offset: usize,
+    ) -> Result<usize> {
+        let written = fs.write(file.handle, buf, offset)?;
+        if written == 0 {
+            return Ok(0);
+        }
+
+        let mut inner = self.inner.lock();
+        for index in offset / PAGE_SIZE..(offset + written).div_ceil(PAGE_SIZE) {
+            let Some(page) = inner.pages.get(&PageKey {
+                inode: file.inode,
+                index,
+            }) else {
+                continue;
+            };
+            let (in_page, in_buf) = overlap(index, offset, written);
+            with_frame(page.frame, |data| {
+                data[in_page].copy_from_slice(&buf[in_buf])
+            })?;
+        }
+        Ok(written)
+    }
+
+    /// Drops the cached content past `size` after the file has been truncated.
+    /// Mapped pages stay in the cache, but are zeroed past `size`.
+    pub fn truncate(&self, inode: InodeId, size: usize) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let first = size / PAGE_SIZE;
+        let keys = inner
+            .pages
+            .range(
+                PageKey {
+                    inode,
+                    index: first,
+                }..=PageKey {
+                    inode,
+                    index: usize::MAX,
+                },
+            )
+            .map(|(key, _)| *key)
+            .collect::<alloc::vec::Vec<_>>();
+        for key in keys {
+            let page = &inner.pages[&key];
+            let page_start = key.index * PAGE_SIZE;
+            if page.pins == 0 && page_start >= size {
+                let page = inner.pages.remove(&key).unwrap();
+                PhysicalMemoryManager::deallocate_frame(page.frame);
+            } else {
+                let zero_from = size.saturating_sub(page_start);
+                with_frame(page.frame, |data| data[zero_from..].fill(0))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops all pages of the inode that are not mapped, because the inode is
+    /// gone and its number may be reused.
+    pub fn invalidate(&self, inode: InodeId) {
+        let mut inner = self.inner.lock();
+        inner.pages.retain(|key, page| {
+            if key.inode != inode || page.pins > 0 {
+                return true;
+            }
+            PhysicalMemoryManager::deallocate_frame(page.frame);
+            false
+        });
+    }
+
+    /// Returns the frame that holds the page with the given index, and pins it
+    /// until it is [unpinned](PageCache::unpin). Pages that are pinned for
+    /// writing are considered dirty.
+    pub fn pin(
+        &self,
+        fs: &mut dyn FileSystem,
+        file: CachedFile,
+        index: usize,
+        writable: bool,
+    ) -> Result<PhysFrame> {
+        let mut inner = self.inner.lock();
+        let frame = self.get_or_fill(&mut inner, fs, file, index)?;
+        let page = inner
+            .pages
+            .get_mut(&PageKey {
+                inode: file.inode,
+                index,
+            })
+            .expect("page must be cached after it was filled");
+        page.pins += 1;
+        page.dirty |= writable;
+        Ok(frame)
+    }
+
+    /// Releases a pin of [`PageCache::pin`], and writes the page back if it is
+    /// dirty. The page must not be mapped anymore.
+    pub fn unpin(&self, fs: &mut dyn FileSystem, file: CachedFile, index: usize) -> Result<()> {
+        let mut inner = self.inner.lock();
+        let key = PageKey {
+            inode: file.inode,
+            index,
+        };
+        let Some(page) = inner.pages.get_mut(&key) else {
+            return Ok(());
+        };
+        page.pins = page.pins.saturating_sub(1);
+        if page.dirty && page.pins == 0 {
+            write_back(fs, file, index, page)?;
+        }
+        Ok(())
+    }
+
+    /// Evicts up to `count` pages without waiting for the cache, and returns
+    /// how many frames were freed. This is what the physical memory manager
+    /// calls when it runs out of frames.
+    pub fn reclaim(&self, count: usize) -> usize {
+        match self.inner.try_lock() {
+            Some(mut inner) => evict(&mut inner, count),
+            None => 0,
+        }
+    }
+
+    /// Returns the number of cached pages.
+    pub fn cached_pages(&self) -> usize {
+        self.inner.lock().pages.len()
+    }
+
+    fn get_or_fill(
+        &self,
+        inner: &mut Inner,
+        fs: &mut dyn FileSystem,
+        file: CachedFile,
+        index: usize,
+    ) -> Result<PhysFrame> {
+        inner.clock += 1;
+        let clock = inner.clock;
+        let key = PageKey {
+            inode: file.inode,
+            index,
+        };
+        if let Some(page) = inner.pages.get_mut(&key) {
+            page.last_used = clock;
+            return Ok(page.frame);
+        }
+
+        if inner.pages.len() >= self.capacity {
+            evict(inner, 1);
+        }
+        let frame = match PhysicalMemoryManager::allocate_frame() {
+            Some(frame) => frame,
+            // the physical memory manager can't reclaim our pages while we hold the lock
+            None if evict(inner, 1) > 0 => {
+                PhysicalMemoryManager::allocate_frame().ok_or(VfsError::NoSpace)?
+            }
+            None => return Err(VfsError::NoSpace),
+        };
+
+        let filled = with_frame(frame, |data| {
+            data.fill(0);
+            fs.read(file.handle, data, index * PAGE_SIZE)
+        })
+        .and_then(|result| result);
+        if let Err(e) = filled {
+            PhysicalMemoryManager::deallocate_frame(frame);
+            return Err(e);
+        }
+
+        inner.pages.insert(
+            key,
+            CachedPage {
+                frame,
+                dirty: false,
+                pins: 0,
+                last_used: clock,
+            },
+        );
+        Ok(frame)
+    }
+}
+
+impl Drop for PageCache {
+    fn drop(&mut self) {
+        for page in self.inner.get_mut().pages.values() {
+            PhysicalMemoryManager::deallocate_frame(page.frame);
+        }
+    }
+}
+
+/// Evicts up to `count` of the least recently used pages that are neither
+/// mapped nor dirty, and returns how many it evicted.
+fn evict(inner: &mut Inner, count: usize) -> usize {
+    let mut evicted = 0;
+    while evicted < count {
+        let Some(key) = inner
+            .pages
+            .iter()
+            .filter(|(_, page)| page.pins == 0 && !page.dirty)
+            .min_by_key(|(_, page)| page.last_used)
+            .map(|(key, _)| *key)
+        else {
+            break;
+        };
+        let page = inner.pages.remove(&key).unwrap();
+        PhysicalMemoryManager::deallocate_frame(page.frame);
+        evicted += 1;
+    }
+    evicted
+}
+
+/// Writes the part of the page that lies within the file back to the file
+/// system.
+fn write_back(
+    fs: &mut dyn FileSystem,
+    file: CachedFile,
+    index: usize,
+    page: &mut CachedPage,
+) -> Result<()> {
+    let page_start = index * PAGE_SIZE;
+    let len = file.size.saturating_sub(page_start).min(PAGE_SIZE);
+    if len > 0 {
+        with_frame(page.frame, |data| {
+            fs.write(file.handle, &data[..len], page_start)
+        })??;
+    }
+    page.dirty = false;
+    Ok(())
+}
+
+/// Returns the ranges within the page with the given index and within the
+/// buffer that overlap for an access of `len` bytes at `offset`.
+fn overlap(index: usize, offset: usize, len: usize) -> (Range<usize>, Range<usize>) {
+    let page_start = index * PAGE_SIZE;
+    let start = offset.max(page_start);
+    let end = (offset + len).min(page_start + PAGE_SIZE);
+    (
+        start - page_start..end - page_start,
+        start - offset..end - offset,
+    )
+}
+
+/// Maps the frame into the current address space while `f` runs. Frames of
+/// the page cache are not mapped anywhere otherwise.
+fn with_frame<R>(frame: PhysFrame, f: impl FnOnce(&mut [u8]) -> R) -> Result<R> {
+    let interval = vmm().reserve(PAGE_SIZE).map_err(|_| VfsError::NoSpace)?;
+    let page = Page::<Size4KiB>::containing_address(interval.start());
+    map_page!(
+        page,
+        frame,
+        Size4KiB,
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE
+    );
+    let data = unsafe {
+        // safety: we just mapped the page, and nothing else refers to it
+        from_raw_parts_mut(page.start_address().as_mut_ptr::<u8>(), PAGE_SIZE)
+    };
+    let result = f(data);
+    unmap_page!(page, Size4KiB);
+    drop(interval); // keep the interval reserved until the page is unmapped
+    Ok(result)
+}
+
+/// The hook that the physical memory manager calls when it runs out of frames.
+pub(super) fn reclaim(count: usize) -> usize {
+    vfs().page_cache().reclaim(count)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::slice::from_raw_parts_mut;
+    use core::sync::atomic::AtomicUsize;
+    use core::sync::atomic::Ordering::Relaxed;
+
+    use foundation::falloc::vec::FVec;
+    use kernel_api::syscall::{FileMode, OpenFlags, Stat};
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+    use x86_64::VirtAddr;
+
+    use crate::io::path::Path;
+    use crate::io::vfs::error::Result;
+    use crate::io::vfs::page_cache::{PAGE_SIZE, READ_AHEAD_PAGES};
+    use crate::io::vfs::{
+        vfs, DirEntry, FileSystem, FileType, FsId, MountFlags, Vfs, VfsError, VfsHandle,
+    };
+    use crate::process::vmm;
+    use crate::syscall::{sys_close, sys_mmap, sys_open, sys_read, sys_write, MapFlags, Prot};
+
+    const FILE_INO: u64 = 2;
+
+    /// A file system with a single file called `file`, which counts how often
+    /// the file is read.
+    struct CountingFs {
+        fsid: FsId,
+        content: Arc<Mutex<Vec<u8>>>,
+        reads: Arc<AtomicUsize>,
+        /// The open handles, and whether they refer to the file.
+        handles: Vec<(VfsHandle, bool)>,
+        next_handle: u64,
+    }
+
+    impl CountingFs {
+        fn new(pages: usize) -> (Self, Arc<Mutex<Vec<u8>>>, Arc<AtomicUsize>) {
+            let content = Arc::new(Mutex::new(
+                (0..pages * PAGE_SIZE)
+                    .map(|i| (i / PAGE_SIZE) as u8)
+                    .collect(),
+            ));
+            let reads = Arc::new(AtomicUsize::new(0));
+            let fs = Self {
+                fsid: FsId::new(),
+                content: content.clone(),
+                reads: reads.clone(),
+                handles: Vec::new(),
+                next_handle: 0,
+            };
+            (fs, content, reads)
+        }
+
+        fn open_handle(&mut self, is_file: bool) -> VfsHandle {
+            let handle = VfsHandle::new(self.next_handle);
+            self.next_handle += 1;
+            self.handles.push((handle, is_file));
+            handle
+        }
+
+        fn is_file(&self, handle: VfsHandle) -> Result<bool> {
+            self.handles
+                .iter()
+                .find(|(h, _)| *h == handle)
+                .map(|(_, is_file)| *is_file)
+                .ok_or(VfsError::HandleClosed)
+        }
+    }
+
+    impl FileSystem for CountingFs {
+        fn fsid(&self) -> FsId {
+            self.fsid
+        }
+
+        fn name(&self) -> &'static str {
+            "countingfs"
+        }
+
+        fn root(&mut self) -> Result<VfsHandle> {
+            Ok(self.open_handle(false))
+        }
+
+        fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+            if self.is_file(parent)? {
+                return Err(VfsError::NotDirectory);
+            }
+            if name != "file" {
+                return Err(VfsError::NoSuchFile);
+            }
+            Ok(self.open_handle(true))
+        }
+
+        fn close(&mut self, handle: VfsHandle) -> Result<()> {
+            self.is_file(handle)?;
+            self.handles.retain(|(h, _)| *h != handle);
+            Ok(())
+        }
+
+        fn read_dir(&mut self, _: VfsHandle, _: u64) -> Result<(FVec<DirEntry>, u64)> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
+            if !self.is_file(handle)? {
+                return Err(VfsError::IsDirectory);
+            }
+            self.reads.fetch_add(1, Relaxed);
+            let content = self.content.lock();
+            let start = offset.min(content.len());
+            let len = buf.len().min(content.len() - start);
+            buf[..len].copy_from_slice(&content[start..start + len]);
+            Ok(len)
+        }
+
+        fn write(&mut self, handle: VfsHandle, buf: &[u8], offset: usize) -> Result<usize> {
+            if !self.is_file(handle)? {
+                return Err(VfsError::IsDirectory);
+            }
+            let mut content = self.content.lock();
+            if content.len() < offset + buf.len() {
+                content.resize(offset + buf.len(), 0);
+            }
+            content[offset..offset + buf.len()].copy_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn truncate(&mut self, _: VfsHandle, _: usize) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
+            if self.is_file(handle)? {
+                stat.mode = FileMode::S_IFREG;
+                stat.ino = FILE_INO;
+                stat.size = self.content.lock().len() as u64;
+            } else {
+                stat.mode = FileMode::S_IFDIR;
+                stat.ino = 1;
+            }
+            stat.nlink = 1;
+            Ok(())
+        }
+
+        fn create(&mut self, _: &Path, _: FileType, _: FileMode) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn remove(&mut self, _: &Path) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn rename(&mut self, _: &Path, _: &Path) -> Result<()> {
+            Err(VfsError::Unsupported)
+        }
+
+        fn uses_page_cache(&self) -> bool {
+            true
+        }
+    }
+
+    #[kernel_test]
+    fn test_second_read_hits_cache() {
+        let (fs, _, reads) = CountingFs::new(3);
+        let vfs = Vfs::new();
+        vfs.mount("/", fs, MountFlags::empty()).unwrap();
+        let file = vfs.open("/file").unwrap();
+
+        let mut buf = [0_u8; 16];
+        assert_eq!(16, vfs.read(&file, &mut buf, 2 * PAGE_SIZE + 10).unwrap());
+        assert_eq!([2; 16], buf);
+        assert_eq!(1, reads.load(Relaxed));
+
+        assert_eq!(16, vfs.read(&file, &mut buf, 2 * PAGE_SIZE + 100).unwrap());
+        assert_eq!(1, reads.load(Relaxed));
+
+        // writes update the cached page instead of dropping it
+        vfs.write(&file, b"cached", 2 * PAGE_SIZE).unwrap();
+        assert_eq!(6, vfs.read(&file, &mut buf[..6], 2 * PAGE_SIZE).unwrap());
+        assert_eq!(b"cached", &buf[..6]);
+        assert_eq!(1, reads.load(Relaxed));
+
+        // reads are cut off at the end of the file
+        assert_eq!(8, vfs.read(&file, &mut buf, 3 * PAGE_SIZE - 8).unwrap());
+        assert_eq!(0, vfs.read(&file, &mut buf, 3 * PAGE_SIZE).unwrap());
+        assert_eq!(1, reads.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_read_ahead_sequential_only() {
+        let (fs, _, reads) = CountingFs::new(16);
+        let vfs = Vfs::new();
+        vfs.mount("/", fs, MountFlags::empty()).unwrap();
+        let mut page = vec![0_u8; PAGE_SIZE];
+
+        let file = vfs.open("/file").unwrap();
+        vfs.read(&file, &mut page, 0).unwrap();
+        assert_eq!(1 + READ_AHEAD_PAGES, reads.load(Relaxed));
+        vfs.read(&file, &mut page, PAGE_SIZE).unwrap();
+        assert_eq!(1, page[0]);
+        // the page was read ahead, and reading it reads ahead one more page
+        assert_eq!(2 + READ_AHEAD_PAGES, reads.load(Relaxed));
+        assert_eq!(2 + READ_AHEAD_PAGES, vfs.page_cache().cached_pages());
+
+        // random accesses through another node don't read ahead
+        let other = vfs.open("/file").unwrap();
+        let before = reads.load(Relaxed);
+        for index in [12, 9, 14] {
+            vfs.read(&other, &mut page, index * PAGE_SIZE).unwrap();
+            assert_eq!(index as u8, page[0]);
+        }
+        assert_eq!(before + 3, reads.load(Relaxed));
+    }
+
+    #[kernel_test]
+    fn test_mmap_and_read_share_pages() {
+        let (fs, content, reads) = CountingFs::new(1);
+        vfs()
+            .mount("/mnt/page_cache", fs, MountFlags::empty())
+            .unwrap();
+
+        let fd = sys_open("/mnt/page_cache/file", OpenFlags::O_RDWR.bits() as usize, 0).unwrap();
+        let addr = sys_mmap(
+            VirtAddr::zero(),
+            PAGE_SIZE,
+            Prot::Read | Prot::Write,
+            MapFlags::Shared,
+            fd,
+            0,
+        )
+        .unwrap();
+        let mapped = unsafe { from_raw_parts_mut(addr.as_mut_ptr::<u8>(), PAGE_SIZE) };
+
+        // what is written to the mapping can be read...
+        mapped[..5].copy_from_slice(b"hello");
+        let mut buf = [0_u8; 5];
+        assert_eq!(5, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"hello", &buf);
+        // ...without reading the file again
+        assert_eq!(1, reads.load(Relaxed));
+
+        // ...and what is written to the file appears in the mapping
+        assert_eq!(5, sys_write(fd, b"world").unwrap());
+        assert_eq!(b"helloworld", &mapped[..10]);
+
+        // unmapping writes the pages back
+        let vm_object = vmm().vm_objects().write().remove(&addr).unwrap();
+        drop(vm_object);
+        assert_eq!(b"helloworld", &content.lock()[..10]);
+
+        sys_close(fd).unwrap();
+        vfs().unmount("/mnt/page_cache").unwrap();
+    }
+}
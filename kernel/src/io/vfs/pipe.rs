@@ -0,0 +1,311 @@
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use foundation::falloc::vec::FVec;
+use foundation::io::{Read, ReadError, Write, WriteError};
+use foundation::mem::RingBuffer;
+use spin::Mutex;
+
+use kernel_api::syscall::{FileMode, PollEvents, Stat};
+
+use crate::io::path::Path;
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::{DirEntry, FileSystem, FileType, FsId, VfsHandle};
+use crate::process::wait_queue::WaitQueue;
+
+const PIPE_BUFFER_SIZE: usize = 64 * 1024;
+
+static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+// starts at 1, because the id is also the inode number of the pipe
+static PIPE_COUNTER: AtomicU64 = AtomicU64::new(1);
+
+fn next_handle() -> VfsHandle {
+    VfsHandle::new(HANDLE_COUNTER.fetch_add(1, Relaxed))
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum End {
+    Read,
+    Write,
+}
+
+struct Pipe {
+    ino: u64,
+    buffer: RingBuffer<u8>,
+    readers: usize,
+    writers: usize,
+}
+
+struct PipeEnd {
+    pipe: Arc<Mutex<Pipe>>,
+    /// Woken when the pipe becomes readable, writable, or when an end is
+    /// closed. It's shared by both ends.
+    waiters: Arc<WaitQueue>,
+    end: End,
+}
+
+pub struct PipeHandles {
+    /// A name for the pipe, which can be used as the path of both nodes.
+    pub name: String,
+    pub read: VfsHandle,
+    pub write: VfsHandle,
+}
+
+/// An anonymous file system that holds pipes.
+///
+/// This file system is not mounted anywhere. Pipes are created with
+/// [`PipeFs::create_pipe`], which returns one handle for each end. Both
+/// ends share a ring buffer, and a pipe is freed once both ends are closed.
+pub struct PipeFs {
+    fsid: FsId,
+    handles: BTreeMap<VfsHandle, PipeEnd>,
+}
+
+impl PipeFs {
+    pub fn new(fsid: FsId) -> Self {
+        Self {
+            fsid,
+            handles: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a new pipe and returns the handles of both ends.
+    pub fn create_pipe(&mut self) -> Result<PipeHandles> {
+        let buffer = RingBuffer::try_with_size(PIPE_BUFFER_SIZE).map_err(|_| VfsError::NoSpace)?;
+        let id = PIPE_COUNTER.fetch_add(1, Relaxed);
+        let pipe = Arc::new(Mutex::new(Pipe {
+            ino: id,
+            buffer,
+            readers: 1,
+            writers: 1,
+        }));
+        let waiters = Arc::new(WaitQueue::new());
+
+        let read_handle = next_handle();
+        let write_handle = next_handle();
+        self.handles.insert(
+            read_handle,
+            PipeEnd {
+                pipe: pipe.clone(),
+                waiters: waiters.clone(),
+                end: End::Read,
+            },
+        );
+        self.handles.insert(
+            write_handle,
+            PipeEnd {
+                pipe,
+                waiters,
+                end: End::Write,
+            },
+        );
+        Ok(PipeHandles {
+            name: format!("pipe:[{id}]"),
+            read: read_handle,
+            write: write_handle,
+        })
+    }
+
+    fn get_end(&self, handle: VfsHandle) -> Result<&PipeEnd> {
+        self.handles.get(&handle).ok_or(VfsError::HandleClosed)
+    }
+}
+
+impl FileSystem for PipeFs {
+    fn fsid(&self) -> FsId {
+        self.fsid
+    }
+
+    fn name(&self) -> &'static str {
+        "pipefs"
+    }
+
+    fn root(&mut self) -> Result<VfsHandle> {
+        // pipes can't be opened by path, they only exist through `create_pipe`
+        Err(VfsError::NoSuchFile)
+    }
+
+    fn lookup(&mut self, _parent: VfsHandle, _name: &str) -> Result<VfsHandle> {
+        Err(VfsError::NotDirectory)
+    }
+
+    fn close(&mut self, handle: VfsHandle) -> Result<()> {
+        let end = self.handles.remove(&handle).ok_or(VfsError::HandleClosed)?;
+        {
+            let mut pipe = end.pipe.lock();
+            match end.end {
+                End::Read => pipe.readers -= 1,
+                End::Write => pipe.writers -= 1,
+            }
+        }
+        // the other end may have hung up now
+        end.waiters.wake_all();
+        Ok(())
+    }
+
+    fn read_dir(&mut self, _handle: VfsHandle, _cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+        Err(VfsError::NotDirectory)
+    }
+
+    fn read(&mut self, handle: VfsHandle, buf: &mut [u8], _offset: usize) -> Result<usize> {
+        let end = self.get_end(handle)?;
+        if end.end != End::Read {
+            return Err(VfsError::Unsupported);
+        }
+
+        let mut pipe = end.pipe.lock();
+        match pipe.buffer.read(buf) {
+            Ok(n) => {
+                drop(pipe);
+                // there is room for writers now
+                end.waiters.wake_all();
+                Ok(n)
+            }
+            // all writers are gone, so this is the end of the file
+            Err(ReadError::WouldBlock) if pipe.writers == 0 => Ok(0),
+            Err(ReadError::WouldBlock) => Err(VfsError::WouldBlock),
+            Err(_) => Err(VfsError::ReadError),
+        }
+    }
+
+    fn write(&mut self, handle: VfsHandle, buf: &[u8], _offset: usize) -> Result<usize> {
+        let end = self.get_end(handle)?;
+        if end.end != End::Write {
+            return Err(VfsError::Unsupported);
+        }
+
+        let mut pipe = end.pipe.lock();
+        if pipe.readers == 0 {
+            return Err(VfsError::BrokenPipe);
+        }
+        match pipe.buffer.write(buf) {
+            Ok(n) => {
+                drop(pipe);
+                // there is data for readers now
+                end.waiters.wake_all();
+                Ok(n)
+            }
+            Err(WriteError::WouldBlock) => Err(VfsError::WouldBlock),
+            Err(_) => Err(VfsError::WriteError),
+        }
+    }
+
+    fn truncate(&mut self, _handle: VfsHandle, _size: usize) -> Result<()> {
+        Err(VfsError::InvalidArgument)
+    }
+
+    fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
+        let end = self.get_end(handle)?;
+        let pipe = end.pipe.lock();
+        let len = pipe.buffer.len();
+
+        stat.mode = FileMode::S_IFIFO | FileMode::S_IRUSR | FileMode::S_IWUSR;
+        stat.dev = self.fsid.0;
+        stat.ino = pipe.ino;
+        stat.nlink = 1;
+        stat.size = len as u64;
+        stat.blksize = PIPE_BUFFER_SIZE as u64;
+        Ok(())
+    }
+
+    fn create(&mut self, _path: &Path, _ftype: FileType, _mode: FileMode) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn remove(&mut self, _path: &Path) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn rename(&mut self, _from: &Path, _to: &Path) -> Result<()> {
+        Err(VfsError::Unsupported)
+    }
+
+    fn poll(&self, handle: VfsHandle, interest: PollEvents) -> Result<PollEvents> {
+        let end = self.get_end(handle)?;
+        let pipe = end.pipe.lock();
+
+        let mut ready = PollEvents::empty();
+        match end.end {
+            End::Read => {
+                if !pipe.buffer.is_empty() {
+                    ready |= PollEvents::POLLIN;
+                }
+                if pipe.writers == 0 {
+                    ready |= PollEvents::POLLHUP;
+                }
+            }
+            End::Write => {
+                if !pipe.buffer.is_full() {
+                    ready |= PollEvents::POLLOUT;
+                }
+                if pipe.readers == 0 {
+                    ready |= PollEvents::POLLERR;
+                }
+            }
+        }
+        Ok(ready & (interest | PollEvents::ALWAYS))
+    }
+
+    fn wait_queue(&self, handle: VfsHandle) -> Option<Arc<WaitQueue>> {
+        self.get_end(handle).ok().map(|end| end.waiters.clone())
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::time::Duration;
+
+    use foundation::time::Instant;
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+    use crate::process::park;
+    use crate::time::HpetInstantProvider;
+
+    /// Parks for at most `timeout`, and returns whether the current thread
+    /// was woken before that.
+    fn woken_within(timeout: Duration) -> bool {
+        let start = Instant::now();
+        park(Some(start + timeout));
+        start.elapsed() < timeout
+    }
+
+    #[kernel_test]
+    fn test_pipe_wakes_waiters() {
+        let mut fs = PipeFs::new(FsId::new());
+        let pipe = fs.create_pipe().unwrap();
+        let other = fs.create_pipe().unwrap();
+        // forget wakes from earlier tests
+        park(Some(Instant::now()));
+
+        let waiter = fs.wait_queue(pipe.read).unwrap().register();
+        fs.write(pipe.write, b"x", 0).unwrap();
+        assert!(woken_within(Duration::from_secs(1)));
+        drop(waiter);
+
+        // both ends share the queue
+        let waiter = fs.wait_queue(pipe.write).unwrap().register();
+        fs.read(pipe.read, &mut [0; 1], 0).unwrap();
+        assert!(woken_within(Duration::from_secs(1)));
+        drop(waiter);
+
+        // other pipes have their own queue
+        let waiter = fs.wait_queue(other.read).unwrap().register();
+        fs.write(pipe.write, b"x", 0).unwrap();
+        assert!(!woken_within(Duration::from_millis(20)));
+        drop(waiter);
+
+        let waiter = fs.wait_queue(pipe.write).unwrap().register();
+        fs.close(pipe.read).unwrap();
+        assert!(woken_within(Duration::from_secs(1)));
+        drop(waiter);
+
+        for handle in [pipe.write, other.read, other.write] {
+            fs.close(handle).unwrap();
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use core::fmt;
+use core::fmt::Write;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use foundation::falloc::vec::FVec;
+use foundation::time::Instant;
+use kernel_api::syscall::{FileMode, Stat, Timespec};
+
+use crate::driver::pci;
+use crate::driver::pci::{PciAddress, PciDevice, ReadConfig};
+use crate::io::path::Path;
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::{vfs, DirEntry, FileSystem, FileType, FsId, MountFlags, VfsHandle};
+use crate::mem::virt::heap;
+use crate::mem::PhysicalMemoryManager;
+use crate::process;
+use crate::process::attributes::ProcessId;
+use crate::process::{page_table_flags_to_string, process_tree, Process, ProcessTree};
+use crate::time::HpetInstantProvider;
+
+static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn next_handle() -> VfsHandle {
+    VfsHandle::new(HANDLE_COUNTER.fetch_add(1, Relaxed))
+}
+
+const ROOT_INO: u64 = 1;
+const SELF_INO: u64 = 2;
+/// The inode number of the first file in the root directory, the others
+/// follow in the order of [`ROOT_FILES`].
+const ROOT_FILES_INO: u64 = 3;
+
+/// The inode number of the `pci` directory.
+const PCI_INO: u64 = ROOT_FILES_INO + ROOT_FILES.len() as u64;
+const PCI_DEVICES_INO: u64 = PCI_INO + 1;
+
+/// Generates the content of a file in the root directory.
+type RootGenerator = fn(&mut Snapshot) -> fmt::Result;
+/// Generates the content of a file in the directory of a process.
+type ProcessGenerator = fn(&mut Snapshot, &ProcessTree, &Arc<Process>) -> fmt::Result;
+
+const ROOT_FILES: &[(&str, RootGenerator)] =
+    &[("meminfo", meminfo), ("mounts", mounts), ("uptime", uptime)];
+
+const PROCESS_FILES: &[(&str, ProcessGenerator)] = &[("maps", maps), ("status", status)];
+
+/// Generates the content of a file in the directory of a PCI device.
+type PciGenerator = fn(&mut Snapshot, &PciDevice) -> fmt::Result;
+
+/// The files in the directory of a PCI device, besides `config`.
+const PCI_DEVICE_FILES: &[(&str, PciGenerator)] = &[
+    ("class", pci_class),
+    ("device", pci_device_id),
+    ("driver", pci_driver),
+    ("vendor", pci_vendor_id),
+];
+
+/// The inode number of the directory of a process if `index` is zero, and of
+/// the file in it at `index - 1` in [`PROCESS_FILES`] otherwise. Numbers below
+/// 16 belong to the root directory and its entries.
+fn process_ino(pid: ProcessId, index: usize) -> u64 {
+    ((u64::from(pid) + 1) << 4) | index as u64
+}
+
+/// The inode number of the directory of a PCI device if `index` is zero, of
+/// its `config` file if it's one, and of the file at `index - 2` in
+/// [`PCI_DEVICE_FILES`] otherwise. The top bit keeps them apart from the
+/// numbers of processes.
+fn pci_ino(address: PciAddress, index: usize) -> u64 {
+    let function = u64::from(address.segment) << 16
+        | u64::from(address.bus) << 8
+        | u64::from(address.device) << 3
+        | u64::from(address.function);
+    1 << 63 | function << 4 | index as u64
+}
+
+/// The content of a file, generated when the file is opened. Reads at any
+/// offset see the same content, even if the state that it describes changes
+/// in between.
+#[derive(Default)]
+struct Snapshot(FVec<u8>);
+
+impl Write for Snapshot {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.try_extend(s.bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+impl Snapshot {
+    /// Generates a snapshot with the given function. The only way that
+    /// writing into a snapshot fails is running out of memory.
+    fn generate(f: impl FnOnce(&mut Snapshot) -> fmt::Result) -> Result<Self> {
+        let mut snapshot = Snapshot::default();
+        f(&mut snapshot).map_err(|_| VfsError::NoSpace)?;
+        Ok(snapshot)
+    }
+}
+
+/// What a handle of the procfs refers to.
+enum ProcNode {
+    Root,
+    /// `/proc/self`, a link to the directory of the process that resolves it.
+    SelfLink,
+    Process(ProcessId),
+    /// `/proc/pci`, with a directory for every PCI device.
+    PciRoot,
+    PciDevice(PciAddress),
+    /// The configuration space of a PCI device, which is read from the device
+    /// on every read instead of being a snapshot.
+    PciConfig(PciAddress),
+    File {
+        ino: u64,
+        content: Snapshot,
+    },
+}
+
+/// A read-only file system that exposes the state of the kernel and its
+/// processes, usually mounted at `/proc`. Processes show up as directories
+/// named after their pid, PCI devices as directories named after their
+/// address in `pci`. The content of the files is generated every time they
+/// are opened.
+pub struct ProcFs {
+    fsid: FsId,
+    handles: BTreeMap<VfsHandle, ProcNode>,
+}
+
+impl ProcFs {
+    pub fn new(fsid: FsId) -> Self {
+        Self {
+            fsid,
+            handles: BTreeMap::new(),
+        }
+    }
+
+    fn insert_handle(&mut self, node: ProcNode) -> VfsHandle {
+        let handle = next_handle();
+        self.handles.insert(handle, node);
+        handle
+    }
+
+    fn node(&self, handle: VfsHandle) -> Result<&ProcNode> {
+        self.handles.get(&handle).ok_or(VfsError::HandleClosed)
+    }
+
+    fn lookup_in_root(name: &str) -> Result<ProcNode> {
+        if name == "self" {
+            return Ok(ProcNode::SelfLink);
+        }
+        if name == "pci" {
+            return Ok(ProcNode::PciRoot);
+        }
+        if let Some((index, (_, generate))) = ROOT_FILES
+            .iter()
+            .enumerate()
+            .find(|(_, (file, _))| *file == name)
+        {
+            return Ok(ProcNode::File {
+                ino: ROOT_FILES_INO + index as u64,
+                content: Snapshot::generate(generate)?,
+            });
+        }
+        let pid = name.parse::<u64>().map_err(|_| VfsError::NoSuchFile)?;
+        process_tree()
+            .read()
+            .process_ids()
+            .find(|&&id| u64::from(id) == pid)
+            .map(|&id| ProcNode::Process(id))
+            .ok_or(VfsError::NoSuchFile)
+    }
+
+    fn lookup_in_process(pid: ProcessId, name: &str) -> Result<ProcNode> {
+        let (index, (_, generate)) = PROCESS_FILES
+            .iter()
+            .enumerate()
+            .find(|(_, (file, _))| *file == name)
+            .ok_or(VfsError::NoSuchFile)?;
+
+        let tree = process_tree().read();
+        // the process may have exited since its directory was opened
+        let process = tree.process_by_id(&pid).ok_or(VfsError::NoSuchFile)?;
+        Ok(ProcNode::File {
+            ino: process_ino(pid, index + 1),
+            content: Snapshot::generate(|out| generate(out, &tree, process))?,
+        })
+    }
+
+    fn lookup_in_pci(name: &str) -> Result<ProcNode> {
+        if name == "devices" {
+            return Ok(ProcNode::File {
+                ino: PCI_DEVICES_INO,
+                content: Snapshot::generate(pci_devices)?,
+            });
+        }
+        pci::devices()
+            .iter()
+            .map(|device| device.lock().address())
+            .find(|address| address.to_string() == name)
+            .map(ProcNode::PciDevice)
+            .ok_or(VfsError::NoSuchFile)
+    }
+
+    fn lookup_in_pci_device(address: PciAddress, name: &str) -> Result<ProcNode> {
+        if name == "config" {
+            return Ok(ProcNode::PciConfig(address));
+        }
+        let (index, (_, generate)) = PCI_DEVICE_FILES
+            .iter()
+            .enumerate()
+            .find(|(_, (file, _))| *file == name)
+            .ok_or(VfsError::NoSuchFile)?;
+        let device = pci::devices()
+            .iter()
+            .find(|device| device.lock().address() == address)
+            .ok_or(VfsError::NoSuchFile)?;
+        Ok(ProcNode::File {
+            ino: pci_ino(address, index + 2),
+            content: Snapshot::generate(|out| generate(out, &device.lock()))?,
+        })
+    }
+}
+
+impl FileSystem for ProcFs {
+    fn fsid(&self) -> FsId {
+        self.fsid
+    }
+
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn root(&mut self) -> Result<VfsHandle> {
+        Ok(self.insert_handle(ProcNode::Root))
+    }
+
+    fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+        let node = match self.node(parent)? {
+            ProcNode::Root => Self::lookup_in_root(name)?,
+            ProcNode::Process(pid) => Self::lookup_in_process(*pid, name)?,
+            ProcNode::PciRoot => Self::lookup_in_pci(name)?,
+            ProcNode::PciDevice(address) => Self::lookup_in_pci_device(*address, name)?,
+            ProcNode::SelfLink | ProcNode::PciConfig(_) | ProcNode::File { .. } => {
+                return Err(VfsError::NotDirectory)
+            }
+        };
+        Ok(self.insert_handle(node))
+    }
+
+    fn close(&mut self, handle: VfsHandle) -> Result<()> {
+        self.handles.remove(&handle).ok_or(VfsError::HandleClosed)?;
+        Ok(())
+    }
+
+    fn read_dir(&mut self, handle: VfsHandle, cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+        let file = |name: &str| DirEntry::new(name.to_string(), FileType::RegularFile);
+        let mut all = FVec::new();
+        match self.node(handle)? {
+            ProcNode::Root => {
+                all.try_extend(ROOT_FILES.iter().map(|(name, _)| file(*name)))
+                    .map_err(|_| VfsError::NoSpace)?;
+                all.try_push(DirEntry::new("self".to_string(), FileType::SymbolicLink))
+                    .map_err(|_| VfsError::NoSpace)?;
+                all.try_push(DirEntry::new("pci".to_string(), FileType::Directory))
+                    .map_err(|_| VfsError::NoSpace)?;
+                all.try_extend(
+                    process_tree()
+                        .read()
+                        .process_ids()
+                        .map(|pid| DirEntry::new(pid.to_string(), FileType::Directory)),
+                )
+                .map_err(|_| VfsError::NoSpace)?;
+            }
+            ProcNode::Process(_) => {
+                all.try_extend(PROCESS_FILES.iter().map(|(name, _)| file(*name)))
+                    .map_err(|_| VfsError::NoSpace)?;
+            }
+            ProcNode::PciRoot => {
+                all.try_push(file("devices"))
+                    .map_err(|_| VfsError::NoSpace)?;
+                all.try_extend(pci::devices().iter().map(|device| {
+                    DirEntry::new(device.lock().address().to_string(), FileType::Directory)
+                }))
+                .map_err(|_| VfsError::NoSpace)?;
+            }
+            ProcNode::PciDevice(_) => {
+                all.try_push(file("config"))
+                    .map_err(|_| VfsError::NoSpace)?;
+                all.try_extend(PCI_DEVICE_FILES.iter().map(|(name, _)| file(*name)))
+                    .map_err(|_| VfsError::NoSpace)?;
+            }
+            ProcNode::SelfLink | ProcNode::PciConfig(_) | ProcNode::File { .. } => {
+                return Err(VfsError::NotDirectory)
+            }
+        }
+
+        // the cookie is the number of entries that have already been read, so
+        // processes that come and go between calls may shift the entries
+        let mut entries = FVec::new();
+        entries
+            .try_extend(all.into_iter().skip(cookie as usize))
+            .map_err(|_| VfsError::NoSpace)?;
+        let next_cookie = cookie + entries.len() as u64;
+        Ok((entries, next_cookie))
+    }
+
+    fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
+        let content = match self.node(handle)? {
+            ProcNode::File { content, .. } => &content.0,
+            ProcNode::PciConfig(address) => return Ok(read_pci_config(*address, buf, offset)),
+            ProcNode::SelfLink => return Err(VfsError::InvalidArgument),
+            ProcNode::Root | ProcNode::Process(_) | ProcNode::PciRoot | ProcNode::PciDevice(_) => {
+                return Err(VfsError::IsDirectory)
+            }
+        };
+        if offset >= content.len() {
+            return Ok(0);
+        }
+        let len = buf.len().min(content.len() - offset);
+        buf[..len].copy_from_slice(&content[offset..offset + len]);
+        Ok(len)
+    }
+
+    fn write(&mut self, _: VfsHandle, _: &[u8], _: usize) -> Result<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&mut self, _: VfsHandle, _: usize) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
+        let dir_mode = FileMode::S_IFDIR
+            | FileMode::S_IRUSR
+            | FileMode::S_IXUSR
+            | FileMode::S_IRGRP
+            | FileMode::S_IXGRP
+            | FileMode::S_IROTH
+            | FileMode::S_IXOTH;
+        match self.node(handle)? {
+            ProcNode::Root => {
+                stat.ino = ROOT_INO;
+                stat.mode = dir_mode;
+                stat.nlink = 2;
+                stat.size = 0;
+            }
+            ProcNode::SelfLink => {
+                stat.ino = SELF_INO;
+                stat.mode =
+                    FileMode::S_IFLNK | FileMode::S_IRWXU | FileMode::S_IRWXG | FileMode::S_IRWXO;
+                stat.nlink = 1;
+                stat.size = 0;
+            }
+            ProcNode::Process(pid) => {
+                stat.ino = process_ino(*pid, 0);
+                stat.mode = dir_mode;
+                stat.nlink = 2;
+                stat.size = 0;
+            }
+            ProcNode::PciRoot => {
+                stat.ino = PCI_INO;
+                stat.mode = dir_mode;
+                stat.nlink = 2;
+                stat.size = 0;
+            }
+            ProcNode::PciDevice(address) => {
+                stat.ino = pci_ino(*address, 0);
+                stat.mode = dir_mode;
+                stat.nlink = 2;
+                stat.size = 0;
+            }
+            ProcNode::PciConfig(address) => {
+                stat.ino = pci_ino(*address, 1);
+                stat.mode = FileMode::S_IFREG | FileMode::S_IRUSR;
+                stat.nlink = 1;
+                stat.size = u64::from(address.config_space_size());
+            }
+            ProcNode::File { ino, content } => {
+                stat.ino = *ino;
+                stat.mode =
+                    FileMode::S_IFREG | FileMode::S_IRUSR | FileMode::S_IRGRP | FileMode::S_IROTH;
+                stat.nlink = 1;
+                stat.size = content.0.len() as u64;
+            }
+        }
+
+        // everything in here describes the current state
+        let now = Timespec::from(Instant::now().duration_since(Instant::new(0)));
+        stat.dev = self.fsid.0;
+        stat.atime = now;
+        stat.mtime = now;
+        stat.ctime = now;
+        stat.blksize = 0;
+        stat.blocks = 0;
+        Ok(())
+    }
+
+    fn readlink(&mut self, handle: VfsHandle) -> Result<String> {
+        match self.node(handle)? {
+            ProcNode::SelfLink => Ok(process::current().pid().to_string()),
+            _ => Err(VfsError::InvalidArgument),
+        }
+    }
+
+    fn create(&mut self, _: &Path, _: FileType, _: FileMode) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn remove(&mut self, _: &Path) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn rename(&mut self, _: &Path, _: &Path) -> Result<()> {
+        Err(VfsError::PermissionDenied)
+    }
+}
+
+fn meminfo(out: &mut Snapshot) -> fmt::Result {
+    const FRAME_KIB: usize = 4;
+
+    let physical = PhysicalMemoryManager::stats();
+    writeln!(out, "MemTotal:\t{} kB", physical.usable_frames * FRAME_KIB)?;
+    writeln!(out, "MemFree:\t{} kB", physical.free_frames * FRAME_KIB)?;
+    writeln!(out, "HeapTotal:\t{} kB", heap::size() / 1024)?;
+    writeln!(out, "HeapFree:\t{} kB", heap::free() / 1024)
+}
+
+fn mounts(out: &mut Snapshot) -> fmt::Result {
+    for mount in vfs().mounts() {
+        let mode = if mount.flags.contains(MountFlags::READ_ONLY) {
+            "ro"
+        } else {
+            "rw"
+        };
+        writeln!(out, "{0} {1} {0} {2} 0 0", mount.fs_name, mount.path, mode)?;
+    }
+    Ok(())
+}
+
+fn uptime(out: &mut Snapshot) -> fmt::Result {
+    let uptime = Instant::now().duration_since(Instant::new(0));
+    writeln!(
+        out,
+        "{}.{:02}",
+        uptime.as_secs(),
+        uptime.subsec_millis() / 10
+    )
+}
+
+fn status(out: &mut Snapshot, tree: &ProcessTree, process: &Arc<Process>) -> fmt::Result {
+    let pid = process.pid();
+    let state = if process.is_terminating() {
+        "Z (terminating)"
+    } else {
+        "R (running)"
+    };
+    // the root process has no parent, like pid 1 on other systems
+    let ppid = tree.parent_of(pid).map_or(0, |&ppid| u64::from(ppid));
+    let threads = tree.threads(pid).map_or(0, |threads| threads.count());
+    let attributes = process.attributes();
+
+    writeln!(out, "Name:\t{}", process.name())?;
+    writeln!(out, "State:\t{state}")?;
+    writeln!(out, "Pid:\t{pid}")?;
+    writeln!(out, "PPid:\t{ppid}")?;
+    writeln!(out, "Uid:\t{}", attributes.uid)?;
+    writeln!(out, "Gid:\t{}", attributes.gid)?;
+    writeln!(out, "Threads:\t{threads}")
+}
+
+/// One line per vm object, with its address range, its flags (as in the
+/// process tree dump) and its name.
+fn maps(out: &mut Snapshot, _: &ProcessTree, process: &Arc<Process>) -> fmt::Result {
+    for vm_object in process.vmm().vm_objects().read().values() {
+        let start = vm_object.addr().as_u64();
+        writeln!(
+            out,
+            "{:016x}-{:016x} {} {}",
+            start,
+            start + vm_object.size() as u64,
+            page_table_flags_to_string(vm_object.flags()),
+            vm_object.name()
+        )?;
+    }
+    Ok(())
+}
+
+/// Reads the configuration space of the device at `offset` into `buf`.
+fn read_pci_config(address: PciAddress, buf: &mut [u8], offset: usize) -> usize {
+    let size = usize::from(address.config_space_size());
+    if offset >= size {
+        return 0;
+    }
+    let len = buf.len().min(size - offset);
+    for (i, byte) in buf[..len].iter_mut().enumerate() {
+        *byte = address.read_config((offset + i) as u16);
+    }
+    len
+}
+
+/// The addresses of all PCI devices, one per line. These are the names of
+/// the directories in `/proc/pci`.
+fn pci_devices(out: &mut Snapshot) -> fmt::Result {
+    for device in pci::devices() {
+        writeln!(out, "{}", device.lock().address())?;
+    }
+    Ok(())
+}
+
+/// The class, subclass and programming interface, followed by the name of
+/// the class.
+fn pci_class(out: &mut Snapshot, device: &PciDevice) -> fmt::Result {
+    writeln!(
+        out,
+        "{:02x}{:02x}{:02x} {}",
+        device.class,
+        device.subclass,
+        device.prog,
+        device.class_name()
+    )
+}
+
+fn pci_device_id(out: &mut Snapshot, device: &PciDevice) -> fmt::Result {
+    writeln!(out, "{:04x}", device.device_id)
+}
+
+fn pci_vendor_id(out: &mut Snapshot, device: &PciDevice) -> fmt::Result {
+    writeln!(out, "{:04x}", device.vendor_id)
+}
+
+/// The name of the driver that the device is bound to, empty if there is
+/// none.
+fn pci_driver(out: &mut Snapshot, device: &PciDevice) -> fmt::Result {
+    match device.driver() {
+        Some(driver) => writeln!(out, "{driver}"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::string::{String, ToString};
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use kernel_api::syscall::Stat;
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::path::Path;
+    use crate::io::vfs::procfs::ProcFs;
+    use crate::io::vfs::{vfs, FileSystem, FileType, FsId, VfsError};
+    use crate::process;
+
+    #[kernel_test]
+    fn test_root_lists_processes() {
+        let mut fs = ProcFs::new(FsId::new());
+        let root = fs.root().unwrap();
+        let (entries, _) = fs.read_dir(root, 0).unwrap();
+        let pid = process::current().pid().to_string();
+        assert!(entries
+            .iter()
+            .any(|entry| entry.name == pid && entry.typ == FileType::Directory));
+        assert!(entries
+            .iter()
+            .any(|entry| entry.name == "self" && entry.typ == FileType::SymbolicLink));
+        assert!(entries.iter().any(|entry| entry.name == "meminfo"));
+
+        // the cookie continues where the previous call stopped
+        let (rest, _) = fs.read_dir(root, 1).unwrap();
+        assert_eq!(entries.len() - 1, rest.len());
+        assert_eq!(entries[1].name, rest[0].name);
+        fs.close(root).unwrap();
+
+        assert!(matches!(
+            fs.open(Path::new("/not_a_pid")),
+            Err(VfsError::NoSuchFile)
+        ));
+    }
+
+    #[kernel_test]
+    fn test_status_is_a_snapshot() {
+        let mut fs = ProcFs::new(FsId::new());
+        let pid = process::current().pid().to_string();
+        let handle = fs
+            .open(Path::new(&alloc::format!("/{pid}/status")))
+            .unwrap();
+        let mut stat = Stat::default();
+        fs.stat(handle, &mut stat).unwrap();
+        assert!(stat.mode.is_regular_file());
+
+        // read in small pieces, which must fit together
+        let mut content = vec![0_u8; stat.size as usize];
+        let mut offset = 0;
+        while offset < content.len() {
+            let end = (offset + 7).min(content.len());
+            offset += fs.read(handle, &mut content[offset..end], offset).unwrap();
+        }
+        assert_eq!(0, fs.read(handle, &mut [0; 8], offset).unwrap());
+        fs.close(handle).unwrap();
+
+        let content = String::from_utf8(content).unwrap();
+        assert!(content.contains(&alloc::format!("\nPid:\t{pid}\n")));
+        assert!(content.contains(&alloc::format!("Name:\t{}\n", process::current().name())));
+    }
+
+    fn read_all(fs: &mut ProcFs, path: &str) -> Vec<u8> {
+        let handle = fs.open(Path::new(path)).unwrap();
+        let mut content = vec![0_u8; 4096];
+        let len = fs.read(handle, &mut content, 0).unwrap();
+        content.truncate(len);
+        fs.close(handle).unwrap();
+        content
+    }
+
+    #[kernel_test]
+    fn test_pci_devices() {
+        let mut fs = ProcFs::new(FsId::new());
+        let devices = String::from_utf8(read_all(&mut fs, "/pci/devices")).unwrap();
+        // there is at least the host bridge
+        let address = devices.lines().next().unwrap();
+        let handle = fs.open(Path::new("/pci")).unwrap();
+        let (entries, _) = fs.read_dir(handle, 0).unwrap();
+        fs.close(handle).unwrap();
+        assert!(entries
+            .iter()
+            .any(|entry| entry.name == address && entry.typ == FileType::Directory));
+
+        let vendor =
+            String::from_utf8(read_all(&mut fs, &alloc::format!("/pci/{address}/vendor"))).unwrap();
+        let vendor = u16::from_str_radix(vendor.trim_end(), 16).unwrap();
+        let class =
+            String::from_utf8(read_all(&mut fs, &alloc::format!("/pci/{address}/class"))).unwrap();
+        let (code, _) = class.split_once(' ').unwrap();
+
+        let config = read_all(&mut fs, &alloc::format!("/pci/{address}/config"));
+        assert!(config.len() == 256 || config.len() == 4096);
+        assert_eq!(vendor, u16::from_le_bytes([config[0], config[1]]));
+        assert_eq!(
+            alloc::format!(
+                "{:02x}{:02x}{:02x}",
+                config[0x0B],
+                config[0x0A],
+                config[0x09]
+            ),
+            code
+        );
+        // reads past the end of the configuration space are empty
+        let handle = fs
+            .open(Path::new(&alloc::format!("/pci/{address}/config")))
+            .unwrap();
+        assert_eq!(0, fs.read(handle, &mut [0; 4], config.len()).unwrap());
+        fs.close(handle).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_self_link() {
+        let link = vfs().open_nofollow("/proc/self").unwrap();
+        assert_eq!(
+            process::current().pid().to_string(),
+            vfs().readlink(&link).unwrap()
+        );
+
+        let mut stat = Stat::default();
+        vfs().stat_path("/proc/self/maps", &mut stat).unwrap();
+        assert!(stat.mode.is_regular_file());
+        assert_eq!(
+            Err(VfsError::PermissionDenied),
+            vfs().create("/proc/file", FileType::RegularFile)
+        );
+    }
+}
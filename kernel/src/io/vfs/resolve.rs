@@ -0,0 +1,108 @@
+use alloc::vec::Vec;
+
+use bitflags::bitflags;
+
+use crate::io::path::{Component, OwnedPath, Path};
+use crate::io::vfs::error::{Result, VfsError};
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+    pub struct ResolveFlags: u32 {
+        /// The path must not leave the start directory. Absolute paths and `..`
+        /// components that would go above the start directory are rejected
+        /// with [`VfsError::CrossDevice`].
+        const BENEATH = 0x01;
+    }
+}
+
+/// Resolves `path` relative to the absolute directory `start` and returns the
+/// resulting absolute path. Absolute paths are resolved from the root, unless
+/// [`ResolveFlags::BENEATH`] is given.
+///
+/// Resolution is purely textual. `.` components are dropped, and `..` removes
+/// the previous component. A `..` at the root stays at the root.
+pub fn resolve(start: &Path, path: &Path, flags: ResolveFlags) -> Result<OwnedPath> {
+    if path.is_empty() {
+        return Err(VfsError::NoSuchFile);
+    }
+
+    let beneath = flags.contains(ResolveFlags::BENEATH);
+    let absolute = matches!(path.components().next(), Some(Component::RootDir));
+    if absolute && beneath {
+        return Err(VfsError::CrossDevice);
+    }
+
+    let mut components = Vec::new();
+    if !absolute {
+        push_components(&mut components, start, 0, false)?;
+    }
+    let floor = if beneath { components.len() } else { 0 };
+    push_components(&mut components, path, floor, beneath)?;
+
+    let mut result = OwnedPath::from("/");
+    components.into_iter().for_each(|c| result.push(c));
+    Ok(result)
+}
+
+fn push_components<'a>(
+    components: &mut Vec<&'a str>,
+    path: &'a Path,
+    floor: usize,
+    beneath: bool,
+) -> Result<()> {
+    for component in path.components() {
+        match component {
+            Component::RootDir | Component::CurrentDir => {}
+            Component::ParentDir if components.len() > floor => {
+                components.pop();
+            }
+            Component::ParentDir if beneath => return Err(VfsError::CrossDevice),
+            Component::ParentDir => {}
+            Component::Normal(name) => components.push(name),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::string::{String, ToString};
+
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::path::Path;
+    use crate::io::vfs::{resolve, ResolveFlags, VfsError};
+
+    fn resolve_str(start: &str, path: &str, flags: ResolveFlags) -> Result<String, VfsError> {
+        resolve(Path::new(start), Path::new(path), flags).map(|p| p.to_string())
+    }
+
+    #[kernel_test]
+    fn test_resolve() {
+        for (start, path, expected) in [
+            ("/", "a", "/a"),
+            ("/a", "b/c", "/a/b/c"),
+            ("/a", "/b", "/b"),
+            ("/a/b", "..", "/a"),
+            ("/a/b", "../../..", "/"),
+            ("/a", "./b//c/", "/a/b/c"),
+            ("/", ".", "/"),
+        ] {
+            assert_eq!(
+                Ok(expected.to_string()),
+                resolve_str(start, path, ResolveFlags::empty())
+            );
+        }
+    }
+
+    #[kernel_test]
+    fn test_resolve_beneath() {
+        let flags = ResolveFlags::BENEATH;
+        assert_eq!(Ok("/a/c".to_string()), resolve_str("/a", "b/../c", flags));
+        assert_eq!(
+            Err(VfsError::CrossDevice),
+            resolve_str("/a", "../../etc", flags)
+        );
+        assert_eq!(Err(VfsError::CrossDevice), resolve_str("/a", "/etc", flags));
+    }
+}
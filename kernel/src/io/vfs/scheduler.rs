@@ -0,0 +1,485 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use filesystem::BlockDevice;
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use spin::Mutex;
+
+use crate::io::vfs::cache::{Flush, MultiBlock};
+
+/// The number of blocks after a sequential read that are read ahead.
+pub const READ_AHEAD_BLOCKS: usize = 32;
+
+/// The most bytes that are read ahead but not requested yet, over all
+/// streams. Every stream gets an equal share of this, so that one stream
+/// can't take the read-ahead away from the others.
+pub const MAX_SPECULATIVE_BYTES: usize = 256 * 1024;
+
+/// The number of sequential streams that are tracked at the same time, like
+/// files that are read concurrently.
+const MAX_STREAMS: usize = 4;
+
+/// Counters of an [`IoScheduler`] since it was created.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct IoSchedulerStats {
+    /// Reads and writes that were sent to the device.
+    pub commands: u64,
+    /// Read requests that were served entirely from blocks that an earlier
+    /// command read ahead, so that they didn't need a command of their own.
+    pub merged_requests: u64,
+    /// Blocks that were read ahead.
+    pub read_ahead_blocks: u64,
+    /// Blocks that were read ahead, and requested afterwards.
+    pub read_ahead_hits: u64,
+}
+
+/// Sits in front of a block device and turns sequential reads, which arrive
+/// one block or one run at a time, into fewer and larger commands.
+///
+/// Reads that continue where a previous read stopped form a stream. For every
+/// stream, the blocks after the last read block are read ahead, with the same
+/// command as the demand read if possible, and later reads are served from
+/// them. Random reads are passed through without reading ahead. Writes are
+/// passed through, and drop the read ahead blocks that they overwrite.
+///
+/// This is meant to be used below a [`BlockCache`](crate::io::vfs::cache::BlockCache),
+/// which holds the blocks once they are requested.
+pub struct IoScheduler<T> {
+    device: T,
+    state: Mutex<State>,
+    commands: AtomicU64,
+    merged_requests: AtomicU64,
+    read_ahead_blocks: AtomicU64,
+    read_ahead_hits: AtomicU64,
+}
+
+#[derive(Default)]
+struct State {
+    streams: Vec<Stream>,
+    /// The blocks that were read ahead and not requested yet.
+    prefetched: BTreeMap<usize, Vec<u8>>,
+    /// Incremented by every write, so that a read ahead that raced with a
+    /// write doesn't keep what it read before the write.
+    generation: u64,
+    clock: u64,
+}
+
+struct Stream {
+    /// The block after the last block that was requested.
+    next: usize,
+    /// The block after the last block that was read ahead.
+    ahead: usize,
+    last_use: u64,
+}
+
+impl<T> IoScheduler<T>
+where
+    T: MultiBlock,
+{
+    pub fn new(device: T) -> Self {
+        Self {
+            device,
+            state: Mutex::default(),
+            commands: AtomicU64::new(0),
+            merged_requests: AtomicU64::new(0),
+            read_ahead_blocks: AtomicU64::new(0),
+            read_ahead_hits: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> IoSchedulerStats {
+        IoSchedulerStats {
+            commands: self.commands.load(Relaxed),
+            merged_requests: self.merged_requests.load(Relaxed),
+            read_ahead_blocks: self.read_ahead_blocks.load(Relaxed),
+            read_ahead_hits: self.read_ahead_hits.load(Relaxed),
+        }
+    }
+
+    /// Reads the blocks, and the blocks to read ahead after them, with a
+    /// single command if they are adjacent.
+    fn read_with_ahead(
+        &self,
+        start: usize,
+        bufs: &mut [&mut [u8]],
+        ahead: Range<usize>,
+        generation: u64,
+    ) -> Result<(), T::Error> {
+        let block_size = self.device.sector_size();
+        let mut ahead_blocks = vec![vec![0_u8; block_size]; ahead.len()];
+        let merged = !bufs.is_empty() && start + bufs.len() == ahead.start;
+
+        if merged {
+            let mut all = bufs
+                .iter_mut()
+                .map(|buf| &mut **buf)
+                .chain(ahead_blocks.iter_mut().map(Vec::as_mut_slice))
+                .collect::<Vec<_>>();
+            self.commands.fetch_add(1, Relaxed);
+            self.device.read_blocks(start, &mut all)?;
+        } else {
+            if !bufs.is_empty() {
+                self.commands.fetch_add(1, Relaxed);
+                self.device.read_blocks(start, bufs)?;
+            }
+            if !ahead.is_empty() {
+                let mut all = ahead_blocks
+                    .iter_mut()
+                    .map(Vec::as_mut_slice)
+                    .collect::<Vec<_>>();
+                self.commands.fetch_add(1, Relaxed);
+                // reading ahead is only a guess, so it may fail
+                if self.device.read_blocks(ahead.start, &mut all).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+
+        if ahead.is_empty() {
+            return Ok(());
+        }
+        self.read_ahead_blocks
+            .fetch_add(ahead.len() as u64, Relaxed);
+        let mut state = self.state.lock();
+        if state.generation == generation {
+            state.prefetched.extend(ahead.zip(ahead_blocks));
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    /// Records the read of the given blocks, and returns the blocks that
+    /// should be read ahead after it.
+    fn read_ahead(
+        &mut self,
+        blocks: Range<usize>,
+        block_size: usize,
+        block_count: usize,
+    ) -> Range<usize> {
+        self.clock += 1;
+        let now = self.clock;
+
+        // reads that land in what we read ahead continue the stream, even if
+        // the blocks in between were not requested, because the cache above
+        // us had them
+        let Some(index) = self
+            .streams
+            .iter()
+            .position(|stream| (stream.next..=stream.ahead).contains(&blocks.start))
+        else {
+            if self.streams.len() == MAX_STREAMS {
+                let (oldest, _) = self
+                    .streams
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, stream)| stream.last_use)
+                    .unwrap();
+                let stream = self.streams.swap_remove(oldest);
+                self.drop_prefetched(stream.next..stream.ahead);
+            }
+            self.streams.push(Stream {
+                next: blocks.end,
+                ahead: blocks.end,
+                last_use: now,
+            });
+            return 0..0;
+        };
+
+        let skipped = self.streams[index].next..blocks.start;
+        self.drop_prefetched(skipped);
+        let stream_count = self.streams.len();
+        let stream = &mut self.streams[index];
+        stream.next = blocks.end;
+        stream.ahead = stream.ahead.max(blocks.end);
+        stream.last_use = now;
+        // read ahead in batches, not one block after every read
+        let own = stream.ahead - stream.next;
+        if own >= READ_AHEAD_BLOCKS / 2 {
+            return 0..0;
+        }
+
+        let budget = MAX_SPECULATIVE_BYTES / block_size;
+        let share = budget / stream_count;
+        let count = (READ_AHEAD_BLOCKS - own)
+            .min(share.saturating_sub(own))
+            .min(budget.saturating_sub(self.prefetched.len()))
+            .min(block_count.saturating_sub(stream.ahead));
+        let ahead = stream.ahead..stream.ahead + count;
+        stream.ahead = ahead.end;
+        ahead
+    }
+
+    fn drop_prefetched(&mut self, blocks: Range<usize>) {
+        if blocks.is_empty() {
+            return;
+        }
+        let dropped = self
+            .prefetched
+            .range(blocks)
+            .map(|(&block, _)| block)
+            .collect::<Vec<_>>();
+        for block in dropped {
+            self.prefetched.remove(&block);
+        }
+    }
+}
+
+impl<T> BlockDevice for IoScheduler<T>
+where
+    T: MultiBlock,
+{
+    type Error = T::Error;
+
+    fn sector_size(&self) -> usize {
+        self.device.sector_size()
+    }
+
+    fn sector_count(&self) -> usize {
+        self.device.sector_count()
+    }
+
+    fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_blocks(sector_index, &mut [buf])
+    }
+
+    fn write_sector(&mut self, sector_index: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_blocks(sector_index, &[buf])
+    }
+}
+
+impl<T> MultiBlock for IoScheduler<T>
+where
+    T: MultiBlock,
+{
+    fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, Self::Error> {
+        let len = bufs.iter().map(|buf| buf.len()).sum();
+        let (hit, ahead, generation) = {
+            let mut state = self.state.lock();
+            let hit = bufs
+                .iter_mut()
+                .enumerate()
+                .map(|(i, buf)| match state.prefetched.remove(&(start + i)) {
+                    Some(data) => {
+                        buf.copy_from_slice(&data);
+                        true
+                    }
+                    None => false,
+                })
+                .collect::<Vec<_>>();
+            let hits = hit.iter().filter(|&&hit| hit).count();
+            self.read_ahead_hits.fetch_add(hits as u64, Relaxed);
+            if hits > 0 && hits == bufs.len() {
+                self.merged_requests.fetch_add(1, Relaxed);
+            }
+            let ahead = state.read_ahead(
+                start..start + bufs.len(),
+                self.device.sector_size(),
+                self.device.sector_count(),
+            );
+            (hit, ahead, state.generation)
+        };
+
+        // the rest is read in runs of missing blocks, and the read ahead goes
+        // with the last run if that ends the request
+        let mut ahead = Some(ahead);
+        let mut i = 0;
+        while i < bufs.len() {
+            if hit[i] {
+                i += 1;
+                continue;
+            }
+            let run = hit[i..].iter().take_while(|&&hit| !hit).count();
+            let run_bufs = &mut bufs[i..i + run];
+            if i + run == hit.len() {
+                let ahead = ahead.take().unwrap();
+                self.read_with_ahead(start + i, run_bufs, ahead, generation)?;
+            } else {
+                self.commands.fetch_add(1, Relaxed);
+                self.device.read_blocks(start + i, run_bufs)?;
+            }
+            i += run;
+        }
+        if let Some(ahead) = ahead {
+            self.read_with_ahead(start + bufs.len(), &mut [], ahead, generation)?;
+        }
+        Ok(len)
+    }
+
+    fn write_blocks(&mut self, start: usize, bufs: &[&[u8]]) -> Result<usize, Self::Error> {
+        {
+            let mut state = self.state.lock();
+            state.generation += 1;
+            state.drop_prefetched(start..start + bufs.len());
+        }
+        self.commands.fetch_add(1, Relaxed);
+        self.device.write_blocks(start, bufs)
+    }
+
+    /// Takes the blocks that were read ahead, and reads the rest with
+    /// [`MultiBlock::read_blocks_async`] of the device, one command per run
+    /// of missing blocks. Asynchronous reads don't read ahead themselves.
+    fn read_blocks_async<'a>(
+        &'a self,
+        start: usize,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, Result<usize, Self::Error>> {
+        async move {
+            let block_size = self.device.sector_size();
+            let mut state = self.state.lock();
+            let hit = buf
+                .chunks_mut(block_size)
+                .enumerate()
+                .map(|(i, block)| match state.prefetched.remove(&(start + i)) {
+                    Some(data) => {
+                        block.copy_from_slice(&data);
+                        true
+                    }
+                    None => false,
+                })
+                .collect::<Vec<_>>();
+            drop(state);
+            let hits = hit.iter().filter(|&&hit| hit).count();
+            self.read_ahead_hits.fetch_add(hits as u64, Relaxed);
+
+            let mut i = 0;
+            while i < hit.len() {
+                if hit[i] {
+                    i += 1;
+                    continue;
+                }
+                let run = hit[i..].iter().take_while(|&&hit| !hit).count();
+                self.commands.fetch_add(1, Relaxed);
+                self.device
+                    .read_blocks_async(start + i, &mut buf[i * block_size..(i + run) * block_size])
+                    .await?;
+                i += run;
+            }
+            Ok(buf.len())
+        }
+        .boxed()
+    }
+}
+
+impl<T> Flush for IoScheduler<T>
+where
+    T: Flush,
+{
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.device.flush()
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+
+    use filesystem::BlockDevice;
+    use kernel_test_framework::kernel_test;
+    use spin::Mutex;
+
+    use crate::io::vfs::cache::MultiBlock;
+    use crate::io::vfs::scheduler::IoScheduler;
+
+    const BLOCKS: usize = 2048;
+
+    /// A device whose blocks are filled with their index, and that records
+    /// the start and length of every command.
+    #[derive(Clone, Default)]
+    struct Recording {
+        commands: Arc<Mutex<Vec<(usize, usize)>>>,
+    }
+
+    fn content(block: usize) -> u8 {
+        (block % 251) as u8
+    }
+
+    impl BlockDevice for Recording {
+        type Error = ();
+
+        fn sector_size(&self) -> usize {
+            512
+        }
+
+        fn sector_count(&self) -> usize {
+            BLOCKS
+        }
+
+        fn read_sector(&self, sector_index: usize, buf: &mut [u8]) -> Result<usize, ()> {
+            self.read_blocks(sector_index, &mut [buf])
+        }
+
+        fn write_sector(&mut self, _: usize, _: &[u8]) -> Result<usize, ()> {
+            Err(())
+        }
+    }
+
+    impl MultiBlock for Recording {
+        fn read_blocks(&self, start: usize, bufs: &mut [&mut [u8]]) -> Result<usize, ()> {
+            assert!(start + bufs.len() <= BLOCKS);
+            self.commands.lock().push((start, bufs.len()));
+            for (i, buf) in bufs.iter_mut().enumerate() {
+                buf.fill(content(start + i));
+            }
+            Ok(bufs.len() * 512)
+        }
+    }
+
+    #[kernel_test]
+    fn test_interleaved_streams_are_merged() {
+        let device = Recording::default();
+        let scheduler = IoScheduler::new(device.clone());
+
+        // two files that are read block by block at the same time
+        let streams = [0, 1000];
+        let mut buf = [0_u8; 512];
+        for i in 0..64 {
+            for first in streams {
+                let block = first + i;
+                assert_eq!(Ok(512), scheduler.read_sector(block, &mut buf));
+                assert_eq!([content(block); 512], buf, "block {block} differs");
+            }
+        }
+
+        let commands = device.commands.lock();
+        assert!(commands.len() < 20, "{} commands", commands.len());
+        assert_eq!(commands.len() as u64, scheduler.stats().commands);
+        // both streams are read ahead, neither starves the other
+        for first in streams {
+            assert!(commands
+                .iter()
+                .any(|&(start, len)| (first..first + 64).contains(&start) && len > 1));
+        }
+        let stats = scheduler.stats();
+        assert!(stats.merged_requests > 100);
+        assert!(stats.read_ahead_hits >= stats.merged_requests);
+        assert!(stats.read_ahead_blocks >= stats.read_ahead_hits);
+    }
+
+    #[kernel_test]
+    fn test_random_access_is_not_read_ahead() {
+        let device = Recording::default();
+        let scheduler = IoScheduler::new(device.clone());
+
+        let blocks = [50, 10, 90, 30, 70, 20, 60, 1500, 5];
+        let mut buf = [0_u8; 512];
+        for block in blocks {
+            assert_eq!(Ok(512), scheduler.read_sector(block, &mut buf));
+            assert_eq!([content(block); 512], buf);
+        }
+
+        assert_eq!(
+            blocks.map(|block| (block, 1)).as_slice(),
+            device.commands.lock().as_slice()
+        );
+        let stats = scheduler.stats();
+        assert_eq!(0, stats.read_ahead_blocks);
+        assert_eq!(0, stats.merged_requests);
+    }
+}
@@ -0,0 +1,799 @@
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use foundation::falloc::vec::FVec;
+
+use kernel_api::syscall::{FileMode, Stat};
+
+use crate::io::path::{Component, Path};
+use crate::io::vfs::error::{Result, VfsError};
+use crate::io::vfs::{DirEntry, FileSystem, FileType, FsId, VfsHandle};
+
+static HANDLE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn next_handle() -> VfsHandle {
+    VfsHandle::new(HANDLE_COUNTER.fetch_add(1, Relaxed))
+}
+
+type Ino = u64;
+
+const ROOT_INO: Ino = 1;
+
+/// File data is stored in chunks of one page, so that large files don't need
+/// large contiguous allocations.
+const CHUNK_SIZE: usize = 4096;
+
+/// The number of bytes of file data that a [`TmpFs`] can hold, unless it is
+/// created with [`TmpFs::with_capacity`].
+pub const DEFAULT_CAPACITY: usize = 2 * 1024 * 1024; // 2 MiB
+
+type Chunk = [u8; CHUNK_SIZE];
+
+/// Keeps track of the memory that the file data of a [`TmpFs`] takes up.
+struct Usage {
+    used: usize,
+    capacity: usize,
+}
+
+impl Usage {
+    fn available(&self) -> usize {
+        self.capacity - self.used
+    }
+}
+
+/// The content of a regular file. Chunks that have never been written to are
+/// `None` and read as zeros. Bytes past the end of the file are always zero,
+/// so that they don't reappear if the file grows again.
+#[derive(Default)]
+struct FileData {
+    len: usize,
+    chunks: FVec<Option<Box<Chunk>>>,
+}
+
+impl FileData {
+    /// The number of bytes that are allocated for the chunks of this file.
+    fn allocated(&self) -> usize {
+        self.chunks.iter().filter(|chunk| chunk.is_some()).count() * CHUNK_SIZE
+    }
+
+    fn read(&self, buf: &mut [u8], offset: usize) -> usize {
+        if offset >= self.len {
+            return 0;
+        }
+        let len = buf.len().min(self.len - offset);
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done;
+            let start = pos % CHUNK_SIZE;
+            let n = (CHUNK_SIZE - start).min(len - done);
+            let dst = &mut buf[done..done + n];
+            match self.chunks.get(pos / CHUNK_SIZE) {
+                Some(Some(chunk)) => dst.copy_from_slice(&chunk[start..start + n]),
+                _ => dst.fill(0),
+            }
+            done += n;
+        }
+        len
+    }
+
+    fn write(&mut self, usage: &mut Usage, buf: &[u8], offset: usize) -> Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let end = offset
+            .checked_add(buf.len())
+            .ok_or(VfsError::FileTooLarge)?;
+        let first = offset / CHUNK_SIZE;
+        let last = (end - 1) / CHUNK_SIZE;
+
+        // check the capacity before anything is allocated, so that a write
+        // that doesn't fit doesn't change the file
+        let missing = (first..=last)
+            .filter(|&index| !matches!(self.chunks.get(index), Some(Some(_))))
+            .count();
+        if missing * CHUNK_SIZE > usage.available() {
+            return Err(VfsError::NoSpace);
+        }
+
+        if self.chunks.len() <= last {
+            self.chunks
+                .try_reserve(last + 1 - self.chunks.len())
+                .map_err(|_| VfsError::NoSpace)?;
+            while self.chunks.len() <= last {
+                let _ = self.chunks.try_push(None);
+            }
+        }
+        for slot in &mut self.chunks[first..=last] {
+            if slot.is_none() {
+                *slot = Some(Box::try_new([0; CHUNK_SIZE]).map_err(|_| VfsError::NoSpace)?);
+                usage.used += CHUNK_SIZE;
+            }
+        }
+
+        let mut done = 0;
+        while done < buf.len() {
+            let pos = offset + done;
+            let start = pos % CHUNK_SIZE;
+            let n = (CHUNK_SIZE - start).min(buf.len() - done);
+            let chunk = self.chunks[pos / CHUNK_SIZE].as_mut().unwrap();
+            chunk[start..start + n].copy_from_slice(&buf[done..done + n]);
+            done += n;
+        }
+        self.len = self.len.max(end);
+        Ok(buf.len())
+    }
+
+    /// Changes the length of the file. Growing the file only adds a hole, so
+    /// this doesn't allocate.
+    fn truncate(&mut self, usage: &mut Usage, size: usize) {
+        if size < self.len {
+            let keep = size.div_ceil(CHUNK_SIZE);
+            while self.chunks.len() > keep {
+                if self.chunks.pop().flatten().is_some() {
+                    usage.used -= CHUNK_SIZE;
+                }
+            }
+            if let Some(Some(chunk)) = self.chunks.get_mut(size / CHUNK_SIZE) {
+                chunk[size % CHUNK_SIZE..].fill(0);
+            }
+        }
+        self.len = size;
+    }
+}
+
+enum Node {
+    Directory,
+    File(FileData),
+}
+
+struct Inode {
+    node: Node,
+    /// The permission bits of the inode.
+    mode: FileMode,
+    /// The number of paths that refer to this inode.
+    links: usize,
+    /// The number of open handles that refer to this inode.
+    open: usize,
+}
+
+/// A file system that lives entirely in memory.
+///
+/// Paths are stored normalized, which is relative to the mount point without
+/// leading, trailing or duplicate separators. The root directory is the empty
+/// path and always exists. Each path refers to an inode, and handles refer to
+/// inodes directly, so that an open file stays usable after it has been removed
+/// or replaced. An inode is freed once it has neither paths nor open handles.
+///
+/// The file data that a tmpfs holds is limited by its capacity, writes that
+/// would exceed it fail with [`VfsError::NoSpace`]. Directories and the
+/// bookkeeping of the file system don't count towards the capacity.
+pub struct TmpFs {
+    fsid: FsId,
+    next_ino: Ino,
+    paths: BTreeMap<String, Ino>,
+    inodes: BTreeMap<Ino, Inode>,
+    handles: BTreeMap<VfsHandle, Ino>,
+    usage: Usage,
+}
+
+impl TmpFs {
+    pub fn new(fsid: FsId) -> Self {
+        Self::with_capacity(fsid, DEFAULT_CAPACITY)
+    }
+
+    /// Creates a tmpfs that holds at most `capacity` bytes of file data.
+    pub fn with_capacity(fsid: FsId, capacity: usize) -> Self {
+        let mut paths = BTreeMap::new();
+        paths.insert(String::new(), ROOT_INO);
+        let mut inodes = BTreeMap::new();
+        inodes.insert(
+            ROOT_INO,
+            Inode {
+                node: Node::Directory,
+                mode: FileMode::S_IRWXU,
+                links: 1,
+                open: 0,
+            },
+        );
+        Self {
+            fsid,
+            next_ino: ROOT_INO + 1,
+            paths,
+            inodes,
+            handles: BTreeMap::new(),
+            usage: Usage { used: 0, capacity },
+        }
+    }
+
+    fn normalize(path: &Path) -> Result<String> {
+        let mut normalized = String::new();
+        for component in path.components() {
+            match component {
+                Component::RootDir | Component::CurrentDir => {}
+                Component::ParentDir => return Err(VfsError::Unsupported),
+                Component::Normal(name) => {
+                    if !normalized.is_empty() {
+                        normalized.push('/');
+                    }
+                    normalized.push_str(name);
+                }
+            }
+        }
+        Ok(normalized)
+    }
+
+    fn parent_of(path: &str) -> &str {
+        path.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+
+    /// Returns whether `path` is `ancestor` or somewhere below it.
+    fn is_within(path: &str, ancestor: &str) -> bool {
+        ancestor.is_empty()
+            || path == ancestor
+            || path
+                .strip_prefix(ancestor)
+                .is_some_and(|rest| rest.starts_with('/'))
+    }
+
+    fn inode_at(&self, path: &str) -> Option<&Inode> {
+        self.paths.get(path).and_then(|ino| self.inodes.get(ino))
+    }
+
+    fn node(&self, handle: VfsHandle) -> Result<&Node> {
+        let ino = self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+        Ok(&self.inodes.get(ino).ok_or(VfsError::HandleClosed)?.node)
+    }
+
+    /// Returns the data of the regular file behind the given handle, together
+    /// with the usage of the file system, which changes with the data.
+    fn file_mut(&mut self, handle: VfsHandle) -> Result<(&mut FileData, &mut Usage)> {
+        let ino = self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+        match &mut self.inodes.get_mut(ino).ok_or(VfsError::HandleClosed)?.node {
+            Node::Directory => Err(VfsError::IsDirectory),
+            Node::File(data) => Ok((data, &mut self.usage)),
+        }
+    }
+
+    fn children<'a>(&'a self, path: &'a str) -> impl Iterator<Item = (&'a String, &'a Ino)> {
+        self.paths
+            .iter()
+            .filter(move |(p, _)| !p.is_empty() && Self::parent_of(p) == path)
+    }
+
+    fn is_empty_dir(&self, path: &str) -> bool {
+        self.children(path).next().is_none()
+    }
+
+    /// Checks that the parent of `path` exists and is a directory.
+    fn check_parent(&self, path: &str) -> Result<()> {
+        match self.inode_at(Self::parent_of(path)) {
+            None => Err(VfsError::NoSuchFile),
+            Some(Inode {
+                node: Node::File(_),
+                ..
+            }) => Err(VfsError::NotDirectory),
+            Some(_) => Ok(()),
+        }
+    }
+
+    fn open_ino(&mut self, ino: Ino) -> VfsHandle {
+        self.inodes.get_mut(&ino).unwrap().open += 1;
+        let handle = next_handle();
+        self.handles.insert(handle, ino);
+        handle
+    }
+
+    /// Returns the current path of the directory behind the given handle.
+    /// Directories can't be hard linked, so there is at most one such path.
+    fn directory_path(&self, handle: VfsHandle) -> Result<String> {
+        let ino = *self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+        if let Node::File(_) = self.inodes[&ino].node {
+            return Err(VfsError::NotDirectory);
+        }
+        self.paths
+            .iter()
+            .find(|(_, &i)| i == ino)
+            .map(|(path, _)| path.clone())
+            // the directory has been removed while it was open
+            .ok_or(VfsError::NoSuchFile)
+    }
+
+    /// Removes the given path and frees its inode if nothing else refers to it.
+    fn unlink(&mut self, path: &str) {
+        let Some(ino) = self.paths.remove(path) else {
+            return;
+        };
+        self.inodes.get_mut(&ino).unwrap().links -= 1;
+        self.free_if_unused(ino);
+    }
+
+    /// Frees the inode and its data if it has neither paths nor open handles.
+    fn free_if_unused(&mut self, ino: Ino) {
+        let inode = &self.inodes[&ino];
+        if inode.links > 0 || inode.open > 0 {
+            return;
+        }
+        if let Some(Inode {
+            node: Node::File(data),
+            ..
+        }) = self.inodes.remove(&ino)
+        {
+            self.usage.used -= data.allocated();
+        }
+    }
+}
+
+impl FileSystem for TmpFs {
+    fn fsid(&self) -> FsId {
+        self.fsid
+    }
+
+    fn name(&self) -> &'static str {
+        "tmpfs"
+    }
+
+    fn root(&mut self) -> Result<VfsHandle> {
+        Ok(self.open_ino(ROOT_INO))
+    }
+
+    fn lookup(&mut self, parent: VfsHandle, name: &str) -> Result<VfsHandle> {
+        let parent = self.directory_path(parent)?;
+        let path = if parent.is_empty() {
+            name.to_string()
+        } else {
+            format!("{parent}/{name}")
+        };
+        let ino = *self.paths.get(&path).ok_or(VfsError::NoSuchFile)?;
+        Ok(self.open_ino(ino))
+    }
+
+    fn close(&mut self, handle: VfsHandle) -> Result<()> {
+        let ino = self.handles.remove(&handle).ok_or(VfsError::HandleClosed)?;
+        self.inodes.get_mut(&ino).unwrap().open -= 1;
+        self.free_if_unused(ino);
+        Ok(())
+    }
+
+    fn read_dir(&mut self, handle: VfsHandle, cookie: u64) -> Result<(FVec<DirEntry>, u64)> {
+        let path = self.directory_path(handle)?;
+
+        // the cookie is the number of children that have already been read
+        let mut entries = FVec::new();
+        entries
+            .try_extend(
+                self.children(&path)
+                    .skip(cookie as usize)
+                    .map(|(p, ino)| DirEntry {
+                        name: p.rsplit('/').next().unwrap_or(p).to_string(),
+                        typ: match self.inodes[ino].node {
+                            Node::Directory => FileType::Directory,
+                            Node::File(_) => FileType::RegularFile,
+                        },
+                    }),
+            )
+            .map_err(|_| VfsError::NoSpace)?;
+        let next_cookie = cookie + entries.len() as u64;
+        Ok((entries, next_cookie))
+    }
+
+    fn read(&mut self, handle: VfsHandle, buf: &mut [u8], offset: usize) -> Result<usize> {
+        match self.node(handle)? {
+            Node::Directory => Err(VfsError::IsDirectory),
+            Node::File(data) => Ok(data.read(buf, offset)),
+        }
+    }
+
+    fn write(&mut self, handle: VfsHandle, buf: &[u8], offset: usize) -> Result<usize> {
+        let (data, usage) = self.file_mut(handle)?;
+        data.write(usage, buf, offset)
+    }
+
+    fn truncate(&mut self, handle: VfsHandle, size: usize) -> Result<()> {
+        let (data, usage) = self.file_mut(handle)?;
+        data.truncate(usage, size);
+        Ok(())
+    }
+
+    fn stat(&mut self, handle: VfsHandle, stat: &mut Stat) -> Result<()> {
+        // TODO: uid, gid, times
+
+        let ino = *self.handles.get(&handle).ok_or(VfsError::HandleClosed)?;
+        let inode = self.inodes.get(&ino).ok_or(VfsError::HandleClosed)?;
+        match &inode.node {
+            Node::Directory => {
+                stat.mode = FileMode::S_IFDIR | inode.mode;
+                stat.size = 0;
+                stat.blocks = 0;
+            }
+            Node::File(data) => {
+                stat.mode = FileMode::S_IFREG | inode.mode;
+                stat.size = data.len as u64;
+                stat.blocks = (data.allocated() / 512) as u64;
+            }
+        }
+        stat.dev = self.fsid.0;
+        stat.ino = ino;
+        stat.nlink = inode.links as u32;
+        stat.blksize = CHUNK_SIZE as u64;
+        Ok(())
+    }
+
+    fn create(&mut self, path: &Path, ftype: FileType, mode: FileMode) -> Result<()> {
+        let path = Self::normalize(path)?;
+        if self.paths.contains_key(&path) {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.check_parent(&path)?;
+
+        let node = match ftype {
+            FileType::RegularFile => Node::File(FileData::default()),
+            FileType::Directory => Node::Directory,
+            _ => return Err(VfsError::Unsupported),
+        };
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.inodes.insert(
+            ino,
+            Inode {
+                node,
+                mode,
+                links: 1,
+                open: 0,
+            },
+        );
+        self.paths.insert(path, ino);
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &Path) -> Result<()> {
+        let path = Self::normalize(path)?;
+        if path.is_empty() {
+            // this is the mount point
+            return Err(VfsError::Busy);
+        }
+        match self.inode_at(&path) {
+            None => return Err(VfsError::NoSuchFile),
+            Some(Inode {
+                node: Node::Directory,
+                ..
+            }) if !self.is_empty_dir(&path) => return Err(VfsError::NotEmpty),
+            Some(_) => {}
+        }
+        self.unlink(&path);
+        Ok(())
+    }
+
+    fn link(&mut self, existing: &Path, to: &Path) -> Result<()> {
+        let existing = Self::normalize(existing)?;
+        let to = Self::normalize(to)?;
+        let ino = *self.paths.get(&existing).ok_or(VfsError::NoSuchFile)?;
+        if matches!(self.inodes[&ino].node, Node::Directory) {
+            return Err(VfsError::PermissionDenied);
+        }
+        if self.paths.contains_key(&to) {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.check_parent(&to)?;
+
+        self.inodes.get_mut(&ino).unwrap().links += 1;
+        self.paths.insert(to, ino);
+        Ok(())
+    }
+
+    fn rename(&mut self, from: &Path, to: &Path) -> Result<()> {
+        let from = Self::normalize(from)?;
+        let to = Self::normalize(to)?;
+        if from.is_empty() || to.is_empty() {
+            return Err(VfsError::Busy);
+        }
+
+        let from_is_dir = match self.inode_at(&from) {
+            None => return Err(VfsError::NoSuchFile),
+            Some(inode) => matches!(inode.node, Node::Directory),
+        };
+        if from == to {
+            return Ok(());
+        }
+        if from_is_dir && Self::is_within(&to, &from) {
+            // can't move a directory into itself
+            return Err(VfsError::InvalidArgument);
+        }
+        self.check_parent(&to)?;
+
+        if let Some(target) = self.inode_at(&to) {
+            match (from_is_dir, &target.node) {
+                (true, Node::File(_)) => return Err(VfsError::NotDirectory),
+                (false, Node::Directory) => return Err(VfsError::IsDirectory),
+                (true, Node::Directory) if !self.is_empty_dir(&to) => {
+                    return Err(VfsError::NotEmpty)
+                }
+                _ => {}
+            }
+            self.unlink(&to);
+        }
+
+        // move the node itself and, for directories, everything below it
+        let moved = self
+            .paths
+            .keys()
+            .filter(|p| Self::is_within(p, &from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for old in moved {
+            let ino = self.paths.remove(&old).unwrap();
+            let new = format!("{}{}", to, &old[from.len()..]);
+            self.paths.insert(new, ino);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+
+    use kernel_api::syscall::{FileMode, Stat};
+    use kernel_test_framework::kernel_test;
+
+    use crate::io::path::Path;
+    use crate::io::vfs::tmpfs::{TmpFs, CHUNK_SIZE};
+    use crate::io::vfs::{FileSystem, FileType, FsId, VfsError};
+
+    const MODE: FileMode = FileMode::from_bits_retain(0o640);
+
+    fn create_file(fs: &mut TmpFs, path: &str, content: &[u8]) {
+        fs.create(Path::new(path), FileType::RegularFile, MODE)
+            .unwrap();
+        let handle = fs.open(Path::new(path)).unwrap();
+        fs.write(handle, content, 0).unwrap();
+        fs.close(handle).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_rename_replaces_target() {
+        let mut fs = TmpFs::new(FsId::new());
+        create_file(&mut fs, "/new", b"new");
+        create_file(&mut fs, "/old", b"old");
+
+        let old_handle = fs.open(Path::new("/old")).unwrap();
+        let mut stat = Stat::default();
+        fs.stat(old_handle, &mut stat).unwrap();
+        let old_ino = stat.ino;
+
+        fs.rename(Path::new("/new"), Path::new("/old")).unwrap();
+        assert!(matches!(
+            fs.open(Path::new("/new")),
+            Err(VfsError::NoSuchFile)
+        ));
+
+        // the open handle still refers to the replaced file
+        let mut buf = vec![0_u8; 3];
+        assert_eq!(3, fs.read(old_handle, &mut buf, 0).unwrap());
+        assert_eq!(b"old", buf.as_slice());
+        assert!(fs.inodes.contains_key(&old_ino));
+
+        let new_handle = fs.open(Path::new("/old")).unwrap();
+        assert_eq!(3, fs.read(new_handle, &mut buf, 0).unwrap());
+        assert_eq!(b"new", buf.as_slice());
+        fs.close(new_handle).unwrap();
+
+        // the data of the replaced file is gone once the last handle is closed
+        fs.close(old_handle).unwrap();
+        assert!(!fs.inodes.contains_key(&old_ino));
+    }
+
+    #[kernel_test]
+    fn test_rename_directory() {
+        let mut fs = TmpFs::new(FsId::new());
+        fs.create(Path::new("/a"), FileType::Directory, MODE)
+            .unwrap();
+        fs.create(Path::new("/a/b"), FileType::Directory, MODE)
+            .unwrap();
+        create_file(&mut fs, "/a/b/c", b"c");
+
+        assert!(matches!(
+            fs.rename(Path::new("/a"), Path::new("/a/b/d")),
+            Err(VfsError::InvalidArgument)
+        ));
+
+        fs.rename(Path::new("/a"), Path::new("/x")).unwrap();
+        let handle = fs.open(Path::new("/x/b/c")).unwrap();
+        fs.close(handle).unwrap();
+        assert!(matches!(
+            fs.open(Path::new("/a/b/c")),
+            Err(VfsError::NoSuchFile)
+        ));
+    }
+
+    #[kernel_test]
+    fn test_operations() {
+        let mut fs = TmpFs::new(FsId::new());
+        fs.create(Path::new("/dir"), FileType::Directory, MODE)
+            .unwrap();
+        create_file(&mut fs, "/dir/file", b"content");
+
+        assert!(matches!(
+            fs.create(Path::new("/dir/file"), FileType::RegularFile, MODE),
+            Err(VfsError::AlreadyExists)
+        ));
+        assert!(matches!(
+            fs.create(Path::new("/missing/file"), FileType::RegularFile, MODE),
+            Err(VfsError::NoSuchFile)
+        ));
+        assert!(matches!(
+            fs.create(Path::new("/dir/file/file"), FileType::RegularFile, MODE),
+            Err(VfsError::NotDirectory)
+        ));
+
+        let mut stat = Stat::default();
+        fs.stat_path(Path::new("/dir/file"), &mut stat).unwrap();
+        assert_eq!(FileMode::S_IFREG | MODE, stat.mode);
+
+        let dir = fs.open(Path::new("/dir")).unwrap();
+        let mut buf = [0_u8; 7];
+        assert!(matches!(
+            fs.read(dir, &mut buf, 0),
+            Err(VfsError::IsDirectory)
+        ));
+        let (entries, _) = fs.read_dir(dir, 0).unwrap();
+        assert_eq!(1, entries.len());
+        assert_eq!("file", entries[0].name);
+        assert_eq!(FileType::RegularFile, entries[0].typ);
+        fs.close(dir).unwrap();
+
+        assert!(matches!(
+            fs.remove(Path::new("/dir")),
+            Err(VfsError::NotEmpty)
+        ));
+        fs.remove(Path::new("/dir/file")).unwrap();
+        fs.remove(Path::new("/dir")).unwrap();
+        assert!(matches!(
+            fs.open(Path::new("/dir")),
+            Err(VfsError::NoSuchFile)
+        ));
+    }
+
+    #[kernel_test]
+    fn test_write_across_chunks() {
+        let mut fs = TmpFs::new(FsId::new());
+        fs.create(Path::new("/file"), FileType::RegularFile, MODE)
+            .unwrap();
+        let handle = fs.open(Path::new("/file")).unwrap();
+
+        // the first chunk stays a hole
+        let offset = 2 * CHUNK_SIZE - 3;
+        assert_eq!(6, fs.write(handle, b"abcdef", offset).unwrap());
+
+        let mut buf = vec![0xFF_u8; 2 * CHUNK_SIZE + 3];
+        assert_eq!(2 * CHUNK_SIZE + 3, fs.read(handle, &mut buf, 0).unwrap());
+        assert!(buf[..offset].iter().all(|&b| b == 0));
+        assert_eq!(b"abcdef", &buf[offset..]);
+
+        let mut stat = Stat::default();
+        fs.stat(handle, &mut stat).unwrap();
+        assert_eq!((2 * CHUNK_SIZE + 3) as u64, stat.size);
+        assert_eq!((2 * CHUNK_SIZE / 512) as u64, stat.blocks);
+        fs.close(handle).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_truncate() {
+        let mut fs = TmpFs::new(FsId::new());
+        create_file(&mut fs, "/file", &[0xAA; CHUNK_SIZE + 100]);
+        let handle = fs.open(Path::new("/file")).unwrap();
+
+        fs.truncate(handle, 10).unwrap();
+        assert_eq!(CHUNK_SIZE, fs.usage.used);
+
+        // the regrown region must not contain the old data
+        fs.truncate(handle, CHUNK_SIZE + 100).unwrap();
+        assert_eq!(CHUNK_SIZE, fs.usage.used);
+        let mut buf = vec![0xFF_u8; CHUNK_SIZE + 100];
+        assert_eq!(CHUNK_SIZE + 100, fs.read(handle, &mut buf, 0).unwrap());
+        assert!(buf[..10].iter().all(|&b| b == 0xAA));
+        assert!(buf[10..].iter().all(|&b| b == 0));
+
+        fs.truncate(handle, 0).unwrap();
+        assert_eq!(0, fs.usage.used);
+        assert_eq!(0, fs.read(handle, &mut buf, 0).unwrap());
+        fs.close(handle).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_capacity() {
+        let mut fs = TmpFs::with_capacity(FsId::new(), 2 * CHUNK_SIZE);
+        create_file(&mut fs, "/a", &[1; CHUNK_SIZE]);
+        create_file(&mut fs, "/b", b"b");
+
+        // a write that doesn't fit fails as a whole
+        let handle = fs.open(Path::new("/b")).unwrap();
+        assert!(matches!(
+            fs.write(handle, &[2; 3], CHUNK_SIZE - 1),
+            Err(VfsError::NoSpace)
+        ));
+        let mut stat = Stat::default();
+        fs.stat(handle, &mut stat).unwrap();
+        assert_eq!(1, stat.size);
+        // rewriting allocated chunks still works
+        assert_eq!(3, fs.write(handle, &[2; 3], 1).unwrap());
+
+        // the space of a removed file is only freed once it is closed
+        fs.remove(Path::new("/b")).unwrap();
+        fs.create(Path::new("/c"), FileType::RegularFile, MODE)
+            .unwrap();
+        let c = fs.open(Path::new("/c")).unwrap();
+        assert!(matches!(fs.write(c, b"c", 0), Err(VfsError::NoSpace)));
+        fs.close(handle).unwrap();
+        assert_eq!(1, fs.write(c, b"c", 0).unwrap());
+        fs.close(c).unwrap();
+
+        fs.remove(Path::new("/a")).unwrap();
+        fs.remove(Path::new("/c")).unwrap();
+        assert_eq!(0, fs.usage.used);
+    }
+
+    #[kernel_test]
+    fn test_link() {
+        let mut fs = TmpFs::new(FsId::new());
+        create_file(&mut fs, "/a", b"hello");
+        fs.create(Path::new("/dir"), FileType::Directory, MODE)
+            .unwrap();
+        fs.link(Path::new("/a"), Path::new("/dir/b")).unwrap();
+        let mut stat = Stat::default();
+        fs.stat_path(Path::new("/a"), &mut stat).unwrap();
+        assert_eq!(2, stat.nlink);
+
+        assert!(matches!(
+            fs.link(Path::new("/a"), Path::new("/dir/b")),
+            Err(VfsError::AlreadyExists)
+        ));
+        assert!(matches!(
+            fs.link(Path::new("/dir"), Path::new("/c")),
+            Err(VfsError::PermissionDenied)
+        ));
+
+        // both names refer to the same data
+        let b = fs.open(Path::new("/dir/b")).unwrap();
+        fs.write(b, b"j", 0).unwrap();
+        fs.close(b).unwrap();
+        let a = fs.open(Path::new("/a")).unwrap();
+        let mut buf = [0_u8; 5];
+        assert_eq!(5, fs.read(a, &mut buf, 0).unwrap());
+        assert_eq!(b"jello", &buf);
+        fs.close(a).unwrap();
+
+        // the data stays until the last name is removed
+        fs.remove(Path::new("/a")).unwrap();
+        fs.stat_path(Path::new("/dir/b"), &mut stat).unwrap();
+        assert_eq!(1, stat.nlink);
+        assert_eq!(CHUNK_SIZE, fs.usage.used);
+        fs.remove(Path::new("/dir/b")).unwrap();
+        assert_eq!(0, fs.usage.used);
+    }
+
+    #[kernel_test]
+    fn test_handles_share_data() {
+        let mut fs = TmpFs::new(FsId::new());
+        fs.create(Path::new("/file"), FileType::RegularFile, MODE)
+            .unwrap();
+        let first = fs.open(Path::new("/file")).unwrap();
+        let second = fs.open(Path::new("/file")).unwrap();
+
+        fs.write(first, b"hello", 0).unwrap();
+        let mut buf = [0_u8; 5];
+        assert_eq!(5, fs.read(second, &mut buf, 0).unwrap());
+        assert_eq!(b"hello", &buf);
+
+        fs.truncate(second, 2).unwrap();
+        assert_eq!(2, fs.read(first, &mut buf, 0).unwrap());
+        assert_eq!(b"he", &buf[..2]);
+
+        fs.close(first).unwrap();
+        fs.close(second).unwrap();
+    }
+}
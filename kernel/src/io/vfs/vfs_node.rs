@@ -1,15 +1,25 @@
 use alloc::sync::Arc;
 use core::fmt::{Debug, Formatter};
 use core::ops::Deref;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Relaxed, SeqCst};
 
-use derive_more::Constructor;
-use spin::RwLock;
+use spin::{Mutex, RwLock};
 use x86_64::instructions::interrupts;
 
 use crate::io::path::{OwnedPath, Path};
 use crate::io::vfs;
-use crate::io::vfs::{FileSystem, VfsHandle};
+use crate::io::vfs::page_cache::ReadAhead;
+use crate::io::vfs::{FileSystem, InodeId, MountState, Result, VfsHandle};
+use crate::process::attributes::ProcessId;
 
+/// An open file, which is the handle of a file system together with the state
+/// that belongs to this open, like its lock and read-ahead state.
+///
+/// Clones refer to the same open file, which is how file descriptors that are
+/// duplicated and memory mappings share it. The file system's handle is closed
+/// exactly once, when the last clone is dropped. Until then, the file stays
+/// usable, even if it has been removed in the meantime.
 #[derive(Clone)]
 pub struct VfsNode {
     inner: Arc<Inner>,
@@ -23,13 +33,23 @@ impl Deref for VfsNode {
     }
 }
 
-#[derive(Constructor)]
 pub struct Inner {
     /// The path of this node.
     path: OwnedPath,
     /// The file system specific handle.
     handle: VfsHandle,
     fs: Arc<RwLock<dyn FileSystem>>,
+    /// The state of the mount that this node belongs to, or `None` if the file
+    /// system is not mounted, like pipefs.
+    mount: Option<Arc<MountState>>,
+    /// Whether this node has been written through, which is counted by its mount.
+    dirty: AtomicBool,
+    /// The advisory lock that was acquired through this node, which is
+    /// released when the last clone of the node is dropped.
+    lock: Mutex<Option<(InodeId, ProcessId)>>,
+    /// Whether this node is read sequentially, in which case the page cache
+    /// reads ahead.
+    read_ahead: Mutex<ReadAhead>,
 }
 
 impl !Clone for Inner {}
@@ -48,9 +68,21 @@ impl VfsNode {
         path: OwnedPath,
         handle: VfsHandle,
         fs: Arc<RwLock<dyn FileSystem>>,
+        mount: Option<Arc<MountState>>,
     ) -> Self {
+        if let Some(mount) = &mount {
+            mount.open_handles.fetch_add(1, Relaxed);
+        }
         Self {
-            inner: Arc::new(Inner::new(path, handle, fs)),
+            inner: Arc::new(Inner {
+                path,
+                handle,
+                fs,
+                mount,
+                dirty: AtomicBool::new(false),
+                lock: Mutex::new(None),
+                read_ahead: Mutex::new(ReadAhead::default()),
+            }),
         }
     }
 }
@@ -67,6 +99,49 @@ impl Inner {
     pub fn fs(&self) -> &Arc<RwLock<dyn FileSystem>> {
         &self.fs
     }
+
+    pub(in crate::io::vfs) fn set_lock(&self, inode: InodeId, owner: ProcessId) {
+        *self.lock.lock() = Some((inode, owner));
+    }
+
+    pub(in crate::io::vfs) fn read_ahead(&self) -> &Mutex<ReadAhead> {
+        &self.read_ahead
+    }
+
+    /// Marks this node as written through before it's used to change the file,
+    /// and returns [`VfsError::ReadOnly`](crate::io::vfs::VfsError::ReadOnly)
+    /// if the file system is mounted read-only.
+    ///
+    /// The node is marked before the mount flags are checked, so a concurrent
+    /// [`Vfs::remount`](crate::io::vfs::Vfs::remount) either sees this node as
+    /// dirty, or this sees the file system as read-only.
+    pub(in crate::io::vfs) fn begin_write(&self) -> Result<()> {
+        let Some(mount) = &self.mount else {
+            return Ok(());
+        };
+        let newly_dirty = !self.dirty.swap(true, SeqCst);
+        if newly_dirty {
+            mount.dirty_handles.fetch_add(1, SeqCst);
+        }
+        let result = mount.check_writable();
+        if result.is_err() && newly_dirty {
+            self.dirty.store(false, SeqCst);
+            mount.dirty_handles.fetch_sub(1, SeqCst);
+        }
+        result
+    }
+
+    /// Returns [`VfsError::DeviceGone`](crate::io::vfs::VfsError::DeviceGone)
+    /// if the device of the file system was removed.
+    pub(in crate::io::vfs) fn check_present(&self) -> Result<()> {
+        self.mount
+            .as_ref()
+            .map_or(Ok(()), |mount| mount.check_present())
+    }
+
+    pub(in crate::io::vfs) fn take_lock(&self) -> Option<(InodeId, ProcessId)> {
+        self.lock.lock().take()
+    }
 }
 
 impl Drop for Inner {
@@ -76,6 +151,12 @@ impl Drop for Inner {
             "interrupts must be enabled when dropping a vfsnode"
         ); // best effort, there is no way to guarantee that we don't get preempted right after this, so...
         vfs::close_vfs_node(self); // ...just pray that this doesn't deadlock
+        if let Some(mount) = &self.mount {
+            if *self.dirty.get_mut() {
+                mount.dirty_handles.fetch_sub(1, SeqCst);
+            }
+            mount.open_handles.fetch_sub(1, Relaxed);
+        }
 
         /*
         In all seriousness, the close function acquires locks.
@@ -13,17 +13,17 @@
 #![feature(vec_push_within_capacity)]
 extern crate alloc;
 
+use ::log::debug;
 use bootloader_api::config::Mapping;
 use bootloader_api::{BootInfo, BootloaderConfig};
 use conquer_once::spin::OnceCell;
-use ::log::debug;
 use x86_64::instructions::interrupts;
 use x86_64::structures::paging::{Page, Size4KiB};
 use x86_64::VirtAddr;
 
 pub use error::Result;
 
-use crate::arch::{gdt, idt};
+use crate::arch::{gdt, idt, serial};
 use crate::driver::apic::KERNEL_IOAPIC_ADDR;
 use crate::driver::{hpet, pci};
 use crate::io::vfs;
@@ -40,7 +40,10 @@ pub mod mem;
 pub mod net;
 pub mod process;
 pub mod qemu;
+pub mod random;
 pub mod syscall;
+#[cfg(feature = "kernel_test")]
+pub mod test_runner;
 pub mod time;
 
 const KERNEL_STACK_SIZE: Size = Size::KiB(128);
@@ -89,6 +92,9 @@ pub fn kernel_init(boot_info: &'static BootInfo) -> Result<()> {
     syscall::init();
     driver::acpi::init(boot_info)?;
     hpet::init();
+    serial::init_input_interrupt();
+    driver::rtc::init();
+    driver::vga::init(boot_info);
     pci::init();
     vfs::init();
 
@@ -1,18 +1,19 @@
+use crate::driver::vga::console;
 use crate::serial_println;
 use log::{info, Level, Metadata, Record};
 
 pub fn init() {
-    ::log::set_logger(&SerialLogger).unwrap();
+    ::log::set_logger(&KernelLogger).unwrap();
     ::log::set_max_level(::log::LevelFilter::Trace);
 
     info!("logging initialized");
 }
 
-pub struct SerialLogger;
+/// Logs to the serial port, and to the screen while the framebuffer console
+/// shows it.
+pub struct KernelLogger;
 
-impl SerialLogger {}
-
-impl log::Log for SerialLogger {
+impl log::Log for KernelLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
         metadata.level() < Level::Trace || metadata.target().starts_with("kernel")
     }
@@ -34,6 +35,7 @@ impl log::Log for SerialLogger {
                 record.target(),
                 record.args()
             );
+            console::log(record);
         }
     }
 
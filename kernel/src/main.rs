@@ -8,6 +8,7 @@ use core::panic::PanicInfo;
 use core::slice::from_raw_parts;
 use foundation::time::Instant;
 use kernel::arch::panic::handle_panic;
+use kernel::driver::vga::console;
 use kernel::process::{change_thread_priority, Priority, Process};
 use kernel::time::HpetInstantProvider;
 use kernel::{bootloader_config, kernel_init, process};
@@ -48,6 +49,8 @@ fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
+    // safety: this is the panic handler
+    unsafe { console::force_enable() };
     error!(
         "kernel panicked in pid={} ({}) tid={} ({}): {}",
         process::current().pid(),
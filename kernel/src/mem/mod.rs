@@ -11,6 +11,7 @@ use x86_64::structures::paging::{Page, PageSize, PageTableFlags, PhysFrame, Size
 use x86_64::{PhysAddr, VirtAddr};
 
 pub use address_space::*;
+pub use physical::{PhysicalMemoryManager, PhysicalMemoryStats};
 pub use size::*;
 
 use crate::mem::virt::heap::{KERNEL_HEAP_ADDR, KERNEL_HEAP_LEN};
@@ -151,3 +152,19 @@ macro_rules! unmap_page {
         address_space.unmap(page).unwrap().1.flush()
     }};
 }
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use core::hint::black_box;
+
+    use kernel_test_framework::{kernel_bench, Bencher};
+
+    #[kernel_bench]
+    fn bench_memcpy_64k(b: &mut Bencher) {
+        let src = vec![0xa5_u8; 64 * 1024];
+        let mut dst = vec![0_u8; 64 * 1024];
+        // without the black boxes, the compiler copies only once
+        b.iter(|| black_box(&mut dst).copy_from_slice(black_box(&src)));
+    }
+}
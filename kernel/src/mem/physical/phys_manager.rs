@@ -1,4 +1,5 @@
 use bootloader_api::BootInfo;
+use conquer_once::spin::OnceCell;
 use spin::Mutex;
 use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
 
@@ -6,6 +7,15 @@ use crate::mem::physical::MemoryMapPhysicalFrameAllocator;
 use crate::mem::physical::TrivialPhysicalFrameAllocator;
 
 static MEMORY_MANAGER: Mutex<Option<PhysicalMemoryManager>> = Mutex::new(None);
+static RECLAIM_HOOK: OnceCell<ReclaimHook> = OnceCell::uninit();
+
+/// The number of frames that are reclaimed at once when the allocator runs out
+/// of frames.
+const RECLAIM_BATCH: usize = 16;
+
+/// Frees up to the given number of frames that are held by caches, and returns
+/// how many it freed. See [`PhysicalMemoryManager::set_reclaim_hook`].
+pub type ReclaimHook = fn(usize) -> usize;
 
 // TODO: remove the two-stage approach
 // Currently, we need this, because the address space requires an initialized stage1 allocator
@@ -40,6 +50,15 @@ impl Allocator {
         }
     }
 
+    fn stats(&self) -> PhysicalMemoryStats {
+        match self {
+            // the first stage doesn't keep track of the frames, and it is only
+            // used until the heap is available
+            Allocator::Stage1(_) => PhysicalMemoryStats::default(),
+            Allocator::Stage2(alloc) => alloc.stats(),
+        }
+    }
+
     unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
         unsafe {
             match self {
@@ -54,14 +73,56 @@ pub struct PhysicalMemoryManager {
     alloc: Allocator,
 }
 
+/// The number of physical frames that the [`PhysicalMemoryManager`] manages.
+/// All frames are 4KiB in size.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct PhysicalMemoryStats {
+    /// The frames of all usable memory, regardless of whether they are
+    /// allocated.
+    pub usable_frames: usize,
+    pub free_frames: usize,
+}
+
 impl PhysicalMemoryManager {
+    /// Allocates a frame. If there are no free frames left, this asks the
+    /// [reclaim hook](PhysicalMemoryManager::set_reclaim_hook) to free some,
+    /// and tries again.
     pub fn allocate_frame() -> Option<PhysFrame> {
+        let frame = MEMORY_MANAGER
+            .lock()
+            .as_mut()
+            .and_then(|mm| mm.alloc.allocate_frame());
+        if frame.is_some() {
+            return frame;
+        }
+
+        // the hook deallocates frames, so we must not hold the lock while calling it
+        let reclaim = RECLAIM_HOOK.get()?;
+        if reclaim(RECLAIM_BATCH) == 0 {
+            return None;
+        }
         MEMORY_MANAGER
             .lock()
             .as_mut()
             .and_then(|mm| mm.alloc.allocate_frame())
     }
 
+    /// Sets the hook that is called when the allocator runs out of frames.
+    /// The hook can be called from any context, including the page fault
+    /// handler and while the caller holds locks, so it must not wait for any
+    /// lock. Only the first hook is kept.
+    pub fn set_reclaim_hook(hook: ReclaimHook) {
+        RECLAIM_HOOK.init_once(|| hook);
+    }
+
+    pub fn stats() -> PhysicalMemoryStats {
+        MEMORY_MANAGER
+            .lock()
+            .as_ref()
+            .map(|mm| mm.alloc.stats())
+            .unwrap_or_default()
+    }
+
     pub fn deallocate_frame(frame: PhysFrame) {
         if let Some(mm) = MEMORY_MANAGER.lock().as_mut() {
             unsafe { mm.alloc.deallocate_frame(frame) };
@@ -77,3 +138,18 @@ unsafe impl FrameAllocator<Size4KiB> for FrameAllocatorDelegate {
         PhysicalMemoryManager::allocate_frame()
     }
 }
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::{kernel_bench, Bencher};
+
+    use super::*;
+
+    #[kernel_bench]
+    fn bench_allocate_and_deallocate_frame(b: &mut Bencher) {
+        b.iter(|| {
+            let frame = PhysicalMemoryManager::allocate_frame().unwrap();
+            PhysicalMemoryManager::deallocate_frame(frame);
+        });
+    }
+}
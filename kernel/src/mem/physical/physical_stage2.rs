@@ -6,7 +6,7 @@ use log::{info, trace};
 use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame, Size4KiB};
 use x86_64::PhysAddr;
 
-use crate::mem::physical::STAGE1_ALLOCATED_FRAMES;
+use crate::mem::physical::{PhysicalMemoryStats, STAGE1_ALLOCATED_FRAMES};
 use crate::mem::virt::heap::{heap_initialized, KERNEL_HEAP_LEN};
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -19,6 +19,8 @@ enum FrameState {
 pub struct MemoryMapPhysicalFrameAllocator {
     frames: Vec<FrameState>,
     first_free: Option<usize>,
+    usable_frames: usize,
+    free_frames: usize,
 }
 
 impl MemoryMapPhysicalFrameAllocator {
@@ -53,6 +55,7 @@ impl MemoryMapPhysicalFrameAllocator {
         );
 
         // mark the usable frames as 'free'
+        let mut usable_frames = 0;
         regions
             .iter()
             .filter(|r| r.kind == MemoryRegionKind::Usable)
@@ -62,6 +65,7 @@ impl MemoryMapPhysicalFrameAllocator {
             .for_each(|frame| {
                 let frame_index = (frame.start_address().as_u64() / Size4KiB::SIZE) as usize;
                 frames[frame_index] = FrameState::Free;
+                usable_frames += 1;
             });
 
         // mark the already allocated heap as 'allocated'
@@ -82,6 +86,15 @@ impl MemoryMapPhysicalFrameAllocator {
         Self {
             frames,
             first_free: Some(stage1_allocated_frames),
+            usable_frames,
+            free_frames: usable_frames.saturating_sub(stage1_allocated_frames),
+        }
+    }
+
+    pub fn stats(&self) -> PhysicalMemoryStats {
+        PhysicalMemoryStats {
+            usable_frames: self.usable_frames,
+            free_frames: self.free_frames,
         }
     }
 
@@ -105,6 +118,7 @@ unsafe impl FrameAllocator<Size4KiB> for MemoryMapPhysicalFrameAllocator {
             .find(|(_, state)| matches!(state, FrameState::Free))?
             .0;
         self.frames[index] = FrameState::Allocated;
+        self.free_frames -= 1;
         self.first_free = self
             .frames
             .iter()
@@ -126,6 +140,9 @@ impl FrameDeallocator<Size4KiB> for MemoryMapPhysicalFrameAllocator {
         } else {
             self.first_free = Some(index);
         }
+        if self.frames[index] == FrameState::Allocated {
+            self.free_frames += 1;
+        }
         self.frames[index] = FrameState::Free;
     }
 }
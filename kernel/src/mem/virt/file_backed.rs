@@ -1,18 +1,40 @@
+use alloc::vec::Vec;
 use core::slice::from_raw_parts_mut;
 
-use derive_more::Constructor;
+use spin::Mutex;
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::VirtAddr;
 
+use crate::io::vfs::page_cache::PAGE_SIZE;
 use crate::io::vfs::{vfs, VfsNode};
 use crate::mem::virt::{AllocationError, MemoryBackedVmObject, VmObject};
 
-// FIXME: once we have write support in the fs, the drop impl should write dirty pages back to disk
-#[derive(Debug, Constructor)]
+#[derive(Debug)]
 pub struct FileBackedVmObject {
     node: VfsNode,
     offset: usize,
     underlying: MemoryBackedVmObject,
+    /// Whether the pages of the page cache are mapped, instead of private copies.
+    shared: bool,
+    /// The page cache pages that are mapped, by their index in the file.
+    pinned: Mutex<Vec<usize>>,
+}
+
+impl FileBackedVmObject {
+    pub fn new(
+        node: VfsNode,
+        offset: usize,
+        underlying: MemoryBackedVmObject,
+        shared: bool,
+    ) -> Self {
+        Self {
+            node,
+            offset,
+            underlying,
+            shared,
+            pinned: Mutex::new(Vec::new()),
+        }
+    }
 }
 
 impl VmObject for FileBackedVmObject {
@@ -38,6 +60,20 @@ impl VmObject for FileBackedVmObject {
 
     fn prepare_for_access(&self, offset: usize) -> Result<(), AllocationError> {
         let file_offset = self.offset + offset;
+        if self.shared {
+            let index = file_offset / PAGE_SIZE;
+            let writable = self.flags().contains(PageTableFlags::WRITABLE);
+            let frame = vfs()
+                .pin_page(&self.node, index, writable)
+                .map_err(|_| AllocationError::IoError)?;
+            // files that are not cached are mapped like private mappings
+            if let Some(frame) = frame {
+                self.pinned.lock().push(index);
+                self.underlying.map_foreign_frame(offset, frame);
+                return Ok(());
+            }
+        }
+
         // make sure that the accessed page is already mapped
         self.underlying
             .prepare_for_access_and_modify_page(offset, |page| {
@@ -70,6 +106,16 @@ impl VmObject for FileBackedVmObject {
     }
 }
 
+impl Drop for FileBackedVmObject {
+    fn drop(&mut self) {
+        // unpinned pages may be evicted, so they must not be accessible anymore
+        self.underlying.unmap_pages();
+        for index in self.pinned.get_mut().drain(..) {
+            let _ = vfs().unpin_page(&self.node, index);
+        }
+    }
+}
+
 fn align_down(v: usize, align: usize) -> usize {
     v & !(align - 1)
 }
@@ -155,6 +155,10 @@ impl VirtualMemoryManager {
         Ok(addr)
     }
 
+    /// Creates a vm object that is backed by the given file, starting at `offset`.
+    /// Pages of a `shared` mapping are the pages of the page cache, so writes
+    /// to them end up in the file. Other mappings get a private copy.
+    #[allow(clippy::too_many_arguments)]
     pub fn allocate_file_backed_vm_object(
         &'static self,
         name: String,
@@ -163,6 +167,7 @@ impl VirtualMemoryManager {
         addr: MapAt,
         size: usize,
         flags: PageTableFlags,
+        shared: bool,
     ) -> Result<VirtAddr, VmmError> {
         let memory_backed = self.create_memory_backed_vmo(
             name,
@@ -171,7 +176,7 @@ impl VirtualMemoryManager {
             AllocationStrategy::AllocateOnAccess,
             flags,
         )?;
-        let vmo = FileBackedVmObject::new(node, offset, memory_backed);
+        let vmo = FileBackedVmObject::new(node, offset, memory_backed, shared);
 
         let addr = vmo.addr();
         self.vm_objects.write().insert(addr, Box::new(vmo));
@@ -6,7 +6,7 @@ use derive_more::Constructor;
 use spin::RwLock;
 use x86_64::instructions::interrupts;
 use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{Page, PageTableFlags, PhysFrame, Size4KiB};
 use x86_64::VirtAddr;
 
 use crate::mem::physical::PhysicalMemoryManager;
@@ -76,6 +76,19 @@ impl MemoryBackedVmObject {
 
         Ok(())
     }
+
+    /// Maps the given frame at the page that contains `offset`. The frame is
+    /// not added to the underlying [`PmObject`], so it is not deallocated
+    /// together with this object.
+    pub(in crate::mem::virt) fn map_foreign_frame(&self, offset: usize, frame: PhysFrame) {
+        let page = Page::<Size4KiB>::containing_address(self.addr() + offset);
+        map_page!(page, frame, Size4KiB, self.flags);
+    }
+
+    /// Unmaps all pages of this object, which happens anyway when it is dropped.
+    pub(in crate::mem::virt) fn unmap_pages(&self) {
+        deallocate(self);
+    }
 }
 
 impl VmObject for MemoryBackedVmObject {
@@ -48,6 +48,12 @@ impl ProcessId {
     }
 }
 
+impl From<ProcessId> for u64 {
+    fn from(value: ProcessId) -> Self {
+        value.0
+    }
+}
+
 int_type!(ProcessGroupId, u64);
 int_type!(EffectiveUserId, u32);
 int_type!(EffectiveGroupId, u32);
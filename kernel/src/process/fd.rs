@@ -1,8 +1,9 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
 
 use derive_more::Display;
+use spin::Mutex;
 
-use kernel_api::syscall::Errno;
+use kernel_api::syscall::{Dirent, Errno, OpenFlags, Stat, Whence};
 
 use crate::io::vfs::{vfs, VfsError, VfsNode};
 
@@ -25,12 +26,6 @@ impl From<Fileno> for usize {
     }
 }
 
-impl From<Fileno> for Errno {
-    fn from(value: Fileno) -> Self {
-        Errno::from(value.0)
-    }
-}
-
 #[derive(Default, Debug)]
 pub struct FilenoAllocator {
     inner: AtomicUsize,
@@ -51,12 +46,25 @@ impl FilenoAllocator {
 #[derive(Debug)]
 pub struct FileDescriptor {
     node: VfsNode,
-    offset: usize,
+    /// The offset that the next read or write starts at. It stays locked for
+    /// the duration of a read or write, so that concurrent reads and writes
+    /// through the same descriptor don't use the same offset.
+    offset: Mutex<usize>,
+    flags: OpenFlags,
 }
 
 impl FileDescriptor {
-    pub fn new(node: VfsNode) -> Self {
-        Self { node, offset: 0 }
+    pub fn new(node: VfsNode, flags: OpenFlags) -> Self {
+        Self {
+            node,
+            offset: Mutex::new(0),
+            flags,
+        }
+    }
+
+    /// The flags that the file was opened with.
+    pub fn flags(&self) -> OpenFlags {
+        self.flags
     }
 
     pub fn into_node(self) -> VfsNode {
@@ -67,31 +75,144 @@ impl FileDescriptor {
         &self.node
     }
 
-    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, VfsError> {
-        match self.read_at(buf, self.offset) {
-            Ok(v) => {
-                self.offset += v;
-                Ok(v)
-            }
-            e => e,
-        }
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, VfsError> {
+        let mut offset = self.offset.lock();
+        let read = self.read_at(buf, *offset)?;
+        *offset += read;
+        Ok(read)
     }
 
-    pub fn read_at(&mut self, buf: &mut [u8], offset: usize) -> Result<usize, VfsError> {
+    pub fn read_at(&self, buf: &mut [u8], offset: usize) -> Result<usize, VfsError> {
+        if !self.flags.is_readable() {
+            return Err(VfsError::NotOpenForAccess);
+        }
         vfs().read(&self.node, buf, offset)
     }
 
-    pub fn write(&mut self, buf: &[u8]) -> Result<usize, VfsError> {
-        match self.write_at(buf, self.offset) {
-            Ok(v) => {
-                self.offset += v;
-                Ok(v)
+    /// Writes at the current offset, or at the end of the file if it was opened
+    /// with [`OpenFlags::O_APPEND`]. Either way, the offset is moved past what
+    /// was written.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, VfsError> {
+        let mut offset = self.offset.lock();
+        if self.flags.contains(OpenFlags::O_APPEND) {
+            if !self.flags.is_writable() {
+                return Err(VfsError::NotOpenForAccess);
             }
-            e => e,
+            let (written, end) = vfs().append(&self.node, buf)?;
+            *offset = end + written;
+            return Ok(written);
         }
+
+        let written = self.write_at(buf, *offset)?;
+        *offset += written;
+        Ok(written)
     }
 
-    pub fn write_at(&mut self, buf: &[u8], offset: usize) -> Result<usize, VfsError> {
+    pub fn write_at(&self, buf: &[u8], offset: usize) -> Result<usize, VfsError> {
+        if !self.flags.is_writable() {
+            return Err(VfsError::NotOpenForAccess);
+        }
         vfs().write(&self.node, buf, offset)
     }
+
+    /// Moves the offset that the next read or write starts at, and returns
+    /// the new offset. The offset may go past the end of the file. Pipes and
+    /// sockets have no offset, so they can't seek.
+    pub fn seek(&self, offset: isize, whence: Whence) -> Result<usize, Errno> {
+        let mut stat = Stat::default();
+        vfs().stat(&self.node, &mut stat)?;
+        if stat.mode.is_fifo() || stat.mode.is_socket() {
+            return Err(Errno::ESPIPE);
+        }
+
+        let mut current = self.offset.lock();
+        let base = match whence {
+            Whence::SeekSet => 0,
+            Whence::SeekCur => *current,
+            Whence::SeekEnd => stat.size as usize,
+        };
+        let new = base.checked_add_signed(offset).ok_or(Errno::EINVAL)?;
+        // the offset is returned as a non-negative isize
+        if isize::try_from(new).is_err() {
+            return Err(Errno::EOVERFLOW);
+        }
+        *current = new;
+        Ok(new)
+    }
+
+    /// Writes the entries of the directory, starting at the current offset,
+    /// as [`Dirent`]s into `buf`, and returns the number of bytes written,
+    /// which is 0 at the end of the directory. Only whole entries are
+    /// written, and the offset is moved past them.
+    ///
+    /// Fails with [`Errno::EINVAL`] if not even the next entry fits.
+    pub fn read_dir(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut offset = self.offset.lock();
+        let mut position = DirPosition::from(*offset);
+        let mut written = 0;
+        loop {
+            let (entries, next_cookie) = vfs().read_dir_batch(&self.node, position.cookie)?;
+            if entries.len() <= position.skip {
+                break;
+            }
+            let count = entries.len();
+            for (index, (entry, ino)) in entries.into_iter().enumerate().skip(position.skip) {
+                let next = if index + 1 == count {
+                    DirPosition {
+                        cookie: next_cookie,
+                        skip: 0,
+                    }
+                } else {
+                    DirPosition {
+                        cookie: position.cookie,
+                        skip: index + 1,
+                    }
+                };
+                let dirent = Dirent {
+                    ino,
+                    off: usize::from(next) as u64,
+                    reclen: 0,
+                    typ: entry.typ.dirent_type(),
+                };
+                match dirent.write(&mut buf[written..], entry.name.as_bytes()) {
+                    Some(len) => written += len,
+                    None if written == 0 => return Err(Errno::EINVAL),
+                    None => {
+                        *offset = position.into();
+                        return Ok(written);
+                    }
+                }
+                position = next;
+            }
+        }
+        *offset = position.into();
+        Ok(written)
+    }
+}
+
+/// A position in a directory, which is what the offset of a file descriptor
+/// of a directory holds. The cookies of the file systems only say where a
+/// batch of entries starts, so the position also holds how many entries of
+/// that batch were already read. The offset has the cookie in the upper and
+/// the number of entries in the lower 32 bits, so that the start of the
+/// directory is 0.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct DirPosition {
+    cookie: u64,
+    skip: usize,
+}
+
+impl From<usize> for DirPosition {
+    fn from(value: usize) -> Self {
+        Self {
+            cookie: (value >> 32) as u64,
+            skip: value & 0xFFFF_FFFF,
+        }
+    }
+}
+
+impl From<DirPosition> for usize {
+    fn from(value: DirPosition) -> Self {
+        ((value.cookie as usize) << 32) | (value.skip & 0xFFFF_FFFF)
+    }
 }
@@ -0,0 +1,135 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use core::sync::atomic::Ordering::{Relaxed, SeqCst};
+use core::sync::atomic::{AtomicU32, AtomicU64};
+
+use kernel_api::syscall::Errno;
+use spin::Mutex;
+use x86_64::instructions::hlt;
+use x86_64::PhysAddr;
+
+static FUTEXES: FutexTable = FutexTable::new();
+
+/// The table that `futex` waits in.
+pub fn futexes() -> &'static FutexTable {
+    &FUTEXES
+}
+
+/// The threads that wait on futexes, which are `u32`s in memory.
+///
+/// Futexes are keyed by their physical address, so that threads find each
+/// other no matter through which mapping they access the memory.
+pub struct FutexTable {
+    /// The tickets of the waiters of every futex, in the order in which they
+    /// started waiting. A waiter is woken by removing its ticket.
+    waiters: Mutex<BTreeMap<PhysAddr, VecDeque<u64>>>,
+    next_ticket: AtomicU64,
+}
+
+impl FutexTable {
+    pub(super) const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(BTreeMap::new()),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Waits until the futex at `key`, whose value is `futex`, is woken with
+    /// [`FutexTable::wake`].
+    ///
+    /// Returns [`Errno::EWOULDBLOCK`] without waiting if the value isn't
+    /// `expected`. The comparison and the start of the wait happen under the
+    /// same lock as waking, so a wake that follows a change of the value is
+    /// never missed.
+    pub fn wait(&self, key: PhysAddr, futex: &AtomicU32, expected: u32) -> Result<(), Errno> {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        {
+            let mut waiters = self.waiters.lock();
+            if futex.load(SeqCst) != expected {
+                return Err(Errno::EWOULDBLOCK);
+            }
+            waiters.entry(key).or_default().push_back(ticket);
+        }
+
+        loop {
+            // we can't park threads yet, so we give up our time slice and check again
+            hlt();
+
+            let waiters = self.waiters.lock();
+            if !waiters
+                .get(&key)
+                .is_some_and(|tickets| tickets.contains(&ticket))
+            {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Wakes up to `count` of the threads that wait on the futex at `key`, the
+    /// ones that have waited the longest first. Returns how many were woken.
+    pub fn wake(&self, key: PhysAddr, count: usize) -> usize {
+        let mut waiters = self.waiters.lock();
+        let Some(tickets) = waiters.get_mut(&key) else {
+            return 0;
+        };
+        let woken = count.min(tickets.len());
+        tickets.drain(..woken);
+        if tickets.is_empty() {
+            waiters.remove(&key);
+        }
+        woken
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::ffi::c_void;
+    use core::ptr;
+    use core::sync::atomic::AtomicBool;
+
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+    use crate::process::{spawn_thread_in_current_process, Priority};
+
+    static TABLE: FutexTable = FutexTable::new();
+    static FUTEX: AtomicU32 = AtomicU32::new(0);
+    static WOKEN: AtomicBool = AtomicBool::new(false);
+
+    fn key() -> PhysAddr {
+        PhysAddr::new(0x1000)
+    }
+
+    fn waiting() -> usize {
+        TABLE.waiters.lock().get(&key()).map_or(0, VecDeque::len)
+    }
+
+    extern "C" fn wait(_: *mut c_void) {
+        TABLE.wait(key(), &FUTEX, 0).unwrap();
+        WOKEN.store(true, SeqCst);
+    }
+
+    #[kernel_test]
+    fn test_wait_with_changed_value() {
+        let futex = AtomicU32::new(1);
+        assert_eq!(Err(Errno::EWOULDBLOCK), TABLE.wait(key(), &futex, 0));
+        assert_eq!(0, TABLE.wake(key(), 1));
+    }
+
+    #[kernel_test]
+    fn test_wait_and_wake() {
+        spawn_thread_in_current_process("futex_waiter", Priority::Normal, wait, ptr::null_mut());
+        while waiting() == 0 {
+            hlt();
+        }
+
+        // other futexes have other waiters
+        assert_eq!(0, TABLE.wake(PhysAddr::new(0x1004), 1));
+        assert!(!WOKEN.load(SeqCst));
+
+        assert_eq!(1, TABLE.wake(key(), usize::MAX));
+        assert_eq!(0, waiting());
+        while !WOKEN.load(SeqCst) {
+            hlt();
+        }
+    }
+}
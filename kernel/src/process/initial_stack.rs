@@ -0,0 +1,95 @@
+use core::mem::size_of;
+
+use kernel_api::auxv::AT_NULL;
+
+/// Writes the arguments and the environment of a process to the top of
+/// `stack`, together with the auxiliary vector `auxv` as key, value pairs.
+/// The layout is the one that the System V ABI describes, see
+/// [`kernel_api::auxv`].
+///
+/// `base` is the address at which `stack` is mapped, which the pointers are
+/// relative to. Returns the offset of `argc`, which is 16 byte aligned, or
+/// [`None`] if everything doesn't fit.
+pub fn write(
+    stack: &mut [u8],
+    base: usize,
+    args: &[&str],
+    env: &[&str],
+    auxv: &[(usize, usize)],
+) -> Option<usize> {
+    let strings = args.iter().chain(env).map(|s| s.len() + 1).sum::<usize>();
+    let words = 1 + args.len() + 1 + env.len() + 1 + 2 * (auxv.len() + 1);
+    let strings_start = stack.len().checked_sub(strings)?;
+    let start = strings_start.checked_sub(words * size_of::<usize>())? & !0xF;
+
+    let mut word = start;
+    let mut push = |stack: &mut [u8], value: usize| {
+        stack[word..word + size_of::<usize>()].copy_from_slice(&value.to_ne_bytes());
+        word += size_of::<usize>();
+    };
+    push(stack, args.len());
+    let mut string = strings_start;
+    for list in [args, env] {
+        for s in list {
+            push(stack, base + string);
+            stack[string..string + s.len()].copy_from_slice(s.as_bytes());
+            stack[string + s.len()] = 0;
+            string += s.len() + 1;
+        }
+        push(stack, 0);
+    }
+    for &(key, value) in auxv.iter().chain(&[(AT_NULL, 0)]) {
+        push(stack, key);
+        push(stack, value);
+    }
+    Some(start)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::ffi::CStr;
+
+    use kernel_api::auxv::{AT_ENTRY, AT_PAGESZ};
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+
+    fn word(stack: &[u8], offset: usize) -> usize {
+        usize::from_ne_bytes(stack[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[kernel_test]
+    fn test_write() {
+        let base = 0x1000;
+        let mut stack = vec![0xAA_u8; 256];
+        let auxv = [(AT_PAGESZ, 4096), (AT_ENTRY, 0x2000)];
+        let start = write(&mut stack, base, &["/bin/a", "b"], &["K=V"], &auxv).unwrap();
+        assert_eq!(0, (base + start) % 16);
+
+        let words = (0..12)
+            .map(|i| word(&stack, start + i * 8))
+            .collect::<Vec<_>>();
+        assert_eq!(2, words[0]);
+        assert_eq!(0, words[3]);
+        assert_eq!(0, words[5]);
+        assert_eq!([AT_PAGESZ, 4096, AT_ENTRY, 0x2000], words[6..10]);
+        assert_eq!([AT_NULL, 0], words[10..12]);
+        let string = |ptr: usize| CStr::from_bytes_until_nul(&stack[ptr - base..]).unwrap();
+        assert_eq!(c"/bin/a", string(words[1]));
+        assert_eq!(c"b", string(words[2]));
+        assert_eq!(c"K=V", string(words[4]));
+        // the strings end at the top of the stack
+        assert_eq!(0, stack[255]);
+    }
+
+    #[kernel_test]
+    fn test_write_too_large() {
+        let mut stack = vec![0_u8; 32];
+        assert_eq!(
+            None,
+            write(&mut stack, 0, &["/bin/a"], &["LONG=VALUE"], &[])
+        );
+    }
+}
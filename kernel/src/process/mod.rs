@@ -1,38 +1,57 @@
 use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
 use alloc::collections::BTreeMap;
 use alloc::format;
 use alloc::string::{String, ToString};
 use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::arch::asm;
 use core::ffi::c_void;
+use core::ops::Range;
 use core::ptr;
-use core::slice::from_raw_parts;
-use core::sync::atomic::AtomicBool;
-use core::sync::atomic::Ordering::{Relaxed, Release};
+use core::slice::{from_raw_parts, from_raw_parts_mut};
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicBool, AtomicUsize};
 
+use conquer_once::spin::OnceCell;
 use elfloader::ElfBinary;
 use log::trace;
-use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-use x86_64::structures::paging::PageTableFlags;
+use spin::{Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use x86_64::instructions::hlt;
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
 use x86_64::VirtAddr;
 
-use kernel_api::syscall::Stat;
+use kernel_api::auxv::{AT_ENTRY, AT_PAGESZ};
+use kernel_api::syscall::{Errno, OpenFlags, PollEvents, SigInfo, Stat, Whence, SI_USER};
 pub use scheduler::*;
 pub use tree::*;
 
 use crate::io::path::{OwnedPath, Path};
+use crate::io::vfs::lock::LockKind;
 use crate::io::vfs::{vfs, VfsError, VfsNode};
-use crate::mem::virt::{MapAt, VirtualMemoryManager};
+use crate::mem::virt::{AllocationStrategy, MapAt, VirtualMemoryManager};
 use crate::mem::{AddressSpace, Size};
 use crate::process::attributes::{Attributes, ProcessId, RealGroupId, RealUserId};
 use crate::process::elf::ElfLoader;
 use crate::process::fd::{FileDescriptor, Fileno, FilenoAllocator};
-use crate::process::thread::{State, Thread};
+use crate::process::signal::{check_signal, Signals};
+use crate::process::thread::{State, Thread, ThreadId};
+use crate::process::wait_queue::WaitQueue;
+use crate::syscall::convert::UserspaceMutPtr;
 
 pub mod attributes;
 pub mod elf;
 pub mod fd;
+pub mod futex;
+mod initial_stack;
 mod scheduler;
+pub mod signal;
 mod tree;
+pub mod wait_queue;
+
+/// The size of the stack that the main thread of a process runs on.
+const MAIN_STACK_SIZE: usize = Size::KiB(256).bytes();
 
 pub fn init(address_space: AddressSpace) {
     let root_process = Process::create_kernel(address_space);
@@ -95,9 +114,25 @@ pub struct Process {
     name: String,
     pid: ProcessId,
     should_terminate: AtomicBool,
+    /// Whether syscalls of this process are logged.
+    traced: AtomicBool,
     next_fd: FilenoAllocator,
     open_fds: RwLock<BTreeMap<Fileno, FileDescriptor>>,
     attributes: RwLock<Attributes>,
+    /// The absolute path that relative paths are resolved against.
+    cwd: RwLock<OwnedPath>,
+    /// The threads that were created with [`Process::create_thread`] and
+    /// haven't been joined yet, with their exit value once they exited.
+    joinable_threads: Mutex<BTreeMap<ThreadId, Option<usize>>>,
+    /// What the main thread finds on its initial stack.
+    arguments: RwLock<Vec<String>>,
+    environment: RwLock<Vec<String>>,
+    /// What the process passed to `exit`.
+    exit_status: AtomicUsize,
+    signals: Mutex<Signals>,
+    /// Where the loaded executable is, which is where faults of the process
+    /// itself, as opposed to faults of the kernel on its behalf, happen.
+    image: OnceCell<Range<usize>>,
 
     executable_file: Option<OwnedPath>,
 }
@@ -128,6 +163,7 @@ extern "C" fn trampoline(_: *mut c_void) {
                 MapAt::Anywhere,
                 size,
                 PageTableFlags::PRESENT | PageTableFlags::WRITABLE,
+                false,
             )
             .expect("failed to create file-backed vm object for executable");
         unsafe { from_raw_parts(addr.as_ptr::<u8>(), size) }
@@ -137,13 +173,28 @@ extern "C" fn trampoline(_: *mut c_void) {
     let elf = ElfBinary::new(elf_data).unwrap();
     elf.load(&mut loader).unwrap();
     let image = loader.into_inner();
+    proc.image
+        .try_init_once(|| {
+            let range = image.as_ptr_range();
+            range.start as usize..range.end as usize
+        })
+        .expect("image of the process was already loaded");
     let code_ptr = unsafe { image.as_ptr().add(elf.entry_point() as usize) };
-
-    let entry_fn: extern "C" fn() = unsafe { core::mem::transmute(code_ptr) };
-
-    entry_fn();
-
-    unreachable!("entry function returned, but must call sys_exit instead");
+    let stack = map_main_stack(proc, code_ptr as usize);
+
+    // the entry point gets the initial stack as its argument, because it is
+    // called instead of jumped to, and must call sys_exit instead of returning
+    unsafe {
+        asm!(
+            "mov rsp, {stack}",
+            "call {entry}",
+            "ud2",
+            stack = in(reg) stack.as_u64(),
+            entry = in(reg) code_ptr,
+            in("rdi") stack.as_u64(),
+            options(noreturn),
+        );
+    }
 
     // TODO: I guess before we can jump to entry_fn in usermode, we need to make sure that the code and stack are actually in user space instead of the kernel heap.
 
@@ -177,6 +228,61 @@ extern "C" fn trampoline(_: *mut c_void) {
     // }
 }
 
+/// Maps the stack of the main thread of the process, and puts the arguments,
+/// the environment and the auxiliary vector on it. Returns where the stack
+/// pointer starts, which is where `argc` is.
+fn map_main_stack(process: &Process, entry: usize) -> VirtAddr {
+    let addr = vmm()
+        .allocate_memory_backed_vmobject(
+            format!("main stack (len={})", MAIN_STACK_SIZE),
+            MapAt::Anywhere,
+            MAIN_STACK_SIZE,
+            // page faults on the stack can't be handled on the stack itself
+            AllocationStrategy::AllocateNow,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .expect("failed to create the stack of the main thread");
+    let stack = unsafe { from_raw_parts_mut(addr.as_mut_ptr::<u8>(), MAIN_STACK_SIZE) };
+
+    let arguments = process.arguments.read();
+    let environment = process.environment.read();
+    let args = arguments.iter().map(String::as_str).collect::<Vec<_>>();
+    let env = environment.iter().map(String::as_str).collect::<Vec<_>>();
+    let auxv = [(AT_PAGESZ, Size4KiB::SIZE as usize), (AT_ENTRY, entry)];
+    let start = initial_stack::write(stack, addr.as_u64() as usize, &args, &env, &auxv)
+        .expect("arguments and environment don't fit on the stack");
+    addr + start as u64
+}
+
+/// Where a thread that was created with [`Process::create_thread`] starts.
+struct ThreadStart {
+    entry: VirtAddr,
+    arg: usize,
+    stack: VirtAddr,
+}
+
+/// Switches to the stack of the thread and calls its entry point, which
+/// returns the exit value of the thread, unless the thread exits itself.
+extern "C" fn thread_trampoline(start: *mut c_void) {
+    let start = *unsafe { Box::from_raw(start.cast::<ThreadStart>()) };
+    let value: usize;
+    unsafe {
+        asm!(
+            "mov r12, rsp",
+            "mov rsp, {stack}",
+            "call {entry}",
+            "mov rsp, r12",
+            stack = in(reg) start.stack.align_down(16_u64).as_u64(),
+            entry = in(reg) start.entry.as_u64(),
+            in("rdi") start.arg,
+            out("r12") _,
+            lateout("rax") value,
+            clobber_abi("C"),
+        );
+    }
+    current().exit_thread(value);
+}
+
 // fn read_rsp() -> usize {
 //     let rsp: usize;
 //     unsafe { asm!("mov {}, rsp", out(reg) rsp) };
@@ -190,13 +296,48 @@ impl Process {
         priority: Priority,
         uid: RealUserId,
         gid: RealGroupId,
+    ) -> Arc<Self> {
+        let proc = Self::create_from_executable(parent, path, uid, gid);
+        proc.start(priority);
+        proc
+    }
+
+    /// Creates a process for the given executable, but doesn't start it.
+    /// This allows the caller to configure the process (for example with
+    /// [`Process::set_traced`]) before it executes any code. Use [`Process::start`]
+    /// to start the process.
+    ///
+    /// The file descriptors 0, 1 and 2 of the process (stdin, stdout and stderr)
+    /// refer to `/dev/console`. The only argument is `path`, and the environment
+    /// is empty.
+    pub fn create_from_executable(
+        parent: &Arc<Process>,
+        path: impl AsRef<Path>,
+        uid: RealUserId,
+        gid: RealGroupId,
     ) -> Arc<Self> {
         let path = path.as_ref();
+        let process = Self::create_user(parent, Some(path.to_owned()), path.to_string(), uid, gid);
+        process.set_arguments(vec![path.to_string()]);
+
+        // stdin, stdout and stderr
+        let console = vfs()
+            .open("/dev/console")
+            .expect("/dev/console should exist");
+        for _ in 0..3 {
+            process.get_fileno_for(console.clone(), OpenFlags::O_RDWR);
+        }
 
-        let proc = Self::create_user(parent, Some(path.to_owned()), path.to_string(), uid, gid);
-        spawn_thread("main", &proc, priority, trampoline, ptr::null_mut());
+        process
+    }
 
-        proc
+    /// Spawns the main thread of a process created with [`Process::create_from_executable`].
+    pub fn start(self: &Arc<Self>, priority: Priority) {
+        assert!(
+            self.executable_file.is_some(),
+            "only processes with an executable file can be started"
+        );
+        spawn_thread("main", self, priority, trampoline, ptr::null_mut());
     }
 
     pub fn create_kernel(address_space: AddressSpace) -> Arc<Self> {
@@ -232,9 +373,17 @@ impl Process {
             name,
             pid,
             should_terminate: AtomicBool::new(false),
+            traced: AtomicBool::new(false),
             next_fd,
             open_fds,
             attributes,
+            cwd: RwLock::new(OwnedPath::from("/")),
+            joinable_threads: Default::default(),
+            arguments: Default::default(),
+            environment: Default::default(),
+            exit_status: AtomicUsize::new(0),
+            signals: Default::default(),
+            image: OnceCell::uninit(),
             executable_file: None,
         });
         process_tree().write().set_root(res.clone());
@@ -273,9 +422,17 @@ impl Process {
             name,
             pid,
             should_terminate: AtomicBool::new(false),
+            traced: AtomicBool::new(false),
             next_fd: Default::default(),
             open_fds: Default::default(),
             attributes,
+            cwd: RwLock::new(parent.cwd()),
+            joinable_threads: Default::default(),
+            arguments: Default::default(),
+            environment: Default::default(),
+            exit_status: AtomicUsize::new(0),
+            signals: Default::default(),
+            image: OnceCell::uninit(),
             executable_file,
         });
         process_tree()
@@ -289,8 +446,14 @@ impl Process {
 
         trace!("terminating process {} ({})", self.pid, self.name);
 
-        // drop open file descriptors - drop must take care of flushing
-        self.open_fds().write().clear();
+        // drop open file descriptors - drop must take care of flushing. They are
+        // dropped after the table is unlocked, see `Process::open_fds`
+        let open_fds = core::mem::take(&mut *self.open_fds().write());
+        drop(open_fds);
+
+        // locks may also be held through nodes that are not in the file
+        // descriptor table, and other threads may still wait for locks
+        vfs().release_locks(self.pid);
 
         // drop vm objects - drop takes care of unmapping
         self.vmm().vm_objects().write().clear();
@@ -298,10 +461,75 @@ impl Process {
         self.should_terminate.store(true, Release);
     }
 
+    /// Whether the process has been terminated, but not all of its threads
+    /// have exited yet.
+    pub fn is_terminating(&self) -> bool {
+        self.should_terminate.load(Acquire)
+    }
+
     pub fn pid(&self) -> &ProcessId {
         &self.pid
     }
 
+    /// Sets the arguments that the main thread finds on its stack. This only
+    /// has an effect before the process is started.
+    pub fn set_arguments(&self, arguments: Vec<String>) {
+        *self.arguments.write() = arguments;
+    }
+
+    /// Sets the environment that the main thread finds on its stack, as
+    /// `NAME=value` strings. This only has an effect before the process is
+    /// started.
+    pub fn set_environment(&self, environment: Vec<String>) {
+        *self.environment.write() = environment;
+    }
+
+    /// What the process passed to `exit`, or 0 if it didn't exit that way.
+    pub fn exit_status(&self) -> usize {
+        self.exit_status.load(Acquire)
+    }
+
+    pub fn set_exit_status(&self, status: usize) {
+        self.exit_status.store(status, Release);
+    }
+
+    /// Sets the exit status to the one of a process that was terminated by
+    /// the signal `sig`, and terminates this process, which must be the
+    /// current one.
+    pub fn terminate_by_signal(&self, sig: usize) -> ! {
+        trace!(
+            "process {} ({}) terminated by signal {}",
+            self.pid,
+            self.name,
+            sig
+        );
+        self.set_exit_status(128 + sig);
+        self.terminate();
+
+        loop {
+            hlt();
+        }
+    }
+
+    pub fn signals(&self) -> MutexGuard<Signals> {
+        self.signals.lock()
+    }
+
+    /// Whether `addr` is inside the executable of this process, which is
+    /// never the case for the kernel.
+    pub fn is_in_image(&self, addr: usize) -> bool {
+        self.image.get().is_some_and(|image| image.contains(&addr))
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.traced.load(Relaxed)
+    }
+
+    /// Enables or disables syscall tracing for this process.
+    pub fn set_traced(&self, traced: bool) {
+        self.traced.store(traced, Relaxed);
+    }
+
     pub fn attributes(&self) -> RwLockReadGuard<Attributes> {
         self.attributes.read()
     }
@@ -310,10 +538,26 @@ impl Process {
         self.attributes.write()
     }
 
+    /// The current working directory of this process.
+    pub fn cwd(&self) -> OwnedPath {
+        self.cwd.read().clone()
+    }
+
+    /// Changes the current working directory. The path must be absolute.
+    pub fn set_cwd(&self, cwd: OwnedPath) {
+        *self.cwd.write() = cwd;
+    }
+
     pub fn vmm(&self) -> &VirtualMemoryManager {
         &self.virtual_memory_manager
     }
 
+    /// The file descriptor table of this process.
+    ///
+    /// The table must only be locked for writing to insert or remove
+    /// descriptors, never while a file is accessed or closed, so that file
+    /// systems can look at the table of the calling process while they are
+    /// locked, like the devfs does for `/dev/fd`.
     pub fn open_fds(&self) -> &RwLock<BTreeMap<Fileno, FileDescriptor>> {
         &self.open_fds
     }
@@ -330,32 +574,149 @@ impl Process {
         self.cr3_value
     }
 
-    pub fn open_file<P>(&self, path: P) -> Result<Fileno, VfsError>
+    /// Creates a thread in this process, which calls `entry` with `arg` on
+    /// the given stack, and whose `fs` segment starts at `tls`. The thread can
+    /// be joined with [`Process::join_thread`].
+    pub fn create_thread(
+        self: &Arc<Self>,
+        entry: VirtAddr,
+        arg: usize,
+        stack: VirtAddr,
+        tls: VirtAddr,
+        priority: Priority,
+    ) -> ThreadId {
+        let start = Box::into_raw(Box::new(ThreadStart { entry, arg, stack }));
+        let thread = Thread::new_ready(self, "user", priority, thread_trampoline, start.cast());
+        thread.set_fs_base(tls);
+        let id = *thread.id();
+        // the thread may exit before this would return
+        self.joinable_threads.lock().insert(id, None);
+        spawn(thread);
+        id
+    }
+
+    /// Exits the current thread, which must belong to this process. If it is
+    /// joinable, `value` is what joining it returns.
+    pub fn exit_thread(&self, value: usize) -> ! {
+        let id = *current_thread().id();
+        if let Some(exit_value) = self.joinable_threads.lock().get_mut(&id) {
+            *exit_value = Some(value);
+        }
+        exit_thread()
+    }
+
+    /// Waits until the thread has exited, and returns its exit value. A
+    /// thread can only be joined once.
+    pub fn join_thread(&self, id: ThreadId) -> Result<usize, Errno> {
+        if id == *current_thread().id() {
+            return Err(Errno::EDEADLK);
+        }
+        loop {
+            {
+                let mut joinable_threads = self.joinable_threads.lock();
+                let exit_value = *joinable_threads.get(&id).ok_or(Errno::ESRCH)?;
+                // the stack of the thread is only unused once the thread is gone
+                // from the tree, which happens after it has been switched away from
+                let gone = !process_tree()
+                    .read()
+                    .threads(&self.pid)
+                    .is_some_and(|mut threads| threads.any(|&thread| thread == id));
+                if let (Some(value), true) = (exit_value, gone) {
+                    joinable_threads.remove(&id);
+                    return Ok(value);
+                }
+            }
+
+            // we don't have wait queues yet, so we give up our time slice and check again
+            hlt();
+        }
+    }
+
+    /// Waits until the child process with the given id is gone, and returns
+    /// its exit status. Returns [`Errno::ECHILD`] if there is no such child.
+    pub fn wait_for_child(&self, pid: u64) -> Result<usize, Errno> {
+        let pid = ProcessId(pid);
+        let child = {
+            let process_tree = process_tree().read();
+            if process_tree.parent_of(&pid) != Some(&self.pid) {
+                return Err(Errno::ECHILD);
+            }
+            process_tree
+                .process_by_id(&pid)
+                .cloned()
+                .ok_or(Errno::ECHILD)?
+        };
+        while process_tree().read().process_by_id(&pid).is_some() {
+            // we don't have wait queues yet, so we give up our time slice and check again
+            hlt();
+        }
+        Ok(child.exit_status())
+    }
+
+    /// Sends the signal `sig` to the process with the given id. A signal of 0
+    /// is never sent, but still checks whether the process exists. Returns
+    /// [`Errno::ESRCH`] if it doesn't, and [`Errno::EPERM`] for the kernel.
+    pub fn send_signal(&self, pid: u64, sig: usize) -> Result<(), Errno> {
+        if sig != 0 {
+            check_signal(sig)?;
+        }
+        let pid = ProcessId(pid);
+        let target = process_tree()
+            .read()
+            .process_by_id(&pid)
+            .cloned()
+            .ok_or(Errno::ESRCH)?;
+        if target.pid.0 == 0 {
+            return Err(Errno::EPERM);
+        }
+        if sig != 0 {
+            target.signals().send(SigInfo {
+                signo: sig as i32,
+                code: SI_USER,
+                pid: self.pid.0 as i32,
+                uid: u32::from(self.attributes().uid),
+                ..Default::default()
+            });
+        }
+        Ok(())
+    }
+
+    pub fn open_file<P>(&self, path: P, flags: OpenFlags) -> Result<Fileno, VfsError>
     where
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        let node = vfs().open(path)?;
-        Ok(self.get_fileno_for(node))
+        let node = if flags.contains(OpenFlags::O_NOFOLLOW) {
+            let node = vfs().open_nofollow(path)?;
+            let mut stat = Stat::default();
+            vfs().stat(&node, &mut stat)?;
+            if stat.mode.is_symlink() {
+                return Err(VfsError::SymlinkLoop);
+            }
+            node
+        } else {
+            vfs().open(path)?
+        };
+        Ok(self.get_fileno_for(node, flags))
     }
 
     pub fn allocate_fileno(&self) -> Fileno {
         self.next_fd.next()
     }
 
-    pub fn get_fileno_for(&self, node: VfsNode) -> Fileno {
+    pub fn get_fileno_for(&self, node: VfsNode, flags: OpenFlags) -> Fileno {
         let fd = self.allocate_fileno();
-        self.open_fds.write().insert(fd, FileDescriptor::new(node));
+        self.open_fds
+            .write()
+            .insert(fd, FileDescriptor::new(node, flags));
         fd
     }
 
+    /// Reads from the file descriptor into `buf`. If there is nothing to read
+    /// yet, this waits until there is, unless the file was opened with
+    /// [`OpenFlags::O_NONBLOCK`].
     pub fn read(&self, fileno: Fileno, buf: &mut [u8]) -> Result<usize, VfsError> {
-        let mut guard = self.open_fds().write();
-        let fd = match guard.get_mut(&fileno) {
-            Some(fd) => fd,
-            None => return Err(VfsError::HandleClosed),
-        };
-        fd.read(buf)
+        self.blocking(fileno, |fd| fd.read(buf))
     }
 
     pub fn read_at(
@@ -364,30 +725,167 @@ impl Process {
         buf: &mut [u8],
         offset: usize,
     ) -> Result<usize, VfsError> {
-        let mut guard = self.open_fds().write();
-        let fd = match guard.get_mut(&fileno) {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fileno) {
             Some(fd) => fd,
             None => return Err(VfsError::HandleClosed),
         };
         fd.read_at(buf, offset)
     }
 
+    /// Writes `buf` to the file descriptor. If the file can't take all of it
+    /// yet, like a full pipe, this waits until it took everything, unless the
+    /// file was opened with [`OpenFlags::O_NONBLOCK`]. Then, only what fits is
+    /// written.
     pub fn write(&self, fileno: Fileno, buf: &[u8]) -> Result<usize, VfsError> {
-        let mut guard = self.open_fds().write();
-        let fd = match guard.get_mut(&fileno) {
+        let mut written = 0;
+        loop {
+            match self.blocking(fileno, |fd| fd.write(&buf[written..])) {
+                Ok(0) => return Ok(written),
+                Ok(n) => {
+                    written += n;
+                    if written == buf.len() {
+                        return Ok(written);
+                    }
+                }
+                // what was written before the error can't be taken back
+                Err(_) if written > 0 => return Ok(written),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Runs `op` on the file descriptor. If that fails with
+    /// [`VfsError::WouldBlock`], this waits until the file changes and runs
+    /// `op` again, unless the file was opened with [`OpenFlags::O_NONBLOCK`].
+    fn blocking<T>(
+        &self,
+        fileno: Fileno,
+        mut op: impl FnMut(&FileDescriptor) -> Result<T, VfsError>,
+    ) -> Result<T, VfsError> {
+        let mut waiter = None;
+        loop {
+            {
+                let guard = self.open_fds().read();
+                let fd = match guard.get(&fileno) {
+                    Some(fd) => fd,
+                    None => return Err(VfsError::HandleClosed),
+                };
+                match op(fd) {
+                    Err(VfsError::WouldBlock) if !fd.flags().contains(OpenFlags::O_NONBLOCK) => {}
+                    result => return result,
+                }
+                if waiter.is_none() {
+                    // files that can't wake us never become ready while we wait
+                    let Some(queue) = vfs().wait_queue(fd.node()) else {
+                        return Err(VfsError::WouldBlock);
+                    };
+                    // the file may have changed before we registered, so we
+                    // try again before we wait
+                    waiter = Some(queue.register());
+                    continue;
+                }
+            }
+
+            park(None);
+            if self.is_terminating() {
+                return Err(VfsError::Interrupted);
+            }
+        }
+    }
+
+    pub fn stat(&self, fd: Fileno, stat: &mut Stat) -> Result<(), VfsError> {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fd) {
             Some(fd) => fd,
             None => return Err(VfsError::HandleClosed),
         };
-        fd.write(buf)
+        vfs().stat(fd.node(), stat)
     }
 
-    pub fn stat(&self, fd: Fileno, stat: &mut Stat) -> Result<(), VfsError> {
+    pub fn poll(&self, fd: Fileno, interest: PollEvents) -> Result<PollEvents, VfsError> {
         let guard = self.open_fds().read();
         let fd = match guard.get(&fd) {
             Some(fd) => fd,
             None => return Err(VfsError::HandleClosed),
         };
-        vfs().stat(fd.node(), stat)
+        vfs().poll(fd.node(), interest)
+    }
+
+    /// Returns the queue that is woken when the file may have become ready, or
+    /// `None` if the file never blocks or the descriptor isn't open.
+    pub fn wait_queue(&self, fd: Fileno) -> Option<Arc<WaitQueue>> {
+        let guard = self.open_fds().read();
+        vfs().wait_queue(guard.get(&fd)?.node())
+    }
+
+    pub fn ioctl(
+        &self,
+        fd: Fileno,
+        request: u32,
+        arg: UserspaceMutPtr<u8>,
+    ) -> Result<usize, Errno> {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fd) {
+            Some(fd) => fd,
+            None => return Err(Errno::EBADF),
+        };
+        vfs().ioctl(fd.node(), request, arg)
+    }
+
+    /// Truncates or extends the file to `len` bytes. The file must have been opened
+    /// for writing.
+    pub fn truncate(&self, fd: Fileno, len: usize) -> Result<(), Errno> {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fd) {
+            Some(fd) => fd,
+            None => return Err(Errno::EBADF),
+        };
+        if !fd.flags().is_writable() {
+            return Err(Errno::EINVAL);
+        }
+        vfs().truncate(fd.node(), len).map_err(Into::into)
+    }
+
+    /// Moves the offset of the file descriptor, see [`FileDescriptor::seek`].
+    pub fn seek(&self, fd: Fileno, offset: isize, whence: Whence) -> Result<usize, Errno> {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fd) {
+            Some(fd) => fd,
+            None => return Err(Errno::EBADF),
+        };
+        fd.seek(offset, whence)
+    }
+
+    /// See [`FileDescriptor::read_dir`].
+    pub fn read_dir(&self, fd: Fileno, buf: &mut [u8]) -> Result<usize, Errno> {
+        let guard = self.open_fds().read();
+        let fd = match guard.get(&fd) {
+            Some(fd) => fd,
+            None => return Err(Errno::EBADF),
+        };
+        fd.read_dir(buf)
+    }
+
+    /// Acquires or converts the advisory lock of the file, see [`Vfs::lock`](crate::io::vfs::Vfs::lock).
+    pub fn lock_file(&self, fd: Fileno, kind: LockKind, block: bool) -> Result<(), VfsError> {
+        // waiting for the lock must not block the file descriptor table
+        let node = self.node_of(fd)?;
+        vfs().lock(&node, self.pid, kind, block)
+    }
+
+    /// Releases the advisory lock of the file, see [`Vfs::unlock`](crate::io::vfs::Vfs::unlock).
+    pub fn unlock_file(&self, fd: Fileno) -> Result<(), VfsError> {
+        let node = self.node_of(fd)?;
+        vfs().unlock(&node, self.pid)
+    }
+
+    fn node_of(&self, fd: Fileno) -> Result<VfsNode, VfsError> {
+        self.open_fds()
+            .read()
+            .get(&fd)
+            .map(|descriptor| descriptor.node().clone())
+            .ok_or(VfsError::HandleClosed)
     }
 
     pub fn close_fd(&self, fd: Fileno) -> Result<(), VfsError> {
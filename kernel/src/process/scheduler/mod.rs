@@ -7,11 +7,12 @@ use core::ffi::c_void;
 use core::iter::Cycle;
 use core::pin::Pin;
 use core::ptr;
-use core::sync::atomic::AtomicBool;
 use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicBool, AtomicU64};
 use log::{debug, trace};
 use x86_64::instructions::hlt;
 
+pub use park::{park, Unparker};
 pub use queues::Priority;
 
 use crate::process::attributes::ProcessId;
@@ -21,6 +22,7 @@ use crate::process::thread::ThreadId;
 use crate::process::Priority::{High, Low, Normal, Realtime};
 use crate::process::{process_tree, spawn_thread_in_current_process, Process};
 
+mod park;
 mod queues;
 mod reschedule;
 pub mod thread;
@@ -52,8 +54,11 @@ fn create_stub_thread() -> Pin<Box<Thread>> {
         priority: Low,
         last_stack_ptr: Box::pin(0),
         stack: None,
+        fs_base: AtomicU64::new(0),
         links: Links::default(),
         state: State::Ready,
+        unparker: Unparker::default(),
+        park_deadline: AtomicU64::new(0),
     })
 }
 
@@ -124,9 +129,16 @@ const STRATEGY_LENGTH: usize = 10;
 pub struct Scheduler {
     current_thread: Box<Thread>,
     current_thread_should_exit: AtomicBool,
+    /// Set by [`park`], so that the current thread is parked instead of made
+    /// ready when it's switched away from.
+    current_thread_should_park: AtomicBool,
     current_thread_prio: AtomicPriority,
     strategy: Cycle<IntoIter<Priority, STRATEGY_LENGTH>>,
     ready: Queues<MpscQueue<Thread>>,
+    /// The threads that wait until they're unparked. Only the scheduler
+    /// itself adds and removes them, so it knows how many there are.
+    parked: MpscQueue<Thread>,
+    parked_count: usize,
     _dummy_last_stack_ptr: usize,
 }
 
@@ -141,6 +153,7 @@ impl Scheduler {
         Self {
             current_thread: Box::new(kernel_thread),
             current_thread_should_exit: AtomicBool::new(false),
+            current_thread_should_park: AtomicBool::new(false),
             current_thread_prio: AtomicPriority::new(priority),
             strategy: [
                 Realtime, High, Normal, Realtime, High, Low, Realtime, High, Realtime, Normal,
@@ -153,6 +166,8 @@ impl Scheduler {
                 MpscQueue::new_with_stub(create_stub_thread()),
                 MpscQueue::new_with_stub(create_stub_thread()),
             ),
+            parked: MpscQueue::new_with_stub(create_stub_thread()),
+            parked_count: 0,
             _dummy_last_stack_ptr: 0,
         }
     }
@@ -214,6 +229,9 @@ fn free_thread(thread: Thread) {
             process.name()
         );
 
+        // dropping the process closes its files, which locks file systems, and
+        // some of them (like the procfs) lock the process tree
+        drop(process_tree);
         drop(process);
     }
 }
@@ -0,0 +1,147 @@
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use foundation::time::Instant;
+use x86_64::instructions::hlt;
+
+use crate::process::{current_thread, scheduler};
+use crate::time::HpetInstantProvider;
+
+/// Wakes a thread that is parked with [`park`].
+///
+/// Unparking a thread that isn't parked makes its next [`park`] return right
+/// away, so a wake between checking for a change and parking isn't lost.
+#[derive(Debug, Clone, Default)]
+pub struct Unparker {
+    unparked: Arc<AtomicBool>,
+}
+
+impl Unparker {
+    /// This doesn't wait for any lock and doesn't allocate, so interrupt
+    /// handlers can call it.
+    pub fn unpark(&self) {
+        self.unparked.store(true, Release);
+    }
+
+    pub(in crate::process::scheduler) fn is_unparked(&self) -> bool {
+        self.unparked.load(Acquire)
+    }
+
+    fn take(&self) -> bool {
+        self.unparked.swap(false, Acquire)
+    }
+}
+
+/// Parks the current thread until it's unparked with its
+/// [`Unparker`](crate::process::thread::Thread::unparker), until `deadline`
+/// passes, or until its process terminates. A parked thread isn't scheduled
+/// until then.
+///
+/// This may return before any of that happens, so callers check for what
+/// they wait for in a loop.
+pub fn park(deadline: Option<Instant>) {
+    let thread = current_thread();
+    let scheduler = unsafe { scheduler() };
+    thread
+        .park_deadline
+        .store(deadline.map_or(0, nanos), Relaxed);
+    loop {
+        // the thread must not be parked while it checks whether it's done
+        scheduler.current_thread_should_park.store(false, Relaxed);
+        if thread.unparker.take()
+            || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || thread.process().is_terminating()
+        {
+            break;
+        }
+        // the scheduler parks the thread when it switches away from it,
+        // unless it's unparked by then
+        scheduler.current_thread_should_park.store(true, Relaxed);
+        hlt();
+    }
+    thread.park_deadline.store(0, Relaxed);
+}
+
+/// The current time in nanoseconds since boot, like the deadlines of parked
+/// threads.
+pub(in crate::process::scheduler) fn now() -> u64 {
+    nanos(Instant::now())
+}
+
+fn nanos(instant: Instant) -> u64 {
+    // zero means that there is no deadline
+    (instant.duration_since(Instant::new(0)).as_nanos() as u64).max(1)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::ffi::c_void;
+    use core::ptr;
+    use core::sync::atomic::Ordering::SeqCst;
+    use core::sync::atomic::{AtomicBool, AtomicUsize};
+    use core::time::Duration;
+
+    use conquer_once::spin::OnceCell;
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+    use crate::process::{spawn_thread_in_current_process, Priority};
+
+    static UNPARKER: OnceCell<Unparker> = OnceCell::uninit();
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    static DONE: AtomicBool = AtomicBool::new(false);
+
+    /// Asks to be parked until it is unparked, and counts how often it runs
+    /// in the meantime.
+    extern "C" fn count_runs(_: *mut c_void) {
+        UNPARKER.init_once(|| current_thread().unparker().clone());
+        let scheduler = unsafe { scheduler() };
+        scheduler.current_thread_should_park.store(true, Relaxed);
+        while !current_thread().unparker.take() {
+            RUNS.fetch_add(1, SeqCst);
+            scheduler.current_thread_should_park.store(true, Relaxed);
+            hlt();
+        }
+        DONE.store(true, SeqCst);
+    }
+
+    #[kernel_test]
+    fn test_park_with_deadline() {
+        let start = Instant::now();
+        park(Some(start + Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[kernel_test]
+    fn test_unpark_before_park() {
+        current_thread().unparker().unpark();
+        let start = Instant::now();
+        park(Some(start + Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[kernel_test]
+    fn test_parked_thread_is_not_scheduled() {
+        spawn_thread_in_current_process("parked", Priority::Normal, count_runs, ptr::null_mut());
+        let unparker = loop {
+            match UNPARKER.get() {
+                Some(unparker) => break unparker,
+                None => hlt(),
+            }
+        };
+
+        // the thread runs once until the scheduler parks it, and maybe once
+        // more if an interrupt other than the timer ends its `hlt` early
+        let deadline = Instant::now() + Duration::from_millis(50);
+        park(Some(deadline));
+        let runs = RUNS.load(SeqCst);
+        assert!(runs <= 2, "the parked thread ran {runs} times");
+        assert!(!DONE.load(SeqCst));
+
+        unparker.unpark();
+        while !DONE.load(SeqCst) {
+            hlt();
+        }
+    }
+}
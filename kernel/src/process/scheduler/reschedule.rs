@@ -4,9 +4,10 @@ use core::pin::Pin;
 use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
 
 use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::FsBase;
 
 use crate::arch::switch::switch;
-use crate::process::scheduler::{finished_threads, new_threads};
+use crate::process::scheduler::{finished_threads, new_threads, park};
 use crate::process::thread::{State, Thread};
 use crate::process::{Priority, Scheduler, IN_RESCHEDULE};
 
@@ -50,6 +51,8 @@ impl Scheduler {
 
         // move new threads from queue into scheduler
         self.take_new_threads();
+        // and parked threads that don't have to wait anymore
+        self.unpark_threads();
 
         // compute the next thread
         let next_thread = self.next_thread();
@@ -60,10 +63,20 @@ impl Scheduler {
 
         let process_should_terminate = old_thread.process().should_terminate.load(Acquire);
         let thread_should_exit = self.current_thread_should_exit.swap(false, Relaxed);
+        let thread_should_park = self.current_thread_should_park.swap(false, Relaxed);
         let old_stack_ptr = if thread_should_exit || process_should_terminate {
             old_thread.set_state(State::Finished);
             finished_threads().enqueue(Box::into_pin(old_thread));
             &mut self._dummy_last_stack_ptr as *mut usize
+        } else if thread_should_park && !old_thread.unparker.is_unparked() {
+            // `unpark_threads` moves the thread into the ready queue of this
+            // priority once it doesn't have to wait anymore
+            old_thread.set_state(State::Parked);
+            old_thread.set_priority(priority);
+            let last_stack_ptr = old_thread.last_stack_ptr_mut().as_mut().get_mut() as *mut usize;
+            self.parked.enqueue(Box::into_pin(old_thread));
+            self.parked_count += 1;
+            last_stack_ptr
         } else {
             old_thread.set_state(State::Ready);
             let last_stack_ptr = old_thread.last_stack_ptr_mut().as_mut().get_mut() as *mut usize;
@@ -73,6 +86,8 @@ impl Scheduler {
 
         let new_stack_ptr = *self.current_thread.last_stack_ptr().as_ref() as *const u8;
         let cr3_value = self.current_thread.process().cr3_value();
+        // this is only a register write, so it doesn't need any locks
+        FsBase::write(self.current_thread.fs_base());
 
         IN_RESCHEDULE.store(false, Relaxed);
 
@@ -101,6 +116,31 @@ impl Scheduler {
         }
     }
 
+    /// Moves the parked threads that were unparked, whose deadline passed, or
+    /// whose process terminates, back into the ready queues.
+    fn unpark_threads(&mut self) {
+        // the clock is only read if a parked thread has a deadline
+        let mut now = None;
+        // threads that still wait are enqueued again, behind the ones that
+        // weren't looked at yet
+        for _ in 0..self.parked_count {
+            let Some(mut thread) = self.parked.dequeue() else {
+                break;
+            };
+            let deadline = thread.park_deadline.load(Relaxed);
+            let wakes = thread.unparker.is_unparked()
+                || thread.process().should_terminate.load(Acquire)
+                || (deadline != 0 && deadline <= *now.get_or_insert_with(park::now));
+            if wakes {
+                thread.set_state(State::Ready);
+                self.parked_count -= 1;
+                self.ready[thread.priority()].enqueue(thread);
+            } else {
+                self.parked.enqueue(thread);
+            }
+        }
+    }
+
     fn take_new_threads(&mut self) {
         // We don't care about the err case, whether it is because the queue is empty,
         // in an inconsistent state or busy, we try again anyway. We don't want to way,
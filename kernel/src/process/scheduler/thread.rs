@@ -14,9 +14,11 @@ use core::sync::atomic::AtomicU64;
 use core::sync::atomic::Ordering::Relaxed;
 use derive_more::Display;
 use x86_64::registers::rflags::RFlags;
+use x86_64::VirtAddr;
 
 use crate::mem::Size;
 use crate::process;
+use crate::process::scheduler::park::Unparker;
 use crate::process::{process_tree, Priority, Process};
 
 const STACK_SIZE: usize = Size::KiB(32).bytes();
@@ -40,12 +42,25 @@ impl ThreadId {
         static COUNTER: AtomicU64 = AtomicU64::new(0);
         ThreadId(COUNTER.fetch_add(1, Relaxed))
     }
+
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Thread ids are passed to and from userspace as integers.
+impl From<u64> for ThreadId {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
 }
 
 #[derive(Copy, Clone, Debug, Display, Eq, PartialEq)]
 pub enum State {
     Ready,
     Running,
+    /// The thread waits until it's unparked, see [`park`](crate::process::park).
+    Parked,
     Finished,
 }
 
@@ -56,10 +71,18 @@ pub struct Thread {
     pub(in crate::process::scheduler) priority: Priority, // TODO: move priority into this module
     pub(in crate::process::scheduler) last_stack_ptr: Pin<Box<usize>>,
     pub(in crate::process::scheduler) stack: Option<Vec<u8>>,
+    /// The base of the `fs` segment, which points to the thread control block
+    /// of threads that have thread local storage. It's written on every switch
+    /// to this thread.
+    pub(in crate::process::scheduler) fs_base: AtomicU64,
 
     pub(in crate::process::scheduler) links: Links<Self>,
 
     pub(in crate::process::scheduler) state: State,
+    pub(in crate::process::scheduler) unparker: Unparker,
+    /// When the thread stops being parked even if nobody unparks it, in
+    /// nanoseconds since boot, or zero if it's parked without a deadline.
+    pub(in crate::process::scheduler) park_deadline: AtomicU64,
 }
 
 impl Debug for Thread {
@@ -71,8 +94,10 @@ impl Debug for Thread {
             .field("last_stack_ptr", &self.last_stack_ptr)
             .field("stack_ptr", &self.stack.as_ref().map(|s| s.as_ptr()))
             .field("stack_len", &self.stack.as_ref().map(|s| s.len()))
+            .field("fs_base", &self.fs_base)
             .field("links", &self.links)
             .field("state", &self.state)
+            .field("park_deadline", &self.park_deadline)
             .finish()
     }
 }
@@ -140,6 +165,20 @@ impl Thread {
     pub fn set_priority(&mut self, priority: Priority) {
         self.priority = priority;
     }
+
+    pub fn fs_base(&self) -> VirtAddr {
+        VirtAddr::new(self.fs_base.load(Relaxed))
+    }
+
+    /// Takes effect with the next switch to this thread.
+    pub fn set_fs_base(&self, fs_base: VirtAddr) {
+        self.fs_base.store(fs_base.as_u64(), Relaxed);
+    }
+
+    /// Wakes the thread when it's parked.
+    pub fn unparker(&self) -> &Unparker {
+        &self.unparker
+    }
 }
 
 struct StackWriter<'a> {
@@ -192,8 +231,11 @@ impl Thread {
             priority,
             last_stack_ptr: Box::pin(0), // will be set correctly in [`setup_stack`]
             stack: Some(vec![0; STACK_SIZE]),
+            fs_base: AtomicU64::new(0),
             links: Links::default(),
             state: State::Ready,
+            unparker: Unparker::default(),
+            park_deadline: AtomicU64::new(0),
         };
         thread.setup_stack(entry_point, arg);
         process_tree()
@@ -274,8 +316,11 @@ impl Thread {
             priority: Priority::Normal,
             last_stack_ptr: Box::pin(0), // will be set correctly during the next `reschedule`
             stack: None, // FIXME: use the correct stack on the heap (obtained through the bootloader)
+            fs_base: AtomicU64::new(0),
             links: Links::default(),
             state: State::Running,
+            unparker: Unparker::default(),
+            park_deadline: AtomicU64::new(0),
         }
     }
 }
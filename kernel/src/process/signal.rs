@@ -0,0 +1,412 @@
+use alloc::collections::BTreeMap;
+
+use kernel_api::syscall::{
+    Errno, SigAction, SigActionFlags, SigInfo, SigSet, SignalContext, SigprocmaskHow, NSIG,
+    SIGCHLD, SIGCONT, SIGSTOP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGWINCH, SIG_DFL, SIG_IGN,
+};
+
+use crate::process::thread::ThreadId;
+
+/// Where a thread was when a signal interrupted it, and where it continues
+/// after the handler returns.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Interrupted {
+    pub rip: usize,
+    pub rsp: usize,
+    pub rflags: usize,
+}
+
+/// A handler that a thread is about to enter. The signal trampoline takes it
+/// with [`Signals::take_delivery`] to set up the frame of the handler.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Delivery {
+    pub info: SigInfo,
+    pub handler: usize,
+    pub restorer: usize,
+    pub interrupted: Interrupted,
+    /// The mask that is restored when the handler returns.
+    pub mask: SigSet,
+}
+
+/// What happens to a thread that returns to userspace.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Action {
+    /// It continues where it was.
+    Resume,
+    /// It enters the handler of a signal, which was stored as a
+    /// [`Delivery`] for the thread.
+    Handle,
+    /// The process is terminated by the signal with the given number.
+    Terminate(usize),
+}
+
+/// The signal state of a process.
+///
+/// There is one signal mask for the whole process instead of one per thread,
+/// and signals are only delivered when a thread returns from a syscall or
+/// faults, so a thread that never makes a syscall never sees the signals that
+/// are sent to it.
+#[derive(Debug)]
+pub struct Signals {
+    actions: [SigAction; NSIG],
+    blocked: SigSet,
+    /// The mask that `sigsuspend` replaced, which is restored once the
+    /// handler of the signal that ended the wait returns.
+    suspended_mask: Option<SigSet>,
+    /// Standard signals don't queue, so there is at most one pending instance
+    /// of every signal, and the information of the first one is kept.
+    pending: SigSet,
+    infos: [SigInfo; NSIG],
+    deliveries: BTreeMap<ThreadId, Delivery>,
+    /// The contexts that threads return to after `sigreturn`.
+    returns: BTreeMap<ThreadId, SignalContext>,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signals {
+    pub const fn new() -> Self {
+        Self {
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: SigActionFlags::empty(),
+                restorer: 0,
+                mask: SigSet::EMPTY,
+            }; NSIG],
+            blocked: SigSet::EMPTY,
+            suspended_mask: None,
+            pending: SigSet::EMPTY,
+            infos: [SigInfo {
+                signo: 0,
+                errno: 0,
+                code: 0,
+                pid: 0,
+                uid: 0,
+                addr: 0,
+                status: 0,
+                value: 0,
+            }; NSIG],
+            deliveries: BTreeMap::new(),
+            returns: BTreeMap::new(),
+        }
+    }
+
+    pub fn action(&self, sig: usize) -> Result<SigAction, Errno> {
+        check_signal(sig)?;
+        Ok(self.actions[sig])
+    }
+
+    /// Replaces the action of `sig`, and returns the old one. The action of
+    /// `SIGKILL` and `SIGSTOP` can't be changed.
+    pub fn set_action(&mut self, sig: usize, action: SigAction) -> Result<SigAction, Errno> {
+        check_signal(sig)?;
+        if SigSet::UNBLOCKABLE.contains(sig) {
+            return Err(Errno::EINVAL);
+        }
+        let old = core::mem::replace(&mut self.actions[sig], action);
+        // setting a pending signal to be ignored discards it
+        if action.handler == SIG_IGN || (action.handler == SIG_DFL && ignored_by_default(sig)) {
+            self.pending = self.pending.without(sig);
+        }
+        Ok(old)
+    }
+
+    pub fn mask(&self) -> SigSet {
+        self.blocked
+    }
+
+    /// Changes the mask as described by `how`, and returns the old one.
+    pub fn set_mask(&mut self, how: SigprocmaskHow, set: SigSet) -> SigSet {
+        let old = self.blocked;
+        let new = match how {
+            SigprocmaskHow::Block => old.union(set),
+            SigprocmaskHow::Unblock => old.difference(set),
+            SigprocmaskHow::SetMask => set,
+        };
+        self.blocked = new.difference(SigSet::UNBLOCKABLE);
+        old
+    }
+
+    /// Replaces the mask for `sigsuspend`, until the handler of the next
+    /// signal returns.
+    pub fn suspend(&mut self, mask: SigSet) {
+        let old = self.blocked;
+        self.blocked = mask.difference(SigSet::UNBLOCKABLE);
+        self.suspended_mask = Some(old);
+    }
+
+    /// Makes the signal in `info` pending, unless it's ignored.
+    pub fn send(&mut self, info: SigInfo) {
+        let sig = info.signo as usize;
+        let handler = self.actions[sig].handler;
+        if handler == SIG_IGN || (handler == SIG_DFL && ignored_by_default(sig)) {
+            return;
+        }
+        if !self.pending.contains(sig) {
+            self.pending = self.pending.with(sig);
+            self.infos[sig] = info;
+        }
+    }
+
+    pub fn pending(&self) -> SigSet {
+        self.pending
+    }
+
+    /// Whether a signal is pending that isn't blocked.
+    pub fn is_deliverable(&self) -> bool {
+        !self.pending.difference(self.blocked).is_empty()
+    }
+
+    /// Decides what happens to the thread `tid` that is about to return to
+    /// userspace at `interrupted`, based on the pending signals that aren't
+    /// blocked.
+    pub fn next(&mut self, tid: ThreadId, interrupted: Interrupted) -> Action {
+        while let Some(sig) = self.pending.difference(self.blocked).first() {
+            self.pending = self.pending.without(sig);
+            let info = self.infos[sig];
+            if let Some(action) = self.deliver(sig, info, tid, interrupted) {
+                return action;
+            }
+        }
+        // a signal that ended `sigsuspend` may have been ignored in the meantime
+        if let Some(mask) = self.suspended_mask.take() {
+            self.blocked = mask;
+        }
+        Action::Resume
+    }
+
+    /// Decides what happens to the thread `tid` that caused a fault that
+    /// raises the signal in `info`. A fault can't be ignored or blocked, so
+    /// if there is no handler, the process is terminated.
+    pub fn fault(&mut self, info: SigInfo, tid: ThreadId, interrupted: Interrupted) -> Action {
+        let sig = info.signo as usize;
+        let handler = self.actions[sig].handler;
+        if self.blocked.contains(sig) || handler == SIG_DFL || handler == SIG_IGN {
+            return Action::Terminate(sig);
+        }
+        self.deliver(sig, info, tid, interrupted)
+            .unwrap_or(Action::Terminate(sig))
+    }
+
+    fn deliver(
+        &mut self,
+        sig: usize,
+        info: SigInfo,
+        tid: ThreadId,
+        interrupted: Interrupted,
+    ) -> Option<Action> {
+        let action = self.actions[sig];
+        match action.handler {
+            SIG_IGN => None,
+            SIG_DFL if ignored_by_default(sig) => None,
+            SIG_DFL => Some(Action::Terminate(sig)),
+            handler => {
+                let mask = self.suspended_mask.take().unwrap_or(self.blocked);
+                let mut blocked = self.blocked.union(action.mask);
+                if !action.flags.contains(SigActionFlags::SA_NODEFER) {
+                    blocked = blocked.with(sig);
+                }
+                self.blocked = blocked.difference(SigSet::UNBLOCKABLE);
+                if action.flags.contains(SigActionFlags::SA_RESETHAND) {
+                    self.actions[sig] = SigAction::default();
+                }
+                self.deliveries.insert(
+                    tid,
+                    Delivery {
+                        info,
+                        handler,
+                        restorer: action.restorer,
+                        interrupted,
+                        mask,
+                    },
+                );
+                Some(Action::Handle)
+            }
+        }
+    }
+
+    pub fn take_delivery(&mut self, tid: ThreadId) -> Option<Delivery> {
+        self.deliveries.remove(&tid)
+    }
+
+    /// Remembers that the thread `tid` returns to `context` once the current
+    /// syscall is done, and restores the mask of the context.
+    pub fn sigreturn(&mut self, tid: ThreadId, context: SignalContext) {
+        self.blocked = context.mask.difference(SigSet::UNBLOCKABLE);
+        self.returns.insert(tid, context);
+    }
+
+    pub fn take_return(&mut self, tid: ThreadId) -> Option<SignalContext> {
+        self.returns.remove(&tid)
+    }
+}
+
+/// Fails with [`Errno::EINVAL`] if `sig` isn't a valid signal number.
+pub fn check_signal(sig: usize) -> Result<(), Errno> {
+    if SigSet::is_valid(sig) {
+        Ok(())
+    } else {
+        Err(Errno::EINVAL)
+    }
+}
+
+/// Whether the default action of `sig` is to ignore it. Stopping isn't
+/// supported, so the stop signals are ignored as well.
+pub fn ignored_by_default(sig: usize) -> bool {
+    matches!(
+        sig,
+        SIGCHLD | SIGCONT | SIGURG | SIGWINCH | SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU
+    )
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_api::syscall::{SIGINT, SIGKILL, SIGSEGV, SIGUSR1, SIGUSR2, SI_USER};
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+
+    const AT: Interrupted = Interrupted {
+        rip: 0x1000,
+        rsp: 0x2000,
+        rflags: 0x202,
+    };
+
+    fn tid() -> ThreadId {
+        ThreadId::from(42_u64)
+    }
+
+    fn user(sig: usize) -> SigInfo {
+        SigInfo {
+            signo: sig as i32,
+            code: SI_USER,
+            pid: 7,
+            ..Default::default()
+        }
+    }
+
+    fn handler(address: usize) -> SigAction {
+        SigAction {
+            handler: address,
+            flags: SigActionFlags::SA_RESTORER,
+            restorer: 0x4000,
+            mask: SigSet::EMPTY.with(SIGUSR2),
+        }
+    }
+
+    #[kernel_test]
+    fn test_default_action() {
+        let mut signals = Signals::new();
+        signals.send(user(SIGCHLD));
+        assert!(signals.pending().is_empty());
+        signals.send(user(SIGUSR1));
+        assert_eq!(Action::Terminate(SIGUSR1), signals.next(tid(), AT));
+        assert_eq!(Action::Resume, signals.next(tid(), AT));
+    }
+
+    #[kernel_test]
+    fn test_handler() {
+        let mut signals = Signals::new();
+        signals.set_action(SIGUSR1, handler(0x3000)).unwrap();
+        signals.send(user(SIGUSR1));
+        assert_eq!(Action::Handle, signals.next(tid(), AT));
+
+        let delivery = signals.take_delivery(tid()).unwrap();
+        assert_eq!(0x3000, delivery.handler);
+        assert_eq!(0x4000, delivery.restorer);
+        assert_eq!(7, delivery.info.pid);
+        assert_eq!(AT, delivery.interrupted);
+        assert_eq!(SigSet::EMPTY, delivery.mask);
+        // the signal itself and the mask of the action are blocked in the handler
+        assert_eq!(SigSet::EMPTY.with(SIGUSR1).with(SIGUSR2), signals.mask());
+
+        signals.sigreturn(
+            tid(),
+            SignalContext {
+                mask: delivery.mask,
+                ..Default::default()
+            },
+        );
+        assert_eq!(SigSet::EMPTY, signals.mask());
+        assert!(signals.take_return(tid()).is_some());
+        assert!(signals.take_return(tid()).is_none());
+    }
+
+    #[kernel_test]
+    fn test_blocked_stays_pending() {
+        let mut signals = Signals::new();
+        signals.set_action(SIGUSR1, handler(0x3000)).unwrap();
+        signals.set_mask(SigprocmaskHow::Block, SigSet::EMPTY.with(SIGUSR1));
+        signals.send(user(SIGUSR1));
+        signals.send(user(SIGUSR1));
+        assert!(!signals.is_deliverable());
+        assert_eq!(Action::Resume, signals.next(tid(), AT));
+
+        signals.set_mask(SigprocmaskHow::Unblock, SigSet::EMPTY.with(SIGUSR1));
+        assert!(signals.is_deliverable());
+        assert_eq!(Action::Handle, signals.next(tid(), AT));
+        // the second one didn't queue
+        assert!(signals.pending().is_empty());
+    }
+
+    #[kernel_test]
+    fn test_unblockable() {
+        let mut signals = Signals::new();
+        assert_eq!(
+            Err(Errno::EINVAL),
+            signals.set_action(SIGKILL, handler(0x3000))
+        );
+        assert_eq!(Err(Errno::EINVAL), signals.action(0));
+        assert_eq!(Err(Errno::EINVAL), signals.action(NSIG));
+        signals.set_mask(SigprocmaskHow::SetMask, SigSet::FULL);
+        assert!(!signals.mask().contains(SIGKILL));
+        assert!(signals.mask().contains(SIGINT));
+    }
+
+    #[kernel_test]
+    fn test_resethand() {
+        let mut signals = Signals::new();
+        let mut action = handler(0x3000);
+        action.flags |= SigActionFlags::SA_RESETHAND | SigActionFlags::SA_NODEFER;
+        signals.set_action(SIGUSR1, action).unwrap();
+        signals.send(user(SIGUSR1));
+        assert_eq!(Action::Handle, signals.next(tid(), AT));
+        assert!(!signals.mask().contains(SIGUSR1));
+        assert_eq!(SIG_DFL, signals.action(SIGUSR1).unwrap().handler);
+    }
+
+    #[kernel_test]
+    fn test_suspend() {
+        let mut signals = Signals::new();
+        signals.set_action(SIGUSR1, handler(0x3000)).unwrap();
+        signals.set_mask(SigprocmaskHow::SetMask, SigSet::EMPTY.with(SIGUSR1));
+        signals.send(user(SIGUSR1));
+        signals.suspend(SigSet::EMPTY);
+        assert!(signals.is_deliverable());
+        assert_eq!(Action::Handle, signals.next(tid(), AT));
+        // the handler returns to the mask from before sigsuspend
+        let delivery = signals.take_delivery(tid()).unwrap();
+        assert_eq!(SigSet::EMPTY.with(SIGUSR1), delivery.mask);
+    }
+
+    #[kernel_test]
+    fn test_fault() {
+        let mut signals = Signals::new();
+        let info = SigInfo {
+            signo: SIGSEGV as i32,
+            addr: 0x10,
+            ..Default::default()
+        };
+        assert_eq!(Action::Terminate(SIGSEGV), signals.fault(info, tid(), AT));
+
+        signals.set_action(SIGSEGV, handler(0x3000)).unwrap();
+        assert_eq!(Action::Handle, signals.fault(info, tid(), AT));
+        assert_eq!(0x10, signals.take_delivery(tid()).unwrap().info.addr);
+        // a fault inside the handler, where SIGSEGV is blocked
+        assert_eq!(Action::Terminate(SIGSEGV), signals.fault(info, tid(), AT));
+    }
+}
@@ -44,6 +44,17 @@ impl ProcessTree {
         self.processes_by_id.get(process_id)
     }
 
+    /// Returns the ids of all processes, in ascending order.
+    pub fn process_ids(&self) -> impl Iterator<Item = &ProcessId> {
+        self.processes_by_id.keys()
+    }
+
+    /// Returns the id of the parent of the given process, or `None` for the
+    /// root process.
+    pub fn parent_of(&self, process_id: &ProcessId) -> Option<&ProcessId> {
+        self.parents.get(process_id)
+    }
+
     pub fn set_root(&mut self, process: Arc<Process>) {
         if self.root_pid.is_some() {
             panic!("root process already set");
@@ -177,7 +188,7 @@ impl ProcessTree {
     }
 }
 
-fn page_table_flags_to_string(flags: PageTableFlags) -> String {
+pub(crate) fn page_table_flags_to_string(flags: PageTableFlags) -> String {
     macro_rules! flag {
         ($buf:expr, $flag:expr, $char:literal) => {
             if flags.contains($flag) {
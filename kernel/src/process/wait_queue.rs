@@ -0,0 +1,111 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::sync::atomic::AtomicU64;
+use core::sync::atomic::Ordering::Relaxed;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::process::{current_thread, Unparker};
+
+/// The threads that wait for a file to change, for example for a pipe to
+/// become readable. Whatever changes the file wakes its queue.
+///
+/// A thread registers before it checks the file, and then parks until it's
+/// woken. A change after registering unparks it, so it's never missed.
+#[derive(Debug, Default)]
+pub struct WaitQueue {
+    waiters: Mutex<BTreeMap<u64, Unparker>>,
+    next_ticket: AtomicU64,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        Self {
+            waiters: Mutex::new(BTreeMap::new()),
+            next_ticket: AtomicU64::new(0),
+        }
+    }
+
+    /// Registers the current thread, which is woken by every
+    /// [`WaitQueue::wake_all`] until the returned waiter is dropped.
+    pub fn register(self: &Arc<Self>) -> Waiter {
+        let ticket = self.next_ticket.fetch_add(1, Relaxed);
+        let unparker = current_thread().unparker().clone();
+        // interrupt handlers wake queues, so they must not interrupt us while
+        // we hold the lock
+        interrupts::without_interrupts(|| self.waiters.lock().insert(ticket, unparker));
+        Waiter {
+            queue: self.clone(),
+            ticket,
+        }
+    }
+
+    /// Wakes every registered thread, so that it checks its files again.
+    ///
+    /// This doesn't allocate or free memory, so interrupt handlers can call it.
+    pub fn wake_all(&self) {
+        interrupts::without_interrupts(|| {
+            self.waiters.lock().values().for_each(Unparker::unpark);
+        });
+    }
+}
+
+/// A thread that is registered in a [`WaitQueue`] until this is dropped.
+#[derive(Debug)]
+pub struct Waiter {
+    queue: Arc<WaitQueue>,
+    ticket: u64,
+}
+
+impl Drop for Waiter {
+    fn drop(&mut self) {
+        let unparker =
+            interrupts::without_interrupts(|| self.queue.waiters.lock().remove(&self.ticket));
+        // freed after the lock is released
+        drop(unparker);
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use core::time::Duration;
+
+    use foundation::time::Instant;
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+    use crate::process::park;
+    use crate::time::HpetInstantProvider;
+
+    #[kernel_test]
+    fn test_wake_all() {
+        let queue = Arc::new(WaitQueue::new());
+        let first = queue.register();
+        let second = queue.register();
+        assert_eq!(2, queue.waiters.lock().len());
+
+        queue.wake_all();
+        let start = Instant::now();
+        park(Some(start + Duration::from_secs(5)));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        drop(first);
+        drop(second);
+        assert!(queue.waiters.lock().is_empty());
+    }
+
+    #[kernel_test]
+    fn test_wake_after_drop() {
+        // forget wakes from earlier tests
+        park(Some(Instant::now()));
+        let queue = Arc::new(WaitQueue::new());
+        drop(queue.register());
+        queue.wake_all();
+
+        // only registered threads are woken
+        let start = Instant::now();
+        park(Some(start + Duration::from_millis(20)));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
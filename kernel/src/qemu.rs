@@ -1,3 +1,6 @@
+use alloc::string::String;
+use alloc::vec;
+
 use x86_64::instructions::port::Port;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
@@ -13,3 +16,66 @@ pub fn exit(exit_code: ExitCode) -> ! {
     }
     unreachable!()
 }
+
+/// The fw_cfg file that holds the kernel command line. The bootloader doesn't
+/// pass a command line to the kernel, so the host passes it to QEMU with
+/// `-fw_cfg name=opt/devos/cmdline,string=...` instead.
+const COMMAND_LINE_FILE: &[u8] = b"opt/devos/cmdline";
+
+/// Returns the kernel command line, or an empty string if the host didn't
+/// pass one, or if the kernel doesn't run in QEMU.
+pub fn command_line() -> String {
+    let mut fw_cfg = FwCfg::new();
+    fw_cfg.select(FwCfg::SIGNATURE);
+    if fw_cfg.read::<4>() != *b"QEMU" {
+        return String::new();
+    }
+
+    fw_cfg.select(FwCfg::FILE_DIR);
+    let count = u32::from_be_bytes(fw_cfg.read());
+    for _ in 0..count {
+        let size = u32::from_be_bytes(fw_cfg.read());
+        let key = u16::from_be_bytes(fw_cfg.read());
+        let _reserved = fw_cfg.read::<2>();
+        let name = fw_cfg.read::<56>();
+        if name.split(|&b| b == 0).next() == Some(COMMAND_LINE_FILE) {
+            fw_cfg.select(key);
+            let mut content = vec![0; size as usize];
+            content.fill_with(|| fw_cfg.read_byte());
+            let content = content.split(|&b| b == 0).next().unwrap_or_default();
+            return String::from_utf8_lossy(content).into_owned();
+        }
+    }
+    String::new()
+}
+
+/// The firmware configuration device of QEMU, through the legacy I/O ports.
+/// See <https://www.qemu.org/docs/master/specs/fw_cfg.html>.
+struct FwCfg {
+    selector: Port<u16>,
+    data: Port<u8>,
+}
+
+impl FwCfg {
+    const SIGNATURE: u16 = 0x0000;
+    const FILE_DIR: u16 = 0x0019;
+
+    fn new() -> Self {
+        Self {
+            selector: Port::new(0x510),
+            data: Port::new(0x511),
+        }
+    }
+
+    fn select(&mut self, key: u16) {
+        unsafe { self.selector.write(key) }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        unsafe { self.data.read() }
+    }
+
+    fn read<const N: usize>(&mut self) -> [u8; N] {
+        core::array::from_fn(|_| self.read_byte())
+    }
+}
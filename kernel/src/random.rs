@@ -0,0 +1,49 @@
+use core::arch::x86_64::_rdtsc;
+
+use conquer_once::spin::OnceCell;
+use foundation::random::{ChaCha20Rng, RandomSource};
+use spin::Mutex;
+
+use crate::driver::hpet::hpet;
+
+static RNG: OnceCell<Mutex<ChaCha20Rng>> = OnceCell::uninit();
+
+/// The number of timing samples that the generator is seeded with.
+const SEED_SAMPLES: usize = 1024;
+
+fn rng() -> &'static Mutex<ChaCha20Rng> {
+    RNG.get_or_init(|| Mutex::new(ChaCha20Rng::from_seed(collect_seed())))
+}
+
+/// Fills `buf` with random bytes from the kernel's random number generator,
+/// which is seeded at first use.
+pub fn fill_bytes(buf: &mut [u8]) {
+    rng().lock().fill_bytes(buf);
+}
+
+/// Mixes `entropy` into the kernel's random number generator.
+pub fn add_entropy(entropy: &[u8]) {
+    rng().lock().add_entropy(entropy);
+}
+
+/// Collects a seed from the jitter between the TSC and the HPET. That is not
+/// much entropy, but it's what we have until there are real entropy sources,
+/// which can feed the generator through [`add_entropy`].
+fn collect_seed() -> [u8; 32] {
+    let mut words = [0_u64; 4];
+    for i in 0..SEED_SAMPLES {
+        let before = unsafe { _rdtsc() };
+        let counter = hpet().read().main_counter_value();
+        let after = unsafe { _rdtsc() };
+
+        let sample = after.wrapping_sub(before).rotate_left(i as u32 % 64) ^ counter ^ after;
+        let word = &mut words[i % words.len()];
+        *word = (word.rotate_left(13) ^ sample).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    let mut seed = [0_u8; 32];
+    for (chunk, word) in seed.as_chunks_mut::<8>().0.iter_mut().zip(words) {
+        *chunk = word.to_le_bytes();
+    }
+    seed
+}
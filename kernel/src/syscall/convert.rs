@@ -1,5 +1,7 @@
-use core::fmt::Pointer;
+use core::fmt::{Debug, Pointer};
+use core::marker::PhantomData;
 use core::ops::Deref;
+use core::ptr;
 use core::slice::{from_raw_parts, from_raw_parts_mut};
 
 use derive_more::Display;
@@ -14,6 +16,12 @@ pub struct NotInUserspace;
 
 impl core::error::Error for NotInUserspace {}
 
+impl From<NotInUserspace> for Errno {
+    fn from(_: NotInUserspace) -> Self {
+        Errno::EFAULT
+    }
+}
+
 pub struct UserspaceAddress(VirtAddr);
 
 impl Pointer for UserspaceAddress {
@@ -52,14 +60,129 @@ pub struct UserspaceRange {
 
 impl UserspaceRange {
     pub fn try_from(start: UserspaceAddress, len: usize) -> Result<Self, NotInUserspace> {
-        assert!(len > 0);
-        let end_incl = *start + len - 1_usize;
-        let _ = UserspaceAddress::try_from(end_incl.as_u64() as usize)?;
-
+        let _ = userspace_end(start.as_u64() as usize, len)?;
         Ok(Self { start, len })
     }
 }
 
+/// Returns the (exclusive) end of the range that starts at `addr` and is `len`
+/// bytes long, if the range is completely in userspace. Ranges whose end doesn't
+/// fit into a `usize` are not in userspace.
+fn userspace_end(addr: usize, len: usize) -> Result<usize, NotInUserspace> {
+    match addr.checked_add(len) {
+        Some(end) if end <= USERSPACE_END => Ok(end),
+        _ => Err(NotInUserspace),
+    }
+}
+
+/// A pointer to a `T` in userspace, which the kernel may write to.
+/// Creating one checks that the whole `T` is located in userspace, but
+/// it doesn't check whether the memory is mapped.
+pub struct UserspaceMutPtr<T> {
+    addr: usize,
+    _type: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserspaceMutPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserspaceMutPtr<T> {}
+
+impl<T> Debug for UserspaceMutPtr<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "UserspaceMutPtr({:#x})", self.addr)
+    }
+}
+
+impl<T> TryFrom<usize> for UserspaceMutPtr<T> {
+    type Error = NotInUserspace;
+
+    fn try_from(addr: usize) -> Result<Self, Self::Error> {
+        let _ = userspace_end(addr, size_of::<T>())?;
+        Ok(Self {
+            addr,
+            _type: PhantomData,
+        })
+    }
+}
+
+impl<T> UserspaceMutPtr<T> {
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// Reinterprets this pointer as a pointer to a `U`, checking that the
+    /// whole `U` is located in userspace.
+    pub fn cast<U>(self) -> Result<UserspaceMutPtr<U>, NotInUserspace> {
+        UserspaceMutPtr::try_from(self.addr)
+    }
+
+    /// Checks that `len` consecutive `T`s starting at this pointer are located
+    /// in userspace, and returns [`Errno::EFAULT`] otherwise.
+    pub fn validate(&self, len: usize) -> Result<(), Errno> {
+        let bytes = len.checked_mul(size_of::<T>()).ok_or(Errno::EFAULT)?;
+        userspace_end(self.addr, bytes)?;
+        Ok(())
+    }
+
+    /// Returns a slice of `len` elements starting at this pointer.
+    ///
+    /// # Safety
+    /// The caller must ensure that the range has been checked with
+    /// [`UserspaceMutPtr::validate`], that it's mapped and writable,
+    /// that the pointer is aligned for `T` and that nothing else accesses
+    /// the memory for the lifetime of the slice.
+    pub unsafe fn as_mut_slice(&mut self, len: usize) -> &mut [T] {
+        debug_assert!(self.validate(len).is_ok());
+        unsafe { from_raw_parts_mut(self.addr as *mut T, len) }
+    }
+
+    /// Copies `data` to userspace, starting at this pointer.
+    /// Returns [`Errno::EFAULT`] if the destination is not completely in userspace.
+    pub fn write_slice(&mut self, data: &[T]) -> Result<(), Errno>
+    where
+        T: Copy,
+    {
+        self.validate(data.len())?;
+        // copy bytes, since userspace pointers don't have to be aligned
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.addr as *mut u8,
+                size_of_val(data),
+            )
+        };
+        Ok(())
+    }
+
+    /// Writes the given value to userspace.
+    /// Returns [`Errno::EFAULT`] if the destination is not completely in userspace.
+    pub fn write_value(&mut self, value: T) -> Result<(), Errno>
+    where
+        T: Copy,
+    {
+        self.validate(1)?;
+        unsafe { (self.addr as *mut T).write_unaligned(value) };
+        Ok(())
+    }
+
+    /// Reads a value from userspace.
+    ///
+    /// # Safety
+    /// The caller must ensure that the memory that this pointer points to
+    /// is mapped and contains a valid `T`.
+    pub unsafe fn read(self) -> T {
+        unsafe { (self.addr as *const T).read_unaligned() }
+    }
+}
+
 pub trait TryFromUserspaceAddress: Sized {
     type Error;
 
@@ -106,3 +229,41 @@ impl TryFromUserspaceRange for &[u8] {
         Ok(unsafe { from_raw_parts(ptr, len) })
     }
 }
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_api::syscall::Errno;
+    use kernel_test_framework::kernel_test;
+
+    use crate::syscall::convert::{
+        UserspaceAddress, UserspaceMutPtr, UserspaceRange, USERSPACE_END,
+    };
+
+    #[kernel_test]
+    fn test_range_at_userspace_boundary() {
+        let ptr = UserspaceMutPtr::<u8>::try_from(USERSPACE_END - 16).unwrap();
+        assert_eq!(Ok(()), ptr.validate(16));
+        assert_eq!(Err(Errno::EFAULT), ptr.validate(17));
+
+        let start = UserspaceAddress::try_from(USERSPACE_END - 16).unwrap();
+        assert!(UserspaceRange::try_from(start, 16).is_ok());
+        let start = UserspaceAddress::try_from(USERSPACE_END - 16).unwrap();
+        assert!(UserspaceRange::try_from(start, 17).is_err());
+
+        assert!(UserspaceMutPtr::<u64>::try_from(USERSPACE_END - 8).is_ok());
+        assert!(UserspaceMutPtr::<u64>::try_from(USERSPACE_END - 7).is_err());
+    }
+
+    #[kernel_test]
+    fn test_range_overflow() {
+        let ptr = UserspaceMutPtr::<u8>::try_from(0x1000).unwrap();
+        assert_eq!(Err(Errno::EFAULT), ptr.validate(usize::MAX));
+
+        // the length in bytes overflows, but the number of elements doesn't
+        let ptr = UserspaceMutPtr::<u64>::try_from(0x1000).unwrap();
+        assert_eq!(Err(Errno::EFAULT), ptr.validate(usize::MAX / 4));
+
+        let start = UserspaceAddress::try_from(0x1000).unwrap();
+        assert!(UserspaceRange::try_from(start, usize::MAX).is_err());
+    }
+}
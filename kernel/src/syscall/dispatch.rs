@@ -1,18 +1,36 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::ffi::CStr;
 use core::ptr;
 use core::slice::from_raw_parts;
+use core::time::Duration;
 
-use kernel_api::syscall::{Errno, FfiSockAddr, SocketDomain, SocketType, Stat, Syscall};
-use kernel_api::PATH_MAX;
+use x86_64::VirtAddr;
 
+use kernel_api::syscall::{
+    ClockId, Errno, FfiSockAddr, FlockOperation, FutexOp, PollFd, SigAction, SigActionFlags,
+    SigSet, SignalFrame, SigprocmaskHow, SocketDomain, SocketType, Stat, Syscall, Timespec, Whence,
+    AT_FDCWD, POLL_NFDS_MAX, SYS_MAX,
+};
+use kernel_api::{ARG_MAX, PATH_MAX};
+
+use crate::process;
 use crate::process::fd::Fileno;
+use crate::process::thread::ThreadId;
 use crate::syscall::convert::{
-    TryFromUserspaceAddress, TryFromUserspaceRange, UserspaceAddress, UserspaceRange,
+    TryFromUserspaceAddress, TryFromUserspaceRange, UserspaceAddress, UserspaceMutPtr,
+    UserspaceRange,
 };
 use crate::syscall::error::Result;
+use crate::syscall::trace;
 use crate::syscall::{
-    sys_access, sys_bind, sys_close, sys_exit, sys_mmap, sys_read, sys_socket, sys_stat, sys_write,
-    MapFlags, Prot,
+    sys_access, sys_bind, sys_chdir, sys_clock_gettime, sys_close, sys_exit, sys_flock, sys_fstat,
+    sys_ftruncate, sys_futex, sys_getcwd, sys_getdents, sys_getpid, sys_ioctl, sys_kill, sys_link,
+    sys_lseek, sys_mkdir, sys_mmap, sys_munmap, sys_nanosleep, sys_openat, sys_pipe, sys_poll,
+    sys_read, sys_readlink, sys_rename, sys_rmdir, sys_set_tls, sys_sigaction, sys_sigprocmask,
+    sys_sigreturn, sys_sigsuspend, sys_socket, sys_spawn, sys_stat, sys_thread_create,
+    sys_thread_exit, sys_thread_join, sys_traceme, sys_unlink, sys_waitpid, sys_write, MapFlags,
+    Prot,
 };
 use crate::syscall::{sys_open, AMode};
 
@@ -21,6 +39,180 @@ fn check_is_userspace(arg: usize) -> Result<()> {
     Ok(())
 }
 
+/// The raw argument registers of a syscall, in order.
+pub type SyscallArgs = [usize; 6];
+
+type SyscallHandler = fn(&SyscallArgs) -> Result<usize>;
+
+/// Converts the successful result of a syscall into the raw value that is
+/// returned to userspace in `rax`.
+trait IntoReturnValue {
+    fn into_return_value(self) -> usize;
+}
+
+impl IntoReturnValue for () {
+    fn into_return_value(self) -> usize {
+        0
+    }
+}
+
+impl IntoReturnValue for usize {
+    fn into_return_value(self) -> usize {
+        self
+    }
+}
+
+impl IntoReturnValue for Fileno {
+    fn into_return_value(self) -> usize {
+        self.as_usize()
+    }
+}
+
+/// How a raw syscall argument is interpreted. This is used to decode
+/// arguments when tracing syscalls.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArgKind {
+    /// A signed or unsigned integer, or flags.
+    Int,
+    /// A file descriptor.
+    Fd,
+    /// A pointer into userspace memory.
+    Ptr,
+    /// A pointer to a nul-terminated path in userspace memory.
+    Path,
+}
+
+/// An entry in the [`SYSCALL_TABLE`].
+#[derive(Copy, Clone)]
+pub struct SyscallEntry {
+    syscall: Syscall,
+    args: &'static [ArgKind],
+    handler: SyscallHandler,
+}
+
+impl SyscallEntry {
+    const fn new(syscall: Syscall, args: &'static [ArgKind], handler: SyscallHandler) -> Self {
+        assert!(args.len() <= 6, "syscalls can have at most 6 arguments");
+        Self {
+            syscall,
+            args,
+            handler,
+        }
+    }
+
+    pub fn syscall(&self) -> Syscall {
+        self.syscall
+    }
+
+    /// The kinds of the arguments that the handler of this syscall uses.
+    pub fn args(&self) -> &'static [ArgKind] {
+        self.args
+    }
+
+    /// The number of arguments that the handler of this syscall uses.
+    pub fn arity(&self) -> usize {
+        self.args.len()
+    }
+}
+
+/// Creates the [`SyscallEntry`] of a syscall, whose handler is called with the
+/// raw arguments in order, one for each of the given argument kinds. A handler
+/// that takes a different number of arguments than the syscall has kinds
+/// doesn't compile, so the arity in the table always matches the handler.
+macro_rules! entry {
+    (@arg $args:ident, $kind:ident) => {
+        // there are at most 6 kinds, see `SyscallEntry::new`
+        $args.next().unwrap()
+    };
+    ($syscall:ident, $handler:ident()) => {
+        SyscallEntry::new(Syscall::$syscall, &[], |_| {
+            $handler().map(IntoReturnValue::into_return_value)
+        })
+    };
+    ($syscall:ident, $handler:ident($($kind:ident),+) -> !) => {
+        SyscallEntry::new(Syscall::$syscall, &[$(ArgKind::$kind),+], |args| {
+            let mut args = args.iter().copied();
+            $handler($(entry!(@arg args, $kind)),+)
+        })
+    };
+    ($syscall:ident, $handler:ident($($kind:ident),+)) => {
+        SyscallEntry::new(Syscall::$syscall, &[$(ArgKind::$kind),+], |args| {
+            let mut args = args.iter().copied();
+            $handler($(entry!(@arg args, $kind)),+).map(IntoReturnValue::into_return_value)
+        })
+    };
+}
+
+/// The syscall table, indexed by syscall number. This is the single place
+/// where syscalls are registered. Syscalls that are known, but not implemented
+/// yet, should be registered with [`dispatch_enosys`].
+static SYSCALL_TABLE: [SyscallEntry; SYS_MAX] = [
+    entry!(Read, dispatch_sys_read(Fd, Ptr, Int)),
+    entry!(Write, dispatch_sys_write(Fd, Ptr, Int)),
+    entry!(Open, dispatch_sys_open(Path, Int, Int)),
+    entry!(Close, dispatch_sys_close(Fd)),
+    entry!(Mmap, dispatch_sys_mmap(Ptr, Int, Int, Int, Fd, Int)),
+    entry!(Access, dispatch_sys_access(Path, Int)),
+    entry!(Exit, dispatch_sys_exit(Int) -> !),
+    entry!(Socket, dispatch_sys_socket(Int, Int, Int)),
+    entry!(Bind, dispatch_sys_bind(Int, Ptr, Int)),
+    entry!(Stat, dispatch_sys_stat(Path, Ptr)),
+    entry!(Poll, dispatch_sys_poll(Ptr, Int, Int)),
+    entry!(Pipe, dispatch_sys_pipe(Ptr)),
+    entry!(TraceMe, dispatch_sys_traceme(Int)),
+    entry!(Ioctl, dispatch_sys_ioctl(Fd, Int, Ptr)),
+    entry!(Mkdir, dispatch_sys_mkdir(Path, Int)),
+    entry!(Unlink, dispatch_sys_unlink(Path)),
+    entry!(Rmdir, dispatch_sys_rmdir(Path)),
+    entry!(Rename, dispatch_sys_rename(Path, Path)),
+    entry!(Ftruncate, dispatch_sys_ftruncate(Fd, Int)),
+    entry!(Openat, dispatch_sys_openat(Fd, Path, Int, Int)),
+    entry!(Chdir, dispatch_sys_chdir(Path)),
+    entry!(Getcwd, dispatch_sys_getcwd(Ptr, Int)),
+    entry!(Readlink, dispatch_sys_readlink(Path, Ptr, Int)),
+    entry!(Fstat, dispatch_sys_fstat(Fd, Ptr)),
+    entry!(Flock, dispatch_sys_flock(Fd, Int)),
+    entry!(Link, dispatch_sys_link(Path, Path)),
+    entry!(Lseek, dispatch_sys_lseek(Fd, Int, Int)),
+    entry!(Munmap, dispatch_sys_munmap(Ptr, Int)),
+    entry!(ThreadCreate, dispatch_sys_thread_create(Ptr, Int, Ptr, Ptr)),
+    entry!(ThreadExit, dispatch_sys_thread_exit(Int) -> !),
+    entry!(ThreadJoin, dispatch_sys_thread_join(Int)),
+    entry!(SetTls, dispatch_sys_set_tls(Ptr)),
+    entry!(Futex, dispatch_sys_futex(Ptr, Int, Int)),
+    entry!(Spawn, dispatch_sys_spawn(Path, Ptr, Ptr)),
+    entry!(Waitpid, dispatch_sys_waitpid(Int)),
+    entry!(ClockGettime, dispatch_sys_clock_gettime(Int, Ptr)),
+    entry!(Getdents, dispatch_sys_getdents(Fd, Ptr, Int)),
+    entry!(Sigaction, dispatch_sys_sigaction(Int, Ptr, Ptr)),
+    entry!(Sigprocmask, dispatch_sys_sigprocmask(Int, Ptr, Ptr)),
+    entry!(Kill, dispatch_sys_kill(Int, Int)),
+    entry!(Sigreturn, dispatch_sys_sigreturn(Ptr)),
+    entry!(Sigsuspend, dispatch_sys_sigsuspend(Ptr)),
+    entry!(Getpid, sys_getpid()),
+    entry!(Nanosleep, dispatch_sys_nanosleep(Ptr, Ptr)),
+];
+
+// Every entry must be at the index of its syscall number. Together with the
+// length of the table being `SYS_MAX`, this makes sure that every syscall has
+// exactly one entry.
+const _: () = {
+    let mut i = 0;
+    while i < SYS_MAX {
+        assert!(
+            SYSCALL_TABLE[i].syscall as usize == i,
+            "syscall table entry is not at the index of its syscall number"
+        );
+        i += 1;
+    }
+};
+
+/// Returns the table entry for the given syscall number, or [`None`] if there is
+/// no such syscall.
+pub fn syscall_entry(syscall: usize) -> Option<&'static SyscallEntry> {
+    SYSCALL_TABLE.get(syscall)
+}
+
 /// Dispatches syscalls. Inputs are the raw register values, the return value
 /// is the result of the syscall that is identified by the [`syscall`] argument.
 // not unsafe because the caller can't do much about the argument validity anyway
@@ -33,24 +225,32 @@ pub fn dispatch_syscall(
     arg5: usize,
     arg6: usize,
 ) -> isize {
-    let syscall = match Syscall::try_from(syscall) {
-        Ok(v) => v,
-        Err(_) => return Errno::ENOSYS.as_isize(),
+    let entry = match syscall_entry(syscall) {
+        Some(entry) => entry,
+        None => return Errno::ENOSYS.into(),
     };
 
-    let syscall_result = match syscall {
-        Syscall::Access => dispatch_sys_access(arg1, arg2).map(Errno::from),
-        Syscall::Close => dispatch_sys_close(arg1).map(Errno::from),
-        Syscall::Exit => dispatch_sys_exit(arg1),
-        Syscall::Mmap => dispatch_sys_mmap(arg1, arg2, arg3, arg4, arg5, arg6).map(Errno::from),
-        Syscall::Open => dispatch_sys_open(arg1, arg2, arg3).map(Errno::from),
-        Syscall::Read => dispatch_sys_read(arg1, arg2, arg3).map(Errno::from),
-        Syscall::Write => dispatch_sys_write(arg1, arg2, arg3).map(Errno::from),
-        Syscall::Socket => dispatch_sys_socket(arg1, arg2, arg3).map(Errno::from),
-        Syscall::Bind => dispatch_sys_bind(arg1, arg2, arg3).map(Errno::from),
-        Syscall::Stat => dispatch_sys_stat(arg1, arg2).map(Errno::from),
+    let args = [arg1, arg2, arg3, arg4, arg5, arg6];
+    let traced = process::current().is_traced();
+    if traced {
+        trace::trace_entry(entry, &args);
+    }
+
+    let result = match (entry.handler)(&args) {
+        Ok(value) => value as isize,
+        Err(errno) => errno.into(),
     };
-    syscall_result.unwrap_or_else(|v| v).as_isize()
+
+    if traced {
+        trace::trace_exit(entry, result);
+    }
+    result
+}
+
+/// Handler for syscalls that are known, but not implemented.
+#[allow(dead_code)]
+fn dispatch_enosys(_: &SyscallArgs) -> Result<usize> {
+    Err(Errno::ENOSYS)
 }
 
 fn dispatch_sys_access(arg1: usize, arg2: usize) -> Result<()> {
@@ -67,9 +267,27 @@ fn dispatch_sys_stat(arg1: usize, arg2: usize) -> Result<()> {
         .map_err(|_| Errno::EINVAL)?
         .to_str()
         .map_err(|_| Errno::EINVAL)?;
-    let stat = unsafe { &mut *(arg2 as *mut Stat) };
+    let mut ptr = UserspaceMutPtr::<Stat>::try_from(arg2)?;
+
+    let mut stat = Stat::default();
+    sys_stat(path, &mut stat)?;
+    ptr.write_value(stat)
+}
+
+fn dispatch_sys_fstat(arg1: usize, arg2: usize) -> Result<()> {
+    let mut ptr = UserspaceMutPtr::<Stat>::try_from(arg2)?;
 
-    sys_stat(path, stat)
+    let mut stat = Stat::default();
+    sys_fstat(Fileno::new(arg1), &mut stat)?;
+    ptr.write_value(stat)
+}
+
+fn dispatch_sys_flock(arg1: usize, arg2: usize) -> Result<()> {
+    let operation = u32::try_from(arg2)
+        .ok()
+        .and_then(FlockOperation::from_bits)
+        .ok_or(Errno::EINVAL)?;
+    sys_flock(Fileno::new(arg1), operation)
 }
 
 fn dispatch_sys_mmap(
@@ -90,14 +308,27 @@ fn dispatch_sys_mmap(
     sys_mmap(*addr, len, prot, flags, fd, offset).map(|addr| addr.as_u64() as usize)
 }
 
+fn dispatch_sys_munmap(arg1: usize, arg2: usize) -> Result<()> {
+    let addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    sys_munmap(*addr, arg2)
+}
+
 fn dispatch_sys_read(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
-    let ptr = UserspaceAddress::try_from(arg2).map_err(|_| Errno::EINVAL)?;
-    let range = UserspaceRange::try_from(ptr, arg3).map_err(|_| Errno::EINVAL)?;
-    let buf = <&mut [u8] as TryFromUserspaceRange>::try_from_userspace_range(range)?;
+    let mut ptr = UserspaceMutPtr::<u8>::try_from(arg2)?;
+    ptr.validate(arg3)?;
+    let buf = unsafe { ptr.as_mut_slice(arg3) };
 
     sys_read(Fileno::new(arg1), buf)
 }
 
+fn dispatch_sys_getdents(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let mut ptr = UserspaceMutPtr::<u8>::try_from(arg2)?;
+    ptr.validate(arg3)?;
+    let buf = unsafe { ptr.as_mut_slice(arg3) };
+
+    sys_getdents(Fileno::new(arg1), buf)
+}
+
 fn dispatch_sys_write(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
     let ptr = UserspaceAddress::try_from(arg2).map_err(|_| Errno::EINVAL)?;
     let range = UserspaceRange::try_from(ptr, arg3).map_err(|_| Errno::EINVAL)?;
@@ -113,6 +344,96 @@ fn dispatch_sys_open(arg1: usize, arg2: usize, arg3: usize) -> Result<Fileno> {
     sys_open(path, arg2, arg3)
 }
 
+fn dispatch_sys_openat(arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> Result<Fileno> {
+    let dirfd = if arg1 as isize == AT_FDCWD as isize {
+        None
+    } else {
+        Some(Fileno::new(arg1))
+    };
+    let userspace_addr = UserspaceAddress::try_from(arg2).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_openat(dirfd, path, arg3, arg4)
+}
+
+fn dispatch_sys_chdir(arg1: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_chdir(path)
+}
+
+fn dispatch_sys_getcwd(arg1: usize, arg2: usize) -> Result<usize> {
+    let mut ptr = UserspaceMutPtr::<u8>::try_from(arg1)?;
+    ptr.validate(arg2)?;
+    let buf = unsafe { ptr.as_mut_slice(arg2) };
+
+    sys_getcwd(buf)
+}
+
+fn dispatch_sys_readlink(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+    let mut ptr = UserspaceMutPtr::<u8>::try_from(arg2)?;
+    ptr.validate(arg3)?;
+    let buf = unsafe { ptr.as_mut_slice(arg3) };
+
+    sys_readlink(path, buf)
+}
+
+fn dispatch_sys_mkdir(arg1: usize, arg2: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_mkdir(path, arg2)
+}
+
+fn dispatch_sys_unlink(arg1: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_unlink(path)
+}
+
+fn dispatch_sys_rmdir(arg1: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_rmdir(path)
+}
+
+fn dispatch_sys_rename(arg1: usize, arg2: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let from = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+    let userspace_addr = UserspaceAddress::try_from(arg2).map_err(|_| Errno::EINVAL)?;
+    let to = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_rename(from, to)
+}
+
+fn dispatch_sys_link(arg1: usize, arg2: usize) -> Result<()> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let existing = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+    let userspace_addr = UserspaceAddress::try_from(arg2).map_err(|_| Errno::EINVAL)?;
+    let to = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+
+    sys_link(existing, to)
+}
+
+fn dispatch_sys_lseek(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    // the offset is an off_t, which may be negative
+    let whence = Whence::try_from(arg3).map_err(|_| Errno::EINVAL)?;
+    sys_lseek(Fileno::new(arg1), arg2 as isize, whence)
+}
+
+fn dispatch_sys_ftruncate(arg1: usize, arg2: usize) -> Result<()> {
+    // the length is an off_t, so negative lengths are invalid
+    if (arg2 as isize) < 0 {
+        return Err(Errno::EINVAL);
+    }
+    sys_ftruncate(Fileno::new(arg1), arg2)
+}
+
 fn dispatch_sys_close(arg1: usize) -> Result<()> {
     sys_close(Fileno::new(arg1))
 }
@@ -121,6 +442,161 @@ fn dispatch_sys_exit(arg1: usize) -> ! {
     sys_exit(arg1)
 }
 
+fn dispatch_sys_thread_create(arg1: usize, arg2: usize, arg3: usize, arg4: usize) -> Result<usize> {
+    // programs are loaded into the kernel heap, so the entry point is not
+    // necessarily a userspace address
+    let entry = VirtAddr::try_new(arg1 as u64).map_err(|_| Errno::EINVAL)?;
+    let stack = UserspaceAddress::try_from(arg3).map_err(|_| Errno::EINVAL)?;
+    let tls = UserspaceAddress::try_from(arg4).map_err(|_| Errno::EINVAL)?;
+    sys_thread_create(entry, arg2, *stack, *tls)
+}
+
+fn dispatch_sys_thread_exit(arg1: usize) -> ! {
+    sys_thread_exit(arg1)
+}
+
+fn dispatch_sys_thread_join(arg1: usize) -> Result<usize> {
+    sys_thread_join(ThreadId::from(arg1 as u64))
+}
+
+fn dispatch_sys_set_tls(arg1: usize) -> Result<()> {
+    let tls = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    sys_set_tls(*tls)
+}
+
+fn dispatch_sys_futex(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    // futexes can be statics of the program, which is loaded into the kernel heap
+    let addr = VirtAddr::try_new(arg1 as u64).map_err(|_| Errno::EINVAL)?;
+    let op = FutexOp::try_from(arg2).map_err(|_| Errno::EINVAL)?;
+    sys_futex(addr, op, arg3)
+}
+
+fn dispatch_sys_spawn(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let userspace_addr = UserspaceAddress::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let path = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)?;
+    let args = strings_from_userspace(arg2)?;
+    let env = strings_from_userspace(arg3)?;
+
+    sys_spawn(path, args, env)
+}
+
+/// Copies a null terminated array of pointers to strings, like `argv`, from
+/// userspace. A null array is empty.
+fn strings_from_userspace(addr: usize) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+    if addr == 0 {
+        return Ok(strings);
+    }
+    let array = UserspaceMutPtr::<usize>::try_from(addr)?;
+    // every pointer takes up space in the new process, so there can't be more
+    // than fit into ARG_MAX
+    for i in 0..ARG_MAX / size_of::<usize>() {
+        array.validate(i + 1)?;
+        let ptr = unsafe { (array.addr() as *const usize).add(i).read_unaligned() };
+        if ptr == 0 {
+            return Ok(strings);
+        }
+        let userspace_addr = UserspaceAddress::try_from(ptr).map_err(|_| Errno::EFAULT)?;
+        let s = <&str as TryFromUserspaceAddress>::try_from_userspace_addr(userspace_addr)
+            .map_err(|e| match e {
+                Errno::ENAMETOOLONG => Errno::E2BIG,
+                e => e,
+            })?;
+        strings.push(s.to_string());
+    }
+    Err(Errno::E2BIG)
+}
+
+fn dispatch_sys_waitpid(arg1: usize) -> Result<usize> {
+    sys_waitpid(arg1)
+}
+
+fn dispatch_sys_clock_gettime(arg1: usize, arg2: usize) -> Result<()> {
+    let clock = ClockId::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+    let mut ptr = UserspaceMutPtr::<Timespec>::try_from(arg2)?;
+
+    let mut tp = Timespec::default();
+    sys_clock_gettime(clock, &mut tp)?;
+    ptr.write_value(tp)
+}
+
+fn dispatch_sys_sigaction(arg1: usize, arg2: usize, arg3: usize) -> Result<()> {
+    let act = if arg2 == 0 {
+        None
+    } else {
+        let act = unsafe { UserspaceMutPtr::<SigAction>::try_from(arg2)?.read() };
+        Some(SigAction {
+            flags: SigActionFlags::from_bits_truncate(act.flags.bits()),
+            mask: SigSet::from_bits(act.mask.bits()),
+            ..act
+        })
+    };
+    let mut oldact = (arg3 != 0)
+        .then(|| UserspaceMutPtr::<SigAction>::try_from(arg3))
+        .transpose()?;
+
+    let old = sys_sigaction(arg1, act)?;
+    match oldact.as_mut() {
+        Some(ptr) => ptr.write_value(old),
+        None => Ok(()),
+    }
+}
+
+fn dispatch_sys_sigprocmask(arg1: usize, arg2: usize, arg3: usize) -> Result<()> {
+    let set = if arg2 == 0 {
+        None
+    } else {
+        let how = SigprocmaskHow::try_from(arg1).map_err(|_| Errno::EINVAL)?;
+        let set = unsafe { UserspaceMutPtr::<SigSet>::try_from(arg2)?.read() };
+        Some((how, SigSet::from_bits(set.bits())))
+    };
+    let mut oldset = (arg3 != 0)
+        .then(|| UserspaceMutPtr::<SigSet>::try_from(arg3))
+        .transpose()?;
+
+    // without a set, `how` is meaningless
+    let old = match set {
+        Some((how, set)) => sys_sigprocmask(how, Some(set))?,
+        None => sys_sigprocmask(SigprocmaskHow::Block, None)?,
+    };
+    match oldset.as_mut() {
+        Some(ptr) => ptr.write_value(old),
+        None => Ok(()),
+    }
+}
+
+fn dispatch_sys_kill(arg1: usize, arg2: usize) -> Result<()> {
+    sys_kill(arg1, arg2)
+}
+
+fn dispatch_sys_sigreturn(arg1: usize) -> Result<()> {
+    let frame = unsafe { UserspaceMutPtr::<SignalFrame>::try_from(arg1)?.read() };
+    sys_sigreturn(&frame)
+}
+
+fn dispatch_sys_sigsuspend(arg1: usize) -> Result<()> {
+    let mask = unsafe { UserspaceMutPtr::<SigSet>::try_from(arg1)?.read() };
+    sys_sigsuspend(SigSet::from_bits(mask.bits()))
+}
+
+fn dispatch_sys_nanosleep(arg1: usize, arg2: usize) -> Result<()> {
+    let req = unsafe { UserspaceMutPtr::<Timespec>::try_from(arg1)?.read() };
+    if req.tv_nsec >= 1_000_000_000 {
+        return Err(Errno::EINVAL);
+    }
+    let mut rem = (arg2 != 0)
+        .then(|| UserspaceMutPtr::<Timespec>::try_from(arg2))
+        .transpose()?;
+
+    let duration = Duration::new(u64::from(req.tv_sec), req.tv_nsec as u32);
+    let mut remaining = Duration::ZERO;
+    let result = sys_nanosleep(duration, &mut remaining);
+    if let (Err(Errno::EINTR), Some(rem)) = (result, rem.as_mut()) {
+        rem.write_value(Timespec::from(remaining))?;
+    }
+    result
+}
+
 fn dispatch_sys_socket(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
     let domain = TryInto::<SocketDomain>::try_into(arg1).map_err(|_| Errno::EINVAL)?;
     let typ = TryInto::<SocketType>::try_into(arg2).map_err(|_| Errno::EINVAL)?;
@@ -136,3 +612,83 @@ fn dispatch_sys_bind(arg1: usize, arg2: usize, arg3: usize) -> Result<()> {
 
     sys_bind(socket, address, address_len)
 }
+
+fn dispatch_sys_poll(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let nfds = arg2;
+    if nfds > POLL_NFDS_MAX {
+        return Err(Errno::EINVAL);
+    }
+    // the timeout is in milliseconds, a negative timeout means no timeout
+    let timeout = u64::try_from(arg3 as i32).ok().map(Duration::from_millis);
+
+    if nfds == 0 {
+        return sys_poll(&mut [], timeout);
+    }
+
+    let mut ptr = UserspaceMutPtr::<PollFd>::try_from(arg1)?;
+    ptr.validate(nfds)?;
+    let userspace_fds = unsafe { from_raw_parts(arg1 as *const PollFd, nfds) };
+
+    // work on a copy, so that userspace can't modify the entries while we're polling
+    let mut fds = Vec::new();
+    fds.try_reserve_exact(nfds).map_err(|_| Errno::ENOMEM)?;
+    fds.extend_from_slice(userspace_fds);
+
+    let ready = sys_poll(&mut fds, timeout)?;
+    ptr.write_slice(&fds)?;
+    Ok(ready)
+}
+
+fn dispatch_sys_pipe(arg1: usize) -> Result<()> {
+    let mut ptr = UserspaceMutPtr::<[i32; 2]>::try_from(arg1)?;
+
+    let (read_fd, write_fd) = sys_pipe()?;
+    let fds = [read_fd.as_usize() as i32, write_fd.as_usize() as i32];
+    ptr.write_value(fds)
+}
+
+fn dispatch_sys_traceme(arg1: usize) -> Result<()> {
+    sys_traceme(arg1 != 0)
+}
+
+fn dispatch_sys_ioctl(arg1: usize, arg2: usize, arg3: usize) -> Result<usize> {
+    let request = u32::try_from(arg2).map_err(|_| Errno::EINVAL)?;
+    let arg = UserspaceMutPtr::try_from(arg3)?;
+    sys_ioctl(Fileno::new(arg1), request, arg)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_api::syscall::{Errno, Syscall, SYS_MAX};
+    use kernel_test_framework::kernel_test;
+
+    use crate::syscall::{dispatch_syscall, syscall_entry};
+
+    #[kernel_test]
+    fn test_unknown_syscall_is_enosys() {
+        for n in [SYS_MAX, SYS_MAX + 1, 0xDEAD, usize::MAX] {
+            assert_eq!(
+                isize::from(Errno::ENOSYS),
+                dispatch_syscall(n, 1, 2, 3, 4, 5, 6)
+            );
+        }
+    }
+
+    #[kernel_test]
+    fn test_syscall_table_complete() {
+        for n in 0..SYS_MAX {
+            let syscall = Syscall::try_from(n).unwrap();
+            assert_eq!(syscall, syscall_entry(n).unwrap().syscall());
+        }
+        assert!(Syscall::try_from(SYS_MAX).is_err());
+    }
+
+    #[kernel_test]
+    fn test_syscall_arity() {
+        let arity = |syscall: Syscall| syscall_entry(syscall as usize).unwrap().arity();
+        assert_eq!(0, arity(Syscall::Getpid));
+        assert_eq!(1, arity(Syscall::Exit));
+        assert_eq!(3, arity(Syscall::Read));
+        assert_eq!(6, arity(Syscall::Mmap));
+    }
+}
@@ -1,29 +1,47 @@
 use alloc::format;
+use alloc::string::String;
 use alloc::vec::Vec;
-use core::ops::BitAnd;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::Relaxed;
+use core::time::Duration;
 
 use bitflags::bitflags;
+use foundation::time::Instant;
 use log::trace;
 use x86_64::instructions::hlt;
-use x86_64::registers::model_specific::Msr;
-use x86_64::structures::paging::PageTableFlags;
+use x86_64::registers::model_specific::{FsBase, Msr};
+use x86_64::structures::paging::mapper::TranslateResult;
+use x86_64::structures::paging::{PageSize, PageTableFlags, Size4KiB};
 use x86_64::VirtAddr;
 
 pub use dispatch::*;
 pub use error::*;
-use kernel_api::syscall::{Errno, FfiSockAddr, FileMode, SocketDomain, SocketType, Stat};
+use kernel_api::syscall::{
+    ClockId, Errno, FfiSockAddr, FileMode, FlockOperation, FutexOp, OpenFlags, PollEvents, PollFd,
+    SigAction, SigActionFlags, SigSet, SignalFrame, SigprocmaskHow, SocketDomain, SocketType, Stat,
+    Timespec, Whence, SIG_DFL, SIG_IGN,
+};
+use kernel_api::ARG_MAX;
 
-use crate::io::path::Path;
+use crate::driver::rtc;
+use crate::io::path::{OwnedPath, Path, SEPARATOR};
 use crate::io::socket::create_socket;
-use crate::io::vfs::vfs;
+use crate::io::vfs::lock::LockKind;
+use crate::io::vfs::{resolve, vfs, FileType, MmapBacking, ResolveFlags, VfsError};
 use crate::mem::virt::{AllocationStrategy, MapAt};
 use crate::process;
 use crate::process::fd::Fileno;
-use crate::process::vmm;
+use crate::process::futex::futexes;
+use crate::process::thread::ThreadId;
+use crate::process::wait_queue::Waiter;
+use crate::process::{park, vmm, Priority, Process};
+use crate::syscall::convert::UserspaceMutPtr;
+use crate::time::HpetInstantProvider;
 
-mod convert;
+pub mod convert;
 mod dispatch;
 mod error;
+mod trace;
 
 pub fn init() {
     let mut ia32_star = Msr::new(0xC0000081);
@@ -111,8 +129,9 @@ pub fn sys_access(path: impl AsRef<Path>, amode: AMode) -> Result<()> {
         return Err(Errno::ENOSYS);
     }
 
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
     vfs()
-        .stat_path(path, &mut Stat::default())
+        .stat_path(path.as_path(), &mut Stat::default())
         .map_err(Into::into)
         .map(|_| ())
 }
@@ -127,13 +146,13 @@ pub fn sys_dup(fd: Fileno) -> Result<Fileno> {
     trace!("sys_dup({:?})", fd);
 
     let process = process::current();
-    let node = process
+    let (node, flags) = process
         .open_fds()
         .read()
         .get(&fd)
-        .map(|desc| desc.node().clone())
+        .map(|desc| (desc.node().clone(), desc.flags()))
         .ok_or(Errno::EBADF)?;
-    let new_fd = process.get_fileno_for(node);
+    let new_fd = process.get_fileno_for(node, flags);
     Ok(new_fd)
 }
 
@@ -145,9 +164,241 @@ pub fn sys_execve(path: impl AsRef<Path>, argv: &[&str], envp: &[&str]) -> Resul
 
 pub fn sys_exit(status: usize) -> ! {
     trace!("sys_exit({})", status);
-    process::current().terminate();
+    let process = process::current();
+    process.set_exit_status(status);
+    process.terminate();
+
+    loop {
+        hlt();
+    }
+}
+
+/// Creates a thread in the current process, which calls `entry` with `arg` on
+/// the stack that `stack` points to the top of. Its `fs` segment starts at
+/// `tls`. Returns the id of the thread, which [`sys_thread_join`] takes.
+pub fn sys_thread_create(
+    entry: VirtAddr,
+    arg: usize,
+    stack: VirtAddr,
+    tls: VirtAddr,
+) -> Result<usize> {
+    trace!(
+        "sys_thread_create({:#x}, {:#x}, {:#x}, {:#x})",
+        entry,
+        arg,
+        stack,
+        tls
+    );
+
+    if entry.is_null() || stack.is_null() {
+        return Err(Errno::EINVAL);
+    }
+    let priority = process::current_thread().priority();
+    let id = process::current().create_thread(entry, arg, stack, tls, priority);
+    Ok(id.as_u64() as usize)
+}
+
+/// Exits the current thread, but not the process, unless it was the last
+/// thread. `value` is what joining the thread returns.
+pub fn sys_thread_exit(value: usize) -> ! {
+    trace!("sys_thread_exit({:#x})", value);
+
+    process::current().exit_thread(value)
+}
+
+/// Waits until the thread has exited, and returns its exit value. Only threads
+/// that were created with [`sys_thread_create`] can be joined, and only once.
+pub fn sys_thread_join(id: ThreadId) -> Result<usize> {
+    trace!("sys_thread_join({})", id);
+
+    process::current().join_thread(id)
+}
+
+/// Sets the base of the `fs` segment of the current thread, which points to
+/// its thread control block.
+pub fn sys_set_tls(tls: VirtAddr) -> Result<()> {
+    trace!("sys_set_tls({:#x})", tls);
+
+    process::current_thread().set_fs_base(tls);
+    FsBase::write(tls);
+    Ok(())
+}
+
+/// Waits on or wakes the threads that wait on the `u32` at `addr`. For
+/// [`FutexOp::Wait`], `value` is the expected value of the futex, for
+/// [`FutexOp::Wake`] it's the number of threads to wake.
+pub fn sys_futex(addr: VirtAddr, op: FutexOp, value: usize) -> Result<usize> {
+    trace!("sys_futex({:#x}, {:?}, {})", addr, op, value);
+
+    if !addr.is_aligned(4_u64) {
+        return Err(Errno::EINVAL);
+    }
+    let futex = unsafe { AtomicU32::from_ptr(addr.as_mut_ptr()) };
+    // memory that is allocated on access has no physical address before it's
+    // accessed for the first time
+    futex.load(Relaxed);
+    let key = match process::current().address_space().read().translate(addr) {
+        TranslateResult::Mapped { frame, offset, .. } => frame.start_address() + offset,
+        _ => return Err(Errno::EFAULT),
+    };
+
+    match op {
+        FutexOp::Wait => futexes().wait(key, futex, value as u32).map(|_| 0),
+        FutexOp::Wake => Ok(futexes().wake(key, value)),
+    }
+}
+
+/// Starts the executable at `path` in a new child process, with the given
+/// arguments and environment. Returns the id of the child, which
+/// [`sys_waitpid`] takes.
+pub fn sys_spawn(path: impl AsRef<Path>, args: Vec<String>, env: Vec<String>) -> Result<usize> {
+    trace!("sys_spawn({}, {:?}, {:?})", path.as_ref(), args, env);
+
+    // every string is terminated, and has a pointer to it
+    let size = args
+        .iter()
+        .chain(&env)
+        .map(|s| s.len() + 1 + size_of::<usize>())
+        .sum::<usize>();
+    if size > ARG_MAX {
+        return Err(Errno::E2BIG);
+    }
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    let mut stat = Stat::default();
+    vfs().stat_path(path.as_path(), &mut stat)?;
+    if !stat.mode.is_regular_file() {
+        return Err(Errno::EACCES);
+    }
+
+    let parent = process::current();
+    let (uid, gid) = {
+        let attributes = parent.attributes();
+        (attributes.uid, attributes.gid)
+    };
+    let child = Process::create_from_executable(parent, path, uid, gid);
+    child.set_arguments(args);
+    child.set_environment(env);
+    child.start(Priority::Normal);
+    Ok(u64::from(*child.pid()) as usize)
+}
+
+/// Waits until the child process with the given id has exited, and returns
+/// its exit status.
+pub fn sys_waitpid(pid: usize) -> Result<usize> {
+    trace!("sys_waitpid({})", pid);
+
+    process::current().wait_for_child(pid as u64)
+}
+
+pub fn sys_clock_gettime(clock: ClockId, tp: &mut Timespec) -> Result<()> {
+    trace!("sys_clock_gettime({:?}, {:#p})", clock, tp);
+
+    let time = match clock {
+        ClockId::Realtime => rtc::now(),
+        ClockId::Monotonic => Instant::now().duration_since(Instant::new(0)),
+    };
+    *tp = Timespec::from(time);
+    Ok(())
+}
+
+/// Replaces the action of the signal `sig` with `act`, if it's given, and
+/// returns the old one.
+pub fn sys_sigaction(sig: usize, act: Option<SigAction>) -> Result<SigAction> {
+    trace!("sys_sigaction({}, {:?})", sig, act);
+
+    if let Some(act) = act {
+        // a handler must have somewhere to return to
+        if act.handler != SIG_DFL
+            && act.handler != SIG_IGN
+            && !act.flags.contains(SigActionFlags::SA_RESTORER)
+        {
+            return Err(Errno::EINVAL);
+        }
+    }
+
+    let mut signals = process::current().signals();
+    match act {
+        Some(act) => signals.set_action(sig, act),
+        None => signals.action(sig),
+    }
+}
+
+/// Changes the signal mask as described by `how`, if `set` is given, and
+/// returns the old mask.
+pub fn sys_sigprocmask(how: SigprocmaskHow, set: Option<SigSet>) -> Result<SigSet> {
+    trace!("sys_sigprocmask({:?}, {:?})", how, set);
+
+    let mut signals = process::current().signals();
+    Ok(match set {
+        Some(set) => signals.set_mask(how, set),
+        None => signals.mask(),
+    })
+}
+
+/// Sends the signal `sig` to the process with the given id. If it's the
+/// current process, the signal is delivered before this syscall returns,
+/// unless it's blocked.
+pub fn sys_kill(pid: usize, sig: usize) -> Result<()> {
+    trace!("sys_kill({}, {})", pid, sig);
 
+    process::current().send_signal(pid as u64, sig)
+}
+
+/// Returns from a signal handler to the context in `frame`, which the
+/// signal trampoline put on the stack. The registers of the context are
+/// restored when the syscall returns, so the return value of this syscall
+/// is never seen.
+pub fn sys_sigreturn(frame: &SignalFrame) -> Result<()> {
+    trace!("sys_sigreturn({:#p})", frame);
+
+    let context = frame.context;
+    VirtAddr::try_new(context.rip as u64).map_err(|_| Errno::EFAULT)?;
+    VirtAddr::try_new(context.rsp as u64).map_err(|_| Errno::EFAULT)?;
+
+    let tid = *process::current_thread().id();
+    process::current().signals().sigreturn(tid, context);
+    Ok(())
+}
+
+/// Replaces the signal mask with `mask`, and waits until a signal is
+/// delivered. Always fails with [`Errno::EINTR`], and the old mask is
+/// restored once the handler of the signal returns.
+pub fn sys_sigsuspend(mask: SigSet) -> Result<()> {
+    trace!("sys_sigsuspend({:?})", mask);
+
+    let process = process::current();
+    process.signals().suspend(mask);
+    while !process.signals().is_deliverable() {
+        // we don't have wait queues yet, so we give up our time slice and check again
+        hlt();
+    }
+    Err(Errno::EINTR)
+}
+
+pub fn sys_getpid() -> Result<usize> {
+    trace!("sys_getpid()");
+
+    Ok(u64::from(*process::current().pid()) as usize)
+}
+
+/// Waits until `duration` has passed. Fails with [`Errno::EINTR`] if a signal
+/// becomes deliverable before that, and stores the time that was left in
+/// `remaining`.
+pub fn sys_nanosleep(duration: Duration, remaining: &mut Duration) -> Result<()> {
+    trace!("sys_nanosleep({:?})", duration);
+
+    let process = process::current();
+    let start = Instant::now();
     loop {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            return Ok(());
+        }
+        if process.signals().is_deliverable() {
+            *remaining = duration - elapsed;
+            return Err(Errno::EINTR);
+        }
+        // we don't have timers for threads yet, so we give up our time slice and check again
         hlt();
     }
 }
@@ -169,6 +420,9 @@ bitflags! {
         const Private = 0x2;
         const Fixed = 0x4;
         const Anon = 0x8;
+        /// Allocates anonymous memory right away, instead of when it's first
+        /// accessed.
+        const Populate = 0x10;
     }
 }
 
@@ -208,12 +462,17 @@ pub fn sys_mmap(
     }
 
     let mapped_address = if map_flags.contains(MapFlags::Anon) {
+        let strategy = if map_flags.contains(MapFlags::Populate) {
+            AllocationStrategy::AllocateNow
+        } else {
+            AllocationStrategy::AllocateOnAccess
+        };
         vmm()
             .allocate_memory_backed_vmobject(
                 format!("mmap anon (len={})", size),
                 addr,
                 size,
-                AllocationStrategy::AllocateOnAccess,
+                strategy,
                 flags,
             )
             .map_err(|_| Errno::ENOMEM)?
@@ -227,13 +486,13 @@ pub fn sys_mmap(
             .node()
             .clone();
 
-        let mut stat = Stat::default();
-        vfs()
-            .stat_path(node.path(), &mut stat)
-            .map_err(Into::<Errno>::into)?;
-
-        if stat.mode.is_regular_file() {
-            vmm()
+        let backing = vfs().mmap(&node, offset, size).map_err(|e| match e {
+            // the file exists, but can't be mapped
+            VfsError::Unsupported => Errno::ENODEV,
+            e => e.into(),
+        })?;
+        match backing {
+            MmapBacking::FileBacked => vmm()
                 .allocate_file_backed_vm_object(
                     format!("mmap '{}' (offset={}, len={})", node.path(), offset, size),
                     node,
@@ -241,34 +500,61 @@ pub fn sys_mmap(
                     addr,
                     size,
                     flags,
+                    map_flags.contains(MapFlags::Shared),
                 )
-                .map_err(|_| Errno::ENOMEM)?
-        } else {
-            // check whether the file is a device and needs special handling
-            if let Some(phys_frames) = node.fs().read().physical_memory(node.handle())? {
-                let frames = phys_frames.collect::<Vec<_>>();
-                vmm()
-                    .allocate_memory_backed_vmobject(
-                        format!("mmap device '{}' (len={})", node.path(), size),
-                        addr,
-                        size,
-                        AllocationStrategy::MapNow(&frames),
-                        flags,
-                    )
-                    .map_err(|_| Errno::ENOMEM)?
-            } else {
-                // we have some non-regular file that doesn't have physical memory, what?
-                panic!(
-                    "mmap unsupported file type: {:#?} (doesn't have physical memory)",
-                    stat.mode.bitand(FileMode::S_IFMT)
-                );
-            }
+                .map_err(|_| Errno::ENOMEM)?,
+            MmapBacking::Physical(frames) => vmm()
+                .allocate_memory_backed_vmobject(
+                    format!(
+                        "mmap device '{}' (offset={}, len={})",
+                        node.path(),
+                        offset,
+                        size
+                    ),
+                    addr,
+                    size,
+                    AllocationStrategy::MapNow(&frames),
+                    flags,
+                )
+                .map_err(|_| Errno::ENOMEM)?,
         }
     };
 
     Ok(mapped_address)
 }
 
+/// Unmaps the mappings in the range. Mappings can't be split, so every
+/// mapping that the range touches must be in it completely.
+pub fn sys_munmap(addr: VirtAddr, len: usize) -> Result<()> {
+    trace!("sys_munmap({:#x}, {})", addr, len);
+
+    if len == 0 || !addr.is_aligned(Size4KiB::SIZE) {
+        return Err(Errno::EINVAL);
+    }
+    let end = addr + len;
+
+    let mut vm_objects = vmm().vm_objects().write();
+    let mut unmapped = Vec::new();
+    for vm_object in vm_objects.values() {
+        let start = vm_object.addr();
+        let object_end = start + vm_object.size();
+        if start < end && addr < object_end {
+            if start < addr || object_end > end.align_up(Size4KiB::SIZE) {
+                return Err(Errno::EINVAL);
+            }
+            unmapped.push(start);
+        }
+    }
+    let unmapped = unmapped
+        .into_iter()
+        .filter_map(|start| vm_objects.remove(&start))
+        .collect::<Vec<_>>();
+    // dropping the objects unmaps them, which locks the address space
+    drop(vm_objects);
+    drop(unmapped);
+    Ok(())
+}
+
 pub fn sys_mount(
     _source: impl AsRef<Path>,
     _target: impl AsRef<Path>,
@@ -278,7 +564,6 @@ pub fn sys_mount(
     Err(Errno::ENOSYS)
 }
 
-// TODO: OpenFlags and Mode
 pub fn sys_open(path: impl AsRef<Path>, flags: usize, mode: usize) -> Result<Fileno> {
     trace!(
         "sys_open({:#p} ({}), {}, {})",
@@ -287,8 +572,229 @@ pub fn sys_open(path: impl AsRef<Path>, flags: usize, mode: usize) -> Result<Fil
         flags,
         mode
     );
+    sys_openat(None, path, flags, mode)
+}
+
+/// Opens the file at `path`, which is resolved relative to the directory that
+/// `dirfd` refers to. If `dirfd` is [`None`], the path is resolved relative to
+/// the current working directory.
+///
+/// If the file is created because of [`OpenFlags::O_CREAT`], it gets the
+/// permission bits of `mode`. Unknown flags and an invalid access mode fail
+/// with [`Errno::EINVAL`].
+pub fn sys_openat(
+    dirfd: Option<Fileno>,
+    path: impl AsRef<Path>,
+    flags: usize,
+    mode: usize,
+) -> Result<Fileno> {
+    trace!(
+        "sys_openat({:?}, {}, {}, {})",
+        dirfd,
+        path.as_ref(),
+        flags,
+        mode
+    );
+    let flags = u32::try_from(flags)
+        .ok()
+        .and_then(OpenFlags::from_bits)
+        .ok_or(Errno::EINVAL)?;
+    if flags & OpenFlags::O_ACCMODE == OpenFlags::O_ACCMODE
+        || flags.contains(OpenFlags::O_DIRECTORY | OpenFlags::O_CREAT)
+    {
+        return Err(Errno::EINVAL);
+    }
+    let resolve_flags = if flags.contains(OpenFlags::O_RESOLVE_BENEATH) {
+        ResolveFlags::BENEATH
+    } else {
+        ResolveFlags::empty()
+    };
+    let path = resolve_at(dirfd, path.as_ref(), resolve_flags)?;
+    if flags.contains(OpenFlags::O_CREAT) {
+        match vfs().create_with_mode(path.as_path(), FileType::RegularFile, permissions(mode)) {
+            Ok(()) => {}
+            Err(VfsError::AlreadyExists) if !flags.contains(OpenFlags::O_EXCL) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let process = process::current();
+    let fd = process.open_file(path.as_path(), flags)?;
+
+    let mut stat = Stat::default();
+    if let Err(e) = process.stat(fd, &mut stat) {
+        let _ = process.close_fd(fd);
+        return Err(e.into());
+    }
+    let invalid = if stat.mode.is_directory() {
+        // directories are changed through paths, never through a file descriptor
+        flags.is_writable().then_some(Errno::EISDIR)
+    } else {
+        flags
+            .contains(OpenFlags::O_DIRECTORY)
+            .then_some(Errno::ENOTDIR)
+    };
+    if let Some(errno) = invalid {
+        let _ = process.close_fd(fd);
+        return Err(errno);
+    }
+
+    // O_TRUNC only affects regular files and is ignored for everything else
+    if flags.contains(OpenFlags::O_TRUNC) && stat.mode.is_regular_file() {
+        let truncated = match process.open_fds().read().get(&fd) {
+            Some(fd) => vfs().truncate(fd.node(), 0),
+            None => Err(VfsError::HandleClosed),
+        };
+        if let Err(e) = truncated {
+            let _ = process.close_fd(fd);
+            return Err(e.into());
+        }
+    }
+
+    Ok(fd)
+}
+
+/// Returns the permission bits of a mode that was passed to a syscall. Everything
+/// else, like file type bits, is ignored.
+fn permissions(mode: usize) -> FileMode {
+    FileMode::from_bits_truncate(mode as u32).permissions()
+}
+
+/// Resolves `path` relative to the directory that `dirfd` refers to, or relative
+/// to the current working directory if `dirfd` is [`None`]. Absolute paths ignore
+/// `dirfd`.
+fn resolve_at(dirfd: Option<Fileno>, path: &Path, flags: ResolveFlags) -> Result<OwnedPath> {
     let process = process::current();
-    process.open_file(&path).map_err(Into::into)
+    let start = if path.starts_with(SEPARATOR) {
+        OwnedPath::from("/")
+    } else if let Some(dirfd) = dirfd {
+        let node = process
+            .open_fds()
+            .read()
+            .get(&dirfd)
+            .map(|desc| desc.node().clone())
+            .ok_or(Errno::EBADF)?;
+        let mut stat = Stat::default();
+        vfs().stat(&node, &mut stat)?;
+        if !stat.mode.is_directory() {
+            return Err(Errno::ENOTDIR);
+        }
+        node.path().to_owned()
+    } else {
+        process.cwd()
+    };
+
+    resolve(start.as_path(), path, flags).map_err(Into::into)
+}
+
+/// Changes the current working directory of the calling process.
+pub fn sys_chdir(path: impl AsRef<Path>) -> Result<()> {
+    trace!("sys_chdir({:?})", path.as_ref());
+
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    let mut stat = Stat::default();
+    vfs().stat_path(path.as_path(), &mut stat)?;
+    if !stat.mode.is_directory() {
+        return Err(Errno::ENOTDIR);
+    }
+    process::current().set_cwd(path);
+    Ok(())
+}
+
+/// Writes the nul-terminated current working directory into `buf`, and returns
+/// the number of bytes written, including the nul byte. Fails with
+/// [`Errno::ERANGE`] if `buf` is too small.
+pub fn sys_getcwd(buf: &mut [u8]) -> Result<usize> {
+    trace!("sys_getcwd({:#p}, {})", buf.as_ptr(), buf.len());
+
+    let cwd = process::current().cwd();
+    let len = cwd.len();
+    if buf.len() <= len {
+        return Err(Errno::ERANGE);
+    }
+    buf[..len].copy_from_slice(cwd.as_path().as_bytes());
+    buf[len] = 0;
+    Ok(len + 1)
+}
+
+/// Writes the target of the symbolic link at `path` into `buf` and returns the
+/// number of bytes written. The target is not nul-terminated, and silently
+/// truncated if `buf` is too small.
+pub fn sys_readlink(path: impl AsRef<Path>, buf: &mut [u8]) -> Result<usize> {
+    trace!(
+        "sys_readlink({:?}, {:#p}, {})",
+        path.as_ref(),
+        buf.as_ptr(),
+        buf.len()
+    );
+
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    let node = vfs().open_nofollow(path.as_path())?;
+    let target = vfs().readlink(&node)?;
+    let len = target.len().min(buf.len());
+    buf[..len].copy_from_slice(&target.as_bytes()[..len]);
+    Ok(len)
+}
+
+/// Truncates or extends the file to `len` bytes. Extended regions read as zeros.
+pub fn sys_ftruncate(fd: Fileno, len: usize) -> Result<()> {
+    trace!("sys_ftruncate({}, {})", fd, len);
+
+    process::current().truncate(fd, len)
+}
+
+/// Moves the offset of the file descriptor, and returns the new offset.
+pub fn sys_lseek(fd: Fileno, offset: isize, whence: Whence) -> Result<usize> {
+    trace!("sys_lseek({}, {}, {:?})", fd, offset, whence);
+
+    process::current().seek(fd, offset, whence)
+}
+
+pub fn sys_mkdir(path: impl AsRef<Path>, mode: usize) -> Result<()> {
+    trace!("sys_mkdir({:?}, {:#o})", path.as_ref(), mode);
+
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    vfs()
+        .create_with_mode(path.as_path(), FileType::Directory, permissions(mode))
+        .map_err(Into::into)
+}
+
+pub fn sys_unlink(path: impl AsRef<Path>) -> Result<()> {
+    trace!("sys_unlink({:?})", path.as_ref());
+
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    vfs().remove_file(path.as_path()).map_err(Into::into)
+}
+
+pub fn sys_rmdir(path: impl AsRef<Path>) -> Result<()> {
+    trace!("sys_rmdir({:?})", path.as_ref());
+
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    vfs().remove_dir(path.as_path()).map_err(Into::into)
+}
+
+/// Atomically moves the node at `from` to `to`, replacing `to` if it exists.
+pub fn sys_rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    trace!("sys_rename({:?}, {:?})", from.as_ref(), to.as_ref());
+
+    let from = resolve_at(None, from.as_ref(), ResolveFlags::empty())?;
+    let to = resolve_at(None, to.as_ref(), ResolveFlags::empty())?;
+    vfs()
+        .rename(from.as_path(), to.as_path())
+        .map_err(Into::into)
+}
+
+/// Adds `to` as another name for the file at `existing`. Fails with
+/// [`Errno::EXDEV`] if the paths are on different file systems, and with
+/// [`Errno::EPERM`] if `existing` is a directory.
+pub fn sys_link(existing: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+    trace!("sys_link({:?}, {:?})", existing.as_ref(), to.as_ref());
+
+    let existing = resolve_at(None, existing.as_ref(), ResolveFlags::empty())?;
+    let to = resolve_at(None, to.as_ref(), ResolveFlags::empty())?;
+    vfs()
+        .link(existing.as_path(), to.as_path())
+        .map_err(Into::into)
 }
 
 pub fn sys_read(fd: Fileno, buf: &mut [u8]) -> Result<usize> {
@@ -297,6 +803,13 @@ pub fn sys_read(fd: Fileno, buf: &mut [u8]) -> Result<usize> {
     process.read(fd, buf).map_err(Into::into)
 }
 
+/// Reads entries of the directory `fd` as [`Dirent`](kernel_api::syscall::Dirent)s
+/// into `buf`, see [`FileDescriptor::read_dir`](crate::process::fd::FileDescriptor::read_dir).
+pub fn sys_getdents(fd: Fileno, buf: &mut [u8]) -> Result<usize> {
+    trace!("sys_getdents({}, {:#p}, {})", fd, buf.as_ptr(), buf.len());
+    process::current().read_dir(fd, buf)
+}
+
 pub fn sys_write(fd: Fileno, buf: &[u8]) -> Result<usize> {
     trace!("sys_write({}, {:#p}, {})", fd, buf.as_ptr(), buf.len());
     let process = process::current();
@@ -319,5 +832,1179 @@ pub fn sys_bind(socket: usize, address: FfiSockAddr, address_len: usize) -> Resu
 pub fn sys_stat(path: impl AsRef<Path>, stat: &mut Stat) -> Result<()> {
     trace!("sys_stat({:?}, {:#p})", path.as_ref(), stat);
 
-    vfs().stat_path(path, stat).map_err(Into::into).map(|_| ())
+    let path = resolve_at(None, path.as_ref(), ResolveFlags::empty())?;
+    vfs()
+        .stat_path(path.as_path(), stat)
+        .map_err(Into::into)
+        .map(|_| ())
+}
+
+pub fn sys_fstat(fd: Fileno, stat: &mut Stat) -> Result<()> {
+    trace!("sys_fstat({}, {:#p})", fd, stat);
+
+    process::current().stat(fd, stat).map_err(Into::into)
+}
+
+pub fn sys_flock(fd: Fileno, operation: FlockOperation) -> Result<()> {
+    trace!("sys_flock({}, {:?})", fd, operation);
+
+    let block = !operation.contains(FlockOperation::LOCK_NB);
+    let operation = operation.difference(FlockOperation::LOCK_NB);
+    let process = process::current();
+    let result = if operation == FlockOperation::LOCK_SH {
+        process.lock_file(fd, LockKind::Shared, block)
+    } else if operation == FlockOperation::LOCK_EX {
+        process.lock_file(fd, LockKind::Exclusive, block)
+    } else if operation == FlockOperation::LOCK_UN {
+        process.unlock_file(fd)
+    } else {
+        return Err(Errno::EINVAL);
+    };
+    result.map_err(Into::into)
+}
+
+pub fn sys_pipe() -> Result<(Fileno, Fileno)> {
+    trace!("sys_pipe()");
+
+    let process = process::current();
+    let (read_end, write_end) = vfs().create_pipe()?;
+    let read_fd = process.get_fileno_for(read_end, OpenFlags::O_RDONLY);
+    let write_fd = process.get_fileno_for(write_end, OpenFlags::O_WRONLY);
+    Ok((read_fd, write_fd))
+}
+
+/// Waits until at least one of the given file descriptors is ready, or the timeout
+/// expires. A timeout of `None` waits indefinitely, a timeout of zero only takes a
+/// snapshot of the current state.
+///
+/// Returns the number of entries with non-empty `revents`.
+pub fn sys_poll(fds: &mut [PollFd], timeout: Option<Duration>) -> Result<usize> {
+    trace!(
+        "sys_poll({:#p}, {}, {:?})",
+        fds.as_ptr(),
+        fds.len(),
+        timeout
+    );
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut waiters = Vec::new();
+    loop {
+        let ready = poll_once(fds);
+        if ready > 0 {
+            return Ok(ready);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            return Ok(0);
+        }
+
+        if waiters.is_empty() {
+            waiters = wait_for(fds);
+            if !waiters.is_empty() {
+                // the files may have changed before we registered, so we poll
+                // them again before we wait
+                continue;
+            }
+            // none of the files can wake us, so only the deadline ends the wait
+        }
+        park(deadline);
+        if process::current().is_terminating() {
+            return Err(Errno::EINTR);
+        }
+    }
+}
+
+/// Registers the current thread in the wait queues of the polled files.
+fn wait_for(fds: &[PollFd]) -> Vec<Waiter> {
+    let process = process::current();
+    fds.iter()
+        .filter(|pollfd| pollfd.fd >= 0)
+        .filter_map(|pollfd| process.wait_queue(Fileno::new(pollfd.fd as usize)))
+        .map(|queue| queue.register())
+        .collect()
+}
+
+fn poll_once(fds: &mut [PollFd]) -> usize {
+    let process = process::current();
+
+    let mut ready = 0;
+    for pollfd in fds.iter_mut() {
+        pollfd.revents = if pollfd.fd < 0 {
+            PollEvents::empty()
+        } else {
+            match process.poll(Fileno::new(pollfd.fd as usize), pollfd.events) {
+                Ok(events) => events & (pollfd.events | PollEvents::ALWAYS),
+                Err(VfsError::HandleClosed) => PollEvents::POLLNVAL,
+                Err(_) => PollEvents::POLLERR,
+            }
+        };
+
+        if !pollfd.revents.is_empty() {
+            ready += 1;
+        }
+    }
+    ready
+}
+
+/// Enables or disables syscall tracing for the current process.
+pub fn sys_traceme(enable: bool) -> Result<()> {
+    trace!("sys_traceme({})", enable);
+
+    process::current().set_traced(enable);
+    Ok(())
+}
+
+/// Performs the device specific operation `request` on the given file descriptor.
+/// What `arg` points to depends on the request.
+pub fn sys_ioctl(fildes: Fileno, request: u32, arg: UserspaceMutPtr<u8>) -> Result<usize> {
+    trace!("sys_ioctl({}, {:#x}, {:?})", fildes, request, arg);
+
+    process::current().ioctl(fildes, request, arg)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use alloc::string::String;
+    use alloc::{format, vec};
+    use core::ffi::c_void;
+    use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+    use core::time::Duration;
+
+    use foundation::time::Instant;
+    use kernel_api::syscall::{
+        ClockId, Dirent, Errno, FbVarScreenInfo, FileMode, FlockOperation, FutexOp, OpenFlags,
+        PollEvents, PollFd, SigAction, SigActionFlags, SigSet, SigprocmaskHow, Stat, Time,
+        Timespec, Whence, DT_DIR, DT_REG, FBIOGET_VSCREENINFO, NSIG, SIGKILL, SIGTERM, SIGUSR1,
+        SIGUSR2, SIG_DFL,
+    };
+    use kernel_test_framework::kernel_test;
+    use x86_64::instructions::hlt;
+    use x86_64::registers::model_specific::FsBase;
+    use x86_64::structures::paging::mapper::TranslateResult;
+    use x86_64::VirtAddr;
+
+    use crate::io::vfs::lock::LockKind;
+    use crate::io::vfs::{vfs, VfsError};
+    use crate::process;
+    use crate::process::attributes::ProcessId;
+    use crate::process::fd::Fileno;
+    use crate::process::thread::ThreadId;
+    use crate::process::Priority;
+    use crate::syscall::convert::UserspaceMutPtr;
+    use crate::syscall::{
+        sys_chdir, sys_clock_gettime, sys_close, sys_dup, sys_flock, sys_fstat, sys_ftruncate,
+        sys_futex, sys_getcwd, sys_getdents, sys_getpid, sys_ioctl, sys_kill, sys_link, sys_lseek,
+        sys_mkdir, sys_mmap, sys_munmap, sys_nanosleep, sys_open, sys_openat, sys_pipe, sys_poll,
+        sys_read, sys_rename, sys_rmdir, sys_sigaction, sys_sigprocmask, sys_spawn, sys_stat,
+        sys_thread_create, sys_thread_join, sys_unlink, sys_waitpid, sys_write, MapFlags, Prot,
+    };
+    use crate::test_runner::fixture::{self, ScratchRegion, TmpFsMount};
+    use crate::time::HpetInstantProvider;
+
+    extern "C" fn write_after_20ms(fd: *mut c_void) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(20) {
+            hlt();
+        }
+        sys_write(Fileno::new(fd as usize), b"x").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_poll_two_pipes() {
+        let (read1, write1) = sys_pipe().unwrap();
+        let (read2, write2) = sys_pipe().unwrap();
+
+        let start = Instant::now();
+        process::spawn_thread_in_current_process(
+            "pipe_writer",
+            Priority::Normal,
+            write_after_20ms,
+            write2.as_usize() as *mut c_void,
+        );
+
+        let mut fds = [
+            PollFd::new(read1.as_usize() as i32, PollEvents::POLLIN),
+            PollFd::new(read2.as_usize() as i32, PollEvents::POLLIN),
+        ];
+        let ready = sys_poll(&mut fds, Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(1, ready);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(PollEvents::empty(), fds[0].revents);
+        assert_eq!(PollEvents::POLLIN, fds[1].revents);
+
+        for fd in [read1, write1, read2, write2] {
+            sys_close(fd).unwrap();
+        }
+    }
+
+    #[kernel_test]
+    fn test_read_waits_for_data() {
+        let (read, write) = sys_pipe().unwrap();
+
+        let start = Instant::now();
+        process::spawn_thread_in_current_process(
+            "pipe_writer",
+            Priority::Normal,
+            write_after_20ms,
+            write.as_usize() as *mut c_void,
+        );
+
+        let mut buf = [0_u8; 4];
+        assert_eq!(1, sys_read(read, &mut buf).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(b'x', buf[0]);
+
+        sys_close(read).unwrap();
+        sys_close(write).unwrap();
+    }
+
+    extern "C" fn read_after_20ms(fd: *mut c_void) {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(20) {
+            hlt();
+        }
+        let mut buf = [0_u8; 4];
+        assert_eq!(4, sys_read(Fileno::new(fd as usize), &mut buf).unwrap());
+    }
+
+    #[kernel_test]
+    fn test_write_waits_for_room() {
+        let (read, write) = sys_pipe().unwrap();
+        let mut stat = Stat::default();
+        sys_fstat(write, &mut stat).unwrap();
+        let capacity = stat.blksize as usize;
+
+        let start = Instant::now();
+        process::spawn_thread_in_current_process(
+            "pipe_reader",
+            Priority::Normal,
+            read_after_20ms,
+            read.as_usize() as *mut c_void,
+        );
+
+        // the last bytes only fit once the reader made room
+        let buf = vec![b'x'; capacity + 4];
+        assert_eq!(buf.len(), sys_write(write, &buf).unwrap());
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        sys_close(read).unwrap();
+        sys_close(write).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_write_to_full_pipe_without_blocking() {
+        let process = process::current();
+        let (read_end, write_end) = vfs().create_pipe().unwrap();
+        let read = process.get_fileno_for(read_end, OpenFlags::O_RDONLY);
+        let write = process.get_fileno_for(write_end, OpenFlags::O_WRONLY | OpenFlags::O_NONBLOCK);
+        let mut stat = Stat::default();
+        sys_fstat(write, &mut stat).unwrap();
+        let capacity = stat.blksize as usize;
+
+        // only what fits is written
+        let buf = vec![b'x'; capacity + 4];
+        assert_eq!(capacity, sys_write(write, &buf).unwrap());
+        assert_eq!(Err(Errno::EWOULDBLOCK), sys_write(write, b"x"));
+
+        sys_close(read).unwrap();
+        sys_close(write).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_poll_zero_timeout() {
+        let (read, write) = sys_pipe().unwrap();
+
+        let mut fds = [
+            PollFd::new(read.as_usize() as i32, PollEvents::POLLIN),
+            PollFd::new(-1, PollEvents::POLLIN),
+        ];
+        assert_eq!(0, sys_poll(&mut fds, Some(Duration::ZERO)).unwrap());
+        assert_eq!(PollEvents::empty(), fds[0].revents);
+
+        fds[0].events = PollEvents::POLLOUT;
+        fds[0].fd = write.as_usize() as i32;
+        assert_eq!(1, sys_poll(&mut fds, Some(Duration::ZERO)).unwrap());
+        assert_eq!(PollEvents::POLLOUT, fds[0].revents);
+        assert_eq!(PollEvents::empty(), fds[1].revents);
+
+        sys_close(read).unwrap();
+        sys_close(write).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_write_to_pipe_without_readers() {
+        let (read, write) = sys_pipe().unwrap();
+        sys_close(read).unwrap();
+        assert_eq!(Err(Errno::EPIPE), sys_write(write, b"x"));
+        sys_close(write).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_poll_hangup() {
+        let (read, write) = sys_pipe().unwrap();
+        sys_close(write).unwrap();
+
+        let mut fds = [PollFd::new(read.as_usize() as i32, PollEvents::POLLIN)];
+        assert_eq!(1, sys_poll(&mut fds, None).unwrap());
+        assert_eq!(PollEvents::POLLHUP, fds[0].revents);
+
+        sys_close(read).unwrap();
+        let mut fds = [PollFd::new(read.as_usize() as i32, PollEvents::POLLIN)];
+        assert_eq!(1, sys_poll(&mut fds, Some(Duration::ZERO)).unwrap());
+        assert_eq!(PollEvents::POLLNVAL, fds[0].revents);
+    }
+
+    #[kernel_test]
+    fn test_ioctl_unknown_request() {
+        let fd = sys_open("/dev/zero", 0, 0).unwrap();
+        let arg = UserspaceMutPtr::try_from(0).unwrap();
+        assert_eq!(Err(Errno::ENOTTY), sys_ioctl(fd, FBIOGET_VSCREENINFO, arg));
+        sys_close(fd).unwrap();
+
+        assert_eq!(
+            Err(Errno::EBADF),
+            sys_ioctl(Fileno::new(9999), FBIOGET_VSCREENINFO, arg)
+        );
+    }
+
+    #[kernel_test]
+    fn test_ioctl_fb_screen_info() {
+        let mut stat = Stat::default();
+        if sys_stat("/dev/fb0", &mut stat).is_err() {
+            // no framebuffer in this machine
+            return;
+        }
+
+        let fd = sys_open("/dev/fb0", 0, 0).unwrap();
+        let buf = sys_mmap(
+            VirtAddr::zero(),
+            4096,
+            Prot::Read | Prot::Write,
+            MapFlags::Anon | MapFlags::Private,
+            Fileno::new(0),
+            0,
+        )
+        .unwrap();
+        let arg = UserspaceMutPtr::try_from(buf.as_u64() as usize).unwrap();
+        assert_eq!(Ok(0), sys_ioctl(fd, FBIOGET_VSCREENINFO, arg));
+
+        let info = unsafe { arg.cast::<FbVarScreenInfo>().unwrap().read() };
+        assert!(info.width > 0);
+        assert!(info.height > 0);
+        assert!(info.pitch >= info.width * info.bytes_per_pixel);
+        assert_eq!(info.format.bytes_per_pixel(), info.bytes_per_pixel as usize);
+
+        // the whole visible framebuffer must be mappable
+        let len = info.pitch as usize * info.height as usize;
+        assert!(len as u64 <= stat.size);
+        sys_mmap(
+            VirtAddr::zero(),
+            len,
+            Prot::Read | Prot::Write,
+            MapFlags::Shared,
+            fd,
+            0,
+        )
+        .unwrap();
+        sys_close(fd).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_mmap_fb_shared() {
+        let mut stat = Stat::default();
+        if sys_stat("/dev/fb0", &mut stat).is_err() {
+            // no framebuffer in this machine
+            return;
+        }
+
+        // two mappings of the framebuffer must share the device memory
+        let fd = sys_open("/dev/fb0", 0, 0).unwrap();
+        let map = || {
+            let addr = sys_mmap(
+                VirtAddr::zero(),
+                4096,
+                Prot::Read | Prot::Write,
+                MapFlags::Shared,
+                fd,
+                0,
+            )
+            .unwrap();
+            addr.as_mut_ptr::<u32>()
+        };
+        let first = map();
+        let second = map();
+        unsafe {
+            let old = first.read_volatile();
+            first.write_volatile(0x00FF_00FF);
+            assert_eq!(0x00FF_00FF, second.read_volatile());
+            first.write_volatile(old);
+        }
+        sys_close(fd).unwrap();
+    }
+
+    /// The modification time that the build script gives `/var/data/hello.txt`.
+    const HELLO_MTIME: u64 = 1_600_000_000;
+
+    #[kernel_test]
+    fn test_stat() {
+        let mut stat = Stat::default();
+        sys_stat("/var/data", &mut stat).unwrap();
+        assert!(stat.mode.is_directory());
+        assert!(stat.nlink >= 2);
+
+        sys_stat("/var/data/hello.txt", &mut stat).unwrap();
+        assert!(stat.mode.is_regular_file());
+        assert_eq!(Time::from(HELLO_MTIME), stat.mtime.tv_sec);
+        assert_eq!(0, stat.mtime.tv_nsec);
+        assert_eq!(1, stat.nlink);
+        assert_ne!(0, stat.ino);
+        assert!(stat.blocks * 512 >= stat.size);
+
+        // fstat must agree with stat
+        let fd = sys_open("/var/data/hello.txt", 0, 0).unwrap();
+        let mut fstat = Stat::default();
+        sys_fstat(fd, &mut fstat).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(stat, fstat);
+        assert_eq!(Err(Errno::EBADF), sys_fstat(fd, &mut fstat));
+
+        let mut null = Stat::default();
+        sys_stat("/dev/null", &mut null).unwrap();
+        assert!(null.mode.is_char_device());
+        assert_ne!(0, null.ino);
+        assert_ne!(stat.dev, null.dev);
+        assert_ne!(Timespec::default(), null.mtime);
+
+        if sys_stat("/dev/fb0", &mut stat).is_ok() {
+            assert!(stat.mode.is_char_device());
+            assert_eq!(null.dev, stat.dev);
+            assert_ne!(null.ino, stat.ino);
+        }
+    }
+
+    #[kernel_test]
+    fn test_mmap_file() {
+        let fd = sys_open("/var/data/hello.txt", 0, 0).unwrap();
+        let mut expected = [0_u8; 64];
+        let len = sys_read(fd, &mut expected).unwrap();
+        assert!(len > 0);
+
+        let addr = sys_mmap(VirtAddr::zero(), len, Prot::Read, MapFlags::Shared, fd, 0).unwrap();
+        let mapped = unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), len) };
+        assert_eq!(&expected[..len], mapped);
+
+        let map_at = |fd, offset| {
+            sys_mmap(
+                VirtAddr::zero(),
+                4096,
+                Prot::Read,
+                MapFlags::Shared,
+                fd,
+                offset,
+            )
+        };
+        assert_eq!(Err(Errno::EOVERFLOW), map_at(fd, 16 * 4096));
+        assert_eq!(Err(Errno::EINVAL), map_at(fd, 1));
+        assert_eq!(Err(Errno::EOVERFLOW), map_at(fd, usize::MAX & !0xFFF));
+        sys_close(fd).unwrap();
+
+        // files that can't be mapped at all
+        let fd = sys_open("/dev/null", 0, 0).unwrap();
+        assert_eq!(Err(Errno::ENODEV), map_at(fd, 0));
+        sys_close(fd).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_mmap_populate() {
+        let flags = MapFlags::Private | MapFlags::Anon;
+        let prot = Prot::Read | Prot::Write;
+        let translate = |addr| process::current().address_space().read().translate(addr);
+
+        let addr = sys_mmap(VirtAddr::zero(), 4096, prot, flags, Fileno::new(0), 0).unwrap();
+        assert!(!matches!(translate(addr), TranslateResult::Mapped { .. }));
+        sys_munmap(addr, 4096).unwrap();
+
+        let populated = flags | MapFlags::Populate;
+        let addr = sys_mmap(VirtAddr::zero(), 4096, prot, populated, Fileno::new(0), 0).unwrap();
+        assert!(matches!(translate(addr), TranslateResult::Mapped { .. }));
+        sys_munmap(addr, 4096).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_dup_outlives_close() {
+        let fd = sys_open("/var/data/hello.txt", 0, 0).unwrap();
+        let dup = sys_dup(fd).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_close(fd));
+
+        let mut buf = [0_u8; 5];
+        assert_eq!(5, sys_read(dup, &mut buf).unwrap());
+        assert_eq!(b"Hello", &buf);
+        sys_close(dup).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_mapping_outlives_close() {
+        let fd = sys_open("/var/data/hello.txt", 0, 0).unwrap();
+        let mut expected = [0_u8; 11];
+        assert_eq!(11, sys_read(fd, &mut expected).unwrap());
+        let addr = sys_mmap(VirtAddr::zero(), 11, Prot::Read, MapFlags::Private, fd, 0).unwrap();
+        sys_close(fd).unwrap();
+
+        // the pages are only read when they are accessed, which is after the close
+        let mapped = unsafe { core::slice::from_raw_parts(addr.as_ptr::<u8>(), 11) };
+        assert_eq!(&expected, mapped);
+
+        let vm_object = process::vmm().vm_objects().write().remove(&addr);
+        drop(vm_object);
+    }
+
+    #[kernel_test]
+    fn test_unlink_open_file() {
+        let rdwr = (OpenFlags::O_CREAT | OpenFlags::O_RDWR).bits() as usize;
+        let fd = sys_open("/tmp/test_unlink_open", rdwr, 0o644).unwrap();
+        sys_write(fd, b"still here").unwrap();
+        let reader = sys_open("/tmp/test_unlink_open", 0, 0).unwrap();
+
+        sys_unlink("/tmp/test_unlink_open").unwrap();
+        let mut stat = Stat::default();
+        assert_eq!(
+            Err(Errno::ENOENT),
+            sys_stat("/tmp/test_unlink_open", &mut stat)
+        );
+
+        // the data stays until the last open file is closed
+        sys_close(fd).unwrap();
+        let mut buf = [0_u8; 10];
+        assert_eq!(10, sys_read(reader, &mut buf).unwrap());
+        assert_eq!(b"still here", &buf);
+        sys_fstat(reader, &mut stat).unwrap();
+        assert_eq!(0, stat.nlink);
+        sys_close(reader).unwrap();
+
+        // a new file with the same name doesn't see the old data
+        let fd = sys_open("/tmp/test_unlink_open", rdwr, 0o644).unwrap();
+        assert_eq!(0, sys_read(fd, &mut buf).unwrap());
+        sys_close(fd).unwrap();
+        sys_unlink("/tmp/test_unlink_open").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_mkdir_unlink_rmdir() {
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+        let excl = (OpenFlags::O_CREAT | OpenFlags::O_EXCL).bits() as usize;
+
+        sys_mkdir("/tmp/test_mkdir", 0o755).unwrap();
+        assert_eq!(Err(Errno::EEXIST), sys_mkdir("/tmp/test_mkdir", 0o755));
+        assert_eq!(Err(Errno::ENOENT), sys_mkdir("/tmp/nonexistent/dir", 0o755));
+
+        let fd = sys_open("/tmp/test_mkdir/file", creat, 0o644).unwrap();
+        sys_write(fd, b"hello").unwrap();
+        sys_close(fd).unwrap();
+        let fd = sys_open("/tmp/test_mkdir/file", creat, 0o644).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(
+            Err(Errno::EEXIST),
+            sys_open("/tmp/test_mkdir/file", excl, 0o644)
+        );
+        assert_eq!(
+            Err(Errno::ENOTDIR),
+            sys_mkdir("/tmp/test_mkdir/file/dir", 0o755)
+        );
+
+        assert_eq!(Err(Errno::ENOTEMPTY), sys_rmdir("/tmp/test_mkdir"));
+        assert_eq!(Err(Errno::ENOTDIR), sys_rmdir("/tmp/test_mkdir/file"));
+        assert_eq!(Err(Errno::EISDIR), sys_unlink("/tmp/test_mkdir"));
+
+        sys_unlink("/tmp/test_mkdir/file").unwrap();
+        assert_eq!(Err(Errno::ENOENT), sys_unlink("/tmp/test_mkdir/file"));
+        sys_rmdir("/tmp/test_mkdir").unwrap();
+        assert_eq!(Err(Errno::ENOENT), sys_rmdir("/tmp/test_mkdir"));
+    }
+
+    #[kernel_test]
+    fn test_mkdir_in_devfs() {
+        assert_eq!(Err(Errno::EPERM), sys_mkdir("/dev/foo", 0o755));
+        assert_eq!(Err(Errno::EPERM), sys_unlink("/dev/zero"));
+        assert_eq!(Err(Errno::EBUSY), sys_rmdir("/dev"));
+    }
+
+    #[kernel_test]
+    fn test_rename() {
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+
+        let fd = sys_open("/tmp/test_rename_new", creat, 0o644).unwrap();
+        sys_write(fd, b"new").unwrap();
+        sys_close(fd).unwrap();
+        let fd = sys_open("/tmp/test_rename_old", creat, 0o644).unwrap();
+        sys_write(fd, b"old").unwrap();
+        sys_close(fd).unwrap();
+
+        sys_rename("/tmp/test_rename_new", "/tmp/test_rename_old").unwrap();
+        let mut stat = Stat::default();
+        assert_eq!(
+            Err(Errno::ENOENT),
+            sys_stat("/tmp/test_rename_new", &mut stat)
+        );
+
+        let fd = sys_open("/tmp/test_rename_old", 0, 0).unwrap();
+        let mut buf = [0_u8; 3];
+        assert_eq!(3, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"new", &buf);
+        sys_close(fd).unwrap();
+
+        assert_eq!(
+            Err(Errno::EXDEV),
+            sys_rename("/tmp/test_rename_old", "/dev/test_rename")
+        );
+        assert_eq!(Err(Errno::EXDEV), sys_rename("/dev/zero", "/tmp/zero"));
+
+        sys_unlink("/tmp/test_rename_old").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_open_flags() {
+        let path = "/tmp/test_open_flags";
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+        let excl = (OpenFlags::O_CREAT | OpenFlags::O_EXCL).bits() as usize;
+        let mut buf = [0_u8; 1];
+
+        // file type bits in the mode are ignored
+        let fd = sys_open(path, creat, 0o060_640).unwrap();
+        let mut stat = Stat::default();
+        sys_fstat(fd, &mut stat).unwrap();
+        assert_eq!(
+            FileMode::S_IFREG | FileMode::from_bits_retain(0o640),
+            stat.mode
+        );
+        assert_eq!(Err(Errno::EBADF), sys_read(fd, &mut buf));
+        sys_write(fd, b"x").unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::EEXIST), sys_open(path, excl, 0o644));
+
+        let fd = sys_open(path, OpenFlags::O_RDONLY.bits() as usize, 0).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_write(fd, b"x"));
+        assert_eq!(1, sys_read(fd, &mut buf).unwrap());
+        sys_close(fd).unwrap();
+
+        for invalid in [
+            OpenFlags::O_ACCMODE.bits() as usize,
+            (OpenFlags::O_CREAT | OpenFlags::O_DIRECTORY).bits() as usize,
+            0x4000_0000,
+            usize::MAX,
+        ] {
+            assert_eq!(Err(Errno::EINVAL), sys_open(path, invalid, 0));
+        }
+
+        let directory = OpenFlags::O_DIRECTORY.bits() as usize;
+        assert_eq!(Err(Errno::ENOTDIR), sys_open(path, directory, 0));
+        let fd = sys_open("/tmp", directory, 0).unwrap();
+        sys_close(fd).unwrap();
+        let wronly = OpenFlags::O_WRONLY.bits() as usize;
+        assert_eq!(Err(Errno::EISDIR), sys_open("/tmp", wronly, 0));
+
+        sys_unlink(path).unwrap();
+    }
+
+    const RECORD_LEN: usize = 16;
+    const RECORDS: usize = 64;
+
+    static APPENDER_DONE: AtomicBool = AtomicBool::new(false);
+
+    fn append_records(fd: Fileno, byte: u8) {
+        for _ in 0..RECORDS {
+            assert_eq!(RECORD_LEN, sys_write(fd, &[byte; RECORD_LEN]).unwrap());
+        }
+    }
+
+    extern "C" fn append_bs(fd: *mut c_void) {
+        append_records(Fileno::new(fd as usize), b'b');
+        APPENDER_DONE.store(true, Ordering::Release);
+    }
+
+    #[kernel_test]
+    fn test_concurrent_appends() {
+        let path = "/tmp/test_concurrent_appends";
+        let append = OpenFlags::O_CREAT | OpenFlags::O_WRONLY | OpenFlags::O_APPEND;
+        let a = sys_open(path, append.bits() as usize, 0o644).unwrap();
+        let b = sys_open(path, append.bits() as usize, 0o644).unwrap();
+
+        process::spawn_thread_in_current_process(
+            "appender",
+            Priority::Normal,
+            append_bs,
+            b.as_usize() as *mut c_void,
+        );
+        append_records(a, b'a');
+        while !APPENDER_DONE.load(Ordering::Acquire) {
+            hlt();
+        }
+        sys_close(a).unwrap();
+        sys_close(b).unwrap();
+
+        // no record was overwritten, and no record was split by another one
+        let mut content = [0_u8; 2 * RECORDS * RECORD_LEN + 1];
+        let fd = sys_open(path, OpenFlags::O_RDONLY.bits() as usize, 0).unwrap();
+        let len = sys_read(fd, &mut content).unwrap();
+        assert_eq!(2 * RECORDS * RECORD_LEN, len);
+        let count = |byte: u8| {
+            content[..len]
+                .chunks(RECORD_LEN)
+                .filter(|record| record.iter().all(|&b| b == byte))
+                .count()
+        };
+        assert_eq!(RECORDS, count(b'a'));
+        assert_eq!(RECORDS, count(b'b'));
+        sys_close(fd).unwrap();
+
+        sys_unlink(path).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_link() {
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+
+        let fd = sys_open("/tmp/test_link", creat, 0o644).unwrap();
+        sys_write(fd, b"linked").unwrap();
+        sys_link("/tmp/test_link", "/tmp/test_link_2").unwrap();
+        let mut stat = Stat::default();
+        sys_fstat(fd, &mut stat).unwrap();
+        assert_eq!(2, stat.nlink);
+        sys_close(fd).unwrap();
+
+        assert_eq!(
+            Err(Errno::EEXIST),
+            sys_link("/tmp/test_link", "/tmp/test_link_2")
+        );
+        assert_eq!(
+            Err(Errno::EXDEV),
+            sys_link("/tmp/test_link", "/var/tmp/test_link")
+        );
+        sys_mkdir("/tmp/test_link_dir", 0o755).unwrap();
+        assert_eq!(
+            Err(Errno::EPERM),
+            sys_link("/tmp/test_link_dir", "/tmp/test_link_3")
+        );
+        sys_rmdir("/tmp/test_link_dir").unwrap();
+
+        sys_unlink("/tmp/test_link").unwrap();
+        let fd = sys_open("/tmp/test_link_2", 0, 0).unwrap();
+        let mut buf = [0_u8; 6];
+        assert_eq!(6, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"linked", &buf);
+        sys_fstat(fd, &mut stat).unwrap();
+        assert_eq!(1, stat.nlink);
+        sys_close(fd).unwrap();
+        sys_unlink("/tmp/test_link_2").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_ftruncate() {
+        let rdwr = (OpenFlags::O_CREAT | OpenFlags::O_RDWR).bits() as usize;
+
+        let fd = sys_open("/tmp/test_ftruncate", rdwr, 0o644).unwrap();
+        sys_write(fd, b"hello world").unwrap();
+
+        // shrink, the file offset is now past the end of the file
+        sys_ftruncate(fd, 5).unwrap();
+        let mut stat = Stat::default();
+        sys_stat("/tmp/test_ftruncate", &mut stat).unwrap();
+        assert_eq!(5, stat.size);
+        let mut buf = [0xFF_u8; 11];
+        assert_eq!(0, sys_read(fd, &mut buf).unwrap());
+
+        // grow again, the regrown region must not contain the old data
+        sys_ftruncate(fd, 11).unwrap();
+        sys_close(fd).unwrap();
+        let fd = sys_open("/tmp/test_ftruncate", 0, 0).unwrap();
+        assert_eq!(11, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"hello\0\0\0\0\0\0", &buf);
+
+        // not opened for writing
+        assert_eq!(Err(Errno::EINVAL), sys_ftruncate(fd, 0));
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_ftruncate(fd, 0));
+
+        let trunc = (OpenFlags::O_TRUNC | OpenFlags::O_WRONLY).bits() as usize;
+        let fd = sys_open("/tmp/test_ftruncate", trunc, 0).unwrap();
+        sys_stat("/tmp/test_ftruncate", &mut stat).unwrap();
+        assert_eq!(0, stat.size);
+        sys_close(fd).unwrap();
+
+        sys_unlink("/tmp/test_ftruncate").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_lseek() {
+        let rdwr = (OpenFlags::O_CREAT | OpenFlags::O_RDWR).bits() as usize;
+        let fd = sys_open("/tmp/test_lseek", rdwr, 0o644).unwrap();
+        sys_write(fd, b"hello world").unwrap();
+
+        assert_eq!(11, sys_lseek(fd, 0, Whence::SeekCur).unwrap());
+        assert_eq!(6, sys_lseek(fd, 6, Whence::SeekSet).unwrap());
+        let mut buf = [0_u8; 5];
+        assert_eq!(5, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"world", &buf);
+
+        assert_eq!(8, sys_lseek(fd, -3, Whence::SeekEnd).unwrap());
+        sys_write(fd, b"LD").unwrap();
+        assert_eq!(4, sys_lseek(fd, -6, Whence::SeekCur).unwrap());
+        assert_eq!(5, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"o woL", &buf);
+
+        // past the end is fine, and writing there leaves a hole of zeros
+        assert_eq!(13, sys_lseek(fd, 2, Whence::SeekEnd).unwrap());
+        sys_write(fd, b"!").unwrap();
+        let mut stat = Stat::default();
+        sys_fstat(fd, &mut stat).unwrap();
+        assert_eq!(14, stat.size);
+
+        // but not before the start
+        assert_eq!(Err(Errno::EINVAL), sys_lseek(fd, -1, Whence::SeekSet));
+        assert_eq!(14, sys_lseek(fd, 0, Whence::SeekCur).unwrap());
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_lseek(fd, 0, Whence::SeekSet));
+        sys_unlink("/tmp/test_lseek").unwrap();
+
+        let (read, write) = sys_pipe().unwrap();
+        assert_eq!(Err(Errno::ESPIPE), sys_lseek(read, 0, Whence::SeekCur));
+        sys_close(read).unwrap();
+        sys_close(write).unwrap();
+    }
+
+    fn map_anonymous(len: usize) -> VirtAddr {
+        sys_mmap(
+            VirtAddr::zero(),
+            len,
+            Prot::Read | Prot::Write,
+            MapFlags::Anon | MapFlags::Private,
+            Fileno::new(0),
+            0,
+        )
+        .unwrap()
+    }
+
+    #[kernel_test]
+    fn test_munmap() {
+        let count = || process::vmm().vm_objects().read().len();
+        let before = count();
+        let addr = map_anonymous(3 * 4096);
+        assert_eq!(before + 1, count());
+
+        // mappings can't be split
+        assert_eq!(Err(Errno::EINVAL), sys_munmap(addr + 4096_u64, 4096));
+        assert_eq!(Err(Errno::EINVAL), sys_munmap(addr + 1_u64, 4096));
+        assert_eq!(Err(Errno::EINVAL), sys_munmap(addr, 0));
+        sys_munmap(addr, 3 * 4096).unwrap();
+        assert_eq!(before, count());
+        // nothing is mapped there anymore, which is fine
+        sys_munmap(addr, 4096).unwrap();
+    }
+
+    extern "C" fn add_fs_base(arg: usize) -> usize {
+        FsBase::read().as_u64() as usize + arg
+    }
+
+    #[kernel_test(fixture = fixture::scratch_region)]
+    fn test_thread_create_and_join(stack: &ScratchRegion) {
+        // the thread never accesses its thread local storage
+        let tls = VirtAddr::new(0x1234_5000);
+        let entry = VirtAddr::new(add_fs_base as usize as u64);
+        let stack_top = stack.addr() + stack.size() as u64;
+        let id = sys_thread_create(entry, 7, stack_top, tls).unwrap();
+        let id = ThreadId::from(id as u64);
+
+        assert_eq!(Ok(0x1234_5007), sys_thread_join(id));
+        // a thread can only be joined once
+        assert_eq!(Err(Errno::ESRCH), sys_thread_join(id));
+        assert_eq!(
+            Err(Errno::EDEADLK),
+            sys_thread_join(*process::current_thread().id())
+        );
+    }
+
+    #[kernel_test]
+    fn test_futex() {
+        let futex = AtomicU32::new(1);
+        let addr = VirtAddr::from_ptr(&futex);
+        assert_eq!(Err(Errno::EWOULDBLOCK), sys_futex(addr, FutexOp::Wait, 0));
+        assert_eq!(
+            Err(Errno::EINVAL),
+            sys_futex(addr + 1_u64, FutexOp::Wake, 1)
+        );
+        assert_eq!(Ok(0), sys_futex(addr, FutexOp::Wake, 1));
+    }
+
+    #[kernel_test(fixture = fixture::tmpfs)]
+    fn test_flock(tmp: &TmpFsMount) {
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_RDWR).bits() as usize;
+        let (sh, ex, nb, un) = (
+            FlockOperation::LOCK_SH,
+            FlockOperation::LOCK_EX,
+            FlockOperation::LOCK_NB,
+            FlockOperation::LOCK_UN,
+        );
+
+        let path = tmp.path("test_flock");
+        let fd = sys_open(&path, creat, 0o644).unwrap();
+        for invalid in [FlockOperation::empty(), nb, sh | ex, ex | un] {
+            assert_eq!(Err(Errno::EINVAL), sys_flock(fd, invalid));
+        }
+
+        // another process that contends for the same file
+        let other = ProcessId::new();
+        let other_node = vfs().open(&path).unwrap();
+        let lock_other = || vfs().lock(&other_node, other, LockKind::Exclusive, false);
+
+        sys_flock(fd, ex | nb).unwrap();
+        assert_eq!(Err(VfsError::WouldBlock), lock_other());
+
+        // the lock is released when the last duplicate is closed
+        let dup = sys_dup(fd).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(Err(VfsError::WouldBlock), lock_other());
+        sys_close(dup).unwrap();
+        lock_other().unwrap();
+
+        let fd = sys_open(&path, OpenFlags::O_RDWR.bits() as usize, 0).unwrap();
+        assert_eq!(Err(Errno::EWOULDBLOCK), sys_flock(fd, sh | nb));
+        vfs().unlock(&other_node, other).unwrap();
+        sys_flock(fd, sh | nb).unwrap();
+        sys_flock(fd, un).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_flock(fd, un));
+
+        drop(other_node);
+    }
+
+    #[kernel_test]
+    fn test_openat() {
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+        let beneath = OpenFlags::O_RESOLVE_BENEATH.bits() as usize;
+
+        sys_mkdir("/tmp/test_openat", 0o755).unwrap();
+        sys_mkdir("/tmp/test_openat/b", 0o755).unwrap();
+        let fd = sys_open("/tmp/test_openat/b/c", creat, 0o644).unwrap();
+        sys_write(fd, b"c").unwrap();
+        sys_close(fd).unwrap();
+
+        let dirfd = sys_open("/tmp/test_openat", 0, 0).unwrap();
+        let fd = sys_openat(Some(dirfd), "b/c", 0, 0).unwrap();
+        let mut buf = [0_u8; 1];
+        assert_eq!(1, sys_read(fd, &mut buf).unwrap());
+        assert_eq!(b"c", &buf);
+
+        // the directory fd must refer to a directory
+        assert_eq!(Err(Errno::ENOTDIR), sys_openat(Some(fd), "c", 0, 0));
+        sys_close(fd).unwrap();
+
+        let fd = sys_openat(Some(dirfd), "b/../b/c", beneath, 0).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(
+            Err(Errno::EXDEV),
+            sys_openat(Some(dirfd), "../../etc", beneath, 0)
+        );
+        assert_eq!(
+            Err(Errno::EXDEV),
+            sys_openat(Some(dirfd), "/tmp/test_openat/b/c", beneath, 0)
+        );
+        sys_close(dirfd).unwrap();
+        assert_eq!(Err(Errno::EBADF), sys_openat(Some(dirfd), "b/c", 0, 0));
+
+        sys_unlink("/tmp/test_openat/b/c").unwrap();
+        sys_rmdir("/tmp/test_openat/b").unwrap();
+        sys_rmdir("/tmp/test_openat").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_chdir() {
+        let creat = OpenFlags::O_CREAT.bits() as usize;
+        let mut buf = [0_u8; 64];
+
+        sys_mkdir("/tmp/test_chdir", 0o755).unwrap();
+        let fd = sys_open("/tmp/test_chdir/file", creat, 0o644).unwrap();
+        sys_close(fd).unwrap();
+
+        sys_chdir("/tmp/test_chdir").unwrap();
+        let len = sys_getcwd(&mut buf).unwrap();
+        assert_eq!(b"/tmp/test_chdir\0", &buf[..len]);
+        assert_eq!(Err(Errno::ERANGE), sys_getcwd(&mut buf[..len - 1]));
+
+        // relative paths are now resolved against the new working directory
+        let fd = sys_open("file", 0, 0).unwrap();
+        sys_close(fd).unwrap();
+        assert_eq!(Err(Errno::ENOTDIR), sys_chdir("file"));
+        sys_chdir("..").unwrap();
+        let len = sys_getcwd(&mut buf).unwrap();
+        assert_eq!(b"/tmp\0", &buf[..len]);
+
+        sys_chdir("/").unwrap();
+        sys_unlink("/tmp/test_chdir/file").unwrap();
+        sys_rmdir("/tmp/test_chdir").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_spawn_errors() {
+        assert_eq!(
+            Err(Errno::ENOENT),
+            sys_spawn("/tmp/test_spawn_missing", vec![], vec![])
+        );
+        assert_eq!(Err(Errno::EACCES), sys_spawn("/tmp", vec![], vec![]));
+        let huge = "A".repeat(kernel_api::ARG_MAX);
+        assert_eq!(Err(Errno::E2BIG), sys_spawn("/tmp", vec![], vec![huge]));
+        // the kernel task has no children
+        assert_eq!(Err(Errno::ECHILD), sys_waitpid(usize::MAX));
+    }
+
+    #[kernel_test]
+    fn test_clock_gettime() {
+        let mut first = Timespec::default();
+        let mut second = Timespec::default();
+        sys_clock_gettime(ClockId::Monotonic, &mut first).unwrap();
+        sys_clock_gettime(ClockId::Monotonic, &mut second).unwrap();
+        let nanos = |ts: Timespec| u64::from(ts.tv_sec) * 1_000_000_000 + ts.tv_nsec;
+        assert!(nanos(first) <= nanos(second));
+        assert!(first.tv_nsec < 1_000_000_000);
+
+        // QEMU starts the real time clock at the time of the host, which is
+        // after this was written
+        let mut now = Timespec::default();
+        sys_clock_gettime(ClockId::Realtime, &mut now).unwrap();
+        assert!(u64::from(now.tv_sec) > 1_700_000_000);
+    }
+
+    #[kernel_test]
+    fn test_getdents() {
+        sys_mkdir("/tmp/test_getdents", 0o755).unwrap();
+        sys_mkdir("/tmp/test_getdents/dir", 0o755).unwrap();
+        let creat = (OpenFlags::O_CREAT | OpenFlags::O_WRONLY).bits() as usize;
+        for i in 0..20 {
+            let fd = sys_open(format!("/tmp/test_getdents/file{i}"), creat, 0o644).unwrap();
+            sys_close(fd).unwrap();
+        }
+
+        let flags = (OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY).bits() as usize;
+        let fd = sys_open("/tmp/test_getdents", flags, 0).unwrap();
+        // small enough that every call only returns a few entries
+        let mut buf = [0_u8; 80];
+        let mut entries = vec![];
+        loop {
+            let len = sys_getdents(fd, &mut buf).unwrap();
+            if len == 0 {
+                break;
+            }
+            let mut rest = &buf[..len];
+            while !rest.is_empty() {
+                let (dirent, name) = Dirent::read(rest).unwrap();
+                assert_ne!(0, dirent.ino);
+                entries.push((String::from_utf8(name.to_vec()).unwrap(), dirent));
+                rest = &rest[usize::from(dirent.reclen)..];
+            }
+        }
+        assert_eq!(21, entries.len());
+        for (name, dirent) in &entries {
+            let expected = if name == "dir" { DT_DIR } else { DT_REG };
+            assert_eq!(expected, dirent.typ, "{name}");
+        }
+        for i in 0..20 {
+            let name = format!("file{i}");
+            assert!(entries.iter().any(|(n, _)| *n == name), "{name}");
+        }
+
+        // the offset of an entry continues after it
+        sys_lseek(fd, entries[2].1.off as isize, Whence::SeekSet).unwrap();
+        let len = sys_getdents(fd, &mut buf).unwrap();
+        assert_eq!(
+            entries[3].0.as_bytes(),
+            Dirent::read(&buf[..len]).unwrap().1
+        );
+        sys_lseek(fd, 0, Whence::SeekSet).unwrap();
+        let len = sys_getdents(fd, &mut buf).unwrap();
+        assert_eq!(
+            entries[0].0.as_bytes(),
+            Dirent::read(&buf[..len]).unwrap().1
+        );
+        assert_eq!(Err(Errno::EINVAL), sys_getdents(fd, &mut buf[..8]));
+        sys_close(fd).unwrap();
+
+        let fd = sys_open("/tmp/test_getdents/file0", 0, 0).unwrap();
+        assert_eq!(Err(Errno::ENOTDIR), sys_getdents(fd, &mut buf));
+        sys_close(fd).unwrap();
+
+        for i in 0..20 {
+            sys_unlink(format!("/tmp/test_getdents/file{i}")).unwrap();
+        }
+        sys_rmdir("/tmp/test_getdents/dir").unwrap();
+        sys_rmdir("/tmp/test_getdents").unwrap();
+    }
+
+    #[kernel_test]
+    fn test_sigaction() {
+        let handler = SigAction {
+            handler: 0x1234,
+            flags: SigActionFlags::SA_RESTORER | SigActionFlags::SA_SIGINFO,
+            restorer: 0x5678,
+            mask: SigSet::EMPTY.with(SIGUSR2),
+        };
+        // without a restorer, a handler would have nowhere to return to
+        let no_restorer = SigAction {
+            flags: SigActionFlags::SA_SIGINFO,
+            ..handler
+        };
+        assert_eq!(
+            Err(Errno::EINVAL),
+            sys_sigaction(SIGUSR1, Some(no_restorer))
+        );
+        assert_eq!(Err(Errno::EINVAL), sys_sigaction(SIGKILL, Some(handler)));
+        assert_eq!(Err(Errno::EINVAL), sys_sigaction(0, None));
+        assert_eq!(Err(Errno::EINVAL), sys_sigaction(NSIG, None));
+
+        let old = sys_sigaction(SIGUSR1, Some(handler)).unwrap();
+        assert_eq!(SIG_DFL, old.handler);
+        assert_eq!(handler, sys_sigaction(SIGUSR1, None).unwrap());
+        assert_eq!(handler, sys_sigaction(SIGUSR1, Some(old)).unwrap());
+        assert_eq!(old, sys_sigaction(SIGUSR1, None).unwrap());
+    }
+
+    #[kernel_test]
+    fn test_sigprocmask() {
+        let old = sys_sigprocmask(SigprocmaskHow::Block, None).unwrap();
+        let set = SigSet::EMPTY.with(SIGUSR1).with(SIGKILL);
+        assert_eq!(
+            old,
+            sys_sigprocmask(SigprocmaskHow::Block, Some(set)).unwrap()
+        );
+        let blocked = sys_sigprocmask(SigprocmaskHow::Unblock, Some(SigSet::EMPTY)).unwrap();
+        assert!(blocked.contains(SIGUSR1));
+        // SIGKILL can't be blocked
+        assert!(!blocked.contains(SIGKILL));
+
+        sys_sigprocmask(SigprocmaskHow::Unblock, Some(set)).unwrap();
+        assert!(!sys_sigprocmask(SigprocmaskHow::Block, None)
+            .unwrap()
+            .contains(SIGUSR1));
+        sys_sigprocmask(SigprocmaskHow::SetMask, Some(old)).unwrap();
+    }
+
+    #[kernel_test]
+    fn test_kill_errors() {
+        // the tests run in the kernel process
+        assert_eq!(0, sys_getpid().unwrap());
+        assert_eq!(Err(Errno::EPERM), sys_kill(0, SIGTERM));
+        assert_eq!(Err(Errno::EPERM), sys_kill(0, 0));
+        assert_eq!(Err(Errno::ESRCH), sys_kill(u32::MAX as usize, SIGTERM));
+        assert_eq!(Err(Errno::ESRCH), sys_kill(u32::MAX as usize, 0));
+        assert_eq!(Err(Errno::EINVAL), sys_kill(0, NSIG));
+    }
+
+    #[kernel_test]
+    fn test_nanosleep() {
+        let mut remaining = Duration::MAX;
+        let start = Instant::now();
+        sys_nanosleep(Duration::from_millis(20), &mut remaining).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        // only an interrupted sleep has time left
+        assert_eq!(Duration::MAX, remaining);
+
+        sys_nanosleep(Duration::ZERO, &mut remaining).unwrap();
+    }
 }
@@ -0,0 +1,198 @@
+use core::fmt::{Display, Formatter};
+use core::time::Duration;
+
+use foundation::time::Instant;
+use log::info;
+use spin::Mutex;
+
+use kernel_api::syscall::Errno;
+
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::process;
+use crate::syscall::convert::UserspaceAddress;
+use crate::syscall::dispatch::{ArgKind, SyscallArgs, SyscallEntry};
+use crate::time::HpetInstantProvider;
+
+/// The maximum number of trace lines that are written per second, across all
+/// traced processes. Lines beyond that are dropped, so that a busy process doesn't
+/// drown the serial console.
+const MAX_LINES_PER_SECOND: usize = 256;
+
+/// Path arguments are truncated after this many bytes.
+const MAX_PATH_LEN: usize = 64;
+
+static RATE_LIMIT: Mutex<Option<RateLimitWindow>> = Mutex::new(None);
+
+struct RateLimitWindow {
+    start: Instant,
+    lines: usize,
+    dropped: usize,
+}
+
+fn may_log() -> bool {
+    let now = Instant::now();
+    let mut guard = RATE_LIMIT.lock();
+    let window = guard.get_or_insert(RateLimitWindow {
+        start: now,
+        lines: 0,
+        dropped: 0,
+    });
+
+    if now.duration_since(window.start) >= Duration::from_secs(1) {
+        if window.dropped > 0 {
+            info!(
+                "[strace] rate limit exceeded, dropped {} lines",
+                window.dropped
+            );
+        }
+        *window = RateLimitWindow {
+            start: now,
+            lines: 0,
+            dropped: 0,
+        };
+    }
+
+    if window.lines < MAX_LINES_PER_SECOND {
+        window.lines += 1;
+        true
+    } else {
+        window.dropped += 1;
+        false
+    }
+}
+
+/// Logs the entry of a syscall of a traced process.
+pub(super) fn trace_entry(entry: &SyscallEntry, args: &SyscallArgs) {
+    if !may_log() {
+        return;
+    }
+
+    info!(
+        "[strace] pid={} tid={} -> {}({})",
+        process::current().pid(),
+        process::current_thread().id(),
+        entry.syscall().name(),
+        TracedArgs { entry, args },
+    );
+}
+
+/// Logs the result of a syscall of a traced process.
+pub(super) fn trace_exit(entry: &SyscallEntry, result: isize) {
+    if !may_log() {
+        return;
+    }
+
+    let pid = process::current().pid();
+    let tid = process::current_thread().id();
+    let name = entry.syscall().name();
+    match Errno::from_return_value(result) {
+        Ok(value) => info!("[strace] pid={pid} tid={tid} <- {name} = {value}"),
+        Err(errno) => info!("[strace] pid={pid} tid={tid} <- {name} = {errno}"),
+    }
+}
+
+struct TracedArgs<'a> {
+    entry: &'a SyscallEntry,
+    args: &'a SyscallArgs,
+}
+
+impl Display for TracedArgs<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        for (i, (kind, &arg)) in self.entry.args().iter().zip(self.args).enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match kind {
+                ArgKind::Int => write!(f, "{}", arg as isize)?,
+                ArgKind::Fd => write!(f, "{}", arg)?,
+                ArgKind::Ptr => write!(f, "{:#x}", arg)?,
+                ArgKind::Path => write_path(f, arg)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes the nul-terminated string at the given address, truncated to
+/// [`MAX_PATH_LEN`] bytes. Addresses that are not in userspace are written
+/// as plain addresses, and so are strings that run into memory that the
+/// process can't read.
+fn write_path(f: &mut Formatter<'_>, addr: usize) -> core::fmt::Result {
+    let Ok(start) = UserspaceAddress::try_from(addr) else {
+        return write!(f, "{:#x}", addr);
+    };
+    let readable = readable_len(*start, MAX_PATH_LEN);
+    if addr == 0 || readable == 0 {
+        return write!(f, "{:#x}", addr);
+    }
+
+    let ptr = addr as *const u8;
+    let mut buf = [0_u8; MAX_PATH_LEN];
+    let mut len = 0;
+    let mut truncated = true;
+    while len < readable {
+        // copy byte by byte, so that we don't read past the terminating nul
+        let b = unsafe { ptr.add(len).read_volatile() };
+        if b == 0 {
+            truncated = false;
+            break;
+        }
+        buf[len] = b;
+        len += 1;
+    }
+    if truncated && len < MAX_PATH_LEN {
+        // the string isn't terminated before the end of the readable memory
+        return write!(f, "{:#x}", addr);
+    }
+
+    match core::str::from_utf8(&buf[..len]) {
+        Ok(s) => write!(f, "{:?}", s)?,
+        Err(_) => write!(f, "{:#x}", addr)?,
+    }
+    if truncated {
+        write!(f, "...")?;
+    }
+    Ok(())
+}
+
+/// How many of the `len` bytes from `addr` on lie in mappings of the current
+/// process that can be read, so that reading them can't fault. Memory that is
+/// allocated on access is readable, guard pages aren't.
+fn readable_len(addr: VirtAddr, len: usize) -> usize {
+    let vm_objects = process::vmm().vm_objects().read();
+    let mut readable = 0;
+    while readable < len {
+        let at = addr + readable as u64;
+        let Some(vm_object) = vm_objects
+            .range(..=at)
+            .next_back()
+            .map(|(_, vm_object)| vm_object)
+            .filter(|vm_object| {
+                vm_object.contains_addr(at) && vm_object.flags().contains(PageTableFlags::PRESENT)
+            })
+        else {
+            break;
+        };
+        readable = (vm_object.addr() + vm_object.size() as u64 - addr) as usize;
+    }
+    readable.min(len)
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+    use crate::test_runner::fixture::{self, ScratchRegion};
+
+    #[kernel_test(fixture = fixture::scratch_region)]
+    fn test_readable_len(region: &ScratchRegion) {
+        let end = region.addr() + region.size() as u64;
+        assert_eq!(MAX_PATH_LEN, readable_len(region.addr(), MAX_PATH_LEN));
+        assert_eq!(3, readable_len(end - 3_u64, 3));
+        // nothing is mapped at the lowest pages, so that null pointers fault
+        assert_eq!(0, readable_len(VirtAddr::new(0x1000), MAX_PATH_LEN));
+    }
+}
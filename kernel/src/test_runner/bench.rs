@@ -0,0 +1,130 @@
+//! Runs the [`KERNEL_BENCHES`] instead of the tests, if the kernel command
+//! line has `test-bench`.
+//!
+//! Benchmarks are reported like tests, so that the host sees which ones
+//! failed, and what each one measured is printed on a line of its own:
+//! `##BENCH <name> <iterations> <median> <min> <max> <median in ns>`, where
+//! the median, min and max are cycles of the TSC per iteration.
+
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use core::arch::x86_64::_rdtsc;
+use core::hint::spin_loop;
+use core::time::Duration;
+
+use foundation::time::Instant;
+use kernel_test_framework::{
+    Bencher, KernelBenchDescription, Summary, DEFAULT_TIMEOUT_MS, KERNEL_BENCHES,
+};
+use spin::Mutex;
+
+use super::{escape, finish, full_name, run_in_thread, Options, Watched};
+use crate::time::HpetInstantProvider;
+use crate::{serial_print, serial_println};
+
+/// What the running benchmark measured, which its thread leaves here.
+static SUMMARY: Mutex<Option<Summary>> = Mutex::new(None);
+
+/// How long the TSC is compared to the HPET to find its frequency.
+const CALIBRATION: Duration = Duration::from_millis(50);
+
+pub(super) fn run_benches(options: &Options) -> ! {
+    let tsc_hz = tsc_hz();
+    serial_println!("TSC frequency: {} kHz", tsc_hz / 1000);
+
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    for bench in KERNEL_BENCHES {
+        let name = full_name(&bench.bench_location, bench.name);
+        if !options.selects(&name) {
+            serial_println!("bench {}...[skipped]", name);
+            serial_println!("##TEST-SKIP {}", name);
+            skipped += 1;
+            continue;
+        }
+
+        serial_println!("##TEST-START {}", name);
+        serial_print!("bench {}...", name);
+        match run_bench(bench, &name, tsc_hz) {
+            Ok((duration, summary)) => {
+                serial_println!(
+                    "[ok] median {} cycles ({} ns), min {}, max {}, {} iterations per sample",
+                    summary.median,
+                    summary.nanos(summary.median),
+                    summary.min,
+                    summary.max,
+                    summary.iterations
+                );
+                serial_println!(
+                    "##BENCH {} {} {} {} {} {}",
+                    name,
+                    summary.iterations,
+                    summary.median,
+                    summary.min,
+                    summary.max,
+                    summary.nanos(summary.median)
+                );
+                serial_println!("##TEST-PASS {} {}", name, duration.as_micros());
+                passed += 1;
+            }
+            Err(reason) => {
+                serial_println!("[failed]");
+                serial_println!("{}", reason);
+                serial_println!("##TEST-FAIL {} {}", name, escape(&reason));
+                failed += 1;
+            }
+        }
+    }
+    finish(passed, failed, skipped)
+}
+
+/// Runs the benchmark, and returns how long that took and what it measured,
+/// or why it failed.
+fn run_bench(
+    bench: &'static KernelBenchDescription,
+    name: &str,
+    tsc_hz: u64,
+) -> Result<(Duration, Summary), String> {
+    let watched = Watched {
+        name: name.to_string(),
+        location: &bench.bench_location,
+        timeout_ms: DEFAULT_TIMEOUT_MS,
+    };
+    let bench_fn = bench.bench_fn;
+    *SUMMARY.lock() = None;
+    let (duration, panic) = watched.watch(|| {
+        run_in_thread(
+            bench.name,
+            watched.timeout(),
+            Box::new(move || {
+                let mut bencher = Bencher::new(tsc_hz);
+                bench_fn(&mut bencher);
+                *SUMMARY.lock() = bencher.summary();
+            }),
+        )
+    });
+
+    if let Some(panic) = panic {
+        return Err(panic.to_string());
+    }
+    let summary = SUMMARY
+        .lock()
+        .take()
+        .ok_or_else(|| "note: the benchmark never called `Bencher::iter`".to_string())?;
+    Ok((duration, summary))
+}
+
+/// The frequency of the TSC, from how often it ticks while the HPET counts
+/// [`CALIBRATION`].
+fn tsc_hz() -> u64 {
+    let start = Instant::now();
+    let tsc_start = unsafe { _rdtsc() };
+    while start.elapsed() < CALIBRATION {
+        spin_loop();
+    }
+    let tsc_end = unsafe { _rdtsc() };
+    let elapsed = start.elapsed();
+
+    (u128::from(tsc_end - tsc_start) * 1_000_000_000 / elapsed.as_nanos()) as u64
+}
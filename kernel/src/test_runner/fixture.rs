@@ -0,0 +1,147 @@
+//! Stock [fixtures](TestFixture) for kernel tests, which also check that the
+//! test cleaned up after itself.
+//!
+//! ```ignore
+//! #[kernel_test(fixture = fixture::tmpfs)]
+//! fn test_something(tmp: &TmpFsMount) {
+//!     let fd = sys_open(&tmp.path("file"), ...).unwrap();
+//!     ...
+//! }
+//! ```
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use kernel_test_framework::TestFixture;
+use x86_64::structures::paging::PageTableFlags;
+use x86_64::VirtAddr;
+
+use crate::io::vfs::tmpfs::TmpFs;
+use crate::io::vfs::{vfs, FsId, MountFlags};
+use crate::mem::virt::{AllocationStrategy, MapAt};
+use crate::process;
+
+/// An empty [`TmpFs`], mounted at a path that no other test uses.
+pub fn tmpfs() -> TmpFsMount {
+    TmpFsMount::setup()
+}
+
+/// A region of memory in the current process that is mapped for the test.
+pub fn scratch_region() -> ScratchRegion {
+    ScratchRegion::setup()
+}
+
+/// How many [`TmpFsMount`]s there have been, which makes their mount points
+/// unique.
+static MOUNTS: AtomicUsize = AtomicUsize::new(0);
+
+/// See [`tmpfs`]. The test must close all files that it opens in it.
+pub struct TmpFsMount {
+    mount_point: String,
+}
+
+impl TmpFsMount {
+    pub fn mount_point(&self) -> &str {
+        &self.mount_point
+    }
+
+    /// The path of the file with the given name in the file system.
+    pub fn path(&self, name: &str) -> String {
+        format!("{}/{name}", self.mount_point)
+    }
+}
+
+impl TestFixture for TmpFsMount {
+    fn setup() -> Self {
+        let mount_point = format!(
+            "/mnt/tmpfs_fixture{}",
+            MOUNTS.fetch_add(1, Ordering::Relaxed)
+        );
+        vfs()
+            .mount(&mount_point, TmpFs::new(FsId::new()), MountFlags::empty())
+            .unwrap();
+        Self { mount_point }
+    }
+
+    fn teardown(self) {
+        let open_handles = vfs()
+            .mounts()
+            .find(|mount| mount.path.as_str() == self.mount_point)
+            .expect("the test unmounted the tmpfs of the fixture")
+            .open_handles;
+        assert_eq!(
+            0, open_handles,
+            "the test left files open in {}",
+            self.mount_point
+        );
+        vfs().unmount(&self.mount_point).unwrap();
+    }
+}
+
+/// See [`scratch_region`]. The test may use the region however it likes, but
+/// must unmap everything else that it maps.
+pub struct ScratchRegion {
+    addr: VirtAddr,
+    /// The vm objects of the process before the region was mapped.
+    before: BTreeSet<VirtAddr>,
+}
+
+impl ScratchRegion {
+    const LEN: usize = 16 * 4096;
+
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+
+    pub fn size(&self) -> usize {
+        Self::LEN
+    }
+}
+
+impl TestFixture for ScratchRegion {
+    fn setup() -> Self {
+        let vmm = process::vmm();
+        let before = vmm.vm_objects().read().keys().copied().collect();
+        let addr = vmm
+            .allocate_memory_backed_vmobject(
+                "kernel test scratch region".into(),
+                MapAt::Anywhere,
+                Self::LEN,
+                AllocationStrategy::AllocateNow,
+                PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+            )
+            .unwrap();
+        Self { addr, before }
+    }
+
+    fn teardown(self) {
+        // dropping a vm object unmaps it, which must not happen while the
+        // vm objects are locked
+        let (region, leaked) = {
+            let mut vm_objects = process::vmm().vm_objects().write();
+            let region = vm_objects.remove(&self.addr);
+            let new = vm_objects
+                .keys()
+                .filter(|addr| !self.before.contains(addr))
+                .copied()
+                .collect::<Vec<_>>();
+            let leaked = new
+                .into_iter()
+                .filter_map(|addr| vm_objects.remove(&addr))
+                .collect::<Vec<_>>();
+            (region, leaked)
+        };
+        let names = leaked
+            .iter()
+            .map(|vm_object| format!("{} at {:p}", vm_object.name(), vm_object.addr()))
+            .collect::<Vec<_>>();
+        drop(leaked);
+
+        assert!(region.is_some(), "the test unmapped the scratch region");
+        drop(region);
+        assert!(names.is_empty(), "the test leaked mappings: {names:?}");
+    }
+}
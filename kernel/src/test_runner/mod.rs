@@ -0,0 +1,524 @@
+//! Runs the [`KERNEL_TESTS`] in a test kernel.
+//!
+//! The kernel can't unwind, so a panicking test can't be caught like in `std`.
+//! Instead, every test runs in a kernel thread of its own, and the panic
+//! handler of the test kernel passes panics to [`handle_panic`]. If the panic
+//! happened in the thread of the running test, that only ends the thread, and
+//! the runner goes on with the next test.
+//!
+//! A test that panics while it holds a lock never releases it, so later tests
+//! that need the same lock will hang. A panic while a panic is handled means
+//! that the test broke more than the runner can recover from, so the runner
+//! fails the test and exits QEMU.
+//!
+//! A test that hangs is stopped by a watchdog in the timer interrupt, which
+//! exits QEMU once the test has run for longer than its `timeout_ms`. It can't
+//! interrupt tests that run with interrupts disabled. It notes that if it
+//! fires late, and if such a test finishes before interrupts are enabled
+//! again, the runner fails it afterwards.
+//!
+//! The runner reads its options from the [kernel command line](crate::qemu::command_line):
+//! * `test-filter=<substring>` only runs the tests whose `module::name`
+//!   contains the substring, and skips the others.
+//! * `test-list` prints the names of all tests and benchmarks instead of
+//!   running them.
+//! * `test-bench` runs the [benchmarks](bench) instead of the tests.
+//!
+//! A test with a `fixture` has its fixture torn down in another thread once
+//! the test has finished, so that teardown happens even if the test panicked.
+//! [`fixture`] has the fixtures that the kernel provides.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::{String, ToString};
+use core::ffi::c_void;
+use core::fmt::{Display, Formatter};
+use core::panic::PanicInfo;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
+use core::time::Duration;
+
+use foundation::time::Instant;
+use kernel_test_framework::{
+    KernelTestDescription, ShouldPanic, SourceLocation, KERNEL_BENCHES, KERNEL_TESTS,
+};
+use spin::Mutex;
+use x86_64::instructions::{hlt, interrupts};
+
+use crate::process::thread::ThreadId;
+use crate::process::{self, Priority};
+use crate::qemu::ExitCode;
+use crate::time::HpetInstantProvider;
+use crate::{serial_print, serial_println};
+
+mod bench;
+pub mod fixture;
+
+/// The test that is currently running, if any.
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+/// When the running test times out, in nanoseconds since boot, or zero if no
+/// test is running. The watchdog can't wait for [`RUNNING`], so this and
+/// [`TIMING_OUT`] are atomics.
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+
+/// The test or benchmark that [`DEADLINE`] belongs to.
+static TIMING_OUT: AtomicPtr<Watched> = AtomicPtr::new(ptr::null_mut());
+
+/// Whether [`handle_panic`] is handling a panic, so that it notices if it
+/// panics itself.
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+/// How much later than the deadline the watchdog may fire before it notes that
+/// interrupts were probably disabled.
+const WATCHDOG_SLACK: Duration = Duration::from_millis(100);
+
+struct Running {
+    /// The thread that runs the test, once it has started.
+    thread: Option<ThreadId>,
+    started: Instant,
+    /// How long the test ran, once it has finished.
+    duration: Option<Duration>,
+    panic: Option<Panic>,
+}
+
+impl Running {
+    fn finish(&mut self, panic: Option<Panic>) {
+        // disarm the watchdog before the runner notices, which may take a while
+        DEADLINE.store(0, Ordering::SeqCst);
+        self.duration = Some(self.started.elapsed());
+        self.panic = panic;
+    }
+}
+
+/// What the watchdog reports about the test or benchmark that it watches.
+struct Watched {
+    name: String,
+    location: &'static SourceLocation,
+    timeout_ms: u64,
+}
+
+impl Watched {
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// Runs `f`, which runs the test or benchmark, with this as the one that
+    /// the watchdog reports if it fires.
+    fn watch<T>(&self, f: impl FnOnce() -> T) -> T {
+        TIMING_OUT.store(ptr::from_ref(self).cast_mut(), Ordering::SeqCst);
+        let result = f();
+        TIMING_OUT.store(ptr::null_mut(), Ordering::SeqCst);
+        result
+    }
+}
+
+/// A panic of a test thread.
+struct Panic {
+    message: String,
+    location: Option<String>,
+}
+
+impl Display for Panic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match &self.location {
+            Some(location) => write!(f, "panicked at {location}:\n{}", self.message),
+            None => write!(f, "panicked:\n{}", self.message),
+        }
+    }
+}
+
+/// The options of the runner, from the kernel command line.
+#[derive(Debug, Default, Eq, PartialEq)]
+struct Options {
+    filter: Option<String>,
+    list: bool,
+    bench: bool,
+}
+
+impl Options {
+    fn parse(command_line: &str) -> Self {
+        let mut options = Self::default();
+        for argument in command_line.split_whitespace() {
+            if let Some(filter) = argument.strip_prefix("test-filter=") {
+                options.filter = Some(filter.to_string());
+            } else if argument == "test-list" {
+                options.list = true;
+            } else if argument == "test-bench" {
+                options.bench = true;
+            }
+        }
+        options
+    }
+
+    /// Whether the test or benchmark with the given full name should run.
+    fn selects(&self, full_name: &str) -> bool {
+        self.filter
+            .as_deref()
+            .is_none_or(|filter| full_name.contains(filter))
+    }
+}
+
+/// The name of the test or benchmark, prefixed with its module.
+fn full_name(location: &SourceLocation, name: &str) -> String {
+    format!("{}::{}", location.module, name)
+}
+
+/// Runs the kernel tests that the kernel command line selects, prints their
+/// results, and exits QEMU with [`ExitCode::Success`] if none of them failed.
+/// With `test-bench`, this runs the benchmarks instead.
+///
+/// Besides the output for humans, the runner prints lines for the host to
+/// parse, which start with `##`:
+/// * `##TEST-START <name>` before a test runs,
+/// * `##TEST-PASS <name> <duration in µs>` after it passed,
+/// * `##TEST-FAIL <name> <reason>` after it failed, where the reason has its
+///   backslashes and newlines escaped as `\\` and `\n`,
+/// * `##TEST-SKIP <name>` for a test that the filter skipped, and
+/// * `##SUITE-END <passed> <failed> <skipped>` after all tests.
+pub fn run_tests() -> ! {
+    let options = Options::parse(&crate::qemu::command_line());
+
+    if options.list {
+        for test in KERNEL_TESTS {
+            serial_println!("{}: test", full_name(&test.test_location, test.name));
+        }
+        for bench in KERNEL_BENCHES {
+            serial_println!("{}: bench", full_name(&bench.bench_location, bench.name));
+        }
+        serial_println!(
+            "{} tests, {} benchmarks",
+            KERNEL_TESTS.len(),
+            KERNEL_BENCHES.len()
+        );
+        crate::qemu::exit(ExitCode::Success)
+    }
+    if options.bench {
+        bench::run_benches(&options)
+    }
+
+    let mut passed = 0;
+    let mut failed = 0;
+    let mut skipped = 0;
+    for test in KERNEL_TESTS {
+        let name = full_name(&test.test_location, test.name);
+        if !options.selects(&name) {
+            serial_println!("test {}...[skipped]", name);
+            serial_println!("##TEST-SKIP {}", name);
+            skipped += 1;
+            continue;
+        }
+
+        serial_println!("##TEST-START {}", name);
+        serial_print!("test {}...", name);
+        match run_test(test, &name) {
+            Ok(duration) => {
+                serial_println!("[ok]");
+                serial_println!("##TEST-PASS {} {}", name, duration.as_micros());
+                passed += 1;
+            }
+            Err(reason) => {
+                serial_println!("[failed]");
+                serial_println!("{}", reason);
+                serial_println!("##TEST-FAIL {} {}", name, escape(&reason));
+                failed += 1;
+            }
+        }
+    }
+    finish(passed, failed, skipped)
+}
+
+/// Prints the counts of the tests or benchmarks, and exits QEMU with
+/// [`ExitCode::Success`] if none of them failed.
+fn finish(passed: usize, failed: usize, skipped: usize) -> ! {
+    serial_println!(
+        "test result: {}. {} run; {} failed; {} skipped",
+        if failed == 0 { "ok" } else { "FAILED" },
+        passed + failed,
+        failed,
+        skipped
+    );
+    serial_println!("##SUITE-END {} {} {}", passed, failed, skipped);
+
+    crate::qemu::exit(if failed == 0 {
+        ExitCode::Success
+    } else {
+        ExitCode::Failed
+    })
+}
+
+/// Escapes the reason of a failed test, so that it fits on a line.
+fn escape(reason: &str) -> String {
+    reason.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Ends the current thread if it runs a test, so that the runner can go on
+/// with the next test. Returns if the panic didn't happen in a test, in which
+/// case the test kernel can't go on. Exits QEMU if this panics itself.
+pub fn handle_panic(info: &PanicInfo) {
+    if PANICKING.swap(true, Ordering::SeqCst) {
+        // formatting the panic is what panicked, most likely, so we can't
+        // tell anything about it
+        bail("panicked while handling a panic");
+    }
+
+    let panic = Panic {
+        message: format!("{}", info.message()),
+        location: info.location().map(|location| location.to_string()),
+    };
+    let current = *process::current_thread().id();
+    {
+        let mut running = RUNNING.lock();
+        let Some(running) = running
+            .as_mut()
+            .filter(|running| running.thread == Some(current))
+        else {
+            PANICKING.store(false, Ordering::SeqCst);
+            return;
+        };
+        running.finish(Some(panic));
+    }
+    PANICKING.store(false, Ordering::SeqCst);
+
+    // the scheduler only switches away from the thread on an interrupt, and
+    // the test may have panicked with interrupts disabled
+    interrupts::enable();
+    process::exit_thread()
+}
+
+/// Fails the running test with the given reason, and exits QEMU, because the
+/// runner can't go on.
+fn bail(reason: &str) -> ! {
+    DEADLINE.store(0, Ordering::SeqCst);
+    serial_println!("[failed]");
+    serial_println!("{}", reason);
+    if let Some(watched) = unsafe { TIMING_OUT.load(Ordering::SeqCst).as_ref() } {
+        serial_println!("##TEST-FAIL {} {}", watched.name, escape(reason));
+    }
+    crate::qemu::exit(ExitCode::Failed)
+}
+
+/// Exits QEMU if the running test has timed out. This is called from the
+/// timer interrupt, so it must not wait for any lock.
+pub fn check_timeout() {
+    let deadline = DEADLINE.load(Ordering::SeqCst);
+    if deadline == 0 {
+        return;
+    }
+    let now = nanos(Instant::now());
+    if now < deadline {
+        return;
+    }
+
+    // the deadline is armed after the test is stored, and disarmed before it's cleared
+    if let Some(watched) = unsafe { TIMING_OUT.load(Ordering::SeqCst).as_ref() } {
+        let mut reason = format!(
+            "the test at {} timed out after {} ms",
+            watched.location, watched.timeout_ms
+        );
+        let late = Duration::from_nanos(now - deadline);
+        if late > WATCHDOG_SLACK {
+            reason += &format!(
+                "\nnote: the watchdog fired {} ms after the timeout, so the test probably ran with interrupts disabled",
+                late.as_millis()
+            );
+        }
+        serial_println!("[timeout]");
+        serial_println!("{}", reason);
+        serial_println!("##TEST-FAIL {} {}", watched.name, escape(&reason));
+    }
+    crate::qemu::exit(ExitCode::Failed)
+}
+
+fn nanos(instant: Instant) -> u64 {
+    instant.duration_since(Instant::new(0)).as_nanos() as u64
+}
+
+/// Runs the test, and returns how long it took if it passed, or why it
+/// failed.
+fn run_test(test: &'static KernelTestDescription, name: &str) -> Result<Duration, String> {
+    let watched = Watched {
+        name: name.to_string(),
+        location: &test.test_location,
+        timeout_ms: test.timeout_ms,
+    };
+    let timeout = watched.timeout();
+    let (duration, panic, teardown) = watched.watch(|| {
+        let (duration, panic) = run_in_thread(test.name, timeout, Box::new(test.test_fn));
+        // the fixture is torn down even if the test panicked
+        let teardown = kernel_test_framework::take_teardown()
+            .map(|teardown| run_in_thread(test.name, timeout, teardown));
+        (duration, panic, teardown)
+    });
+
+    let mut result = if duration > timeout {
+        Err(format!(
+            "note: test ran for {} ms, longer than its timeout of {} ms, without the watchdog stopping it, so it probably ran with interrupts disabled",
+            duration.as_millis(),
+            test.timeout_ms
+        ))
+    } else {
+        check(&test.should_panic, panic).map(|()| duration)
+    };
+    if let Some((_, Some(panic))) = teardown {
+        let reason = format!("the teardown of the fixture {panic}");
+        result = Err(match result {
+            Ok(_) => reason,
+            Err(earlier) => format!("{earlier}\n{reason}"),
+        });
+    }
+    result
+}
+
+/// Something to run in a thread of its own, which is a test or the teardown
+/// of its fixture.
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Runs the job in a new thread with the watchdog armed, and returns how long
+/// it ran, and how it panicked, if it did.
+fn run_in_thread(name: &str, timeout: Duration, job: Job) -> (Duration, Option<Panic>) {
+    let started = Instant::now();
+    *RUNNING.lock() = Some(Running {
+        thread: None,
+        started,
+        duration: None,
+        panic: None,
+    });
+    DEADLINE.store(nanos(started + timeout), Ordering::SeqCst);
+    process::spawn_thread_in_current_process(
+        name,
+        Priority::Normal,
+        job_thread,
+        Box::into_raw(Box::new(job)).cast(),
+    );
+
+    // we don't have wait queues yet, so we give up our time slice and check again
+    let running = loop {
+        if let Some(running) = RUNNING.lock().take_if(|running| running.duration.is_some()) {
+            break running;
+        }
+        hlt();
+    };
+    (running.duration.unwrap_or_default(), running.panic)
+}
+
+extern "C" fn job_thread(arg: *mut c_void) {
+    let job = *unsafe { Box::from_raw(arg.cast::<Job>()) };
+    if let Some(running) = RUNNING.lock().as_mut() {
+        running.thread = Some(*process::current_thread().id());
+    }
+
+    job();
+
+    if let Some(running) = RUNNING.lock().as_mut() {
+        running.finish(None);
+    }
+    // the scheduler can't switch away from the thread if the test left
+    // interrupts disabled
+    interrupts::enable();
+}
+
+/// Decides whether a test passed, given whether it should have panicked and
+/// how it actually did.
+fn check(should_panic: &ShouldPanic, panic: Option<Panic>) -> Result<(), String> {
+    match (should_panic, panic) {
+        (ShouldPanic::No, None) | (ShouldPanic::Yes, Some(_)) => Ok(()),
+        (ShouldPanic::No, Some(panic)) => Err(panic.to_string()),
+        (ShouldPanic::Expected(expected), Some(panic)) => {
+            if panic.message.contains(expected) {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{panic}\nnote: panic did not contain expected string\n      panic message: {:?}\n expected substring: {expected:?}",
+                    panic.message
+                ))
+            }
+        }
+        (ShouldPanic::Yes | ShouldPanic::Expected(_), None) => {
+            Err("note: test did not panic as expected".to_string())
+        }
+    }
+}
+
+#[cfg(feature = "kernel_test")]
+mod tests {
+    use kernel_test_framework::kernel_test;
+
+    use super::*;
+
+    fn panic(message: &str) -> Option<Panic> {
+        Some(Panic {
+            message: message.to_string(),
+            location: None,
+        })
+    }
+
+    #[kernel_test]
+    fn test_check() {
+        assert!(check(&ShouldPanic::No, None).is_ok());
+        assert!(check(&ShouldPanic::No, panic("oops")).is_err());
+        assert!(check(&ShouldPanic::Yes, panic("oops")).is_ok());
+        assert!(check(&ShouldPanic::Yes, None).is_err());
+        assert!(check(
+            &ShouldPanic::Expected("out of"),
+            panic("index out of bounds")
+        )
+        .is_ok());
+        assert!(check(
+            &ShouldPanic::Expected("overflow"),
+            panic("index out of bounds")
+        )
+        .is_err());
+        assert!(check(&ShouldPanic::Expected("out of"), None).is_err());
+    }
+
+    #[kernel_test]
+    fn test_options() {
+        assert_eq!(Options::default(), Options::parse(""));
+        assert_eq!(
+            Options {
+                filter: Some("vfs::".to_string()),
+                list: false,
+                bench: false,
+            },
+            Options::parse("quiet test-filter=vfs:: other=1")
+        );
+        assert_eq!(
+            Options {
+                filter: None,
+                list: true,
+                bench: false,
+            },
+            Options::parse("test-list")
+        );
+        assert_eq!(
+            Options {
+                filter: Some("mem::".to_string()),
+                list: false,
+                bench: true,
+            },
+            Options::parse("test-bench test-filter=mem::")
+        );
+    }
+
+    #[kernel_test]
+    fn test_escape() {
+        assert_eq!("a b", escape("a b"));
+        assert_eq!(
+            "panicked at x:\\nboom \\\\n",
+            escape("panicked at x:\nboom \\n")
+        );
+    }
+
+    #[kernel_test(should_panic)]
+    fn test_should_panic() {
+        panic!("this test is supposed to panic");
+    }
+
+    #[kernel_test(should_panic(expected = "out of bounds"))]
+    fn test_should_panic_with_expected_message() {
+        let values = [1, 2, 3];
+        let index = values.len();
+        let _ = core::hint::black_box(values)[core::hint::black_box(index)];
+    }
+}
@@ -0,0 +1,59 @@
+use syn::meta::ParseNestedMeta;
+use syn::{LitInt, LitStr, Path};
+
+/// The arguments of `#[kernel_test(...)]`.
+#[derive(Default)]
+pub struct Arguments {
+    /// `None` if the test must not panic, `Some(None)` for `should_panic` and
+    /// `Some(Some(expected))` for `should_panic(expected = "...")`.
+    pub should_panic: Option<Option<LitStr>>,
+    /// The `timeout_ms = ...` of the test, if it has one.
+    pub timeout_ms: Option<u64>,
+    /// The function that sets up the fixture of the test, if it has one.
+    pub fixture: Option<Path>,
+}
+
+impl Arguments {
+    pub fn parse(&mut self, meta: ParseNestedMeta) -> syn::Result<()> {
+        if meta.path.is_ident("should_panic") {
+            if self.should_panic.is_some() {
+                return Err(meta.error("duplicate `should_panic`"));
+            }
+            let mut expected = None;
+            if !meta.input.is_empty() && !meta.input.peek(syn::Token![,]) {
+                meta.parse_nested_meta(|meta| {
+                    if meta.path.is_ident("expected") {
+                        expected = Some(meta.value()?.parse()?);
+                        Ok(())
+                    } else {
+                        Err(meta.error("expected `expected = \"...\"`"))
+                    }
+                })?;
+            }
+            self.should_panic = Some(expected);
+            Ok(())
+        } else if meta.path.is_ident("timeout_ms") {
+            if self.timeout_ms.is_some() {
+                return Err(meta.error("duplicate `timeout_ms`"));
+            }
+            let literal: LitInt = meta.value()?.parse()?;
+            let timeout_ms = literal.base10_parse()?;
+            if timeout_ms == 0 {
+                return Err(syn::Error::new(
+                    literal.span(),
+                    "`timeout_ms` must be positive",
+                ));
+            }
+            self.timeout_ms = Some(timeout_ms);
+            Ok(())
+        } else if meta.path.is_ident("fixture") {
+            if self.fixture.is_some() {
+                return Err(meta.error("duplicate `fixture`"));
+            }
+            self.fixture = Some(meta.value()?.parse()?);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported kernel_test argument"))
+        }
+    }
+}
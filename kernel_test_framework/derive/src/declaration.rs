@@ -2,7 +2,9 @@ use proc_macro2::TokenStream;
 use quote::{format_ident, quote};
 use syn::ItemFn;
 
-pub fn expand(test_fn: ItemFn) -> TokenStream {
+use crate::arguments::Arguments;
+
+pub fn expand(arguments: Arguments, test_fn: ItemFn) -> TokenStream {
     let fn_name_ident = &test_fn.sig.ident;
     let fn_name = fn_name_ident.to_string();
 
@@ -10,13 +12,30 @@ pub fn expand(test_fn: ItemFn) -> TokenStream {
 
     let name = quote! { #fn_name };
 
-    let test_location = quote! {
-        kernel_test_framework::SourceLocation {
-            module: module_path!(),
-            file: file!(),
-            line: line!(),
-            column: column!(),
-        }
+    let test_location = source_location();
+
+    let should_panic = match arguments.should_panic {
+        None => quote! { kernel_test_framework::ShouldPanic::No },
+        Some(None) => quote! { kernel_test_framework::ShouldPanic::Yes },
+        Some(Some(expected)) => quote! { kernel_test_framework::ShouldPanic::Expected(#expected) },
+    };
+
+    let timeout_ms = match arguments.timeout_ms {
+        None => quote! { kernel_test_framework::DEFAULT_TIMEOUT_MS },
+        Some(timeout_ms) => quote! { #timeout_ms },
+    };
+
+    // a test with a fixture takes it by reference, so the glue sets it up
+    let test = match arguments.fixture {
+        None => quote! { #fn_name_ident },
+        Some(fixture) => quote! {
+            {
+                fn with_fixture() {
+                    kernel_test_framework::run_with_fixture(#fixture, #fn_name_ident)
+                }
+                with_fixture
+            }
+        },
     };
 
     quote! {
@@ -25,8 +44,41 @@ pub fn expand(test_fn: ItemFn) -> TokenStream {
         #[linkme::distributed_slice(kernel_test_framework::KERNEL_TESTS)]
         static #description_name: kernel_test_framework::KernelTestDescription = kernel_test_framework::KernelTestDescription {
             name: #name,
-            test_fn: #fn_name_ident,
+            test_fn: #test,
             test_location: #test_location,
+            should_panic: #should_panic,
+            timeout_ms: #timeout_ms,
+        };
+    }
+}
+
+pub fn expand_bench(bench_fn: ItemFn) -> TokenStream {
+    let fn_name_ident = &bench_fn.sig.ident;
+    let fn_name = fn_name_ident.to_string();
+
+    let description_name = format_ident!("__KERNEL_BENCH_{}", fn_name);
+
+    let bench_location = source_location();
+
+    quote! {
+        #bench_fn
+
+        #[linkme::distributed_slice(kernel_test_framework::KERNEL_BENCHES)]
+        static #description_name: kernel_test_framework::KernelBenchDescription = kernel_test_framework::KernelBenchDescription {
+            name: #fn_name,
+            bench_fn: #fn_name_ident,
+            bench_location: #bench_location,
         };
     }
 }
+
+fn source_location() -> TokenStream {
+    quote! {
+        kernel_test_framework::SourceLocation {
+            module: module_path!(),
+            file: file!(),
+            line: line!(),
+            column: column!(),
+        }
+    }
+}
@@ -1,11 +1,28 @@
 use proc_macro::TokenStream;
 use syn::parse_macro_input;
 
+use crate::arguments::Arguments;
+
+mod arguments;
 mod declaration;
 
 #[proc_macro_attribute]
-pub fn kernel_test(_attribute: TokenStream, item: TokenStream) -> TokenStream {
-    let expanded = declaration::expand(parse_macro_input!(item));
+pub fn kernel_test(attribute: TokenStream, item: TokenStream) -> TokenStream {
+    let mut arguments = Arguments::default();
+    let parser = syn::meta::parser(|meta| arguments.parse(meta));
+    parse_macro_input!(attribute with parser);
+
+    let expanded = declaration::expand(arguments, parse_macro_input!(item));
+
+    TokenStream::from(expanded)
+}
+
+#[proc_macro_attribute]
+pub fn kernel_bench(attribute: TokenStream, item: TokenStream) -> TokenStream {
+    let parser = syn::meta::parser(|meta| Err(meta.error("unsupported kernel_bench argument")));
+    parse_macro_input!(attribute with parser);
+
+    let expanded = declaration::expand_bench(parse_macro_input!(item));
 
     TokenStream::from(expanded)
 }
@@ -0,0 +1,150 @@
+use core::arch::x86_64::{__cpuid, __rdtscp, _mm_lfence, _rdtsc, CpuidResult};
+use core::hint::black_box;
+
+/// How long measuring a benchmark should take, roughly, in milliseconds
+const TARGET_RUNTIME_MS: u64 = 500;
+
+/// How many samples of a benchmark are measured. Each one runs the routine
+/// as many times as fit into its share of [`TARGET_RUNTIME_MS`].
+const SAMPLES: usize = 51;
+
+/// The most iterations of a routine per sample, in case the routine is so
+/// fast that the compiler removed it
+const MAX_ITERATIONS: u64 = 1 << 30;
+
+/// Measures a benchmark, which gets it from the runner.
+///
+/// ```ignore
+/// #[kernel_bench]
+/// fn bench_something(b: &mut Bencher) {
+///     b.iter(|| something());
+/// }
+/// ```
+pub struct Bencher {
+    tsc_hz: u64,
+    timestamps: Timestamps,
+    summary: Option<Summary>,
+}
+
+/// The cycles that one iteration of a benchmark took, over all samples.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Summary {
+    /// How often the routine ran per sample
+    pub iterations: u64,
+    pub median: u64,
+    pub min: u64,
+    pub max: u64,
+    /// The frequency of the TSC, which the cycles are counted in
+    pub tsc_hz: u64,
+}
+
+impl Summary {
+    /// The given number of cycles in nanoseconds.
+    pub fn nanos(&self, cycles: u64) -> u64 {
+        (u128::from(cycles) * 1_000_000_000 / u128::from(self.tsc_hz)) as u64
+    }
+}
+
+impl Bencher {
+    /// A bencher that converts cycles to time with the given frequency of the
+    /// TSC, which the runner calibrates.
+    pub fn new(tsc_hz: u64) -> Self {
+        Self {
+            tsc_hz,
+            timestamps: Timestamps::detect(),
+            summary: None,
+        }
+    }
+
+    /// Measures how many cycles the routine takes. Its result is passed
+    /// through [`black_box`], so that the compiler can't remove it.
+    ///
+    /// The number of iterations per sample is doubled until a sample takes
+    /// long enough, and the time that the loop itself takes is subtracted.
+    pub fn iter<T>(&mut self, mut routine: impl FnMut() -> T) {
+        let sample_cycles = self.tsc_hz * TARGET_RUNTIME_MS / 1000 / SAMPLES as u64;
+        let mut iterations = 1;
+        while iterations < MAX_ITERATIONS && self.measure(iterations, &mut routine) < sample_cycles
+        {
+            iterations *= 2;
+        }
+
+        let mut overhead = [0; SAMPLES];
+        for sample in &mut overhead {
+            *sample = self.measure(iterations, &mut || ());
+        }
+        overhead.sort_unstable();
+        let overhead = overhead[SAMPLES / 2];
+
+        let mut samples = [0; SAMPLES];
+        for sample in &mut samples {
+            *sample = self
+                .measure(iterations, &mut routine)
+                .saturating_sub(overhead)
+                / iterations;
+        }
+        samples.sort_unstable();
+        self.summary = Some(Summary {
+            iterations,
+            median: samples[SAMPLES / 2],
+            min: samples[0],
+            max: samples[SAMPLES - 1],
+            tsc_hz: self.tsc_hz,
+        });
+    }
+
+    /// What [`Bencher::iter`] measured, or `None` if the benchmark didn't
+    /// call it.
+    pub fn summary(&self) -> Option<Summary> {
+        self.summary
+    }
+
+    /// How many cycles it takes to run the routine the given number of times.
+    fn measure<T>(&self, iterations: u64, routine: &mut impl FnMut() -> T) -> u64 {
+        let start = self.timestamps.start();
+        for _ in 0..iterations {
+            black_box(routine());
+        }
+        self.timestamps.end().wrapping_sub(start)
+    }
+}
+
+/// Reads the TSC so that no instructions of the measured code are reordered
+/// around it.
+#[derive(Debug, Copy, Clone)]
+struct Timestamps {
+    /// `rdtscp` waits for the instructions before it, but not every CPU
+    /// supports it, e.g. QEMU's default CPU doesn't
+    rdtscp: bool,
+}
+
+impl Timestamps {
+    fn detect() -> Self {
+        let CpuidResult { edx, .. } = __cpuid(0x8000_0001);
+        Self {
+            rdtscp: edx & (1 << 27) != 0,
+        }
+    }
+
+    fn start(self) -> u64 {
+        unsafe {
+            _mm_lfence();
+            let timestamp = _rdtsc();
+            _mm_lfence();
+            timestamp
+        }
+    }
+
+    fn end(self) -> u64 {
+        unsafe {
+            let timestamp = if self.rdtscp {
+                __rdtscp(&mut 0)
+            } else {
+                _mm_lfence();
+                _rdtsc()
+            };
+            _mm_lfence();
+            timestamp
+        }
+    }
+}
@@ -0,0 +1,52 @@
+use alloc::boxed::Box;
+
+use spin::Mutex;
+
+/// A fixture of a kernel test, as declared with
+/// `#[kernel_test(fixture = ...)]`. It is set up before the test, passed to
+/// it by reference, and torn down after it, even if the test panics.
+pub trait TestFixture {
+    /// Creates the fixture, before the test runs
+    fn setup() -> Self
+    where
+        Self: Sized;
+
+    /// Cleans up after the test. A fixture that finds that the test left
+    /// something behind panics, which fails the test.
+    fn teardown(self);
+}
+
+/// The teardown of the fixture of the test that ran last, which the runner
+/// takes with [`take_teardown`].
+static TEARDOWN: Mutex<Option<Teardown>> = Mutex::new(None);
+
+/// Tears down the fixture of a test
+pub type Teardown = Box<dyn FnOnce() + Send>;
+
+/// Sets up the fixture, and runs the test with it. This is what
+/// `#[kernel_test(fixture = ...)]` generates as the test function.
+///
+/// The kernel can't unwind, so a test that panics never returns here. The
+/// fixture is therefore on the heap, and the runner tears it down after the
+/// test with [`take_teardown`], whether it returned or not.
+#[doc(hidden)]
+pub fn run_with_fixture<F>(setup: impl FnOnce() -> F, test: fn(&F))
+where
+    F: TestFixture + Send + 'static,
+{
+    let fixture = Box::into_raw(Box::new(setup()));
+    // raw pointers aren't `Send`, but the test is done with the fixture once
+    // the runner tears it down
+    let address = fixture as usize;
+    *TEARDOWN.lock() = Some(Box::new(move || {
+        unsafe { Box::from_raw(address as *mut F) }.teardown()
+    }));
+
+    test(unsafe { &*fixture });
+}
+
+/// Takes the teardown of the fixture of the test that ran last, if it has a
+/// fixture. It must only be called once the test has returned or panicked.
+pub fn take_teardown() -> Option<Teardown> {
+    TEARDOWN.lock().take()
+}
@@ -1,13 +1,26 @@
 #![no_std]
 #![deny(unsafe_op_in_unsafe_fn)]
+extern crate alloc;
 
-pub use derive::kernel_test;
+pub use bench::*;
+pub use derive::{kernel_bench, kernel_test};
+pub use fixture::*;
 
 use linkme::distributed_slice;
 
+mod bench;
+mod fixture;
+
 /// Function signature used for kernel test functions
 pub type KernelTestFn = fn();
 
+/// Function signature used for kernel benchmark functions
+pub type KernelBenchFn = fn(&mut Bencher);
+
+/// How long a kernel test may run, unless it declares otherwise with
+/// `#[kernel_test(timeout_ms = ...)]`
+pub const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
 /// The source code location of a test function
 #[derive(Debug, Clone)]
 pub struct SourceLocation {
@@ -27,13 +40,40 @@ impl core::fmt::Display for SourceLocation {
     }
 }
 
+/// Whether a kernel test is expected to panic, as declared with
+/// `#[kernel_test(should_panic)]`
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum ShouldPanic {
+    /// The test passes if it returns without panicking
+    No,
+    /// The test passes if it panics
+    Yes,
+    /// The test passes if it panics with a message that contains the given
+    /// substring
+    Expected(&'static str),
+}
+
 /// Description of a single kernel test
 #[derive(Debug, Clone)]
 pub struct KernelTestDescription {
     pub name: &'static str,
     pub test_fn: KernelTestFn,
     pub test_location: SourceLocation,
+    pub should_panic: ShouldPanic,
+    /// How long the test may run before the runner fails it, in milliseconds
+    pub timeout_ms: u64,
 }
 
 #[distributed_slice]
 pub static KERNEL_TESTS: [KernelTestDescription] = [..];
+
+/// Description of a single kernel benchmark
+#[derive(Debug, Clone)]
+pub struct KernelBenchDescription {
+    pub name: &'static str,
+    pub bench_fn: KernelBenchFn,
+    pub bench_location: SourceLocation,
+}
+
+#[distributed_slice]
+pub static KERNEL_BENCHES: [KernelBenchDescription] = [..];
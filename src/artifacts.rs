@@ -0,0 +1,139 @@
+//! What is kept of a run of a test kernel, so that a failure in CI can be
+//! looked into without running the test again.
+//!
+//! The artifacts of a host test go into `target/test-artifacts/<test>/`:
+//! - `serial.log`, all serial output of the kernel,
+//! - `command.txt`, the command line of QEMU,
+//! - `overlays.txt`, the paths of the overlays of the disks, which are kept,
+//! - `tests/<kernel test>.log`, the output of each kernel test that failed or
+//!   never finished.
+//!
+//! They're only kept if the run failed, or if `MUFFIN_KEEP_ARTIFACTS=1`, which
+//! also keeps the output of the kernel tests that passed.
+
+use std::path::PathBuf;
+
+use crate::report::{split_by_test, Report};
+
+/// The environment variable that keeps the artifacts of runs that passed.
+pub const KEEP_ARTIFACTS: &str = "MUFFIN_KEEP_ARTIFACTS";
+
+/// Whether [`KEEP_ARTIFACTS`] is set to `1`.
+pub fn keep_all() -> bool {
+    std::env::var(KEEP_ARTIFACTS).is_ok_and(|value| value == "1")
+}
+
+/// A run of a test kernel.
+pub struct Run<'a> {
+    /// The name of the host test, which names the directory.
+    pub test: &'a str,
+    pub command: &'a str,
+    pub serial_log: &'a str,
+    pub overlays: &'a [String],
+}
+
+impl Run<'_> {
+    /// The directory that [`Run::save`] writes to.
+    pub fn dir(&self) -> PathBuf {
+        let target = std::env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/target")));
+        target.join("test-artifacts").join(file_name(self.test))
+    }
+
+    /// Replaces the artifacts of an earlier run of the same host test with
+    /// these, and returns where they are. With `all_tests`, the output of every
+    /// kernel test is saved, not just of the ones that failed.
+    pub fn save(&self, all_tests: bool) -> std::io::Result<PathBuf> {
+        let dir = self.dir();
+        match std::fs::remove_dir_all(&dir) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        std::fs::create_dir_all(&dir)?;
+
+        std::fs::write(dir.join("serial.log"), self.serial_log)?;
+        std::fs::write(dir.join("command.txt"), format!("{}\n", self.command))?;
+        let overlays = self
+            .overlays
+            .iter()
+            .map(|overlay| format!("{overlay}\n"))
+            .collect::<String>();
+        std::fs::write(dir.join("overlays.txt"), overlays)?;
+
+        let report = Report::parse(self.serial_log);
+        let logs = split_by_test(self.serial_log)
+            .into_iter()
+            .filter(|log| all_tests || report.failures().any(|test| test.name == log.name))
+            .collect::<Vec<_>>();
+        if !logs.is_empty() {
+            std::fs::create_dir(dir.join("tests"))?;
+        }
+        for log in logs {
+            let path = dir
+                .join("tests")
+                .join(format!("{}.log", file_name(&log.name)));
+            std::fs::write(path, log.log)?;
+        }
+        Ok(dir)
+    }
+}
+
+/// The name of a test as a file name. Test names are paths of Rust items, so
+/// only `::` has to go.
+fn file_name(name: &str) -> String {
+    name.replace("::", ".")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(
+            "kernel.io.vfs.tests.test_mount",
+            file_name("kernel::io::vfs::tests::test_mount")
+        );
+        assert_eq!("test_kernel_vfs", file_name("test_kernel_vfs"));
+    }
+
+    #[test]
+    fn test_save() {
+        let serial_log = include_str!("../tests/transcripts/interleaved.txt");
+        let overlays = ["/tmp/a.qcow2".to_string()];
+        let run = Run {
+            test: "artifacts::tests::test_save",
+            command: "qemu-system-x86_64 -nographic",
+            serial_log,
+            overlays: &overlays,
+        };
+        let dir = run.save(false).unwrap();
+        assert_eq!(run.dir(), dir);
+        assert!(dir.ends_with("test-artifacts/artifacts.tests.test_save"));
+
+        let read = |path: &str| std::fs::read_to_string(dir.join(path)).unwrap();
+        assert_eq!(serial_log, read("serial.log"));
+        assert_eq!("qemu-system-x86_64 -nographic\n", read("command.txt"));
+        assert_eq!("/tmp/a.qcow2\n", read("overlays.txt"));
+        let mut logs = std::fs::read_dir(dir.join("tests"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        logs.sort();
+        assert_eq!(
+            vec![
+                "kernel.io.vfs.tests.test_mount.log",
+                "kernel.tests.test_broken.log",
+                "kernel.tests.test_hangs.log",
+            ],
+            logs
+        );
+        assert!(read("tests/kernel.tests.test_broken.log").ends_with("\\nboom\n"));
+
+        // saving again replaces the artifacts
+        run.save(true).unwrap();
+        assert!(dir.join("tests/kernel.tests.test_it_works.log").exists());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+//! The checked-in medians of the kernel benchmarks, which new measurements are
+//! compared to, to notice when a change makes the kernel slower.
+
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::report::Report;
+
+/// The median cycles per iteration of each benchmark.
+///
+/// A baseline file has a line `<name> <median>` for each benchmark. Empty
+/// lines and lines that start with `#` are ignored.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Baseline {
+    medians: BTreeMap<String, u64>,
+}
+
+/// A benchmark whose median is too far above its baseline.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Regression {
+    pub name: String,
+    pub baseline: u64,
+    pub median: u64,
+}
+
+impl Display for Regression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: median of {} cycles, baseline is {} cycles (+{}%)",
+            self.name,
+            self.median,
+            self.baseline,
+            (self.median - self.baseline) * 100 / self.baseline.max(1)
+        )
+    }
+}
+
+impl Baseline {
+    pub fn parse(baseline: &str) -> Result<Self, String> {
+        let mut medians = BTreeMap::new();
+        for (number, line) in baseline.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let median = line
+                .split_once(' ')
+                .and_then(|(name, median)| Some((name, median.trim().parse().ok()?)));
+            let Some((name, median)) = median else {
+                return Err(format!(
+                    "line {}: expected `<name> <median>`, got {line:?}",
+                    number + 1
+                ));
+            };
+            medians.insert(name.to_string(), median);
+        }
+        Ok(Self { medians })
+    }
+
+    /// The baseline of the benchmarks in the report, for when the checked-in
+    /// baseline needs to be updated.
+    pub fn of(report: &Report) -> Self {
+        Self {
+            medians: report
+                .benches()
+                .map(|(name, bench)| (name.to_string(), bench.median))
+                .collect(),
+        }
+    }
+
+    /// Whether no benchmark has a baseline yet, which is the case until one
+    /// was recorded from a real run.
+    pub fn is_empty(&self) -> bool {
+        self.medians.is_empty()
+    }
+
+    /// The benchmarks in the report whose median is more than
+    /// `tolerance_percent` above the baseline. Benchmarks without a baseline
+    /// can't regress, see [`Baseline::missing`] for them.
+    pub fn regressions(&self, report: &Report, tolerance_percent: u64) -> Vec<Regression> {
+        report
+            .benches()
+            .filter_map(|(name, bench)| {
+                let baseline = *self.medians.get(name)?;
+                (bench.median * 100 > baseline * (100 + tolerance_percent)).then(|| Regression {
+                    name: name.to_string(),
+                    baseline,
+                    median: bench.median,
+                })
+            })
+            .collect()
+    }
+
+    /// The benchmarks in the report that have no baseline, so that nothing
+    /// notices when they get slower.
+    pub fn missing<'a>(&self, report: &'a Report) -> Vec<&'a str> {
+        report
+            .benches()
+            .map(|(name, _)| name)
+            .filter(|name| !self.medians.contains_key(*name))
+            .collect()
+    }
+}
+
+/// The baseline as a file, which [`Baseline::parse`] reads back.
+impl Display for Baseline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "# The median cycles per iteration of the kernel benchmarks, which\n\
+             # `test_kernel_benches` compares new measurements to. Run it with\n\
+             # DEVOS_UPDATE_BENCH_BASELINE=1 to update this file."
+        )?;
+        for (name, median) in &self.medians {
+            writeln!(f, "{name} {median}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report(medians: &[(&str, u64)]) -> Report {
+        let mut output = String::new();
+        for (name, median) in medians {
+            output += &format!("##TEST-START {name}\n##BENCH {name} 64 {median} 1 2 3\n");
+            output += &format!("##TEST-PASS {name} 1000\n");
+        }
+        Report::parse(&output)
+    }
+
+    #[test]
+    fn test_parse() {
+        let baseline = Baseline::parse("# comment\n\nkernel::a 100\n  kernel::b 2500  \n").unwrap();
+        assert_eq!(Some(&100), baseline.medians.get("kernel::a"));
+        assert_eq!(Some(&2500), baseline.medians.get("kernel::b"));
+        assert!(!baseline.is_empty());
+        assert!(Baseline::parse("# comment\n\n").unwrap().is_empty());
+        assert_eq!(
+            Err("line 2: expected `<name> <median>`, got \"kernel::a\"".to_string()),
+            Baseline::parse("# comment\nkernel::a\n")
+        );
+        assert!(Baseline::parse("kernel::a fast").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let baseline = Baseline::of(&report(&[("kernel::a", 100), ("kernel::b", 2500)]));
+        assert_eq!(Ok(baseline.clone()), Baseline::parse(&baseline.to_string()));
+    }
+
+    #[test]
+    fn test_regressions() {
+        let baseline = Baseline::parse("kernel::a 100\nkernel::b 100\nkernel::c 100\n").unwrap();
+        let report = report(&[
+            ("kernel::a", 150),
+            ("kernel::b", 151),
+            ("kernel::c", 20),
+            ("kernel::new", 1_000_000),
+        ]);
+        let regressions = baseline.regressions(&report, 50);
+        assert_eq!(
+            vec![Regression {
+                name: "kernel::b".to_string(),
+                baseline: 100,
+                median: 151,
+            }],
+            regressions
+        );
+        assert_eq!(
+            "kernel::b: median of 151 cycles, baseline is 100 cycles (+51%)",
+            regressions[0].to_string()
+        );
+    }
+
+    #[test]
+    fn test_missing() {
+        let baseline = Baseline::parse(
+            "kernel::a 100
+kernel::gone 100
+",
+        )
+        .unwrap();
+        let report = report(&[("kernel::a", 100), ("kernel::new", 100)]);
+        assert_eq!(vec!["kernel::new"], baseline.missing(&report));
+        assert!(Baseline::of(&report).missing(&report).is_empty());
+    }
+}
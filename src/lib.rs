@@ -1,25 +1,44 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::path::Path;
+use std::process::{ExitStatus, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
 use rand::distr::Alphanumeric;
 use rand::Rng;
 
+use crate::report::Report;
+
+pub mod artifacts;
+pub mod baseline;
+pub mod report;
+
 // these are set in build.rs at build time
 pub const UEFI_PATH: &str = env!("UEFI_PATH");
 pub const KERNEL_BINARY: &str = env!("KERNEL_BINARY");
 pub const OS_DISK: &str = env!("OS_DISK");
+pub const CDROM_IMAGE: &str = env!("CDROM_IMAGE");
 
+/// Creates a qcow2 overlay of the given raw image, so that the kernel can
+/// write to the disk without changing the image. Returns the path of the
+/// overlay.
 pub fn create_qcow_image(os_disk: &str) -> String {
-    let name = rand::rng()
-        .sample_iter(&Alphanumeric)
-        .take(10)
-        .map(char::from)
-        .collect::<String>();
-    let disk_image = format!("{}/{}.qcow2", env!("OUT_DIR"), name);
+    create_overlay(os_disk, DiskFormat::Raw)
+}
+
+fn create_overlay(backing_file: &str, format: DiskFormat) -> String {
+    let disk_image = format!("{}/{}.qcow2", env!("OUT_DIR"), random_name());
 
     let output = std::process::Command::new("qemu-img")
         .arg("create")
         .arg("-f")
         .arg("qcow2")
         .arg("-o")
-        .arg(format!("backing_file={os_disk},backing_fmt=raw"))
+        .arg(format!(
+            "backing_file={backing_file},backing_fmt={}",
+            format.as_str()
+        ))
         .arg(&disk_image)
         .output()
         .expect("failed to execute qemu-img");
@@ -27,28 +46,583 @@ pub fn create_qcow_image(os_disk: &str) -> String {
     disk_image
 }
 
-pub fn run_test_kernel(kernel: &str, os_disk: &str) {
-    let os_disk = create_qcow_image(os_disk);
+fn random_name() -> String {
+    rand::rng()
+        .sample_iter(&Alphanumeric)
+        .take(10)
+        .map(char::from)
+        .collect::<String>()
+}
+
+/// Runs the given test kernel in QEMU with the OS disk, asserts that it exits
+/// successfully and returns its serial output.
+pub fn run_test_kernel(kernel: &str) -> String {
+    QemuConfig::default().run(kernel)
+}
+
+/// The QEMU arguments that pass the given kernel command line. The bootloader
+/// has no command line of its own, so it goes through QEMU's fw_cfg device.
+pub fn command_line_args(command_line: &str) -> Vec<String> {
+    // fw_cfg splits its option at commas, which the value escapes by doubling
+    vec![
+        "-fw_cfg".to_string(),
+        format!(
+            "name=opt/devos/cmdline,string={}",
+            command_line.replace(',', ",,")
+        ),
+    ]
+}
+
+/// The format of a disk image.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DiskFormat {
+    Raw,
+    Qcow2,
+}
+
+impl DiskFormat {
+    fn as_str(self) -> &'static str {
+        match self {
+            DiskFormat::Raw => "raw",
+            DiskFormat::Qcow2 => "qcow2",
+        }
+    }
+}
+
+/// How a disk is attached to the machine.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DiskBus {
+    /// The IDE controller of the machine. The disk of the kernel and the
+    /// CD-ROM already take two of its four slots.
+    Ide,
+    VirtioBlk,
+    /// A port of an AHCI controller, which is added with the first such disk.
+    Ahci,
+    /// The namespace of an NVMe controller of its own.
+    Nvme,
+}
+
+#[derive(Debug, Clone)]
+struct Disk {
+    path: String,
+    format: DiskFormat,
+    bus: DiskBus,
+    /// Whether the kernel writes to an overlay instead of the image.
+    overlay: bool,
+}
+
+/// The IDE slots that disks go into. The disk of the kernel is in the first
+/// one, and the CD-ROM on the secondary channel, in the third.
+const IDE_SLOTS: [usize; 2] = [1, 3];
+
+/// The machine that a test kernel runs on.
+///
+/// ```no_run
+/// # use devos::{DiskBus, DiskFormat, QemuConfig};
+/// # let kernel = "test_kernel.img";
+/// let output = QemuConfig::default()
+///     .add_disk("data.img", DiskFormat::Raw, DiskBus::Nvme)
+///     .smp(2)
+///     .run(kernel);
+/// ```
+#[derive(Debug, Clone)]
+pub struct QemuConfig {
+    disks: Vec<Disk>,
+    nic: Option<String>,
+    smp: Option<usize>,
+    memory_mb: Option<usize>,
+    command_line: Option<String>,
+    input: Vec<u8>,
+    extra_args: Vec<String>,
+    exit_code: i32,
+}
+
+/// The OS disk on the IDE controller, behind an overlay, which is what most
+/// test kernels need.
+impl Default for QemuConfig {
+    fn default() -> Self {
+        Self::new().add_disk(OS_DISK, DiskFormat::Raw, DiskBus::Ide)
+    }
+}
+
+impl QemuConfig {
+    /// A machine with no disks besides the disk of the kernel and the CD-ROM.
+    pub fn new() -> Self {
+        Self {
+            disks: Vec::new(),
+            nic: None,
+            smp: None,
+            memory_mb: None,
+            command_line: None,
+            input: Vec::new(),
+            extra_args: Vec::new(),
+            exit_code: EXIT_SUCCESS,
+        }
+    }
+
+    /// Attaches the image behind an overlay, which is removed after the run,
+    /// so that the kernel can't change the image.
+    pub fn add_disk(self, path: impl Into<String>, format: DiskFormat, bus: DiskBus) -> Self {
+        self.with_disk(path.into(), format, bus, true)
+    }
+
+    /// Attaches the image itself, so that tests can inspect what the kernel
+    /// wrote, or boot again on the same disk.
+    pub fn add_disk_in_place(
+        self,
+        path: impl Into<String>,
+        format: DiskFormat,
+        bus: DiskBus,
+    ) -> Self {
+        self.with_disk(path.into(), format, bus, false)
+    }
+
+    fn with_disk(mut self, path: String, format: DiskFormat, bus: DiskBus, overlay: bool) -> Self {
+        assert!(
+            bus != DiskBus::Ide
+                || self
+                    .disks
+                    .iter()
+                    .filter(|disk| disk.bus == DiskBus::Ide)
+                    .count()
+                    < IDE_SLOTS.len(),
+            "the IDE controller has no slot left for {path}"
+        );
+        self.disks.push(Disk {
+            path,
+            format,
+            bus,
+            overlay,
+        });
+        self
+    }
+
+    /// Adds a network card of the given model, e.g. `rtl8139`, with user mode
+    /// networking.
+    pub fn nic(mut self, model: &str) -> Self {
+        self.nic = Some(model.to_string());
+        self
+    }
+
+    pub fn smp(mut self, cpus: usize) -> Self {
+        self.smp = Some(cpus);
+        self
+    }
+
+    /// The size of the memory, in MiB.
+    pub fn memory(mut self, mb: usize) -> Self {
+        self.memory_mb = Some(mb);
+        self
+    }
+
+    /// Passes the given kernel command line, which the kernel reads with
+    /// `kernel::qemu::command_line`.
+    pub fn command_line(mut self, command_line: &str) -> Self {
+        self.command_line = Some(command_line.to_string());
+        self
+    }
+
+    /// Sends `input` to the serial port of the kernel, as if it was typed into
+    /// the console. QEMU only passes the input on once the kernel can receive
+    /// it.
+    pub fn input(mut self, input: &[u8]) -> Self {
+        self.input = input.to_vec();
+        self
+    }
+
+    /// Passes more arguments to QEMU, after all others.
+    pub fn extra_args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.extra_args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Expects the kernel to exit with the failure code, for tests of how
+    /// kernels fail.
+    pub fn expect_failure(mut self) -> Self {
+        self.exit_code = EXIT_FAILED;
+        self
+    }
+
+    /// Runs the given test kernel in QEMU, asserts that it exits with the
+    /// expected code and returns its serial output. The overlays of the disks
+    /// are removed afterwards, unless the run failed, in which case they are
+    /// kept, along with the rest of the [artifacts](artifacts).
+    pub fn run(&self, kernel: &str) -> String {
+        let mut temp_files = TempFiles::default();
+        let disks = self
+            .disks
+            .iter()
+            .map(|disk| {
+                if disk.overlay {
+                    let overlay = create_overlay(&disk.path, disk.format);
+                    temp_files.0.push(overlay.clone());
+                    (overlay, DiskFormat::Qcow2, disk.bus)
+                } else {
+                    (disk.path.clone(), disk.format, disk.bus)
+                }
+            })
+            .collect::<Vec<_>>();
+        let overlays = temp_files.0.clone();
+
+        let args = self.args(kernel, &disks);
+        let command = format!(
+            "qemu-system-x86_64 {}",
+            args.iter()
+                .map(|arg| shell_quote(arg))
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+        println!("qemu command: {command}");
 
+        let kernel_name = Path::new(kernel)
+            .file_stem()
+            .and_then(|name| name.to_str())
+            .unwrap_or("kernel");
+        let run_name = format!("{}/{kernel_name}-{}", env!("OUT_DIR"), random_name());
+        let serial_log = format!("{run_name}.log");
+        temp_files.0.push(serial_log.clone());
+        println!("serial output is logged to {serial_log}");
+
+        let qemu = run_qemu(&args, &self.input, Path::new(&serial_log));
+        let report = Report::parse(&qemu.stdout);
+        if !report.is_empty() {
+            println!("{report}");
+            let report_path = format!("{run_name}.json");
+            std::fs::write(&report_path, report.to_json())
+                .expect("failed to write the test report");
+            println!("test report written to {report_path}");
+        }
+
+        let failure = qemu.failure(self.exit_code, &report);
+        if failure.is_some() || artifacts::keep_all() {
+            // cargo runs each test in a thread with the name of the test
+            let test = std::thread::current()
+                .name()
+                .filter(|name| *name != "main")
+                .unwrap_or(kernel_name)
+                .to_string();
+            let run = artifacts::Run {
+                test: &test,
+                command: &command,
+                serial_log: &qemu.stdout,
+                overlays: &overlays,
+            };
+            let dir = run
+                .save(artifacts::keep_all())
+                .expect("failed to save the test artifacts");
+            println!("test artifacts saved to {}", dir.display());
+            // the overlays are artifacts too
+            temp_files.0.retain(|file| !overlays.contains(file));
+        }
+        if let Some(failure) = failure {
+            panic!("{failure}");
+        }
+        qemu.stdout
+    }
+
+    fn args(&self, kernel: &str, disks: &[(String, DiskFormat, DiskBus)]) -> Vec<String> {
+        let mut args = vec![
+            "--no-reboot".to_string(),
+            "-d".to_string(),
+            "guest_errors".to_string(),
+            "-bios".to_string(),
+            ovmf_prebuilt::ovmf_pure_efi().display().to_string(),
+            "-drive".to_string(),
+            format!("format=raw,file={kernel}"),
+        ];
+
+        let mut ide_slots = IDE_SLOTS.iter();
+        let mut ahci_ports = 0;
+        let mut nvme_controllers = 0;
+        for (i, (path, format, bus)) in disks.iter().enumerate() {
+            let format = format.as_str();
+            if *bus == DiskBus::Ide {
+                let slot = ide_slots.next().unwrap();
+                args.push("-drive".to_string());
+                args.push(format!("file={path},if=ide,index={slot},format={format}"));
+                continue;
+            }
+
+            args.push("-drive".to_string());
+            args.push(format!("id=disk{i},file={path},if=none,format={format}"));
+            let device = match bus {
+                DiskBus::Ide => unreachable!(),
+                DiskBus::VirtioBlk => format!("virtio-blk-pci,drive=disk{i}"),
+                DiskBus::Ahci => {
+                    if ahci_ports == 0 {
+                        args.push("-device".to_string());
+                        args.push("ahci,id=ahci".to_string());
+                    }
+                    ahci_ports += 1;
+                    format!("ide-hd,drive=disk{i},bus=ahci.{}", ahci_ports - 1)
+                }
+                DiskBus::Nvme => {
+                    nvme_controllers += 1;
+                    format!("nvme,serial=devos{nvme_controllers},drive=disk{i}")
+                }
+            };
+            args.push("-device".to_string());
+            args.push(device);
+        }
+
+        // the CD-ROM is the master of the secondary channel, see IDE_SLOTS
+        args.push("-cdrom".to_string());
+        args.push(CDROM_IMAGE.to_string());
+        args.push("-nographic".to_string());
+        args.push("-device".to_string());
+        args.push("isa-debug-exit,iobase=0xf4,iosize=0x04".to_string());
+
+        if let Some(cpus) = self.smp {
+            args.push("-smp".to_string());
+            args.push(cpus.to_string());
+        }
+        if let Some(mb) = self.memory_mb {
+            args.push("-m".to_string());
+            args.push(format!("{mb}M"));
+        }
+        if let Some(model) = &self.nic {
+            args.push("-netdev".to_string());
+            args.push("user,id=net0".to_string());
+            args.push("-device".to_string());
+            args.push(format!("{model},netdev=net0"));
+        }
+        if let Some(command_line) = &self.command_line {
+            args.extend(command_line_args(command_line));
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+/// Files that are removed when this is dropped, which also happens when a
+/// test panics.
+#[derive(Default)]
+struct TempFiles(Vec<String>);
+
+impl Drop for TempFiles {
+    fn drop(&mut self) {
+        for file in &self.0 {
+            let _ = std::fs::remove_file(file);
+        }
+    }
+}
+
+/// Quotes the argument for a POSIX shell, if it needs quoting.
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_=,./:+@%".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// The exit codes of QEMU when the kernel exits through the isa-debug-exit
+/// device with `ExitCode::Success` and `ExitCode::Failed`.
+const EXIT_SUCCESS: i32 = 33;
+const EXIT_FAILED: i32 = 35;
+
+/// How long a test kernel may run before QEMU is killed, in case the kernel
+/// hangs somewhere that no watchdog catches.
+const QEMU_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How QEMU exited, and what it printed.
+struct QemuOutput {
+    /// `None` if QEMU didn't exit within [`QEMU_TIMEOUT`] and was killed.
+    status: Option<ExitStatus>,
+    stdout: String,
+    stderr: String,
+}
+
+impl QemuOutput {
+    /// Why the run failed, if QEMU didn't exit with the expected code, or a
+    /// kernel test failed although the kernel should succeed.
+    fn failure(&self, exit_code: i32, report: &Report) -> Option<String> {
+        let stderr = &self.stderr;
+        let Some(status) = self.status else {
+            return Some(format!(
+                "qemu didn't exit within {QEMU_TIMEOUT:?} and was killed\nstderr:\n{stderr}"
+            ));
+        };
+        if status.code() != Some(exit_code) {
+            return Some(format!(
+                "test failed, see stdout above\nqemu exited with {status}, expected exit code {exit_code}\nstderr:\n{stderr}"
+            ));
+        }
+        if exit_code == EXIT_SUCCESS && !report.is_empty() && !report.is_success() {
+            return Some(format!(
+                "kernel tests failed, or the suite didn't finish:\n{report}"
+            ));
+        }
+        None
+    }
+}
+
+/// Runs QEMU, and writes its serial output to the log file while it runs, so
+/// that the log is there even if QEMU or the test hangs.
+fn run_qemu(args: &[String], input: &[u8], serial_log: &Path) -> QemuOutput {
     let mut cmd = std::process::Command::new("qemu-system-x86_64");
-    cmd.arg("--no-reboot");
-    cmd.arg("-d").arg("guest_errors");
-    cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-    cmd.arg("-drive").arg(format!("format=raw,file={kernel}"));
-    cmd.arg("-drive")
-        .arg(format!("file={},if=ide,format=qcow2", os_disk));
-    cmd.arg("-nographic");
-    cmd.arg("-device")
-        .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
-
-    let output = cmd.output().expect("failed to execute qemu");
-    assert_eq!(
-        output.status.code(),
-        Some(33),
-        "test failed:\nstdout:\n{}\nstderr:\n{}",
+    cmd.args(args);
+
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+
+    let log = File::create(serial_log).expect("failed to create the serial log");
+    let mut child = cmd.spawn().expect("failed to execute qemu");
+    // dropping stdin after writing closes it, so that QEMU doesn't wait for more
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input)
+        .expect("failed to write the input to qemu");
+    let stdout = read_to_end_in_background(child.stdout.take().unwrap(), Some(log));
+    let stderr = read_to_end_in_background(child.stderr.take().unwrap(), None);
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().expect("failed to wait for qemu") {
+            break Some(status);
+        }
+        if started.elapsed() > QEMU_TIMEOUT {
+            child.kill().expect("failed to kill qemu");
+            child.wait().expect("failed to wait for qemu");
+            break None;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    let stdout = String::from_utf8_lossy(&stdout.join().unwrap()).to_string();
+    let stderr = String::from_utf8_lossy(&stderr.join().unwrap()).to_string();
+    println!("{}", stdout);
+    QemuOutput {
+        status,
+        stdout,
+        stderr,
+    }
+}
+
+/// Reads the pipe until it's closed, and copies what it read to the file as
+/// it goes.
+fn read_to_end_in_background(
+    mut pipe: impl Read + Send + 'static,
+    mut copy: Option<File>,
+) -> JoinHandle<Vec<u8>> {
+    std::thread::spawn(move || {
+        let mut content = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = match pipe.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => panic!("failed to read the output of qemu: {e}"),
+            };
+            if let Some(copy) = &mut copy {
+                copy.write_all(&buffer[..read])
+                    .expect("failed to write the serial log");
+            }
+            content.extend_from_slice(&buffer[..read]);
+        }
+        content
+    })
+}
+
+/// Runs `e2fsck` on the ext2 file system in the given qcow2 image without
+/// modifying it, and asserts that the file system is clean.
+pub fn assert_ext2_clean(qcow_image: &str) {
+    let raw_image = format!("{qcow_image}.raw");
+    let output = std::process::Command::new("qemu-img")
+        .arg("convert")
+        .arg("-O")
+        .arg("raw")
+        .arg(qcow_image)
+        .arg(&raw_image)
+        .output()
+        .expect("failed to execute qemu-img");
+    assert!(output.status.success());
+
+    let output = std::process::Command::new("e2fsck")
+        .arg("-f")
+        .arg("-n")
+        .arg(&raw_image)
+        .output()
+        .expect("failed to execute e2fsck");
+    let _ = std::fs::remove_file(&raw_image);
+    assert!(
+        output.status.success(),
+        "e2fsck found errors:\nstdout:\n{}\nstderr:\n{}",
         String::from_utf8_lossy(&output.stdout),
         String::from_utf8_lossy(&output.stderr)
-    ); // 33=success, 35=failed
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disks(config: &QemuConfig) -> Vec<(String, DiskFormat, DiskBus)> {
+        config
+            .disks
+            .iter()
+            .map(|disk| (disk.path.clone(), disk.format, disk.bus))
+            .collect()
+    }
+
+    #[test]
+    fn test_disk_args() {
+        let config = QemuConfig::new()
+            .add_disk("os.img", DiskFormat::Raw, DiskBus::Ide)
+            .add_disk("a.img", DiskFormat::Raw, DiskBus::Ahci)
+            .add_disk("b.qcow2", DiskFormat::Qcow2, DiskBus::Ahci)
+            .add_disk("n.img", DiskFormat::Raw, DiskBus::Nvme)
+            .add_disk("d.img", DiskFormat::Raw, DiskBus::Ide);
+        let args = config.args("kernel.img", &disks(&config)).join(" ");
+        for expected in [
+            "-drive format=raw,file=kernel.img -drive file=os.img,if=ide,index=1,format=raw ",
+            "-drive id=disk1,file=a.img,if=none,format=raw -device ahci,id=ahci -device ide-hd,drive=disk1,bus=ahci.0 ",
+            "-drive id=disk2,file=b.qcow2,if=none,format=qcow2 -device ide-hd,drive=disk2,bus=ahci.1 ",
+            "-drive id=disk3,file=n.img,if=none,format=raw -device nvme,serial=devos1,drive=disk3 ",
+            "-drive file=d.img,if=ide,index=3,format=raw -cdrom ",
+        ] {
+            assert!(args.contains(expected), "{expected:?} is not in {args:?}");
+        }
+        assert_eq!(1, args.matches("-device ahci,").count());
+    }
+
+    #[test]
+    #[should_panic(expected = "no slot left for c.img")]
+    fn test_ide_slots() {
+        let _ = QemuConfig::default()
+            .add_disk("b.img", DiskFormat::Raw, DiskBus::Ide)
+            .add_disk("c.img", DiskFormat::Raw, DiskBus::Ide);
+    }
+
+    #[test]
+    fn test_machine_args() {
+        let config = QemuConfig::new()
+            .smp(2)
+            .memory(512)
+            .nic("rtl8139")
+            .command_line("test-filter=a,b")
+            .extra_args(["-vga", "none"]);
+        let args = config.args("kernel.img", &[]).join(" ");
+        assert!(args.ends_with(
+            "-smp 2 -m 512M -netdev user,id=net0 -device rtl8139,netdev=net0 \
+             -fw_cfg name=opt/devos/cmdline,string=test-filter=a,,b -vga none"
+        ));
+    }
 
-    println!("{}", String::from_utf8_lossy(&output.stdout));
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!("file=a.img,if=ide", shell_quote("file=a.img,if=ide"));
+        assert_eq!("'string=a b'", shell_quote("string=a b"));
+        assert_eq!("'it'\\''s'", shell_quote("it's"));
+        assert_eq!("''", shell_quote(""));
+    }
 }
@@ -3,7 +3,10 @@ extern crate devos;
 
 use clap::Parser;
 
-use devos::{create_qcow_image, KERNEL_BINARY, OS_DISK, UEFI_PATH};
+use devos::{command_line_args, create_qcow_image, KERNEL_BINARY, OS_DISK, UEFI_PATH};
+
+/// The kernel that runs the kernel tests.
+const TEST_KERNEL_PATH: &str = env!("TEST_KERNEL_UNITTESTS_PATH");
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = "The boot tool for DevOS.")]
@@ -25,6 +28,48 @@ struct Args {
     no_run: bool,
     #[arg(long, help = "Do not attach any graphical output devices")]
     headless: bool,
+    #[arg(long, help = "Boot the kernel that runs the kernel tests")]
+    test: bool,
+    #[arg(
+        long,
+        requires = "test",
+        help = "Only run the kernel tests whose module::name contains this"
+    )]
+    filter: Option<String>,
+    #[arg(
+        long,
+        requires = "test",
+        help = "List the kernel tests instead of running them"
+    )]
+    list: bool,
+    #[arg(
+        long,
+        requires = "test",
+        help = "Run the kernel benchmarks instead of the tests"
+    )]
+    bench: bool,
+    #[arg(long, help = "Pass this command line to the kernel")]
+    command_line: Option<String>,
+}
+
+impl Args {
+    /// The kernel command line, from `--command-line` and the test options.
+    fn kernel_command_line(&self) -> String {
+        let mut arguments = Vec::new();
+        arguments.extend(self.command_line.clone());
+        arguments.extend(
+            self.filter
+                .iter()
+                .map(|filter| format!("test-filter={filter}")),
+        );
+        if self.list {
+            arguments.push("test-list".to_string());
+        }
+        if self.bench {
+            arguments.push("test-bench".to_string());
+        }
+        arguments.join(" ")
+    }
 }
 
 fn main() {
@@ -68,8 +113,21 @@ b rust_begin_unwind"#
         cmd.arg("-S");
     }
     cmd.arg("-bios").arg(ovmf_prebuilt::ovmf_pure_efi());
-    cmd.arg("-drive")
-        .arg(format!("format=raw,file={UEFI_PATH}"));
+    let kernel = if args.test {
+        TEST_KERNEL_PATH
+    } else {
+        UEFI_PATH
+    };
+    cmd.arg("-drive").arg(format!("format=raw,file={kernel}"));
+    if args.test {
+        // the test kernel exits QEMU through this device when it's done
+        cmd.arg("-device")
+            .arg("isa-debug-exit,iobase=0xf4,iosize=0x04");
+    }
+    let command_line = args.kernel_command_line();
+    if !command_line.is_empty() {
+        cmd.args(command_line_args(&command_line));
+    }
 
     if args.headless {
         cmd.arg("-vga").arg("none");
@@ -0,0 +1,530 @@
+//! Parses the lines that the kernel test runner prints for the host, which
+//! start with `##`. See `kernel::test_runner::run_tests` for the protocol.
+
+use std::fmt::{Display, Formatter, Write};
+use std::time::Duration;
+
+/// What happened to a single kernel test.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    Passed(Duration),
+    Failed(String),
+    Skipped,
+    /// The test started, but the kernel never said how it ended, because it
+    /// hung or crashed.
+    Unfinished,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+    /// What the test measured, if it's a benchmark that passed.
+    pub bench: Option<Measurement>,
+}
+
+/// The cycles of the TSC that one iteration of a kernel benchmark took.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Measurement {
+    pub iterations: u64,
+    pub median: u64,
+    pub min: u64,
+    pub max: u64,
+    pub median_ns: u64,
+}
+
+/// The counts that the kernel prints after the last test.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SuiteEnd {
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+/// The results of the kernel tests in the serial output of a test kernel.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Report {
+    pub tests: Vec<TestResult>,
+    /// `None` if the kernel didn't get to the end of the suite.
+    pub suite_end: Option<SuiteEnd>,
+}
+
+impl Report {
+    /// Parses the given serial output. Lines that aren't part of the protocol
+    /// are ignored, so the output may be truncated, or contain anything else
+    /// the kernel printed.
+    pub fn parse(output: &str) -> Self {
+        let mut report = Self::default();
+        for line in output.lines() {
+            let line = line.trim_end_matches('\r');
+            let Some((marker, rest)) = line.split_once(' ') else {
+                continue;
+            };
+            match marker {
+                "##TEST-START" => report.set(rest, Outcome::Unfinished),
+                "##TEST-PASS" => {
+                    let (name, micros) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let duration = Duration::from_micros(micros.parse().unwrap_or_default());
+                    report.set(name, Outcome::Passed(duration));
+                }
+                "##TEST-FAIL" => {
+                    let (name, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                    report.set(name, Outcome::Failed(unescape(reason)));
+                }
+                "##TEST-SKIP" => report.set(rest, Outcome::Skipped),
+                "##BENCH" => {
+                    let mut fields = rest.split(' ');
+                    let name = fields.next().unwrap_or_default();
+                    let mut numbers = fields.map(str::parse);
+                    if let (
+                        Some(Ok(iterations)),
+                        Some(Ok(median)),
+                        Some(Ok(min)),
+                        Some(Ok(max)),
+                        Some(Ok(median_ns)),
+                    ) = (
+                        numbers.next(),
+                        numbers.next(),
+                        numbers.next(),
+                        numbers.next(),
+                        numbers.next(),
+                    ) {
+                        report.set_bench(
+                            name,
+                            Measurement {
+                                iterations,
+                                median,
+                                min,
+                                max,
+                                median_ns,
+                            },
+                        );
+                    }
+                }
+                "##SUITE-END" => {
+                    let mut counts = rest.split(' ').map(str::parse);
+                    if let (Some(Ok(passed)), Some(Ok(failed)), Some(Ok(skipped))) =
+                        (counts.next(), counts.next(), counts.next())
+                    {
+                        report.suite_end = Some(SuiteEnd {
+                            passed,
+                            failed,
+                            skipped,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        report
+    }
+
+    fn set(&mut self, name: &str, outcome: Outcome) {
+        match self.tests.iter_mut().find(|test| test.name == name) {
+            Some(test) => test.outcome = outcome,
+            None => self.tests.push(TestResult {
+                name: name.to_string(),
+                outcome,
+                bench: None,
+            }),
+        }
+    }
+
+    fn set_bench(&mut self, name: &str, measurement: Measurement) {
+        if let Some(test) = self.tests.iter_mut().find(|test| test.name == name) {
+            test.bench = Some(measurement);
+        }
+    }
+
+    /// The benchmarks that passed, and what they measured.
+    pub fn benches(&self) -> impl Iterator<Item = (&str, &Measurement)> {
+        self.tests
+            .iter()
+            .filter_map(|test| Some((test.name.as_str(), test.bench.as_ref()?)))
+    }
+
+    /// Whether the output contained any kernel tests at all. Test kernels that
+    /// don't use the test runner print none.
+    pub fn is_empty(&self) -> bool {
+        self.tests.is_empty() && self.suite_end.is_none()
+    }
+
+    /// Whether the kernel got to the end of the suite.
+    pub fn is_complete(&self) -> bool {
+        self.suite_end.is_some()
+    }
+
+    /// The tests that failed or never finished.
+    pub fn failures(&self) -> impl Iterator<Item = &TestResult> {
+        self.tests
+            .iter()
+            .filter(|test| matches!(test.outcome, Outcome::Failed(_) | Outcome::Unfinished))
+    }
+
+    /// Whether the suite ran to the end, and no test failed.
+    pub fn is_success(&self) -> bool {
+        self.is_complete() && self.failures().next().is_none()
+    }
+
+    /// The report as a JSON object, with an array of `tests` and the counts of
+    /// the `suite_end`, which is `null` if the kernel didn't get there.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"tests\": [");
+        for (i, test) in self.tests.iter().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            write!(json, "\n    {{\"name\": {}, ", json_string(&test.name)).unwrap();
+            match &test.outcome {
+                Outcome::Passed(duration) => {
+                    write!(
+                        json,
+                        "\"outcome\": \"passed\", \"duration_us\": {}",
+                        duration.as_micros()
+                    )
+                    .unwrap();
+                    if let Some(bench) = test.bench {
+                        write!(
+                            json,
+                            ", \"bench\": {{\"iterations\": {}, \"median\": {}, \"min\": {}, \"max\": {}, \"median_ns\": {}}}",
+                            bench.iterations, bench.median, bench.min, bench.max, bench.median_ns
+                        )
+                        .unwrap();
+                    }
+                    write!(json, "}}")
+                }
+                Outcome::Failed(reason) => write!(
+                    json,
+                    "\"outcome\": \"failed\", \"reason\": {}}}",
+                    json_string(reason)
+                ),
+                Outcome::Skipped => write!(json, "\"outcome\": \"skipped\"}}"),
+                Outcome::Unfinished => write!(json, "\"outcome\": \"unfinished\"}}"),
+            }
+            .unwrap();
+        }
+        json.push_str("\n  ],\n  \"suite_end\": ");
+        match self.suite_end {
+            Some(end) => write!(
+                json,
+                "{{\"passed\": {}, \"failed\": {}, \"skipped\": {}}}",
+                end.passed, end.failed, end.skipped
+            )
+            .unwrap(),
+            None => json.push_str("null"),
+        }
+        json.push_str("\n}\n");
+        json
+    }
+}
+
+/// The serial output of a single kernel test.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TestLog {
+    pub name: String,
+    /// The lines from the `##TEST-START` of the test to its `##TEST-PASS` or
+    /// `##TEST-FAIL`, with both markers.
+    pub log: String,
+}
+
+/// Splits the serial output into the output of each test that started.
+///
+/// Lines before the first test, and between the end of one test and the start
+/// of the next, belong to no test. A test that never ended, because the kernel
+/// hung or crashed, gets everything up to the start of the next test, or up to
+/// the end of the output.
+pub fn split_by_test(output: &str) -> Vec<TestLog> {
+    let mut logs = Vec::new();
+    let mut current: Option<TestLog> = None;
+    for line in output.lines() {
+        let trimmed = line.trim_end_matches('\r');
+        let (marker, rest) = trimmed.split_once(' ').unwrap_or((trimmed, ""));
+        if marker == "##TEST-START" {
+            logs.extend(current.take());
+            current = Some(TestLog {
+                name: rest.to_string(),
+                log: String::new(),
+            });
+        }
+        let Some(test) = &mut current else {
+            continue;
+        };
+        test.log.push_str(line);
+        test.log.push('\n');
+        let name = rest.split_once(' ').map_or(rest, |(name, _)| name);
+        if matches!(marker, "##TEST-PASS" | "##TEST-FAIL") && name == test.name {
+            logs.extend(current.take());
+        }
+    }
+    logs.extend(current);
+    logs
+}
+
+/// A table with a row for each test, and a line with the counts.
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let width = self
+            .tests
+            .iter()
+            .map(|test| test.name.len())
+            .max()
+            .unwrap_or_default()
+            .max("kernel test".len());
+        writeln!(f, "{:width$}  result      duration", "kernel test")?;
+        for test in &self.tests {
+            write!(f, "{:width$}  ", test.name)?;
+            match &test.outcome {
+                Outcome::Passed(duration) => match test.bench {
+                    Some(bench) => writeln!(
+                        f,
+                        "passed      {duration:?}, median {} cycles ({} ns)",
+                        bench.median, bench.median_ns
+                    )?,
+                    None => writeln!(f, "passed      {duration:?}")?,
+                },
+                Outcome::Failed(_) => writeln!(f, "FAILED")?,
+                Outcome::Skipped => writeln!(f, "skipped")?,
+                Outcome::Unfinished => writeln!(f, "UNFINISHED")?,
+            }
+        }
+        match self.suite_end {
+            Some(end) => write!(
+                f,
+                "{} passed; {} failed; {} skipped",
+                end.passed, end.failed, end.skipped
+            ),
+            None => write!(f, "the suite didn't finish, QEMU probably died mid-run"),
+        }
+    }
+}
+
+/// Undoes the escaping of the reason of a failed test.
+fn unescape(reason: &str) -> String {
+    let mut unescaped = String::with_capacity(reason.len());
+    let mut chars = reason.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                unescaped.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                unescaped.push('\\');
+                chars.next();
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+fn json_string(s: &str) -> String {
+    let mut json = String::with_capacity(s.len() + 2);
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn outcome<'a>(report: &'a Report, name: &str) -> &'a Outcome {
+        &report
+            .tests
+            .iter()
+            .find(|test| test.name == name)
+            .unwrap()
+            .outcome
+    }
+
+    #[test]
+    fn test_parse_passed() {
+        let report = Report::parse(include_str!("../tests/transcripts/passed.txt"));
+        assert!(report.is_success());
+        assert_eq!(4, report.tests.len());
+        assert_eq!(
+            &Outcome::Passed(Duration::from_micros(1523)),
+            outcome(&report, "kernel::tests::test_it_works")
+        );
+        assert_eq!(
+            &Outcome::Skipped,
+            outcome(&report, "kernel::test_runner::tests::test_options")
+        );
+        assert_eq!(
+            Some(SuiteEnd {
+                passed: 3,
+                failed: 0,
+                skipped: 1,
+            }),
+            report.suite_end
+        );
+    }
+
+    #[test]
+    fn test_parse_failed() {
+        let report = Report::parse(include_str!("../tests/transcripts/failed.txt"));
+        assert!(report.is_complete());
+        assert!(!report.is_success());
+        assert_eq!(
+            vec!["kernel::tests::test_broken"],
+            report
+                .failures()
+                .map(|test| test.name.as_str())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            &Outcome::Failed(
+                "panicked at kernel/src/lib.rs:120:9:\nassertion `left == right` failed\n  left: \"a\\\\b\"\n right: 5".to_string()
+            ),
+            outcome(&report, "kernel::tests::test_broken")
+        );
+        assert!(matches!(
+            outcome(&report, "kernel::tests::test_it_works"),
+            Outcome::Passed(_)
+        ));
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let report = Report::parse(include_str!("../tests/transcripts/truncated.txt"));
+        assert!(!report.is_complete());
+        assert!(!report.is_success());
+        assert_eq!(None, report.suite_end);
+        assert_eq!(
+            &Outcome::Unfinished,
+            outcome(&report, "kernel::io::vfs::tests::test_mount")
+        );
+        assert_eq!(1, report.failures().count());
+        assert!(report.to_string().ends_with("QEMU probably died mid-run"));
+    }
+
+    #[test]
+    fn test_parse_other_output() {
+        let report = Report::parse("[INFO] booting\ntest result: ok.\n## not a marker\n");
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn test_parse_benches() {
+        let report = Report::parse(
+            "##TEST-START kernel::mem::tests::bench_memcpy_64k\n\
+             ##BENCH kernel::mem::tests::bench_memcpy_64k 4096 3764 3377 4361 1254\n\
+             ##TEST-PASS kernel::mem::tests::bench_memcpy_64k 612000\n\
+             ##TEST-FAIL kernel::tests::bench_broken oops\n\
+             ##BENCH kernel::tests::bench_unknown 1 2 3 4 5\n\
+             ##SUITE-END 1 1 0\n",
+        );
+        assert_eq!(
+            vec![(
+                "kernel::mem::tests::bench_memcpy_64k",
+                &Measurement {
+                    iterations: 4096,
+                    median: 3764,
+                    min: 3377,
+                    max: 4361,
+                    median_ns: 1254,
+                }
+            )],
+            report.benches().collect::<Vec<_>>()
+        );
+        assert_eq!(2, report.tests.len());
+        assert!(report.to_json().contains(
+            r#""bench": {"iterations": 4096, "median": 3764, "min": 3377, "max": 4361, "median_ns": 1254}}"#
+        ));
+    }
+
+    #[test]
+    fn test_split_by_test() {
+        let logs = split_by_test(include_str!("../tests/transcripts/interleaved.txt"));
+        assert_eq!(
+            vec![
+                "kernel::tests::test_it_works",
+                "kernel::tests::test_broken",
+                "kernel::tests::test_hangs",
+                "kernel::io::vfs::tests::test_mount",
+            ],
+            logs.iter().map(|log| log.name.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            "##TEST-START kernel::tests::test_it_works\n\
+             [DEBUG] spawned thread 4 (worker)\n\
+             test kernel::tests::test_it_works...[ok]\n\
+             ##TEST-PASS kernel::tests::test_it_works 1301\n",
+            logs[0].log
+        );
+        assert_eq!(
+            "##TEST-START kernel::tests::test_broken\n\
+             test kernel::tests::test_broken...[WARN] no device at 0x1f0\n\
+             [failed]\n\
+             panicked at kernel/src/lib.rs:120:9:\n\
+             boom\n\
+             ##TEST-PASS kernel::tests::test_it_works 1\n\
+             ##TEST-FAIL kernel::tests::test_broken panicked at kernel/src/lib.rs:120:9:\\nboom\n",
+            logs[1].log
+        );
+        assert_eq!(
+            "##TEST-START kernel::tests::test_hangs\n\
+             test kernel::tests::test_hangs...[INFO] timer tick\n",
+            logs[2].log
+        );
+        assert_eq!(
+            "##TEST-START kernel::io::vfs::tests::test_mount\n\
+             test kernel::io::vfs::tests::test_mount...[ERROR] page fault at 0x0\n\
+             [ERROR] kernel panicked\n",
+            logs[3].log
+        );
+    }
+
+    #[test]
+    fn test_split_by_test_other_output() {
+        assert_eq!(
+            Vec::<TestLog>::new(),
+            split_by_test("[INFO] booting\n##TEST-PASS a 1\n##TEST-SKIP b\n")
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let report = Report::parse(
+            "##TEST-PASS a 7\n##TEST-FAIL b \"quoted\"\\nline\n##TEST-SKIP c\n##TEST-START d\n",
+        );
+        assert_eq!(
+            r#"{
+  "tests": [
+    {"name": "a", "outcome": "passed", "duration_us": 7},
+    {"name": "b", "outcome": "failed", "reason": "\"quoted\"\nline"},
+    {"name": "c", "outcome": "skipped"},
+    {"name": "d", "outcome": "unfinished"}
+  ],
+  "suite_end": null
+}
+"#,
+            report.to_json()
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let report =
+            Report::parse("##TEST-PASS kernel::a 1500\n##TEST-FAIL b x\n##SUITE-END 1 1 0\n");
+        assert_eq!(
+            "kernel test  result      duration\n\
+             kernel::a    passed      1.5ms\n\
+             b            FAILED\n\
+             1 passed; 1 failed; 0 skipped",
+            report.to_string()
+        );
+    }
+}
@@ -0,0 +1,102 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use log::{error, info};
+
+use kernel::driver::disk;
+use kernel::driver::disk::Disk;
+use kernel::io::vfs::vfs;
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+// keep in sync with run_raw_disk_test in tests/test_kernels.rs
+const SECTOR_SIZE: usize = 512;
+const SECTORS: usize = 2048;
+/// More sectors than a single command transfers.
+const READ_SECTORS: usize = 300;
+const WRITTEN_SECTOR: usize = 1024;
+const WRITTEN_SECTORS: usize = 64;
+
+/// Reads the SATA disk that the host filled with a known pattern, and writes
+/// a different pattern into a region of it, which the host side of this test
+/// checks in the image. The disk must also be readable through the devfs.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let (index, disk) = disk::devices()
+        .lock()
+        .iter()
+        .enumerate()
+        .find_map(|(index, disk)| Some((index, disk.filter_map(Disk::as_ahci)?)))
+        .expect("no disk on the AHCI controller");
+    info!("ahci_check: found {}", disk.info().model);
+    assert_eq!(SECTORS as u64, disk.info().sector_count);
+
+    let mut buf = vec![0_u8; READ_SECTORS * SECTOR_SIZE];
+    disk.read_sectors(0, &mut buf).expect("failed to read");
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of the disk");
+    }
+
+    let written = (0..WRITTEN_SECTORS * SECTOR_SIZE)
+        .map(|i| (i % 253) as u8 ^ 0xA5)
+        .collect::<Vec<_>>();
+    disk.write_sectors(WRITTEN_SECTOR, &written)
+        .expect("failed to write");
+    let mut buf = vec![0_u8; written.len()];
+    disk.read_sectors(WRITTEN_SECTOR, &mut buf)
+        .expect("failed to read back");
+    assert_eq!(written, buf);
+
+    // a sector past the end of the disk fails, and the port recovers
+    assert!(disk.read_sectors(SECTORS, &mut buf[..SECTOR_SIZE]).is_err());
+    disk.read_sectors(0, &mut buf[..SECTOR_SIZE])
+        .expect("failed to read after an error");
+
+    // the disk is published like every other disk
+    let path = format!("/dev/blk{index}");
+    let node = vfs().open(&path).expect("the disk is not in /dev");
+    let mut buf = vec![0_u8; SECTOR_SIZE];
+    assert_eq!(Ok(SECTOR_SIZE), vfs().read(&node, &mut buf, 0));
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of {path}");
+    }
+    info!("ahci_check: published as {path}");
+
+    info!("ahci_check: verified");
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use foundation::time::Instant;
+use log::{error, info};
+use x86_64::instructions::hlt;
+
+use kernel::process::{process_tree, Priority, Process};
+use kernel::qemu::ExitCode;
+use kernel::time::HpetInstantProvider;
+use kernel::{bootloader_config, kernel_init, process};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+/// Runs `/bin/alloc_check`, which allocates from several threads at once. The
+/// host side of this test checks the serial output for its success message.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let child =
+        Process::create_from_executable(process::current(), "/bin/alloc_check", 0.into(), 0.into());
+    child.start(Priority::Normal);
+
+    let pid = *child.pid();
+    drop(child);
+
+    let start = Instant::now();
+    while process_tree().read().process_by_id(&pid).is_some() {
+        assert!(
+            start.elapsed() < Duration::from_secs(30),
+            "alloc_check did not exit in time"
+        );
+        hlt();
+    }
+    info!("alloc_check process {} exited", pid);
+
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
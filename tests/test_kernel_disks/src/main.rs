@@ -0,0 +1,66 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use log::{error, info};
+
+use kernel::driver::disk;
+use kernel::driver::disk::Disk;
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+/// Lists the block devices that the drivers found, with their size. The host
+/// side of this test attaches disks of different sizes to different buses,
+/// and checks that each of them is listed.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    for disk in disk::devices().lock().iter() {
+        let (bus, sectors, model) = match &**disk {
+            Disk::Ide(device) => (
+                "ide",
+                device.info().sector_count,
+                device.info().model.as_str(),
+            ),
+            Disk::Ahci(device) => (
+                "ahci",
+                device.info().sector_count,
+                device.info().model.as_str(),
+            ),
+            Disk::Nvme(device) => ("nvme", device.block_count(), device.model()),
+            Disk::VirtioBlk(device) => ("virtio_blk", device.block_count(), "virtio-blk"),
+        };
+        info!("disks_check: {bus} {sectors} sectors, {model}");
+    }
+
+    info!("disks_check: done");
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
@@ -0,0 +1,301 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use log::{error, info};
+
+use kernel::io::vfs::{vfs, FileType, MountFlags, VfsError};
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+use kernel_api::syscall::Stat;
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+const DIR: &str = "/var/ext2_check";
+const SMALL: &str = "/var/ext2_check/small.txt";
+const SMALL_CONTENT: &[u8] = b"Hello, ext2!\n";
+/// Large enough to need the singly indirect block.
+const LARGE: &str = "/var/ext2_check/large.bin";
+const LARGE_LEN: usize = 100 * 1024;
+const REMOVED: &str = "/var/ext2_check/removed.txt";
+/// Another name for [`SMALL`].
+const LINKED: &str = "/var/ext2_check/linked.txt";
+/// The only name that is left of a file whose original name was removed.
+const RENAMED_BY_LINK: &str = "/var/ext2_check/renamed_by_link.txt";
+/// Renamed within [`DIR`].
+const RENAMED: &str = "/var/ext2_check/renamed.txt";
+const RENAMED_CONTENT: &[u8] = b"renamed in the same directory\n";
+/// Where files and directories are moved to from [`DIR`].
+const SUB_DIR: &str = "/var/ext2_check/sub";
+const MOVED: &str = "/var/ext2_check/sub/moved.txt";
+const MOVED_CONTENT: &[u8] = b"moved into another directory\n";
+/// A directory that is moved into [`SUB_DIR`], together with its file.
+const MOVED_DIR: &str = "/var/ext2_check/sub/moved_dir";
+const MOVED_DIR_FILE: &str = "/var/ext2_check/sub/moved_dir/file.txt";
+/// Replaced by another file while it's still open.
+const REPLACED: &str = "/var/ext2_check/replaced.txt";
+const REPLACED_CONTENT: &[u8] = b"replaced the old file\n";
+/// An empty directory that is replaced by another one.
+const REPLACED_DIR: &str = "/var/ext2_check/sub/replaced_dir";
+const TRUNCATED: &str = "/var/ext2_check/truncated.bin";
+const TRUNCATED_LEN: usize = 1500;
+/// Written far enough into the file to need the triply indirect block.
+const DEEP: &str = "/var/ext2_check/deep.bin";
+const DEEP_OFFSET: usize = 70 * 1024 * 1024;
+const DEEP_CONTENT: &[u8] = b"deep down";
+
+fn pattern(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i % 251) as u8).collect()
+}
+
+/// Runs twice on the same disk. The first boot writes files to the ext2 root
+/// file system, the second boot checks that they are still there. The host
+/// side of this test runs `e2fsck` on the disk after each boot.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    if vfs()
+        .exists(DIR)
+        .expect("unable to check for the test directory")
+    {
+        verify();
+        info!("ext2_check: verified");
+    } else {
+        write();
+        info!("ext2_check: written");
+    }
+
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+fn write() {
+    // the root file system is mounted read-only by default
+    assert_eq!(
+        Err(VfsError::ReadOnly),
+        vfs().create(DIR, FileType::Directory)
+    );
+    vfs()
+        .remount("/", MountFlags::empty())
+        .expect("unable to remount the root file system read-write");
+
+    vfs()
+        .create(DIR, FileType::Directory)
+        .expect("unable to create directory");
+
+    write_file(SMALL, SMALL_CONTENT);
+    write_file(LARGE, &pattern(LARGE_LEN));
+
+    write_file(REMOVED, b"this file is removed again");
+    vfs().remove_file(REMOVED).expect("unable to remove file");
+
+    vfs().link(SMALL, LINKED).expect("unable to link file");
+    write_file(REMOVED, SMALL_CONTENT);
+    vfs()
+        .link(REMOVED, RENAMED_BY_LINK)
+        .expect("unable to link file");
+    vfs().remove_file(REMOVED).expect("unable to remove file");
+    assert_eq!(Err(VfsError::PermissionDenied), vfs().link(DIR, REMOVED));
+
+    rename();
+
+    write_file(TRUNCATED, &pattern(5000));
+    let node = vfs().open(TRUNCATED).expect("unable to open file");
+    vfs()
+        .truncate(&node, TRUNCATED_LEN)
+        .expect("unable to truncate file");
+    drop(node);
+
+    vfs()
+        .create(DEEP, FileType::RegularFile)
+        .expect("unable to create file");
+    let node = vfs().open(DEEP).expect("unable to open file");
+    let written = vfs()
+        .write(&node, DEEP_CONTENT, DEEP_OFFSET)
+        .expect("unable to write file");
+    assert_eq!(DEEP_CONTENT.len(), written);
+    drop(node);
+
+    // all written nodes are closed, and making the file system read-only
+    // writes it back
+    vfs()
+        .remount("/", MountFlags::READ_ONLY)
+        .expect("unable to remount the root file system read-only");
+}
+
+fn rename() {
+    // within the same directory
+    write_file("/var/ext2_check/rename_me.txt", RENAMED_CONTENT);
+    vfs()
+        .rename("/var/ext2_check/rename_me.txt", RENAMED)
+        .expect("unable to rename file");
+
+    // into another directory, for files and directories
+    vfs()
+        .create(SUB_DIR, FileType::Directory)
+        .expect("unable to create directory");
+    write_file("/var/ext2_check/move_me.txt", MOVED_CONTENT);
+    vfs()
+        .rename("/var/ext2_check/move_me.txt", MOVED)
+        .expect("unable to move file");
+    vfs()
+        .create("/var/ext2_check/move_me", FileType::Directory)
+        .expect("unable to create directory");
+    write_file("/var/ext2_check/move_me/file.txt", MOVED_CONTENT);
+    vfs()
+        .rename("/var/ext2_check/move_me", MOVED_DIR)
+        .expect("unable to move directory");
+    assert_eq!(
+        Err(VfsError::InvalidArgument),
+        vfs().rename(SUB_DIR, "/var/ext2_check/sub/moved_dir/sub")
+    );
+
+    // replacing a file that is still open, which keeps its content until it's
+    // closed
+    write_file(REPLACED, b"the old file");
+    let old = vfs().open(REPLACED).expect("unable to open file");
+    write_file("/var/ext2_check/replacement.txt", REPLACED_CONTENT);
+    vfs()
+        .rename("/var/ext2_check/replacement.txt", REPLACED)
+        .expect("unable to replace file");
+    let mut buf = [0_u8; 12];
+    assert_eq!(12, vfs().read(&old, &mut buf, 0).expect("unable to read"));
+    assert_eq!(b"the old file", &buf);
+    drop(old);
+
+    // replacing an empty directory, but not a non-empty one
+    vfs()
+        .create(REPLACED_DIR, FileType::Directory)
+        .expect("unable to create directory");
+    vfs()
+        .create("/var/ext2_check/replacement", FileType::Directory)
+        .expect("unable to create directory");
+    vfs()
+        .rename("/var/ext2_check/replacement", REPLACED_DIR)
+        .expect("unable to replace directory");
+    assert_eq!(
+        Err(VfsError::NotEmpty),
+        vfs().rename(REPLACED_DIR, MOVED_DIR)
+    );
+    assert_eq!(
+        Err(VfsError::NotDirectory),
+        vfs().rename(REPLACED_DIR, MOVED)
+    );
+    assert_eq!(
+        Err(VfsError::IsDirectory),
+        vfs().rename(MOVED, REPLACED_DIR)
+    );
+}
+
+fn write_file(path: &str, content: &[u8]) {
+    vfs()
+        .create(path, FileType::RegularFile)
+        .expect("unable to create file");
+    let node = vfs().open(path).expect("unable to open file");
+    // write in odd chunks, so that writes cross block boundaries
+    for (i, chunk) in content.chunks(1000).enumerate() {
+        let written = vfs()
+            .write(&node, chunk, i * 1000)
+            .expect("unable to write file");
+        assert_eq!(chunk.len(), written);
+    }
+}
+
+fn verify() {
+    assert_eq!(SMALL_CONTENT, read_file(SMALL).as_slice());
+    assert_eq!(pattern(LARGE_LEN), read_file(LARGE));
+    assert_eq!(pattern(TRUNCATED_LEN), read_file(TRUNCATED));
+    assert_eq!(Err(VfsError::NoSuchFile), vfs().open(REMOVED).map(|_| ()));
+
+    assert_eq!(SMALL_CONTENT, read_file(LINKED).as_slice());
+    assert_eq!(SMALL_CONTENT, read_file(RENAMED_BY_LINK).as_slice());
+    let mut stat = Stat::default();
+    vfs()
+        .stat_path(SMALL, &mut stat)
+        .expect("unable to stat file");
+    assert_eq!(2, stat.nlink);
+    vfs()
+        .stat_path(RENAMED_BY_LINK, &mut stat)
+        .expect("unable to stat file");
+    assert_eq!(1, stat.nlink);
+
+    assert_eq!(RENAMED_CONTENT, read_file(RENAMED).as_slice());
+    assert_eq!(MOVED_CONTENT, read_file(MOVED).as_slice());
+    assert_eq!(MOVED_CONTENT, read_file(MOVED_DIR_FILE).as_slice());
+    assert_eq!(REPLACED_CONTENT, read_file(REPLACED).as_slice());
+    for removed in [
+        "/var/ext2_check/rename_me.txt",
+        "/var/ext2_check/move_me.txt",
+        "/var/ext2_check/move_me",
+        "/var/ext2_check/replacement.txt",
+        "/var/ext2_check/replacement",
+    ] {
+        assert!(!vfs().exists(removed).expect("unable to check for file"));
+    }
+    // the `..` entries of the subdirectories count as links
+    vfs()
+        .stat_path(SUB_DIR, &mut stat)
+        .expect("unable to stat directory");
+    assert_eq!(4, stat.nlink);
+    vfs()
+        .stat_path(DIR, &mut stat)
+        .expect("unable to stat directory");
+    assert_eq!(3, stat.nlink);
+
+    let node = vfs().open(DEEP).expect("unable to open file");
+    let mut buf = [0xFF_u8; 64];
+    let len = DEEP_CONTENT.len();
+    let n = vfs()
+        .read(&node, &mut buf, DEEP_OFFSET - 32)
+        .expect("unable to read file");
+    assert_eq!(32 + len, n);
+    assert!(buf[..32].iter().all(|&b| b == 0));
+    assert_eq!(DEEP_CONTENT, &buf[32..32 + len]);
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    let node = vfs().open(path).expect("unable to open file");
+    // one more byte than expected, so that we notice if the file is too large
+    let mut buf = vec![0_u8; LARGE_LEN + 1];
+    let mut len = 0;
+    loop {
+        let n = vfs()
+            .read(&node, &mut buf[len..], len)
+            .expect("unable to read file");
+        if n == 0 {
+            break;
+        }
+        len += n;
+    }
+    buf.truncate(len);
+    buf
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
@@ -0,0 +1,116 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use foundation::time::Instant;
+use log::{error, info};
+use x86_64::instructions::hlt;
+
+use kernel::io::vfs::lock::LockKind;
+use kernel::io::vfs::{vfs, FileType};
+use kernel::process::{process_tree, Priority, Process};
+use kernel::qemu::ExitCode;
+use kernel::time::HpetInstantProvider;
+use kernel::{bootloader_config, kernel_init, process};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+const LOCK_FILE: &str = "/tmp/flock";
+/// Written by `flock_check` right before it waits for the lock.
+const WAITING: &[u8] = b"waiting";
+/// Written by this kernel right before it releases the lock.
+const RELEASED: &[u8] = b"released";
+
+/// Holds an exclusive lock on [`LOCK_FILE`] while `/bin/flock_check` contends
+/// for it. `flock_check` has to wait until the lock is released, and it exits
+/// while holding the lock, which must release it again. The host side of this
+/// test checks the order of the messages in the serial output.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let pid = *process::current().pid();
+    vfs()
+        .create(LOCK_FILE, FileType::RegularFile)
+        .expect("unable to create the lock file");
+    let node = vfs().open(LOCK_FILE).expect("unable to open the lock file");
+    vfs()
+        .lock(&node, pid, LockKind::Exclusive, false)
+        .expect("unable to lock the lock file");
+    info!("flock: holding the lock");
+
+    let child =
+        Process::create_from_executable(process::current(), "/bin/flock_check", 0.into(), 0.into());
+    child.start(Priority::Normal);
+    let child_pid = *child.pid();
+    drop(child);
+
+    wait_until("flock_check did not try to take the lock", || {
+        let mut buf = [0_u8; 8];
+        let n = vfs().read(&node, &mut buf, 0).unwrap();
+        &buf[..n] == WAITING
+    });
+    // give flock_check some time to actually start waiting
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(200) {
+        hlt();
+    }
+    assert!(
+        process_tree().read().process_by_id(&child_pid).is_some(),
+        "flock_check exited while the lock was held"
+    );
+
+    vfs()
+        .write(&node, RELEASED, 0)
+        .expect("unable to write to the lock file");
+    info!("flock: releasing the lock");
+    vfs()
+        .unlock(&node, pid)
+        .expect("unable to unlock the lock file");
+
+    wait_until("flock_check did not exit in time", || {
+        process_tree().read().process_by_id(&child_pid).is_none()
+    });
+    info!("flock_check process {} exited", child_pid);
+
+    // flock_check exited while holding the lock, so it must be free again
+    vfs()
+        .lock(&node, pid, LockKind::Exclusive, false)
+        .expect("the lock of flock_check was not released when it exited");
+
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+fn wait_until(message: &str, condition: impl Fn() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(10), "{message}");
+        hlt();
+    }
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
@@ -0,0 +1,110 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use log::{error, info};
+
+use kernel::driver::disk;
+use kernel::driver::disk::Disk;
+use kernel::driver::nvme::{NvmeError, StatusCode};
+use kernel::io::vfs::vfs;
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+// keep in sync with run_raw_disk_test in tests/test_kernels.rs
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 2048;
+/// More blocks than a single command transfers.
+const READ_BLOCKS: usize = 300;
+const WRITTEN_BLOCK: usize = 1024;
+/// More than two pages, so that the transfer needs a PRP list.
+const WRITTEN_BLOCKS: usize = 64;
+
+/// Reads the NVMe namespace that the host filled with a known pattern, and
+/// writes a different pattern into a region of it, which the host side of
+/// this test checks in the image. The namespace must also be readable
+/// through the devfs.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let (index, disk) = disk::devices()
+        .lock()
+        .iter()
+        .enumerate()
+        .find_map(|(index, disk)| Some((index, disk.filter_map(Disk::as_nvme)?)))
+        .expect("no NVMe namespace");
+    info!("nvme_check: found {}", disk.model());
+    assert_eq!(BLOCK_SIZE, disk.block_size());
+    assert_eq!(BLOCKS as u64, disk.block_count());
+
+    let mut buf = vec![0_u8; READ_BLOCKS * BLOCK_SIZE];
+    disk.read_blocks_into(0, &mut buf).expect("failed to read");
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of the disk");
+    }
+
+    let written = (0..WRITTEN_BLOCKS * BLOCK_SIZE)
+        .map(|i| (i % 253) as u8 ^ 0xA5)
+        .collect::<Vec<_>>();
+    disk.write_blocks_from(WRITTEN_BLOCK, &written)
+        .expect("failed to write");
+    disk.flush_write_cache().expect("failed to flush");
+    let mut buf = vec![0_u8; written.len()];
+    disk.read_blocks_into(WRITTEN_BLOCK, &mut buf)
+        .expect("failed to read back");
+    assert_eq!(written, buf);
+
+    // a block past the end of the namespace fails, and the queue keeps working
+    assert_eq!(
+        Err(NvmeError::Command(StatusCode::LbaOutOfRange)),
+        disk.read_blocks_into(BLOCKS, &mut buf[..BLOCK_SIZE])
+    );
+    disk.read_blocks_into(0, &mut buf[..BLOCK_SIZE])
+        .expect("failed to read after an error");
+
+    // the namespace is published like every other disk
+    let path = format!("/dev/blk{index}");
+    let node = vfs().open(&path).expect("the namespace is not in /dev");
+    let mut buf = vec![0_u8; BLOCK_SIZE];
+    assert_eq!(Ok(BLOCK_SIZE), vfs().read(&node, &mut buf, 0));
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of {path}");
+    }
+    info!("nvme_check: published as {path}");
+
+    info!("nvme_check: verified");
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
@@ -0,0 +1,100 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+use core::time::Duration;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use foundation::time::Instant;
+use kernel::qemu::ExitCode;
+use kernel::time::HpetInstantProvider;
+use kernel::{bootloader_config, kernel_init, test_runner};
+use kernel_test_framework::kernel_test;
+use log::error;
+use x86_64::instructions::{hlt, interrupts};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    test_runner::run_tests()
+}
+
+/// Hangs, so that the watchdog has to stop it.
+#[kernel_test(timeout_ms = 100)]
+fn test_hangs() {
+    loop {
+        hlt();
+    }
+}
+
+/// Runs past its timeout with interrupts disabled, and returns without
+/// enabling them, so that the watchdog can't stop it, and the runner has to
+/// fail it afterwards.
+#[kernel_test(timeout_ms = 100)]
+fn test_spins_with_interrupts_disabled() {
+    interrupts::disable();
+    let started = Instant::now();
+    while started.elapsed() < Duration::from_millis(200) {
+        core::hint::spin_loop();
+    }
+}
+
+/// A failing test between two passing ones, to check that the runner goes on
+/// after a failure, and reports all three.
+mod sandwich {
+    use kernel_test_framework::kernel_test;
+
+    #[kernel_test]
+    fn test_1_passes() {}
+
+    #[kernel_test]
+    fn test_2_fails() {
+        assert_eq!(1 + 1, 3, "deliberately failing");
+    }
+
+    #[kernel_test]
+    fn test_3_passes() {}
+}
+
+/// Panics with a message that panics when it's formatted, so that the runner
+/// panics while handling the first panic.
+#[kernel_test]
+fn test_double_panic() {
+    struct Unprintable;
+
+    impl core::fmt::Display for Unprintable {
+        fn fmt(&self, _: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            panic!("can't format this")
+        }
+    }
+
+    panic!("{}", Unprintable);
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    // only returns if the panic didn't happen in a test
+    test_runner::handle_panic(info);
+
+    error!("[failed]");
+    error!(
+        "thread '{}' panicked at {}:\n{}",
+        kernel::process::current_thread().name(),
+        info.location().unwrap(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
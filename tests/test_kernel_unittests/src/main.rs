@@ -5,7 +5,7 @@ use core::panic::PanicInfo;
 
 use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
 use kernel::qemu::ExitCode;
-use kernel::{bootloader_config, kernel_init, serial_print, serial_println};
+use kernel::{bootloader_config, kernel_init, test_runner};
 use log::error;
 
 const CONFIG: BootloaderConfig = bootloader_config();
@@ -15,17 +15,14 @@ entry_point!(kernel_main, config = &CONFIG);
 fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
     kernel_init(boot_info).expect("kernel_init failed");
 
-    for test in kernel_test_framework::KERNEL_TESTS {
-        serial_print!("test {}...", test.name);
-        (test.test_fn)();
-        serial_println!("[ok]")
-    }
-
-    kernel::qemu::exit(ExitCode::Success)
+    test_runner::run_tests()
 }
 
 #[panic_handler]
 fn panic_handler(info: &PanicInfo) -> ! {
+    // only returns if the panic didn't happen in a test
+    test_runner::handle_panic(info);
+
     error!("[failed]");
     error!(
         "thread '{}' panicked at {}:\n{}",
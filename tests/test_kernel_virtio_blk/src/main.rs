@@ -0,0 +1,187 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::ToString;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::panic::PanicInfo;
+use core::slice;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use foundation::future::executor::{Executor, Tick};
+use foundation::future::yield_now;
+use log::{error, info};
+use x86_64::structures::paging::PageTableFlags;
+
+use kernel::driver::disk;
+use kernel::driver::disk::Disk;
+use kernel::driver::virtio::blk::{VirtioBlkDevice, VirtioBlkError};
+use kernel::io::vfs::cache::MultiBlock;
+use kernel::io::vfs::vfs;
+use kernel::mem::virt::{AllocationStrategy, MapAt};
+use kernel::process::vmm;
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+// keep in sync with run_raw_disk_test in tests/test_kernels.rs
+const BLOCK_SIZE: usize = 512;
+const BLOCKS: usize = 2048;
+/// More blocks than a single request transfers.
+const READ_BLOCKS: usize = 300;
+const WRITTEN_BLOCK: usize = 1024;
+const WRITTEN_BLOCKS: usize = 64;
+
+/// Reads of 64KiB that are all outstanding at the same time. There are more
+/// of them than the disk has regions of that size.
+const CONCURRENT_READS: usize = 128;
+const REGION_BLOCKS: usize = 128;
+const REGION_LEN: usize = REGION_BLOCKS * BLOCK_SIZE;
+const REGIONS: usize = BLOCKS / REGION_BLOCKS;
+
+/// Reads the virtio-blk disk that the host filled with a known pattern, and
+/// writes a different pattern into a region of it, which the host side of
+/// this test checks in the image. The disk must also be readable through the
+/// devfs.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let (index, disk) = disk::devices()
+        .lock()
+        .iter()
+        .enumerate()
+        .find_map(|(index, disk)| Some((index, disk.filter_map(Disk::as_virtio_blk)?)))
+        .expect("no virtio-blk disk");
+    info!("virtio_blk_check: found {disk:?}");
+    assert_eq!(BLOCK_SIZE, disk.block_size());
+    assert_eq!(BLOCKS as u64, disk.block_count());
+    assert!(disk.interrupts(), "requests don't complete by interrupt");
+
+    let mut buf = vec![0_u8; READ_BLOCKS * BLOCK_SIZE];
+    disk.read_blocks_into(0, &mut buf).expect("failed to read");
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of the disk");
+    }
+
+    read_concurrently(&disk);
+
+    let written = (0..WRITTEN_BLOCKS * BLOCK_SIZE)
+        .map(|i| (i % 253) as u8 ^ 0xA5)
+        .collect::<Vec<_>>();
+    disk.write_blocks_from(WRITTEN_BLOCK, &written)
+        .expect("failed to write");
+    disk.flush_write_cache().expect("failed to flush");
+    // what was flushed is on the disk, not in the queue that is discarded
+    disk.reset().expect("failed to reset");
+    let mut buf = vec![0_u8; written.len()];
+    disk.read_blocks_into(WRITTEN_BLOCK, &mut buf)
+        .expect("failed to read back");
+    assert_eq!(written, buf);
+
+    // a block past the end of the disk fails, and the queue keeps working
+    assert_eq!(
+        Err(VirtioBlkError::IoError),
+        disk.read_blocks_into(BLOCKS, &mut buf[..BLOCK_SIZE])
+    );
+    disk.read_blocks_into(0, &mut buf[..BLOCK_SIZE])
+        .expect("failed to read after an error");
+
+    // the disk is published like every other disk
+    let path = format!("/dev/blk{index}");
+    let node = vfs().open(&path).expect("the disk is not in /dev");
+    let mut buf = vec![0_u8; BLOCK_SIZE];
+    assert_eq!(Ok(BLOCK_SIZE), vfs().read(&node, &mut buf, 0));
+    for (i, &byte) in buf.iter().enumerate() {
+        assert_eq!((i % 251) as u8, byte, "byte {i} of {path}");
+    }
+    info!("virtio_blk_check: published as {path}");
+
+    info!("virtio_blk_check: verified");
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+/// Starts all [`CONCURRENT_READS`] reads on a single executor before the
+/// first of them is sent, so that the driver has more requests than slots,
+/// and compares what they read with the pattern.
+fn read_concurrently(disk: &VirtioBlkDevice) {
+    // the buffers of all reads don't fit into the kernel heap
+    let len = CONCURRENT_READS * REGION_LEN;
+    let addr = vmm()
+        .allocate_memory_backed_vmobject(
+            "virtio_blk_check buffers".to_string(),
+            MapAt::Anywhere,
+            len,
+            AllocationStrategy::AllocateNow,
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::NO_EXECUTE,
+        )
+        .expect("failed to allocate the buffers");
+    let buffers = unsafe {
+        // safety: the memory is ours until the vm object is removed below
+        slice::from_raw_parts_mut(addr.as_mut_ptr::<u8>(), len)
+    };
+
+    let started = AtomicUsize::new(0);
+    let done = AtomicUsize::new(0);
+    let executor = Executor::default();
+    for (read, buf) in buffers.chunks_mut(REGION_LEN).enumerate() {
+        let (started, done) = (&started, &done);
+        executor.spawn(async move {
+            started.fetch_add(1, Ordering::SeqCst);
+            yield_now().await;
+            assert_eq!(
+                CONCURRENT_READS,
+                started.load(Ordering::SeqCst),
+                "read {read} was sent before all reads started"
+            );
+
+            let region = read % REGIONS;
+            disk.read_blocks_async(region * REGION_BLOCKS, buf)
+                .await
+                .expect("failed to read concurrently");
+            let offset = region * REGION_LEN;
+            for (i, &byte) in buf.iter().enumerate() {
+                assert_eq!(
+                    ((offset + i) % 251) as u8,
+                    byte,
+                    "byte {i} of region {region}"
+                );
+            }
+            done.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    while done.load(Ordering::SeqCst) < CONCURRENT_READS {
+        executor.tick();
+    }
+    drop(executor);
+
+    let _ = vmm().vm_objects().write().remove(&addr);
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
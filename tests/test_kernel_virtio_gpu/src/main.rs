@@ -0,0 +1,81 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use bootloader_api::{entry_point, BootInfo, BootloaderConfig};
+use log::{error, info};
+
+use kernel::driver::virtio::gpu;
+use kernel::driver::virtio::gpu::Rect;
+use kernel::qemu::ExitCode;
+use kernel::{bootloader_config, kernel_init};
+
+const CONFIG: BootloaderConfig = bootloader_config();
+
+entry_point!(kernel_main, config = &CONFIG);
+
+/// Draws into the framebuffer of the virtio-gpu device that the host
+/// attached, and flushes it to the display.
+fn kernel_main(boot_info: &'static mut BootInfo) -> ! {
+    kernel_init(boot_info).expect("kernel_init failed");
+
+    let gpu = gpu::devices()
+        .lock()
+        .first()
+        .cloned()
+        .expect("no virtio-gpu device");
+    let info = gpu.screen_info();
+    info!(
+        "virtio_gpu_check: found a {}x{} display",
+        info.width, info.height
+    );
+    assert!(info.width > 0 && info.height > 0);
+    assert_eq!(4, info.bytes_per_pixel);
+    assert_eq!(info.width * 4, info.pitch);
+    assert_eq!(
+        (info.pitch * info.height).div_ceil(4096) as usize,
+        gpu.physical_frames().len()
+    );
+
+    let width = info.width as usize;
+    gpu.with_framebuffer(|pixels| {
+        assert_eq!(width * info.height as usize, pixels.len());
+        for row in pixels.chunks_exact_mut(width).skip(10).take(20) {
+            row[10..50].fill(0x00FF_8000);
+        }
+    });
+    gpu.flush(Rect::new(10, 10, 40, 20))
+        .expect("failed to flush a rectangle");
+    // rectangles that reach past the display are cut
+    gpu.flush(Rect::new(info.width - 8, info.height - 8, 64, 64))
+        .expect("failed to flush a rectangle at the edge");
+    gpu.flush(Rect::new(0, 0, info.width, info.height))
+        .expect("failed to flush the display");
+    gpu.with_framebuffer(|pixels| assert_eq!(0x00FF_8000, pixels[15 * width + 20]));
+
+    info!("virtio_gpu_check: verified");
+    kernel::qemu::exit(ExitCode::Success)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    error!(
+        "kernel panicked in pid={} ({}) tid={} ({}): {}",
+        kernel::process::current().pid(),
+        kernel::process::current().name(),
+        kernel::process::current_thread().id(),
+        kernel::process::current_thread().name(),
+        info.message()
+    );
+    if let Some(location) = info.location() {
+        error!(
+            "\tat {}:{}:{}",
+            location.file(),
+            location.line(),
+            location.column()
+        );
+    }
+
+    kernel::qemu::exit(ExitCode::Failed)
+}
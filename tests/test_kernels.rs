@@ -1,28 +1,504 @@
 extern crate devos;
 
-use devos::{run_test_kernel, OS_DISK};
+use devos::baseline::Baseline;
+use devos::report::{Outcome, Report};
+use devos::{
+    assert_ext2_clean, create_qcow_image, run_test_kernel, DiskBus, DiskFormat, QemuConfig, OS_DISK,
+};
 
 #[test]
 fn test_kernel_unittests() {
-    run_test_kernel(env!("TEST_KERNEL_UNITTESTS_PATH"), OS_DISK);
+    let output = run_test_kernel(env!("TEST_KERNEL_UNITTESTS_PATH"));
+    assert!(output.contains("test kernel::test_runner::tests::test_should_panic...[ok]"));
+    assert!(output.contains(
+        "test kernel::test_runner::tests::test_should_panic_with_expected_message...[ok]"
+    ));
+    assert!(output.contains("test result: ok. "));
+    assert!(output.contains("; 0 skipped"));
+}
+
+#[test]
+fn test_kernel_unittests_filter() {
+    let output = QemuConfig::default()
+        .command_line("test-filter=test_runner::tests::test_options")
+        .run(env!("TEST_KERNEL_UNITTESTS_PATH"));
+    assert!(output.contains("test kernel::test_runner::tests::test_options...[ok]"));
+    assert!(output.contains("test kernel::test_runner::tests::test_check...[skipped]"));
+    assert!(output.contains("test result: ok. 1 run; 0 failed; "));
+    assert_eq!(1, output.matches("[ok]").count());
+    assert!(output.contains("##TEST-PASS kernel::test_runner::tests::test_options "));
+    assert!(output.contains("##TEST-SKIP kernel::test_runner::tests::test_check"));
+    assert!(output.contains("##SUITE-END 1 0 "));
+}
+
+#[test]
+fn test_kernel_unittests_list() {
+    let output = QemuConfig::default()
+        .command_line("test-list")
+        .run(env!("TEST_KERNEL_UNITTESTS_PATH"));
+    assert!(output.contains("kernel::test_runner::tests::test_options: test"));
+    assert!(output.contains("kernel::mem::tests::bench_memcpy_64k: bench"));
+    assert!(!output.contains("[ok]"));
+}
+
+/// How much slower than the baseline a kernel benchmark may get, in percent.
+/// QEMU emulates the CPU, so the measurements vary a lot between machines.
+const BENCH_TOLERANCE_PERCENT: u64 = 50;
+
+#[test]
+fn test_kernel_benches() {
+    let output = QemuConfig::default()
+        .command_line("test-bench")
+        .run(env!("TEST_KERNEL_UNITTESTS_PATH"));
+    let report = Report::parse(&output);
+    assert!(report
+        .benches()
+        .any(|(name, _)| name == "kernel::mem::tests::bench_memcpy_64k"));
+    assert!(!report
+        .tests
+        .iter()
+        .any(|test| test.name.contains("::tests::test_")));
+
+    let path = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/bench_baseline.txt");
+    if std::env::var_os("DEVOS_UPDATE_BENCH_BASELINE").is_some() {
+        std::fs::write(path, Baseline::of(&report).to_string())
+            .expect("failed to write the bench baseline");
+        return;
+    }
+    let baseline = std::fs::read_to_string(path).expect("failed to read the bench baseline");
+    let baseline = Baseline::parse(&baseline).unwrap();
+    if baseline.is_empty() {
+        // nothing to compare to until a baseline is recorded from a real run
+        eprintln!("no bench baseline in {path}, record it with DEVOS_UPDATE_BENCH_BASELINE=1");
+        return;
+    }
+    let missing = baseline.missing(&report);
+    assert!(
+        missing.is_empty(),
+        "kernel benchmarks have no baseline in {path}, record it with \
+         DEVOS_UPDATE_BENCH_BASELINE=1:\n{}",
+        missing.join("\n")
+    );
+    let regressions = baseline.regressions(&report, BENCH_TOLERANCE_PERCENT);
+    assert!(
+        regressions.is_empty(),
+        "kernel benchmarks got more than {BENCH_TOLERANCE_PERCENT}% slower than {path}:\n{}",
+        regressions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    );
+}
+
+#[test]
+fn test_kernel_timeout_hang() {
+    let output = QemuConfig::default()
+        .command_line("test-filter=test_kernel_timeout::test_hangs")
+        .expect_failure()
+        .run(env!("TEST_KERNEL_TIMEOUT_PATH"));
+    assert!(output.contains("##TEST-START test_kernel_timeout::test_hangs"));
+    assert!(output.contains("##TEST-FAIL test_kernel_timeout::test_hangs the test at "));
+    assert!(output.contains(" timed out after 100 ms"));
+    assert!(!output.contains("##SUITE-END"));
+}
+
+#[test]
+fn test_kernel_timeout_interrupts_disabled() {
+    let output = QemuConfig::default()
+        .command_line("test-filter=test_kernel_timeout::test_spins_with_interrupts_disabled")
+        .expect_failure()
+        .run(env!("TEST_KERNEL_TIMEOUT_PATH"));
+    assert!(
+        output.contains("test test_kernel_timeout::test_spins_with_interrupts_disabled...[failed]")
+    );
+    assert!(output.contains("probably ran with interrupts disabled"));
+    assert!(output.contains("test result: FAILED. 1 run; 1 failed; "));
+}
+
+#[test]
+fn test_kernel_continue_after_failure() {
+    let output = QemuConfig::default()
+        .command_line("test-filter=test_kernel_timeout::sandwich::")
+        .expect_failure()
+        .run(env!("TEST_KERNEL_TIMEOUT_PATH"));
+    let report = Report::parse(&output);
+    let outcome = |name: &str| {
+        report
+            .tests
+            .iter()
+            .find(|test| test.name == format!("test_kernel_timeout::sandwich::{name}"))
+            .unwrap_or_else(|| panic!("{name} wasn't reported"))
+            .outcome
+            .clone()
+    };
+    assert!(matches!(outcome("test_1_passes"), Outcome::Passed(_)));
+    assert!(matches!(outcome("test_3_passes"), Outcome::Passed(_)));
+    let Outcome::Failed(reason) = outcome("test_2_fails") else {
+        panic!("test_2_fails didn't fail");
+    };
+    assert!(reason.contains("deliberately failing"));
+    assert!(reason.contains("main.rs:"));
+    assert!(output.contains("##SUITE-END 2 1 "));
+}
+
+#[test]
+fn test_kernel_double_panic() {
+    let output = QemuConfig::default()
+        .command_line("test-filter=test_kernel_timeout::test_double_panic")
+        .expect_failure()
+        .run(env!("TEST_KERNEL_TIMEOUT_PATH"));
+    assert!(output.contains(
+        "##TEST-FAIL test_kernel_timeout::test_double_panic panicked while handling a panic"
+    ));
+    assert!(!output.contains("##SUITE-END"));
 }
 
 #[test]
 fn test_kernel_multitasking() {
-    run_test_kernel(env!("TEST_KERNEL_MULTITASKING_PATH"), OS_DISK);
+    run_test_kernel(env!("TEST_KERNEL_MULTITASKING_PATH"));
 }
 
 #[test]
 fn test_kernel_vfs() {
-    run_test_kernel(env!("TEST_KERNEL_VFS_PATH"), OS_DISK);
+    run_test_kernel(env!("TEST_KERNEL_VFS_PATH"));
 }
 
 #[test]
 fn test_kernel_vmobject() {
-    run_test_kernel(env!("TEST_KERNEL_VMOBJECT_PATH"), OS_DISK);
+    run_test_kernel(env!("TEST_KERNEL_VMOBJECT_PATH"));
 }
 
 #[test]
 fn test_kernel_file_vmobject() {
-    run_test_kernel(env!("TEST_KERNEL_FILE_VMOBJECT_PATH"), OS_DISK);
+    run_test_kernel(env!("TEST_KERNEL_FILE_VMOBJECT_PATH"));
+}
+
+#[test]
+fn test_kernel_strace() {
+    let output = run_test_kernel(env!("TEST_KERNEL_STRACE_PATH"));
+    let traced = output
+        .lines()
+        .filter(|line| line.contains("[strace]"))
+        .collect::<Vec<_>>();
+
+    let expect = |needle: &str| {
+        assert!(
+            traced.iter().any(|line| line.contains(needle)),
+            "expected a trace line containing '{needle}', got:\n{}",
+            traced.join("\n")
+        );
+    };
+
+    // fds 0, 1 and 2 are taken by the console, so the file is opened as fd 3
+    expect(r#"-> open("/var/data/hello.txt", 0, 0)"#);
+    expect("<- open = 3");
+    expect("-> read(3, 0x");
+    expect("<- read = 13");
+    expect("-> close(3)");
+    expect("<- close = 0");
+    expect("-> exit(0)");
+}
+
+#[test]
+fn test_kernel_syscall() {
+    let output = run_test_kernel(env!("TEST_KERNEL_SYSCALL_PATH"));
+    assert!(
+        output.contains("syscall_check: ok"),
+        "syscall_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_devices() {
+    let output = run_test_kernel(env!("TEST_KERNEL_DEVICES_PATH"));
+    assert!(
+        output.contains("dev_check: ok"),
+        "dev_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_libmuffin() {
+    let output = run_test_kernel(env!("TEST_KERNEL_LIBMUFFIN_PATH"));
+    assert!(
+        output.contains("muffin_check: ok"),
+        "muffin_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_flock() {
+    let output = run_test_kernel(env!("TEST_KERNEL_FLOCK_PATH"));
+    assert!(
+        output.contains("flock_check: lock is held"),
+        "LOCK_NB did not fail while the lock was held, output:\n{output}"
+    );
+    let released = output
+        .find("flock: releasing the lock")
+        .unwrap_or_else(|| panic!("the lock was never released, output:\n{output}"));
+    let acquired = output
+        .find("flock_check: ok")
+        .unwrap_or_else(|| panic!("flock_check did not succeed, output:\n{output}"));
+    assert!(
+        released < acquired,
+        "flock_check got the lock before it was released, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_alloc() {
+    let output = run_test_kernel(env!("TEST_KERNEL_ALLOC_PATH"));
+    assert!(
+        output.contains("alloc_check: ok"),
+        "alloc_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_thread() {
+    let output = run_test_kernel(env!("TEST_KERNEL_THREAD_PATH"));
+    assert!(
+        output.contains("thread 'panicker' panicked at"),
+        "the panicking thread did not report its panic, output:\n{output}"
+    );
+    assert!(
+        output.contains("thread_check: ok"),
+        "thread_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_fs() {
+    let output = run_test_kernel(env!("TEST_KERNEL_FS_PATH"));
+    assert!(
+        output.contains("fs_check: ok"),
+        "fs_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_print() {
+    const LINES: usize = 100;
+    const BATCH: usize = 10;
+
+    let output = run_test_kernel(env!("TEST_KERNEL_PRINT_PATH"));
+    assert!(
+        output.contains("print_check: ok"),
+        "print_check did not succeed, output:\n{output}"
+    );
+    assert!(
+        !output.contains("[strace] rate limit exceeded"),
+        "trace lines were dropped, output:\n{output}"
+    );
+
+    // every line is printed in one piece, even though two threads print at once
+    for name in ["main", "thread"] {
+        for line in 0..LINES {
+            let expected = format!("print_check: {name} line {line}");
+            assert!(
+                output.lines().any(|l| l.trim_end() == expected),
+                "'{expected}' is missing or torn, output:\n{output}"
+            );
+        }
+    }
+
+    // one write per batch of lines and one for the success message, instead
+    // of one per line
+    let writes = output
+        .lines()
+        .filter(|line| line.contains("[strace]") && line.contains("-> write(1,"))
+        .count();
+    assert!(
+        writes <= 2 * LINES / BATCH + 1,
+        "expected at most {} writes to stdout, got {writes}, output:\n{output}",
+        2 * LINES / BATCH + 1
+    );
+}
+
+#[test]
+fn test_kernel_env() {
+    let output = run_test_kernel(env!("TEST_KERNEL_ENV_PATH"));
+    for line in [
+        "env_check: arg 0 = '/bin/env_check'",
+        "env_check: arg 1 = 'one'",
+        "env_check: arg 2 = 'two words'",
+        "env_check: arg 3 = 'thrée'",
+        "env_check: var ENV_CHECK_A = 'alpha'",
+        "env_check: var ENV_CHECK_B = 'beta=gamma'",
+        "env_check: ok",
+    ] {
+        assert!(
+            output.contains(line),
+            "env_check did not print '{line}', output:\n{output}"
+        );
+    }
+    assert!(
+        !output.contains("env_check: arg 4"),
+        "env_check got more arguments than it was started with, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_console() {
+    // a typo that is corrected with DEL, and more input after the line
+    let output = QemuConfig::default()
+        .input(b"hellp\x7fo world\rxy")
+        .run(env!("TEST_KERNEL_CONSOLE_PATH"));
+    assert!(
+        output.contains("console_check: read line 'hello world'"),
+        "console_check did not read the edited line, output:\n{output}"
+    );
+    assert!(
+        output.contains("console_check: written through /dev/fd/1"),
+        "console_check could not write through /dev/fd/1, output:\n{output}"
+    );
+    assert!(
+        output.contains("console_check: ok"),
+        "console_check did not succeed, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_procfs() {
+    let output = run_test_kernel(env!("TEST_KERNEL_PROCFS_PATH"));
+    assert!(
+        output.contains("proc_check: ok"),
+        "proc_check did not succeed, output:\n{output}"
+    );
+
+    // the devices of the default QEMU machine, as lspci lists them
+    for line in [
+        "00:00.0 Host bridge [0600]: [8086:1237]",
+        "00:01.1 IDE interface [0101]: [8086:7010] (driver IDEController)",
+    ] {
+        assert!(
+            output.contains(line),
+            "lspci did not list '{line}', output:\n{output}"
+        );
+    }
+}
+
+#[test]
+fn test_kernel_ext2_write() {
+    let disk = create_qcow_image(OS_DISK);
+    let config = QemuConfig::new().add_disk_in_place(&disk, DiskFormat::Qcow2, DiskBus::Ide);
+
+    // the first boot writes the files, the second one reads them back
+    let output = config.run(env!("TEST_KERNEL_EXT2_WRITE_PATH"));
+    assert!(
+        output.contains("ext2_check: written"),
+        "ext2_check did not write its files, output:\n{output}"
+    );
+    assert_ext2_clean(&disk);
+
+    let output = config.run(env!("TEST_KERNEL_EXT2_WRITE_PATH"));
+    assert!(
+        output.contains("ext2_check: verified"),
+        "ext2_check did not verify its files, output:\n{output}"
+    );
+    assert_ext2_clean(&disk);
+}
+
+#[test]
+fn test_kernel_ahci() {
+    run_raw_disk_test("ahci", env!("TEST_KERNEL_AHCI_PATH"), DiskBus::Ahci);
+}
+
+#[test]
+fn test_kernel_nvme() {
+    run_raw_disk_test("nvme", env!("TEST_KERNEL_NVME_PATH"), DiskBus::Nvme);
+}
+
+#[test]
+fn test_kernel_virtio_blk() {
+    run_raw_disk_test(
+        "virtio_blk",
+        env!("TEST_KERNEL_VIRTIO_BLK_PATH"),
+        DiskBus::VirtioBlk,
+    );
+}
+
+#[test]
+fn test_kernel_disks() {
+    // the sizes tell the disks apart
+    let disks = [("ahci", DiskBus::Ahci, 2048), ("nvme", DiskBus::Nvme, 6144)];
+    let mut config = QemuConfig::default();
+    let mut images = Vec::new();
+    for (name, bus, sectors) in disks {
+        let image =
+            std::env::temp_dir().join(format!("devos_disks_{name}_{}.img", std::process::id()));
+        std::fs::write(&image, vec![0_u8; sectors * 512]).unwrap();
+        config = config.add_disk(image.to_str().unwrap(), DiskFormat::Raw, bus);
+        images.push(image);
+    }
+
+    let output = config.run(env!("TEST_KERNEL_DISKS_PATH"));
+    for image in images {
+        let _ = std::fs::remove_file(image);
+    }
+    for (name, _, sectors) in disks {
+        let line = format!("disks_check: {name} {sectors} sectors");
+        assert!(
+            output.contains(&line),
+            "the kernel did not list '{line}', output:\n{output}"
+        );
+    }
+    assert!(
+        output.contains("disks_check: done"),
+        "disks_check did not finish, output:\n{output}"
+    );
+}
+
+#[test]
+fn test_kernel_virtio_gpu() {
+    let output = QemuConfig::default()
+        .extra_args(["-vga", "none", "-device", "virtio-gpu-pci"])
+        .run(env!("TEST_KERNEL_VIRTIO_GPU_PATH"));
+    assert!(
+        output.contains("virtio_gpu_check: verified"),
+        "the virtio-gpu device was not verified, output:\n{output}"
+    );
+}
+
+/// Runs a test kernel with a raw disk that holds a known pattern. The kernel
+/// verifies the pattern, also through the disk's file in `/dev`, and writes a
+/// different one into a region of the disk, which is checked in the image
+/// afterwards.
+fn run_raw_disk_test(name: &str, kernel: &str, bus: DiskBus) {
+    // keep in sync with tests/test_kernel_ahci, tests/test_kernel_nvme and
+    // tests/test_kernel_virtio_blk
+    const SECTOR_SIZE: usize = 512;
+    const SECTORS: usize = 2048;
+    const WRITTEN: std::ops::Range<usize> = 1024 * SECTOR_SIZE..1088 * SECTOR_SIZE;
+
+    let image = std::env::temp_dir().join(format!("devos_{name}_{}.img", std::process::id()));
+    let original = (0..SECTORS * SECTOR_SIZE)
+        .map(|i| (i % 251) as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(&image, &original).unwrap();
+
+    let output = QemuConfig::default()
+        .add_disk_in_place(image.to_str().unwrap(), DiskFormat::Raw, bus)
+        .run(kernel);
+    assert!(
+        output.contains(&format!("{name}_check: published as /dev/blk")),
+        "the {name} disk is not in /dev, output:\n{output}"
+    );
+    assert!(
+        output.contains(&format!("{name}_check: verified")),
+        "the {name} disk was not verified, output:\n{output}"
+    );
+
+    let written = std::fs::read(&image).unwrap();
+    let _ = std::fs::remove_file(&image);
+    assert_eq!(original.len(), written.len());
+    for (i, (&before, &after)) in original.iter().zip(&written).enumerate() {
+        let expected = if WRITTEN.contains(&i) {
+            ((i - WRITTEN.start) % 253) as u8 ^ 0xA5
+        } else {
+            before
+        };
+        assert_eq!(expected, after, "byte {i} of the {name} disk");
+    }
 }
@@ -0,0 +1,176 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::alloc::{alloc, dealloc, realloc, Layout};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::ptr::{null, null_mut};
+
+use libmuffin::pthread::{pthread_create, pthread_join, pthread_t};
+use std::heap::MAX_SMALL;
+use std::syscall::sys_exit;
+use std::{println, rt};
+
+/// The threads that allocate at the same time, besides the main thread.
+const THREADS: usize = 3;
+/// The allocations that each thread makes.
+const ROUNDS: usize = 1000;
+/// The allocations that each thread keeps alive at once.
+const SLOTS: usize = 32;
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn main() {
+    check_aligned(Layout::from_size_align(4096, 4096).unwrap());
+    check_aligned(Layout::from_size_align(MAX_SMALL, 4096).unwrap());
+    check_realloc();
+
+    let mut threads = [0 as pthread_t; THREADS];
+    for (i, thread) in threads.iter_mut().enumerate() {
+        let seed = i + 1;
+        assert_eq!(0, unsafe {
+            pthread_create(thread, null(), stress_thread, seed as *mut c_void)
+        });
+    }
+    stress(THREADS as u64 + 1);
+    for thread in threads {
+        assert_eq!(0, unsafe { pthread_join(thread, null_mut()) });
+    }
+
+    println!("alloc_check: ok");
+}
+
+/// Checks that an allocation is aligned, and that freeing it makes its
+/// memory available again, which is where the next allocation with the same
+/// layout ends up.
+fn check_aligned(layout: Layout) {
+    unsafe {
+        let ptr = alloc(layout);
+        assert!(!ptr.is_null());
+        assert_eq!(0, ptr as usize % layout.align());
+        ptr.write_bytes(0xAA, layout.size());
+        dealloc(ptr, layout);
+
+        let again = alloc(layout);
+        if layout.size() < MAX_SMALL {
+            assert_eq!(ptr, again);
+        }
+        assert_eq!(0, again as usize % layout.align());
+        dealloc(again, layout);
+    }
+}
+
+/// Checks that reallocating keeps the contents, and that growing within the
+/// size class doesn't move them.
+fn check_realloc() {
+    unsafe {
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = alloc(layout);
+        fill(ptr, 100, 1);
+        let grown = realloc(ptr, layout, 120);
+        assert_eq!(ptr, grown);
+        check(grown, 100, 1);
+
+        let layout = Layout::from_size_align(120, 8).unwrap();
+        let moved = realloc(grown, layout, 4000);
+        check(moved, 120, 1);
+
+        let layout = Layout::from_size_align(4000, 8).unwrap();
+        fill(moved, 4000, 2);
+        let large = realloc(moved, layout, 2 * MAX_SMALL);
+        check(large, 4000, 2);
+        dealloc(large, Layout::from_size_align(2 * MAX_SMALL, 8).unwrap());
+    }
+}
+
+extern "C" fn stress_thread(seed: *mut c_void) -> *mut c_void {
+    stress(seed as u64);
+    null_mut()
+}
+
+/// Allocates, reallocates and frees blocks of mixed sizes and alignments.
+/// Every block is filled with a canary, which is checked before the block is
+/// reallocated or freed, so that blocks that overlap with each other, or
+/// with the heap's own bookkeeping, are noticed.
+fn stress(seed: u64) {
+    let mut rng = XorShift(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15));
+    let mut slots: Vec<Option<(*mut u8, Layout, u8)>> = vec![None; SLOTS];
+    for round in 0..ROUNDS {
+        let slot = rng.below(SLOTS);
+        let canary = (round % 251) as u8 + 1;
+        let block = match slots[slot].take() {
+            Some((ptr, layout, old_canary)) if rng.below(4) == 0 => unsafe {
+                check(ptr, layout.size(), old_canary);
+                let new_size = size(&mut rng);
+                let ptr = realloc(ptr, layout, new_size);
+                assert!(!ptr.is_null());
+                check(ptr, layout.size().min(new_size), old_canary);
+                (
+                    ptr,
+                    Layout::from_size_align_unchecked(new_size, layout.align()),
+                )
+            },
+            old => unsafe {
+                if let Some((ptr, layout, old_canary)) = old {
+                    check(ptr, layout.size(), old_canary);
+                    dealloc(ptr, layout);
+                }
+                let layout = Layout::from_size_align(size(&mut rng), 1 << rng.below(13)).unwrap();
+                let ptr = alloc(layout);
+                assert!(!ptr.is_null());
+                (ptr, layout)
+            },
+        };
+        assert_eq!(0, block.0 as usize % block.1.align());
+        unsafe { fill(block.0, block.1.size(), canary) };
+        slots[slot] = Some((block.0, block.1, canary));
+    }
+    for (ptr, layout, canary) in slots.into_iter().flatten() {
+        unsafe {
+            check(ptr, layout.size(), canary);
+            dealloc(ptr, layout);
+        }
+    }
+}
+
+/// Mostly small sizes, sometimes one that is larger than a page, and rarely
+/// one that is too large for a size class.
+fn size(rng: &mut XorShift) -> usize {
+    match rng.below(64) {
+        0 => MAX_SMALL + 1 + rng.below(2 * MAX_SMALL),
+        1..=12 => 1 + rng.below(8 * 1024),
+        _ => 1 + rng.below(512),
+    }
+}
+
+unsafe fn fill(ptr: *mut u8, len: usize, canary: u8) {
+    ptr.write_bytes(canary, len);
+}
+
+unsafe fn check(ptr: *const u8, len: usize, canary: u8) {
+    let block = core::slice::from_raw_parts(ptr, len);
+    if let Some(i) = block.iter().position(|&b| b != canary) {
+        panic!("canary overwritten at byte {i} of {len}");
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
@@ -0,0 +1,82 @@
+#![no_std]
+#![no_main]
+
+use kernel_api::syscall::{
+    OpenFlags, CONSOLE_GET_MODE, CONSOLE_MODE_LINE, CONSOLE_MODE_RAW, CONSOLE_SET_MODE,
+};
+use std::io::{read_line, STDIN};
+use std::syscall::{sys_close, sys_exit, sys_ioctl, sys_open, sys_read, sys_write, Errno};
+use std::{println, rt};
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn must(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            println!("console_check: {errno}");
+            sys_exit(errno.code() as isize)
+        }
+    }
+}
+
+/// The host types `hellp<DEL>o world<CR>xy` into the serial console.
+fn main() {
+    let mut buf = [0_u8; 64];
+    let n = must(read_line(STDIN, &mut buf));
+    let line = core::str::from_utf8(&buf[..n]).unwrap();
+    println!("console_check: read line '{}'", line.trim_end());
+    assert_eq!("hello world\n", line);
+
+    // in raw mode, the rest of the input is readable without a newline
+    must(sys_ioctl(
+        STDIN,
+        CONSOLE_SET_MODE,
+        CONSOLE_MODE_RAW as usize,
+    ));
+    let mut mode = u32::MAX;
+    must(sys_ioctl(
+        STDIN,
+        CONSOLE_GET_MODE,
+        &mut mode as *mut u32 as usize,
+    ));
+    assert_eq!(CONSOLE_MODE_RAW, mode);
+
+    let mut read = 0;
+    while read < 2 {
+        read += must(sys_read(STDIN, &mut buf[read..2]));
+    }
+    assert_eq!(b"xy", &buf[..2]);
+
+    // there is no more input, so a non-blocking read fails instead of waiting
+    let flags = OpenFlags::O_RDONLY | OpenFlags::O_NONBLOCK;
+    let console = must(sys_open("/dev/console", flags.bits() as usize, 0));
+    assert_eq!(Err(Errno::EWOULDBLOCK), sys_read(console, &mut buf));
+    must(sys_close(console));
+
+    // /dev/fd/1 is the console that the standard output refers to
+    let stdout = must(sys_open(
+        "/dev/fd/1",
+        OpenFlags::O_WRONLY.bits() as usize,
+        0,
+    ));
+    must(sys_write(
+        stdout,
+        b"console_check: written through /dev/fd/1\n",
+    ));
+    must(sys_close(stdout));
+
+    must(sys_ioctl(
+        STDIN,
+        CONSOLE_SET_MODE,
+        CONSOLE_MODE_LINE as usize,
+    ));
+    println!("console_check: ok");
+}
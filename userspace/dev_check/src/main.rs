@@ -0,0 +1,60 @@
+#![no_std]
+#![no_main]
+
+use kernel_api::syscall::OpenFlags;
+use std::syscall::{sys_close, sys_exit, sys_open, sys_read, sys_write, Errno};
+use std::{println, rt};
+
+const CHUNK_SIZE: usize = 4096;
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn must(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            println!("dev_check: {errno}");
+            sys_exit(errno.code() as isize)
+        }
+    }
+}
+
+/// Reads one chunk from `from` and writes it to `to`, like `cat` would, and
+/// returns the chunk.
+fn cat(from: &str, to: &str) -> [u8; CHUNK_SIZE] {
+    let src = must(sys_open(from, OpenFlags::O_RDONLY.bits() as usize, 0));
+    let dst = must(sys_open(to, OpenFlags::O_WRONLY.bits() as usize, 0));
+
+    let mut buf = [0_u8; CHUNK_SIZE];
+    assert_eq!(CHUNK_SIZE, must(sys_read(src, &mut buf)));
+    assert_eq!(CHUNK_SIZE, must(sys_write(dst, &buf)));
+
+    must(sys_close(src));
+    must(sys_close(dst));
+    buf
+}
+
+fn main() {
+    let zeros = cat("/dev/zero", "/dev/null");
+    assert!(
+        zeros.iter().all(|&b| b == 0),
+        "/dev/zero returned non-zero bytes"
+    );
+
+    let first = cat("/dev/urandom", "/dev/null");
+    let second = cat("/dev/urandom", "/dev/null");
+    assert!(
+        first.iter().any(|&b| b != 0),
+        "/dev/urandom returned only zeros"
+    );
+    assert_ne!(first, second, "/dev/urandom returned the same bytes twice");
+
+    println!("dev_check: ok");
+}
@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+use std::env::{self, VarError};
+use std::syscall::sys_exit;
+use std::{println, rt};
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+/// Prints the arguments and the `ENV_CHECK_*` variables that the test kernel
+/// started this with, for the host side of the test to compare.
+fn main() {
+    for (i, arg) in env::args().enumerate() {
+        println!("env_check: arg {} = '{}'", i, arg);
+    }
+    for (name, value) in env::vars().filter(|(name, _)| name.starts_with("ENV_CHECK_")) {
+        println!("env_check: var {} = '{}'", name, value);
+    }
+
+    assert_eq!(Ok("alpha"), env::var("ENV_CHECK_A"));
+    assert_eq!(Err(VarError::NotPresent), env::var("ENV_CHECK"));
+    assert_eq!(Err(VarError::NotPresent), env::var("ENV_CHECK_MISSING"));
+
+    assert_eq!(Some(4096), rt::page_size());
+    assert_eq!(Some(_start as *const () as usize), rt::entry());
+
+    println!("env_check: ok");
+}
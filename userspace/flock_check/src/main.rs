@@ -0,0 +1,50 @@
+#![no_std]
+#![no_main]
+
+use kernel_api::syscall::OpenFlags;
+use std::syscall::{sys_exit, sys_flock, sys_open, sys_read, sys_write, Errno, FlockOperation};
+use std::{println, rt};
+
+/// Locked exclusively by the test kernel when this starts.
+const LOCK_FILE: &str = "/tmp/flock";
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn must(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            println!("flock_check: {errno}");
+            sys_exit(errno.code() as isize)
+        }
+    }
+}
+
+fn main() {
+    let fd = must(sys_open(LOCK_FILE, OpenFlags::O_RDWR.bits() as usize, 0));
+
+    assert_eq!(
+        Err(Errno::EWOULDBLOCK),
+        sys_flock(fd, FlockOperation::LOCK_EX | FlockOperation::LOCK_NB)
+    );
+    println!("flock_check: lock is held");
+
+    must(sys_write(fd, b"waiting"));
+    must(sys_flock(fd, FlockOperation::LOCK_EX));
+
+    // the holder writes this right before it releases the lock
+    let check = must(sys_open(LOCK_FILE, OpenFlags::O_RDONLY.bits() as usize, 0));
+    let mut buf = [0_u8; 8];
+    assert_eq!(8, must(sys_read(check, &mut buf)));
+    assert_eq!(b"released", &buf);
+    println!("flock_check: ok");
+
+    // exit while holding the lock, which releases it
+}
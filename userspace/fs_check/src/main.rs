@@ -0,0 +1,103 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use std::fs::{self, File, FileType, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::syscall::sys_exit;
+use std::{println, rt};
+
+const DIR: &str = "/tmp/fs_check";
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn main() {
+    fs::create_dir(DIR).unwrap();
+    let path = Path::new(DIR).join("data.txt");
+
+    let mut file = File::create(&path).unwrap();
+    file.write_all(b"hello ").unwrap();
+    file.write_all(b"world\n").unwrap();
+    drop(file);
+
+    let mut contents = Vec::new();
+    let mut file = File::open(&path).unwrap();
+    assert_eq!(12, file.read_to_end(&mut contents).unwrap());
+    assert_eq!(b"hello world\n", contents.as_slice());
+
+    // seek back and read a part again
+    assert_eq!(6, file.seek(SeekFrom::Start(6)).unwrap());
+    let mut word = [0; 5];
+    file.read_exact(&mut word).unwrap();
+    assert_eq!(b"world", &word);
+    assert_eq!(11, file.stream_position().unwrap());
+    assert_eq!(
+        ErrorKind::UnexpectedEof,
+        file.read_exact(&mut word).unwrap_err().kind()
+    );
+
+    let metadata = file.metadata().unwrap();
+    assert!(metadata.is_file());
+    assert_eq!(12, metadata.len());
+    assert_eq!(metadata.ino(), fs::metadata(&path).unwrap().ino());
+    assert!(fs::metadata(DIR).unwrap().is_dir());
+    drop(file);
+
+    // a file that is opened for reading can't be written to
+    let mut file = File::open(&path).unwrap();
+    assert!(file.write_all(b"nope").is_err());
+    drop(file);
+
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(b"again\n").unwrap();
+    drop(file);
+    assert_eq!("hello world\nagain\n", fs::read_to_string(&path).unwrap());
+
+    fs::write(Path::new(DIR).join("other.txt"), b"other").unwrap();
+    let mut entries = fs::read_dir(DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap())
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+    let names = entries.iter().map(|e| e.file_name()).collect::<Vec<_>>();
+    assert_eq!(["data.txt", "other.txt"], names.as_slice());
+    assert_eq!(path.as_path(), entries[0].path());
+    assert!(matches!(
+        entries[0].file_type(),
+        FileType::File | FileType::Unknown
+    ));
+    assert_eq!(Some(Path::new(DIR)), entries[0].path().parent());
+
+    for entry in &entries {
+        fs::remove_file(entry.path()).unwrap();
+    }
+    fs::remove_dir(DIR).unwrap();
+
+    let missing = File::open(&path).unwrap_err();
+    assert_eq!(ErrorKind::NotFound, missing.kind());
+    assert_eq!(ErrorKind::NotFound, fs::metadata(DIR).unwrap_err().kind());
+    assert_eq!(
+        ErrorKind::NotADirectory,
+        fs::read_dir("/var/data/hello.txt").unwrap_err().kind()
+    );
+    assert_eq!(
+        ErrorKind::InvalidInput,
+        OpenOptions::new()
+            .open("/var/data/hello.txt")
+            .unwrap_err()
+            .kind()
+    );
+
+    println!("fs_check: ok");
+}
@@ -0,0 +1,709 @@
+//! Drawing into a buffer of `0x00RRGGBB` pixels, like the back buffer of the
+//! screen.
+//!
+//! Everything that is drawn is cut off at the edges of the canvas, and the
+//! shapes additionally at a clip rectangle. The canvas keeps track of which
+//! parts of it were drawn to, so that only those need to be presented.
+
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+use kernel_api::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+
+use crate::color::{blend_pixel, blend_row, Argb8888};
+use crate::{DamageTracker, Extent, Rect, Vec2};
+
+/// The pixels, `width` per row, in any buffer that can be borrowed as a
+/// slice, like a `Vec` or a mapping.
+pub struct Canvas<B> {
+    pixels: B,
+    width: usize,
+    height: usize,
+    damage: DamageTracker,
+}
+
+impl<B: DerefMut<Target = [u32]>> Canvas<B> {
+    /// Creates a canvas with `width` pixels per row, which keeps whatever is
+    /// in the buffer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the buffer doesn't hold exactly `width` times `height`
+    /// pixels.
+    pub fn new(pixels: B, width: usize, height: usize) -> Self {
+        assert_eq!(width * height, pixels.len());
+        Self {
+            pixels,
+            width,
+            height,
+            damage: DamageTracker::new(),
+        }
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    /// The area of the canvas.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Cuts the rectangle to the canvas, so that it can be used as buffer
+    /// coordinates.
+    fn clip(&self, rect: Rect) -> Rect {
+        rect.clamp_to(&self.bounds())
+    }
+
+    /// The rows of the rectangle, each cut to the rectangle.
+    pub fn rows(&self, rect: Rect) -> impl Iterator<Item = &[u32]> + '_ {
+        let rect = self.clip(rect);
+        let (x, y) = rect.origin.to_unsigned();
+        self.pixels
+            .chunks_exact(self.width)
+            .skip(y)
+            .take(rect.size.height)
+            .map(move |row| &row[x..x + rect.size.width])
+    }
+
+    /// The rows of the clipped rectangle in `buffer`, each cut to the
+    /// rectangle.
+    fn rows_mut(
+        buffer: &mut [u32],
+        stride: usize,
+        rect: Rect,
+    ) -> impl Iterator<Item = &mut [u32]> + '_ {
+        let (x, y) = rect.origin.to_unsigned();
+        buffer
+            .chunks_exact_mut(stride)
+            .skip(y)
+            .take(rect.size.height)
+            .map(move |row| &mut row[x..x + rect.size.width])
+    }
+
+    /// Marks the rectangle as changed. Drawing does this on its own, this is
+    /// only needed when writing to the buffer in some other way.
+    pub fn damage(&mut self, rect: Rect) {
+        let rect = self.clip(rect);
+        self.damage.add(rect);
+    }
+
+    /// The rectangles that changed since this was last called, for when they
+    /// are presented. None of them overlap or touch.
+    pub fn take_damage(&mut self) -> Vec<Rect> {
+        self.damage.take()
+    }
+
+    /// Fills the rectangle in the canvas.
+    pub fn fill_rect(&mut self, rect: Rect, color: u32) {
+        let rect = self.clip(rect);
+        self.damage.add(rect);
+        for row in Self::rows_mut(&mut self.pixels, self.width, rect) {
+            row.fill(color);
+        }
+    }
+
+    /// Composites the color over the rectangle in the canvas.
+    pub fn fill_rect_blended(&mut self, rect: Rect, color: Argb8888) {
+        match color.alpha() {
+            0 => {}
+            0xFF => self.fill_rect(rect, color.0 & 0x00FF_FFFF),
+            _ => {
+                let rect = self.clip(rect);
+                self.damage.add(rect);
+                for row in Self::rows_mut(&mut self.pixels, self.width, rect) {
+                    row.iter_mut().for_each(|pixel| blend_pixel(color, pixel));
+                }
+            }
+        }
+    }
+
+    /// Composites the ARGB image, which has `width` pixels per row, over the
+    /// canvas, with its top left corner at `at`. The parts of the image that
+    /// are off the canvas are skipped.
+    pub fn blit_blended(&mut self, at: Vec2, width: usize, image: &[u32]) {
+        let height = image.len() / width;
+        let image_rect = Rect {
+            origin: at,
+            size: Extent::new(width, height),
+        };
+        let rect = self.clip(image_rect);
+        self.damage.add(rect);
+        // where in the image the visible part starts
+        let (skip_x, skip_y) = (rect.origin - at).to_unsigned();
+        let dst = Self::rows_mut(&mut self.pixels, self.width, rect);
+        let src = image.chunks_exact(width).skip(skip_y);
+        for (dst, src) in dst.zip(src) {
+            blend_row(&src[skip_x..skip_x + dst.len()], dst);
+        }
+    }
+
+    /// Sets the pixel in the canvas if it's within `clip`, which must be
+    /// clipped to the canvas.
+    fn plot(&mut self, clip: Rect, point: Vec2, color: u32) {
+        if clip.contains(point) {
+            let (x, y) = point.to_unsigned();
+            self.pixels[y * self.width + x] = color;
+        }
+    }
+
+    /// Fills the pixels from `x0` to `x1`, both inclusive, in row `y` of the
+    /// canvas, as far as they are within `clip`.
+    fn span(&mut self, clip: Rect, x0: i32, x1: i32, y: i32, color: u32) {
+        let (x0, x1) = (x0.min(x1), x0.max(x1));
+        let span = Rect::bounding(Vec2::new(x0, y), Vec2::new(x1, y)).clamp_to(&clip);
+        for row in Self::rows_mut(&mut self.pixels, self.width, span) {
+            row.fill(color);
+        }
+    }
+
+    /// Draws a line from `from` to `to`, both inclusive, into the canvas.
+    /// Nothing outside of `clip` is drawn.
+    pub fn draw_line(&mut self, clip: Rect, from: Vec2, to: Vec2, color: u32) {
+        let clip = self.clip(clip);
+        self.damage.add(Rect::bounding(from, to).clamp_to(&clip));
+        // Bresenham, stepping along both axes with the error term deciding
+        // when to step along the minor one, so it works in every octant
+        let dx = (to.x - from.x).abs();
+        let dy = -(to.y - from.y).abs();
+        let sx = if from.x < to.x { 1 } else { -1 };
+        let sy = if from.y < to.y { 1 } else { -1 };
+        let mut error = dx + dy;
+        let mut point = from;
+        loop {
+            self.plot(clip, point, color);
+            if point == to {
+                break;
+            }
+            let e2 = 2 * error;
+            if e2 >= dy {
+                error += dy;
+                point.x += sx;
+            }
+            if e2 <= dx {
+                error += dx;
+                point.y += sy;
+            }
+        }
+    }
+
+    /// Draws lines through all points, in order, into the canvas. Nothing
+    /// outside of `clip` is drawn.
+    pub fn draw_polyline(&mut self, clip: Rect, points: &[Vec2], color: u32) {
+        match points {
+            [] => {}
+            [point] => self.draw_line(clip, *point, *point, color),
+            _ => {
+                for line in points.windows(2) {
+                    self.draw_line(clip, line[0], line[1], color);
+                }
+            }
+        }
+    }
+
+    /// Calls `f` with the offsets of the points on one octant of a circle with
+    /// the radius, computed with the midpoint algorithm. The other octants
+    /// are the mirror images.
+    fn circle_octant(radius: i32, mut f: impl FnMut(i32, i32)) {
+        let mut x = radius;
+        let mut y = 0;
+        let mut decision = 1 - radius;
+        while y <= x {
+            f(x, y);
+            y += 1;
+            if decision < 0 {
+                decision += 2 * y + 1;
+            } else {
+                x -= 1;
+                decision += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    /// Draws the outline of a circle into the canvas. Nothing outside of
+    /// `clip` is drawn.
+    pub fn draw_circle(&mut self, clip: Rect, center: Vec2, radius: usize, color: u32) {
+        let clip = self.clip(clip);
+        self.damage_circle(clip, center, radius);
+        Self::circle_octant(radius as i32, |x, y| {
+            for (x, y) in [(x, y), (y, x)] {
+                for offset in [(x, y), (-x, y), (x, -y), (-x, -y)] {
+                    self.plot(clip, center + Vec2::new(offset.0, offset.1), color);
+                }
+            }
+        });
+    }
+
+    fn damage_circle(&mut self, clip: Rect, center: Vec2, radius: usize) {
+        let radius = Vec2::new(radius as i32, radius as i32);
+        self.damage
+            .add(Rect::bounding(center - radius, center + radius).clamp_to(&clip));
+    }
+
+    /// Fills a circle in the canvas. Nothing outside of `clip` is drawn.
+    pub fn fill_circle(&mut self, clip: Rect, center: Vec2, radius: usize, color: u32) {
+        let clip = self.clip(clip);
+        self.damage_circle(clip, center, radius);
+        Self::circle_octant(radius as i32, |x, y| {
+            for (x, y) in [(x, y), (y, x)] {
+                for y in [center.y + y, center.y - y] {
+                    self.span(clip, center.x - x, center.x + x, y, color);
+                }
+            }
+        });
+    }
+
+    /// Fills the triangle in the canvas, one row at a time. Nothing
+    /// outside of `clip` is drawn.
+    pub fn fill_triangle(&mut self, clip: Rect, a: Vec2, b: Vec2, c: Vec2, color: u32) {
+        let clip = self.clip(clip);
+        let mut points = [a, b, c];
+        points.sort_by_key(|p| p.y);
+        let [top, middle, bottom] = points;
+        let left = top.x.min(middle.x).min(bottom.x);
+        let right = top.x.max(middle.x).max(bottom.x);
+        self.damage.add(
+            Rect::bounding(Vec2::new(left, top.y), Vec2::new(right, bottom.y)).clamp_to(&clip),
+        );
+        if top.y == bottom.y {
+            self.span(clip, left, right, top.y, color);
+            return;
+        }
+
+        // x on the edge from `from` to `to` in row `y`, rounded towards
+        // `from`
+        let edge = |from: Vec2, to: Vec2, y: i32| {
+            from.x + (to.x - from.x) * (y - from.y) / (to.y - from.y)
+        };
+        // rows outside of the clip rectangle are skipped entirely, so a huge
+        // triangle doesn't cost a loop iteration per row
+        let first = top.y.max(clip.top());
+        let last = bottom.y.min(clip.bottom() - 1);
+        for y in first..=last {
+            let long = edge(top, bottom, y);
+            let short = if y < middle.y {
+                edge(top, middle, y)
+            } else if middle.y == bottom.y {
+                middle.x
+            } else {
+                edge(middle, bottom, y)
+            };
+            self.span(clip, long, short, y, color);
+        }
+    }
+
+    /// Copies the rectangle of the canvas so that its top left corner
+    /// ends up at `to`. The two may overlap. Only the part of the rectangle
+    /// that is on the canvas both before and after the copy is copied.
+    pub fn copy_rect(&mut self, from: Rect, to: Vec2) {
+        let offset = to - from.origin;
+        let from = self.clip(from);
+        // cut off the part that would end up outside of the canvas, and
+        // whatever would have been copied there
+        let dest = self.clip(from.translate(offset));
+        if dest.is_empty() {
+            return;
+        }
+        let from = dest.translate(-offset);
+        self.damage.add(dest);
+
+        let (from_x, from_y) = from.origin.to_unsigned();
+        let (to_x, to_y) = dest.origin.to_unsigned();
+        let width = from.size.width;
+        // like memmove, copy the rows that would otherwise be overwritten
+        // before they are copied first; copy_within takes care of overlap
+        // within a row
+        let copy_row = |back: &mut [u32], row: usize| {
+            let start = (from_y + row) * self.width + from_x;
+            let dest = (to_y + row) * self.width + to_x;
+            back.copy_within(start..start + width, dest);
+        };
+        if to_y > from_y {
+            (0..from.size.height)
+                .rev()
+                .for_each(|row| copy_row(&mut self.pixels, row));
+        } else {
+            (0..from.size.height).for_each(|row| copy_row(&mut self.pixels, row));
+        }
+    }
+
+    /// Moves the contents of the region up by `rows`, and fills the rows that
+    /// become free at the bottom.
+    pub fn scroll_up(&mut self, region: Rect, rows: usize, fill: u32) {
+        let region = self.clip(region);
+        let rows = rows.min(region.size.height);
+        let width = region.size.width;
+        self.copy_rect(
+            Rect {
+                origin: region.origin + Vec2::new(0, rows as i32),
+                size: Extent::new(width, region.size.height - rows),
+            },
+            region.origin,
+        );
+        self.fill_rect(
+            Rect::new(region.left(), region.bottom() - rows as i32, width, rows),
+            fill,
+        );
+    }
+
+    /// Draws the character with its top left corner at `at` into the
+    /// canvas. Without a background color, only the pixels of the glyph are
+    /// drawn. Glyphs that cross an edge are cut off at the edge.
+    pub fn draw_char(&mut self, at: Vec2, c: char, fg: u32, bg: Option<u32>) {
+        let glyph_rect = Rect {
+            origin: at,
+            size: Extent::new(GLYPH_WIDTH, GLYPH_HEIGHT),
+        };
+        let rect = self.clip(glyph_rect);
+        self.damage.add(rect);
+        // the columns and rows of the glyph that are off the canvas
+        let (skip_x, skip_y) = (rect.origin - at).to_unsigned();
+        let glyph = glyph(c).iter().skip(skip_y);
+        for (pixels, &bits) in Self::rows_mut(&mut self.pixels, self.width, rect).zip(glyph) {
+            let column = |i: usize| bits & (0x80 >> (skip_x + i)) != 0;
+            match bg {
+                // every pixel is written, so the row is built first and then
+                // copied in one go
+                Some(bg) => {
+                    let mut row = [bg; GLYPH_WIDTH];
+                    for (i, pixel) in row.iter_mut().enumerate().take(pixels.len()) {
+                        if column(i) {
+                            *pixel = fg;
+                        }
+                    }
+                    pixels.copy_from_slice(&row[..pixels.len()]);
+                }
+                None => {
+                    for (i, pixel) in pixels.iter_mut().enumerate() {
+                        if column(i) {
+                            *pixel = fg;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws the string with its top left corner at `at` into the
+    /// canvas, see [`Canvas::draw_char`]. A newline continues at the same
+    /// column on the next line, and nothing wraps at the right edge.
+    pub fn draw_str(&mut self, at: Vec2, s: &str, fg: u32, bg: Option<u32>) {
+        let right = self.bounds().right();
+        for (line, text) in (0..).zip(s.split('\n')) {
+            let y = at.y + line * GLYPH_HEIGHT as i32;
+            for (column, c) in (0..).zip(text.chars()) {
+                let x = at.x + column * GLYPH_WIDTH as i32;
+                if x >= right {
+                    break;
+                }
+                self.draw_char(Vec2::new(x, y), c, fg, bg);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+
+    use super::*;
+
+    const FG: u32 = 0x00FF_FFFF;
+    const BG: u32 = 0x0000_00FF;
+
+    fn canvas(width: usize, height: usize) -> Canvas<Vec<u32>> {
+        Canvas::new(vec![0; width * height], width, height)
+    }
+
+    /// The canvas as text, a line per row: `.` for black, `#` for [`FG`], `o`
+    /// for [`BG`], and the letters for the colors from 1 to 26.
+    fn render(canvas: &Canvas<Vec<u32>>) -> Vec<String> {
+        canvas
+            .rows(canvas.bounds())
+            .map(|row| {
+                row.iter()
+                    .map(|&pixel| match pixel {
+                        0 => '.',
+                        FG => '#',
+                        BG => 'o',
+                        1..=26 => (b'a' + pixel as u8 - 1) as char,
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_draw_char() {
+        let mut canvas = canvas(GLYPH_WIDTH, GLYPH_HEIGHT);
+        canvas.draw_char(Vec2::new(0, 0), 'F', FG, Some(BG));
+        assert_eq!(
+            render(&canvas),
+            [
+                "oooooooo", //
+                "o#####oo", "o#####oo", "o#oooooo", "o#oooooo", "o#oooooo", "o#oooooo", "o####ooo",
+                "o####ooo", "o#oooooo", "o#oooooo", "o#oooooo", "o#oooooo", "o#oooooo", "o#oooooo",
+                "oooooooo",
+            ]
+        );
+        assert_eq!(vec![canvas.bounds()], canvas.take_damage());
+
+        // without a background, only the pixels of the glyph are drawn, and
+        // the rest of the F stays
+        canvas.draw_char(Vec2::new(0, 0), 'L', FG, None);
+        assert_eq!("o####ooo", render(&canvas)[7]);
+        assert_eq!("o#####oo", render(&canvas)[13]);
+    }
+
+    #[test]
+    fn test_draw_char_clipped() {
+        let mut canvas = canvas(6, 6);
+        canvas.draw_char(Vec2::new(-3, -1), 'A', FG, None);
+        assert_eq!(vec![Rect::new(0, 0, 5, 6)], canvas.take_damage());
+        canvas.draw_char(Vec2::new(4, 3), 'L', FG, Some(BG));
+        assert_eq!(vec![Rect::new(4, 3, 2, 3)], canvas.take_damage());
+        assert_eq!(
+            render(&canvas),
+            [
+                "##....", //
+                "##....", "..#...", "..#.oo", "..#.o#", "..#.o#",
+            ]
+        );
+
+        // glyphs that are entirely off the canvas draw nothing
+        let before = render(&canvas);
+        canvas.draw_char(Vec2::new(6, 0), 'A', FG, Some(BG));
+        canvas.draw_char(Vec2::new(0, -16), 'A', FG, Some(BG));
+        assert_eq!(before, render(&canvas));
+        assert!(canvas.take_damage().is_empty());
+    }
+
+    #[test]
+    fn test_draw_str() {
+        let mut canvas = canvas(20, 2 * GLYPH_HEIGHT);
+        canvas.draw_str(Vec2::new(2, 0), "ALA!\nF", FG, None);
+
+        // the third character is cut off at the edge, and the fourth is
+        // skipped
+        let mut expected = self::canvas(20, 2 * GLYPH_HEIGHT);
+        expected.draw_char(Vec2::new(2, 0), 'A', FG, None);
+        expected.draw_char(Vec2::new(10, 0), 'L', FG, None);
+        expected.draw_char(Vec2::new(18, 0), 'A', FG, None);
+        expected.draw_char(Vec2::new(2, 16), 'F', FG, None);
+        assert_eq!(render(&expected), render(&canvas));
+        assert!(render(&canvas)[3].ends_with(".#"));
+    }
+
+    #[test]
+    fn test_draw_line() {
+        let mut canvas = canvas(7, 5);
+        let bounds = canvas.bounds();
+        canvas.draw_line(bounds, Vec2::new(0, 0), Vec2::new(6, 2), FG);
+        canvas.draw_line(bounds, Vec2::new(1, 4), Vec2::new(3, 0), 1);
+        assert_eq!(
+            render(&canvas),
+            [
+                "##.a...", //
+                "..#a#..", "..a..##", "..a....", ".a.....",
+            ]
+        );
+        assert_eq!(vec![bounds], canvas.take_damage());
+    }
+
+    #[test]
+    fn test_draw_line_clipped() {
+        let mut canvas = canvas(7, 7);
+        let bounds = canvas.bounds();
+        canvas.draw_line(
+            Rect::new(1, 1, 5, 5),
+            Vec2::new(-3, -3),
+            Vec2::new(9, 9),
+            FG,
+        );
+        assert_eq!(vec![Rect::new(1, 1, 5, 5)], canvas.take_damage());
+        canvas.draw_line(bounds, Vec2::new(-3, 6), Vec2::new(9, 6), 1);
+        assert_eq!(vec![Rect::new(0, 6, 7, 1)], canvas.take_damage());
+        assert_eq!(
+            render(&canvas),
+            [
+                ".......", //
+                ".#.....", "..#....", "...#...", "....#..", ".....#.", "aaaaaaa",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_draw_polyline() {
+        let mut canvas = canvas(7, 7);
+        let bounds = canvas.bounds();
+        let square = [(1, 1), (5, 1), (5, 5), (1, 5), (1, 1)].map(|(x, y)| Vec2::new(x, y));
+        canvas.draw_polyline(bounds, &square, FG);
+        canvas.draw_polyline(bounds, &[Vec2::new(3, 3)], 1);
+        canvas.draw_polyline(bounds, &[], 2);
+        assert_eq!(
+            render(&canvas),
+            [
+                ".......", //
+                ".#####.", ".#...#.", ".#.a.#.", ".#...#.", ".#####.", ".......",
+            ]
+        );
+        assert_eq!(vec![Rect::new(1, 1, 5, 5)], canvas.take_damage());
+    }
+
+    #[test]
+    fn test_circles() {
+        let mut canvas = canvas(7, 7);
+        let bounds = canvas.bounds();
+        canvas.draw_circle(bounds, Vec2::new(3, 3), 3, FG);
+        canvas.fill_circle(bounds, Vec2::new(3, 3), 1, 1);
+        assert_eq!(
+            render(&canvas),
+            [
+                "..###..", //
+                ".#...#.", "#..a..#", "#.aaa.#", "#..a..#", ".#...#.", "..###..",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_circles_clipped() {
+        let mut canvas = canvas(7, 7);
+        let bounds = canvas.bounds();
+        canvas.fill_circle(bounds, Vec2::new(0, 6), 3, FG);
+        assert_eq!(vec![Rect::new(0, 3, 4, 4)], canvas.take_damage());
+        canvas.draw_circle(Rect::new(3, 0, 4, 7), Vec2::new(3, 3), 3, 1);
+        assert_eq!(vec![Rect::new(3, 0, 4, 7)], canvas.take_damage());
+        assert_eq!(
+            render(&canvas),
+            [
+                "...aa..", //
+                ".....a.", "......a", "##....a", "###...a", "####.a.", "###aa..",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fill_triangle() {
+        let mut canvas = canvas(7, 7);
+        let bounds = canvas.bounds();
+        canvas.fill_triangle(
+            bounds,
+            Vec2::new(0, 0),
+            Vec2::new(6, 0),
+            Vec2::new(0, 6),
+            FG,
+        );
+        assert_eq!(
+            render(&canvas),
+            [
+                "#######", //
+                "######.", "#####..", "####...", "###....", "##.....", "#......",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fill_triangle_clipped() {
+        let mut canvas = canvas(7, 7);
+        let clip = Rect::new(0, 2, 7, 3);
+        let (top, left, right) = (Vec2::new(3, -10), Vec2::new(-10, 20), Vec2::new(20, 20));
+        canvas.fill_triangle(clip, top, left, right, FG);
+        assert_eq!(
+            render(&canvas),
+            [
+                ".......", //
+                ".......", "#######", "#######", "#######", ".......", ".......",
+            ]
+        );
+        assert_eq!(vec![clip], canvas.take_damage());
+    }
+
+    /// A canvas with the colors from 1 on, so that every pixel shows up as
+    /// a different letter.
+    fn letters(width: usize, height: usize) -> Canvas<Vec<u32>> {
+        Canvas::new((1..=(width * height) as u32).collect(), width, height)
+    }
+
+    #[test]
+    fn test_copy_rect_overlapping() {
+        // down and to the right, where rows have to be copied bottom up
+        let mut canvas = letters(5, 4);
+        canvas.copy_rect(Rect::new(0, 0, 3, 3), Vec2::new(1, 1));
+        assert_eq!(render(&canvas), ["abcde", "fabcj", "kfgho", "pklmt"]);
+        assert_eq!(vec![Rect::new(1, 1, 3, 3)], canvas.take_damage());
+
+        // up and to the left
+        let mut canvas = letters(5, 4);
+        canvas.copy_rect(Rect::new(1, 1, 4, 3), Vec2::new(0, 0));
+        assert_eq!(render(&canvas), ["ghije", "lmnoj", "qrsto", "pqrst"]);
+        assert_eq!(vec![Rect::new(0, 0, 4, 3)], canvas.take_damage());
+    }
+
+    #[test]
+    fn test_copy_rect_clipped() {
+        // only the column that is on the canvas both before and after the
+        // copy is copied
+        let mut canvas = letters(5, 4);
+        canvas.copy_rect(Rect::new(-1, 0, 3, 3), Vec2::new(3, 2));
+        assert_eq!(render(&canvas), ["abcde", "fghij", "klmna", "pqrsf"]);
+        assert_eq!(vec![Rect::new(4, 2, 1, 2)], canvas.take_damage());
+
+        let before = render(&canvas);
+        canvas.copy_rect(Rect::new(0, 0, 2, 2), Vec2::new(5, 0));
+        assert_eq!(before, render(&canvas));
+        assert!(canvas.take_damage().is_empty());
+    }
+
+    #[test]
+    fn test_scroll_up() {
+        let mut canvas = letters(5, 4);
+        canvas.scroll_up(Rect::new(1, 0, 3, 4), 1, 0);
+        assert_eq!(render(&canvas), ["aghie", "flmnj", "kqrso", "p...t"]);
+        assert_eq!(vec![Rect::new(1, 0, 3, 4)], canvas.take_damage());
+
+        // scrolling by more than the region clears it
+        canvas.scroll_up(canvas.bounds(), 9, FG);
+        assert_eq!(render(&canvas), ["#####"; 4]);
+    }
+
+    #[test]
+    fn test_blit_blended_clipped() {
+        // opaque colors from 11 on, with a transparent pixel in the center
+        let image = [1, 2, 3, 4, 0, 6, 7, 8, 9].map(|i| match i {
+            0 => 0,
+            i => 0xFF00_0000 | (i + 10),
+        });
+        let mut canvas = letters(4, 3);
+        // cut off at the top left, the bottom right, and entirely off
+        canvas.blit_blended(Vec2::new(-1, -1), 3, &image);
+        canvas.blit_blended(Vec2::new(3, 2), 3, &image);
+        canvas.blit_blended(Vec2::new(4, 0), 3, &image);
+        assert_eq!(render(&canvas), ["apcd", "rsgh", "ijkk"]);
+        assert_eq!(
+            vec![Rect::new(0, 0, 2, 2), Rect::new(3, 2, 1, 1)],
+            canvas.take_damage()
+        );
+    }
+
+    #[test]
+    fn test_overlap_order() {
+        // what is drawn later ends up on top
+        let mut canvas = canvas(6, 4);
+        canvas.fill_rect(Rect::new(0, 0, 4, 3), 1);
+        canvas.fill_rect(Rect::new(2, 1, 4, 3), 2);
+        canvas.draw_char(Vec2::new(-1, -1), 'L', FG, None);
+        assert_eq!(render(&canvas), ["#aaa..", "#abbbb", "#abbbb", "#.bbbb"]);
+
+        // and translucent colors are composited over what is below them
+        canvas.fill_rect(canvas.bounds(), 0x0000_0000);
+        canvas.fill_rect(Rect::new(0, 0, 2, 1), 0x00FF_FFFF);
+        canvas.fill_rect_blended(Rect::new(1, 0, 2, 1), Argb8888::new(0x80, 0xFF, 0, 0));
+        canvas.fill_rect_blended(Rect::new(2, 0, 2, 1), Argb8888::new(0x80, 0, 0, 0xFF));
+        assert_eq!(
+            [0x00FF_FFFF, 0x00FF_7F7F, 0x0040_0080, 0x0000_0080, 0, 0],
+            canvas.pixels()[..6]
+        );
+    }
+}
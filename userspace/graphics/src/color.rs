@@ -0,0 +1,213 @@
+//! Colors with alpha, and compositing them onto the screen.
+//!
+//! Pixels in the back buffer carry no meaningful alpha, so when they are the
+//! destination of a blend, they are treated as opaque.
+
+/// A color with 8 bits per channel and alpha in the top byte, which is not
+/// premultiplied unless stated otherwise.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Argb8888(pub u32);
+
+impl Argb8888 {
+    pub const TRANSPARENT: Self = Self(0);
+
+    pub const fn new(alpha: u8, red: u8, green: u8, blue: u8) -> Self {
+        Self((alpha as u32) << 24 | (red as u32) << 16 | (green as u32) << 8 | blue as u32)
+    }
+
+    /// The color of a pixel of the screen, which is `0x00RRGGBB`, as an opaque
+    /// color.
+    pub const fn opaque(rgb: u32) -> Self {
+        Self(0xFF00_0000 | rgb)
+    }
+
+    pub const fn alpha(self) -> u8 {
+        (self.0 >> 24) as u8
+    }
+
+    pub const fn red(self) -> u8 {
+        (self.0 >> 16) as u8
+    }
+
+    pub const fn green(self) -> u8 {
+        (self.0 >> 8) as u8
+    }
+
+    pub const fn blue(self) -> u8 {
+        self.0 as u8
+    }
+
+    /// Multiplies the color channels by alpha.
+    pub const fn premultiply(self) -> Self {
+        let alpha = self.alpha() as u32;
+        Self::new(
+            self.alpha(),
+            mul_div_255(self.red() as u32, alpha) as u8,
+            mul_div_255(self.green() as u32, alpha) as u8,
+            mul_div_255(self.blue() as u32, alpha) as u8,
+        )
+    }
+
+    /// Divides the color channels of a premultiplied color by alpha.
+    pub fn unpremultiply(self) -> Self {
+        let alpha = self.alpha() as u32;
+        if alpha == 0 {
+            return Self::TRANSPARENT;
+        }
+        let channel = |c: u8| ((c as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+        Self::new(
+            self.alpha(),
+            channel(self.red()),
+            channel(self.green()),
+            channel(self.blue()),
+        )
+    }
+}
+
+/// `x * y / 255`, rounded to the nearest integer.
+const fn mul_div_255(x: u32, y: u32) -> u32 {
+    (x * y + 127) / 255
+}
+
+/// Composites `src` over `dst`.
+pub fn blend(src: Argb8888, dst: Argb8888) -> Argb8888 {
+    match (src.alpha(), dst.alpha()) {
+        (0xFF, _) => src,
+        (0, _) => dst,
+        // the common case of drawing onto the screen, where a single rounding
+        // step is enough
+        (alpha, 0xFF) => {
+            let alpha = alpha as u32;
+            let channel =
+                |s: u8, d: u8| ((s as u32 * alpha + d as u32 * (255 - alpha) + 127) / 255) as u8;
+            Argb8888::new(
+                0xFF,
+                channel(src.red(), dst.red()),
+                channel(src.green(), dst.green()),
+                channel(src.blue(), dst.blue()),
+            )
+        }
+        _ => blend_premultiplied(src.premultiply(), dst.premultiply()).unpremultiply(),
+    }
+}
+
+/// Composites `src` over `dst`, both of which are premultiplied. This is
+/// cheaper than [`blend`], since it doesn't divide by alpha, so it's the
+/// better choice for colors that are blended many times.
+pub fn blend_premultiplied(src: Argb8888, dst: Argb8888) -> Argb8888 {
+    let rest = 255 - src.alpha() as u32;
+    let channel = |s: u8, d: u8| (s as u32 + mul_div_255(d as u32, rest)) as u8;
+    Argb8888::new(
+        channel(src.alpha(), dst.alpha()),
+        channel(src.red(), dst.red()),
+        channel(src.green(), dst.green()),
+        channel(src.blue(), dst.blue()),
+    )
+}
+
+/// Composites the row of ARGB pixels over the row of screen pixels. Fully
+/// transparent pixels leave the destination alone, and fully opaque ones
+/// overwrite it without reading it.
+pub fn blend_row(src: &[u32], dst: &mut [u32]) {
+    for (&src, dst) in src.iter().zip(dst) {
+        blend_pixel(Argb8888(src), dst);
+    }
+}
+
+/// Composites the color over the screen pixel, see [`blend_row`].
+pub fn blend_pixel(src: Argb8888, dst: &mut u32) {
+    match src.alpha() {
+        0 => {}
+        0xFF => *dst = src.0 & 0x00FF_FFFF,
+        _ => *dst = blend(src, Argb8888::opaque(*dst & 0x00FF_FFFF)).0 & 0x00FF_FFFF,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Colors with every channel at the extremes and in between.
+    const COLORS: [u32; 6] = [
+        0x0000_0000,
+        0x00FF_FFFF,
+        0x00FF_0000,
+        0x0000_FF80,
+        0x0012_3456,
+        0x0080_8080,
+    ];
+
+    #[test]
+    fn test_blend() {
+        let dst = Argb8888::opaque(0x0000_FF32);
+        // fully transparent leaves the destination alone, fully opaque replaces it
+        assert_eq!(dst, blend(Argb8888::new(0, 0xFF, 0, 0x64), dst));
+        assert_eq!(
+            Argb8888::new(0xFF, 0xFF, 0, 0x64),
+            blend(Argb8888::new(0xFF, 0xFF, 0, 0x64), dst)
+        );
+        assert_eq!(
+            Argb8888::new(0xFF, 128, 127, 75),
+            blend(Argb8888::new(128, 0xFF, 0, 100), dst)
+        );
+
+        // onto a translucent destination
+        let dst = Argb8888::new(128, 0, 0, 0xFF);
+        assert_eq!(dst, blend(Argb8888::TRANSPARENT, dst));
+        assert_eq!(
+            Argb8888::new(0xFF, 0xFF, 0, 0),
+            blend(Argb8888::new(0xFF, 0xFF, 0, 0), dst)
+        );
+        assert_eq!(
+            Argb8888::new(192, 170, 0, 85),
+            blend(Argb8888::new(128, 0xFF, 0, 0), dst)
+        );
+    }
+
+    #[test]
+    fn test_blend_premultiplied() {
+        let dst = Argb8888::new(0xFF, 200, 100, 0);
+        assert_eq!(dst, blend_premultiplied(Argb8888::TRANSPARENT, dst));
+        assert_eq!(
+            Argb8888::new(0xFF, 10, 20, 30),
+            blend_premultiplied(Argb8888::new(0xFF, 10, 20, 30), dst)
+        );
+        assert_eq!(
+            Argb8888::new(0xFF, 164, 50, 128),
+            blend_premultiplied(Argb8888::new(128, 64, 0, 128), dst)
+        );
+
+        let dst = Argb8888::new(128, 0, 0, 128);
+        assert_eq!(
+            Argb8888::new(192, 128, 0, 64),
+            blend_premultiplied(Argb8888::new(128, 128, 0, 0), dst)
+        );
+    }
+
+    #[test]
+    fn test_blend_row_fast_paths() {
+        for alpha in [0, 0xFF] {
+            let src = COLORS.map(|rgb| alpha << 24 | rgb);
+            for rgb in COLORS {
+                let mut dst = [rgb; COLORS.len()];
+                blend_row(&src, &mut dst);
+
+                // what compositing the premultiplied colors gives, without
+                // any of the shortcuts
+                let slow = src.map(|src| {
+                    let src = Argb8888(src).premultiply();
+                    blend_premultiplied(src, Argb8888::opaque(rgb)).0 & 0x00FF_FFFF
+                });
+                assert_eq!(slow, dst, "alpha {alpha:#x} onto {rgb:#08x}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_blend_row() {
+        let src = [0x0000_0000, 0xFF12_3456, 0x80FF_0000, 0x8000_0000];
+        let mut dst = [0x00AB_CDEF; 4];
+        blend_row(&src, &mut dst);
+        assert_eq!([0x00AB_CDEF, 0x0012_3456, 0x00D5_6677, 0x0055_6677], dst);
+    }
+}
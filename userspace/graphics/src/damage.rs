@@ -0,0 +1,136 @@
+//! Tracking which parts of the back buffer changed, so that presenting only
+//! copies those to the framebuffer.
+
+use alloc::vec::Vec;
+
+use crate::Rect;
+
+/// How many rectangles are tracked before they are replaced by their bounding
+/// box. Past this, copying a few pixels too many is cheaper than keeping
+/// track of them.
+const MAX_RECTS: usize = 16;
+
+/// The parts of the back buffer that changed since they were last presented.
+/// The rectangles are clipped to the screen, so none of them are at negative
+/// coordinates.
+pub struct DamageTracker {
+    rects: Vec<Rect>,
+}
+
+impl Default for DamageTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DamageTracker {
+    pub const fn new() -> Self {
+        Self { rects: Vec::new() }
+    }
+
+    /// Marks the rectangle as changed. Rectangles that overlap or touch are
+    /// merged into their bounding box.
+    pub fn add(&mut self, mut rect: Rect) {
+        if rect.is_empty() {
+            return;
+        }
+        // merging may make the rectangle touch one that it didn't touch
+        // before, so keep going until nothing touches it anymore
+        while let Some(i) = self.rects.iter().position(|other| other.touches(&rect)) {
+            rect = rect.union(&self.rects.swap_remove(i));
+        }
+        self.rects.push(rect);
+
+        if self.rects.len() > MAX_RECTS {
+            let bounding = self
+                .rects
+                .iter()
+                .fold(self.rects[0], |bounding, rect| bounding.union(rect));
+            self.rects.clear();
+            self.rects.push(bounding);
+        }
+    }
+
+    /// Removes the changed rectangles, for when they are presented. None of
+    /// them overlap or touch.
+    pub fn take(&mut self) -> Vec<Rect> {
+        core::mem::take(&mut self.rects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::Vec2;
+
+    /// Whether the rectangles cover exactly the same pixels.
+    fn assert_covers(expected: &[Rect], actual: &[Rect]) {
+        let covered = |rects: &[Rect], x, y| rects.iter().any(|r| r.contains(Vec2::new(x, y)));
+        for x in -1..40 {
+            for y in -1..40 {
+                assert_eq!(
+                    covered(expected, x, y),
+                    covered(actual, x, y),
+                    "({x}, {y}) in {actual:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_disjoint() {
+        let mut damage = DamageTracker::new();
+        let rects = [Rect::new(1, 1, 2, 2), Rect::new(10, 4, 3, 1)];
+        rects.iter().for_each(|&rect| damage.add(rect));
+        damage.add(Rect::new(5, 5, 0, 3));
+
+        let taken = damage.take();
+        assert_eq!(2, taken.len());
+        assert_covers(&rects, &taken);
+        // presenting clears the damage
+        assert!(damage.take().is_empty());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut damage = DamageTracker::new();
+        damage.add(Rect::new(0, 0, 4, 4));
+        damage.add(Rect::new(2, 2, 4, 4));
+        assert_eq!(vec![Rect::new(0, 0, 6, 6)], damage.take());
+
+        // rectangles that only touch are merged as well
+        damage.add(Rect::new(0, 0, 4, 4));
+        damage.add(Rect::new(4, 0, 2, 4));
+        assert_eq!(vec![Rect::new(0, 0, 6, 4)], damage.take());
+
+        // a rectangle that bridges two others merges all three, and the
+        // merged one then touches a fourth
+        damage.add(Rect::new(0, 0, 2, 4));
+        damage.add(Rect::new(10, 0, 2, 4));
+        damage.add(Rect::new(4, 3, 4, 2));
+        assert_eq!(3, damage.rects.len());
+        damage.add(Rect::new(2, 0, 8, 1));
+        assert_eq!(vec![Rect::new(0, 0, 12, 5)], damage.take());
+    }
+
+    #[test]
+    fn test_too_many_rects() {
+        let mut damage = DamageTracker::new();
+        // a grid of single pixels with gaps between them
+        let rects = (0..MAX_RECTS as i32)
+            .map(|i| Rect::new(i % 4 * 2, i / 4 * 2, 1, 1))
+            .collect::<Vec<_>>();
+        rects.iter().for_each(|&rect| damage.add(rect));
+        let taken = damage.take();
+        assert_eq!(MAX_RECTS, taken.len());
+        assert_covers(&rects, &taken);
+
+        // one more degrades to the bounding box, which is the whole screen
+        // when the damage is spread across it
+        rects.iter().for_each(|&rect| damage.add(rect));
+        damage.add(Rect::new(20, 20, 12, 12));
+        assert_eq!(vec![Rect::new(0, 0, 32, 32)], damage.take());
+    }
+}
@@ -0,0 +1,492 @@
+//! Positions and rectangles in pixels.
+//!
+//! Coordinates are signed, because things may be partly off the screen, like
+//! a window that was dragged over the edge, and whatever is drawn relative to
+//! it. Only what is clipped to the screen can be converted to the unsigned
+//! coordinates of the framebuffer.
+
+use core::cmp::{max, min};
+use core::ops::{Add, Mul, Neg, Sub};
+
+/// A signed integer that coordinates are in.
+pub trait Scalar:
+    Copy
+    + Default
+    + Ord
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Neg<Output = Self>
+{
+    const ZERO: Self;
+    const MAX: Self;
+
+    /// Converts the value, or returns [`Self::MAX`] if it's too large.
+    fn from_usize_saturating(value: usize) -> Self;
+
+    /// Converts the value, or returns zero if it's negative.
+    fn to_usize_clamped(self) -> usize;
+
+    fn saturating_add(self, rhs: Self) -> Self;
+
+    fn saturating_sub(self, rhs: Self) -> Self;
+}
+
+macro_rules! impl_scalar {
+    ($($t:ty),*) => {
+        $(
+            impl Scalar for $t {
+                const ZERO: Self = 0;
+                const MAX: Self = <$t>::MAX;
+
+                fn from_usize_saturating(value: usize) -> Self {
+                    Self::try_from(value).unwrap_or(Self::MAX)
+                }
+
+                fn to_usize_clamped(self) -> usize {
+                    usize::try_from(self).unwrap_or(0)
+                }
+
+                fn saturating_add(self, rhs: Self) -> Self {
+                    <$t>::saturating_add(self, rhs)
+                }
+
+                fn saturating_sub(self, rhs: Self) -> Self {
+                    <$t>::saturating_sub(self, rhs)
+                }
+            }
+        )*
+    };
+}
+
+impl_scalar!(i8, i16, i32, i64, isize);
+
+/// A position, or the offset between two positions.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Vec2<T: Scalar = i32> {
+    pub x: T,
+    pub y: T,
+}
+
+impl<T: Scalar> Vec2<T> {
+    pub const ZERO: Self = Self::new(T::ZERO, T::ZERO);
+
+    pub const fn new(x: T, y: T) -> Self {
+        Self { x, y }
+    }
+
+    /// Converts framebuffer coordinates, saturating at the largest value that
+    /// `T` can hold.
+    pub fn from_unsigned(x: usize, y: usize) -> Self {
+        Self::new(T::from_usize_saturating(x), T::from_usize_saturating(y))
+    }
+
+    /// Converts to framebuffer coordinates, clamping negative coordinates to
+    /// zero.
+    pub fn to_unsigned(self) -> (usize, usize) {
+        (self.x.to_usize_clamped(), self.y.to_usize_clamped())
+    }
+
+    /// The smaller of the coordinates of both, one axis at a time.
+    pub fn min(self, other: Self) -> Self {
+        Self::new(min(self.x, other.x), min(self.y, other.y))
+    }
+
+    /// The larger of the coordinates of both, one axis at a time.
+    pub fn max(self, other: Self) -> Self {
+        Self::new(max(self.x, other.x), max(self.y, other.y))
+    }
+}
+
+impl<T: Scalar> Add for Vec2<T> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y)
+    }
+}
+
+impl<T: Scalar> Sub for Vec2<T> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y)
+    }
+}
+
+impl<T: Scalar> Mul<T> for Vec2<T> {
+    type Output = Self;
+
+    fn mul(self, rhs: T) -> Self {
+        Self::new(self.x * rhs, self.y * rhs)
+    }
+}
+
+impl<T: Scalar> Neg for Vec2<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.x, -self.y)
+    }
+}
+
+/// A size in pixels, which can't be negative.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Extent {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Extent {
+    pub const ZERO: Self = Self::new(0, 0);
+
+    pub const fn new(width: usize, height: usize) -> Self {
+        Self { width, height }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The number of pixels.
+    pub const fn area(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// A rectangle, which covers the pixels from its origin up to, but not
+/// including, its origin plus its size.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct Rect<T: Scalar = i32> {
+    pub origin: Vec2<T>,
+    pub size: Extent,
+}
+
+impl<T: Scalar> Rect<T> {
+    pub const fn new(x: T, y: T, width: usize, height: usize) -> Self {
+        Self {
+            origin: Vec2::new(x, y),
+            size: Extent::new(width, height),
+        }
+    }
+
+    /// The smallest rectangle that contains both points.
+    pub fn bounding(a: Vec2<T>, b: Vec2<T>) -> Self {
+        let min = a.min(b);
+        let max = a.max(b);
+        Self::from_edges(
+            min.x,
+            min.y,
+            max.x.saturating_add(one()),
+            max.y.saturating_add(one()),
+        )
+    }
+
+    /// The rectangle between the edges, which is empty if they are the wrong
+    /// way around.
+    fn from_edges(left: T, top: T, right: T, bottom: T) -> Self {
+        let size = Extent::new(
+            right.saturating_sub(left).to_usize_clamped(),
+            bottom.saturating_sub(top).to_usize_clamped(),
+        );
+        Self {
+            origin: Vec2::new(left, top),
+            size: if size.is_empty() { Extent::ZERO } else { size },
+        }
+    }
+
+    pub fn left(&self) -> T {
+        self.origin.x
+    }
+
+    pub fn top(&self) -> T {
+        self.origin.y
+    }
+
+    /// The first column to the right of the rectangle.
+    pub fn right(&self) -> T {
+        self.origin
+            .x
+            .saturating_add(T::from_usize_saturating(self.size.width))
+    }
+
+    /// The first row below the rectangle.
+    pub fn bottom(&self) -> T {
+        self.origin
+            .y
+            .saturating_add(T::from_usize_saturating(self.size.height))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size.is_empty()
+    }
+
+    pub fn contains(&self, point: Vec2<T>) -> bool {
+        point.x >= self.left()
+            && point.y >= self.top()
+            && point.x < self.right()
+            && point.y < self.bottom()
+    }
+
+    /// The pixels that are in both rectangles. If there are none, the result
+    /// is empty, with its origin where the two come closest.
+    pub fn intersection(&self, other: &Self) -> Self {
+        Self::from_edges(
+            max(self.left(), other.left()),
+            max(self.top(), other.top()),
+            min(self.right(), other.right()),
+            min(self.bottom(), other.bottom()),
+        )
+    }
+
+    /// The smallest rectangle that contains both. An empty rectangle
+    /// contributes nothing, wherever it is.
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        Self::from_edges(
+            min(self.left(), other.left()),
+            min(self.top(), other.top()),
+            max(self.right(), other.right()),
+            max(self.bottom(), other.bottom()),
+        )
+    }
+
+    /// Whether the rectangles overlap or share an edge or a corner.
+    pub fn touches(&self, other: &Self) -> bool {
+        self.left() <= other.right()
+            && other.left() <= self.right()
+            && self.top() <= other.bottom()
+            && other.top() <= self.bottom()
+    }
+
+    /// The rectangle moved by the offset.
+    pub fn translate(&self, offset: Vec2<T>) -> Self {
+        Self {
+            origin: self.origin + offset,
+            size: self.size,
+        }
+    }
+
+    /// The part of the rectangle that is within `bounds`. Unlike with
+    /// [`Rect::intersection`], even an empty result has its origin within
+    /// `bounds` or on their right or bottom edge, so it's a valid position in
+    /// whatever `bounds` covers.
+    pub fn clamp_to(&self, bounds: &Self) -> Self {
+        let mut clamped = self.intersection(bounds);
+        clamped.origin = clamped
+            .origin
+            .max(bounds.origin)
+            .min(Vec2::new(bounds.right(), bounds.bottom()));
+        clamped
+    }
+}
+
+fn one<T: Scalar>() -> T {
+    T::from_usize_saturating(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every rectangle with its origin in a small area around zero, and a
+    /// size of up to three pixels in each direction.
+    fn rects() -> impl Iterator<Item = Rect> + Clone {
+        (-3..3).flat_map(|x| {
+            (-3..3).flat_map(move |y| {
+                (0..4)
+                    .flat_map(move |width| (0..4).map(move |height| Rect::new(x, y, width, height)))
+            })
+        })
+    }
+
+    /// Every point that any of the rectangles can cover, and one around it.
+    fn points() -> impl Iterator<Item = Vec2> {
+        (-4..7).flat_map(|x| (-4..7).map(move |y| Vec2::new(x, y)))
+    }
+
+    #[test]
+    fn test_vec2_operators() {
+        let a = Vec2::new(3, -4);
+        let b = Vec2::new(-5, 2);
+        assert_eq!(Vec2::new(-2, -2), a + b);
+        assert_eq!(Vec2::new(8, -6), a - b);
+        assert_eq!(Vec2::new(-9, 12), a * -3);
+        assert_eq!(Vec2::new(-3, 4), -a);
+        assert_eq!(Vec2::new(-5, -4), a.min(b));
+        assert_eq!(Vec2::new(3, 2), a.max(b));
+        assert_eq!(Vec2::<i64>::new(1, 2), Vec2::new(1_i64, 2_i64));
+    }
+
+    #[test]
+    fn test_vec2_unsigned() {
+        assert_eq!((3, 0), Vec2::new(3, -4).to_unsigned());
+        assert_eq!((0, 0), Vec2::new(i32::MIN, -1).to_unsigned());
+        assert_eq!(Vec2::new(7, 9), Vec2::<i32>::from_unsigned(7, 9));
+        assert_eq!(
+            Vec2::new(i32::MAX, 1),
+            Vec2::<i32>::from_unsigned(usize::MAX, 1)
+        );
+        assert_eq!(
+            Vec2::new(i8::MAX, i8::MAX),
+            Vec2::<i8>::from_unsigned(128, 1000)
+        );
+    }
+
+    #[test]
+    fn test_rect_edges() {
+        let rect = Rect::new(-2, 3, 4, 5);
+        assert_eq!(-2, rect.left());
+        assert_eq!(3, rect.top());
+        assert_eq!(2, rect.right());
+        assert_eq!(8, rect.bottom());
+        assert!(!rect.is_empty());
+        assert!(Rect::new(1, 1, 0, 5).is_empty());
+        assert!(Rect::new(1, 1, 5, 0).is_empty());
+        // the edges saturate instead of overflowing
+        assert_eq!(i32::MAX, Rect::new(i32::MAX - 1, 0, 10, 1).right());
+        assert_eq!(i32::MAX, Rect::new(0, 0, 1, usize::MAX).bottom());
+    }
+
+    #[test]
+    fn test_rect_bounding() {
+        assert_eq!(
+            Rect::new(-3, -1, 6, 4),
+            Rect::bounding(Vec2::new(2, -1), Vec2::new(-3, 2))
+        );
+        assert_eq!(
+            Rect::new(4, 4, 1, 1),
+            Rect::bounding(Vec2::new(4, 4), Vec2::new(4, 4))
+        );
+    }
+
+    #[test]
+    fn test_rect_contains() {
+        for rect in rects() {
+            for point in points() {
+                let inside = (rect.left()..rect.right()).contains(&point.x)
+                    && (rect.top()..rect.bottom()).contains(&point.y);
+                assert_eq!(inside, rect.contains(point), "{rect:?} {point:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_intersection() {
+        for a in rects() {
+            for b in rects() {
+                let intersection = a.intersection(&b);
+                assert_eq!(intersection, b.intersection(&a));
+                for point in points() {
+                    assert_eq!(
+                        a.contains(point) && b.contains(point),
+                        intersection.contains(point),
+                        "{a:?} {b:?} {point:?}"
+                    );
+                }
+                if intersection.is_empty() {
+                    assert_eq!(Extent::ZERO, intersection.size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_empty_intersection() {
+        let a = Rect::new(-10, -10, 5, 5);
+        let b = Rect::new(0, 0, 5, 5);
+        let intersection = a.intersection(&b);
+        assert!(intersection.is_empty());
+        assert_eq!(Vec2::new(0, 0), intersection.origin);
+        // rectangles that only share an edge have no pixel in common
+        assert!(Rect::new(0, 0, 2, 2)
+            .intersection(&Rect::new(2, 0, 2, 2))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_rect_union() {
+        for a in rects() {
+            for b in rects() {
+                let union = a.union(&b);
+                for point in points() {
+                    if a.contains(point) || b.contains(point) {
+                        assert!(union.contains(point), "{a:?} {b:?} {point:?}");
+                    }
+                }
+                match (a.is_empty(), b.is_empty()) {
+                    (true, true) => assert!(union.is_empty()),
+                    (true, false) => assert_eq!(b, union),
+                    (false, true) => assert_eq!(a, union),
+                    (false, false) => {
+                        assert_eq!(union, b.union(&a));
+                        // the union is the bounding box, and not any larger
+                        assert_eq!(min(a.left(), b.left()), union.left());
+                        assert_eq!(min(a.top(), b.top()), union.top());
+                        assert_eq!(max(a.right(), b.right()), union.right());
+                        assert_eq!(max(a.bottom(), b.bottom()), union.bottom());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_touches() {
+        for a in rects().filter(|r| !r.is_empty()) {
+            for b in rects().filter(|r| !r.is_empty()) {
+                // grown by a pixel in every direction, a rectangle overlaps
+                // everything that it touches
+                let grown = Rect::new(
+                    a.left() - 1,
+                    a.top() - 1,
+                    a.size.width + 2,
+                    a.size.height + 2,
+                );
+                let overlaps_grown = !grown.intersection(&b).is_empty();
+                assert_eq!(overlaps_grown, a.touches(&b), "{a:?} {b:?}");
+                assert_eq!(a.touches(&b), b.touches(&a));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rect_translate() {
+        let rect = Rect::new(1, 2, 3, 4);
+        assert_eq!(Rect::new(-4, 9, 3, 4), rect.translate(Vec2::new(-5, 7)));
+        assert_eq!(rect, rect.translate(Vec2::ZERO));
+        assert_eq!(
+            rect,
+            rect.translate(Vec2::new(-5, 7))
+                .translate(-Vec2::new(-5, 7))
+        );
+    }
+
+    #[test]
+    fn test_rect_clamp_to() {
+        let bounds = Rect::new(0, 0, 3, 3);
+        for rect in rects() {
+            let clamped = rect.clamp_to(&bounds);
+            assert_eq!(rect.intersection(&bounds).size, clamped.size, "{rect:?}");
+            if !clamped.is_empty() {
+                assert_eq!(rect.intersection(&bounds), clamped);
+            }
+            assert!((0..=3).contains(&clamped.left()), "{rect:?}");
+            assert!((0..=3).contains(&clamped.top()), "{rect:?}");
+            assert!(clamped.right() <= 3 && clamped.bottom() <= 3, "{rect:?}");
+        }
+
+        // a window dragged past the top left corner of the screen
+        let window = Rect::new(-20, -10, 50, 30);
+        let screen = Rect::new(0, 0, 640, 480);
+        assert_eq!(Rect::new(0, 0, 30, 20), window.clamp_to(&screen));
+        // and one that is entirely off the screen
+        let window = Rect::new(700, -100, 50, 30);
+        assert_eq!(Rect::new(640, 0, 0, 0), window.clamp_to(&screen));
+    }
+}
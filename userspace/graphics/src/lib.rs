@@ -0,0 +1,16 @@
+#![no_std]
+
+extern crate alloc;
+
+pub use canvas::Canvas;
+pub use damage::DamageTracker;
+pub use geometry::*;
+pub use overlay::Overlay;
+pub use present::FrontBuffer;
+
+mod canvas;
+pub mod color;
+mod damage;
+mod geometry;
+mod overlay;
+mod present;
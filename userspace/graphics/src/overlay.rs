@@ -0,0 +1,275 @@
+//! An image that floats above everything else on the screen, like a mouse
+//! cursor.
+//!
+//! The overlay is never drawn into the back buffer. It's composited onto the
+//! rows of pixels on their way to the framebuffer, so the pixels below it
+//! don't need to be saved and restored, and reading the back buffer never
+//! shows it. When it changes, only the rectangles that it left and that it
+//! covers now need to be presented again.
+
+use alloc::vec::Vec;
+
+use crate::color::{blend_row, Argb8888};
+use crate::{Extent, Rect, Vec2};
+
+pub struct Overlay {
+    /// The ARGB pixels, `size.width` per row.
+    image: Vec<u32>,
+    size: Extent,
+    /// Where the top left corner of the image is on the screen.
+    position: Vec2,
+    visible: bool,
+}
+
+impl Default for Overlay {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Overlay {
+    /// An overlay without an image, which is hidden.
+    pub const fn new() -> Self {
+        Self {
+            image: Vec::new(),
+            size: Extent::ZERO,
+            position: Vec2::ZERO,
+            visible: false,
+        }
+    }
+
+    /// The part of the screen that the overlay covers, which is empty while
+    /// it's hidden.
+    pub fn bounds(&self) -> Rect {
+        if !self.visible {
+            return Rect::default();
+        }
+        Rect {
+            origin: self.position,
+            size: self.size,
+        }
+    }
+
+    /// Replaces the image, which has `size.width` pixels per row, and shows
+    /// the overlay. Returns the rectangles that have to be presented again.
+    ///
+    /// # Panics
+    /// Panics if the image doesn't have exactly as many pixels as `size`.
+    pub fn set_image(&mut self, image: &[Argb8888], size: Extent) -> [Rect; 2] {
+        assert_eq!(size.area(), image.len(), "image doesn't match its size");
+        let before = self.bounds();
+        self.image.clear();
+        self.image.extend(image.iter().map(|color| color.0));
+        self.size = size;
+        self.visible = true;
+        [before, self.bounds()]
+    }
+
+    /// Moves the top left corner of the image to `position`, which may be
+    /// off the screen. Returns the rectangles that have to be presented
+    /// again, which are empty if nothing visibly changed.
+    pub fn move_to(&mut self, position: Vec2) -> [Rect; 2] {
+        let before = self.bounds();
+        self.position = position;
+        self.damage_since(before)
+    }
+
+    /// Shows or hides the overlay. Returns the rectangles that have to be
+    /// presented again, which are empty if nothing visibly changed.
+    pub fn set_visible(&mut self, visible: bool) -> [Rect; 2] {
+        let before = self.bounds();
+        self.visible = visible;
+        self.damage_since(before)
+    }
+
+    fn damage_since(&self, before: Rect) -> [Rect; 2] {
+        let after = self.bounds();
+        if before == after {
+            return [Rect::default(); 2];
+        }
+        [before, after]
+    }
+
+    /// Composites the overlay onto the row of screen pixels whose first pixel
+    /// is at `at`, as far as it covers the row.
+    pub fn composite(&self, at: Vec2, row: &mut [u32]) {
+        let row_rect = Rect {
+            origin: at,
+            size: Extent::new(row.len(), 1),
+        };
+        let covered = self.bounds().intersection(&row_rect);
+        if covered.is_empty() {
+            return;
+        }
+        let (image_x, image_y) = (covered.origin - self.position).to_unsigned();
+        let (row_x, _) = (covered.origin - at).to_unsigned();
+        let width = covered.size.width;
+        let start = image_y * self.size.width + image_x;
+        blend_row(
+            &self.image[start..start + width],
+            &mut row[row_x..row_x + width],
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::color::blend_pixel;
+
+    const SCREEN: Rect = Rect::new(0, 0, 16, 12);
+
+    /// A small screen that presents the way the window server does: the
+    /// damaged rows of the back buffer are composited with the overlay and
+    /// copied to the front buffer.
+    struct Screen {
+        back: Vec<u32>,
+        front: Vec<u32>,
+        overlay: Overlay,
+    }
+
+    impl Screen {
+        fn new() -> Self {
+            // every pixel is different, so a pixel that ends up in the wrong
+            // place is noticed
+            let back = (0..SCREEN.size.area() as u32)
+                .map(|i| i.wrapping_mul(0x0009_1E37) & 0x00FF_FFFF)
+                .collect::<Vec<_>>();
+            Self {
+                front: back.clone(),
+                back,
+                overlay: Overlay::new(),
+            }
+        }
+
+        fn present(&mut self, damage: [Rect; 2]) {
+            let width = SCREEN.size.width;
+            for rect in damage.map(|rect| rect.clamp_to(&SCREEN)) {
+                let (x, y) = rect.origin.to_unsigned();
+                for y in y..y + rect.size.height {
+                    let start = y * width + x;
+                    let mut row = self.back[start..start + rect.size.width].to_vec();
+                    self.overlay.composite(Vec2::from_unsigned(x, y), &mut row);
+                    self.front[start..start + row.len()].copy_from_slice(&row);
+                }
+            }
+        }
+
+        /// Checks every pixel of the front buffer, computed one pixel at a
+        /// time rather than a row at a time.
+        fn assert_shows_overlay(&self) {
+            let bounds = self.overlay.bounds();
+            for (i, (&back, &front)) in self.back.iter().zip(&self.front).enumerate() {
+                let point = Vec2::from_unsigned(i % SCREEN.size.width, i / SCREEN.size.width);
+                let mut expected = back;
+                if bounds.contains(point) {
+                    let (x, y) = (point - bounds.origin).to_unsigned();
+                    let color = self.overlay.image[y * bounds.size.width + x];
+                    blend_pixel(Argb8888(color), &mut expected);
+                }
+                assert_eq!(expected, front, "{point:?} with the overlay at {bounds:?}");
+            }
+        }
+    }
+
+    /// An arrow-like image with opaque, transparent and translucent pixels.
+    fn cursor() -> (Vec<Argb8888>, Extent) {
+        let size = Extent::new(4, 3);
+        let image = (0..size.area())
+            .map(|i| match (i % 4, i / 4) {
+                (x, y) if x < y => Argb8888::new(0x80, 0xFF, 0x00, 0x40),
+                (x, y) if x == y => Argb8888::new(0xFF, 0xFF, 0xFF, 0xFF),
+                _ => Argb8888::TRANSPARENT,
+            })
+            .collect();
+        (image, size)
+    }
+
+    #[test]
+    fn test_overlay_moves_across_background() {
+        let mut screen = Screen::new();
+        let background = screen.back.clone();
+        let (image, size) = cursor();
+        let damage = screen.overlay.set_image(&image, size);
+        screen.present(damage);
+        screen.assert_shows_overlay();
+
+        // across the whole screen and past every edge, in steps that overlap
+        // the previous position and in steps that don't
+        let positions = (-5..18).step_by(3).flat_map(|y| {
+            (-6..20)
+                .step_by(if y % 2 == 0 { 1 } else { 5 })
+                .map(move |x| (x, y))
+        });
+        for (x, y) in positions {
+            let damage = screen.overlay.move_to(Vec2::new(x, y));
+            screen.present(damage);
+            screen.assert_shows_overlay();
+            assert_eq!(background, screen.back);
+        }
+
+        let damage = screen.overlay.set_visible(false);
+        screen.present(damage);
+        assert_eq!(background, screen.front);
+    }
+
+    #[test]
+    fn test_overlay_damage() {
+        let mut overlay = Overlay::new();
+        let (image, size) = cursor();
+        let nothing = [Rect::default(); 2];
+
+        assert_eq!(nothing, overlay.move_to(Vec2::new(3, 3)));
+        assert_eq!(
+            [Rect::default(), Rect::new(3, 3, 4, 3)],
+            overlay.set_image(&image, size)
+        );
+        assert_eq!(
+            [Rect::new(3, 3, 4, 3), Rect::new(-2, 5, 4, 3)],
+            overlay.move_to(Vec2::new(-2, 5))
+        );
+        assert_eq!(nothing, overlay.move_to(Vec2::new(-2, 5)));
+        assert_eq!(nothing, overlay.set_visible(true));
+        assert_eq!(
+            [Rect::new(-2, 5, 4, 3), Rect::default()],
+            overlay.set_visible(false)
+        );
+        assert_eq!(nothing, overlay.set_visible(false));
+        // moving while hidden changes nothing on the screen
+        assert_eq!(nothing, overlay.move_to(Vec2::new(0, 0)));
+        assert_eq!(
+            [Rect::default(), Rect::new(0, 0, 4, 3)],
+            overlay.set_visible(true)
+        );
+    }
+
+    #[test]
+    fn test_overlay_composite_clipped() {
+        let mut overlay = Overlay::new();
+        let image = [0xFF00_0001, 0xFF00_0002, 0xFF00_0003, 0xFF00_0004].map(Argb8888);
+        overlay.set_image(&image, Extent::new(2, 2));
+        overlay.move_to(Vec2::new(-1, 0));
+
+        let mut row = vec![0; 3];
+        overlay.composite(Vec2::new(0, 1), &mut row);
+        assert_eq!(vec![4, 0, 0], row);
+
+        let mut row = vec![0; 3];
+        overlay.composite(Vec2::new(-2, 0), &mut row);
+        assert_eq!(vec![0, 1, 2], row);
+
+        let mut row = vec![0; 3];
+        overlay.composite(Vec2::new(0, 2), &mut row);
+        assert_eq!(vec![0; 3], row);
+    }
+
+    #[test]
+    #[should_panic = "image doesn't match its size"]
+    fn test_overlay_size_mismatch() {
+        let (image, _) = cursor();
+        Overlay::new().set_image(&image, Extent::new(3, 3));
+    }
+}
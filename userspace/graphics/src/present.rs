@@ -0,0 +1,301 @@
+//! Copying what was drawn into a canvas to the framebuffer.
+//!
+//! The framebuffer is mapped uncached, so presenting copies whole rows, which
+//! the CPU can write in bursts, instead of single pixels, and only the rows of
+//! the rectangles that were drawn to since the last present. The pixels are
+//! converted into the format of the framebuffer on the way, and the overlay is
+//! composited onto them.
+
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+
+use kernel_api::pixel::{Bgrx8888Writer, PixelFormat, PixelWriter, Rgb565Writer, Rgbx8888Writer};
+
+use crate::{Canvas, Overlay, Rect, Vec2};
+
+/// The pixels of a framebuffer, in any buffer that can be borrowed as a
+/// slice of bytes, like a mapping of the framebuffer.
+pub struct FrontBuffer<B> {
+    pixels: B,
+    width: usize,
+    height: usize,
+    /// The number of bytes between the starts of two rows, which may be more
+    /// than the visible width.
+    pitch: usize,
+    format: PixelFormat,
+    /// A row of converted pixels, before it's copied to the framebuffer.
+    row: Vec<u8>,
+    /// A row of the canvas with the overlay composited onto it.
+    composited: Vec<u32>,
+}
+
+impl<B: DerefMut<Target = [u8]>> FrontBuffer<B> {
+    /// Creates the front buffer for `height` rows of `width` pixels, which
+    /// start `pitch` bytes apart. The bytes between the end of a row and the
+    /// start of the next one are never written.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a row doesn't fit into the pitch, or if the buffer doesn't
+    /// hold exactly `height` rows.
+    pub fn new(pixels: B, width: usize, height: usize, pitch: usize, format: PixelFormat) -> Self {
+        assert!(pitch >= width * format.bytes_per_pixel());
+        assert_eq!(pitch * height, pixels.len());
+        Self {
+            pixels,
+            width,
+            height,
+            pitch,
+            format,
+            row: vec![0; width * format.bytes_per_pixel()],
+            composited: vec![0; width],
+        }
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// The area of the framebuffer.
+    pub fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width, self.height)
+    }
+
+    /// Copies the rectangles of the canvas that were drawn to since the last
+    /// present, with the overlay on top. Returns how many pixels were copied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the canvas doesn't have the size of the framebuffer.
+    pub fn present<C: DerefMut<Target = [u32]>>(
+        &mut self,
+        back: &mut Canvas<C>,
+        overlay: &Overlay,
+    ) -> usize {
+        assert_eq!(self.bounds(), back.bounds());
+        match self.format {
+            PixelFormat::Bgrx8888 => self.present_with::<Bgrx8888Writer, C>(back, overlay),
+            PixelFormat::Rgbx8888 => self.present_with::<Rgbx8888Writer, C>(back, overlay),
+            PixelFormat::Rgb565 => self.present_with::<Rgb565Writer, C>(back, overlay),
+        }
+    }
+
+    fn present_with<W: PixelWriter, C: DerefMut<Target = [u32]>>(
+        &mut self,
+        back: &mut Canvas<C>,
+        overlay: &Overlay,
+    ) -> usize {
+        let mut copied = 0;
+        let covered = overlay.bounds();
+        for rect in back.take_damage() {
+            let (x, y) = rect.origin.to_unsigned();
+            let len = rect.size.width * W::BYTES_PER_PIXEL;
+            for (y, row) in (y..).zip(back.rows(rect)) {
+                let at = Vec2::from_unsigned(x, y);
+                let row = if (covered.top()..covered.bottom()).contains(&at.y) {
+                    let composited = &mut self.composited[..row.len()];
+                    composited.copy_from_slice(row);
+                    overlay.composite(at, composited);
+                    composited
+                } else {
+                    row
+                };
+                W::write_row(row, &mut self.row[..len]);
+                let start = y * self.pitch + x * W::BYTES_PER_PIXEL;
+                self.pixels[start..start + len].copy_from_slice(&self.row[..len]);
+            }
+            copied += rect.size.area();
+        }
+        copied
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::color::{blend_pixel, Argb8888};
+    use crate::Extent;
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 12;
+    /// What the front buffer is filled with before anything is presented.
+    const UNTOUCHED: u8 = 0xAA;
+
+    /// A canvas in which every pixel is different, so a pixel that ends up in
+    /// the wrong place is noticed.
+    fn canvas() -> Canvas<Vec<u32>> {
+        let pixels = (0..(WIDTH * HEIGHT) as u32)
+            .map(|i| i.wrapping_mul(0x0009_1E37) & 0x00FF_FFFF)
+            .collect();
+        Canvas::new(pixels, WIDTH, HEIGHT)
+    }
+
+    fn front(pitch: usize, format: PixelFormat) -> FrontBuffer<Vec<u8>> {
+        FrontBuffer::new(
+            vec![UNTOUCHED; pitch * HEIGHT],
+            WIDTH,
+            HEIGHT,
+            pitch,
+            format,
+        )
+    }
+
+    fn write_pixel(format: PixelFormat, color: u32, dst: &mut [u8]) {
+        match format {
+            PixelFormat::Bgrx8888 => Bgrx8888Writer::write_row(&[color], dst),
+            PixelFormat::Rgbx8888 => Rgbx8888Writer::write_row(&[color], dst),
+            PixelFormat::Rgb565 => Rgb565Writer::write_row(&[color], dst),
+        }
+    }
+
+    /// Checks every byte of the front buffer, computed one pixel at a time:
+    /// the pixels in `damage` show the canvas with the overlay on top, and
+    /// every other byte, including the padding at the end of the rows, is
+    /// untouched.
+    fn assert_presented(
+        front: &FrontBuffer<Vec<u8>>,
+        back: &Canvas<Vec<u32>>,
+        overlay: &Overlay,
+        damage: &[Rect],
+    ) {
+        let bytes_per_pixel = front.format.bytes_per_pixel();
+        let mut expected = vec![UNTOUCHED; front.pixels().len()];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let point = Vec2::from_unsigned(x, y);
+                if !damage.iter().any(|rect| rect.contains(point)) {
+                    continue;
+                }
+                let mut color = back.pixels()[y * WIDTH + x];
+                overlay.composite(point, core::slice::from_mut(&mut color));
+                let start = y * front.pitch + x * bytes_per_pixel;
+                write_pixel(
+                    front.format,
+                    color,
+                    &mut expected[start..start + bytes_per_pixel],
+                );
+            }
+        }
+        for (i, (expected, actual)) in expected.iter().zip(front.pixels()).enumerate() {
+            let (y, x) = (i / front.pitch, i % front.pitch);
+            assert_eq!(expected, actual, "byte {x} of row {y}, damage {damage:?}");
+        }
+    }
+
+    #[test]
+    fn test_present_only_damage() {
+        let mut back = canvas();
+        let mut front = front(WIDTH * 4, PixelFormat::Bgrx8888);
+        let overlay = Overlay::new();
+
+        let damage = [Rect::new(2, 1, 5, 3), Rect::new(9, 6, 4, 4)];
+        damage.iter().for_each(|&rect| back.damage(rect));
+        assert_eq!(15 + 16, front.present(&mut back, &overlay));
+        assert_presented(&front, &back, &overlay, &damage);
+
+        // nothing is copied again without new damage
+        assert_eq!(0, front.present(&mut back, &overlay));
+        assert_presented(&front, &back, &overlay, &damage);
+    }
+
+    #[test]
+    fn test_present_padded_pitch() {
+        for format in [
+            PixelFormat::Bgrx8888,
+            PixelFormat::Rgbx8888,
+            PixelFormat::Rgb565,
+        ] {
+            let mut back = canvas();
+            // the rows are 6 bytes apart from the end of one to the start of
+            // the next, which is not a whole pixel in any format
+            let pitch = WIDTH * format.bytes_per_pixel() + 6;
+            let mut front = front(pitch, format);
+            let overlay = Overlay::new();
+
+            let damage = [back.bounds()];
+            back.damage(back.bounds());
+            assert_eq!(WIDTH * HEIGHT, front.present(&mut back, &overlay));
+            assert_presented(&front, &back, &overlay, &damage);
+        }
+    }
+
+    #[test]
+    fn test_present_overlay_across_damage_edge() {
+        let mut back = canvas();
+        let mut front = front(WIDTH * 4 + 8, PixelFormat::Bgrx8888);
+        let mut overlay = Overlay::new();
+        let image = (0..12)
+            .map(|i| Argb8888::new(if i % 3 == 0 { 0xFF } else { 0x80 }, 0xFF, i * 20, 0x40))
+            .collect::<Vec<_>>();
+        // the damage that showing the overlay causes is ignored, so that only
+        // the rectangle below is presented
+        overlay.set_image(&image, Extent::new(4, 3));
+        overlay.move_to(Vec2::new(5, 3));
+
+        // the overlay covers x 5..9 and y 3..6, the damage ends at x 7 and
+        // y 5, so only the top left part of the overlay is presented
+        let damage = [Rect::new(0, 0, 7, 5)];
+        back.damage(damage[0]);
+        assert_eq!(35, front.present(&mut back, &overlay));
+        assert_presented(&front, &back, &overlay, &damage);
+
+        // a pixel below the overlay really is blended
+        let mut expected = back.pixels()[3 * WIDTH + 6];
+        blend_pixel(image[1], &mut expected);
+        let start = 3 * front.pitch + 6 * 4;
+        assert_eq!(
+            &expected.to_le_bytes()[..3],
+            &front.pixels()[start..start + 3]
+        );
+    }
+
+    /// Drawing many rectangles and presenting once copies less than presenting
+    /// after each of them, and both end up with the same front buffer as
+    /// presenting the whole screen.
+    #[test]
+    fn test_present_many_rects() {
+        const COUNT: usize = 1000;
+        let rect = |i: usize| Rect::new((i * 7 % 19) as i32 - 2, (i * 5 % 13) as i32 - 2, 3, 4);
+        let overlay = Overlay::new();
+
+        let mut back = canvas();
+        let mut each = front(WIDTH * 4, PixelFormat::Bgrx8888);
+        let mut copied_each = 0;
+        for i in 0..COUNT {
+            back.fill_rect(rect(i), i as u32);
+            copied_each += each.present(&mut back, &overlay);
+        }
+
+        let mut back = canvas();
+        let mut once = front(WIDTH * 4, PixelFormat::Bgrx8888);
+        for i in 0..COUNT {
+            back.fill_rect(rect(i), i as u32);
+        }
+        let copied_once = once.present(&mut back, &overlay);
+        assert!(
+            copied_once <= WIDTH * HEIGHT && copied_once < copied_each,
+            "presenting once copied {copied_once} pixels, presenting each {copied_each}"
+        );
+
+        let mut whole = front(WIDTH * 4, PixelFormat::Bgrx8888);
+        back.damage(back.bounds());
+        whole.present(&mut back, &overlay);
+        let touched = |front: &FrontBuffer<Vec<u8>>| {
+            front
+                .pixels()
+                .as_chunks::<4>()
+                .0
+                .iter()
+                .zip(whole.pixels().as_chunks::<4>().0)
+                .filter(|(pixel, _)| **pixel != [UNTOUCHED; 4])
+                .all(|(pixel, whole)| pixel == whole)
+        };
+        assert!(touched(&each));
+        assert!(touched(&once));
+        assert_eq!(each.pixels(), once.pixels());
+    }
+}
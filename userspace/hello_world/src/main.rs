@@ -3,26 +3,25 @@
 
 extern crate alloc;
 
-use alloc::vec;
 use alloc::vec::Vec;
 
-use std::syscall::{sys_close, sys_exit, sys_open, sys_read, Errno};
-use std::{println, rt};
+use std::syscall::sys_exit;
+use std::{fs, io, println, rt};
 
 #[no_mangle]
-pub fn _start() -> isize {
-    rt::start();
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
 
     main();
 
     sys_exit(0);
 }
 
-fn must(errno: Errno) -> usize {
-    if errno.as_isize() < 0 {
-        sys_exit(-errno.as_isize());
+fn must<T>(result: io::Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => sys_exit(e.errno().map_or(1, |errno| errno.code() as isize)),
     }
-    errno.as_isize() as usize
 }
 
 fn main() {
@@ -31,14 +30,6 @@ fn main() {
         v.push(i);
     }
 
-    let greeting = must(sys_open("/var/data/hello.txt", 0, 0));
-    let mut data = vec![0_u8; 13];
-    let n_read = must(sys_read(greeting, &mut data[0..13]));
-    assert_eq!(n_read, 13);
-    println!(
-        "hello.txt contained: '{}'",
-        core::str::from_utf8(&data).unwrap()
-    );
-
-    sys_close(greeting);
+    let greeting = must(fs::read_to_string("/var/data/hello.txt"));
+    println!("hello.txt contained: '{}'", greeting);
 }
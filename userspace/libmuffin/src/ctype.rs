@@ -0,0 +1,170 @@
+//! `ctype.h`.
+//!
+//! The classes of the characters, and their upper and lower case, come from
+//! the [current](crate::locale::current) locale. Every function takes an
+//! `unsigned char` or `EOF`, which isn't in any class.
+
+use core::ffi::c_int;
+
+use crate::locale::current;
+
+/// A character class of `ctype.h`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Class {
+    Alnum,
+    Alpha,
+    Blank,
+    Cntrl,
+    Digit,
+    Graph,
+    Lower,
+    Print,
+    Punct,
+    Space,
+    Upper,
+    Xdigit,
+}
+
+fn is(c: c_int, class: Class) -> c_int {
+    c_int::from(current().is(c, class))
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isalnum.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isalnum(c: c_int) -> c_int {
+    is(c, Class::Alnum)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isalpha.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isalpha(c: c_int) -> c_int {
+    is(c, Class::Alpha)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isblank.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isblank(c: c_int) -> c_int {
+    is(c, Class::Blank)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/iscntrl.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn iscntrl(c: c_int) -> c_int {
+    is(c, Class::Cntrl)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isdigit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isdigit(c: c_int) -> c_int {
+    is(c, Class::Digit)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isgraph.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isgraph(c: c_int) -> c_int {
+    is(c, Class::Graph)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/islower.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn islower(c: c_int) -> c_int {
+    is(c, Class::Lower)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isprint.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isprint(c: c_int) -> c_int {
+    is(c, Class::Print)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/ispunct.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn ispunct(c: c_int) -> c_int {
+    is(c, Class::Punct)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isspace.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isspace(c: c_int) -> c_int {
+    is(c, Class::Space)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isupper.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isupper(c: c_int) -> c_int {
+    is(c, Class::Upper)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/isxdigit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn isxdigit(c: c_int) -> c_int {
+    is(c, Class::Xdigit)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/tolower.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn tolower(c: c_int) -> c_int {
+    current().to_lower(c)
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/toupper.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn toupper(c: c_int) -> c_int {
+    current().to_upper(c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stdio::EOF;
+
+    #[test]
+    fn test_classes() {
+        /// The name of the class, its predicate, its size, some of its
+        /// members and some others.
+        type Class = (
+            &'static str,
+            extern "C" fn(c_int) -> c_int,
+            usize,
+            &'static [u8],
+            &'static [u8],
+        );
+        let classes: [Class; 12] = [
+            ("alnum", isalnum, 62, b"09azAZ", b" _-"),
+            ("alpha", isalpha, 52, b"azAZ", b"09 _"),
+            ("blank", isblank, 2, b" \t", b"\n\r"),
+            ("cntrl", iscntrl, 33, b"\0\t\n\x1f\x7f", b" a~"),
+            ("digit", isdigit, 10, b"0123456789", b"a/:"),
+            ("graph", isgraph, 94, b"!09az~", b" \t\x7f"),
+            ("lower", islower, 26, b"az", b"AZ09"),
+            ("print", isprint, 95, b" !09az~", b"\t\x7f"),
+            ("punct", ispunct, 32, b"!/:@[`{~", b" 09azAZ"),
+            ("space", isspace, 6, b" \t\n\x0b\x0c\r", b"\0a"),
+            ("upper", isupper, 26, b"AZ", b"az09"),
+            ("xdigit", isxdigit, 22, b"09afAF", b"gG "),
+        ];
+        for (name, predicate, size, members, others) in classes {
+            let is = |c: u8| predicate(c_int::from(c)) != 0;
+            assert_eq!(size, (0..=255).filter(|&c| is(c)).count(), "size of {name}");
+            for &c in members {
+                assert!(is(c), "{c:#x} is in {name}");
+            }
+            for &c in others {
+                assert!(!is(c), "{c:#x} is not in {name}");
+            }
+            assert!(!is(0x80) && !is(0xff), "only ASCII is in {name}");
+            assert_eq!(0, predicate(EOF), "EOF is not in {name}");
+        }
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(c_int::from(b'a'), tolower(c_int::from(b'A')));
+        assert_eq!(c_int::from(b'a'), tolower(c_int::from(b'a')));
+        assert_eq!(c_int::from(b'Z'), toupper(c_int::from(b'z')));
+        assert_eq!(c_int::from(b'1'), toupper(c_int::from(b'1')));
+        assert_eq!(0xc4, tolower(0xc4));
+        assert_eq!(EOF, tolower(EOF));
+        assert_eq!(EOF, toupper(EOF));
+    }
+}
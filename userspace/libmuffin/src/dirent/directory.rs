@@ -0,0 +1,284 @@
+//! The buffering behind a `DIR`.
+//!
+//! The kernel only ever writes whole entries, so an entry is never split
+//! between two refills of the buffer. A refill that returns nothing is the
+//! end of the directory.
+
+use core::ffi::c_char;
+
+use kernel_api::syscall::{Dirent, Errno};
+use kernel_api::NAME_MAX;
+
+use crate::dirent::dirent;
+
+/// The size of the buffer that `getdents` fills.
+const BUF_SIZE: usize = 4096;
+
+/// What a directory reads its entries from, which is a file descriptor
+/// outside of tests.
+pub trait Backend {
+    /// Fills `buf` with whole entries, and returns how many bytes that took,
+    /// which is 0 at the end of the directory.
+    fn getdents(&mut self, buf: &mut [u8]) -> Result<usize, Errno>;
+
+    /// Continues reading at `position`, which is 0 or the
+    /// [`Dirent::off`] of an entry.
+    fn seek(&mut self, position: u64) -> Result<(), Errno>;
+
+    fn close(&mut self) -> Result<(), Errno>;
+}
+
+pub struct Directory<B> {
+    backend: B,
+    buf: [u8; BUF_SIZE],
+    /// `buf[pos..end]` holds the entries that weren't returned yet.
+    pos: usize,
+    end: usize,
+    /// The position after the entry that was returned last.
+    position: u64,
+    entry: dirent,
+}
+
+impl<B: Backend> Directory<B> {
+    pub const fn new(backend: B) -> Self {
+        Self {
+            backend,
+            buf: [0; BUF_SIZE],
+            pos: 0,
+            end: 0,
+            position: 0,
+            entry: dirent {
+                d_ino: 0,
+                d_off: 0,
+                d_reclen: 0,
+                d_type: 0,
+                d_name: [0; NAME_MAX + 1],
+            },
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// The next entry, or [`None`] at the end of the directory. Fails with
+    /// [`Errno::EOVERFLOW`] for an entry whose name is longer than
+    /// [`NAME_MAX`], which is skipped.
+    pub fn read(&mut self) -> Result<Option<&dirent>, Errno> {
+        if self.pos == self.end {
+            self.end = self.backend.getdents(&mut self.buf)?;
+            self.pos = 0;
+            if self.end == 0 {
+                return Ok(None);
+            }
+        }
+
+        let (header, name) = Dirent::read(&self.buf[self.pos..self.end]).ok_or(Errno::EIO)?;
+        self.pos += usize::from(header.reclen);
+        self.position = header.off;
+        if name.len() > NAME_MAX {
+            return Err(Errno::EOVERFLOW);
+        }
+        self.entry.d_ino = header.ino;
+        self.entry.d_off = header.off as i64;
+        self.entry.d_reclen = header.reclen;
+        self.entry.d_type = header.typ;
+        for (dst, &src) in self.entry.d_name.iter_mut().zip(name) {
+            *dst = src as c_char;
+        }
+        self.entry.d_name[name.len()] = 0;
+        Ok(Some(&self.entry))
+    }
+
+    /// The position after the entry that was returned last, which
+    /// [`Directory::seek`] accepts.
+    pub fn tell(&self) -> u64 {
+        self.position
+    }
+
+    /// Continues with the entry after the one that `position` was taken
+    /// after, or from the start if it's 0. Whatever is buffered is dropped.
+    pub fn seek(&mut self, position: u64) -> Result<(), Errno> {
+        self.pos = 0;
+        self.end = 0;
+        self.backend.seek(position)?;
+        self.position = position;
+        Ok(())
+    }
+
+    pub fn rewind(&mut self) -> Result<(), Errno> {
+        self.seek(0)
+    }
+
+    pub fn close(&mut self) -> Result<(), Errno> {
+        self.backend.close()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::boxed::Box;
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::ffi::CStr;
+
+    use kernel_api::syscall::{DT_DIR, DT_REG};
+
+    use super::*;
+
+    /// A directory in memory, which hands out at most `batch` entries per
+    /// call, like a kernel with a small batch would.
+    struct MemoryDirectory {
+        entries: Vec<(String, u8)>,
+        next: usize,
+        batch: usize,
+        calls: usize,
+    }
+
+    impl MemoryDirectory {
+        fn new(entries: Vec<(String, u8)>, batch: usize) -> Self {
+            Self {
+                entries,
+                next: 0,
+                batch,
+                calls: 0,
+            }
+        }
+    }
+
+    impl Backend for MemoryDirectory {
+        fn getdents(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+            self.calls += 1;
+            let mut written = 0;
+            for _ in 0..self.batch {
+                let Some((name, typ)) = self.entries.get(self.next) else {
+                    break;
+                };
+                let dirent = Dirent {
+                    ino: self.next as u64 + 100,
+                    off: self.next as u64 + 1,
+                    reclen: 0,
+                    typ: *typ,
+                };
+                match dirent.write(&mut buf[written..], name.as_bytes()) {
+                    Some(len) => written += len,
+                    None if written == 0 => return Err(Errno::EINVAL),
+                    None => break,
+                }
+                self.next += 1;
+            }
+            Ok(written)
+        }
+
+        fn seek(&mut self, position: u64) -> Result<(), Errno> {
+            self.next = position as usize;
+            Ok(())
+        }
+
+        fn close(&mut self) -> Result<(), Errno> {
+            Ok(())
+        }
+    }
+
+    fn files(count: usize) -> Vec<(String, u8)> {
+        (0..count).map(|i| (format!("file{i}"), DT_REG)).collect()
+    }
+
+    fn read_all<B: Backend>(directory: &mut Directory<B>) -> Vec<(String, u64, u8)> {
+        let mut names = Vec::new();
+        while let Some(entry) = directory.read().unwrap() {
+            let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) };
+            names.push((
+                String::from(name.to_str().unwrap()),
+                entry.d_ino,
+                entry.d_type,
+            ));
+        }
+        names
+    }
+
+    #[test]
+    fn test_read_all() {
+        let mut entries = files(500);
+        entries.insert(123, (String::from("dir"), DT_DIR));
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(entries.clone(), 7)));
+
+        let read = read_all(&mut directory);
+        assert_eq!(501, read.len());
+        for (i, ((name, typ), (read_name, ino, read_typ))) in entries.iter().zip(&read).enumerate()
+        {
+            assert_eq!(name, read_name);
+            assert_eq!(typ, read_typ);
+            assert_eq!(i as u64 + 100, *ino);
+        }
+        // the last call returned nothing, and the end stays the end
+        assert_eq!(501_usize.div_ceil(7) + 1, directory.backend().calls);
+        assert!(directory.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_many_per_refill() {
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(files(500), 1000)));
+        assert_eq!(500, read_all(&mut directory).len());
+        // every entry takes up at most 32 bytes, and the last call returns
+        // nothing
+        assert!(directory.backend().calls <= 500_usize.div_ceil(BUF_SIZE / 32) + 1);
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(Vec::new(), 7)));
+        assert!(directory.read().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(files(50), 7)));
+        let first = read_all(&mut directory);
+        directory.rewind().unwrap();
+        assert_eq!(first, read_all(&mut directory));
+
+        // rewinding in the middle drops what is buffered
+        directory.rewind().unwrap();
+        directory.read().unwrap();
+        directory.read().unwrap();
+        directory.rewind().unwrap();
+        assert_eq!(first, read_all(&mut directory));
+    }
+
+    #[test]
+    fn test_tell_and_seek() {
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(files(50), 7)));
+        assert_eq!(0, directory.tell());
+        for _ in 0..10 {
+            directory.read().unwrap();
+        }
+        let position = directory.tell();
+        let rest = read_all(&mut directory);
+        assert_eq!("file10", rest[0].0);
+
+        directory.seek(position).unwrap();
+        assert_eq!(rest, read_all(&mut directory));
+    }
+
+    #[test]
+    fn test_long_name() {
+        let mut entries = files(3);
+        entries[1].0 = "x".repeat(NAME_MAX + 1);
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(entries, 7)));
+        directory.read().unwrap();
+        assert_eq!(Err(Errno::EOVERFLOW), directory.read().map(|_| ()));
+        // the entry is skipped, not retried
+        let rest = read_all(&mut directory);
+        assert_eq!(1, rest.len());
+        assert_eq!("file2", rest[0].0);
+
+        let mut entries = files(1);
+        entries[0].0 = "x".repeat(NAME_MAX);
+        let mut directory = Box::new(Directory::new(MemoryDirectory::new(entries, 7)));
+        assert_eq!(NAME_MAX, read_all(&mut directory)[0].0.len());
+    }
+}
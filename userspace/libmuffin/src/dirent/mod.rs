@@ -0,0 +1,237 @@
+//! `dirent.h`.
+//!
+//! A `DIR` reads the entries of a directory in batches with the `getdents`
+//! syscall. The entries that [`readdir`] returns live in the `DIR` itself,
+//! and are overwritten by the next call for the same `DIR`.
+
+use core::ffi::{c_char, c_int, c_long};
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+use kernel_api::syscall::{Errno, OpenFlags, Syscall, Whence};
+use kernel_api::NAME_MAX;
+
+use crate::dirent::directory::{Backend, Directory};
+use crate::errno::{check, set_errno};
+use crate::lock::{Mutex, MutexGuard};
+use crate::stdlib::{free, malloc};
+use crate::syscall::syscall;
+
+mod directory;
+
+pub use kernel_api::syscall::{
+    DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK, DT_UNKNOWN,
+};
+
+#[allow(non_camel_case_types)]
+pub type ino_t = u64;
+#[allow(non_camel_case_types)]
+pub type off_t = i64;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct dirent {
+    pub d_ino: ino_t,
+    /// The position after this entry, as [`telldir`] would return it.
+    pub d_off: off_t,
+    pub d_reclen: u16,
+    /// One of the `DT_*` constants.
+    pub d_type: u8,
+    /// The nul terminated name of the entry.
+    pub d_name: [c_char; NAME_MAX + 1],
+}
+
+/// The backend of a directory stream, which is a file descriptor that was
+/// opened with [`OpenFlags::O_DIRECTORY`].
+pub struct Fd(c_int);
+
+impl Backend for Fd {
+    fn getdents(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let args = [
+            self.0 as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            0,
+            0,
+            0,
+        ];
+        check(unsafe { syscall(Syscall::Getdents, args) })
+    }
+
+    fn seek(&mut self, position: u64) -> Result<(), Errno> {
+        let args = [
+            self.0 as usize,
+            position as usize,
+            Whence::SeekSet as usize,
+            0,
+            0,
+            0,
+        ];
+        check(unsafe { syscall(Syscall::Lseek, args) }).map(|_| ())
+    }
+
+    fn close(&mut self) -> Result<(), Errno> {
+        let args = [self.0 as usize, 0, 0, 0, 0, 0];
+        check(unsafe { syscall(Syscall::Close, args) }).map(|_| ())
+    }
+}
+
+/// A directory stream. C code only ever sees pointers to it.
+#[allow(clippy::upper_case_acronyms)]
+pub struct DIR {
+    directory: Mutex<Directory<Fd>>,
+}
+
+impl DIR {
+    fn lock(&self) -> MutexGuard<'_, Directory<Fd>> {
+        self.directory.lock()
+    }
+}
+
+/// Opens the directory at `name` for reading its entries. Returns null and
+/// sets `errno` on failure.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/opendir.html>.
+///
+/// # Safety
+/// `name` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn opendir(name: *const c_char) -> *mut DIR {
+    let flags = OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY;
+    let args = [name as usize, flags.bits() as usize, 0, 0, 0, 0];
+    let Ok(fd) = check(unsafe { syscall(Syscall::Open, args) }) else {
+        return null_mut();
+    };
+    let dirp = unsafe { malloc(size_of::<DIR>()) }.cast::<DIR>();
+    if dirp.is_null() {
+        let _ = Fd(fd as c_int).close();
+        return null_mut();
+    }
+    let directory = Directory::new(Fd(fd as c_int));
+    unsafe {
+        dirp.write(DIR {
+            directory: Mutex::new(directory),
+        })
+    };
+    dirp
+}
+
+/// Returns the next entry of the directory, or null at the end of it, in
+/// which case `errno` is left alone. The entry stays valid until the next
+/// call for the same `dirp`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/readdir.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn readdir(dirp: *mut DIR) -> *mut dirent {
+    let mut directory = unsafe { &*dirp }.lock();
+    match directory.read() {
+        Ok(Some(entry)) => (entry as *const dirent).cast_mut(),
+        Ok(None) => null_mut(),
+        Err(e) => {
+            set_errno(e);
+            null_mut()
+        }
+    }
+}
+
+/// Copies the next entry of the directory to `entry`, and points `result` at
+/// it, or sets `result` to null at the end of the directory. Returns 0, or
+/// an error number on failure.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/readdir_r.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream, and `entry` and `result` must be
+/// valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn readdir_r(
+    dirp: *mut DIR,
+    entry: *mut dirent,
+    result: *mut *mut dirent,
+) -> c_int {
+    let mut directory = unsafe { &*dirp }.lock();
+    match directory.read() {
+        Ok(Some(next)) => {
+            unsafe {
+                entry.write(*next);
+                result.write(entry);
+            }
+            0
+        }
+        Ok(None) => {
+            unsafe { result.write(null_mut()) };
+            0
+        }
+        Err(e) => e.code(),
+    }
+}
+
+/// Starts reading the directory from the beginning again, which also picks
+/// up entries that were added since it was opened.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/rewinddir.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn rewinddir(dirp: *mut DIR) {
+    // rewinddir can't fail
+    let _ = unsafe { &*dirp }.lock().rewind();
+}
+
+/// The position in the directory after the entry that was read last.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/telldir.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn telldir(dirp: *mut DIR) -> c_long {
+    unsafe { &*dirp }.lock().tell() as c_long
+}
+
+/// Continues reading the directory at `loc`, which [`telldir`] returned for
+/// the same `dirp`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/seekdir.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn seekdir(dirp: *mut DIR, loc: c_long) {
+    if let Err(e) = unsafe { &*dirp }.lock().seek(loc as u64) {
+        set_errno(e);
+    }
+}
+
+/// The file descriptor of the directory stream.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/dirfd.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn dirfd(dirp: *mut DIR) -> c_int {
+    unsafe { &*dirp }.lock().backend().0
+}
+
+/// Closes the directory stream, which can't be used afterwards, even if this
+/// fails.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/closedir.html>.
+///
+/// # Safety
+/// `dirp` must be an open directory stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn closedir(dirp: *mut DIR) -> c_int {
+    let result = unsafe { &*dirp }.lock().close();
+    unsafe {
+        dirp.drop_in_place();
+        free(dirp.cast());
+    }
+    result.map_or(-1, |_| 0)
+}
@@ -0,0 +1,86 @@
+//! `errno.h`.
+//!
+//! Every thread has its own `errno`, which is a thread local variable. C code
+//! reaches it through [`__errno_location`], which is what the `errno` macro
+//! expands to:
+//!
+//! ```c
+//! #define errno (*__errno_location())
+//! ```
+
+use core::ffi::c_int;
+
+use kernel_api::syscall::Errno;
+
+/// Zero in every new thread, because it's part of the zeroed end of the TLS
+/// block.
+#[thread_local]
+static mut ERRNO: c_int = 0;
+
+/// The `errno` of the current thread.
+///
+/// The main thread only gets its TLS when the first thread is created, so it
+/// is set up here if that hasn't happened yet.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn __errno_location() -> *mut c_int {
+    // host tests run on threads that already have their TLS
+    #[cfg(not(test))]
+    if let Err(e) = crate::pthread::set_up_tls() {
+        panic!("can't set up the TLS of the main thread: {e}");
+    }
+    &raw mut ERRNO
+}
+
+pub fn errno() -> c_int {
+    unsafe { *__errno_location() }
+}
+
+pub fn set_errno(errno: Errno) {
+    unsafe { *__errno_location() = errno.code() };
+}
+
+/// Interprets the raw return value of a syscall like
+/// [`Errno::from_return_value`], and stores the error in `errno`.
+pub fn check(ret: isize) -> Result<usize, Errno> {
+    Errno::from_return_value(ret).inspect_err(|&e| set_errno(e))
+}
+
+/// Turns the raw return value of a syscall into what C functions return:
+/// the value itself, or -1 with the error in `errno`.
+pub fn set_errno_from_syscall(ret: isize) -> isize {
+    check(ret).map_or(-1, |value| value as isize)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_set_errno_from_syscall() {
+        set_errno(Errno::EINTR);
+        assert_eq!(7, set_errno_from_syscall(7));
+        assert_eq!(Errno::EINTR.code(), errno());
+
+        assert_eq!(-1, set_errno_from_syscall(Errno::ENOENT.into()));
+        assert_eq!(Errno::ENOENT.code(), errno());
+        // unknown codes are reported as invalid
+        assert_eq!(-1, set_errno_from_syscall(-10_000));
+        assert_eq!(Errno::EINVAL.code(), errno());
+    }
+
+    #[test]
+    fn test_per_thread() {
+        set_errno(Errno::EBADF);
+        thread::spawn(|| {
+            assert_eq!(0, errno());
+            set_errno(Errno::ENOMEM);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(Errno::EBADF.code(), errno());
+    }
+}
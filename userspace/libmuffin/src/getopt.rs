@@ -0,0 +1,887 @@
+//! `getopt.h`, and `getopt` of `unistd.h`.
+//!
+//! Like glibc, the elements of `argv` are permuted while they are scanned, so
+//! that the options end up in front of the operands, where [`optind`] points
+//! in the end. An optstring that starts with `+` stops at the first operand
+//! instead, and one that starts with `-` returns every operand as the
+//! argument of an option with the code 1. A `:` after that turns off the
+//! error messages, and makes a missing argument return `:` instead of `?`.
+//!
+//! Setting [`optind`] to 0 starts over, for another `argv` or for another
+//! pass over the same one.
+
+use core::ffi::{c_char, c_int, CStr};
+use core::fmt::{self, Display, Formatter, Write};
+use core::ptr::{null, null_mut};
+use core::slice;
+
+use crate::lock::Mutex;
+use crate::stdio::{fwrite, stderr};
+
+/// The argument of the option that was returned last, or null if it has
+/// none.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut optarg: *mut c_char = null_mut();
+/// The index of the next element of `argv`.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut optind: c_int = 1;
+/// Whether errors are printed to `stderr`, unless the optstring starts with
+/// `:`.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut opterr: c_int = 1;
+/// The option character of the last error.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut optopt: c_int = 0;
+
+#[allow(non_upper_case_globals)]
+pub const no_argument: c_int = 0;
+#[allow(non_upper_case_globals)]
+pub const required_argument: c_int = 1;
+#[allow(non_upper_case_globals)]
+pub const optional_argument: c_int = 2;
+
+/// A long option of [`getopt_long`]. The array of them ends with one whose
+/// name is null.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
+pub struct option {
+    pub name: *const c_char,
+    /// One of [`no_argument`], [`required_argument`] and
+    /// [`optional_argument`].
+    pub has_arg: c_int,
+    /// If not null, `val` is stored here and `getopt_long` returns 0.
+    pub flag: *mut c_int,
+    pub val: c_int,
+}
+
+static PARSER: Mutex<Parser> = Mutex::new(Parser::new());
+
+/// What happens to operands.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Ordering {
+    /// They are moved behind the options.
+    Permute,
+    /// The first one ends the options.
+    RequireOrder,
+    /// They are returned as the argument of the option 1.
+    ReturnInOrder,
+}
+
+/// Why an option was rejected.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Error<'a> {
+    UnknownOption(u8),
+    MissingArgument(u8),
+    UnknownLongOption(&'a [u8]),
+    AmbiguousLongOption(&'a [u8]),
+    UnexpectedArgument(&'a [u8]),
+    MissingLongArgument(&'a [u8]),
+}
+
+impl Display for Error<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::UnknownOption(c) => write!(f, "invalid option -- '{}'", c as char),
+            Error::MissingArgument(c) => {
+                write!(f, "option requires an argument -- '{}'", c as char)
+            }
+            Error::UnknownLongOption(name) => write!(f, "unrecognized option '--{}'", Lossy(name)),
+            Error::AmbiguousLongOption(name) => {
+                write!(f, "option '--{}' is ambiguous", Lossy(name))
+            }
+            Error::UnexpectedArgument(name) => {
+                write!(f, "option '--{}' doesn't allow an argument", Lossy(name))
+            }
+            Error::MissingLongArgument(name) => {
+                write!(f, "option '--{}' requires an argument", Lossy(name))
+            }
+        }
+    }
+}
+
+/// Displays bytes as UTF-8, with replacement characters where they aren't.
+struct Lossy<'a>(&'a [u8]);
+
+impl Display for Lossy<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for chunk in self.0.utf8_chunks() {
+            f.write_str(chunk.valid())?;
+            if !chunk.invalid().is_empty() {
+                f.write_char(char::REPLACEMENT_CHARACTER)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The state of the scan over `argv`.
+pub struct Parser {
+    pub optind: usize,
+    pub optarg: *mut c_char,
+    pub optopt: c_int,
+    pub opterr: bool,
+    /// Where the next option character is in the element at `optind`, or 0
+    /// if that element hasn't been looked at yet.
+    next: usize,
+    /// The operands that were skipped, `argv[first_operand..last_operand]`,
+    /// which are moved behind the options that follow them.
+    first_operand: usize,
+    last_operand: usize,
+    initialized: bool,
+}
+
+// the arguments belong to the program, not to a thread
+unsafe impl Send for Parser {}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Parser {
+    pub const fn new() -> Self {
+        Self {
+            optind: 1,
+            optarg: null_mut(),
+            optopt: 0,
+            opterr: true,
+            next: 0,
+            first_operand: 1,
+            last_operand: 1,
+            initialized: false,
+        }
+    }
+
+    fn start(&mut self) {
+        if self.optind == 0 {
+            self.optind = 1;
+        }
+        self.next = 0;
+        self.first_operand = self.optind;
+        self.last_operand = self.optind;
+        self.initialized = true;
+    }
+
+    /// Moves the skipped operands behind the options that were found after
+    /// them.
+    fn move_operands(&mut self, argv: &mut [*mut c_char]) {
+        argv[self.first_operand..self.optind].rotate_left(self.last_operand - self.first_operand);
+        self.first_operand += self.optind - self.last_operand;
+        self.last_operand = self.optind;
+    }
+
+    /// Returns the next option like `getopt_long`, with no long options for
+    /// `getopt`, or -1 if there are none left. Errors are passed to `report`
+    /// if they should be printed.
+    ///
+    /// # Safety
+    /// The elements of `argv` and the names of `longopts` must be valid C
+    /// strings, and the flags of `longopts` must be null or valid.
+    pub unsafe fn next(
+        &mut self,
+        argv: &mut [*mut c_char],
+        optstring: &[u8],
+        longopts: &[option],
+        longindex: Option<&mut c_int>,
+        report: &mut dyn FnMut(Error<'_>),
+    ) -> c_int {
+        let (ordering, optstring) = match optstring.split_first() {
+            Some((b'+', rest)) => (Ordering::RequireOrder, rest),
+            Some((b'-', rest)) => (Ordering::ReturnInOrder, rest),
+            _ => (Ordering::Permute, optstring),
+        };
+        let (silent, optstring) = match optstring.strip_prefix(b":") {
+            Some(rest) => (true, rest),
+            None => (false, optstring),
+        };
+        let print = self.opterr && !silent;
+        let mut report = |error: Error<'_>| {
+            if print {
+                report(error);
+            }
+        };
+        let missing = if silent {
+            c_int::from(b':')
+        } else {
+            c_int::from(b'?')
+        };
+
+        self.optarg = null_mut();
+        if self.optind == 0 || !self.initialized {
+            self.start();
+        }
+        let argc = argv.len();
+        let is_operand = |arg: *mut c_char| {
+            let arg = unsafe { CStr::from_ptr(arg) }.to_bytes();
+            arg.first() != Some(&b'-') || arg.len() == 1
+        };
+
+        if self.next == 0 {
+            // the program may have moved `optind` back
+            self.last_operand = self.last_operand.min(self.optind);
+            self.first_operand = self.first_operand.min(self.optind);
+
+            if ordering == Ordering::Permute {
+                if self.first_operand != self.last_operand && self.last_operand != self.optind {
+                    self.move_operands(argv);
+                } else if self.last_operand != self.optind {
+                    self.first_operand = self.optind;
+                }
+                while self.optind < argc && is_operand(argv[self.optind]) {
+                    self.optind += 1;
+                }
+                self.last_operand = self.optind;
+            }
+
+            // everything after `--` is an operand
+            if self.optind < argc && unsafe { CStr::from_ptr(argv[self.optind]) } == c"--" {
+                self.optind += 1;
+                if self.first_operand != self.last_operand && self.last_operand != self.optind {
+                    self.move_operands(argv);
+                } else if self.first_operand == self.last_operand {
+                    self.first_operand = self.optind;
+                }
+                self.last_operand = argc;
+                self.optind = argc;
+            }
+
+            if self.optind >= argc {
+                // point to the operands, which were moved to the end
+                if self.first_operand != self.last_operand {
+                    self.optind = self.first_operand;
+                }
+                return -1;
+            }
+
+            if is_operand(argv[self.optind]) {
+                if ordering == Ordering::RequireOrder {
+                    return -1;
+                }
+                self.optarg = argv[self.optind];
+                self.optind += 1;
+                return 1;
+            }
+
+            let arg = unsafe { CStr::from_ptr(argv[self.optind]) }.to_bytes();
+            if !longopts.is_empty() && arg.len() > 2 && arg[1] == b'-' {
+                return unsafe { self.next_long(argv, longopts, longindex, missing, &mut report) };
+            }
+            self.next = 1;
+        }
+
+        let arg = unsafe { CStr::from_ptr(argv[self.optind]) }.to_bytes();
+        let c = arg[self.next];
+        self.next += 1;
+        let rest = if self.next == arg.len() {
+            self.optind += 1;
+            self.next = 0;
+            None
+        } else {
+            Some(unsafe { argv[self.optind].add(self.next) })
+        };
+
+        let Some(i) = optstring.iter().position(|&b| b == c).filter(|_| c != b':') else {
+            self.optopt = c_int::from(c);
+            report(Error::UnknownOption(c));
+            return c_int::from(b'?');
+        };
+        let takes_argument = optstring.get(i + 1) == Some(&b':');
+        let optional = takes_argument && optstring.get(i + 2) == Some(&b':');
+        if takes_argument {
+            if let Some(rest) = rest {
+                self.optarg = rest;
+                self.optind += 1;
+                self.next = 0;
+            } else if optional {
+                // only an attached argument counts
+            } else if self.optind < argc {
+                self.optarg = argv[self.optind];
+                self.optind += 1;
+            } else {
+                self.optopt = c_int::from(c);
+                report(Error::MissingArgument(c));
+                return missing;
+            }
+        }
+        c_int::from(c)
+    }
+
+    /// Handles the long option at `optind`, which starts with `--`.
+    ///
+    /// # Safety
+    /// See [`Parser::next`].
+    unsafe fn next_long(
+        &mut self,
+        argv: &mut [*mut c_char],
+        longopts: &[option],
+        longindex: Option<&mut c_int>,
+        missing: c_int,
+        report: &mut dyn FnMut(Error<'_>),
+    ) -> c_int {
+        let start = argv[self.optind];
+        let arg = &unsafe { CStr::from_ptr(start) }.to_bytes()[2..];
+        let (name, value) = match arg.iter().position(|&b| b == b'=') {
+            Some(i) => (&arg[..i], Some(unsafe { start.add(2 + i + 1) })),
+            None => (arg, None),
+        };
+        self.optind += 1;
+        self.next = 0;
+
+        let index = match unsafe { find_long(longopts, name) } {
+            Ok(index) => index,
+            Err(error) => {
+                self.optopt = 0;
+                report(error);
+                return c_int::from(b'?');
+            }
+        };
+        let option = &longopts[index];
+        let full_name = unsafe { CStr::from_ptr(option.name) }.to_bytes();
+        match value {
+            Some(_) if option.has_arg == no_argument => {
+                self.optopt = option.val;
+                report(Error::UnexpectedArgument(full_name));
+                return c_int::from(b'?');
+            }
+            Some(value) => self.optarg = value,
+            None if option.has_arg == required_argument => {
+                if self.optind < argv.len() {
+                    self.optarg = argv[self.optind];
+                    self.optind += 1;
+                } else {
+                    self.optopt = option.val;
+                    report(Error::MissingLongArgument(full_name));
+                    return missing;
+                }
+            }
+            None => {}
+        }
+
+        if let Some(longindex) = longindex {
+            *longindex = index as c_int;
+        }
+        if option.flag.is_null() {
+            option.val
+        } else {
+            unsafe { *option.flag = option.val };
+            0
+        }
+    }
+}
+
+/// The index of the long option `name`, or of the only one that it's a
+/// prefix of. Prefixes of several options are fine if they all do the same.
+///
+/// # Safety
+/// The names of `longopts` must be valid C strings.
+unsafe fn find_long<'a>(longopts: &[option], name: &'a [u8]) -> Result<usize, Error<'a>> {
+    let mut found: Option<usize> = None;
+    let mut ambiguous = false;
+    for (i, option) in longopts.iter().enumerate() {
+        let candidate = unsafe { CStr::from_ptr(option.name) }.to_bytes();
+        if candidate == name {
+            return Ok(i);
+        }
+        if !candidate.starts_with(name) {
+            continue;
+        }
+        match found.map(|j| &longopts[j]) {
+            None => found = Some(i),
+            Some(first)
+                if first.has_arg == option.has_arg
+                    && first.flag == option.flag
+                    && first.val == option.val => {}
+            Some(_) => ambiguous = true,
+        }
+    }
+    match found {
+        Some(_) if ambiguous => Err(Error::AmbiguousLongOption(name)),
+        Some(i) => Ok(i),
+        None => Err(Error::UnknownLongOption(name)),
+    }
+}
+
+/// Prints `program: error` to `stderr`, in a single write.
+fn print_error(program: &[u8], error: Error<'_>) {
+    struct Message {
+        buf: [u8; 256],
+        len: usize,
+    }
+
+    impl Write for Message {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let len = s.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+            self.len += len;
+            Ok(())
+        }
+    }
+
+    let mut message = Message {
+        buf: [0; 256],
+        len: 0,
+    };
+    let _ = writeln!(message, "{}: {}", Lossy(program), error);
+    unsafe { fwrite(message.buf.as_ptr().cast(), 1, message.len, stderr) };
+}
+
+/// Returns the next option character of `argv` that is in `optstring`, or
+/// -1 when there are none left. A character that is followed by `:` takes an
+/// argument, which is either the rest of the element or the next one, and
+/// one that is followed by `::` takes an optional argument, which can only be
+/// the rest of the element. The argument is in [`optarg`].
+///
+/// An unknown option returns `?`, and so does a missing argument, unless the
+/// optstring starts with `:`. The option is in [`optopt`] then.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/getopt.html>.
+///
+/// # Safety
+/// `argv` must be an array of `argc` valid C strings, and `optstring` must be
+/// a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn getopt(
+    argc: c_int,
+    argv: *const *mut c_char,
+    optstring: *const c_char,
+) -> c_int {
+    unsafe { getopt_long(argc, argv, optstring, null(), null_mut()) }
+}
+
+/// Like [`getopt`], but also accepts the options of `longopts` as
+/// `--name`, `--name=argument` and `--name argument`, where the name can be
+/// abbreviated as long as that is unambiguous. For a long option, the index
+/// in `longopts` is stored in `longindex` unless that is null.
+///
+/// # Safety
+/// See [`getopt`]. `longopts` must be null or an array that ends with an
+/// option without a name.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn getopt_long(
+    argc: c_int,
+    argv: *const *mut c_char,
+    optstring: *const c_char,
+    longopts: *const option,
+    longindex: *mut c_int,
+) -> c_int {
+    // the elements are permuted, like everywhere else
+    let argv = unsafe { slice::from_raw_parts_mut(argv.cast_mut(), argc.max(0) as usize) };
+    let optstring = unsafe { CStr::from_ptr(optstring) }.to_bytes();
+    let longopts = if longopts.is_null() {
+        &[][..]
+    } else {
+        let len = (0..)
+            .find(|&i| unsafe { *longopts.add(i) }.name.is_null())
+            .unwrap();
+        unsafe { slice::from_raw_parts(longopts, len) }
+    };
+    let program = argv
+        .first()
+        .map_or(&b""[..], |&arg| unsafe { CStr::from_ptr(arg) }.to_bytes());
+
+    let mut parser = PARSER.lock();
+    unsafe {
+        parser.optind = usize::try_from(optind).unwrap_or(0);
+        parser.opterr = opterr != 0;
+        let c = parser.next(
+            argv,
+            optstring,
+            longopts,
+            longindex.as_mut(),
+            &mut |error| print_error(program, error),
+        );
+        optind = parser.optind as c_int;
+        optarg = parser.optarg;
+        optopt = parser.optopt;
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::ffi::CString;
+    use std::string::{String, ToString};
+    use std::vec::Vec;
+    use std::{format, vec};
+
+    use super::*;
+
+    /// An `argv` that owns its strings.
+    struct Args {
+        _strings: Vec<CString>,
+        argv: Vec<*mut c_char>,
+    }
+
+    impl Args {
+        fn new(args: &[&str]) -> Self {
+            let strings = args
+                .iter()
+                .map(|&arg| CString::new(arg).unwrap())
+                .collect::<Vec<_>>();
+            let argv = strings.iter().map(|s| s.as_ptr().cast_mut()).collect();
+            Self {
+                _strings: strings,
+                argv,
+            }
+        }
+
+        fn strings(&self) -> Vec<String> {
+            self.argv
+                .iter()
+                .map(|&arg| unsafe { CStr::from_ptr(arg) }.to_str().unwrap().to_string())
+                .collect()
+        }
+    }
+
+    /// What a whole pass returned, as `c` or `c=optarg`, and the errors that
+    /// would have been printed.
+    struct Pass {
+        options: Vec<String>,
+        errors: Vec<String>,
+    }
+
+    fn run(parser: &mut Parser, args: &mut Args, optstring: &str, longopts: &[option]) -> Pass {
+        let mut pass = Pass {
+            options: vec![],
+            errors: vec![],
+        };
+        loop {
+            let c = unsafe {
+                parser.next(
+                    &mut args.argv,
+                    optstring.as_bytes(),
+                    longopts,
+                    None,
+                    &mut |error| pass.errors.push(error.to_string()),
+                )
+            };
+            if c == -1 {
+                return pass;
+            }
+            let c = match c {
+                0 => '0',
+                1 => '1',
+                c => char::from(c as u8),
+            };
+            pass.options.push(if parser.optarg.is_null() {
+                format!("{c}")
+            } else {
+                let arg = unsafe { CStr::from_ptr(parser.optarg) };
+                format!("{c}={}", arg.to_str().unwrap())
+            });
+        }
+    }
+
+    struct Case {
+        args: &'static [&'static str],
+        optstring: &'static str,
+        options: &'static [&'static str],
+        optind: usize,
+        /// The arguments after the pass.
+        permuted: &'static [&'static str],
+    }
+
+    #[test]
+    fn test_getopt_table() {
+        let cases = [
+            // clustering
+            Case {
+                args: &["prog", "-abc"],
+                optstring: "abc",
+                options: &["a", "b", "c"],
+                optind: 2,
+                permuted: &["prog", "-abc"],
+            },
+            // attached and separated arguments
+            Case {
+                args: &["prog", "-ofile", "-o", "other", "-abox"],
+                optstring: "abo:",
+                options: &["o=file", "o=other", "a", "b", "o=x"],
+                optind: 5,
+                permuted: &["prog", "-ofile", "-o", "other", "-abox"],
+            },
+            // an argument that looks like an option
+            Case {
+                args: &["prog", "-o", "-a", "-a"],
+                optstring: "ao:",
+                options: &["o=-a", "a"],
+                optind: 4,
+                permuted: &["prog", "-o", "-a", "-a"],
+            },
+            // the terminator
+            Case {
+                args: &["prog", "-a", "--", "-b"],
+                optstring: "ab",
+                options: &["a"],
+                optind: 3,
+                permuted: &["prog", "-a", "--", "-b"],
+            },
+            // operands are moved behind the options
+            Case {
+                args: &["prog", "x", "-a", "y", "-o", "f", "z"],
+                optstring: "ao:",
+                options: &["a", "o=f"],
+                optind: 4,
+                permuted: &["prog", "-a", "-o", "f", "x", "y", "z"],
+            },
+            // ... and in front of the terminator
+            Case {
+                args: &["prog", "x", "-a", "--", "-b", "y"],
+                optstring: "ab",
+                options: &["a"],
+                optind: 3,
+                permuted: &["prog", "-a", "--", "x", "-b", "y"],
+            },
+            // a lone dash is an operand
+            Case {
+                args: &["prog", "-", "-a"],
+                optstring: "a",
+                options: &["a"],
+                optind: 2,
+                permuted: &["prog", "-a", "-"],
+            },
+            // optional arguments are only attached ones
+            Case {
+                args: &["prog", "-ofile", "-o", "x"],
+                optstring: "o::",
+                options: &["o=file", "o"],
+                optind: 3,
+                permuted: &["prog", "-ofile", "-o", "x"],
+            },
+            // `+` stops at the first operand
+            Case {
+                args: &["prog", "-a", "x", "-a"],
+                optstring: "+a",
+                options: &["a"],
+                optind: 2,
+                permuted: &["prog", "-a", "x", "-a"],
+            },
+            // `-` returns operands in order
+            Case {
+                args: &["prog", "x", "-a", "y"],
+                optstring: "-a",
+                options: &["1=x", "a", "1=y"],
+                optind: 4,
+                permuted: &["prog", "x", "-a", "y"],
+            },
+            // without long options, `--name` is a cluster
+            Case {
+                args: &["prog", "--ab"],
+                optstring: "ab",
+                options: &["?", "a", "b"],
+                optind: 2,
+                permuted: &["prog", "--ab"],
+            },
+            Case {
+                args: &["prog"],
+                optstring: "a",
+                options: &[],
+                optind: 1,
+                permuted: &["prog"],
+            },
+        ];
+        for case in cases {
+            let mut args = Args::new(case.args);
+            let mut parser = Parser::new();
+            parser.opterr = false;
+            let pass = run(&mut parser, &mut args, case.optstring, &[]);
+            assert_eq!(case.options, pass.options, "options of {:?}", case.args);
+            assert_eq!(case.optind, parser.optind, "optind of {:?}", case.args);
+            assert_eq!(case.permuted, args.strings(), "argv of {:?}", case.args);
+        }
+    }
+
+    #[test]
+    fn test_getopt_errors() {
+        let mut args = Args::new(&["prog", "-x", "-a", "-o"]);
+        let mut parser = Parser::new();
+        let pass = run(&mut parser, &mut args, "ao:", &[]);
+        assert_eq!(["?", "a", "?"], pass.options[..]);
+        assert_eq!(c_int::from(b'o'), parser.optopt);
+        assert_eq!(
+            [
+                "invalid option -- 'x'",
+                "option requires an argument -- 'o'"
+            ],
+            pass.errors[..]
+        );
+
+        // a leading `:` is silent, and tells the two apart
+        let mut args = Args::new(&["prog", "-x", "-o"]);
+        let mut parser = Parser::new();
+        let pass = run(&mut parser, &mut args, ":ao:", &[]);
+        assert_eq!(["?", ":"], pass.options[..]);
+        assert!(pass.errors.is_empty());
+
+        // `:` itself is never an option
+        let mut args = Args::new(&["prog", "-:"]);
+        let mut parser = Parser::new();
+        parser.opterr = false;
+        let pass = run(&mut parser, &mut args, "a:", &[]);
+        assert_eq!(["?"], pass.options[..]);
+        assert_eq!(c_int::from(b':'), parser.optopt);
+    }
+
+    #[test]
+    fn test_getopt_reset() {
+        let mut args = Args::new(&["prog", "x", "-abc", "-o", "f"]);
+        let mut parser = Parser::new();
+        // stop in the middle of a cluster
+        let c = unsafe { parser.next(&mut args.argv, b"abco:", &[], None, &mut |_| {}) };
+        assert_eq!(c_int::from(b'a'), c);
+
+        for _ in 0..2 {
+            parser.optind = 0;
+            let pass = run(&mut parser, &mut args, "abco:", &[]);
+            assert_eq!(["a", "b", "c", "o=f"], pass.options[..]);
+            assert_eq!(4, parser.optind);
+            assert_eq!(["prog", "-abc", "-o", "f", "x"], args.strings()[..]);
+        }
+    }
+
+    #[test]
+    fn test_getopt_long() {
+        let mut debug = 0;
+        let longopts = [
+            option {
+                name: c"verbose".as_ptr(),
+                has_arg: no_argument,
+                flag: null_mut(),
+                val: c_int::from(b'v'),
+            },
+            option {
+                name: c"version".as_ptr(),
+                has_arg: no_argument,
+                flag: null_mut(),
+                val: c_int::from(b'V'),
+            },
+            option {
+                name: c"output".as_ptr(),
+                has_arg: required_argument,
+                flag: null_mut(),
+                val: c_int::from(b'o'),
+            },
+            option {
+                name: c"color".as_ptr(),
+                has_arg: optional_argument,
+                flag: null_mut(),
+                val: c_int::from(b'c'),
+            },
+            option {
+                name: c"colour".as_ptr(),
+                has_arg: optional_argument,
+                flag: null_mut(),
+                val: c_int::from(b'c'),
+            },
+            option {
+                name: c"debug".as_ptr(),
+                has_arg: no_argument,
+                flag: &mut debug,
+                val: 42,
+            },
+        ];
+
+        let mut args = Args::new(&[
+            "prog",
+            "--verbose",
+            "x",
+            "--output=f",
+            "--out",
+            "g",
+            "--col",
+            "--color=auto",
+            "--verb",
+            "-a",
+            "--debug",
+            "--",
+            "--version",
+        ]);
+        let mut parser = Parser::new();
+        let pass = run(&mut parser, &mut args, "a", &longopts);
+        assert_eq!(
+            ["v", "o=f", "o=g", "c", "c=auto", "v", "a", "0"],
+            pass.options[..]
+        );
+        assert!(pass.errors.is_empty());
+        assert_eq!(42, debug);
+        assert_eq!(11, parser.optind);
+        assert_eq!(
+            [
+                "prog",
+                "--verbose",
+                "--output=f",
+                "--out",
+                "g",
+                "--col",
+                "--color=auto",
+                "--verb",
+                "-a",
+                "--debug",
+                "--",
+                "x",
+                "--version"
+            ],
+            args.strings()[..]
+        );
+
+        let mut args = Args::new(&["prog", "--ver", "--nope", "--verbose=1", "--output"]);
+        let mut parser = Parser::new();
+        let pass = run(&mut parser, &mut args, "", &longopts);
+        assert_eq!(["?", "?", "?", "?"], pass.options[..]);
+        assert_eq!(c_int::from(b'o'), parser.optopt);
+        assert_eq!(
+            [
+                "option '--ver' is ambiguous",
+                "unrecognized option '--nope'",
+                "option '--verbose' doesn't allow an argument",
+                "option '--output' requires an argument",
+            ],
+            pass.errors[..]
+        );
+
+        let mut args = Args::new(&["prog", "--output"]);
+        let mut parser = Parser::new();
+        let pass = run(&mut parser, &mut args, ":", &longopts);
+        assert_eq!([":"], pass.options[..]);
+    }
+
+    #[test]
+    fn test_getopt_long_index() {
+        let longopts = [
+            option {
+                name: c"first".as_ptr(),
+                has_arg: no_argument,
+                flag: null_mut(),
+                val: 1000,
+            },
+            option {
+                name: c"second".as_ptr(),
+                has_arg: no_argument,
+                flag: null_mut(),
+                val: 2000,
+            },
+        ];
+        let mut args = Args::new(&["prog", "--sec"]);
+        let mut parser = Parser::new();
+        let mut index = -1;
+        let c = unsafe {
+            parser.next(
+                &mut args.argv,
+                b"",
+                &longopts,
+                Some(&mut index),
+                &mut |_| {},
+            )
+        };
+        assert_eq!(2000, c);
+        assert_eq!(1, index);
+    }
+}
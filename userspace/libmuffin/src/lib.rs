@@ -0,0 +1,27 @@
+//! The C library for DevOS.
+//!
+//! Every C function is an `extern "C"` function with its POSIX name, which is
+//! exported unmangled. In host tests, they keep their mangled names, so that
+//! they don't replace the functions of the host's C library.
+
+#![no_std]
+// `memcpy` and friends are written as loops, which LLVM would otherwise turn
+// back into calls to themselves.
+#![no_builtins]
+#![feature(thread_local)]
+
+pub mod ctype;
+pub mod dirent;
+pub mod errno;
+pub mod getopt;
+pub mod locale;
+mod lock;
+pub mod pthread;
+pub mod setjmp;
+pub mod signal;
+pub mod stdio;
+pub mod stdlib;
+pub mod string;
+mod syscall;
+pub mod time;
+pub mod unistd;
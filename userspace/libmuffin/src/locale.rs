@@ -0,0 +1,367 @@
+//! `locale.h`.
+//!
+//! The only locale is the C locale, which is also what `"POSIX"` and `""`,
+//! the locale of the environment, stand for. Everything that depends on the
+//! locale, like the decimal point of `printf` and `strtod` and the character
+//! classes of `ctype.h`, asks [`current`], so that other locales only have to
+//! be added here.
+
+use core::ffi::{c_char, c_int, CStr};
+use core::ptr::{self, null_mut};
+
+use kernel_api::syscall::Errno;
+
+use crate::ctype::Class;
+use crate::errno::set_errno;
+
+pub const LC_CTYPE: c_int = 0;
+pub const LC_NUMERIC: c_int = 1;
+pub const LC_TIME: c_int = 2;
+pub const LC_COLLATE: c_int = 3;
+pub const LC_MONETARY: c_int = 4;
+pub const LC_MESSAGES: c_int = 5;
+pub const LC_ALL: c_int = 6;
+
+pub const LC_CTYPE_MASK: c_int = 1 << LC_CTYPE;
+pub const LC_NUMERIC_MASK: c_int = 1 << LC_NUMERIC;
+pub const LC_TIME_MASK: c_int = 1 << LC_TIME;
+pub const LC_COLLATE_MASK: c_int = 1 << LC_COLLATE;
+pub const LC_MONETARY_MASK: c_int = 1 << LC_MONETARY;
+pub const LC_MESSAGES_MASK: c_int = 1 << LC_MESSAGES;
+pub const LC_ALL_MASK: c_int = LC_CTYPE_MASK
+    | LC_NUMERIC_MASK
+    | LC_TIME_MASK
+    | LC_COLLATE_MASK
+    | LC_MONETARY_MASK
+    | LC_MESSAGES_MASK;
+
+/// What `uselocale` returns for a thread that uses the global locale.
+pub const LC_GLOBAL_LOCALE: locale_t = ptr::without_provenance_mut(usize::MAX);
+
+/// The value of the `char` fields of [`lconv`] that aren't available.
+const CHAR_MAX: c_char = c_char::MAX;
+
+/// How numbers and amounts of money are formatted. The strings must not be
+/// modified.
+#[repr(C)]
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+pub struct lconv {
+    pub decimal_point: *mut c_char,
+    pub thousands_sep: *mut c_char,
+    pub grouping: *mut c_char,
+    pub int_curr_symbol: *mut c_char,
+    pub currency_symbol: *mut c_char,
+    pub mon_decimal_point: *mut c_char,
+    pub mon_thousands_sep: *mut c_char,
+    pub mon_grouping: *mut c_char,
+    pub positive_sign: *mut c_char,
+    pub negative_sign: *mut c_char,
+    pub int_frac_digits: c_char,
+    pub frac_digits: c_char,
+    pub p_cs_precedes: c_char,
+    pub p_sep_by_space: c_char,
+    pub n_cs_precedes: c_char,
+    pub n_sep_by_space: c_char,
+    pub p_sign_posn: c_char,
+    pub n_sign_posn: c_char,
+    pub int_p_cs_precedes: c_char,
+    pub int_p_sep_by_space: c_char,
+    pub int_n_cs_precedes: c_char,
+    pub int_n_sep_by_space: c_char,
+    pub int_p_sign_posn: c_char,
+    pub int_n_sign_posn: c_char,
+}
+
+/// A locale. C code only ever sees pointers to it.
+#[derive(Debug)]
+pub struct Locale {
+    name: &'static CStr,
+    lconv: lconv,
+}
+
+#[allow(non_camel_case_types)]
+pub type locale_t = *mut Locale;
+
+// the strings are static and never modified
+unsafe impl Sync for Locale {}
+
+const fn string(s: &'static CStr) -> *mut c_char {
+    s.as_ptr().cast_mut()
+}
+
+static C: Locale = Locale {
+    name: c"C",
+    lconv: lconv {
+        decimal_point: string(c"."),
+        thousands_sep: string(c""),
+        grouping: string(c""),
+        int_curr_symbol: string(c""),
+        currency_symbol: string(c""),
+        mon_decimal_point: string(c""),
+        mon_thousands_sep: string(c""),
+        mon_grouping: string(c""),
+        positive_sign: string(c""),
+        negative_sign: string(c""),
+        int_frac_digits: CHAR_MAX,
+        frac_digits: CHAR_MAX,
+        p_cs_precedes: CHAR_MAX,
+        p_sep_by_space: CHAR_MAX,
+        n_cs_precedes: CHAR_MAX,
+        n_sep_by_space: CHAR_MAX,
+        p_sign_posn: CHAR_MAX,
+        n_sign_posn: CHAR_MAX,
+        int_p_cs_precedes: CHAR_MAX,
+        int_p_sep_by_space: CHAR_MAX,
+        int_n_cs_precedes: CHAR_MAX,
+        int_n_sep_by_space: CHAR_MAX,
+        int_p_sign_posn: CHAR_MAX,
+        int_n_sign_posn: CHAR_MAX,
+    },
+};
+
+impl Locale {
+    pub fn name(&self) -> &'static CStr {
+        self.name
+    }
+
+    /// The decimal point of numbers. It's always a single byte.
+    pub fn decimal_point(&self) -> u8 {
+        unsafe { *self.lconv.decimal_point as u8 }
+    }
+
+    /// Whether `c`, an `unsigned char` or `EOF`, is in `class`.
+    pub fn is(&self, c: c_int, class: Class) -> bool {
+        let Ok(b) = u8::try_from(c) else {
+            return false;
+        };
+        match class {
+            Class::Alnum => b.is_ascii_alphanumeric(),
+            Class::Alpha => b.is_ascii_alphabetic(),
+            Class::Blank => b == b' ' || b == b'\t',
+            Class::Cntrl => b.is_ascii_control(),
+            Class::Digit => b.is_ascii_digit(),
+            Class::Graph => b.is_ascii_graphic(),
+            Class::Lower => b.is_ascii_lowercase(),
+            Class::Print => b.is_ascii_graphic() || b == b' ',
+            Class::Punct => b.is_ascii_punctuation(),
+            // unlike `is_ascii_whitespace`, this includes the vertical tab
+            Class::Space => matches!(b, b' ' | b'\t' | b'\n' | 0x0b | 0x0c | b'\r'),
+            Class::Upper => b.is_ascii_uppercase(),
+            Class::Xdigit => b.is_ascii_hexdigit(),
+        }
+    }
+
+    pub fn to_lower(&self, c: c_int) -> c_int {
+        u8::try_from(c).map_or(c, |b| c_int::from(b.to_ascii_lowercase()))
+    }
+
+    pub fn to_upper(&self, c: c_int) -> c_int {
+        u8::try_from(c).map_or(c, |b| c_int::from(b.to_ascii_uppercase()))
+    }
+}
+
+/// The locale that the process uses. There is only one, so it never
+/// changes.
+pub fn current() -> &'static Locale {
+    &C
+}
+
+/// The locale with the given name.
+fn find(name: &[u8]) -> Option<&'static Locale> {
+    matches!(name, b"C" | b"POSIX" | b"").then_some(&C)
+}
+
+/// Sets the locale of `category`, or of all of them for [`LC_ALL`], and
+/// returns its name. A null `locale` only returns the name. Returns null and
+/// leaves the locale alone if there is no such locale.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/setlocale.html>.
+///
+/// # Safety
+/// `locale` must be null or a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn setlocale(category: c_int, locale: *const c_char) -> *mut c_char {
+    if !(LC_CTYPE..=LC_ALL).contains(&category) {
+        set_errno(Errno::EINVAL);
+        return null_mut();
+    }
+    if locale.is_null() {
+        return string(current().name());
+    }
+    match find(unsafe { CStr::from_ptr(locale) }.to_bytes()) {
+        // the only locale is the current one already
+        Some(locale) => string(locale.name()),
+        None => {
+            set_errno(Errno::ENOENT);
+            null_mut()
+        }
+    }
+}
+
+/// The formatting conventions of the current locale.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/localeconv.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn localeconv() -> *mut lconv {
+    (&raw const current().lconv).cast_mut()
+}
+
+/// Returns the locale with the given name for the categories in
+/// `category_mask`. Since there is only one locale, `base` doesn't matter.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/newlocale.html>.
+///
+/// # Safety
+/// `locale` must be null or a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn newlocale(
+    category_mask: c_int,
+    locale: *const c_char,
+    _base: locale_t,
+) -> locale_t {
+    if category_mask & !LC_ALL_MASK != 0 || locale.is_null() {
+        set_errno(Errno::EINVAL);
+        return null_mut();
+    }
+    match find(unsafe { CStr::from_ptr(locale) }.to_bytes()) {
+        Some(locale) => ptr::from_ref(locale).cast_mut(),
+        None => {
+            set_errno(Errno::ENOENT);
+            null_mut()
+        }
+    }
+}
+
+/// Every thread uses the global locale, so this always returns
+/// [`LC_GLOBAL_LOCALE`], and `newloc` changes nothing.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/uselocale.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn uselocale(_newloc: locale_t) -> locale_t {
+    LC_GLOBAL_LOCALE
+}
+
+/// Locales are static, so the copy is the locale itself.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/duplocale.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn duplocale(locobj: locale_t) -> locale_t {
+    if locobj == LC_GLOBAL_LOCALE {
+        ptr::from_ref(current()).cast_mut()
+    } else {
+        locobj
+    }
+}
+
+/// Locales are static, so there is nothing to free.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/freelocale.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn freelocale(_locobj: locale_t) {}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::string::String;
+
+    use super::*;
+    use crate::stdio::snprintf;
+
+    fn name(s: *const c_char) -> Option<&'static str> {
+        (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_str().unwrap())
+    }
+
+    fn setlocale_name(category: c_int, locale: Option<&CStr>) -> Option<&'static str> {
+        name(unsafe { setlocale(category, locale.map_or(ptr::null(), CStr::as_ptr)) })
+    }
+
+    #[test]
+    fn test_setlocale() {
+        assert_eq!(Some("C"), setlocale_name(LC_ALL, None));
+        assert_eq!(Some("C"), setlocale_name(LC_ALL, Some(c"")));
+        assert_eq!(Some("C"), setlocale_name(LC_NUMERIC, Some(c"POSIX")));
+        assert_eq!(Some("C"), setlocale_name(LC_CTYPE, Some(c"C")));
+        assert_eq!(None, setlocale_name(LC_ALL, Some(c"de_DE.UTF-8")));
+        assert_eq!(None, setlocale_name(LC_ALL, Some(c"c")));
+        assert_eq!(None, setlocale_name(LC_ALL + 1, None));
+        assert_eq!(None, setlocale_name(-1, Some(c"C")));
+        // a rejected locale changes nothing
+        assert_eq!(Some("C"), setlocale_name(LC_MESSAGES, None));
+    }
+
+    #[test]
+    fn test_localeconv() {
+        let lconv = unsafe { &*localeconv() };
+        assert_eq!(Some("."), name(lconv.decimal_point));
+        for s in [
+            lconv.thousands_sep,
+            lconv.grouping,
+            lconv.int_curr_symbol,
+            lconv.currency_symbol,
+            lconv.mon_decimal_point,
+            lconv.mon_thousands_sep,
+            lconv.mon_grouping,
+            lconv.positive_sign,
+            lconv.negative_sign,
+        ] {
+            assert_eq!(Some(""), name(s));
+        }
+        for c in [
+            lconv.int_frac_digits,
+            lconv.frac_digits,
+            lconv.p_cs_precedes,
+            lconv.p_sep_by_space,
+            lconv.n_cs_precedes,
+            lconv.n_sep_by_space,
+            lconv.p_sign_posn,
+            lconv.n_sign_posn,
+            lconv.int_p_cs_precedes,
+            lconv.int_p_sep_by_space,
+            lconv.int_n_cs_precedes,
+            lconv.int_n_sep_by_space,
+            lconv.int_p_sign_posn,
+            lconv.int_n_sign_posn,
+        ] {
+            assert_eq!(127, c);
+        }
+    }
+
+    #[test]
+    fn test_printf_uses_the_decimal_point() {
+        let format = |value: f64| {
+            let mut buf = [0 as c_char; 64];
+            unsafe {
+                snprintf(
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    c"%.2f %g".as_ptr(),
+                    value,
+                    value,
+                )
+            };
+            String::from(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap())
+        };
+        for locale in [c"", c"de_DE.UTF-8", c"fr_FR"] {
+            unsafe { setlocale(LC_ALL, locale.as_ptr()) };
+            assert_eq!("1.50 1.5", format(1.5));
+            assert_eq!("0.00 2.5e-05", format(0.000025));
+        }
+    }
+
+    #[test]
+    fn test_newlocale() {
+        let locale = unsafe { newlocale(LC_ALL_MASK, c"".as_ptr(), null_mut()) };
+        assert_eq!(ptr::from_ref(current()).cast_mut(), locale);
+        assert_eq!(locale, duplocale(locale));
+        assert_eq!(locale, duplocale(LC_GLOBAL_LOCALE));
+        assert_eq!(LC_GLOBAL_LOCALE, uselocale(locale));
+        assert_eq!(LC_GLOBAL_LOCALE, uselocale(null_mut()));
+        freelocale(locale);
+
+        assert!(unsafe { newlocale(LC_ALL_MASK, c"de_DE".as_ptr(), null_mut()) }.is_null());
+        assert!(unsafe { newlocale(1 << 20, c"C".as_ptr(), null_mut()) }.is_null());
+        assert!(unsafe { newlocale(LC_ALL_MASK, ptr::null(), null_mut()) }.is_null());
+    }
+}
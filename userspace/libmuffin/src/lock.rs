@@ -0,0 +1,51 @@
+//! The lock that the state of the C library is behind, like the heap, the
+//! streams and the threads. It sleeps on a futex while another thread holds
+//! it.
+
+use core::sync::atomic::AtomicU32;
+
+use kernel_api::syscall::{FutexOp, Syscall};
+use sync::Futex;
+
+use crate::syscall::syscall;
+
+/// The futexes of the kernel.
+pub struct Kernel;
+
+impl Futex for Kernel {
+    fn wait(futex: &AtomicU32, expected: u32) {
+        let args = [
+            futex.as_ptr() as usize,
+            FutexOp::Wait as usize,
+            expected as usize,
+            0,
+            0,
+            0,
+        ];
+        // callers check the value again anyway, so it doesn't matter whether
+        // it had changed already
+        unsafe { syscall(Syscall::Futex, args) };
+    }
+
+    fn wake(futex: &AtomicU32, count: usize) {
+        let args = [
+            futex.as_ptr() as usize,
+            FutexOp::Wake as usize,
+            count,
+            0,
+            0,
+            0,
+        ];
+        unsafe { syscall(Syscall::Futex, args) };
+    }
+}
+
+/// Host tests run in parallel, and may contend for the state of the C
+/// library, so they can't sleep on the futexes of the kernel.
+#[cfg(not(test))]
+type LockFutex = Kernel;
+#[cfg(test)]
+type LockFutex = sync::futex::host::HostFutex;
+
+pub type Mutex<T> = sync::Mutex<T, LockFutex>;
+pub type MutexGuard<'a, T> = sync::MutexGuard<'a, T, LockFutex>;
@@ -0,0 +1,235 @@
+//! Condition variables, which sleep on a sequence number that every signal
+//! and broadcast bumps.
+//!
+//! A waiter reads the sequence number before it releases the mutex, and only
+//! sleeps if it is still the same then. So every signal that comes after the
+//! waiter released the mutex either changes the number before the waiter
+//! sleeps, or wakes it.
+
+use core::ffi::c_int;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::Relaxed;
+
+use sync::{raw, Futex};
+
+use crate::lock::Kernel;
+use crate::pthread::mutex;
+use crate::pthread::mutex::pthread_mutex_t;
+
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+#[repr(C)]
+pub struct pthread_cond_t {
+    sequence: u32,
+}
+
+/// Only the default attributes are supported, so they are ignored.
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+#[repr(C)]
+pub struct pthread_condattr_t {
+    _unused: c_int,
+}
+
+pub const PTHREAD_COND_INITIALIZER: pthread_cond_t = pthread_cond_t { sequence: 0 };
+
+pub fn wait<F: Futex>(sequence: &AtomicU32, mutex: &AtomicU32) {
+    let current = sequence.load(Relaxed);
+    raw::unlock::<F>(mutex);
+    F::wait(sequence, current);
+    raw::lock::<F>(mutex);
+}
+
+pub fn signal<F: Futex>(sequence: &AtomicU32) {
+    sequence.fetch_add(1, Relaxed);
+    F::wake(sequence, 1);
+}
+
+pub fn broadcast<F: Futex>(sequence: &AtomicU32) {
+    sequence.fetch_add(1, Relaxed);
+    F::wake(sequence, usize::MAX);
+}
+
+/// # Safety
+/// `cond` must be valid for reads and writes, and only ever be accessed
+/// atomically while any thread uses it.
+unsafe fn sequence<'a>(cond: *mut pthread_cond_t) -> &'a AtomicU32 {
+    unsafe { AtomicU32::from_ptr(addr_of_mut!((*cond).sequence)) }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_cond_init.html>.
+///
+/// # Safety
+/// `cond` must be valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_cond_init(
+    cond: *mut pthread_cond_t,
+    _attr: *const pthread_condattr_t,
+) -> c_int {
+    unsafe { cond.write(PTHREAD_COND_INITIALIZER) };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_cond_destroy.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_cond_destroy(_cond: *mut pthread_cond_t) -> c_int {
+    0
+}
+
+/// Releases `mutex`, waits until `cond` is signalled, and acquires `mutex`
+/// again. The wait may also end without a signal, so callers have to check
+/// what they wait for in a loop.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_cond_wait.html>.
+///
+/// # Safety
+/// `cond` must point to an initialized condition variable, and `mutex` to a
+/// mutex that the current thread holds.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_cond_wait(
+    cond: *mut pthread_cond_t,
+    mutex: *mut pthread_mutex_t,
+) -> c_int {
+    unsafe { wait::<Kernel>(sequence(cond), mutex::state(mutex)) };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_cond_signal.html>.
+///
+/// # Safety
+/// `cond` must point to an initialized condition variable.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_cond_signal(cond: *mut pthread_cond_t) -> c_int {
+    signal::<Kernel>(unsafe { sequence(cond) });
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_cond_broadcast.html>.
+///
+/// # Safety
+/// `cond` must point to an initialized condition variable.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_cond_broadcast(cond: *mut pthread_cond_t) -> c_int {
+    broadcast::<Kernel>(unsafe { sequence(cond) });
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::UnsafeCell;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    use sync::futex::host::HostFutex;
+    use sync::raw::{lock, unlock};
+
+    use super::*;
+
+    /// Shared state that is only accessed while holding `mutex`.
+    struct Shared<T> {
+        mutex: AtomicU32,
+        first: AtomicU32,
+        second: AtomicU32,
+        value: UnsafeCell<T>,
+    }
+
+    unsafe impl<T> Sync for Shared<T> {}
+
+    impl<T> Shared<T> {
+        fn new(value: T) -> Arc<Self> {
+            Arc::new(Self {
+                mutex: AtomicU32::new(0),
+                first: AtomicU32::new(0),
+                second: AtomicU32::new(0),
+                value: UnsafeCell::new(value),
+            })
+        }
+
+        /// Calls `f` while holding the mutex.
+        fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+            lock::<HostFutex>(&self.mutex);
+            let result = f(unsafe { &mut *self.value.get() });
+            unlock::<HostFutex>(&self.mutex);
+            result
+        }
+
+        /// Waits on `cond` until `done` returns true, while holding the mutex.
+        fn wait_until(&self, cond: &AtomicU32, done: impl Fn(&mut T) -> bool) {
+            while !done(unsafe { &mut *self.value.get() }) {
+                wait::<HostFutex>(cond, &self.mutex);
+            }
+        }
+    }
+
+    /// A producer hands items to a consumer through a single slot, so every
+    /// item needs a signal in each direction. A missed one hangs the test.
+    #[test]
+    fn test_producer_and_consumer() {
+        const ITEMS: usize = 1_000_000;
+
+        // `first` is signalled when the slot is filled, `second` when it's emptied
+        let channel = Shared::new(None);
+        let consumer = {
+            let channel = channel.clone();
+            thread::spawn(move || {
+                for expected in 0..ITEMS {
+                    lock::<HostFutex>(&channel.mutex);
+                    channel.wait_until(&channel.first, |slot| slot.is_some());
+                    let item = unsafe { (*channel.value.get()).take() };
+                    assert_eq!(Some(expected), item);
+                    signal::<HostFutex>(&channel.second);
+                    unlock::<HostFutex>(&channel.mutex);
+                }
+            })
+        };
+
+        for item in 0..ITEMS {
+            lock::<HostFutex>(&channel.mutex);
+            channel.wait_until(&channel.second, |slot| slot.is_none());
+            unsafe { *channel.value.get() = Some(item) };
+            signal::<HostFutex>(&channel.first);
+            unlock::<HostFutex>(&channel.mutex);
+        }
+        consumer.join().unwrap();
+        assert_eq!(None, channel.with(|slot| *slot));
+    }
+
+    /// All waiters are woken by a single broadcast, and each of them exactly
+    /// once.
+    #[test]
+    fn test_broadcast() {
+        const WAITERS: usize = 16;
+
+        // (waiting, released, woken)
+        let state = Shared::new((0, false, 0));
+        let waiters = (0..WAITERS)
+            .map(|_| {
+                let state = state.clone();
+                thread::spawn(move || {
+                    lock::<HostFutex>(&state.mutex);
+                    unsafe { (*state.value.get()).0 += 1 };
+                    // tell the main thread that we're about to wait
+                    signal::<HostFutex>(&state.second);
+                    state.wait_until(&state.first, |(_, released, _)| *released);
+                    unsafe { (*state.value.get()).2 += 1 };
+                    unlock::<HostFutex>(&state.mutex);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        lock::<HostFutex>(&state.mutex);
+        state.wait_until(&state.second, |(waiting, _, _)| *waiting == WAITERS);
+        unsafe { (*state.value.get()).1 = true };
+        broadcast::<HostFutex>(&state.first);
+        unlock::<HostFutex>(&state.mutex);
+
+        for waiter in waiters {
+            waiter.join().unwrap();
+        }
+        assert_eq!((WAITERS, true, WAITERS), state.with(|state| *state));
+    }
+}
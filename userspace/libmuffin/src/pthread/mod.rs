@@ -0,0 +1,537 @@
+//! `pthread.h`.
+//!
+//! Every thread is a kernel thread of the process, which runs on a stack that
+//! is mapped here, with a guard page below it. A [`pthread_t`] is the index of
+//! the thread in a table of all threads, in which the main thread is 0.
+
+use core::ffi::{c_int, c_void};
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::{Acquire, Release};
+
+use kernel_api::syscall::{Errno, Syscall};
+
+use crate::lock::Mutex;
+pub use crate::pthread::cond::{
+    pthread_cond_broadcast, pthread_cond_destroy, pthread_cond_init, pthread_cond_signal,
+    pthread_cond_t, pthread_cond_wait, pthread_condattr_t, PTHREAD_COND_INITIALIZER,
+};
+pub use crate::pthread::mutex::{
+    pthread_mutex_destroy, pthread_mutex_init, pthread_mutex_lock, pthread_mutex_t,
+    pthread_mutex_trylock, pthread_mutex_unlock, pthread_mutexattr_t, PTHREAD_MUTEX_INITIALIZER,
+};
+use crate::pthread::tls::Template;
+use crate::stdlib::{
+    MAP_ANONYMOUS, MAP_FIXED, MAP_POPULATE, MAP_PRIVATE, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+use crate::syscall::syscall;
+
+mod cond;
+mod mutex;
+mod tls;
+
+#[allow(non_camel_case_types)]
+pub type pthread_t = usize;
+
+/// The attributes of a thread, which are used when it is created.
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct pthread_attr_t {
+    detach_state: c_int,
+    stack_size: usize,
+}
+
+impl pthread_attr_t {
+    const DEFAULT: Self = Self {
+        detach_state: PTHREAD_CREATE_JOINABLE,
+        stack_size: DEFAULT_STACK_SIZE,
+    };
+}
+
+pub const PTHREAD_CREATE_JOINABLE: c_int = 0;
+pub const PTHREAD_CREATE_DETACHED: c_int = 1;
+
+pub const PTHREAD_STACK_MIN: usize = 16 * 1024;
+
+/// The number of threads that can exist at the same time, including the main
+/// thread.
+pub const PTHREAD_THREADS_MAX: usize = 64;
+
+const DEFAULT_STACK_SIZE: usize = 128 * 1024;
+const PAGE_SIZE: usize = 4096;
+/// Running into the guard page faults, instead of overwriting whatever is
+/// mapped below the stack.
+const GUARD_SIZE: usize = PAGE_SIZE;
+/// How often mapping a stack is retried if another thread maps something in
+/// the place that was picked for it.
+const MAP_ATTEMPTS: usize = 4;
+
+const EAGAIN: c_int = Errno::EWOULDBLOCK.code();
+const EDEADLK: c_int = Errno::EDEADLK.code();
+const EINVAL: c_int = Errno::EINVAL.code();
+const ESRCH: c_int = Errno::ESRCH.code();
+
+type StartRoutine = extern "C" fn(*mut c_void) -> *mut c_void;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Free,
+    Joinable,
+    Detached,
+    /// Exited, and waiting to be joined.
+    Exited,
+    /// Exited while detached. Nobody joins it, so the next [`pthread_create`]
+    /// or [`pthread_join`] cleans up after it.
+    Zombie,
+    /// Being joined or cleaned up.
+    Joining,
+}
+
+#[derive(Copy, Clone)]
+struct Thread {
+    state: State,
+    /// The id of the kernel thread, once it's created. The main thread
+    /// doesn't have one, because it can't be joined.
+    tid: Option<usize>,
+    memory: Option<Memory>,
+    start_routine: Option<StartRoutine>,
+    arg: usize,
+}
+
+impl Thread {
+    const FREE: Self = Self {
+        state: State::Free,
+        tid: None,
+        memory: None,
+        start_routine: None,
+        arg: 0,
+    };
+}
+
+static THREADS: Mutex<[Thread; PTHREAD_THREADS_MAX]> = Mutex::new({
+    let mut threads = [Thread::FREE; PTHREAD_THREADS_MAX];
+    threads[0].state = State::Joinable;
+    threads
+});
+
+/// Whether the main thread has its TLS. It is set up when the first thread
+/// is created or `errno` is first used, and until the former, the main thread
+/// is the only thread.
+static MAIN_TLS: AtomicBool = AtomicBool::new(false);
+
+fn mmap(addr: usize, len: usize, prot: usize, flags: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall(Syscall::Mmap, [addr, len, prot, flags, 0, 0]) })
+}
+
+fn munmap(addr: usize, len: usize) -> Result<(), Errno> {
+    Errno::from_return_value(unsafe { syscall(Syscall::Munmap, [addr, len, 0, 0, 0, 0]) })
+        .map(|_| ())
+}
+
+/// The memory of a thread: the guard page, and above it the stack, which is
+/// followed by the TLS block and the TCB.
+#[derive(Debug, Copy, Clone)]
+struct Memory {
+    guard: usize,
+    len: usize,
+}
+
+impl Memory {
+    /// Maps `len` bytes with a guard page below them.
+    fn map(len: usize) -> Option<Self> {
+        for _ in 0..MAP_ATTEMPTS {
+            // a mapping has the same protection everywhere, so find a place that
+            // fits both, and map them there separately
+            let guard = mmap(0, GUARD_SIZE + len, PROT_NONE, MAP_PRIVATE | MAP_ANONYMOUS).ok()?;
+            munmap(guard, GUARD_SIZE + len).ok()?;
+
+            let flags = MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED;
+            if mmap(guard, GUARD_SIZE, PROT_NONE, flags).is_err() {
+                continue;
+            }
+            // a page fault can't be handled on a stack that isn't mapped yet,
+            // because the CPU pushes its interrupt frame onto that stack
+            let populated = flags | MAP_POPULATE;
+            if mmap(guard + GUARD_SIZE, len, PROT_READ | PROT_WRITE, populated).is_ok() {
+                return Some(Self { guard, len });
+            }
+            let _ = munmap(guard, GUARD_SIZE);
+        }
+        None
+    }
+
+    fn start(&self) -> usize {
+        self.guard + GUARD_SIZE
+    }
+
+    fn unmap(self) {
+        let _ = munmap(self.start(), self.len);
+        let _ = munmap(self.guard, GUARD_SIZE);
+    }
+}
+
+/// Makes sure that the current thread has its TLS. Only the main thread can
+/// be without, and only until it first needs it.
+pub(crate) fn set_up_tls() -> Result<(), Errno> {
+    if MAIN_TLS.load(Acquire) {
+        return Ok(());
+    }
+    let _threads = THREADS.lock();
+    if !MAIN_TLS.load(Acquire) {
+        set_up_main_tls(&Template::of_executable())?;
+    }
+    Ok(())
+}
+
+/// Gives the main thread its TLS, which stays mapped until the process exits.
+fn set_up_main_tls(template: &Template) -> Result<(), Errno> {
+    let len = template.layout().size.next_multiple_of(PAGE_SIZE);
+    let area = mmap(0, len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS)?;
+    let tcb = unsafe { tls::initialize(area as *mut u8, template, 0) };
+    Errno::from_return_value(unsafe { syscall(Syscall::SetTls, [tcb, 0, 0, 0, 0, 0]) })?;
+    MAIN_TLS.store(true, Release);
+    Ok(())
+}
+
+/// Where threads start. The kernel calls it on the stack of the thread.
+extern "C" fn start(thread: pthread_t) -> ! {
+    let (start_routine, arg) = {
+        let threads = THREADS.lock();
+        (threads[thread].start_routine.unwrap(), threads[thread].arg)
+    };
+    unsafe { pthread_exit(start_routine(arg as *mut c_void)) }
+}
+
+/// Waits until the kernel thread is gone, and releases the thread and its
+/// memory. Returns the exit value of the thread.
+fn release(thread: pthread_t, tid: usize) -> Result<usize, Errno> {
+    let value =
+        Errno::from_return_value(unsafe { syscall(Syscall::ThreadJoin, [tid, 0, 0, 0, 0, 0]) })?;
+    let memory = {
+        let mut threads = THREADS.lock();
+        let memory = threads[thread].memory;
+        threads[thread] = Thread::FREE;
+        memory
+    };
+    if let Some(memory) = memory {
+        memory.unmap();
+    }
+    Ok(value)
+}
+
+/// Cleans up after the threads that exited while detached.
+fn reap_zombies() {
+    for thread in 1..PTHREAD_THREADS_MAX {
+        let tid = {
+            let mut threads = THREADS.lock();
+            match (threads[thread].state, threads[thread].tid) {
+                (State::Zombie, Some(tid)) => {
+                    threads[thread].state = State::Joining;
+                    tid
+                }
+                _ => continue,
+            }
+        };
+        let _ = release(thread, tid);
+    }
+}
+
+/// Creates a thread that calls `start_routine` with `arg`, and exits with
+/// what it returns. Returns `EAGAIN` if there are too many threads already,
+/// or there is no memory for the stack.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_create.html>.
+///
+/// # Safety
+/// `thread` must be valid for writes, and `attr` must be null or point to
+/// initialized attributes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_create(
+    thread: *mut pthread_t,
+    attr: *const pthread_attr_t,
+    start_routine: StartRoutine,
+    arg: *mut c_void,
+) -> c_int {
+    reap_zombies();
+
+    let attr = if attr.is_null() {
+        pthread_attr_t::DEFAULT
+    } else {
+        unsafe { *attr }
+    };
+    if let Err(e) = set_up_tls() {
+        return e.code();
+    }
+    let template = Template::of_executable();
+    let layout = template.layout();
+
+    let index = {
+        let mut threads = THREADS.lock();
+        let Some(index) = threads.iter().position(|t| t.state == State::Free) else {
+            return EAGAIN;
+        };
+        threads[index] = Thread {
+            state: if attr.detach_state == PTHREAD_CREATE_DETACHED {
+                State::Detached
+            } else {
+                State::Joinable
+            },
+            start_routine: Some(start_routine),
+            arg: arg as usize,
+            ..Thread::FREE
+        };
+        index
+    };
+
+    // the stack grows down from the TLS block
+    let stack_size = attr.stack_size.next_multiple_of(layout.align);
+    let Some(memory) = Memory::map((stack_size + layout.size).next_multiple_of(PAGE_SIZE)) else {
+        THREADS.lock()[index] = Thread::FREE;
+        return EAGAIN;
+    };
+    let stack = memory.start() + stack_size;
+    let tcb = unsafe { tls::initialize(stack as *mut u8, &template, index) };
+
+    let args = [start as *const () as usize, index, stack, tcb, 0, 0];
+    let tid = match Errno::from_return_value(unsafe { syscall(Syscall::ThreadCreate, args) }) {
+        Ok(tid) => tid,
+        Err(_) => {
+            memory.unmap();
+            THREADS.lock()[index] = Thread::FREE;
+            return EAGAIN;
+        }
+    };
+    // the thread may already have exited, but it only changes its state
+    {
+        let mut threads = THREADS.lock();
+        threads[index].tid = Some(tid);
+        threads[index].memory = Some(memory);
+    }
+
+    unsafe { thread.write(index) };
+    0
+}
+
+/// Exits the current thread with `value`, which is what joining it returns.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_exit.html>.
+///
+/// # Safety
+/// Nothing may use the stack of the thread anymore once it has been joined.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_exit(value: *mut c_void) -> ! {
+    let thread = pthread_self();
+    {
+        let mut threads = THREADS.lock();
+        let state = &mut threads[thread].state;
+        *state = match *state {
+            State::Detached => State::Zombie,
+            _ => State::Exited,
+        };
+    }
+    unsafe { syscall(Syscall::ThreadExit, [value as usize, 0, 0, 0, 0, 0]) };
+    unreachable!("thread_exit returned")
+}
+
+/// Waits until `thread` has exited, stores its exit value in `retval` unless
+/// that is null, and releases its stack. The main thread and detached threads
+/// can't be joined.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_join.html>.
+///
+/// # Safety
+/// `retval` must be null or valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_join(thread: pthread_t, retval: *mut *mut c_void) -> c_int {
+    reap_zombies();
+
+    if thread == pthread_self() {
+        return EDEADLK;
+    }
+    let tid = {
+        let mut threads = THREADS.lock();
+        let Some(t) = threads.get_mut(thread) else {
+            return ESRCH;
+        };
+        match (t.state, t.tid) {
+            (State::Free, _) => return ESRCH,
+            (State::Joinable | State::Exited, Some(tid)) => {
+                t.state = State::Joining;
+                tid
+            }
+            _ => return EINVAL,
+        }
+    };
+
+    match release(thread, tid) {
+        Ok(value) => {
+            if !retval.is_null() {
+                unsafe { retval.write(value as *mut c_void) };
+            }
+            0
+        }
+        Err(e) => e.code(),
+    }
+}
+
+/// Makes `thread` clean up after itself when it exits, instead of waiting to
+/// be joined.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_detach.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_detach(thread: pthread_t) -> c_int {
+    let mut threads = THREADS.lock();
+    let Some(t) = threads.get_mut(thread) else {
+        return ESRCH;
+    };
+    t.state = match t.state {
+        State::Free => return ESRCH,
+        State::Joinable => State::Detached,
+        State::Exited => State::Zombie,
+        State::Detached | State::Zombie | State::Joining => return EINVAL,
+    };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_self.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_self() -> pthread_t {
+    if !MAIN_TLS.load(Acquire) {
+        return 0;
+    }
+    unsafe { tls::current() }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_equal.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_equal(t1: pthread_t, t2: pthread_t) -> c_int {
+    (t1 == t2) as c_int
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_init.html>.
+///
+/// # Safety
+/// `attr` must be valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_attr_init(attr: *mut pthread_attr_t) -> c_int {
+    unsafe { attr.write(pthread_attr_t::DEFAULT) };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_destroy.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_attr_destroy(_attr: *mut pthread_attr_t) -> c_int {
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_setdetachstate.html>.
+///
+/// # Safety
+/// `attr` must point to initialized attributes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_attr_setdetachstate(
+    attr: *mut pthread_attr_t,
+    detach_state: c_int,
+) -> c_int {
+    if detach_state != PTHREAD_CREATE_JOINABLE && detach_state != PTHREAD_CREATE_DETACHED {
+        return EINVAL;
+    }
+    unsafe { (*attr).detach_state = detach_state };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_getdetachstate.html>.
+///
+/// # Safety
+/// `attr` must point to initialized attributes, and `detach_state` must be
+/// valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_attr_getdetachstate(
+    attr: *const pthread_attr_t,
+    detach_state: *mut c_int,
+) -> c_int {
+    unsafe { detach_state.write((*attr).detach_state) };
+    0
+}
+
+/// Returns `EINVAL` if `stack_size` is less than [`PTHREAD_STACK_MIN`].
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_setstacksize.html>.
+///
+/// # Safety
+/// `attr` must point to initialized attributes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_attr_setstacksize(
+    attr: *mut pthread_attr_t,
+    stack_size: usize,
+) -> c_int {
+    if stack_size < PTHREAD_STACK_MIN {
+        return EINVAL;
+    }
+    unsafe { (*attr).stack_size = stack_size };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_attr_getstacksize.html>.
+///
+/// # Safety
+/// `attr` must point to initialized attributes, and `stack_size` must be
+/// valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_attr_getstacksize(
+    attr: *const pthread_attr_t,
+    stack_size: *mut usize,
+) -> c_int {
+    unsafe { stack_size.write((*attr).stack_size) };
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::MaybeUninit;
+
+    use super::*;
+
+    #[test]
+    fn test_attributes() {
+        let mut attr = MaybeUninit::uninit();
+        let (mut detach_state, mut stack_size) = (-1, 0);
+        unsafe {
+            assert_eq!(0, pthread_attr_init(attr.as_mut_ptr()));
+            let attr = attr.as_mut_ptr();
+
+            assert_eq!(0, pthread_attr_getdetachstate(attr, &mut detach_state));
+            assert_eq!(PTHREAD_CREATE_JOINABLE, detach_state);
+            assert_eq!(
+                0,
+                pthread_attr_setdetachstate(attr, PTHREAD_CREATE_DETACHED)
+            );
+            assert_eq!(EINVAL, pthread_attr_setdetachstate(attr, 7));
+            assert_eq!(0, pthread_attr_getdetachstate(attr, &mut detach_state));
+            assert_eq!(PTHREAD_CREATE_DETACHED, detach_state);
+
+            assert_eq!(0, pthread_attr_getstacksize(attr, &mut stack_size));
+            assert_eq!(DEFAULT_STACK_SIZE, stack_size);
+            assert_eq!(
+                EINVAL,
+                pthread_attr_setstacksize(attr, PTHREAD_STACK_MIN - 1)
+            );
+            assert_eq!(0, pthread_attr_setstacksize(attr, PTHREAD_STACK_MIN));
+            assert_eq!(0, pthread_attr_getstacksize(attr, &mut stack_size));
+            assert_eq!(PTHREAD_STACK_MIN, stack_size);
+
+            assert_eq!(0, pthread_attr_destroy(attr));
+        }
+    }
+
+    #[test]
+    fn test_main_thread() {
+        assert_eq!(0, pthread_self());
+        assert_ne!(0, pthread_equal(pthread_self(), 0));
+        assert_eq!(0, pthread_equal(pthread_self(), 1));
+        assert_eq!(EDEADLK, unsafe { pthread_join(0, core::ptr::null_mut()) });
+        assert_eq!(ESRCH, unsafe {
+            pthread_join(PTHREAD_THREADS_MAX, core::ptr::null_mut())
+        });
+    }
+}
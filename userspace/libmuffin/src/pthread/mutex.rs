@@ -0,0 +1,91 @@
+//! Mutexes, which sleep on a futex while they are contended, see
+//! [`sync::raw`].
+
+use core::ffi::c_int;
+use core::ptr::addr_of_mut;
+use core::sync::atomic::AtomicU32;
+
+use kernel_api::syscall::Errno;
+use sync::raw::{lock, try_lock, unlock, UNLOCKED};
+
+use crate::lock::Kernel;
+
+const EBUSY: c_int = Errno::EBUSY.code();
+
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+#[repr(C)]
+pub struct pthread_mutex_t {
+    state: u32,
+}
+
+/// Only the default attributes are supported, so they are ignored.
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+#[repr(C)]
+pub struct pthread_mutexattr_t {
+    _unused: c_int,
+}
+
+pub const PTHREAD_MUTEX_INITIALIZER: pthread_mutex_t = pthread_mutex_t { state: UNLOCKED };
+
+/// # Safety
+/// `mutex` must be valid for reads and writes, and only ever be accessed
+/// atomically while any thread uses it.
+pub(super) unsafe fn state<'a>(mutex: *mut pthread_mutex_t) -> &'a AtomicU32 {
+    unsafe { AtomicU32::from_ptr(addr_of_mut!((*mutex).state)) }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_mutex_init.html>.
+///
+/// # Safety
+/// `mutex` must be valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_init(
+    mutex: *mut pthread_mutex_t,
+    _attr: *const pthread_mutexattr_t,
+) -> c_int {
+    unsafe { mutex.write(PTHREAD_MUTEX_INITIALIZER) };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_mutex_destroy.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn pthread_mutex_destroy(_mutex: *mut pthread_mutex_t) -> c_int {
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_mutex_lock.html>.
+///
+/// # Safety
+/// `mutex` must point to an initialized mutex.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_lock(mutex: *mut pthread_mutex_t) -> c_int {
+    lock::<Kernel>(unsafe { state(mutex) });
+    0
+}
+
+/// Returns `EBUSY` if the mutex is held.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_mutex_trylock.html>.
+///
+/// # Safety
+/// `mutex` must point to an initialized mutex.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_trylock(mutex: *mut pthread_mutex_t) -> c_int {
+    if try_lock(unsafe { state(mutex) }) {
+        0
+    } else {
+        EBUSY
+    }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_mutex_unlock.html>.
+///
+/// # Safety
+/// `mutex` must point to a mutex that the current thread holds.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_mutex_unlock(mutex: *mut pthread_mutex_t) -> c_int {
+    unlock::<Kernel>(unsafe { state(mutex) });
+    0
+}
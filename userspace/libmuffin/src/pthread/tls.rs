@@ -0,0 +1,223 @@
+//! Thread local storage, laid out like the x86_64 System V ABI describes it
+//! ("variant II"): the TLS block of the executable sits right below the
+//! thread control block (TCB), which the `fs` segment of the thread points to.
+
+use core::arch::asm;
+use core::mem::size_of;
+use core::ptr;
+
+use crate::pthread::pthread_t;
+
+const PT_LOAD: u32 = 1;
+const PT_TLS: u32 = 7;
+
+extern "C" {
+    /// The ELF header of the executable, provided by the linker.
+    static __ehdr_start: Elf64Header;
+}
+
+#[repr(C)]
+struct Elf64Header {
+    ident: [u8; 16],
+    typ: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+struct Elf64ProgramHeader {
+    typ: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// What every TLS block starts with: the initialized data of the `PT_TLS`
+/// segment, followed by zeros.
+#[derive(Debug, Copy, Clone)]
+pub struct Template {
+    image: *const u8,
+    file_size: usize,
+    mem_size: usize,
+    align: usize,
+}
+
+impl Template {
+    /// The template of the executable, or an empty one if it has no thread
+    /// local variables.
+    pub fn of_executable() -> Self {
+        let mut template = Self {
+            image: ptr::null(),
+            file_size: 0,
+            mem_size: 0,
+            align: 1,
+        };
+        let header = &raw const __ehdr_start;
+        let program_headers = unsafe {
+            core::slice::from_raw_parts(
+                header
+                    .cast::<u8>()
+                    .add((*header).phoff as usize)
+                    .cast::<Elf64ProgramHeader>(),
+                (*header).phnum as usize,
+            )
+        };
+        // the segment that contains the headers tells where the image was loaded
+        let Some(first) = program_headers
+            .iter()
+            .find(|ph| ph.typ == PT_LOAD && ph.offset == 0)
+        else {
+            return template;
+        };
+        let base = header as usize - first.vaddr as usize;
+        if let Some(tls) = program_headers.iter().find(|ph| ph.typ == PT_TLS) {
+            template.image = (base + tls.vaddr as usize) as *const u8;
+            template.file_size = tls.filesz as usize;
+            template.mem_size = tls.memsz as usize;
+            template.align = tls.align.max(1) as usize;
+        }
+        template
+    }
+
+    pub fn layout(&self) -> Layout {
+        Layout::new(self.mem_size, self.align)
+    }
+}
+
+/// The thread control block. The ABI only requires the first word, the rest
+/// is ours.
+#[repr(C)]
+struct Tcb {
+    /// Points to the TCB itself, so that its address can be read from `fs:0`.
+    this: *mut Tcb,
+    thread: pthread_t,
+}
+
+/// Where the TLS block and the TCB are, relative to the start of an area that
+/// is aligned to [`Layout::align`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Layout {
+    pub size: usize,
+    pub align: usize,
+    /// The offset of the TCB. The TLS block lies in front of it.
+    pub tcb: usize,
+}
+
+impl Layout {
+    pub const fn new(mem_size: usize, align: usize) -> Self {
+        let align = if align > 16 { align } else { 16 };
+        let tcb = mem_size.next_multiple_of(align);
+        Self {
+            size: tcb + size_of::<Tcb>(),
+            align,
+            tcb,
+        }
+    }
+}
+
+/// Initializes the TLS block and the TCB of `thread` in `area`, and returns
+/// the address of the TCB, which is what `fs` must point to.
+///
+/// # Safety
+/// `area` must be valid for `layout.size` bytes and aligned to `layout.align`.
+pub unsafe fn initialize(area: *mut u8, template: &Template, thread: pthread_t) -> usize {
+    let layout = template.layout();
+    unsafe {
+        let tcb = area.add(layout.tcb).cast::<Tcb>();
+        // the linker computes the offsets of thread local variables like this
+        let block = tcb
+            .cast::<u8>()
+            .sub(template.mem_size.next_multiple_of(template.align));
+        if template.file_size > 0 {
+            ptr::copy_nonoverlapping(template.image, block, template.file_size);
+        }
+        ptr::write_bytes(
+            block.add(template.file_size),
+            0,
+            template.mem_size - template.file_size,
+        );
+        tcb.write(Tcb { this: tcb, thread });
+        tcb as usize
+    }
+}
+
+/// The thread that the current TLS belongs to.
+///
+/// # Safety
+/// The `fs` segment of the current thread must point to a TCB that was set up
+/// with [`initialize`].
+pub unsafe fn current() -> pthread_t {
+    let thread: pthread_t;
+    unsafe {
+        asm!(
+            "mov {}, fs:[{}]",
+            out(reg) thread,
+            const size_of::<*mut Tcb>(),
+            options(nostack, readonly, preserves_flags),
+        );
+    }
+    thread
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_without_tls() {
+        let layout = Layout::new(0, 1);
+        assert_eq!(0, layout.tcb);
+        assert_eq!(16, layout.align);
+        assert_eq!(size_of::<Tcb>(), layout.size);
+    }
+
+    #[test]
+    fn test_tcb_is_aligned() {
+        let layout = Layout::new(20, 8);
+        assert_eq!(32, layout.tcb);
+        assert_eq!(32 + size_of::<Tcb>(), layout.size);
+
+        let layout = Layout::new(100, 64);
+        assert_eq!(64, layout.align);
+        assert_eq!(128, layout.tcb);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let image = [1_u8, 2, 3];
+        let template = Template {
+            image: image.as_ptr(),
+            file_size: 3,
+            mem_size: 6,
+            align: 4,
+        };
+        let layout = template.layout();
+        let mut area = [0xAA_u8; 64];
+        // the array isn't aligned, so start at an offset that is
+        let offset = area.as_ptr().align_offset(layout.align);
+        let area = &mut area[offset..offset + layout.size];
+        let tcb = unsafe { initialize(area.as_mut_ptr(), &template, 5) };
+
+        assert_eq!(area.as_ptr() as usize + layout.tcb, tcb);
+        // the block starts at a multiple of its own alignment below the TCB
+        assert_eq!([0xAA; 8], area[..8]);
+        assert_eq!([1, 2, 3, 0, 0, 0, 0xAA, 0xAA], area[8..16]);
+        let tcb = unsafe { &*(tcb as *const Tcb) };
+        assert_eq!(tcb as *const Tcb, tcb.this.cast_const());
+        assert_eq!(5, tcb.thread);
+    }
+}
@@ -0,0 +1,245 @@
+//! `setjmp.h`.
+//!
+//! [`setjmp`] saves the registers that a function call must preserve, which
+//! are `rbx`, `rbp` and `r12` to `r15`, together with the stack pointer and
+//! the return address. [`longjmp`] restores them and returns from `setjmp`
+//! a second time. Everything else, like the caller-saved registers, was
+//! already given up by the call to `setjmp`, so the compiler doesn't expect
+//! it to survive.
+//!
+//! Both only ever touch the stack above the stack pointer, so they don't
+//! care about a red zone. Local variables of the function that called
+//! `setjmp` that were changed after it have an unspecified value after
+//! `longjmp`, unless they are `volatile`, because they may live in one of
+//! the restored registers.
+//!
+//! [`sigsetjmp`] can also save the signal mask, which [`siglongjmp`] then
+//! restores. That's the only way to leave a signal handler with a jump
+//! without leaving its signal blocked.
+
+use core::arch::naked_asm;
+use core::ffi::c_int;
+use core::mem::{offset_of, size_of};
+
+use crate::signal::{set_mask, SIG_BLOCK, SIG_SETMASK};
+
+/// What [`setjmp`] saves. C code only ever sees it through [`jmp_buf`].
+#[allow(non_camel_case_types)]
+#[derive(Debug, Default, Copy, Clone)]
+#[repr(C)]
+pub struct __jmp_buf_tag {
+    /// `rbx`, `rbp`, `r12`, `r13`, `r14`, `r15`, the stack pointer after
+    /// returning, and the return address, in this order.
+    registers: [u64; 8],
+    /// Whether [`sigsetjmp`] saved the signal mask.
+    mask_was_saved: u64,
+    saved_mask: u64,
+}
+
+/// An array of one element, so that it's passed by reference like in C.
+#[allow(non_camel_case_types)]
+pub type jmp_buf = [__jmp_buf_tag; 1];
+#[allow(non_camel_case_types)]
+pub type sigjmp_buf = jmp_buf;
+
+// the assembly below and C code depend on this layout
+const _: () = assert!(offset_of!(__jmp_buf_tag, registers) == 0);
+const _: () = assert!(offset_of!(__jmp_buf_tag, mask_was_saved) == 64);
+const _: () = assert!(size_of::<jmp_buf>() == 80);
+
+/// Saves the calling environment in `env`, and returns 0. A later
+/// [`longjmp`] with the same `env` returns from here again, with the value
+/// that was passed to it.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/setjmp.html>.
+///
+/// # Safety
+/// `env` must be valid for writes.
+#[unsafe(naked)]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn setjmp(env: *mut __jmp_buf_tag) -> c_int {
+    naked_asm!(
+        "mov [rdi], rbx",
+        "mov [rdi + 8], rbp",
+        "mov [rdi + 16], r12",
+        "mov [rdi + 24], r13",
+        "mov [rdi + 32], r14",
+        "mov [rdi + 40], r15",
+        // the stack pointer as it is after returning, above the return address
+        "lea rdx, [rsp + 8]",
+        "mov [rdi + 48], rdx",
+        "mov rdx, [rsp]",
+        "mov [rdi + 56], rdx",
+        "xor eax, eax",
+        "ret",
+    )
+}
+
+/// The same as [`setjmp`], which doesn't touch the signal mask either.
+///
+/// # Safety
+/// See [`setjmp`].
+#[unsafe(naked)]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn _setjmp(env: *mut __jmp_buf_tag) -> c_int {
+    // a jump and not a call, so that setjmp sees the frame of our caller
+    naked_asm!("jmp {}", sym setjmp)
+}
+
+/// Like [`setjmp`], and also saves the signal mask if `savemask` is
+/// non-zero.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigsetjmp.html>.
+///
+/// # Safety
+/// See [`setjmp`].
+#[unsafe(naked)]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigsetjmp(env: *mut __jmp_buf_tag, savemask: c_int) -> c_int {
+    naked_asm!(
+        "mov qword ptr [rdi + 64], 0",
+        "test esi, esi",
+        "jz 2f",
+        // saving the mask doesn't touch the registers that setjmp saves, and
+        // the stack is aligned for the call after pushing env
+        "push rdi",
+        "call {save_mask}",
+        "pop rdi",
+        "2:",
+        "jmp {setjmp}",
+        save_mask = sym save_mask,
+        setjmp = sym setjmp,
+    )
+}
+
+extern "C" fn save_mask(env: &mut __jmp_buf_tag) {
+    // only fails for an invalid `how`
+    env.saved_mask = set_mask(SIG_BLOCK, None).unwrap();
+    env.mask_was_saved = 1;
+}
+
+/// Returns from the [`setjmp`] that saved `env` again, with `val`, or 1 if
+/// `val` is 0.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/longjmp.html>.
+///
+/// # Safety
+/// `env` must have been filled by [`setjmp`] in a function that hasn't
+/// returned yet, on the stack of the current thread. The frames in between
+/// are left without running any destructors.
+#[unsafe(naked)]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn longjmp(env: *const __jmp_buf_tag, val: c_int) -> ! {
+    naked_asm!(
+        // eax = val + (val == 0), because only 0 is below 1
+        "xor eax, eax",
+        "cmp esi, 1",
+        "adc eax, esi",
+        "mov rbx, [rdi]",
+        "mov rbp, [rdi + 8]",
+        "mov r12, [rdi + 16]",
+        "mov r13, [rdi + 24]",
+        "mov r14, [rdi + 32]",
+        "mov r15, [rdi + 40]",
+        "mov rsp, [rdi + 48]",
+        "jmp qword ptr [rdi + 56]",
+    )
+}
+
+/// The same as [`longjmp`], but also restores the signal mask if
+/// [`sigsetjmp`] saved it.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/siglongjmp.html>.
+///
+/// # Safety
+/// See [`longjmp`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn siglongjmp(env: *const __jmp_buf_tag, val: c_int) -> ! {
+    let tag = unsafe { &*env };
+    if tag.mask_was_saved != 0 {
+        set_mask(SIG_SETMASK, Some(tag.saved_mask)).unwrap();
+    }
+    unsafe { longjmp(env, val) }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::hint::black_box;
+    use core::ptr::{read_volatile, write_volatile};
+
+    use super::*;
+
+    /// Goes `depth` frames deep before jumping back.
+    #[inline(never)]
+    unsafe fn jump_from(env: *const __jmp_buf_tag, depth: u32, val: c_int) {
+        if depth == 0 {
+            unsafe { longjmp(env, val) };
+        }
+        unsafe { jump_from(env, black_box(depth - 1), val) };
+        // not a tail call, so that the frames really pile up
+        black_box(depth);
+    }
+
+    /// Overwrites all registers that `setjmp` saves before jumping, so that
+    /// the caller only survives if `longjmp` restores them.
+    #[unsafe(naked)]
+    unsafe extern "C" fn clobber_and_jump(env: *const __jmp_buf_tag, val: c_int) -> ! {
+        naked_asm!(
+            "mov rbx, 0x0BAD",
+            "mov rbp, 0x0BAD",
+            "mov r12, 0x0BAD",
+            "mov r13, 0x0BAD",
+            "mov r14, 0x0BAD",
+            "mov r15, 0x0BAD",
+            "jmp {}",
+            sym longjmp,
+        )
+    }
+
+    #[test]
+    fn test_returns_value() {
+        let mut env = jmp_buf::default();
+        let mut calls = 0;
+        let ret = unsafe { setjmp(env.as_mut_ptr()) };
+        unsafe { write_volatile(&mut calls, read_volatile(&calls) + 1) };
+        if ret == 0 {
+            unsafe { jump_from(env.as_ptr(), 3, 42) };
+            unreachable!();
+        }
+        assert_eq!(42, ret);
+        assert_eq!(2, unsafe { read_volatile(&calls) });
+    }
+
+    #[test]
+    fn test_zero_becomes_one() {
+        let mut env = jmp_buf::default();
+        let ret = unsafe { setjmp(env.as_mut_ptr()) };
+        if ret == 0 {
+            unsafe { jump_from(env.as_ptr(), 0, 0) };
+        }
+        assert_eq!(1, ret);
+
+        // saving the mask needs the kernel, see muffin_check
+        let mut env = jmp_buf::default();
+        let ret = unsafe { sigsetjmp(env.as_mut_ptr(), 0) };
+        if ret == 0 {
+            unsafe { siglongjmp(env.as_ptr(), -1) };
+        }
+        assert_eq!(-1, ret);
+    }
+
+    #[test]
+    fn test_callee_saved_registers() {
+        // values that are live across the jump, which the compiler keeps in
+        // callee-saved registers or on the stack
+        let values = black_box([3_u64, 5, 7, 11, 13, 17, 19]);
+        let [a, b, c, d, e, f, g] = values;
+        let mut env = jmp_buf::default();
+        let ret = unsafe { _setjmp(env.as_mut_ptr()) };
+        if ret == 0 {
+            unsafe { clobber_and_jump(env.as_ptr(), 7) };
+        }
+        assert_eq!(7, ret);
+        assert_eq!(values, black_box([a, b, c, d, e, f, g]));
+    }
+}
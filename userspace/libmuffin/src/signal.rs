@@ -0,0 +1,412 @@
+//! `signal.h`.
+//!
+//! Handlers are called as `handler(signo, info, context)` whether
+//! [`SA_SIGINFO`] is set or not, which also works for handlers that only take
+//! the signal number. `context` points to the registers of the interrupted
+//! code, which the handler shouldn't rely on.
+//!
+//! A handler returns to [`__restore_rt`], which [`sigaction`] installs for
+//! every handler, and which makes the `sigreturn` syscall. There is one
+//! signal mask for the whole process, and signals are only delivered when a
+//! thread makes a syscall or faults.
+
+use core::arch::naked_asm;
+use core::ffi::{c_int, c_void};
+use core::mem::{offset_of, size_of};
+use core::ptr::null;
+
+use kernel_api::syscall::{
+    Errno, SigAction, SigActionFlags, SigInfo, SigSet, SigprocmaskHow, Syscall,
+};
+
+use crate::errno::{check, set_errno, set_errno_from_syscall};
+use crate::syscall::syscall;
+use crate::unistd::{getpid, pid_t, uid_t};
+
+pub use kernel_api::syscall::{
+    NSIG, SEGV_ACCERR, SEGV_MAPERR, SIGABRT, SIGALRM, SIGBUS, SIGCHLD, SIGCONT, SIGFPE, SIGHUP,
+    SIGILL, SIGINT, SIGIO, SIGKILL, SIGPIPE, SIGPROF, SIGPWR, SIGQUIT, SIGSEGV, SIGSTKFLT, SIGSTOP,
+    SIGSYS, SIGTERM, SIGTRAP, SIGTSTP, SIGTTIN, SIGTTOU, SIGURG, SIGUSR1, SIGUSR2, SIGVTALRM,
+    SIGWINCH, SIGXCPU, SIGXFSZ, SI_KERNEL, SI_USER,
+};
+
+#[allow(non_camel_case_types)]
+pub type sigset_t = u64;
+/// A handler, or [`SIG_DFL`] or [`SIG_IGN`].
+#[allow(non_camel_case_types)]
+pub type sighandler_t = usize;
+
+pub const SIG_DFL: sighandler_t = kernel_api::syscall::SIG_DFL;
+pub const SIG_IGN: sighandler_t = kernel_api::syscall::SIG_IGN;
+/// What [`signal`] returns on failure.
+pub const SIG_ERR: sighandler_t = usize::MAX;
+
+pub const SA_NOCLDSTOP: c_int = SigActionFlags::SA_NOCLDSTOP.bits() as c_int;
+pub const SA_SIGINFO: c_int = SigActionFlags::SA_SIGINFO.bits() as c_int;
+pub const SA_RESTORER: c_int = SigActionFlags::SA_RESTORER.bits() as c_int;
+pub const SA_ONSTACK: c_int = SigActionFlags::SA_ONSTACK.bits() as c_int;
+pub const SA_RESTART: c_int = SigActionFlags::SA_RESTART.bits() as c_int;
+pub const SA_NODEFER: c_int = SigActionFlags::SA_NODEFER.bits() as c_int;
+pub const SA_RESETHAND: c_int = SigActionFlags::SA_RESETHAND.bits() as c_int;
+
+pub const SIG_BLOCK: c_int = SigprocmaskHow::Block as c_int;
+pub const SIG_UNBLOCK: c_int = SigprocmaskHow::Unblock as c_int;
+pub const SIG_SETMASK: c_int = SigprocmaskHow::SetMask as c_int;
+
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub union sigval {
+    pub sival_int: c_int,
+    pub sival_ptr: *mut c_void,
+}
+
+/// What a handler that was installed with [`SA_SIGINFO`] learns about the
+/// signal.
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct siginfo_t {
+    pub si_signo: c_int,
+    pub si_errno: c_int,
+    /// [`SI_USER`] for signals from [`kill`] and [`raise`], or why the
+    /// kernel sent the signal, like [`SEGV_MAPERR`].
+    pub si_code: c_int,
+    /// The process that sent the signal, for [`SI_USER`].
+    pub si_pid: pid_t,
+    pub si_uid: uid_t,
+    /// The faulting address, for [`SIGSEGV`].
+    pub si_addr: *mut c_void,
+    pub si_status: c_int,
+    pub si_value: sigval,
+}
+
+// the kernel writes a SigInfo, which C code reads as a siginfo_t
+const _: () = assert!(size_of::<siginfo_t>() == size_of::<SigInfo>());
+const _: () = assert!(offset_of!(siginfo_t, si_code) == offset_of!(SigInfo, code));
+const _: () = assert!(offset_of!(siginfo_t, si_pid) == offset_of!(SigInfo, pid));
+const _: () = assert!(offset_of!(siginfo_t, si_uid) == offset_of!(SigInfo, uid));
+const _: () = assert!(offset_of!(siginfo_t, si_addr) == offset_of!(SigInfo, addr));
+const _: () = assert!(offset_of!(siginfo_t, si_status) == offset_of!(SigInfo, status));
+const _: () = assert!(offset_of!(siginfo_t, si_value) == offset_of!(SigInfo, value));
+
+/// The action of a signal. `sa_sigaction` is also what C calls
+/// `sa_handler`, and it's called with a [`siginfo_t`] if [`SA_SIGINFO`] is
+/// set.
+#[allow(non_camel_case_types)]
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct sigaction {
+    pub sa_sigaction: sighandler_t,
+    pub sa_mask: sigset_t,
+    pub sa_flags: c_int,
+    /// Ignored, [`sigaction`] always uses [`__restore_rt`].
+    pub sa_restorer: Option<unsafe extern "C" fn()>,
+}
+
+/// Where signal handlers return to, with the stack pointer pointing to the
+/// frame that the kernel set up for them.
+///
+/// # Safety
+/// Must only be returned to by a signal handler.
+#[unsafe(naked)]
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn __restore_rt() -> ! {
+    naked_asm!(
+        "mov rdi, rsp",
+        "mov eax, {sigreturn}",
+        "int 0x80",
+        // sigreturn only fails if the frame was overwritten
+        "ud2",
+        sigreturn = const Syscall::Sigreturn as usize,
+    )
+}
+
+/// Fails with [`Errno::EINVAL`] for anything that isn't a signal number.
+fn check_signal(signo: c_int) -> Result<usize, Errno> {
+    usize::try_from(signo)
+        .ok()
+        .filter(|&sig| SigSet::is_valid(sig))
+        .ok_or(Errno::EINVAL)
+}
+
+/// Stores an error in `errno` and returns -1, or returns 0.
+fn to_c(result: Result<(), Errno>) -> c_int {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            set_errno(e);
+            -1
+        }
+    }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigemptyset.html>.
+///
+/// # Safety
+/// `set` must be valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigemptyset(set: *mut sigset_t) -> c_int {
+    unsafe { *set = SigSet::EMPTY.bits() };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigfillset.html>.
+///
+/// # Safety
+/// `set` must be valid for writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigfillset(set: *mut sigset_t) -> c_int {
+    unsafe { *set = SigSet::FULL.bits() };
+    0
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigaddset.html>.
+///
+/// # Safety
+/// `set` must be valid for reads and writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigaddset(set: *mut sigset_t, signo: c_int) -> c_int {
+    to_c(check_signal(signo).map(|sig| unsafe {
+        *set = SigSet::from_bits(*set).with(sig).bits();
+    }))
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigdelset.html>.
+///
+/// # Safety
+/// `set` must be valid for reads and writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigdelset(set: *mut sigset_t, signo: c_int) -> c_int {
+    to_c(check_signal(signo).map(|sig| unsafe {
+        *set = SigSet::from_bits(*set).without(sig).bits();
+    }))
+}
+
+/// Returns 1 if `signo` is in `set`, and 0 if it isn't.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigismember.html>.
+///
+/// # Safety
+/// `set` must be valid for reads.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigismember(set: *const sigset_t, signo: c_int) -> c_int {
+    match check_signal(signo) {
+        Ok(sig) => SigSet::from_bits(unsafe { *set }).contains(sig) as c_int,
+        Err(e) => {
+            set_errno(e);
+            -1
+        }
+    }
+}
+
+/// Installs `act` as the action of `sig` if it isn't null, and stores the old
+/// action in `oact` if that isn't null.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigaction.html>.
+///
+/// # Safety
+/// `act` must be null or valid for reads, and `oact` null or valid for
+/// writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigaction(
+    sig: c_int,
+    act: *const sigaction,
+    oact: *mut sigaction,
+) -> c_int {
+    let new = unsafe { act.as_ref() }.map(|act| SigAction {
+        handler: act.sa_sigaction,
+        flags: SigActionFlags::from_bits_truncate(u64::from(act.sa_flags as u32))
+            | SigActionFlags::SA_RESTORER,
+        restorer: __restore_rt as *const () as usize,
+        mask: SigSet::from_bits(act.sa_mask),
+    });
+    let mut old = SigAction::default();
+    let args = [
+        sig as usize,
+        new.as_ref().map_or(null(), |new| new as *const SigAction) as usize,
+        &raw mut old as usize,
+        0,
+        0,
+        0,
+    ];
+    if let Err(e) = check(unsafe { syscall(Syscall::Sigaction, args) }) {
+        return to_c(Err(e));
+    }
+    if let Some(oact) = unsafe { oact.as_mut() } {
+        *oact = sigaction {
+            sa_sigaction: old.handler,
+            sa_mask: old.mask.bits(),
+            sa_flags: old.flags.bits() as u32 as c_int,
+            sa_restorer: None,
+        };
+    }
+    0
+}
+
+/// Changes the signal mask as described by `how` if `set` isn't null, and
+/// returns the old mask.
+pub(crate) fn set_mask(how: c_int, set: Option<sigset_t>) -> Result<sigset_t, Errno> {
+    let mut old: sigset_t = 0;
+    let set = set.map(SigSet::from_bits);
+    let args = [
+        how as usize,
+        set.as_ref().map_or(null(), |set| set as *const SigSet) as usize,
+        &raw mut old as usize,
+        0,
+        0,
+        0,
+    ];
+    check(unsafe { syscall(Syscall::Sigprocmask, args) })?;
+    Ok(old)
+}
+
+/// There is only one mask for the whole process, so this changes the mask
+/// of all threads.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigprocmask.html>.
+///
+/// # Safety
+/// `set` must be null or valid for reads, and `oset` null or valid for
+/// writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigprocmask(
+    how: c_int,
+    set: *const sigset_t,
+    oset: *mut sigset_t,
+) -> c_int {
+    match set_mask(how, unsafe { set.as_ref() }.copied()) {
+        Ok(old) => {
+            if let Some(oset) = unsafe { oset.as_mut() } {
+                *oset = old;
+            }
+            0
+        }
+        Err(e) => to_c(Err(e)),
+    }
+}
+
+/// The same as [`sigprocmask`], but returns the error number instead of
+/// setting `errno`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/pthread_sigmask.html>.
+///
+/// # Safety
+/// See [`sigprocmask`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn pthread_sigmask(
+    how: c_int,
+    set: *const sigset_t,
+    oset: *mut sigset_t,
+) -> c_int {
+    match set_mask(how, unsafe { set.as_ref() }.copied()) {
+        Ok(old) => {
+            if let Some(oset) = unsafe { oset.as_mut() } {
+                *oset = old;
+            }
+            0
+        }
+        Err(e) => e.code(),
+    }
+}
+
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/kill.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn kill(pid: pid_t, sig: c_int) -> c_int {
+    let args = [pid as usize, sig as usize, 0, 0, 0, 0];
+    set_errno_from_syscall(unsafe { syscall(Syscall::Kill, args) }) as c_int
+}
+
+/// Sends `sig` to the current process. If it isn't blocked, its handler has
+/// run when this returns.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/raise.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn raise(sig: c_int) -> c_int {
+    kill(getpid(), sig)
+}
+
+/// Installs `handler` for `sig` with BSD semantics, which means that the
+/// handler stays installed, `sig` is blocked while it runs and interrupted
+/// syscalls are restarted. Returns the old handler, or [`SIG_ERR`].
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/signal.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn signal(sig: c_int, handler: sighandler_t) -> sighandler_t {
+    let act = sigaction {
+        sa_sigaction: handler,
+        sa_mask: 0,
+        sa_flags: SA_RESTART,
+        sa_restorer: None,
+    };
+    let mut old = act;
+    if unsafe { sigaction(sig, &act, &mut old) } == 0 {
+        old.sa_sigaction
+    } else {
+        SIG_ERR
+    }
+}
+
+/// Replaces the signal mask with `sigmask` until a signal handler has run.
+/// Always returns -1 with `errno` set to `EINTR`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sigsuspend.html>.
+///
+/// # Safety
+/// `sigmask` must be valid for reads.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sigsuspend(sigmask: *const sigset_t) -> c_int {
+    let args = [sigmask as usize, 0, 0, 0, 0, 0];
+    set_errno_from_syscall(unsafe { syscall(Syscall::Sigsuspend, args) }) as c_int
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errno::errno;
+
+    #[test]
+    fn test_sigset() {
+        let mut set: sigset_t = 0xFFFF;
+        unsafe {
+            assert_eq!(0, sigemptyset(&mut set));
+            assert_eq!(0, set);
+            assert_eq!(0, sigaddset(&mut set, SIGINT as c_int));
+            assert_eq!(0, sigaddset(&mut set, SIGSYS as c_int));
+            assert_eq!(1, sigismember(&set, SIGINT as c_int));
+            assert_eq!(1, sigismember(&set, SIGSYS as c_int));
+            assert_eq!(0, sigismember(&set, SIGTERM as c_int));
+            assert_eq!(0, sigdelset(&mut set, SIGINT as c_int));
+            assert_eq!(0, sigismember(&set, SIGINT as c_int));
+
+            assert_eq!(0, sigfillset(&mut set));
+            for sig in 1..NSIG as c_int {
+                assert_eq!(1, sigismember(&set, sig));
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_signal() {
+        let mut set: sigset_t = 0;
+        unsafe {
+            for sig in [0, -1, NSIG as c_int, c_int::MAX] {
+                assert_eq!(-1, sigaddset(&mut set, sig));
+                assert_eq!(Errno::EINVAL.code(), errno());
+                assert_eq!(-1, sigdelset(&mut set, sig));
+                assert_eq!(-1, sigismember(&set, sig));
+            }
+        }
+        assert_eq!(0, set);
+    }
+
+    #[test]
+    fn test_flags() {
+        // SA_RESETHAND is the sign bit
+        const { assert!(SA_RESETHAND < 0) };
+        assert_eq!(
+            SigActionFlags::SA_RESETHAND,
+            SigActionFlags::from_bits_truncate(u64::from(SA_RESETHAND as u32))
+        );
+    }
+}
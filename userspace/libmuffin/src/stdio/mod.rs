@@ -0,0 +1,442 @@
+//! `stdio.h`.
+//!
+//! Streams are fully buffered, unless they turn out to be a terminal, which
+//! makes them line buffered. `stderr` is unbuffered.
+//!
+//! `exit` flushes all streams after the exit handlers ran, but `_exit` and
+//! `quick_exit` don't, so whatever is still buffered is lost then.
+
+use core::ffi::{c_char, c_int, c_long, c_void, CStr};
+use core::mem::size_of;
+use core::ptr::{addr_of, null_mut};
+use core::slice;
+
+use kernel_api::syscall::{Errno, OpenFlags, Stat, Syscall, Whence};
+
+use crate::errno::{check, set_errno};
+use crate::lock::{Mutex, MutexGuard};
+pub use crate::stdio::printf::{
+    fprintf, printf, snprintf, sprintf, vfprintf, vprintf, vsnprintf, vsprintf,
+};
+pub use crate::stdio::stream::BUFSIZ;
+use crate::stdio::stream::{Backend, Buffering, Stream};
+use crate::stdlib::{free, malloc};
+use crate::string::strlen;
+use crate::syscall::syscall;
+
+mod printf;
+mod stream;
+
+pub const EOF: c_int = -1;
+
+/// The number of streams that can be open at the same time, including
+/// `stdin`, `stdout` and `stderr`.
+pub const FOPEN_MAX: usize = 64;
+
+pub const SEEK_SET: c_int = Whence::SeekSet as c_int;
+pub const SEEK_CUR: c_int = Whence::SeekCur as c_int;
+pub const SEEK_END: c_int = Whence::SeekEnd as c_int;
+
+/// The backend of the streams, which is a file descriptor. Failing syscalls
+/// set `errno`.
+pub struct Fd(c_int);
+
+impl Backend for Fd {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let args = [
+            self.0 as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+            0,
+            0,
+            0,
+        ];
+        check(unsafe { syscall(Syscall::Read, args) })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Errno> {
+        let args = [self.0 as usize, buf.as_ptr() as usize, buf.len(), 0, 0, 0];
+        check(unsafe { syscall(Syscall::Write, args) })
+    }
+
+    fn seek(&mut self, offset: isize, whence: Whence) -> Result<usize, Errno> {
+        let args = [self.0 as usize, offset as usize, whence as usize, 0, 0, 0];
+        check(unsafe { syscall(Syscall::Lseek, args) })
+    }
+
+    /// There is no `isatty` yet, so every character device counts as a
+    /// terminal.
+    fn is_terminal(&mut self) -> bool {
+        let mut stat = Stat::default();
+        let args = [self.0 as usize, &mut stat as *mut Stat as usize, 0, 0, 0, 0];
+        let res = unsafe { syscall(Syscall::Fstat, args) };
+        res == 0 && stat.mode.is_char_device()
+    }
+
+    fn close(&mut self) -> Result<(), Errno> {
+        let args = [self.0 as usize, 0, 0, 0, 0, 0];
+        check(unsafe { syscall(Syscall::Close, args) }).map(|_| ())
+    }
+}
+
+/// A stream. C code only ever sees pointers to it.
+#[allow(non_camel_case_types)]
+pub struct FILE {
+    stream: Mutex<Stream<Fd>>,
+}
+
+impl FILE {
+    const fn new(fd: c_int, readable: bool, writable: bool, buffering: Option<Buffering>) -> Self {
+        let stream = Stream::new(Fd(fd), readable, writable, buffering);
+        Self {
+            stream: Mutex::new(stream),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Stream<Fd>> {
+        self.stream.lock()
+    }
+}
+
+static STDIN: FILE = FILE::new(0, true, false, None);
+static STDOUT: FILE = FILE::new(1, false, true, None);
+static STDERR: FILE = FILE::new(2, false, true, Some(Buffering::Unbuffered));
+
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut stdin: *mut FILE = addr_of!(STDIN).cast_mut();
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut stdout: *mut FILE = addr_of!(STDOUT).cast_mut();
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut stderr: *mut FILE = addr_of!(STDERR).cast_mut();
+
+/// The streams that were opened with [`fopen`], so that [`fflush`] can find
+/// them.
+static OPEN_FILES: Mutex<OpenFiles> = Mutex::new(OpenFiles([null_mut(); FOPEN_MAX - 3]));
+
+struct OpenFiles([*mut FILE; FOPEN_MAX - 3]);
+
+// the streams are only ever accessed through their own locks
+unsafe impl Send for OpenFiles {}
+
+fn is_standard_stream(stream: *mut FILE) -> bool {
+    [addr_of!(STDIN), addr_of!(STDOUT), addr_of!(STDERR)].contains(&stream.cast_const())
+}
+
+/// The flags to open a file with for the mode of [`fopen`], or [`None`] if
+/// the mode is invalid.
+fn open_flags(mode: &[u8]) -> Option<OpenFlags> {
+    let (&kind, modifiers) = mode.split_first()?;
+    let mut flags = match kind {
+        b'r' => OpenFlags::O_RDONLY,
+        b'w' => OpenFlags::O_WRONLY | OpenFlags::O_CREAT | OpenFlags::O_TRUNC,
+        b'a' => OpenFlags::O_WRONLY | OpenFlags::O_CREAT | OpenFlags::O_APPEND,
+        _ => return None,
+    };
+    for &modifier in modifiers {
+        match modifier {
+            b'+' => flags = flags.difference(OpenFlags::O_ACCMODE) | OpenFlags::O_RDWR,
+            b'x' if kind == b'w' => flags |= OpenFlags::O_EXCL,
+            // there is no difference between text and binary files, and no
+            // exec, so nothing can be inherited
+            b'b' | b'e' => {}
+            _ => return None,
+        }
+    }
+    Some(flags)
+}
+
+/// Opens the file at `path` as a stream. The mode is one of `r`, `w` or `a`,
+/// optionally followed by `+`, `b`, `x` and `e`. Returns null and sets
+/// `errno` on failure.
+///
+/// # Safety
+/// Both must be valid C strings.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fopen(path: *const c_char, mode: *const c_char) -> *mut FILE {
+    let Some(flags) = open_flags(unsafe { CStr::from_ptr(mode) }.to_bytes()) else {
+        set_errno(Errno::EINVAL);
+        return null_mut();
+    };
+
+    let mut open_files = OPEN_FILES.lock();
+    let Some(slot) = open_files.0.iter_mut().find(|slot| slot.is_null()) else {
+        set_errno(Errno::EMFILE);
+        return null_mut();
+    };
+    let args = [path as usize, flags.bits() as usize, 0o666, 0, 0, 0];
+    let Ok(fd) = check(unsafe { syscall(Syscall::Open, args) }) else {
+        return null_mut();
+    };
+    let stream = unsafe { malloc(size_of::<FILE>()) }.cast::<FILE>();
+    if stream.is_null() {
+        let _ = Fd(fd as c_int).close();
+        return null_mut();
+    }
+    let file = FILE::new(fd as c_int, flags.is_readable(), flags.is_writable(), None);
+    unsafe { stream.write(file) };
+    *slot = stream;
+    stream
+}
+
+/// Flushes and closes the stream, which can't be used afterwards, even if
+/// this fails.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fclose(stream: *mut FILE) -> c_int {
+    let result = unsafe { &*stream }.lock().close();
+    if !is_standard_stream(stream) {
+        let mut open_files = OPEN_FILES.lock();
+        if let Some(slot) = open_files.0.iter_mut().find(|slot| **slot == stream) {
+            *slot = null_mut();
+        }
+        unsafe {
+            stream.drop_in_place();
+            free(stream.cast());
+        }
+    }
+    result.map_or(EOF, |_| 0)
+}
+
+/// Writes everything that is buffered for the stream, or for all streams if
+/// it's null. For a stream that is being read, the bytes that were read ahead
+/// are given back, so that the file offset is where the program is.
+///
+/// # Safety
+/// `stream` must be null or an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fflush(stream: *mut FILE) -> c_int {
+    if !stream.is_null() {
+        return unsafe { &*stream }.lock().flush().map_or(EOF, |_| 0);
+    }
+
+    let mut result = 0;
+    let open_files = OPEN_FILES.lock();
+    let streams = open_files.0.iter().filter(|stream| !stream.is_null());
+    for stream in [&STDOUT, &STDERR]
+        .into_iter()
+        .chain(streams.map(|&stream| unsafe { &*stream }))
+    {
+        if stream.lock().flush_writes().is_err() {
+            result = EOF;
+        }
+    }
+    result
+}
+
+/// Reads up to `count` items of `size` bytes. Returns the number of items
+/// that were read completely, which is less than `count` only at the end of
+/// the file or on an error.
+///
+/// # Safety
+/// `ptr` must be valid for `count * size` bytes, and `stream` must be an
+/// open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fread(
+    ptr: *mut c_void,
+    size: usize,
+    count: usize,
+    stream: *mut FILE,
+) -> usize {
+    let Some(len) = size.checked_mul(count).filter(|&len| len > 0) else {
+        return 0;
+    };
+    let buf = unsafe { slice::from_raw_parts_mut(ptr.cast::<u8>(), len) };
+    unsafe { &*stream }.lock().read(buf) / size
+}
+
+/// Writes `count` items of `size` bytes. Returns the number of items that
+/// were written completely, which is less than `count` only on an error.
+///
+/// # Safety
+/// `ptr` must be valid for `count * size` bytes, and `stream` must be an
+/// open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fwrite(
+    ptr: *const c_void,
+    size: usize,
+    count: usize,
+    stream: *mut FILE,
+) -> usize {
+    let Some(len) = size.checked_mul(count).filter(|&len| len > 0) else {
+        return 0;
+    };
+    let buf = unsafe { slice::from_raw_parts(ptr.cast::<u8>(), len) };
+    unsafe { &*stream }.lock().write(buf) / size
+}
+
+/// Reads the next byte, or returns [`EOF`].
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fgetc(stream: *mut FILE) -> c_int {
+    unsafe { &*stream }
+        .lock()
+        .read_byte()
+        .map_or(EOF, c_int::from)
+}
+
+/// Reads a line, including the newline, but at most `size - 1` bytes, and
+/// terminates it. Returns null if nothing could be read.
+///
+/// # Safety
+/// `s` must be valid for `size` bytes, and `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fgets(s: *mut c_char, size: c_int, stream: *mut FILE) -> *mut c_char {
+    let Some(len) = usize::try_from(size)
+        .ok()
+        .and_then(|size| size.checked_sub(1))
+    else {
+        return null_mut();
+    };
+    let buf = unsafe { slice::from_raw_parts_mut(s.cast::<u8>(), len) };
+    let read = unsafe { &*stream }.lock().read_line(buf);
+    if read == 0 && len > 0 {
+        return null_mut();
+    }
+    unsafe { s.add(read).write(0) };
+    s
+}
+
+/// Writes `c`, converted to an `unsigned char`, and returns it, or [`EOF`]
+/// on an error.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fputc(c: c_int, stream: *mut FILE) -> c_int {
+    let byte = c as u8;
+    if unsafe { &*stream }.lock().write(&[byte]) == 1 {
+        c_int::from(byte)
+    } else {
+        EOF
+    }
+}
+
+/// Writes the string, without its terminator.
+///
+/// # Safety
+/// `s` must be a valid C string, and `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fputs(s: *const c_char, stream: *mut FILE) -> c_int {
+    let buf = unsafe { slice::from_raw_parts(s.cast::<u8>(), strlen(s)) };
+    if unsafe { &*stream }.lock().write(buf) == buf.len() {
+        0
+    } else {
+        EOF
+    }
+}
+
+/// Pushes `c`, converted to an `unsigned char`, back onto the stream, so that
+/// it's the next byte that is read. Only one byte can be pushed back until it
+/// is read again, and seeking discards it.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn ungetc(c: c_int, stream: *mut FILE) -> c_int {
+    if c == EOF {
+        return EOF;
+    }
+    let byte = c as u8;
+    if unsafe { &*stream }.lock().unread_byte(byte) {
+        c_int::from(byte)
+    } else {
+        EOF
+    }
+}
+
+/// Moves the stream to the offset, relative to [`SEEK_SET`], [`SEEK_CUR`] or
+/// [`SEEK_END`]. Clears the end of file flag.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fseek(stream: *mut FILE, offset: c_long, whence: c_int) -> c_int {
+    let Ok(whence) = Whence::try_from(whence as usize) else {
+        set_errno(Errno::EINVAL);
+        return -1;
+    };
+    unsafe { &*stream }
+        .lock()
+        .seek(offset as isize, whence)
+        .map_or(-1, |_| 0)
+}
+
+/// The offset of the stream, which includes what is buffered.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn ftell(stream: *mut FILE) -> c_long {
+    unsafe { &*stream }
+        .lock()
+        .tell()
+        .map_or(-1, |pos| pos as c_long)
+}
+
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn feof(stream: *mut FILE) -> c_int {
+    c_int::from(unsafe { &*stream }.lock().is_eof())
+}
+
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn ferror(stream: *mut FILE) -> c_int {
+    c_int::from(unsafe { &*stream }.lock().is_error())
+}
+
+/// Clears the end of file and error flags.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn clearerr(stream: *mut FILE) {
+    unsafe { &*stream }.lock().clear_flags();
+}
+
+/// The file descriptor of the stream.
+///
+/// # Safety
+/// `stream` must be an open stream.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fileno(stream: *mut FILE) -> c_int {
+    unsafe { &*stream }.lock().backend().0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_flags() {
+        let (rdonly, wronly, rdwr) = (OpenFlags::O_RDONLY, OpenFlags::O_WRONLY, OpenFlags::O_RDWR);
+        let (creat, trunc, append) = (OpenFlags::O_CREAT, OpenFlags::O_TRUNC, OpenFlags::O_APPEND);
+        assert_eq!(Some(rdonly), open_flags(b"r"));
+        assert_eq!(Some(rdonly), open_flags(b"rb"));
+        assert_eq!(Some(rdwr), open_flags(b"r+"));
+        assert_eq!(Some(rdwr), open_flags(b"rb+"));
+        assert_eq!(Some(wronly | creat | trunc), open_flags(b"w"));
+        assert_eq!(Some(rdwr | creat | trunc), open_flags(b"w+"));
+        assert_eq!(
+            Some(wronly | creat | trunc | OpenFlags::O_EXCL),
+            open_flags(b"wx")
+        );
+        assert_eq!(Some(wronly | creat | append), open_flags(b"a"));
+        assert_eq!(Some(rdwr | creat | append), open_flags(b"a+"));
+
+        assert_eq!(None, open_flags(b""));
+        assert_eq!(None, open_flags(b"+"));
+        assert_eq!(None, open_flags(b"rx"));
+        assert_eq!(None, open_flags(b"rw"));
+        assert_eq!(None, open_flags(b"q"));
+    }
+}
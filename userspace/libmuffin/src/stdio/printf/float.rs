@@ -0,0 +1,454 @@
+//! The decimal digits of doubles for `%f`, `%e` and `%g`.
+//!
+//! The digits are exact: the double is turned into a big integer, scaled by
+//! a power of ten and rounded half to even, like glibc does. Only the first
+//! [`MAX_PRECISION`] digits after the decimal point are computed, any further
+//! ones are zeros. The decimal point is the one of the current locale.
+
+use core::cmp::Ordering;
+
+use crate::locale;
+
+/// The most digits after the decimal point that are computed.
+pub const MAX_PRECISION: usize = 150;
+
+/// Enough for 2^1024 * 10^474, the largest number that [`scaled`] computes.
+const LIMBS: usize = 64;
+
+/// An unsigned integer of up to `LIMBS * 32` bits.
+struct Big {
+    /// Least significant first.
+    limbs: [u32; LIMBS],
+    len: usize,
+}
+
+impl Big {
+    fn new(value: u64) -> Self {
+        let mut big = Self {
+            limbs: [0; LIMBS],
+            len: 2,
+        };
+        big.limbs[0] = value as u32;
+        big.limbs[1] = (value >> 32) as u32;
+        big.trim();
+        big
+    }
+
+    fn trim(&mut self) {
+        while self.len > 0 && self.limbs[self.len - 1] == 0 {
+            self.len -= 1;
+        }
+    }
+
+    fn is_odd(&self) -> bool {
+        self.limbs[0] & 1 == 1
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0;
+        for limb in &mut self.limbs[..self.len] {
+            let product = u64::from(*limb) * u64::from(factor) + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry > 0 {
+            self.limbs[self.len] = carry as u32;
+            self.len += 1;
+        }
+    }
+
+    fn mul_pow10(&mut self, mut exponent: usize) {
+        while exponent >= 9 {
+            self.mul_small(1_000_000_000);
+            exponent -= 9;
+        }
+        self.mul_small(10_u32.pow(exponent as u32));
+    }
+
+    /// Divides by `divisor` and returns the remainder.
+    fn div_small(&mut self, divisor: u32) -> u32 {
+        let mut remainder = 0;
+        for limb in self.limbs[..self.len].iter_mut().rev() {
+            let dividend = (remainder << 32) | u64::from(*limb);
+            *limb = (dividend / u64::from(divisor)) as u32;
+            remainder = dividend % u64::from(divisor);
+        }
+        self.trim();
+        remainder as u32
+    }
+
+    /// Divides by 10 to the power of `exponent`, and returns whether there
+    /// was a remainder.
+    fn div_pow10(&mut self, mut exponent: usize) -> bool {
+        let mut inexact = false;
+        while exponent >= 9 {
+            inexact |= self.div_small(1_000_000_000) != 0;
+            exponent -= 9;
+        }
+        inexact |= self.div_small(10_u32.pow(exponent as u32)) != 0;
+        inexact
+    }
+
+    fn shl(&mut self, bits: usize) {
+        let (limbs, bits) = (bits / 32, bits % 32);
+        if bits > 0 {
+            self.mul_small(1 << bits);
+        }
+        if limbs > 0 && self.len > 0 {
+            self.limbs.copy_within(..self.len, limbs);
+            self.limbs[..limbs].fill(0);
+            self.len += limbs;
+        }
+    }
+
+    /// Shifts right, and returns whether any of the bits that were shifted
+    /// out were set.
+    fn shr(&mut self, bits: usize) -> bool {
+        let (limbs, bits) = (bits / 32, bits % 32);
+        if limbs >= self.len {
+            let inexact = self.len > 0;
+            self.len = 0;
+            return inexact;
+        }
+        let mut inexact = self.limbs[..limbs].iter().any(|&limb| limb != 0);
+        self.limbs.copy_within(limbs..self.len, 0);
+        self.len -= limbs;
+        if bits > 0 {
+            inexact |= self.div_small(1 << bits) != 0;
+        }
+        inexact
+    }
+
+    fn increment(&mut self) {
+        for limb in &mut self.limbs[..self.len] {
+            let (sum, overflow) = limb.overflowing_add(1);
+            *limb = sum;
+            if !overflow {
+                return;
+            }
+        }
+        self.limbs[self.len] = 1;
+        self.len += 1;
+    }
+
+    /// Writes the decimal digits to the end of `buf`, which must be large
+    /// enough for them and some slack, and returns where they start. Zero
+    /// has no digits.
+    fn write_digits(mut self, buf: &mut [u8]) -> usize {
+        let mut start = buf.len();
+        while self.len > 0 {
+            let mut chunk = self.div_small(1_000_000_000);
+            for _ in 0..9 {
+                start -= 1;
+                buf[start] = b'0' + (chunk % 10) as u8;
+                chunk /= 10;
+            }
+        }
+        while start < buf.len() && buf[start] == b'0' {
+            start += 1;
+        }
+        start
+    }
+}
+
+/// `value` as `mantissa * 2^exponent`.
+fn decompose(value: f64) -> (u64, isize) {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 52) & 0x7FF) as isize;
+    let fraction = bits & ((1 << 52) - 1);
+    if exponent == 0 {
+        (fraction, -1074)
+    } else {
+        (fraction | (1 << 52), exponent - 1075)
+    }
+}
+
+/// `value * 10^scale`, rounded to an integer, half to even. `value` must be
+/// finite and not negative.
+fn scaled(value: f64, scale: isize) -> Big {
+    let (mantissa, exponent) = decompose(value);
+    let mut n = Big::new(mantissa);
+    if exponent > 0 {
+        n.shl(exponent as usize);
+    }
+    if scale > 0 {
+        n.mul_pow10(scale as usize);
+    }
+    // dividing twice the number keeps the bit that decides the rounding
+    n.shl(1);
+    let mut inexact = false;
+    if exponent < 0 {
+        inexact |= n.shr(exponent.unsigned_abs());
+    }
+    if scale < 0 {
+        inexact |= n.div_pow10(scale.unsigned_abs());
+    }
+    let half = n.is_odd();
+    n.shr(1);
+    if half && (inexact || n.is_odd()) {
+        n.increment();
+    }
+    n
+}
+
+/// The text of a formatted double, without its sign: `digits`, then `zeros`
+/// zeros, then `exponent`.
+pub struct Decimal {
+    buf: [u8; 520],
+    end: usize,
+    pub zeros: usize,
+    point: u8,
+    exponent_buf: [u8; 6],
+    exponent_len: usize,
+}
+
+impl Decimal {
+    fn new() -> Self {
+        Self {
+            buf: [0; 520],
+            end: 0,
+            zeros: 0,
+            point: locale::current().decimal_point(),
+            exponent_buf: [0; 6],
+            exponent_len: 0,
+        }
+    }
+
+    pub fn digits(&self) -> &[u8] {
+        &self.buf[..self.end]
+    }
+
+    pub fn exponent(&self) -> &[u8] {
+        &self.exponent_buf[..self.exponent_len]
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.buf[self.end] = byte;
+        self.end += 1;
+    }
+
+    /// Removes the zeros at the end of the fraction, and the decimal point
+    /// if nothing is left after it.
+    fn strip_fraction(&mut self) {
+        if !self.digits().contains(&self.point) {
+            return;
+        }
+        self.zeros = 0;
+        while self.buf[self.end - 1] == b'0' {
+            self.end -= 1;
+        }
+        if self.buf[self.end - 1] == self.point {
+            self.end -= 1;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Style {
+    /// `%f`: `ddd.ddd`.
+    Fixed,
+    /// `%e`: `d.ddde+dd`.
+    Exponent,
+    /// `%g`: whichever of the two is shorter, without trailing zeros.
+    General,
+}
+
+/// Formats the finite, not negative `value` with `precision` digits after the
+/// decimal point, or significant digits for [`Style::General`]. `alternate`
+/// keeps the decimal point even without digits after it, and trailing zeros.
+pub fn format(value: f64, style: Style, precision: usize, alternate: bool, upper: bool) -> Decimal {
+    match style {
+        Style::Fixed => fixed(value, precision, alternate),
+        Style::Exponent => exponential(value, precision, alternate, upper).0,
+        Style::General => {
+            let precision = precision.max(1);
+            let (mut decimal, exponent) = exponential(value, precision - 1, alternate, upper);
+            if exponent >= -4 && exponent < precision as isize {
+                decimal = fixed(
+                    value,
+                    (precision as isize - 1 - exponent) as usize,
+                    alternate,
+                );
+            }
+            if !alternate {
+                decimal.strip_fraction();
+            }
+            decimal
+        }
+    }
+}
+
+fn fixed(value: f64, precision: usize, alternate: bool) -> Decimal {
+    let mut decimal = Decimal::new();
+    let computed = precision.min(MAX_PRECISION);
+    decimal.zeros = precision - computed;
+
+    let mut digits = [0; 520];
+    let start = scaled(value, computed as isize).write_digits(&mut digits);
+    let digits = &digits[start..];
+    // there is at least one digit in front of the point
+    let leading = (computed + 1).saturating_sub(digits.len());
+    let integer = digits.len() + leading - computed;
+    for i in 0..leading + digits.len() {
+        if i == integer {
+            decimal.push(decimal.point);
+        }
+        decimal.push(if i < leading {
+            b'0'
+        } else {
+            digits[i - leading]
+        });
+    }
+    if computed == 0 && (alternate || decimal.zeros > 0) {
+        decimal.push(decimal.point);
+    }
+    decimal
+}
+
+/// Also returns the decimal exponent.
+fn exponential(value: f64, precision: usize, alternate: bool, upper: bool) -> (Decimal, isize) {
+    let mut decimal = Decimal::new();
+    let computed = precision.min(MAX_PRECISION);
+    decimal.zeros = precision - computed;
+
+    let mut digits = [0; 520];
+    let (start, exponent) = if value == 0.0 {
+        let start = digits.len() - computed - 1;
+        digits[start..].fill(b'0');
+        (start, 0)
+    } else {
+        // floor(log2(value)) * log10(2) is the exponent, or one less
+        let (mantissa, exponent) = decompose(value);
+        let log2 = 63 - mantissa.leading_zeros() as isize + exponent;
+        let mut exponent = (log2 * 78913) >> 18;
+        loop {
+            let start = scaled(value, computed as isize - exponent).write_digits(&mut digits);
+            match (digits.len() - start).cmp(&(computed + 1)) {
+                Ordering::Equal => break (start, exponent),
+                // one digit too many, or rounding carried into a new digit
+                Ordering::Greater => exponent += 1,
+                // the estimate is a little too large for some tiny numbers
+                Ordering::Less => exponent -= 1,
+            }
+        }
+    };
+    let digits = &digits[start..];
+    decimal.push(digits[0]);
+    if computed > 0 || alternate || decimal.zeros > 0 {
+        decimal.push(decimal.point);
+    }
+    for &digit in &digits[1..] {
+        decimal.push(digit);
+    }
+
+    let e = &mut decimal.exponent_buf;
+    e[0] = if upper { b'E' } else { b'e' };
+    e[1] = if exponent < 0 { b'-' } else { b'+' };
+    let magnitude = exponent.unsigned_abs();
+    let mut len = 2;
+    if magnitude >= 100 {
+        e[len] = b'0' + (magnitude / 100) as u8;
+        len += 1;
+    }
+    e[len] = b'0' + (magnitude / 10 % 10) as u8;
+    e[len + 1] = b'0' + (magnitude % 10) as u8;
+    decimal.exponent_len = len + 2;
+    (decimal, exponent)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::string::String;
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    fn text(value: f64, style: Style, precision: usize, alternate: bool) -> String {
+        let decimal = format(value, style, precision, alternate, false);
+        let mut bytes = Vec::from(decimal.digits());
+        bytes.resize(bytes.len() + decimal.zeros, b'0');
+        bytes.extend_from_slice(decimal.exponent());
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_fixed() {
+        let f = |value, precision| text(value, Style::Fixed, precision, false);
+        assert_eq!("0.000000", f(0.0, 6));
+        assert_eq!("3.141593", f(core::f64::consts::PI, 6));
+        assert_eq!("3", f(core::f64::consts::PI, 0));
+        assert_eq!("3.", text(core::f64::consts::PI, Style::Fixed, 0, true));
+        assert_eq!("0.1000000000000000055511151231257827", f(0.1, 34));
+        assert_eq!(
+            "123456789012345677877719597056.0",
+            f(1.2345678901234568e29, 1)
+        );
+        assert!(f(f64::MAX, 2).starts_with("17976931348623157081452742373170435679807056752584"));
+        assert_eq!(309 + 3, f(f64::MAX, 2).len());
+    }
+
+    #[test]
+    fn test_rounding_is_exact() {
+        let f = |value, precision| text(value, Style::Fixed, precision, false);
+        // ties go to even
+        assert_eq!("0", f(0.5, 0));
+        assert_eq!("2", f(1.5, 0));
+        assert_eq!("2", f(2.5, 0));
+        assert_eq!("0.12", f(0.125, 2));
+        assert_eq!("0.38", f(0.375, 2));
+        // 1.005 is slightly below that as a double
+        assert_eq!("1.00", f(1.005, 2));
+        assert_eq!("10.0", f(9.96, 1));
+        assert_eq!("1.000e+01", text(9.9996, Style::Exponent, 3, false));
+    }
+
+    #[test]
+    fn test_exponent() {
+        let e = |value, precision| text(value, Style::Exponent, precision, false);
+        assert_eq!("0.00e+00", e(0.0, 2));
+        assert_eq!("1.235e+04", e(12345.678, 3));
+        assert_eq!("1e+00", e(1.0, 0));
+        assert_eq!("1.e+00", text(1.0, Style::Exponent, 0, true));
+        assert_eq!("1.000e-310", e(1e-310, 3));
+        assert_eq!("4.9406564584124654e-324", e(5e-324, 16));
+        assert_eq!("1.7976931348623157e+308", e(f64::MAX, 16));
+        assert_eq!("1.00E+100", text_upper(1e100, 2));
+    }
+
+    fn text_upper(value: f64, precision: usize) -> String {
+        let decimal = format(value, Style::Exponent, precision, false, true);
+        let mut bytes = Vec::from(decimal.digits());
+        bytes.extend_from_slice(decimal.exponent());
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_general() {
+        let g = |value, precision| text(value, Style::General, precision, false);
+        assert_eq!("100000", g(100000.0, 6));
+        assert_eq!("1e+06", g(1000000.0, 6));
+        assert_eq!("0.0001", g(0.0001, 6));
+        assert_eq!("1e-05", g(0.00001, 6));
+        assert_eq!("123.456", g(123.456, 6));
+        assert_eq!("0.5", g(0.5, 0));
+        assert_eq!("1e+01", g(9.5, 1));
+        assert_eq!("0", g(0.0, 6));
+        assert_eq!("1.00000", text(1.0, Style::General, 6, true));
+        assert_eq!("3.14159", g(core::f64::consts::PI, 6));
+    }
+
+    #[test]
+    fn test_beyond_max_precision() {
+        let f = |value, precision| text(value, Style::Fixed, precision, false);
+        let half = f(0.5, MAX_PRECISION + 2);
+        assert_eq!(MAX_PRECISION + 4, half.len());
+        assert!(half.starts_with("0.5") && half[3..].bytes().all(|b| b == b'0'));
+        let tiny = f(5e-324, MAX_PRECISION + 10);
+        assert!(tiny[2..].bytes().all(|b| b == b'0'));
+        let e = text(1.0, Style::Exponent, MAX_PRECISION + 1, false);
+        assert!(e.ends_with("00e+00"));
+        assert_eq!(MAX_PRECISION + 7, e.len());
+    }
+}
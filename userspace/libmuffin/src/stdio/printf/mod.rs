@@ -0,0 +1,696 @@
+//! The `printf` family.
+//!
+//! Conversions are `d`, `i`, `u`, `o`, `x`, `X`, `c`, `s`, `p`, `f`, `F`,
+//! `e`, `E`, `g`, `G` and `%`, with the flags `-`, `0`, `+`, space and `#`,
+//! a width and a precision, either of which can be `*`, and the length
+//! modifiers `hh`, `h`, `l`, `ll`, `j`, `z` and `t`. There is no `long double`,
+//! so `L` is not supported, and neither is `%n`. A conversion that can't be
+//! parsed is written as it is.
+
+use core::ffi::{c_char, c_int, c_void, CStr, VaList};
+use core::slice;
+
+use crate::stdio::printf::float::Style;
+use crate::stdio::stream::Stream;
+use crate::stdio::{stdout, Fd, FILE};
+use crate::string::{strlen, strnlen};
+
+mod float;
+
+/// Where the formatted bytes go.
+trait Sink {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// A C buffer of some size, which the bytes that don't fit are cut from.
+struct Buffer {
+    ptr: *mut u8,
+    /// Without the terminator.
+    capacity: usize,
+    len: usize,
+}
+
+impl Buffer {
+    /// A buffer for `size` bytes, including the terminator.
+    fn new(ptr: *mut c_char, size: usize) -> Self {
+        Self {
+            ptr: ptr.cast(),
+            capacity: size.saturating_sub(1),
+            len: 0,
+        }
+    }
+
+    /// Writes the terminator, if there is room for one.
+    ///
+    /// # Safety
+    /// The buffer must be valid for the size it was created with.
+    unsafe fn terminate(self, size: usize) {
+        if size > 0 {
+            unsafe { self.ptr.add(self.len).write(0) };
+        }
+    }
+}
+
+impl Sink for Buffer {
+    fn write(&mut self, bytes: &[u8]) {
+        let len = bytes.len().min(self.capacity - self.len);
+        if len == 0 {
+            return;
+        }
+        unsafe {
+            self.ptr
+                .add(self.len)
+                .copy_from_nonoverlapping(bytes.as_ptr(), len)
+        };
+        self.len += len;
+    }
+}
+
+/// A stream that remembers whether a write failed.
+struct StreamSink<'a> {
+    stream: &'a mut Stream<Fd>,
+    failed: bool,
+}
+
+impl Sink for StreamSink<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        if !self.failed && self.stream.write(bytes) < bytes.len() {
+            self.failed = true;
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Length {
+    Default,
+    Char,
+    Short,
+    Long,
+    LongLong,
+    IntMax,
+    Size,
+    PtrDiff,
+}
+
+/// A conversion specification, without the conversion itself.
+#[derive(Debug, Default)]
+struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+enum Piece<'a> {
+    Bytes(&'a [u8]),
+    Zeros(usize),
+}
+
+impl Piece<'_> {
+    fn len(&self) -> usize {
+        match self {
+            Piece::Bytes(bytes) => bytes.len(),
+            Piece::Zeros(count) => *count,
+        }
+    }
+}
+
+/// Counts what is written to the sink, including what doesn't fit into it.
+struct Writer<'a, S> {
+    sink: &'a mut S,
+    count: usize,
+}
+
+impl<S: Sink> Writer<'_, S> {
+    fn write(&mut self, bytes: &[u8]) {
+        self.sink.write(bytes);
+        self.count += bytes.len();
+    }
+
+    fn repeat(&mut self, byte: u8, mut count: usize) {
+        let chunk = [byte; 32];
+        while count > 0 {
+            let len = count.min(chunk.len());
+            self.write(&chunk[..len]);
+            count -= len;
+        }
+    }
+
+    fn piece(&mut self, piece: &Piece<'_>) {
+        match *piece {
+            Piece::Bytes(bytes) => self.write(bytes),
+            Piece::Zeros(count) => self.repeat(b'0', count),
+        }
+    }
+
+    /// Writes the prefix and the body, padded to the width of the spec. With
+    /// `zero_pad`, the padding are zeros between the two.
+    fn pad(&mut self, spec: &Spec, prefix: &[u8], body: &[Piece<'_>], zero_pad: bool) {
+        let len = prefix.len() + body.iter().map(Piece::len).sum::<usize>();
+        let fill = spec.width.saturating_sub(len);
+        if !spec.left && !zero_pad {
+            self.repeat(b' ', fill);
+        }
+        self.write(prefix);
+        if !spec.left && zero_pad {
+            self.repeat(b'0', fill);
+        }
+        for piece in body {
+            self.piece(piece);
+        }
+        if spec.left {
+            self.repeat(b' ', fill);
+        }
+    }
+
+    /// Writes an integer of the given base. `sign` is what goes in front of
+    /// it, if anything.
+    fn integer(&mut self, spec: &Spec, sign: &[u8], value: u64, base: u64, upper: bool) {
+        let alphabet = if upper {
+            b"0123456789ABCDEF"
+        } else {
+            b"0123456789abcdef"
+        };
+        let mut buf = [0; 24];
+        let mut start = buf.len();
+        let mut rest = value;
+        while rest > 0 {
+            start -= 1;
+            buf[start] = alphabet[(rest % base) as usize];
+            rest /= base;
+        }
+        let digits = &buf[start..];
+
+        // the digits are at least as many as the precision, and without one
+        // there is at least one, even for zero
+        let mut min_digits = spec.precision.unwrap_or(1);
+        let mut prefix = sign;
+        if spec.alternate {
+            match base {
+                8 => min_digits = min_digits.max(digits.len() + 1),
+                16 if value != 0 => prefix = if upper { b"0X" } else { b"0x" },
+                _ => {}
+            }
+        }
+        let zeros = Piece::Zeros(min_digits.saturating_sub(digits.len()));
+        let zero_pad = spec.zero && !spec.left && spec.precision.is_none();
+        self.pad(spec, prefix, &[zeros, Piece::Bytes(digits)], zero_pad);
+    }
+
+    fn float(&mut self, spec: &Spec, value: f64, conversion: u8) {
+        let upper = conversion.is_ascii_uppercase();
+        let sign: &[u8] = if value.is_sign_negative() {
+            b"-"
+        } else if spec.plus {
+            b"+"
+        } else if spec.space {
+            b" "
+        } else {
+            b""
+        };
+        if !value.is_finite() {
+            let text: &[u8] = match (value.is_nan(), upper) {
+                (true, false) => b"nan",
+                (true, true) => b"NAN",
+                (false, false) => b"inf",
+                (false, true) => b"INF",
+            };
+            self.pad(spec, sign, &[Piece::Bytes(text)], false);
+            return;
+        }
+
+        let style = match conversion.to_ascii_lowercase() {
+            b'f' => Style::Fixed,
+            b'e' => Style::Exponent,
+            _ => Style::General,
+        };
+        let precision = spec.precision.unwrap_or(6);
+        let decimal = float::format(value.abs(), style, precision, spec.alternate, upper);
+        let body = [
+            Piece::Bytes(decimal.digits()),
+            Piece::Zeros(decimal.zeros),
+            Piece::Bytes(decimal.exponent()),
+        ];
+        self.pad(spec, sign, &body, spec.zero && !spec.left);
+    }
+}
+
+/// Formats `fmt` with `args` into `sink`, and returns the number of bytes
+/// that were written.
+///
+/// # Safety
+/// `args` must match the conversions in `fmt`.
+unsafe fn format<S: Sink>(sink: &mut S, fmt: &[u8], mut args: VaList<'_>) -> usize {
+    let mut writer = Writer { sink, count: 0 };
+    let mut rest = fmt;
+    while !rest.is_empty() {
+        let literal = rest.iter().position(|&b| b == b'%').unwrap_or(rest.len());
+        writer.write(&rest[..literal]);
+        rest = &rest[literal..];
+        if rest.is_empty() {
+            break;
+        }
+
+        let len = unsafe { conversion(&mut writer, rest, &mut args) }.unwrap_or_else(|| {
+            // written as it is, up to where it stopped making sense
+            let len = rest[1..]
+                .iter()
+                .position(|&b| b.is_ascii_alphabetic() || b == b'%')
+                .map_or(rest.len(), |pos| pos + 2);
+            writer.write(&rest[..len]);
+            len
+        });
+        rest = &rest[len..];
+    }
+    writer.count
+}
+
+/// Parses and writes the conversion at the start of `fmt`, which starts with
+/// `%`, and returns its length. Nothing is written if it can't be parsed.
+///
+/// # Safety
+/// `args` must match the conversion.
+unsafe fn conversion<S: Sink>(
+    writer: &mut Writer<'_, S>,
+    fmt: &[u8],
+    args: &mut VaList<'_>,
+) -> Option<usize> {
+    let mut spec = Spec::default();
+    let mut i = 1;
+    while let Some(&flag) = fmt.get(i) {
+        match flag {
+            b'-' => spec.left = true,
+            b'0' => spec.zero = true,
+            b'+' => spec.plus = true,
+            b' ' => spec.space = true,
+            b'#' => spec.alternate = true,
+            _ => break,
+        }
+        i += 1;
+    }
+
+    if fmt.get(i) == Some(&b'*') {
+        let width = unsafe { args.next_arg::<c_int>() };
+        // a negative width is the `-` flag
+        spec.left |= width < 0;
+        spec.width = width.unsigned_abs() as usize;
+        i += 1;
+    } else {
+        spec.width = number(fmt, &mut i);
+    }
+
+    if fmt.get(i) == Some(&b'.') {
+        i += 1;
+        if fmt.get(i) == Some(&b'*') {
+            // a negative precision is as if there was none
+            let precision = unsafe { args.next_arg::<c_int>() };
+            spec.precision = usize::try_from(precision).ok();
+            i += 1;
+        } else {
+            spec.precision = Some(number(fmt, &mut i));
+        }
+    }
+
+    let length = match (fmt.get(i), fmt.get(i + 1)) {
+        (Some(b'h'), Some(b'h')) => Length::Char,
+        (Some(b'h'), _) => Length::Short,
+        (Some(b'l'), Some(b'l')) => Length::LongLong,
+        (Some(b'l'), _) => Length::Long,
+        (Some(b'j'), _) => Length::IntMax,
+        (Some(b'z'), _) => Length::Size,
+        (Some(b't'), _) => Length::PtrDiff,
+        _ => Length::Default,
+    };
+    i += match length {
+        Length::Default => 0,
+        Length::Char | Length::LongLong => 2,
+        _ => 1,
+    };
+
+    let conversion = *fmt.get(i)?;
+    match conversion {
+        b'd' | b'i' => {
+            let value = unsafe {
+                match length {
+                    Length::Default => i64::from(args.next_arg::<c_int>()),
+                    Length::Char => i64::from(args.next_arg::<c_int>() as i8),
+                    Length::Short => i64::from(args.next_arg::<c_int>() as i16),
+                    Length::Long | Length::LongLong | Length::IntMax => args.next_arg::<i64>(),
+                    Length::Size | Length::PtrDiff => args.next_arg::<isize>() as i64,
+                }
+            };
+            let sign: &[u8] = if value < 0 {
+                b"-"
+            } else if spec.plus {
+                b"+"
+            } else if spec.space {
+                b" "
+            } else {
+                b""
+            };
+            writer.integer(&spec, sign, value.unsigned_abs(), 10, false);
+        }
+        b'u' | b'o' | b'x' | b'X' => {
+            let value = unsafe {
+                match length {
+                    Length::Default => u64::from(args.next_arg::<u32>()),
+                    Length::Char => u64::from(args.next_arg::<u32>() as u8),
+                    Length::Short => u64::from(args.next_arg::<u32>() as u16),
+                    Length::Long | Length::LongLong | Length::IntMax => args.next_arg::<u64>(),
+                    Length::Size | Length::PtrDiff => args.next_arg::<usize>() as u64,
+                }
+            };
+            let base = match conversion {
+                b'u' => 10,
+                b'o' => 8,
+                _ => 16,
+            };
+            writer.integer(&spec, b"", value, base, conversion == b'X');
+        }
+        b'f' | b'F' | b'e' | b'E' | b'g' | b'G' if length == Length::Default => {
+            let value = unsafe { args.next_arg::<f64>() };
+            writer.float(&spec, value, conversion);
+        }
+        b'c' if length == Length::Default => {
+            let c = unsafe { args.next_arg::<c_int>() } as u8;
+            writer.pad(&spec, b"", &[Piece::Bytes(&[c])], false);
+        }
+        b's' if length == Length::Default => {
+            let s = unsafe { args.next_arg::<*const c_char>() };
+            let bytes: &[u8] = if s.is_null() {
+                b"(null)"
+            } else {
+                let len = match spec.precision {
+                    Some(precision) => unsafe { strnlen(s, precision) },
+                    None => unsafe { strlen(s) },
+                };
+                unsafe { slice::from_raw_parts(s.cast(), len) }
+            };
+            let len = bytes.len().min(spec.precision.unwrap_or(usize::MAX));
+            writer.pad(&spec, b"", &[Piece::Bytes(&bytes[..len])], false);
+        }
+        b'p' if length == Length::Default => {
+            let p = unsafe { args.next_arg::<*const c_void>() };
+            if p.is_null() {
+                writer.pad(&spec, b"", &[Piece::Bytes(b"(nil)")], false);
+            } else {
+                spec.alternate = true;
+                writer.integer(&spec, b"", p.addr() as u64, 16, false);
+            }
+        }
+        b'%' => writer.write(b"%"),
+        _ => return None,
+    }
+    Some(i + 1)
+}
+
+/// Parses the decimal number at `fmt[*i..]`, if there is one, and moves `i`
+/// past it.
+fn number(fmt: &[u8], i: &mut usize) -> usize {
+    let mut value = 0_usize;
+    while let Some(digit) = fmt.get(*i).filter(|b| b.is_ascii_digit()) {
+        value = value
+            .saturating_mul(10)
+            .saturating_add(usize::from(digit - b'0'));
+        *i += 1;
+    }
+    value
+}
+
+/// The return value for `count` bytes, which is -1 if that doesn't fit.
+fn count_result(count: usize) -> c_int {
+    c_int::try_from(count).unwrap_or(-1)
+}
+
+/// Writes the formatted output to `stdout`, and returns the number of bytes
+/// written, or a negative value on an error.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/printf.html>.
+///
+/// # Safety
+/// `format` must be a valid C string, and the arguments must match its
+/// conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn printf(format: *const c_char, args: ...) -> c_int {
+    unsafe { vfprintf(stdout, format, args) }
+}
+
+/// Like [`printf`], but writes to `stream`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/fprintf.html>.
+///
+/// # Safety
+/// `stream` must be an open stream, `format` must be a valid C string, and
+/// the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn fprintf(stream: *mut FILE, format: *const c_char, args: ...) -> c_int {
+    unsafe { vfprintf(stream, format, args) }
+}
+
+/// Like [`printf`], but with the arguments as a `va_list`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/vprintf.html>.
+///
+/// # Safety
+/// `format` must be a valid C string, and the arguments must match its
+/// conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn vprintf(format: *const c_char, args: VaList<'_>) -> c_int {
+    unsafe { vfprintf(stdout, format, args) }
+}
+
+/// Like [`fprintf`], but with the arguments as a `va_list`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/vfprintf.html>.
+///
+/// # Safety
+/// `stream` must be an open stream, `format` must be a valid C string, and
+/// the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn vfprintf(
+    stream: *mut FILE,
+    format: *const c_char,
+    args: VaList<'_>,
+) -> c_int {
+    let fmt = unsafe { CStr::from_ptr(format) }.to_bytes();
+    let mut stream = unsafe { &*stream }.lock();
+    let mut sink = StreamSink {
+        stream: &mut stream,
+        failed: false,
+    };
+    let count = unsafe { self::format(&mut sink, fmt, args) };
+    if sink.failed {
+        return -1;
+    }
+    count_result(count)
+}
+
+/// Writes the formatted output and a terminator to `s`, which must be large
+/// enough for it.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/sprintf.html>.
+///
+/// # Safety
+/// `s` must be large enough for the output, `format` must be a valid C string,
+/// and the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn sprintf(s: *mut c_char, format: *const c_char, args: ...) -> c_int {
+    unsafe { vsprintf(s, format, args) }
+}
+
+/// Like [`sprintf`], but with the arguments as a `va_list`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/vsprintf.html>.
+///
+/// # Safety
+/// `s` must be large enough for the output, `format` must be a valid C string,
+/// and the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn vsprintf(
+    s: *mut c_char,
+    format: *const c_char,
+    args: VaList<'_>,
+) -> c_int {
+    unsafe { vsnprintf(s, usize::MAX, format, args) }
+}
+
+/// Writes at most `size` bytes of the formatted output to `s`, including a
+/// terminator, unless `size` is 0. Returns the length that the output would
+/// have had, so a result of `size` or more means it was cut off.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/snprintf.html>.
+///
+/// # Safety
+/// `s` must be valid for `size` bytes, `format` must be a valid C string, and
+/// the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn snprintf(
+    s: *mut c_char,
+    size: usize,
+    format: *const c_char,
+    args: ...
+) -> c_int {
+    unsafe { vsnprintf(s, size, format, args) }
+}
+
+/// Like [`snprintf`], but with the arguments as a `va_list`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/vsnprintf.html>.
+///
+/// # Safety
+/// `s` must be valid for `size` bytes, `format` must be a valid C string, and
+/// the arguments must match its conversions.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn vsnprintf(
+    s: *mut c_char,
+    size: usize,
+    format: *const c_char,
+    args: VaList<'_>,
+) -> c_int {
+    let fmt = unsafe { CStr::from_ptr(format) }.to_bytes();
+    let mut buffer = Buffer::new(s, size);
+    let count = unsafe { self::format(&mut buffer, fmt, args) };
+    unsafe { buffer.terminate(size) };
+    count_result(count)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::string::String;
+
+    use super::*;
+
+    /// Formats with our `snprintf` into a large buffer, and checks that the
+    /// returned length matches.
+    macro_rules! sprintf {
+        ($fmt:expr $(, $arg:expr)* $(,)?) => {{
+            let mut buf = [0 as c_char; 512];
+            let len = unsafe { snprintf(buf.as_mut_ptr(), buf.len(), $fmt.as_ptr() $(, $arg)*) };
+            let s = unsafe { CStr::from_ptr(buf.as_ptr()) };
+            assert_eq!(len as usize, s.count_bytes());
+            String::from(s.to_str().unwrap())
+        }};
+    }
+
+    #[test]
+    fn test_integers() {
+        assert_eq!("42", sprintf!(c"%d", 42));
+        assert_eq!("-2147483648", sprintf!(c"%d", c_int::MIN));
+        assert_eq!("-9223372036854775808", sprintf!(c"%lld", i64::MIN));
+        assert_eq!("18446744073709551615", sprintf!(c"%llu", u64::MAX));
+        assert_eq!("4294967295", sprintf!(c"%u", u32::MAX));
+        assert_eq!(
+            "-1 255 -1 65535",
+            sprintf!(c"%hhd %hhu %hd %hu", 255, 255, 65535, 65535)
+        );
+        assert_eq!("ff FF 777", sprintf!(c"%x %X %o", 255, 255, 0o777));
+        assert_eq!(
+            "12345678 -5",
+            sprintf!(c"%zu %ti", 12345678_usize, -5_isize)
+        );
+        assert_eq!(
+            "0x1f 0X1F 017 0 0",
+            sprintf!(c"%#x %#X %#o %#x %#o", 31, 31, 15, 0, 0)
+        );
+    }
+
+    #[test]
+    fn test_integer_padding() {
+        assert_eq!("[   42]", sprintf!(c"[%5d]", 42));
+        assert_eq!("[42   ]", sprintf!(c"[%-5d]", 42));
+        assert_eq!("[00042]", sprintf!(c"[%05d]", 42));
+        assert_eq!("[-0042]", sprintf!(c"[%05d]", -42));
+        assert_eq!("[+42] [ 42]", sprintf!(c"[%+d] [% d]", 42, 42));
+        assert_eq!("[  042]", sprintf!(c"[%05.3d]", 42));
+        assert_eq!("[0x002a]", sprintf!(c"[%#06x]", 42));
+        assert_eq!(
+            "[] [    ] [0]",
+            sprintf!(c"[%.0d] [%4.0x] [%#.0o]", 0, 0, 0)
+        );
+        assert_eq!("[   42] [42   ]", sprintf!(c"[%*d] [%*d]", 5, 42, -5, 42));
+        assert_eq!("[00042] [42]", sprintf!(c"[%.*d] [%.*d]", 5, 42, -1, 42));
+    }
+
+    #[test]
+    fn test_characters_and_strings() {
+        assert_eq!(
+            "a [  b] [c  ]",
+            sprintf!(c"%c [%3c] [%-3c]", 'a' as c_int, 'b' as c_int, 'c' as c_int)
+        );
+        assert_eq!("hello", sprintf!(c"%s", c"hello".as_ptr()));
+        assert_eq!(
+            "[hel] [  hel]",
+            sprintf!(c"[%.3s] [%5.3s]", c"hello".as_ptr(), c"hello".as_ptr())
+        );
+        assert_eq!("[hi   ]", sprintf!(c"[%-5s]", c"hi".as_ptr()));
+        assert_eq!("(null)", sprintf!(c"%s", core::ptr::null::<c_char>()));
+        // the precision allows a string that isn't terminated
+        let unterminated = [b'a' as c_char; 4];
+        assert_eq!("aa", sprintf!(c"%.2s", unterminated.as_ptr()));
+        assert_eq!("100%", sprintf!(c"%d%%", 100));
+    }
+
+    #[test]
+    fn test_pointers() {
+        assert_eq!("0x1234", sprintf!(c"%p", 0x1234 as *const c_void));
+        assert_eq!("(nil)", sprintf!(c"%p", core::ptr::null::<c_void>()));
+        assert_eq!("[  0xff]", sprintf!(c"[%6p]", 0xff as *const c_void));
+    }
+
+    #[test]
+    fn test_floats() {
+        assert_eq!("3.141593", sprintf!(c"%f", core::f64::consts::PI));
+        assert_eq!("-0.000000", sprintf!(c"%f", -0.0));
+        assert_eq!(
+            "[  1.23] [1.23  ]",
+            sprintf!(c"[%6.2f] [%-6.2f]", 1.23456, 1.23456)
+        );
+        assert_eq!(
+            "[-001.23] [+1.2]",
+            sprintf!(c"[%07.2f] [%+.1f]", -1.23456, 1.23456)
+        );
+        assert_eq!(
+            "1.234500e+03 1.2345E+03",
+            sprintf!(c"%e %.4E", 1234.5, 1234.5)
+        );
+        assert_eq!(
+            "0.0001 1e-05 1.5",
+            sprintf!(c"%g %g %g", 0.0001, 0.00001, 1.5)
+        );
+        assert_eq!("1.00000 1E+10", sprintf!(c"%#g %G", 1.0, 1e10));
+        assert_eq!(
+            "inf -inf NAN",
+            sprintf!(c"%f %e %G", f64::INFINITY, f64::NEG_INFINITY, f64::NAN)
+        );
+        assert_eq!("[  inf]", sprintf!(c"[%05f]", f64::INFINITY));
+        assert_eq!("7.", sprintf!(c"%#.0f", 7.0));
+    }
+
+    #[test]
+    fn test_invalid_conversions() {
+        assert_eq!("%y %", sprintf!(c"%y %"));
+        assert_eq!("%-5Lf 1", sprintf!(c"%-5Lf %d", 1));
+    }
+
+    #[test]
+    fn test_snprintf_size() {
+        let mut buf = [b'x' as c_char; 8];
+        let len = unsafe { snprintf(buf.as_mut_ptr(), 4, c"%d".as_ptr(), 123456) };
+        assert_eq!(6, len);
+        assert_eq!(c"123", unsafe { CStr::from_ptr(buf.as_ptr()) });
+        assert_eq!(b'x' as c_char, buf[4]);
+
+        // nothing is written without a size, not even the terminator
+        let len = unsafe { snprintf(buf.as_mut_ptr(), 0, c"%s".as_ptr(), c"abc".as_ptr()) };
+        assert_eq!(3, len);
+        assert_eq!(b'1' as c_char, buf[0]);
+
+        let len = unsafe { snprintf(core::ptr::null_mut(), 0, c"%d".as_ptr(), -1) };
+        assert_eq!(2, len);
+    }
+}
@@ -0,0 +1,560 @@
+//! The buffering behind a `FILE`.
+//!
+//! A stream has a single buffer, which holds either bytes that were read
+//! ahead, or bytes that wait to be written, never both. Reading after writing
+//! flushes the buffer, and writing after reading gives the bytes that were
+//! read ahead back by seeking, so that the underlying file offset is always
+//! where the program thinks it is, no matter how reads, writes and seeks are
+//! mixed.
+
+use kernel_api::syscall::{Errno, Whence};
+
+/// The size of the buffer of buffered streams.
+pub const BUFSIZ: usize = 4096;
+
+/// What a stream reads from and writes to, which is a file descriptor outside
+/// of tests.
+pub trait Backend {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno>;
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Errno>;
+
+    fn seek(&mut self, offset: isize, whence: Whence) -> Result<usize, Errno>;
+
+    /// Whether this is an interactive device, whose output is line buffered.
+    fn is_terminal(&mut self) -> bool;
+
+    fn close(&mut self) -> Result<(), Errno>;
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Buffering {
+    /// Every write goes to the backend immediately.
+    Unbuffered,
+    /// Writes are buffered until a newline is written or the buffer is full.
+    Line,
+    /// Writes are buffered until the buffer is full.
+    Full,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum State {
+    Idle,
+    /// `buf[pos..end]` was read from the backend, but not by the program.
+    Reading {
+        pos: usize,
+        end: usize,
+    },
+    /// `buf[..len]` hasn't been written to the backend yet.
+    Writing {
+        len: usize,
+    },
+}
+
+pub struct Stream<B> {
+    backend: B,
+    readable: bool,
+    writable: bool,
+    /// Decided on the first read or write if it's not known when the stream
+    /// is created, because that needs to ask the backend.
+    buffering: Option<Buffering>,
+    state: State,
+    buf: [u8; BUFSIZ],
+    /// The byte that was pushed back with `ungetc`, which is read before
+    /// anything else.
+    pushback: Option<u8>,
+    eof: bool,
+    error: bool,
+}
+
+impl<B: Backend> Stream<B> {
+    pub const fn new(
+        backend: B,
+        readable: bool,
+        writable: bool,
+        buffering: Option<Buffering>,
+    ) -> Self {
+        Self {
+            backend,
+            readable,
+            writable,
+            buffering,
+            state: State::Idle,
+            buf: [0; BUFSIZ],
+            pushback: None,
+            eof: false,
+            error: false,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.error
+    }
+
+    pub fn clear_flags(&mut self) {
+        self.eof = false;
+        self.error = false;
+    }
+
+    fn buffering(&mut self) -> Buffering {
+        *self.buffering.get_or_insert_with(|| {
+            if self.backend.is_terminal() {
+                Buffering::Line
+            } else {
+                Buffering::Full
+            }
+        })
+    }
+
+    fn capacity(&mut self) -> usize {
+        match self.buffering() {
+            Buffering::Unbuffered => 0,
+            Buffering::Line | Buffering::Full => BUFSIZ,
+        }
+    }
+
+    /// The number of bytes that were read from the backend, but not by the
+    /// program.
+    fn unread(&self) -> usize {
+        let buffered = match self.state {
+            State::Reading { pos, end } => end - pos,
+            _ => 0,
+        };
+        buffered + usize::from(self.pushback.is_some())
+    }
+
+    /// Reads up to `dst.len()` bytes, and only returns fewer at the end of
+    /// the file or on an error, which set the respective flag.
+    pub fn read(&mut self, dst: &mut [u8]) -> usize {
+        if !self.readable {
+            self.error = true;
+            return 0;
+        }
+        if self.flush_writes().is_err() {
+            return 0;
+        }
+
+        let mut n = 0;
+        if !dst.is_empty() {
+            if let Some(byte) = self.pushback.take() {
+                dst[0] = byte;
+                n = 1;
+            }
+        }
+        let capacity = self.capacity();
+        while n < dst.len() {
+            if let State::Reading { pos, end } = &mut self.state {
+                if *pos < *end {
+                    let len = (*end - *pos).min(dst.len() - n);
+                    dst[n..n + len].copy_from_slice(&self.buf[*pos..*pos + len]);
+                    *pos += len;
+                    n += len;
+                    continue;
+                }
+            }
+
+            // the buffer is empty, so large reads bypass it
+            let rest = &mut dst[n..];
+            let result = if rest.len() >= capacity {
+                self.state = State::Idle;
+                self.backend.read(rest)
+            } else {
+                self.backend
+                    .read(&mut self.buf[..capacity])
+                    .inspect(|&len| {
+                        self.state = State::Reading { pos: 0, end: len };
+                    })
+            };
+            match result {
+                Ok(0) => {
+                    self.eof = true;
+                    break;
+                }
+                Ok(len) if rest.len() >= capacity => n += len,
+                Ok(_) => {}
+                Err(_) => {
+                    self.error = true;
+                    break;
+                }
+            }
+        }
+        n
+    }
+
+    /// Reads a single byte.
+    pub fn read_byte(&mut self) -> Option<u8> {
+        let mut byte = 0;
+        (self.read(core::slice::from_mut(&mut byte)) == 1).then_some(byte)
+    }
+
+    /// Reads up to and including the next newline, but at most `dst.len()`
+    /// bytes, and returns how many were read.
+    pub fn read_line(&mut self, dst: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < dst.len() {
+            let Some(byte) = self.read_byte() else {
+                break;
+            };
+            dst[n] = byte;
+            n += 1;
+            if byte == b'\n' {
+                break;
+            }
+        }
+        n
+    }
+
+    /// Pushes the byte back, so that it's the next one that is read. Only one
+    /// byte can be pushed back at a time.
+    pub fn unread_byte(&mut self, byte: u8) -> bool {
+        if self.pushback.is_some() || self.flush_writes().is_err() {
+            return false;
+        }
+        self.pushback = Some(byte);
+        self.eof = false;
+        true
+    }
+
+    /// Writes all of `src`, and only returns fewer bytes if writing to the
+    /// backend failed, which sets the error flag.
+    pub fn write(&mut self, src: &[u8]) -> usize {
+        if !self.writable {
+            self.error = true;
+            return 0;
+        }
+        if self.discard_reads().is_err() {
+            self.error = true;
+            return 0;
+        }
+
+        let capacity = self.capacity();
+        let pending = match self.state {
+            State::Writing { len } => len,
+            _ => 0,
+        };
+        if pending + src.len() > capacity {
+            if self.flush_writes().is_err() {
+                return 0;
+            }
+            if src.len() >= capacity {
+                return self.write_through(src);
+            }
+        }
+
+        let pending = match self.state {
+            State::Writing { len } => len,
+            _ => 0,
+        };
+        self.buf[pending..pending + src.len()].copy_from_slice(src);
+        self.state = State::Writing {
+            len: pending + src.len(),
+        };
+        if self.buffering() == Buffering::Line && src.contains(&b'\n') {
+            // the bytes are in the buffer either way, a failure only shows
+            // up in the error flag
+            let _ = self.flush_writes();
+        }
+        src.len()
+    }
+
+    /// Writes directly to the backend, until everything is written or it
+    /// fails.
+    fn write_through(&mut self, src: &[u8]) -> usize {
+        let mut written = 0;
+        while written < src.len() {
+            match self.backend.write(&src[written..]) {
+                Ok(0) | Err(_) => {
+                    self.error = true;
+                    break;
+                }
+                Ok(len) => written += len,
+            }
+        }
+        written
+    }
+
+    /// Writes the buffered bytes to the backend. The bytes that couldn't be
+    /// written stay in the buffer.
+    pub fn flush_writes(&mut self) -> Result<(), Errno> {
+        let State::Writing { len } = self.state else {
+            return Ok(());
+        };
+        let mut written = 0;
+        let mut result = Ok(());
+        while written < len {
+            match self.backend.write(&self.buf[written..len]) {
+                Ok(0) => result = Err(Errno::EIO),
+                Ok(n) => written += n,
+                Err(errno) => result = Err(errno),
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        self.buf.copy_within(written..len, 0);
+        self.state = match len - written {
+            0 => State::Idle,
+            len => State::Writing { len },
+        };
+        if result.is_err() {
+            self.error = true;
+        }
+        result
+    }
+
+    /// Gives the bytes that were read ahead back to the backend, so that its
+    /// offset is where the program is. Backends that can't seek keep theirs,
+    /// so the bytes are lost.
+    fn discard_reads(&mut self) -> Result<(), Errno> {
+        let unread = self.unread();
+        if unread > 0 {
+            match self.backend.seek(-(unread as isize), Whence::SeekCur) {
+                Ok(_) | Err(Errno::ESPIPE) => {}
+                Err(errno) => return Err(errno),
+            }
+        }
+        if let State::Reading { .. } = self.state {
+            self.state = State::Idle;
+        }
+        self.pushback = None;
+        Ok(())
+    }
+
+    /// Writes everything that is buffered, or gives back what was read ahead.
+    pub fn flush(&mut self) -> Result<(), Errno> {
+        self.flush_writes()?;
+        self.discard_reads()
+    }
+
+    /// Moves to the offset, and returns the new one. Anything that is
+    /// buffered is written or discarded first.
+    pub fn seek(&mut self, offset: isize, whence: Whence) -> Result<usize, Errno> {
+        self.flush_writes()?;
+        let offset = match whence {
+            // relative to where the program is, not the backend
+            Whence::SeekCur => offset - self.unread() as isize,
+            Whence::SeekSet | Whence::SeekEnd => offset,
+        };
+        let pos = self.backend.seek(offset, whence)?;
+        self.state = State::Idle;
+        self.pushback = None;
+        self.eof = false;
+        Ok(pos)
+    }
+
+    /// The offset that the program is at, which includes what is buffered.
+    pub fn tell(&mut self) -> Result<usize, Errno> {
+        let pos = self.backend.seek(0, Whence::SeekCur)?;
+        Ok(match self.state {
+            State::Writing { len } => pos + len,
+            State::Idle | State::Reading { .. } => pos.saturating_sub(self.unread()),
+        })
+    }
+
+    /// Flushes the stream and closes the backend.
+    pub fn close(&mut self) -> Result<(), Errno> {
+        let flushed = self.flush_writes();
+        let closed = self.backend.close();
+        flushed.and(closed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// A file in memory, which counts how often it's accessed.
+    #[derive(Default)]
+    struct MemoryFile {
+        data: Vec<u8>,
+        offset: usize,
+        terminal: bool,
+        reads: usize,
+        writes: usize,
+    }
+
+    impl Backend for MemoryFile {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Errno> {
+            self.reads += 1;
+            let len = buf.len().min(self.data.len().saturating_sub(self.offset));
+            buf[..len].copy_from_slice(&self.data[self.offset..self.offset + len]);
+            self.offset += len;
+            Ok(len)
+        }
+
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Errno> {
+            self.writes += 1;
+            let end = self.offset + buf.len();
+            if self.data.len() < end {
+                self.data.resize(end, 0);
+            }
+            self.data[self.offset..end].copy_from_slice(buf);
+            self.offset = end;
+            Ok(buf.len())
+        }
+
+        fn seek(&mut self, offset: isize, whence: Whence) -> Result<usize, Errno> {
+            let base = match whence {
+                Whence::SeekSet => 0,
+                Whence::SeekCur => self.offset,
+                Whence::SeekEnd => self.data.len(),
+            };
+            self.offset = base.checked_add_signed(offset).ok_or(Errno::EINVAL)?;
+            Ok(self.offset)
+        }
+
+        fn is_terminal(&mut self) -> bool {
+            self.terminal
+        }
+
+        fn close(&mut self) -> Result<(), Errno> {
+            Ok(())
+        }
+    }
+
+    fn in_memory(data: &[u8]) -> Stream<MemoryFile> {
+        let file = MemoryFile {
+            data: data.to_vec(),
+            ..MemoryFile::default()
+        };
+        Stream::new(file, true, true, None)
+    }
+
+    #[test]
+    fn test_write_seek_read() {
+        let mut stream = in_memory(b"");
+        assert_eq!(11, stream.write(b"hello world"));
+        // still buffered
+        assert_eq!(0, stream.backend.writes);
+        assert_eq!(11, stream.tell().unwrap());
+
+        assert_eq!(6, stream.seek(6, Whence::SeekSet).unwrap());
+        assert_eq!(b"hello world", stream.backend.data.as_slice());
+        let mut buf = [0; 8];
+        assert_eq!(5, stream.read(&mut buf));
+        assert!(stream.is_eof());
+        assert_eq!(b"world", &buf[..5]);
+    }
+
+    #[test]
+    fn test_write_after_read_goes_to_the_read_position() {
+        let mut stream = in_memory(b"0123456789");
+        let mut buf = [0; 3];
+        assert_eq!(3, stream.read(&mut buf));
+        // the whole file was read ahead
+        assert_eq!(10, stream.backend.offset);
+        assert_eq!(3, stream.tell().unwrap());
+
+        stream.write(b"abc");
+        assert_eq!(6, stream.tell().unwrap());
+        assert_eq!(3, stream.read(&mut buf));
+        assert_eq!(b"678", &buf);
+        stream.flush().unwrap();
+        assert_eq!(b"012abc6789", stream.backend.data.as_slice());
+        assert_eq!(9, stream.backend.offset);
+    }
+
+    #[test]
+    fn test_seek_relative_to_what_was_read() {
+        let mut stream = in_memory(b"0123456789");
+        assert_eq!(Some(b'0'), stream.read_byte());
+        assert_eq!(Some(b'1'), stream.read_byte());
+        assert_eq!(4, stream.seek(2, Whence::SeekCur).unwrap());
+        assert_eq!(Some(b'4'), stream.read_byte());
+
+        assert!(stream.unread_byte(b'x'));
+        assert!(!stream.unread_byte(b'y'));
+        assert_eq!(4, stream.tell().unwrap());
+        assert_eq!(Some(b'x'), stream.read_byte());
+        assert_eq!(Some(b'5'), stream.read_byte());
+
+        // seeking drops the pushed back byte
+        stream.unread_byte(b'x');
+        assert_eq!(5, stream.seek(0, Whence::SeekCur).unwrap());
+        assert_eq!(Some(b'5'), stream.read_byte());
+        assert_eq!(8, stream.seek(-2, Whence::SeekEnd).unwrap());
+        assert_eq!(Some(b'8'), stream.read_byte());
+    }
+
+    #[test]
+    fn test_buffering_modes() {
+        let mut stream = in_memory(b"");
+        stream.backend.terminal = true;
+        stream.write(b"no newline");
+        assert_eq!(0, stream.backend.writes);
+        stream.write(b", but now\nand more");
+        assert_eq!(1, stream.backend.writes);
+        assert_eq!(
+            b"no newline, but now\nand more",
+            stream.backend.data.as_slice()
+        );
+
+        let mut stream = in_memory(b"");
+        stream.buffering = Some(Buffering::Unbuffered);
+        stream.write(b"a");
+        stream.write(b"b");
+        assert_eq!(2, stream.backend.writes);
+
+        let mut stream = in_memory(b"");
+        stream.write(b"a\n");
+        stream.write(&[b'x'; BUFSIZ - 2]);
+        assert_eq!(0, stream.backend.writes);
+        stream.write(b"y");
+        assert_eq!(1, stream.backend.writes);
+        // large writes bypass the buffer, after flushing it
+        stream.write(&[b'z'; BUFSIZ * 2]);
+        assert_eq!(3, stream.backend.writes);
+        assert_eq!(2 + BUFSIZ - 2 + 1 + BUFSIZ * 2, stream.tell().unwrap());
+    }
+
+    #[test]
+    fn test_large_reads_bypass_the_buffer() {
+        let data = (0..BUFSIZ * 3).map(|i| i as u8).collect::<Vec<_>>();
+        let mut stream = in_memory(&data);
+        let mut buf = alloc::vec![0; BUFSIZ * 2];
+        assert_eq!(Some(0), stream.read_byte());
+        assert_eq!(1, stream.backend.reads);
+        assert_eq!(BUFSIZ * 2, stream.read(&mut buf));
+        assert_eq!(&data[1..BUFSIZ * 2 + 1], buf.as_slice());
+        // the rest of the buffer, and then one read directly into `buf`
+        assert_eq!(2, stream.backend.reads);
+    }
+
+    #[test]
+    fn test_read_line() {
+        let mut stream = in_memory(b"one\ntwo\nthree");
+        let mut buf = [0; 16];
+        assert_eq!(4, stream.read_line(&mut buf));
+        assert_eq!(b"one\n", &buf[..4]);
+        assert_eq!(2, stream.read_line(&mut buf[..2]));
+        assert_eq!(b"tw", &buf[..2]);
+        assert_eq!(2, stream.read_line(&mut buf));
+        assert_eq!(5, stream.read_line(&mut buf));
+        assert!(stream.is_eof());
+        assert_eq!(0, stream.read_line(&mut buf));
+    }
+
+    #[test]
+    fn test_access_mode() {
+        let mut stream = Stream::new(MemoryFile::default(), true, false, None);
+        assert_eq!(0, stream.write(b"x"));
+        assert!(stream.is_error());
+        stream.clear_flags();
+        assert!(!stream.is_error());
+        let mut stream = Stream::new(MemoryFile::default(), false, true, None);
+        assert_eq!(None, stream.read_byte());
+        assert!(stream.is_error());
+    }
+}
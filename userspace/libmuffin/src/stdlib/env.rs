@@ -0,0 +1,514 @@
+//! The environment of the process.
+//!
+//! [`environ`] starts out pointing at the array that the kernel put on the
+//! initial stack. That array can't grow, so it's copied to the heap the first
+//! time the environment is modified, and again whenever the program assigns
+//! its own array to `environ`. The strings that `setenv` creates are never
+//! freed, since pointers that `getenv` returned may still point into them.
+
+use core::ffi::{c_char, c_int, CStr};
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+
+use kernel_api::syscall::Errno;
+
+use crate::errno::set_errno;
+use crate::lock::Mutex;
+use crate::stdlib::{free, malloc};
+
+/// The environment as a null terminated array of `NAME=value` strings.
+#[allow(non_upper_case_globals)]
+#[cfg_attr(not(test), no_mangle)]
+pub static mut environ: *mut *mut c_char = null_mut();
+
+static ENVIRONMENT: Mutex<Environment<Malloc>> = Mutex::new(Environment::new(Malloc));
+
+/// Sets [`environ`] to the `envp` array on the initial stack, which starts
+/// with `argc`.
+///
+/// # Safety
+/// `stack` must point to the initial stack that the kernel set up.
+pub unsafe fn init(stack: *const usize) {
+    unsafe {
+        let argc = *stack;
+        environ = stack.add(argc + 2) as *mut *mut c_char;
+    }
+}
+
+/// Where the environment gets its memory from.
+pub trait Allocator {
+    /// Allocates `size` bytes, aligned for pointers, or returns null.
+    fn allocate(&mut self, size: usize) -> *mut u8;
+
+    /// Frees what [`Allocator::allocate`] returned for `size` bytes.
+    ///
+    /// # Safety
+    /// `ptr` must have been allocated with `size` bytes by this allocator.
+    unsafe fn free(&mut self, ptr: *mut u8, size: usize);
+}
+
+/// Allocates from the heap behind `malloc`.
+pub struct Malloc;
+
+impl Allocator for Malloc {
+    fn allocate(&mut self, size: usize) -> *mut u8 {
+        unsafe { malloc(size) }.cast()
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, _: usize) {
+        unsafe { free(ptr.cast()) }
+    }
+}
+
+/// A null terminated array of `NAME=value` strings, which is copied to memory
+/// of the [`Allocator`] before it's modified.
+pub struct Environment<A: Allocator> {
+    array: *mut *mut c_char,
+    /// The number of pointers that fit into the array, or 0 if it isn't ours.
+    capacity: usize,
+    allocator: A,
+}
+
+unsafe impl<A: Allocator + Send> Send for Environment<A> {}
+
+impl<A: Allocator> Environment<A> {
+    pub const fn new(allocator: A) -> Self {
+        Self {
+            array: null_mut(),
+            capacity: 0,
+            allocator,
+        }
+    }
+
+    pub fn array(&self) -> *mut *mut c_char {
+        self.array
+    }
+
+    /// Makes `array` the environment, unless it already is. A null array is
+    /// empty.
+    ///
+    /// # Safety
+    /// `array` must be null or a null terminated array of pointers to
+    /// strings, which stays valid as long as it's the environment.
+    pub unsafe fn adopt(&mut self, array: *mut *mut c_char) {
+        if array != self.array {
+            self.array = array;
+            self.capacity = 0;
+        }
+    }
+
+    fn len(&self) -> usize {
+        if self.array.is_null() {
+            return 0;
+        }
+        (0..)
+            .find(|&i| unsafe { *self.array.add(i) }.is_null())
+            .unwrap()
+    }
+
+    fn entries(&self) -> impl Iterator<Item = *mut c_char> + '_ {
+        (0..self.len()).map(|i| unsafe { *self.array.add(i) })
+    }
+
+    /// The index of the entry for `name`.
+    fn find(&self, name: &[u8]) -> Option<usize> {
+        self.entries().position(|entry| {
+            let entry = unsafe { CStr::from_ptr(entry) }.to_bytes();
+            entry.starts_with(name) && entry.get(name.len()) == Some(&b'=')
+        })
+    }
+
+    /// The value of the variable `name`, or null if it isn't set.
+    pub fn get(&self, name: &[u8]) -> *mut c_char {
+        match self.find(name) {
+            Some(i) => unsafe { (*self.array.add(i)).add(name.len() + 1) },
+            None => null_mut(),
+        }
+    }
+
+    /// Makes the array ours, with room for at least `len` entries and the
+    /// null after them.
+    fn reserve(&mut self, len: usize) -> Result<(), Errno> {
+        if len < self.capacity {
+            return Ok(());
+        }
+        let old_len = self.len();
+        let capacity = (len + 1).max(self.capacity * 2).max(16);
+        let array =
+            self.allocator.allocate(capacity * size_of::<*mut c_char>()) as *mut *mut c_char;
+        if array.is_null() {
+            return Err(Errno::ENOMEM);
+        }
+        unsafe {
+            if old_len > 0 {
+                ptr::copy_nonoverlapping(self.array, array, old_len);
+            }
+            *array.add(old_len) = null_mut();
+            if self.capacity > 0 {
+                let size = self.capacity * size_of::<*mut c_char>();
+                self.allocator.free(self.array.cast(), size);
+            }
+        }
+        self.array = array;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Puts `entry`, whose name is `name_len` bytes long, into the
+    /// environment. An existing entry with the same name is only replaced if
+    /// `overwrite` is set. Returns whether `entry` was put in.
+    ///
+    /// # Safety
+    /// `entry` must be a `NAME=value` string that stays valid as long as
+    /// it's part of the environment.
+    pub unsafe fn put(
+        &mut self,
+        entry: *mut c_char,
+        name_len: usize,
+        overwrite: bool,
+    ) -> Result<bool, Errno> {
+        let name = unsafe { &CStr::from_ptr(entry).to_bytes()[..name_len] };
+        let (index, len) = match self.find(name) {
+            Some(_) if !overwrite => return Ok(false),
+            Some(i) => (i, self.len()),
+            None => (self.len(), self.len() + 1),
+        };
+        self.reserve(len)?;
+        unsafe {
+            *self.array.add(index) = entry;
+            *self.array.add(len) = null_mut();
+        }
+        Ok(true)
+    }
+
+    /// Removes all entries for `name`.
+    pub fn remove(&mut self, name: &[u8]) -> Result<(), Errno> {
+        if self.find(name).is_none() {
+            return Ok(());
+        }
+        self.reserve(0)?;
+        while let Some(i) = self.find(name) {
+            let len = self.len();
+            // move the rest, including the null, one to the front
+            unsafe { ptr::copy(self.array.add(i + 1), self.array.add(i), len - i) };
+        }
+        Ok(())
+    }
+
+    /// Creates the string `name=value`, which is never freed.
+    fn entry(&mut self, name: &[u8], value: &[u8]) -> Result<*mut c_char, Errno> {
+        let entry = self.allocator.allocate(name.len() + 1 + value.len() + 1);
+        if entry.is_null() {
+            return Err(Errno::ENOMEM);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(name.as_ptr(), entry, name.len());
+            *entry.add(name.len()) = b'=';
+            let value_start = entry.add(name.len() + 1);
+            ptr::copy_nonoverlapping(value.as_ptr(), value_start, value.len());
+            *value_start.add(value.len()) = 0;
+        }
+        Ok(entry.cast())
+    }
+
+    /// Sets the variable `name` to `value`, if it isn't set or `overwrite`
+    /// is set.
+    pub fn set(&mut self, name: &[u8], value: &[u8], overwrite: bool) -> Result<(), Errno> {
+        if self.find(name).is_some() && !overwrite {
+            return Ok(());
+        }
+        let entry = self.entry(name, value)?;
+        unsafe { self.put(entry, name.len(), true) }.map(|_| ())
+    }
+}
+
+/// Runs `f` on the environment that [`environ`] points to, and points
+/// `environ` to wherever it is afterwards.
+fn with_environment<T>(
+    f: impl FnOnce(&mut Environment<Malloc>) -> Result<T, Errno>,
+) -> Result<T, Errno> {
+    let mut environment = ENVIRONMENT.lock();
+    unsafe { environment.adopt(environ) };
+    let result = f(&mut environment);
+    unsafe { environ = environment.array() };
+    result
+}
+
+/// Checks that `name` can be the name of a variable, which can't be empty or
+/// contain `=`.
+///
+/// # Safety
+/// `name` must be null or a nul terminated string.
+unsafe fn valid_name<'a>(name: *const c_char) -> Result<&'a [u8], Errno> {
+    if name.is_null() {
+        return Err(Errno::EINVAL);
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+    if name.is_empty() || name.contains(&b'=') {
+        return Err(Errno::EINVAL);
+    }
+    Ok(name)
+}
+
+/// Returns the value of the variable `name`, or null if it isn't set.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/getenv.html>.
+///
+/// # Safety
+/// `name` must be null or a nul terminated string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn getenv(name: *const c_char) -> *mut c_char {
+    if name.is_null() {
+        return null_mut();
+    }
+    let name = unsafe { CStr::from_ptr(name) }.to_bytes();
+    with_environment(|environment| Ok(environment.get(name))).unwrap_or(null_mut())
+}
+
+/// Sets the variable `name` to a copy of `value`. An existing variable is only
+/// changed if `overwrite` is non-zero.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/setenv.html>.
+///
+/// # Safety
+/// `name` and `value` must be null or nul terminated strings.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn setenv(
+    name: *const c_char,
+    value: *const c_char,
+    overwrite: c_int,
+) -> c_int {
+    let result = unsafe { valid_name(name) }.and_then(|name| {
+        if value.is_null() {
+            return Err(Errno::EINVAL);
+        }
+        let value = unsafe { CStr::from_ptr(value) }.to_bytes();
+        with_environment(|environment| environment.set(name, value, overwrite != 0))
+    });
+    if let Err(e) = result {
+        set_errno(e);
+        return -1;
+    }
+    0
+}
+
+/// Removes the variable `name`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/unsetenv.html>.
+///
+/// # Safety
+/// `name` must be null or a nul terminated string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn unsetenv(name: *const c_char) -> c_int {
+    let result = unsafe { valid_name(name) }
+        .and_then(|name| with_environment(|environment| environment.remove(name)));
+    if let Err(e) = result {
+        set_errno(e);
+        return -1;
+    }
+    0
+}
+
+/// Puts `string`, which has the form `NAME=value`, into the environment
+/// itself, so changing the string changes the environment.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/putenv.html>.
+///
+/// # Safety
+/// `string` must be a nul terminated string that stays valid as long as it's
+/// part of the environment.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn putenv(string: *mut c_char) -> c_int {
+    let name_len = match unsafe { CStr::from_ptr(string) }
+        .to_bytes()
+        .iter()
+        .position(|&b| b == b'=')
+    {
+        Some(0) | None => {
+            set_errno(Errno::EINVAL);
+            return -1;
+        }
+        Some(len) => len,
+    };
+    match with_environment(|environment| unsafe { environment.put(string, name_len, true) }) {
+        Ok(_) => 0,
+        Err(e) => {
+            set_errno(e);
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::alloc::{alloc, dealloc, Layout};
+    use std::ffi::CString;
+    use std::vec;
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// Allocates from the host, and counts the arrays that are live.
+    #[derive(Default)]
+    struct HostAllocator {
+        live: usize,
+    }
+
+    impl Allocator for HostAllocator {
+        fn allocate(&mut self, size: usize) -> *mut u8 {
+            self.live += 1;
+            unsafe { alloc(Layout::from_size_align(size, 8).unwrap()) }
+        }
+
+        unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+            self.live -= 1;
+            unsafe { dealloc(ptr, Layout::from_size_align(size, 8).unwrap()) }
+        }
+    }
+
+    /// An environment that starts out as the given strings, in an array that
+    /// isn't its own, like the one on the initial stack.
+    struct Fixture {
+        _strings: Vec<CString>,
+        array: Vec<*mut c_char>,
+        environment: Environment<HostAllocator>,
+    }
+
+    impl Fixture {
+        fn new(initial: &[&str]) -> Self {
+            let strings = initial
+                .iter()
+                .map(|&s| CString::new(s).unwrap())
+                .collect::<Vec<_>>();
+            let mut array = strings
+                .iter()
+                .map(|s| s.as_ptr().cast_mut())
+                .chain([null_mut()])
+                .collect::<Vec<_>>();
+            let mut environment = Environment::new(HostAllocator::default());
+            unsafe { environment.adopt(array.as_mut_ptr()) };
+            Self {
+                _strings: strings,
+                array,
+                environment,
+            }
+        }
+
+        fn get(&self, name: &str) -> Option<&str> {
+            let value = self.environment.get(name.as_bytes());
+            (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) }.to_str().unwrap())
+        }
+
+        fn entries(&self) -> Vec<&str> {
+            self.environment
+                .entries()
+                .map(|entry| unsafe { CStr::from_ptr(entry) }.to_str().unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_get() {
+        let fixture = Fixture::new(&["HOME=/root", "PATH=/bin", "EMPTY="]);
+        assert_eq!(Some("/root"), fixture.get("HOME"));
+        assert_eq!(Some("/bin"), fixture.get("PATH"));
+        assert_eq!(Some(""), fixture.get("EMPTY"));
+        // names only match as a whole
+        assert_eq!(None, fixture.get("HOM"));
+        assert_eq!(None, fixture.get("HOME=/root"));
+        assert_eq!(None, fixture.get("USER"));
+    }
+
+    #[test]
+    fn test_null_environment() {
+        let mut environment = Environment::new(HostAllocator::default());
+        assert!(environment.get(b"HOME").is_null());
+        environment.remove(b"HOME").unwrap();
+        environment.set(b"HOME", b"/root", false).unwrap();
+        assert!(!environment.get(b"HOME").is_null());
+    }
+
+    #[test]
+    fn test_set_copies_the_initial_array() {
+        let mut fixture = Fixture::new(&["HOME=/root", "PATH=/bin"]);
+        let initial = fixture.array.clone();
+
+        fixture.environment.set(b"HOME", b"/tmp", false).unwrap();
+        assert_eq!(Some("/root"), fixture.get("HOME"));
+        // nothing changed, so the initial array is still the environment
+        assert_eq!(fixture.array.as_mut_ptr(), fixture.environment.array());
+
+        fixture.environment.set(b"HOME", b"/tmp", true).unwrap();
+        fixture.environment.set(b"USER", b"root", false).unwrap();
+        assert_eq!(
+            vec!["HOME=/tmp", "PATH=/bin", "USER=root"],
+            fixture.entries()
+        );
+        assert_ne!(fixture.array.as_mut_ptr(), fixture.environment.array());
+        assert_eq!(initial, fixture.array);
+    }
+
+    #[test]
+    fn test_set_grows() {
+        let mut fixture = Fixture::new(&[]);
+        let names = (0..100).map(|i| std::format!("VAR{i}")).collect::<Vec<_>>();
+        for name in &names {
+            fixture
+                .environment
+                .set(name.as_bytes(), b"x", true)
+                .unwrap();
+        }
+        assert_eq!(100, fixture.entries().len());
+        for name in &names {
+            assert_eq!(Some("x"), fixture.get(name));
+        }
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut fixture = Fixture::new(&["A=1", "B=2", "A=3", "C=4"]);
+        fixture.environment.remove(b"D").unwrap();
+        // removing something that isn't there doesn't copy the array
+        assert_eq!(0, fixture.environment.allocator.live);
+
+        fixture.environment.remove(b"A").unwrap();
+        assert_eq!(vec!["B=2", "C=4"], fixture.entries());
+        assert_eq!(None, fixture.get("A"));
+        fixture.environment.remove(b"C").unwrap();
+        assert_eq!(vec!["B=2"], fixture.entries());
+    }
+
+    #[test]
+    fn test_put_aliases_the_string() {
+        let mut fixture = Fixture::new(&["A=1"]);
+        let mut string = *b"B=2\0";
+        let entry = string.as_mut_ptr().cast();
+        assert!(unsafe { fixture.environment.put(entry, 1, true) }.unwrap());
+        assert_eq!(Some("2"), fixture.get("B"));
+        unsafe { *entry.add(2) = b'5' as c_char };
+        assert_eq!(Some("5"), fixture.get("B"));
+
+        let mut other = *b"B=9\0";
+        let entry = other.as_mut_ptr().cast();
+        assert!(!unsafe { fixture.environment.put(entry, 1, false) }.unwrap());
+        assert_eq!(Some("5"), fixture.get("B"));
+    }
+
+    #[test]
+    fn test_adopt_new_array() {
+        let mut fixture = Fixture::new(&["A=1"]);
+        fixture.environment.set(b"B", b"2", true).unwrap();
+        // the program assigned its own array to environ
+        let other = Fixture::new(&["C=3"]);
+        let mut array = other.array.clone();
+        unsafe { fixture.environment.adopt(array.as_mut_ptr()) };
+        assert_eq!(vec!["C=3"], fixture.entries());
+
+        fixture.environment.set(b"D", b"4", true).unwrap();
+        assert_eq!(vec!["C=3", "D=4"], fixture.entries());
+        // the program's array is left alone
+        assert_eq!(other.array, array);
+    }
+}
@@ -0,0 +1,317 @@
+//! Terminating the process, and the handlers that run before it does.
+//!
+//! The handlers of `atexit` and `__cxa_atexit` are in the same list, and run
+//! in the reverse order of their registration. The first [`FIXED_SLOTS`] of
+//! them are stored in place, so registering them can't fail, and the rest
+//! are stored on the heap. The list is only locked while a handler is taken
+//! from it, so handlers can register other handlers, which run next.
+
+use core::ffi::{c_int, c_void};
+use core::mem::size_of;
+use core::ptr::{self, null_mut};
+
+use kernel_api::syscall::Syscall;
+
+use crate::lock::Mutex;
+use crate::stdio::fflush;
+use crate::stdlib::env::{Allocator, Malloc};
+use crate::syscall::syscall;
+
+pub const EXIT_SUCCESS: c_int = 0;
+pub const EXIT_FAILURE: c_int = 1;
+
+/// The number of handlers that can be registered without allocating, which
+/// is the minimum that POSIX requires.
+pub const FIXED_SLOTS: usize = 32;
+
+static AT_EXIT: Mutex<Handlers<Malloc>> = Mutex::new(Handlers::new(Malloc));
+static AT_QUICK_EXIT: Mutex<Handlers<Malloc>> = Mutex::new(Handlers::new(Malloc));
+
+/// A registered handler.
+#[derive(Clone, Copy)]
+pub enum Handler {
+    /// Registered with `atexit` or `at_quick_exit`.
+    Plain(extern "C" fn()),
+    /// Registered with `__cxa_atexit` by the shared object `dso`, which is
+    /// null for the executable.
+    Cxa {
+        func: unsafe extern "C" fn(*mut c_void),
+        arg: *mut c_void,
+        dso: *mut c_void,
+    },
+}
+
+impl Handler {
+    fn dso(&self) -> *mut c_void {
+        match *self {
+            Self::Plain(_) => null_mut(),
+            Self::Cxa { dso, .. } => dso,
+        }
+    }
+
+    /// # Safety
+    /// Whoever registered the handler must have made calling it safe.
+    unsafe fn call(self) {
+        match self {
+            Self::Plain(func) => func(),
+            Self::Cxa { func, arg, .. } => unsafe { func(arg) },
+        }
+    }
+}
+
+/// A stack of handlers, which stores handlers beyond [`FIXED_SLOTS`] in
+/// memory of the [`Allocator`].
+pub struct Handlers<A: Allocator> {
+    fixed: [Option<Handler>; FIXED_SLOTS],
+    more: *mut Option<Handler>,
+    /// The number of handlers that fit into `more`.
+    capacity: usize,
+    /// The number of slots in use, including the ones of handlers that have
+    /// already been taken by [`Handlers::take_last`].
+    len: usize,
+    allocator: A,
+}
+
+unsafe impl<A: Allocator + Send> Send for Handlers<A> {}
+
+impl<A: Allocator> Handlers<A> {
+    pub const fn new(allocator: A) -> Self {
+        Self {
+            fixed: [None; FIXED_SLOTS],
+            more: null_mut(),
+            capacity: 0,
+            len: 0,
+            allocator,
+        }
+    }
+
+    fn slot(&mut self, i: usize) -> &mut Option<Handler> {
+        if i < FIXED_SLOTS {
+            &mut self.fixed[i]
+        } else {
+            unsafe { &mut *self.more.add(i - FIXED_SLOTS) }
+        }
+    }
+
+    /// Adds `handler` on top of the stack. Returns `false` if there was no
+    /// room and no memory to make room.
+    pub fn push(&mut self, handler: Handler) -> bool {
+        if self.len == FIXED_SLOTS + self.capacity {
+            let capacity = (self.capacity * 2).max(FIXED_SLOTS);
+            let more = self
+                .allocator
+                .allocate(capacity * size_of::<Option<Handler>>())
+                .cast::<Option<Handler>>();
+            if more.is_null() {
+                return false;
+            }
+            if !self.more.is_null() {
+                unsafe {
+                    ptr::copy_nonoverlapping(self.more, more, self.capacity);
+                    self.allocator.free(
+                        self.more.cast(),
+                        self.capacity * size_of::<Option<Handler>>(),
+                    );
+                }
+            }
+            self.more = more;
+            self.capacity = capacity;
+        }
+        let len = self.len;
+        *self.slot(len) = Some(handler);
+        self.len += 1;
+        true
+    }
+
+    /// Removes the handler that was registered last by `dso`, or the one
+    /// that was registered last of all if `dso` is null.
+    pub fn take_last(&mut self, dso: *mut c_void) -> Option<Handler> {
+        let handler = (0..self.len).rev().find_map(|i| {
+            let slot = self.slot(i);
+            let matches = slot.is_some_and(|h| dso.is_null() || h.dso() == dso);
+            matches.then(|| slot.take()).flatten()
+        });
+        while self.len > 0 && self.slot(self.len - 1).is_none() {
+            self.len -= 1;
+        }
+        handler
+    }
+}
+
+/// Calls the handlers in `handlers` that were registered by `dso`, or all of
+/// them if it's null, until there are none left.
+fn run(handlers: &Mutex<Handlers<Malloc>>, dso: *mut c_void) {
+    loop {
+        // the lock must not be held while the handler runs
+        let Some(handler) = handlers.lock().take_last(dso) else {
+            break;
+        };
+        unsafe { handler.call() };
+    }
+}
+
+/// Registers `func` to be called by [`exit`]. Returns 0 on success.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/atexit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn atexit(func: extern "C" fn()) -> c_int {
+    if AT_EXIT.lock().push(Handler::Plain(func)) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Registers `func` to be called with `arg` by [`exit`], or by
+/// [`__cxa_finalize`] when the shared object `dso` is unloaded. This is what
+/// compilers use for the destructors of static objects. Returns 0 on success.
+///
+/// # Safety
+/// Calling `func` with `arg` must be safe whenever the handler runs.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn __cxa_atexit(
+    func: unsafe extern "C" fn(*mut c_void),
+    arg: *mut c_void,
+    dso: *mut c_void,
+) -> c_int {
+    if AT_EXIT.lock().push(Handler::Cxa { func, arg, dso }) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Calls the handlers that the shared object `dso` registered, or all
+/// handlers if it's null, and unregisters them.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn __cxa_finalize(dso: *mut c_void) {
+    run(&AT_EXIT, dso);
+}
+
+/// Calls the handlers of [`atexit`] and [`__cxa_atexit`], flushes all
+/// streams and terminates the process with `status`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/exit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn exit(status: c_int) -> ! {
+    __cxa_finalize(null_mut());
+    unsafe { fflush(null_mut()) };
+    _Exit(status)
+}
+
+/// Terminates the process with `status`, without calling any handlers or
+/// flushing any streams.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/_Exit.html>.
+#[allow(non_snake_case)]
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn _Exit(status: c_int) -> ! {
+    unsafe { syscall(Syscall::Exit, [status as usize, 0, 0, 0, 0, 0]) };
+    unreachable!("exit returned")
+}
+
+/// The same as [`_Exit`].
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/_exit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn _exit(status: c_int) -> ! {
+    _Exit(status)
+}
+
+/// Registers `func` to be called by [`quick_exit`]. Returns 0 on success.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/at_quick_exit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn at_quick_exit(func: extern "C" fn()) -> c_int {
+    if AT_QUICK_EXIT.lock().push(Handler::Plain(func)) {
+        0
+    } else {
+        -1
+    }
+}
+
+/// Calls the handlers of [`at_quick_exit`] and terminates the process with
+/// `status`, without flushing any streams.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/quick_exit.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn quick_exit(status: c_int) -> ! {
+    run(&AT_QUICK_EXIT, null_mut());
+    _Exit(status)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::alloc::{alloc, dealloc, Layout};
+    use std::vec::Vec;
+
+    use super::*;
+
+    struct HostAllocator;
+
+    impl Allocator for HostAllocator {
+        fn allocate(&mut self, size: usize) -> *mut u8 {
+            unsafe { alloc(Layout::from_size_align(size, 8).unwrap()) }
+        }
+
+        unsafe fn free(&mut self, ptr: *mut u8, size: usize) {
+            unsafe { dealloc(ptr, Layout::from_size_align(size, 8).unwrap()) }
+        }
+    }
+
+    unsafe extern "C" fn nop(_: *mut c_void) {}
+
+    /// A handler that is told apart from the others by its `arg`.
+    fn handler(arg: usize, dso: usize) -> Handler {
+        Handler::Cxa {
+            func: nop,
+            arg: arg as *mut c_void,
+            dso: dso as *mut c_void,
+        }
+    }
+
+    fn arg(handler: Option<Handler>) -> Option<usize> {
+        handler.map(|handler| match handler {
+            Handler::Cxa { arg, .. } => arg as usize,
+            Handler::Plain(_) => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn test_last_in_first_out() {
+        let mut handlers = Handlers::new(HostAllocator);
+        let count = 3 * FIXED_SLOTS + 1;
+        for i in 0..count {
+            assert!(handlers.push(handler(i, 0)));
+        }
+        let order = (0..count)
+            .map(|_| arg(handlers.take_last(null_mut())).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!((0..count).rev().collect::<Vec<_>>(), order);
+        assert_eq!(None, arg(handlers.take_last(null_mut())));
+
+        // the memory is reused
+        assert!(handlers.push(handler(7, 0)));
+        assert_eq!(Some(7), arg(handlers.take_last(null_mut())));
+    }
+
+    #[test]
+    fn test_finalize_dso() {
+        let mut handlers = Handlers::new(HostAllocator);
+        for (i, dso) in [0, 1, 2, 1, 0].into_iter().enumerate() {
+            assert!(handlers.push(handler(i, dso)));
+        }
+        let dso = ptr::without_provenance_mut(1);
+        assert_eq!(Some(3), arg(handlers.take_last(dso)));
+        assert_eq!(Some(1), arg(handlers.take_last(dso)));
+        assert_eq!(None, arg(handlers.take_last(dso)));
+
+        // the handlers of the other objects are left, in their order
+        assert_eq!(Some(4), arg(handlers.take_last(null_mut())));
+        assert_eq!(Some(2), arg(handlers.take_last(null_mut())));
+        assert_eq!(Some(0), arg(handlers.take_last(null_mut())));
+        assert_eq!(None, arg(handlers.take_last(null_mut())));
+    }
+}
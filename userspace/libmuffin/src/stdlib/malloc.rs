@@ -0,0 +1,648 @@
+//! The heap behind `malloc`.
+//!
+//! Every block starts with a [`Header`] of 16 bytes, and the sizes of all
+//! blocks are multiples of 16, so the memory that is handed out is 16 byte
+//! aligned.
+//!
+//! Small blocks, of up to [`MAX_SMALL`] bytes, are rounded up to a size
+//! class. Every class has a list of its free blocks, and carves new blocks
+//! from a slab of its own when the list is empty. Small blocks never change
+//! their size, so they are never merged.
+//!
+//! Everything larger, including the slabs, is a chunk in a region that the
+//! [`Source`] mapped. Allocating takes the smallest free chunk that fits, and
+//! splits off what isn't needed. Freeing merges a chunk with the free chunks
+//! next to it, so that no two free chunks are ever neighbors. There is no way
+//! to unmap memory, so regions are never given back.
+
+use core::mem::size_of;
+use core::ptr::{self, null_mut, NonNull};
+
+/// The alignment of every allocation.
+pub const ALIGN: usize = 16;
+
+/// The largest allocation that is served from a size class.
+pub const MAX_SMALL: usize = 2048;
+
+const CLASSES: [usize; 16] = [
+    16, 32, 48, 64, 80, 96, 112, 128, 192, 256, 384, 512, 768, 1024, 1536, MAX_SMALL,
+];
+
+const HEADER: usize = size_of::<Header>();
+
+/// The size of the chunk that a size class carves its blocks from.
+const SLAB_SIZE: usize = 64 * 1024;
+
+/// The smallest region that is mapped, so that not every large allocation
+/// needs a syscall.
+const MIN_REGION: usize = 1024 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+/// The smallest chunk that is split off of a larger one. Anything smaller
+/// stays part of the chunk it would have been split from.
+const MIN_SPLIT: usize = 64;
+
+/// The block is allocated.
+const IN_USE: usize = 1 << 0;
+/// The chunk is the last one in its region.
+const LAST: usize = 1 << 1;
+/// The block belongs to a size class.
+const SMALL: usize = 1 << 2;
+const FLAGS: usize = ALIGN - 1;
+
+/// Where the heap gets its memory from.
+pub trait Source {
+    /// Maps `len` bytes, which is a multiple of the page size. The memory
+    /// must be aligned to at least [`ALIGN`] bytes.
+    fn map(&mut self, len: usize) -> Option<NonNull<u8>>;
+}
+
+/// The bookkeeping in front of every block.
+#[repr(C)]
+struct Header {
+    /// The size of the chunk in front of this one, or 0 if this is the first
+    /// chunk in its region. Unused for small blocks.
+    prev_size: usize,
+    /// The number of usable bytes after the header, with the flags in the low
+    /// bits.
+    size: usize,
+}
+
+impl Header {
+    fn size(&self) -> usize {
+        self.size & !FLAGS
+    }
+
+    fn is(&self, flag: usize) -> bool {
+        self.size & flag != 0
+    }
+
+    /// Changes the size, keeping the flags.
+    fn set_size(&mut self, size: usize) {
+        self.size = size | (self.size & FLAGS);
+    }
+}
+
+/// The links of a free chunk, which are stored where its data would be.
+#[repr(C)]
+struct Links {
+    next: *mut Header,
+    prev: *mut Header,
+}
+
+pub struct Heap<S> {
+    source: S,
+    /// The first free block of every size class. Each free block stores the
+    /// next one where its data would be.
+    classes: [*mut Header; CLASSES.len()],
+    /// The start and the end of the part of each class's slab that hasn't
+    /// been carved into blocks yet.
+    slabs: [(*mut u8, *mut u8); CLASSES.len()],
+    /// The first free chunk.
+    free: *mut Header,
+}
+
+// the heap owns all of the memory that its pointers point to
+unsafe impl<S: Send> Send for Heap<S> {}
+
+impl<S: Source> Heap<S> {
+    pub const fn new(source: S) -> Self {
+        Self {
+            source,
+            classes: [null_mut(); CLASSES.len()],
+            slabs: [(null_mut(), null_mut()); CLASSES.len()],
+            free: null_mut(),
+        }
+    }
+
+    /// Allocates at least `size` bytes, or returns null if there is no
+    /// memory left. Zero bytes get a block of their own, like any other size.
+    pub fn allocate(&mut self, size: usize) -> *mut u8 {
+        match CLASSES.iter().position(|&class| size <= class) {
+            Some(class) => self.allocate_small(class),
+            None => self.allocate_large(size),
+        }
+    }
+
+    /// Allocates `count` elements of `size` bytes, all of them zero. Returns
+    /// null if the total size overflows, or if there is no memory left.
+    pub fn allocate_zeroed(&mut self, count: usize, size: usize) -> *mut u8 {
+        let Some(size) = count.checked_mul(size) else {
+            return null_mut();
+        };
+        let ptr = self.allocate(size);
+        if !ptr.is_null() {
+            unsafe { ptr.write_bytes(0, size) };
+        }
+        ptr
+    }
+
+    /// Frees the block. Null is ignored.
+    ///
+    /// # Safety
+    /// `ptr` must be null, or have been returned by this heap and not have
+    /// been freed since.
+    pub unsafe fn free(&mut self, ptr: *mut u8) {
+        if ptr.is_null() {
+            return;
+        }
+        let header = unsafe { header(ptr) };
+        let block = unsafe { &mut *header };
+        debug_assert!(block.is(IN_USE), "double free of {ptr:p}");
+        block.size &= !IN_USE;
+        if block.is(SMALL) {
+            let class = CLASSES
+                .iter()
+                .position(|&class| class == block.size())
+                .expect("small block without a size class");
+            unsafe { ptr.cast::<*mut Header>().write(self.classes[class]) };
+            self.classes[class] = header;
+        } else {
+            unsafe { self.release(header) };
+        }
+    }
+
+    /// Changes the size of the block to at least `size` bytes, keeping its
+    /// contents, as far as they fit. Large blocks grow into the free chunk
+    /// after them if they can, and everything else is moved to a new block.
+    /// Returns null, and leaves the block alone, if there is no memory left.
+    ///
+    /// # Safety
+    /// `ptr` must be null, or have been returned by this heap and not have
+    /// been freed since.
+    pub unsafe fn reallocate(&mut self, ptr: *mut u8, size: usize) -> *mut u8 {
+        if ptr.is_null() {
+            return self.allocate(size);
+        }
+        let header = unsafe { header(ptr) };
+        let old = unsafe { (*header).size() };
+        if unsafe { !(*header).is(SMALL) } {
+            let Some(needed) = round_up(size) else {
+                return null_mut();
+            };
+            if needed <= old || unsafe { self.grow_in_place(header, needed) } {
+                unsafe { self.split(header, needed) };
+                return ptr;
+            }
+        } else if size <= old {
+            return ptr;
+        }
+
+        let new = self.allocate(size);
+        if !new.is_null() {
+            unsafe {
+                ptr::copy_nonoverlapping(ptr, new, old.min(size));
+                self.free(ptr);
+            }
+        }
+        new
+    }
+
+    /// The number of bytes that can be used in the block, which may be more
+    /// than were requested.
+    ///
+    /// # Safety
+    /// `ptr` must have been returned by this heap and not have been freed
+    /// since.
+    pub unsafe fn usable_size(&self, ptr: *mut u8) -> usize {
+        unsafe { (*header(ptr)).size() }
+    }
+
+    fn allocate_small(&mut self, class: usize) -> *mut u8 {
+        let block = self.classes[class];
+        if !block.is_null() {
+            unsafe {
+                self.classes[class] = payload(block).cast::<*mut Header>().read();
+                (*block).size |= IN_USE;
+                return payload(block);
+            }
+        }
+
+        let size = CLASSES[class];
+        let (start, end) = self.slabs[class];
+        if end.addr() - start.addr() < HEADER + size {
+            let slab = self.allocate_large(SLAB_SIZE - HEADER);
+            if slab.is_null() {
+                return null_mut();
+            }
+            self.slabs[class] = (slab, unsafe { slab.add(SLAB_SIZE - HEADER) });
+        }
+        let (start, _) = &mut self.slabs[class];
+        let block = start.cast::<Header>();
+        *start = unsafe { start.add(HEADER + size) };
+        unsafe {
+            block.write(Header {
+                prev_size: 0,
+                size: size | SMALL | IN_USE,
+            });
+            payload(block)
+        }
+    }
+
+    fn allocate_large(&mut self, size: usize) -> *mut u8 {
+        let Some(size) = round_up(size) else {
+            return null_mut();
+        };
+        let chunk = match self.best_fit(size) {
+            Some(chunk) => {
+                unsafe { self.unlink(chunk) };
+                chunk
+            }
+            None => match self.map_region(size) {
+                Some(chunk) => chunk,
+                None => return null_mut(),
+            },
+        };
+        unsafe {
+            (*chunk).size |= IN_USE;
+            self.split(chunk, size);
+            payload(chunk)
+        }
+    }
+
+    /// The smallest free chunk that has room for `size` bytes.
+    fn best_fit(&self, size: usize) -> Option<*mut Header> {
+        let mut best: Option<*mut Header> = None;
+        let mut chunk = self.free;
+        while !chunk.is_null() {
+            let chunk_size = unsafe { (*chunk).size() };
+            if chunk_size == size {
+                return Some(chunk);
+            }
+            if chunk_size > size && best.is_none_or(|best| chunk_size < unsafe { (*best).size() }) {
+                best = Some(chunk);
+            }
+            chunk = unsafe { (*links(chunk)).next };
+        }
+        best
+    }
+
+    /// Maps a region that fits a chunk of `size` bytes, which is the only
+    /// chunk in it.
+    fn map_region(&mut self, size: usize) -> Option<*mut Header> {
+        let len = size
+            .checked_add(HEADER + PAGE_SIZE - 1)
+            .filter(|&len| len <= isize::MAX as usize)?
+            / PAGE_SIZE
+            * PAGE_SIZE;
+        let len = len.max(MIN_REGION);
+        let chunk = self.source.map(len)?.as_ptr().cast::<Header>();
+        debug_assert_eq!(0, chunk as usize & (ALIGN - 1));
+        unsafe {
+            chunk.write(Header {
+                prev_size: 0,
+                size: (len - HEADER) | LAST,
+            })
+        };
+        Some(chunk)
+    }
+
+    /// Merges the free chunk after the chunk into it, if that makes it large
+    /// enough for `size` bytes.
+    unsafe fn grow_in_place(&mut self, chunk: *mut Header, size: usize) -> bool {
+        let Some(next) = (unsafe { next(chunk) }) else {
+            return false;
+        };
+        let next_size = unsafe { (*next).size() };
+        let merged = unsafe { (*chunk).size() } + HEADER + next_size;
+        if unsafe { (*next).is(IN_USE) } || merged < size {
+            return false;
+        }
+        unsafe {
+            self.unlink(next);
+            self.absorb_next(chunk, next);
+        }
+        true
+    }
+
+    /// Cuts the chunk down to `size` bytes, and frees the rest, if it's
+    /// worth splitting off.
+    unsafe fn split(&mut self, chunk: *mut Header, size: usize) {
+        let chunk_size = unsafe { (*chunk).size() };
+        if chunk_size < size + HEADER + MIN_SPLIT {
+            return;
+        }
+        let rest = unsafe { payload(chunk).add(size) }.cast::<Header>();
+        unsafe {
+            rest.write(Header {
+                prev_size: size,
+                size: (chunk_size - size - HEADER) | IN_USE | ((*chunk).size & LAST),
+            });
+            (*chunk).size &= !LAST;
+            (*chunk).set_size(size);
+            if let Some(next) = next(rest) {
+                (*next).prev_size = (*rest).size();
+            }
+            self.release(rest);
+        }
+    }
+
+    /// Frees the large chunk, merging it with the free chunks around it.
+    unsafe fn release(&mut self, mut chunk: *mut Header) {
+        unsafe {
+            (*chunk).size &= !IN_USE;
+            if let Some(next) = next(chunk).filter(|&next| !(*next).is(IN_USE)) {
+                self.unlink(next);
+                self.absorb_next(chunk, next);
+            }
+            if let Some(prev) = prev(chunk).filter(|&prev| !(*prev).is(IN_USE)) {
+                self.unlink(prev);
+                self.absorb_next(prev, chunk);
+                chunk = prev;
+            }
+            self.link(chunk);
+        }
+    }
+
+    /// Makes the chunk after the chunk part of it.
+    unsafe fn absorb_next(&mut self, chunk: *mut Header, next: *mut Header) {
+        unsafe {
+            let size = (*chunk).size() + HEADER + (*next).size();
+            (*chunk).set_size(size);
+            (*chunk).size |= (*next).size & LAST;
+            if let Some(after) = self::next(chunk) {
+                (*after).prev_size = size;
+            }
+        }
+    }
+
+    unsafe fn link(&mut self, chunk: *mut Header) {
+        unsafe {
+            links(chunk).write(Links {
+                next: self.free,
+                prev: null_mut(),
+            });
+            if !self.free.is_null() {
+                (*links(self.free)).prev = chunk;
+            }
+        }
+        self.free = chunk;
+    }
+
+    unsafe fn unlink(&mut self, chunk: *mut Header) {
+        unsafe {
+            let Links { next, prev } = links(chunk).read();
+            if prev.is_null() {
+                self.free = next;
+            } else {
+                (*links(prev)).next = next;
+            }
+            if !next.is_null() {
+                (*links(next)).prev = prev;
+            }
+        }
+    }
+}
+
+/// Rounds the size up to the alignment, or returns `None` if it's too large
+/// to ever be allocated.
+fn round_up(size: usize) -> Option<usize> {
+    size.max(ALIGN)
+        .checked_next_multiple_of(ALIGN)
+        .filter(|&size| size <= isize::MAX as usize / 2)
+}
+
+unsafe fn header(ptr: *mut u8) -> *mut Header {
+    unsafe { ptr.sub(HEADER) }.cast()
+}
+
+unsafe fn payload(header: *mut Header) -> *mut u8 {
+    unsafe { header.cast::<u8>().add(HEADER) }
+}
+
+unsafe fn links(chunk: *mut Header) -> *mut Links {
+    unsafe { payload(chunk) }.cast()
+}
+
+/// The chunk after the chunk, if it isn't the last one in its region.
+unsafe fn next(chunk: *mut Header) -> Option<*mut Header> {
+    unsafe {
+        if (*chunk).is(LAST) {
+            return None;
+        }
+        Some(payload(chunk).add((*chunk).size()).cast())
+    }
+}
+
+/// The chunk in front of the chunk, if it isn't the first one in its region.
+unsafe fn prev(chunk: *mut Header) -> Option<*mut Header> {
+    unsafe {
+        let prev_size = (*chunk).prev_size;
+        if prev_size == 0 {
+            return None;
+        }
+        Some(chunk.cast::<u8>().sub(prev_size + HEADER).cast())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+    use alloc::vec::Vec;
+
+    use super::*;
+
+    /// Maps memory from the host's allocator, and counts how much it mapped.
+    #[derive(Default)]
+    struct TestSource {
+        regions: Vec<(*mut u8, Layout)>,
+    }
+
+    impl TestSource {
+        fn mapped(&self) -> usize {
+            self.regions.iter().map(|(_, layout)| layout.size()).sum()
+        }
+    }
+
+    impl Source for TestSource {
+        fn map(&mut self, len: usize) -> Option<NonNull<u8>> {
+            assert_eq!(0, len % PAGE_SIZE);
+            let layout = Layout::from_size_align(len, PAGE_SIZE).unwrap();
+            let ptr = unsafe { alloc_zeroed(layout) };
+            self.regions.push((ptr, layout));
+            NonNull::new(ptr)
+        }
+    }
+
+    impl Drop for TestSource {
+        fn drop(&mut self) {
+            for (ptr, layout) in self.regions.drain(..) {
+                unsafe { dealloc(ptr, layout) };
+            }
+        }
+    }
+
+    fn heap() -> Heap<TestSource> {
+        Heap::new(TestSource::default())
+    }
+
+    /// A byte that depends on where it is and in which allocation, so that
+    /// allocations that overlap overwrite each other's pattern.
+    fn canary(id: usize, offset: usize) -> u8 {
+        (id.wrapping_mul(31) ^ offset.wrapping_mul(7)) as u8
+    }
+
+    unsafe fn fill(ptr: *mut u8, len: usize, id: usize) {
+        for offset in 0..len {
+            unsafe { ptr.add(offset).write(canary(id, offset)) };
+        }
+    }
+
+    unsafe fn check(ptr: *mut u8, len: usize, id: usize) {
+        for offset in 0..len {
+            assert_eq!(
+                canary(id, offset),
+                unsafe { ptr.add(offset).read() },
+                "allocation {id} was overwritten at {offset}"
+            );
+        }
+    }
+
+    /// Pseudo random sizes, mostly small, some large.
+    fn sizes(count: usize) -> impl Iterator<Item = usize> {
+        let mut state = 0x2545_F491_u32;
+        (0..count).map(move |_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            match state % 8 {
+                0 => state as usize % 40_000,
+                1 => 0,
+                _ => state as usize % 700,
+            }
+        })
+    }
+
+    #[test]
+    fn test_alignment_and_usable_size() {
+        let mut heap = heap();
+        for size in (0..5000).step_by(7) {
+            let ptr = heap.allocate(size);
+            assert!(!ptr.is_null());
+            assert_eq!(0, ptr as usize % ALIGN, "{size} bytes at {ptr:p}");
+            assert!(unsafe { heap.usable_size(ptr) } >= size);
+        }
+    }
+
+    #[test]
+    fn test_interleaved_with_canaries() {
+        let mut heap = heap();
+        let mut live: Vec<(*mut u8, usize, usize)> = Vec::new();
+        for (id, size) in sizes(3000).enumerate() {
+            let ptr = heap.allocate(size);
+            assert!(!ptr.is_null());
+            unsafe { fill(ptr, size, id) };
+            live.push((ptr, size, id));
+            // free every third allocation, from somewhere in the middle
+            if id % 3 == 2 {
+                let (ptr, size, id) = live.swap_remove(id * 7 % live.len());
+                unsafe {
+                    check(ptr, size, id);
+                    heap.free(ptr);
+                }
+            }
+        }
+        for (ptr, size, id) in live {
+            unsafe {
+                check(ptr, size, id);
+                heap.free(ptr);
+            }
+        }
+    }
+
+    #[test]
+    fn test_small_blocks_are_reused() {
+        let mut heap = heap();
+        let a = heap.allocate(100);
+        let b = heap.allocate(100);
+        unsafe { heap.free(a) };
+        assert_eq!(a, heap.allocate(97));
+        assert_eq!(112, unsafe { heap.usable_size(b) });
+    }
+
+    #[test]
+    fn test_realloc_preserves_contents() {
+        let mut heap = heap();
+        let mut ptr = heap.allocate(10);
+        let mut size = 10;
+        unsafe { fill(ptr, size, 1) };
+        for new_size in [20, 100, 3000, 70_000, 50, 5000, 0, 1] {
+            ptr = unsafe { heap.reallocate(ptr, new_size) };
+            assert!(!ptr.is_null());
+            unsafe { check(ptr, size.min(new_size), 1) };
+            size = new_size;
+            unsafe { fill(ptr, size, 1) };
+        }
+        unsafe { heap.free(ptr) };
+    }
+
+    #[test]
+    fn test_realloc_grows_in_place() {
+        let mut heap = heap();
+        let a = heap.allocate(10_000);
+        let b = heap.allocate(10_000);
+        let c = heap.allocate(10_000);
+        unsafe {
+            fill(a, 10_000, 1);
+            heap.free(b);
+            // there's room where b was
+            assert_eq!(a, heap.reallocate(a, 20_000));
+            check(a, 10_000, 1);
+            // but not for this much, since c is in the way
+            assert_ne!(a, heap.reallocate(a, 30_000));
+            heap.free(c);
+        }
+    }
+
+    #[test]
+    fn test_realloc_shrinks_in_place() {
+        let mut heap = heap();
+        let a = heap.allocate(50_000);
+        unsafe {
+            assert_eq!(a, heap.reallocate(a, 10_000));
+            assert!(heap.usable_size(a) < 50_000);
+            // the part that was split off is free again
+            let b = heap.allocate(30_000);
+            assert_eq!(a.add(heap.usable_size(a) + HEADER), b);
+        }
+    }
+
+    #[test]
+    fn test_fragmentation() {
+        let mut heap = heap();
+        let blocks = (0..200)
+            .map(|i| heap.allocate(4000 + i % 3 * 16))
+            .collect::<Vec<_>>();
+        let mapped = heap.source.mapped();
+        // free every other block first, so that no two free blocks are next
+        // to each other until the rest is freed
+        for ptr in blocks
+            .iter()
+            .step_by(2)
+            .chain(blocks.iter().skip(1).step_by(2))
+        {
+            unsafe { heap.free(*ptr) };
+        }
+        // everything was merged back into a single chunk, which fits this
+        let large = heap.allocate(700_000);
+        assert!(!large.is_null());
+        assert_eq!(mapped, heap.source.mapped());
+    }
+
+    #[test]
+    fn test_calloc_overflow() {
+        let mut heap = heap();
+        assert!(heap.allocate_zeroed(usize::MAX / 2, 3).is_null());
+        assert!(heap.allocate(usize::MAX - 8).is_null());
+        let ptr = heap.allocate(64);
+        unsafe {
+            ptr.write_bytes(0xAA, 64);
+            heap.free(ptr);
+        }
+        let ptr = heap.allocate_zeroed(4, 16);
+        assert!((0..64).all(|i| unsafe { ptr.add(i).read() } == 0));
+    }
+}
@@ -0,0 +1,104 @@
+//! `stdlib.h`.
+
+use core::ffi::c_void;
+use core::ptr::{null_mut, NonNull};
+
+use kernel_api::syscall::{Errno, Syscall};
+
+use crate::errno::set_errno;
+use crate::lock::Mutex;
+use crate::stdlib::malloc::{Heap, Source};
+use crate::syscall::syscall;
+
+pub mod env;
+pub mod exit;
+pub mod malloc;
+pub mod num;
+pub mod sort;
+
+pub use env::{environ, getenv, putenv, setenv, unsetenv};
+pub use exit::{
+    _Exit, __cxa_atexit, __cxa_finalize, _exit, at_quick_exit, atexit, exit, quick_exit,
+    EXIT_FAILURE, EXIT_SUCCESS,
+};
+pub use num::{atof, atoi, atol, atoll, strtod, strtol, strtoll, strtoul, strtoull};
+pub use sort::{bsearch, qsort, qsort_r};
+
+pub(crate) const PROT_NONE: usize = 0x0;
+pub(crate) const PROT_READ: usize = 0x1;
+pub(crate) const PROT_WRITE: usize = 0x2;
+pub(crate) const MAP_PRIVATE: usize = 0x2;
+pub(crate) const MAP_FIXED: usize = 0x4;
+pub(crate) const MAP_ANONYMOUS: usize = 0x8;
+pub(crate) const MAP_POPULATE: usize = 0x10;
+
+static HEAP: Mutex<Heap<AnonymousMemory>> = Mutex::new(Heap::new(AnonymousMemory));
+
+/// Maps private anonymous memory for the heap.
+struct AnonymousMemory;
+
+impl Source for AnonymousMemory {
+    fn map(&mut self, len: usize) -> Option<NonNull<u8>> {
+        let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+        let ret = unsafe { syscall(Syscall::Mmap, [0, len, PROT_READ | PROT_WRITE, flags, 0, 0]) };
+        if ret < 0 {
+            return None;
+        }
+        NonNull::new(ret as *mut u8)
+    }
+}
+
+/// Sets `errno` to `ENOMEM` if the allocation failed.
+fn allocated(ptr: *mut u8) -> *mut c_void {
+    if ptr.is_null() {
+        set_errno(Errno::ENOMEM);
+    }
+    ptr.cast()
+}
+
+/// # Safety
+/// The returned memory is uninitialized.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn malloc(size: usize) -> *mut c_void {
+    allocated(HEAP.lock().allocate(size))
+}
+
+/// # Safety
+/// `ptr` must be null, or have been returned by the allocation functions and
+/// not have been freed since.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn free(ptr: *mut c_void) {
+    unsafe { HEAP.lock().free(ptr.cast()) }
+}
+
+/// Allocates `count` elements of `size` bytes, which are zeroed. Returns null
+/// if the total size doesn't fit into a `usize`.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn calloc(count: usize, size: usize) -> *mut c_void {
+    allocated(HEAP.lock().allocate_zeroed(count, size))
+}
+
+/// Resizing to zero bytes frees the block and returns null, like glibc does.
+///
+/// # Safety
+/// `ptr` must be null, or have been returned by the allocation functions and
+/// not have been freed since.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void {
+    if size == 0 && !ptr.is_null() {
+        unsafe { free(ptr) };
+        return null_mut();
+    }
+    allocated(unsafe { HEAP.lock().reallocate(ptr.cast(), size) })
+}
+
+/// # Safety
+/// `ptr` must be null, or have been returned by the allocation functions and
+/// not have been freed since.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn malloc_usable_size(ptr: *mut c_void) -> usize {
+    if ptr.is_null() {
+        return 0;
+    }
+    unsafe { HEAP.lock().usable_size(ptr.cast()) }
+}
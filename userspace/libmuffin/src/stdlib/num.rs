@@ -0,0 +1,699 @@
+//! Converting strings to numbers.
+//!
+//! The integer functions accept leading white space, a sign, and digits in a
+//! base from 2 to 36, where base 0 means 16 after `0x`, 8 after `0` and 10
+//! otherwise. Values that don't fit are clamped, and set `errno` to `ERANGE`.
+//!
+//! `strtod` accepts decimal and hexadecimal floats with the decimal point of
+//! the current locale, `inf`, `infinity`, `nan` and `nan(chars)`. Its results are correctly rounded, to nearest with ties
+//! to even, for all inputs: decimal floats are parsed by `core`, which is
+//! exact, and hexadecimal ones are rounded here. Results that overflow are
+//! infinite, and results that are subnormal or zero although the input isn't
+//! set `errno` to `ERANGE`, except for hexadecimal floats that are exact.
+
+use core::ffi::{c_char, c_double, c_int, c_long, c_longlong, c_ulong, c_ulonglong, CStr};
+use core::ptr::null_mut;
+
+use kernel_api::syscall::Errno;
+
+use crate::ctype::Class;
+use crate::errno::set_errno;
+use crate::locale;
+
+/// An integer as it was written, before it's checked against the range of
+/// its type.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Integer {
+    negative: bool,
+    /// Only meaningful if there was no overflow.
+    magnitude: u64,
+    /// Whether the magnitude doesn't fit into a `u64`.
+    overflow: bool,
+    /// The number of bytes that were consumed.
+    len: usize,
+}
+
+impl Integer {
+    /// The value as an `i64`, or the clamped value if it doesn't fit.
+    fn signed(self) -> Result<i64, i64> {
+        if self.negative {
+            if self.overflow || self.magnitude > i64::MIN.unsigned_abs() {
+                Err(i64::MIN)
+            } else {
+                Ok(0i64.wrapping_sub_unsigned(self.magnitude))
+            }
+        } else if self.overflow || self.magnitude > i64::MAX as u64 {
+            Err(i64::MAX)
+        } else {
+            Ok(self.magnitude as i64)
+        }
+    }
+
+    /// The value as a `u64`, where negative values wrap around like C's
+    /// conversions do, or the clamped value if it doesn't fit.
+    fn unsigned(self) -> Result<u64, u64> {
+        if self.overflow {
+            Err(u64::MAX)
+        } else if self.negative {
+            Ok(self.magnitude.wrapping_neg())
+        } else {
+            Ok(self.magnitude)
+        }
+    }
+}
+
+fn is_space(b: u8) -> bool {
+    locale::current().is(c_int::from(b), Class::Space)
+}
+
+fn digit(b: u8) -> Option<u32> {
+    char::from(b).to_digit(36)
+}
+
+/// The index after the leading white space and the sign, and whether the
+/// sign is negative.
+fn skip_space_and_sign(s: &[u8]) -> (usize, bool) {
+    let i = s.iter().position(|&b| !is_space(b)).unwrap_or(s.len());
+    match s.get(i) {
+        Some(b'-') => (i + 1, true),
+        Some(b'+') => (i + 1, false),
+        _ => (i, false),
+    }
+}
+
+/// Whether `s` starts with `0x` or `0X`, followed by something that `valid`
+/// accepts.
+fn has_hex_prefix(s: &[u8], valid: impl Fn(&[u8]) -> bool) -> bool {
+    matches!(s, [b'0', b'x' | b'X', rest @ ..] if valid(rest))
+}
+
+fn is_hex_digit(s: &[u8]) -> bool {
+    s.first().is_some_and(u8::is_ascii_hexdigit)
+}
+
+/// Parses an integer in `base`, which is 0 or from 2 to 36. Returns [`None`]
+/// if there are no digits.
+fn parse_integer(s: &[u8], base: u32) -> Option<Integer> {
+    let (mut i, negative) = skip_space_and_sign(s);
+    let prefix = has_hex_prefix(&s[i..], is_hex_digit);
+    let base = match base {
+        0 | 16 if prefix => {
+            i += 2;
+            16
+        }
+        0 if s.get(i) == Some(&b'0') => 8,
+        0 => 10,
+        base => base,
+    };
+
+    let start = i;
+    let mut magnitude = 0_u64;
+    let mut overflow = false;
+    while let Some(d) = s.get(i).and_then(|&b| digit(b)).filter(|&d| d < base) {
+        match magnitude
+            .checked_mul(u64::from(base))
+            .and_then(|m| m.checked_add(u64::from(d)))
+        {
+            Some(m) => magnitude = m,
+            None => overflow = true,
+        }
+        i += 1;
+    }
+    (i > start).then_some(Integer {
+        negative,
+        magnitude,
+        overflow,
+        len: i,
+    })
+}
+
+/// # Safety
+/// `endptr` must be null or valid for writes, and `len` must be within the
+/// string at `nptr`.
+unsafe fn set_end(endptr: *mut *mut c_char, nptr: *const c_char, len: usize) {
+    if !endptr.is_null() {
+        unsafe { *endptr = nptr.add(len).cast_mut() };
+    }
+}
+
+/// The common part of the `strtol` family, with `range` checking the value
+/// against the range of the result type.
+///
+/// # Safety
+/// `nptr` must be a valid C string, and `endptr` must be null or valid for
+/// writes.
+unsafe fn strto<T: Default>(
+    nptr: *const c_char,
+    endptr: *mut *mut c_char,
+    base: c_int,
+    range: impl FnOnce(Integer) -> Result<T, T>,
+) -> T {
+    unsafe { set_end(endptr, nptr, 0) };
+    let Some(base) = u32::try_from(base)
+        .ok()
+        .filter(|&base| base == 0 || (2..=36).contains(&base))
+    else {
+        set_errno(Errno::EINVAL);
+        return T::default();
+    };
+    let s = unsafe { CStr::from_ptr(nptr) }.to_bytes();
+    let Some(integer) = parse_integer(s, base) else {
+        return T::default();
+    };
+    unsafe { set_end(endptr, nptr, integer.len) };
+    range(integer).unwrap_or_else(|clamped| {
+        set_errno(Errno::ERANGE);
+        clamped
+    })
+}
+
+/// Converts the start of the string to a `long`. `endptr`, unless it's null,
+/// is set to the first byte after the number, or to `nptr` if there is none.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strtol.html>.
+///
+/// # Safety
+/// `nptr` must be a valid C string, and `endptr` must be null or valid for
+/// writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtol(
+    nptr: *const c_char,
+    endptr: *mut *mut c_char,
+    base: c_int,
+) -> c_long {
+    unsafe { strto(nptr, endptr, base, Integer::signed) }
+}
+
+/// Like [`strtol`], for a `long long`.
+///
+/// # Safety
+/// See [`strtol`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtoll(
+    nptr: *const c_char,
+    endptr: *mut *mut c_char,
+    base: c_int,
+) -> c_longlong {
+    unsafe { strto(nptr, endptr, base, Integer::signed) }
+}
+
+/// Like [`strtol`], for an `unsigned long`. A negative number is negated in
+/// the unsigned type, so `-1` is `ULONG_MAX`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strtoul.html>.
+///
+/// # Safety
+/// See [`strtol`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtoul(
+    nptr: *const c_char,
+    endptr: *mut *mut c_char,
+    base: c_int,
+) -> c_ulong {
+    unsafe { strto(nptr, endptr, base, Integer::unsigned) }
+}
+
+/// Like [`strtoul`], for an `unsigned long long`.
+///
+/// # Safety
+/// See [`strtol`].
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtoull(
+    nptr: *const c_char,
+    endptr: *mut *mut c_char,
+    base: c_int,
+) -> c_ulonglong {
+    unsafe { strto(nptr, endptr, base, Integer::unsigned) }
+}
+
+/// A double as it was written.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Double {
+    value: f64,
+    /// The number of bytes that were consumed.
+    len: usize,
+    /// Whether the value overflowed or underflowed.
+    out_of_range: bool,
+}
+
+fn starts_with_ignore_case(s: &[u8], prefix: &[u8]) -> bool {
+    s.get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// Parses a double. Returns [`None`] if there is none.
+fn parse_double(s: &[u8]) -> Option<Double> {
+    let (start, negative) = skip_space_and_sign(s);
+    let rest = &s[start..];
+    let (value, len, out_of_range) = if starts_with_ignore_case(rest, b"inf") {
+        let len = if starts_with_ignore_case(rest, b"infinity") {
+            8
+        } else {
+            3
+        };
+        (f64::INFINITY, len, false)
+    } else if starts_with_ignore_case(rest, b"nan") {
+        // the chars in parentheses only count if the parentheses are closed
+        let chars = rest[3..]
+            .strip_prefix(b"(")
+            .and_then(|chars| {
+                chars
+                    .iter()
+                    .position(|&b| !(b.is_ascii_alphanumeric() || b == b'_'))
+                    .filter(|&end| chars[end] == b')')
+            })
+            .map_or(0, |end| end + 2);
+        (f64::NAN, 3 + chars, false)
+    } else if has_hex_prefix(rest, |s| {
+        is_hex_digit(s)
+            || (s.first() == Some(&locale::current().decimal_point()) && is_hex_digit(&s[1..]))
+    }) {
+        parse_hex(&rest[2..]).map(|(value, len, out_of_range)| (value, len + 2, out_of_range))?
+    } else {
+        parse_decimal(rest)?
+    };
+    Some(Double {
+        value: if negative { -value } else { value },
+        len: start + len,
+        out_of_range,
+    })
+}
+
+/// Parses digits with an optional decimal point and an optional exponent,
+/// without a sign. Returns the value, the number of bytes that it took and
+/// whether it's out of range.
+fn parse_decimal(s: &[u8]) -> Option<(f64, usize, bool)> {
+    let digits = |from: usize| s[from..].iter().take_while(|b| b.is_ascii_digit()).count();
+    let integer = digits(0);
+    let mut len = integer;
+    let mut fraction = 0;
+    if s.get(len) == Some(&locale::current().decimal_point()) {
+        fraction = digits(len + 1);
+        len += 1 + fraction;
+    }
+    if integer + fraction == 0 {
+        return None;
+    }
+    let nonzero = s[..len].iter().any(|b| (b'1'..=b'9').contains(b));
+
+    if matches!(s.get(len), Some(b'e' | b'E')) {
+        let sign = usize::from(matches!(s.get(len + 1), Some(b'+' | b'-')));
+        let exponent = digits(len + 1 + sign);
+        if exponent > 0 {
+            len += 1 + sign + exponent;
+        }
+    }
+
+    // only ASCII digits, the decimal point, `e` and signs, and `core` only
+    // knows `.`, which is the decimal point of every locale there is so far
+    let text = unsafe { core::str::from_utf8_unchecked(&s[..len]) };
+    let value = text.parse::<f64>().ok()?;
+    let out_of_range = value.is_infinite() || (nonzero && (value == 0.0 || value.is_subnormal()));
+    Some((value, len, out_of_range))
+}
+
+/// Parses hexadecimal digits with an optional point and an optional binary
+/// exponent, after the `0x`. Returns the value, the number of bytes that it
+/// took and whether it's out of range.
+fn parse_hex(s: &[u8]) -> Option<(f64, usize, bool)> {
+    // the value is `(mantissa + a bit of sticky) * 2^exponent`
+    let mut mantissa = 0_u64;
+    let mut sticky = false;
+    let mut exponent = 0_i64;
+    let mut point = false;
+    let mut any = false;
+    let mut len = 0;
+    let decimal_point = locale::current().decimal_point();
+    for &b in s {
+        match b {
+            _ if b == decimal_point && !point => point = true,
+            _ => {
+                let Some(d) = char::from(b).to_digit(16) else {
+                    break;
+                };
+                any = true;
+                if mantissa >> 60 == 0 {
+                    mantissa = mantissa << 4 | u64::from(d);
+                    if point {
+                        exponent -= 4;
+                    }
+                } else {
+                    sticky |= d != 0;
+                    if !point {
+                        exponent += 4;
+                    }
+                }
+            }
+        }
+        len += 1;
+    }
+    if !any {
+        return None;
+    }
+
+    if matches!(s.get(len), Some(b'p' | b'P')) {
+        let (sign, negative) = match s.get(len + 1) {
+            Some(b'-') => (1, true),
+            Some(b'+') => (1, false),
+            _ => (0, false),
+        };
+        let mut digits = 0;
+        let mut value = 0_i64;
+        for &b in s[len + 1 + sign..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+        {
+            // far beyond where anything but zero or infinity is left
+            value = (value * 10 + i64::from(b - b'0')).min(1 << 20);
+            digits += 1;
+        }
+        if digits > 0 {
+            len += 1 + sign + digits;
+            exponent += if negative { -value } else { value };
+        }
+    }
+
+    let (value, inexact) = round_binary(mantissa, sticky, exponent);
+    let out_of_range = value.is_infinite() || (inexact && (value == 0.0 || value.is_subnormal()));
+    Some((value, len, out_of_range))
+}
+
+/// Rounds `(mantissa + sticky) * 2^exponent` to the nearest double, ties to
+/// even, where `sticky` stands for nonzero bits below the mantissa. Returns
+/// the double, and whether it's inexact.
+fn round_binary(mantissa: u64, sticky: bool, exponent: i64) -> (f64, bool) {
+    if mantissa == 0 {
+        return (0.0, false);
+    }
+    // the leading bit becomes bit 63, which is worth 2^top
+    let shift = mantissa.leading_zeros();
+    let mantissa = u128::from(mantissa << shift);
+    let top = exponent - i64::from(shift) + 63;
+    if top > 1023 {
+        return (f64::INFINITY, true);
+    }
+
+    // 53 bits are kept, fewer for subnormals, whose leading bit is worth less
+    // than 2^-1022
+    let dropped = if top < -1022 { 11 + (-1022 - top) } else { 11 };
+    if dropped > 64 {
+        // less than half of the smallest subnormal
+        return (0.0, true);
+    }
+    let dropped = dropped as u32;
+    let mut kept = mantissa >> dropped;
+    let remainder = mantissa & ((1 << dropped) - 1);
+    let half = 1 << (dropped - 1);
+    let inexact = remainder != 0 || sticky;
+    if remainder > half || (remainder == half && (sticky || kept & 1 == 1)) {
+        kept += 1;
+    }
+
+    let bits = if top < -1022 {
+        // rounding up to 2^52 makes it the smallest normal, which has the
+        // same bits
+        kept as u64
+    } else {
+        let (kept, top) = if kept == 1 << 53 {
+            (kept >> 1, top + 1)
+        } else {
+            (kept, top)
+        };
+        if top > 1023 {
+            return (f64::INFINITY, true);
+        }
+        ((top + 1023) as u64) << 52 | (kept as u64 & ((1 << 52) - 1))
+    };
+    (f64::from_bits(bits), inexact)
+}
+
+/// Converts the start of the string to a `double`. `endptr`, unless it's
+/// null, is set to the first byte after the number, or to `nptr` if there is
+/// none. Overflows return `HUGE_VAL` with the sign of the number.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strtod.html>.
+///
+/// # Safety
+/// `nptr` must be a valid C string, and `endptr` must be null or valid for
+/// writes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtod(nptr: *const c_char, endptr: *mut *mut c_char) -> c_double {
+    let s = unsafe { CStr::from_ptr(nptr) }.to_bytes();
+    let Some(double) = parse_double(s) else {
+        unsafe { set_end(endptr, nptr, 0) };
+        return 0.0;
+    };
+    unsafe { set_end(endptr, nptr, double.len) };
+    if double.out_of_range {
+        set_errno(Errno::ERANGE);
+    }
+    double.value
+}
+
+/// `(int) strtol(nptr, NULL, 10)`.
+///
+/// # Safety
+/// `nptr` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn atoi(nptr: *const c_char) -> c_int {
+    unsafe { strtol(nptr, null_mut(), 10) as c_int }
+}
+
+/// `strtol(nptr, NULL, 10)`.
+///
+/// # Safety
+/// `nptr` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn atol(nptr: *const c_char) -> c_long {
+    unsafe { strtol(nptr, null_mut(), 10) }
+}
+
+/// `strtoll(nptr, NULL, 10)`.
+///
+/// # Safety
+/// `nptr` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn atoll(nptr: *const c_char) -> c_longlong {
+    unsafe { strtoll(nptr, null_mut(), 10) }
+}
+
+/// `strtod(nptr, NULL)`.
+///
+/// # Safety
+/// `nptr` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn atof(nptr: *const c_char) -> c_double {
+    unsafe { strtod(nptr, null_mut()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ffi::CStr;
+
+    use super::*;
+    use crate::errno::{__errno_location, errno};
+
+    /// Calls `f` with a cleared `errno`, and returns the result, the number
+    /// of bytes consumed and `errno`.
+    fn call<T>(
+        s: &CStr,
+        f: impl FnOnce(*const c_char, *mut *mut c_char) -> T,
+    ) -> (T, usize, c_int) {
+        unsafe { *__errno_location() = 0 };
+        let mut end = null_mut();
+        let result = f(s.as_ptr(), &mut end);
+        (result, end as usize - s.as_ptr() as usize, errno())
+    }
+
+    const OK: c_int = 0;
+    const ERANGE: c_int = Errno::ERANGE.code();
+    const EINVAL: c_int = Errno::EINVAL.code();
+
+    #[test]
+    fn test_strtol() {
+        let cases: &[(&CStr, c_int, c_long, usize, c_int)] = &[
+            (c"42", 10, 42, 2, OK),
+            (c" +42abc", 10, 42, 4, OK),
+            (c"\t\n -17", 10, -17, 6, OK),
+            (c"0x", 0, 0, 1, OK),
+            (c"0x", 16, 0, 1, OK),
+            (c"0xg", 16, 0, 1, OK),
+            (c"0x1F", 0, 31, 4, OK),
+            (c"0X1f", 16, 31, 4, OK),
+            (c"1f", 16, 31, 2, OK),
+            (c"010", 0, 8, 3, OK),
+            (c"08", 0, 0, 1, OK),
+            (c"010", 10, 10, 3, OK),
+            (c"1012", 2, 5, 3, OK),
+            (c"zZ", 36, 1295, 2, OK),
+            (c"-0", 10, 0, 2, OK),
+            (c"", 10, 0, 0, OK),
+            (c"  -", 10, 0, 0, OK),
+            (c"+-1", 10, 0, 0, OK),
+            (c"abc", 10, 0, 0, OK),
+            (c"9223372036854775807", 10, c_long::MAX, 19, OK),
+            (c"9223372036854775808", 10, c_long::MAX, 19, ERANGE),
+            (c"-9223372036854775808", 10, c_long::MIN, 20, OK),
+            (c"-9223372036854775809", 10, c_long::MIN, 20, ERANGE),
+            (c"99999999999999999999999x", 10, c_long::MAX, 23, ERANGE),
+            (c"-0x8000000000000000", 0, c_long::MIN, 19, OK),
+            (c"12", 1, 0, 0, EINVAL),
+            (c"12", 37, 0, 0, EINVAL),
+            (c"12", -1, 0, 0, EINVAL),
+        ];
+        for &(s, base, value, len, error) in cases {
+            let result = call(s, |nptr, endptr| unsafe { strtol(nptr, endptr, base) });
+            assert_eq!((value, len, error), result, "strtol({s:?}, {base})");
+            let result = call(s, |nptr, endptr| unsafe { strtoll(nptr, endptr, base) });
+            assert_eq!((value, len, error), result, "strtoll({s:?}, {base})");
+        }
+    }
+
+    #[test]
+    fn test_strtoul() {
+        let cases: &[(&CStr, c_int, c_ulong, usize, c_int)] = &[
+            (c"42", 10, 42, 2, OK),
+            (c"-1", 10, c_ulong::MAX, 2, OK),
+            (c"-18446744073709551615", 10, 1, 21, OK),
+            (c"18446744073709551615", 10, c_ulong::MAX, 20, OK),
+            (c"18446744073709551616", 10, c_ulong::MAX, 20, ERANGE),
+            (c"-18446744073709551616", 10, c_ulong::MAX, 21, ERANGE),
+            (c"0xffffffffffffffff", 0, c_ulong::MAX, 18, OK),
+            (c"0x", 0, 0, 1, OK),
+            (c"777", 8, 511, 3, OK),
+            (c"x", 10, 0, 0, OK),
+        ];
+        for &(s, base, value, len, error) in cases {
+            let result = call(s, |nptr, endptr| unsafe { strtoul(nptr, endptr, base) });
+            assert_eq!((value, len, error), result, "strtoul({s:?}, {base})");
+            let result = call(s, |nptr, endptr| unsafe { strtoull(nptr, endptr, base) });
+            assert_eq!((value, len, error), result, "strtoull({s:?}, {base})");
+        }
+    }
+
+    #[test]
+    fn test_strtod() {
+        let min_subnormal = f64::from_bits(1);
+        let cases: &[(&CStr, f64, usize, c_int)] = &[
+            (c"1.5", 1.5, 3, OK),
+            (c" -0.25e2x", -25.0, 8, OK),
+            (c"+.5", 0.5, 3, OK),
+            (c"5.", 5.0, 2, OK),
+            (c"1.e3", 1000.0, 4, OK),
+            (c"1e", 1.0, 1, OK),
+            (c"1e+", 1.0, 1, OK),
+            (c"1E-2", 0.01, 4, OK),
+            (c"0.1", 0.1, 3, OK),
+            (
+                c"123456789012345678901234567890",
+                1.2345678901234568e29,
+                30,
+                OK,
+            ),
+            // halfway between 1 and the next double, and just above it
+            (
+                c"1.00000000000000011102230246251565404236316680908203125",
+                1.0,
+                55,
+                OK,
+            ),
+            (
+                c"1.00000000000000011102230246251565404236316680908203126",
+                1.0000000000000002,
+                55,
+                OK,
+            ),
+            (c"1.7976931348623157e308", f64::MAX, 22, OK),
+            (c"1e309", f64::INFINITY, 5, ERANGE),
+            (c"-1e309", f64::NEG_INFINITY, 6, ERANGE),
+            (c"1e99999999999999999999", f64::INFINITY, 22, ERANGE),
+            (c"2.2250738585072014e-308", f64::MIN_POSITIVE, 23, OK),
+            (
+                c"2.2250738585072009e-308",
+                f64::from_bits(0x000f_ffff_ffff_ffff),
+                23,
+                ERANGE,
+            ),
+            (c"4.9e-324", min_subnormal, 8, ERANGE),
+            (c"2e-324", 0.0, 6, ERANGE),
+            (c"1e-400", 0.0, 6, ERANGE),
+            (c"0e99999", 0.0, 7, OK),
+            (c"0x1.8p1", 3.0, 7, OK),
+            (c"0x.8", 0.5, 4, OK),
+            (c"0X1P+3", 8.0, 6, OK),
+            (c"0x1p", 1.0, 3, OK),
+            (c"0x", 0.0, 1, OK),
+            (c"0x.p1", 0.0, 1, OK),
+            (c"0xAbC", 2748.0, 5, OK),
+            (c"0x1p-1074", min_subnormal, 9, OK),
+            (c"0x1p-1075", 0.0, 9, ERANGE),
+            (c"0x1.8p-1075", min_subnormal, 11, ERANGE),
+            (c"0x1p-1022", f64::MIN_POSITIVE, 9, OK),
+            (c"0x1.fffffffffffffp1023", f64::MAX, 22, OK),
+            (c"0x1.fffffffffffff8p1023", f64::INFINITY, 23, ERANGE),
+            (c"0x1p1024", f64::INFINITY, 8, ERANGE),
+            // ties go to even, anything beyond them rounds up
+            (c"0x1.00000000000008p0", 1.0, 20, OK),
+            (c"0x1.00000000000018p0", 1.0000000000000004, 20, OK),
+            (
+                c"0x1.000000000000080000000001p0",
+                1.0000000000000002,
+                30,
+                OK,
+            ),
+            (
+                c"0x10000000000000000000000001",
+                1.2676506002282294e30,
+                28,
+                OK,
+            ),
+            (c"inf", f64::INFINITY, 3, OK),
+            (c"-INFINITY", f64::NEG_INFINITY, 9, OK),
+            (c"Infinit", f64::INFINITY, 3, OK),
+            (c"", 0.0, 0, OK),
+            (c" .", 0.0, 0, OK),
+            (c"-", 0.0, 0, OK),
+            (c"e5", 0.0, 0, OK),
+        ];
+        for &(s, value, len, error) in cases {
+            let result = call(s, |nptr, endptr| unsafe { strtod(nptr, endptr) });
+            assert_eq!(
+                (value.to_bits(), len, error),
+                (result.0.to_bits(), result.1, result.2),
+                "strtod({s:?}) = {}",
+                result.0
+            );
+        }
+    }
+
+    #[test]
+    fn test_strtod_nan() {
+        let cases: &[(&CStr, usize, bool)] = &[
+            (c"nan", 3, false),
+            (c"NaN", 3, false),
+            (c"-nan", 4, true),
+            (c"nan(chars)", 10, false),
+            (c"nan(0x_1)x", 9, false),
+            (c"nan()", 5, false),
+            (c"nan(", 3, false),
+            (c"nan(a b)", 3, false),
+        ];
+        for &(s, len, negative) in cases {
+            let (value, consumed, error) = call(s, |nptr, endptr| unsafe { strtod(nptr, endptr) });
+            assert!(value.is_nan(), "strtod({s:?}) = {value}");
+            assert_eq!(negative, value.is_sign_negative(), "sign of strtod({s:?})");
+            assert_eq!((len, OK), (consumed, error), "strtod({s:?})");
+        }
+    }
+
+    #[test]
+    fn test_ato() {
+        unsafe {
+            assert_eq!(-12, atoi(c"  -12.5".as_ptr()));
+            assert_eq!(0, atoi(c"x1".as_ptr()));
+            assert_eq!(1 << 40, atol(c"1099511627776".as_ptr()));
+            assert_eq!(-(1 << 40), atoll(c"-1099511627776".as_ptr()));
+            assert_eq!(-12.5, atof(c"  -12.5".as_ptr()));
+        }
+    }
+}
@@ -0,0 +1,481 @@
+//! Sorting and searching arrays of elements of any size.
+//!
+//! `qsort` is an introsort: a quicksort with a median of three pivot, which
+//! sorts short ranges with an insertion sort and falls back to a heapsort
+//! when it recurses too deep. It only ever recurses into the smaller half, so
+//! the stack stays small, and it doesn't allocate. Every index is checked
+//! against the bounds of the range it's in, so a comparator that isn't
+//! consistent produces an unsorted array, but never accesses memory outside
+//! of it.
+
+use core::ffi::{c_int, c_void};
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+/// Ranges up to this length are sorted with an insertion sort.
+const INSERTION_SORT_MAX: usize = 16;
+
+pub type Comparator = unsafe extern "C" fn(*const c_void, *const c_void) -> c_int;
+pub type ComparatorWithContext =
+    unsafe extern "C" fn(*const c_void, *const c_void, *mut c_void) -> c_int;
+
+/// An array of elements of `size` bytes, and how to order them.
+struct Elements<F> {
+    base: *mut u8,
+    size: usize,
+    compare: F,
+}
+
+impl<F: FnMut(*const c_void, *const c_void) -> c_int> Elements<F> {
+    fn at(&self, i: usize) -> *mut u8 {
+        unsafe { self.base.add(i * self.size) }
+    }
+
+    fn less(&mut self, i: usize, j: usize) -> bool {
+        let (a, b) = (self.at(i), self.at(j));
+        (self.compare)(a.cast(), b.cast()) < 0
+    }
+
+    fn swap(&mut self, i: usize, j: usize) {
+        if i == j {
+            return;
+        }
+        let (a, b) = (self.at(i), self.at(j));
+        unsafe {
+            // whole words where the elements allow it, bytes where they don't
+            if self.size.is_multiple_of(size_of::<usize>()) && a.cast::<usize>().is_aligned() {
+                let (a, b) = (a.cast::<usize>(), b.cast::<usize>());
+                for k in 0..self.size / size_of::<usize>() {
+                    a.add(k).swap(b.add(k));
+                }
+            } else {
+                for k in 0..self.size {
+                    a.add(k).swap(b.add(k));
+                }
+            }
+        }
+    }
+
+    /// Sorts the elements `start..end`.
+    fn sort(&mut self, mut start: usize, mut end: usize) {
+        // the depth at which quicksort has degenerated
+        let mut depth = 2 * (usize::BITS - (end - start).leading_zeros());
+        while end - start > INSERTION_SORT_MAX {
+            if depth == 0 {
+                self.heapsort(start, end);
+                return;
+            }
+            depth -= 1;
+
+            let pivot = self.partition(start, end);
+            if pivot - start < end - pivot {
+                self.sort(start, pivot);
+                start = pivot + 1;
+            } else {
+                self.sort(pivot + 1, end);
+                end = pivot;
+            }
+        }
+        self.insertion_sort(start, end);
+    }
+
+    /// Moves the median of the first, middle and last element to `start`,
+    /// and partitions `start..end` around it. Returns where the pivot ends
+    /// up. Everything before it isn't greater, everything after it isn't
+    /// less.
+    fn partition(&mut self, start: usize, end: usize) -> usize {
+        let (mut a, mut b, mut c) = (start, start + (end - start) / 2, end - 1);
+        if self.less(b, a) {
+            core::mem::swap(&mut a, &mut b);
+        }
+        if self.less(c, b) {
+            core::mem::swap(&mut b, &mut c);
+            if self.less(b, a) {
+                core::mem::swap(&mut a, &mut b);
+            }
+        }
+        self.swap(start, b);
+
+        // both scans stop at elements equal to the pivot, which keeps the
+        // halves balanced if there are many of them
+        let (mut i, mut j) = (start, end);
+        loop {
+            i += 1;
+            while i < end - 1 && self.less(i, start) {
+                i += 1;
+            }
+            j -= 1;
+            while j > start && self.less(start, j) {
+                j -= 1;
+            }
+            if i >= j {
+                break;
+            }
+            self.swap(i, j);
+        }
+        self.swap(start, j);
+        j
+    }
+
+    fn insertion_sort(&mut self, start: usize, end: usize) {
+        for i in start + 1..end {
+            let mut j = i;
+            while j > start && self.less(j, j - 1) {
+                self.swap(j, j - 1);
+                j -= 1;
+            }
+        }
+    }
+
+    fn heapsort(&mut self, start: usize, end: usize) {
+        let len = end - start;
+        for i in (0..len / 2).rev() {
+            self.sift_down(start, i, len);
+        }
+        for last in (1..len).rev() {
+            self.swap(start, start + last);
+            self.sift_down(start, 0, last);
+        }
+    }
+
+    /// Moves the element at `node` down into the max heap of `len` elements
+    /// that starts at `start`.
+    fn sift_down(&mut self, start: usize, mut node: usize, len: usize) {
+        loop {
+            let mut child = 2 * node + 1;
+            if child >= len {
+                return;
+            }
+            if child + 1 < len && self.less(start + child, start + child + 1) {
+                child += 1;
+            }
+            if !self.less(start + node, start + child) {
+                return;
+            }
+            self.swap(start + node, start + child);
+            node = child;
+        }
+    }
+}
+
+/// Sorts `nmemb` elements of `size` bytes at `base`, in place. The order of
+/// equal elements is unspecified.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/qsort.html>.
+///
+/// # Safety
+/// `base` must be valid for `nmemb * size` bytes, and `compar` must be safe
+/// to call with pointers to any two of the elements.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn qsort(base: *mut c_void, nmemb: usize, size: usize, compar: Comparator) {
+    let compare = |a, b| unsafe { compar(a, b) };
+    unsafe { sort(base, nmemb, size, compare) };
+}
+
+/// Like [`qsort`], but passes `arg` to every call of `compar`. The arguments
+/// are in the order of glibc, which POSIX adopted: `arg` comes last, both in
+/// `qsort_r` and in `compar`. The BSDs put it first instead.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/qsort.html>.
+///
+/// # Safety
+/// `base` must be valid for `nmemb * size` bytes, and `compar` must be safe
+/// to call with pointers to any two of the elements and `arg`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn qsort_r(
+    base: *mut c_void,
+    nmemb: usize,
+    size: usize,
+    compar: ComparatorWithContext,
+    arg: *mut c_void,
+) {
+    let compare = |a, b| unsafe { compar(a, b, arg) };
+    unsafe { sort(base, nmemb, size, compare) };
+}
+
+unsafe fn sort(
+    base: *mut c_void,
+    nmemb: usize,
+    size: usize,
+    compare: impl FnMut(*const c_void, *const c_void) -> c_int,
+) {
+    if nmemb < 2 || size == 0 {
+        return;
+    }
+    let mut elements = Elements {
+        base: base.cast(),
+        size,
+        compare,
+    };
+    elements.sort(0, nmemb);
+}
+
+/// Searches the `nmemb` elements of `size` bytes at `base`, which are sorted
+/// in the order of `compar`, for one that is equal to `key`. Returns a
+/// pointer to it, or null if there is none. `compar` is called with `key`
+/// first.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/bsearch.html>.
+///
+/// # Safety
+/// `base` must be valid for `nmemb * size` bytes, and `compar` must be safe
+/// to call with `key` and a pointer to any of the elements.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn bsearch(
+    key: *const c_void,
+    base: *const c_void,
+    nmemb: usize,
+    size: usize,
+    compar: Comparator,
+) -> *mut c_void {
+    let (mut start, mut end) = (0, nmemb);
+    while start < end {
+        let middle = start + (end - start) / 2;
+        let element = unsafe { base.byte_add(middle * size) };
+        match unsafe { compar(key, element) } {
+            0 => return element.cast_mut(),
+            ordering if ordering < 0 => end = middle,
+            _ => start = middle + 1,
+        }
+    }
+    null_mut()
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::cmp::Ordering;
+    use core::ptr::null;
+
+    use super::*;
+
+    fn ordering(ordering: Ordering) -> c_int {
+        ordering as c_int
+    }
+
+    unsafe extern "C" fn compare_u32(a: *const c_void, b: *const c_void) -> c_int {
+        let (a, b) = unsafe { (*a.cast::<u32>(), *b.cast::<u32>()) };
+        ordering(a.cmp(&b))
+    }
+
+    /// Pseudo random numbers.
+    fn random(seed: u32, len: usize) -> Vec<u32> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state
+            })
+            .collect()
+    }
+
+    fn qsort_u32(values: &mut [u32]) {
+        unsafe {
+            qsort(
+                values.as_mut_ptr().cast(),
+                values.len(),
+                size_of::<u32>(),
+                compare_u32,
+            )
+        };
+    }
+
+    /// Depends on the position of every value, so that it only matches if
+    /// the order is the same.
+    fn checksum(values: &[u32]) -> u64 {
+        values.iter().enumerate().fold(0, |sum, (i, &value)| {
+            sum.wrapping_mul(31)
+                .wrapping_add(u64::from(value) ^ i as u64)
+        })
+    }
+
+    #[repr(C)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    struct Person {
+        age: u32,
+        name: [u8; 4],
+    }
+
+    /// Sorts by age first, and by name if the ages are equal.
+    unsafe extern "C" fn compare_people(a: *const c_void, b: *const c_void) -> c_int {
+        let (a, b) = unsafe { (&*a.cast::<Person>(), &*b.cast::<Person>()) };
+        ordering(a.age.cmp(&b.age).then(a.name.cmp(&b.name)))
+    }
+
+    #[test]
+    fn test_qsort_structs_by_multiple_keys() {
+        let mut people = (0..100)
+            .map(|i: u32| Person {
+                age: i * 7 % 10,
+                name: (i * 13 % 100).to_be_bytes(),
+            })
+            .collect::<Vec<_>>();
+        let mut expected = people.clone();
+        expected.sort_by(|a, b| a.age.cmp(&b.age).then(a.name.cmp(&b.name)));
+
+        unsafe {
+            qsort(
+                people.as_mut_ptr().cast(),
+                people.len(),
+                size_of::<Person>(),
+                compare_people,
+            )
+        };
+        assert_eq!(expected, people);
+    }
+
+    #[test]
+    fn test_qsort_sorted_and_reversed() {
+        for len in [0, 1, 2, 3, 16, 17, 1000, 10_000] {
+            let sorted = (0..len).collect::<Vec<u32>>();
+            let mut values = sorted.clone();
+            qsort_u32(&mut values);
+            assert_eq!(sorted, values);
+
+            values.reverse();
+            qsort_u32(&mut values);
+            assert_eq!(sorted, values);
+        }
+    }
+
+    #[test]
+    fn test_qsort_many_equal() {
+        let mut values = random(3, 10_000)
+            .into_iter()
+            .map(|value| value % 4)
+            .collect::<Vec<_>>();
+        let mut expected = values.clone();
+        expected.sort_unstable();
+        qsort_u32(&mut values);
+        assert_eq!(expected, values);
+    }
+
+    unsafe extern "C" fn compare_first_byte(a: *const c_void, b: *const c_void) -> c_int {
+        let (a, b) = unsafe { (*a.cast::<u8>(), *b.cast::<u8>()) };
+        ordering(a.cmp(&b))
+    }
+
+    #[test]
+    fn test_qsort_element_size_3() {
+        let mut elements = random(5, 500)
+            .into_iter()
+            .map(|value| {
+                let [a, ..] = value.to_le_bytes();
+                // the other two bytes travel with the key
+                [a, a.wrapping_add(1), a.wrapping_add(2)]
+            })
+            .collect::<Vec<_>>();
+        let mut expected = elements.clone();
+        expected.sort_unstable();
+
+        unsafe {
+            qsort(
+                elements.as_mut_ptr().cast(),
+                elements.len(),
+                3,
+                compare_first_byte,
+            )
+        };
+        assert_eq!(expected, elements);
+    }
+
+    #[test]
+    fn test_qsort_one_million() {
+        let mut values = random(7, 1_000_000);
+        let mut expected = values.clone();
+        expected.sort_unstable();
+        qsort_u32(&mut values);
+        assert_eq!(checksum(&expected), checksum(&values));
+    }
+
+    unsafe extern "C" fn compare_randomly(a: *const c_void, b: *const c_void) -> c_int {
+        // the answers only depend on the addresses, and contradict each other
+        let mixed = (a.addr() ^ b.addr().rotate_left(7)).wrapping_mul(0x9E37_79B9);
+        (mixed >> 16) as c_int % 3 - 1
+    }
+
+    #[test]
+    fn test_qsort_inconsistent_comparator() {
+        // the values are in the middle of a larger buffer, whose ends must
+        // stay untouched
+        let mut buffer = vec![u32::MAX; 10_000 + 64];
+        for (i, value) in buffer[32..10_032].iter_mut().enumerate() {
+            *value = i as u32;
+        }
+        unsafe {
+            qsort(
+                buffer[32..].as_mut_ptr().cast(),
+                10_000,
+                size_of::<u32>(),
+                compare_randomly,
+            )
+        };
+        assert!(buffer[..32].iter().all(|&value| value == u32::MAX));
+        assert!(buffer[10_032..].iter().all(|&value| value == u32::MAX));
+        // it's still a permutation
+        let mut values = buffer[32..10_032].to_vec();
+        values.sort_unstable();
+        assert!(values.iter().enumerate().all(|(i, &v)| v == i as u32));
+    }
+
+    unsafe extern "C" fn compare_modulo(
+        a: *const c_void,
+        b: *const c_void,
+        arg: *mut c_void,
+    ) -> c_int {
+        let modulus = arg.addr() as u32;
+        let (a, b) = unsafe { (*a.cast::<u32>(), *b.cast::<u32>()) };
+        ordering((a % modulus).cmp(&(b % modulus)).then(a.cmp(&b)))
+    }
+
+    #[test]
+    fn test_qsort_r() {
+        let mut values = (0..20).rev().collect::<Vec<u32>>();
+        unsafe {
+            qsort_r(
+                values.as_mut_ptr().cast(),
+                values.len(),
+                size_of::<u32>(),
+                compare_modulo,
+                null_mut::<c_void>().with_addr(5),
+            )
+        };
+        assert_eq!(
+            vec![0, 5, 10, 15, 1, 6, 11, 16, 2, 7, 12, 17, 3, 8, 13, 18, 4, 9, 14, 19],
+            values
+        );
+    }
+
+    fn bsearch_u32(values: &[u32], key: u32) -> Option<usize> {
+        let found = unsafe {
+            bsearch(
+                (&raw const key).cast(),
+                values.as_ptr().cast(),
+                values.len(),
+                size_of::<u32>(),
+                compare_u32,
+            )
+        };
+        (!found.is_null()).then(|| (found.addr() - values.as_ptr().addr()) / size_of::<u32>())
+    }
+
+    #[test]
+    fn test_bsearch() {
+        let values = (0..100).map(|i| i * 2 + 10).collect::<Vec<u32>>();
+        for (i, &value) in values.iter().enumerate() {
+            assert_eq!(Some(i), bsearch_u32(&values, value));
+        }
+        // misses before, between and after the elements
+        assert_eq!(None, bsearch_u32(&values, 9));
+        assert_eq!(None, bsearch_u32(&values, 11));
+        assert_eq!(None, bsearch_u32(&values, 207));
+        assert_eq!(None, bsearch_u32(&values, u32::MAX));
+        assert_eq!(None, bsearch_u32(&[], 10));
+        assert!(unsafe { bsearch(null(), null(), 0, 4, compare_u32) }.is_null());
+    }
+}
@@ -0,0 +1,645 @@
+//! `string.h`.
+//!
+//! The functions on memory work a word at a time once the destination is
+//! aligned, and read the source with unaligned loads where it isn't. `strlen`
+//! reads whole aligned words, which may go past the terminator, but never
+//! into the next page.
+//!
+//! Strings are compared as `unsigned char`, as POSIX requires.
+
+use core::ffi::{c_char, c_int, c_void};
+use core::mem::size_of;
+use core::ptr::null_mut;
+
+const WORD: usize = size_of::<usize>();
+/// A one in every byte of a word.
+const ONES: usize = usize::from_ne_bytes([0x01; WORD]);
+/// The highest bit of every byte of a word.
+const HIGHS: usize = usize::from_ne_bytes([0x80; WORD]);
+
+/// Whether any byte of the word is zero.
+const fn has_zero_byte(word: usize) -> bool {
+    word.wrapping_sub(ONES) & !word & HIGHS != 0
+}
+
+/// Copies `n` bytes from `src` to `dst`, which must not overlap.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/memcpy.html>.
+///
+/// # Safety
+/// Both must be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memcpy(dst: *mut c_void, src: *const c_void, n: usize) -> *mut c_void {
+    unsafe { copy_forward(dst.cast(), src.cast(), n) };
+    dst
+}
+
+/// Copies `n` bytes from `src` to `dst`, which may overlap.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/memmove.html>.
+///
+/// # Safety
+/// Both must be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memmove(dst: *mut c_void, src: *const c_void, n: usize) -> *mut c_void {
+    // copying from the front only overwrites what was already read if the
+    // destination comes first, and from the back if it comes last
+    if dst.addr() <= src.addr() {
+        unsafe { copy_forward(dst.cast(), src.cast(), n) };
+    } else {
+        unsafe { copy_backward(dst.cast(), src.cast(), n) };
+    }
+    dst
+}
+
+/// Copies from the first byte to the last. A word is always read before it's
+/// written, so this also works if `dst` is below an overlapping `src`.
+unsafe fn copy_forward(dst: *mut u8, src: *const u8, n: usize) {
+    let head = dst.align_offset(WORD).min(n);
+    let mut i = 0;
+    unsafe {
+        while i < head {
+            dst.add(i).write(src.add(i).read());
+            i += 1;
+        }
+        while n - i >= WORD {
+            let word = src.add(i).cast::<usize>().read_unaligned();
+            dst.add(i).cast::<usize>().write(word);
+            i += WORD;
+        }
+        while i < n {
+            dst.add(i).write(src.add(i).read());
+            i += 1;
+        }
+    }
+}
+
+/// Copies from the last byte to the first, for when `dst` is above an
+/// overlapping `src`.
+unsafe fn copy_backward(dst: *mut u8, src: *const u8, n: usize) {
+    // the end of the destination is aligned after this many bytes
+    let tail = unsafe { dst.add(n) }.addr() % WORD;
+    let mut i = n;
+    unsafe {
+        while i > n - tail.min(n) {
+            i -= 1;
+            dst.add(i).write(src.add(i).read());
+        }
+        while i >= WORD {
+            i -= WORD;
+            let word = src.add(i).cast::<usize>().read_unaligned();
+            dst.add(i).cast::<usize>().write(word);
+        }
+        while i > 0 {
+            i -= 1;
+            dst.add(i).write(src.add(i).read());
+        }
+    }
+}
+
+/// Sets `n` bytes at `dst` to `c`, converted to an `unsigned char`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/memset.html>.
+///
+/// # Safety
+/// `dst` must be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memset(dst: *mut c_void, c: c_int, n: usize) -> *mut c_void {
+    let byte = c as u8;
+    let word = ONES * usize::from(byte);
+    let ptr = dst.cast::<u8>();
+    let head = ptr.align_offset(WORD).min(n);
+    let mut i = 0;
+    unsafe {
+        while i < head {
+            ptr.add(i).write(byte);
+            i += 1;
+        }
+        while n - i >= WORD {
+            ptr.add(i).cast::<usize>().write(word);
+            i += WORD;
+        }
+        while i < n {
+            ptr.add(i).write(byte);
+            i += 1;
+        }
+    }
+    dst
+}
+
+/// Compares `n` bytes. Returns the difference of the first bytes that
+/// differ, or zero if all are equal.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/memcmp.html>.
+///
+/// # Safety
+/// Both must be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memcmp(a: *const c_void, b: *const c_void, n: usize) -> c_int {
+    let (a, b) = (a.cast::<u8>(), b.cast::<u8>());
+    let mut i = 0;
+    unsafe {
+        // skip the words that are equal, the first difference is then in the
+        // next word, or in the bytes after the last one
+        while n - i >= WORD
+            && a.add(i).cast::<usize>().read_unaligned()
+                == b.add(i).cast::<usize>().read_unaligned()
+        {
+            i += WORD;
+        }
+        while i < n {
+            let (x, y) = (a.add(i).read(), b.add(i).read());
+            if x != y {
+                return c_int::from(x) - c_int::from(y);
+            }
+            i += 1;
+        }
+    }
+    0
+}
+
+/// Finds the first of the `n` bytes at `s` that is `c`, converted to an
+/// `unsigned char`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/memchr.html>.
+///
+/// # Safety
+/// `s` must be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn memchr(s: *const c_void, c: c_int, n: usize) -> *mut c_void {
+    let s = s.cast::<u8>();
+    for i in 0..n {
+        if unsafe { s.add(i).read() } == c as u8 {
+            return unsafe { s.add(i) }.cast_mut().cast();
+        }
+    }
+    null_mut()
+}
+
+/// The number of bytes in front of the terminator.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strlen.html>.
+///
+/// # Safety
+/// `s` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strlen(s: *const c_char) -> usize {
+    let s = s.cast::<u8>();
+    let head = s.align_offset(WORD);
+    let mut i = 0;
+    unsafe {
+        while i < head {
+            if s.add(i).read() == 0 {
+                return i;
+            }
+            i += 1;
+        }
+        // an aligned word never crosses into the next page, so if the
+        // terminator is in it, the whole word is readable. Rust's memory
+        // model knows nothing about pages though, so Miri reads bytes.
+        #[cfg(not(miri))]
+        while !has_zero_byte(s.add(i).cast::<usize>().read()) {
+            i += WORD;
+        }
+        while s.add(i).read() != 0 {
+            i += 1;
+        }
+    }
+    i
+}
+
+/// Compares two strings.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strcmp.html>.
+///
+/// # Safety
+/// Both must be valid C strings.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strcmp(a: *const c_char, b: *const c_char) -> c_int {
+    unsafe { strncmp(a, b, usize::MAX) }
+}
+
+/// Compares at most `n` bytes of two strings.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strncmp.html>.
+///
+/// # Safety
+/// Both must be valid C strings, or be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strncmp(a: *const c_char, b: *const c_char, n: usize) -> c_int {
+    let (a, b) = (a.cast::<u8>(), b.cast::<u8>());
+    for i in 0..n {
+        let (x, y) = unsafe { (a.add(i).read(), b.add(i).read()) };
+        if x != y || x == 0 {
+            return c_int::from(x) - c_int::from(y);
+        }
+    }
+    0
+}
+
+/// Copies the string, including its terminator, to `dst`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strcpy.html>.
+///
+/// # Safety
+/// `src` must be a valid C string, and `dst` must have room for it. They
+/// must not overlap.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strcpy(dst: *mut c_char, src: *const c_char) -> *mut c_char {
+    unsafe {
+        let len = strlen(src);
+        copy_forward(dst.cast(), src.cast(), len + 1);
+    }
+    dst
+}
+
+/// Copies at most `n` bytes of the string to `dst`, and fills the rest of
+/// the `n` bytes with zeros. If the string is `n` bytes or longer, `dst`
+/// isn't terminated.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strncpy.html>.
+///
+/// # Safety
+/// `src` must be a valid C string, or be valid for `n` bytes, and `dst` must
+/// be valid for `n` bytes. They must not overlap.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strncpy(dst: *mut c_char, src: *const c_char, n: usize) -> *mut c_char {
+    unsafe {
+        let len = strnlen(src, n);
+        copy_forward(dst.cast(), src.cast(), len);
+        memset(dst.add(len).cast(), 0, n - len);
+    }
+    dst
+}
+
+/// The number of bytes in front of the terminator, but at most `n`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strlen.html>.
+///
+/// # Safety
+/// `s` must be a valid C string, or be valid for `n` bytes.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strnlen(s: *const c_char, n: usize) -> usize {
+    (0..n)
+        .find(|&i| unsafe { s.add(i).read() } == 0)
+        .unwrap_or(n)
+}
+
+/// Appends the string `src` to the string `dst`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strcat.html>.
+///
+/// # Safety
+/// Both must be valid C strings, and `dst` must have room for both. They
+/// must not overlap.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strcat(dst: *mut c_char, src: *const c_char) -> *mut c_char {
+    unsafe { strcpy(dst.add(strlen(dst)), src) };
+    dst
+}
+
+/// Finds the first `c`, converted to a `char`, in the string. The terminator
+/// is part of the string, so looking for zero finds it.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strchr.html>.
+///
+/// # Safety
+/// `s` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strchr(s: *const c_char, c: c_int) -> *mut c_char {
+    let c = c as c_char;
+    let mut s = s;
+    unsafe {
+        loop {
+            if s.read() == c {
+                return s.cast_mut();
+            }
+            if s.read() == 0 {
+                return null_mut();
+            }
+            s = s.add(1);
+        }
+    }
+}
+
+/// Finds the last `c`, converted to a `char`, in the string, see
+/// [`strchr`].
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strrchr.html>.
+///
+/// # Safety
+/// `s` must be a valid C string.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strrchr(s: *const c_char, c: c_int) -> *mut c_char {
+    let c = c as c_char;
+    let len = unsafe { strlen(s) };
+    (0..=len)
+        .rev()
+        .map(|i| unsafe { s.add(i) })
+        .find(|&p| unsafe { p.read() } == c)
+        .map_or(null_mut(), |p| p.cast_mut())
+}
+
+/// Finds the first occurrence of the string `needle` in `haystack`. An empty
+/// needle is found at the start.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strstr.html>.
+///
+/// # Safety
+/// Both must be valid C strings.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strstr(haystack: *const c_char, needle: *const c_char) -> *mut c_char {
+    unsafe {
+        let len = strlen(needle);
+        if len == 0 {
+            return haystack.cast_mut();
+        }
+        let mut candidate = haystack;
+        loop {
+            candidate = strchr(candidate, c_int::from(needle.read()));
+            if candidate.is_null() {
+                return null_mut();
+            }
+            // the candidate is terminated, so this stops at its end
+            if strncmp(candidate, needle, len) == 0 {
+                return candidate.cast_mut();
+            }
+            candidate = candidate.add(1);
+        }
+    }
+}
+
+/// Splits the string into tokens that are separated by any of the bytes in
+/// `delim`. The first call passes the string, and later calls pass null to
+/// continue where `saveptr` points. Returns null once there are no tokens
+/// left.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strtok_r.html>.
+///
+/// # Safety
+/// `s` must be null or a valid, writable C string, `delim` must be a valid C
+/// string, and `saveptr` must be valid. With a null `s`, `saveptr` must hold
+/// what the previous call left there.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strtok_r(
+    s: *mut c_char,
+    delim: *const c_char,
+    saveptr: *mut *mut c_char,
+) -> *mut c_char {
+    unsafe {
+        let mut s = if s.is_null() { saveptr.read() } else { s };
+        if s.is_null() {
+            return null_mut();
+        }
+        let is_delim = |c: c_char| c != 0 && !strchr(delim, c_int::from(c)).is_null();
+        while is_delim(s.read()) {
+            s = s.add(1);
+        }
+        if s.read() == 0 {
+            saveptr.write(null_mut());
+            return null_mut();
+        }
+        let token = s;
+        while s.read() != 0 && !is_delim(s.read()) {
+            s = s.add(1);
+        }
+        if s.read() == 0 {
+            saveptr.write(null_mut());
+        } else {
+            s.write(0);
+            saveptr.write(s.add(1));
+        }
+        token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::ffi::CStr;
+
+    use super::*;
+
+    /// A buffer that is aligned to a word, so that tests can place data at
+    /// any offset from an alignment boundary.
+    fn buffer(len: usize) -> Vec<u64> {
+        vec![0; len.div_ceil(8) + 1]
+    }
+
+    fn bytes(buffer: &mut [u64]) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(buffer.as_mut_ptr().cast(), buffer.len() * 8) }
+    }
+
+    /// Pseudo random bytes.
+    fn random(seed: u32, len: usize) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_memcpy_offsets_and_lengths() {
+        let src = random(1, 80);
+        for src_offset in 0..WORD {
+            for dst_offset in 0..WORD {
+                for len in 0..(64 - WORD) {
+                    let mut dst = buffer(80);
+                    let dst = bytes(&mut dst);
+                    unsafe {
+                        memcpy(
+                            dst[dst_offset..].as_mut_ptr().cast(),
+                            src[src_offset..].as_ptr().cast(),
+                            len,
+                        )
+                    };
+                    assert_eq!(
+                        &src[src_offset..src_offset + len],
+                        &dst[dst_offset..dst_offset + len]
+                    );
+                    // nothing around the copy was touched
+                    assert!(dst[..dst_offset].iter().all(|&b| b == 0));
+                    assert!(dst[dst_offset + len..].iter().all(|&b| b == 0));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_memmove_overlapping() {
+        let data = random(2, 96);
+        for from in 0..24 {
+            for to in 0..24 {
+                for len in [0, 1, 7, 8, 9, 31, 64, 72] {
+                    let mut expected = data.clone();
+                    expected.copy_within(from..from + len, to);
+                    let mut actual = data.clone();
+                    let ptr = actual.as_mut_ptr();
+                    unsafe { memmove(ptr.add(to).cast(), ptr.add(from).cast(), len) };
+                    assert_eq!(expected, actual, "{len} bytes from {from} to {to}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_memset() {
+        for offset in 0..WORD {
+            for len in [0, 1, 7, 8, 9, 17, 40] {
+                let mut buf = [0x11_u8; 64];
+                let ret = unsafe { memset(buf[offset..].as_mut_ptr().cast(), 0x1A5, len) };
+                assert_eq!(buf[offset..].as_mut_ptr().cast(), ret);
+                for (i, &b) in buf.iter().enumerate() {
+                    let expected = if (offset..offset + len).contains(&i) {
+                        0xA5
+                    } else {
+                        0x11
+                    };
+                    assert_eq!(expected, b, "{len} bytes at {offset}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_memcmp_against_naive() {
+        for seed in 0..200 {
+            let a = random(seed, 40);
+            let mut b = a.clone();
+            let len = seed as usize % 40;
+            if seed % 3 != 0 && len > 0 {
+                // change one byte, possibly to one with the high bit set
+                let i = (seed as usize * 7) % len;
+                b[i] = b[i].wrapping_add(seed as u8 | 1);
+            }
+            let expected = a[..len]
+                .iter()
+                .zip(&b[..len])
+                .find(|(x, y)| x != y)
+                .map_or(0, |(&x, &y)| c_int::from(x) - c_int::from(y));
+            let actual = unsafe { memcmp(a.as_ptr().cast(), b.as_ptr().cast(), len) };
+            assert_eq!(expected, actual, "seed {seed}");
+        }
+    }
+
+    #[test]
+    fn test_strlen_across_alignment() {
+        let mut buf = buffer(64);
+        let buf = bytes(&mut buf);
+        for start in 0..WORD * 2 {
+            for len in 0..40 {
+                buf.fill(b'x');
+                buf[start + len] = 0;
+                assert_eq!(len, unsafe { strlen(buf[start..].as_ptr().cast()) });
+            }
+        }
+        // bytes with the high bit set aren't mistaken for the terminator
+        assert_eq!(3, unsafe { strlen(c"\x80\xff\x81".as_ptr()) });
+    }
+
+    #[test]
+    fn test_strcmp() {
+        let cmp = |a: &CStr, b: &CStr| unsafe { strcmp(a.as_ptr(), b.as_ptr()) }.signum();
+        assert_eq!(0, cmp(c"", c""));
+        assert_eq!(0, cmp(c"abc", c"abc"));
+        assert_eq!(-1, cmp(c"abc", c"abd"));
+        assert_eq!(1, cmp(c"abc", c"ab"));
+        assert_eq!(-1, cmp(c"", c"a"));
+        // compared as unsigned char
+        assert_eq!(1, cmp(c"\xff", c"a"));
+        let ncmp = |a: &CStr, b: &CStr, n| unsafe { strncmp(a.as_ptr(), b.as_ptr(), n) }.signum();
+        assert_eq!(0, ncmp(c"abcx", c"abcy", 3));
+        assert_eq!(-1, ncmp(c"abcx", c"abcy", 4));
+        assert_eq!(0, ncmp(c"a", c"b", 0));
+    }
+
+    #[test]
+    fn test_strcpy_strncpy_strcat() {
+        let mut buf = [0x7F as c_char; 16];
+        let as_bytes = |buf: &[c_char]| buf.iter().map(|&c| c as u8).collect::<Vec<_>>();
+        unsafe {
+            assert_eq!(buf.as_mut_ptr(), strcpy(buf.as_mut_ptr(), c"foo".as_ptr()));
+            assert_eq!(c"foo", CStr::from_ptr(buf.as_ptr()));
+            strcat(buf.as_mut_ptr(), c"bar".as_ptr());
+            assert_eq!(c"foobar", CStr::from_ptr(buf.as_ptr()));
+            strcat(buf.as_mut_ptr(), c"".as_ptr());
+            assert_eq!(c"foobar", CStr::from_ptr(buf.as_ptr()));
+
+            buf.fill(0x7F);
+            strncpy(buf.as_mut_ptr(), c"ab".as_ptr(), 5);
+            assert_eq!(b"ab\0\0\0\x7F", &as_bytes(&buf)[..6]);
+            // not terminated if the string doesn't fit
+            buf.fill(0x7F);
+            strncpy(buf.as_mut_ptr(), c"abcdef".as_ptr(), 3);
+            assert_eq!(b"abc\x7F", &as_bytes(&buf)[..4]);
+        }
+    }
+
+    #[test]
+    fn test_strchr_strrchr() {
+        let s = c"hello world";
+        let find = |f: unsafe extern "C" fn(*const c_char, c_int) -> *mut c_char, c: u8| {
+            let found = unsafe { f(s.as_ptr(), c_int::from(c)) };
+            (!found.is_null()).then(|| found.addr() - s.as_ptr().addr())
+        };
+        assert_eq!(Some(2), find(strchr, b'l'));
+        assert_eq!(Some(9), find(strrchr, b'l'));
+        assert_eq!(Some(0), find(strrchr, b'h'));
+        assert_eq!(None, find(strchr, b'z'));
+        assert_eq!(None, find(strrchr, b'z'));
+        assert_eq!(Some(11), find(strchr, 0));
+        assert_eq!(Some(11), find(strrchr, 0));
+    }
+
+    #[test]
+    fn test_strstr() {
+        let find = |haystack: &CStr, needle: &CStr| {
+            let found = unsafe { strstr(haystack.as_ptr(), needle.as_ptr()) };
+            (!found.is_null()).then(|| found.addr() - haystack.as_ptr().addr())
+        };
+        assert_eq!(Some(0), find(c"abc", c""));
+        assert_eq!(Some(0), find(c"", c""));
+        assert_eq!(None, find(c"", c"a"));
+        assert_eq!(Some(3), find(c"aababc", c"abc"));
+        assert_eq!(None, find(c"aababx", c"abc"));
+        assert_eq!(None, find(c"ab", c"abc"));
+        assert_eq!(Some(4), find(c"xxxxabc", c"abc"));
+    }
+
+    #[test]
+    fn test_strtok_r() {
+        let mut text = *b",,one, two,,three,\0";
+        let delim = c", ";
+        let mut save = null_mut();
+        let mut tokens = Vec::new();
+        let mut s = text.as_mut_ptr().cast::<c_char>();
+        loop {
+            let token = unsafe { strtok_r(s, delim.as_ptr(), &mut save) };
+            if token.is_null() {
+                break;
+            }
+            tokens.push(unsafe { CStr::from_ptr(token) }.to_bytes().to_vec());
+            s = null_mut();
+        }
+        assert_eq!(
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()],
+            tokens
+        );
+        // and it stays done
+        assert!(unsafe { strtok_r(null_mut(), delim.as_ptr(), &mut save) }.is_null());
+
+        let mut empty = *b",,\0";
+        let mut save = null_mut();
+        assert!(
+            unsafe { strtok_r(empty.as_mut_ptr().cast(), delim.as_ptr(), &mut save) }.is_null()
+        );
+    }
+}
@@ -0,0 +1,28 @@
+use core::arch::asm;
+
+use kernel_api::syscall::Syscall;
+
+/// Performs the syscall, and returns what the kernel returned, which is a
+/// negated error code on failure. Syscalls with fewer arguments ignore the
+/// remaining ones.
+///
+/// # Safety
+/// Depending on the syscall, the caller must ensure that all arguments are
+/// valid.
+pub unsafe fn syscall(syscall: Syscall, args: [usize; 6]) -> isize {
+    let res: isize;
+    unsafe {
+        asm! {
+        "int 0x80",
+        in("rax") syscall as usize,
+        in("rdi") args[0],
+        in("rsi") args[1],
+        in("rdx") args[2],
+        in("rcx") args[3],
+        in("r8") args[4],
+        in("r9") args[5],
+        lateout("rax") res,
+        }
+    }
+    res
+}
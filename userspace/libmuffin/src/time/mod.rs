@@ -0,0 +1,398 @@
+//! `time.h` and `gettimeofday` from `sys/time.h`.
+//!
+//! There are no time zones yet, so local time is UTC, and the `localtime`
+//! functions are the same as the `gmtime` ones.
+
+use core::ffi::{c_char, c_int, c_long, c_void, CStr};
+use core::mem::size_of;
+use core::ptr::{null, null_mut};
+
+use kernel_api::syscall::{Errno, Syscall, Timespec};
+use kernel_api::time::{civil_from_days, days_from_civil, weekday_from_days, SECONDS_PER_DAY};
+use spin::Once;
+
+use crate::errno::{set_errno, set_errno_from_syscall};
+use crate::syscall::syscall;
+
+pub use strftime::strftime;
+
+mod strftime;
+
+#[allow(non_camel_case_types)]
+pub type time_t = i64;
+#[allow(non_camel_case_types)]
+pub type clock_t = c_long;
+#[allow(non_camel_case_types)]
+pub type clockid_t = c_int;
+#[allow(non_camel_case_types)]
+pub type suseconds_t = c_long;
+
+/// The wall clock time since the Unix epoch.
+pub const CLOCK_REALTIME: clockid_t = 0;
+/// The time since boot, which never goes backwards.
+pub const CLOCK_MONOTONIC: clockid_t = 1;
+
+pub const CLOCKS_PER_SEC: clock_t = 1_000_000;
+
+/// The name of the only time zone there is.
+static UTC: &CStr = c"UTC";
+
+/// A point in time broken down into its parts, as in the C standard.
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct tm {
+    pub tm_sec: c_int,
+    pub tm_min: c_int,
+    pub tm_hour: c_int,
+    /// The day of the month, starting at 1.
+    pub tm_mday: c_int,
+    /// The month, starting at 0 for January.
+    pub tm_mon: c_int,
+    /// The years since 1900.
+    pub tm_year: c_int,
+    /// The day of the week, starting at 0 for Sunday.
+    pub tm_wday: c_int,
+    /// The day of the year, starting at 0 for January 1st.
+    pub tm_yday: c_int,
+    pub tm_isdst: c_int,
+    /// The offset from UTC in seconds.
+    pub tm_gmtoff: c_long,
+    pub tm_zone: *const c_char,
+}
+
+impl tm {
+    const fn zeroed() -> Self {
+        Self {
+            tm_sec: 0,
+            tm_min: 0,
+            tm_hour: 0,
+            tm_mday: 0,
+            tm_mon: 0,
+            tm_year: 0,
+            tm_wday: 0,
+            tm_yday: 0,
+            tm_isdst: 0,
+            tm_gmtoff: 0,
+            tm_zone: null(),
+        }
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct timespec {
+    pub tv_sec: time_t,
+    pub tv_nsec: c_long,
+}
+
+// the kernel writes its timespec right into the one of the caller
+const _: () = assert!(size_of::<timespec>() == size_of::<Timespec>());
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct timeval {
+    pub tv_sec: time_t,
+    pub tv_usec: suseconds_t,
+}
+
+/// Breaks `time` down into its parts in UTC. Returns [`None`] if the year
+/// doesn't fit into a `tm`.
+pub fn to_tm(time: time_t) -> Option<tm> {
+    let days = time.div_euclid(SECONDS_PER_DAY);
+    let seconds = time.rem_euclid(SECONDS_PER_DAY) as c_int;
+    let (year, month, day) = civil_from_days(days);
+    let tm_year = c_int::try_from(year - 1900).ok()?;
+    Some(tm {
+        tm_sec: seconds % 60,
+        tm_min: seconds / 60 % 60,
+        tm_hour: seconds / 3600,
+        tm_mday: day as c_int,
+        tm_mon: month as c_int - 1,
+        tm_year,
+        tm_wday: weekday_from_days(days) as c_int,
+        tm_yday: (days - days_from_civil(year, 1, 1)) as c_int,
+        tm_isdst: 0,
+        tm_gmtoff: 0,
+        tm_zone: UTC.as_ptr(),
+    })
+}
+
+/// The seconds since the epoch of the time in `tm`, which is in UTC. The
+/// fields may be out of their ranges, like the 32nd of January or the -1st
+/// hour, which count on from the next larger field. `tm_wday` and `tm_yday`
+/// are ignored.
+pub fn from_tm(tm: &tm) -> Option<time_t> {
+    let month = i64::from(tm.tm_mon);
+    let year = i64::from(tm.tm_year) + 1900 + month.div_euclid(12);
+    let month = month.rem_euclid(12) as u32 + 1;
+    let days = days_from_civil(year, month, 1) + i64::from(tm.tm_mday) - 1;
+    let seconds = i64::from(tm.tm_hour) * 3600 + i64::from(tm.tm_min) * 60 + i64::from(tm.tm_sec);
+    days.checked_mul(SECONDS_PER_DAY)?.checked_add(seconds)
+}
+
+/// Reads the clock `clock_id` into `tp`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/clock_gettime.html>.
+///
+/// # Safety
+/// `tp` must be valid for writing a `timespec`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn clock_gettime(clock_id: clockid_t, tp: *mut timespec) -> c_int {
+    let args = [clock_id as usize, tp as usize, 0, 0, 0, 0];
+    set_errno_from_syscall(unsafe { syscall(Syscall::ClockGettime, args) }) as c_int
+}
+
+fn now(clock_id: clockid_t) -> Option<timespec> {
+    let mut tp = timespec::default();
+    (unsafe { clock_gettime(clock_id, &mut tp) } == 0).then_some(tp)
+}
+
+/// Returns the seconds since the epoch, and also stores them in `tloc` unless
+/// it's null.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/time.html>.
+///
+/// # Safety
+/// `tloc` must be null or valid for writing a `time_t`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn time(tloc: *mut time_t) -> time_t {
+    let Some(now) = now(CLOCK_REALTIME) else {
+        return -1;
+    };
+    if !tloc.is_null() {
+        unsafe { tloc.write(now.tv_sec) };
+    }
+    now.tv_sec
+}
+
+/// Stores the wall clock time in `tv`. There are no time zones, so `tz` must
+/// be null.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/gettimeofday.html>.
+///
+/// # Safety
+/// `tv` must be valid for writing a `timeval`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn gettimeofday(tv: *mut timeval, tz: *mut c_void) -> c_int {
+    if !tz.is_null() {
+        set_errno(Errno::EINVAL);
+        return -1;
+    }
+    let Some(now) = now(CLOCK_REALTIME) else {
+        return -1;
+    };
+    let value = timeval {
+        tv_sec: now.tv_sec,
+        tv_usec: now.tv_nsec / 1000,
+    };
+    unsafe { tv.write(value) };
+    0
+}
+
+/// The processor time that the program used, in [`CLOCKS_PER_SEC`].
+///
+/// The kernel doesn't account how long processes run yet, so this is the
+/// time since the first call instead. That still works for what `clock` is
+/// meant for, which is measuring the difference between two calls.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/clock.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn clock() -> clock_t {
+    static START: Once<Option<timespec>> = Once::new();
+
+    let micros = |ts: timespec| ts.tv_sec * 1_000_000 + ts.tv_nsec / 1000;
+    match (
+        START.call_once(|| now(CLOCK_MONOTONIC)),
+        now(CLOCK_MONOTONIC),
+    ) {
+        (Some(start), Some(now)) => micros(now) - micros(*start),
+        _ => -1,
+    }
+}
+
+/// Breaks `*timep` down into `result`, in UTC.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/gmtime.html>.
+///
+/// # Safety
+/// `timep` must be valid for reading a `time_t`, and `result` for writing a
+/// `tm`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn gmtime_r(timep: *const time_t, result: *mut tm) -> *mut tm {
+    let Some(tm) = to_tm(unsafe { timep.read() }) else {
+        set_errno(Errno::EOVERFLOW);
+        return null_mut();
+    };
+    unsafe { result.write(tm) };
+    result
+}
+
+/// Like [`gmtime_r`], but into a `tm` that every thread has one of, and which
+/// the next call overwrites.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/gmtime.html>.
+///
+/// # Safety
+/// `timep` must be valid for reading a `time_t`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn gmtime(timep: *const time_t) -> *mut tm {
+    #[thread_local]
+    static mut RESULT: tm = tm::zeroed();
+
+    unsafe { gmtime_r(timep, &raw mut RESULT) }
+}
+
+/// The same as [`gmtime_r`], since local time is UTC.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/localtime.html>.
+///
+/// # Safety
+/// `timep` must be valid for reading a `time_t`, and `result` for writing a
+/// `tm`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn localtime_r(timep: *const time_t, result: *mut tm) -> *mut tm {
+    unsafe { gmtime_r(timep, result) }
+}
+
+/// The same as [`gmtime`], since local time is UTC.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/localtime.html>.
+///
+/// # Safety
+/// `timep` must be valid for reading a `time_t`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn localtime(timep: *const time_t) -> *mut tm {
+    unsafe { gmtime(timep) }
+}
+
+/// Converts the local time in `*tm` to seconds since the epoch, and
+/// normalizes all fields of `*tm`, including `tm_wday` and `tm_yday`.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/mktime.html>.
+///
+/// # Safety
+/// `tm` must be valid for reading and writing a `tm`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn mktime(tm: *mut tm) -> time_t {
+    let Some((time, normalized)) = from_tm(unsafe { &*tm }).and_then(|t| Some((t, to_tm(t)?)))
+    else {
+        set_errno(Errno::EOVERFLOW);
+        return -1;
+    };
+    unsafe { tm.write(normalized) };
+    time
+}
+
+/// Like [`mktime`], but for a time in UTC, which is the same here.
+///
+/// # Safety
+/// `tm` must be valid for reading and writing a `tm`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn timegm(tm: *mut tm) -> time_t {
+    unsafe { mktime(tm) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tm(year: c_int, mon: c_int, mday: c_int, hour: c_int, min: c_int, sec: c_int) -> tm {
+        tm {
+            tm_year: year - 1900,
+            tm_mon: mon - 1,
+            tm_mday: mday,
+            tm_hour: hour,
+            tm_min: min,
+            tm_sec: sec,
+            ..tm::zeroed()
+        }
+    }
+
+    fn mktime_of(mut value: tm) -> (time_t, tm) {
+        let time = unsafe { mktime(&mut value) };
+        (time, value)
+    }
+
+    #[test]
+    fn test_epoch() {
+        let tm = to_tm(0).unwrap();
+        assert_eq!((70, 0, 1), (tm.tm_year, tm.tm_mon, tm.tm_mday));
+        assert_eq!((0, 0, 0), (tm.tm_hour, tm.tm_min, tm.tm_sec));
+        // a Thursday
+        assert_eq!(4, tm.tm_wday);
+        assert_eq!(0, tm.tm_yday);
+        assert_eq!(Some(0), from_tm(&tm));
+
+        let tm = to_tm(-1).unwrap();
+        assert_eq!((69, 11, 31), (tm.tm_year, tm.tm_mon, tm.tm_mday));
+        assert_eq!((23, 59, 59), (tm.tm_hour, tm.tm_min, tm.tm_sec));
+        assert_eq!(364, tm.tm_yday);
+    }
+
+    #[test]
+    fn test_leap_day() {
+        let (time, tm) = mktime_of(tm(2024, 2, 29, 12, 0, 0));
+        assert_eq!(1_709_208_000, time);
+        assert_eq!((124, 1, 29), (tm.tm_year, tm.tm_mon, tm.tm_mday));
+        assert_eq!(59, tm.tm_yday);
+        assert_eq!(4, tm.tm_wday);
+        assert_eq!(Some(tm), to_tm(time));
+    }
+
+    #[test]
+    fn test_round_trip_across_years() {
+        for year in [1969, 1970, 1999, 2000, 2023, 2024, 2038, 2100] {
+            let new_year = from_tm(&tm(year, 1, 1, 0, 0, 0)).unwrap();
+            for time in [new_year - 1, new_year, new_year + 1] {
+                let tm = to_tm(time).unwrap();
+                assert_eq!(Some(time), from_tm(&tm));
+            }
+            let before = to_tm(new_year - 1).unwrap();
+            assert_eq!(
+                (year - 1 - 1900, 11, 31),
+                (before.tm_year, before.tm_mon, before.tm_mday)
+            );
+            let after = to_tm(new_year).unwrap();
+            assert_eq!(
+                (year - 1900, 0, 1),
+                (after.tm_year, after.tm_mon, after.tm_mday)
+            );
+        }
+    }
+
+    #[test]
+    fn test_mktime_normalizes() {
+        // the 32nd of January is the 1st of February
+        let (time, tm_) = mktime_of(tm(2023, 1, 32, 0, 0, 0));
+        assert_eq!(from_tm(&tm(2023, 2, 1, 0, 0, 0)), Some(time));
+        assert_eq!((1, 1), (tm_.tm_mon, tm_.tm_mday));
+        assert_eq!(31, tm_.tm_yday);
+
+        // the 13th month is January of the next year, and the 0th day the
+        // last of the month before
+        let (_, tm_) = mktime_of(tm(2023, 13, 0, 0, 0, 0));
+        assert_eq!((123, 11, 31), (tm_.tm_year, tm_.tm_mon, tm_.tm_mday));
+
+        // negative fields count backwards
+        let (_, tm_) = mktime_of(tm(2024, 3, 1, 0, 0, -1));
+        assert_eq!((1, 29), (tm_.tm_mon, tm_.tm_mday));
+        assert_eq!((23, 59, 59), (tm_.tm_hour, tm_.tm_min, tm_.tm_sec));
+        // month -1 is two before January
+        let (_, tm_) = mktime_of(tm(2024, -1, 15, 0, 0, 0));
+        assert_eq!((123, 10), (tm_.tm_year, tm_.tm_mon));
+        let (_, tm_) = mktime_of(tm(2024, 1, 1, 48, 90, 0));
+        assert_eq!((3, 1, 30), (tm_.tm_mday, tm_.tm_hour, tm_.tm_min));
+    }
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(None, to_tm(time_t::MAX));
+        let mut too_late = tm(1970, 1, 1, 0, 0, 0);
+        too_late.tm_year = c_int::MAX;
+        too_late.tm_mon = 12;
+        assert_eq!(-1, unsafe { mktime(&mut too_late) });
+    }
+}
@@ -0,0 +1,200 @@
+//! `strftime`.
+//!
+//! Conversions are `Y`, `m`, `d`, `e`, `H`, `M`, `S`, `j`, `a`, `b`, `z`
+//! and `%`. A conversion that isn't supported is written as it is.
+
+use core::ffi::{c_char, CStr};
+use core::slice;
+
+use crate::time::tm;
+
+const WEEKDAYS: [&[u8]; 7] = [b"Sun", b"Mon", b"Tue", b"Wed", b"Thu", b"Fri", b"Sat"];
+const MONTHS: [&[u8]; 12] = [
+    b"Jan", b"Feb", b"Mar", b"Apr", b"May", b"Jun", b"Jul", b"Aug", b"Sep", b"Oct", b"Nov", b"Dec",
+];
+
+/// A buffer that remembers whether something didn't fit.
+struct Output<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    overflowed: bool,
+}
+
+impl Output<'_> {
+    fn write(&mut self, bytes: &[u8]) {
+        match self.buf.get_mut(self.len..self.len + bytes.len()) {
+            Some(dst) => {
+                dst.copy_from_slice(bytes);
+                self.len += bytes.len();
+            }
+            None => self.overflowed = true,
+        }
+    }
+
+    /// Writes `value` in decimal, with at least `width` digits, which are
+    /// padded with `pad`.
+    fn number(&mut self, value: i64, width: usize, pad: u8) {
+        let mut digits = [0_u8; 20];
+        let mut start = digits.len();
+        let mut rest = value.unsigned_abs();
+        loop {
+            start -= 1;
+            digits[start] = b'0' + (rest % 10) as u8;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+        if value < 0 {
+            self.write(b"-");
+        }
+        for _ in digits.len() - start..width {
+            self.write(&[pad]);
+        }
+        self.write(&digits[start..]);
+    }
+
+    /// The name of a weekday or month, or `?` if `index` is out of range.
+    fn name(&mut self, names: &[&[u8]], index: i32) {
+        let name = usize::try_from(index).ok().and_then(|i| names.get(i));
+        self.write(name.copied().unwrap_or(b"?"));
+    }
+}
+
+/// Formats `tm` into `s`, which has room for `max` bytes including the
+/// terminator. Returns the length without the terminator, or 0 if it didn't
+/// fit, in which case the contents of `s` are unspecified.
+pub fn format(s: &mut [u8], format: &[u8], tm: &tm) -> usize {
+    let mut out = Output {
+        buf: s,
+        len: 0,
+        overflowed: false,
+    };
+    let mut bytes = format.iter();
+    while let Some(&byte) = bytes.next() {
+        if byte != b'%' {
+            out.write(&[byte]);
+            continue;
+        }
+        match bytes.next() {
+            Some(b'Y') => out.number(i64::from(tm.tm_year) + 1900, 4, b'0'),
+            Some(b'm') => out.number(i64::from(tm.tm_mon) + 1, 2, b'0'),
+            Some(b'd') => out.number(tm.tm_mday.into(), 2, b'0'),
+            Some(b'e') => out.number(tm.tm_mday.into(), 2, b' '),
+            Some(b'H') => out.number(tm.tm_hour.into(), 2, b'0'),
+            Some(b'M') => out.number(tm.tm_min.into(), 2, b'0'),
+            Some(b'S') => out.number(tm.tm_sec.into(), 2, b'0'),
+            Some(b'j') => out.number(i64::from(tm.tm_yday) + 1, 3, b'0'),
+            Some(b'a') => out.name(&WEEKDAYS, tm.tm_wday),
+            Some(b'b') => out.name(&MONTHS, tm.tm_mon),
+            Some(b'z') => {
+                let offset = tm.tm_gmtoff / 60;
+                out.write(if offset < 0 { b"-" } else { b"+" });
+                out.number(offset.abs() / 60 * 100 + offset.abs() % 60, 4, b'0');
+            }
+            Some(b'%') => out.write(b"%"),
+            Some(&other) => out.write(&[b'%', other]),
+            None => out.write(b"%"),
+        }
+    }
+    // the terminator must fit as well
+    out.write(&[0]);
+    if out.overflowed {
+        return 0;
+    }
+    out.len - 1
+}
+
+/// Formats the time in `tm` like `format` says.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/strftime.html>.
+///
+/// # Safety
+/// `s` must be valid for writing `max` bytes, `format` must be a nul
+/// terminated string, and `tm` must be valid for reading a `tm`.
+#[cfg_attr(not(test), no_mangle)]
+pub unsafe extern "C" fn strftime(
+    s: *mut c_char,
+    max: usize,
+    format: *const c_char,
+    tm: *const tm,
+) -> usize {
+    let buf = if max == 0 {
+        &mut []
+    } else {
+        unsafe { slice::from_raw_parts_mut(s.cast(), max) }
+    };
+    let format_bytes = unsafe { CStr::from_ptr(format) }.to_bytes();
+    self::format(buf, format_bytes, unsafe { &*tm })
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use alloc::vec;
+
+    use super::*;
+    use crate::time::{time_t, to_tm};
+
+    fn formatted(time: time_t, format: &str) -> alloc::string::String {
+        let mut buf = vec![0xAA; 128];
+        let len = self::format(&mut buf, format.as_bytes(), &to_tm(time).unwrap());
+        assert_eq!(0, buf[len]);
+        alloc::string::String::from_utf8(buf[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_known_strings() {
+        let table: [(time_t, &str, &str); 8] = [
+            (0, "%Y-%m-%d %H:%M:%S", "1970-01-01 00:00:00"),
+            (0, "%a %b %e %j %z", "Thu Jan  1 001 +0000"),
+            (-1, "%Y-%m-%d %H:%M:%S %a", "1969-12-31 23:59:59 Wed"),
+            (951_782_400, "%Y-%m-%d %a %j", "2000-02-29 Tue 060"),
+            (
+                1_709_208_000,
+                "%a, %d %b %Y %H:%M:%S",
+                "Thu, 29 Feb 2024 12:00:00",
+            ),
+            (
+                1_735_689_599,
+                "%Y-%m-%d %H:%M:%S %j",
+                "2024-12-31 23:59:59 366",
+            ),
+            (2_147_483_647, "%Y%m%d%H%M%S", "20380119031407"),
+            (1_700_000_000, "100%% at %e.%m.", "100% at 14.11."),
+        ];
+        for (time, format, expected) in table {
+            assert_eq!(expected, formatted(time, format), "{time} with {format}");
+        }
+    }
+
+    #[test]
+    fn test_unsupported_conversions() {
+        assert_eq!("%Q %", formatted(0, "%Q %"));
+    }
+
+    #[test]
+    fn test_buffer_size() {
+        let tm = to_tm(0).unwrap();
+        // 10 bytes and the terminator
+        let mut buf = [0_u8; 11];
+        assert_eq!(10, format(&mut buf, b"%Y-%m-%d", &tm));
+        assert_eq!(b"1970-01-01\0", &buf);
+        assert_eq!(0, format(&mut buf[..10], b"%Y-%m-%d", &tm));
+        assert_eq!(0, format(&mut [], b"", &tm));
+        // an empty result fits into a single byte, but is also 0
+        let mut one = [0xAA_u8; 1];
+        assert_eq!(0, format(&mut one, b"", &tm));
+        assert_eq!(0, one[0]);
+    }
+
+    #[test]
+    fn test_offset() {
+        let mut tm = to_tm(0).unwrap();
+        tm.tm_gmtoff = -(5 * 3600 + 30 * 60);
+        let mut buf = [0_u8; 8];
+        let len = format(&mut buf, b"%z", &tm);
+        assert_eq!(b"-0530", &buf[..len]);
+    }
+}
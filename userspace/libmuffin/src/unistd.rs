@@ -0,0 +1,21 @@
+//! `unistd.h`.
+
+use core::ffi::c_int;
+
+use kernel_api::syscall::Syscall;
+
+pub use crate::getopt::{getopt, optarg, opterr, optind, optopt};
+use crate::syscall::syscall;
+
+#[allow(non_camel_case_types)]
+pub type pid_t = c_int;
+#[allow(non_camel_case_types)]
+pub type uid_t = u32;
+
+/// The id of the current process, which can't fail.
+///
+/// See <https://pubs.opengroup.org/onlinepubs/9799919799/functions/getpid.html>.
+#[cfg_attr(not(test), no_mangle)]
+pub extern "C" fn getpid() -> pid_t {
+    unsafe { syscall(Syscall::Getpid, [0; 6]) as pid_t }
+}
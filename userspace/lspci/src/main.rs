@@ -0,0 +1,74 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::format;
+use core::str::from_utf8;
+
+use kernel_api::syscall::OpenFlags;
+use std::syscall::{sys_close, sys_exit, sys_open, sys_read, Errno};
+use std::{println, rt};
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn must(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            println!("lspci: {errno}");
+            sys_exit(errno.code() as isize)
+        }
+    }
+}
+
+/// Reads the whole file into `buf` and strips the trailing newline. The heap
+/// is tiny, so the buffers live on the stack.
+fn read_file<'a>(path: &str, buf: &'a mut [u8]) -> &'a str {
+    let fd = must(sys_open(path, OpenFlags::O_RDONLY.bits() as usize, 0));
+    let mut len = 0;
+    loop {
+        let n = must(sys_read(fd, &mut buf[len..]));
+        if n == 0 {
+            break;
+        }
+        len += n;
+        assert!(len < buf.len(), "{path} doesn't fit into the buffer");
+    }
+    must(sys_close(fd));
+    from_utf8(&buf[..len])
+        .expect("procfs files must be valid utf-8")
+        .trim_end()
+}
+
+/// Prints one line per PCI device, like `lspci -nn` does, followed by the
+/// driver that is bound to the device.
+fn main() {
+    let mut devices = [0_u8; 2048];
+    for address in read_file("/proc/pci/devices", &mut devices).lines() {
+        let (mut class, mut vendor, mut device, mut driver) = ([0; 64], [0; 8], [0; 8], [0; 64]);
+        let class = read_file(&format!("/proc/pci/{address}/class"), &mut class);
+        let vendor = read_file(&format!("/proc/pci/{address}/vendor"), &mut vendor);
+        let device = read_file(&format!("/proc/pci/{address}/device"), &mut device);
+        let driver = read_file(&format!("/proc/pci/{address}/driver"), &mut driver);
+
+        // the class file holds the class, subclass and programming interface,
+        // followed by the name of the class
+        let (code, name) = class.split_once(' ').unwrap_or((class, ""));
+        let code = code.get(..4).unwrap_or(code);
+        // the segment is always 0, so it's left out like lspci does
+        let address = address.strip_prefix("0000:").unwrap_or(address);
+        if driver.is_empty() {
+            println!("{address} {name} [{code}]: [{vendor}:{device}]");
+        } else {
+            println!("{address} {name} [{code}]: [{vendor}:{device}] (driver {driver})");
+        }
+    }
+}
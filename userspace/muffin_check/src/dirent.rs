@@ -0,0 +1,146 @@
+use core::ffi::{c_long, CStr};
+use core::fmt::Write;
+use core::ptr::null_mut;
+
+use libmuffin::dirent::{
+    closedir, dirent, opendir, readdir, readdir_r, rewinddir, seekdir, telldir, DIR, DT_DIR, DT_REG,
+};
+use libmuffin::errno::errno;
+use libmuffin::stdio::{fclose, fopen};
+use std::syscall::{sys_mkdir, sys_rmdir, sys_unlink, Errno};
+
+const DIR_PATH: &str = "/tmp/muffin_check_dirent";
+const SUBDIR_PATH: &str = "/tmp/muffin_check_dirent/dir";
+const FILES: usize = 500;
+
+/// A nul terminated path that is built without allocating.
+struct PathBuf {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl PathBuf {
+    fn file(index: usize) -> Self {
+        let mut path = Self {
+            bytes: [0; 64],
+            len: 0,
+        };
+        write!(path, "{DIR_PATH}/file{index}\0").unwrap();
+        path
+    }
+
+    fn as_c_str(&self) -> &CStr {
+        CStr::from_bytes_with_nul(&self.bytes[..self.len]).unwrap()
+    }
+
+    fn as_str(&self) -> &str {
+        self.as_c_str().to_str().unwrap()
+    }
+}
+
+impl Write for PathBuf {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let dst = self
+            .bytes
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(core::fmt::Error)?;
+        dst.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+pub fn check() {
+    sys_mkdir(DIR_PATH, 0o755).unwrap();
+    sys_mkdir(SUBDIR_PATH, 0o755).unwrap();
+    for i in 0..FILES {
+        let file = unsafe { fopen(PathBuf::file(i).as_c_str().as_ptr(), c"w".as_ptr()) };
+        assert!(!file.is_null());
+        assert_eq!(0, unsafe { fclose(file) });
+    }
+
+    unsafe {
+        let dirp = opendir(c"/tmp/muffin_check_dirent".as_ptr());
+        assert!(!dirp.is_null());
+        let first = enumerate(dirp, false);
+        rewinddir(dirp);
+        let second = enumerate(dirp, true);
+        assert_eq!(first, second);
+        seek(dirp);
+        assert_eq!(0, closedir(dirp));
+
+        assert!(opendir(c"/tmp/muffin_check_dirent/file0".as_ptr()).is_null());
+        assert_eq!(Errno::ENOTDIR.code(), errno());
+        assert!(opendir(c"/tmp/muffin_check_dirent_missing".as_ptr()).is_null());
+        assert_eq!(Errno::ENOENT.code(), errno());
+    }
+
+    for i in 0..FILES {
+        sys_unlink(PathBuf::file(i).as_str()).unwrap();
+    }
+    sys_rmdir(SUBDIR_PATH).unwrap();
+    sys_rmdir(DIR_PATH).unwrap();
+}
+
+/// Reads the whole directory, either with `readdir` or `readdir_r`, checks
+/// that every file and the subdirectory show up exactly once, and returns a
+/// checksum of the order that they came in.
+unsafe fn enumerate(dirp: *mut DIR, reentrant: bool) -> usize {
+    let mut seen = [false; FILES];
+    let mut seen_subdir = false;
+    let mut count = 0;
+    let mut checksum = 0_usize;
+    let mut storage = unsafe { core::mem::zeroed::<dirent>() };
+    loop {
+        let entry = if reentrant {
+            let mut result = null_mut();
+            assert_eq!(0, unsafe { readdir_r(dirp, &mut storage, &mut result) });
+            result
+        } else {
+            unsafe { readdir(dirp) }
+        };
+        if entry.is_null() {
+            break;
+        }
+        let entry = unsafe { &*entry };
+        let name = unsafe { CStr::from_ptr(entry.d_name.as_ptr()) }
+            .to_str()
+            .unwrap();
+        assert_ne!(0, entry.d_ino);
+        count += 1;
+        if name == "dir" {
+            assert_eq!(DT_DIR, entry.d_type);
+            assert!(!seen_subdir);
+            seen_subdir = true;
+            checksum = checksum.wrapping_mul(31).wrapping_add(FILES);
+            continue;
+        }
+        assert_eq!(DT_REG, entry.d_type, "{name}");
+        let index: usize = name.strip_prefix("file").unwrap().parse().unwrap();
+        assert!(!seen[index], "{name} came twice");
+        seen[index] = true;
+        checksum = checksum.wrapping_mul(31).wrapping_add(index);
+    }
+    assert_eq!(FILES + 1, count);
+    assert!(seen_subdir);
+    assert!(seen.iter().all(|&seen| seen));
+    checksum
+}
+
+/// Remembers the position in the middle of the directory, and checks that
+/// seeking back there continues with the same entry.
+unsafe fn seek(dirp: *mut DIR) {
+    unsafe {
+        rewinddir(dirp);
+        for _ in 0..FILES / 2 {
+            assert!(!readdir(dirp).is_null());
+        }
+        let position: c_long = telldir(dirp);
+        let next = (*readdir(dirp)).d_name;
+        for _ in 0..10 {
+            assert!(!readdir(dirp).is_null());
+        }
+        seekdir(dirp, position);
+        assert_eq!(next, (*readdir(dirp)).d_name);
+    }
+}
@@ -0,0 +1,89 @@
+use core::ffi::{c_char, CStr};
+use core::ptr::null;
+
+use libmuffin::stdlib::{environ, getenv, putenv, setenv, unsetenv};
+use std::rt;
+use std::syscall::{sys_spawn_raw, sys_waitpid};
+
+/// Set in the environment of the copy of muffin_check that checks what it
+/// inherited.
+const CHILD: &CStr = c"MUFFIN_CHECK_CHILD";
+
+/// What this copy of muffin_check checks if it was started by [`check`] or
+/// [`run_child`] as a child, and [`None`] if it's the parent.
+pub fn child() -> Option<&'static CStr> {
+    unsafe { get(CHILD) }
+}
+
+/// Runs a copy of muffin_check, which only runs the check `name`, and waits
+/// for it. Returns its exit status.
+pub fn run_child(name: &CStr) -> usize {
+    unsafe {
+        assert_eq!(0, setenv(CHILD.as_ptr(), name.as_ptr(), 1));
+        let args = [c"/bin/muffin_check".as_ptr(), null()];
+        let pid = sys_spawn_raw(c"/bin/muffin_check", &args, environ_slice()).unwrap();
+        let status = sys_waitpid(pid).unwrap();
+        assert_eq!(0, unsetenv(CHILD.as_ptr()));
+        status
+    }
+}
+
+/// The kernel test starts muffin_check with `MUFFIN_CHECK_HOME`,
+/// `MUFFIN_CHECK_USER` and `MUFFIN_CHECK_SHELL` in its environment.
+pub fn check() {
+    assert!(rt::env().any(|entry| entry == c"MUFFIN_CHECK_HOME=/root"));
+    unsafe {
+        assert_eq!(Some(c"/root"), get(c"MUFFIN_CHECK_HOME"));
+        assert_eq!(Some(c"root"), get(c"MUFFIN_CHECK_USER"));
+        assert_eq!(Some(c"/bin/sh"), get(c"MUFFIN_CHECK_SHELL"));
+        assert_eq!(None, get(c"MUFFIN_CHECK_MISSING"));
+
+        assert_eq!(
+            0,
+            setenv(c"MUFFIN_CHECK_USER".as_ptr(), c"nobody".as_ptr(), 0)
+        );
+        assert_eq!(Some(c"root"), get(c"MUFFIN_CHECK_USER"));
+        assert_eq!(
+            0,
+            setenv(c"MUFFIN_CHECK_USER".as_ptr(), c"nobody".as_ptr(), 1)
+        );
+        assert_eq!(Some(c"nobody"), get(c"MUFFIN_CHECK_USER"));
+        assert_eq!(-1, setenv(c"A=B".as_ptr(), c"C".as_ptr(), 1));
+        assert_eq!(0, unsetenv(c"MUFFIN_CHECK_SHELL".as_ptr()));
+        assert_eq!(None, get(c"MUFFIN_CHECK_SHELL"));
+        // what the program started with doesn't change
+        assert!(rt::env().any(|entry| entry == c"MUFFIN_CHECK_SHELL=/bin/sh"));
+
+        static mut CHILD_ENTRY: [u8; 23] = *b"MUFFIN_CHECK_CHILD=env\0";
+        assert_eq!(0, putenv((&raw mut CHILD_ENTRY).cast()));
+
+        // the child gets the modified environment
+        let args = [c"/bin/muffin_check".as_ptr(), null()];
+        let pid = sys_spawn_raw(c"/bin/muffin_check", &args, environ_slice()).unwrap();
+        assert_eq!(0, sys_waitpid(pid).unwrap());
+
+        assert_eq!(0, unsetenv(CHILD.as_ptr()));
+    }
+}
+
+/// Checks the environment that [`check`] passes on.
+pub fn check_child() {
+    unsafe {
+        assert_eq!(Some(c"/root"), get(c"MUFFIN_CHECK_HOME"));
+        assert_eq!(Some(c"nobody"), get(c"MUFFIN_CHECK_USER"));
+        assert_eq!(None, get(c"MUFFIN_CHECK_SHELL"));
+    }
+}
+
+unsafe fn get(name: &CStr) -> Option<&'static CStr> {
+    let value = unsafe { getenv(name.as_ptr()) };
+    (!value.is_null()).then(|| unsafe { CStr::from_ptr(value) })
+}
+
+/// [`environ`], including the null at its end.
+unsafe fn environ_slice() -> &'static [*const c_char] {
+    unsafe {
+        let len = (0..).find(|&i| (*environ.add(i)).is_null()).unwrap();
+        core::slice::from_raw_parts(environ.cast(), len + 1)
+    }
+}
@@ -0,0 +1,52 @@
+use core::ffi::{c_void, CStr};
+use core::ptr::null_mut;
+
+use libmuffin::errno::__errno_location;
+use libmuffin::pthread::{pthread_create, pthread_join, pthread_self};
+use libmuffin::stdio::fopen;
+use std::syscall::Errno;
+
+const ATTEMPTS: usize = 500;
+
+/// Opening a missing file fails with `ENOENT`, opening a directory for
+/// writing with `EISDIR`.
+static FAILURES: [(&CStr, &CStr, Errno); 2] = [
+    (c"/tmp/muffin_check_missing", c"r", Errno::ENOENT),
+    (c"/tmp", c"w", Errno::EISDIR),
+];
+
+pub fn check() {
+    unsafe {
+        assert!(fopen(c"/tmp".as_ptr(), c"q".as_ptr()).is_null());
+        assert_eq!(Errno::EINVAL.code(), *__errno_location());
+
+        // both threads fail over and over, and never see the error of the other
+        let mut thread = 0;
+        assert_eq!(
+            0,
+            pthread_create(
+                &mut thread,
+                null_mut(),
+                fail,
+                &raw const FAILURES[1] as *mut c_void
+            )
+        );
+        fail(&raw const FAILURES[0] as *mut c_void);
+        assert_eq!(0, pthread_join(thread, null_mut()));
+    }
+}
+
+extern "C" fn fail(failure: *mut c_void) -> *mut c_void {
+    let (path, mode, errno) = unsafe { *failure.cast::<(&CStr, &CStr, Errno)>() };
+    unsafe {
+        // a new thread starts with no error
+        if pthread_self() != 0 {
+            assert_eq!(0, *__errno_location());
+        }
+        for _ in 0..ATTEMPTS {
+            assert!(fopen(path.as_ptr(), mode.as_ptr()).is_null());
+            assert_eq!(errno.code(), *__errno_location());
+        }
+    }
+    null_mut()
+}
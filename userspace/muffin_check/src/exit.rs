@@ -0,0 +1,131 @@
+use core::ffi::{c_void, CStr};
+use core::ptr::null_mut;
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
+
+use libmuffin::stdio::{fopen, fwrite, FILE};
+use libmuffin::stdlib::exit::FIXED_SLOTS;
+use libmuffin::stdlib::{__cxa_atexit, _exit, atexit};
+use std::syscall::{sys_close, sys_open, sys_read, sys_unlink};
+
+use crate::env;
+
+const PATH: &CStr = c"/tmp/muffin_check_exit";
+
+/// The handlers that [`check_child`] registers beyond the ones that don't
+/// allocate.
+const EXTRA: usize = 8;
+
+/// The stream that the last handler writes the order of the handlers to.
+static STREAM: AtomicPtr<FILE> = AtomicPtr::new(null_mut());
+
+static ORDER: [AtomicUsize; 64] = [const { AtomicUsize::new(0) }; 64];
+static ORDER_LEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Runs the child that exits normally, and the one that calls `_exit`, and
+/// checks what they left in the file.
+pub fn check() {
+    assert_eq!(0, env::run_child(c"exit"));
+    let mut expected = [0; 64];
+    let len = expected_order(&mut expected);
+    let mut buf = [0; 64];
+    assert_eq!(&expected[..len], contents(&mut buf));
+
+    assert_eq!(0, env::run_child(c"_exit"));
+    assert_eq!(b"", contents(&mut buf));
+
+    sys_unlink(PATH.to_str().unwrap()).unwrap();
+}
+
+/// Registers handlers and returns, so that `_start` calls `exit`. With
+/// `skip` set, it calls `_exit` instead, which neither runs the handlers nor
+/// flushes the stream.
+pub fn check_child(skip: bool) {
+    unsafe {
+        let stream = fopen(PATH.as_ptr(), c"w".as_ptr());
+        assert!(!stream.is_null());
+        STREAM.store(stream, SeqCst);
+
+        assert_eq!(0, atexit(write_order));
+        assert_eq!(0, atexit(append_a));
+        for _ in 0..FIXED_SLOTS + EXTRA {
+            assert_eq!(0, atexit(append_dot));
+        }
+        assert_eq!(
+            0,
+            __cxa_atexit(append_arg, b'b' as usize as *mut c_void, null_mut())
+        );
+        assert_eq!(0, atexit(register_c));
+        assert_eq!(0, atexit(append_d));
+
+        if skip {
+            _exit(0);
+        }
+    }
+}
+
+/// The order in which the handlers of [`check_child`] run: last in, first
+/// out, with `c` running right after the handler that registers it.
+fn expected_order(buf: &mut [u8]) -> usize {
+    let dots = FIXED_SLOTS + EXTRA;
+    buf[..3].copy_from_slice(b"dcb");
+    buf[3..3 + dots].fill(b'.');
+    buf[3 + dots] = b'a';
+    dots + 4
+}
+
+fn append(c: u8) {
+    let i = ORDER_LEN.fetch_add(1, SeqCst);
+    ORDER[i].store(c as usize, SeqCst);
+}
+
+extern "C" fn append_a() {
+    append(b'a');
+}
+
+unsafe extern "C" fn append_arg(arg: *mut c_void) {
+    append(arg as usize as u8);
+}
+
+extern "C" fn append_c() {
+    append(b'c');
+}
+
+extern "C" fn append_d() {
+    append(b'd');
+}
+
+extern "C" fn append_dot() {
+    append(b'.');
+}
+
+extern "C" fn register_c() {
+    assert_eq!(0, atexit(append_c));
+}
+
+/// Writes the order to the stream without flushing it, so it only ends up in
+/// the file if `exit` flushes the streams after the handlers.
+extern "C" fn write_order() {
+    let mut buf = [0; 64];
+    let len = ORDER_LEN.load(SeqCst);
+    for (b, c) in buf.iter_mut().zip(&ORDER[..len]) {
+        *b = c.load(SeqCst) as u8;
+    }
+    let stream = STREAM.load(SeqCst);
+    assert_eq!(len, unsafe { fwrite(buf.as_ptr().cast(), 1, len, stream) });
+}
+
+/// The contents of the file, as they are on disk.
+fn contents(buf: &mut [u8]) -> &[u8] {
+    let fd = sys_open(PATH.to_str().unwrap(), 0, 0).unwrap();
+    let mut len = 0;
+    loop {
+        let read = sys_read(fd, &mut buf[len..]).unwrap();
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    sys_close(fd).unwrap();
+    &buf[..len]
+}
@@ -0,0 +1,62 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::CStr;
+
+use std::{println, rt};
+
+mod dirent;
+mod env;
+mod errno;
+mod exit;
+mod malloc;
+mod printf;
+mod pthread;
+mod setjmp;
+mod signal;
+mod sort;
+mod stdio;
+mod string;
+mod time;
+
+/// # Safety
+/// Only the kernel calls this, with the initial stack that it set up.
+#[no_mangle]
+pub unsafe extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+    unsafe { libmuffin::stdlib::env::init(stack) };
+
+    main();
+
+    // like returning from `main` in C
+    libmuffin::stdlib::exit(0);
+}
+
+/// Runs the self tests of libmuffin through its C interface, the way C
+/// programs use it. A failing check panics, so the success message is only
+/// printed if all of them pass.
+fn main() {
+    match env::child().map(CStr::to_bytes) {
+        Some(b"env") => return env::check_child(),
+        Some(b"exit") => return exit::check_child(false),
+        Some(b"_exit") => return exit::check_child(true),
+        Some(name) => panic!("unknown child check {name:?}"),
+        None => {}
+    }
+
+    malloc::check();
+    string::check();
+    sort::check();
+    setjmp::check();
+    signal::check();
+    stdio::check();
+    printf::check();
+    pthread::check();
+    errno::check();
+    env::check();
+    exit::check();
+    time::check();
+    dirent::check();
+
+    println!("muffin_check: ok");
+}
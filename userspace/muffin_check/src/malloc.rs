@@ -0,0 +1,124 @@
+use core::ffi::c_void;
+use core::ptr::null_mut;
+
+use libmuffin::stdlib::{calloc, free, malloc, malloc_usable_size, realloc};
+
+/// A byte that depends on where it is and in which allocation, so that
+/// allocations that overlap overwrite each other's pattern.
+fn canary(id: usize, offset: usize) -> u8 {
+    (id.wrapping_mul(31) ^ offset.wrapping_mul(7)) as u8
+}
+
+unsafe fn fill(ptr: *mut c_void, len: usize, id: usize) {
+    let ptr = ptr.cast::<u8>();
+    for offset in 0..len {
+        unsafe { ptr.add(offset).write(canary(id, offset)) };
+    }
+}
+
+unsafe fn check_canary(ptr: *mut c_void, len: usize, id: usize) {
+    let ptr = ptr.cast::<u8>();
+    for offset in 0..len {
+        assert_eq!(
+            canary(id, offset),
+            unsafe { ptr.add(offset).read() },
+            "allocation {} was overwritten at {}",
+            id,
+            offset
+        );
+    }
+}
+
+pub fn check() {
+    unsafe {
+        interleaved();
+        realloc_preserves_contents();
+        fragmentation();
+        calloc_overflow();
+    }
+}
+
+/// Allocates and frees blocks of all kinds of sizes in an interleaved order,
+/// and checks that none of them overwrite each other.
+unsafe fn interleaved() {
+    const LIVE: usize = 64;
+    let mut live = [(null_mut::<c_void>(), 0_usize, 0_usize); LIVE];
+    let mut state = 0x2545_F491_u32;
+    for id in 0..2000 {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        let size = match state % 8 {
+            0 => state as usize % 40_000,
+            1 => 0,
+            _ => state as usize % 700,
+        };
+        let slot = &mut live[state as usize / 8 % LIVE];
+        unsafe {
+            if !slot.0.is_null() {
+                check_canary(slot.0, slot.1, slot.2);
+                free(slot.0);
+            }
+            let ptr = malloc(size);
+            assert!(!ptr.is_null(), "malloc({}) failed", size);
+            assert_eq!(0, ptr as usize % 16, "malloc({}) is misaligned", size);
+            assert!(malloc_usable_size(ptr) >= size);
+            fill(ptr, size, id);
+            *slot = (ptr, size, id);
+        }
+    }
+    for (ptr, size, id) in live {
+        unsafe {
+            check_canary(ptr, size, id);
+            free(ptr);
+        }
+    }
+}
+
+unsafe fn realloc_preserves_contents() {
+    unsafe {
+        let mut ptr = malloc(10);
+        let mut size = 10;
+        fill(ptr, size, 1);
+        for new_size in [20, 100, 3000, 70_000, 50, 5000, 1] {
+            ptr = realloc(ptr, new_size);
+            assert!(!ptr.is_null(), "realloc to {} failed", new_size);
+            check_canary(ptr, size.min(new_size), 1);
+            size = new_size;
+            fill(ptr, size, 1);
+        }
+        assert!(realloc(ptr, 0).is_null());
+    }
+}
+
+/// Fragments the heap with blocks that are freed in an order that keeps them
+/// apart for as long as possible, and then needs all of them for a single
+/// allocation.
+unsafe fn fragmentation() {
+    const COUNT: usize = 200;
+    let mut blocks = [null_mut::<c_void>(); COUNT];
+    unsafe {
+        for (i, block) in blocks.iter_mut().enumerate() {
+            *block = malloc(4000 + i % 3 * 16);
+        }
+        for block in blocks
+            .iter()
+            .step_by(2)
+            .chain(blocks.iter().skip(1).step_by(2))
+        {
+            free(*block);
+        }
+        let large = malloc(700_000);
+        assert!(!large.is_null());
+        fill(large, 700_000, 2);
+        check_canary(large, 700_000, 2);
+        free(large);
+    }
+}
+
+unsafe fn calloc_overflow() {
+    assert!(calloc(usize::MAX / 2, 3).is_null());
+    let ptr = calloc(16, 16);
+    assert!((0..256).all(|i| unsafe { ptr.cast::<u8>().add(i).read() } == 0));
+    unsafe { free(ptr) };
+}
@@ -0,0 +1,169 @@
+use core::ffi::{c_char, c_int, c_long, c_void, CStr};
+use core::ptr::{null, null_mut};
+
+use libmuffin::stdio::{fclose, fopen, fprintf, snprintf};
+use std::syscall::{sys_close, sys_open, sys_read, sys_unlink};
+
+const PATH: &CStr = c"/tmp/muffin_check_printf";
+
+/// Formats with `snprintf` into a buffer that is large enough, and checks the
+/// output and the returned length.
+macro_rules! check {
+    ($expected:expr, $fmt:expr $(, $arg:expr)* $(,)?) => {{
+        let mut buf = [0 as c_char; 128];
+        let len = unsafe { snprintf(buf.as_mut_ptr(), buf.len(), $fmt.as_ptr() $(, $arg)*) };
+        let output = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        assert_eq!($expected, output, "for {:?}", $fmt);
+        assert_eq!($expected.count_bytes(), len as usize, "for {:?}", $fmt);
+    }};
+}
+
+pub fn check() {
+    table();
+    unsafe {
+        sizes();
+        streams();
+    }
+}
+
+fn table() {
+    check!(c"0", c"%d", 0);
+    check!(c"-2147483648", c"%d", c_int::MIN);
+    check!(c"2147483647", c"%i", c_int::MAX);
+    check!(c"-9223372036854775808", c"%ld", c_long::MIN);
+    check!(c"18446744073709551615", c"%llu", u64::MAX);
+    check!(c"-128 255", c"%hhd %hhu", 128, -1);
+    check!(c"-32768 65535", c"%hd %hu", 32768, -1);
+    check!(c"4294967295", c"%u", u32::MAX);
+    check!(c"18446744073709551615", c"%zu", usize::MAX);
+    check!(
+        c"deadbeef DEADBEEF 17",
+        c"%x %X %o",
+        0xdeadbeef_u32,
+        0xdeadbeef_u32,
+        15
+    );
+    check!(c"0xff 0XFF 0377 0", c"%#x %#X %#o %#x", 255, 255, 255, 0);
+
+    check!(c"[    7]", c"[%5d]", 7);
+    check!(c"[7    ]", c"[%-5d]", 7);
+    check!(c"[00007]", c"[%05d]", 7);
+    check!(c"[-0007]", c"[%05d]", -7);
+    check!(c"[7    ]", c"[%-05d]", 7);
+    check!(c"[  007]", c"[%5.3d]", 7);
+    check!(c"[  007]", c"[%05.3d]", 7);
+    check!(c"[+7] [ 7] [-7]", c"[%+d] [% d] [% d]", 7, 7, -7);
+    check!(c"[] [0]", c"[%.0d] [%#.0o]", 0, 0);
+    check!(c"[   ab] [ab   ]", c"[%*x] [%*x]", 5, 0xab, -5, 0xab);
+    check!(c"[00ab] [ab]", c"[%.*x] [%.*x]", 4, 0xab, -1, 0xab);
+    check!(c"[0x00ab]", c"[%#06x]", 0xab);
+
+    check!(
+        c"x [  y] [z  ]",
+        c"%c [%3c] [%-3c]",
+        'x' as c_int,
+        'y' as c_int,
+        'z' as c_int
+    );
+    check!(c"muffin", c"%s", c"muffin".as_ptr());
+    check!(
+        c"[muf] [   muf]",
+        c"[%.3s] [%6.3s]",
+        c"muffin".as_ptr(),
+        c"muffin".as_ptr()
+    );
+    check!(c"[ab    ]", c"[%-6s]", c"ab".as_ptr());
+    check!(c"(null)", c"%s", null::<c_char>());
+    check!(
+        c"0x1000 (nil)",
+        c"%p %p",
+        0x1000 as *const c_void,
+        null::<c_void>()
+    );
+    check!(c"100%", c"%d%%", 100);
+
+    check!(c"0.000000", c"%f", 0.0);
+    check!(c"-1.500000", c"%f", -1.5);
+    check!(c"0.1000000000000000055511151231257827", c"%.34f", 0.1);
+    check!(c"2 2 4", c"%.0f %.0f %.0f", 1.5, 2.5, 3.5);
+    check!(c"[  -1.25] [+1.25  ]", c"[%7.2f] [%-+7.2f]", -1.25, 1.25);
+    check!(c"[-001.25]", c"[%07.2f]", -1.25);
+    check!(c"1.000000e+00 1.50E-10", c"%e %.2E", 1.0, 1.5e-10);
+    check!(
+        c"100000 1e+06 1e-05 0.0001",
+        c"%g %g %g %g",
+        1e5,
+        1e6,
+        1e-5,
+        1e-4
+    );
+    check!(c"1.5 1.50000 1E+100", c"%g %#g %G", 1.5, 1.5, 1e100);
+    check!(
+        c"inf -inf nan INF",
+        c"%f %f %f %F",
+        f64::INFINITY,
+        f64::NEG_INFINITY,
+        f64::NAN,
+        f64::INFINITY
+    );
+
+    check!(c"%y", c"%y");
+}
+
+/// `snprintf` never writes more than it is told to, and returns the length
+/// that the output would have had.
+unsafe fn sizes() {
+    unsafe {
+        let mut buf = [b'#' as c_char; 16];
+        assert_eq!(
+            11,
+            snprintf(buf.as_mut_ptr(), 0, c"%d".as_ptr(), c_int::MIN)
+        );
+        assert_eq!(b'#' as c_char, buf[0]);
+        assert_eq!(11, snprintf(null_mut(), 0, c"%d".as_ptr(), c_int::MIN));
+
+        assert_eq!(
+            11,
+            snprintf(buf.as_mut_ptr(), 1, c"%d".as_ptr(), c_int::MIN)
+        );
+        assert_eq!(0, buf[0]);
+        assert_eq!(b'#' as c_char, buf[1]);
+
+        assert_eq!(
+            11,
+            snprintf(buf.as_mut_ptr(), 5, c"%d".as_ptr(), c_int::MIN)
+        );
+        assert_eq!(c"-214", CStr::from_ptr(buf.as_ptr()));
+        assert_eq!(b'#' as c_char, buf[5]);
+
+        assert_eq!(
+            11,
+            snprintf(buf.as_mut_ptr(), 12, c"%d".as_ptr(), c_int::MIN)
+        );
+        assert_eq!(c"-2147483648", CStr::from_ptr(buf.as_ptr()));
+        assert_eq!(b'#' as c_char, buf[12]);
+    }
+}
+
+unsafe fn streams() {
+    unsafe {
+        let file = fopen(PATH.as_ptr(), c"w".as_ptr());
+        assert!(!file.is_null());
+        let len = fprintf(
+            file,
+            c"%s=%05.1f%c".as_ptr(),
+            c"pi".as_ptr(),
+            3.25,
+            '\n' as c_int,
+        );
+        assert_eq!(9, len);
+        assert_eq!(0, fclose(file));
+    }
+
+    let mut buf = [0; 16];
+    let fd = sys_open(PATH.to_str().unwrap(), 0, 0).unwrap();
+    let read = sys_read(fd, &mut buf).unwrap();
+    sys_close(fd).unwrap();
+    assert_eq!(b"pi=003.2\n", &buf[..read]);
+    sys_unlink(PATH.to_str().unwrap()).unwrap();
+}
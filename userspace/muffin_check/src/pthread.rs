@@ -0,0 +1,218 @@
+use core::cell::UnsafeCell;
+use core::ffi::c_void;
+use core::ptr::null_mut;
+use core::sync::atomic::AtomicUsize;
+use core::sync::atomic::Ordering::Relaxed;
+
+use libmuffin::pthread::{
+    pthread_cond_broadcast, pthread_cond_signal, pthread_cond_t, pthread_cond_wait, pthread_create,
+    pthread_equal, pthread_join, pthread_mutex_lock, pthread_mutex_t, pthread_mutex_trylock,
+    pthread_mutex_unlock, pthread_self, pthread_t, PTHREAD_COND_INITIALIZER,
+    PTHREAD_MUTEX_INITIALIZER,
+};
+use std::syscall::{sys_close, sys_open, sys_read};
+
+const THREADS: usize = 8;
+const INCREMENTS: usize = 100_000;
+/// Every item makes a thread sleep, and the kernel only wakes it with the
+/// next timer tick, so there are far fewer items than in the host tests.
+const ITEMS: usize = 200;
+const WAITERS: usize = 16;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A value that is only accessed while holding the mutex, and two condition
+/// variables to wait for changes of it.
+struct Shared<T> {
+    mutex: UnsafeCell<pthread_mutex_t>,
+    first: UnsafeCell<pthread_cond_t>,
+    second: UnsafeCell<pthread_cond_t>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Shared<T> {}
+
+impl<T> Shared<T> {
+    const fn new(value: T) -> Self {
+        Self {
+            mutex: UnsafeCell::new(PTHREAD_MUTEX_INITIALIZER),
+            first: UnsafeCell::new(PTHREAD_COND_INITIALIZER),
+            second: UnsafeCell::new(PTHREAD_COND_INITIALIZER),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    unsafe fn lock(&self) {
+        assert_eq!(0, unsafe { pthread_mutex_lock(self.mutex.get()) });
+    }
+
+    unsafe fn unlock(&self) {
+        assert_eq!(0, unsafe { pthread_mutex_unlock(self.mutex.get()) });
+    }
+
+    /// Waits on `cond` until `done` returns true. The mutex must be held.
+    unsafe fn wait_until(&self, cond: &UnsafeCell<pthread_cond_t>, done: impl Fn(&T) -> bool) {
+        unsafe {
+            while !done(&*self.value.get()) {
+                assert_eq!(0, pthread_cond_wait(cond.get(), self.mutex.get()));
+            }
+        }
+    }
+}
+
+/// The slot between producer and consumer. `first` is signalled when it's
+/// filled, `second` when it's emptied.
+static CHANNEL: Shared<Option<usize>> = Shared::new(None);
+/// How many threads are waiting, whether they were released, and how many
+/// were woken. `first` is broadcast on release, `second` signalled when a
+/// thread is about to wait.
+static BARRIER: Shared<(usize, bool, usize)> = Shared::new((0, false, 0));
+
+pub fn check() {
+    unsafe {
+        // the first thread also sets up the TLS of the main thread, which
+        // stays mapped
+        let mut thread = 0;
+        assert_eq!(
+            0,
+            pthread_create(&mut thread, null_mut(), identity, 7 as *mut c_void)
+        );
+        let mut value = null_mut();
+        assert_eq!(0, pthread_join(thread, &mut value));
+        assert_eq!(7, value as usize);
+
+        let mappings = count_mappings();
+        counter();
+        // all stacks were unmapped again
+        assert_eq!(mappings, count_mappings());
+
+        mutex();
+        producer_and_consumer();
+        broadcast();
+    }
+}
+
+extern "C" fn identity(arg: *mut c_void) -> *mut c_void {
+    assert_eq!(0, pthread_equal(pthread_self(), 0));
+    arg
+}
+
+extern "C" fn increment(_: *mut c_void) -> *mut c_void {
+    for _ in 0..INCREMENTS {
+        COUNTER.fetch_add(1, Relaxed);
+    }
+    pthread_self() as *mut c_void
+}
+
+unsafe fn counter() {
+    let mut threads: [pthread_t; THREADS] = [0; THREADS];
+    unsafe {
+        for thread in &mut threads {
+            assert_eq!(0, pthread_create(thread, null_mut(), increment, null_mut()));
+        }
+        for thread in threads {
+            let mut value = null_mut();
+            assert_eq!(0, pthread_join(thread, &mut value));
+            // every thread knows who it is
+            assert_eq!(thread, value as pthread_t);
+        }
+    }
+    assert_eq!(THREADS * INCREMENTS, COUNTER.load(Relaxed));
+}
+
+unsafe fn mutex() {
+    let mut mutex = PTHREAD_MUTEX_INITIALIZER;
+    unsafe {
+        assert_eq!(0, pthread_mutex_trylock(&mut mutex));
+        assert_ne!(0, pthread_mutex_trylock(&mut mutex));
+        assert_eq!(0, pthread_mutex_unlock(&mut mutex));
+        assert_eq!(0, pthread_mutex_lock(&mut mutex));
+        assert_eq!(0, pthread_mutex_unlock(&mut mutex));
+    }
+}
+
+extern "C" fn consume(_: *mut c_void) -> *mut c_void {
+    unsafe {
+        for expected in 0..ITEMS {
+            CHANNEL.lock();
+            CHANNEL.wait_until(&CHANNEL.first, Option::is_some);
+            assert_eq!(Some(expected), (*CHANNEL.value.get()).take());
+            pthread_cond_signal(CHANNEL.second.get());
+            CHANNEL.unlock();
+        }
+    }
+    null_mut()
+}
+
+/// Hands items through a single slot, so that every item needs a signal in
+/// each direction.
+unsafe fn producer_and_consumer() {
+    unsafe {
+        let mut consumer = 0;
+        assert_eq!(
+            0,
+            pthread_create(&mut consumer, null_mut(), consume, null_mut())
+        );
+        for item in 0..ITEMS {
+            CHANNEL.lock();
+            CHANNEL.wait_until(&CHANNEL.second, Option::is_none);
+            *CHANNEL.value.get() = Some(item);
+            pthread_cond_signal(CHANNEL.first.get());
+            CHANNEL.unlock();
+        }
+        assert_eq!(0, pthread_join(consumer, null_mut()));
+    }
+}
+
+extern "C" fn wait_for_release(_: *mut c_void) -> *mut c_void {
+    unsafe {
+        BARRIER.lock();
+        (*BARRIER.value.get()).0 += 1;
+        pthread_cond_signal(BARRIER.second.get());
+        BARRIER.wait_until(&BARRIER.first, |&(_, released, _)| released);
+        (*BARRIER.value.get()).2 += 1;
+        BARRIER.unlock();
+    }
+    null_mut()
+}
+
+/// A single broadcast wakes all waiters, each of them exactly once.
+unsafe fn broadcast() {
+    let mut waiters: [pthread_t; WAITERS] = [0; WAITERS];
+    unsafe {
+        for waiter in &mut waiters {
+            assert_eq!(
+                0,
+                pthread_create(waiter, null_mut(), wait_for_release, null_mut())
+            );
+        }
+        BARRIER.lock();
+        BARRIER.wait_until(&BARRIER.second, |&(waiting, _, _)| waiting == WAITERS);
+        (*BARRIER.value.get()).1 = true;
+        pthread_cond_broadcast(BARRIER.first.get());
+        BARRIER.unlock();
+
+        for waiter in waiters {
+            assert_eq!(0, pthread_join(waiter, null_mut()));
+        }
+        BARRIER.lock();
+        assert_eq!((WAITERS, true, WAITERS), *BARRIER.value.get());
+        BARRIER.unlock();
+    }
+}
+
+/// The number of lines in `/proc/self/maps`, which has one per mapping.
+fn count_mappings() -> usize {
+    let fd = sys_open("/proc/self/maps", 0, 0).unwrap();
+    let mut buf = [0; 512];
+    let mut lines = 0;
+    loop {
+        let read = sys_read(fd, &mut buf).unwrap();
+        if read == 0 {
+            break;
+        }
+        lines += buf[..read].iter().filter(|&&b| b == b'\n').count();
+    }
+    sys_close(fd).unwrap();
+    lines
+}
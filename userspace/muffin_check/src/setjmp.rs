@@ -0,0 +1,76 @@
+use core::ffi::c_int;
+use core::hint::black_box;
+use core::ptr::{read_volatile, write_volatile};
+
+use libmuffin::setjmp::{__jmp_buf_tag, jmp_buf, longjmp, setjmp};
+
+pub fn check() {
+    unsafe {
+        nested();
+        zero_becomes_one();
+        volatile_locals();
+    }
+}
+
+/// Jumps back to `env` from a frame `depth` frames below the caller.
+#[inline(never)]
+unsafe fn jump_from(env: *const __jmp_buf_tag, depth: u32, val: c_int) {
+    if depth == 0 {
+        unsafe { longjmp(env, val) };
+    }
+    unsafe { jump_from(env, black_box(depth - 1), val) };
+    black_box(depth);
+}
+
+/// Sets up a second `setjmp` one frame below the first, and then jumps from
+/// two frames below that to the outer one, skipping the inner one.
+unsafe fn nested() {
+    let mut outer = jmp_buf::default();
+    let mut inner_returns = 0;
+    let ret = unsafe { setjmp(outer.as_mut_ptr()) };
+    if ret == 0 {
+        unsafe { inner(outer.as_ptr(), &mut inner_returns) };
+        unreachable!("the inner frame returned");
+    }
+    assert_eq!(2, ret);
+    assert_eq!(1, unsafe { read_volatile(&inner_returns) });
+}
+
+#[inline(never)]
+unsafe fn inner(outer: *const __jmp_buf_tag, returns: *mut i32) {
+    let mut env = jmp_buf::default();
+    let ret = unsafe { setjmp(env.as_mut_ptr()) };
+    unsafe { write_volatile(returns, read_volatile(returns) + 1) };
+    if ret == 0 {
+        unsafe { jump_from(outer, 2, 2) };
+    }
+    unreachable!("the inner setjmp returned twice");
+}
+
+unsafe fn zero_becomes_one() {
+    let mut env = jmp_buf::default();
+    let ret = unsafe { setjmp(env.as_mut_ptr()) };
+    if ret == 0 {
+        unsafe { jump_from(env.as_ptr(), 1, 0) };
+    }
+    assert_eq!(1, ret);
+}
+
+/// Volatile locals that are changed between `setjmp` and `longjmp` keep
+/// their new values.
+unsafe fn volatile_locals() {
+    let mut env = jmp_buf::default();
+    let mut counter = 0_u64;
+    let mut sum = 0_u64;
+    let ret = unsafe { setjmp(env.as_mut_ptr()) };
+    unsafe {
+        let count = read_volatile(&counter) + 1;
+        write_volatile(&mut counter, count);
+        write_volatile(&mut sum, read_volatile(&sum) + count * 10);
+    }
+    if ret < 5 {
+        unsafe { jump_from(env.as_ptr(), ret as u32, ret + 1) };
+    }
+    assert_eq!(6, unsafe { read_volatile(&counter) });
+    assert_eq!(210, unsafe { read_volatile(&sum) });
+}
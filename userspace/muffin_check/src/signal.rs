@@ -0,0 +1,164 @@
+use core::ffi::{c_int, c_void};
+use core::ptr::{null, null_mut, read_volatile};
+use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
+
+use libmuffin::errno::errno;
+use libmuffin::setjmp::{__jmp_buf_tag, jmp_buf, siglongjmp, sigsetjmp};
+use libmuffin::signal::{
+    kill, raise, sigaction, sigaddset, sigemptyset, siginfo_t, sigismember, signal, sigprocmask,
+    sigset_t, SA_SIGINFO, SEGV_MAPERR, SIGKILL, SIGSEGV, SIGUSR1, SIGUSR2, SIG_BLOCK, SIG_DFL,
+    SIG_SETMASK, SIG_UNBLOCK, SI_USER,
+};
+use libmuffin::unistd::getpid;
+use std::syscall::Errno;
+
+/// Nothing is ever mapped in the first page.
+const BAD_ADDRESS: usize = 0x10;
+
+/// Where the `SIGSEGV` handler jumps to.
+static ESCAPE: AtomicPtr<__jmp_buf_tag> = AtomicPtr::new(null_mut());
+
+static FAULT_ADDRESS: AtomicUsize = AtomicUsize::new(0);
+static FAULT_CODE: AtomicUsize = AtomicUsize::new(0);
+static USR1_COUNT: AtomicUsize = AtomicUsize::new(0);
+static USR1_SENDER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn check() {
+    unsafe {
+        segv();
+        user_signal();
+        blocked();
+        errors();
+    }
+}
+
+extern "C" fn on_segv(signo: c_int, info: *mut siginfo_t, _context: *mut c_void) {
+    let info = unsafe { &*info };
+    assert_eq!(SIGSEGV as c_int, signo);
+    FAULT_ADDRESS.store(info.si_addr as usize, SeqCst);
+    FAULT_CODE.store(info.si_code as usize, SeqCst);
+    unsafe { siglongjmp(ESCAPE.load(SeqCst), 1) };
+}
+
+extern "C" fn on_usr1(signo: c_int, info: *mut siginfo_t, _context: *mut c_void) {
+    assert_eq!(SIGUSR1 as c_int, signo);
+    let info = unsafe { &*info };
+    assert_eq!(SI_USER, info.si_code);
+    USR1_SENDER.store(info.si_pid as usize, SeqCst);
+    USR1_COUNT.fetch_add(1, SeqCst);
+}
+
+/// Faults on purpose twice, and escapes from the handler each time. The
+/// second fault only reaches the handler if leaving the first one restored
+/// the mask that blocks `SIGSEGV` while the handler runs.
+unsafe fn segv() {
+    unsafe {
+        let mut act = sigaction {
+            sa_sigaction: on_segv as *const () as usize,
+            sa_mask: 0,
+            sa_flags: SA_SIGINFO,
+            sa_restorer: None,
+        };
+        sigemptyset(&mut act.sa_mask);
+        assert_eq!(0, sigaction(SIGSEGV as c_int, &act, null_mut()));
+
+        let mut env = jmp_buf::default();
+        ESCAPE.store(env.as_mut_ptr(), SeqCst);
+        for _ in 0..2 {
+            FAULT_ADDRESS.store(0, SeqCst);
+            if sigsetjmp(env.as_mut_ptr(), 1) == 0 {
+                read_volatile(BAD_ADDRESS as *const u64);
+                unreachable!("reading from {BAD_ADDRESS:#x} didn't fault");
+            }
+            assert_eq!(BAD_ADDRESS, FAULT_ADDRESS.load(SeqCst));
+            assert_eq!(SEGV_MAPERR as usize, FAULT_CODE.load(SeqCst));
+            assert_eq!(0, sigismember(&current_mask(), SIGSEGV as c_int));
+        }
+
+        assert_eq!(
+            on_segv as *const () as usize,
+            signal(SIGSEGV as c_int, SIG_DFL)
+        );
+    }
+}
+
+unsafe fn current_mask() -> sigset_t {
+    let mut mask = 0;
+    assert_eq!(0, unsafe { sigprocmask(SIG_BLOCK, null(), &mut mask) });
+    mask
+}
+
+/// `raise` runs the handler before it returns.
+unsafe fn user_signal() {
+    unsafe {
+        let act = sigaction {
+            sa_sigaction: on_usr1 as *const () as usize,
+            sa_mask: 0,
+            sa_flags: SA_SIGINFO,
+            sa_restorer: None,
+        };
+        let mut old = act;
+        assert_eq!(0, sigaction(SIGUSR1 as c_int, &act, &mut old));
+        assert_eq!(SIG_DFL, old.sa_sigaction);
+
+        USR1_COUNT.store(0, SeqCst);
+        assert_eq!(0, raise(SIGUSR1 as c_int));
+        assert_eq!(1, USR1_COUNT.load(SeqCst));
+        assert_eq!(getpid() as usize, USR1_SENDER.load(SeqCst));
+        assert_eq!(0, kill(getpid(), SIGUSR1 as c_int));
+        assert_eq!(2, USR1_COUNT.load(SeqCst));
+    }
+}
+
+/// A blocked signal stays pending, doesn't queue, and is delivered as soon
+/// as it's unblocked.
+unsafe fn blocked() {
+    unsafe {
+        let mut set = 0;
+        sigemptyset(&mut set);
+        sigaddset(&mut set, SIGUSR1 as c_int);
+        sigaddset(&mut set, SIGKILL as c_int);
+        let mut old = 0;
+        assert_eq!(0, sigprocmask(SIG_BLOCK, &set, &mut old));
+        // SIGKILL can't be blocked
+        assert_eq!(0, sigismember(&current_mask(), SIGKILL as c_int));
+
+        USR1_COUNT.store(0, SeqCst);
+        assert_eq!(0, raise(SIGUSR1 as c_int));
+        assert_eq!(0, raise(SIGUSR1 as c_int));
+        assert_eq!(0, USR1_COUNT.load(SeqCst));
+        assert_eq!(0, sigprocmask(SIG_UNBLOCK, &set, null_mut()));
+        assert_eq!(1, USR1_COUNT.load(SeqCst));
+
+        assert_eq!(0, sigprocmask(SIG_SETMASK, &old, null_mut()));
+        assert_eq!(
+            on_usr1 as *const () as usize,
+            signal(SIGUSR1 as c_int, SIG_DFL)
+        );
+    }
+}
+
+unsafe fn errors() {
+    let act = sigaction {
+        sa_sigaction: on_usr1 as *const () as usize,
+        sa_mask: 0,
+        sa_flags: 0,
+        sa_restorer: None,
+    };
+    unsafe {
+        assert_eq!(-1, sigaction(SIGKILL as c_int, &act, null_mut()));
+        assert_eq!(Errno::EINVAL.code(), errno());
+        assert_eq!(-1, sigaction(0, &act, null_mut()));
+        assert_eq!(Errno::EINVAL.code(), errno());
+    }
+    assert_eq!(-1, kill(getpid(), 1000));
+    assert_eq!(Errno::EINVAL.code(), errno());
+    // signal 0 only checks whether the process exists
+    assert_eq!(0, kill(getpid(), 0));
+    assert_eq!(-1, kill(0x7FFF_FFFF, SIGUSR2 as c_int));
+    assert_eq!(Errno::ESRCH.code(), errno());
+    let mut mask = 0;
+    assert_eq!(-1, unsafe { sigprocmask(7, &mask, &mut mask) });
+    assert_eq!(Errno::EINVAL.code(), errno());
+}
@@ -0,0 +1,111 @@
+use core::ffi::{c_int, c_void};
+use core::mem::size_of;
+use core::slice::from_raw_parts_mut;
+
+use libmuffin::stdlib::{bsearch, free, malloc, qsort};
+
+/// The host tests sort a million values, which takes too long in a debug
+/// build under QEMU.
+const VALUES: usize = 100_000;
+
+unsafe extern "C" fn compare_u32(a: *const c_void, b: *const c_void) -> c_int {
+    let (a, b) = unsafe { (*a.cast::<u32>(), *b.cast::<u32>()) };
+    a.cmp(&b) as c_int
+}
+
+/// Sorts by the first byte only, so the other two must move along with it.
+unsafe extern "C" fn compare_first_byte(a: *const c_void, b: *const c_void) -> c_int {
+    let (a, b) = unsafe { (*a.cast::<u8>(), *b.cast::<u8>()) };
+    a.cmp(&b) as c_int
+}
+
+pub fn check() {
+    unsafe {
+        random();
+        element_size_3();
+        search();
+    }
+}
+
+/// Depends on the position of every value, so that it only matches if the
+/// order is the same.
+fn checksum(values: &[u32]) -> u64 {
+    values.iter().enumerate().fold(0, |sum, (i, &value)| {
+        sum.wrapping_mul(31)
+            .wrapping_add(u64::from(value) ^ i as u64)
+    })
+}
+
+unsafe fn random() {
+    unsafe {
+        let values = malloc(VALUES * size_of::<u32>()).cast::<u32>();
+        assert!(!values.is_null());
+        let values = from_raw_parts_mut(values, VALUES);
+        let mut state = 0x1234_5678_u32;
+        for value in values.iter_mut() {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *value = state;
+        }
+        let expected = malloc(VALUES * size_of::<u32>()).cast::<u32>();
+        assert!(!expected.is_null());
+        let expected = from_raw_parts_mut(expected, VALUES);
+        expected.copy_from_slice(values);
+        expected.sort_unstable();
+
+        qsort(
+            values.as_mut_ptr().cast(),
+            VALUES,
+            size_of::<u32>(),
+            compare_u32,
+        );
+        assert!(values.is_sorted());
+        assert_eq!(checksum(expected), checksum(values));
+
+        free(values.as_mut_ptr().cast());
+        free(expected.as_mut_ptr().cast());
+    }
+}
+
+unsafe fn element_size_3() {
+    let mut elements: [[u8; 3]; 100] = core::array::from_fn(|i| {
+        let key = (i * 37 % 100) as u8;
+        [key, key ^ 0x55, key ^ 0xAA]
+    });
+    unsafe {
+        qsort(
+            elements.as_mut_ptr().cast(),
+            elements.len(),
+            3,
+            compare_first_byte,
+        )
+    };
+    for (i, element) in elements.iter().enumerate() {
+        let key = i as u8;
+        assert_eq!([key, key ^ 0x55, key ^ 0xAA], *element);
+    }
+}
+
+unsafe fn search() {
+    let values: [u32; 64] = core::array::from_fn(|i| i as u32 * 3 + 1);
+    let find = |key: u32| {
+        let found = unsafe {
+            bsearch(
+                (&raw const key).cast(),
+                values.as_ptr().cast(),
+                values.len(),
+                size_of::<u32>(),
+                compare_u32,
+            )
+        };
+        (!found.is_null()).then(|| (found.addr() - values.as_ptr().addr()) / size_of::<u32>())
+    };
+    // hits and misses at both ends
+    assert_eq!(Some(0), find(1));
+    assert_eq!(Some(63), find(190));
+    assert_eq!(None, find(0));
+    assert_eq!(None, find(191));
+    assert_eq!(Some(20), find(61));
+    assert_eq!(None, find(62));
+}
@@ -0,0 +1,150 @@
+use core::ffi::{c_char, c_int, CStr};
+use core::ptr::null_mut;
+
+use libmuffin::stdio::{
+    fclose, feof, fflush, fgetc, fgets, fopen, fputc, fputs, fread, fseek, ftell, fwrite, ungetc,
+    BUFSIZ, EOF, SEEK_CUR, SEEK_END, SEEK_SET,
+};
+use std::syscall::{sys_close, sys_open, sys_read, sys_unlink};
+
+const PATH: &CStr = c"/tmp/muffin_check_stdio";
+
+pub fn check() {
+    unsafe {
+        update_stream();
+        large_writes();
+        append();
+    }
+    sys_unlink(PATH.to_str().unwrap()).unwrap();
+}
+
+/// The contents of the file, as they are on disk.
+fn contents(buf: &mut [u8]) -> &[u8] {
+    let fd = sys_open(PATH.to_str().unwrap(), 0, 0).unwrap();
+    let mut len = 0;
+    loop {
+        let read = sys_read(fd, &mut buf[len..]).unwrap();
+        if read == 0 {
+            break;
+        }
+        len += read;
+    }
+    sys_close(fd).unwrap();
+    &buf[..len]
+}
+
+/// Writes, seeks back, reads and writes again on a stream that is open for
+/// both, and checks what ends up on disk.
+unsafe fn update_stream() {
+    unsafe {
+        let file = fopen(PATH.as_ptr(), c"w+".as_ptr());
+        assert!(!file.is_null());
+        assert_eq!(0, fputs(c"first line\nsecond line\n".as_ptr(), file));
+        assert_eq!(23, ftell(file));
+
+        assert_eq!(0, fseek(file, 0, SEEK_SET));
+        let mut line = [0 as c_char; 32];
+        assert!(!fgets(line.as_mut_ptr(), 32, file).is_null());
+        assert_eq!(c"first line\n", CStr::from_ptr(line.as_ptr()));
+        assert_eq!(11, ftell(file));
+
+        // overwrite "second" after having read ahead past it
+        assert_eq!('s' as c_int, fgetc(file));
+        assert_eq!(0, fseek(file, -1, SEEK_CUR));
+        assert_eq!(6, fwrite(c"SECOND".as_ptr().cast(), 1, 6, file));
+        assert_eq!(' ' as c_int, fgetc(file));
+        assert_eq!('l' as c_int, ungetc('l' as c_int, file));
+        assert_eq!(17, ftell(file));
+        assert_eq!('l' as c_int, fgetc(file));
+
+        // and append at the end, past what the stream had buffered
+        assert_eq!(0, fseek(file, 0, SEEK_END));
+        assert_eq!('!' as c_int, fputc('!' as c_int, file));
+        assert_eq!(0, fseek(file, -3, SEEK_END));
+        let mut tail = [0_u8; 8];
+        assert_eq!(3, fread(tail.as_mut_ptr().cast(), 1, 8, file));
+        assert_ne!(0, feof(file));
+        assert_eq!(b"e\n!", &tail[..3]);
+        assert!(fgets(line.as_mut_ptr(), 32, file).is_null());
+        assert_eq!(EOF, fgetc(file));
+
+        assert_eq!(0, fclose(file));
+        assert_eq!(b"first line\nSECOND line\n!", contents(&mut [0; 64]));
+    }
+}
+
+fn pattern(offset: usize) -> u8 {
+    (offset * 7 % 251) as u8
+}
+
+/// Mixes writes that are smaller and larger than the buffer, and reads them
+/// back in items.
+unsafe fn large_writes() {
+    unsafe {
+        let file = fopen(PATH.as_ptr(), c"w".as_ptr());
+        let mut chunk = [0_u8; BUFSIZ + 1];
+        let mut written = 0;
+        for len in [1, 100, BUFSIZ - 50, BUFSIZ + 1, 10, BUFSIZ - 62] {
+            for (i, byte) in chunk[..len].iter_mut().enumerate() {
+                *byte = pattern(written + i);
+            }
+            assert_eq!(len, fwrite(chunk.as_ptr().cast(), 1, len, file));
+            written += len;
+        }
+        // reading a stream that is only open for writing fails
+        assert_eq!(EOF, fgetc(file));
+        assert_eq!(0, fflush(null_mut()));
+
+        let fd = sys_open(PATH.to_str().unwrap(), 0, 0).unwrap();
+        let mut on_disk = 0;
+        loop {
+            let read = sys_read(fd, &mut chunk[..512]).unwrap();
+            if read == 0 {
+                break;
+            }
+            for (i, &byte) in chunk[..read].iter().enumerate() {
+                assert_eq!(pattern(on_disk + i), byte, "at {}", on_disk + i);
+            }
+            on_disk += read;
+        }
+        sys_close(fd).unwrap();
+        assert_eq!(written, on_disk);
+        assert_eq!(0, fclose(file));
+
+        let file = fopen(PATH.as_ptr(), c"r".as_ptr());
+        let mut items = [[0_u8; 5]; 100];
+        let mut read = 0;
+        loop {
+            let count = fread(items.as_mut_ptr().cast(), 5, items.len(), file);
+            for (i, &byte) in items.as_flattened()[..count * 5].iter().enumerate() {
+                assert_eq!(pattern(read + i), byte, "at {}", read + i);
+            }
+            read += count * 5;
+            if count < items.len() {
+                break;
+            }
+        }
+        // only whole items count
+        assert_eq!(written / 5 * 5, read);
+        assert_ne!(0, feof(file));
+        assert_eq!(0, fclose(file));
+    }
+}
+
+unsafe fn append() {
+    unsafe {
+        let file = fopen(PATH.as_ptr(), c"w".as_ptr());
+        fputs(c"abc".as_ptr(), file);
+        assert_eq!(0, fclose(file));
+
+        let file = fopen(PATH.as_ptr(), c"a+".as_ptr());
+        assert_eq!('a' as c_int, fgetc(file));
+        // appending always writes at the end, no matter where the stream is
+        fputs(c"def".as_ptr(), file);
+        assert_eq!(0, fclose(file));
+        assert_eq!(b"abcdef", contents(&mut [0; 16]));
+
+        assert!(fopen(c"/tmp/does/not/exist".as_ptr(), c"r".as_ptr()).is_null());
+        assert!(fopen(PATH.as_ptr(), c"z".as_ptr()).is_null());
+    }
+}
@@ -0,0 +1,179 @@
+use core::ffi::{c_char, c_int, c_void};
+use core::ptr::null_mut;
+
+use libmuffin::string::{
+    memcmp, memcpy, memmove, memset, strcat, strchr, strcmp, strcpy, strlen, strncmp, strncpy,
+    strrchr, strstr, strtok_r,
+};
+
+/// Large enough for every length and offset that the checks use, and aligned
+/// to a word, so that the offsets are relative to an alignment boundary.
+#[repr(align(16))]
+#[derive(Clone, Copy)]
+struct Buffer([u8; 128]);
+
+impl Buffer {
+    /// Pseudo random bytes, none of which is zero.
+    fn random(state: &mut u32) -> Self {
+        let mut buffer = Self([0; 128]);
+        for byte in &mut buffer.0 {
+            *state ^= *state << 13;
+            *state ^= *state >> 17;
+            *state ^= *state << 5;
+            *byte = (*state as u8).max(1);
+        }
+        buffer
+    }
+
+    fn at(&mut self, offset: usize) -> *mut c_void {
+        self.0[offset..].as_mut_ptr().cast()
+    }
+}
+
+pub fn check() {
+    unsafe {
+        edge_cases();
+        strings();
+        against_naive();
+    }
+}
+
+/// Zero lengths, single bytes and overlapping moves in both directions.
+unsafe fn edge_cases() {
+    let mut state = 0x1234_5678;
+    let original = Buffer::random(&mut state);
+    unsafe {
+        let mut buffer = original;
+        memcpy(buffer.at(0), buffer.at(8).cast_const(), 0);
+        memmove(buffer.at(0), buffer.at(8).cast_const(), 0);
+        memset(buffer.at(0), 0, 0);
+        assert_eq!(original.0, buffer.0);
+        assert_eq!(0, memcmp(buffer.at(0), buffer.at(1), 0));
+
+        memset(buffer.at(3), 0x100 | 0x42, 1);
+        assert_eq!(0x42, buffer.0[3]);
+        assert_eq!(original.0[4..], buffer.0[4..]);
+        let ptr = buffer.at(0).cast::<u8>();
+        memcpy(ptr.add(5).cast(), ptr.add(3).cast(), 1);
+        assert_eq!(0x42, buffer.0[5]);
+        assert!(memcmp(buffer.at(3), original.0[3..].as_ptr().cast(), 1) != 0);
+
+        // moving up by less than a word, and down by more than one
+        for (from, to, len) in [(0, 3, 40), (3, 0, 40), (2, 17, 60), (17, 2, 60)] {
+            let mut buffer = original;
+            let ptr = buffer.at(0).cast::<u8>();
+            memmove(ptr.add(to).cast(), ptr.add(from).cast(), len);
+            assert_eq!(original.0[from..from + len], buffer.0[to..to + len]);
+        }
+
+        // the terminator in every position of a word, at every alignment
+        for start in 0..16 {
+            for len in 0..24 {
+                let mut buffer = original;
+                buffer.0[start + len] = 0;
+                assert_eq!(len, strlen(buffer.at(start).cast()));
+            }
+        }
+    }
+}
+
+unsafe fn strings() {
+    unsafe {
+        assert_eq!(0, strcmp(c"".as_ptr(), c"".as_ptr()));
+        assert!(strcmp(c"a".as_ptr(), c"".as_ptr()) > 0);
+        assert!(strcmp(c"\x80".as_ptr(), c"\x7f".as_ptr()) > 0);
+        assert_eq!(0, strncmp(c"abX".as_ptr(), c"abY".as_ptr(), 2));
+
+        let mut text = [0 as c_char; 32];
+        strcpy(text.as_mut_ptr(), c"devos".as_ptr());
+        strcat(text.as_mut_ptr(), c" is".as_ptr());
+        strcat(text.as_mut_ptr(), c" fun".as_ptr());
+        assert_eq!(0, strcmp(text.as_ptr(), c"devos is fun".as_ptr()));
+        assert_eq!(
+            text.as_mut_ptr().add(1),
+            strchr(text.as_ptr(), 'e' as c_int)
+        );
+        assert_eq!(
+            text.as_mut_ptr().add(3),
+            strrchr(text.as_ptr(), 'o' as c_int)
+        );
+        assert_eq!(text.as_mut_ptr().add(12), strchr(text.as_ptr(), 0));
+        assert_eq!(
+            text.as_mut_ptr().add(6),
+            strstr(text.as_ptr(), c"is".as_ptr())
+        );
+        assert!(strstr(text.as_ptr(), c"isn't".as_ptr()).is_null());
+
+        let mut padded = [0x55 as c_char; 8];
+        strncpy(padded.as_mut_ptr(), c"ab".as_ptr(), 6);
+        assert_eq!(
+            [b'a', b'b', 0, 0, 0, 0, 0x55, 0x55],
+            padded.map(|c| c as u8)
+        );
+
+        let delim = c" ".as_ptr();
+        let mut save = null_mut();
+        let first = strtok_r(text.as_mut_ptr(), delim, &mut save);
+        let second = strtok_r(null_mut(), delim, &mut save);
+        let third = strtok_r(null_mut(), delim, &mut save);
+        assert_eq!(0, strcmp(first, c"devos".as_ptr()));
+        assert_eq!(0, strcmp(second, c"is".as_ptr()));
+        assert_eq!(0, strcmp(third, c"fun".as_ptr()));
+        assert!(strtok_r(null_mut(), delim, &mut save).is_null());
+    }
+}
+
+/// Compares the results byte for byte with obviously correct implementations,
+/// for random data at random offsets.
+unsafe fn against_naive() {
+    let mut state = 0x2545_F491;
+    for round in 0..2000 {
+        let a = Buffer::random(&mut state);
+        let mut b = a;
+        let src = state as usize % 16;
+        let dst = state as usize / 16 % 16;
+        let len = state as usize / 256 % 64;
+        if round % 2 == 0 {
+            b.0[src + len / 2] ^= (state >> 24) as u8 | 1;
+        }
+
+        unsafe {
+            let mut actual = b;
+            let mut expected = b;
+            memcpy(actual.at(dst + 32), a.0[src..].as_ptr().cast(), len);
+            expected.0[dst + 32..dst + 32 + len].copy_from_slice(&a.0[src..src + len]);
+            assert_eq!(expected.0, actual.0, "memcpy in round {}", round);
+
+            let mut actual = a;
+            let mut expected = a;
+            let ptr = actual.at(0).cast::<u8>();
+            memmove(ptr.add(dst).cast(), ptr.add(src).cast(), len);
+            for i in 0..len {
+                expected.0[dst + i] = a.0[src + i];
+            }
+            assert_eq!(expected.0, actual.0, "memmove in round {}", round);
+
+            let mut actual = a;
+            let mut expected = a;
+            memset(actual.at(dst), round, len);
+            expected.0[dst..dst + len].fill(round as u8);
+            assert_eq!(expected.0, actual.0, "memset in round {}", round);
+
+            let mut a = a;
+            let expected = a.0[src..src + len]
+                .iter()
+                .zip(&b.0[src..src + len])
+                .find(|(x, y)| x != y)
+                .map_or(0, |(&x, &y)| c_int::from(x) - c_int::from(y));
+            assert_eq!(
+                expected,
+                memcmp(a.at(src), b.at(src), len),
+                "memcmp in round {}",
+                round
+            );
+
+            a.0[src + len] = 0;
+            assert_eq!(len, strlen(a.at(src).cast()), "strlen in round {}", round);
+        }
+    }
+}
@@ -0,0 +1,49 @@
+use core::ptr::null_mut;
+
+use libmuffin::time::{
+    clock, clock_gettime, gettimeofday, gmtime_r, localtime, mktime, strftime, time, timespec,
+    timeval, tm, CLOCK_MONOTONIC,
+};
+
+/// QEMU starts the real time clock at the time of the host, which is after
+/// this was written.
+const WRITTEN_AT: i64 = 1_700_000_000;
+
+pub fn check() {
+    unsafe {
+        let mut stored = 0;
+        let now = time(&mut stored);
+        assert!(now > WRITTEN_AT, "the time is {now}");
+        assert_eq!(now, stored);
+
+        let mut tv = timeval::default();
+        assert_eq!(0, gettimeofday(&mut tv, null_mut()));
+        assert!(tv.tv_sec >= now);
+        assert!((0..1_000_000).contains(&tv.tv_usec));
+
+        let mut first = timespec::default();
+        let mut second = timespec::default();
+        assert_eq!(0, clock_gettime(CLOCK_MONOTONIC, &mut first));
+        let start = clock();
+        assert_eq!(0, clock_gettime(CLOCK_MONOTONIC, &mut second));
+        assert!((first.tv_sec, first.tv_nsec) <= (second.tv_sec, second.tv_nsec));
+        assert!(clock() >= start);
+        assert_eq!(-1, clock_gettime(7, &mut first));
+
+        // breaking the time down and putting it back together is lossless
+        let mut broken_down = core::mem::zeroed::<tm>();
+        assert!(!gmtime_r(&now, &mut broken_down).is_null());
+        assert_eq!(now, mktime(&mut broken_down));
+        assert_eq!(broken_down, *localtime(&now));
+
+        let epoch = 0;
+        let mut buf = [0_u8; 32];
+        let len = strftime(
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+            c"%Y-%m-%d %H:%M:%S %a".as_ptr(),
+            localtime(&epoch),
+        );
+        assert_eq!(b"1970-01-01 00:00:00 Thu", &buf[..len]);
+    }
+}
@@ -0,0 +1,55 @@
+#![no_std]
+#![no_main]
+
+use core::ffi::c_void;
+use core::ptr::{null, null_mut};
+
+use libmuffin::pthread::{pthread_create, pthread_join};
+use std::io::{stdout, Write};
+use std::syscall::{sys_exit, sys_traceme};
+use std::{println, rt};
+
+/// The lines that each thread prints.
+const LINES: usize = 100;
+/// The lines that each thread prints while holding the lock.
+const BATCH: usize = 10;
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+/// Prints numbered lines from two threads at the same time, with syscall
+/// tracing enabled. The host side of the test checks that no line is torn,
+/// and counts the writes.
+fn main() {
+    sys_traceme(true).unwrap();
+
+    let mut thread = 0;
+    assert_eq!(0, unsafe {
+        pthread_create(&mut thread, null(), print_from_thread, null_mut())
+    });
+    print_lines("main");
+    assert_eq!(0, unsafe { pthread_join(thread, null_mut()) });
+
+    println!("print_check: ok");
+    sys_traceme(false).unwrap();
+}
+
+extern "C" fn print_from_thread(_: *mut c_void) -> *mut c_void {
+    print_lines("thread");
+    null_mut()
+}
+
+fn print_lines(name: &str) {
+    for batch in 0..LINES / BATCH {
+        let mut out = stdout().lock();
+        for line in batch * BATCH..(batch + 1) * BATCH {
+            writeln!(out, "print_check: {name} line {line}").unwrap();
+        }
+    }
+}
@@ -0,0 +1,114 @@
+#![no_std]
+#![no_main]
+
+use core::str::from_utf8;
+
+use kernel_api::syscall::OpenFlags;
+use std::syscall::{sys_close, sys_exit, sys_open, sys_read, sys_readlink, Errno};
+use std::{println, rt};
+
+/// The address of the heap that [`rt::start`] maps.
+const HEAP_MAPPING: &str = "0000333300000000-0000333300002000";
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn must(result: Result<usize, Errno>) -> usize {
+    match result {
+        Ok(value) => value,
+        Err(errno) => {
+            println!("proc_check: {errno}");
+            sys_exit(errno.code() as isize)
+        }
+    }
+}
+
+/// Reads the whole file into `buf`. The heap is tiny, so the buffers live on
+/// the stack.
+fn read_file<'a>(path: &str, buf: &'a mut [u8]) -> &'a str {
+    let fd = must(sys_open(path, OpenFlags::O_RDONLY.bits() as usize, 0));
+    let mut len = 0;
+    loop {
+        let n = must(sys_read(fd, &mut buf[len..]));
+        if n == 0 {
+            break;
+        }
+        len += n;
+        assert!(len < buf.len(), "{path} doesn't fit into the buffer");
+    }
+    must(sys_close(fd));
+    from_utf8(&buf[..len]).expect("procfs files must be valid utf-8")
+}
+
+/// Returns the value of a `Name:\tvalue` line.
+fn field<'a>(content: &'a str, name: &str) -> Option<&'a str> {
+    content
+        .lines()
+        .find_map(|line| line.strip_prefix(name)?.strip_prefix(":\t"))
+}
+
+fn kib(meminfo: &str, name: &str) -> usize {
+    field(meminfo, name)
+        .and_then(|value| value.strip_suffix(" kB"))
+        .and_then(|value| value.parse().ok())
+        .unwrap_or_else(|| panic!("{name} is missing in /proc/meminfo"))
+}
+
+fn main() {
+    let mut link = [0_u8; 32];
+    let len = must(sys_readlink("/proc/self", &mut link));
+    let pid = from_utf8(&link[..len]).unwrap();
+
+    let mut buf = [0_u8; 1024];
+    let status = read_file("/proc/self/status", &mut buf);
+    assert_eq!(Some(pid), field(status, "Pid"), "status:\n{status}");
+    assert_eq!(Some("/bin/proc_check"), field(status, "Name"));
+    assert_eq!(Some("R (running)"), field(status, "State"));
+    assert_eq!(Some("1"), field(status, "Threads"));
+    let ppid = field(status, "PPid").expect("status has no parent pid");
+    assert!(ppid.parse::<u64>().is_ok() && ppid != pid);
+
+    let mut buf = [0_u8; 2048];
+    let maps = read_file("/proc/self/maps", &mut buf);
+    for line in maps.lines() {
+        let (range, _) = line.split_once(' ').unwrap();
+        let (start, end) = range.split_once('-').unwrap();
+        let start = u64::from_str_radix(start, 16).unwrap();
+        let end = u64::from_str_radix(end, 16).unwrap();
+        assert!(start < end, "invalid range in maps: {line}");
+    }
+    assert!(
+        maps.lines().any(|line| line.starts_with(HEAP_MAPPING)),
+        "the heap is missing in maps:\n{maps}"
+    );
+    assert!(
+        maps.contains("executable '/bin/proc_check'"),
+        "the executable is missing in maps:\n{maps}"
+    );
+
+    let mut buf = [0_u8; 256];
+    let meminfo = read_file("/proc/meminfo", &mut buf);
+    let total = kib(meminfo, "MemTotal");
+    let free = kib(meminfo, "MemFree");
+    assert!(
+        total > 0 && free > 0 && free <= total,
+        "meminfo:\n{meminfo}"
+    );
+
+    let mut buf = [0_u8; 512];
+    let mounts = read_file("/proc/mounts", &mut buf);
+    assert!(
+        mounts
+            .lines()
+            .any(|line| line == "procfs /proc procfs rw 0 0"),
+        "mounts:\n{mounts}"
+    );
+
+    println!("proc_check: ok");
+}
@@ -0,0 +1,67 @@
+//! The arguments and the environment of the program.
+//!
+//! Both are the ones that the kernel put on the initial stack, see
+//! [`crate::rt`]. They are C strings, which are only handed out as `str` if
+//! they are UTF-8. [`args`] and [`vars`] panic on strings that aren't, like
+//! they do in the Rust standard library, and [`var`] reports them as
+//! [`VarError::NotUnicode`]. The C strings themselves are available through
+//! [`crate::rt::args`] and [`crate::rt::env`].
+
+use core::ffi::CStr;
+use core::fmt::{self, Display, Formatter};
+
+use crate::rt;
+
+/// Why [`var`] didn't return a value.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VarError {
+    NotPresent,
+    /// The variable is set, but its value isn't UTF-8.
+    NotUnicode(&'static CStr),
+}
+
+impl Display for VarError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotPresent => f.write_str("environment variable not found"),
+            Self::NotUnicode(_) => f.write_str("environment variable was not valid unicode"),
+        }
+    }
+}
+
+/// The arguments of the program, starting with the path of its executable.
+///
+/// # Panics
+/// When an argument isn't UTF-8.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    rt::args().map(|arg| {
+        arg.to_str()
+            .unwrap_or_else(|_| panic!("argument {arg:?} is not valid unicode"))
+    })
+}
+
+/// The environment variables of the program as name, value pairs. Entries
+/// without a `=` are skipped.
+///
+/// # Panics
+/// When an entry isn't UTF-8.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    rt::env().filter_map(|entry| {
+        entry
+            .to_str()
+            .unwrap_or_else(|_| panic!("environment variable {entry:?} is not valid unicode"))
+            .split_once('=')
+    })
+}
+
+/// The value of the environment variable `name`.
+pub fn var(name: &str) -> Result<&'static str, VarError> {
+    let value = rt::env()
+        .find_map(|entry| {
+            let value = entry.to_bytes_with_nul().strip_prefix(name.as_bytes())?;
+            let value = value.strip_prefix(b"=")?;
+            Some(CStr::from_bytes_with_nul(value).unwrap())
+        })
+        .ok_or(VarError::NotPresent)?;
+    value.to_str().map_err(|_| VarError::NotUnicode(value))
+}
@@ -0,0 +1,434 @@
+//! Files and directories.
+//!
+//! A [`File`] owns its file descriptor and closes it when it's dropped.
+//! Errors of closing are ignored then, like they are by everyone else.
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use kernel_api::syscall::{
+    Dirent, OpenFlags, DT_BLK, DT_CHR, DT_DIR, DT_FIFO, DT_LNK, DT_REG, DT_SOCK,
+};
+
+use crate::io::{self, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use crate::path::{Path, PathBuf};
+use crate::syscall::{
+    sys_close, sys_fstat, sys_ftruncate, sys_getdents, sys_lseek, sys_mkdir, sys_open, sys_read,
+    sys_rename, sys_rmdir, sys_stat, sys_unlink, sys_write, FileMode, Stat, Timespec, Whence,
+};
+
+/// The permissions of files that are created, before the umask.
+const CREATE_MODE: usize = 0o666;
+/// The permissions of directories that are created, before the umask.
+const CREATE_DIR_MODE: usize = 0o777;
+/// How much [`ReadDir`] asks `getdents` for at once.
+const DIRENT_BUF_SIZE: usize = 512;
+
+/// An open file.
+#[derive(Debug)]
+pub struct File {
+    fd: usize,
+}
+
+impl File {
+    /// Opens the file at `path` for reading.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        OpenOptions::new().read(true).open(path)
+    }
+
+    /// Opens the file at `path` for writing, and creates it if it doesn't
+    /// exist or truncates it if it does.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    }
+
+    pub fn options() -> OpenOptions {
+        OpenOptions::new()
+    }
+
+    /// The file descriptor, which stays owned by this file.
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        let mut stat = Stat::default();
+        sys_fstat(self.fd, &mut stat)?;
+        Ok(Metadata(stat))
+    }
+
+    /// Truncates or extends the file to `len` bytes.
+    pub fn set_len(&self, len: u64) -> io::Result<()> {
+        sys_ftruncate(self.fd, len as usize)?;
+        Ok(())
+    }
+}
+
+impl Drop for File {
+    fn drop(&mut self) {
+        let _ = sys_close(self.fd);
+    }
+}
+
+impl Read for File {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Ok(sys_read(self.fd, buf)?)
+    }
+}
+
+impl Write for File {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(sys_write(self.fd, buf)?)
+    }
+
+    /// Files aren't buffered in userspace, so there is nothing to flush.
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for File {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let (offset, whence) = match pos {
+            SeekFrom::Start(offset) => (offset as isize, Whence::SeekSet),
+            SeekFrom::End(offset) => (offset as isize, Whence::SeekEnd),
+            SeekFrom::Current(offset) => (offset as isize, Whence::SeekCur),
+        };
+        Ok(sys_lseek(self.fd, offset, whence)? as u64)
+    }
+}
+
+/// How to open a file, with the flags of `open`.
+#[derive(Debug, Default, Clone)]
+pub struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+}
+
+impl OpenOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    pub fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    /// Writes at the end of the file. Implies [`OpenOptions::write`].
+    pub fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    pub fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    pub fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    /// Creates the file, and fails if it already exists.
+    pub fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    fn flags(&self) -> io::Result<OpenFlags> {
+        let write = self.write || self.append;
+        let mut flags = match (self.read, write) {
+            (true, false) => OpenFlags::O_RDONLY,
+            (false, true) => OpenFlags::O_WRONLY,
+            (true, true) => OpenFlags::O_RDWR,
+            (false, false) => return Err(ErrorKind::InvalidInput.into()),
+        };
+        if (self.truncate || self.create || self.create_new) && !write {
+            return Err(ErrorKind::InvalidInput.into());
+        }
+        flags.set(OpenFlags::O_APPEND, self.append);
+        flags.set(OpenFlags::O_TRUNC, self.truncate);
+        flags.set(OpenFlags::O_CREAT, self.create || self.create_new);
+        flags.set(OpenFlags::O_EXCL, self.create_new);
+        Ok(flags)
+    }
+
+    pub fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<File> {
+        let flags = self.flags()?;
+        let mode = if flags.contains(OpenFlags::O_CREAT) {
+            CREATE_MODE
+        } else {
+            0
+        };
+        let fd = sys_open(path.as_ref().as_str(), flags.bits() as usize, mode)?;
+        Ok(File { fd })
+    }
+}
+
+/// The type of a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+    CharDevice,
+    BlockDevice,
+    Fifo,
+    Socket,
+    /// The file system didn't say.
+    Unknown,
+}
+
+impl FileType {
+    fn from_mode(mode: FileMode) -> Self {
+        match mode & FileMode::S_IFMT {
+            FileMode::S_IFREG => Self::File,
+            FileMode::S_IFDIR => Self::Dir,
+            FileMode::S_IFLNK => Self::Symlink,
+            FileMode::S_IFCHR => Self::CharDevice,
+            FileMode::S_IFBLK => Self::BlockDevice,
+            FileMode::S_IFIFO => Self::Fifo,
+            FileMode::S_IFSOCK => Self::Socket,
+            _ => Self::Unknown,
+        }
+    }
+
+    fn from_dirent(typ: u8) -> Self {
+        match typ {
+            DT_REG => Self::File,
+            DT_DIR => Self::Dir,
+            DT_LNK => Self::Symlink,
+            DT_CHR => Self::CharDevice,
+            DT_BLK => Self::BlockDevice,
+            DT_FIFO => Self::Fifo,
+            DT_SOCK => Self::Socket,
+            _ => Self::Unknown,
+        }
+    }
+
+    pub fn is_file(self) -> bool {
+        self == Self::File
+    }
+
+    pub fn is_dir(self) -> bool {
+        self == Self::Dir
+    }
+
+    pub fn is_symlink(self) -> bool {
+        self == Self::Symlink
+    }
+}
+
+/// What `stat` says about a file.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Metadata(Stat);
+
+impl Metadata {
+    pub fn file_type(&self) -> FileType {
+        FileType::from_mode(self.0.mode)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.file_type().is_file()
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type().is_dir()
+    }
+
+    pub fn len(&self) -> u64 {
+        self.0.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The permission bits, without the file type.
+    pub fn permissions(&self) -> FileMode {
+        self.0.mode - FileMode::S_IFMT
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.0.ino
+    }
+
+    pub fn nlink(&self) -> u32 {
+        self.0.nlink
+    }
+
+    pub fn modified(&self) -> Timespec {
+        self.0.mtime
+    }
+
+    pub fn accessed(&self) -> Timespec {
+        self.0.atime
+    }
+
+    /// Everything, the way the kernel reported it.
+    pub fn stat(&self) -> &Stat {
+        &self.0
+    }
+}
+
+/// The entries of a directory, without `.` and `..`.
+#[derive(Debug)]
+pub struct ReadDir {
+    dir: File,
+    path: PathBuf,
+    buf: Vec<u8>,
+    /// Where the next entry in `buf` starts.
+    pos: usize,
+    /// How much of `buf` the last `getdents` filled.
+    len: usize,
+}
+
+impl ReadDir {
+    /// Parses the next entry from the buffer, and refills it when it's used
+    /// up. Returns [`None`] at the end of the directory.
+    fn next_entry(&mut self) -> io::Result<Option<DirEntry>> {
+        loop {
+            if self.pos == self.len {
+                self.len = sys_getdents(self.dir.fd, &mut self.buf)?;
+                self.pos = 0;
+                if self.len == 0 {
+                    return Ok(None);
+                }
+            }
+            let (dirent, name) = Dirent::read(&self.buf[self.pos..self.len])
+                .ok_or(Error::new(ErrorKind::InvalidData))?;
+            self.pos += usize::from(dirent.reclen);
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let name =
+                core::str::from_utf8(name).map_err(|_| Error::new(ErrorKind::InvalidData))?;
+            return Ok(Some(DirEntry {
+                path: self.path.join(name),
+                ino: dirent.ino,
+                file_type: FileType::from_dirent(dirent.typ),
+            }));
+        }
+    }
+}
+
+impl Iterator for ReadDir {
+    type Item = io::Result<DirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entry().transpose()
+    }
+}
+
+/// An entry of a directory, as returned by [`read_dir`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct DirEntry {
+    path: PathBuf,
+    ino: u64,
+    file_type: FileType,
+}
+
+impl DirEntry {
+    /// The path of the directory that was read, joined with the name of the
+    /// entry.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn file_name(&self) -> &str {
+        self.path.file_name().unwrap_or_default()
+    }
+
+    pub fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// The type from the directory entry, which saves a `stat`, but may be
+    /// [`FileType::Unknown`].
+    pub fn file_type(&self) -> FileType {
+        self.file_type
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        metadata(&self.path)
+    }
+}
+
+/// The entries of the directory at `path`.
+pub fn read_dir<P: AsRef<Path>>(path: P) -> io::Result<ReadDir> {
+    let path = path.as_ref();
+    let flags = OpenFlags::O_RDONLY | OpenFlags::O_DIRECTORY;
+    let fd = sys_open(path.as_str(), flags.bits() as usize, 0)?;
+    Ok(ReadDir {
+        dir: File { fd },
+        path: path.to_path_buf(),
+        buf: vec![0; DIRENT_BUF_SIZE],
+        pos: 0,
+        len: 0,
+    })
+}
+
+/// The whole contents of the file at `path`.
+pub fn read<P: AsRef<Path>>(path: P) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    File::open(path)?.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// The whole contents of the file at `path`, which must be UTF-8.
+pub fn read_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let mut s = String::new();
+    File::open(path)?.read_to_string(&mut s)?;
+    Ok(s)
+}
+
+/// Replaces the contents of the file at `path`, which is created if it
+/// doesn't exist.
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    File::create(path)?.write_all(contents.as_ref())
+}
+
+/// What `stat` says about the file at `path`, following symbolic links.
+pub fn metadata<P: AsRef<Path>>(path: P) -> io::Result<Metadata> {
+    let mut stat = Stat::default();
+    sys_stat(path.as_ref().as_str(), &mut stat)?;
+    Ok(Metadata(stat))
+}
+
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    sys_unlink(path.as_ref().as_str())?;
+    Ok(())
+}
+
+pub fn create_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    sys_mkdir(path.as_ref().as_str(), CREATE_DIR_MODE)?;
+    Ok(())
+}
+
+/// Removes the directory at `path`, which must be empty.
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    sys_rmdir(path.as_ref().as_str())?;
+    Ok(())
+}
+
+/// Moves `from` to `to`, replacing `to` if it exists.
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    sys_rename(from.as_ref().as_str(), to.as_ref().as_str())?;
+    Ok(())
+}
@@ -0,0 +1,49 @@
+//! The program break, on top of `mmap`.
+//!
+//! The kernel has no `brk` syscall, so the break is kept here. It starts at
+//! [`BASE`], and when it moves past what is mapped, the pages in between are
+//! mapped at a fixed address, at least [`STEP`] bytes at a time. Moving the
+//! break back doesn't unmap anything, the pages stay mapped for when it grows
+//! again.
+
+use crate::heap::map;
+use crate::lock::Mutex;
+use crate::syscall::Errno;
+
+/// Where the break starts.
+const BASE: usize = 0x3333_0000_0000;
+/// How far the break can move from [`BASE`].
+const MAX_LEN: usize = 1 << 30;
+/// The least that is mapped when the break grows past what is mapped.
+const STEP: usize = 64 * 1024;
+
+static BREAK: Mutex<Break> = Mutex::new(Break {
+    current: BASE,
+    mapped: BASE,
+});
+
+struct Break {
+    current: usize,
+    /// The end of what is mapped, which is at or after `current`.
+    mapped: usize,
+}
+
+/// Moves the break by `increment` bytes, and returns where it was before.
+/// The memory in between is readable and writable.
+pub fn sbrk(increment: isize) -> Result<*mut u8, Errno> {
+    let mut brk = BREAK.lock();
+    let old = brk.current;
+    let new = old
+        .checked_add_signed(increment)
+        .filter(|new| (BASE..=BASE + MAX_LEN).contains(new))
+        .ok_or(Errno::ENOMEM)?;
+    if new > brk.mapped {
+        let len = (new - brk.mapped).next_multiple_of(STEP);
+        if map(brk.mapped, len)? != brk.mapped {
+            return Err(Errno::ENOMEM);
+        }
+        brk.mapped += len;
+    }
+    brk.current = new;
+    Ok(old as *mut u8)
+}
@@ -0,0 +1,258 @@
+//! The global allocator.
+//!
+//! Allocations of up to [`MAX_SMALL`] bytes are rounded up to a power of two,
+//! which is their size class. Every class has a list of its free blocks, and
+//! when that is empty, it carves new blocks from memory that it gets from
+//! [`sbrk`]. Blocks never change their class, so they are never merged, and
+//! the memory is never given back.
+//!
+//! Larger allocations are mapped on their own, and unmapped when they are
+//! freed.
+//!
+//! Blocks are aligned to [`MIN_ALIGN`]. For larger alignments, the block is
+//! `align` bytes larger than asked for, and the pointer that is handed out is
+//! the first aligned one in it that leaves room for a pointer back to the
+//! start of the block, right in front of it.
+//!
+//! There are no headers. `dealloc` gets the layout that the memory was
+//! allocated with, which says everything there is to know: the size class or
+//! the length of the mapping, and whether there is a back pointer.
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::size_of;
+use core::ptr::{self, null_mut, NonNull};
+
+pub use brk::sbrk;
+
+use crate::lock::{Mutex, MutexGuard};
+use crate::rt;
+use crate::syscall::{
+    sys_mmap, sys_munmap, Errno, MAP_ANONYMOUS, MAP_PRIVATE, PROT_READ, PROT_WRITE,
+};
+
+mod brk;
+
+/// The alignment of every block.
+pub const MIN_ALIGN: usize = 16;
+
+/// The largest block that is served from a size class.
+pub const MAX_SMALL: usize = 128 * 1024;
+
+/// The number of size classes, one for every power of two from [`MIN_ALIGN`]
+/// to [`MAX_SMALL`].
+const CLASSES: usize = (MAX_SMALL / MIN_ALIGN).ilog2() as usize + 1;
+
+/// The least that a size class takes from [`sbrk`] at once.
+const SLAB_SIZE: usize = 64 * 1024;
+
+const PAGE_SIZE: usize = 4096;
+
+#[global_allocator]
+static HEAP: Heap = Heap {
+    classes: Mutex::new(Classes {
+        free: [None; CLASSES],
+    }),
+};
+
+struct Heap {
+    classes: Mutex<Classes>,
+}
+
+/// The free lists of the size classes.
+struct Classes {
+    free: [Option<NonNull<Free>>; CLASSES],
+}
+
+// the free blocks belong to the heap, not to a thread
+unsafe impl Send for Classes {}
+
+/// A free block, which points to the next free block of its class.
+struct Free {
+    next: Option<NonNull<Free>>,
+}
+
+/// Where the memory of an allocation comes from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Block {
+    /// A block of the size class with the given index.
+    Small(usize),
+    /// A mapping of the given length.
+    Large(usize),
+}
+
+impl Block {
+    /// The block for `layout`, or [`None`] if it would be larger than the
+    /// address space.
+    fn of(layout: Layout) -> Option<Self> {
+        let size = if layout.align() > MIN_ALIGN {
+            layout.size().checked_add(layout.align())?
+        } else {
+            layout.size()
+        };
+        Some(if size <= MAX_SMALL {
+            let size = size.max(MIN_ALIGN).next_power_of_two();
+            Self::Small((size / MIN_ALIGN).ilog2() as usize)
+        } else {
+            Self::Large(size.checked_next_multiple_of(PAGE_SIZE)?)
+        })
+    }
+}
+
+impl Classes {
+    fn pop(&mut self, class: usize) -> Option<NonNull<u8>> {
+        if self.free[class].is_none() {
+            self.refill(class)?;
+        }
+        let block = self.free[class]?;
+        self.free[class] = unsafe { block.as_ref().next };
+        Some(block.cast())
+    }
+
+    /// # Safety
+    /// `block` must be a block of `class` that isn't in use.
+    unsafe fn push(&mut self, class: usize, block: NonNull<u8>) {
+        let block = block.cast::<Free>();
+        block.write(Free {
+            next: self.free[class],
+        });
+        self.free[class] = Some(block);
+    }
+
+    /// Carves new blocks for `class` from memory that it gets from [`sbrk`].
+    fn refill(&mut self, class: usize) -> Option<()> {
+        let size = MIN_ALIGN << class;
+        let len = size.max(SLAB_SIZE);
+        // someone else may have left the break unaligned
+        let slab = sbrk((len + MIN_ALIGN) as isize).ok()?;
+        let start = unsafe { slab.add(slab.align_offset(MIN_ALIGN)) };
+        // the other way around, so that the list starts with the first block
+        for i in (0..len / size).rev() {
+            unsafe { self.push(class, NonNull::new_unchecked(start.add(i * size))) };
+        }
+        Some(())
+    }
+}
+
+impl Heap {
+    /// Locks the size classes. A thread that panics doesn't wait for the
+    /// lock, since it may be the one holding it, so that allocating while
+    /// panicking fails instead of hanging.
+    fn lock(&self) -> Option<MutexGuard<'_, Classes>> {
+        if rt::panicking() {
+            return self.classes.try_lock();
+        }
+        Some(self.classes.lock())
+    }
+
+    fn alloc_block(&self, block: Block) -> *mut u8 {
+        match block {
+            Block::Small(class) => self
+                .lock()
+                .and_then(|mut classes| classes.pop(class))
+                .map_or(null_mut(), NonNull::as_ptr),
+            Block::Large(len) => map(0, len).map_or(null_mut(), |addr| addr as *mut u8),
+        }
+    }
+
+    /// Frees a block. A small block is leaked if the lock is held while
+    /// panicking.
+    ///
+    /// # Safety
+    /// `start` must be the start of a block that [`Heap::alloc_block`]
+    /// returned for `block`.
+    unsafe fn dealloc_block(&self, start: *mut u8, block: Block) {
+        match block {
+            Block::Small(class) => {
+                if let Some(mut classes) = self.lock() {
+                    classes.push(class, NonNull::new_unchecked(start));
+                }
+            }
+            Block::Large(len) => {
+                let _ = sys_munmap(start as usize, len);
+            }
+        }
+    }
+
+    /// Grows the mapping of a large block by mapping the pages right after
+    /// it. Returns `false` if they are already in use.
+    ///
+    /// # Safety
+    /// `start` must be the start of a large block of `len` bytes.
+    unsafe fn grow_in_place(&self, start: *mut u8, len: usize, new_len: usize) -> bool {
+        let end = start as usize + len;
+        map(end, new_len - len) == Ok(end)
+    }
+}
+
+/// Maps `len` bytes of memory for reading and writing, at `addr` unless that
+/// is 0.
+pub(crate) fn map(addr: usize, len: usize) -> Result<usize, Errno> {
+    sys_mmap(
+        addr,
+        len,
+        PROT_READ | PROT_WRITE,
+        MAP_PRIVATE | MAP_ANONYMOUS,
+        0,
+        0,
+    )
+}
+
+/// The start of the block of an allocation.
+///
+/// # Safety
+/// `ptr` must have been returned by [`Heap::alloc`] for `layout`.
+unsafe fn block_start(ptr: *mut u8, layout: Layout) -> *mut u8 {
+    if layout.align() > MIN_ALIGN {
+        ptr.cast::<*mut u8>().sub(1).read()
+    } else {
+        ptr
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let Some(block) = Block::of(layout) else {
+            return null_mut();
+        };
+        let start = self.alloc_block(block);
+        if start.is_null() || layout.align() <= MIN_ALIGN {
+            return start;
+        }
+
+        // the block is `align` bytes larger, which is enough to get from a
+        // multiple of MIN_ALIGN to a multiple of `align`, leaving room for
+        // the back pointer
+        let ptr = start.add(size_of::<*mut u8>());
+        let ptr = ptr.add(ptr.align_offset(layout.align()));
+        ptr.cast::<*mut u8>().sub(1).write(start);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // allocating would have failed if there was no block for the layout
+        let block = Block::of(layout).unwrap();
+        self.dealloc_block(block_start(ptr, layout), block);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+        // the pointer stays where it is if the layouts end up in the same
+        // block, or if the mapping of a large block can grow
+        match (Block::of(layout), Block::of(new_layout)) {
+            (Some(old), Some(new)) if old == new => return ptr,
+            (Some(Block::Large(len)), Some(Block::Large(new_len)))
+                if new_len > len && self.grow_in_place(block_start(ptr, layout), len, new_len) =>
+            {
+                return ptr;
+            }
+            _ => {}
+        }
+
+        let new_ptr = self.alloc(new_layout);
+        if !new_ptr.is_null() {
+            ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+            self.dealloc(ptr, layout);
+        }
+        new_ptr
+    }
+}
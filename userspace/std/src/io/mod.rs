@@ -0,0 +1,288 @@
+//! Reading and writing, and the errors that come with it.
+//!
+//! [`Read`], [`Write`] and [`Seek`] are implemented by everything that is
+//! backed by a file descriptor, like [`crate::fs::File`], and by [`stdout`]
+//! and [`stderr`].
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter};
+
+pub use self::stdio::{stderr, stdout, try_flush_stdout, Stderr, Stdout, StdoutLock};
+use crate::syscall::{sys_read, Errno};
+
+mod stdio;
+
+/// The file descriptor of the standard input.
+pub const STDIN: usize = 0;
+/// The file descriptor of the standard output.
+pub const STDOUT: usize = 1;
+/// The file descriptor of the standard error.
+pub const STDERR: usize = 2;
+
+/// How much [`Read::read_to_end`] reads at least at once.
+const MIN_READ: usize = 32;
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// The category of an [`Error`], for deciding how to react to it without
+/// looking at the exact error code.
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ErrorKind {
+    NotFound,
+    PermissionDenied,
+    AlreadyExists,
+    WouldBlock,
+    NotADirectory,
+    IsADirectory,
+    DirectoryNotEmpty,
+    InvalidInput,
+    InvalidData,
+    Interrupted,
+    BrokenPipe,
+    Unsupported,
+    OutOfMemory,
+    /// The end of the file was reached before a buffer could be filled.
+    UnexpectedEof,
+    /// Writing returned that it wrote nothing, which would never end.
+    WriteZero,
+    Other,
+}
+
+impl ErrorKind {
+    fn of(errno: Errno) -> Self {
+        match errno {
+            Errno::ENOENT => Self::NotFound,
+            Errno::EPERM | Errno::EACCES => Self::PermissionDenied,
+            Errno::EEXIST => Self::AlreadyExists,
+            Errno::EWOULDBLOCK => Self::WouldBlock,
+            Errno::ENOTDIR => Self::NotADirectory,
+            Errno::EISDIR => Self::IsADirectory,
+            Errno::ENOTEMPTY => Self::DirectoryNotEmpty,
+            Errno::EINVAL | Errno::ENAMETOOLONG => Self::InvalidInput,
+            Errno::EINTR => Self::Interrupted,
+            Errno::EPIPE => Self::BrokenPipe,
+            Errno::ENOSYS => Self::Unsupported,
+            Errno::ENOMEM => Self::OutOfMemory,
+            _ => Self::Other,
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Self::NotFound => "entity not found",
+            Self::PermissionDenied => "permission denied",
+            Self::AlreadyExists => "entity already exists",
+            Self::WouldBlock => "operation would block",
+            Self::NotADirectory => "not a directory",
+            Self::IsADirectory => "is a directory",
+            Self::DirectoryNotEmpty => "directory not empty",
+            Self::InvalidInput => "invalid input parameter",
+            Self::InvalidData => "invalid data",
+            Self::Interrupted => "operation interrupted",
+            Self::BrokenPipe => "broken pipe",
+            Self::Unsupported => "unsupported",
+            Self::OutOfMemory => "out of memory",
+            Self::UnexpectedEof => "unexpected end of file",
+            Self::WriteZero => "write zero",
+            Self::Other => "other error",
+        }
+    }
+}
+
+/// An error of an I/O operation, which is either an error code of a syscall,
+/// or an error that was detected in userspace.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Error {
+    kind: ErrorKind,
+    errno: Option<Errno>,
+}
+
+impl Error {
+    pub const fn new(kind: ErrorKind) -> Self {
+        Self { kind, errno: None }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    /// The error code of the syscall that failed, if this error came from
+    /// one.
+    pub fn errno(&self) -> Option<Errno> {
+        self.errno
+    }
+}
+
+impl From<Errno> for Error {
+    fn from(errno: Errno) -> Self {
+        Self {
+            kind: ErrorKind::of(errno),
+            errno: Some(errno),
+        }
+    }
+}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.errno {
+            Some(errno) => write!(f, "{} ({})", errno.message(), errno.name()),
+            None => f.write_str(self.kind.description()),
+        }
+    }
+}
+
+/// Something that bytes can be read from.
+pub trait Read {
+    /// Reads into `buf`, and returns how many bytes were read, which is zero
+    /// at the end of the file.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize>;
+
+    /// Reads until `buf` is full, and fails with
+    /// [`ErrorKind::UnexpectedEof`] if the end of the file comes first.
+    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.read(buf) {
+                Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+                Ok(n) => buf = &mut buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends everything up to the end of the file to `buf`, and returns how
+    /// many bytes that were.
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        loop {
+            if buf.capacity() - buf.len() < MIN_READ {
+                buf.try_reserve(MIN_READ)
+                    .map_err(|_| Error::new(ErrorKind::OutOfMemory))?;
+            }
+            let len = buf.len();
+            buf.resize(buf.capacity(), 0);
+            let result = self.read(&mut buf[len..]);
+            let read = *result.as_ref().unwrap_or(&0);
+            buf.truncate(len + read);
+            match result {
+                Ok(0) => return Ok(buf.len() - start),
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Like [`Read::read_to_end`], but fails with [`ErrorKind::InvalidData`]
+    /// if what was read isn't UTF-8, in which case `buf` is left unchanged.
+    fn read_to_string(&mut self, buf: &mut String) -> Result<usize> {
+        let mut bytes = Vec::new();
+        let read = self.read_to_end(&mut bytes)?;
+        let s = core::str::from_utf8(&bytes).map_err(|_| Error::new(ErrorKind::InvalidData))?;
+        buf.push_str(s);
+        Ok(read)
+    }
+}
+
+/// Something that bytes can be written to.
+pub trait Write {
+    /// Writes from `buf`, and returns how many bytes were written.
+    fn write(&mut self, buf: &[u8]) -> Result<usize>;
+
+    /// Writes out whatever is buffered.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Writes all of `buf`, no matter how many calls to [`Write::write`]
+    /// that takes.
+    fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            match self.write(buf) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => buf = &buf[n..],
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the formatted `args`, which is what [`write!`] calls.
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        /// Remembers the error of the writer, since [`fmt::Error`] can't
+        /// carry it.
+        struct Adapter<'a, W: ?Sized> {
+            inner: &'a mut W,
+            result: Result<()>,
+        }
+
+        impl<W: Write + ?Sized> fmt::Write for Adapter<'_, W> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                self.result = self.inner.write_all(s.as_bytes());
+                self.result.map_err(|_| fmt::Error)
+            }
+        }
+
+        let mut adapter = Adapter {
+            inner: self,
+            result: Ok(()),
+        };
+        match fmt::write(&mut adapter, args) {
+            Ok(()) => Ok(()),
+            // a formatting trait failed on its own
+            Err(_) => adapter.result.and(Err(ErrorKind::Other.into())),
+        }
+    }
+}
+
+/// Where [`Seek::seek`] moves to.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SeekFrom {
+    Start(u64),
+    End(i64),
+    Current(i64),
+}
+
+/// Something with a position that can be moved.
+pub trait Seek {
+    /// Moves the position, and returns the new position relative to the
+    /// start.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64>;
+
+    fn rewind(&mut self) -> Result<()> {
+        self.seek(SeekFrom::Start(0)).map(|_| ())
+    }
+
+    fn stream_position(&mut self) -> Result<u64> {
+        self.seek(SeekFrom::Current(0))
+    }
+}
+
+/// Reads a line from `fd` into `buf`, like `fgets` does. Reading stops after
+/// a newline, which is kept, when the buffer is full, or at the end of the file.
+///
+/// Returns the number of bytes that were read, which is zero at the end of the
+/// file.
+pub fn read_line(fd: usize, buf: &mut [u8]) -> core::result::Result<usize, Errno> {
+    let mut read = 0;
+    while read < buf.len() {
+        // read byte by byte, so that nothing after the newline is consumed
+        let n = sys_read(fd, &mut buf[read..=read])?;
+        if n == 0 {
+            break;
+        }
+        read += n;
+        if buf[read - 1] == b'\n' {
+            break;
+        }
+    }
+    Ok(read)
+}
@@ -0,0 +1,215 @@
+//! The standard output and error streams.
+//!
+//! Standard output goes through a buffer behind a lock. Holding the lock
+//! keeps other threads from writing in between, and when it's released,
+//! everything up to the last newline is written with a single syscall. A
+//! partial line stays buffered until its newline follows, or until the buffer
+//! is full. So [`crate::println!`] writes one line at a time, and
+//! [`Stdout::lock`] batches as many lines as fit into the buffer.
+//!
+//! Standard error isn't buffered.
+
+use core::fmt;
+
+use crate::io::{ErrorKind, Result, Write, STDERR, STDOUT};
+use crate::lock::{Mutex, MutexGuard};
+use crate::syscall::sys_write;
+
+/// How much standard output buffers.
+const STDOUT_BUF_SIZE: usize = 1024;
+/// How much [`Stderr`] formats at once before writing it.
+const STDERR_CHUNK_SIZE: usize = 256;
+
+static STDOUT_BUF: Mutex<LineBuffer> = Mutex::new(LineBuffer::new());
+
+/// Bytes that are waiting to be written to [`STDOUT`].
+struct LineBuffer {
+    buf: [u8; STDOUT_BUF_SIZE],
+    len: usize,
+}
+
+impl LineBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; STDOUT_BUF_SIZE],
+            len: 0,
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            if self.len == self.buf.len() {
+                self.flush_up_to(self.len)?;
+            }
+            let n = data.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+
+    /// Writes the complete lines, and keeps the partial line at the end.
+    fn flush_lines(&mut self) -> Result<()> {
+        match self.buf[..self.len].iter().rposition(|&b| b == b'\n') {
+            Some(i) => self.flush_up_to(i + 1),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the first `len` bytes and moves the rest to the front. If
+    /// writing fails, whatever wasn't written is dropped, so that a broken
+    /// output can't fill the buffer for good.
+    fn flush_up_to(&mut self, len: usize) -> Result<()> {
+        let result = write_all(STDOUT, &self.buf[..len]);
+        self.buf.copy_within(len..self.len, 0);
+        self.len -= len;
+        result
+    }
+}
+
+fn write_all(fd: usize, mut data: &[u8]) -> Result<()> {
+    while !data.is_empty() {
+        let written = sys_write(fd, data)?;
+        if written == 0 {
+            return Err(ErrorKind::WriteZero.into());
+        }
+        data = &data[written..];
+    }
+    Ok(())
+}
+
+/// A handle to the standard output of the process.
+#[derive(Debug, Copy, Clone)]
+pub struct Stdout {
+    _private: (),
+}
+
+/// The standard output of the process.
+pub fn stdout() -> Stdout {
+    Stdout { _private: () }
+}
+
+impl Stdout {
+    /// Locks standard output until the returned guard is dropped, so that
+    /// other threads can't write in between.
+    pub fn lock(&self) -> StdoutLock<'static> {
+        StdoutLock {
+            buf: STDOUT_BUF.lock(),
+        }
+    }
+}
+
+impl Write for Stdout {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.lock().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.lock().flush()
+    }
+}
+
+/// Standard output, locked for the current thread.
+pub struct StdoutLock<'a> {
+    buf: MutexGuard<'a, LineBuffer>,
+}
+
+impl Write for StdoutLock<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.buf.write(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Writes everything, including a partial line.
+    fn flush(&mut self) -> Result<()> {
+        let len = self.buf.len;
+        self.buf.flush_up_to(len)
+    }
+}
+
+impl Drop for StdoutLock<'_> {
+    fn drop(&mut self) {
+        let _ = self.buf.flush_lines();
+    }
+}
+
+/// Writes what is buffered for standard output, unless another thread holds
+/// the lock, in which case that thread flushes it once it's done. This is
+/// for exiting and panicking, where waiting for the lock could mean waiting
+/// forever.
+pub fn try_flush_stdout() {
+    if let Some(mut buf) = STDOUT_BUF.try_lock() {
+        let len = buf.len;
+        let _ = buf.flush_up_to(len);
+    }
+}
+
+/// A handle to the standard error of the process, which isn't buffered.
+#[derive(Debug, Copy, Clone)]
+pub struct Stderr {
+    _private: (),
+}
+
+/// The standard error of the process.
+pub fn stderr() -> Stderr {
+    Stderr { _private: () }
+}
+
+impl Write for Stderr {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(sys_write(STDERR, buf)?)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Formats into a buffer on the stack, so that short messages are
+    /// written with a single syscall, without allocating.
+    fn write_fmt(&mut self, args: fmt::Arguments<'_>) -> Result<()> {
+        let mut chunk = Chunk {
+            buf: [0; STDERR_CHUNK_SIZE],
+            len: 0,
+            result: Ok(()),
+        };
+        let formatted = fmt::write(&mut chunk, args);
+        chunk.flush()?;
+        chunk.result?;
+        formatted.map_err(|_| ErrorKind::Other.into())
+    }
+}
+
+/// The buffer of [`Stderr::write_fmt`].
+struct Chunk {
+    buf: [u8; STDERR_CHUNK_SIZE],
+    len: usize,
+    result: Result<()>,
+}
+
+impl Chunk {
+    fn flush(&mut self) -> Result<()> {
+        let result = write_all(STDERR, &self.buf[..self.len]);
+        self.len = 0;
+        result
+    }
+}
+
+impl fmt::Write for Chunk {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut data = s.as_bytes();
+        while !data.is_empty() {
+            if self.len == self.buf.len() {
+                self.result = self.flush();
+                if self.result.is_err() {
+                    return Err(fmt::Error);
+                }
+            }
+            let n = data.len().min(self.buf.len() - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            data = &data[n..];
+        }
+        Ok(())
+    }
+}
@@ -1,26 +1,53 @@
 #![no_std]
+#![feature(alloc_error_handler)]
+#![feature(thread_local)]
 
 extern crate alloc;
 
 use crate::syscall::sys_exit;
 
 pub mod arch;
+pub mod env;
+pub mod fs;
+pub mod heap;
+pub mod io;
+mod lock;
+pub mod path;
 pub mod print;
 pub mod rt;
 pub mod syscall;
+pub mod thread;
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic_handler(info: &core::panic::PanicInfo) -> ! {
+    rt::set_panicking();
+    // the panic may have happened while standard output was locked
+    io::try_flush_stdout();
     if let Some(location) = info.location() {
-        println!(
-            "thread '{}' panicked at {}:{}:{}:\n{}",
-            "unknown", // TODO: get the current thread name
-            location.file(),
-            location.line(),
-            location.column(),
-            info.message(),
-        );
+        thread::with_current_name(|name| {
+            eprintln!(
+                "thread '{}' panicked at {}:{}:{}:\n{}",
+                name,
+                location.file(),
+                location.line(),
+                location.column(),
+                info.message(),
+            )
+        });
     }
+    thread::exit_on_panic(info);
+    sys_exit(2)
+}
+
+#[cfg(not(test))]
+#[alloc_error_handler]
+fn alloc_error_handler(layout: core::alloc::Layout) -> ! {
+    io::try_flush_stdout();
+    eprintln!(
+        "memory allocation of {} bytes (aligned to {}) failed",
+        layout.size(),
+        layout.align(),
+    );
     sys_exit(2)
 }
@@ -0,0 +1,27 @@
+//! The lock that the state of the standard library is behind, like the heap
+//! and the buffer of standard output. It sleeps on a futex while another
+//! thread holds it.
+
+use core::sync::atomic::AtomicU32;
+
+use sync::Futex;
+
+use crate::syscall::{sys_futex_wait, sys_futex_wake};
+
+/// The futexes of the kernel.
+pub struct Kernel;
+
+impl Futex for Kernel {
+    fn wait(futex: &AtomicU32, expected: u32) {
+        // callers check the value again anyway, so it doesn't matter whether
+        // it had changed already
+        let _ = sys_futex_wait(futex, expected);
+    }
+
+    fn wake(futex: &AtomicU32, count: usize) {
+        let _ = sys_futex_wake(futex, count);
+    }
+}
+
+pub type Mutex<T> = sync::Mutex<T, Kernel>;
+pub type MutexGuard<'a, T> = sync::MutexGuard<'a, T, Kernel>;
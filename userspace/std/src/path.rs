@@ -0,0 +1,187 @@
+//! Paths, which are UTF-8 strings with `/` as the separator.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use core::borrow::Borrow;
+use core::fmt::{self, Display, Formatter};
+use core::ops::Deref;
+
+pub const SEPARATOR: char = '/';
+
+/// A borrowed path.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct Path(str);
+
+impl Path {
+    pub fn new<S: AsRef<str> + ?Sized>(s: &S) -> &Self {
+        // Path is a transparent wrapper around str
+        unsafe { &*(s.as_ref() as *const str as *const Self) }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_absolute(&self) -> bool {
+        self.0.starts_with(SEPARATOR)
+    }
+
+    /// The path without its last component, or [`None`] if there is nothing
+    /// left then, which is the case for `/` and for single relative
+    /// components.
+    pub fn parent(&self) -> Option<&Path> {
+        let trimmed = self.0.trim_end_matches(SEPARATOR);
+        let (parent, _) = trimmed.rsplit_once(SEPARATOR)?;
+        let parent = parent.trim_end_matches(SEPARATOR);
+        if parent.is_empty() {
+            // the parent is the root, unless this is the root
+            return (!trimmed.is_empty()).then(|| Path::new("/"));
+        }
+        Some(Path::new(parent))
+    }
+
+    /// The last component, or [`None`] if the path ends with `..` or is the
+    /// root.
+    pub fn file_name(&self) -> Option<&str> {
+        let name = self
+            .0
+            .trim_end_matches(SEPARATOR)
+            .rsplit(SEPARATOR)
+            .next()?;
+        (!name.is_empty() && name != "..").then_some(name)
+    }
+
+    /// The components between the separators, without empty ones.
+    pub fn components(&self) -> impl Iterator<Item = &str> {
+        self.0.split(SEPARATOR).filter(|c| !c.is_empty())
+    }
+
+    /// This path with `path` appended, or `path` if it is absolute.
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        let mut buf = self.to_path_buf();
+        buf.push(path);
+        buf
+    }
+
+    pub fn to_path_buf(&self) -> PathBuf {
+        PathBuf(self.0.to_owned())
+    }
+}
+
+impl AsRef<Path> for Path {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for str {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<Path> for String {
+    fn as_ref(&self) -> &Path {
+        Path::new(self)
+    }
+}
+
+impl AsRef<str> for Path {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for Path {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl ToOwned for Path {
+    type Owned = PathBuf;
+
+    fn to_owned(&self) -> PathBuf {
+        self.to_path_buf()
+    }
+}
+
+/// An owned path.
+#[derive(Debug, Default, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct PathBuf(String);
+
+impl PathBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends `path` with a separator in between, or replaces this path
+    /// with `path` if it is absolute.
+    pub fn push<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        if path.is_absolute() {
+            self.0.clear();
+        } else if !self.0.is_empty() && !self.0.ends_with(SEPARATOR) {
+            self.0.push(SEPARATOR);
+        }
+        self.0.push_str(path.as_str());
+    }
+
+    /// Removes the last component. Returns `false` if there is no parent.
+    pub fn pop(&mut self) -> bool {
+        match self.parent().map(|parent| parent.0.len()) {
+            Some(len) => {
+                self.0.truncate(len);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn as_path(&self) -> &Path {
+        self
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl Deref for PathBuf {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        Path::new(&self.0)
+    }
+}
+
+impl Borrow<Path> for PathBuf {
+    fn borrow(&self) -> &Path {
+        self
+    }
+}
+
+impl AsRef<Path> for PathBuf {
+    fn as_ref(&self) -> &Path {
+        self
+    }
+}
+
+impl From<String> for PathBuf {
+    fn from(s: String) -> Self {
+        Self(s)
+    }
+}
+
+impl From<&str> for PathBuf {
+    fn from(s: &str) -> Self {
+        Self(s.into())
+    }
+}
+
+impl Display for PathBuf {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
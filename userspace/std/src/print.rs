@@ -1,13 +1,17 @@
-use alloc::format;
-
-use crate::syscall::sys_write;
+use crate::io::{stderr, stdout, Write};
 
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
-    let text = format!("{}", args);
-    let _ = sys_write(0, text.as_bytes()); // FIXME: posix mandates that stdout is 1, not 0
+    let _ = stdout().lock().write_fmt(args);
 }
 
+#[doc(hidden)]
+pub fn _eprint(args: core::fmt::Arguments) {
+    let _ = stderr().write_fmt(args);
+}
+
+/// Prints to the standard output, which is written once the statement has a
+/// complete line. See [`crate::io::stdout`].
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => ($crate::print::_print(format_args!($($arg)*)));
@@ -19,3 +23,16 @@ macro_rules! println {
     ($fmt:expr) => ($crate::print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+/// Prints to the standard error, which isn't buffered.
+#[macro_export]
+macro_rules! eprint {
+    ($($arg:tt)*) => ($crate::print::_eprint(format_args!($($arg)*)));
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => ($crate::eprint!("\n"));
+    ($fmt:expr) => ($crate::eprint!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::eprint!(concat!($fmt, "\n"), $($arg)*));
+}
@@ -1,41 +1,125 @@
-use linked_list_allocator::LockedHeap;
+use core::cell::Cell;
+use core::ffi::{c_char, CStr};
+use core::iter::from_fn;
+use core::ptr::{null, null_mut};
+use core::sync::atomic::Ordering::Relaxed;
+use core::sync::atomic::{AtomicPtr, AtomicUsize};
 
-use crate::syscall::{sys_exit, sys_mmap, sys_open, Errno};
+use kernel_api::auxv::{AT_ENTRY, AT_NULL, AT_PAGESZ};
 
-#[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+use crate::syscall::sys_exit;
+use crate::thread;
 
-fn init(heap_start: *mut u8, heap_size: usize) {
-    unsafe {
-        ALLOCATOR.lock().init(heap_start, heap_size);
+/// The initial stack that the kernel set up for the program, which starts with
+/// `argc`, followed by the null terminated `argv` and `envp` arrays and the
+/// auxiliary vector. See [`kernel_api::auxv`] for the layout.
+static STACK: AtomicPtr<usize> = AtomicPtr::new(null_mut());
+
+/// The values of the auxiliary vector that the runtime keeps, or 0 if the
+/// kernel didn't pass them.
+static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+static ENTRY: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether the current thread has panicked. The thread ends once the panic is
+/// reported, so this never goes back to `false`.
+#[thread_local]
+static PANICKING: Cell<bool> = Cell::new(false);
+
+/// Sets up the runtime. `stack` is the initial stack that `_start` gets from
+/// the kernel.
+pub fn start(stack: *const usize) {
+    STACK.store(stack.cast_mut(), Relaxed);
+    for (key, value) in auxv() {
+        match key {
+            AT_PAGESZ => PAGE_SIZE.store(value, Relaxed),
+            AT_ENTRY => ENTRY.store(value, Relaxed),
+            _ => {}
+        }
+    }
+    // stdin, stdout and stderr are already open, the kernel wires them to the console,
+    // and the heap maps its memory when it's first used
+    if let Err(errno) = thread::tls::set_up_main() {
+        sys_exit(errno.code() as isize);
     }
+    thread::init_main();
 }
 
-pub fn start() {
-    init_heap();
-    init_fds();
+/// The arguments of the program, starting with the path of its executable.
+pub fn args() -> impl Iterator<Item = &'static CStr> {
+    // argv starts right after argc
+    initial_stack()
+        .map(|stack| unsafe { strings(stack.add(1)) })
+        .into_iter()
+        .flatten()
 }
 
-fn init_heap() {
-    let start = 0x3333_0000_0000;
-    let len = 8 * 1024;
+/// The environment of the program as `NAME=value` strings, in the state that
+/// the program was started with.
+pub fn env() -> impl Iterator<Item = &'static CStr> {
+    // envp starts after argc, argv and the null that ends argv
+    initial_stack()
+        .map(|stack| unsafe { strings(stack.add(*stack + 2)) })
+        .into_iter()
+        .flatten()
+}
 
-    let eno = sys_mmap(start, len, 0x1 | 0x2, 0x2 | 0x8, 0, 0);
-    if eno.as_isize() < 0 {
-        sys_exit(-eno.as_isize());
-    }
+/// The key, value pairs of the auxiliary vector, without the one that ends
+/// it.
+pub fn auxv() -> impl Iterator<Item = (usize, usize)> {
+    // the auxiliary vector starts after the null that ends envp
+    let mut next = initial_stack()
+        .map(|stack| unsafe {
+            let envp = stack.add(*stack + 2);
+            let envc = (0..).find(|&i| *envp.add(i) == 0).unwrap();
+            envp.add(envc + 1)
+        })
+        .unwrap_or(null());
+    from_fn(move || {
+        if next.is_null() {
+            return None;
+        }
+        let (key, value) = unsafe { (*next, *next.add(1)) };
+        if key == AT_NULL {
+            return None;
+        }
+        next = unsafe { next.add(2) };
+        Some((key, value))
+    })
+}
 
-    init(start as *mut u8, len);
+/// The size of a page, from [`AT_PAGESZ`].
+pub fn page_size() -> Option<usize> {
+    Some(PAGE_SIZE.load(Relaxed)).filter(|&size| size != 0)
 }
 
-fn init_fds() {
-    let stdout = must(sys_open("/dev/stdout", 0, 0));
-    assert_eq!(stdout, 0); // FIXME: posix mandates that stdout is 1, not 0
+/// The address of the entry point of the program, from [`AT_ENTRY`].
+pub fn entry() -> Option<usize> {
+    Some(ENTRY.load(Relaxed)).filter(|&entry| entry != 0)
 }
 
-fn must(errno: Errno) -> usize {
-    if errno.as_isize() < 0 {
-        sys_exit(-errno.as_isize());
-    }
-    errno.as_isize() as usize
+fn initial_stack() -> Option<*const usize> {
+    let stack = STACK.load(Relaxed);
+    (!stack.is_null()).then_some(stack.cast_const())
+}
+
+/// Iterates over a null terminated array of strings.
+unsafe fn strings(array: *const usize) -> impl Iterator<Item = &'static CStr> {
+    let mut next = array;
+    from_fn(move || {
+        let ptr = unsafe { *next } as *const c_char;
+        if ptr.is_null() {
+            return None;
+        }
+        next = unsafe { next.add(1) };
+        Some(unsafe { CStr::from_ptr(ptr) })
+    })
+}
+
+/// Whether the current thread is panicking.
+pub fn panicking() -> bool {
+    PANICKING.get()
+}
+
+pub(crate) fn set_panicking() {
+    PANICKING.set(true);
 }
@@ -1,29 +1,96 @@
 use alloc::ffi::CString;
-use core::ptr::addr_of;
+use alloc::vec::Vec;
+use core::ffi::{c_char, CStr};
+use core::ptr::{addr_of, null, null_mut};
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
 
-pub use kernel_api::syscall::Errno;
-use kernel_api::syscall::{FfiSockAddr, SocketDomain, SocketType, Stat, Syscall};
+pub use kernel_api::syscall::{
+    is_char_device, is_directory, is_regular_file, is_symlink, Errno, FileMode, FlockOperation,
+    Stat, Timespec, Whence, AT_FDCWD,
+};
+use kernel_api::syscall::{
+    FfiSockAddr, FutexOp, OpenFlags, PollFd, SocketDomain, SocketType, Syscall,
+};
 
 use crate::arch::syscall::syscall6;
-use crate::arch::syscall::{syscall1, syscall2, syscall3};
+use crate::arch::syscall::{syscall1, syscall2, syscall3, syscall4};
 
-pub fn sys_read(fd: usize, buf: &mut [u8]) -> Errno {
-    unsafe { syscall3(Syscall::Read, fd, buf.as_mut_ptr() as usize, buf.len()) }.into()
+pub fn sys_read(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Read, fd, buf.as_mut_ptr() as usize, buf.len())
+    })
 }
 
-pub fn sys_write(fd: usize, buf: &[u8]) -> Errno {
-    unsafe { syscall3(Syscall::Write, fd, buf.as_ptr() as usize, buf.len()) }.into()
+pub fn sys_write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Write, fd, buf.as_ptr() as usize, buf.len())
+    })
 }
 
-pub fn sys_open(path: &str, flags: usize, mode: usize) -> Errno {
+pub fn sys_open(path: &str, flags: usize, mode: usize) -> Result<usize, Errno> {
     let cstring = CString::new(path).unwrap();
-    unsafe { syscall3(Syscall::Open, cstring.as_ptr() as usize, flags, mode) }.into()
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Open, cstring.as_ptr() as usize, flags, mode)
+    })
 }
 
-pub fn sys_close(fd: usize) -> Errno {
-    unsafe { syscall1(Syscall::Close, fd) }.into()
+/// Opens the file at `path` relative to the directory `dirfd`. Pass [`AT_FDCWD`]
+/// to resolve relative to the current working directory.
+pub fn sys_openat(dirfd: i32, path: &str, flags: usize, mode: usize) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe {
+        syscall4(
+            Syscall::Openat,
+            dirfd as isize as usize,
+            cstring.as_ptr() as usize,
+            flags,
+            mode,
+        )
+    })
+}
+
+pub fn sys_chdir(path: &str) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe { syscall1(Syscall::Chdir, cstring.as_ptr() as usize) })
 }
 
+/// Writes the nul-terminated current working directory into `buf` and returns
+/// its length including the nul byte.
+pub fn sys_getcwd(buf: &mut [u8]) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall2(Syscall::Getcwd, buf.as_mut_ptr() as usize, buf.len())
+    })
+}
+
+/// Writes the target of the symbolic link at `path` into `buf` and returns the
+/// number of bytes written. The target is not nul-terminated.
+pub fn sys_readlink(path: &str, buf: &mut [u8]) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe {
+        syscall3(
+            Syscall::Readlink,
+            cstring.as_ptr() as usize,
+            buf.as_mut_ptr() as usize,
+            buf.len(),
+        )
+    })
+}
+
+pub fn sys_close(fd: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::Close, fd) })
+}
+
+pub const PROT_NONE: usize = 0x0;
+pub const PROT_READ: usize = 0x1;
+pub const PROT_WRITE: usize = 0x2;
+
+pub const MAP_PRIVATE: usize = 0x2;
+pub const MAP_FIXED: usize = 0x4;
+pub const MAP_ANONYMOUS: usize = 0x8;
+/// Allocates the memory right away, instead of when it's first accessed.
+pub const MAP_POPULATE: usize = 0x10;
+
 pub fn sys_mmap(
     addr: usize,
     len: usize,
@@ -31,39 +98,286 @@ pub fn sys_mmap(
     flags: usize,
     fd: usize,
     offset: usize,
-) -> Errno {
-    unsafe { syscall6(Syscall::Mmap, addr, len, prot, flags, fd, offset) }.into()
+) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall6(Syscall::Mmap, addr, len, prot, flags, fd, offset) })
 }
 
+/// Unmaps the mappings in `len` bytes from `addr`, which must each lie
+/// entirely in that range.
+pub fn sys_munmap(addr: usize, len: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall2(Syscall::Munmap, addr, len) })
+}
+
+/// Exits the process, after writing what is buffered for the standard
+/// output.
 pub fn sys_exit(status: isize) -> ! {
+    crate::io::try_flush_stdout();
     unsafe { syscall1(Syscall::Exit, status as usize) };
     unreachable!()
 }
 
-pub fn sys_socket(domain: SocketDomain, ty: SocketType, protocol: usize) -> Errno {
-    unsafe { syscall3(Syscall::Socket, domain as usize, ty as usize, protocol) }.into()
+pub fn sys_socket(domain: SocketDomain, ty: SocketType, protocol: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Socket, domain as usize, ty as usize, protocol)
+    })
 }
 
-pub fn sys_bind(socket: usize, address: FfiSockAddr, address_len: usize) -> Errno {
-    unsafe {
+pub fn sys_bind(socket: usize, address: FfiSockAddr, address_len: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
         syscall3(
             Syscall::Bind,
             socket,
             addr_of!(address) as usize,
             address_len,
         )
-    }
-    .into()
+    })
 }
 
-pub fn sys_stat(path: &str, stat: &mut Stat) -> Errno {
+pub fn sys_stat(path: &str, stat: &mut Stat) -> Result<usize, Errno> {
     let cstring = CString::new(path).unwrap();
-    unsafe {
+    Errno::from_return_value(unsafe {
         syscall2(
             Syscall::Stat,
             cstring.as_ptr() as usize,
             stat as *mut Stat as usize,
         )
-    }
-    .into()
+    })
+}
+
+pub fn sys_fstat(fd: usize, stat: &mut Stat) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall2(Syscall::Fstat, fd, stat as *mut Stat as usize) })
+}
+
+/// Waits for one of the given file descriptors to become ready. A negative
+/// timeout (in milliseconds) waits indefinitely.
+pub fn sys_poll(fds: &mut [PollFd], timeout: i32) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(
+            Syscall::Poll,
+            fds.as_mut_ptr() as usize,
+            fds.len(),
+            timeout as usize,
+        )
+    })
+}
+
+/// Creates a pipe. The read end is written to `fds[0]`, the write end to `fds[1]`.
+pub fn sys_pipe(fds: &mut [i32; 2]) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::Pipe, fds.as_mut_ptr() as usize) })
+}
+
+/// Enables or disables syscall tracing for the current process.
+pub fn sys_traceme(enable: bool) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::TraceMe, enable as usize) })
+}
+
+/// Performs the device specific operation `request` on the given file descriptor.
+/// `arg` is passed to the device as is, most requests expect a pointer to a
+/// request specific struct.
+pub fn sys_ioctl(fd: usize, request: u32, arg: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall3(Syscall::Ioctl, fd, request as usize, arg) })
+}
+
+pub fn sys_mkdir(path: &str, mode: usize) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe { syscall2(Syscall::Mkdir, cstring.as_ptr() as usize, mode) })
+}
+
+pub fn sys_unlink(path: &str) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe { syscall1(Syscall::Unlink, cstring.as_ptr() as usize) })
+}
+
+pub fn sys_rmdir(path: &str) -> Result<usize, Errno> {
+    let cstring = CString::new(path).unwrap();
+    Errno::from_return_value(unsafe { syscall1(Syscall::Rmdir, cstring.as_ptr() as usize) })
+}
+
+/// Atomically moves `from` to `to`, replacing `to` if it exists.
+pub fn sys_rename(from: &str, to: &str) -> Result<usize, Errno> {
+    let from = CString::new(from).unwrap();
+    let to = CString::new(to).unwrap();
+    Errno::from_return_value(unsafe {
+        syscall2(
+            Syscall::Rename,
+            from.as_ptr() as usize,
+            to.as_ptr() as usize,
+        )
+    })
+}
+
+/// Adds `to` as another name for the file at `existing`.
+pub fn sys_link(existing: &str, to: &str) -> Result<usize, Errno> {
+    let existing = CString::new(existing).unwrap();
+    let to = CString::new(to).unwrap();
+    Errno::from_return_value(unsafe {
+        syscall2(
+            Syscall::Link,
+            existing.as_ptr() as usize,
+            to.as_ptr() as usize,
+        )
+    })
+}
+
+/// Truncates or extends the file to `len` bytes. The file must be open for writing.
+pub fn sys_ftruncate(fd: usize, len: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall2(Syscall::Ftruncate, fd, len) })
+}
+
+/// Reads directory entries from the directory `fd` into `buf`, in the layout
+/// of [`kernel_api::syscall::Dirent`]. Returns the number of bytes that were
+/// filled, which is zero at the end of the directory.
+pub fn sys_getdents(fd: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Getdents, fd, buf.as_mut_ptr() as usize, buf.len())
+    })
+}
+
+/// Moves the offset of the file descriptor, and returns the new offset.
+pub fn sys_lseek(fd: usize, offset: isize, whence: Whence) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(Syscall::Lseek, fd, offset as usize, whence as usize)
+    })
+}
+
+/// Applies or removes an advisory lock on the open file. Without
+/// [`FlockOperation::LOCK_NB`], this waits until the lock can be acquired.
+pub fn sys_flock(fd: usize, operation: FlockOperation) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall2(Syscall::Flock, fd, operation.bits() as usize) })
+}
+
+/// Truncates or extends the file at the given path to `len` bytes.
+pub fn sys_truncate(path: &str, len: usize) -> Result<usize, Errno> {
+    let fd = sys_open(path, OpenFlags::O_WRONLY.bits() as usize, 0)?;
+    let res = sys_ftruncate(fd, len);
+    let _ = sys_close(fd);
+    res
+}
+
+/// Starts the executable at `path` in a new child process, with the given
+/// arguments and environment, and returns the id of the child.
+pub fn sys_spawn(path: &str, args: &[&str], env: &[&str]) -> Result<usize, Errno> {
+    let path = CString::new(path).unwrap();
+    let args = args
+        .iter()
+        .map(|&s| CString::new(s).unwrap())
+        .collect::<Vec<_>>();
+    let env = env
+        .iter()
+        .map(|&s| CString::new(s).unwrap())
+        .collect::<Vec<_>>();
+    unsafe { sys_spawn_raw(&path, &pointers(&args), &pointers(&env)) }
+}
+
+/// Like [`sys_spawn`], but takes the arguments and the environment as null
+/// terminated arrays of pointers, the way C passes them around.
+///
+/// # Safety
+/// `args` and `env` must be null terminated, and every other pointer in them
+/// must point to a nul terminated string.
+pub unsafe fn sys_spawn_raw(
+    path: &CStr,
+    args: &[*const c_char],
+    env: &[*const c_char],
+) -> Result<usize, Errno> {
+    debug_assert_eq!(Some(&null()), args.last());
+    debug_assert_eq!(Some(&null()), env.last());
+    Errno::from_return_value(unsafe {
+        syscall3(
+            Syscall::Spawn,
+            path.as_ptr() as usize,
+            args.as_ptr() as usize,
+            env.as_ptr() as usize,
+        )
+    })
+}
+
+fn pointers(strings: &[CString]) -> Vec<*const c_char> {
+    strings.iter().map(|s| s.as_ptr()).chain([null()]).collect()
+}
+
+/// Waits until the child process with the given id has exited, and returns
+/// its exit status.
+pub fn sys_waitpid(pid: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::Waitpid, pid) })
+}
+
+/// Creates a thread in the current process, which calls `entry` with `arg` on
+/// `stack`, with the `fs` segment pointing to `tls`. Returns the id of the
+/// thread, which [`sys_thread_join`] takes.
+///
+/// # Safety
+/// `stack` must be the top of a stack that stays mapped until the thread has
+/// exited, and `tls` must be null or point to a TCB.
+pub unsafe fn sys_thread_create(
+    entry: extern "C" fn(usize) -> !,
+    arg: usize,
+    stack: *mut u8,
+    tls: *mut u8,
+) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall4(
+            Syscall::ThreadCreate,
+            entry as usize,
+            arg,
+            stack as usize,
+            tls as usize,
+        )
+    })
+}
+
+/// Exits the current thread with `value`, which is what joining it returns.
+/// The process exits with the last thread.
+pub fn sys_thread_exit(value: usize) -> ! {
+    unsafe { syscall1(Syscall::ThreadExit, value) };
+    unreachable!()
+}
+
+/// Waits until the thread has exited, and returns its exit value.
+pub fn sys_thread_join(tid: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::ThreadJoin, tid) })
+}
+
+/// Points the `fs` segment of the current thread to `tls`.
+///
+/// # Safety
+/// `tls` must point to a TCB that stays valid as long as the thread uses it.
+pub unsafe fn sys_set_tls(tls: *mut u8) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe { syscall1(Syscall::SetTls, tls as usize) })
+}
+
+/// Waits until the futex is woken with [`sys_futex_wake`], unless its value
+/// isn't `expected`, in which case this fails with [`Errno::EWOULDBLOCK`].
+pub fn sys_futex_wait(futex: &AtomicU32, expected: u32) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(
+            Syscall::Futex,
+            futex.as_ptr() as usize,
+            FutexOp::Wait as usize,
+            expected as usize,
+        )
+    })
+}
+
+/// Wakes up to `count` of the threads that wait on the futex, and returns how
+/// many were woken.
+pub fn sys_futex_wake(futex: &AtomicU32, count: usize) -> Result<usize, Errno> {
+    Errno::from_return_value(unsafe {
+        syscall3(
+            Syscall::Futex,
+            futex.as_ptr() as usize,
+            FutexOp::Wake as usize,
+            count,
+        )
+    })
+}
+
+/// Waits until `duration` has passed. If a signal interrupts the wait, this
+/// fails with [`Errno::EINTR`], and `remaining` is the time that was left.
+pub fn sys_nanosleep(duration: Duration, remaining: Option<&mut Timespec>) -> Result<usize, Errno> {
+    let req = Timespec::from(duration);
+    let rem = remaining.map_or(null_mut(), |rem| rem as *mut Timespec);
+    Errno::from_return_value(unsafe {
+        syscall2(Syscall::Nanosleep, addr_of!(req) as usize, rem as usize)
+    })
 }
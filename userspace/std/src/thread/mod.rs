@@ -0,0 +1,397 @@
+//! Threads.
+//!
+//! Every thread is a kernel thread of the process, which runs on a stack that
+//! is mapped for it, with a guard page below it, and its TLS block and TCB
+//! above it. The main thread gets its TLS in [`crate::rt::start`].
+//!
+//! Programs are built with `panic = "abort"`, so panics don't unwind. A
+//! thread that was spawned here ends when it panics, instead of the whole
+//! process, and joining it returns the panic message as the error. Nothing
+//! that the thread owned is dropped then, and whatever it had locked stays
+//! locked.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::cell::{Cell, UnsafeCell};
+use core::fmt;
+use core::num::NonZeroU64;
+use core::panic::PanicInfo;
+use core::ptr::null;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+use core::sync::atomic::{AtomicU32, AtomicU64};
+use core::time::Duration;
+
+use crate::io;
+use crate::lock::Mutex;
+use crate::syscall::{
+    sys_futex_wait, sys_futex_wake, sys_mmap, sys_munmap, sys_nanosleep, sys_thread_create,
+    sys_thread_exit, sys_thread_join, Errno, Timespec, MAP_ANONYMOUS, MAP_FIXED, MAP_POPULATE,
+    MAP_PRIVATE, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+use crate::thread::tls::Template;
+
+pub(crate) mod tls;
+
+/// What joining a thread returns: the value of its closure, or the panic
+/// message if it panicked.
+pub type Result<T> = core::result::Result<T, Box<dyn Any + Send + 'static>>;
+
+const DEFAULT_STACK_SIZE: usize = 128 * 1024;
+const PAGE_SIZE: usize = 4096;
+/// Running into the guard page faults, instead of overwriting whatever is
+/// mapped below the stack.
+const GUARD_SIZE: usize = PAGE_SIZE;
+/// How often mapping a stack is retried if another thread maps something in
+/// the place that was picked for it.
+const MAP_ATTEMPTS: usize = 4;
+
+/// The states of a parker.
+const EMPTY: u32 = 0;
+const NOTIFIED: u32 = 1;
+const PARKED: u32 = u32::MAX;
+
+/// The thread that is running, or null until [`current`] is first called in
+/// a thread that wasn't spawned here. Spawned threads own the reference that
+/// this points to, all others leak it.
+#[thread_local]
+static CURRENT: Cell<*const Inner> = Cell::new(null());
+
+/// A unique identifier of a thread.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct ThreadId(NonZeroU64);
+
+impl ThreadId {
+    fn next() -> Self {
+        static NEXT: AtomicU64 = AtomicU64::new(1);
+        Self(NonZeroU64::new(NEXT.fetch_add(1, Relaxed)).unwrap())
+    }
+
+    pub fn as_u64(&self) -> NonZeroU64 {
+        self.0
+    }
+}
+
+/// A handle to a thread.
+#[derive(Clone)]
+pub struct Thread {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    id: ThreadId,
+    name: Option<String>,
+    /// Whether the thread was spawned here, and ends when it panics.
+    spawned: bool,
+    /// The message of the panic that ended the thread.
+    panic: Mutex<Option<String>>,
+    parker: AtomicU32,
+}
+
+impl Thread {
+    fn new(name: Option<String>, spawned: bool) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                id: ThreadId::next(),
+                name,
+                spawned,
+                panic: Mutex::new(None),
+                parker: AtomicU32::new(EMPTY),
+            }),
+        }
+    }
+
+    pub fn id(&self) -> ThreadId {
+        self.inner.id
+    }
+
+    pub fn name(&self) -> Option<&str> {
+        self.inner.name.as_deref()
+    }
+
+    /// Wakes the thread if it's parked, or makes its next call to [`park`]
+    /// return right away.
+    pub fn unpark(&self) {
+        if self.inner.parker.swap(NOTIFIED, Release) == PARKED {
+            let _ = sys_futex_wake(&self.inner.parker, 1);
+        }
+    }
+
+    fn park(&self) {
+        // NOTIFIED becomes EMPTY, and EMPTY becomes PARKED
+        if self.inner.parker.fetch_sub(1, Acquire) == NOTIFIED {
+            return;
+        }
+        loop {
+            let _ = sys_futex_wait(&self.inner.parker, PARKED);
+            if self
+                .inner
+                .parker
+                .compare_exchange(NOTIFIED, EMPTY, Acquire, Acquire)
+                .is_ok()
+            {
+                return;
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Thread {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Thread")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The thread that is running. The main thread is called `main`, and threads
+/// that weren't spawned here don't have a name.
+pub fn current() -> Thread {
+    let mut inner = CURRENT.get();
+    if inner.is_null() {
+        inner = Arc::into_raw(Thread::new(None, false).inner);
+        CURRENT.set(inner);
+    }
+    unsafe {
+        Arc::increment_strong_count(inner);
+        Thread {
+            inner: Arc::from_raw(inner),
+        }
+    }
+}
+
+/// Names the main thread. This is called before anything else can call
+/// [`current`].
+pub(crate) fn init_main() {
+    CURRENT.set(Arc::into_raw(Thread::new(Some("main".into()), false).inner));
+}
+
+/// Waits until the current thread is unparked with [`Thread::unpark`]. May
+/// return without having been unparked.
+pub fn park() {
+    current().park();
+}
+
+/// Waits for at least `duration`, even if signal handlers run in between.
+pub fn sleep(duration: Duration) {
+    let mut duration = duration;
+    let mut remaining = Timespec::default();
+    while sys_nanosleep(duration, Some(&mut remaining)) == Err(Errno::EINTR) {
+        duration = Duration::new(u64::from(remaining.tv_sec), remaining.tv_nsec as u32);
+    }
+}
+
+/// Calls `f` with the name of the current thread, without allocating, for
+/// panic messages.
+pub(crate) fn with_current_name<R>(f: impl FnOnce(&str) -> R) -> R {
+    let inner = unsafe { CURRENT.get().as_ref() };
+    f(inner
+        .and_then(|inner| inner.name.as_deref())
+        .unwrap_or("<unnamed>"))
+}
+
+/// Ends the current thread if it was spawned here, after keeping the panic
+/// message for whoever joins it. Returns if the thread wasn't spawned here,
+/// in which case the panic ends the process.
+pub(crate) fn exit_on_panic(info: &PanicInfo) {
+    let Some(inner) = (unsafe { CURRENT.get().as_ref() }) else {
+        return;
+    };
+    if !inner.spawned {
+        return;
+    }
+    if let Some(mut panic) = inner.panic.try_lock() {
+        *panic = Some(format!("{}", info.message()));
+    }
+    sys_thread_exit(0)
+}
+
+/// Spawns a thread that runs `f`, and returns a handle to join it.
+///
+/// # Panics
+/// When the thread can't be created, see [`Builder::spawn`].
+pub fn spawn<F, T>(f: F) -> JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    Builder::new().spawn(f).expect("failed to spawn thread")
+}
+
+/// Configures a thread before it's spawned.
+#[derive(Debug, Default)]
+pub struct Builder {
+    name: Option<String>,
+    stack_size: Option<usize>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The name of the thread, which panic messages include.
+    pub fn name(mut self, name: String) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// The size of the stack of the thread. It is mapped right away, so that
+    /// the thread never faults on it.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Spawns a thread that runs `f`. Fails if there is no memory for the
+    /// stack, or the kernel doesn't create the thread.
+    pub fn spawn<F, T>(self, f: F) -> io::Result<JoinHandle<T>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let thread = Thread::new(self.name, true);
+        let packet = Arc::new(Packet {
+            result: UnsafeCell::new(None),
+        });
+
+        let their_thread = thread.clone();
+        let their_packet = packet.clone();
+        let main: Box<dyn FnOnce() + Send> = Box::new(move || {
+            CURRENT.set(Arc::as_ptr(&their_thread.inner));
+            let value = f();
+            // the joiner only looks at the result once the thread has exited
+            unsafe { *their_packet.result.get() = Some(value) };
+            CURRENT.set(null());
+        });
+
+        let template = Template::of_executable();
+        // the stack grows down from the TLS block
+        let stack_size = self
+            .stack_size
+            .unwrap_or(DEFAULT_STACK_SIZE)
+            .next_multiple_of(template.align());
+        let memory = Memory::map((stack_size + template.size()).next_multiple_of(PAGE_SIZE))?;
+        let stack = (memory.start() + stack_size) as *mut u8;
+        let tcb = unsafe { template.initialize(stack) };
+
+        let main = Box::into_raw(Box::new(main));
+        match unsafe { sys_thread_create(start, main as usize, stack, tcb) } {
+            Ok(tid) => Ok(JoinHandle {
+                tid,
+                memory,
+                thread,
+                packet,
+            }),
+            Err(errno) => {
+                drop(unsafe { Box::from_raw(main) });
+                memory.unmap();
+                Err(errno.into())
+            }
+        }
+    }
+}
+
+/// Where spawned threads start. The kernel calls it on the stack of the
+/// thread.
+extern "C" fn start(main: usize) -> ! {
+    let main = unsafe { Box::from_raw(main as *mut Box<dyn FnOnce() + Send>) };
+    main();
+    sys_thread_exit(0)
+}
+
+/// Where a thread leaves the value of its closure.
+struct Packet<T> {
+    result: UnsafeCell<Option<T>>,
+}
+
+// the result is written by the thread before it exits, and read by the
+// joiner after that
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// A handle to join a thread. Dropping it detaches the thread, whose stack
+/// then stays mapped.
+pub struct JoinHandle<T> {
+    tid: usize,
+    memory: Memory,
+    thread: Thread,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn thread(&self) -> &Thread {
+        &self.thread
+    }
+
+    /// Waits until the thread has exited, and returns the value of its
+    /// closure, or the panic message if it panicked.
+    pub fn join(self) -> Result<T> {
+        sys_thread_join(self.tid).expect("failed to join thread");
+        // the thread is gone, so nothing uses its stack anymore
+        self.memory.unmap();
+        match unsafe { (*self.packet.result.get()).take() } {
+            Some(value) => Ok(value),
+            None => {
+                let message = self.thread.inner.panic.lock().take();
+                Err(Box::new(message.unwrap_or_default()))
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("thread", &self.thread)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The memory of a thread: the guard page, and above it the stack, which is
+/// followed by the TLS block and the TCB.
+#[derive(Debug, Copy, Clone)]
+struct Memory {
+    guard: usize,
+    len: usize,
+}
+
+impl Memory {
+    /// Maps `len` bytes with a guard page below them.
+    fn map(len: usize) -> core::result::Result<Self, Errno> {
+        let mut error = Errno::ENOMEM;
+        for _ in 0..MAP_ATTEMPTS {
+            // a mapping has the same protection everywhere, so find a place that
+            // fits both, and map them there separately
+            let flags = MAP_PRIVATE | MAP_ANONYMOUS;
+            let guard = sys_mmap(0, GUARD_SIZE + len, PROT_NONE, flags, 0, 0)?;
+            sys_munmap(guard, GUARD_SIZE + len)?;
+
+            if let Err(errno) = sys_mmap(guard, GUARD_SIZE, PROT_NONE, flags | MAP_FIXED, 0, 0) {
+                error = errno;
+                continue;
+            }
+            // a page fault can't be handled on a stack that isn't mapped yet,
+            // because the CPU pushes its interrupt frame onto that stack
+            let prot = PROT_READ | PROT_WRITE;
+            let populated = flags | MAP_FIXED | MAP_POPULATE;
+            match sys_mmap(guard + GUARD_SIZE, len, prot, populated, 0, 0) {
+                Ok(_) => return Ok(Self { guard, len }),
+                Err(errno) => error = errno,
+            }
+            let _ = sys_munmap(guard, GUARD_SIZE);
+        }
+        Err(error)
+    }
+
+    fn start(&self) -> usize {
+        self.guard + GUARD_SIZE
+    }
+
+    fn unmap(self) {
+        let _ = sys_munmap(self.start(), self.len);
+        let _ = sys_munmap(self.guard, GUARD_SIZE);
+    }
+}
@@ -0,0 +1,152 @@
+//! Thread local storage, laid out like the x86_64 System V ABI describes it
+//! ("variant II"): the TLS block of the executable sits right below the
+//! thread control block (TCB), which the `fs` segment of the thread points to.
+//! This is what `#[thread_local]` statics are compiled against.
+
+use core::mem::size_of;
+use core::ptr;
+
+use crate::heap;
+use crate::syscall::{sys_set_tls, Errno};
+
+const PT_LOAD: u32 = 1;
+const PT_TLS: u32 = 7;
+
+extern "C" {
+    /// The ELF header of the executable, provided by the linker.
+    static __ehdr_start: Elf64Header;
+}
+
+#[repr(C)]
+struct Elf64Header {
+    ident: [u8; 16],
+    typ: u16,
+    machine: u16,
+    version: u32,
+    entry: u64,
+    phoff: u64,
+    shoff: u64,
+    flags: u32,
+    ehsize: u16,
+    phentsize: u16,
+    phnum: u16,
+    shentsize: u16,
+    shnum: u16,
+    shstrndx: u16,
+}
+
+#[repr(C)]
+struct Elf64ProgramHeader {
+    typ: u32,
+    flags: u32,
+    offset: u64,
+    vaddr: u64,
+    paddr: u64,
+    filesz: u64,
+    memsz: u64,
+    align: u64,
+}
+
+/// What every TLS block starts with: the initialized data of the `PT_TLS`
+/// segment, followed by zeros.
+#[derive(Debug, Copy, Clone)]
+pub struct Template {
+    image: *const u8,
+    file_size: usize,
+    mem_size: usize,
+    align: usize,
+}
+
+impl Template {
+    /// The template of the executable, or an empty one if it has no thread
+    /// local variables.
+    pub fn of_executable() -> Self {
+        let mut template = Self {
+            image: ptr::null(),
+            file_size: 0,
+            mem_size: 0,
+            align: 1,
+        };
+        let header = &raw const __ehdr_start;
+        let program_headers = unsafe {
+            core::slice::from_raw_parts(
+                header
+                    .cast::<u8>()
+                    .add((*header).phoff as usize)
+                    .cast::<Elf64ProgramHeader>(),
+                (*header).phnum as usize,
+            )
+        };
+        // the segment that contains the headers tells where the image was loaded
+        let Some(first) = program_headers
+            .iter()
+            .find(|ph| ph.typ == PT_LOAD && ph.offset == 0)
+        else {
+            return template;
+        };
+        let base = header as usize - first.vaddr as usize;
+        if let Some(tls) = program_headers.iter().find(|ph| ph.typ == PT_TLS) {
+            template.image = (base + tls.vaddr as usize) as *const u8;
+            template.file_size = tls.filesz as usize;
+            template.mem_size = tls.memsz as usize;
+            template.align = tls.align.max(1) as usize;
+        }
+        template
+    }
+
+    /// The alignment of the area that [`Template::initialize`] takes.
+    pub fn align(&self) -> usize {
+        self.align.max(16)
+    }
+
+    /// The offset of the TCB in the area. The TLS block lies in front of it.
+    fn tcb_offset(&self) -> usize {
+        self.mem_size.next_multiple_of(self.align())
+    }
+
+    /// The size of the area that [`Template::initialize`] takes.
+    pub fn size(&self) -> usize {
+        self.tcb_offset() + size_of::<Tcb>()
+    }
+
+    /// Initializes the TLS block and the TCB in `area`, and returns the
+    /// address of the TCB, which is what `fs` must point to.
+    ///
+    /// # Safety
+    /// `area` must be valid for [`Template::size`] bytes and aligned to
+    /// [`Template::align`].
+    pub unsafe fn initialize(&self, area: *mut u8) -> *mut u8 {
+        unsafe {
+            let tcb = area.add(self.tcb_offset()).cast::<Tcb>();
+            // the linker computes the offsets of thread local variables like this
+            let block = tcb
+                .cast::<u8>()
+                .sub(self.mem_size.next_multiple_of(self.align));
+            if self.file_size > 0 {
+                ptr::copy_nonoverlapping(self.image, block, self.file_size);
+            }
+            ptr::write_bytes(block.add(self.file_size), 0, self.mem_size - self.file_size);
+            tcb.write(Tcb { this: tcb });
+            tcb.cast()
+        }
+    }
+}
+
+/// The thread control block. The ABI only requires it to point to itself.
+#[repr(C)]
+struct Tcb {
+    /// Points to the TCB itself, so that its address can be read from `fs:0`.
+    this: *mut Tcb,
+}
+
+/// Gives the main thread its TLS, which stays mapped until the process exits.
+pub fn set_up_main() -> Result<(), Errno> {
+    let template = Template::of_executable();
+    // mappings are page aligned, which is enough for any TLS block
+    let area = heap::map(0, template.size())?;
+    unsafe {
+        let tcb = template.initialize(area as *mut u8);
+        sys_set_tls(tcb)?;
+    }
+    Ok(())
+}
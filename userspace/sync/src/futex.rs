@@ -0,0 +1,64 @@
+//! Waiting for `u32`s to change, which is what locks and condition variables
+//! sleep on.
+
+use core::sync::atomic::AtomicU32;
+
+/// Where threads wait on futexes.
+pub trait Futex {
+    /// Waits until woken with [`Futex::wake`], unless `futex` isn't
+    /// `expected`. May return without having been woken.
+    fn wait(futex: &AtomicU32, expected: u32);
+
+    /// Wakes up to `count` of the threads that wait on `futex`.
+    fn wake(futex: &AtomicU32, count: usize);
+}
+
+#[cfg(any(test, feature = "host"))]
+pub mod host {
+    extern crate std;
+
+    use core::sync::atomic::AtomicU32;
+    use core::sync::atomic::Ordering::SeqCst;
+    use std::sync::{Condvar, Mutex};
+    use std::vec::Vec;
+
+    use super::Futex;
+
+    /// The waiters of all futexes, as address and ticket, in the order in
+    /// which they started waiting, and the next ticket.
+    static WAITERS: Mutex<(Vec<(usize, u64)>, u64)> = Mutex::new((Vec::new(), 0));
+    static WOKEN: Condvar = Condvar::new();
+
+    /// Futexes for host tests, which wake exactly like the ones of the
+    /// kernel, so that they don't hide missed wakeups by waking too many
+    /// threads.
+    pub struct HostFutex;
+
+    impl Futex for HostFutex {
+        fn wait(futex: &AtomicU32, expected: u32) {
+            let mut waiters = WAITERS.lock().unwrap();
+            if futex.load(SeqCst) != expected {
+                return;
+            }
+            let waiter = (futex.as_ptr() as usize, waiters.1);
+            waiters.1 += 1;
+            waiters.0.push(waiter);
+            while waiters.0.contains(&waiter) {
+                waiters = WOKEN.wait(waiters).unwrap();
+            }
+        }
+
+        fn wake(futex: &AtomicU32, count: usize) {
+            let mut waiters = WAITERS.lock().unwrap();
+            let mut woken = 0;
+            waiters.0.retain(|&(addr, _)| {
+                if addr != futex.as_ptr() as usize || woken == count {
+                    return true;
+                }
+                woken += 1;
+                false
+            });
+            WOKEN.notify_all();
+        }
+    }
+}
@@ -0,0 +1,15 @@
+//! Locks for userspace, which sleep on a futex while they are contended
+//! instead of spinning.
+//!
+//! The locks are generic over the [`Futex`] that they sleep on, since the
+//! standard library and the C library each make their own syscalls, and host
+//! tests sleep on [`futex::host::HostFutex`] instead of the kernel.
+
+#![no_std]
+
+pub use futex::Futex;
+pub use mutex::{Mutex, MutexGuard};
+
+pub mod futex;
+mod mutex;
+pub mod raw;
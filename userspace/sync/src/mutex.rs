@@ -0,0 +1,165 @@
+use core::cell::UnsafeCell;
+use core::fmt::{Debug, Formatter};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::AtomicU32;
+
+use crate::raw;
+use crate::Futex;
+
+/// A mutual exclusion lock around a value, which sleeps on the futex `F`
+/// while another thread holds it.
+pub struct Mutex<T: ?Sized, F> {
+    state: AtomicU32,
+    futex: PhantomData<F>,
+    value: UnsafeCell<T>,
+}
+
+// like a spin lock, the value is only ever accessed by the thread that holds
+// the lock
+unsafe impl<T: ?Sized + Send, F> Send for Mutex<T, F> {}
+unsafe impl<T: ?Sized + Send, F> Sync for Mutex<T, F> {}
+
+/// Access to the value of a [`Mutex`], which unlocks it when it's dropped.
+pub struct MutexGuard<'a, T: ?Sized, F: Futex> {
+    mutex: &'a Mutex<T, F>,
+}
+
+unsafe impl<T: ?Sized + Sync, F: Futex> Sync for MutexGuard<'_, T, F> {}
+
+impl<T, F> Mutex<T, F> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(raw::UNLOCKED),
+            futex: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<T: ?Sized, F: Futex> Mutex<T, F> {
+    /// Locks the mutex, and sleeps until that's possible if another thread
+    /// holds it. Locking it again on the same thread never returns.
+    pub fn lock(&self) -> MutexGuard<'_, T, F> {
+        raw::lock::<F>(&self.state);
+        MutexGuard { mutex: self }
+    }
+
+    /// Locks the mutex if no thread holds it, without waiting.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T, F>> {
+        raw::try_lock(&self.state).then_some(MutexGuard { mutex: self })
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+impl<T: Default, F> Default for Mutex<T, F> {
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: ?Sized + Debug, F: Futex> Debug for Mutex<T, F> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self.try_lock() {
+            Some(value) => f.debug_struct("Mutex").field("value", &&*value).finish(),
+            None => f.write_str("Mutex { <locked> }"),
+        }
+    }
+}
+
+impl<T: ?Sized, F: Futex> Deref for MutexGuard<'_, T, F> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized, F: Futex> DerefMut for MutexGuard<'_, T, F> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: ?Sized, F: Futex> Drop for MutexGuard<'_, T, F> {
+    fn drop(&mut self) {
+        raw::unlock::<F>(&self.mutex.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use std::vec::Vec;
+
+    use crate::futex::host::HostFutex;
+
+    type Mutex<T> = super::Mutex<T, HostFutex>;
+
+    #[test]
+    fn test_lock() {
+        let mutex = Mutex::new(1);
+        {
+            let mut value = mutex.lock();
+            *value += 1;
+            assert!(mutex.try_lock().is_none());
+        }
+        assert_eq!(2, *mutex.try_lock().unwrap());
+        assert_eq!(2, mutex.into_inner());
+    }
+
+    #[test]
+    fn test_waiter_sleeps_until_unlocked() {
+        let mutex = Arc::new(Mutex::new(Vec::new()));
+        let (locked, waiting) = channel();
+        let guard = mutex.lock();
+        let waiter = thread::spawn({
+            let mutex = mutex.clone();
+            move || {
+                locked.send(()).unwrap();
+                mutex.lock().push("waiter");
+            }
+        });
+        waiting.recv().unwrap();
+        // give the waiter time to go to sleep on the futex
+        thread::sleep(Duration::from_millis(50));
+        drop(guard);
+        waiter.join().unwrap();
+        mutex.lock().push("main");
+        assert_eq!(["waiter", "main"], mutex.lock()[..]);
+    }
+
+    #[test]
+    fn test_contention() {
+        const THREADS: usize = 8;
+        const INCREMENTS: usize = 20_000;
+
+        let counter = Arc::new(Mutex::new(0));
+        let threads = (0..THREADS)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        *counter.lock() += 1;
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(THREADS * INCREMENTS, *counter.lock());
+    }
+}
@@ -0,0 +1,106 @@
+//! Locking a bare `u32`, for locks whose state lives in memory that C code
+//! owns, like `pthread_mutex_t`.
+//!
+//! The state of a lock is [`UNLOCKED`], [`LOCKED`], or [`CONTENDED`] if
+//! there may be threads that wait for it. Locking only goes through the
+//! kernel if the lock is held, and unlocking only if there may be waiters.
+
+use core::sync::atomic::AtomicU32;
+use core::sync::atomic::Ordering::{Acquire, Relaxed, Release};
+
+use crate::Futex;
+
+pub const UNLOCKED: u32 = 0;
+pub const LOCKED: u32 = 1;
+pub const CONTENDED: u32 = 2;
+
+pub fn lock<F: Futex>(state: &AtomicU32) {
+    if state
+        .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+        .is_ok()
+    {
+        return;
+    }
+    // once we waited, we can't know whether there are other waiters, so we
+    // have to assume that there are
+    while state.swap(CONTENDED, Acquire) != UNLOCKED {
+        F::wait(state, CONTENDED);
+    }
+}
+
+pub fn try_lock(state: &AtomicU32) -> bool {
+    state
+        .compare_exchange(UNLOCKED, LOCKED, Acquire, Relaxed)
+        .is_ok()
+}
+
+pub fn unlock<F: Futex>(state: &AtomicU32) {
+    if state.swap(UNLOCKED, Release) == CONTENDED {
+        F::wake(state, 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use core::cell::UnsafeCell;
+    use std::sync::Arc;
+    use std::thread;
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::futex::host::HostFutex;
+
+    #[test]
+    fn test_states() {
+        let state = AtomicU32::new(UNLOCKED);
+        lock::<HostFutex>(&state);
+        assert_eq!(LOCKED, state.load(Relaxed));
+        assert!(!try_lock(&state));
+        unlock::<HostFutex>(&state);
+        assert_eq!(UNLOCKED, state.load(Relaxed));
+
+        assert!(try_lock(&state));
+        // a waiter would set this
+        state.store(CONTENDED, Relaxed);
+        unlock::<HostFutex>(&state);
+        assert_eq!(UNLOCKED, state.load(Relaxed));
+    }
+
+    struct Counter {
+        state: AtomicU32,
+        value: UnsafeCell<usize>,
+    }
+
+    // the value is only accessed while holding the lock
+    unsafe impl Sync for Counter {}
+
+    #[test]
+    fn test_contention() {
+        const THREADS: usize = 8;
+        const INCREMENTS: usize = 20_000;
+
+        let counter = Arc::new(Counter {
+            state: AtomicU32::new(UNLOCKED),
+            value: UnsafeCell::new(0),
+        });
+        let threads = (0..THREADS)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        lock::<HostFutex>(&counter.state);
+                        unsafe { *counter.value.get() += 1 };
+                        unlock::<HostFutex>(&counter.state);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(THREADS * INCREMENTS, unsafe { *counter.value.get() });
+        assert_eq!(UNLOCKED, counter.state.load(Relaxed));
+    }
+}
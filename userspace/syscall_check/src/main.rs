@@ -0,0 +1,49 @@
+#![no_std]
+#![no_main]
+
+use core::arch::asm;
+
+use kernel_api::syscall::{Errno, SYS_MAX};
+use std::syscall::sys_exit;
+use std::{println, rt};
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+/// Issues the syscall with the given number, which the wrappers in `std`
+/// can't, because they only take known syscalls.
+fn raw_syscall(number: usize) -> isize {
+    let res: isize;
+    unsafe {
+        asm! {
+        "int 0x80",
+        in("rax") number,
+        in("rdi") 1,
+        in("rsi") 2,
+        in("rdx") 3,
+        lateout("rax") res,
+        }
+    }
+    res
+}
+
+fn main() {
+    for number in [SYS_MAX, SYS_MAX + 1, 0xDEAD, usize::MAX] {
+        let result = Errno::from_return_value(raw_syscall(number));
+        assert_eq!(
+            Err(Errno::ENOSYS),
+            result,
+            "syscall {number:#x} didn't fail with ENOSYS"
+        );
+    }
+
+    // printing makes another syscall, so this also checks that the process
+    // survived the unknown ones
+    println!("syscall_check: ok");
+}
@@ -0,0 +1,92 @@
+#![no_std]
+#![no_main]
+#![feature(thread_local)]
+
+extern crate alloc;
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::Ordering::SeqCst;
+use core::time::Duration;
+
+use std::syscall::sys_exit;
+use std::thread::{self, Builder};
+use std::{println, rt};
+
+const THREADS: u64 = 16;
+
+/// Every thread has its own, which starts out as 7.
+#[thread_local]
+static LOCAL: Cell<u64> = Cell::new(7);
+
+#[no_mangle]
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
+
+    main();
+
+    sys_exit(0);
+}
+
+fn main() {
+    assert_eq!(Some("main"), thread::current().name());
+
+    let handles = (1..=THREADS)
+        .map(|i| {
+            thread::spawn(move || {
+                assert_eq!(7, LOCAL.get());
+                LOCAL.set(i);
+                thread::sleep(Duration::from_millis(10));
+                // nobody else wrote to it in the meantime
+                LOCAL.get() * i
+            })
+        })
+        .collect::<Vec<_>>();
+    let sum = handles
+        .into_iter()
+        .map(|handle| handle.join().unwrap())
+        .sum::<u64>();
+    assert_eq!((1..=THREADS).map(|i| i * i).sum::<u64>(), sum);
+    assert_eq!(7, LOCAL.get());
+
+    check_panic();
+    check_park();
+
+    println!("thread_check: ok");
+}
+
+/// A thread that panics ends, but the process goes on.
+fn check_panic() {
+    let handle = Builder::new()
+        .name("panicker".into())
+        .spawn(|| {
+            assert_eq!(Some("panicker"), thread::current().name());
+            panic!("thread_check: expected panic");
+        })
+        .unwrap();
+    let error = handle.join().unwrap_err();
+    assert_eq!(
+        Some(&String::from("thread_check: expected panic")),
+        error.downcast_ref::<String>()
+    );
+}
+
+fn check_park() {
+    static READY: AtomicBool = AtomicBool::new(false);
+
+    let main = thread::current();
+    let handle = thread::spawn(move || {
+        while !READY.load(SeqCst) {
+            thread::park();
+        }
+        main.unpark();
+    });
+    assert_ne!(thread::current().id(), handle.thread().id());
+
+    READY.store(true, SeqCst);
+    handle.thread().unpark();
+    thread::park();
+    handle.join().unwrap();
+}
@@ -3,18 +3,30 @@
 
 extern crate alloc;
 
+use alloc::format;
 use alloc::string::ToString;
-use core::slice::from_raw_parts_mut;
+use alloc::vec::Vec;
+#[cfg(feature = "benchmark")]
+use core::arch::x86_64::_rdtsc;
 
-use kernel_api::syscall::{FfiSockAddr, SocketDomain, SocketType, Stat};
+use graphics::color::Argb8888;
+use graphics::{Extent, Rect, Vec2};
+use kernel_api::font::GLYPH_HEIGHT;
+use kernel_api::syscall::{
+    FbVarScreenInfo, FfiSockAddr, SocketDomain, SocketType, Stat, FBIOGET_VSCREENINFO,
+};
 use std::syscall::{
-    sys_bind, sys_close, sys_exit, sys_mmap, sys_open, sys_socket, sys_stat, Errno,
+    sys_bind, sys_close, sys_exit, sys_ioctl, sys_mmap, sys_open, sys_socket, sys_stat, Errno,
 };
 use std::{println, rt};
 
+use crate::screen::Screen;
+
+mod screen;
+
 #[no_mangle]
-pub fn _start() -> isize {
-    rt::start();
+pub extern "C" fn _start(stack: *const usize) -> isize {
+    rt::start(stack);
 
     main();
 
@@ -43,7 +55,7 @@ fn main() {
 
     let mut stat = Stat::default();
     let res = sys_stat("/dev/fb0", &mut stat);
-    if res == Errno::ENOENT {
+    if res == Err(Errno::ENOENT) {
         println!("No framebuffer found");
         return;
     }
@@ -52,22 +64,151 @@ fn main() {
     println!("framebuffer stat: {:?}", stat);
 
     let fd = sys_open("/dev/fb0", 0, 0).unwrap();
-    let addr = sys_mmap(0, stat.size as usize, 3, 2, fd, 0).unwrap();
+
+    let mut info = FbVarScreenInfo::default();
+    sys_ioctl(
+        fd,
+        FBIOGET_VSCREENINFO,
+        &mut info as *mut FbVarScreenInfo as usize,
+    )
+    .unwrap();
+    println!("framebuffer info: {:?}", info);
+
+    let len = info.pitch as usize * info.height as usize;
+    let addr = sys_mmap(0, len, 3, 2, fd, 0).unwrap();
     sys_close(fd).unwrap();
-    let fb = unsafe { from_raw_parts_mut(addr as *mut u32, stat.size as usize / 4) };
-    fb.fill(0x0000_FF00);
-
-    const WIDTH: usize = 1280;
-    #[allow(dead_code)]
-    const HEIGHT: usize = 800;
-
-    for v in (0x00..0xFF).chain((0x00..0xFF).rev()).cycle() {
-        for _ in 0..5 {
-            fb.chunks_exact_mut(WIDTH)
-                .skip(200)
-                .take(80)
-                .flat_map(|row| row.iter_mut().skip(400).take(80))
-                .for_each(|pixel| *pixel = (0xFF - (v / 2)) << 8 | v);
+    let mut screen = unsafe { Screen::new(addr, &info) }.unwrap();
+    #[cfg(feature = "benchmark")]
+    benchmark(&mut screen);
+
+    let bounds = screen.bounds();
+    screen.fill_rect(bounds, 0x0000_FF00);
+    screen.draw_str(Vec2::new(16, 16), "devos window server", 0x00FF_FFFF, None);
+    screen.fill_triangle(
+        bounds,
+        Vec2::new(100, 300),
+        Vec2::new(250, 200),
+        Vec2::new(200, 450),
+        0x00FF_FF00,
+    );
+    screen.fill_circle(bounds, Vec2::new(700, 300), 60, 0x0000_00FF);
+    screen.draw_circle(bounds, Vec2::new(700, 300), 70, 0x00FF_FFFF);
+    screen.fill_rect_blended(Rect::new(150, 260, 600, 80), Argb8888::new(0x80, 0, 0, 0));
+    screen.blit_blended(
+        Vec2::new(860, 268),
+        64,
+        &glow(64, Argb8888::new(0xFF, 0xFF, 0x80, 0)),
+    );
+    let log = Rect::new(16, 500, 400, 160);
+    screen.fill_rect(log, 0);
+    // the outline goes around the log, on the pixels next to it
+    let (left, top) = (log.left() - 1, log.top() - 1);
+    let (right, bottom) = (log.right(), log.bottom());
+    screen.draw_polyline(
+        bounds,
+        &[
+            Vec2::new(left, top),
+            Vec2::new(right, top),
+            Vec2::new(right, bottom),
+            Vec2::new(left, bottom),
+            Vec2::new(left, top),
+        ],
+        0x00FF_FFFF,
+    );
+    screen.present();
+
+    let (image, size) = cursor();
+    let square = Rect::new(400, 200, 80, 80);
+    for (frame, v) in (0x00..0xFF).chain((0x00..0xFF).rev()).cycle().enumerate() {
+        screen.fill_rect(square, (0xFF - (v / 2)) << 8 | v);
+        // every other pass, the cursor crosses the square and the glow,
+        // starting half off the screen
+        match frame % 2000 {
+            0 => screen.set_overlay(&image, size),
+            1000 => screen.hide_overlay(),
+            _ => {}
+        }
+        screen.move_overlay(Vec2::new((frame % 1000) as i32 - 6, 250));
+        if frame % 0xFF == 0 {
+            screen.scroll_up(log, GLYPH_HEIGHT, 0);
+            let line = format!("frame {frame}");
+            let y = log.bottom() - GLYPH_HEIGHT as i32;
+            screen.draw_str(Vec2::new(log.left(), y), &line, 0x00FF_FFFF, None);
         }
+        screen.present();
     }
 }
+
+/// Draws 1000 rectangles, once presenting each of them on its own, and once
+/// presenting them all at once, and compares that to presenting the whole
+/// screen. Prints how many cycles each took and how many pixels were copied.
+#[cfg(feature = "benchmark")]
+fn benchmark(screen: &mut Screen) {
+    const COUNT: usize = 1000;
+    let rect = |i: usize| Rect::new((i * 37 % 1200) as i32, (i * 23 % 720) as i32, 64, 64);
+
+    let start = unsafe { _rdtsc() };
+    let mut copied = 0;
+    for i in 0..COUNT {
+        screen.fill_rect(rect(i), i as u32);
+        copied += screen.present();
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+    println!(
+        "drawing {} rectangles, presenting each: {} cycles, {} pixels",
+        COUNT, cycles, copied
+    );
+
+    let start = unsafe { _rdtsc() };
+    for i in 0..COUNT {
+        screen.fill_rect(rect(i), i as u32);
+    }
+    let copied = screen.present();
+    let cycles = unsafe { _rdtsc() } - start;
+    println!(
+        "drawing {} rectangles, presenting once: {} cycles, {} pixels",
+        COUNT, cycles, copied
+    );
+
+    let start = unsafe { _rdtsc() };
+    let bounds = screen.bounds();
+    screen.damage(bounds);
+    let copied = screen.present();
+    let cycles = unsafe { _rdtsc() } - start;
+    println!(
+        "presenting the whole screen: {} cycles, {} pixels",
+        cycles, copied
+    );
+}
+
+/// A mouse pointer: a white arrow with a black outline.
+fn cursor() -> (Vec<Argb8888>, Extent) {
+    let size = Extent::new(12, 18);
+    let image = (0..size.area())
+        .map(|i| {
+            let (x, y) = (i % size.width, i / size.width);
+            if x > y || y >= 17 {
+                Argb8888::TRANSPARENT
+            } else if x == 0 || x == y || y == 16 {
+                Argb8888::opaque(0)
+            } else {
+                Argb8888::opaque(0x00FF_FFFF)
+            }
+        })
+        .collect();
+    (image, size)
+}
+
+/// A square image of the color that fades out towards the edges.
+fn glow(size: usize, color: Argb8888) -> Vec<u32> {
+    let radius = size as isize / 2;
+    (0..size * size)
+        .map(|i| {
+            let x = (i % size) as isize - radius;
+            let y = (i / size) as isize - radius;
+            let distance = (x * x + y * y) * 255 / (radius * radius);
+            let alpha = (255 - distance.min(255)) as u32 * color.alpha() as u32 / 255;
+            (alpha << 24) | (color.0 & 0x00FF_FFFF)
+        })
+        .collect()
+}
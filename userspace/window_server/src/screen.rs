@@ -0,0 +1,119 @@
+use core::ops::{Deref, DerefMut};
+use core::slice::from_raw_parts_mut;
+
+use graphics::color::Argb8888;
+use graphics::{Canvas, Extent, FrontBuffer, Overlay, Vec2};
+use kernel_api::syscall::FbVarScreenInfo;
+use std::syscall::{sys_mmap, Errno};
+
+const PROT_READ_WRITE: usize = 0x1 | 0x2;
+const MAP_PRIVATE_ANON: usize = 0x2 | 0x8;
+
+/// The framebuffer, along with a back buffer that everything is drawn into.
+/// Nothing is visible until it's presented, so the screen never shows a
+/// partly drawn frame.
+///
+/// The back buffer holds `0x00RRGGBB` pixels, whatever the format of the
+/// framebuffer is, and they are converted when they are presented.
+///
+/// Only the rectangles that were drawn to since the last present are
+/// copied, see [`FrontBuffer`]. An overlay, like the mouse cursor, is
+/// composited onto them as they are presented, so it's never part of the
+/// back buffer.
+///
+/// Drawing on the screen draws into the back buffer, see [`Canvas`].
+pub struct Screen {
+    front: FrontBuffer<&'static mut [u8]>,
+    /// As many pixels per row as are visible.
+    back: Canvas<&'static mut [u32]>,
+    overlay: Overlay,
+}
+
+impl Deref for Screen {
+    type Target = Canvas<&'static mut [u32]>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.back
+    }
+}
+
+impl DerefMut for Screen {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.back
+    }
+}
+
+impl Screen {
+    /// Creates the screen for the framebuffer that is mapped at `front`. The
+    /// back buffer is allocated with an anonymous mapping, since it's far
+    /// larger than the heap.
+    ///
+    /// A geometry that doesn't fit the pixel format fails with
+    /// [`Errno::EINVAL`].
+    ///
+    /// # Safety
+    ///
+    /// `front` must be the start of the mapped framebuffer, and nothing else
+    /// may access it for as long as the screen lives.
+    pub unsafe fn new(front: usize, info: &FbVarScreenInfo) -> Result<Self, Errno> {
+        let (width, height) = (info.width as usize, info.height as usize);
+        let bytes_per_pixel = info.format.bytes_per_pixel();
+        if info.bytes_per_pixel as usize != bytes_per_pixel
+            || (info.pitch as usize) < width * bytes_per_pixel
+        {
+            return Err(Errno::EINVAL);
+        }
+        let len = info.pitch as usize * height;
+        let back = sys_mmap(
+            0,
+            width * height * 4,
+            PROT_READ_WRITE,
+            MAP_PRIVATE_ANON,
+            0,
+            0,
+        )?;
+        unsafe {
+            Ok(Self {
+                front: FrontBuffer::new(
+                    from_raw_parts_mut(front as *mut u8, len),
+                    width,
+                    height,
+                    info.pitch as usize,
+                    info.format,
+                ),
+                back: Canvas::new(
+                    from_raw_parts_mut(back as *mut u32, width * height),
+                    width,
+                    height,
+                ),
+                overlay: Overlay::new(),
+            })
+        }
+    }
+
+    /// Shows the ARGB image, which has `size.width` pixels per row, above
+    /// everything else, replacing the previous overlay.
+    pub fn set_overlay(&mut self, image: &[Argb8888], size: Extent) {
+        let damage = self.overlay.set_image(image, size);
+        damage.into_iter().for_each(|rect| self.back.damage(rect));
+    }
+
+    /// Moves the top left corner of the overlay to `position`. The overlay may
+    /// be partly or entirely off the screen.
+    pub fn move_overlay(&mut self, position: Vec2) {
+        let damage = self.overlay.move_to(position);
+        damage.into_iter().for_each(|rect| self.back.damage(rect));
+    }
+
+    /// Hides the overlay until it's set again, which shows what is below it.
+    pub fn hide_overlay(&mut self) {
+        let damage = self.overlay.set_visible(false);
+        damage.into_iter().for_each(|rect| self.back.damage(rect));
+    }
+
+    /// Copies everything that was drawn since the last present to the
+    /// screen. Returns how many pixels were copied.
+    pub fn present(&mut self) -> usize {
+        self.front.present(&mut self.back, &self.overlay)
+    }
+}